@@ -39,10 +39,11 @@ use serde::{Deserialize, Deserializer};
 use std::str::FromStr;
 pub use table::TableItemRequest;
 pub use transaction::{
-    AccountSignature, BlockMetadataTransaction, DeleteModule, DeleteResource, DeleteTableItem,
-    DirectWriteSet, Ed25519Signature, EncodeSubmissionRequest, EntryFunctionPayload, Event,
-    GasEstimation, GasEstimationBcs, GenesisPayload, GenesisTransaction, ModuleBundlePayload,
-    MultiAgentSignature, MultiEd25519Signature, PendingTransaction, ScriptPayload, ScriptWriteSet,
+    AccountSignature, BlockMetadataTransaction, DecodedTableData, DeleteModule, DeleteResource,
+    DeleteTableItem, DeletedTableData, DirectWriteSet, Ed25519Signature, EncodeSubmissionRequest,
+    EntryFunctionPayload, Event, GasEstimation, GasEstimationBcs, GenesisPayload,
+    GenesisTransaction, ModuleBundlePayload, MultiAgentSignature, MultiEd25519Signature,
+    PendingTransaction, ScriptPayload, ScriptWriteSet, StateCheckpointTransaction,
     SubmitTransactionRequest, Transaction, TransactionData, TransactionId, TransactionInfo,
     TransactionOnChainData, TransactionPayload, TransactionSignature, TransactionSigningMessage,
     TransactionsBatchSingleSubmissionFailure, TransactionsBatchSubmissionResult,
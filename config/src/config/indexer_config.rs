@@ -2,6 +2,7 @@
 // SPDX-License-Identifier: Apache-2.0
 
 use serde::{Deserialize, Serialize};
+use std::{collections::BTreeMap, path::PathBuf};
 
 pub const DEFAULT_BATCH_SIZE: u16 = 500;
 pub const DEFAULT_FETCH_TASKS: u8 = 5;
@@ -21,11 +22,23 @@ pub struct IndexerConfig {
     #[serde(default, skip_serializing_if = "Option::is_none")]
     pub postgres_uri: Option<String>,
 
+    /// Postgres schema the indexer's tables are created and written in, ex: "testnet", so that
+    /// indexers of different networks can share a database. Migrations create it and are tracked
+    /// in it. If null, the public schema
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub postgres_schema: Option<String>,
+
     /// The specific processor that it will run, ex: "token_processor"
     /// Alternatively can set the `PROCESSOR_NAME` env var
     #[serde(default, skip_serializing_if = "Option::is_none")]
     pub processor: Option<String>,
 
+    /// Other processors to run alongside `processor`. Each continues from its own version, and a
+    /// processor that's behind reads batches the others fetched from `fetch_cache` instead of
+    /// fetching them again. If null, only `processor` runs
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub additional_processors: Option<Vec<String>>,
+
     /// If set, will ignore database contents and start processing from the specified version.
     /// This will not delete any database contents, just transactions as it reprocesses them.
     /// Alternatively can set the `STARTING_VERSION` env var
@@ -35,10 +48,16 @@ pub struct IndexerConfig {
     ///////////////////
     ///////////////////
     ///////////////////
-    /// If set, don't run any migrations
+    /// If set, don't run any migrations. The indexer still refuses to start against a database
+    /// with pending migrations.
     #[serde(default, skip_serializing_if = "Option::is_none")]
     pub skip_migrations: Option<bool>,
 
+    /// If set, rewrite the token and collection hashes of a database written with an older hash
+    /// scheme before starting. Without it, the indexer refuses to start against such a database.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub rehash: Option<bool>,
+
     /// If set, will make sure that we're indexing the right chain
     #[serde(default, skip_serializing_if = "Option::is_none")]
     pub check_chain_id: Option<bool>,
@@ -47,10 +66,44 @@ pub struct IndexerConfig {
     #[serde(default, skip_serializing_if = "Option::is_none")]
     pub batch_size: Option<u16>,
 
-    /// How many tasks to run for fetching the transactions
+    /// How many pages of `batch_size` versions to fetch at once. Pages are still handed to the
+    /// processor in version order
     #[serde(default, skip_serializing_if = "Option::is_none")]
     pub fetch_tasks: Option<u8>,
 
+    /// Shrinks the page size of fetches (`batch_size`) after a fetch fails or is slow, and grows
+    /// it back after a run of fetches that aren't. If null, every page is `batch_size` versions
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub adaptive_fetch: Option<AdaptiveFetchConfig>,
+
+    /// Backoff, rate limit and circuit breaker for fetches from the node. Failed fetches are
+    /// retried until they succeed unless the versions are missing from storage. If null, the
+    /// defaults apply and fetches aren't rate limited
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub fetch_retry: Option<FetchRetryConfig>,
+
+    /// Node storage the standalone indexer reads transactions from instead of the `storage.dir` of
+    /// the node config, ex: a restored backup or a copy of a stopped node's db. Not used by the
+    /// indexer running within a node. If null, the standalone indexer opens `storage.dir`
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub node_storage: Option<NodeStorageConfig>,
+
+    /// Fullnodes to fetch transactions from over their REST API instead of the local node's
+    /// storage. One is active at a time, and it's failed over from when it keeps failing or its
+    /// ledger version stops advancing. If null, transactions are fetched from the local node
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub upstream_nodes: Option<UpstreamNodesConfig>,
+
+    /// Reads transactions from a Firehose stream of protobuf transactions instead of fetching
+    /// them as JSON. Can't be combined with `upstream_nodes`. If null, transactions are fetched
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub transaction_stream: Option<TransactionStreamConfig>,
+
+    /// Batches fetched by one processor that are kept for the `additional_processors`. Not used
+    /// when reading from `transaction_stream`. If null, the default number of batches is kept
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub fetch_cache: Option<FetchCacheConfig>,
+
     /// How many tasks to run for processing the transactions
     #[serde(default, skip_serializing_if = "Option::is_none")]
     pub processor_tasks: Option<u8>,
@@ -69,6 +122,599 @@ pub struct IndexerConfig {
     /// Which address does the ans contract live at. Only available for token_processor. If null, disable ANS indexing
     #[serde(default, skip_serializing_if = "Option::is_none")]
     pub ans_contract_address: Option<String>,
+
+    /// Additional ans contracts to index, e.g. the v2 contract. Only available for token_processor.
+    /// `ans_contract_address` is indexed as a v1 contract alongside these.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub ans_contracts: Option<Vec<AnsContractConfig>>,
+
+    /// Creator of the v1 ANS domain tokens, i.e. the resource account the contract mints them
+    /// from, which isn't derivable from the contract's address. Only available for
+    /// token_processor. If null, ANS domain sales aren't attributed to their domain
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub ans_token_creator: Option<String>,
+
+    /// Built in marketplaces to parse the events of, any of bluemove, topaz and souffl3. Only
+    /// available for token_processor. If null, all of them
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub marketplace_adapters: Option<Vec<String>>,
+
+    /// Declarative parsing rules for marketplaces with simple events. Only available for
+    /// token_processor. Marketplaces with a typed parser ignore these.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub marketplace_event_mappings: Option<Vec<MarketplaceEventMapping>>,
+
+    /// YAML or JSON file listing `MarketplaceDefinition`s, for adding marketplaces without a
+    /// release. Loaded and validated when the processor starts, which fails on any problem. Only
+    /// available for token_processor. If null, no marketplaces are loaded from a file
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub marketplace_definitions_path: Option<PathBuf>,
+
+    /// Marketplace event types to parse with one of the indexer's typed parsers, for contracts
+    /// whose events need more than a mapping, ex: Souffl3 sweeps covering several tokens. Only
+    /// available for token_processor.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub marketplace_typed_event_mappings: Option<Vec<MarketplaceTypedEventMapping>>,
+
+    /// Listing resources and table items of marketplaces that change listings without emitting
+    /// the events we parse. Only available for token_processor.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub marketplace_listing_mappings: Option<Vec<MarketplaceListingMapping>>,
+
+    /// Entry functions of marketplaces that sell tokens without emitting a sale event. Sales are
+    /// inferred from the token deposit and the APT the buyer paid. Only available for
+    /// token_processor.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub marketplace_payload_mappings: Option<Vec<MarketplacePayloadMapping>>,
+
+    /// Addresses that hold listed tokens in escrow for a marketplace, ex: the marketplace's
+    /// resource account. Their current_token_ownerships are marked as marketplace_escrow and
+    /// credited to the seller of the token's active listing. Only available for token_processor.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub marketplace_escrow_addresses: Option<Vec<String>>,
+
+    /// Record what each sale's buyer actually paid as nft_sales.settlement_amount, from the coin
+    /// events in the sale's transaction. Only available for token_processor. Adds the parsing of
+    /// coin events and coin stores to every transaction with a sale. If null, disabled
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub record_settlement_amounts: Option<bool>,
+
+    /// Also write each token activity to account_token_activities, once for its from_address and
+    /// once for its to_address, which the API's account activity feed reads from. Roughly doubles
+    /// the storage of token_activities. Only available for token_processor. If null, disabled
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub account_token_activities: Option<bool>,
+
+    /// Price APIs the standalone indexer's update-coin-prices command polls into coin_prices,
+    /// which the token_processor uses to value sales in USD. If null, coin_prices is only filled
+    /// by hand
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub coin_price_sources: Option<Vec<CoinPriceSourceConfig>>,
+
+    /// Periodically compares a sample of current_collection_volumes against the sum of nft_sales
+    /// and records drift in data_integrity_findings. Only available for token_processor. If null,
+    /// disable the check
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub volume_reconciliation: Option<VolumeReconciliationConfig>,
+
+    /// Periodically replays token_activities and the volume history for a sample of tokens and
+    /// records where the current tables disagree in data_integrity_findings. Only available for
+    /// token_processor. If null, only the standalone indexer's check-consistency command runs it
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub consistency_check: Option<ConsistencyCheckConfig>,
+
+    /// Versions to log a detailed trace for (parse results per event, rows produced per table),
+    /// for debugging specific transactions. Only available for token_processor.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub trace_versions: Option<Vec<u64>>,
+
+    /// Recompute trait frequencies and rarity ranks for collections with token data changes
+    /// every N versions. Only available for token_processor. If null, rarity is only computed by
+    /// the standalone indexer's recompute-rarity command
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub rarity_refresh_every_n_versions: Option<u64>,
+
+    /// Recompute the median sale price of collections with new sales every N versions. Only
+    /// available for token_processor. If null, current_collection_price_stats has no median
+    /// unless the standalone indexer's recompute-price-medians command is run
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub price_median_refresh_every_n_versions: Option<u64>,
+
+    /// Tables to write, ex: ["current_marketplace_listings", "collection_volumes"]. Only
+    /// available for token_processor. If null, every table is written
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub enabled_tables: Option<Vec<String>>,
+
+    /// Versions per token_activities partition, once token_activities has been partitioned with
+    /// the partition_token_activities procedure. Only available for token_processor. If null,
+    /// token_activities is expected to be a plain table
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub token_activities_partition_size: Option<u64>,
+
+    /// How long to keep the history tables, used by the standalone indexer's prune command. If
+    /// null, nothing is pruned
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub pruning: Option<PruningConfig>,
+
+    /// Session timeouts for the processor's database connections. A batch whose transaction
+    /// times out is retried. If null, the server's defaults apply
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub database_timeouts: Option<DatabaseTimeoutsConfig>,
+
+    /// Number of shards the token processor splits a batch's writes into by collection, each
+    /// committed in its own transaction on its own connection. Rows that aren't keyed by
+    /// collection are written by the first shard. Only available for token_processor. If null,
+    /// a batch is written in a single transaction
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub token_processor_shards: Option<u64>,
+
+    /// Bounds the token processor's in memory cache of collection table handle creators. Only
+    /// available for token_processor. If null, the 100000 most recently used creators are kept,
+    /// and never expire
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub table_handle_cache: Option<TableHandleCacheConfig>,
+
+    /// Fetches the JSON behind current_token_datas.metadata_uri into token_metadata_cache, in the
+    /// background. Needs the indexer built with the `metadata-fetcher` feature. If null, metadata
+    /// isn't fetched
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub metadata_fetcher: Option<MetadataFetcherConfig>,
+
+    /// Daily per collection stats written to collection_stats_snapshots after committed batches.
+    /// Only available for token_processor. If null, snapshots are only written by the standalone
+    /// indexer's backfill-collection-stats command
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub collection_stats_snapshots: Option<CollectionStatsSnapshotsConfig>,
+
+    /// Top collections and traders by volume, rebuilt in collection_leaderboard and
+    /// trader_leaderboard after committed batches. Only available for token_processor. If null,
+    /// the leaderboards aren't maintained
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub leaderboards: Option<LeaderboardsConfig>,
+
+    /// Read only HTTP API over the token tables, served by the standalone indexer's serve
+    /// command, or by the indexer itself with `serve_with_indexer`. Needs the indexer built with
+    /// the `http-api` feature. If null, the defaults apply
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub http_api: Option<HttpApiConfig>,
+
+    /// Copies the token_activities and nft_sales rows of committed batches to Parquet files, for
+    /// analytics. Only available for token_processor. Needs the indexer built with the
+    /// `parquet-sink` feature. If null, nothing is exported
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub parquet_sink: Option<ParquetSinkConfig>,
+
+    /// Characters token and collection names and uris are truncated to before they're stored.
+    /// Hashes are always computed over the whole strings, and the whole values of truncated ones
+    /// are kept in truncated_strings. Only available for token_processor. If null, names are
+    /// truncated to 128 characters and uris to 512
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub string_limits: Option<StringLimitsConfig>,
+
+    /// Largest price and token amount a token event can carry before it's taken for a malicious
+    /// or broken contract's. Events over them, or with a negative one, are still recorded in the
+    /// activities, flagged with suspect_value, but left out of volumes, floors and leaderboards.
+    /// Only available for token_processor. If null, both are 10^30
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub value_limits: Option<ValueLimitsConfig>,
+}
+
+#[derive(Clone, Debug, Deserialize, PartialEq, Eq, Serialize)]
+#[serde(deny_unknown_fields)]
+pub struct AnsContractConfig {
+    /// Address the ans contract is deployed at
+    pub address: String,
+    /// Contract version, 1 or 2. The versions differ in module names and name record layout.
+    pub version: u8,
+}
+
+/// Maps one marketplace event type to a token activity. Every field other than `event_type`,
+/// `kind` and `launchpad` is a dot separated path into the event data, ex:
+/// "token_id.token_data_id.creator", where numeric segments index into arrays.
+#[derive(Clone, Debug, Deserialize, PartialEq, Eq, Serialize)]
+#[serde(deny_unknown_fields)]
+pub struct MarketplaceEventMapping {
+    /// Fully qualified event type, ex: "0xabc::marketplace::BuyEvent"
+    pub event_type: String,
+    /// One of "list", "delist", "buy", "bid", "cancel_bid" or "mint". Mints are launchpad mint
+    /// events, with the minter as the buyer and the mint price as the price.
+    pub kind: String,
+    pub creator: String,
+    pub collection: String,
+    pub name: String,
+    /// Defaults to property version 0 if not set
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub property_version: Option<String>,
+    /// Defaults to an amount of 1 if not set
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub amount: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub price: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub coin_type: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub buyer: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub seller: Option<String>,
+    /// Name of the launchpad mints are attributed to, ex: "bluemove". Required for mint events.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub launchpad: Option<String>,
+}
+
+/// A marketplace whose events all map 1:1 onto a token activity, so it needs no adapter in the
+/// indexer. Read from the file at `marketplace_definitions_path`, which is a list of these.
+#[derive(Clone, Debug, Deserialize, PartialEq, Eq, Serialize)]
+#[serde(deny_unknown_fields)]
+pub struct MarketplaceDefinition {
+    /// Unique among the definitions and the built in marketplaces, ex: "fakemarket"
+    pub name: String,
+    /// Addresses of the marketplace's modules, every event type must be declared at one of them
+    pub addresses: Vec<String>,
+    pub events: Vec<MarketplaceEventMapping>,
+    /// Files holding a sample event each, as {"type": ..., "data": ...}, relative to the
+    /// definitions file. Parsed by `validate-config --validate-marketplace-config`.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub samples: Vec<String>,
+}
+
+/// Registers an event type with a typed parser. Whether the event counts as a sale goes by its
+/// type name, as for every other marketplace event.
+#[derive(Clone, Debug, Deserialize, PartialEq, Eq, Serialize)]
+#[serde(deny_unknown_fields)]
+pub struct MarketplaceTypedEventMapping {
+    /// Fully qualified event type, ex: "0xabc::FixedPriceMarketV2::BuyTokenEvent"
+    pub event_type: String,
+    /// One of "souffl3_v2_buy_token", "souffl3_v2_list_token", "souffl3_v2_cancel_list_token" or
+    /// "souffl3_sweep_buy"
+    pub parser: String,
+}
+
+/// Maps a marketplace's listing struct, written as a resource or a table item, to a listing.
+/// `creator`, `collection`, `name`, `property_version`, `amount`, `price` and `seller` are dot
+/// separated paths into the struct, same as in `MarketplaceEventMapping`.
+#[derive(Clone, Debug, Deserialize, PartialEq, Eq, Serialize)]
+#[serde(deny_unknown_fields)]
+pub struct MarketplaceListingMapping {
+    /// Address the marketplace is deployed at, stored as the listing's market address
+    pub market_address: String,
+    /// Fully qualified type of the listing struct without type arguments, ex:
+    /// "0xabc::marketplace::Listing". Matched against the type of written resources and the
+    /// value type of written table items.
+    pub listing_type: String,
+    pub creator: String,
+    pub collection: String,
+    pub name: String,
+    /// Defaults to property version 0 if not set
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub property_version: Option<String>,
+    /// Defaults to an amount of 1 if not set. Listings written with an amount of 0 are delisted.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub amount: Option<String>,
+    pub price: String,
+    pub seller: String,
+    /// Key type of the table holding the listings, ex: "0x3::token::TokenId". If set, deleting a
+    /// key of this type delists the token.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub key_type: Option<String>,
+    /// Path to the 0x3::token::TokenId within the table key. Defaults to the key itself.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub key_token_id: Option<String>,
+}
+
+/// An entry function that buys a token from a marketplace which emits no sale event of its own
+#[derive(Clone, Debug, Deserialize, PartialEq, Eq, Serialize)]
+#[serde(deny_unknown_fields)]
+pub struct MarketplacePayloadMapping {
+    /// Address the marketplace module is deployed at, stored as the sale's market address
+    pub module_address: String,
+    /// Module and function, ex: "market::buy_token"
+    pub function_name: String,
+}
+
+/// A price API for one coin, polled by update-coin-prices
+#[derive(Clone, Debug, Deserialize, PartialEq, Eq, Serialize)]
+#[serde(deny_unknown_fields)]
+pub struct CoinPriceSourceConfig {
+    /// Fully qualified coin type, ex: "0x1::aptos_coin::AptosCoin"
+    pub coin_type: String,
+    /// Decimals of the coin's raw amounts, ex: 8 for APT
+    pub decimals: u8,
+    /// Url returning the price as json, fetched with a GET
+    pub url: String,
+    /// Dot separated path to the USD price in the response, ex: "aptos.usd"
+    pub price_path: String,
+}
+
+#[derive(Clone, Debug, Deserialize, PartialEq, Eq, Serialize)]
+#[serde(deny_unknown_fields)]
+pub struct VolumeReconciliationConfig {
+    /// Run the check after every Nth successfully committed batch
+    pub every_n_batches: u64,
+    /// How many collections to sample per check, defaults to 100
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub sample_size: Option<u64>,
+    /// Largest difference, in the smallest unit of the coin (ex: octas), that isn't reported.
+    /// Defaults to 0
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub tolerance: Option<u64>,
+}
+
+#[derive(Clone, Debug, Deserialize, PartialEq, Eq, Serialize)]
+#[serde(deny_unknown_fields)]
+pub struct ConsistencyCheckConfig {
+    /// Run the check after every Nth successfully committed batch
+    pub every_n_batches: u64,
+    /// How many tokens to sample per check, defaults to 20
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub sample_size: Option<u64>,
+    /// First version from which token_activities is complete, i.e. the first version the
+    /// token_processor indexed. Only tokens minted since are checked. Defaults to 0
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub complete_from_version: Option<u64>,
+    /// First version from which current_token_pending_claims is complete. Offers with activity
+    /// before it aren't checked. Defaults to complete_from_version
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub claims_complete_from_version: Option<u64>,
+}
+
+#[derive(Clone, Debug, Default, Deserialize, PartialEq, Eq, Serialize)]
+#[serde(deny_unknown_fields)]
+pub struct CollectionStatsSnapshotsConfig {
+    /// Seconds of chain time between refreshes of the day's snapshots, defaults to 3600
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub interval_secs: Option<u64>,
+}
+
+#[derive(Clone, Debug, Default, Deserialize, PartialEq, Eq, Serialize)]
+#[serde(deny_unknown_fields)]
+pub struct LeaderboardsConfig {
+    /// Seconds of chain time between rebuilds, defaults to 300
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub interval_secs: Option<u64>,
+    /// Number of collections kept per ranking and of traders kept, defaults to 1000
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub top_n: Option<u64>,
+}
+
+#[derive(Clone, Debug, Deserialize, PartialEq, Eq, Serialize)]
+#[serde(deny_unknown_fields)]
+pub struct PruningConfig {
+    /// Days of history to keep per table, ex: {"token_activities": 90}. Only token_activities,
+    /// account_token_activities, collection_volumes and token_volumes can be pruned
+    pub retention_days: BTreeMap<String, u64>,
+    /// Rows deleted per batch, defaults to 50000
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub batch_size: Option<u64>,
+    /// Pause between batches in milliseconds, defaults to 1000
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub batch_pause_ms: Option<u64>,
+}
+
+#[derive(Clone, Debug, Default, Deserialize, PartialEq, Eq, Serialize)]
+#[serde(deny_unknown_fields)]
+pub struct AdaptiveFetchConfig {
+    /// Smallest page size, defaults to 10 or `batch_size` if that's smaller
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub min_batch_size: Option<u16>,
+    /// A fetch slower than this, in milliseconds, shrinks the page size like a failed one.
+    /// Defaults to 5000
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub slow_fetch_ms: Option<u64>,
+    /// Fetches in a row that are neither failed nor slow before the page size is doubled, up to
+    /// `batch_size`. Defaults to 10
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub grow_after_fetches: Option<u64>,
+}
+
+#[derive(Clone, Debug, Default, Deserialize, PartialEq, Eq, Serialize)]
+#[serde(deny_unknown_fields)]
+pub struct FetchRetryConfig {
+    /// Backoff before the first retry of a failed fetch in milliseconds, doubled for every
+    /// further retry. Defaults to 300
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub starting_backoff_ms: Option<u64>,
+    /// Longest backoff in milliseconds, defaults to 120000
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub max_backoff_ms: Option<u64>,
+    /// Fetches per second across all fetch tasks, including retries. If null, not limited
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub requests_per_second: Option<u64>,
+    /// Failed fetches in a row, across all fetch tasks, after which fetching pauses. Defaults
+    /// to 10
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub circuit_breaker_failures: Option<u64>,
+    /// How long fetching pauses for in milliseconds, defaults to 30000
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub circuit_breaker_pause_ms: Option<u64>,
+}
+
+#[derive(Clone, Debug, Default, Deserialize, PartialEq, Eq, Serialize)]
+#[serde(deny_unknown_fields)]
+pub struct NodeStorageConfig {
+    /// Directory of the db, laid out like a node's `storage.dir`
+    pub dir: String,
+    /// Has to be true, to acknowledge that the db is opened read only and that nothing may write
+    /// to it while it's read. Only transactions committed before it was opened are seen.
+    #[serde(default)]
+    pub read_only: bool,
+}
+
+#[derive(Clone, Debug, Default, Deserialize, PartialEq, Eq, Serialize)]
+#[serde(deny_unknown_fields)]
+pub struct UpstreamNodesConfig {
+    pub nodes: Vec<UpstreamNodeConfig>,
+    /// Failed requests in a row, including health checks, after which the active node is failed
+    /// over from. Defaults to 5
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub max_errors: Option<u64>,
+    /// How long the active node's ledger version can go without advancing before it's failed
+    /// over from, in milliseconds. Defaults to 30000
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub stall_timeout_ms: Option<u64>,
+    /// How often every node's ledger info is checked, in milliseconds. Defaults to 5000
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub health_check_interval_ms: Option<u64>,
+    /// When failing over, nodes more than this many versions behind the highest ledger version
+    /// are only picked if no other node is healthy. Defaults to 1000
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub max_lag_versions: Option<u64>,
+}
+
+#[derive(Clone, Debug, Deserialize, PartialEq, Eq, Serialize)]
+#[serde(deny_unknown_fields)]
+pub struct UpstreamNodeConfig {
+    /// REST API url of the node, ex: "https://fullnode.mainnet.aptoslabs.com"
+    pub url: String,
+    /// Preference among the nodes that are caught up when failing over, higher is preferred.
+    /// Defaults to 1
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub weight: Option<u64>,
+}
+
+#[derive(Clone, Debug, Deserialize, PartialEq, Eq, Serialize)]
+#[serde(deny_unknown_fields)]
+pub struct TransactionStreamConfig {
+    /// "host:port" serving the `FIRE` lines a node with `firehose_stream.enabled` prints. The
+    /// stream has to start at or before the next version to index
+    pub address: String,
+}
+
+#[derive(Clone, Debug, Default, Deserialize, PartialEq, Eq, Serialize)]
+#[serde(deny_unknown_fields)]
+pub struct FetchCacheConfig {
+    /// Most recently used batches to keep. Defaults to 100
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub max_batches: Option<u64>,
+}
+
+#[derive(Clone, Debug, Default, Deserialize, PartialEq, Eq, Serialize)]
+#[serde(deny_unknown_fields)]
+pub struct TableHandleCacheConfig {
+    /// Most recently used creators to keep. Defaults to 100000
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub max_entries: Option<u64>,
+    /// Creators are looked up in the db again once they've been cached this long. If null, they
+    /// don't expire
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub ttl_secs: Option<u64>,
+}
+
+#[derive(Clone, Debug, Default, Deserialize, PartialEq, Eq, Serialize)]
+#[serde(deny_unknown_fields)]
+pub struct MetadataFetcherConfig {
+    /// Fetches in flight at once. Defaults to 8
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub concurrency: Option<u64>,
+    /// Fetches started per second to any one host, ex: the IPFS gateway. Defaults to 5
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub requests_per_second_per_host: Option<u64>,
+    /// URIs with any other scheme are marked as permanently failed. Defaults to https, ipfs and ar
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub allowed_schemes: Option<Vec<String>>,
+    /// ipfs://<cid>/<path> is fetched from <ipfs_gateway><cid>/<path>. Defaults to
+    /// https://ipfs.io/ipfs/
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub ipfs_gateway: Option<String>,
+    /// ar://<id> is fetched from <arweave_gateway><id>. Defaults to https://arweave.net/
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub arweave_gateway: Option<String>,
+    /// Larger responses fail permanently. Defaults to 1048576
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub max_response_bytes: Option<u64>,
+    /// Defaults to 10000
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub timeout_ms: Option<u64>,
+    /// Failed fetches are retried until this many attempts, then marked as permanently failed.
+    /// Defaults to 5
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub max_attempts: Option<u64>,
+    /// Wait before the first retry, doubled after every failure. Defaults to 60
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub starting_backoff_secs: Option<u64>,
+    /// Defaults to 3600
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub max_backoff_secs: Option<u64>,
+    /// Pause between polls of current_token_datas when there's nothing to fetch. Defaults to 5000
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub poll_interval_ms: Option<u64>,
+}
+
+#[derive(Clone, Debug, Default, Deserialize, PartialEq, Eq, Serialize)]
+#[serde(deny_unknown_fields)]
+pub struct HttpApiConfig {
+    /// Address and port to listen on. Defaults to 127.0.0.1:8090
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub bind_address: Option<String>,
+    /// Connections to postgres kept for requests. Defaults to 10
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub pool_size: Option<u64>,
+    /// Also serve the API from the indexer itself, alongside its processors, which adds the
+    /// websocket endpoint fed by the token processor's committed batches. Defaults to false
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub serve_with_indexer: Option<bool>,
+    /// Channels a single websocket connection can subscribe to. Defaults to 50
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub max_subscriptions_per_connection: Option<u64>,
+    /// Messages kept for each websocket connection that hasn't read them yet, after which its
+    /// oldest are dropped. Defaults to 1024
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub subscriber_buffer_size: Option<u64>,
+}
+
+#[derive(Clone, Debug, Default, Deserialize, PartialEq, Eq, Serialize)]
+#[serde(deny_unknown_fields)]
+pub struct ParquetSinkConfig {
+    /// Directory to write the files to, or `s3://<bucket>/<prefix>`. S3 credentials and region
+    /// come from the usual AWS_* environment variables
+    pub location: String,
+    /// Rows in a file before it's closed and the next one started. Defaults to 1000000
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub max_rows_per_file: Option<u64>,
+    /// Seconds a file stays open when it isn't full. Defaults to 600
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub max_file_age_secs: Option<u64>,
+    /// Committed batches waiting to be written, after which new ones are dropped rather than
+    /// holding up the processor. Defaults to 100
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub queue_size: Option<u64>,
+}
+
+#[derive(Clone, Debug, Default, Deserialize, PartialEq, Eq, Serialize)]
+#[serde(deny_unknown_fields)]
+pub struct StringLimitsConfig {
+    /// Limit of collection_name and name columns, at most 512. Defaults to 128
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub name_length: Option<u64>,
+    /// Limit of metadata_uri columns, at most 2048. Defaults to 512
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub uri_length: Option<u64>,
+}
+
+/// Decimal strings, since they're past what a u64 holds
+#[derive(Clone, Debug, Default, Deserialize, PartialEq, Eq, Serialize)]
+#[serde(deny_unknown_fields)]
+pub struct ValueLimitsConfig {
+    /// Largest price, in the smallest unit of the coin. Defaults to 10^30
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub max_price: Option<String>,
+    /// Largest token amount. Defaults to 10^30
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub max_amount: Option<String>,
+}
+
+/// Timeouts in milliseconds, each unset one is left at the server's default
+#[derive(Clone, Debug, Default, Deserialize, PartialEq, Eq, Serialize)]
+#[serde(deny_unknown_fields)]
+pub struct DatabaseTimeoutsConfig {
+    /// Cancels statements running longer than this, including time spent waiting for locks
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub statement_timeout_ms: Option<u64>,
+    /// Cancels statements waiting longer than this for a lock, e.g. one held by a migration
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub lock_timeout_ms: Option<u64>,
+    /// Closes connections left idle within a transaction for longer than this
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub idle_in_transaction_session_timeout_ms: Option<u64>,
 }
 
 pub fn env_or_default<T: std::str::FromStr>(
@@ -0,0 +1,154 @@
+// Copyright (c) Aptos
+// SPDX-License-Identifier: Apache-2.0
+
+//! Looking up the creators of the collection tables a large mint writes to, one query per table
+//! handle against one prefetch for the whole batch. Needs `INDEXER_DATABASE_URL`, and is skipped
+//! without it.
+
+#[macro_use]
+extern crate criterion;
+
+use aptos_api_types::Transaction;
+use aptos_indexer::{
+    database::new_db_pool,
+    indexer::tailer::MIGRATIONS,
+    models::token_models::{
+        collection_datas::{CollectionCreators, CurrentCollectionData},
+        marketplace_event_mappings::MarketplaceEventMappings,
+        table_handle_cache::TableHandleCache,
+    },
+    processors::token_processor::ParsedTransaction,
+};
+use criterion::{BenchmarkId, Criterion, Throughput};
+use diesel::RunQueryDsl;
+use diesel_migrations::MigrationHarness;
+use serde_json::json;
+
+const BASE_TRANSACTION: &str = concat!(
+    env!("CARGO_MANIFEST_DIR"),
+    "/fixtures/golden/transactions/bluemove_buy.json"
+);
+/// Creator of the collections the benchmark inserts, deleted again when it's done
+const BENCH_CREATOR: &str = "0x000000000000000000000000000000000000000000000000000000000000be4c";
+const NUM_COLLECTIONS: [usize; 2] = [500, 5000];
+
+fn table_handle(index: usize) -> String {
+    format!("0x{:064x}", index)
+}
+
+/// A transaction minting one token in each of num_collections collections. Only the collections'
+/// tables are written, not their creators' Collections resources, as when a launchpad mints.
+fn large_mint(num_collections: usize) -> Transaction {
+    let mut transaction: serde_json::Value =
+        serde_json::from_str(&std::fs::read_to_string(BASE_TRANSACTION).unwrap()).unwrap();
+    transaction["events"] = json!([]);
+    transaction["changes"] = (0..num_collections)
+        .map(|index| {
+            json!({
+                "type": "write_table_item",
+                "state_key_hash": "0x00",
+                "handle": table_handle(index),
+                "key": "0x00",
+                "value": "0x00",
+                "data": {
+                    "key": format!("Collection #{}", index),
+                    "key_type": "0x1::string::String",
+                    "value": {
+                        "description": "",
+                        "maximum": "10000",
+                        "mutability_config": {"description": false, "maximum": false, "uri": false},
+                        "name": format!("Collection #{}", index),
+                        "supply": "1",
+                        "uri": ""
+                    },
+                    "value_type": "0x3::token::CollectionData"
+                }
+            })
+        })
+        .collect();
+    serde_json::from_value(transaction).unwrap()
+}
+
+fn lookup(c: &mut Criterion) {
+    let database_url = match std::env::var("INDEXER_DATABASE_URL") {
+        Ok(database_url) => database_url,
+        Err(_) => {
+            eprintln!("`INDEXER_DATABASE_URL` is not set: skipping collection_creators");
+            return;
+        }
+    };
+    let conn_pool = new_db_pool(&database_url).unwrap();
+    let mut conn = conn_pool.get().unwrap();
+    conn.run_pending_migrations(MIGRATIONS).unwrap();
+    let max_collections = *NUM_COLLECTIONS.iter().max().unwrap();
+    diesel::sql_query(format!(
+        "INSERT INTO current_collection_datas (
+            collection_data_id_hash, creator_address, collection_name, description, metadata_uri,
+            supply, maximum, maximum_mutable, uri_mutable, description_mutable,
+            last_transaction_version, table_handle, last_transaction_timestamp
+        )
+        SELECT encode(sha256(convert_to('{0}::Collection #' || i, 'UTF8')), 'hex'), '{0}',
+            'Collection #' || i, '', '', 0, 10000, false, false, false, 0,
+            '0x' || lpad(to_hex(i), 64, '0'), NOW()
+        FROM generate_series(0, {1}) i
+        ON CONFLICT DO NOTHING",
+        BENCH_CREATOR,
+        max_collections - 1,
+    ))
+    .execute(&mut conn)
+    .unwrap();
+
+    let mappings = MarketplaceEventMappings::default();
+    let mut group = c.benchmark_group("collection_creators");
+    group.sample_size(10);
+    for num_collections in NUM_COLLECTIONS {
+        let parsed = ParsedTransaction::from_transaction(
+            &large_mint(num_collections),
+            None,
+            &mappings,
+            &[],
+            false,
+        )
+        .unwrap();
+        let collection_items = parsed.tokens().collection_items();
+        group.throughput(Throughput::Elements(num_collections as u64));
+        // How each item was looked up before, with an empty cache
+        group.bench_with_input(
+            BenchmarkId::new("per_table_item", num_collections),
+            &num_collections,
+            |b, num_collections| {
+                b.iter(|| {
+                    for index in 0..*num_collections {
+                        CurrentCollectionData::get_by_table_handle(&mut conn, &table_handle(index))
+                            .unwrap();
+                    }
+                })
+            },
+        );
+        group.bench_with_input(
+            BenchmarkId::new("prefetched", num_collections),
+            &collection_items,
+            |b, collection_items| {
+                b.iter(|| {
+                    CollectionCreators::prefetch(
+                        &mut conn,
+                        &TableHandleCache::new(max_collections),
+                        collection_items.iter(),
+                    )
+                    .unwrap()
+                })
+            },
+        );
+    }
+    group.finish();
+
+    diesel::sql_query(format!(
+        "DELETE FROM current_collection_datas WHERE creator_address = '{}'",
+        BENCH_CREATOR
+    ))
+    .execute(&mut conn)
+    .unwrap();
+}
+
+criterion_group!(benches, lookup);
+criterion_main!(benches);
@@ -0,0 +1,114 @@
+// Copyright (c) Aptos
+// SPDX-License-Identifier: Apache-2.0
+
+#[macro_use]
+extern crate criterion;
+
+use aptos_api_types::Transaction;
+use aptos_indexer::{
+    models::token_models::{
+        marketplace_event_mappings::MarketplaceEventMappings,
+        token_utils::{TokenEvent, TokenEvents},
+    },
+    processors::token_processor::{parse_transactions, ParsedTransaction},
+};
+use criterion::{BenchmarkId, Criterion, Throughput};
+
+const FIXTURE_DIR: &str = concat!(env!("CARGO_MANIFEST_DIR"), "/fixtures/golden/transactions");
+const BATCH_SIZES: [usize; 2] = [100, 1000];
+
+/// The golden fixtures, repeated up to batch_size transactions of marketplace activity
+fn batch(batch_size: usize) -> Vec<Transaction> {
+    let mut paths = std::fs::read_dir(FIXTURE_DIR)
+        .unwrap()
+        .map(|entry| entry.unwrap().path())
+        .collect::<Vec<_>>();
+    paths.sort();
+    let fixtures = paths
+        .iter()
+        .map(|path| serde_json::from_str(&std::fs::read_to_string(path).unwrap()).unwrap())
+        .collect::<Vec<Transaction>>();
+    fixtures.into_iter().cycle().take(batch_size).collect()
+}
+
+fn parse(c: &mut Criterion) {
+    let mappings = MarketplaceEventMappings::default();
+    let mut group = c.benchmark_group("parse_transactions");
+    for batch_size in BATCH_SIZES {
+        let transactions = batch(batch_size);
+        group.throughput(Throughput::Elements(batch_size as u64));
+        // How the processor parsed a batch before, one transaction after another
+        group.bench_with_input(
+            BenchmarkId::new("serial", batch_size),
+            &transactions,
+            |b, transactions| {
+                b.iter(|| {
+                    transactions
+                        .iter()
+                        .map(|txn| {
+                            ParsedTransaction::from_transaction(txn, None, &mappings, &[], false)
+                                .unwrap()
+                        })
+                        .collect::<Vec<_>>()
+                })
+            },
+        );
+        group.bench_with_input(
+            BenchmarkId::new("parallel", batch_size),
+            &transactions,
+            |b, transactions| {
+                b.iter(|| parse_transactions(transactions, &mappings, &[], false).unwrap())
+            },
+        );
+    }
+    group.finish();
+}
+
+/// Models that used to parse every event of the transaction themselves
+const EVENT_CONSUMERS: usize = 8;
+
+fn parse_events(c: &mut Criterion) {
+    let mut group = c.benchmark_group("parse_token_events");
+    for batch_size in BATCH_SIZES {
+        let transactions = batch(batch_size);
+        group.throughput(Throughput::Elements(batch_size as u64));
+        // How the events were parsed before, a clone of the data per model
+        group.bench_with_input(
+            BenchmarkId::new("per_model", batch_size),
+            &transactions,
+            |b, transactions| {
+                b.iter(|| {
+                    for txn in transactions {
+                        if let Transaction::UserTransaction(user_txn) = txn {
+                            let txn_version = user_txn.info.version.0 as i64;
+                            for event in &user_txn.events {
+                                let event_type = event.typ.to_string();
+                                for _ in 0..EVENT_CONSUMERS {
+                                    let data = event.data.clone();
+                                    TokenEvent::from_event(&event_type, &data, txn_version)
+                                        .unwrap();
+                                }
+                            }
+                        }
+                    }
+                })
+            },
+        );
+        group.bench_with_input(
+            BenchmarkId::new("once", batch_size),
+            &transactions,
+            |b, transactions| {
+                b.iter(|| {
+                    transactions
+                        .iter()
+                        .map(|txn| TokenEvents::from_transaction(txn).unwrap())
+                        .collect::<Vec<_>>()
+                })
+            },
+        );
+    }
+    group.finish();
+}
+
+criterion_group!(benches, parse, parse_events);
+criterion_main!(benches);
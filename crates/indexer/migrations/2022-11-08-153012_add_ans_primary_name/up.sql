@@ -0,0 +1,13 @@
+-- Your SQL goes here
+-- reverse lookup for aptos name service, i.e. the primary name of an address
+CREATE TABLE current_ans_primary_name (
+  registered_address VARCHAR(66) UNIQUE PRIMARY KEY NOT NULL,
+  -- null domain means the primary name was cleared
+  domain VARCHAR(64),
+  -- if subdomain is null set to empty string, null only when domain is null
+  subdomain VARCHAR(64),
+  last_transaction_version BIGINT NOT NULL,
+  inserted_at TIMESTAMP NOT NULL DEFAULT NOW()
+);
+CREATE INDEX capn_d_s_index ON current_ans_primary_name (domain, subdomain);
+CREATE INDEX capn_insat_index ON current_ans_primary_name (inserted_at);
\ No newline at end of file
@@ -0,0 +1,37 @@
+-- Your SQL goes here
+-- marketplace sales, with the gas market context of the sale transaction
+CREATE TABLE nft_sales (
+  transaction_version BIGINT NOT NULL,
+  event_account_address VARCHAR(66) NOT NULL,
+  event_creation_number BIGINT NOT NULL,
+  event_sequence_number BIGINT NOT NULL,
+  market_address VARCHAR(66) NOT NULL,
+  event_type VARCHAR(150) NOT NULL,
+  token_data_id_hash VARCHAR(64) NOT NULL,
+  property_version NUMERIC NOT NULL,
+  collection_data_id_hash VARCHAR(64) NOT NULL,
+  creator_address VARCHAR(66) NOT NULL,
+  collection_name VARCHAR(128) NOT NULL,
+  name VARCHAR(128) NOT NULL,
+  seller VARCHAR(66),
+  buyer VARCHAR(66),
+  token_amount NUMERIC NOT NULL,
+  coin_type TEXT,
+  price NUMERIC,
+  gas_unit_price NUMERIC NOT NULL,
+  -- null when the block boundary wasn't in the processed batch
+  transaction_rank_in_block BIGINT,
+  transaction_timestamp TIMESTAMP NOT NULL,
+  inserted_at TIMESTAMP NOT NULL DEFAULT NOW(),
+  -- Constraints
+  PRIMARY KEY (
+    transaction_version,
+    event_account_address,
+    event_creation_number,
+    event_sequence_number
+  )
+);
+CREATE INDEX ns_cdih_tv_index ON nft_sales (collection_data_id_hash, transaction_version);
+CREATE INDEX ns_tdih_index ON nft_sales (token_data_id_hash);
+CREATE INDEX ns_ma_index ON nft_sales (market_address);
+CREATE INDEX ns_insat_index ON nft_sales (inserted_at);
\ No newline at end of file
@@ -0,0 +1,3 @@
+-- This file should undo anything in `up.sql`
+DROP TABLE IF EXISTS collection_price_candles;
+DROP TABLE IF EXISTS collection_daily_reports;
\ No newline at end of file
@@ -0,0 +1,48 @@
+-- Your SQL goes here
+-- hourly OHLC of sale prices. coin_type and market_address are 'all' on the rollup row
+CREATE TABLE collection_price_candles (
+  collection_data_id_hash VARCHAR(64) NOT NULL,
+  coin_type VARCHAR(5000) NOT NULL,
+  market_address VARCHAR(66) NOT NULL,
+  interval_start TIMESTAMP NOT NULL,
+  open_price NUMERIC NOT NULL,
+  high_price NUMERIC NOT NULL,
+  low_price NUMERIC NOT NULL,
+  close_price NUMERIC NOT NULL,
+  volume NUMERIC NOT NULL,
+  sales_count BIGINT NOT NULL,
+  first_transaction_version BIGINT NOT NULL,
+  last_transaction_version BIGINT NOT NULL,
+  inserted_at TIMESTAMP NOT NULL DEFAULT NOW(),
+  -- Constraints
+  PRIMARY KEY (
+    collection_data_id_hash,
+    coin_type,
+    market_address,
+    interval_start
+  )
+);
+CREATE INDEX cpc_is_index ON collection_price_candles (interval_start);
+CREATE INDEX cpc_insat_index ON collection_price_candles (inserted_at);
+-- daily sales summary. coin_type and market_address are 'all' on the rollup row
+CREATE TABLE collection_daily_reports (
+  collection_data_id_hash VARCHAR(64) NOT NULL,
+  coin_type VARCHAR(5000) NOT NULL,
+  market_address VARCHAR(66) NOT NULL,
+  report_date DATE NOT NULL,
+  volume NUMERIC NOT NULL,
+  sales_count BIGINT NOT NULL,
+  min_price NUMERIC NOT NULL,
+  max_price NUMERIC NOT NULL,
+  last_transaction_version BIGINT NOT NULL,
+  inserted_at TIMESTAMP NOT NULL DEFAULT NOW(),
+  -- Constraints
+  PRIMARY KEY (
+    collection_data_id_hash,
+    coin_type,
+    market_address,
+    report_date
+  )
+);
+CREATE INDEX cdr_rd_index ON collection_daily_reports (report_date);
+CREATE INDEX cdr_insat_index ON collection_daily_reports (inserted_at);
\ No newline at end of file
@@ -0,0 +1,15 @@
+-- Your SQL goes here
+-- discrepancies found by the processors' self checks, ex: current_collection_volumes drifting from nft_sales
+CREATE TABLE data_integrity_findings (
+  check_name VARCHAR(100) NOT NULL,
+  subject VARCHAR(5000) NOT NULL,
+  -- last version committed when the check ran
+  transaction_version BIGINT NOT NULL,
+  expected NUMERIC NOT NULL,
+  actual NUMERIC NOT NULL,
+  delta NUMERIC NOT NULL,
+  inserted_at TIMESTAMP NOT NULL DEFAULT NOW(),
+  -- Constraints
+  PRIMARY KEY (check_name, subject, transaction_version)
+);
+CREATE INDEX dif_insat_index ON data_integrity_findings (inserted_at);
\ No newline at end of file
@@ -0,0 +1,3 @@
+-- This file should undo anything in `up.sql`
+DROP INDEX IF EXISTS curr_to_cdih_oa_index;
+DROP TABLE IF EXISTS current_collection_holder_counts;
\ No newline at end of file
@@ -0,0 +1,13 @@
+-- Your SQL goes here
+-- distinct owners with a positive balance in the collection, and the sum of their balances
+CREATE TABLE current_collection_holder_counts (
+  collection_data_id_hash VARCHAR(64) UNIQUE PRIMARY KEY NOT NULL,
+  distinct_holders BIGINT NOT NULL,
+  total_tokens_held NUMERIC NOT NULL,
+  last_transaction_version BIGINT NOT NULL,
+  inserted_at TIMESTAMP NOT NULL DEFAULT NOW()
+);
+CREATE INDEX cchc_dh_index ON current_collection_holder_counts (distinct_holders);
+CREATE INDEX cchc_insat_index ON current_collection_holder_counts (inserted_at);
+-- balance lookups by collection and owner
+CREATE INDEX curr_to_cdih_oa_index ON current_token_ownerships (collection_data_id_hash, owner_address);
\ No newline at end of file
@@ -0,0 +1,16 @@
+-- Your SQL goes here
+-- one row per key of a token's default properties, values are decoded when they're primitives
+CREATE TABLE token_properties_flat (
+  token_data_id_hash VARCHAR(64) NOT NULL,
+  property_key VARCHAR(128) NOT NULL,
+  property_value TEXT NOT NULL,
+  property_type VARCHAR(128) NOT NULL,
+  collection_data_id_hash VARCHAR(64) NOT NULL,
+  last_transaction_version BIGINT NOT NULL,
+  inserted_at TIMESTAMP NOT NULL DEFAULT NOW(),
+  -- Constraints
+  PRIMARY KEY (token_data_id_hash, property_key)
+);
+CREATE INDEX tpf_cdih_pk_pv_index ON token_properties_flat (collection_data_id_hash, property_key, property_value);
+CREATE INDEX tpf_pk_pv_index ON token_properties_flat (property_key, property_value);
+CREATE INDEX tpf_insat_index ON token_properties_flat (inserted_at);
\ No newline at end of file
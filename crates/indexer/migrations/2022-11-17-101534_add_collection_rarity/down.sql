@@ -0,0 +1,7 @@
+-- This file should undo anything in `up.sql`
+DROP TABLE IF EXISTS collection_rarity_status;
+DROP INDEX IF EXISTS curr_td_ltv_index;
+DROP INDEX IF EXISTS curr_td_cdih_rr_index;
+ALTER TABLE current_token_datas DROP COLUMN IF EXISTS rarity_score,
+  DROP COLUMN IF EXISTS rarity_rank;
+DROP TABLE IF EXISTS collection_trait_frequencies;
\ No newline at end of file
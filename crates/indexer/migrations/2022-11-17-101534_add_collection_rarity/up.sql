@@ -0,0 +1,28 @@
+-- Your SQL goes here
+-- how many of a collection's tokens have each (property_key, property_value) pair, frequency is
+-- token_count over the collection's tokens with properties
+CREATE TABLE collection_trait_frequencies (
+  collection_data_id_hash VARCHAR(64) NOT NULL,
+  property_key VARCHAR(128) NOT NULL,
+  property_value TEXT NOT NULL,
+  token_count BIGINT NOT NULL,
+  frequency DOUBLE PRECISION NOT NULL,
+  last_transaction_version BIGINT NOT NULL,
+  inserted_at TIMESTAMP NOT NULL DEFAULT NOW(),
+  -- Constraints
+  PRIMARY KEY (collection_data_id_hash, property_key, property_value)
+);
+CREATE INDEX ctf_insat_index ON collection_trait_frequencies (inserted_at);
+-- sum of 1 / frequency over the token's properties, rank 1 is the rarest token in the collection
+ALTER TABLE current_token_datas
+ADD COLUMN rarity_score DOUBLE PRECISION,
+  ADD COLUMN rarity_rank BIGINT;
+CREATE INDEX curr_td_cdih_rr_index ON current_token_datas (collection_data_id_hash, rarity_rank);
+-- finding collections with token data changes since the last rarity refresh
+CREATE INDEX curr_td_ltv_index ON current_token_datas (last_transaction_version);
+-- highest version whose token data changes are reflected in the rarity tables
+CREATE TABLE collection_rarity_status (
+  processor VARCHAR(50) UNIQUE PRIMARY KEY NOT NULL,
+  last_transaction_version BIGINT NOT NULL,
+  inserted_at TIMESTAMP NOT NULL DEFAULT NOW()
+);
\ No newline at end of file
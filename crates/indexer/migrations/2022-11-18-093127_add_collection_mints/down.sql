@@ -0,0 +1,3 @@
+-- This file should undo anything in `up.sql`
+DROP TABLE IF EXISTS current_collection_mint_stats;
+DROP TABLE IF EXISTS collection_mints;
\ No newline at end of file
@@ -0,0 +1,33 @@
+-- Your SQL goes here
+-- one row per MintTokenEvent, price is estimated from the APT (in octas) the sender paid in the
+-- same transaction. is_price_unknown is set when the sender didn't pay any APT
+CREATE TABLE collection_mints (
+  transaction_version BIGINT NOT NULL,
+  event_index BIGINT NOT NULL,
+  token_data_id_hash VARCHAR(64) NOT NULL,
+  collection_data_id_hash VARCHAR(64) NOT NULL,
+  creator_address VARCHAR(66) NOT NULL,
+  collection_name VARCHAR(128) NOT NULL,
+  name VARCHAR(128) NOT NULL,
+  minter_address VARCHAR(66) NOT NULL,
+  amount NUMERIC NOT NULL,
+  price NUMERIC NOT NULL,
+  is_price_unknown BOOLEAN NOT NULL,
+  transaction_timestamp TIMESTAMP NOT NULL,
+  inserted_at TIMESTAMP NOT NULL DEFAULT NOW(),
+  -- Constraints
+  PRIMARY KEY (transaction_version, event_index)
+);
+CREATE INDEX cm_cdih_ma_index ON collection_mints (collection_data_id_hash, minter_address);
+CREATE INDEX cm_ma_index ON collection_mints (minter_address);
+CREATE INDEX cm_insat_index ON collection_mints (inserted_at);
+-- mint_volume_apt is in octas
+CREATE TABLE current_collection_mint_stats (
+  collection_data_id_hash VARCHAR(64) UNIQUE PRIMARY KEY NOT NULL,
+  total_minted NUMERIC NOT NULL,
+  distinct_minters BIGINT NOT NULL,
+  mint_volume_apt NUMERIC NOT NULL,
+  last_transaction_version BIGINT NOT NULL,
+  inserted_at TIMESTAMP NOT NULL DEFAULT NOW()
+);
+CREATE INDEX ccms_insat_index ON current_collection_mint_stats (inserted_at);
\ No newline at end of file
@@ -0,0 +1,4 @@
+-- This file should undo anything in `up.sql`
+DROP INDEX IF EXISTS cm_launchpad_index;
+ALTER TABLE collection_mints DROP COLUMN IF EXISTS launchpad;
+ALTER TABLE current_collection_mint_stats DROP COLUMN IF EXISTS launchpad;
\ No newline at end of file
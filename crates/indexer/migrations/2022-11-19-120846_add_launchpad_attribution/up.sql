@@ -0,0 +1,7 @@
+-- Your SQL goes here
+-- set for mints parsed from a launchpad's mint event, ex: bluemove
+ALTER TABLE collection_mints
+ADD COLUMN launchpad VARCHAR(50);
+CREATE INDEX cm_launchpad_index ON collection_mints (launchpad);
+ALTER TABLE current_collection_mint_stats
+ADD COLUMN launchpad VARCHAR(50);
\ No newline at end of file
@@ -0,0 +1,4 @@
+-- This file should undo anything in `up.sql`
+ALTER TABLE nft_sales DROP COLUMN IF EXISTS is_primary;
+ALTER TABLE current_collection_volumes DROP COLUMN IF EXISTS primary_volume,
+  DROP COLUMN IF EXISTS secondary_volume;
\ No newline at end of file
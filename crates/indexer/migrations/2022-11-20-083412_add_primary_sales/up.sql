@@ -0,0 +1,8 @@
+-- Your SQL goes here
+-- a sale is primary when the creator sells, or when the seller is the only owner the token has had
+ALTER TABLE nft_sales
+ADD COLUMN is_primary BOOLEAN NOT NULL DEFAULT FALSE;
+-- sales from before this migration are in neither split, so primary + secondary can be below volume
+ALTER TABLE current_collection_volumes
+ADD COLUMN primary_volume NUMERIC NOT NULL DEFAULT 0,
+ADD COLUMN secondary_volume NUMERIC NOT NULL DEFAULT 0;
\ No newline at end of file
@@ -0,0 +1,2 @@
+-- This file should undo anything in `up.sql`
+DROP TABLE IF EXISTS current_wallet_nft_stats;
\ No newline at end of file
@@ -0,0 +1,16 @@
+-- Your SQL goes here
+-- trading stats per wallet from both sides of every sale. volumes are in octas and only count
+-- sales priced in APT, while counts include every sale
+CREATE TABLE current_wallet_nft_stats (
+  wallet_address VARCHAR(66) UNIQUE PRIMARY KEY NOT NULL,
+  buy_count BIGINT NOT NULL,
+  sell_count BIGINT NOT NULL,
+  buy_volume NUMERIC NOT NULL,
+  sell_volume NUMERIC NOT NULL,
+  first_trade_version BIGINT NOT NULL,
+  last_trade_version BIGINT NOT NULL,
+  inserted_at TIMESTAMP NOT NULL DEFAULT NOW()
+);
+CREATE INDEX cwns_bv_index ON current_wallet_nft_stats (buy_volume);
+CREATE INDEX cwns_sv_index ON current_wallet_nft_stats (sell_volume);
+CREATE INDEX cwns_insat_index ON current_wallet_nft_stats (inserted_at);
\ No newline at end of file
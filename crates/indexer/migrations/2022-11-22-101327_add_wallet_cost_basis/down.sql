@@ -0,0 +1,4 @@
+-- This file should undo anything in `up.sql`
+ALTER TABLE current_wallet_nft_stats DROP COLUMN IF EXISTS realized_pnl;
+ALTER TABLE nft_sales DROP COLUMN IF EXISTS realized_pnl;
+DROP TABLE IF EXISTS wallet_token_cost_basis;
\ No newline at end of file
@@ -0,0 +1,19 @@
+-- Your SQL goes here
+-- what a wallet last paid for a token (in octas, for `amount` of it). cost_basis is null when the
+-- token was received via transfer, bought with another coin or minted at an unknown price
+CREATE TABLE wallet_token_cost_basis (
+  wallet_address VARCHAR(66) NOT NULL,
+  token_data_id_hash VARCHAR(64) NOT NULL,
+  cost_basis NUMERIC,
+  amount NUMERIC NOT NULL,
+  last_transaction_version BIGINT NOT NULL,
+  inserted_at TIMESTAMP NOT NULL DEFAULT NOW(),
+  -- Constraints
+  PRIMARY KEY (wallet_address, token_data_id_hash)
+);
+CREATE INDEX wtcb_insat_index ON wallet_token_cost_basis (inserted_at);
+-- sale price minus the seller's cost basis of the amount sold, null if the basis is unknown
+ALTER TABLE nft_sales
+ADD COLUMN realized_pnl NUMERIC;
+ALTER TABLE current_wallet_nft_stats
+ADD COLUMN realized_pnl NUMERIC NOT NULL DEFAULT 0;
\ No newline at end of file
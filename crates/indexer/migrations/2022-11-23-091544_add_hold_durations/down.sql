@@ -0,0 +1,4 @@
+-- This file should undo anything in `up.sql`
+DROP TABLE IF EXISTS collection_hold_durations;
+ALTER TABLE nft_sales DROP COLUMN IF EXISTS hold_duration_secs;
+DROP TABLE IF EXISTS token_acquisitions;
\ No newline at end of file
@@ -0,0 +1,26 @@
+-- Your SQL goes here
+-- when an owner last received a token, by a deposit or as the buyer of a sale
+CREATE TABLE token_acquisitions (
+  token_data_id_hash VARCHAR(64) NOT NULL,
+  owner_address VARCHAR(66) NOT NULL,
+  acquired_at TIMESTAMP NOT NULL,
+  last_transaction_version BIGINT NOT NULL,
+  inserted_at TIMESTAMP NOT NULL DEFAULT NOW(),
+  -- Constraints
+  PRIMARY KEY (token_data_id_hash, owner_address)
+);
+CREATE INDEX tacq_insat_index ON token_acquisitions (inserted_at);
+-- seconds between the seller acquiring the token and the sale, null if the acquisition is from
+-- before indexing
+ALTER TABLE nft_sales
+ADD COLUMN hold_duration_secs BIGINT;
+-- recomputed from nft_sales for collections with sales in each batch
+CREATE TABLE collection_hold_durations (
+  collection_data_id_hash VARCHAR(64) UNIQUE PRIMARY KEY NOT NULL,
+  avg_hold_duration_secs DOUBLE PRECISION NOT NULL,
+  median_hold_duration_secs DOUBLE PRECISION NOT NULL,
+  sales_count BIGINT NOT NULL,
+  last_transaction_version BIGINT NOT NULL,
+  inserted_at TIMESTAMP NOT NULL DEFAULT NOW()
+);
+CREATE INDEX chd_insat_index ON collection_hold_durations (inserted_at);
\ No newline at end of file
@@ -0,0 +1,3 @@
+-- This file should undo anything in `up.sql`
+DROP TABLE IF EXISTS current_collection_best_offers;
+DROP TABLE IF EXISTS current_collection_offers;
\ No newline at end of file
@@ -0,0 +1,37 @@
+-- Your SQL goes here
+-- latest collection offer (bid) per buyer and marketplace
+CREATE TABLE current_collection_offers (
+  collection_data_id_hash VARCHAR(64) NOT NULL,
+  buyer VARCHAR(66) NOT NULL,
+  market_address VARCHAR(66) NOT NULL,
+  bid_id NUMERIC NOT NULL,
+  creator_address VARCHAR(66) NOT NULL,
+  collection_name VARCHAR(128) NOT NULL,
+  price NUMERIC NOT NULL,
+  amount_remaining NUMERIC NOT NULL,
+  coin_type VARCHAR(5000) NOT NULL,
+  -- unix seconds, offers past it stay active so filter on it for open offers
+  deadline NUMERIC NOT NULL,
+  -- active, cancelled or filled
+  status VARCHAR(16) NOT NULL,
+  last_transaction_version BIGINT NOT NULL,
+  last_transaction_timestamp TIMESTAMP NOT NULL,
+  inserted_at TIMESTAMP NOT NULL DEFAULT NOW(),
+  -- Constraints
+  PRIMARY KEY (collection_data_id_hash, buyer, market_address)
+);
+CREATE INDEX cco_status_deadline_index ON current_collection_offers (collection_data_id_hash, status, deadline);
+CREATE INDEX cco_buyer_index ON current_collection_offers (buyer);
+CREATE INDEX cco_insat_index ON current_collection_offers (inserted_at);
+-- highest active APT offer per collection, recomputed for collections with offer activity in each batch
+CREATE TABLE current_collection_best_offers (
+  collection_data_id_hash VARCHAR(64) UNIQUE PRIMARY KEY NOT NULL,
+  best_offer NUMERIC NOT NULL,
+  buyer VARCHAR(66) NOT NULL,
+  market_address VARCHAR(66) NOT NULL,
+  bid_id NUMERIC NOT NULL,
+  deadline NUMERIC NOT NULL,
+  last_transaction_version BIGINT NOT NULL,
+  inserted_at TIMESTAMP NOT NULL DEFAULT NOW()
+);
+CREATE INDEX ccbo_insat_index ON current_collection_best_offers (inserted_at);
\ No newline at end of file
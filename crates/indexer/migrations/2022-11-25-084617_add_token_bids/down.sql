@@ -0,0 +1,3 @@
+-- This file should undo anything in `up.sql`
+DROP TABLE IF EXISTS current_token_top_bids;
+DROP TABLE IF EXISTS current_token_bids;
\ No newline at end of file
@@ -0,0 +1,49 @@
+-- Your SQL goes here
+-- latest token bid per bidder and marketplace
+CREATE TABLE current_token_bids (
+  token_data_id_hash VARCHAR(64) NOT NULL,
+  property_version NUMERIC NOT NULL,
+  buyer VARCHAR(66) NOT NULL,
+  market_address VARCHAR(66) NOT NULL,
+  collection_data_id_hash VARCHAR(64) NOT NULL,
+  creator_address VARCHAR(66) NOT NULL,
+  collection_name VARCHAR(128) NOT NULL,
+  name VARCHAR(128) NOT NULL,
+  -- null for BlueMove auction bids
+  bid_id NUMERIC,
+  price NUMERIC NOT NULL,
+  amount_remaining NUMERIC NOT NULL,
+  coin_type VARCHAR(5000) NOT NULL,
+  -- unix seconds, null for BlueMove auction bids
+  deadline NUMERIC,
+  -- active, cancelled, filled or expired
+  status VARCHAR(16) NOT NULL,
+  last_transaction_version BIGINT NOT NULL,
+  last_transaction_timestamp TIMESTAMP NOT NULL,
+  inserted_at TIMESTAMP NOT NULL DEFAULT NOW(),
+  -- Constraints
+  PRIMARY KEY (
+    token_data_id_hash,
+    property_version,
+    buyer,
+    market_address
+  )
+);
+CREATE INDEX ctb_status_index ON current_token_bids (token_data_id_hash, status);
+CREATE INDEX ctb_collection_index ON current_token_bids (collection_data_id_hash);
+CREATE INDEX ctb_buyer_index ON current_token_bids (buyer);
+CREATE INDEX ctb_insat_index ON current_token_bids (inserted_at);
+-- highest active APT bid per token, recomputed for tokens with bid activity in each batch
+CREATE TABLE current_token_top_bids (
+  token_data_id_hash VARCHAR(64) NOT NULL,
+  property_version NUMERIC NOT NULL,
+  top_bid NUMERIC NOT NULL,
+  buyer VARCHAR(66) NOT NULL,
+  market_address VARCHAR(66) NOT NULL,
+  bid_id NUMERIC,
+  last_transaction_version BIGINT NOT NULL,
+  inserted_at TIMESTAMP NOT NULL DEFAULT NOW(),
+  -- Constraints
+  PRIMARY KEY (token_data_id_hash, property_version)
+);
+CREATE INDEX cttb_insat_index ON current_token_top_bids (inserted_at);
\ No newline at end of file
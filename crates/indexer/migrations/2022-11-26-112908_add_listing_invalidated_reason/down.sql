@@ -0,0 +1,2 @@
+-- This file should undo anything in `up.sql`
+ALTER TABLE current_marketplace_listings DROP COLUMN IF EXISTS invalidated_reason;
\ No newline at end of file
@@ -0,0 +1,5 @@
+-- Your SQL goes here
+-- set when a listing can never fill, ex: token_withdrawn when the seller moves the token of an
+-- escrowless listing
+ALTER TABLE current_marketplace_listings
+ADD COLUMN invalidated_reason VARCHAR(50);
\ No newline at end of file
@@ -0,0 +1,10 @@
+-- Your SQL goes here
+-- successfully processed versions per processor, with contiguous ranges merged on write
+CREATE TABLE processed_version_ranges (
+  processor VARCHAR(50) NOT NULL,
+  start_version BIGINT NOT NULL,
+  end_version BIGINT NOT NULL,
+  last_updated TIMESTAMP NOT NULL DEFAULT NOW(),
+  -- Constraints
+  PRIMARY KEY (processor, start_version)
+);
\ No newline at end of file
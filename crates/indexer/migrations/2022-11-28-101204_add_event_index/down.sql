@@ -0,0 +1,5 @@
+-- This file should undo anything in `up.sql`
+DROP INDEX IF EXISTS ta_version_event_index;
+ALTER TABLE token_activities DROP COLUMN IF EXISTS event_index;
+DROP INDEX IF EXISTS ns_version_event_index;
+ALTER TABLE nft_sales DROP COLUMN IF EXISTS event_index;
@@ -0,0 +1,9 @@
+-- Your SQL goes here
+-- position of the event within its transaction. Module events (event v2) have no guid, so this
+-- is what tells them apart. Null for rows indexed before it was added
+ALTER TABLE token_activities
+ADD COLUMN event_index BIGINT;
+CREATE UNIQUE INDEX ta_version_event_index ON token_activities (transaction_version, event_index);
+ALTER TABLE nft_sales
+ADD COLUMN event_index BIGINT;
+CREATE UNIQUE INDEX ns_version_event_index ON nft_sales (transaction_version, event_index);
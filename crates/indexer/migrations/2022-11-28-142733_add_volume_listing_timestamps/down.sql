@@ -0,0 +1,6 @@
+-- This file should undo anything in `up.sql`
+ALTER TABLE current_marketplace_listings DROP COLUMN IF EXISTS last_transaction_timestamp;
+ALTER TABLE current_collection_volumes DROP COLUMN IF EXISTS last_transaction_timestamp;
+ALTER TABLE collection_volumes DROP COLUMN IF EXISTS last_transaction_timestamp;
+ALTER TABLE current_token_volumes DROP COLUMN IF EXISTS last_transaction_timestamp;
+ALTER TABLE token_volumes DROP COLUMN IF EXISTS last_transaction_timestamp;
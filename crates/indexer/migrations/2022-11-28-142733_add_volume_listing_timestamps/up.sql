@@ -0,0 +1,59 @@
+-- Your SQL goes here
+-- block time of the last transaction that touched the row, inserted_at is kept for debugging
+ALTER TABLE current_marketplace_listings
+ADD COLUMN last_transaction_timestamp TIMESTAMP;
+ALTER TABLE current_collection_volumes
+ADD COLUMN last_transaction_timestamp TIMESTAMP;
+ALTER TABLE collection_volumes
+ADD COLUMN last_transaction_timestamp TIMESTAMP;
+ALTER TABLE current_token_volumes
+ADD COLUMN last_transaction_timestamp TIMESTAMP;
+ALTER TABLE token_volumes
+ADD COLUMN last_transaction_timestamp TIMESTAMP;
+-- Backfill from the activities of the same version. Rows without one fall back to inserted_at,
+-- which the processor has been setting from the transaction timestamp for these tables
+UPDATE current_marketplace_listings t
+SET last_transaction_timestamp = ta.transaction_timestamp
+FROM token_activities ta
+WHERE ta.transaction_version = t.last_transaction_version;
+UPDATE current_marketplace_listings
+SET last_transaction_timestamp = inserted_at
+WHERE last_transaction_timestamp IS NULL;
+ALTER TABLE current_marketplace_listings
+ALTER COLUMN last_transaction_timestamp SET NOT NULL;
+UPDATE current_collection_volumes t
+SET last_transaction_timestamp = ta.transaction_timestamp
+FROM token_activities ta
+WHERE ta.transaction_version = t.last_transaction_version;
+UPDATE current_collection_volumes
+SET last_transaction_timestamp = inserted_at
+WHERE last_transaction_timestamp IS NULL;
+ALTER TABLE current_collection_volumes
+ALTER COLUMN last_transaction_timestamp SET NOT NULL;
+UPDATE collection_volumes t
+SET last_transaction_timestamp = ta.transaction_timestamp
+FROM token_activities ta
+WHERE ta.transaction_version = t.last_transaction_version;
+UPDATE collection_volumes
+SET last_transaction_timestamp = inserted_at
+WHERE last_transaction_timestamp IS NULL;
+ALTER TABLE collection_volumes
+ALTER COLUMN last_transaction_timestamp SET NOT NULL;
+UPDATE current_token_volumes t
+SET last_transaction_timestamp = ta.transaction_timestamp
+FROM token_activities ta
+WHERE ta.transaction_version = t.last_transaction_version;
+UPDATE current_token_volumes
+SET last_transaction_timestamp = inserted_at
+WHERE last_transaction_timestamp IS NULL;
+ALTER TABLE current_token_volumes
+ALTER COLUMN last_transaction_timestamp SET NOT NULL;
+UPDATE token_volumes t
+SET last_transaction_timestamp = ta.transaction_timestamp
+FROM token_activities ta
+WHERE ta.transaction_version = t.last_transaction_version;
+UPDATE token_volumes
+SET last_transaction_timestamp = inserted_at
+WHERE last_transaction_timestamp IS NULL;
+ALTER TABLE token_volumes
+ALTER COLUMN last_transaction_timestamp SET NOT NULL;
@@ -0,0 +1,13 @@
+-- This file should undo anything in `up.sql`
+-- fails if reprocessing added duplicate guid events
+ALTER TABLE token_activities DROP CONSTRAINT IF EXISTS token_activities_pkey;
+ALTER TABLE token_activities
+ADD PRIMARY KEY (
+    transaction_version,
+    event_account_address,
+    event_creation_number,
+    event_sequence_number
+  );
+ALTER TABLE token_activities
+ALTER COLUMN event_index DROP NOT NULL;
+CREATE UNIQUE INDEX IF NOT EXISTS ta_version_event_index ON token_activities (transaction_version, event_index);
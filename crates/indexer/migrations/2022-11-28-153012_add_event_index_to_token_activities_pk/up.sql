@@ -0,0 +1,20 @@
+-- Your SQL goes here
+-- duplicate guid events in a transaction only differ by their position, so it has to be part of
+-- the key. Rows indexed before event_index get 0, reprocessing those versions can re-add the
+-- duplicates that were dropped
+DROP INDEX IF EXISTS ta_version_event_index;
+UPDATE token_activities
+SET event_index = 0
+WHERE event_index IS NULL;
+ALTER TABLE token_activities
+ALTER COLUMN event_index
+SET NOT NULL;
+ALTER TABLE token_activities DROP CONSTRAINT IF EXISTS token_activities_pkey;
+ALTER TABLE token_activities
+ADD PRIMARY KEY (
+    transaction_version,
+    event_account_address,
+    event_creation_number,
+    event_sequence_number,
+    event_index
+  );
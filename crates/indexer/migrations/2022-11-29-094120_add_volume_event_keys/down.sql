@@ -0,0 +1,19 @@
+-- This file should undo anything in `up.sql`
+-- keeps the first sale of every transaction
+DROP INDEX IF EXISTS cv_cdih_index;
+DELETE FROM collection_volumes a USING collection_volumes b
+WHERE a.last_transaction_version = b.last_transaction_version
+  AND a.event_index > b.event_index;
+ALTER TABLE collection_volumes DROP CONSTRAINT IF EXISTS collection_volumes_pkey;
+ALTER TABLE collection_volumes DROP COLUMN IF EXISTS event_index,
+  DROP COLUMN IF EXISTS is_primary;
+ALTER TABLE collection_volumes
+ADD PRIMARY KEY (last_transaction_version);
+DROP INDEX IF EXISTS tv_tdih_index;
+DELETE FROM token_volumes a USING token_volumes b
+WHERE a.last_transaction_version = b.last_transaction_version
+  AND a.event_index > b.event_index;
+ALTER TABLE token_volumes DROP CONSTRAINT IF EXISTS token_volumes_pkey;
+ALTER TABLE token_volumes DROP COLUMN IF EXISTS event_index;
+ALTER TABLE token_volumes
+ADD PRIMARY KEY (last_transaction_version);
@@ -0,0 +1,21 @@
+-- Your SQL goes here
+-- one row per sale instead of per transaction, so that current volumes can be recomputed from
+-- history. Rows written before only kept one sale per transaction and get event_index -1, the
+-- processor replaces them when their versions are reprocessed
+ALTER TABLE collection_volumes
+ADD COLUMN event_index BIGINT NOT NULL DEFAULT -1,
+  ADD COLUMN is_primary BOOLEAN NOT NULL DEFAULT FALSE;
+ALTER TABLE collection_volumes
+ALTER COLUMN event_index DROP DEFAULT;
+ALTER TABLE collection_volumes DROP CONSTRAINT IF EXISTS collection_volumes_pkey;
+ALTER TABLE collection_volumes
+ADD PRIMARY KEY (last_transaction_version, event_index);
+CREATE INDEX cv_cdih_index ON collection_volumes (collection_data_id_hash);
+ALTER TABLE token_volumes
+ADD COLUMN event_index BIGINT NOT NULL DEFAULT -1;
+ALTER TABLE token_volumes
+ALTER COLUMN event_index DROP DEFAULT;
+ALTER TABLE token_volumes DROP CONSTRAINT IF EXISTS token_volumes_pkey;
+ALTER TABLE token_volumes
+ADD PRIMARY KEY (last_transaction_version, event_index);
+CREATE INDEX tv_tdih_index ON token_volumes (token_data_id_hash);
@@ -0,0 +1,2 @@
+-- This file should undo anything in `up.sql`
+ALTER TABLE data_integrity_findings DROP COLUMN IF EXISTS offending_version;
@@ -0,0 +1,4 @@
+-- Your SQL goes here
+-- last version that wrote the checked row or its history, if the check can tell
+ALTER TABLE data_integrity_findings
+ADD COLUMN offending_version BIGINT;
@@ -0,0 +1,13 @@
+-- Your SQL goes here
+-- one row per batch of history deleted by pruning
+CREATE TABLE pruning_log (
+  table_name VARCHAR(100) NOT NULL,
+  pruned_at TIMESTAMP NOT NULL DEFAULT NOW(),
+  -- rows older than this were deleted
+  cutoff_timestamp TIMESTAMP NOT NULL,
+  -- latest version deleted by the batch
+  max_version BIGINT NOT NULL,
+  num_rows BIGINT NOT NULL,
+  -- Constraints
+  PRIMARY KEY (table_name, pruned_at)
+);
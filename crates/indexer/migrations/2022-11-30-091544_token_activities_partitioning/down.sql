@@ -0,0 +1,4 @@
+-- This file should undo anything in `up.sql`
+-- an already partitioned token_activities is left as is
+DROP PROCEDURE IF EXISTS partition_token_activities;
+DROP FUNCTION IF EXISTS create_token_activities_partition;
@@ -0,0 +1,89 @@
+-- Your SQL goes here
+-- token_activities stays a plain table unless partition_token_activities is called. Partitions are
+-- named token_activities_<start_version> and hold versions [start_version, end_version).
+CREATE OR REPLACE FUNCTION create_token_activities_partition(start_version BIGINT, end_version BIGINT)
+RETURNS VOID AS $$
+BEGIN
+  IF to_regclass('token_activities_' || start_version) IS NOT NULL THEN
+    RETURN;
+  END IF;
+  EXECUTE format(
+    'CREATE TABLE IF NOT EXISTS %I PARTITION OF token_activities FOR VALUES FROM (%s) TO (%s)',
+    'token_activities_' || start_version,
+    start_version,
+    end_version
+  );
+EXCEPTION
+  -- another processor task created it first
+  WHEN duplicate_table THEN NULL;
+END;
+$$ LANGUAGE plpgsql;
+
+-- Turns token_activities into a table partitioned by transaction_version, every partition_size
+-- versions, and moves the existing rows into it chunk_versions versions per transaction. Rows
+-- not moved yet stay readable in token_activities_unpartitioned, which is dropped at the end. If
+-- interrupted, CALL it again with the same arguments to resume. Must not be called from within
+-- a transaction, ex: psql -c "CALL partition_token_activities(10000000, 1000000)"
+CREATE OR REPLACE PROCEDURE partition_token_activities(
+  partition_size BIGINT,
+  chunk_versions BIGINT DEFAULT 1000000
+) AS $$
+DECLARE
+  index_defs TEXT[];
+  index_def TEXT;
+  pkey_def TEXT;
+  chunk_start BIGINT;
+  chunk_end BIGINT;
+  partition_start BIGINT;
+BEGIN
+  IF to_regclass('token_activities_unpartitioned') IS NULL THEN
+    IF EXISTS (
+      SELECT 1 FROM pg_partitioned_table WHERE partrelid = 'token_activities'::regclass
+    ) THEN
+      RAISE NOTICE 'token_activities is already partitioned';
+      RETURN;
+    END IF;
+    SELECT array_agg(indexdef) INTO index_defs FROM pg_indexes
+    WHERE schemaname = current_schema() AND tablename = 'token_activities'
+      AND indexname <> 'token_activities_pkey';
+    SELECT pg_get_constraintdef(oid) INTO pkey_def FROM pg_constraint
+    WHERE conrelid = 'token_activities'::regclass AND contype = 'p';
+
+    ALTER TABLE token_activities RENAME TO token_activities_unpartitioned;
+    ALTER TABLE token_activities_unpartitioned
+      RENAME CONSTRAINT token_activities_pkey TO token_activities_unpartitioned_pkey;
+    FOR index_def IN
+      SELECT indexname FROM pg_indexes
+      WHERE schemaname = current_schema() AND tablename = 'token_activities_unpartitioned'
+        AND indexname <> 'token_activities_unpartitioned_pkey'
+    LOOP
+      EXECUTE format('ALTER INDEX %I RENAME TO %I', index_def, index_def || '_unpartitioned');
+    END LOOP;
+
+    CREATE TABLE token_activities (LIKE token_activities_unpartitioned INCLUDING DEFAULTS)
+    PARTITION BY RANGE (transaction_version);
+    EXECUTE 'ALTER TABLE token_activities ADD ' || pkey_def;
+    FOREACH index_def IN ARRAY COALESCE(index_defs, ARRAY[]::TEXT[]) LOOP
+      EXECUTE index_def;
+    END LOOP;
+    COMMIT;
+  END IF;
+
+  LOOP
+    SELECT MIN(transaction_version) INTO chunk_start FROM token_activities_unpartitioned;
+    EXIT WHEN chunk_start IS NULL;
+    chunk_end := chunk_start + chunk_versions;
+    partition_start := chunk_start / partition_size * partition_size;
+    WHILE partition_start < chunk_end LOOP
+      PERFORM create_token_activities_partition(partition_start, partition_start + partition_size);
+      partition_start := partition_start + partition_size;
+    END LOOP;
+    INSERT INTO token_activities
+    SELECT * FROM token_activities_unpartitioned WHERE transaction_version < chunk_end
+    ON CONFLICT DO NOTHING;
+    DELETE FROM token_activities_unpartitioned WHERE transaction_version < chunk_end;
+    COMMIT;
+  END LOOP;
+  DROP TABLE token_activities_unpartitioned;
+END;
+$$ LANGUAGE plpgsql;
@@ -0,0 +1,7 @@
+-- Your SQL goes here
+-- collections hidden from owners' tokens unless spam is asked for, maintained by hand
+CREATE TABLE spam_collections (
+  collection_data_id_hash VARCHAR(64) PRIMARY KEY NOT NULL,
+  reason TEXT,
+  inserted_at TIMESTAMP NOT NULL DEFAULT NOW()
+);
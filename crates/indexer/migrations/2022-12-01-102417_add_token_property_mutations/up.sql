@@ -0,0 +1,26 @@
+-- Your SQL goes here
+-- one row per MutateTokenPropertyMapEvent. mutated_properties has the keys the event set, with
+-- decoded values, and new_token_properties the new token's whole property map if it was in the
+-- write set. The old map isn't in the transaction, it's the previous mutation's
+-- new_token_properties, or the token data's default_properties for property_version 0
+CREATE TABLE token_property_mutations (
+  transaction_version BIGINT NOT NULL,
+  event_index BIGINT NOT NULL,
+  old_token_data_id_hash VARCHAR(64) NOT NULL,
+  new_token_data_id_hash VARCHAR(64) NOT NULL,
+  old_property_version NUMERIC NOT NULL,
+  new_property_version NUMERIC NOT NULL,
+  collection_data_id_hash VARCHAR(64) NOT NULL,
+  creator_address VARCHAR(66) NOT NULL,
+  collection_name VARCHAR(128) NOT NULL,
+  name VARCHAR(128) NOT NULL,
+  mutated_properties JSONB NOT NULL,
+  new_token_properties JSONB NOT NULL,
+  transaction_timestamp TIMESTAMP NOT NULL,
+  inserted_at TIMESTAMP NOT NULL DEFAULT NOW(),
+  -- Constraints
+  PRIMARY KEY (transaction_version, event_index)
+);
+CREATE INDEX tpm_ntdih_index ON token_property_mutations (new_token_data_id_hash);
+CREATE INDEX tpm_cdih_tv_index ON token_property_mutations (collection_data_id_hash, transaction_version);
+CREATE INDEX tpm_insat_index ON token_property_mutations (inserted_at);
@@ -0,0 +1,22 @@
+-- Your SQL goes here
+-- the JSON behind current_token_datas.metadata_uri, maintained by the metadata fetcher.
+-- fetch_status is one of pending, fetched, failed (retried at next_fetch_at) or
+-- permanently_failed. content_hash is the sha256 of the fetched body
+CREATE TABLE token_metadata_cache (
+  token_data_id_hash VARCHAR(64) UNIQUE PRIMARY KEY NOT NULL,
+  metadata_uri VARCHAR(512) NOT NULL,
+  fetch_status VARCHAR(32) NOT NULL,
+  name TEXT,
+  description TEXT,
+  image TEXT,
+  attributes JSONB,
+  content_hash VARCHAR(64),
+  fetch_attempts BIGINT NOT NULL,
+  last_error TEXT,
+  next_fetch_at TIMESTAMP NOT NULL,
+  fetched_at TIMESTAMP,
+  last_transaction_version BIGINT NOT NULL,
+  inserted_at TIMESTAMP NOT NULL DEFAULT NOW()
+);
+CREATE INDEX tmc_fs_nfa_index ON token_metadata_cache (fetch_status, next_fetch_at);
+CREATE INDEX tmc_insat_index ON token_metadata_cache (inserted_at);
@@ -0,0 +1,5 @@
+-- This file should undo anything in `up.sql`
+DROP INDEX IF EXISTS curr_td_muc_index;
+DROP INDEX IF EXISTS curr_td_us_index;
+ALTER TABLE current_token_datas DROP COLUMN IF EXISTS metadata_uri_canonical,
+  DROP COLUMN IF EXISTS uri_scheme;
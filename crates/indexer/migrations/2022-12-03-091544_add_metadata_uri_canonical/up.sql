@@ -0,0 +1,9 @@
+-- Your SQL goes here
+-- metadata_uri stays the raw uri (truncated), the canonical form is ipfs://<cid>, ar://<id> or
+-- the parsed url, null when there's nothing to fetch. uri_scheme is invalid for uris that couldn't
+-- be parsed. Both are null for rows written before this until they're written again.
+ALTER TABLE current_token_datas
+ADD COLUMN metadata_uri_canonical VARCHAR(512),
+  ADD COLUMN uri_scheme VARCHAR(16);
+CREATE INDEX curr_td_muc_index ON current_token_datas (metadata_uri_canonical);
+CREATE INDEX curr_td_us_index ON current_token_datas (uri_scheme);
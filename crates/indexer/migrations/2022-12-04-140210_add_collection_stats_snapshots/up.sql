@@ -0,0 +1,25 @@
+-- Your SQL goes here
+-- one row per collection per day, overwritten until the day is over. The 24h columns cover the
+-- 24 hours before snapshot_at, or the whole day for backfilled rows. Columns without history
+-- (floor_price, listed_count, holder_count) are null on backfilled rows.
+CREATE TABLE collection_stats_snapshots (
+  collection_data_id_hash VARCHAR(64) NOT NULL,
+  snapshot_date DATE NOT NULL,
+  creator_address VARCHAR(66) NOT NULL,
+  collection_name VARCHAR(128) NOT NULL,
+  floor_price NUMERIC,
+  listed_count BIGINT,
+  holder_count BIGINT,
+  supply NUMERIC,
+  volume_24h NUMERIC NOT NULL,
+  sales_count_24h BIGINT NOT NULL,
+  average_sale_price_24h NUMERIC,
+  total_volume NUMERIC,
+  snapshot_at TIMESTAMP NOT NULL,
+  is_backfilled BOOLEAN NOT NULL,
+  inserted_at TIMESTAMP NOT NULL DEFAULT NOW(),
+  -- Constraints
+  PRIMARY KEY (collection_data_id_hash, snapshot_date)
+);
+CREATE INDEX css_sd_index ON collection_stats_snapshots (snapshot_date);
+CREATE INDEX css_insat_index ON collection_stats_snapshots (inserted_at);
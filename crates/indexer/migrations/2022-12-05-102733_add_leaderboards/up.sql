@@ -0,0 +1,35 @@
+-- Your SQL goes here
+-- rebuilt as a whole by the token processor. A collection is kept if it's in the top n of any of
+-- the three rankings, and its rank is null in the rankings where it had no volume.
+CREATE TABLE collection_leaderboard (
+  collection_data_id_hash VARCHAR(64) NOT NULL,
+  creator_address VARCHAR(66) NOT NULL,
+  collection_name VARCHAR(128) NOT NULL,
+  volume_24h NUMERIC NOT NULL,
+  volume_7d NUMERIC NOT NULL,
+  total_volume NUMERIC NOT NULL,
+  rank_24h BIGINT,
+  rank_7d BIGINT,
+  rank_all_time BIGINT,
+  refreshed_at TIMESTAMP NOT NULL,
+  inserted_at TIMESTAMP NOT NULL DEFAULT NOW(),
+  -- Constraints
+  PRIMARY KEY (collection_data_id_hash)
+);
+CREATE INDEX cl_r24_index ON collection_leaderboard (rank_24h);
+CREATE INDEX cl_r7_index ON collection_leaderboard (rank_7d);
+CREATE INDEX cl_rat_index ON collection_leaderboard (rank_all_time);
+-- top n traders by volume bought and sold in the 24 hours before refreshed_at
+CREATE TABLE trader_leaderboard (
+  trader_address VARCHAR(66) NOT NULL,
+  volume_24h NUMERIC NOT NULL,
+  bought_volume_24h NUMERIC NOT NULL,
+  sold_volume_24h NUMERIC NOT NULL,
+  trades_24h BIGINT NOT NULL,
+  rank BIGINT NOT NULL,
+  refreshed_at TIMESTAMP NOT NULL,
+  inserted_at TIMESTAMP NOT NULL DEFAULT NOW(),
+  -- Constraints
+  PRIMARY KEY (trader_address)
+);
+CREATE INDEX tl_rank_index ON trader_leaderboard (rank);
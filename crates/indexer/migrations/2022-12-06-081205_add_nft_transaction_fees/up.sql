@@ -0,0 +1,20 @@
+-- Your SQL goes here
+-- one row per user transaction with at least one token activity. gas_fee is
+-- gas_used * gas_unit_price in octas, market_address the module of its first marketplace event
+CREATE TABLE nft_transaction_fees (
+  transaction_version BIGINT NOT NULL,
+  sender VARCHAR(66) NOT NULL,
+  gas_used NUMERIC NOT NULL,
+  gas_unit_price NUMERIC NOT NULL,
+  gas_fee NUMERIC NOT NULL,
+  num_token_events BIGINT NOT NULL,
+  market_address VARCHAR(66),
+  transaction_timestamp TIMESTAMP NOT NULL,
+  inserted_at TIMESTAMP NOT NULL DEFAULT NOW(),
+  -- Constraints
+  PRIMARY KEY (transaction_version)
+);
+CREATE INDEX ntf_sender_index ON nft_transaction_fees (sender);
+CREATE INDEX ntf_ma_tt_index ON nft_transaction_fees (market_address, transaction_timestamp);
+CREATE INDEX ntf_tt_index ON nft_transaction_fees (transaction_timestamp);
+CREATE INDEX ntf_insat_index ON nft_transaction_fees (inserted_at);
@@ -0,0 +1,5 @@
+-- Your SQL goes here
+-- 'event' for sales parsed from marketplace events, 'payload_inferred' for sales inferred from the
+-- entry function of markets that don't emit any
+ALTER TABLE nft_sales
+ADD COLUMN source VARCHAR(32) NOT NULL DEFAULT 'event';
\ No newline at end of file
@@ -0,0 +1,2 @@
+-- This file should undo anything in `up.sql`
+ALTER TABLE nft_sales DROP COLUMN IF EXISTS settlement_amount;
\ No newline at end of file
@@ -0,0 +1,5 @@
+-- Your SQL goes here
+-- coins the buyer withdrew in the sale's transaction, split by price across the buyer's sales.
+-- null unless record_settlement_amounts is set, or when the withdrawals can't be tied to the sales
+ALTER TABLE nft_sales
+ADD COLUMN settlement_amount NUMERIC;
\ No newline at end of file
@@ -0,0 +1,6 @@
+-- This file should undo anything in `up.sql`
+ALTER TABLE current_collection_volumes DROP COLUMN IF EXISTS volume_usd;
+ALTER TABLE collection_volumes DROP COLUMN IF EXISTS volume_usd;
+ALTER TABLE nft_sales DROP COLUMN IF EXISTS coin_price_usd,
+  DROP COLUMN IF EXISTS price_usd;
+DROP TABLE IF EXISTS coin_prices;
@@ -0,0 +1,20 @@
+-- Your SQL goes here
+-- usd prices of coins, appended by update-coin-prices or by hand. coin_type is in the form
+-- 0x1::aptos_coin::AptosCoin and decimals are those of the coin's raw amounts (8 for APT)
+CREATE TABLE coin_prices (
+  coin_type VARCHAR(5000) NOT NULL,
+  price_usd NUMERIC NOT NULL,
+  decimals INT NOT NULL,
+  as_of TIMESTAMP NOT NULL,
+  inserted_at TIMESTAMP NOT NULL DEFAULT NOW(),
+  PRIMARY KEY (coin_type, as_of)
+);
+-- the latest coin price when the sale was processed, null if the coin had none
+ALTER TABLE nft_sales
+ADD COLUMN coin_price_usd NUMERIC,
+  ADD COLUMN price_usd NUMERIC;
+-- sum of the sales' price_usd, null while none of them had a price
+ALTER TABLE collection_volumes
+ADD COLUMN volume_usd NUMERIC;
+ALTER TABLE current_collection_volumes
+ADD COLUMN volume_usd NUMERIC;
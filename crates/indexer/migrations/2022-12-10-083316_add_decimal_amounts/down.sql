@@ -0,0 +1,8 @@
+-- This file should undo anything in `up.sql`
+ALTER TABLE current_token_volumes DROP COLUMN IF EXISTS volume_decimal;
+ALTER TABLE token_volumes DROP COLUMN IF EXISTS volume_decimal;
+ALTER TABLE current_collection_volumes DROP COLUMN IF EXISTS volume_decimal;
+ALTER TABLE collection_volumes DROP COLUMN IF EXISTS volume_decimal;
+ALTER TABLE current_marketplace_listings DROP COLUMN IF EXISTS price_decimal;
+ALTER TABLE nft_sales DROP COLUMN IF EXISTS price_decimal;
+DROP TABLE IF EXISTS coin_decimals;
@@ -0,0 +1,31 @@
+-- Your SQL goes here
+-- decimals of coins sales can be paid in, for when the coin processor hasn't filled coin_infos.
+-- coin_infos wins for coins in both
+CREATE TABLE coin_decimals (
+  coin_type VARCHAR(5000) PRIMARY KEY NOT NULL,
+  decimals INT NOT NULL,
+  inserted_at TIMESTAMP NOT NULL DEFAULT NOW()
+);
+INSERT INTO coin_decimals (coin_type, decimals)
+VALUES ('0x1::aptos_coin::AptosCoin', 8),
+  (
+    '0xf22bede237a07e121b56d91a491eb7bcdfd1f5907926a9e58338f964a01b17fa::asset::USDC',
+    6
+  ),
+  (
+    '0xf22bede237a07e121b56d91a491eb7bcdfd1f5907926a9e58338f964a01b17fa::asset::USDT',
+    6
+  );
+-- raw amounts divided by 10^decimals of their coin, null when the decimals aren't known
+ALTER TABLE nft_sales
+ADD COLUMN price_decimal NUMERIC;
+ALTER TABLE current_marketplace_listings
+ADD COLUMN price_decimal NUMERIC;
+ALTER TABLE collection_volumes
+ADD COLUMN volume_decimal NUMERIC;
+ALTER TABLE current_collection_volumes
+ADD COLUMN volume_decimal NUMERIC;
+ALTER TABLE token_volumes
+ADD COLUMN volume_decimal NUMERIC;
+ALTER TABLE current_token_volumes
+ADD COLUMN volume_decimal NUMERIC;
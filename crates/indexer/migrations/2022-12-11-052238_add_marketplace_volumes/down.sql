@@ -0,0 +1,6 @@
+-- This file should undo anything in `up.sql`
+DROP TABLE IF EXISTS marketplace_collection_volumes;
+DROP TABLE IF EXISTS marketplace_volumes;
+DROP TABLE IF EXISTS current_marketplace_volumes;
+ALTER TABLE collection_volumes DROP COLUMN IF EXISTS market_address,
+  DROP COLUMN IF EXISTS coin_type;
@@ -0,0 +1,48 @@
+-- Your SQL goes here
+-- marketplace and coin of the sale, so the marketplace volumes are counted from the same rows
+ALTER TABLE collection_volumes
+ADD COLUMN market_address VARCHAR(66),
+  ADD COLUMN coin_type VARCHAR(5000);
+CREATE TABLE current_marketplace_volumes (
+  market_address VARCHAR(66) NOT NULL,
+  coin_type VARCHAR(5000) NOT NULL,
+  volume NUMERIC NOT NULL,
+  trade_count BIGINT NOT NULL,
+  volume_usd NUMERIC,
+  volume_decimal NUMERIC,
+  last_transaction_version BIGINT NOT NULL,
+  last_transaction_timestamp TIMESTAMP NOT NULL,
+  inserted_at TIMESTAMP NOT NULL DEFAULT NOW(),
+  -- Constraints
+  PRIMARY KEY (market_address, coin_type)
+);
+CREATE TABLE marketplace_volumes (
+  market_address VARCHAR(66) NOT NULL,
+  coin_type VARCHAR(5000) NOT NULL,
+  volume_date DATE NOT NULL,
+  volume NUMERIC NOT NULL,
+  trade_count BIGINT NOT NULL,
+  volume_usd NUMERIC,
+  volume_decimal NUMERIC,
+  last_transaction_version BIGINT NOT NULL,
+  last_transaction_timestamp TIMESTAMP NOT NULL,
+  inserted_at TIMESTAMP NOT NULL DEFAULT NOW(),
+  -- Constraints
+  PRIMARY KEY (market_address, coin_type, volume_date)
+);
+CREATE INDEX mv_vd_index ON marketplace_volumes (volume_date);
+CREATE TABLE marketplace_collection_volumes (
+  market_address VARCHAR(66) NOT NULL,
+  collection_data_id_hash VARCHAR(64) NOT NULL,
+  coin_type VARCHAR(5000) NOT NULL,
+  volume NUMERIC NOT NULL,
+  trade_count BIGINT NOT NULL,
+  volume_usd NUMERIC,
+  volume_decimal NUMERIC,
+  last_transaction_version BIGINT NOT NULL,
+  last_transaction_timestamp TIMESTAMP NOT NULL,
+  inserted_at TIMESTAMP NOT NULL DEFAULT NOW(),
+  -- Constraints
+  PRIMARY KEY (market_address, collection_data_id_hash, coin_type)
+);
+CREATE INDEX mcv_cdh_index ON marketplace_collection_volumes (collection_data_id_hash);
@@ -0,0 +1,4 @@
+-- This file should undo anything in `up.sql`
+DROP INDEX IF EXISTS ns_sweep_index;
+ALTER TABLE nft_sales DROP COLUMN IF EXISTS sale_group_id,
+  DROP COLUMN IF EXISTS group_size;
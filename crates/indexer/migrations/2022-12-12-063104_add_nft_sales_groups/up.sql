@@ -0,0 +1,20 @@
+-- Your SQL goes here
+-- sales in the same transaction, ex: a sweep, share a group. group_size is the number of sales in it
+ALTER TABLE nft_sales
+ADD COLUMN sale_group_id BIGINT,
+  ADD COLUMN group_size BIGINT NOT NULL DEFAULT 1;
+UPDATE nft_sales
+SET sale_group_id = nft_sales.transaction_version,
+  group_size = groups.group_size
+FROM (
+    SELECT transaction_version,
+      COUNT(*) AS group_size
+    FROM nft_sales
+    GROUP BY transaction_version
+  ) groups
+WHERE groups.transaction_version = nft_sales.transaction_version;
+ALTER TABLE nft_sales
+ALTER COLUMN sale_group_id
+SET NOT NULL;
+CREATE INDEX ns_sweep_index ON nft_sales (sale_group_id)
+WHERE group_size > 1;
@@ -0,0 +1,4 @@
+-- This file should undo anything in `up.sql`
+DROP INDEX IF EXISTS curr_to_bo_index;
+ALTER TABLE current_token_ownerships DROP COLUMN IF EXISTS owner_type,
+  DROP COLUMN IF EXISTS beneficial_owner;
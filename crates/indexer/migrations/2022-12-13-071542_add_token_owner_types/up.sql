@@ -0,0 +1,8 @@
+-- Your SQL goes here
+-- user, marketplace_escrow or unknown_contract. beneficial_owner is the seller of the active listing
+-- for tokens in a marketplace's escrow
+ALTER TABLE current_token_ownerships
+ADD COLUMN owner_type VARCHAR(32) NOT NULL DEFAULT 'user',
+  ADD COLUMN beneficial_owner VARCHAR(66);
+CREATE INDEX curr_to_bo_index ON current_token_ownerships (beneficial_owner)
+WHERE beneficial_owner IS NOT NULL;
@@ -0,0 +1,28 @@
+-- Your SQL goes here
+-- offers from 0x3::token_transfers, maintained from the offer, claim and cancel events
+CREATE TABLE current_token_transfer_offers (
+  token_data_id_hash VARCHAR(64) NOT NULL,
+  property_version NUMERIC NOT NULL,
+  from_address VARCHAR(66) NOT NULL,
+  to_address VARCHAR(66) NOT NULL,
+  collection_data_id_hash VARCHAR(64) NOT NULL,
+  creator_address VARCHAR(66) NOT NULL,
+  collection_name VARCHAR(128) NOT NULL,
+  name VARCHAR(128) NOT NULL,
+  amount NUMERIC NOT NULL,
+  -- pending, claimed or cancelled
+  status VARCHAR(16) NOT NULL,
+  last_transaction_version BIGINT NOT NULL,
+  last_transaction_timestamp TIMESTAMP NOT NULL,
+  inserted_at TIMESTAMP NOT NULL DEFAULT NOW(),
+  -- Constraints
+  PRIMARY KEY (
+    token_data_id_hash,
+    property_version,
+    from_address,
+    to_address
+  )
+);
+CREATE INDEX ctto_ta_s_index ON current_token_transfer_offers (to_address, status);
+CREATE INDEX ctto_fa_s_index ON current_token_transfer_offers (from_address, status);
+CREATE INDEX ctto_insat_index ON current_token_transfer_offers (inserted_at);
@@ -0,0 +1,39 @@
+-- This file should undo anything in `up.sql`
+-- keeps the first token of every sweep
+DELETE FROM token_volumes
+WHERE token_index > 0;
+ALTER TABLE token_volumes DROP CONSTRAINT IF EXISTS token_volumes_pkey;
+ALTER TABLE token_volumes DROP COLUMN IF EXISTS token_index;
+ALTER TABLE token_volumes
+ADD PRIMARY KEY (last_transaction_version, event_index);
+DELETE FROM collection_volumes
+WHERE token_index > 0;
+ALTER TABLE collection_volumes DROP CONSTRAINT IF EXISTS collection_volumes_pkey;
+ALTER TABLE collection_volumes DROP COLUMN IF EXISTS token_index;
+ALTER TABLE collection_volumes
+ADD PRIMARY KEY (last_transaction_version, event_index);
+DELETE FROM nft_sales
+WHERE token_index > 0;
+DROP INDEX IF EXISTS ns_version_event_index;
+ALTER TABLE nft_sales DROP CONSTRAINT IF EXISTS nft_sales_pkey;
+ALTER TABLE nft_sales DROP COLUMN IF EXISTS token_index;
+ALTER TABLE nft_sales
+ADD PRIMARY KEY (
+    transaction_version,
+    event_account_address,
+    event_creation_number,
+    event_sequence_number
+  );
+CREATE UNIQUE INDEX ns_version_event_index ON nft_sales (transaction_version, event_index);
+DELETE FROM token_activities
+WHERE token_index > 0;
+ALTER TABLE token_activities DROP CONSTRAINT IF EXISTS token_activities_pkey;
+ALTER TABLE token_activities DROP COLUMN IF EXISTS token_index;
+ALTER TABLE token_activities
+ADD PRIMARY KEY (
+    transaction_version,
+    event_account_address,
+    event_creation_number,
+    event_sequence_number,
+    event_index
+  );
@@ -0,0 +1,38 @@
+-- Your SQL goes here
+-- a sweep event sells several tokens, each of them gets its own row keyed by its position in the
+-- event. Every other event has token_index 0
+ALTER TABLE token_activities
+ADD COLUMN token_index BIGINT NOT NULL DEFAULT 0;
+ALTER TABLE token_activities DROP CONSTRAINT IF EXISTS token_activities_pkey;
+ALTER TABLE token_activities
+ADD PRIMARY KEY (
+    transaction_version,
+    event_account_address,
+    event_creation_number,
+    event_sequence_number,
+    event_index,
+    token_index
+  );
+ALTER TABLE nft_sales
+ADD COLUMN token_index BIGINT NOT NULL DEFAULT 0;
+ALTER TABLE nft_sales DROP CONSTRAINT IF EXISTS nft_sales_pkey;
+ALTER TABLE nft_sales
+ADD PRIMARY KEY (
+    transaction_version,
+    event_account_address,
+    event_creation_number,
+    event_sequence_number,
+    token_index
+  );
+DROP INDEX IF EXISTS ns_version_event_index;
+CREATE UNIQUE INDEX ns_version_event_index ON nft_sales (transaction_version, event_index, token_index);
+ALTER TABLE collection_volumes
+ADD COLUMN token_index BIGINT NOT NULL DEFAULT 0;
+ALTER TABLE collection_volumes DROP CONSTRAINT IF EXISTS collection_volumes_pkey;
+ALTER TABLE collection_volumes
+ADD PRIMARY KEY (last_transaction_version, event_index, token_index);
+ALTER TABLE token_volumes
+ADD COLUMN token_index BIGINT NOT NULL DEFAULT 0;
+ALTER TABLE token_volumes DROP CONSTRAINT IF EXISTS token_volumes_pkey;
+ALTER TABLE token_volumes
+ADD PRIMARY KEY (last_transaction_version, event_index, token_index);
@@ -0,0 +1,6 @@
+-- This file should undo anything in `up.sql`
+DROP INDEX IF EXISTS ctb_active_expires_at_index;
+ALTER TABLE current_token_bids DROP COLUMN IF EXISTS expires_at;
+DROP INDEX IF EXISTS cco_active_expires_at_index;
+ALTER TABLE current_collection_offers DROP COLUMN IF EXISTS expires_at;
+ALTER TABLE processor_status DROP COLUMN IF EXISTS last_transaction_timestamp;
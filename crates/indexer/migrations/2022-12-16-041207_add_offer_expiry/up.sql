@@ -0,0 +1,23 @@
+-- Your SQL goes here
+-- chain time of the latest transaction processed, offers expire against it rather than the wall
+-- clock so that backfills expire the same offers
+ALTER TABLE processor_status
+ADD COLUMN last_transaction_timestamp TIMESTAMP;
+-- deadline as a timestamp, capped at 9999-12-31
+ALTER TABLE current_collection_offers
+ADD COLUMN expires_at TIMESTAMP;
+UPDATE current_collection_offers
+SET expires_at = to_timestamp(LEAST(deadline, 253402300799)) AT TIME ZONE 'UTC';
+ALTER TABLE current_collection_offers
+ALTER COLUMN expires_at
+SET NOT NULL;
+CREATE INDEX cco_active_expires_at_index ON current_collection_offers (expires_at)
+WHERE status = 'active';
+-- BlueMove auction bids have no deadline
+ALTER TABLE current_token_bids
+ADD COLUMN expires_at TIMESTAMP;
+UPDATE current_token_bids
+SET expires_at = to_timestamp(LEAST(deadline, 253402300799)) AT TIME ZONE 'UTC'
+WHERE deadline IS NOT NULL;
+CREATE INDEX ctb_active_expires_at_index ON current_token_bids (expires_at)
+WHERE status = 'active';
@@ -0,0 +1,22 @@
+-- Your SQL goes here
+-- repricings of active listings, from BlueMove's ChangePriceEvent and from list events relisting
+-- a token already listed on the same marketplace. old_price is null when the earlier price isn't
+-- known, ex: a listing from before the indexer started
+CREATE TABLE marketplace_listing_price_changes (
+  transaction_version BIGINT NOT NULL,
+  event_index BIGINT NOT NULL,
+  token_data_id_hash VARCHAR(64) NOT NULL,
+  property_version NUMERIC NOT NULL,
+  collection_data_id_hash VARCHAR(64) NOT NULL,
+  market_address VARCHAR(66) NOT NULL,
+  seller VARCHAR(66) NOT NULL,
+  old_price NUMERIC,
+  new_price NUMERIC NOT NULL,
+  transaction_timestamp TIMESTAMP NOT NULL,
+  inserted_at TIMESTAMP NOT NULL DEFAULT NOW(),
+  -- Constraints
+  PRIMARY KEY (transaction_version, event_index)
+);
+CREATE INDEX mlpc_tdih_tv_index ON marketplace_listing_price_changes (token_data_id_hash, transaction_version);
+CREATE INDEX mlpc_cdih_index ON marketplace_listing_price_changes (collection_data_id_hash);
+CREATE INDEX mlpc_insat_index ON marketplace_listing_price_changes (inserted_at);
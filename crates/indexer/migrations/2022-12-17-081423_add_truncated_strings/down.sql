@@ -0,0 +1,59 @@
+-- This file should undo anything in `up.sql`
+DROP TABLE IF EXISTS truncated_strings;
+-- Longer values are cut back to the default limits
+ALTER TABLE collection_datas
+ALTER COLUMN collection_name TYPE VARCHAR(128) USING left(collection_name, 128),
+  ALTER COLUMN metadata_uri TYPE VARCHAR(512) USING left(metadata_uri, 512);
+ALTER TABLE collection_leaderboard
+ALTER COLUMN collection_name TYPE VARCHAR(128) USING left(collection_name, 128);
+ALTER TABLE collection_mints
+ALTER COLUMN collection_name TYPE VARCHAR(128) USING left(collection_name, 128),
+  ALTER COLUMN name TYPE VARCHAR(128) USING left(name, 128);
+ALTER TABLE collection_stats_snapshots
+ALTER COLUMN collection_name TYPE VARCHAR(128) USING left(collection_name, 128);
+ALTER TABLE current_collection_datas
+ALTER COLUMN collection_name TYPE VARCHAR(128) USING left(collection_name, 128),
+  ALTER COLUMN metadata_uri TYPE VARCHAR(512) USING left(metadata_uri, 512);
+ALTER TABLE current_collection_offers
+ALTER COLUMN collection_name TYPE VARCHAR(128) USING left(collection_name, 128);
+ALTER TABLE current_marketplace_listings
+ALTER COLUMN collection_name TYPE VARCHAR(128) USING left(collection_name, 128),
+  ALTER COLUMN name TYPE VARCHAR(128) USING left(name, 128);
+ALTER TABLE current_token_bids
+ALTER COLUMN collection_name TYPE VARCHAR(128) USING left(collection_name, 128),
+  ALTER COLUMN name TYPE VARCHAR(128) USING left(name, 128);
+ALTER TABLE current_token_datas
+ALTER COLUMN collection_name TYPE VARCHAR(128) USING left(collection_name, 128),
+  ALTER COLUMN name TYPE VARCHAR(128) USING left(name, 128),
+  ALTER COLUMN metadata_uri TYPE VARCHAR(512) USING left(metadata_uri, 512),
+  ALTER COLUMN metadata_uri_canonical TYPE VARCHAR(512) USING left(metadata_uri_canonical, 512);
+ALTER TABLE current_token_ownerships
+ALTER COLUMN collection_name TYPE VARCHAR(128) USING left(collection_name, 128),
+  ALTER COLUMN name TYPE VARCHAR(128) USING left(name, 128);
+ALTER TABLE current_token_pending_claims
+ALTER COLUMN collection_name TYPE VARCHAR(128) USING left(collection_name, 128),
+  ALTER COLUMN name TYPE VARCHAR(128) USING left(name, 128);
+ALTER TABLE current_token_transfer_offers
+ALTER COLUMN collection_name TYPE VARCHAR(128) USING left(collection_name, 128),
+  ALTER COLUMN name TYPE VARCHAR(128) USING left(name, 128);
+ALTER TABLE nft_sales
+ALTER COLUMN collection_name TYPE VARCHAR(128) USING left(collection_name, 128),
+  ALTER COLUMN name TYPE VARCHAR(128) USING left(name, 128);
+ALTER TABLE token_activities
+ALTER COLUMN collection_name TYPE VARCHAR(128) USING left(collection_name, 128),
+  ALTER COLUMN name TYPE VARCHAR(128) USING left(name, 128);
+ALTER TABLE token_datas
+ALTER COLUMN collection_name TYPE VARCHAR(128) USING left(collection_name, 128),
+  ALTER COLUMN name TYPE VARCHAR(128) USING left(name, 128),
+  ALTER COLUMN metadata_uri TYPE VARCHAR(512) USING left(metadata_uri, 512);
+ALTER TABLE token_metadata_cache
+ALTER COLUMN metadata_uri TYPE VARCHAR(512) USING left(metadata_uri, 512);
+ALTER TABLE token_ownerships
+ALTER COLUMN collection_name TYPE VARCHAR(128) USING left(collection_name, 128),
+  ALTER COLUMN name TYPE VARCHAR(128) USING left(name, 128);
+ALTER TABLE token_property_mutations
+ALTER COLUMN collection_name TYPE VARCHAR(128) USING left(collection_name, 128),
+  ALTER COLUMN name TYPE VARCHAR(128) USING left(name, 128);
+ALTER TABLE tokens
+ALTER COLUMN collection_name TYPE VARCHAR(128) USING left(collection_name, 128),
+  ALTER COLUMN name TYPE VARCHAR(128) USING left(name, 128);
@@ -0,0 +1,72 @@
+-- Your SQL goes here
+-- Room for the limits of the string_limits config, at most 512 characters for names and 2048
+-- for uris. Widening a VARCHAR doesn't rewrite the table.
+ALTER TABLE collection_datas
+ALTER COLUMN collection_name TYPE VARCHAR(512),
+  ALTER COLUMN metadata_uri TYPE VARCHAR(2048);
+ALTER TABLE collection_leaderboard
+ALTER COLUMN collection_name TYPE VARCHAR(512);
+ALTER TABLE collection_mints
+ALTER COLUMN collection_name TYPE VARCHAR(512),
+  ALTER COLUMN name TYPE VARCHAR(512);
+ALTER TABLE collection_stats_snapshots
+ALTER COLUMN collection_name TYPE VARCHAR(512);
+ALTER TABLE current_collection_datas
+ALTER COLUMN collection_name TYPE VARCHAR(512),
+  ALTER COLUMN metadata_uri TYPE VARCHAR(2048);
+ALTER TABLE current_collection_offers
+ALTER COLUMN collection_name TYPE VARCHAR(512);
+ALTER TABLE current_marketplace_listings
+ALTER COLUMN collection_name TYPE VARCHAR(512),
+  ALTER COLUMN name TYPE VARCHAR(512);
+ALTER TABLE current_token_bids
+ALTER COLUMN collection_name TYPE VARCHAR(512),
+  ALTER COLUMN name TYPE VARCHAR(512);
+ALTER TABLE current_token_datas
+ALTER COLUMN collection_name TYPE VARCHAR(512),
+  ALTER COLUMN name TYPE VARCHAR(512),
+  ALTER COLUMN metadata_uri TYPE VARCHAR(2048),
+  ALTER COLUMN metadata_uri_canonical TYPE VARCHAR(2048);
+ALTER TABLE current_token_ownerships
+ALTER COLUMN collection_name TYPE VARCHAR(512),
+  ALTER COLUMN name TYPE VARCHAR(512);
+ALTER TABLE current_token_pending_claims
+ALTER COLUMN collection_name TYPE VARCHAR(512),
+  ALTER COLUMN name TYPE VARCHAR(512);
+ALTER TABLE current_token_transfer_offers
+ALTER COLUMN collection_name TYPE VARCHAR(512),
+  ALTER COLUMN name TYPE VARCHAR(512);
+ALTER TABLE nft_sales
+ALTER COLUMN collection_name TYPE VARCHAR(512),
+  ALTER COLUMN name TYPE VARCHAR(512);
+ALTER TABLE token_activities
+ALTER COLUMN collection_name TYPE VARCHAR(512),
+  ALTER COLUMN name TYPE VARCHAR(512);
+ALTER TABLE token_datas
+ALTER COLUMN collection_name TYPE VARCHAR(512),
+  ALTER COLUMN name TYPE VARCHAR(512),
+  ALTER COLUMN metadata_uri TYPE VARCHAR(2048);
+ALTER TABLE token_metadata_cache
+ALTER COLUMN metadata_uri TYPE VARCHAR(2048);
+ALTER TABLE token_ownerships
+ALTER COLUMN collection_name TYPE VARCHAR(512),
+  ALTER COLUMN name TYPE VARCHAR(512);
+ALTER TABLE token_property_mutations
+ALTER COLUMN collection_name TYPE VARCHAR(512),
+  ALTER COLUMN name TYPE VARCHAR(512);
+ALTER TABLE tokens
+ALTER COLUMN collection_name TYPE VARCHAR(512),
+  ALTER COLUMN name TYPE VARCHAR(512);
+-- The whole values of names and uris longer than the limits they were truncated to. field is
+-- collection_name or metadata_uri for a collection, keyed by collection_data_id_hash, and name or
+-- metadata_uri for a token, keyed by token_data_id_hash.
+CREATE TABLE truncated_strings (
+  hash VARCHAR(64) NOT NULL,
+  field VARCHAR(32) NOT NULL,
+  full_value TEXT NOT NULL,
+  last_transaction_version BIGINT NOT NULL,
+  inserted_at TIMESTAMP NOT NULL DEFAULT NOW(),
+  -- Constraints
+  PRIMARY KEY (hash, field)
+);
+CREATE INDEX ts_insat_index ON truncated_strings (inserted_at);
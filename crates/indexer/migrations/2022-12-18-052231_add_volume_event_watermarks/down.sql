@@ -0,0 +1,6 @@
+-- This file should undo anything in `up.sql`
+ALTER TABLE current_collection_volumes DROP COLUMN IF EXISTS last_event_index;
+ALTER TABLE current_token_volumes DROP COLUMN IF EXISTS last_event_index;
+ALTER TABLE current_marketplace_volumes DROP COLUMN IF EXISTS last_event_index;
+ALTER TABLE marketplace_volumes DROP COLUMN IF EXISTS last_event_index;
+ALTER TABLE marketplace_collection_volumes DROP COLUMN IF EXISTS last_event_index;
@@ -0,0 +1,14 @@
+-- Your SQL goes here
+-- the event index of the latest sale added to each additive volume row, so together with
+-- last_transaction_version it marks the last event counted. Null on rows from before it was
+-- tracked, which counted every event of their last_transaction_version
+ALTER TABLE current_collection_volumes
+ADD COLUMN last_event_index BIGINT;
+ALTER TABLE current_token_volumes
+ADD COLUMN last_event_index BIGINT;
+ALTER TABLE current_marketplace_volumes
+ADD COLUMN last_event_index BIGINT;
+ALTER TABLE marketplace_volumes
+ADD COLUMN last_event_index BIGINT;
+ALTER TABLE marketplace_collection_volumes
+ADD COLUMN last_event_index BIGINT;
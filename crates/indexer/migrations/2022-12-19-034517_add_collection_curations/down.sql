@@ -0,0 +1,3 @@
+-- This file should undo anything in `up.sql`
+ALTER TABLE current_collection_datas DROP COLUMN IF EXISTS is_verified;
+DROP TABLE IF EXISTS collection_curations;
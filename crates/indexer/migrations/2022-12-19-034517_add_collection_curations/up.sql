@@ -0,0 +1,15 @@
+-- Your SQL goes here
+-- Verified badges and display overrides per collection, maintained by operators with the
+-- import-collection-curations command
+CREATE TABLE collection_curations (
+  collection_data_id_hash VARCHAR(64) PRIMARY KEY NOT NULL,
+  is_verified BOOLEAN NOT NULL,
+  display_name_override VARCHAR(512),
+  discord_url VARCHAR(512),
+  twitter_url VARCHAR(512),
+  updated_by VARCHAR(128) NOT NULL,
+  updated_at TIMESTAMP NOT NULL DEFAULT NOW()
+);
+-- Copied from collection_curations by the import and whenever the processor writes the row
+ALTER TABLE current_collection_datas
+ADD COLUMN is_verified BOOLEAN NOT NULL DEFAULT FALSE;
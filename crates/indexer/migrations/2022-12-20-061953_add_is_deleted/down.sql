@@ -0,0 +1,5 @@
+-- This file should undo anything in `up.sql`
+ALTER TABLE current_ans_lookup DROP COLUMN IF EXISTS is_deleted;
+ALTER TABLE current_marketplace_listings DROP COLUMN IF EXISTS is_deleted;
+ALTER TABLE current_token_pending_claims DROP COLUMN IF EXISTS is_deleted;
+ALTER TABLE current_token_ownerships DROP COLUMN IF EXISTS is_deleted;
@@ -0,0 +1,10 @@
+-- Your SQL goes here
+-- Rows whose token, offer, listing or name is gone, kept so a later version can bring them back
+ALTER TABLE current_token_ownerships
+ADD COLUMN is_deleted BOOLEAN NOT NULL DEFAULT FALSE;
+ALTER TABLE current_token_pending_claims
+ADD COLUMN is_deleted BOOLEAN NOT NULL DEFAULT FALSE;
+ALTER TABLE current_marketplace_listings
+ADD COLUMN is_deleted BOOLEAN NOT NULL DEFAULT FALSE;
+ALTER TABLE current_ans_lookup
+ADD COLUMN is_deleted BOOLEAN NOT NULL DEFAULT FALSE;
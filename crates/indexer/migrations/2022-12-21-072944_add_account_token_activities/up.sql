@@ -0,0 +1,41 @@
+-- Your SQL goes here
+-- token_activities once per account involved, so an account's feed is one index range instead of
+-- an OR over from_address and to_address. A sale or transfer has a row for each side. Only
+-- written with account_token_activities enabled.
+CREATE TABLE account_token_activities (
+  account_address VARCHAR(66) NOT NULL,
+  transaction_version BIGINT NOT NULL,
+  event_index BIGINT NOT NULL,
+  event_account_address VARCHAR(66) NOT NULL,
+  event_creation_number BIGINT NOT NULL,
+  event_sequence_number BIGINT NOT NULL,
+  token_index BIGINT NOT NULL,
+  -- from, to, or both for a transfer to oneself
+  side VARCHAR(8) NOT NULL,
+  collection_data_id_hash VARCHAR(64) NOT NULL,
+  token_data_id_hash VARCHAR(64) NOT NULL,
+  property_version NUMERIC NOT NULL,
+  creator_address VARCHAR(66) NOT NULL,
+  collection_name VARCHAR(512) NOT NULL,
+  name VARCHAR(512) NOT NULL,
+  transfer_type VARCHAR(150) NOT NULL,
+  from_address VARCHAR(66),
+  to_address VARCHAR(66),
+  token_amount NUMERIC NOT NULL,
+  coin_type TEXT,
+  coin_amount NUMERIC,
+  transaction_timestamp TIMESTAMP NOT NULL,
+  inserted_at TIMESTAMP NOT NULL DEFAULT NOW(),
+  -- Constraints, in the feed's order
+  PRIMARY KEY (
+    account_address,
+    transaction_version,
+    event_index,
+    event_account_address,
+    event_creation_number,
+    event_sequence_number,
+    token_index
+  )
+);
+-- for pruning
+CREATE INDEX ata_version_index ON account_token_activities (transaction_version);
@@ -0,0 +1,4 @@
+-- This file should undo anything in `up.sql`
+DROP INDEX IF EXISTS ta_cdih_version_index;
+ALTER TABLE account_token_activities DROP COLUMN IF EXISTS transfer_kind;
+ALTER TABLE token_activities DROP COLUMN IF EXISTS transfer_kind;
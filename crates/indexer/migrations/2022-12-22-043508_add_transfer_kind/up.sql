@@ -0,0 +1,13 @@
+-- Your SQL goes here
+-- What each activity is (sale, listing, transfer, ...), see TransferKind. Null for rows indexed
+-- before this migration until the backfill-transfer-kinds command fills them.
+ALTER TABLE token_activities
+ADD COLUMN transfer_kind VARCHAR(16);
+ALTER TABLE account_token_activities
+ADD COLUMN transfer_kind VARCHAR(16);
+-- for a collection's activity feed, newest first with its kinds filtered on the way
+CREATE INDEX ta_cdih_version_index ON token_activities (
+  collection_data_id_hash,
+  transaction_version,
+  event_index
+);
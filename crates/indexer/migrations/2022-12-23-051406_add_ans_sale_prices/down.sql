@@ -0,0 +1,6 @@
+-- This file should undo anything in `up.sql`
+DROP TABLE IF EXISTS current_ans_sale_prices;
+DROP INDEX IF EXISTS ns_domain_index;
+ALTER TABLE nft_sales DROP COLUMN IF EXISTS domain;
+ALTER TABLE account_token_activities DROP COLUMN IF EXISTS domain;
+ALTER TABLE token_activities DROP COLUMN IF EXISTS domain;
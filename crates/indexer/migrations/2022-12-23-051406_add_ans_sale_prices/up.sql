@@ -0,0 +1,24 @@
+-- Your SQL goes here
+-- The ANS domain a token is named after, for tokens of the ans_token_creator's domain collection.
+-- Null for other tokens, subdomains, and rows indexed before this migration.
+ALTER TABLE token_activities
+ADD COLUMN domain VARCHAR(64);
+ALTER TABLE account_token_activities
+ADD COLUMN domain VARCHAR(64);
+ALTER TABLE nft_sales
+ADD COLUMN domain VARCHAR(64);
+CREATE INDEX ns_domain_index ON nft_sales (domain)
+WHERE domain IS NOT NULL;
+-- last sale of each ANS domain
+CREATE TABLE current_ans_sale_prices (
+  domain VARCHAR(64) UNIQUE PRIMARY KEY NOT NULL,
+  token_data_id_hash VARCHAR(64) NOT NULL,
+  market_address VARCHAR(66) NOT NULL,
+  coin_type TEXT,
+  last_sale_price NUMERIC,
+  last_sale_price_usd NUMERIC,
+  last_transaction_version BIGINT NOT NULL,
+  last_transaction_timestamp TIMESTAMP NOT NULL,
+  inserted_at TIMESTAMP NOT NULL DEFAULT NOW()
+);
+CREATE INDEX casp_price_index ON current_ans_sale_prices (last_sale_price);
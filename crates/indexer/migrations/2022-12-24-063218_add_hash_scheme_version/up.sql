@@ -0,0 +1,19 @@
+-- Your SQL goes here
+-- Settings of the data the indexer wrote, which a binary has to agree with to keep writing
+CREATE TABLE IF NOT EXISTS indexer_metadata (
+  name VARCHAR(64) UNIQUE PRIMARY KEY NOT NULL,
+  value VARCHAR(256) NOT NULL,
+  updated_at TIMESTAMP NOT NULL DEFAULT NOW()
+);
+-- How token and collection ids are hashed, see hash_scheme.rs. A database with creator addresses
+-- that aren't padded hashed them verbatim (scheme 0) and needs a rehash, any other is on scheme 1
+INSERT INTO indexer_metadata (name, value)
+SELECT 'hash_scheme_version',
+  CASE
+    WHEN EXISTS (
+      SELECT 1
+      FROM current_collection_datas
+      WHERE creator_address !~ '^0x[0-9a-f]{64}$'
+    ) THEN '0'
+    ELSE '1'
+  END ON CONFLICT (name) DO NOTHING;
@@ -0,0 +1,5 @@
+-- This file should undo anything in `up.sql`
+DROP INDEX IF EXISTS ns_suspect_value_index;
+ALTER TABLE nft_sales DROP COLUMN IF EXISTS suspect_value;
+ALTER TABLE account_token_activities DROP COLUMN IF EXISTS suspect_value;
+ALTER TABLE token_activities DROP COLUMN IF EXISTS suspect_value;
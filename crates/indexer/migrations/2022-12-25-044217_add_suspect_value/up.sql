@@ -0,0 +1,11 @@
+-- Your SQL goes here
+-- Set when an event's price or amount is negative or past the indexer's value_limits. Such rows
+-- are kept, but left out of volumes, floors and leaderboards.
+ALTER TABLE token_activities
+ADD COLUMN suspect_value BOOLEAN NOT NULL DEFAULT false;
+ALTER TABLE account_token_activities
+ADD COLUMN suspect_value BOOLEAN NOT NULL DEFAULT false;
+ALTER TABLE nft_sales
+ADD COLUMN suspect_value BOOLEAN NOT NULL DEFAULT false;
+CREATE INDEX ns_suspect_value_index ON nft_sales (transaction_version)
+WHERE suspect_value;
@@ -0,0 +1,18 @@
+-- Your SQL goes here
+-- sale price stats per collection, from the APT priced sales that aren't suspect. The median is
+-- recomputed from nft_sales by a periodic pass, median_sale_count is the sale_count it was
+-- computed at, so it's stale whenever the two differ
+CREATE TABLE current_collection_price_stats (
+  collection_data_id_hash VARCHAR(64) UNIQUE PRIMARY KEY NOT NULL,
+  all_time_high_price NUMERIC NOT NULL,
+  all_time_high_version BIGINT NOT NULL,
+  min_sale_price NUMERIC NOT NULL,
+  sale_count BIGINT NOT NULL,
+  sum_price NUMERIC NOT NULL,
+  median_price NUMERIC,
+  median_sale_count BIGINT,
+  last_transaction_version BIGINT NOT NULL,
+  last_event_index BIGINT NOT NULL,
+  inserted_at TIMESTAMP NOT NULL DEFAULT NOW()
+);
+CREATE INDEX ccps_ath_index ON current_collection_price_stats (all_time_high_price);
@@ -0,0 +1,6 @@
+-- Your SQL goes here
+-- index of the event that last changed the listing, so that changes within one transaction are
+-- applied in event order. Listings from the write set come after every event. Null for rows
+-- written before it was added.
+ALTER TABLE current_marketplace_listings
+ADD COLUMN last_event_index BIGINT;
@@ -0,0 +1,30 @@
+// Copyright (c) Aptos
+// SPDX-License-Identifier: Apache-2.0
+
+use aptos_indexer::cli::{CommandStatus, TokenIndexerCli};
+use aptos_logger::{error, Level};
+use clap::Parser;
+use std::process::ExitCode;
+
+/// The command ran but found problems, e.g. an invalid config or replay differences
+const EXIT_CODE_CHECKS_FAILED: u8 = 1;
+/// The command could not run to completion
+const EXIT_CODE_ERROR: u8 = 2;
+
+#[tokio::main]
+async fn main() -> ExitCode {
+    let mut logger = aptos_logger::Logger::new();
+    logger.level(Level::Info).read_env();
+    logger.build();
+
+    let args = TokenIndexerCli::parse();
+    match args.command.execute().await {
+        Ok(CommandStatus::Success) => ExitCode::SUCCESS,
+        Ok(CommandStatus::ChecksFailed) => ExitCode::from(EXIT_CODE_CHECKS_FAILED),
+        Err(err) => {
+            error!(error = format!("{:?}", err), "Command failed");
+            eprintln!("Error: {:?}", err);
+            ExitCode::from(EXIT_CODE_ERROR)
+        }
+    }
+}
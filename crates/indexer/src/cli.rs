@@ -0,0 +1,1963 @@
+// Copyright (c) Aptos
+// SPDX-License-Identifier: Apache-2.0
+
+//! Subcommands of the standalone `aptos-token-indexer` binary. Every subcommand reads the
+//! `indexer` and `storage` sections of a node config and reuses the processors the node runs.
+
+#[cfg(feature = "http-api")]
+use crate::http_api::HttpApi;
+use crate::{
+    database::{
+        new_db_pool_with_settings, ConnectionSettings, PgDbPool, PostgresSchema, DEFAULT_POOL_SIZE,
+    },
+    hash_scheme::{get_hash_scheme_version, HASH_SCHEME_VERSION},
+    indexer::{
+        fetch_cache::FetchCache,
+        fetch_retry::FetchRetry,
+        fetcher::{fetch_nexts, AdaptiveFetch},
+        node_storage::NodeStorage,
+        tailer::MIGRATIONS,
+        transaction_processor::TransactionProcessor,
+        transaction_stream::TransactionStream,
+        upstream_nodes::UpstreamNodes,
+    },
+    migrations::prepare_schema,
+    models::{
+        processed_version_ranges::ProcessedVersionRange,
+        processor_status::{backfill_checkpoint_name, ProcessorStatusV2, ProcessorStatusV2Query},
+        token_models::{
+            activity_partitions::{is_partitioned, TokenActivityPartitions},
+            address_normalization::normalize_addresses,
+            ans_lookup::AnsContract,
+            ans_sales::AnsDomains,
+            coin_prices::{insert_coin_prices, CoinPriceUpdater},
+            collection_curations::{curations_from_records, import_curations, read_curation_file},
+            collection_holder_counts::CurrentCollectionHolderCount,
+            collection_price_stats::CollectionPriceMedians,
+            collection_rarity::CollectionRarity,
+            collection_stats_snapshots::CollectionStatsSnapshots,
+            consistency_check::ConsistencyCheck,
+            leaderboards::Leaderboards,
+            marketplace_event_mappings::MarketplaceEventMappings,
+            marketplaces::{declarative::check_samples, MarketplaceAdapters},
+            pruning::{is_volume_history_pruned, Pruner},
+            table_handle_cache::TableHandleCache,
+            token_activities::{
+                backfill_transfer_kinds, TokenActivity, DEFAULT_TRANSFER_KIND_CHUNK_VERSIONS,
+            },
+            token_tables::TokenTables,
+            token_utils::{CollectionDataIdType, StringLimits, TokenEvents, ValueLimits},
+            volume_recompute::{count_legacy_rows, find_volume_drift, recompute_volumes},
+            volume_reconciliation::VolumeReconciliation,
+        },
+    },
+    parquet_sink::check_parquet_sink,
+    processors::{
+        token_processor::{self, TokenTransactionProcessor},
+        Processor,
+    },
+    runtime::{
+        build_processor, build_token_processor, check_metadata_fetcher, processor_names,
+        run_forever,
+    },
+    schema::token_activities,
+};
+use anyhow::{anyhow, ensure, Context as AnyhowContext, Result};
+use aptos_api::context::Context;
+use aptos_config::config::{
+    ConsistencyCheckConfig, IndexerConfig, NodeConfig, DEFAULT_BATCH_SIZE,
+    NO_OP_STORAGE_PRUNER_CONFIG,
+};
+use aptos_logger::info;
+use aptos_state_view::account_with_state_view::AsAccountWithStateView;
+use aptos_types::{account_config::CORE_CODE_ADDRESS, account_view::AccountView};
+use aptosdb::AptosDB;
+use bigdecimal::BigDecimal;
+use clap::{Parser, Subcommand};
+use diesel::{ExpressionMethods, QueryDsl, RunQueryDsl};
+use diesel_migrations::MigrationHarness;
+use std::{collections::BTreeMap, path::PathBuf, sync::Arc};
+use storage_interface::{state_view::LatestDbStateCheckpointView, DbReader};
+
+/// Runs and maintains the token indexer outside of a node
+#[derive(Debug, Parser)]
+#[clap(name = "aptos-token-indexer", author, version)]
+pub struct TokenIndexerCli {
+    #[clap(subcommand)]
+    pub command: TokenIndexerCommand,
+}
+
+#[derive(Debug, Subcommand)]
+pub enum TokenIndexerCommand {
+    /// Index continuously from the last processed version, like the node's embedded indexer
+    Run(RunArgs),
+    /// Reprocess a fixed range of versions, optionally for a few tables only, then exit
+    Backfill(BackfillArgs),
+    /// Reprocess every version with token activity for a single collection
+    ReindexCollection(ReindexCollectionArgs),
+    /// Re-parse a range of versions and print token activities that differ from postgres
+    ReplayDiff(ReplayDiffArgs),
+    /// Parse one transaction and print every row the processor makes of it, optionally writing them
+    DebugTransaction(DebugTransactionArgs),
+    /// Print versions missing from the processor's processed ranges, optionally reprocessing them
+    FindGaps(FindGapsArgs),
+    /// Check that the indexer config is usable, optionally against the database
+    ValidateConfig(ValidateConfigArgs),
+    /// Rebuild collection holder counts from current token ownerships, e.g. nightly
+    RecomputeHolderCounts(RecomputeHolderCountsArgs),
+    /// Recompute trait frequencies and rarity ranks for collections changed since the last refresh
+    RecomputeRarity(RecomputeRarityArgs),
+    /// Recompute the median sale price of collections with sales since their last median
+    RecomputePriceMedians(RecomputePriceMediansArgs),
+    /// Pad short addresses and merge the token and collection rows they split, once per database
+    NormalizeAddresses(NormalizeAddressesArgs),
+    /// Fill token_activities.transfer_kind for activities indexed before it was added
+    BackfillTransferKinds(BackfillTransferKindsArgs),
+    /// Rebuild current collection and token volumes from the volume history
+    RecomputeVolumes(RecomputeVolumesArgs),
+    /// Replay the history of a sample of tokens and print where the current tables disagree
+    CheckConsistency(CheckConsistencyArgs),
+    /// Delete history older than the configured retention, in batches
+    Prune(PruneArgs),
+    /// Rebuild past days of collection_stats_snapshots from the history tables
+    BackfillCollectionStats(BackfillCollectionStatsArgs),
+    /// Fetch the configured coin price APIs into coin_prices, e.g. every few minutes
+    UpdateCoinPrices(UpdateCoinPricesArgs),
+    /// Upsert collection curations from a CSV or JSON file and sync their verified flags
+    ImportCollectionCurations(ImportCollectionCurationsArgs),
+    /// Serve the read only HTTP API over the token tables
+    #[cfg(feature = "http-api")]
+    Serve(ServeArgs),
+}
+
+impl TokenIndexerCommand {
+    pub async fn execute(self) -> Result<CommandStatus> {
+        match self {
+            Self::Run(args) => args.execute().await,
+            Self::Backfill(args) => args.execute().await,
+            Self::ReindexCollection(args) => args.execute().await,
+            Self::ReplayDiff(args) => args.execute().await,
+            Self::DebugTransaction(args) => args.execute().await,
+            Self::FindGaps(args) => args.execute().await,
+            Self::ValidateConfig(args) => args.execute(),
+            Self::RecomputeHolderCounts(args) => args.execute(),
+            Self::RecomputeRarity(args) => args.execute(),
+            Self::RecomputePriceMedians(args) => args.execute(),
+            Self::NormalizeAddresses(args) => args.execute(),
+            Self::BackfillTransferKinds(args) => args.execute(),
+            Self::RecomputeVolumes(args) => args.execute(),
+            Self::CheckConsistency(args) => args.execute(),
+            Self::Prune(args) => args.execute(),
+            Self::BackfillCollectionStats(args) => args.execute(),
+            Self::UpdateCoinPrices(args) => args.execute().await,
+            Self::ImportCollectionCurations(args) => args.execute(),
+            #[cfg(feature = "http-api")]
+            Self::Serve(args) => args.execute().await,
+        }
+    }
+}
+
+/// Result of a subcommand that ran to completion
+#[derive(Debug, PartialEq, Eq)]
+pub enum CommandStatus {
+    Success,
+    /// The command ran but found problems, e.g. an invalid config or replay differences
+    ChecksFailed,
+}
+
+#[derive(Debug, Parser)]
+pub struct ConfigArgs {
+    /// Path to a node config file with the `indexer` section enabled
+    #[clap(long, short = 'f', parse(from_os_str))]
+    pub config: PathBuf,
+    /// Run pending migrations before starting, even if the config sets skip_migrations
+    #[clap(long)]
+    pub migrate: bool,
+    /// Rehash the token and collection ids of a database on an older hash scheme before starting
+    #[clap(long)]
+    pub rehash: bool,
+}
+
+impl ConfigArgs {
+    /// Loads the node config, which also fills in the indexer defaults
+    fn load(&self) -> Result<NodeConfig> {
+        let mut node_config = NodeConfig::load(&self.config)
+            .map_err(|err| anyhow!("Failed to load {}: {}", self.config.display(), err))?;
+        ensure!(
+            node_config.indexer.enabled,
+            "Indexer is not enabled in {}",
+            self.config.display()
+        );
+        if self.migrate {
+            node_config.indexer.skip_migrations = Some(false);
+        }
+        if self.rehash {
+            node_config.indexer.rehash = Some(true);
+        }
+        Ok(node_config)
+    }
+}
+
+#[derive(Debug, Parser)]
+pub struct RunArgs {
+    #[clap(flatten)]
+    pub config: ConfigArgs,
+}
+
+impl RunArgs {
+    pub async fn execute(self) -> Result<CommandStatus> {
+        let node_config = self.config.load()?;
+        let context = open_node_context(&node_config)?;
+        run_forever(node_config.indexer, context).await;
+        Ok(CommandStatus::Success)
+    }
+}
+
+#[derive(Debug, Parser)]
+pub struct BackfillArgs {
+    #[clap(flatten)]
+    pub config: ConfigArgs,
+    /// Processor to run, defaults to the one in the config
+    #[clap(long)]
+    pub processor: Option<String>,
+    /// First version to process
+    #[clap(long)]
+    pub start_version: u64,
+    /// Last version to process, inclusive
+    #[clap(long)]
+    pub end_version: u64,
+    /// Comma separated tables to write, ex: "current_marketplace_listings,collection_volumes".
+    /// Defaults to the config's enabled_tables. Only available for token_processor.
+    #[clap(long, use_value_delimiter = true)]
+    pub tables: Option<Vec<String>>,
+}
+
+impl BackfillArgs {
+    pub async fn execute(self) -> Result<CommandStatus> {
+        ensure!(
+            self.start_version <= self.end_version,
+            "Start version {} is after end version {}",
+            self.start_version,
+            self.end_version
+        );
+        let mut node_config = self.config.load()?;
+        if let Some(processor) = self.processor {
+            node_config.indexer.processor = Some(processor);
+        }
+        if let Some(tables) = self.tables {
+            ensure!(
+                node_config.indexer.processor.as_deref() == Some(token_processor::NAME),
+                "--tables is only available for {}",
+                token_processor::NAME
+            );
+            TokenTables::from_config(Some(&tables))?;
+            node_config.indexer.enabled_tables = Some(tables);
+        }
+        let conn_pool = connect(&node_config.indexer)?;
+        let context = open_node_context(&node_config)?;
+        let processor = build_processor(&node_config.indexer, conn_pool);
+        let num_versions = backfill_versions(
+            context,
+            processor,
+            self.start_version,
+            self.end_version,
+            node_config.indexer.batch_size.unwrap(),
+        )
+        .await?;
+        info!(
+            start_version = self.start_version,
+            end_version = self.end_version,
+            num_versions = num_versions,
+            "Backfill finished"
+        );
+        Ok(CommandStatus::Success)
+    }
+}
+
+#[derive(Debug, Parser)]
+pub struct ReindexCollectionArgs {
+    #[clap(flatten)]
+    pub config: ConfigArgs,
+    /// Address of the collection creator
+    #[clap(long)]
+    pub creator_address: String,
+    /// Name of the collection
+    #[clap(long)]
+    pub collection_name: String,
+}
+
+impl ReindexCollectionArgs {
+    pub async fn execute(self) -> Result<CommandStatus> {
+        let node_config = self.config.load()?;
+        let conn_pool = connect(&node_config.indexer)?;
+        let versions =
+            get_collection_versions(&conn_pool, &self.creator_address, &self.collection_name)?;
+        info!(
+            creator_address = self.creator_address,
+            collection_name = self.collection_name,
+            num_versions = versions.len(),
+            "Reindexing collection"
+        );
+        let context = open_node_context(&node_config)?;
+        let processor = build_processor(&node_config.indexer, conn_pool);
+        for version in versions {
+            process_versions(
+                context.clone(),
+                processor.clone(),
+                version as u64,
+                version as u64,
+                1,
+            )
+            .await?;
+        }
+        Ok(CommandStatus::Success)
+    }
+}
+
+#[derive(Debug, Parser)]
+pub struct ReplayDiffArgs {
+    #[clap(flatten)]
+    pub config: ConfigArgs,
+    /// First version to replay
+    #[clap(long)]
+    pub start_version: u64,
+    /// Last version to replay, inclusive
+    #[clap(long)]
+    pub end_version: u64,
+}
+
+impl ReplayDiffArgs {
+    pub async fn execute(self) -> Result<CommandStatus> {
+        ensure!(
+            self.start_version <= self.end_version,
+            "Start version {} is after end version {}",
+            self.start_version,
+            self.end_version
+        );
+        let node_config = self.config.load()?;
+        // Replaying is read only, so don't run migrations here
+        let conn_pool = new_schema_pool(&node_config.indexer)?;
+        let context = open_node_context(&node_config)?;
+        let marketplace_event_mappings = MarketplaceEventMappings::from_config(
+            node_config
+                .indexer
+                .marketplace_event_mappings
+                .as_deref()
+                .unwrap_or_default(),
+        )?;
+        let differences = replay_diff(
+            context,
+            &conn_pool,
+            &marketplace_event_mappings,
+            self.start_version,
+            self.end_version,
+            node_config.indexer.batch_size.unwrap(),
+        )
+        .await?;
+        for difference in &differences {
+            println!("{}", difference);
+        }
+        if differences.is_empty() {
+            Ok(CommandStatus::Success)
+        } else {
+            Ok(CommandStatus::ChecksFailed)
+        }
+    }
+}
+
+#[derive(Debug, Parser)]
+pub struct DebugTransactionArgs {
+    #[clap(flatten)]
+    pub config: ConfigArgs,
+    /// Version of the transaction
+    #[clap(long)]
+    pub version: u64,
+    /// Processor to parse it with, only token_processor can parse a transaction on its own
+    #[clap(long, default_value = "token_processor")]
+    pub processor: String,
+    /// Also write the rows to the enabled tables, without recording any processor status
+    #[clap(long)]
+    pub commit: bool,
+}
+
+impl DebugTransactionArgs {
+    pub async fn execute(self) -> Result<CommandStatus> {
+        ensure!(
+            self.processor == token_processor::NAME,
+            "Only {} can debug a transaction, not {}",
+            token_processor::NAME,
+            self.processor
+        );
+        let mut node_config = self.config.load()?;
+        node_config.indexer.processor = Some(self.processor);
+        // Only migrate when writing
+        let conn_pool = if self.commit {
+            connect(&node_config.indexer)?
+        } else {
+            new_schema_pool(&node_config.indexer)?
+        };
+        let context = open_node_context(&node_config)?;
+        let processor = build_token_processor(&node_config.indexer, conn_pool, None, None);
+        let rows = debug_transaction(context, &processor, self.version, self.commit).await?;
+        println!("{}", serde_json::to_string_pretty(&rows)?);
+        Ok(CommandStatus::Success)
+    }
+}
+
+#[derive(Debug, Parser)]
+pub struct FindGapsArgs {
+    #[clap(flatten)]
+    pub config: ConfigArgs,
+    /// Last version to check, inclusive
+    #[clap(long)]
+    pub up_to_version: u64,
+    /// Process the missing versions after printing them
+    #[clap(long)]
+    pub reprocess: bool,
+}
+
+impl FindGapsArgs {
+    pub async fn execute(self) -> Result<CommandStatus> {
+        let node_config = self.config.load()?;
+        let conn_pool = connect(&node_config.indexer)?;
+        let processor_name = node_config.indexer.processor.clone().unwrap();
+        let gaps = ProcessedVersionRange::find_gaps(
+            &mut conn_pool.get()?,
+            &processor_name,
+            self.up_to_version as i64,
+        )?;
+        for (start_version, end_version) in &gaps {
+            println!("{} {}", start_version, end_version);
+        }
+        if gaps.is_empty() {
+            return Ok(CommandStatus::Success);
+        }
+        if !self.reprocess {
+            return Ok(CommandStatus::ChecksFailed);
+        }
+        let context = open_node_context(&node_config)?;
+        let processor = build_processor(&node_config.indexer, conn_pool);
+        for (start_version, end_version) in gaps {
+            process_versions(
+                context.clone(),
+                processor.clone(),
+                start_version as u64,
+                end_version as u64,
+                node_config.indexer.batch_size.unwrap(),
+            )
+            .await?;
+            info!(
+                processor_name = processor_name,
+                start_version = start_version,
+                end_version = end_version,
+                "Reprocessed gap"
+            );
+        }
+        Ok(CommandStatus::Success)
+    }
+}
+
+#[derive(Debug, Parser)]
+pub struct ValidateConfigArgs {
+    #[clap(flatten)]
+    pub config: ConfigArgs,
+    /// Also connect to postgres and check for pending migrations
+    #[clap(long)]
+    pub check_database: bool,
+    /// Also parse the sample events of every marketplace definition and print what they map to
+    #[clap(long)]
+    pub validate_marketplace_config: bool,
+}
+
+impl ValidateConfigArgs {
+    pub fn execute(self) -> Result<CommandStatus> {
+        let mut problems = match self.config.load() {
+            Ok(node_config) => {
+                let mut problems = validate_indexer_config(&node_config.indexer);
+                if self.validate_marketplace_config {
+                    problems.extend(check_marketplace_samples(&node_config.indexer));
+                }
+                if self.check_database && problems.is_empty() {
+                    problems.extend(check_database(&node_config.indexer));
+                }
+                problems
+            }
+            Err(err) => vec![err.to_string()],
+        };
+        if problems.is_empty() {
+            println!("Config is valid");
+            return Ok(CommandStatus::Success);
+        }
+        for problem in problems.drain(..) {
+            println!("{}", problem);
+        }
+        Ok(CommandStatus::ChecksFailed)
+    }
+}
+
+#[derive(Debug, Parser)]
+pub struct RecomputeHolderCountsArgs {
+    #[clap(flatten)]
+    pub config: ConfigArgs,
+}
+
+impl RecomputeHolderCountsArgs {
+    pub fn execute(self) -> Result<CommandStatus> {
+        let node_config = self.config.load()?;
+        let conn_pool = connect(&node_config.indexer)?;
+        let num_collections = CurrentCollectionHolderCount::recompute_all(&mut conn_pool.get()?)?;
+        info!(
+            num_collections = num_collections,
+            "Recomputed collection holder counts"
+        );
+        Ok(CommandStatus::Success)
+    }
+}
+
+#[derive(Debug, Parser)]
+pub struct RecomputeRarityArgs {
+    #[clap(flatten)]
+    pub config: ConfigArgs,
+}
+
+impl RecomputeRarityArgs {
+    /// Shares its bookkeeping with the token processor's periodic refresh, so this only catches
+    /// up on collections changed since the last refresh of either
+    pub fn execute(self) -> Result<CommandStatus> {
+        let node_config = self.config.load()?;
+        let conn_pool = connect(&node_config.indexer)?;
+        let num_collections =
+            CollectionRarity::refresh(&mut conn_pool.get()?, token_processor::NAME, None)?;
+        info!(
+            num_collections = num_collections,
+            "Recomputed collection rarity"
+        );
+        Ok(CommandStatus::Success)
+    }
+}
+
+#[derive(Debug, Parser)]
+pub struct RecomputePriceMediansArgs {
+    #[clap(flatten)]
+    pub config: ConfigArgs,
+}
+
+impl RecomputePriceMediansArgs {
+    pub fn execute(self) -> Result<CommandStatus> {
+        let node_config = self.config.load()?;
+        let conn_pool = connect(&node_config.indexer)?;
+        let num_collections = CollectionPriceMedians::refresh(&mut conn_pool.get()?)?;
+        info!(
+            num_collections = num_collections,
+            "Recomputed collection price medians"
+        );
+        Ok(CommandStatus::Success)
+    }
+}
+
+#[derive(Debug, Parser)]
+pub struct NormalizeAddressesArgs {
+    #[clap(flatten)]
+    pub config: ConfigArgs,
+}
+
+impl NormalizeAddressesArgs {
+    /// Holder counts and rarity aren't merged, so `recompute-holder-counts` and `recompute-rarity`
+    /// need to run afterwards. A database on hash scheme 0 is rehashed to the current scheme on
+    /// connecting, and rows written with short addresses since are normalized after.
+    pub fn execute(self) -> Result<CommandStatus> {
+        let mut node_config = self.config.load()?;
+        node_config.indexer.rehash = Some(true);
+        let conn_pool = connect(&node_config.indexer)?;
+        let num_rows = normalize_addresses(&mut conn_pool.get()?)?;
+        info!(num_rows = num_rows, "Normalized addresses");
+        Ok(CommandStatus::Success)
+    }
+}
+
+#[derive(Debug, Parser)]
+pub struct BackfillTransferKindsArgs {
+    #[clap(flatten)]
+    pub config: ConfigArgs,
+    /// Versions updated per statement, defaults to 1000000
+    #[clap(long)]
+    pub chunk_versions: Option<i64>,
+}
+
+impl BackfillTransferKindsArgs {
+    /// The config's marketplace mappings classify the events they map, like when indexing
+    pub fn execute(self) -> Result<CommandStatus> {
+        let chunk_versions = self
+            .chunk_versions
+            .unwrap_or(DEFAULT_TRANSFER_KIND_CHUNK_VERSIONS);
+        ensure!(
+            chunk_versions > 0,
+            "--chunk-versions must be greater than 0"
+        );
+        let node_config = self.config.load()?;
+        let conn_pool = connect(&node_config.indexer)?;
+        let mappings = MarketplaceEventMappings::from_config(
+            node_config
+                .indexer
+                .marketplace_event_mappings
+                .as_deref()
+                .unwrap_or_default(),
+        )?
+        .with_typed_event_mappings(
+            node_config
+                .indexer
+                .marketplace_typed_event_mappings
+                .as_deref()
+                .unwrap_or_default(),
+        )?;
+        let num_rows = backfill_transfer_kinds(&mut conn_pool.get()?, &mappings, chunk_versions)?;
+        info!(num_rows = num_rows, "Backfilled transfer kinds");
+        Ok(CommandStatus::Success)
+    }
+}
+
+#[derive(Debug, Parser)]
+pub struct RecomputeVolumesArgs {
+    #[clap(flatten)]
+    pub config: ConfigArgs,
+    /// Only recompute the collection by this creator, requires --collection-name
+    #[clap(long, requires = "collection-name")]
+    pub creator_address: Option<String>,
+    /// Name of the collection
+    #[clap(long, requires = "creator-address")]
+    pub collection_name: Option<String>,
+    /// Print the volumes that differ from their history without rewriting them
+    #[clap(long)]
+    pub check_only: bool,
+}
+
+impl RecomputeVolumesArgs {
+    pub fn execute(self) -> Result<CommandStatus> {
+        let node_config = self.config.load()?;
+        let conn_pool = connect(&node_config.indexer)?;
+        let mut conn = conn_pool.get()?;
+        let collection_data_id_hashes = match (self.creator_address, self.collection_name) {
+            (Some(creator_address), Some(collection_name)) => {
+                Some(vec![CollectionDataIdType::new(
+                    creator_address,
+                    collection_name,
+                )
+                .to_hash()])
+            }
+            _ => None,
+        };
+        ensure!(
+            !is_volume_history_pruned(&mut conn)?,
+            "The volume history has been pruned, volumes can't be recomputed from it"
+        );
+        let num_legacy_rows = count_legacy_rows(&mut conn)?;
+        if num_legacy_rows > 0 {
+            aptos_logger::warn!(
+                num_legacy_rows = num_legacy_rows,
+                "collection_volumes has rows from before volumes were keyed by event, backfill \
+                 collection_volumes and token_volumes over their versions first"
+            );
+        }
+        let drift = find_volume_drift(&mut conn, collection_data_id_hashes.as_deref())?;
+        for row in &drift {
+            println!(
+                "{} {} {}: recorded {}, recomputed {}",
+                row.table, row.subject, row.column, row.recorded, row.recomputed
+            );
+        }
+        if self.check_only {
+            return Ok(if drift.is_empty() {
+                CommandStatus::Success
+            } else {
+                CommandStatus::ChecksFailed
+            });
+        }
+        let (num_collections, num_tokens) =
+            recompute_volumes(&mut conn, collection_data_id_hashes.as_deref())?;
+        info!(
+            num_collections = num_collections,
+            num_tokens = num_tokens,
+            num_drifted = drift.len(),
+            "Recomputed volumes"
+        );
+        Ok(CommandStatus::Success)
+    }
+}
+
+#[derive(Debug, Parser)]
+pub struct CheckConsistencyArgs {
+    #[clap(flatten)]
+    pub config: ConfigArgs,
+    /// How many tokens to sample, defaults to the consistency_check config or 20
+    #[clap(long)]
+    pub sample_size: Option<u64>,
+    /// First version from which token_activities is complete, defaults to the
+    /// consistency_check config or 0
+    #[clap(long)]
+    pub complete_from_version: Option<u64>,
+    /// First version from which current_token_pending_claims is complete, defaults to the
+    /// consistency_check config or --complete-from-version
+    #[clap(long)]
+    pub claims_complete_from_version: Option<u64>,
+}
+
+impl CheckConsistencyArgs {
+    pub fn execute(self) -> Result<CommandStatus> {
+        let node_config = self.config.load()?;
+        let mut check_config =
+            node_config
+                .indexer
+                .consistency_check
+                .clone()
+                .unwrap_or(ConsistencyCheckConfig {
+                    every_n_batches: 1,
+                    sample_size: None,
+                    complete_from_version: None,
+                    claims_complete_from_version: None,
+                });
+        check_config.sample_size = self.sample_size.or(check_config.sample_size);
+        check_config.complete_from_version = self
+            .complete_from_version
+            .or(check_config.complete_from_version);
+        check_config.claims_complete_from_version = self
+            .claims_complete_from_version
+            .or(check_config.claims_complete_from_version);
+        let check = ConsistencyCheck::from_config(Some(&check_config))?.unwrap();
+
+        let conn_pool = connect(&node_config.indexer)?;
+        let mut conn = conn_pool.get()?;
+        let last_version =
+            ProcessorStatusV2Query::get_by_processor(token_processor::NAME, &mut conn)?
+                .map(|status| status.last_success_version)
+                .unwrap_or_default();
+        let findings = check.run(&mut conn, last_version)?;
+        for finding in &findings {
+            println!(
+                "{} {}: expected {}, actual {}, last changed at version {}",
+                finding.check_name,
+                finding.subject,
+                finding.expected,
+                finding.actual,
+                finding.offending_version.unwrap_or_default()
+            );
+        }
+        Ok(if findings.is_empty() {
+            CommandStatus::Success
+        } else {
+            CommandStatus::ChecksFailed
+        })
+    }
+}
+
+#[derive(Debug, Parser)]
+pub struct PruneArgs {
+    #[clap(flatten)]
+    pub config: ConfigArgs,
+}
+
+impl PruneArgs {
+    pub fn execute(self) -> Result<CommandStatus> {
+        let node_config = self.config.load()?;
+        let pruner = Pruner::from_config(node_config.indexer.pruning.as_ref())?
+            .ok_or_else(|| anyhow!("Missing pruning in the indexer config"))?;
+        let conn_pool = connect(&node_config.indexer)?;
+        let pruned = pruner.run(&mut conn_pool.get()?, chrono::Utc::now().naive_utc())?;
+        for (table, num_rows) in pruned {
+            info!(table = table, num_rows = num_rows, "Pruned table");
+        }
+        Ok(CommandStatus::Success)
+    }
+}
+
+#[derive(Debug, Parser)]
+pub struct BackfillCollectionStatsArgs {
+    #[clap(flatten)]
+    pub config: ConfigArgs,
+    /// First day to rebuild, e.g. 2022-10-12
+    #[clap(long)]
+    pub start_date: chrono::NaiveDate,
+    /// Last day to rebuild, inclusive
+    #[clap(long)]
+    pub end_date: chrono::NaiveDate,
+}
+
+impl BackfillCollectionStatsArgs {
+    /// Each day is written in its own statement, so an interrupted backfill can be rerun over the
+    /// same days
+    pub fn execute(self) -> Result<CommandStatus> {
+        ensure!(
+            self.start_date <= self.end_date,
+            "--start-date is after --end-date"
+        );
+        let node_config = self.config.load()?;
+        let conn_pool = connect(&node_config.indexer)?;
+        let mut conn = conn_pool.get()?;
+        let volume_history_complete = !is_volume_history_pruned(&mut conn)?;
+        if !volume_history_complete {
+            info!("Volume history is pruned, total_volume won't be rebuilt");
+        }
+        let mut date = self.start_date;
+        while date <= self.end_date {
+            let num_collections =
+                CollectionStatsSnapshots::backfill_day(&mut conn, date, volume_history_complete)?;
+            info!(
+                date = date.to_string(),
+                num_collections = num_collections,
+                "Rebuilt collection stats snapshots"
+            );
+            date = date.succ();
+        }
+        Ok(CommandStatus::Success)
+    }
+}
+
+#[derive(Debug, Parser)]
+pub struct UpdateCoinPricesArgs {
+    #[clap(flatten)]
+    pub config: ConfigArgs,
+}
+
+impl UpdateCoinPricesArgs {
+    pub async fn execute(self) -> Result<CommandStatus> {
+        let node_config = self.config.load()?;
+        let updater =
+            CoinPriceUpdater::from_config(node_config.indexer.coin_price_sources.as_deref())?
+                .ok_or_else(|| anyhow!("Missing coin_price_sources in the indexer config"))?;
+        let conn_pool = connect(&node_config.indexer)?;
+        let (prices, failures) = updater.fetch_prices().await?;
+        insert_coin_prices(&mut conn_pool.get()?, &prices)?;
+        for price in &prices {
+            info!(
+                coin_type = price.coin_type.as_str(),
+                price_usd = price.price_usd.to_string(),
+                "Updated coin price"
+            );
+        }
+        for (coin_type, err) in &failures {
+            println!("Could not fetch the price of {}: {:#}", coin_type, err);
+        }
+        Ok(if failures.is_empty() {
+            CommandStatus::Success
+        } else {
+            CommandStatus::ChecksFailed
+        })
+    }
+}
+
+#[derive(Debug, Parser)]
+pub struct ImportCollectionCurationsArgs {
+    #[clap(flatten)]
+    pub config: ConfigArgs,
+    /// .csv file with a header row or .json file with an array of objects, with the fields
+    /// collection_data_id_hash (or creator_address and collection_name), is_verified,
+    /// display_name_override, discord_url and twitter_url
+    #[clap(long, parse(from_os_str))]
+    pub file: PathBuf,
+    /// Who made the change, recorded in updated_by
+    #[clap(long)]
+    pub updated_by: String,
+}
+
+impl ImportCollectionCurationsArgs {
+    /// The whole file is checked before anything is written, and written in one transaction
+    pub fn execute(self) -> Result<CommandStatus> {
+        ensure!(!self.updated_by.is_empty(), "--updated-by can't be empty");
+        let curations = curations_from_records(
+            read_curation_file(&self.file)?,
+            &self.updated_by,
+            chrono::Utc::now().naive_utc(),
+        )?;
+        let node_config = self.config.load()?;
+        let conn_pool = connect(&node_config.indexer)?;
+        let num_synced = import_curations(&mut conn_pool.get()?, &curations)?;
+        info!(
+            num_curations = curations.len(),
+            num_synced = num_synced,
+            "Imported collection curations"
+        );
+        Ok(CommandStatus::Success)
+    }
+}
+
+#[cfg(feature = "http-api")]
+#[derive(Debug, Parser)]
+pub struct ServeArgs {
+    #[clap(flatten)]
+    pub config: ConfigArgs,
+}
+
+#[cfg(feature = "http-api")]
+impl ServeArgs {
+    /// Doesn't run migrations, the processor writing the tables does. Without the processor,
+    /// there's no live feed to serve.
+    pub async fn execute(self) -> Result<CommandStatus> {
+        let node_config = self.config.load()?;
+        let http_api = HttpApi::from_config(
+            node_config.indexer.http_api.as_ref(),
+            node_config
+                .indexer
+                .account_token_activities
+                .unwrap_or(false),
+        )?;
+        let conn_pool = new_db_pool_with_settings(
+            node_config.indexer.postgres_uri.as_ref().unwrap(),
+            ConnectionSettings::from_config(&node_config.indexer)?,
+            http_api.pool_size(),
+        )?;
+        http_api.serve(conn_pool, None).await;
+        Ok(CommandStatus::Success)
+    }
+}
+
+/// Checks the indexer config after defaults have been applied. Returns a list of problems.
+pub fn validate_indexer_config(config: &IndexerConfig) -> Vec<String> {
+    let mut problems = vec![];
+    match &config.processor {
+        Some(processor_name) => {
+            if Processor::try_from_string(processor_name).is_none() {
+                problems.push(format!("Unsupported processor '{}'", processor_name));
+            }
+        }
+        None => problems.push("Missing processor".to_string()),
+    }
+    match processor_names(config) {
+        Ok(processor_names) => {
+            if let Err(err) =
+                FetchCache::from_config(config.fetch_cache.as_ref(), processor_names.len())
+            {
+                problems.push(format!("Invalid fetch_cache: {:#}", err));
+            }
+        }
+        Err(err) => problems.push(format!("Invalid additional_processors: {:#}", err)),
+    }
+    match &config.postgres_uri {
+        Some(postgres_uri) => {
+            if let Err(err) = url::Url::parse(postgres_uri) {
+                problems.push(format!("Invalid postgres_uri: {}", err));
+            }
+        }
+        None => problems.push("Missing postgres_uri".to_string()),
+    }
+    if let Err(err) = PostgresSchema::from_config(config.postgres_schema.as_deref()) {
+        problems.push(format!("Invalid postgres_schema: {:#}", err));
+    }
+    if let Some(ans_contracts) = &config.ans_contracts {
+        if let Err(err) =
+            AnsContract::from_config(config.ans_contract_address.as_ref(), ans_contracts)
+        {
+            problems.push(format!("Invalid ans_contracts: {:#}", err));
+        }
+    }
+    if let Err(err) = AnsDomains::from_config(config.ans_token_creator.as_deref()) {
+        problems.push(format!("Invalid ans_token_creator: {:#}", err));
+    }
+    if let Some(names) = &config.marketplace_adapters {
+        if let Err(err) = MarketplaceAdapters::from_config(names) {
+            problems.push(format!("Invalid marketplace_adapters: {:#}", err));
+        }
+    }
+    if let Some(mappings) = &config.marketplace_event_mappings {
+        if let Err(err) = MarketplaceEventMappings::from_config(mappings) {
+            problems.push(format!("Invalid marketplace_event_mappings: {:#}", err));
+        }
+    }
+    if let Some(path) = &config.marketplace_definitions_path {
+        // Checked against the event mappings too, since a type can't be in both
+        let event_mappings = MarketplaceEventMappings::from_config(
+            config
+                .marketplace_event_mappings
+                .as_deref()
+                .unwrap_or_default(),
+        )
+        .unwrap_or_default();
+        if let Err(err) = event_mappings.with_definitions_file(path) {
+            problems.push(format!("Invalid marketplace_definitions_path: {:#}", err));
+        }
+    }
+    if let Some(mappings) = &config.marketplace_typed_event_mappings {
+        // Checked against the event mappings too, since a type can't be in both
+        let event_mappings = MarketplaceEventMappings::from_config(
+            config
+                .marketplace_event_mappings
+                .as_deref()
+                .unwrap_or_default(),
+        )
+        .unwrap_or_default();
+        if let Err(err) = event_mappings.with_typed_event_mappings(mappings) {
+            problems.push(format!(
+                "Invalid marketplace_typed_event_mappings: {:#}",
+                err
+            ));
+        }
+    }
+    if let Some(mappings) = &config.marketplace_listing_mappings {
+        if let Err(err) = MarketplaceEventMappings::default().with_listing_mappings(mappings) {
+            problems.push(format!("Invalid marketplace_listing_mappings: {:#}", err));
+        }
+    }
+    if let Some(mappings) = &config.marketplace_payload_mappings {
+        if let Err(err) = MarketplaceEventMappings::default().with_payload_mappings(mappings) {
+            problems.push(format!("Invalid marketplace_payload_mappings: {:#}", err));
+        }
+    }
+    if let Some(addresses) = &config.marketplace_escrow_addresses {
+        if let Err(err) = MarketplaceEventMappings::default().with_escrow_addresses(addresses) {
+            problems.push(format!("Invalid marketplace_escrow_addresses: {:#}", err));
+        }
+    }
+    if let Err(err) = CoinPriceUpdater::from_config(config.coin_price_sources.as_deref()) {
+        problems.push(format!("Invalid coin_price_sources: {:#}", err));
+    }
+    if let Err(err) = VolumeReconciliation::from_config(config.volume_reconciliation.as_ref()) {
+        problems.push(format!("Invalid volume_reconciliation: {:#}", err));
+    }
+    if let Err(err) = ConsistencyCheck::from_config(config.consistency_check.as_ref()) {
+        problems.push(format!("Invalid consistency_check: {:#}", err));
+    }
+    if let Err(err) = TokenActivityPartitions::from_config(config.token_activities_partition_size) {
+        problems.push(format!("{:#}", err));
+    }
+    if let Err(err) = Pruner::from_config(config.pruning.as_ref()) {
+        problems.push(format!("Invalid pruning: {:#}", err));
+    }
+    if let Err(err) = CollectionRarity::from_config(config.rarity_refresh_every_n_versions) {
+        problems.push(format!("{:#}", err));
+    }
+    if let Err(err) =
+        CollectionPriceMedians::from_config(config.price_median_refresh_every_n_versions)
+    {
+        problems.push(format!("{:#}", err));
+    }
+    if let Err(err) =
+        CollectionStatsSnapshots::from_config(config.collection_stats_snapshots.as_ref())
+    {
+        problems.push(format!("Invalid collection_stats_snapshots: {:#}", err));
+    }
+    if let Err(err) = Leaderboards::from_config(config.leaderboards.as_ref()) {
+        problems.push(format!("Invalid leaderboards: {:#}", err));
+    }
+    #[cfg(feature = "http-api")]
+    if let Err(err) = HttpApi::from_config(
+        config.http_api.as_ref(),
+        config.account_token_activities.unwrap_or(false),
+    ) {
+        problems.push(format!("Invalid http_api: {:#}", err));
+    }
+    #[cfg(not(feature = "http-api"))]
+    if crate::runtime::serves_http_api_with_indexer(config) {
+        problems.push(
+            "http_api.serve_with_indexer needs the indexer built with the http-api feature"
+                .to_string(),
+        );
+    }
+    if let Err(err) = StringLimits::from_config(config.string_limits.as_ref()) {
+        problems.push(format!("Invalid string_limits: {:#}", err));
+    }
+    if let Err(err) = ValueLimits::from_config(config.value_limits.as_ref()) {
+        problems.push(format!("Invalid value_limits: {:#}", err));
+    }
+    if let Err(err) = TokenTables::from_config(config.enabled_tables.as_deref()) {
+        problems.push(format!("{:#}", err));
+    }
+    if let Err(err) =
+        TokenTransactionProcessor::num_shards_from_config(config.token_processor_shards)
+    {
+        problems.push(format!("{:#}", err));
+    }
+    if let Err(err) = TableHandleCache::from_config(config.table_handle_cache.as_ref()) {
+        problems.push(format!("Invalid table_handle_cache: {:#}", err));
+    }
+    if let Err(err) = AdaptiveFetch::from_config(
+        config.adaptive_fetch.as_ref(),
+        config.batch_size.unwrap_or(DEFAULT_BATCH_SIZE),
+    ) {
+        problems.push(format!("Invalid adaptive_fetch: {:#}", err));
+    }
+    if let Err(err) = FetchRetry::from_config(config.fetch_retry.as_ref()) {
+        problems.push(format!("Invalid fetch_retry: {:#}", err));
+    }
+    if let Err(err) = NodeStorage::from_config(
+        config.node_storage.as_ref(),
+        config.upstream_nodes.as_ref(),
+        config.transaction_stream.as_ref(),
+    ) {
+        problems.push(format!("Invalid node_storage: {:#}", err));
+    }
+    if let Err(err) = UpstreamNodes::from_config(config.upstream_nodes.as_ref()) {
+        problems.push(format!("Invalid upstream_nodes: {:#}", err));
+    }
+    if let Err(err) = TransactionStream::from_config(
+        config.transaction_stream.as_ref(),
+        config.upstream_nodes.as_ref(),
+    ) {
+        problems.push(format!("Invalid transaction_stream: {:#}", err));
+    }
+    if let Err(err) = check_metadata_fetcher(config) {
+        problems.push(format!("Invalid metadata_fetcher: {:#}", err));
+    }
+    if let Err(err) = check_parquet_sink(config.parquet_sink.as_ref()) {
+        problems.push(format!("Invalid parquet_sink: {:#}", err));
+    }
+    problems
+}
+
+/// Checks that postgres is reachable and that the schema can be brought up to date
+pub fn check_database(config: &IndexerConfig) -> Vec<String> {
+    let mut conn =
+        match new_schema_pool(config).and_then(|pool| pool.get().map_err(anyhow::Error::from)) {
+            Ok(conn) => conn,
+            Err(err) => return vec![format!("Could not connect to postgres: {}", err)],
+        };
+    let mut problems = match conn.has_pending_migration(MIGRATIONS) {
+        Ok(true) if config.skip_migrations.unwrap_or(false) => {
+            vec!["Database has pending migrations but skip_migrations is set".to_string()]
+        }
+        Ok(true) => vec![],
+        // The hash scheme version is recorded by a migration
+        Ok(false) => match get_hash_scheme_version(&mut conn) {
+            Ok(Some(version)) if version > HASH_SCHEME_VERSION => vec![format!(
+                "Database ids are hashed with scheme {}, newer than the indexer's {}",
+                version, HASH_SCHEME_VERSION
+            )],
+            Ok(Some(version))
+                if version < HASH_SCHEME_VERSION && !config.rehash.unwrap_or(false) =>
+            {
+                vec![format!(
+                    "Database ids are hashed with scheme {} but rehash isn't set",
+                    version
+                )]
+            }
+            Ok(_) => vec![],
+            Err(err) => vec![format!("Could not check the hash scheme: {:#}", err)],
+        },
+        Err(err) => vec![format!("Could not check migrations: {}", err)],
+    };
+    if config.token_activities_partition_size.is_some() {
+        match is_partitioned(&mut conn) {
+            Ok(true) => {}
+            Ok(false) => problems.push(
+                "token_activities_partition_size is set but token_activities isn't partitioned"
+                    .to_string(),
+            ),
+            Err(err) => problems.push(format!("Could not check partitioning: {}", err)),
+        }
+    }
+    problems
+}
+
+/// Parses the sample events next to the marketplace definitions file, printing what each one
+/// maps to and returning the samples that don't map
+pub fn check_marketplace_samples(config: &IndexerConfig) -> Vec<String> {
+    let path = match &config.marketplace_definitions_path {
+        Some(path) => path,
+        None => {
+            return vec![
+                "--validate-marketplace-config needs marketplace_definitions_path".to_string(),
+            ]
+        }
+    };
+    match check_samples(path) {
+        Ok(report) => {
+            for (sample, event) in &report.parsed {
+                println!(
+                    "{}: {} of {} from {:?} to {:?} for {:?}",
+                    sample,
+                    event.kind.as_str(),
+                    event.token_data_id,
+                    event.from_address,
+                    event.to_address,
+                    event.coin_amount.as_ref().map(ToString::to_string)
+                );
+            }
+            report.problems
+        }
+        Err(err) => vec![format!("Invalid marketplace_definitions_path: {:#}", err)],
+    }
+}
+
+/// Connects to postgres after running migrations unless the config skips them. Fails if the
+/// schema is behind either way.
+fn connect(config: &IndexerConfig) -> Result<PgDbPool> {
+    prepare_schema(
+        config.postgres_uri.as_ref().unwrap(),
+        &ConnectionSettings::from_config(config)?,
+        !config.skip_migrations.unwrap(),
+        config.rehash.unwrap_or(false),
+    )?;
+    new_schema_pool(config)
+}
+
+/// A pool on the config's postgres_schema. The database_timeouts are left to the processors'
+/// pools, subcommands run statements that take longer than a batch's.
+fn new_schema_pool(config: &IndexerConfig) -> Result<PgDbPool> {
+    let settings = ConnectionSettings {
+        schema: PostgresSchema::from_config(config.postgres_schema.as_deref())?,
+        ..ConnectionSettings::default()
+    };
+    Ok(new_db_pool_with_settings(
+        config.postgres_uri.as_ref().unwrap(),
+        settings,
+        DEFAULT_POOL_SIZE,
+    )?)
+}
+
+/// Opens the node storage, or the config's node_storage, read only. Only transactions committed
+/// before opening are visible, so point this at a stopped node or a restored backup.
+fn open_node_context(node_config: &NodeConfig) -> Result<Arc<Context>> {
+    let indexer_config = &node_config.indexer;
+    let node_storage = NodeStorage::from_config(
+        indexer_config.node_storage.as_ref(),
+        indexer_config.upstream_nodes.as_ref(),
+        indexer_config.transaction_stream.as_ref(),
+    )
+    .context("Invalid node_storage")?;
+    let storage_dir = NodeStorage::dir(node_storage.as_ref(), node_config);
+    ensure!(
+        storage_dir.is_dir(),
+        "Node storage {} doesn't exist",
+        storage_dir.display()
+    );
+    info!(
+        storage_dir = storage_dir.display().to_string(),
+        "Opening node storage read only"
+    );
+    let aptos_db = AptosDB::open(
+        &storage_dir,
+        true, /* readonly */
+        NO_OP_STORAGE_PRUNER_CONFIG,
+        node_config.storage.rocksdb_configs,
+        false, /* indexer */
+        node_config.storage.buffered_state_target_items,
+        node_config.storage.max_num_nodes_per_lru_cache_shard,
+    )
+    .context("Failed to open node storage")?;
+    let db: Arc<dyn DbReader> = Arc::new(aptos_db);
+    let db_state_view = db.latest_state_checkpoint_view()?;
+    let chain_id = db_state_view
+        .as_account_with_state_view(&CORE_CODE_ADDRESS)
+        .get_chain_id_resource()?
+        .ok_or_else(|| anyhow!("Missing chain id resource in node storage"))?
+        .chain_id();
+    // Nothing is ever submitted, so the receiving end of the mempool channel is dropped
+    let (mp_sender, _) = futures::channel::mpsc::channel(0);
+    Ok(Arc::new(Context::new(
+        chain_id,
+        db,
+        mp_sender,
+        node_config.clone(),
+    )))
+}
+
+fn get_ledger_version(context: &Context, end_version: u64) -> Result<u64> {
+    let ledger_version = context.get_latest_ledger_info_wrapped()?.ledger_version.0;
+    ensure!(
+        end_version <= ledger_version,
+        "End version {} is past the latest ledger version {}",
+        end_version,
+        ledger_version
+    );
+    Ok(ledger_version)
+}
+
+/// Fetches and processes versions `start_version..=end_version` in batches, recording processor
+/// statuses like the tailer does. Returns the number of versions processed.
+pub async fn process_versions(
+    context: Arc<Context>,
+    processor: Arc<dyn TransactionProcessor>,
+    start_version: u64,
+    end_version: u64,
+    batch_size: u16,
+) -> Result<u64> {
+    let ledger_version = get_ledger_version(&context, end_version)?;
+    let mut version = start_version;
+    while version <= end_version {
+        let num_to_fetch = std::cmp::min(batch_size as u64, end_version - version + 1) as u16;
+        let transactions =
+            fetch_nexts(context.clone(), version, ledger_version, num_to_fetch).await;
+        version += transactions.len() as u64;
+        if let Err(tpe) = processor
+            .process_transactions_with_status(transactions)
+            .await
+        {
+            let (err, batch_start_version, batch_end_version, name) = tpe.inner();
+            return Err(anyhow!(
+                "{} failed to process versions {} to {}: {:?}",
+                name,
+                batch_start_version,
+                batch_end_version,
+                err
+            ));
+        }
+    }
+    Ok(version - start_version)
+}
+
+/// Reprocesses versions `start_version..=end_version` without recording processor statuses, so
+/// the main checkpoint and processed ranges are left alone. Progress is checkpointed in its own
+/// processor_status row instead, which is removed once the backfill finishes. Returns the number
+/// of versions processed by this run.
+pub async fn backfill_versions(
+    context: Arc<Context>,
+    processor: Arc<dyn TransactionProcessor>,
+    start_version: u64,
+    end_version: u64,
+    batch_size: u16,
+) -> Result<u64> {
+    let ledger_version = get_ledger_version(&context, end_version)?;
+    let mut conn = processor.get_conn();
+    let checkpoint_name = backfill_checkpoint_name(processor.name(), start_version);
+    let checkpoint = ProcessorStatusV2Query::get_by_processor(&checkpoint_name, &mut conn)?;
+    let first_version = match checkpoint {
+        Some(checkpoint) => {
+            info!(
+                checkpoint = checkpoint_name,
+                last_success_version = checkpoint.last_success_version,
+                "Resuming backfill"
+            );
+            checkpoint.last_success_version as u64 + 1
+        }
+        None => start_version,
+    };
+    let mut version = first_version;
+    while version <= end_version {
+        let num_to_fetch = std::cmp::min(batch_size as u64, end_version - version + 1) as u16;
+        let transactions =
+            fetch_nexts(context.clone(), version, ledger_version, num_to_fetch).await;
+        let batch_start_version = version;
+        version += transactions.len() as u64;
+        processor
+            .process_transactions(transactions, batch_start_version, version - 1)
+            .await
+            .map_err(|tpe| {
+                let (err, batch_start_version, batch_end_version, name) = tpe.inner();
+                anyhow!(
+                    "{} failed to backfill versions {} to {}: {:?}",
+                    name,
+                    batch_start_version,
+                    batch_end_version,
+                    err
+                )
+            })?;
+        ProcessorStatusV2 {
+            processor: checkpoint_name.clone(),
+            last_success_version: (version - 1) as i64,
+        }
+        .upsert(&mut conn)?;
+        info!(
+            checkpoint = checkpoint_name,
+            last_success_version = version - 1,
+            num_remaining = end_version + 1 - version,
+            "Backfilled batch"
+        );
+    }
+    ProcessorStatusV2Query::delete_by_processor(&checkpoint_name, &mut conn)?;
+    Ok(version.saturating_sub(first_version))
+}
+
+/// Versions with token activity for a collection, in ascending order
+pub fn get_collection_versions(
+    conn_pool: &PgDbPool,
+    creator_address: &str,
+    collection_name: &str,
+) -> Result<Vec<i64>> {
+    let collection_data_id_hash =
+        CollectionDataIdType::new(creator_address.to_string(), collection_name.to_string())
+            .to_hash();
+    Ok(token_activities::table
+        .filter(token_activities::collection_data_id_hash.eq(collection_data_id_hash))
+        .select(token_activities::transaction_version)
+        .distinct()
+        .order(token_activities::transaction_version.asc())
+        .load::<i64>(&mut conn_pool.get()?)?)
+}
+
+// (transaction_version, event_account_address, event_creation_number, event_sequence_number,
+//  token_index)
+type TokenActivityKey = (i64, String, i64, i64, i64);
+// (token_data_id_hash, property_version, transfer_type, from_address, to_address, token_amount,
+//  coin_type, coin_amount)
+type TokenActivityValues = (
+    String,
+    BigDecimal,
+    String,
+    Option<String>,
+    Option<String>,
+    BigDecimal,
+    Option<String>,
+    Option<BigDecimal>,
+);
+
+/// Re-parses token activities for `start_version..=end_version` and compares them with what is
+/// stored. Returns one line per difference.
+pub async fn replay_diff(
+    context: Arc<Context>,
+    conn_pool: &PgDbPool,
+    marketplace_event_mappings: &MarketplaceEventMappings,
+    start_version: u64,
+    end_version: u64,
+    batch_size: u16,
+) -> Result<Vec<String>> {
+    let ledger_version = get_ledger_version(&context, end_version)?;
+    let mut parsed: BTreeMap<TokenActivityKey, TokenActivityValues> = BTreeMap::new();
+    let mut version = start_version;
+    while version <= end_version {
+        let num_to_fetch = std::cmp::min(batch_size as u64, end_version - version + 1) as u16;
+        let transactions =
+            fetch_nexts(context.clone(), version, ledger_version, num_to_fetch).await;
+        version += transactions.len() as u64;
+        for txn in &transactions {
+            let token_events =
+                TokenEvents::from_transaction_with_mappings(txn, marketplace_event_mappings)?;
+            for activity in
+                TokenActivity::from_transaction(txn, &token_events, marketplace_event_mappings)
+            {
+                parsed.insert(
+                    (
+                        activity.transaction_version,
+                        activity.event_account_address,
+                        activity.event_creation_number,
+                        activity.event_sequence_number,
+                        activity.token_index,
+                    ),
+                    (
+                        activity.token_data_id_hash,
+                        activity.property_version,
+                        activity.transfer_type,
+                        activity.from_address,
+                        activity.to_address,
+                        activity.token_amount,
+                        activity.coin_type,
+                        activity.coin_amount,
+                    ),
+                );
+            }
+        }
+    }
+
+    let mut stored: BTreeMap<TokenActivityKey, TokenActivityValues> = {
+        use token_activities::dsl::*;
+        token_activities
+            .filter(transaction_version.between(start_version as i64, end_version as i64))
+            .select((
+                (
+                    transaction_version,
+                    event_account_address,
+                    event_creation_number,
+                    event_sequence_number,
+                    token_index,
+                ),
+                (
+                    token_data_id_hash,
+                    property_version,
+                    transfer_type,
+                    from_address,
+                    to_address,
+                    token_amount,
+                    coin_type,
+                    coin_amount,
+                ),
+            ))
+            .load::<(TokenActivityKey, TokenActivityValues)>(&mut conn_pool.get()?)?
+            .into_iter()
+            .collect()
+    };
+
+    let mut differences = vec![];
+    for (key, parsed_values) in parsed {
+        match stored.remove(&key) {
+            None => differences.push(format!("missing from db: {:?} {:?}", key, parsed_values)),
+            Some(stored_values) => {
+                if stored_values != parsed_values {
+                    differences.push(format!(
+                        "differs: {:?} parsed {:?} stored {:?}",
+                        key, parsed_values, stored_values
+                    ));
+                }
+            }
+        }
+    }
+    for (key, stored_values) in stored {
+        differences.push(format!("not parsed: {:?} {:?}", key, stored_values));
+    }
+    Ok(differences)
+}
+
+/// Parses the transaction at `version` on its own, the way the token processor parses a batch,
+/// and returns every row it makes of it as JSON keyed by table, whether or not the table is
+/// enabled. The rows are written as well if `commit` is set.
+pub async fn debug_transaction(
+    context: Arc<Context>,
+    processor: &TokenTransactionProcessor,
+    version: u64,
+    commit: bool,
+) -> Result<serde_json::Value> {
+    let ledger_version = get_ledger_version(&context, version)?;
+    let transactions = fetch_nexts(context, version, ledger_version, 1).await;
+    ensure!(
+        transactions.first().and_then(|txn| txn.version()) == Some(version),
+        "Could not fetch transaction {}",
+        version
+    );
+    let (mut conn, rows) = processor
+        .build_rows(processor.get_conn(), transactions)
+        .await?;
+    let json = serde_json::to_value(&rows)?;
+    if commit {
+        processor.write_rows(&mut conn, rows, version, version)?;
+        info!(version = version, "Committed the transaction's rows");
+    }
+    Ok(json)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        database::new_db_pool, indexer::tailer::test::wipe_database, schema::processor_statuses,
+    };
+    use aptos_api_test_context::new_test_context;
+    use aptos_config::config::{
+        AdaptiveFetchConfig, CoinPriceSourceConfig, CollectionStatsSnapshotsConfig,
+        FetchCacheConfig, FetchRetryConfig, LeaderboardsConfig, MarketplaceEventMapping,
+        MarketplacePayloadMapping, MarketplaceTypedEventMapping, MetadataFetcherConfig,
+        NodeStorageConfig, StringLimitsConfig, TableHandleCacheConfig, TransactionStreamConfig,
+        UpstreamNodesConfig, ValueLimitsConfig,
+    };
+
+    fn token_indexer_config() -> IndexerConfig {
+        IndexerConfig {
+            enabled: true,
+            postgres_uri: std::env::var("INDEXER_DATABASE_URL").ok(),
+            processor: Some("token_processor".to_string()),
+            skip_migrations: Some(false),
+            batch_size: Some(10),
+            ..IndexerConfig::default()
+        }
+    }
+
+    fn setup() -> (PgDbPool, Arc<Context>) {
+        let database_url = std::env::var("INDEXER_DATABASE_URL")
+            .expect("must set 'INDEXER_DATABASE_URL' to run tests!");
+        let conn_pool = new_db_pool(database_url.as_str()).unwrap();
+        wipe_database(&mut conn_pool.get().unwrap());
+        conn_pool
+            .get()
+            .unwrap()
+            .run_pending_migrations(MIGRATIONS)
+            .unwrap();
+        let test_context = new_test_context("token_indexer_cli".to_string(), true);
+        (conn_pool, Arc::new(test_context.context))
+    }
+
+    #[test]
+    fn test_parse_subcommands() {
+        for args in [
+            vec!["run", "-f", "node.yaml"],
+            vec![
+                "backfill",
+                "-f",
+                "node.yaml",
+                "--start-version",
+                "0",
+                "--end-version",
+                "10",
+            ],
+            vec![
+                "backfill",
+                "-f",
+                "node.yaml",
+                "--processor",
+                "token_processor",
+                "--start-version",
+                "0",
+                "--end-version",
+                "10",
+                "--tables",
+                "current_marketplace_listings,collection_volumes",
+            ],
+            vec![
+                "reindex-collection",
+                "-f",
+                "node.yaml",
+                "--creator-address",
+                "0x1",
+                "--collection-name",
+                "Aptos Names V1",
+            ],
+            vec![
+                "replay-diff",
+                "-f",
+                "node.yaml",
+                "--start-version",
+                "0",
+                "--end-version",
+                "10",
+            ],
+            vec![
+                "debug-transaction",
+                "-f",
+                "node.yaml",
+                "--version",
+                "10",
+                "--processor",
+                "token_processor",
+                "--commit",
+            ],
+            vec![
+                "find-gaps",
+                "-f",
+                "node.yaml",
+                "--up-to-version",
+                "10",
+                "--reprocess",
+            ],
+            vec!["validate-config", "-f", "node.yaml", "--check-database"],
+            vec!["recompute-holder-counts", "-f", "node.yaml"],
+            vec!["recompute-rarity", "-f", "node.yaml"],
+            vec!["recompute-price-medians", "-f", "node.yaml"],
+            vec!["normalize-addresses", "-f", "node.yaml"],
+            vec!["prune", "-f", "node.yaml"],
+            vec![
+                "check-consistency",
+                "-f",
+                "node.yaml",
+                "--sample-size",
+                "5",
+                "--complete-from-version",
+                "1000",
+            ],
+            vec![
+                "recompute-volumes",
+                "-f",
+                "node.yaml",
+                "--creator-address",
+                "0x1",
+                "--collection-name",
+                "Aptos Names V1",
+                "--check-only",
+            ],
+            vec![
+                "import-collection-curations",
+                "-f",
+                "node.yaml",
+                "--file",
+                "curations.csv",
+                "--updated-by",
+                "ops",
+            ],
+        ] {
+            let args = std::iter::once("aptos-token-indexer").chain(args);
+            TokenIndexerCli::try_parse_from(args).unwrap();
+        }
+        assert!(TokenIndexerCli::try_parse_from(["aptos-token-indexer", "backfill"]).is_err());
+        assert!(TokenIndexerCli::try_parse_from([
+            "aptos-token-indexer",
+            "recompute-volumes",
+            "-f",
+            "node.yaml",
+            "--creator-address",
+            "0x1",
+        ])
+        .is_err());
+    }
+
+    /// Asserts one of the config's problems starts with `expected`
+    fn assert_problem(config: &IndexerConfig, expected: &str) {
+        let problems = validate_indexer_config(config);
+        assert!(
+            problems.iter().any(|problem| problem.starts_with(expected)),
+            "No '{}' in {:?}",
+            expected,
+            problems
+        );
+    }
+
+    #[test]
+    fn test_validate_config() {
+        let mut config = token_indexer_config();
+        config.postgres_uri = Some("postgresql://localhost:5432/indexer".to_string());
+        assert!(validate_indexer_config(&config).is_empty());
+
+        config.processor = Some("nft_processor".to_string());
+        config.postgres_uri = None;
+        assert_problem(&config, "Unsupported processor 'nft_processor'");
+        assert_problem(&config, "Missing postgres_uri");
+
+        config.marketplace_event_mappings = Some(vec![MarketplaceEventMapping {
+            event_type: "0xfa4e::market::ListEvent".to_string(),
+            kind: "list".to_string(),
+            creator: "token.creator".to_string(),
+            collection: "token.collection".to_string(),
+            name: "token.name".to_string(),
+            property_version: None,
+            amount: None,
+            price: Some("price".to_string()),
+            coin_type: None,
+            buyer: None,
+            seller: None,
+            launchpad: None,
+        }]);
+        assert_problem(&config, "Invalid marketplace_event_mappings");
+
+        config.rarity_refresh_every_n_versions = Some(0);
+        assert_problem(
+            &config,
+            "rarity_refresh_every_n_versions must be greater than 0",
+        );
+
+        config.consistency_check = Some(ConsistencyCheckConfig {
+            every_n_batches: 0,
+            sample_size: None,
+            complete_from_version: None,
+            claims_complete_from_version: None,
+        });
+        assert_problem(&config, "Invalid consistency_check");
+
+        config.token_processor_shards = Some(0);
+        assert_problem(&config, "token_processor_shards must be greater than 0");
+
+        config.table_handle_cache = Some(TableHandleCacheConfig {
+            max_entries: None,
+            ttl_secs: Some(0),
+        });
+        assert_problem(
+            &config,
+            "Invalid table_handle_cache: ttl_secs must be greater than 0",
+        );
+
+        config.adaptive_fetch = Some(AdaptiveFetchConfig {
+            grow_after_fetches: Some(0),
+            ..AdaptiveFetchConfig::default()
+        });
+        assert_problem(&config, "Invalid adaptive_fetch");
+
+        config.fetch_retry = Some(FetchRetryConfig {
+            requests_per_second: Some(0),
+            ..FetchRetryConfig::default()
+        });
+        assert_problem(&config, "Invalid fetch_retry");
+
+        config.upstream_nodes = Some(UpstreamNodesConfig::default());
+        assert_problem(&config, "Invalid upstream_nodes");
+
+        config.transaction_stream = Some(TransactionStreamConfig {
+            address: "localhost:9000".to_string(),
+        });
+        assert_problem(
+            &config,
+            "Invalid transaction_stream: Can't be combined with upstream_nodes",
+        );
+
+        // Additional processors have to be supported too
+        config.additional_processors = Some(vec!["nft_processor".to_string()]);
+        assert_problem(&config, "Invalid additional_processors");
+
+        config.additional_processors = Some(vec!["coin_processor".to_string()]);
+        config.fetch_cache = Some(FetchCacheConfig {
+            max_batches: Some(0),
+        });
+        assert_problem(&config, "Invalid fetch_cache");
+
+        // A problem either way, without the feature it can't be set at all
+        config.metadata_fetcher = Some(MetadataFetcherConfig {
+            concurrency: Some(0),
+            ..MetadataFetcherConfig::default()
+        });
+        assert_problem(&config, "Invalid metadata_fetcher");
+
+        config.collection_stats_snapshots = Some(CollectionStatsSnapshotsConfig {
+            interval_secs: Some(0),
+        });
+        assert_problem(&config, "Invalid collection_stats_snapshots");
+
+        config.leaderboards = Some(LeaderboardsConfig {
+            interval_secs: None,
+            top_n: Some(0),
+        });
+        assert_problem(&config, "Invalid leaderboards");
+
+        config.marketplace_payload_mappings = Some(vec![MarketplacePayloadMapping {
+            module_address: "0xfa4e".to_string(),
+            function_name: "buy_token".to_string(),
+        }]);
+        assert_problem(&config, "Invalid marketplace_payload_mappings");
+
+        config.coin_price_sources = Some(vec![CoinPriceSourceConfig {
+            coin_type: "0x1::aptos_coin::AptosCoin".to_string(),
+            decimals: 8,
+            url: "not a url".to_string(),
+            price_path: "aptos.usd".to_string(),
+        }]);
+        assert_problem(&config, "Invalid coin_price_sources");
+
+        config.marketplace_escrow_addresses =
+            Some(vec!["0xfa4e".to_string(), "0xFA4E".to_string()]);
+        assert_problem(&config, "Invalid marketplace_escrow_addresses");
+
+        config.marketplace_typed_event_mappings = Some(vec![MarketplaceTypedEventMapping {
+            event_type: "0xfa4e::sweep::SweepBuyEvent".to_string(),
+            parser: "souffl3_sweep".to_string(),
+        }]);
+        assert_problem(&config, "Invalid marketplace_typed_event_mappings");
+
+        config.string_limits = Some(StringLimitsConfig {
+            name_length: Some(1024),
+            uri_length: None,
+        });
+        assert_problem(&config, "Invalid string_limits");
+
+        config.node_storage = Some(NodeStorageConfig {
+            dir: "/data/snapshots/mainnet".to_string(),
+            read_only: false,
+        });
+        assert_problem(&config, "Invalid node_storage: read_only must be true");
+
+        config.postgres_schema = Some("Mainnet".to_string());
+        assert_problem(&config, "Invalid postgres_schema");
+
+        config.marketplace_adapters = Some(vec!["topaz".to_string(), "opensea".to_string()]);
+        assert_problem(&config, "Invalid marketplace_adapters");
+
+        config.marketplace_definitions_path = Some(PathBuf::from("/nonexistent/markets.yaml"));
+        assert_problem(&config, "Invalid marketplace_definitions_path");
+
+        config.ans_token_creator = Some("Aptos Names".to_string());
+        assert_problem(&config, "Invalid ans_token_creator");
+
+        config.value_limits = Some(ValueLimitsConfig {
+            max_price: Some("-1".to_string()),
+            max_amount: None,
+        });
+        assert_problem(&config, "Invalid value_limits");
+
+        config.price_median_refresh_every_n_versions = Some(0);
+        assert_problem(
+            &config,
+            "price_median_refresh_every_n_versions must be greater than 0",
+        );
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn test_validate_config_against_database() {
+        if crate::should_skip_pg_tests() {
+            return;
+        }
+        let _ = setup();
+        assert!(check_database(&token_indexer_config()).is_empty());
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn test_run_builds_token_processor() {
+        if crate::should_skip_pg_tests() {
+            return;
+        }
+        let (conn_pool, _) = setup();
+        let processor = build_processor(&token_indexer_config(), conn_pool);
+        assert_eq!(processor.name(), "token_processor");
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn test_backfill_and_replay_diff() {
+        if crate::should_skip_pg_tests() {
+            return;
+        }
+        let (conn_pool, context) = setup();
+        let processor = build_processor(&token_indexer_config(), conn_pool.clone());
+        let num_versions = process_versions(context.clone(), processor, 0, 0, 10)
+            .await
+            .unwrap();
+        assert_eq!(num_versions, 1);
+
+        let statuses = processor_statuses::table
+            .filter(processor_statuses::name.eq("token_processor"))
+            .filter(processor_statuses::success.eq(true))
+            .count()
+            .get_result::<i64>(&mut conn_pool.get().unwrap())
+            .unwrap();
+        assert_eq!(statuses, 1);
+
+        let differences = replay_diff(
+            context.clone(),
+            &conn_pool,
+            &MarketplaceEventMappings::default(),
+            0,
+            0,
+            10,
+        )
+        .await
+        .unwrap();
+        assert!(differences.is_empty());
+
+        let processor = build_processor(&token_indexer_config(), conn_pool);
+        assert!(process_versions(context, processor, 0, u64::MAX, 10)
+            .await
+            .is_err());
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn test_debug_transaction() {
+        if crate::should_skip_pg_tests() {
+            return;
+        }
+        let (conn_pool, context) = setup();
+        let processor =
+            build_token_processor(&token_indexer_config(), conn_pool.clone(), None, None);
+        let rows = debug_transaction(context.clone(), &processor, 0, false)
+            .await
+            .unwrap();
+        assert!(rows["token_activities"].is_array());
+        assert!(rows["current_marketplace_listings"].is_array());
+
+        // Committing writes the rows without moving the processor's checkpoint
+        debug_transaction(context.clone(), &processor, 0, true)
+            .await
+            .unwrap();
+        let statuses = processor_statuses::table
+            .count()
+            .get_result::<i64>(&mut conn_pool.get().unwrap())
+            .unwrap();
+        assert_eq!(statuses, 0);
+
+        assert!(debug_transaction(context, &processor, u64::MAX, false)
+            .await
+            .is_err());
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn test_backfill_leaves_processor_statuses() {
+        if crate::should_skip_pg_tests() {
+            return;
+        }
+        let (conn_pool, context) = setup();
+        let mut config = token_indexer_config();
+        config.enabled_tables = Some(vec!["token_activities".to_string()]);
+        let processor = build_processor(&config, conn_pool.clone());
+        let num_versions = backfill_versions(context, processor, 0, 0, 10)
+            .await
+            .unwrap();
+        assert_eq!(num_versions, 1);
+
+        let mut conn = conn_pool.get().unwrap();
+        let statuses = processor_statuses::table
+            .count()
+            .get_result::<i64>(&mut conn)
+            .unwrap();
+        assert_eq!(statuses, 0);
+        let checkpoint = ProcessorStatusV2Query::get_by_processor(
+            &backfill_checkpoint_name("token_processor", 0),
+            &mut conn,
+        )
+        .unwrap();
+        assert!(checkpoint.is_none());
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn test_reindex_collection_without_activity() {
+        if crate::should_skip_pg_tests() {
+            return;
+        }
+        let (conn_pool, _) = setup();
+        let versions = get_collection_versions(&conn_pool, "0x1", "missing").unwrap();
+        assert!(versions.is_empty());
+    }
+}
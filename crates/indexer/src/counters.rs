@@ -2,8 +2,9 @@
 // SPDX-License-Identifier: Apache-2.0
 
 use aptos_metrics_core::{
-    register_int_counter, register_int_counter_vec, register_int_gauge_vec, IntCounter,
-    IntCounterVec, IntGaugeVec,
+    register_histogram, register_histogram_vec, register_int_counter, register_int_counter_vec,
+    register_int_gauge, register_int_gauge_vec, Histogram, HistogramVec, IntCounter, IntCounterVec,
+    IntGauge, IntGaugeVec,
 };
 use once_cell::sync::Lazy;
 
@@ -55,6 +56,56 @@ pub static GOT_CONNECTION: Lazy<IntCounter> = Lazy::new(|| {
     .unwrap()
 });
 
+/// Connections open in a processor's pool, as of its last checkout
+pub static CONNECTION_POOL_CONNECTIONS: Lazy<IntGaugeVec> = Lazy::new(|| {
+    register_int_gauge_vec!(
+        "indexer_connection_pool_connections",
+        "Number of connections open in the connection pool",
+        &["processor_name"]
+    )
+    .unwrap()
+});
+
+/// Idle connections in a processor's pool, as of its last checkout
+pub static CONNECTION_POOL_IDLE_CONNECTIONS: Lazy<IntGaugeVec> = Lazy::new(|| {
+    register_int_gauge_vec!(
+        "indexer_connection_pool_idle_connections",
+        "Number of idle connections in the connection pool",
+        &["processor_name"]
+    )
+    .unwrap()
+});
+
+/// Checkouts waiting for a connection. Above zero, the pool is saturated.
+pub static CONNECTION_POOL_WAIT_COUNT: Lazy<IntGaugeVec> = Lazy::new(|| {
+    register_int_gauge_vec!(
+        "indexer_connection_pool_wait_count",
+        "Number of checkouts waiting for a connection from the connection pool",
+        &["processor_name"]
+    )
+    .unwrap()
+});
+
+/// Time taken to check out a connection, including retries after pool timeouts
+pub static CONNECTION_CHECKOUT_SECONDS: Lazy<HistogramVec> = Lazy::new(|| {
+    register_histogram_vec!(
+        "indexer_connection_checkout_seconds",
+        "Time taken to check out a connection from the connection pool",
+        &["processor_name"]
+    )
+    .unwrap()
+});
+
+/// Checkouts slower than the backpressure threshold
+pub static SLOW_CONNECTION_CHECKOUTS: Lazy<IntCounterVec> = Lazy::new(|| {
+    register_int_counter_vec!(
+        "indexer_slow_connection_checkout_count",
+        "Number of connection checkouts that took longer than the backpressure threshold",
+        &["processor_name"]
+    )
+    .unwrap()
+});
+
 /// Number of times the indexer has been unable to fetch a transaction. Ideally zero.
 pub static UNABLE_TO_FETCH_TRANSACTION: Lazy<IntCounter> = Lazy::new(|| {
     register_int_counter!(
@@ -73,6 +124,91 @@ pub static FETCHED_TRANSACTION: Lazy<IntCounter> = Lazy::new(|| {
     .unwrap()
 });
 
+/// Time taken by a single fetch of a page of transactions, without converting them
+pub static FETCH_LATENCY_SECONDS: Lazy<Histogram> = Lazy::new(|| {
+    register_histogram!(
+        "indexer_fetch_latency_seconds",
+        "Time taken to fetch a page of transactions from storage"
+    )
+    .unwrap()
+});
+
+/// Fetches retried after an error
+pub static FETCH_RETRIES: Lazy<IntCounter> = Lazy::new(|| {
+    register_int_counter!(
+        "indexer_fetch_retry_count",
+        "Number of times a failed fetch of a page of transactions was retried"
+    )
+    .unwrap()
+});
+
+/// Versions per fetch, only changes with adaptive fetching
+pub static FETCH_PAGE_SIZE: Lazy<IntGauge> = Lazy::new(|| {
+    register_int_gauge!(
+        "indexer_fetch_page_size",
+        "Number of versions requested per fetch of a page of transactions"
+    )
+    .unwrap()
+});
+
+/// 1 while fetching is paused because the node keeps failing, until a fetch succeeds again
+pub static FETCH_CIRCUIT_OPEN: Lazy<IntGauge> = Lazy::new(|| {
+    register_int_gauge!(
+        "indexer_fetch_circuit_open",
+        "Whether fetching is paused because fetches from the node keep failing"
+    )
+    .unwrap()
+});
+
+/// 1 for the upstream node transactions are fetched from, 0 for the others
+pub static UPSTREAM_NODE_ACTIVE: Lazy<IntGaugeVec> = Lazy::new(|| {
+    register_int_gauge_vec!(
+        "indexer_upstream_node_active",
+        "Whether transactions are fetched from the upstream node",
+        &["url"]
+    )
+    .unwrap()
+});
+
+/// Ledger version of each upstream node, as of its last health check
+pub static UPSTREAM_NODE_LEDGER_VERSION: Lazy<IntGaugeVec> = Lazy::new(|| {
+    register_int_gauge_vec!(
+        "indexer_upstream_node_ledger_version",
+        "Ledger version of the upstream node as of its last health check",
+        &["url"]
+    )
+    .unwrap()
+});
+
+/// Number of times fetching switched to another upstream node
+pub static UPSTREAM_NODE_FAILOVERS: Lazy<IntCounter> = Lazy::new(|| {
+    register_int_counter!(
+        "indexer_upstream_node_failover_count",
+        "Number of times fetching failed over to another upstream node"
+    )
+    .unwrap()
+});
+
+/// Lookups of a processor's next batch in the fetch cache, by whether it was cached
+pub static FETCH_CACHE_LOOKUPS: Lazy<IntCounterVec> = Lazy::new(|| {
+    register_int_counter_vec!(
+        "indexer_fetch_cache_lookup_count",
+        "Number of lookups of a processor's next batch in the fetch cache, by hit or miss",
+        &["processor_name", "result"]
+    )
+    .unwrap()
+});
+
+/// Versions a processor's fetcher is behind the node's ledger version
+pub static PROCESSOR_LAG_VERSIONS: Lazy<IntGaugeVec> = Lazy::new(|| {
+    register_int_gauge_vec!(
+        "indexer_processor_lag_versions",
+        "Number of versions the node's ledger is ahead of the versions sent to a processor",
+        &["processor_name"]
+    )
+    .unwrap()
+});
+
 /// Max version processed
 pub static LATEST_PROCESSED_VERSION: Lazy<IntGaugeVec> = Lazy::new(|| {
     register_int_gauge_vec!(
@@ -82,3 +218,94 @@ pub static LATEST_PROCESSED_VERSION: Lazy<IntGaugeVec> = Lazy::new(|| {
     )
     .unwrap()
 });
+
+/// Current volume rows that differ from the sum of their history
+pub static VOLUME_DRIFT: Lazy<IntGaugeVec> = Lazy::new(|| {
+    register_int_gauge_vec!(
+        "indexer_volume_drift_count",
+        "Number of current volume rows that differed from their history at the last check",
+        &["table"]
+    )
+    .unwrap()
+});
+
+/// Lookups of a collection table handle's creator, by whether they were served from the cache
+pub static TABLE_HANDLE_CACHE_LOOKUPS: Lazy<IntCounterVec> = Lazy::new(|| {
+    register_int_counter_vec!(
+        "indexer_table_handle_cache_lookup_count",
+        "Number of collection table handle creator lookups, by hit or miss of the cache",
+        &["result"]
+    )
+    .unwrap()
+});
+
+/// Creators dropped from the table handle cache, by whether it was full or they expired
+pub static TABLE_HANDLE_CACHE_EVICTIONS: Lazy<IntCounterVec> = Lazy::new(|| {
+    register_int_counter_vec!(
+        "indexer_table_handle_cache_eviction_count",
+        "Number of collection table handle creators evicted from the cache, by reason",
+        &["reason"]
+    )
+    .unwrap()
+});
+
+/// Creators in the table handle cache
+pub static TABLE_HANDLE_CACHE_ENTRIES: Lazy<IntGauge> = Lazy::new(|| {
+    register_int_gauge!(
+        "indexer_table_handle_cache_entries",
+        "Number of collection table handle creators in the cache"
+    )
+    .unwrap()
+});
+
+/// Metadata uri fetches, by whether they were fetched, will be retried or failed for good
+pub static METADATA_FETCHES: Lazy<IntCounterVec> = Lazy::new(|| {
+    register_int_counter_vec!(
+        "indexer_metadata_fetch_count",
+        "Number of token metadata uri fetches, by result",
+        &["result"]
+    )
+    .unwrap()
+});
+
+/// Time taken to answer a request to the HTTP API, by endpoint and response status
+pub static HTTP_API_LATENCY_SECONDS: Lazy<HistogramVec> = Lazy::new(|| {
+    register_histogram_vec!(
+        "indexer_http_api_latency_seconds",
+        "Time taken to answer a request to the HTTP API",
+        &["endpoint", "status"]
+    )
+    .unwrap()
+});
+
+/// Rows of committed batches handed to the parquet sink, by whether they were written to a file,
+/// dropped because the sink was behind, or lost to a failed write
+pub static PARQUET_SINK_ROWS: Lazy<IntCounterVec> = Lazy::new(|| {
+    register_int_counter_vec!(
+        "indexer_parquet_sink_row_count",
+        "Number of rows handed to the parquet sink, by table and result",
+        &["table", "result"]
+    )
+    .unwrap()
+});
+
+/// Time taken to encode and upload a Parquet file, along with its manifest
+pub static PARQUET_SINK_WRITE_SECONDS: Lazy<HistogramVec> = Lazy::new(|| {
+    register_histogram_vec!(
+        "indexer_parquet_sink_write_seconds",
+        "Time taken to write a Parquet file and its manifest",
+        &["table"]
+    )
+    .unwrap()
+});
+
+/// Token events and inferred sales with a price or amount out of bounds, which are recorded but
+/// left out of volumes, floors and leaderboards, by the source of the value
+pub static SUSPECT_VALUE_EXCLUSIONS: Lazy<IntCounterVec> = Lazy::new(|| {
+    register_int_counter_vec!(
+        "indexer_suspect_value_exclusion_count",
+        "Number of token events and inferred sales left out of aggregates for a suspect value",
+        &["source"]
+    )
+    .unwrap()
+});
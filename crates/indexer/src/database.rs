@@ -4,11 +4,15 @@
 //! Database-related functions
 #![allow(clippy::extra_unused_lifetimes)]
 use crate::util::remove_null_bytes;
+use anyhow::ensure;
+use aptos_config::config::{DatabaseTimeoutsConfig, IndexerConfig};
 use diesel::{
+    connection::SimpleConnection,
     pg::{Pg, PgConnection},
     query_builder::{AstPass, Query, QueryFragment},
-    r2d2::{ConnectionManager, PoolError, PooledConnection},
-    QueryResult, RunQueryDsl,
+    r2d2::{ConnectionManager, CustomizeConnection, PoolError, PooledConnection},
+    result::{DatabaseErrorKind, Error},
+    Connection, QueryResult, RunQueryDsl,
 };
 use std::{cmp::min, sync::Arc};
 
@@ -56,11 +60,198 @@ pub fn clean_data_for_db<T: serde::Serialize + for<'de> serde::Deserialize<'de>>
     }
 }
 
+/// Same as r2d2's default
+pub const DEFAULT_POOL_SIZE: u32 = 10;
+
 pub fn new_db_pool(database_url: &str) -> Result<PgDbPool, PoolError> {
     let manager = ConnectionManager::<PgConnection>::new(database_url);
     PgPool::builder().build(manager).map(Arc::new)
 }
 
+/// Like new_db_pool, with the settings applied to every connection the pool opens and room for
+/// max_size connections
+pub fn new_db_pool_with_settings(
+    database_url: &str,
+    settings: ConnectionSettings,
+    max_size: u32,
+) -> Result<PgDbPool, PoolError> {
+    let manager = ConnectionManager::<PgConnection>::new(database_url);
+    PgPool::builder()
+        .max_size(max_size)
+        .connection_customizer(Box::new(settings))
+        .build(manager)
+        .map(Arc::new)
+}
+
+/// Session settings of the indexer's connections. Settings last for the session, so they're set
+/// once when a connection is opened rather than on each checkout.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct ConnectionSettings {
+    pub timeouts: ConnectionTimeouts,
+    pub schema: Option<PostgresSchema>,
+}
+
+impl ConnectionSettings {
+    pub fn from_config(config: &IndexerConfig) -> anyhow::Result<Self> {
+        Ok(Self {
+            timeouts: ConnectionTimeouts::from_config(config.database_timeouts.as_ref()),
+            schema: PostgresSchema::from_config(config.postgres_schema.as_deref())?,
+        })
+    }
+
+    fn set_statements(&self) -> String {
+        let mut set_statements = self.timeouts.set_statements();
+        if let Some(schema) = &self.schema {
+            if !set_statements.is_empty() {
+                set_statements.push(' ');
+            }
+            set_statements.push_str(&format!("SET search_path TO {};", schema.name()));
+        }
+        set_statements
+    }
+
+    /// Applies the settings to a connection that isn't from a pool, e.g. the migrations'
+    pub fn apply(&self, conn: &mut PgConnection) -> QueryResult<()> {
+        let set_statements = self.set_statements();
+        if set_statements.is_empty() {
+            return Ok(());
+        }
+        conn.batch_execute(&set_statements)
+    }
+}
+
+impl CustomizeConnection<PgConnection, diesel::r2d2::Error> for ConnectionSettings {
+    fn on_acquire(&self, conn: &mut PgConnection) -> Result<(), diesel::r2d2::Error> {
+        self.apply(conn).map_err(diesel::r2d2::Error::QueryError)
+    }
+}
+
+/// Postgres schema an indexer's tables are in instead of public, which lets indexers of different
+/// networks share a database. It's the only schema on the connections' search_path, so tables
+/// missing from it fail loudly rather than resolving to another indexer's. Limited to unquoted
+/// lowercase identifiers, so it can be written into statements as is.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct PostgresSchema(String);
+
+impl PostgresSchema {
+    pub fn from_config(schema: Option<&str>) -> anyhow::Result<Option<Self>> {
+        let schema = match schema {
+            Some(schema) => schema,
+            None => return Ok(None),
+        };
+        ensure!(
+            !schema.is_empty()
+                && schema.len() <= 63
+                && !schema.starts_with(|c: char| c.is_ascii_digit())
+                && schema
+                    .chars()
+                    .all(|c| c.is_ascii_lowercase() || c.is_ascii_digit() || c == '_'),
+            "postgres_schema '{}' must be at most 63 lowercase letters, digits and underscores, \
+            not starting with a digit",
+            schema
+        );
+        ensure!(
+            !schema.starts_with("pg_"),
+            "postgres_schema '{}' can't start with pg_, which postgres reserves",
+            schema
+        );
+        Ok(Some(Self(schema.to_string())))
+    }
+
+    pub fn name(&self) -> &str {
+        &self.0
+    }
+}
+
+/// Session timeouts in milliseconds, unset ones are left at the server's default
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct ConnectionTimeouts {
+    pub statement_timeout_ms: Option<u64>,
+    pub lock_timeout_ms: Option<u64>,
+    pub idle_in_transaction_session_timeout_ms: Option<u64>,
+}
+
+impl ConnectionTimeouts {
+    pub fn from_config(config: Option<&DatabaseTimeoutsConfig>) -> Self {
+        match config {
+            Some(config) => Self {
+                statement_timeout_ms: config.statement_timeout_ms,
+                lock_timeout_ms: config.lock_timeout_ms,
+                idle_in_transaction_session_timeout_ms: config
+                    .idle_in_transaction_session_timeout_ms,
+            },
+            None => Self::default(),
+        }
+    }
+
+    fn set_statements(&self) -> String {
+        [
+            ("statement_timeout", self.statement_timeout_ms),
+            ("lock_timeout", self.lock_timeout_ms),
+            (
+                "idle_in_transaction_session_timeout",
+                self.idle_in_transaction_session_timeout_ms,
+            ),
+        ]
+        .iter()
+        .filter_map(|(setting, timeout_ms)| {
+            timeout_ms.map(|timeout_ms| format!("SET {} = {};", setting, timeout_ms))
+        })
+        .collect::<Vec<_>>()
+        .join(" ")
+    }
+}
+
+/// Errors that running the same transaction again could get past: timeouts, conflicts with
+/// concurrent transactions and dropped connections
+pub fn is_retryable_error(error: &Error) -> bool {
+    match error {
+        Error::DatabaseError(kind, info) => match kind {
+            DatabaseErrorKind::SerializationFailure
+            | DatabaseErrorKind::ClosedConnection
+            | DatabaseErrorKind::UnableToSendCommand => true,
+            // Postgres error codes aren't exposed, only the message
+            DatabaseErrorKind::Unknown => {
+                let message = info.message();
+                message.contains("due to statement timeout")
+                    || message.contains("due to lock timeout")
+                    || message.contains("due to idle-in-transaction timeout")
+                    || message.contains("deadlock detected")
+            }
+            _ => false,
+        },
+        _ => false,
+    }
+}
+
+pub const MAX_TRANSACTION_RETRIES: u32 = 3;
+pub const TRANSACTION_RETRY_DELAY_MS: u64 = 500;
+
+/// Runs `f` in a read write transaction, running it again after a growing pause each time it
+/// fails with a retryable error, up to MAX_TRANSACTION_RETRIES times
+pub fn run_transaction_with_retries<T, F>(conn: &mut PgConnection, f: F) -> QueryResult<T>
+where
+    F: Fn(&mut PgConnection) -> QueryResult<T>,
+{
+    let mut retries = 0;
+    loop {
+        match conn.build_transaction().read_write().run(&f) {
+            Err(error) if is_retryable_error(&error) && retries < MAX_TRANSACTION_RETRIES => {
+                retries += 1;
+                aptos_logger::warn!(
+                    retries = retries,
+                    error = format!("{:?}", error),
+                    "Retrying transaction"
+                );
+                std::thread::sleep(std::time::Duration::from_millis(
+                    TRANSACTION_RETRY_DELAY_MS * retries as u64,
+                ));
+            }
+            res => return res,
+        }
+    }
+}
+
 pub fn execute_with_better_error<
     T: diesel::Table + diesel::QuerySource + diesel::query_builder::QueryId + 'static,
     U: diesel::query_builder::QueryFragment<diesel::pg::Pg>
@@ -69,10 +260,30 @@ pub fn execute_with_better_error<
 >(
     conn: &mut PgConnection,
     query: diesel::query_builder::InsertStatement<T, U>,
-    mut additional_where_clause: Option<&'static str>,
+    additional_where_clause: Option<&'static str>,
 ) -> diesel::QueryResult<usize>
 where
     <T as diesel::QuerySource>::FromClause: diesel::query_builder::QueryFragment<diesel::pg::Pg>,
+{
+    let final_query = with_where_clause(query, additional_where_clause);
+    let debug = diesel::debug_query::<diesel::pg::Pg, _>(&final_query).to_string();
+    aptos_logger::debug!("Executing query: {:?}", debug);
+    let res = final_query.execute(conn);
+    if let Err(ref e) = res {
+        aptos_logger::warn!("Error running query: {:?}\n{}", e, debug);
+    }
+    res
+}
+
+fn with_where_clause<T, U>(
+    query: diesel::query_builder::InsertStatement<T, U>,
+    mut additional_where_clause: Option<&'static str>,
+) -> UpsertFilterLatestTransactionQuery<diesel::query_builder::InsertStatement<T, U>>
+where
+    T: diesel::Table + diesel::QuerySource,
+    U: diesel::query_builder::QueryFragment<diesel::pg::Pg>
+        + diesel::insertable::CanInsertInSingleQuery<diesel::pg::Pg>,
+    <T as diesel::QuerySource>::FromClause: diesel::query_builder::QueryFragment<diesel::pg::Pg>,
 {
     let original_query = diesel::debug_query::<diesel::pg::Pg, _>(&query).to_string();
     // This is needed because if we don't insert any row, then diesel makes a call like this
@@ -80,17 +291,171 @@ where
     if original_query.to_lowercase().contains("where") {
         additional_where_clause = None;
     }
-    let final_query = UpsertFilterLatestTransactionQuery {
+    UpsertFilterLatestTransactionQuery {
         query,
         where_clause: additional_where_clause,
+    }
+}
+
+/// Logged rows are cut off after this many characters
+pub const MAX_LOGGED_ROW_LENGTH: usize = 2000;
+
+/// Runs one chunk of a chunked insert, built from `items` by `build_query`. The chunk runs in a
+/// savepoint so that if it fails, the transaction it's in can still be used to find out why: the
+/// failure is logged with the table and chunk, and if it was the rows rather than the connection
+/// at fault, the chunk is bisected to find and log the offending row. The original error is
+/// returned either way.
+pub fn execute_chunk_with_context<'a, Item, T, U, F>(
+    conn: &mut PgConnection,
+    table_name: &str,
+    chunk_index: usize,
+    items: &'a [Item],
+    build_query: F,
+    additional_where_clause: Option<&'static str>,
+) -> diesel::QueryResult<usize>
+where
+    Item: serde::Serialize,
+    F: Fn(&'a [Item]) -> diesel::query_builder::InsertStatement<T, U>,
+    T: diesel::Table + diesel::QuerySource + diesel::query_builder::QueryId + 'static,
+    U: diesel::query_builder::QueryFragment<diesel::pg::Pg>
+        + diesel::query_builder::QueryId
+        + diesel::insertable::CanInsertInSingleQuery<diesel::pg::Pg>,
+    <T as diesel::QuerySource>::FromClause: diesel::query_builder::QueryFragment<diesel::pg::Pg>,
+{
+    let res = conn.transaction(|conn| {
+        execute_with_better_error(conn, build_query(items), additional_where_clause)
+    });
+    let error = match res {
+        Ok(num_rows) => return Ok(num_rows),
+        Err(error) => error,
     };
-    let debug = diesel::debug_query::<diesel::pg::Pg, _>(&final_query).to_string();
-    aptos_logger::debug!("Executing query: {:?}", debug);
-    let res = final_query.execute(conn);
-    if let Err(ref e) = res {
-        aptos_logger::warn!("Error running query: {:?}\n{}", e, debug);
+    aptos_logger::error!(
+        table = table_name,
+        chunk_index = chunk_index,
+        chunk_size = items.len(),
+        error = format!("{:?}", error),
+        "Failed to insert chunk"
+    );
+    if is_data_error(&error) {
+        match find_offending_row(conn, items, &build_query, additional_where_clause) {
+            Ok(Some(row_index)) => aptos_logger::error!(
+                table = table_name,
+                chunk_index = chunk_index,
+                row_index = row_index,
+                row = serialize_truncated(&items[row_index]),
+                "Offending row of failed chunk"
+            ),
+            Ok(None) => aptos_logger::error!(
+                table = table_name,
+                chunk_index = chunk_index,
+                "Every row of the failed chunk inserts on its own, the rows conflict with each other"
+            ),
+            Err(bisect_error) => aptos_logger::warn!(
+                table = table_name,
+                chunk_index = chunk_index,
+                error = format!("{:?}", bisect_error),
+                "Couldn't narrow down the failed chunk"
+            ),
+        }
+    }
+    Err(error)
+}
+
+/// Errors caused by the values inserted, which a smaller insert could avoid. Anything else, e.g.
+/// a dropped connection or a timeout, isn't the rows' fault.
+fn is_data_error(error: &Error) -> bool {
+    if is_retryable_error(error) {
+        return false;
+    }
+    match error {
+        Error::DatabaseError(kind, _) => matches!(
+            kind,
+            DatabaseErrorKind::UniqueViolation
+                | DatabaseErrorKind::ForeignKeyViolation
+                | DatabaseErrorKind::NotNullViolation
+                | DatabaseErrorKind::CheckViolation
+                // Includes invalid encodings, e.g. a NUL byte in a string
+                | DatabaseErrorKind::Unknown
+        ),
+        Error::SerializationError(_) => true,
+        _ => false,
+    }
+}
+
+/// Halves the failing rows until one is left, returning its index. None if neither half fails on
+/// its own, which happens when rows only fail together, e.g. two upserts of the same key.
+fn find_offending_row<'a, Item, T, U, F>(
+    conn: &mut PgConnection,
+    items: &'a [Item],
+    build_query: &F,
+    additional_where_clause: Option<&'static str>,
+) -> diesel::QueryResult<Option<usize>>
+where
+    F: Fn(&'a [Item]) -> diesel::query_builder::InsertStatement<T, U>,
+    T: diesel::Table + diesel::QuerySource + diesel::query_builder::QueryId + 'static,
+    U: diesel::query_builder::QueryFragment<diesel::pg::Pg>
+        + diesel::query_builder::QueryId
+        + diesel::insertable::CanInsertInSingleQuery<diesel::pg::Pg>,
+    <T as diesel::QuerySource>::FromClause: diesel::query_builder::QueryFragment<diesel::pg::Pg>,
+{
+    if items.is_empty() {
+        return Ok(None);
+    }
+    let (mut start, mut end) = (0, items.len());
+    while end - start > 1 {
+        let mid = start + (end - start) / 2;
+        if fails(
+            conn,
+            &items[start..mid],
+            build_query,
+            additional_where_clause,
+        )? {
+            end = mid;
+        } else if fails(conn, &items[mid..end], build_query, additional_where_clause)? {
+            start = mid;
+        } else {
+            return Ok(None);
+        }
+    }
+    Ok(Some(start))
+}
+
+/// Whether inserting the rows fails with a data error. The insert is always rolled back.
+fn fails<'a, Item, T, U, F>(
+    conn: &mut PgConnection,
+    items: &'a [Item],
+    build_query: &F,
+    additional_where_clause: Option<&'static str>,
+) -> diesel::QueryResult<bool>
+where
+    F: Fn(&'a [Item]) -> diesel::query_builder::InsertStatement<T, U>,
+    T: diesel::Table + diesel::QuerySource + diesel::query_builder::QueryId + 'static,
+    U: diesel::query_builder::QueryFragment<diesel::pg::Pg>
+        + diesel::query_builder::QueryId
+        + diesel::insertable::CanInsertInSingleQuery<diesel::pg::Pg>,
+    <T as diesel::QuerySource>::FromClause: diesel::query_builder::QueryFragment<diesel::pg::Pg>,
+{
+    let res = conn.transaction::<(), Error, _>(|conn| {
+        with_where_clause(build_query(items), additional_where_clause).execute(conn)?;
+        Err(Error::RollbackTransaction)
+    });
+    match res {
+        Err(Error::RollbackTransaction) => Ok(false),
+        Err(error) if is_data_error(&error) => Ok(true),
+        Err(error) => Err(error),
+        Ok(()) => Ok(false),
+    }
+}
+
+fn serialize_truncated<Item: serde::Serialize>(item: &Item) -> String {
+    match serde_json::to_string(item) {
+        Ok(row) if row.chars().count() > MAX_LOGGED_ROW_LENGTH => format!(
+            "{}...",
+            row.chars().take(MAX_LOGGED_ROW_LENGTH).collect::<String>()
+        ),
+        Ok(row) => row,
+        Err(error) => format!("<unserializable: {}>", error),
     }
-    res
 }
 
 /// Section below is required to modify the query.
@@ -116,6 +481,25 @@ where
 #[cfg(test)]
 mod test {
     use super::*;
+    use crate::{
+        indexer::tailer::{test::wipe_database, MIGRATIONS},
+        models::token_models::ans_lookup::CurrentAnsPrimaryName,
+        schema::current_ans_primary_name,
+    };
+    use diesel::QueryDsl;
+    use diesel_migrations::MigrationHarness;
+
+    fn primary_names() -> Vec<CurrentAnsPrimaryName> {
+        (0..5)
+            .map(|i| CurrentAnsPrimaryName {
+                registered_address: format!("0x{}", i),
+                // Postgres doesn't accept NUL bytes in text
+                domain: Some(if i == 3 { "nul\0name" } else { "name" }.to_string()),
+                subdomain: None,
+                last_transaction_version: i,
+            })
+            .collect()
+    }
 
     #[tokio::test]
     async fn test_get_chunks_logic() {
@@ -135,4 +519,161 @@ mod test {
             vec![(0, 21845), (21845, 43690), (43690, 65535)]
         );
     }
+
+    #[test]
+    fn test_is_data_error() {
+        assert!(is_data_error(&Error::DatabaseError(
+            DatabaseErrorKind::UniqueViolation,
+            Box::new("duplicate key".to_string()),
+        )));
+        assert!(is_data_error(&Error::DatabaseError(
+            DatabaseErrorKind::Unknown,
+            Box::new("invalid byte sequence for encoding \"UTF8\": 0x00".to_string()),
+        )));
+        assert!(!is_data_error(&Error::DatabaseError(
+            DatabaseErrorKind::ClosedConnection,
+            Box::new("server closed the connection".to_string()),
+        )));
+        assert!(!is_data_error(&Error::NotFound));
+    }
+
+    #[test]
+    fn test_is_retryable_error() {
+        assert!(is_retryable_error(&Error::DatabaseError(
+            DatabaseErrorKind::Unknown,
+            Box::new("canceling statement due to statement timeout".to_string()),
+        )));
+        assert!(is_retryable_error(&Error::DatabaseError(
+            DatabaseErrorKind::Unknown,
+            Box::new("canceling statement due to lock timeout".to_string()),
+        )));
+        assert!(is_retryable_error(&Error::DatabaseError(
+            DatabaseErrorKind::SerializationFailure,
+            Box::new("could not serialize access".to_string()),
+        )));
+        assert!(!is_retryable_error(&Error::DatabaseError(
+            DatabaseErrorKind::UniqueViolation,
+            Box::new("duplicate key".to_string()),
+        )));
+        // A timeout isn't the rows' fault, the chunk isn't bisected
+        assert!(!is_data_error(&Error::DatabaseError(
+            DatabaseErrorKind::Unknown,
+            Box::new("canceling statement due to statement timeout".to_string()),
+        )));
+    }
+
+    #[test]
+    fn test_timeout_set_statements() {
+        assert_eq!(ConnectionTimeouts::default().set_statements(), "");
+        assert_eq!(
+            ConnectionTimeouts {
+                statement_timeout_ms: Some(30000),
+                lock_timeout_ms: None,
+                idle_in_transaction_session_timeout_ms: Some(60000),
+            }
+            .set_statements(),
+            "SET statement_timeout = 30000; SET idle_in_transaction_session_timeout = 60000;"
+        );
+    }
+
+    #[test]
+    fn test_postgres_schema() {
+        assert_eq!(PostgresSchema::from_config(None).unwrap(), None);
+        let schema = PostgresSchema::from_config(Some("testnet_2")).unwrap();
+        assert_eq!(
+            ConnectionSettings {
+                timeouts: ConnectionTimeouts {
+                    lock_timeout_ms: Some(5000),
+                    ..ConnectionTimeouts::default()
+                },
+                schema: schema.clone(),
+            }
+            .set_statements(),
+            "SET lock_timeout = 5000; SET search_path TO testnet_2;"
+        );
+        assert_eq!(
+            ConnectionSettings {
+                timeouts: ConnectionTimeouts::default(),
+                schema,
+            }
+            .set_statements(),
+            "SET search_path TO testnet_2;"
+        );
+        for schema in [
+            "",
+            "Mainnet",
+            "2mainnet",
+            "main-net",
+            "mainnet; DROP SCHEMA public",
+            "pg_temp",
+        ] {
+            assert!(PostgresSchema::from_config(Some(schema)).is_err());
+        }
+        assert!(PostgresSchema::from_config(Some(&"a".repeat(64))).is_err());
+    }
+
+    #[test]
+    fn test_failed_chunk_finds_offending_row() {
+        if crate::should_skip_pg_tests() {
+            return;
+        }
+        let database_url = std::env::var("INDEXER_DATABASE_URL")
+            .expect("must set 'INDEXER_DATABASE_URL' to run tests!");
+        let conn_pool = new_db_pool(database_url.as_str()).unwrap();
+        let mut conn = conn_pool.get().unwrap();
+        wipe_database(&mut conn);
+        conn.run_pending_migrations(MIGRATIONS).unwrap();
+
+        let names = primary_names();
+        assert_eq!(
+            find_offending_row(
+                &mut conn,
+                &names,
+                &|chunk| diesel::insert_into(current_ans_primary_name::table).values(chunk),
+                None,
+            )
+            .unwrap(),
+            Some(3)
+        );
+        assert_eq!(
+            find_offending_row(
+                &mut conn,
+                &names[..3],
+                &|chunk| diesel::insert_into(current_ans_primary_name::table).values(chunk),
+                None,
+            )
+            .unwrap(),
+            None
+        );
+
+        // The failed chunk is rolled back without aborting the transaction it's in
+        conn.transaction::<_, Error, _>(|conn| {
+            let res = execute_chunk_with_context(
+                conn,
+                "current_ans_primary_name",
+                0,
+                &names,
+                |chunk| diesel::insert_into(current_ans_primary_name::table).values(chunk),
+                None,
+            );
+            assert!(matches!(res, Err(ref error) if is_data_error(error)));
+            execute_chunk_with_context(
+                conn,
+                "current_ans_primary_name",
+                1,
+                &names[..3],
+                |chunk| diesel::insert_into(current_ans_primary_name::table).values(chunk),
+                None,
+            )?;
+            Ok(())
+        })
+        .unwrap();
+        assert_eq!(
+            current_ans_primary_name::table
+                .count()
+                .get_result::<i64>(&mut conn)
+                .unwrap(),
+            3
+        );
+    }
 }
@@ -0,0 +1,261 @@
+// Copyright (c) Aptos
+// SPDX-License-Identifier: Apache-2.0
+
+//! Token and collection ids are stored by the sha256 of their display string, so every row a
+//! database has is keyed by the scheme that was current when it was written. A change to how ids
+//! are formatted or hashed is a new scheme version: the version is recorded in indexer_metadata,
+//! and the indexer refuses to write to a database on another version, since its rows would be
+//! split from the stored ones under different hashes. Databases on an older scheme are rehashed
+//! to the current one when asked to.
+//!
+//! Versions:
+//! - 0: sha256 of `creator::collection[::name]` with the creator as the API formats it, leading
+//!   zeros stripped
+//! - 1: the same with the creator padded to 64 hex chars, see `util::standardize_address`
+
+use crate::{
+    migrations::with_migration_lock,
+    models::token_models::address_normalization::normalize_addresses, schema,
+};
+use anyhow::{bail, Context, Result};
+use aptos_logger::info;
+use diesel::{ExpressionMethods, OptionalExtension, PgConnection, QueryDsl, RunQueryDsl};
+
+pub use crate::util::hash_str;
+
+/// Scheme the ids this binary writes are hashed with
+pub const HASH_SCHEME_VERSION: i32 = 1;
+const HASH_SCHEME_VERSION_NAME: &str = "hash_scheme_version";
+
+/// Scheme of the stored rows, None if the database was never migrated
+pub fn get_hash_scheme_version(conn: &mut PgConnection) -> Result<Option<i32>> {
+    use schema::indexer_metadata::dsl::*;
+
+    let version = indexer_metadata
+        .filter(name.eq(HASH_SCHEME_VERSION_NAME))
+        .select(value)
+        .first::<String>(conn)
+        .optional()
+        .context("Could not read the hash scheme version")?;
+    version
+        .map(|version| {
+            version
+                .parse()
+                .with_context(|| format!("Invalid hash scheme version '{}'", version))
+        })
+        .transpose()
+}
+
+fn set_hash_scheme_version(conn: &mut PgConnection, version: i32) -> Result<()> {
+    use schema::indexer_metadata::dsl::*;
+
+    diesel::insert_into(schema::indexer_metadata::table)
+        .values((
+            name.eq(HASH_SCHEME_VERSION_NAME),
+            value.eq(version.to_string()),
+        ))
+        .on_conflict(name)
+        .do_update()
+        .set((
+            value.eq(version.to_string()),
+            updated_at.eq(diesel::dsl::now),
+        ))
+        .execute(conn)
+        .context("Could not record the hash scheme version")?;
+    Ok(())
+}
+
+/// Rewrites the rows hashed with `from_version` to the current scheme one version at a time,
+/// recording each version as it's reached, and returns the number of rows changed. A rehash that
+/// stops halfway is picked up by running it again.
+pub fn rehash(conn: &mut PgConnection, from_version: i32) -> Result<usize> {
+    let mut num_rows = 0;
+    for version in from_version..HASH_SCHEME_VERSION {
+        info!(
+            from_version = version,
+            to_version = version + 1,
+            "Rehashing token and collection ids..."
+        );
+        num_rows += match version {
+            0 => normalize_addresses(conn).context("Could not rehash to scheme 1")?,
+            _ => bail!("No rehash from hash scheme {}", version),
+        };
+        set_hash_scheme_version(conn, version + 1)?;
+    }
+    Ok(num_rows)
+}
+
+/// Scheme of the stored rows, failing if there's none or it's newer than this binary's
+fn stored_hash_scheme_version(conn: &mut PgConnection) -> Result<i32> {
+    match get_hash_scheme_version(conn)? {
+        Some(version) if version > HASH_SCHEME_VERSION => bail!(
+            "Database ids are hashed with scheme {} but this indexer only knows up to {}, it's \
+            older than the one that wrote them",
+            version,
+            HASH_SCHEME_VERSION
+        ),
+        Some(version) => Ok(version),
+        None => bail!("Database has no hash scheme version, it needs migrating"),
+    }
+}
+
+/// Fails unless the database's rows are on this binary's hash scheme. A database on an older one
+/// is rehashed first if `rehash_if_behind` is set. Replicas starting together all find it behind,
+/// so the rehash holds the migration lock and reads the scheme again once it has it: the first
+/// replica rehashes and the others find it current.
+pub fn ensure_hash_scheme_is_current(
+    conn: &mut PgConnection,
+    rehash_if_behind: bool,
+) -> Result<()> {
+    let version = stored_hash_scheme_version(conn)?;
+    if version < HASH_SCHEME_VERSION {
+        if !rehash_if_behind {
+            bail!(
+                "Database ids are hashed with scheme {} but the indexer hashes with {}. Run with \
+                --rehash, or with rehash set, to rewrite them.",
+                version,
+                HASH_SCHEME_VERSION
+            );
+        }
+        let num_rows = with_migration_lock(conn, |conn| {
+            let version = stored_hash_scheme_version(conn)?;
+            rehash(conn, version)
+        })?;
+        info!(
+            num_rows = num_rows,
+            hash_scheme_version = HASH_SCHEME_VERSION,
+            "Rehashed token and collection ids"
+        );
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        database::new_db_pool,
+        indexer::tailer::{test::wipe_database, MIGRATIONS},
+        models::token_models::token_utils::{CollectionDataIdType, TokenDataIdType},
+    };
+    use diesel::{sql_query, Connection};
+    use diesel_migrations::MigrationHarness;
+    use serde_json::json;
+
+    /// Changing any of these orphans every stored row, see the module doc before updating them
+    #[test]
+    fn test_hash_golden_vectors() {
+        assert_eq!(
+            hash_str(""),
+            "e3b0c44298fc1c149afbf4c8996fb92427ae41e4649b934ca495991b7852b855"
+        );
+        assert_eq!(
+            hash_str("Potions"),
+            "b5e0cd8a0fdcaf9bc9cdb6e35357a51d1d00728fb53280f4455633a60cd8bdfd"
+        );
+
+        // Short creators are padded before hashing
+        let token_data_id: TokenDataIdType = serde_json::from_value(json!({
+            "creator": "0xC4E7",
+            "collection": "Potions",
+            "name": "Potion",
+        }))
+        .unwrap();
+        assert_eq!(
+            token_data_id.to_hash(),
+            "73e01552c5a453064bd9d2003081fa4620163578b993f0110b4f77d4510428b6"
+        );
+        assert_eq!(
+            token_data_id.get_collection_data_id_hash(),
+            "00491681bc2d94528d9ada0b9b5b3bb801e9f45268c2330ba40333b9b6c68aa0"
+        );
+        assert_eq!(
+            CollectionDataIdType::new(
+                "0x0000000000000000000000000000000000000000000000000000000000c4e7".to_string(),
+                "Potions".to_string()
+            )
+            .to_hash(),
+            "00491681bc2d94528d9ada0b9b5b3bb801e9f45268c2330ba40333b9b6c68aa0"
+        );
+    }
+
+    #[test]
+    fn test_ensure_hash_scheme_is_current() {
+        if crate::should_skip_pg_tests() {
+            return;
+        }
+        let database_url = std::env::var("INDEXER_DATABASE_URL")
+            .expect("must set 'INDEXER_DATABASE_URL' to run tests!");
+        wipe_database(&mut new_db_pool(&database_url).unwrap().get().unwrap());
+        let mut conn = PgConnection::establish(&database_url).unwrap();
+        conn.run_pending_migrations(MIGRATIONS).unwrap();
+
+        // A fresh database starts on the current scheme
+        assert_eq!(
+            get_hash_scheme_version(&mut conn).unwrap(),
+            Some(HASH_SCHEME_VERSION)
+        );
+        ensure_hash_scheme_is_current(&mut conn, false).unwrap();
+
+        // One written before addresses were padded has to be rehashed
+        sql_query(format!(
+            "INSERT INTO current_collection_datas (
+                collection_data_id_hash, creator_address, collection_name, description,
+                metadata_uri, supply, maximum, maximum_mutable, uri_mutable, description_mutable,
+                last_transaction_version, table_handle, last_transaction_timestamp
+            ) VALUES ('{}', '0xc4e7', 'Potions', '', '', 1, 1, false, false, false, 1, '0x7', NOW())",
+            hash_str("0xc4e7::Potions"),
+        ))
+        .execute(&mut conn)
+        .unwrap();
+        set_hash_scheme_version(&mut conn, 0).unwrap();
+        assert!(ensure_hash_scheme_is_current(&mut conn, false).is_err());
+        ensure_hash_scheme_is_current(&mut conn, true).unwrap();
+        assert_eq!(
+            get_hash_scheme_version(&mut conn).unwrap(),
+            Some(HASH_SCHEME_VERSION)
+        );
+        let hash = schema::current_collection_datas::table
+            .select(schema::current_collection_datas::collection_data_id_hash)
+            .first::<String>(&mut conn)
+            .unwrap();
+        assert_eq!(
+            hash,
+            "00491681bc2d94528d9ada0b9b5b3bb801e9f45268c2330ba40333b9b6c68aa0"
+        );
+
+        // Nor can a binary that's behind the database write to it
+        set_hash_scheme_version(&mut conn, HASH_SCHEME_VERSION + 1).unwrap();
+        assert!(ensure_hash_scheme_is_current(&mut conn, true).is_err());
+    }
+
+    #[test]
+    fn test_replicas_rehash_once() {
+        if crate::should_skip_pg_tests() {
+            return;
+        }
+        let database_url = std::env::var("INDEXER_DATABASE_URL")
+            .expect("must set 'INDEXER_DATABASE_URL' to run tests!");
+        wipe_database(&mut new_db_pool(&database_url).unwrap().get().unwrap());
+        let mut conn = PgConnection::establish(&database_url).unwrap();
+        conn.run_pending_migrations(MIGRATIONS).unwrap();
+        set_hash_scheme_version(&mut conn, 0).unwrap();
+
+        let replicas = (0..3)
+            .map(|_| {
+                let database_url = database_url.clone();
+                std::thread::spawn(move || {
+                    let mut conn = PgConnection::establish(&database_url).unwrap();
+                    ensure_hash_scheme_is_current(&mut conn, true)
+                })
+            })
+            .collect::<Vec<_>>();
+        for replica in replicas {
+            replica.join().unwrap().unwrap();
+        }
+        assert_eq!(
+            get_hash_scheme_version(&mut conn).unwrap(),
+            Some(HASH_SCHEME_VERSION)
+        );
+    }
+}
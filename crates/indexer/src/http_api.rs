@@ -0,0 +1,782 @@
+// Copyright (c) Aptos
+// SPDX-License-Identifier: Apache-2.0
+
+//! Read only HTTP API over the token tables, for frontends and services that would otherwise
+//! query postgres themselves. Only built with the `http-api` feature. Every endpoint is a thin
+//! wrapper around a function of `queries`, and answers with the rows as JSON.
+//!
+//! Lists are paged with an opaque `cursor`: each page has a `next_cursor` to pass back for the
+//! page after it, which is null on the last page. There's no authentication, so the API should
+//! only be reachable by trusted callers.
+//!
+//! `/status` answers with the migration the database's schema is at, next to the latest one the
+//! binary embeds, so deployments can tell whether a replica is waiting on a migration.
+//!
+//! `/accounts/<address>/activities` reads from account_token_activities when the indexer's
+//! account_token_activities config is enabled, and from token_activities otherwise.
+//!
+//! When served by the indexer itself, `/ws` also streams the live feed of committed batches.
+//! Clients send `{"subscribe": "<channel>"}` and `{"unsubscribe": "<channel>"}`, and get every
+//! message of the channels they're subscribed to. Falling behind by more than the subscriber
+//! buffer drops the oldest messages, which a `{"type": "lagged", "skipped": <n>}` notice reports.
+
+use crate::{
+    counters::HTTP_API_LATENCY_SECONDS,
+    database::PgDbPool,
+    live_feed::{self, parse_channel, LiveFeed, LiveMessage},
+    migrations::SchemaStatus,
+    models::token_models::{
+        collection_stats_snapshots::CollectionStatsSnapshot,
+        collection_volume::CurrentCollectionVolume,
+    },
+    queries::{
+        get_account_activities, get_account_token_activities, get_active_listings_page,
+        get_collection_volume, get_latest_collection_stats, get_owner_tokens_page,
+        get_token_activities, ActivityCursor, ListingCursor, OwnershipCursor, VolumeWindow,
+        ACTIVITY_PAGE_SIZE,
+    },
+};
+use anyhow::{ensure, Context};
+use aptos_config::config::HttpApiConfig;
+use aptos_logger::{error, info};
+use diesel::PgConnection;
+use futures::{SinkExt, StreamExt};
+use serde::{de::DeserializeOwned, Deserialize, Serialize};
+use serde_json::json;
+use std::{collections::HashSet, convert::Infallible, net::SocketAddr, sync::Arc, time::Instant};
+use tokio::sync::broadcast::{error::RecvError, Receiver};
+use warp::{
+    http::StatusCode,
+    reply::{Reply, Response},
+    ws::{Message, WebSocket, Ws},
+    Filter, Rejection,
+};
+
+pub const DEFAULT_BIND_ADDRESS: &str = "127.0.0.1:8090";
+pub const DEFAULT_POOL_SIZE: u64 = 10;
+pub const DEFAULT_MAX_SUBSCRIPTIONS_PER_CONNECTION: u64 = 50;
+/// Rows per page when the request doesn't set a limit, and the most it can set
+pub const MAX_PAGE_SIZE: i64 = 100;
+
+#[derive(Debug)]
+pub struct HttpApi {
+    bind_address: SocketAddr,
+    pool_size: u32,
+    serve_with_indexer: bool,
+    max_subscriptions: usize,
+    subscriber_buffer_size: usize,
+    account_token_activities: bool,
+}
+
+impl HttpApi {
+    /// account_token_activities is the indexer's config of the same name, whether the token
+    /// processor writes the table
+    pub fn from_config(
+        config: Option<&HttpApiConfig>,
+        account_token_activities: bool,
+    ) -> anyhow::Result<Self> {
+        let default_config = HttpApiConfig::default();
+        let config = config.unwrap_or(&default_config);
+        let bind_address = config
+            .bind_address
+            .as_deref()
+            .unwrap_or(DEFAULT_BIND_ADDRESS);
+        let bind_address = bind_address
+            .parse::<SocketAddr>()
+            .with_context(|| format!("Invalid bind_address {}", bind_address))?;
+        let pool_size = config.pool_size.unwrap_or(DEFAULT_POOL_SIZE);
+        ensure!(
+            pool_size > 0 && pool_size <= u32::MAX as u64,
+            "pool_size must be between 1 and {}",
+            u32::MAX
+        );
+        let max_subscriptions = config
+            .max_subscriptions_per_connection
+            .unwrap_or(DEFAULT_MAX_SUBSCRIPTIONS_PER_CONNECTION);
+        ensure!(
+            max_subscriptions > 0,
+            "max_subscriptions_per_connection must be greater than 0"
+        );
+        let subscriber_buffer_size = config
+            .subscriber_buffer_size
+            .unwrap_or(live_feed::DEFAULT_BUFFER_SIZE);
+        // The broadcast channel's limit
+        ensure!(
+            subscriber_buffer_size > 0 && subscriber_buffer_size <= usize::MAX as u64 / 2,
+            "subscriber_buffer_size must be greater than 0"
+        );
+        Ok(Self {
+            bind_address,
+            pool_size: pool_size as u32,
+            serve_with_indexer: config.serve_with_indexer.unwrap_or(false),
+            max_subscriptions: max_subscriptions as usize,
+            subscriber_buffer_size: subscriber_buffer_size as usize,
+            account_token_activities,
+        })
+    }
+
+    pub fn pool_size(&self) -> u32 {
+        self.pool_size
+    }
+
+    pub fn serve_with_indexer(&self) -> bool {
+        self.serve_with_indexer
+    }
+
+    /// A feed for the token processor to publish to, with room for subscriber_buffer_size
+    /// messages per subscriber
+    pub fn live_feed(&self) -> LiveFeed {
+        LiveFeed::new(self.subscriber_buffer_size)
+    }
+
+    /// Serves requests until the process exits. /ws is only served with a live feed.
+    pub async fn serve(self, conn_pool: PgDbPool, live_feed: Option<LiveFeed>) {
+        info!(
+            bind_address = self.bind_address.to_string(),
+            live_feed = live_feed.is_some(),
+            account_token_activities = self.account_token_activities,
+            "Serving the HTTP API"
+        );
+        warp::serve(routes(
+            conn_pool,
+            live_feed,
+            self.max_subscriptions,
+            self.account_token_activities,
+        ))
+        .run(self.bind_address)
+        .await;
+    }
+}
+
+/// The collection's all time volume and its latest daily stats
+#[derive(Debug, Serialize)]
+pub struct CollectionStats {
+    pub volume: Option<CurrentCollectionVolume>,
+    pub latest_snapshot: Option<CollectionStatsSnapshot>,
+}
+
+#[derive(Debug, Serialize)]
+pub struct Page<T> {
+    pub data: Vec<T>,
+    pub next_cursor: Option<String>,
+}
+
+impl<T> Page<T> {
+    /// A page shorter than the page size is the last one
+    fn new<C: Serialize>(data: Vec<T>, page_size: i64, to_cursor: impl Fn(&T) -> C) -> Self {
+        let next_cursor = if data.len() as i64 == page_size {
+            data.last().map(|row| encode_cursor(&to_cursor(row)))
+        } else {
+            None
+        };
+        Self { data, next_cursor }
+    }
+}
+
+#[derive(Debug, Default, Deserialize)]
+struct PageParams {
+    cursor: Option<String>,
+    limit: Option<i64>,
+}
+
+/// Activities always come ACTIVITY_PAGE_SIZE at a time
+#[derive(Debug, Default, Deserialize)]
+struct CursorParams {
+    cursor: Option<String>,
+}
+
+#[derive(Debug, Default, Deserialize)]
+struct OwnerTokensParams {
+    cursor: Option<String>,
+    limit: Option<i64>,
+    include_spam: Option<bool>,
+    include_deleted: Option<bool>,
+}
+
+#[derive(Debug)]
+enum ApiError {
+    BadRequest(String),
+    Internal(String),
+}
+
+impl From<diesel::result::Error> for ApiError {
+    fn from(err: diesel::result::Error) -> Self {
+        Self::Internal(err.to_string())
+    }
+}
+
+fn page_size(limit: Option<i64>) -> Result<i64, ApiError> {
+    match limit {
+        None => Ok(MAX_PAGE_SIZE),
+        Some(limit) if limit > 0 && limit <= MAX_PAGE_SIZE => Ok(limit),
+        Some(_) => Err(ApiError::BadRequest(format!(
+            "limit must be between 1 and {}",
+            MAX_PAGE_SIZE
+        ))),
+    }
+}
+
+fn encode_cursor<C: Serialize>(cursor: &C) -> String {
+    let json = serde_json::to_vec(cursor).expect("Cursors serialize to json");
+    base64::encode_config(json, base64::URL_SAFE_NO_PAD)
+}
+
+fn decode_cursor<C: DeserializeOwned>(cursor: Option<&str>) -> Result<Option<C>, ApiError> {
+    let cursor = match cursor {
+        Some(cursor) => cursor,
+        None => return Ok(None),
+    };
+    base64::decode_config(cursor, base64::URL_SAFE_NO_PAD)
+        .ok()
+        .and_then(|json| serde_json::from_slice(&json).ok())
+        .map(Some)
+        .ok_or_else(|| ApiError::BadRequest("Invalid cursor".to_string()))
+}
+
+fn error_response(status: StatusCode, message: &str) -> Response {
+    warp::reply::with_status(
+        warp::reply::json(&serde_json::json!({ "error": message })),
+        status,
+    )
+    .into_response()
+}
+
+/// Runs the query on a pooled connection, off the async workers since diesel blocks, and answers
+/// with its result. None is answered with a 404.
+async fn respond<T, F>(
+    endpoint: &'static str,
+    conn_pool: PgDbPool,
+    query: F,
+) -> Result<Response, Infallible>
+where
+    T: Serialize + Send + 'static,
+    F: FnOnce(&mut PgConnection) -> Result<Option<T>, ApiError> + Send + 'static,
+{
+    let start = Instant::now();
+    let result = tokio::task::spawn_blocking(move || {
+        let mut conn = conn_pool
+            .get()
+            .map_err(|err| ApiError::Internal(err.to_string()))?;
+        query(&mut conn)
+    })
+    .await
+    .unwrap_or_else(|err| Err(ApiError::Internal(err.to_string())));
+    let response = match result {
+        Ok(Some(value)) => warp::reply::json(&value).into_response(),
+        Ok(None) => error_response(StatusCode::NOT_FOUND, "Not found"),
+        Err(ApiError::BadRequest(message)) => error_response(StatusCode::BAD_REQUEST, &message),
+        Err(ApiError::Internal(message)) => {
+            error!(
+                endpoint = endpoint,
+                error = message,
+                "HTTP API request failed"
+            );
+            error_response(StatusCode::INTERNAL_SERVER_ERROR, "Internal error")
+        }
+    };
+    HTTP_API_LATENCY_SECONDS
+        .with_label_values(&[endpoint, response.status().as_str()])
+        .observe(start.elapsed().as_secs_f64());
+    Ok(response)
+}
+
+async fn status(conn_pool: PgDbPool) -> Result<Response, Infallible> {
+    respond("status", conn_pool, |conn| {
+        SchemaStatus::get(conn)
+            .map(Some)
+            .map_err(|err| ApiError::Internal(format!("{:#}", err)))
+    })
+    .await
+}
+
+async fn collection_stats(
+    collection_hash: String,
+    conn_pool: PgDbPool,
+) -> Result<Response, Infallible> {
+    respond("collection_stats", conn_pool, move |conn| {
+        let volume = get_collection_volume(conn, &collection_hash, VolumeWindow::AllTime)?;
+        let latest_snapshot = get_latest_collection_stats(conn, &collection_hash)?;
+        if volume.is_none() && latest_snapshot.is_none() {
+            return Ok(None);
+        }
+        Ok(Some(CollectionStats {
+            volume,
+            latest_snapshot,
+        }))
+    })
+    .await
+}
+
+async fn collection_listings(
+    collection_hash: String,
+    params: PageParams,
+    conn_pool: PgDbPool,
+) -> Result<Response, Infallible> {
+    respond("collection_listings", conn_pool, move |conn| {
+        let limit = page_size(params.limit)?;
+        let cursor = decode_cursor::<ListingCursor>(params.cursor.as_deref())?;
+        let listings = get_active_listings_page(conn, &collection_hash, cursor.as_ref(), limit)?;
+        Ok(Some(Page::new(listings, limit, |listing| {
+            ListingCursor::from(listing)
+        })))
+    })
+    .await
+}
+
+async fn token_activities(
+    token_hash: String,
+    params: CursorParams,
+    conn_pool: PgDbPool,
+) -> Result<Response, Infallible> {
+    respond("token_activities", conn_pool, move |conn| {
+        let cursor = decode_cursor::<ActivityCursor>(params.cursor.as_deref())?;
+        let activities = get_token_activities(conn, &token_hash, cursor.as_ref())?;
+        Ok(Some(Page::new(
+            activities,
+            ACTIVITY_PAGE_SIZE,
+            |activity| ActivityCursor::from(activity),
+        )))
+    })
+    .await
+}
+
+async fn account_tokens(
+    address: String,
+    params: OwnerTokensParams,
+    conn_pool: PgDbPool,
+) -> Result<Response, Infallible> {
+    respond("account_tokens", conn_pool, move |conn| {
+        let limit = page_size(params.limit)?;
+        let cursor = decode_cursor::<OwnershipCursor>(params.cursor.as_deref())?;
+        let ownerships = get_owner_tokens_page(
+            conn,
+            &address,
+            params.include_spam.unwrap_or(false),
+            params.include_deleted.unwrap_or(false),
+            cursor.as_ref(),
+            limit,
+        )?;
+        Ok(Some(Page::new(ownerships, limit, |ownership| {
+            OwnershipCursor::from(ownership)
+        })))
+    })
+    .await
+}
+
+async fn account_activities(
+    address: String,
+    params: CursorParams,
+    account_token_activities: bool,
+    conn_pool: PgDbPool,
+) -> Result<Response, Infallible> {
+    respond("account_activities", conn_pool, move |conn| {
+        let cursor = decode_cursor::<ActivityCursor>(params.cursor.as_deref())?;
+        let activities = if account_token_activities {
+            get_account_token_activities(conn, &address, cursor.as_ref())?
+        } else {
+            get_account_activities(conn, &address, cursor.as_ref())?
+        };
+        Ok(Some(Page::new(
+            activities,
+            ACTIVITY_PAGE_SIZE,
+            |activity| ActivityCursor::from(activity),
+        )))
+    })
+    .await
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "snake_case")]
+enum SubscriptionRequest {
+    Subscribe(String),
+    Unsubscribe(String),
+}
+
+/// Applies a client's request to its channels, and returns the reply to send it
+fn apply_subscription_request(
+    channels: &mut HashSet<String>,
+    request: &str,
+    max_subscriptions: usize,
+) -> serde_json::Value {
+    let request = match serde_json::from_str::<SubscriptionRequest>(request) {
+        Ok(request) => request,
+        Err(_) => {
+            return json!({
+                "type": "error",
+                "message": "Expected a subscribe or unsubscribe request",
+            })
+        }
+    };
+    let (requested, subscribe) = match &request {
+        SubscriptionRequest::Subscribe(channel) => (channel, true),
+        SubscriptionRequest::Unsubscribe(channel) => (channel, false),
+    };
+    let channel = match parse_channel(requested) {
+        Some(channel) => channel,
+        None => {
+            return json!({
+                "type": "error",
+                "message": format!("Unknown channel {}", requested),
+            })
+        }
+    };
+    if !subscribe {
+        channels.remove(&channel);
+        return json!({ "type": "unsubscribed", "channel": channel });
+    }
+    if !channels.contains(&channel) && channels.len() >= max_subscriptions {
+        return json!({
+            "type": "error",
+            "message": format!("At most {} subscriptions per connection", max_subscriptions),
+        });
+    }
+    channels.insert(channel.clone());
+    json!({ "type": "subscribed", "channel": channel })
+}
+
+/// Relays the live feed to one client until it disconnects. A client that doesn't keep up only
+/// lags its own receiver.
+async fn run_subscriber(
+    socket: WebSocket,
+    mut receiver: Receiver<Arc<LiveMessage>>,
+    max_subscriptions: usize,
+) {
+    let (mut sender, mut requests) = socket.split();
+    let mut channels = HashSet::new();
+    loop {
+        let reply = tokio::select! {
+            request = requests.next() => match request {
+                Some(Ok(request)) if request.is_close() => break,
+                Some(Ok(request)) => match request.to_str() {
+                    Ok(request) => {
+                        apply_subscription_request(&mut channels, request, max_subscriptions)
+                            .to_string()
+                    }
+                    // Pings and binary messages
+                    Err(_) => continue,
+                },
+                _ => break,
+            },
+            message = receiver.recv() => match message {
+                Ok(message) if channels.contains(&message.channel) => {
+                    serde_json::to_string(&*message).expect("Messages serialize to json")
+                }
+                Ok(_) => continue,
+                Err(RecvError::Lagged(skipped)) => {
+                    json!({ "type": "lagged", "skipped": skipped }).to_string()
+                }
+                Err(RecvError::Closed) => break,
+            },
+        };
+        if sender.send(Message::text(reply)).await.is_err() {
+            break;
+        }
+    }
+}
+
+pub fn routes(
+    conn_pool: PgDbPool,
+    live_feed: Option<LiveFeed>,
+    max_subscriptions: usize,
+    account_token_activities: bool,
+) -> impl Filter<Extract = (Response,), Error = Rejection> + Clone {
+    let with_pool = warp::any().map(move || conn_pool.clone());
+    let live_feed_route = warp::path!("ws")
+        .and(warp::ws())
+        .map(move |ws: Ws| match &live_feed {
+            Some(live_feed) => {
+                let receiver = live_feed.subscribe();
+                ws.on_upgrade(move |socket| run_subscriber(socket, receiver, max_subscriptions))
+                    .into_response()
+            }
+            None => error_response(
+                StatusCode::NOT_FOUND,
+                "The live feed is only served by the indexer itself",
+            ),
+        });
+    let status_route = warp::path!("status")
+        .and(with_pool.clone())
+        .and_then(status);
+    let collection_stats_route = warp::path!("collections" / String / "stats")
+        .and(with_pool.clone())
+        .and_then(collection_stats);
+    let collection_listings_route = warp::path!("collections" / String / "listings")
+        .and(warp::query::<PageParams>())
+        .and(with_pool.clone())
+        .and_then(collection_listings);
+    let token_activities_route = warp::path!("tokens" / String / "activities")
+        .and(warp::query::<CursorParams>())
+        .and(with_pool.clone())
+        .and_then(token_activities);
+    let account_tokens_route = warp::path!("accounts" / String / "tokens")
+        .and(warp::query::<OwnerTokensParams>())
+        .and(with_pool.clone())
+        .and_then(account_tokens);
+    let account_activities_route = warp::path!("accounts" / String / "activities")
+        .and(warp::query::<CursorParams>())
+        .and(warp::any().map(move || account_token_activities))
+        .and(with_pool)
+        .and_then(account_activities);
+    warp::get().and(
+        collection_stats_route
+            .or(collection_listings_route)
+            .unify()
+            .or(token_activities_route)
+            .unify()
+            .or(account_tokens_route)
+            .unify()
+            .or(account_activities_route)
+            .unify()
+            .or(live_feed_route)
+            .unify()
+            .or(status_route)
+            .unify(),
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_utils::timestamp;
+    use crate::{
+        database::new_db_pool,
+        indexer::tailer::{test::wipe_database, MIGRATIONS},
+        models::token_models::{
+            account_token_activities::AccountTokenActivity,
+            marketplace_listings::CurrentMarketplaceListing, token_activities::TokenActivity,
+        },
+        schema::{account_token_activities, current_marketplace_listings, token_activities},
+        util::standardize_address,
+    };
+    use bigdecimal::BigDecimal;
+    use diesel::RunQueryDsl;
+    use diesel_migrations::MigrationHarness;
+
+    fn setup() -> PgDbPool {
+        let database_url = std::env::var("INDEXER_DATABASE_URL")
+            .expect("must set 'INDEXER_DATABASE_URL' to run tests!");
+        let conn_pool = new_db_pool(database_url.as_str()).unwrap();
+        let mut conn = conn_pool.get().unwrap();
+        wipe_database(&mut conn);
+        conn.run_pending_migrations(MIGRATIONS).unwrap();
+        conn_pool
+    }
+
+    fn listing(token_data_id_hash: &str, price: i64) -> CurrentMarketplaceListing {
+        CurrentMarketplaceListing {
+            collection_data_id_hash: "potions".to_string(),
+            market_address: "0xbb".to_string(),
+            token_data_id_hash: token_data_id_hash.to_string(),
+            property_version: BigDecimal::from(0),
+            creator_address: "0x1".to_string(),
+            collection_name: "Potions".to_string(),
+            name: token_data_id_hash.to_string(),
+            seller: "0x2".to_string(),
+            amount: BigDecimal::from(1),
+            price: BigDecimal::from(price),
+            event_type: "0xbb::market::ListEvent".to_string(),
+            inserted_at: timestamp(),
+            last_transaction_version: 1,
+            invalidated_reason: None,
+            last_transaction_timestamp: timestamp(),
+            price_decimal: None,
+            is_deleted: false,
+            last_event_index: Some(0),
+        }
+    }
+
+    fn deposit(transaction_version: i64, to_address: &str) -> TokenActivity {
+        TokenActivity {
+            transaction_version,
+            event_account_address: to_address.to_string(),
+            event_creation_number: 0,
+            event_sequence_number: transaction_version,
+            event_index: 0,
+            token_index: 0,
+            token_data_id_hash: "potion".to_string(),
+            property_version: BigDecimal::from(0),
+            creator_address: "0x1".to_string(),
+            collection_name: "Potions".to_string(),
+            name: "Potion".to_string(),
+            transfer_type: "0x3::token::DepositEvent".to_string(),
+            transfer_kind: Some("transfer".to_string()),
+            domain: None,
+            from_address: None,
+            to_address: Some(to_address.to_string()),
+            token_amount: BigDecimal::from(1),
+            coin_type: None,
+            coin_amount: None,
+            suspect_value: false,
+            collection_data_id_hash: "potions".to_string(),
+            transaction_timestamp: timestamp(),
+        }
+    }
+
+    async fn get(conn_pool: &PgDbPool, path: &str) -> (StatusCode, serde_json::Value) {
+        get_with_account_feed(conn_pool, path, false).await
+    }
+
+    /// get against an API that reads account activities from account_token_activities or not
+    async fn get_with_account_feed(
+        conn_pool: &PgDbPool,
+        path: &str,
+        account_token_activities: bool,
+    ) -> (StatusCode, serde_json::Value) {
+        let response = warp::test::request()
+            .method("GET")
+            .path(path)
+            .reply(&routes(
+                conn_pool.clone(),
+                None,
+                1,
+                account_token_activities,
+            ))
+            .await;
+        (
+            response.status(),
+            serde_json::from_slice(response.body()).unwrap(),
+        )
+    }
+
+    #[test]
+    fn test_subscription_requests() {
+        let mut channels = HashSet::new();
+        let reply =
+            apply_subscription_request(&mut channels, r#"{"subscribe": "sales:potions"}"#, 2);
+        assert_eq!(reply["type"], "subscribed");
+        let reply =
+            apply_subscription_request(&mut channels, r#"{"subscribe": "wallet:0xA11CE"}"#, 2);
+        assert_eq!(
+            reply["channel"],
+            format!("wallet:{}", standardize_address("0xa11ce"))
+        );
+        // Over the limit, unless it's already subscribed
+        let reply =
+            apply_subscription_request(&mut channels, r#"{"subscribe": "listings:potions"}"#, 2);
+        assert_eq!(reply["type"], "error");
+        let reply =
+            apply_subscription_request(&mut channels, r#"{"subscribe": "sales:potions"}"#, 2);
+        assert_eq!(reply["type"], "subscribed");
+        assert_eq!(channels.len(), 2);
+
+        let reply =
+            apply_subscription_request(&mut channels, r#"{"unsubscribe": "sales:potions"}"#, 2);
+        assert_eq!(reply["type"], "unsubscribed");
+        let reply =
+            apply_subscription_request(&mut channels, r#"{"subscribe": "listings:potions"}"#, 2);
+        assert_eq!(reply["type"], "subscribed");
+
+        for request in [
+            r#"{"subscribe": "bids:potions"}"#,
+            r#"{"publish": "sales:potions"}"#,
+            "hello",
+        ] {
+            assert_eq!(
+                apply_subscription_request(&mut channels, request, 2)["type"],
+                "error"
+            );
+        }
+    }
+
+    #[tokio::test]
+    async fn test_collection_listings_pages() {
+        if crate::should_skip_pg_tests() {
+            return;
+        }
+        let conn_pool = setup();
+        diesel::insert_into(current_marketplace_listings::table)
+            .values(&vec![
+                listing("sword", 30),
+                listing("shield", 10),
+                listing("potion", 20),
+            ])
+            .execute(&mut conn_pool.get().unwrap())
+            .unwrap();
+
+        let (status, first_page) = get(&conn_pool, "/collections/potions/listings?limit=2").await;
+        assert_eq!(status, StatusCode::OK);
+        let hashes = |page: &serde_json::Value| {
+            page["data"]
+                .as_array()
+                .unwrap()
+                .iter()
+                .map(|listing| listing["token_data_id_hash"].as_str().unwrap().to_string())
+                .collect::<Vec<_>>()
+        };
+        assert_eq!(hashes(&first_page), vec!["shield", "potion"]);
+
+        let next_cursor = first_page["next_cursor"].as_str().unwrap();
+        let (status, second_page) = get(
+            &conn_pool,
+            &format!(
+                "/collections/potions/listings?limit=2&cursor={}",
+                next_cursor
+            ),
+        )
+        .await;
+        assert_eq!(status, StatusCode::OK);
+        assert_eq!(hashes(&second_page), vec!["sword"]);
+        assert!(second_page["next_cursor"].is_null());
+
+        let (status, _) = get(&conn_pool, "/collections/potions/listings?cursor=nope").await;
+        assert_eq!(status, StatusCode::BAD_REQUEST);
+        let (status, _) = get(&conn_pool, "/collections/potions/listings?limit=1000").await;
+        assert_eq!(status, StatusCode::BAD_REQUEST);
+        let (status, _) = get(&conn_pool, "/collections/potions/stats").await;
+        assert_eq!(status, StatusCode::NOT_FOUND);
+    }
+
+    #[tokio::test]
+    async fn test_account_activities() {
+        if crate::should_skip_pg_tests() {
+            return;
+        }
+        let conn_pool = setup();
+        let alice = standardize_address("0xa11ce");
+        let activities = vec![
+            deposit(1, &alice),
+            deposit(2, &standardize_address("0xb0b")),
+            deposit(3, &alice),
+        ];
+        diesel::insert_into(token_activities::table)
+            .values(&activities)
+            .execute(&mut conn_pool.get().unwrap())
+            .unwrap();
+        diesel::insert_into(account_token_activities::table)
+            .values(&AccountTokenActivity::from_token_activities(&activities))
+            .execute(&mut conn_pool.get().unwrap())
+            .unwrap();
+
+        // Short addresses are padded like the indexed ones. Both tables give the same page.
+        for account_token_activities in [false, true] {
+            let (status, page) = get_with_account_feed(
+                &conn_pool,
+                "/accounts/0xa11ce/activities",
+                account_token_activities,
+            )
+            .await;
+            assert_eq!(status, StatusCode::OK);
+            let versions = page["data"]
+                .as_array()
+                .unwrap()
+                .iter()
+                .map(|activity| activity["transaction_version"].as_i64().unwrap())
+                .collect::<Vec<_>>();
+            assert_eq!(versions, vec![3, 1]);
+            assert!(page["next_cursor"].is_null());
+        }
+    }
+
+    #[tokio::test]
+    async fn test_status() {
+        if crate::should_skip_pg_tests() {
+            return;
+        }
+        let conn_pool = setup();
+        let (status, body) = get(&conn_pool, "/status").await;
+        assert_eq!(status, StatusCode::OK);
+        assert_eq!(body["pending_migrations"], 0);
+        assert!(body["migration_version"].is_string());
+        assert_eq!(body["migration_version"], body["latest_migration_version"]);
+    }
+}
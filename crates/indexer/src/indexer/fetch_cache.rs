@@ -0,0 +1,232 @@
+// Copyright (c) Aptos
+// SPDX-License-Identifier: Apache-2.0
+
+//! Batches recently fetched for one processor, kept for the other processors running in the same
+//! indexer. Every processor fetches from its own version, so one that was restarted further back
+//! reads what the others already fetched from here, and only fetches versions that have been
+//! evicted.
+
+use crate::counters::FETCH_CACHE_LOOKUPS;
+use anyhow::ensure;
+use aptos_api_types::Transaction;
+use aptos_config::config::FetchCacheConfig;
+use std::{collections::BTreeMap, sync::Mutex};
+
+pub const DEFAULT_FETCH_CACHE_BATCHES: u64 = 100;
+
+#[derive(Debug)]
+struct CachedBatch {
+    /// Consecutive versions, starting at the batch's key
+    transactions: Vec<Transaction>,
+    last_used: u64,
+}
+
+impl CachedBatch {
+    fn contains(&self, start_version: u64, version: u64) -> bool {
+        version < start_version + self.transactions.len() as u64
+    }
+}
+
+/// Least recently used batches are evicted once the cache is full
+#[derive(Debug)]
+struct Lru {
+    capacity: usize,
+    next_use: u64,
+    /// By first version
+    batches: BTreeMap<u64, CachedBatch>,
+    by_last_used: BTreeMap<u64, u64>,
+}
+
+impl Lru {
+    fn touch(&mut self, start_version: u64) {
+        let next_use = self.next_use;
+        self.next_use += 1;
+        if let Some(batch) = self.batches.get_mut(&start_version) {
+            self.by_last_used.remove(&batch.last_used);
+            batch.last_used = next_use;
+            self.by_last_used.insert(next_use, start_version);
+        }
+    }
+
+    fn evict(&mut self) {
+        while self.batches.len() >= self.capacity {
+            let last_used = match self.by_last_used.keys().next() {
+                Some(last_used) => *last_used,
+                None => return,
+            };
+            if let Some(start_version) = self.by_last_used.remove(&last_used) {
+                self.batches.remove(&start_version);
+            }
+        }
+    }
+}
+
+#[derive(Debug)]
+pub struct FetchCache {
+    lru: Mutex<Lru>,
+}
+
+impl FetchCache {
+    /// None when only one processor runs, since nothing else would read the cache
+    pub fn from_config(
+        config: Option<&FetchCacheConfig>,
+        num_processors: usize,
+    ) -> anyhow::Result<Option<Self>> {
+        let max_batches = config
+            .and_then(|config| config.max_batches)
+            .unwrap_or(DEFAULT_FETCH_CACHE_BATCHES);
+        ensure!(max_batches > 0, "max_batches must be greater than 0");
+        if num_processors < 2 {
+            return Ok(None);
+        }
+        Ok(Some(Self::new(max_batches as usize)))
+    }
+
+    pub fn new(capacity: usize) -> Self {
+        Self {
+            lru: Mutex::new(Lru {
+                capacity: capacity.max(1),
+                next_use: 1,
+                batches: BTreeMap::new(),
+                by_last_used: BTreeMap::new(),
+            }),
+        }
+    }
+
+    /// The cached versions from `version` to the end of the batch that has it, counted as a hit
+    /// or miss for processor_name
+    pub fn get(&self, version: u64, processor_name: &str) -> Option<Vec<Transaction>> {
+        let mut lru = self.lru.lock().unwrap();
+        let cached = lru
+            .batches
+            .range(..=version)
+            .rev()
+            .find(|(start_version, batch)| batch.contains(**start_version, version))
+            .map(|(start_version, batch)| {
+                (
+                    *start_version,
+                    batch.transactions[(version - start_version) as usize..].to_vec(),
+                )
+            });
+        match cached {
+            Some((start_version, transactions)) => {
+                lru.touch(start_version);
+                FETCH_CACHE_LOOKUPS
+                    .with_label_values(&[processor_name, "hit"])
+                    .inc();
+                Some(transactions)
+            }
+            None => {
+                FETCH_CACHE_LOOKUPS
+                    .with_label_values(&[processor_name, "miss"])
+                    .inc();
+                None
+            }
+        }
+    }
+
+    /// Caches a fetched batch, keeping the longer one if a batch starting at the same version is
+    /// already cached
+    pub fn insert(&self, transactions: &[Transaction]) {
+        let start_version = match transactions.first() {
+            Some(txn) => txn.version().unwrap(),
+            None => return,
+        };
+        let mut lru = self.lru.lock().unwrap();
+        match lru.batches.get_mut(&start_version) {
+            Some(batch) => {
+                if batch.transactions.len() < transactions.len() {
+                    batch.transactions = transactions.to_vec();
+                }
+            }
+            None => {
+                lru.evict();
+                lru.batches.insert(
+                    start_version,
+                    CachedBatch {
+                        transactions: transactions.to_vec(),
+                        last_used: 0,
+                    },
+                );
+            }
+        }
+        lru.touch(start_version);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    fn batch(versions: std::ops::Range<u64>) -> Vec<Transaction> {
+        let hash = format!("0x{}", "00".repeat(32));
+        versions
+            .map(|version| {
+                serde_json::from_value(json!({
+                    "type": "state_checkpoint_transaction",
+                    "version": version.to_string(),
+                    "hash": hash,
+                    "state_change_hash": hash,
+                    "event_root_hash": hash,
+                    "state_checkpoint_hash": hash,
+                    "gas_used": "0",
+                    "success": true,
+                    "vm_status": "Executed successfully",
+                    "accumulator_root_hash": hash,
+                    "changes": [],
+                    "timestamp": "0",
+                }))
+                .unwrap()
+            })
+            .collect()
+    }
+
+    fn versions(transactions: Option<Vec<Transaction>>) -> Option<Vec<u64>> {
+        transactions.map(|transactions| {
+            transactions
+                .iter()
+                .map(|txn| txn.version().unwrap())
+                .collect()
+        })
+    }
+
+    #[test]
+    fn test_reads_from_within_a_batch() {
+        let cache = FetchCache::new(10);
+        cache.insert(&batch(100..110));
+        cache.insert(&batch(110..115));
+        assert_eq!(versions(cache.get(100, "test")), Some((100..110).collect()));
+        assert_eq!(versions(cache.get(107, "test")), Some((107..110).collect()));
+        assert_eq!(versions(cache.get(114, "test")), Some((114..115).collect()));
+        assert!(cache.get(99, "test").is_none());
+        assert!(cache.get(115, "test").is_none());
+
+        // A shorter batch at a later version doesn't hide a longer one that has the version
+        cache.insert(&batch(50..80));
+        cache.insert(&batch(60..62));
+        assert_eq!(versions(cache.get(70, "test")), Some((70..80).collect()));
+    }
+
+    #[test]
+    fn test_least_recently_used_is_evicted() {
+        let cache = FetchCache::new(2);
+        cache.insert(&batch(0..10));
+        cache.insert(&batch(10..20));
+        assert!(cache.get(5, "test").is_some());
+        cache.insert(&batch(20..30));
+        assert!(cache.get(0, "test").is_some());
+        assert!(cache.get(10, "test").is_none());
+        assert!(cache.get(20, "test").is_some());
+    }
+
+    #[test]
+    fn test_from_config() {
+        assert!(FetchCache::from_config(None, 1).unwrap().is_none());
+        assert!(FetchCache::from_config(None, 2).unwrap().is_some());
+        let config = FetchCacheConfig {
+            max_batches: Some(0),
+        };
+        assert!(FetchCache::from_config(Some(&config), 1).is_err());
+    }
+}
@@ -0,0 +1,285 @@
+// Copyright (c) Aptos
+// SPDX-License-Identifier: Apache-2.0
+
+//! Retries of failed fetches from the node's storage or upstream nodes. Failures that can't be
+//! fixed by retrying stop the fetcher, every other one is retried with exponential backoff, so
+//! that processors see a stalled stream instead of an error. Fetches can be rate limited, and
+//! fetching pauses for a while once the node keeps failing, instead of hammering it with retries.
+
+use crate::counters::FETCH_CIRCUIT_OPEN;
+use anyhow::ensure;
+use aptos_config::config::FetchRetryConfig;
+use aptos_logger::{info, warn};
+use aptos_rest_client::error::RestError;
+use aptosdb::errors::AptosDbError;
+use reqwest::StatusCode;
+use std::{
+    sync::Mutex,
+    time::{Duration, Instant},
+};
+
+pub const DEFAULT_STARTING_BACKOFF_MS: u64 = 300;
+pub const DEFAULT_MAX_BACKOFF_MS: u64 = 120_000;
+pub const DEFAULT_CIRCUIT_BREAKER_FAILURES: u64 = 10;
+pub const DEFAULT_CIRCUIT_BREAKER_PAUSE_MS: u64 = 30_000;
+
+#[derive(Debug, PartialEq, Eq)]
+pub enum FetchErrorKind {
+    /// More versions were requested than storage returns at once
+    PageTooLarge,
+    /// The versions aren't in storage, e.g. because they were pruned
+    Missing,
+    /// Anything else, worth retrying
+    Transient,
+}
+
+impl FetchErrorKind {
+    pub fn classify(err: &anyhow::Error) -> Self {
+        if let Some(rest_error) = err
+            .chain()
+            .find_map(|cause| cause.downcast_ref::<RestError>())
+        {
+            // Upstream nodes may not have caught up to a version yet, so only a rejected page
+            // size is treated specially
+            return match rest_error {
+                RestError::Api(response)
+                    if response.status_code == StatusCode::PAYLOAD_TOO_LARGE =>
+                {
+                    Self::PageTooLarge
+                }
+                RestError::Http(StatusCode::PAYLOAD_TOO_LARGE, _) => Self::PageTooLarge,
+                _ => Self::Transient,
+            };
+        }
+        match err
+            .chain()
+            .find_map(|cause| cause.downcast_ref::<AptosDbError>())
+        {
+            Some(AptosDbError::TooManyRequested(..)) => Self::PageTooLarge,
+            Some(AptosDbError::NotFound(_)) => Self::Missing,
+            None => Self::Transient,
+        }
+    }
+}
+
+/// Spaces out fetches evenly across all fetch tasks
+#[derive(Debug)]
+struct RateLimiter {
+    interval: Duration,
+    next_fetch: Mutex<Instant>,
+}
+
+impl RateLimiter {
+    async fn acquire(&self) {
+        let fetch_at = {
+            let mut next_fetch = self.next_fetch.lock().unwrap();
+            let fetch_at = std::cmp::max(*next_fetch, Instant::now());
+            *next_fetch = fetch_at + self.interval;
+            fetch_at
+        };
+        tokio::time::sleep_until(fetch_at.into()).await;
+    }
+}
+
+#[derive(Debug, Default)]
+struct CircuitState {
+    /// Failed fetches in a row, across all fetch tasks
+    consecutive_failures: u64,
+    /// Set while paused, and left set once the pause is over until a fetch succeeds. A failure
+    /// before then pauses fetching again right away.
+    paused_until: Option<Instant>,
+}
+
+#[derive(Debug)]
+pub struct FetchRetry {
+    starting_backoff: Duration,
+    max_backoff: Duration,
+    rate_limiter: Option<RateLimiter>,
+    circuit_breaker_failures: u64,
+    circuit_breaker_pause: Duration,
+    circuit: Mutex<CircuitState>,
+}
+
+impl FetchRetry {
+    pub fn from_config(config: Option<&FetchRetryConfig>) -> anyhow::Result<Self> {
+        let config = config.cloned().unwrap_or_default();
+        let starting_backoff_ms = config
+            .starting_backoff_ms
+            .unwrap_or(DEFAULT_STARTING_BACKOFF_MS);
+        let max_backoff_ms = config.max_backoff_ms.unwrap_or(DEFAULT_MAX_BACKOFF_MS);
+        ensure!(
+            starting_backoff_ms <= max_backoff_ms,
+            "starting_backoff_ms must not be greater than max_backoff_ms"
+        );
+        if let Some(requests_per_second) = config.requests_per_second {
+            ensure!(
+                requests_per_second > 0,
+                "requests_per_second must be greater than 0"
+            );
+        }
+        let circuit_breaker_failures = config
+            .circuit_breaker_failures
+            .unwrap_or(DEFAULT_CIRCUIT_BREAKER_FAILURES);
+        ensure!(
+            circuit_breaker_failures > 0,
+            "circuit_breaker_failures must be greater than 0"
+        );
+        Ok(Self {
+            starting_backoff: Duration::from_millis(starting_backoff_ms),
+            max_backoff: Duration::from_millis(max_backoff_ms),
+            rate_limiter: config
+                .requests_per_second
+                .map(|requests_per_second| RateLimiter {
+                    interval: Duration::from_secs_f64(1.0 / requests_per_second as f64),
+                    next_fetch: Mutex::new(Instant::now()),
+                }),
+            circuit_breaker_failures,
+            circuit_breaker_pause: Duration::from_millis(
+                config
+                    .circuit_breaker_pause_ms
+                    .unwrap_or(DEFAULT_CIRCUIT_BREAKER_PAUSE_MS),
+            ),
+            circuit: Mutex::new(CircuitState::default()),
+        })
+    }
+
+    /// Waits out a pause and the rate limit before a fetch
+    pub async fn wait_to_fetch(&self) {
+        loop {
+            let paused_until = self.circuit.lock().unwrap().paused_until;
+            match paused_until {
+                Some(paused_until) if paused_until > Instant::now() => {
+                    tokio::time::sleep_until(paused_until.into()).await
+                }
+                _ => break,
+            }
+        }
+        if let Some(rate_limiter) = &self.rate_limiter {
+            rate_limiter.acquire().await;
+        }
+    }
+
+    pub fn record_success(&self) {
+        let mut circuit = self.circuit.lock().unwrap();
+        circuit.consecutive_failures = 0;
+        if circuit.paused_until.take().is_some() {
+            FETCH_CIRCUIT_OPEN.set(0);
+            info!("Node recovered, resuming fetching");
+        }
+    }
+
+    /// Records a failed fetch, returning how long to back off before the retry_number-th retry
+    pub fn record_failure(&self, retry_number: u32) -> Duration {
+        let mut circuit = self.circuit.lock().unwrap();
+        circuit.consecutive_failures += 1;
+        let now = Instant::now();
+        let is_paused = matches!(circuit.paused_until, Some(paused_until) if paused_until > now);
+        if circuit.consecutive_failures >= self.circuit_breaker_failures && !is_paused {
+            circuit.paused_until = Some(now + self.circuit_breaker_pause);
+            FETCH_CIRCUIT_OPEN.set(1);
+            warn!(
+                consecutive_failures = circuit.consecutive_failures,
+                pause_millis = self.circuit_breaker_pause.as_millis() as u64,
+                "Node keeps failing, pausing fetching"
+            );
+        }
+        let multiplier = 2u32.saturating_pow(retry_number.saturating_sub(1));
+        self.starting_backoff
+            .checked_mul(multiplier)
+            .map_or(self.max_backoff, |backoff| {
+                std::cmp::min(backoff, self.max_backoff)
+            })
+    }
+
+    pub fn is_paused(&self) -> bool {
+        matches!(
+            self.circuit.lock().unwrap().paused_until,
+            Some(paused_until) if paused_until > Instant::now()
+        )
+    }
+}
+
+impl Default for FetchRetry {
+    fn default() -> Self {
+        Self::from_config(None).unwrap()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn fetch_retry(circuit_breaker_pause_ms: u64) -> FetchRetry {
+        FetchRetry::from_config(Some(&FetchRetryConfig {
+            starting_backoff_ms: Some(100),
+            max_backoff_ms: Some(1000),
+            requests_per_second: None,
+            circuit_breaker_failures: Some(3),
+            circuit_breaker_pause_ms: Some(circuit_breaker_pause_ms),
+        }))
+        .unwrap()
+    }
+
+    #[test]
+    fn test_classify() {
+        let too_large: anyhow::Error = AptosDbError::TooManyRequested(1000, 100).into();
+        assert_eq!(
+            FetchErrorKind::classify(&too_large.context("Failed to get transactions")),
+            FetchErrorKind::PageTooLarge
+        );
+        let missing: anyhow::Error = AptosDbError::NotFound("Version 1".to_string()).into();
+        assert_eq!(FetchErrorKind::classify(&missing), FetchErrorKind::Missing);
+        assert_eq!(
+            FetchErrorKind::classify(&anyhow::anyhow!("no start version from database")),
+            FetchErrorKind::Transient
+        );
+    }
+
+    #[test]
+    fn test_backoff_doubles_up_to_max() {
+        let retry = fetch_retry(60_000);
+        let backoffs = (1..=6)
+            .map(|retry_number| retry.record_failure(retry_number).as_millis())
+            .collect::<Vec<_>>();
+        assert_eq!(backoffs, vec![100, 200, 400, 800, 1000, 1000]);
+        assert_eq!(retry.record_failure(u32::MAX), Duration::from_millis(1000));
+    }
+
+    #[tokio::test]
+    async fn test_circuit_pauses_and_recovers() {
+        let retry = fetch_retry(50);
+        retry.record_failure(1);
+        retry.record_failure(2);
+        assert!(!retry.is_paused());
+        retry.record_failure(3);
+        assert!(retry.is_paused());
+
+        let wait_start = Instant::now();
+        retry.wait_to_fetch().await;
+        assert!(wait_start.elapsed() >= Duration::from_millis(50));
+        assert!(!retry.is_paused());
+
+        // Still failing after the pause, so it pauses again right away
+        retry.record_failure(4);
+        assert!(retry.is_paused());
+        retry.wait_to_fetch().await;
+        retry.record_success();
+        retry.record_failure(1);
+        assert!(!retry.is_paused());
+    }
+
+    #[tokio::test]
+    async fn test_rate_limit() {
+        let retry = FetchRetry::from_config(Some(&FetchRetryConfig {
+            requests_per_second: Some(100),
+            ..FetchRetryConfig::default()
+        }))
+        .unwrap();
+        let start = Instant::now();
+        for _ in 0..6 {
+            retry.wait_to_fetch().await;
+        }
+        // The first fetch doesn't wait
+        assert!(start.elapsed() >= Duration::from_millis(50));
+    }
+}
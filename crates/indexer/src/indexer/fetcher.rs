@@ -1,15 +1,29 @@
 // Copyright (c) Aptos
 // SPDX-License-Identifier: Apache-2.0
 
-use crate::counters::{FETCHED_TRANSACTION, UNABLE_TO_FETCH_TRANSACTION};
+use crate::{
+    counters::{
+        FETCHED_TRANSACTION, FETCH_LATENCY_SECONDS, FETCH_PAGE_SIZE, FETCH_RETRIES,
+        PROCESSOR_LAG_VERSIONS, UNABLE_TO_FETCH_TRANSACTION,
+    },
+    indexer::{
+        fetch_cache::FetchCache,
+        fetch_retry::{FetchErrorKind, FetchRetry},
+        transaction_stream::TransactionStream,
+        upstream_nodes::UpstreamNodes,
+    },
+};
+use anyhow::ensure;
 use aptos_api::Context;
 use aptos_api_types::{AsConverter, LedgerInfo, Transaction, TransactionOnChainData};
+use aptos_config::config::AdaptiveFetchConfig;
 use aptos_logger::prelude::*;
 use aptos_vm::data_cache::StorageAdapterOwned;
 use futures::channel::mpsc;
-use futures::{SinkExt, StreamExt};
-use std::sync::Arc;
-use std::time::Duration;
+use futures::{future, Future, SinkExt, StreamExt};
+use std::collections::VecDeque;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
 use storage_interface::state_view::DbStateView;
 use tokio::task::JoinHandle;
 
@@ -18,6 +32,9 @@ const RETRY_TIME_MILLIS: u64 = 300;
 const MAX_RETRY_TIME_MILLIS: u64 = 120000;
 const TRANSACTION_FETCH_BATCH_SIZE: u16 = 500;
 const TRANSACTION_CHANNEL_SIZE: usize = 35;
+const MIN_PAGE_SIZE: u16 = 10;
+const SLOW_FETCH_MILLIS: u64 = 5000;
+const GROW_AFTER_FETCHES: u64 = 10;
 
 #[derive(Debug)]
 pub struct Fetcher {
@@ -26,6 +43,7 @@ pub struct Fetcher {
     chain_id: u8,
     current_version: u64,
     highest_known_version: u64,
+    page_size: Arc<PageSize>,
     transactions_sender: mpsc::Sender<Vec<Transaction>>,
 }
 
@@ -36,20 +54,31 @@ impl Fetcher {
         options: TransactionFetcherOptions,
         transactions_sender: mpsc::Sender<Vec<Transaction>>,
     ) -> Self {
+        let page_size = Arc::new(PageSize::new(
+            options.transaction_fetch_batch_size,
+            options.adaptive_fetch.clone(),
+        ));
         Self {
             context,
             options,
             chain_id: 0,
             current_version: starting_version,
             highest_known_version: 0,
+            page_size,
             transactions_sender,
         }
     }
 
-    pub fn set_highest_known_version(&mut self) -> anyhow::Result<()> {
-        let info = self.context.get_latest_ledger_info_wrapped()?;
-        self.highest_known_version = info.ledger_version.0 as u64;
-        self.chain_id = info.chain_id;
+    pub async fn set_highest_known_version(&mut self) -> anyhow::Result<()> {
+        let (chain_id, ledger_version) = match &self.options.upstream_nodes {
+            Some(upstream_nodes) => upstream_nodes.ledger_info().await?,
+            None => {
+                let info = self.context.get_latest_ledger_info_wrapped()?;
+                (info.chain_id, info.ledger_version.0)
+            }
+        };
+        self.highest_known_version = ledger_version;
+        self.chain_id = chain_id;
         Ok(())
     }
 
@@ -62,7 +91,7 @@ impl Fetcher {
                 tokio::time::sleep(self.options.starting_retry_time).await;
             }
             empty_loops += 1;
-            if let Err(err) = self.set_highest_known_version() {
+            if let Err(err) = self.set_highest_known_version().await {
                 error!(
                     error = format!("{:?}", err),
                     "Failed to set highest known version"
@@ -81,68 +110,168 @@ impl Fetcher {
     }
 
     /// Main loop for fetching transactions
-    /// Fetches transactions in batches of `options.transaction_fetch_batch_size` and sends them to the processor channel.
+    /// Fetches transactions in pages of up to `options.transaction_fetch_batch_size` and sends them to the processor channel.
     /// If the processor channel is full, it will wait for the processor to catch up.
     /// 1. Get the latest ledger info, and set the highest known version (if we've caught up)
-    /// 2. Determine how many batches of size `options.transaction_fetch_batch_size` we need to catch up
-    /// 3. Spawn tasks which fetch 'raw' `OnChainTransactions` from storage, and convert them to `Transaction`s. We spawn at most `options.max_tasks` tasks.
-    /// 4. We wait for all the tasks to complete, then send the `Transaction`s to the processor, via the `transactions_sender` channel.
+    /// 2. Keep up to `options.max_tasks` tasks fetching the next pages. Each fetches 'raw' `OnChainTransactions` from storage, and converts them to `Transaction`s.
+    /// 3. Wait for the task of the oldest page, then send its `Transaction`s to the processor via the `transactions_sender` channel.
+    ///    Pages are sent in version order, a slow page only holds up the pages after it.
+    ///
+    /// With a fetch cache, a page the cache has is taken from it instead of being fetched, and
+    /// fetched pages are added to it.
     pub async fn run(&mut self) {
-        let transaction_fetch_batch_size = self.options.transaction_fetch_batch_size;
+        if let Some(transaction_stream) = self.options.transaction_stream.clone() {
+            return self.run_transaction_stream(&transaction_stream).await;
+        }
+        if let Some(upstream_nodes) = &self.options.upstream_nodes {
+            tokio::spawn(upstream_nodes.clone().run_health_checks());
+        }
+        let mut tasks: VecDeque<JoinHandle<Vec<Transaction>>> = VecDeque::new();
+        let mut starting_version = self.current_version;
         loop {
-            self.ensure_highest_known_version().await;
-
-            info!(
-                current_version = self.current_version,
-                highest_known_version = self.highest_known_version,
-                max_batch_size = transaction_fetch_batch_size,
-                "Preparing to fetch transactions"
-            );
-
-            let fetch_start = chrono::Utc::now().naive_utc();
-            let mut tasks = vec![];
-            let mut starting_version = self.current_version;
-            let mut num_fetches = 0;
+            if tasks.is_empty() {
+                self.ensure_highest_known_version().await;
+                info!(
+                    current_version = self.current_version,
+                    highest_known_version = self.highest_known_version,
+                    max_batch_size = self.options.transaction_fetch_batch_size,
+                    "Preparing to fetch transactions"
+                );
+            }
 
-            while num_fetches < self.options.max_tasks
+            while tasks.len() < self.options.max_tasks
                 && starting_version <= self.highest_known_version
             {
+                if let Some(fetch_cache) = &self.options.fetch_cache {
+                    if let Some(batch) =
+                        fetch_cache.get(starting_version, &self.options.processor_name)
+                    {
+                        starting_version += batch.len() as u64;
+                        tasks.push_back(tokio::spawn(future::ready(batch)));
+                        continue;
+                    }
+                }
                 let num_transactions_to_fetch = std::cmp::min(
-                    transaction_fetch_batch_size as u64,
+                    self.page_size.get() as u64,
                     self.highest_known_version - starting_version + 1,
                 ) as u16;
 
                 let context = self.context.clone();
                 let highest_known_version = self.highest_known_version;
+                let page_size = self.page_size.clone();
+                let fetch_retry = self.options.fetch_retry.clone();
+                let upstream_nodes = self.options.upstream_nodes.clone();
+                let fetch_cache = self.options.fetch_cache.clone();
                 let task = tokio::spawn(async move {
-                    fetch_nexts(
-                        context,
-                        starting_version,
-                        highest_known_version,
-                        num_transactions_to_fetch,
-                    )
-                    .await
+                    let batch = match upstream_nodes {
+                        Some(upstream_nodes) => {
+                            fetch_from_upstream_nodes(
+                                &upstream_nodes,
+                                starting_version,
+                                num_transactions_to_fetch,
+                                &page_size,
+                                &fetch_retry,
+                            )
+                            .await
+                        }
+                        None => {
+                            fetch_nexts_with_page_size(
+                                context,
+                                starting_version,
+                                highest_known_version,
+                                num_transactions_to_fetch,
+                                &page_size,
+                                &fetch_retry,
+                            )
+                            .await
+                        }
+                    };
+                    if let Some(fetch_cache) = fetch_cache {
+                        fetch_cache.insert(&batch);
+                    }
+                    batch
                 });
-                tasks.push(task);
+                tasks.push_back(task);
                 starting_version += num_transactions_to_fetch as u64;
-                num_fetches += 1;
             }
 
-            let batches = match futures::future::try_join_all(tasks).await {
-                Ok(res) => res,
-                Err(err) => panic!("Error fetching transaction batches: {:?}", err),
+            let batch = match tasks.pop_front().unwrap().await {
+                Ok(batch) => batch,
+                Err(err) => panic!("Error fetching transaction batch: {:?}", err),
             };
+            self.send_transaction_batches(vec![batch]).await;
+            PROCESSOR_LAG_VERSIONS
+                .with_label_values(&[&self.options.processor_name])
+                .set((self.highest_known_version + 1).saturating_sub(self.current_version) as i64);
+        }
+    }
 
-            let versions_fetched = batches.iter().fold(0, |acc, v| acc + v.len());
-            let fetch_millis = (chrono::Utc::now().naive_utc() - fetch_start).num_milliseconds();
-            info!(
-                versions_fetched = versions_fetched,
-                fetch_millis = fetch_millis,
-                num_batches = batches.len(),
-                "Finished fetching transaction batches"
-            );
-            self.send_transaction_batches(batches).await;
+    /// Reads transactions from the Firehose stream instead of fetching them, reconnecting with
+    /// backoff whenever the stream ends or fails. Only transactions that were sent on count as
+    /// read, so versions read before a reconnect are read again rather than skipped.
+    async fn run_transaction_stream(&mut self, transaction_stream: &TransactionStream) {
+        let mut retries = 0;
+        loop {
+            let starting_version = self.current_version;
+            let result = self.read_transaction_stream(transaction_stream).await;
+            if self.current_version > starting_version {
+                retries = 0;
+                self.options.fetch_retry.record_success();
+            }
+            retries += 1;
+            let backoff = self.options.fetch_retry.record_failure(retries);
+            FETCH_RETRIES.inc();
+            match result {
+                Ok(()) => warn!(
+                    current_version = self.current_version,
+                    "Transaction stream ended, reconnecting"
+                ),
+                Err(err) => error!(
+                    current_version = self.current_version,
+                    error = format!("{:?}", err),
+                    "Failed to read transaction stream, reconnecting"
+                ),
+            }
+            tokio::time::sleep(backoff).await;
+        }
+    }
+
+    /// Sends on the stream's blocks from `current_version`, in batches of up to
+    /// `transaction_fetch_batch_size` transactions, or fewer once caught up with the stream
+    async fn read_transaction_stream(
+        &mut self,
+        transaction_stream: &TransactionStream,
+    ) -> anyhow::Result<()> {
+        let mut reader = transaction_stream.connect().await?;
+        let batch_size = self.options.transaction_fetch_batch_size as usize;
+        let mut pending: Vec<Transaction> = vec![];
+        while let Some(block) = reader.next_block().await? {
+            let mut next_version = self.current_version + pending.len() as u64;
+            for txn in block {
+                let version = txn.version().unwrap();
+                if version < next_version {
+                    continue;
+                }
+                ensure!(
+                    version == next_version,
+                    "The stream is missing versions {} to {}, it has to start at an earlier block",
+                    next_version,
+                    version - 1
+                );
+                pending.push(txn);
+                next_version += 1;
+            }
+            if pending.len() >= batch_size || (!pending.is_empty() && reader.is_caught_up()) {
+                let mut batches = vec![];
+                while !pending.is_empty() {
+                    let rest = pending.split_off(std::cmp::min(batch_size, pending.len()));
+                    batches.push(std::mem::replace(&mut pending, rest));
+                }
+                FETCHED_TRANSACTION.inc();
+                self.send_transaction_batches(batches).await;
+            }
         }
+        Ok(())
     }
 
     /// Sends the transaction batches to the processor via the `transactions_sender` channel
@@ -178,49 +307,162 @@ async fn fetch_raw_txns_with_retries(
     starting_version: u64,
     ledger_version: u64,
     num_transactions_to_fetch: u16,
-    max_retries: u8,
+    page_size: &PageSize,
+    fetch_retry: &FetchRetry,
 ) -> Vec<TransactionOnChainData> {
-    let mut retries = 0;
-    loop {
-        match context.get_transactions(
-            starting_version as u64,
-            num_transactions_to_fetch,
-            ledger_version as u64,
-        ) {
-            Ok(raw_txns) => return raw_txns,
+    fetch_pages_with_retries(
+        starting_version,
+        num_transactions_to_fetch,
+        page_size,
+        fetch_retry,
+        |page_version, num_page_transactions| {
+            future::ready(context.get_transactions(
+                page_version,
+                num_page_transactions,
+                ledger_version,
+            ))
+        },
+    )
+    .await
+}
+
+/// Fetches `num_transactions_to_fetch` transactions starting at `starting_version` from the
+/// active upstream node
+async fn fetch_from_upstream_nodes(
+    upstream_nodes: &UpstreamNodes,
+    starting_version: u64,
+    num_transactions_to_fetch: u16,
+    page_size: &PageSize,
+    fetch_retry: &FetchRetry,
+) -> Vec<Transaction> {
+    let transactions = fetch_pages_with_retries(
+        starting_version,
+        num_transactions_to_fetch,
+        page_size,
+        fetch_retry,
+        |page_version, num_page_transactions| {
+            upstream_nodes.get_transactions(page_version, num_page_transactions)
+        },
+    )
+    .await;
+    info!(
+        starting_version = starting_version,
+        num_transactions = transactions.len(),
+        "Fetched transactions from upstream node",
+    );
+    FETCHED_TRANSACTION.inc();
+    transactions
+}
+
+/// Fetches the versions in pages of `page_size`. A page that's too large is retried right away
+/// if the page size is adaptive and can still shrink. Any other failure is retried as set out by
+/// `fetch_retry`, unless the versions are missing from storage.
+async fn fetch_pages_with_retries<T, F>(
+    starting_version: u64,
+    num_transactions_to_fetch: u16,
+    page_size: &PageSize,
+    fetch_retry: &FetchRetry,
+    fetch_page: impl Fn(u64, u16) -> F,
+) -> Vec<T>
+where
+    F: Future<Output = anyhow::Result<Vec<T>>>,
+{
+    let end_version = starting_version + num_transactions_to_fetch as u64;
+    let mut raw_txns = Vec::with_capacity(num_transactions_to_fetch as usize);
+    let mut page_version = starting_version;
+    let mut retry_number = 0;
+    while page_version < end_version {
+        let num_page_transactions =
+            std::cmp::min(page_size.get() as u64, end_version - page_version) as u16;
+        fetch_retry.wait_to_fetch().await;
+        let fetch_start = Instant::now();
+        match fetch_page(page_version, num_page_transactions).await {
+            Ok(page) => {
+                let fetch_time = fetch_start.elapsed();
+                FETCH_LATENCY_SECONDS.observe(fetch_time.as_secs_f64());
+                page_size.record_fetch(fetch_time);
+                fetch_retry.record_success();
+                retry_number = 0;
+                if page.is_empty() {
+                    break;
+                }
+                page_version += page.len() as u64;
+                raw_txns.extend(page);
+            }
             Err(err) => {
                 UNABLE_TO_FETCH_TRANSACTION.inc();
-                retries += 1;
-                if retries >= max_retries {
-                    error!(
-                        starting_version = starting_version,
-                        num_transactions = num_transactions_to_fetch,
-                        error = format!("{:?}", err),
-                        "Could not fetch transactions: retries exhausted",
-                    );
-                    panic!(
-                        "Could not fetch {} transactions after {} retries, starting at {}: {:?}",
-                        num_transactions_to_fetch, retries, starting_version, err
-                    );
-                } else {
-                    error!(
-                        starting_version = starting_version,
-                        num_transactions = num_transactions_to_fetch,
-                        error = format!("{:?}", err),
-                        "Could not fetch transactions: will retry",
-                    );
+                match FetchErrorKind::classify(&err) {
+                    FetchErrorKind::PageTooLarge if page_size.shrink() => {
+                        FETCH_RETRIES.inc();
+                        warn!(
+                            starting_version = page_version,
+                            num_transactions = num_page_transactions,
+                            error = format!("{:?}", err),
+                            "Could not fetch transactions: will retry with a smaller page",
+                        );
+                    }
+                    FetchErrorKind::Transient => {
+                        FETCH_RETRIES.inc();
+                        // A smaller page may get through, if the page size is adaptive
+                        page_size.shrink();
+                        retry_number += 1;
+                        let backoff = fetch_retry.record_failure(retry_number);
+                        error!(
+                            starting_version = page_version,
+                            num_transactions = num_page_transactions,
+                            retry_number = retry_number,
+                            backoff_millis = backoff.as_millis() as u64,
+                            error = format!("{:?}", err),
+                            "Could not fetch transactions: will retry",
+                        );
+                        tokio::time::sleep(backoff).await;
+                    }
+                    kind => {
+                        error!(
+                            starting_version = page_version,
+                            num_transactions = num_page_transactions,
+                            error = format!("{:?}", err),
+                            "Could not fetch transactions: can't be retried",
+                        );
+                        panic!(
+                            "Could not fetch {} transactions starting at {} ({:?}): {:?}",
+                            num_page_transactions, page_version, kind, err
+                        );
+                    }
                 }
-                tokio::time::sleep(Duration::from_millis(300)).await;
             }
         }
     }
+    raw_txns
+}
+
+/// Fetches `num_transactions_to_fetch` transactions starting at `starting_version` from storage and
+/// converts them to API transactions, filling in block height and epoch.
+pub async fn fetch_nexts(
+    context: Arc<Context>,
+    starting_version: u64,
+    ledger_version: u64,
+    num_transactions_to_fetch: u16,
+) -> Vec<Transaction> {
+    fetch_nexts_with_page_size(
+        context,
+        starting_version,
+        ledger_version,
+        num_transactions_to_fetch,
+        &PageSize::new(num_transactions_to_fetch, None),
+        &FetchRetry::default(),
+    )
+    .await
 }
 
-async fn fetch_nexts(
+/// Same as `fetch_nexts`, fetching from storage in pages of `page_size`
+async fn fetch_nexts_with_page_size(
     context: Arc<Context>,
     starting_version: u64,
     ledger_version: u64,
     num_transactions_to_fetch: u16,
+    page_size: &PageSize,
+    fetch_retry: &FetchRetry,
 ) -> Vec<Transaction> {
     let start_millis = chrono::Utc::now().naive_utc();
 
@@ -229,7 +471,8 @@ async fn fetch_nexts(
         starting_version,
         ledger_version,
         num_transactions_to_fetch,
-        3,
+        page_size,
+        fetch_retry,
     )
     .await;
 
@@ -270,27 +513,7 @@ async fn fetch_nexts(
             converter
                 .try_into_onchain_transaction(timestamp, t)
                 .map(|mut txn| {
-                    match txn {
-                        Transaction::PendingTransaction(_) => {
-                            unreachable!("Indexer should never see pending transactions")
-                        }
-                        Transaction::UserTransaction(ref mut ut) => {
-                            ut.info.block_height = Some(block_height_bcs);
-                            ut.info.epoch = Some(epoch_bcs);
-                        }
-                        Transaction::GenesisTransaction(ref mut gt) => {
-                            gt.info.block_height = Some(block_height_bcs);
-                            gt.info.epoch = Some(epoch_bcs);
-                        }
-                        Transaction::BlockMetadataTransaction(ref mut bmt) => {
-                            bmt.info.block_height = Some(block_height_bcs);
-                            bmt.info.epoch = Some(epoch_bcs);
-                        }
-                        Transaction::StateCheckpointTransaction(ref mut sct) => {
-                            sct.info.block_height = Some(block_height_bcs);
-                            sct.info.epoch = Some(epoch_bcs);
-                        }
-                    };
+                    set_block_info(&mut txn, block_height_bcs, epoch_bcs);
                     txn
                 })
         })
@@ -335,6 +558,35 @@ async fn fetch_nexts(
     transactions
 }
 
+/// Fills in the block height and epoch, which storage and the REST API leave out
+pub fn set_block_info(
+    txn: &mut Transaction,
+    block_height: aptos_api_types::U64,
+    epoch: aptos_api_types::U64,
+) {
+    match txn {
+        Transaction::PendingTransaction(_) => {
+            unreachable!("Indexer should never see pending transactions")
+        }
+        Transaction::UserTransaction(ref mut ut) => {
+            ut.info.block_height = Some(block_height);
+            ut.info.epoch = Some(epoch);
+        }
+        Transaction::GenesisTransaction(ref mut gt) => {
+            gt.info.block_height = Some(block_height);
+            gt.info.epoch = Some(epoch);
+        }
+        Transaction::BlockMetadataTransaction(ref mut bmt) => {
+            bmt.info.block_height = Some(block_height);
+            bmt.info.epoch = Some(epoch);
+        }
+        Transaction::StateCheckpointTransaction(ref mut sct) => {
+            sct.info.block_height = Some(block_height);
+            sct.info.epoch = Some(epoch);
+        }
+    };
+}
+
 #[derive(Clone, Debug)]
 pub struct TransactionFetcherOptions {
     pub starting_retry_time_millis: u64,
@@ -344,6 +596,17 @@ pub struct TransactionFetcherOptions {
     pub transaction_fetch_batch_size: u16,
     pub max_pending_batches: usize,
     pub max_tasks: usize,
+    pub adaptive_fetch: Option<AdaptiveFetch>,
+    /// Shared by all fetch tasks
+    pub fetch_retry: Arc<FetchRetry>,
+    /// If set, transactions are fetched from these nodes instead of the local node's storage
+    pub upstream_nodes: Option<Arc<UpstreamNodes>>,
+    /// If set, transactions are read from this stream instead of being fetched
+    pub transaction_stream: Option<TransactionStream>,
+    /// If set, shared with the fetchers of the other processors
+    pub fetch_cache: Option<Arc<FetchCache>>,
+    /// Processor the transactions are fetched for, labels its metrics
+    pub processor_name: String,
 }
 
 fn default_if_zero<T>(value: Option<T>, default: T) -> T
@@ -388,6 +651,154 @@ impl TransactionFetcherOptions {
             transaction_fetch_batch_size,
             max_pending_batches,
             max_tasks: std::cmp::max(max_tasks, 1),
+            adaptive_fetch: None,
+            fetch_retry: Arc::new(FetchRetry::default()),
+            upstream_nodes: None,
+            transaction_stream: None,
+            fetch_cache: None,
+            processor_name: String::new(),
+        }
+    }
+
+    pub fn with_adaptive_fetch(mut self, adaptive_fetch: Option<AdaptiveFetch>) -> Self {
+        self.adaptive_fetch = adaptive_fetch;
+        self
+    }
+
+    pub fn with_fetch_retry(mut self, fetch_retry: FetchRetry) -> Self {
+        self.fetch_retry = Arc::new(fetch_retry);
+        self
+    }
+
+    pub fn with_upstream_nodes(mut self, upstream_nodes: Option<UpstreamNodes>) -> Self {
+        self.upstream_nodes = upstream_nodes.map(Arc::new);
+        self
+    }
+
+    pub fn with_transaction_stream(
+        mut self,
+        transaction_stream: Option<TransactionStream>,
+    ) -> Self {
+        self.transaction_stream = transaction_stream;
+        self
+    }
+
+    pub fn with_fetch_cache(mut self, fetch_cache: Option<FetchCache>) -> Self {
+        self.fetch_cache = fetch_cache.map(Arc::new);
+        self
+    }
+
+    pub fn with_processor_name(mut self, processor_name: &str) -> Self {
+        self.processor_name = processor_name.to_string();
+        self
+    }
+}
+
+#[derive(Clone, Debug)]
+pub struct AdaptiveFetch {
+    min_page_size: u16,
+    slow_fetch: Duration,
+    grow_after_fetches: u64,
+}
+
+impl AdaptiveFetch {
+    /// batch_size is the largest page size
+    pub fn from_config(
+        config: Option<&AdaptiveFetchConfig>,
+        batch_size: u16,
+    ) -> anyhow::Result<Option<Self>> {
+        let config = match config {
+            Some(config) => config,
+            None => return Ok(None),
+        };
+        let min_page_size = config
+            .min_batch_size
+            .unwrap_or_else(|| std::cmp::min(MIN_PAGE_SIZE, batch_size));
+        ensure!(min_page_size > 0, "min_batch_size must be greater than 0");
+        ensure!(
+            min_page_size <= batch_size,
+            "min_batch_size must not be greater than batch_size ({})",
+            batch_size
+        );
+        let grow_after_fetches = config.grow_after_fetches.unwrap_or(GROW_AFTER_FETCHES);
+        ensure!(
+            grow_after_fetches > 0,
+            "grow_after_fetches must be greater than 0"
+        );
+        Ok(Some(Self {
+            min_page_size,
+            slow_fetch: Duration::from_millis(config.slow_fetch_ms.unwrap_or(SLOW_FETCH_MILLIS)),
+            grow_after_fetches,
+        }))
+    }
+}
+
+#[derive(Debug)]
+struct PageSizeState {
+    current: u16,
+    good_fetches: u64,
+}
+
+/// Versions per fetch from storage. Always the batch size unless fetching is adaptive, in which
+/// case it's halved after a fetch fails or is slow and doubled after a run of fetches that
+/// aren't.
+#[derive(Debug)]
+pub struct PageSize {
+    max: u16,
+    adaptive_fetch: Option<AdaptiveFetch>,
+    state: Mutex<PageSizeState>,
+}
+
+impl PageSize {
+    pub fn new(max: u16, adaptive_fetch: Option<AdaptiveFetch>) -> Self {
+        Self {
+            max,
+            adaptive_fetch,
+            state: Mutex::new(PageSizeState {
+                current: max,
+                good_fetches: 0,
+            }),
+        }
+    }
+
+    pub fn get(&self) -> u16 {
+        self.state.lock().unwrap().current
+    }
+
+    /// Returns false if the page size isn't adaptive or is already at its minimum
+    pub fn shrink(&self) -> bool {
+        let adaptive_fetch = match &self.adaptive_fetch {
+            Some(adaptive_fetch) => adaptive_fetch,
+            None => return false,
+        };
+        let mut state = self.state.lock().unwrap();
+        state.good_fetches = 0;
+        if state.current <= adaptive_fetch.min_page_size {
+            return false;
+        }
+        state.current = std::cmp::max(state.current / 2, adaptive_fetch.min_page_size);
+        FETCH_PAGE_SIZE.set(state.current as i64);
+        warn!(page_size = state.current, "Shrinking the fetch page size");
+        true
+    }
+
+    /// Records a successful fetch that took fetch_time
+    pub fn record_fetch(&self, fetch_time: Duration) {
+        let adaptive_fetch = match &self.adaptive_fetch {
+            Some(adaptive_fetch) => adaptive_fetch,
+            None => return,
+        };
+        if fetch_time > adaptive_fetch.slow_fetch {
+            self.shrink();
+            return;
+        }
+        let mut state = self.state.lock().unwrap();
+        state.good_fetches += 1;
+        if state.current < self.max && state.good_fetches >= adaptive_fetch.grow_after_fetches {
+            state.current = std::cmp::min(state.current.saturating_mul(2), self.max);
+            state.good_fetches = 0;
+            FETCH_PAGE_SIZE.set(state.current as i64);
+            info!(page_size = state.current, "Growing the fetch page size");
         }
     }
 }
@@ -486,3 +897,147 @@ pub trait TransactionFetcherTrait: Send + Sync {
 
     async fn start(&mut self);
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use anyhow::anyhow;
+    use aptos_config::config::FetchRetryConfig;
+    use aptosdb::errors::AptosDbError;
+    use std::sync::atomic::{AtomicU64, Ordering};
+
+    #[test]
+    fn test_page_size_shrinks_and_grows() {
+        let adaptive_fetch = AdaptiveFetch::from_config(
+            Some(&AdaptiveFetchConfig {
+                min_batch_size: Some(100),
+                slow_fetch_ms: Some(1000),
+                grow_after_fetches: Some(2),
+            }),
+            500,
+        )
+        .unwrap();
+        let page_size = PageSize::new(500, adaptive_fetch);
+        assert!(page_size.shrink());
+        assert_eq!(page_size.get(), 250);
+        page_size.record_fetch(Duration::from_secs(2));
+        assert_eq!(page_size.get(), 125);
+        // Never below the minimum
+        assert!(page_size.shrink());
+        assert!(!page_size.shrink());
+        assert_eq!(page_size.get(), 100);
+
+        // A slow fetch resets the run of good ones
+        page_size.record_fetch(Duration::from_millis(10));
+        page_size.record_fetch(Duration::from_secs(2));
+        page_size.record_fetch(Duration::from_millis(10));
+        assert_eq!(page_size.get(), 100);
+        for _ in 0..10 {
+            page_size.record_fetch(Duration::from_millis(10));
+        }
+        assert_eq!(page_size.get(), 500);
+
+        // Not adaptive, the page size stays at the batch size
+        let page_size = PageSize::new(500, None);
+        assert!(!page_size.shrink());
+        page_size.record_fetch(Duration::from_secs(10));
+        assert_eq!(page_size.get(), 500);
+    }
+
+    fn fetch_retry() -> FetchRetry {
+        FetchRetry::from_config(Some(&FetchRetryConfig {
+            starting_backoff_ms: Some(1),
+            max_backoff_ms: Some(5),
+            requests_per_second: None,
+            circuit_breaker_failures: Some(3),
+            circuit_breaker_pause_ms: Some(20),
+        }))
+        .unwrap()
+    }
+
+    #[tokio::test]
+    async fn test_failure_storm_stalls_instead_of_failing() {
+        let fetch_retry = fetch_retry();
+        let attempts = AtomicU64::new(0);
+        let start = Instant::now();
+        let versions = fetch_pages_with_retries(
+            100,
+            50,
+            &PageSize::new(20, None),
+            &fetch_retry,
+            |page_version, num_page_transactions| {
+                // The second page fails 5 times before the node recovers
+                let attempt = attempts.fetch_add(1, Ordering::Relaxed);
+                future::ready(if (1..6).contains(&attempt) {
+                    Err(anyhow!("Service unavailable"))
+                } else {
+                    Ok((page_version..page_version + num_page_transactions as u64).collect())
+                })
+            },
+        )
+        .await;
+        assert_eq!(versions, (100..150).collect::<Vec<u64>>());
+        assert_eq!(attempts.load(Ordering::Relaxed), 8);
+        // Paused after the third failure in a row
+        assert!(start.elapsed() >= Duration::from_millis(20));
+        assert!(!fetch_retry.is_paused());
+    }
+
+    #[tokio::test]
+    async fn test_too_large_pages_shrink() {
+        let adaptive_fetch = AdaptiveFetch::from_config(
+            Some(&AdaptiveFetchConfig {
+                min_batch_size: Some(10),
+                ..AdaptiveFetchConfig::default()
+            }),
+            500,
+        )
+        .unwrap();
+        let page_size = PageSize::new(500, adaptive_fetch);
+        let versions = fetch_pages_with_retries(
+            0,
+            500,
+            &page_size,
+            &fetch_retry(),
+            |page_version, num_page_transactions| {
+                future::ready(if num_page_transactions > 100 {
+                    Err(AptosDbError::TooManyRequested(num_page_transactions as u64, 100).into())
+                } else {
+                    Ok((page_version..page_version + num_page_transactions as u64).collect())
+                })
+            },
+        )
+        .await;
+        assert_eq!(versions, (0..500).collect::<Vec<u64>>());
+        assert_eq!(page_size.get(), 62);
+    }
+
+    #[tokio::test]
+    #[should_panic(expected = "Missing")]
+    async fn test_missing_versions_are_not_retried() {
+        fetch_pages_with_retries(
+            0,
+            10,
+            &PageSize::new(10, None),
+            &fetch_retry(),
+            |_, _| -> future::Ready<anyhow::Result<Vec<u64>>> {
+                future::ready(Err(AptosDbError::NotFound("Version 0".to_string()).into()))
+            },
+        )
+        .await;
+    }
+
+    #[test]
+    fn test_adaptive_fetch_from_config() {
+        assert!(AdaptiveFetch::from_config(None, 500).unwrap().is_none());
+        let adaptive_fetch = AdaptiveFetch::from_config(Some(&AdaptiveFetchConfig::default()), 5)
+            .unwrap()
+            .unwrap();
+        assert_eq!(adaptive_fetch.min_page_size, 5);
+        let config = AdaptiveFetchConfig {
+            min_batch_size: Some(600),
+            ..AdaptiveFetchConfig::default()
+        };
+        assert!(AdaptiveFetch::from_config(Some(&config), 500).is_err());
+    }
+}
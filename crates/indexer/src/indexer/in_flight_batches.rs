@@ -0,0 +1,113 @@
+// Copyright (c) Aptos
+// SPDX-License-Identifier: Apache-2.0
+
+//! Limits how many batches the tailer processes at once. Each batch that reports a saturated
+//! connection pool lowers the limit by one, and it's raised back by one after a run of batches
+//! that don't, up to the number of processor tasks.
+
+use aptos_logger::{info, warn};
+use std::sync::{Arc, Mutex};
+use tokio::sync::{OwnedSemaphorePermit, Semaphore};
+
+/// Batches in a row without a saturated pool before the limit is raised
+pub const RAISE_LIMIT_AFTER_BATCHES: u64 = 100;
+
+#[derive(Debug)]
+struct Limit {
+    current: usize,
+    unsaturated_batches: u64,
+}
+
+#[derive(Debug)]
+pub struct InFlightBatches {
+    max: usize,
+    permits: Arc<Semaphore>,
+    limit: Mutex<Limit>,
+}
+
+impl InFlightBatches {
+    pub fn new(max: usize) -> Self {
+        let max = max.max(1);
+        Self {
+            max,
+            permits: Arc::new(Semaphore::new(max)),
+            limit: Mutex::new(Limit {
+                current: max,
+                unsaturated_batches: 0,
+            }),
+        }
+    }
+
+    pub fn limit(&self) -> usize {
+        self.limit.lock().unwrap().current
+    }
+
+    /// Waits until there's room for another batch
+    pub async fn start(&self) -> OwnedSemaphorePermit {
+        self.permits
+            .clone()
+            .acquire_owned()
+            .await
+            .expect("The semaphore is never closed")
+    }
+
+    /// Ends a batch started with `start`, adjusting the limit by whether the pool was saturated
+    pub fn finish(&self, permit: OwnedSemaphorePermit, pool_saturated: bool) {
+        let mut limit = self.limit.lock().unwrap();
+        if pool_saturated {
+            limit.unsaturated_batches = 0;
+            if limit.current > 1 {
+                limit.current -= 1;
+                // Not returning the permit leaves room for one less batch
+                permit.forget();
+                warn!(
+                    limit = limit.current,
+                    "Connection pool saturated, processing fewer batches at once"
+                );
+            }
+            return;
+        }
+        limit.unsaturated_batches += 1;
+        if limit.current < self.max && limit.unsaturated_batches >= RAISE_LIMIT_AFTER_BATCHES {
+            limit.current += 1;
+            limit.unsaturated_batches = 0;
+            self.permits.add_permits(1);
+            info!(limit = limit.current, "Processing more batches at once");
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn test_limit_shrinks_and_recovers() {
+        let in_flight = InFlightBatches::new(3);
+        let first = in_flight.start().await;
+        let second = in_flight.start().await;
+        let third = in_flight.start().await;
+        assert_eq!(in_flight.permits.available_permits(), 0);
+
+        in_flight.finish(first, true);
+        in_flight.finish(second, false);
+        in_flight.finish(third, false);
+        assert_eq!(in_flight.limit(), 2);
+        assert_eq!(in_flight.permits.available_permits(), 2);
+
+        // Never below one batch
+        for _ in 0..3 {
+            let permit = in_flight.start().await;
+            in_flight.finish(permit, true);
+        }
+        assert_eq!(in_flight.limit(), 1);
+        assert_eq!(in_flight.permits.available_permits(), 1);
+
+        for _ in 0..RAISE_LIMIT_AFTER_BATCHES * 3 {
+            let permit = in_flight.start().await;
+            in_flight.finish(permit, false);
+        }
+        assert_eq!(in_flight.limit(), 3);
+        assert_eq!(in_flight.permits.available_permits(), 3);
+    }
+}
@@ -2,7 +2,15 @@
 // SPDX-License-Identifier: Apache-2.0
 
 pub mod errors;
+pub mod fetch_cache;
+pub mod fetch_retry;
 pub mod fetcher;
+pub mod in_flight_batches;
+pub mod node_storage;
 pub mod processing_result;
+pub mod proto_convert;
 pub mod tailer;
 pub mod transaction_processor;
+pub mod transaction_stream;
+pub mod transaction_trace;
+pub mod upstream_nodes;
@@ -0,0 +1,100 @@
+// Copyright (c) Aptos
+// SPDX-License-Identifier: Apache-2.0
+
+//! Which node storage the standalone indexer reads transactions from. It's opened read only and
+//! converted to API transactions by the same converter the node's REST API uses, so processors
+//! see the same transactions whichever node they come from. The storage is a point in time: a
+//! restored backup, or a copy of a node's db taken while the node was stopped. RocksDB doesn't
+//! guard a read only open against a node writing to the same directory, hence the required
+//! `read_only` acknowledgement.
+
+use anyhow::ensure;
+use aptos_config::config::{
+    NodeConfig, NodeStorageConfig, TransactionStreamConfig, UpstreamNodesConfig,
+};
+use std::path::PathBuf;
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct NodeStorage {
+    dir: PathBuf,
+}
+
+impl NodeStorage {
+    pub fn from_config(
+        config: Option<&NodeStorageConfig>,
+        upstream_nodes: Option<&UpstreamNodesConfig>,
+        transaction_stream: Option<&TransactionStreamConfig>,
+    ) -> anyhow::Result<Option<Self>> {
+        let config = match config {
+            Some(config) => config,
+            None => return Ok(None),
+        };
+        ensure!(!config.dir.is_empty(), "dir can't be empty");
+        ensure!(
+            config.read_only,
+            "read_only must be true, and dir must never be a running node's storage"
+        );
+        ensure!(
+            upstream_nodes.is_none() && transaction_stream.is_none(),
+            "Can't be combined with upstream_nodes or transaction_stream"
+        );
+        Ok(Some(Self {
+            dir: PathBuf::from(&config.dir),
+        }))
+    }
+
+    /// The configured directory, or the node config's `storage.dir`
+    pub fn dir(node_storage: Option<&Self>, node_config: &NodeConfig) -> PathBuf {
+        match node_storage {
+            Some(node_storage) => node_storage.dir.clone(),
+            None => node_config.storage.dir(),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn config(read_only: bool) -> NodeStorageConfig {
+        NodeStorageConfig {
+            dir: "/data/snapshots/mainnet".to_string(),
+            read_only,
+        }
+    }
+
+    #[test]
+    fn test_from_config() {
+        assert_eq!(NodeStorage::from_config(None, None, None).unwrap(), None);
+        let node_storage = NodeStorage::from_config(Some(&config(true)), None, None)
+            .unwrap()
+            .unwrap();
+        assert_eq!(
+            NodeStorage::dir(Some(&node_storage), &NodeConfig::default()),
+            PathBuf::from("/data/snapshots/mainnet")
+        );
+        assert_eq!(
+            NodeStorage::dir(None, &NodeConfig::default()),
+            NodeConfig::default().storage.dir()
+        );
+
+        // The read only open has to be acknowledged
+        assert!(NodeStorage::from_config(Some(&config(false)), None, None).is_err());
+        assert!(NodeStorage::from_config(
+            Some(&NodeStorageConfig {
+                dir: "".to_string(),
+                read_only: true,
+            }),
+            None,
+            None
+        )
+        .is_err());
+        // Transactions wouldn't be read from it
+        assert!(NodeStorage::from_config(
+            Some(&config(true)),
+            Some(&UpstreamNodesConfig::default()),
+            None
+        )
+        .is_err());
+    }
+}
@@ -6,6 +6,11 @@ pub struct ProcessingResult {
     pub name: &'static str,
     pub start_version: u64,
     pub end_version: u64,
+    /// Checking out a connection was slow while the batch was processed, the tailer should run
+    /// fewer batches at once
+    pub pool_saturated: bool,
+    /// The batch's success was written along with its rows, see `BatchCheckpoint`
+    pub success_recorded: bool,
 }
 
 impl ProcessingResult {
@@ -14,6 +19,8 @@ impl ProcessingResult {
             name,
             start_version,
             end_version,
+            pool_saturated: false,
+            success_recorded: false,
         }
     }
 }
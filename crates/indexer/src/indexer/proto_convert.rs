@@ -0,0 +1,495 @@
+// Copyright (c) Aptos
+// SPDX-License-Identifier: Apache-2.0
+
+//! Converts the protobuf transactions of the Firehose stream back into the API transactions the
+//! processors consume. This undoes `aptos_fh_stream::convert`. Module and script ABIs are parsed
+//! again from the bytecode. Raw table values aren't in the stream, so `WriteTableItem::value` is
+//! left empty, and processors read the decoded `data` instead.
+
+use anyhow::{bail, Context as AnyhowContext};
+use aptos_api_types::{
+    AccountSignature, Address, BlockMetadataTransaction, DecodedTableData, DeleteModule,
+    DeleteResource, DeleteTableItem, DeletedTableData, DirectWriteSet, Ed25519Signature,
+    EntryFunctionId, EntryFunctionPayload, Event, EventGuid, GenesisPayload, GenesisTransaction,
+    HashValue, HexEncodedBytes, ModuleBundlePayload, MoveModuleBytecode, MoveModuleId,
+    MoveResource, MoveScriptBytecode, MoveStructTag, MoveType, MultiAgentSignature,
+    MultiEd25519Signature, ScriptPayload, ScriptWriteSet, StateCheckpointTransaction, Transaction,
+    TransactionInfo, TransactionPayload, TransactionSignature, UserTransaction,
+    UserTransactionRequest, WriteModule, WriteResource, WriteSet, WriteSetChange, WriteSetPayload,
+    WriteTableItem, U64,
+};
+use aptos_bitvec::BitVec;
+use aptos_protos::{extractor::v1 as extractor, util::timestamp::Timestamp};
+use std::str::FromStr;
+
+/// Bits in a multi-ed25519 signature's bitmap
+const MULTI_ED25519_BITMAP_BITS: u16 = 32;
+
+fn parse<T: FromStr<Err = anyhow::Error>>(value: &str, what: &str) -> anyhow::Result<T> {
+    T::from_str(value).with_context(|| format!("Invalid {} {:?}", what, value))
+}
+
+fn parse_json(value: &str, what: &str) -> anyhow::Result<serde_json::Value> {
+    serde_json::from_str(value).with_context(|| format!("Invalid {} json {:?}", what, value))
+}
+
+fn required<'a, T>(field: &'a Option<T>, what: &str) -> anyhow::Result<&'a T> {
+    field.as_ref().with_context(|| format!("Missing {}", what))
+}
+
+fn convert_hash(bytes: &[u8]) -> anyhow::Result<HashValue> {
+    parse(&format!("0x{}", hex::encode(bytes)), "hash")
+}
+
+fn convert_state_key_hash(bytes: &[u8]) -> String {
+    format!("0x{}", hex::encode(bytes))
+}
+
+fn convert_timestamp_usecs(timestamp: Option<&Timestamp>) -> U64 {
+    timestamp.map_or(U64(0), |timestamp| {
+        U64(timestamp.seconds as u64 * 1_000_000 + timestamp.nanos as u64 / 1_000)
+    })
+}
+
+fn convert_move_module_id(module_id: &extractor::MoveModuleId) -> anyhow::Result<MoveModuleId> {
+    Ok(MoveModuleId {
+        address: parse(&module_id.address, "module address")?,
+        name: parse(&module_id.name, "module name")?,
+    })
+}
+
+fn convert_move_struct_tag(struct_tag: &extractor::MoveStructTag) -> anyhow::Result<MoveStructTag> {
+    Ok(MoveStructTag::new(
+        parse(&struct_tag.address, "struct address")?,
+        parse(&struct_tag.module, "struct module")?,
+        parse(&struct_tag.name, "struct name")?,
+        convert_move_types(&struct_tag.generic_type_params)?,
+    ))
+}
+
+fn convert_move_types(move_types: &[extractor::MoveType]) -> anyhow::Result<Vec<MoveType>> {
+    move_types.iter().map(convert_move_type).collect()
+}
+
+fn convert_move_type(move_type: &extractor::MoveType) -> anyhow::Result<MoveType> {
+    use extractor::{move_type::Content, MoveTypes};
+
+    Ok(match (move_type.r#type(), &move_type.content) {
+        (MoveTypes::Bool, _) => MoveType::Bool,
+        (MoveTypes::U8, _) => MoveType::U8,
+        (MoveTypes::U64, _) => MoveType::U64,
+        (MoveTypes::U128, _) => MoveType::U128,
+        (MoveTypes::Address, _) => MoveType::Address,
+        (MoveTypes::Signer, _) => MoveType::Signer,
+        (MoveTypes::Vector, Some(Content::Vector(items))) => MoveType::Vector {
+            items: Box::new(convert_move_type(items)?),
+        },
+        (MoveTypes::Struct, Some(Content::Struct(struct_tag))) => {
+            MoveType::Struct(convert_move_struct_tag(struct_tag)?)
+        }
+        (MoveTypes::GenericTypeParam, Some(Content::GenericTypeParamIndex(index))) => {
+            MoveType::GenericTypeParam {
+                index: u16::try_from(*index).context("Invalid generic type param index")?,
+            }
+        }
+        (MoveTypes::Reference, Some(Content::Reference(reference))) => MoveType::Reference {
+            mutable: reference.mutable,
+            to: Box::new(convert_move_type(
+                reference.to.as_deref().context("Missing referenced type")?,
+            )?),
+        },
+        (MoveTypes::Unparsable, Some(Content::Unparsable(unparsable))) => {
+            MoveType::Unparsable(unparsable.clone())
+        }
+        (r#type, content) => bail!("Move type {:?} doesn't match {:?}", r#type, content),
+    })
+}
+
+fn convert_event(event: &extractor::Event) -> anyhow::Result<Event> {
+    let key = required(&event.key, "event key")?;
+    Ok(Event {
+        guid: EventGuid {
+            creation_number: U64(key.creation_number),
+            account_address: parse(&key.account_address, "event account address")?,
+        },
+        sequence_number: U64(event.sequence_number),
+        typ: convert_move_type(required(&event.r#type, "event type")?)?,
+        data: parse_json(&event.data, "event data")?,
+    })
+}
+
+fn convert_events(events: &[extractor::Event]) -> anyhow::Result<Vec<Event>> {
+    events.iter().map(convert_event).collect()
+}
+
+fn convert_write_set_change(change: &extractor::WriteSetChange) -> anyhow::Result<WriteSetChange> {
+    use extractor::write_set_change::Change;
+
+    Ok(match required(&change.change, "write set change")? {
+        Change::DeleteModule(delete_module) => WriteSetChange::DeleteModule(DeleteModule {
+            address: parse(&delete_module.address, "module address")?,
+            state_key_hash: convert_state_key_hash(&delete_module.state_key_hash),
+            module: convert_move_module_id(required(&delete_module.module, "deleted module")?)?,
+        }),
+        Change::DeleteResource(delete_resource) => WriteSetChange::DeleteResource(DeleteResource {
+            address: parse(&delete_resource.address, "resource address")?,
+            state_key_hash: convert_state_key_hash(&delete_resource.state_key_hash),
+            resource: convert_move_struct_tag(required(
+                &delete_resource.r#type,
+                "deleted resource type",
+            )?)?,
+        }),
+        Change::DeleteTableItem(delete_table_item) => {
+            let data = required(&delete_table_item.data, "deleted table item data")?;
+            WriteSetChange::DeleteTableItem(DeleteTableItem {
+                state_key_hash: convert_state_key_hash(&delete_table_item.state_key_hash),
+                handle: parse(&delete_table_item.handle, "table handle")?,
+                key: parse(&delete_table_item.key, "table key")?,
+                data: Some(DeletedTableData {
+                    key: parse_json(&data.key, "table key")?,
+                    key_type: data.key_type.clone(),
+                }),
+            })
+        }
+        Change::WriteModule(write_module) => WriteSetChange::WriteModule(WriteModule {
+            address: parse(&write_module.address, "module address")?,
+            state_key_hash: convert_state_key_hash(&write_module.state_key_hash),
+            data: convert_move_module_bytecode(required(&write_module.data, "module bytecode")?)?,
+        }),
+        Change::WriteResource(write_resource) => WriteSetChange::WriteResource(WriteResource {
+            address: parse(&write_resource.address, "resource address")?,
+            state_key_hash: convert_state_key_hash(&write_resource.state_key_hash),
+            data: serde_json::from_str::<MoveResource>(&write_resource.data)
+                .with_context(|| format!("Invalid resource json {:?}", write_resource.data))?,
+        }),
+        Change::WriteTableItem(write_table_item) => {
+            let data = required(&write_table_item.data, "table item data")?;
+            WriteSetChange::WriteTableItem(WriteTableItem {
+                state_key_hash: convert_state_key_hash(&write_table_item.state_key_hash),
+                handle: parse(&write_table_item.handle, "table handle")?,
+                key: parse(&write_table_item.key, "table key")?,
+                value: HexEncodedBytes(vec![]),
+                data: Some(DecodedTableData {
+                    key: parse_json(&data.key, "table key")?,
+                    key_type: data.key_type.clone(),
+                    value: parse_json(&data.value, "table value")?,
+                    value_type: data.value_type.clone(),
+                }),
+            })
+        }
+    })
+}
+
+fn convert_write_set_changes(
+    changes: &[extractor::WriteSetChange],
+) -> anyhow::Result<Vec<WriteSetChange>> {
+    changes.iter().map(convert_write_set_change).collect()
+}
+
+fn convert_move_module_bytecode(
+    bytecode: &extractor::MoveModuleBytecode,
+) -> anyhow::Result<MoveModuleBytecode> {
+    MoveModuleBytecode::new(bytecode.bytecode.clone()).try_parse_abi()
+}
+
+fn convert_arguments(arguments: &[String]) -> anyhow::Result<Vec<serde_json::Value>> {
+    arguments
+        .iter()
+        .map(|argument| parse_json(argument, "argument"))
+        .collect()
+}
+
+fn convert_script_payload(payload: &extractor::ScriptPayload) -> anyhow::Result<ScriptPayload> {
+    let code = required(&payload.code, "script code")?;
+    Ok(ScriptPayload {
+        code: MoveScriptBytecode::new(code.bytecode.clone()).try_parse_abi(),
+        type_arguments: convert_move_types(&payload.type_arguments)?,
+        arguments: convert_arguments(&payload.arguments)?,
+    })
+}
+
+fn convert_transaction_payload(
+    payload: &extractor::TransactionPayload,
+) -> anyhow::Result<TransactionPayload> {
+    use extractor::transaction_payload::Payload;
+
+    Ok(match required(&payload.payload, "payload")? {
+        Payload::EntryFunctionPayload(entry_function) => {
+            let function = required(&entry_function.function, "entry function")?;
+            TransactionPayload::EntryFunctionPayload(EntryFunctionPayload {
+                function: EntryFunctionId {
+                    module: convert_move_module_id(required(
+                        &function.module,
+                        "entry function module",
+                    )?)?,
+                    name: parse(&function.name, "entry function name")?,
+                },
+                type_arguments: convert_move_types(&entry_function.type_arguments)?,
+                arguments: convert_arguments(&entry_function.arguments)?,
+            })
+        }
+        Payload::ScriptPayload(script) => {
+            TransactionPayload::ScriptPayload(convert_script_payload(script)?)
+        }
+        Payload::ModuleBundlePayload(module_bundle) => {
+            TransactionPayload::ModuleBundlePayload(ModuleBundlePayload {
+                modules: module_bundle
+                    .modules
+                    .iter()
+                    .map(convert_move_module_bytecode)
+                    .collect::<anyhow::Result<_>>()?,
+            })
+        }
+        Payload::WriteSetPayload(_) => bail!("User transactions can't have write set payloads"),
+    })
+}
+
+fn convert_write_set(write_set: &extractor::WriteSet) -> anyhow::Result<WriteSet> {
+    use extractor::write_set::WriteSet as WriteSetPB;
+
+    Ok(match required(&write_set.write_set, "write set")? {
+        WriteSetPB::ScriptWriteSet(script_write_set) => WriteSet::ScriptWriteSet(ScriptWriteSet {
+            execute_as: parse(&script_write_set.execute_as, "execute as address")?,
+            script: convert_script_payload(required(
+                &script_write_set.script,
+                "write set script",
+            )?)?,
+        }),
+        WriteSetPB::DirectWriteSet(direct_write_set) => WriteSet::DirectWriteSet(DirectWriteSet {
+            changes: convert_write_set_changes(&direct_write_set.write_set_change)?,
+            events: convert_events(&direct_write_set.events)?,
+        }),
+    })
+}
+
+fn convert_ed25519_signature(signature: &extractor::Ed25519Signature) -> Ed25519Signature {
+    Ed25519Signature {
+        public_key: HexEncodedBytes(signature.public_key.clone()),
+        signature: HexEncodedBytes(signature.signature.clone()),
+    }
+}
+
+fn convert_multi_ed25519_signature(
+    signature: &extractor::MultiEd25519Signature,
+) -> anyhow::Result<MultiEd25519Signature> {
+    let mut bitmap = BitVec::with_num_bits(MULTI_ED25519_BITMAP_BITS);
+    for index in &signature.public_key_indices {
+        if *index >= MULTI_ED25519_BITMAP_BITS as u32 {
+            bail!("Invalid multi ed25519 public key index {}", index);
+        }
+        bitmap.set(*index as u16);
+    }
+    Ok(MultiEd25519Signature {
+        public_keys: signature
+            .public_keys
+            .iter()
+            .map(|public_key| HexEncodedBytes(public_key.clone()))
+            .collect(),
+        signatures: signature
+            .signatures
+            .iter()
+            .map(|signature| HexEncodedBytes(signature.clone()))
+            .collect(),
+        threshold: u8::try_from(signature.threshold).context("Invalid multi ed25519 threshold")?,
+        bitmap: HexEncodedBytes(bitmap.into()),
+    })
+}
+
+fn convert_account_signature(
+    signature: &extractor::AccountSignature,
+) -> anyhow::Result<AccountSignature> {
+    use extractor::account_signature::Signature;
+
+    Ok(match required(&signature.signature, "account signature")? {
+        Signature::Ed25519(ed25519) => {
+            AccountSignature::Ed25519Signature(convert_ed25519_signature(ed25519))
+        }
+        Signature::MultiEd25519(multi_ed25519) => {
+            AccountSignature::MultiEd25519Signature(convert_multi_ed25519_signature(multi_ed25519)?)
+        }
+    })
+}
+
+fn convert_transaction_signature(
+    signature: &extractor::Signature,
+) -> anyhow::Result<TransactionSignature> {
+    use extractor::signature::Signature;
+
+    Ok(match required(&signature.signature, "signature")? {
+        Signature::Ed25519(ed25519) => {
+            TransactionSignature::Ed25519Signature(convert_ed25519_signature(ed25519))
+        }
+        Signature::MultiEd25519(multi_ed25519) => TransactionSignature::MultiEd25519Signature(
+            convert_multi_ed25519_signature(multi_ed25519)?,
+        ),
+        Signature::MultiAgent(multi_agent) => {
+            TransactionSignature::MultiAgentSignature(MultiAgentSignature {
+                sender: convert_account_signature(required(
+                    &multi_agent.sender,
+                    "multi agent sender",
+                )?)?,
+                secondary_signer_addresses: multi_agent
+                    .secondary_signer_addresses
+                    .iter()
+                    .map(|address| parse::<Address>(address, "secondary signer address"))
+                    .collect::<anyhow::Result<_>>()?,
+                secondary_signers: multi_agent
+                    .secondary_signers
+                    .iter()
+                    .map(convert_account_signature)
+                    .collect::<anyhow::Result<_>>()?,
+            })
+        }
+    })
+}
+
+fn convert_transaction_info(
+    transaction: &extractor::Transaction,
+) -> anyhow::Result<TransactionInfo> {
+    let info = required(&transaction.info, "transaction info")?;
+    Ok(TransactionInfo {
+        version: U64(transaction.version),
+        hash: convert_hash(&info.hash)?,
+        state_change_hash: convert_hash(&info.state_change_hash)?,
+        event_root_hash: convert_hash(&info.event_root_hash)?,
+        state_checkpoint_hash: info
+            .state_checkpoint_hash
+            .as_deref()
+            .map(convert_hash)
+            .transpose()?,
+        gas_used: U64(info.gas_used),
+        success: info.success,
+        vm_status: info.vm_status.clone(),
+        accumulator_root_hash: convert_hash(&info.accumulator_root_hash)?,
+        changes: convert_write_set_changes(&info.changes)?,
+        block_height: Some(U64(transaction.block_height)),
+        epoch: Some(U64(transaction.epoch)),
+    })
+}
+
+/// Converts a protobuf transaction into the API transaction processors take, including its block
+/// height and epoch
+pub fn convert_transaction(transaction: &extractor::Transaction) -> anyhow::Result<Transaction> {
+    use extractor::transaction::TxnData;
+
+    let convert = || -> anyhow::Result<Transaction> {
+        let info = convert_transaction_info(transaction)?;
+        let timestamp = convert_timestamp_usecs(transaction.timestamp.as_ref());
+        Ok(match required(&transaction.txn_data, "transaction data")? {
+            TxnData::User(user) => {
+                let request = required(&user.request, "user transaction request")?;
+                Transaction::UserTransaction(Box::new(UserTransaction {
+                    info,
+                    request: UserTransactionRequest {
+                        sender: parse(&request.sender, "sender")?,
+                        sequence_number: U64(request.sequence_number),
+                        max_gas_amount: U64(request.max_gas_amount),
+                        gas_unit_price: U64(request.gas_unit_price),
+                        expiration_timestamp_secs: U64(request
+                            .expiration_timestamp_secs
+                            .as_ref()
+                            .map_or(0, |timestamp| timestamp.seconds as u64)),
+                        payload: convert_transaction_payload(required(
+                            &request.payload,
+                            "payload",
+                        )?)?,
+                        signature: request
+                            .signature
+                            .as_ref()
+                            .map(convert_transaction_signature)
+                            .transpose()?,
+                    },
+                    events: convert_events(&user.events)?,
+                    timestamp,
+                }))
+            }
+            TxnData::Genesis(genesis) => Transaction::GenesisTransaction(GenesisTransaction {
+                info,
+                payload: GenesisPayload::WriteSetPayload(WriteSetPayload {
+                    write_set: convert_write_set(required(&genesis.payload, "genesis payload")?)?,
+                }),
+                events: convert_events(&genesis.events)?,
+            }),
+            TxnData::BlockMetadata(block_metadata) => {
+                Transaction::BlockMetadataTransaction(BlockMetadataTransaction {
+                    info,
+                    id: parse(&block_metadata.id, "block id")?,
+                    epoch: U64(transaction.epoch),
+                    round: U64(block_metadata.round),
+                    events: convert_events(&block_metadata.events)?,
+                    previous_block_votes_bitvec: block_metadata.previous_block_votes_bitvec.clone(),
+                    proposer: parse(&block_metadata.proposer, "proposer")?,
+                    failed_proposer_indices: block_metadata.failed_proposer_indices.clone(),
+                    timestamp,
+                })
+            }
+            TxnData::StateCheckpoint(_) => {
+                Transaction::StateCheckpointTransaction(StateCheckpointTransaction {
+                    info,
+                    timestamp,
+                })
+            }
+        })
+    };
+    convert().with_context(|| format!("Failed to convert transaction {}", transaction.version))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::indexer::fetcher::set_block_info;
+    use prost::Message;
+    use std::fs;
+
+    const FIXTURE_DIR: &str = concat!(env!("CARGO_MANIFEST_DIR"), "/fixtures");
+
+    fn fixture_transactions() -> Vec<Transaction> {
+        let mut transactions: Vec<Transaction> = serde_json::from_str(
+            &fs::read_to_string(format!("{}/transaction_stream/block.json", FIXTURE_DIR)).unwrap(),
+        )
+        .unwrap();
+        let mut paths: Vec<_> = fs::read_dir(format!("{}/golden/transactions", FIXTURE_DIR))
+            .unwrap()
+            .map(|entry| entry.unwrap().path())
+            .collect();
+        paths.sort();
+        for path in paths {
+            transactions.push(serde_json::from_str(&fs::read_to_string(path).unwrap()).unwrap());
+        }
+        transactions
+    }
+
+    #[test]
+    fn test_round_trips_the_stream_conversion() {
+        for mut expected in fixture_transactions() {
+            let encoded = aptos_fh_stream::convert::convert_transaction(&expected, 5, 2);
+            let decoded =
+                extractor::Transaction::decode(encoded.encode_to_vec().as_slice()).unwrap();
+            let actual = convert_transaction(&decoded).unwrap();
+
+            set_block_info(&mut expected, U64(5), U64(2));
+            let changes = match &mut expected {
+                Transaction::UserTransaction(txn) => &mut txn.info.changes,
+                Transaction::GenesisTransaction(txn) => &mut txn.info.changes,
+                Transaction::BlockMetadataTransaction(txn) => &mut txn.info.changes,
+                Transaction::StateCheckpointTransaction(txn) => &mut txn.info.changes,
+                Transaction::PendingTransaction(_) => unreachable!(),
+            };
+            // Raw table values aren't in the stream
+            for change in changes.iter_mut() {
+                if let WriteSetChange::WriteTableItem(item) = change {
+                    item.value = HexEncodedBytes(vec![]);
+                }
+            }
+            assert_eq!(actual, expected, "version {:?}", expected.version());
+        }
+    }
+
+    #[test]
+    fn test_missing_fields_fail() {
+        let mut transaction =
+            aptos_fh_stream::convert::convert_transaction(&fixture_transactions().remove(0), 5, 2);
+        transaction.info = None;
+        assert!(convert_transaction(&transaction).is_err());
+    }
+}
@@ -6,6 +6,7 @@ use crate::{
     indexer::{
         errors::TransactionProcessingError,
         fetcher::{TransactionFetcher, TransactionFetcherOptions, TransactionFetcherTrait},
+        in_flight_batches::InFlightBatches,
         processing_result::ProcessingResult,
         transaction_processor::TransactionProcessor,
     },
@@ -32,6 +33,8 @@ pub struct Tailer {
     pub transaction_fetcher: Arc<Mutex<dyn TransactionFetcherTrait>>,
     processor: Arc<dyn TransactionProcessor>,
     connection_pool: PgDbPool,
+    /// If set, limits the batches processed at once, see `limit_in_flight_batches`
+    in_flight: Option<Arc<InFlightBatches>>,
 }
 
 impl Tailer {
@@ -42,15 +45,27 @@ impl Tailer {
         options: TransactionFetcherOptions,
     ) -> Result<Tailer, ParseError> {
         let resolver = Arc::new(context.move_resolver().unwrap());
-        let transaction_fetcher = TransactionFetcher::new(context, resolver, 0, options);
+        let transaction_fetcher = TransactionFetcher::new(
+            context,
+            resolver,
+            0,
+            options.with_processor_name(processor.name()),
+        );
 
         Ok(Self {
             transaction_fetcher: Arc::new(Mutex::new(transaction_fetcher)),
             connection_pool,
             processor,
+            in_flight: None,
         })
     }
 
+    /// Processes at most `max` batches at once across the tailer's clones, fewer while batches
+    /// report a saturated connection pool
+    pub fn limit_in_flight_batches(&mut self, max: usize) {
+        self.in_flight = Some(Arc::new(InFlightBatches::new(max)));
+    }
+
     pub fn run_migrations(&self) {
         let _ = &self
             .connection_pool
@@ -125,6 +140,10 @@ impl Tailer {
     pub async fn process_next_batch(
         &self,
     ) -> (u64, Result<ProcessingResult, TransactionProcessingError>) {
+        let permit = match &self.in_flight {
+            Some(in_flight) => Some(in_flight.start().await),
+            None => None,
+        };
         let transactions = self
             .transaction_fetcher
             .lock()
@@ -160,6 +179,11 @@ impl Tailer {
             "Finished processing of transaction batch"
         );
 
+        if let (Some(in_flight), Some(permit)) = (&self.in_flight, permit) {
+            let pool_saturated = matches!(&results, Ok(result) if result.pool_saturated);
+            in_flight.finish(permit, pool_saturated);
+        }
+
         (num_txns, results)
     }
 
@@ -266,7 +290,7 @@ pub async fn await_tasks<T: Debug>(tasks: Vec<JoinHandle<T>>) -> Vec<T> {
 }
 
 #[cfg(test)]
-mod test {
+pub(crate) mod test {
     use super::*;
     use crate::{
         database::{new_db_pool, PgPoolConnection},
@@ -321,6 +345,7 @@ mod test {
     }
 
     pub fn wipe_database(conn: &mut PgPoolConnection) {
+        crate::test_utils::lock_database();
         for command in [
             "DROP SCHEMA public CASCADE",
             "CREATE SCHEMA public",
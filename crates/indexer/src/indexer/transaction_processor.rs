@@ -5,20 +5,37 @@ use crate::counters::LATEST_PROCESSED_VERSION;
 use crate::database::get_chunks;
 use crate::{
     counters::{
-        GOT_CONNECTION, PROCESSOR_ERRORS, PROCESSOR_INVOCATIONS, PROCESSOR_SUCCESSES,
-        UNABLE_TO_GET_CONNECTION,
+        CONNECTION_CHECKOUT_SECONDS, CONNECTION_POOL_CONNECTIONS, CONNECTION_POOL_IDLE_CONNECTIONS,
+        CONNECTION_POOL_WAIT_COUNT, GOT_CONNECTION, PROCESSOR_ERRORS, PROCESSOR_INVOCATIONS,
+        PROCESSOR_SUCCESSES, SLOW_CONNECTION_CHECKOUTS, UNABLE_TO_GET_CONNECTION,
     },
     database::{execute_with_better_error, PgDbPool, PgPoolConnection},
     indexer::{errors::TransactionProcessingError, processing_result::ProcessingResult},
-    models::processor_statuses::ProcessorStatusModel,
+    models::{
+        processed_version_ranges::ProcessedVersionRange, processor_statuses::ProcessorStatusModel,
+    },
     schema,
 };
 use aptos_api_types::Transaction;
 use async_trait::async_trait;
-use diesel::{pg::upsert::excluded, prelude::*};
+use diesel::{pg::upsert::excluded, prelude::*, PgConnection};
 use field_count::FieldCount;
+use once_cell::sync::Lazy;
 use schema::processor_statuses::{self, dsl};
-use std::fmt::Debug;
+use std::{
+    fmt::Debug,
+    time::{Duration, Instant},
+};
+use tokio::sync::Semaphore;
+
+/// Checkouts slower than this mean the pool can't keep up, and the tailer is told to run fewer
+/// batches at once
+pub const SLOW_CHECKOUT_MILLIS: u64 = 1000;
+
+/// Blocking database work running at once, across processors
+pub const MAX_BLOCKING_DB_TASKS: usize = 16;
+
+static BLOCKING_DB_TASKS: Lazy<Semaphore> = Lazy::new(|| Semaphore::new(MAX_BLOCKING_DB_TASKS));
 
 /// The `TransactionProcessor` is used by an instance of a `Tailer` to process transactions
 #[async_trait]
@@ -36,6 +53,22 @@ pub trait TransactionProcessor: Send + Sync + Debug {
         end_version: u64,
     ) -> Result<ProcessingResult, TransactionProcessingError>;
 
+    /// Same as `process_transactions`, for processors that write `checkpoint` on the batch's own
+    /// database transaction, so a batch is never applied again once it committed. These set
+    /// `ProcessingResult::success_recorded`. By default the checkpoint is left to the caller.
+    async fn process_transactions_with_checkpoint(
+        &self,
+        transactions: Vec<Transaction>,
+        checkpoint: BatchCheckpoint,
+    ) -> Result<ProcessingResult, TransactionProcessingError> {
+        self.process_transactions(
+            transactions,
+            checkpoint.start_version,
+            checkpoint.end_version,
+        )
+        .await
+    }
+
     /// Gets a reference to the connection pool
     /// This is used by the `get_conn()` helper below
     fn connection_pool(&self) -> &PgDbPool;
@@ -44,24 +77,18 @@ pub trait TransactionProcessor: Send + Sync + Debug {
 
     /// Gets the connection.
     /// If it was unable to do so (default timeout: 30s), it will keep retrying until it can.
+    /// This blocks the executor while it waits, async code should use `get_conn_async`.
     fn get_conn(&self) -> PgPoolConnection {
-        let pool = self.connection_pool();
-        loop {
-            match pool.get() {
-                Ok(conn) => {
-                    GOT_CONNECTION.inc();
-                    return conn;
-                }
-                Err(err) => {
-                    UNABLE_TO_GET_CONNECTION.inc();
-                    aptos_logger::error!(
-                        "Could not get DB connection from pool, will retry in {:?}. Err: {:?}",
-                        pool.connection_timeout(),
-                        err
-                    );
-                }
-            };
-        }
+        checkout_conn(self.connection_pool(), self.name())
+    }
+
+    /// Gets the connection like `get_conn`, waiting for it on the blocking thread pool. Checkouts
+    /// don't count towards MAX_BLOCKING_DB_TASKS, or work holding a connection could be waiting
+    /// on checkouts waiting on its connection.
+    async fn get_conn_async(&self) -> PgPoolConnection {
+        let pool = self.connection_pool().clone();
+        let processor_name = self.name();
+        spawn_blocking_and_wait(move || checkout_conn(&pool, processor_name)).await
     }
 
     /// This is a helper method, tying together the other helper methods to allow tracking status in the DB
@@ -81,9 +108,23 @@ pub trait TransactionProcessor: Send + Sync + Debug {
         let end_version = txns.last().unwrap().version().unwrap();
 
         self.mark_versions_started(start_version, end_version);
+        let slow_checkouts = SLOW_CONNECTION_CHECKOUTS
+            .with_label_values(&[self.name()])
+            .get();
         let res = self
-            .process_transactions(txns, start_version, end_version)
-            .await;
+            .process_transactions_with_checkpoint(
+                txns,
+                BatchCheckpoint::new(self.name(), start_version, end_version),
+            )
+            .await
+            .map(|mut processing_result| {
+                // Any slow checkout while the batch ran, the pool is shared by every batch
+                processing_result.pool_saturated |= SLOW_CONNECTION_CHECKOUTS
+                    .with_label_values(&[self.name()])
+                    .get()
+                    > slow_checkouts;
+                processing_result
+            });
         // Handle block success/failure
         match res.as_ref() {
             Ok(processing_result) => self.update_status_success(processing_result),
@@ -110,7 +151,8 @@ pub trait TransactionProcessor: Send + Sync + Debug {
         self.apply_processor_status(&psms);
     }
 
-    /// Writes that a version has been completed successfully for this `TransactionProcessor` to the DB
+    /// Writes that a version has been completed successfully for this `TransactionProcessor` to the DB,
+    /// unless the processor already did along with the batch
     fn update_status_success(&self, processing_result: &ProcessingResult) {
         aptos_logger::debug!(
             "[{}] Marking processing version OK from versions {} to {}",
@@ -122,6 +164,9 @@ pub trait TransactionProcessor: Send + Sync + Debug {
         LATEST_PROCESSED_VERSION
             .with_label_values(&[self.name()])
             .set(processing_result.end_version as i64);
+        if processing_result.success_recorded {
+            return;
+        }
         let psms = ProcessorStatusModel::from_versions(
             self.name(),
             processing_result.start_version,
@@ -130,6 +175,10 @@ pub trait TransactionProcessor: Send + Sync + Debug {
             None,
         );
         self.apply_processor_status(&psms);
+        self.record_processed_range(
+            processing_result.start_version,
+            processing_result.end_version,
+        );
     }
 
     /// Writes that a version has errored for this `TransactionProcessor` to the DB
@@ -144,25 +193,188 @@ pub trait TransactionProcessor: Send + Sync + Debug {
         self.apply_processor_status(&psm);
     }
 
+    /// Merges a successful batch into the processed ranges used to find gaps
+    fn record_processed_range(&self, start_version: u64, end_version: u64) {
+        let mut conn = self.get_conn();
+        ProcessedVersionRange::record(
+            &mut conn,
+            self.name(),
+            start_version as i64,
+            end_version as i64,
+        )
+        .expect("Error updating processed version ranges!");
+    }
+
     /// Actually performs the write for a `ProcessorStatusModel` changeset
     fn apply_processor_status(&self, psms: &[ProcessorStatusModel]) {
         let mut conn = self.get_conn();
-        let chunks = get_chunks(psms.len(), ProcessorStatusModel::field_count());
-        for (start_ind, end_ind) in chunks {
-            execute_with_better_error(
-                &mut conn,
-                diesel::insert_into(processor_statuses::table)
-                    .values(&psms[start_ind..end_ind])
-                    .on_conflict((dsl::name, dsl::version))
-                    .do_update()
-                    .set((
-                        dsl::success.eq(excluded(dsl::success)),
-                        dsl::details.eq(excluded(dsl::details)),
-                        dsl::last_updated.eq(excluded(dsl::last_updated)),
-                    )),
-                None,
-            )
-            .expect("Error updating Processor Status!");
+        write_processor_statuses(&mut conn, psms).expect("Error updating Processor Status!");
+    }
+}
+
+fn write_processor_statuses(
+    conn: &mut PgConnection,
+    psms: &[ProcessorStatusModel],
+) -> QueryResult<()> {
+    let chunks = get_chunks(psms.len(), ProcessorStatusModel::field_count());
+    for (start_ind, end_ind) in chunks {
+        execute_with_better_error(
+            conn,
+            diesel::insert_into(processor_statuses::table)
+                .values(&psms[start_ind..end_ind])
+                .on_conflict((dsl::name, dsl::version))
+                .do_update()
+                .set((
+                    dsl::success.eq(excluded(dsl::success)),
+                    dsl::details.eq(excluded(dsl::details)),
+                    dsl::last_updated.eq(excluded(dsl::last_updated)),
+                )),
+            None,
+        )?;
+    }
+    Ok(())
+}
+
+/// The versions of a batch, recorded as processed by the database transaction that writes the
+/// batch's rows. Processed ranges are checked before the rows are written, so a batch that's
+/// retried or fetched again after it committed is skipped instead of being applied twice, which
+/// would double count upserts that add to a row, e.g. volumes.
+#[derive(Clone, Debug)]
+pub struct BatchCheckpoint {
+    processor_name: &'static str,
+    /// Set when the batch is written in shards, each committed separately
+    shard: Option<usize>,
+    pub start_version: u64,
+    pub end_version: u64,
+}
+
+impl BatchCheckpoint {
+    pub fn new(processor_name: &'static str, start_version: u64, end_version: u64) -> Self {
+        Self {
+            processor_name,
+            shard: None,
+            start_version,
+            end_version,
+        }
+    }
+
+    /// Checkpoint of one shard of the batch. Shards only record their processed ranges, the
+    /// processor's statuses are written once every shard has committed.
+    pub fn for_shard(&self, shard: usize) -> Self {
+        Self {
+            shard: Some(shard),
+            ..self.clone()
+        }
+    }
+
+    /// Name the processed ranges are recorded under
+    pub fn name(&self) -> String {
+        match self.shard {
+            Some(shard) => format!("{}_shard_{}", self.processor_name, shard),
+            None => self.processor_name.to_string(),
+        }
+    }
+
+    /// Whether the batch's versions were already committed
+    pub fn is_committed(&self, conn: &mut PgConnection) -> QueryResult<bool> {
+        ProcessedVersionRange::covers(
+            conn,
+            &self.name(),
+            self.start_version as i64,
+            self.end_version as i64,
+        )
+    }
+
+    /// Records the batch's versions as processed, on the batch's database transaction
+    pub fn commit(&self, conn: &mut PgConnection) -> QueryResult<()> {
+        if self.shard.is_none() {
+            write_processor_statuses(
+                conn,
+                &ProcessorStatusModel::from_versions(
+                    self.processor_name,
+                    self.start_version,
+                    self.end_version,
+                    true,
+                    None,
+                ),
+            )?;
         }
+        ProcessedVersionRange::record(
+            conn,
+            &self.name(),
+            self.start_version as i64,
+            self.end_version as i64,
+        )?;
+        Ok(())
+    }
+}
+
+/// Checks out a connection, recording how long that took and the pool's state
+fn checkout_conn(pool: &PgDbPool, processor_name: &'static str) -> PgPoolConnection {
+    let checkout_start = Instant::now();
+    CONNECTION_POOL_WAIT_COUNT
+        .with_label_values(&[processor_name])
+        .inc();
+    let conn = loop {
+        match pool.get() {
+            Ok(conn) => {
+                GOT_CONNECTION.inc();
+                break conn;
+            }
+            Err(err) => {
+                UNABLE_TO_GET_CONNECTION.inc();
+                aptos_logger::error!(
+                    "Could not get DB connection from pool, will retry in {:?}. Err: {:?}",
+                    pool.connection_timeout(),
+                    err
+                );
+            }
+        };
+    };
+    CONNECTION_POOL_WAIT_COUNT
+        .with_label_values(&[processor_name])
+        .dec();
+    let checkout_time = checkout_start.elapsed();
+    CONNECTION_CHECKOUT_SECONDS
+        .with_label_values(&[processor_name])
+        .observe(checkout_time.as_secs_f64());
+    if checkout_time > Duration::from_millis(SLOW_CHECKOUT_MILLIS) {
+        SLOW_CONNECTION_CHECKOUTS
+            .with_label_values(&[processor_name])
+            .inc();
+    }
+    let state = pool.state();
+    CONNECTION_POOL_CONNECTIONS
+        .with_label_values(&[processor_name])
+        .set(state.connections as i64);
+    CONNECTION_POOL_IDLE_CONNECTIONS
+        .with_label_values(&[processor_name])
+        .set(state.idle_connections as i64);
+    conn
+}
+
+/// Runs blocking work, e.g. a batch's diesel inserts, on tokio's blocking thread pool rather than
+/// on the executor's threads. At most MAX_BLOCKING_DB_TASKS run at once, the rest wait their turn.
+pub async fn run_blocking<F, T>(f: F) -> T
+where
+    F: FnOnce() -> T + Send + 'static,
+    T: Send + 'static,
+{
+    let _permit = BLOCKING_DB_TASKS
+        .acquire()
+        .await
+        .expect("The semaphore is never closed");
+    spawn_blocking_and_wait(f).await
+}
+
+/// Panics in `f` are resumed in the caller
+async fn spawn_blocking_and_wait<F, T>(f: F) -> T
+where
+    F: FnOnce() -> T + Send + 'static,
+    T: Send + 'static,
+{
+    match tokio::task::spawn_blocking(f).await {
+        Ok(res) => res,
+        Err(err) => std::panic::resume_unwind(err.into_panic()),
     }
 }
@@ -0,0 +1,210 @@
+// Copyright (c) Aptos
+// SPDX-License-Identifier: Apache-2.0
+
+//! Reading transactions from a Firehose stream instead of fetching them. A node with
+//! `firehose_stream.enabled` prints each block as `FIRE BLOCK_START <height>`, one
+//! `FIRE TRX <base64 protobuf>` line per transaction, then `FIRE BLOCK_END <height>`. A block
+//! that fails validation on the node is started again without being ended, so only ended blocks
+//! are returned.
+
+use crate::indexer::proto_convert::convert_transaction;
+use anyhow::{ensure, Context as AnyhowContext};
+use aptos_api_types::Transaction;
+use aptos_config::config::{TransactionStreamConfig, UpstreamNodesConfig};
+use aptos_protos::extractor::v1 as extractor;
+use prost::Message;
+use tokio::{
+    io::{AsyncBufReadExt, AsyncRead, BufReader, Lines},
+    net::TcpStream,
+};
+
+#[derive(Clone, Debug)]
+pub struct TransactionStream {
+    address: String,
+}
+
+impl TransactionStream {
+    pub fn from_config(
+        config: Option<&TransactionStreamConfig>,
+        upstream_nodes: Option<&UpstreamNodesConfig>,
+    ) -> anyhow::Result<Option<Self>> {
+        let config = match config {
+            Some(config) => config,
+            None => return Ok(None),
+        };
+        ensure!(
+            upstream_nodes.is_none(),
+            "Can't be combined with upstream_nodes"
+        );
+        let port = config
+            .address
+            .rsplit_once(':')
+            .map(|(_, port)| port.parse::<u16>());
+        ensure!(
+            matches!(port, Some(Ok(_))),
+            "address must be \"host:port\", got {:?}",
+            config.address
+        );
+        Ok(Some(Self {
+            address: config.address.clone(),
+        }))
+    }
+
+    pub async fn connect(&self) -> anyhow::Result<FirehoseReader<TcpStream>> {
+        let stream = TcpStream::connect(&self.address)
+            .await
+            .with_context(|| format!("Failed to connect to {}", self.address))?;
+        Ok(FirehoseReader::new(stream))
+    }
+}
+
+pub struct FirehoseReader<T> {
+    lines: Lines<BufReader<T>>,
+    /// Transactions of the block being read, None until a block starts
+    block: Option<Vec<Transaction>>,
+}
+
+impl<T: AsyncRead + Unpin> FirehoseReader<T> {
+    pub fn new(reader: T) -> Self {
+        Self {
+            lines: BufReader::new(reader).lines(),
+            block: None,
+        }
+    }
+
+    /// Returns the transactions of the next ended block, or None once the stream ends
+    pub async fn next_block(&mut self) -> anyhow::Result<Option<Vec<Transaction>>> {
+        while let Some(line) = self.lines.next_line().await? {
+            let mut parts = line.trim().splitn(3, ' ');
+            if parts.next() != Some("FIRE") {
+                // The node's own output
+                continue;
+            }
+            match (parts.next(), parts.next()) {
+                (Some("BLOCK_START"), _) => self.block = Some(vec![]),
+                (Some("TRX"), Some(encoded)) => {
+                    // Connected partway through a block, it's skipped
+                    if let Some(block) = &mut self.block {
+                        block.push(decode_transaction(encoded)?);
+                    }
+                }
+                (Some("BLOCK_END"), _) => {
+                    if let Some(block) = self.block.take() {
+                        return Ok(Some(block));
+                    }
+                }
+                _ => {}
+            }
+        }
+        Ok(None)
+    }
+
+    /// Whether everything the stream has sent so far has been read
+    pub fn is_caught_up(&self) -> bool {
+        self.lines.get_ref().buffer().is_empty()
+    }
+}
+
+fn decode_transaction(encoded: &str) -> anyhow::Result<Transaction> {
+    let bytes = base64::decode(encoded).context("Invalid base64 transaction")?;
+    let transaction =
+        extractor::Transaction::decode(bytes.as_slice()).context("Invalid protobuf transaction")?;
+    convert_transaction(&transaction)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use aptos_protos::util::timestamp::Timestamp;
+
+    fn encoded_transaction(version: u64) -> String {
+        let transaction = extractor::Transaction {
+            timestamp: Some(Timestamp {
+                seconds: 1,
+                nanos: 500_000_000,
+            }),
+            version,
+            info: Some(extractor::TransactionInfo {
+                hash: vec![1; 32],
+                state_change_hash: vec![2; 32],
+                event_root_hash: vec![3; 32],
+                state_checkpoint_hash: Some(vec![4; 32]),
+                gas_used: 0,
+                success: true,
+                vm_status: "Executed successfully".to_string(),
+                accumulator_root_hash: vec![5; 32],
+                changes: vec![],
+            }),
+            epoch: 2,
+            block_height: 7,
+            r#type: extractor::transaction::TransactionType::StateCheckpoint as i32,
+            txn_data: Some(extractor::transaction::TxnData::StateCheckpoint(
+                extractor::StateCheckpointTransaction {},
+            )),
+        };
+        base64::encode(transaction.encode_to_vec())
+    }
+
+    fn versions(block: Vec<Transaction>) -> Vec<u64> {
+        block.iter().map(|txn| txn.version().unwrap()).collect()
+    }
+
+    #[tokio::test]
+    async fn test_only_ended_blocks_are_read() {
+        let stream = format!(
+            "\nFIRE TRX {}\nFIRE BLOCK_END 6\n\
+             2022-11-10T00:00:00Z [fh-stream] some log line\n\
+             \nFIRE BLOCK_START 7\nFIRE TRX {}\n\
+             \nFIRE BLOCK_START 7\nFIRE TRX {}\nFIRE TRX {}\nFIRE BLOCK_END 7\n\
+             \nFIRE BLOCK_START 8\nFIRE TRX {}\n",
+            encoded_transaction(9),
+            encoded_transaction(10),
+            encoded_transaction(10),
+            encoded_transaction(11),
+            encoded_transaction(12),
+        );
+        let mut reader = FirehoseReader::new(stream.as_bytes());
+        let block = reader.next_block().await.unwrap().unwrap();
+        assert_eq!(versions(block.clone()), vec![10, 11]);
+        match &block[0] {
+            Transaction::StateCheckpointTransaction(txn) => {
+                assert_eq!(txn.timestamp.0, 1_500_000);
+                assert_eq!(txn.info.block_height.unwrap().0, 7);
+                assert_eq!(txn.info.epoch.unwrap().0, 2);
+            }
+            txn => panic!("Expected a state checkpoint, got {:?}", txn),
+        }
+        // Block 8 never ends
+        assert!(reader.next_block().await.unwrap().is_none());
+    }
+
+    #[tokio::test]
+    async fn test_invalid_transactions_fail() {
+        let stream = "FIRE BLOCK_START 1\nFIRE TRX not-base64\nFIRE BLOCK_END 1\n";
+        assert!(FirehoseReader::new(stream.as_bytes())
+            .next_block()
+            .await
+            .is_err());
+    }
+
+    #[test]
+    fn test_from_config() {
+        let config = |address: &str| TransactionStreamConfig {
+            address: address.to_string(),
+        };
+        assert!(TransactionStream::from_config(None, None)
+            .unwrap()
+            .is_none());
+        assert!(
+            TransactionStream::from_config(Some(&config("localhost:9000")), None)
+                .unwrap()
+                .is_some()
+        );
+        assert!(TransactionStream::from_config(Some(&config("localhost")), None).is_err());
+        assert!(TransactionStream::from_config(
+            Some(&config("localhost:9000")),
+            Some(&UpstreamNodesConfig::default())
+        )
+        .is_err());
+    }
+}
@@ -0,0 +1,177 @@
+// Copyright (c) Aptos
+// SPDX-License-Identifier: Apache-2.0
+
+use aptos_api_types::Transaction;
+use serde::Serialize;
+use serde_json::{Map, Value};
+use std::collections::HashSet;
+
+/// One node of a transaction trace. Spans are recorded in the order the processor produces them.
+#[derive(Clone, Debug, PartialEq, Serialize)]
+pub struct TraceSpan {
+    pub name: String,
+    pub attributes: Map<String, Value>,
+    pub children: Vec<TraceSpan>,
+}
+
+impl TraceSpan {
+    pub fn new(name: &str) -> Self {
+        Self {
+            name: name.to_string(),
+            attributes: Map::new(),
+            children: vec![],
+        }
+    }
+
+    pub fn attribute<T: Serialize>(&mut self, key: &str, value: T) -> &mut Self {
+        self.attributes.insert(
+            key.to_string(),
+            serde_json::to_value(value).unwrap_or(Value::Null),
+        );
+        self
+    }
+
+    /// Appends a new child span and returns it
+    pub fn child(&mut self, name: &str) -> &mut TraceSpan {
+        self.children.push(TraceSpan::new(name));
+        self.children.last_mut().unwrap()
+    }
+
+    /// First child span with the given name, creating it if it doesn't exist yet
+    pub fn child_once(&mut self, name: &str) -> &mut TraceSpan {
+        match self.children.iter().position(|span| span.name == name) {
+            Some(index) => &mut self.children[index],
+            None => self.child(name),
+        }
+    }
+}
+
+/// Decides which transactions get a detailed trace, from the `trace_versions` config. Tracing is
+/// meant for debugging a handful of transactions, so untraced transactions cost a set lookup.
+#[derive(Debug, Default)]
+pub struct TransactionTracer {
+    trace_versions: HashSet<u64>,
+}
+
+impl TransactionTracer {
+    pub fn new(trace_versions: &[u64]) -> Self {
+        Self {
+            trace_versions: trace_versions.iter().copied().collect(),
+        }
+    }
+
+    /// Root span for the transaction if its version should be traced
+    pub fn start(&self, processor_name: &str, transaction: &Transaction) -> Option<TraceSpan> {
+        let version = transaction.version()?;
+        if !self.trace_versions.contains(&version) {
+            return None;
+        }
+        let mut root = TraceSpan::new(processor_name);
+        root.attribute("transaction_version", version)
+            .attribute("transaction_type", transaction.type_str());
+        Some(root)
+    }
+
+    /// Logs the span tree as structured json
+    pub fn finish(root: TraceSpan) -> TraceSpan {
+        aptos_logger::info!(
+            processor_name = root.name,
+            trace = root,
+            "Transaction trace"
+        );
+        root
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::models::token_models::{
+        marketplace_event_mappings::MarketplaceEventMappings, token_activities::TokenActivity,
+    };
+    use serde_json::json;
+
+    const HASH: &str = "0x6a527d06063dfd42c6b3a862574d5f3ec1660afb8058135edda5072712bfdb51";
+
+    fn mint_txn(version: u64) -> Transaction {
+        serde_json::from_value(json!({
+            "type": "user_transaction",
+            "version": version.to_string(),
+            "hash": HASH,
+            "state_change_hash": HASH,
+            "event_root_hash": HASH,
+            "state_checkpoint_hash": null,
+            "gas_used": "100",
+            "success": true,
+            "vm_status": "Executed successfully",
+            "accumulator_root_hash": HASH,
+            "changes": [],
+            "sender": "0xc4e7",
+            "sequence_number": "0",
+            "max_gas_amount": "2000",
+            "gas_unit_price": "100",
+            "expiration_timestamp_secs": "1668000600",
+            "payload": {
+                "type": "entry_function_payload",
+                "function": "0x3::token::create_token_script",
+                "type_arguments": [],
+                "arguments": []
+            },
+            "events": [{
+                "guid": {"creation_number": "6", "account_address": "0xc4e7"},
+                "sequence_number": "0",
+                "type": "0x3::token::MintTokenEvent",
+                "data": {
+                    "amount": "1",
+                    "id": {"creator": "0xc4e7", "collection": "Potions", "name": "Potion"}
+                }
+            }, {
+                "guid": {"creation_number": "2", "account_address": "0x1"},
+                "sequence_number": "0",
+                "type": "0x1::unknown::Event",
+                "data": {}
+            }],
+            "timestamp": "1668000000000000"
+        }))
+        .unwrap()
+    }
+
+    #[test]
+    fn test_only_flagged_versions_are_traced() {
+        let tracer = TransactionTracer::new(&[7]);
+        assert!(tracer.start("token_processor", &mint_txn(6)).is_none());
+
+        let mut root = tracer.start("token_processor", &mint_txn(7)).unwrap();
+        assert_eq!(root.attributes["transaction_version"], json!(7));
+        root.child_once("rows").attribute("tokens", 1);
+        root.child_once("rows").attribute("token_activities", 1);
+        assert_eq!(root.children.len(), 1);
+        assert_eq!(
+            serde_json::to_value(&root.children[0]).unwrap(),
+            json!({
+                "name": "rows",
+                "attributes": {"tokens": 1, "token_activities": 1},
+                "children": []
+            })
+        );
+    }
+
+    #[test]
+    fn test_event_parse_results_are_traced() {
+        let txn = mint_txn(7);
+        let mut root = TransactionTracer::new(&[7])
+            .start("token_processor", &txn)
+            .unwrap();
+        TokenActivity::trace_events(
+            root.child("events"),
+            &txn,
+            &MarketplaceEventMappings::default(),
+        );
+        let root = TransactionTracer::finish(root);
+        let events = &root.children[0].children;
+        assert_eq!(events.len(), 2);
+        assert_eq!(events[0].attributes["parser"], json!("MintTokenEvent"));
+        assert_eq!(events[0].attributes["is_sale"], json!(false));
+        assert_eq!(events[1].attributes["parser"], json!(null));
+    }
+}
@@ -0,0 +1,474 @@
+// Copyright (c) Aptos
+// SPDX-License-Identifier: Apache-2.0
+
+//! Fetching from the REST APIs of upstream fullnodes instead of the local node's storage. One
+//! node is active at a time. It's failed over from when its requests keep failing, or when its
+//! ledger version stops advancing, to the node that's furthest ahead. Transactions are always
+//! requested by explicit versions, so failing over never skips or repeats any.
+
+use crate::{
+    counters::{UPSTREAM_NODE_ACTIVE, UPSTREAM_NODE_FAILOVERS, UPSTREAM_NODE_LEDGER_VERSION},
+    indexer::fetcher::set_block_info,
+};
+use anyhow::{bail, ensure, Context as AnyhowContext};
+use aptos_api_types::{Transaction, U64};
+use aptos_config::config::UpstreamNodesConfig;
+use aptos_logger::{info, warn};
+use aptos_rest_client::Client;
+use std::{
+    sync::{
+        atomic::{AtomicBool, AtomicUsize, Ordering},
+        Arc, Mutex,
+    },
+    time::{Duration, Instant},
+};
+use url::Url;
+
+pub const DEFAULT_MAX_ERRORS: u64 = 5;
+pub const DEFAULT_STALL_TIMEOUT_MS: u64 = 30_000;
+pub const DEFAULT_HEALTH_CHECK_INTERVAL_MS: u64 = 5_000;
+pub const DEFAULT_MAX_LAG_VERSIONS: u64 = 1_000;
+pub const DEFAULT_WEIGHT: u64 = 1;
+
+#[derive(Debug)]
+struct NodeHealth {
+    ledger_version: Option<u64>,
+    /// When the ledger version last went up
+    last_advanced: Instant,
+    consecutive_errors: u64,
+}
+
+#[derive(Debug)]
+struct UpstreamNode {
+    url: String,
+    weight: u64,
+    client: Client,
+    health: Mutex<NodeHealth>,
+}
+
+/// What failing over looks at for each node
+#[derive(Clone, Debug, PartialEq, Eq)]
+struct NodeStatus {
+    weight: u64,
+    ledger_version: Option<u64>,
+    healthy: bool,
+}
+
+#[derive(Debug)]
+pub struct UpstreamNodes {
+    nodes: Vec<UpstreamNode>,
+    active: AtomicUsize,
+    /// Every processor's fetcher shares the nodes, only the first one runs the health checks
+    health_checks_started: AtomicBool,
+    max_errors: u64,
+    stall_timeout: Duration,
+    health_check_interval: Duration,
+    max_lag_versions: u64,
+}
+
+impl UpstreamNodes {
+    pub fn from_config(config: Option<&UpstreamNodesConfig>) -> anyhow::Result<Option<Self>> {
+        let config = match config {
+            Some(config) => config,
+            None => return Ok(None),
+        };
+        ensure!(!config.nodes.is_empty(), "nodes must not be empty");
+        let max_errors = config.max_errors.unwrap_or(DEFAULT_MAX_ERRORS);
+        ensure!(max_errors > 0, "max_errors must be greater than 0");
+        let health_check_interval_ms = config
+            .health_check_interval_ms
+            .unwrap_or(DEFAULT_HEALTH_CHECK_INTERVAL_MS);
+        ensure!(
+            health_check_interval_ms > 0,
+            "health_check_interval_ms must be greater than 0"
+        );
+        let mut nodes = vec![];
+        for node in &config.nodes {
+            let url =
+                Url::parse(&node.url).with_context(|| format!("Invalid node url {}", node.url))?;
+            let weight = node.weight.unwrap_or(DEFAULT_WEIGHT);
+            ensure!(weight > 0, "weight of {} must be greater than 0", node.url);
+            nodes.push(UpstreamNode {
+                url: node.url.clone(),
+                weight,
+                client: Client::new(url),
+                health: Mutex::new(NodeHealth {
+                    ledger_version: None,
+                    last_advanced: Instant::now(),
+                    consecutive_errors: 0,
+                }),
+            });
+        }
+        Ok(Some(Self {
+            nodes,
+            active: AtomicUsize::new(0),
+            health_checks_started: AtomicBool::new(false),
+            max_errors,
+            stall_timeout: Duration::from_millis(
+                config.stall_timeout_ms.unwrap_or(DEFAULT_STALL_TIMEOUT_MS),
+            ),
+            health_check_interval: Duration::from_millis(health_check_interval_ms),
+            max_lag_versions: config.max_lag_versions.unwrap_or(DEFAULT_MAX_LAG_VERSIONS),
+        }))
+    }
+
+    pub fn active_url(&self) -> &str {
+        &self.nodes[self.active.load(Ordering::Relaxed)].url
+    }
+
+    /// Returns the chain id and ledger version of the active node
+    pub async fn ledger_info(&self) -> anyhow::Result<(u8, u64)> {
+        let index = self.active.load(Ordering::Relaxed);
+        let node = &self.nodes[index];
+        match node.client.get_ledger_information().await {
+            Ok(response) => {
+                let state = response.into_inner();
+                self.record_ledger_version(index, state.version);
+                Ok((state.chain_id, state.version))
+            }
+            Err(err) => {
+                self.record_error(index);
+                Err(err).with_context(|| format!("Failed to get ledger info from {}", node.url))
+            }
+        }
+    }
+
+    /// Fetches up to `limit` transactions from the active node, starting at `start_version`.
+    /// Errors instead of returning a page that doesn't start at `start_version`, so that no
+    /// versions are skipped.
+    pub async fn get_transactions(
+        &self,
+        start_version: u64,
+        limit: u16,
+    ) -> anyhow::Result<Vec<Transaction>> {
+        let index = self.active.load(Ordering::Relaxed);
+        let node = &self.nodes[index];
+        let result = Self::fetch_transactions(&node.client, start_version, limit)
+            .await
+            .with_context(|| {
+                format!(
+                    "Failed to get transactions at version {} from {}",
+                    start_version, node.url
+                )
+            });
+        match &result {
+            Ok(_) => node.health.lock().unwrap().consecutive_errors = 0,
+            Err(_) => self.record_error(index),
+        }
+        result
+    }
+
+    async fn fetch_transactions(
+        client: &Client,
+        start_version: u64,
+        limit: u16,
+    ) -> anyhow::Result<Vec<Transaction>> {
+        let mut transactions = client
+            .get_transactions(Some(start_version), Some(limit))
+            .await?
+            .into_inner();
+        match transactions.first() {
+            Some(first) if first.version() == Some(start_version) => {}
+            Some(first) => bail!(
+                "Expected transactions starting at version {}, got version {:?}",
+                start_version,
+                first.version()
+            ),
+            None => bail!("No transactions at version {}", start_version),
+        }
+
+        // The REST API leaves out the block height and epoch, so they're looked up from the
+        // block the first transaction is in, and carried forward like the local fetcher does
+        let block = client
+            .get_block_by_version(start_version, false)
+            .await?
+            .into_inner();
+        let mut block_height = block.block_height.0;
+        let mut epoch = match &transactions[0] {
+            Transaction::BlockMetadataTransaction(bmt) => bmt.epoch.0,
+            _ => match client
+                .get_transaction_by_version(block.first_version.0)
+                .await?
+                .into_inner()
+            {
+                Transaction::BlockMetadataTransaction(bmt) => bmt.epoch.0,
+                Transaction::GenesisTransaction(_) => 0,
+                txn => bail!(
+                    "Block at height {} doesn't start with block metadata, got {:?}",
+                    block_height,
+                    txn.type_str()
+                ),
+            },
+        };
+        for (i, txn) in transactions.iter_mut().enumerate() {
+            if let Transaction::BlockMetadataTransaction(bmt) = txn {
+                if i > 0 {
+                    block_height += 1;
+                }
+                epoch = bmt.epoch.0;
+            }
+            set_block_info(txn, U64::from(block_height), U64::from(epoch));
+        }
+        Ok(transactions)
+    }
+
+    /// Checks the ledger info of every node forever, unless that's already running
+    pub async fn run_health_checks(self: Arc<Self>) {
+        if self.health_checks_started.swap(true, Ordering::Relaxed) {
+            return;
+        }
+        UPSTREAM_NODE_ACTIVE
+            .with_label_values(&[self.active_url()])
+            .set(1);
+        loop {
+            tokio::time::sleep(self.health_check_interval).await;
+            self.check_health().await;
+        }
+    }
+
+    pub async fn check_health(&self) {
+        for (index, node) in self.nodes.iter().enumerate() {
+            match node.client.get_ledger_information().await {
+                Ok(response) => {
+                    let version = response.into_inner().version;
+                    self.record_ledger_version(index, version);
+                    node.health.lock().unwrap().consecutive_errors = 0;
+                }
+                Err(err) => {
+                    warn!(
+                        url = node.url,
+                        error = format!("{:?}", err),
+                        "Health check of upstream node failed"
+                    );
+                    self.record_error(index);
+                }
+            }
+        }
+
+        let active = self.active.load(Ordering::Relaxed);
+        let statuses = self.statuses();
+        if !statuses[active].healthy {
+            self.fail_over(active, "unhealthy");
+        } else if let Some(highest) = statuses.iter().filter_map(|s| s.ledger_version).max() {
+            let active_version = statuses[active].ledger_version.unwrap_or_default();
+            if active_version + self.max_lag_versions < highest {
+                self.fail_over(active, "lagging");
+            }
+        }
+    }
+
+    fn statuses(&self) -> Vec<NodeStatus> {
+        self.nodes
+            .iter()
+            .map(|node| {
+                let health = node.health.lock().unwrap();
+                NodeStatus {
+                    weight: node.weight,
+                    ledger_version: health.ledger_version,
+                    healthy: health.consecutive_errors < self.max_errors
+                        && health.last_advanced.elapsed() < self.stall_timeout,
+                }
+            })
+            .collect()
+    }
+
+    fn record_ledger_version(&self, index: usize, version: u64) {
+        let node = &self.nodes[index];
+        let mut health = node.health.lock().unwrap();
+        if health
+            .ledger_version
+            .map_or(true, |previous| version > previous)
+        {
+            health.ledger_version = Some(version);
+            health.last_advanced = Instant::now();
+        }
+        UPSTREAM_NODE_LEDGER_VERSION
+            .with_label_values(&[&node.url])
+            .set(version as i64);
+    }
+
+    fn record_error(&self, index: usize) {
+        let consecutive_errors = {
+            let mut health = self.nodes[index].health.lock().unwrap();
+            health.consecutive_errors += 1;
+            health.consecutive_errors
+        };
+        if consecutive_errors >= self.max_errors && index == self.active.load(Ordering::Relaxed) {
+            self.fail_over(index, "erroring");
+        }
+    }
+
+    fn fail_over(&self, from: usize, reason: &str) {
+        let to = match pick_node(&self.statuses(), from, self.max_lag_versions) {
+            Some(to) => to,
+            None => {
+                warn!(
+                    url = self.nodes[from].url,
+                    reason = reason,
+                    "Active upstream node is unhealthy, but there's no other node to fail over to"
+                );
+                return;
+            }
+        };
+        // Another task may have failed over already
+        if self
+            .active
+            .compare_exchange(from, to, Ordering::Relaxed, Ordering::Relaxed)
+            .is_err()
+        {
+            return;
+        }
+        UPSTREAM_NODE_FAILOVERS.inc();
+        UPSTREAM_NODE_ACTIVE
+            .with_label_values(&[&self.nodes[from].url])
+            .set(0);
+        UPSTREAM_NODE_ACTIVE
+            .with_label_values(&[&self.nodes[to].url])
+            .set(1);
+        warn!(
+            from = self.nodes[from].url,
+            to = self.nodes[to].url,
+            reason = reason,
+            "Failed over to another upstream node"
+        );
+        // Gives the new node a full stall timeout to show it's advancing
+        let mut health = self.nodes[to].health.lock().unwrap();
+        health.consecutive_errors = 0;
+        health.last_advanced = Instant::now();
+        info!(url = self.nodes[to].url, "Fetching from upstream node");
+    }
+}
+
+/// Picks the node to fail over to from `current`: the healthy one with the highest weight among
+/// those within `max_lag_versions` of the highest ledger version, the one furthest ahead on ties.
+/// Falls back to any healthy node, and returns None if there's no other healthy node.
+fn pick_node(statuses: &[NodeStatus], current: usize, max_lag_versions: u64) -> Option<usize> {
+    let candidates = statuses
+        .iter()
+        .enumerate()
+        .filter(|(index, status)| *index != current && status.healthy)
+        .collect::<Vec<_>>();
+    let highest = candidates
+        .iter()
+        .filter_map(|(_, status)| status.ledger_version)
+        .max();
+    let caught_up = candidates
+        .iter()
+        .filter(|(_, status)| match (status.ledger_version, highest) {
+            (Some(version), Some(highest)) => version + max_lag_versions >= highest,
+            _ => false,
+        })
+        .max_by_key(|(_, status)| (status.weight, status.ledger_version))
+        .map(|(index, _)| *index);
+    caught_up.or_else(|| {
+        candidates
+            .iter()
+            .max_by_key(|(_, status)| (status.ledger_version, status.weight))
+            .map(|(index, _)| *index)
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use aptos_config::config::UpstreamNodeConfig;
+
+    fn status(weight: u64, ledger_version: Option<u64>, healthy: bool) -> NodeStatus {
+        NodeStatus {
+            weight,
+            ledger_version,
+            healthy,
+        }
+    }
+
+    #[test]
+    fn test_pick_node() {
+        let statuses = vec![
+            status(1, Some(1_000_000), false),
+            status(1, Some(999_500), true),
+            status(5, Some(998_000), true),
+            status(1, Some(999_900), true),
+        ];
+        // The heavier node is too far behind, so the one furthest ahead wins the tie
+        assert_eq!(pick_node(&statuses, 0, 1000), Some(3));
+        // Within the lag, weight wins
+        assert_eq!(pick_node(&statuses, 0, 5000), Some(2));
+        // Never the current node
+        assert_eq!(pick_node(&statuses, 3, 1000), Some(1));
+    }
+
+    #[test]
+    fn test_pick_node_without_healthy_nodes() {
+        let statuses = vec![
+            status(1, Some(100), true),
+            status(10, Some(200), false),
+            status(1, None, true),
+        ];
+        // A node that hasn't answered a health check yet is only picked as a last resort
+        assert_eq!(pick_node(&statuses, 0, 1000), Some(2));
+        assert_eq!(pick_node(&statuses[..2], 0, 1000), None);
+    }
+
+    #[test]
+    fn test_from_config() {
+        assert!(UpstreamNodes::from_config(None).unwrap().is_none());
+        let node = |url: &str, weight| UpstreamNodeConfig {
+            url: url.to_string(),
+            weight,
+        };
+        assert!(
+            UpstreamNodes::from_config(Some(&UpstreamNodesConfig::default())).is_err(),
+            "Needs at least one node"
+        );
+        assert!(UpstreamNodes::from_config(Some(&UpstreamNodesConfig {
+            nodes: vec![node("not a url", None)],
+            ..UpstreamNodesConfig::default()
+        }))
+        .is_err());
+        assert!(UpstreamNodes::from_config(Some(&UpstreamNodesConfig {
+            nodes: vec![node("http://localhost:8080", Some(0))],
+            ..UpstreamNodesConfig::default()
+        }))
+        .is_err());
+        let upstream_nodes = UpstreamNodes::from_config(Some(&UpstreamNodesConfig {
+            nodes: vec![
+                node("http://localhost:8080", None),
+                node("http://localhost:8081", Some(3)),
+            ],
+            ..UpstreamNodesConfig::default()
+        }))
+        .unwrap()
+        .unwrap();
+        assert_eq!(upstream_nodes.active_url(), "http://localhost:8080");
+        assert_eq!(upstream_nodes.nodes[1].weight, 3);
+    }
+
+    #[test]
+    fn test_fails_over_after_max_errors() {
+        let upstream_nodes = UpstreamNodes::from_config(Some(&UpstreamNodesConfig {
+            nodes: vec![
+                UpstreamNodeConfig {
+                    url: "http://localhost:8080".to_string(),
+                    weight: None,
+                },
+                UpstreamNodeConfig {
+                    url: "http://localhost:8081".to_string(),
+                    weight: None,
+                },
+            ],
+            max_errors: Some(2),
+            ..UpstreamNodesConfig::default()
+        }))
+        .unwrap()
+        .unwrap();
+        upstream_nodes.record_ledger_version(1, 100);
+        upstream_nodes.record_error(0);
+        assert_eq!(upstream_nodes.active_url(), "http://localhost:8080");
+        upstream_nodes.record_error(0);
+        assert_eq!(upstream_nodes.active_url(), "http://localhost:8081");
+        // Nowhere healthy to go back to, so it stays
+        upstream_nodes.record_error(1);
+        upstream_nodes.record_error(1);
+        assert_eq!(upstream_nodes.active_url(), "http://localhost:8081");
+    }
+}
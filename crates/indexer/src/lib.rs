@@ -11,22 +11,38 @@ extern crate diesel_migrations;
 #[macro_use]
 extern crate diesel;
 
+pub mod cli;
 pub mod counters;
 pub mod database;
+pub mod hash_scheme;
+#[cfg(feature = "http-api")]
+pub mod http_api;
 pub mod indexer;
+pub mod live_feed;
+#[cfg(feature = "metadata-fetcher")]
+pub mod metadata_fetcher;
+pub mod migrations;
 pub mod models;
+pub mod parquet_sink;
 pub mod processors;
+pub mod queries;
 pub mod runtime;
 pub mod schema;
+#[cfg(test)]
+mod test_utils;
+pub mod token_stream;
 mod util;
 
 /// By default, skips test unless `INDEXER_DATABASE_URL` is set.
 /// In CI, will explode if `INDEXER_DATABASE_URL` is NOT set.
+/// Tests that don't skip hold the shared database until they finish, see `test_utils::lock_database`.
 pub fn should_skip_pg_tests() -> bool {
     if std::env::var("CIRCLECI").is_ok() {
         std::env::var("INDEXER_DATABASE_URL").expect("must set 'INDEXER_DATABASE_URL' in CI!");
     }
     if std::env::var("INDEXER_DATABASE_URL").is_ok() {
+        #[cfg(test)]
+        test_utils::lock_database();
         false
     } else {
         aptos_logger::warn!("`INDEXER_DATABASE_URL` is not set: skipping indexer tests");
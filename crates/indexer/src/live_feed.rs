@@ -0,0 +1,208 @@
+// Copyright (c) Aptos
+// SPDX-License-Identifier: Apache-2.0
+
+//! Sales, listings and wallet activities of the batches the token processor commits, published
+//! to the HTTP API's websocket subscribers as they're committed. Subscribers listen to channels:
+//! `sales:<collection_data_id_hash>`, `listings:<collection_data_id_hash>` and
+//! `wallet:<address>`.
+//!
+//! Every subscriber has its own buffer of buffer_size messages. One that falls behind loses the
+//! oldest messages of its buffer and is told how many it missed, so a slow client never holds up
+//! the processor or the other clients. Messages carry their transaction_version to catch up on
+//! what was missed through the HTTP API.
+
+use crate::{
+    models::token_models::{
+        marketplace_listings::CurrentMarketplaceListing, nft_sales::NftSale,
+        token_activities::TokenActivity,
+    },
+    util::standardize_address,
+};
+use serde::Serialize;
+use std::sync::Arc;
+use tokio::sync::broadcast;
+
+pub const DEFAULT_BUFFER_SIZE: u64 = 1024;
+
+pub const SALES_CHANNEL: &str = "sales";
+pub const LISTINGS_CHANNEL: &str = "listings";
+pub const WALLET_CHANNEL: &str = "wallet";
+
+#[derive(Clone, Debug, PartialEq, Serialize)]
+pub struct LiveMessage {
+    pub channel: String,
+    /// "sale", "listing" or "activity"
+    #[serde(rename = "type")]
+    pub message_type: &'static str,
+    pub transaction_version: i64,
+    /// The row as it was written, serialized like the HTTP API returns it
+    pub data: serde_json::Value,
+}
+
+/// Parses a channel a client asked for. Wallet addresses are padded like the indexed ones, so
+/// returns the channel as it's published.
+pub fn parse_channel(channel: &str) -> Option<String> {
+    let (name, key) = channel.split_once(':')?;
+    if key.is_empty() {
+        return None;
+    }
+    match name {
+        SALES_CHANNEL | LISTINGS_CHANNEL => Some(channel.to_string()),
+        WALLET_CHANNEL => Some(format!("{}:{}", WALLET_CHANNEL, standardize_address(key))),
+        _ => None,
+    }
+}
+
+#[derive(Clone, Debug)]
+pub struct LiveFeed {
+    sender: broadcast::Sender<Arc<LiveMessage>>,
+}
+
+impl LiveFeed {
+    pub fn new(buffer_size: usize) -> Self {
+        let (sender, _) = broadcast::channel(buffer_size);
+        Self { sender }
+    }
+
+    pub fn subscribe(&self) -> broadcast::Receiver<Arc<LiveMessage>> {
+        self.sender.subscribe()
+    }
+
+    /// Messages are only worth building while someone is connected
+    pub fn has_subscribers(&self) -> bool {
+        self.sender.receiver_count() > 0
+    }
+
+    /// Messages for the rows of a batch, built before they're handed to the shards that write
+    /// them. Activities go to the wallets on both sides of them.
+    pub fn batch_messages(
+        nft_sales: &[NftSale],
+        listings: &[CurrentMarketplaceListing],
+        activities: &[TokenActivity],
+    ) -> Vec<LiveMessage> {
+        let mut messages = vec![];
+        for sale in nft_sales {
+            messages.push(LiveMessage {
+                channel: format!("{}:{}", SALES_CHANNEL, sale.collection_data_id_hash),
+                message_type: "sale",
+                transaction_version: sale.transaction_version,
+                data: serde_json::to_value(sale).expect("Rows serialize to json"),
+            });
+        }
+        for listing in listings {
+            messages.push(LiveMessage {
+                channel: format!("{}:{}", LISTINGS_CHANNEL, listing.collection_data_id_hash),
+                message_type: "listing",
+                transaction_version: listing.last_transaction_version,
+                data: serde_json::to_value(listing).expect("Rows serialize to json"),
+            });
+        }
+        for activity in activities {
+            let mut addresses = activity
+                .from_address
+                .iter()
+                .chain(activity.to_address.iter())
+                .collect::<Vec<_>>();
+            addresses.dedup();
+            for address in addresses {
+                messages.push(LiveMessage {
+                    channel: format!("{}:{}", WALLET_CHANNEL, address),
+                    message_type: "activity",
+                    transaction_version: activity.transaction_version,
+                    data: serde_json::to_value(activity).expect("Rows serialize to json"),
+                });
+            }
+        }
+        messages
+    }
+
+    /// Called once the batch is committed. Sending only fails when nobody is subscribed.
+    pub fn publish(&self, messages: Vec<LiveMessage>) {
+        for message in messages {
+            let _ = self.sender.send(Arc::new(message));
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use bigdecimal::BigDecimal;
+
+    fn activity(from_address: Option<&str>, to_address: Option<&str>) -> TokenActivity {
+        TokenActivity {
+            transaction_version: 7,
+            event_account_address: "0x1".to_string(),
+            event_creation_number: 0,
+            event_sequence_number: 0,
+            event_index: 0,
+            token_index: 0,
+            token_data_id_hash: "potion".to_string(),
+            property_version: BigDecimal::from(0),
+            creator_address: "0x1".to_string(),
+            collection_name: "Potions".to_string(),
+            name: "Potion".to_string(),
+            transfer_type: "0x3::token::DepositEvent".to_string(),
+            transfer_kind: Some("transfer".to_string()),
+            domain: None,
+            from_address: from_address.map(|address| address.to_string()),
+            to_address: to_address.map(|address| address.to_string()),
+            token_amount: BigDecimal::from(1),
+            coin_type: None,
+            coin_amount: None,
+            suspect_value: false,
+            collection_data_id_hash: "potions".to_string(),
+            transaction_timestamp: chrono::NaiveDateTime::from_timestamp(1668000000, 0),
+        }
+    }
+
+    #[test]
+    fn test_parse_channel() {
+        assert_eq!(
+            parse_channel("sales:potions"),
+            Some("sales:potions".to_string())
+        );
+        assert_eq!(
+            parse_channel("wallet:0xA11CE"),
+            Some(format!("wallet:{}", standardize_address("0xa11ce")))
+        );
+        assert_eq!(parse_channel("sales:"), None);
+        assert_eq!(parse_channel("sales"), None);
+        assert_eq!(parse_channel("bids:potions"), None);
+    }
+
+    #[test]
+    fn test_wallet_messages_and_lag() {
+        let messages = LiveFeed::batch_messages(
+            &[],
+            &[],
+            &[
+                activity(Some("0xa"), Some("0xb")),
+                activity(None, Some("0xb")),
+                activity(Some("0xa"), Some("0xa")),
+            ],
+        );
+        assert_eq!(
+            messages
+                .iter()
+                .map(|message| message.channel.as_str())
+                .collect::<Vec<_>>(),
+            vec!["wallet:0xa", "wallet:0xb", "wallet:0xb", "wallet:0xa"]
+        );
+        assert!(messages
+            .iter()
+            .all(|message| message.transaction_version == 7));
+
+        let live_feed = LiveFeed::new(2);
+        assert!(!live_feed.has_subscribers());
+        let mut receiver = live_feed.subscribe();
+        live_feed.publish(messages);
+        // The two oldest were dropped for the newest
+        assert!(matches!(
+            receiver.try_recv(),
+            Err(broadcast::error::TryRecvError::Lagged(2))
+        ));
+        assert_eq!(receiver.try_recv().unwrap().channel, "wallet:0xb");
+        assert_eq!(receiver.try_recv().unwrap().channel, "wallet:0xa");
+    }
+}
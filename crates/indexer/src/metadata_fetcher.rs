@@ -0,0 +1,501 @@
+// Copyright (c) Aptos
+// SPDX-License-Identifier: Apache-2.0
+
+//! Background worker filling token_metadata_cache with the JSON behind each token data's
+//! metadata_uri, so frontends don't each fetch it from IPFS, Arweave or the creator's server.
+//! Only built with the `metadata-fetcher` feature.
+//!
+//! Fetches are spread out per host, and bodies are capped in size. A failed fetch is retried with
+//! a doubling backoff, up to max_attempts. Responses that won't get better by retrying (ex: a 404
+//! from the creator's server, a body that isn't a JSON object) fail permanently right away.
+
+use crate::{
+    counters::METADATA_FETCHES,
+    database::PgDbPool,
+    models::{
+        processor_status::{ProcessorStatusV2, ProcessorStatusV2Query},
+        token_models::token_metadata_cache::{
+            enqueue_changed_uris, get_due_fetches, record_failure, record_fetched, TokenMetadata,
+            METADATA_FETCHER_STATUS_NAME,
+        },
+    },
+};
+use anyhow::{ensure, Context};
+use aptos_config::config::MetadataFetcherConfig;
+use aptos_logger::{error, info};
+use futures::StreamExt;
+use reqwest::StatusCode;
+use std::{
+    collections::{HashMap, HashSet},
+    sync::Mutex,
+    time::{Duration, Instant},
+};
+use url::Url;
+
+pub const DEFAULT_CONCURRENCY: u64 = 8;
+pub const DEFAULT_REQUESTS_PER_SECOND_PER_HOST: u64 = 5;
+pub const DEFAULT_ALLOWED_SCHEMES: &[&str] = &["https", "ipfs", "ar"];
+pub const DEFAULT_IPFS_GATEWAY: &str = "https://ipfs.io/ipfs/";
+pub const DEFAULT_ARWEAVE_GATEWAY: &str = "https://arweave.net/";
+pub const DEFAULT_MAX_RESPONSE_BYTES: u64 = 1024 * 1024;
+pub const DEFAULT_TIMEOUT_MS: u64 = 10_000;
+pub const DEFAULT_MAX_ATTEMPTS: u64 = 5;
+pub const DEFAULT_STARTING_BACKOFF_SECS: u64 = 60;
+pub const DEFAULT_MAX_BACKOFF_SECS: u64 = 3600;
+pub const DEFAULT_POLL_INTERVAL_MS: u64 = 5000;
+/// Token datas read from current_token_datas per poll
+const ENQUEUE_BATCH_SIZE: i64 = 1000;
+/// Due fetches claimed per poll, per fetch in flight
+const FETCHES_PER_TASK: u64 = 4;
+const MAX_REDIRECTS: usize = 5;
+
+#[derive(Debug, PartialEq, Eq)]
+enum FetchError {
+    Retryable(String),
+    Permanent(String),
+}
+
+/// Spaces out the fetches started to each host
+#[derive(Debug)]
+struct HostRateLimiter {
+    interval: Duration,
+    next_slots: Mutex<HashMap<String, Instant>>,
+}
+
+impl HostRateLimiter {
+    fn new(requests_per_second: u64) -> Self {
+        Self {
+            interval: Duration::from_secs(1) / requests_per_second as u32,
+            next_slots: Mutex::new(HashMap::new()),
+        }
+    }
+
+    /// Reserves the next slot for the host, which is when its fetch may start
+    fn reserve(&self, host: &str, now: Instant) -> Instant {
+        let mut next_slots = self.next_slots.lock().unwrap();
+        let slot = match next_slots.get(host) {
+            Some(next_slot) => (*next_slot).max(now),
+            None => now,
+        };
+        next_slots.insert(host.to_string(), slot + self.interval);
+        slot
+    }
+
+    async fn wait(&self, host: &str) {
+        let slot = self.reserve(host, Instant::now());
+        tokio::time::sleep_until(slot.into()).await;
+    }
+}
+
+#[derive(Debug)]
+pub struct MetadataFetcher {
+    concurrency: usize,
+    allowed_schemes: Vec<String>,
+    ipfs_gateway: Url,
+    arweave_gateway: Url,
+    max_response_bytes: u64,
+    timeout: Duration,
+    max_attempts: i64,
+    starting_backoff: Duration,
+    max_backoff: Duration,
+    poll_interval: Duration,
+    rate_limiter: HostRateLimiter,
+}
+
+/// Gateways are joined with the content id, so they need to end with a slash
+fn parse_gateway(gateway: &str) -> anyhow::Result<Url> {
+    let gateway = if gateway.ends_with('/') {
+        gateway.to_string()
+    } else {
+        format!("{}/", gateway)
+    };
+    let url = Url::parse(&gateway).with_context(|| format!("Invalid gateway {}", gateway))?;
+    ensure!(
+        matches!(url.scheme(), "http" | "https"),
+        "Gateway {} must be an http or https url",
+        gateway
+    );
+    Ok(url)
+}
+
+impl MetadataFetcher {
+    pub fn from_config(config: Option<&MetadataFetcherConfig>) -> anyhow::Result<Option<Self>> {
+        let config = match config {
+            Some(config) => config,
+            None => return Ok(None),
+        };
+        let concurrency = config.concurrency.unwrap_or(DEFAULT_CONCURRENCY);
+        ensure!(concurrency > 0, "concurrency must be greater than 0");
+        let requests_per_second = config
+            .requests_per_second_per_host
+            .unwrap_or(DEFAULT_REQUESTS_PER_SECOND_PER_HOST);
+        ensure!(
+            requests_per_second > 0 && requests_per_second <= 1000,
+            "requests_per_second_per_host must be between 1 and 1000"
+        );
+        let allowed_schemes = match &config.allowed_schemes {
+            Some(schemes) => schemes
+                .iter()
+                .map(|scheme| scheme.to_lowercase())
+                .collect::<Vec<_>>(),
+            None => DEFAULT_ALLOWED_SCHEMES
+                .iter()
+                .map(|scheme| scheme.to_string())
+                .collect(),
+        };
+        ensure!(
+            !allowed_schemes.is_empty(),
+            "allowed_schemes can't be empty"
+        );
+        for scheme in &allowed_schemes {
+            ensure!(
+                matches!(scheme.as_str(), "http" | "https" | "ipfs" | "ar"),
+                "Unsupported scheme {} in allowed_schemes, expected http, https, ipfs or ar",
+                scheme
+            );
+        }
+        let max_attempts = config.max_attempts.unwrap_or(DEFAULT_MAX_ATTEMPTS);
+        ensure!(max_attempts > 0, "max_attempts must be greater than 0");
+        let starting_backoff_secs = config
+            .starting_backoff_secs
+            .unwrap_or(DEFAULT_STARTING_BACKOFF_SECS);
+        let max_backoff_secs = config.max_backoff_secs.unwrap_or(DEFAULT_MAX_BACKOFF_SECS);
+        ensure!(
+            starting_backoff_secs <= max_backoff_secs,
+            "starting_backoff_secs can't be greater than max_backoff_secs"
+        );
+        let timeout_ms = config.timeout_ms.unwrap_or(DEFAULT_TIMEOUT_MS);
+        ensure!(timeout_ms > 0, "timeout_ms must be greater than 0");
+        let max_response_bytes = config
+            .max_response_bytes
+            .unwrap_or(DEFAULT_MAX_RESPONSE_BYTES);
+        ensure!(
+            max_response_bytes > 0,
+            "max_response_bytes must be greater than 0"
+        );
+        Ok(Some(Self {
+            concurrency: concurrency as usize,
+            allowed_schemes,
+            ipfs_gateway: parse_gateway(
+                config
+                    .ipfs_gateway
+                    .as_deref()
+                    .unwrap_or(DEFAULT_IPFS_GATEWAY),
+            )?,
+            arweave_gateway: parse_gateway(
+                config
+                    .arweave_gateway
+                    .as_deref()
+                    .unwrap_or(DEFAULT_ARWEAVE_GATEWAY),
+            )?,
+            max_response_bytes,
+            timeout: Duration::from_millis(timeout_ms),
+            max_attempts: max_attempts as i64,
+            starting_backoff: Duration::from_secs(starting_backoff_secs),
+            max_backoff: Duration::from_secs(max_backoff_secs),
+            poll_interval: Duration::from_millis(
+                config.poll_interval_ms.unwrap_or(DEFAULT_POLL_INTERVAL_MS),
+            ),
+            rate_limiter: HostRateLimiter::new(requests_per_second),
+        }))
+    }
+
+    /// The http url to fetch the uri from, with ipfs:// and ar:// rewritten through their
+    /// gateways
+    fn resolve_uri(&self, uri: &str) -> Result<Url, FetchError> {
+        let uri = uri.trim();
+        let (scheme, rest) = uri
+            .split_once("://")
+            .ok_or_else(|| FetchError::Permanent(format!("{} isn't a url", uri)))?;
+        let scheme = scheme.to_lowercase();
+        if !self.allowed_schemes.contains(&scheme) {
+            return Err(FetchError::Permanent(format!(
+                "Scheme {} isn't allowed",
+                scheme
+            )));
+        }
+        // Content ids are case sensitive, so they're joined as is rather than parsed as a host
+        let url = match scheme.as_str() {
+            "ipfs" => self
+                .ipfs_gateway
+                .join(rest.trim_start_matches("ipfs/").trim_start_matches('/')),
+            "ar" => self.arweave_gateway.join(rest.trim_start_matches('/')),
+            _ => Url::parse(uri),
+        };
+        url.map_err(|err| FetchError::Permanent(format!("Invalid url {}: {}", uri, err)))
+    }
+
+    /// When to retry after the entry's `fetch_attempts` failed attempts plus the current one, None
+    /// once it's out of attempts
+    fn retry_at(
+        &self,
+        fetch_attempts: i64,
+        now: chrono::NaiveDateTime,
+    ) -> Option<chrono::NaiveDateTime> {
+        let attempts = fetch_attempts + 1;
+        if attempts >= self.max_attempts {
+            return None;
+        }
+        let exponent = (attempts - 1).clamp(0, 31) as u32;
+        let backoff = self
+            .starting_backoff
+            .saturating_mul(1 << exponent)
+            .min(self.max_backoff);
+        Some(now + chrono::Duration::from_std(backoff).unwrap())
+    }
+
+    async fn fetch(
+        &self,
+        client: &reqwest::Client,
+        uri: &str,
+    ) -> Result<TokenMetadata, FetchError> {
+        let url = self.resolve_uri(uri)?;
+        self.rate_limiter
+            .wait(url.host_str().unwrap_or_default())
+            .await;
+        let mut response = client
+            .get(url)
+            .send()
+            .await
+            .map_err(|err| FetchError::Retryable(err.to_string()))?;
+        let status = response.status();
+        if !status.is_success() {
+            let message = format!("Got status {}", status);
+            // Throttling and timeouts pass, other client errors won't
+            return Err(
+                if status.is_client_error()
+                    && status != StatusCode::REQUEST_TIMEOUT
+                    && status != StatusCode::TOO_MANY_REQUESTS
+                {
+                    FetchError::Permanent(message)
+                } else {
+                    FetchError::Retryable(message)
+                },
+            );
+        }
+        let too_large = || {
+            FetchError::Permanent(format!(
+                "Response is larger than {} bytes",
+                self.max_response_bytes
+            ))
+        };
+        if response
+            .content_length()
+            .map_or(false, |length| length > self.max_response_bytes)
+        {
+            return Err(too_large());
+        }
+        // The content length can be missing or wrong, so the body is counted as it's read
+        let mut body = vec![];
+        while let Some(chunk) = response
+            .chunk()
+            .await
+            .map_err(|err| FetchError::Retryable(err.to_string()))?
+        {
+            body.extend_from_slice(&chunk);
+            if body.len() as u64 > self.max_response_bytes {
+                return Err(too_large());
+            }
+        }
+        TokenMetadata::from_json(&body).map_err(|err| FetchError::Permanent(format!("{:#}", err)))
+    }
+
+    /// Queues changed uris and fetches the entries that are due. Returns whether there was
+    /// anything to do, if not the caller waits before polling again.
+    async fn run_once(
+        &self,
+        conn_pool: &PgDbPool,
+        client: &reqwest::Client,
+    ) -> anyhow::Result<bool> {
+        let mut conn = conn_pool.get()?;
+        let now = chrono::Utc::now().naive_utc();
+        let status_name = METADATA_FETCHER_STATUS_NAME.to_string();
+        let after_version = ProcessorStatusV2Query::get_by_processor(&status_name, &mut conn)?
+            .map_or(0, |status| status.last_success_version);
+        let queued_version =
+            enqueue_changed_uris(&mut conn, after_version, ENQUEUE_BATCH_SIZE, now)?;
+        if let Some(queued_version) = queued_version {
+            ProcessorStatusV2 {
+                processor: status_name,
+                last_success_version: queued_version,
+            }
+            .upsert(&mut conn)?;
+        }
+        let due_fetches = get_due_fetches(
+            &mut conn,
+            now,
+            (self.concurrency as u64 * FETCHES_PER_TASK) as i64,
+        )?;
+        // Not held while fetching
+        drop(conn);
+
+        // Uris are canonical, so tokens sharing metadata (ex: an edition) are fetched once
+        let uris = due_fetches
+            .iter()
+            .map(|fetch| fetch.metadata_uri.as_str())
+            .collect::<HashSet<&str>>();
+        let results = futures::stream::iter(uris)
+            .map(|uri| async move { (uri, self.fetch(client, uri).await) })
+            .buffer_unordered(self.concurrency)
+            .collect::<HashMap<&str, Result<TokenMetadata, FetchError>>>()
+            .await;
+
+        let mut conn = conn_pool.get()?;
+        let now = chrono::Utc::now().naive_utc();
+        for fetch in &due_fetches {
+            let (result_label, recorded) = match &results[fetch.metadata_uri.as_str()] {
+                Ok(metadata) => ("fetched", record_fetched(&mut conn, fetch, metadata, now)),
+                Err(FetchError::Retryable(error)) => match self.retry_at(fetch.fetch_attempts, now)
+                {
+                    Some(retry_at) => (
+                        "failed",
+                        record_failure(&mut conn, fetch, error, Some(retry_at), now),
+                    ),
+                    None => (
+                        "permanently_failed",
+                        record_failure(&mut conn, fetch, error, None, now),
+                    ),
+                },
+                Err(FetchError::Permanent(error)) => (
+                    "permanently_failed",
+                    record_failure(&mut conn, fetch, error, None, now),
+                ),
+            };
+            recorded?;
+            METADATA_FETCHES.with_label_values(&[result_label]).inc();
+        }
+        Ok(queued_version.is_some() || !due_fetches.is_empty())
+    }
+
+    /// Runs until the indexer stops. Database errors are logged and retried after the poll
+    /// interval, they don't stop the indexer.
+    pub async fn run(self, conn_pool: PgDbPool) {
+        let client = reqwest::Client::builder()
+            .timeout(self.timeout)
+            .redirect(reqwest::redirect::Policy::limited(MAX_REDIRECTS))
+            .build()
+            .expect("Failed to build the metadata fetcher's http client");
+        info!(
+            concurrency = self.concurrency,
+            "Starting the metadata fetcher"
+        );
+        loop {
+            match self.run_once(&conn_pool, &client).await {
+                Ok(true) => {}
+                Ok(false) => tokio::time::sleep(self.poll_interval).await,
+                Err(err) => {
+                    error!(
+                        error = format!("{:#}", err),
+                        "Metadata fetcher failed, retrying"
+                    );
+                    tokio::time::sleep(self.poll_interval).await;
+                }
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn fetcher(config: MetadataFetcherConfig) -> MetadataFetcher {
+        MetadataFetcher::from_config(Some(&config))
+            .unwrap()
+            .unwrap()
+    }
+
+    #[test]
+    fn test_resolve_uri() {
+        let fetcher = fetcher(MetadataFetcherConfig {
+            ipfs_gateway: Some("https://gateway.example/ipfs".to_string()),
+            ..MetadataFetcherConfig::default()
+        });
+        let resolve = |uri: &str| fetcher.resolve_uri(uri).map(|url| url.to_string());
+        assert_eq!(
+            resolve("ipfs://QmYwAPJzv5CZsnA/1.json").unwrap(),
+            "https://gateway.example/ipfs/QmYwAPJzv5CZsnA/1.json"
+        );
+        assert_eq!(
+            resolve("ipfs://ipfs/QmYwAPJzv5CZsnA").unwrap(),
+            "https://gateway.example/ipfs/QmYwAPJzv5CZsnA"
+        );
+        assert_eq!(
+            resolve("ar://bNbA3TEQVL60xlgCcqdz4ZPHFZ711cZ3hmkpGttDt_U").unwrap(),
+            "https://arweave.net/bNbA3TEQVL60xlgCcqdz4ZPHFZ711cZ3hmkpGttDt_U"
+        );
+        assert_eq!(
+            resolve("https://potions.example/1.json").unwrap(),
+            "https://potions.example/1.json"
+        );
+        // http isn't allowed by default
+        assert!(matches!(
+            resolve("http://potions.example/1.json"),
+            Err(FetchError::Permanent(_))
+        ));
+        assert!(matches!(resolve(""), Err(FetchError::Permanent(_))));
+    }
+
+    #[test]
+    fn test_retry_backoff() {
+        let fetcher = fetcher(MetadataFetcherConfig {
+            max_attempts: Some(6),
+            starting_backoff_secs: Some(60),
+            max_backoff_secs: Some(200),
+            ..MetadataFetcherConfig::default()
+        });
+        let now = chrono::NaiveDateTime::from_timestamp(1668000000, 0);
+        let backoffs = (0..6)
+            .map(|attempts| {
+                fetcher
+                    .retry_at(attempts, now)
+                    .map(|retry_at| (retry_at - now).num_seconds())
+            })
+            .collect::<Vec<_>>();
+        assert_eq!(
+            backoffs,
+            vec![Some(60), Some(120), Some(200), Some(200), Some(200), None]
+        );
+    }
+
+    #[test]
+    fn test_rate_limit_per_host() {
+        let limiter = HostRateLimiter::new(4);
+        let now = Instant::now();
+        assert_eq!(limiter.reserve("ipfs.io", now), now);
+        assert_eq!(
+            limiter.reserve("ipfs.io", now),
+            now + Duration::from_millis(250)
+        );
+        assert_eq!(limiter.reserve("arweave.net", now), now);
+        // A host that's been idle isn't held back by its old slots
+        let later = now + Duration::from_secs(10);
+        assert_eq!(limiter.reserve("ipfs.io", later), later);
+    }
+
+    #[test]
+    fn test_from_config() {
+        assert!(MetadataFetcher::from_config(None).unwrap().is_none());
+        for config in [
+            MetadataFetcherConfig {
+                concurrency: Some(0),
+                ..MetadataFetcherConfig::default()
+            },
+            MetadataFetcherConfig {
+                allowed_schemes: Some(vec!["ftp".to_string()]),
+                ..MetadataFetcherConfig::default()
+            },
+            MetadataFetcherConfig {
+                ipfs_gateway: Some("ipfs.io".to_string()),
+                ..MetadataFetcherConfig::default()
+            },
+            MetadataFetcherConfig {
+                starting_backoff_secs: Some(600),
+                max_backoff_secs: Some(60),
+                ..MetadataFetcherConfig::default()
+            },
+        ] {
+            assert!(MetadataFetcher::from_config(Some(&config)).is_err());
+        }
+    }
+}
@@ -0,0 +1,199 @@
+// Copyright (c) Aptos
+// SPDX-License-Identifier: Apache-2.0
+
+//! Brings the database up to the migrations embedded in the binary, so deployments don't need
+//! the diesel CLI. Replicas starting at the same time each take the same postgres advisory lock
+//! before migrating: the first runs the pending migrations, the others wait for it and then find
+//! nothing left to run. Rehashing takes the same lock. Processors don't start against a schema with pending migrations, since
+//! their inserts would fail on tables and columns that don't exist yet, nor against rows hashed
+//! with another hash scheme, see `hash_scheme`.
+
+use crate::{
+    database::ConnectionSettings, hash_scheme::ensure_hash_scheme_is_current,
+    indexer::tailer::MIGRATIONS,
+};
+use anyhow::{anyhow, ensure, Context, Result};
+use aptos_logger::info;
+use diesel::{
+    migration::MigrationSource, pg::Pg, sql_types::BigInt, Connection, PgConnection, RunQueryDsl,
+};
+use diesel_migrations::MigrationHarness;
+use serde::Serialize;
+
+/// Key of the advisory lock held while migrating, "indexer" in ascii
+pub const MIGRATION_LOCK_KEY: i64 = 0x69_6e64_6578_6572;
+
+/// Where the database's schema is relative to the binary's migrations
+#[derive(Clone, Debug, PartialEq, Eq, Serialize)]
+pub struct SchemaStatus {
+    /// Latest migration applied to the database, null before the first one
+    pub migration_version: Option<String>,
+    /// Latest migration embedded in the binary
+    pub latest_migration_version: Option<String>,
+    pub pending_migrations: usize,
+}
+
+impl SchemaStatus {
+    pub fn get(conn: &mut PgConnection) -> Result<Self> {
+        let migration_version = conn
+            .applied_migrations()
+            .map_err(|err| anyhow!("Could not read applied migrations: {}", err))?
+            .into_iter()
+            .max()
+            .map(|version| version.to_string());
+        let latest_migration_version = MigrationSource::<Pg>::migrations(&MIGRATIONS)
+            .map_err(|err| anyhow!("Could not read embedded migrations: {}", err))?
+            .iter()
+            .map(|migration| migration.name().version().to_string())
+            .max();
+        let pending_migrations = conn
+            .pending_migrations(MIGRATIONS)
+            .map_err(|err| anyhow!("Could not check migrations: {}", err))?
+            .len();
+        Ok(Self {
+            migration_version,
+            latest_migration_version,
+            pending_migrations,
+        })
+    }
+}
+
+/// Runs `f` while holding the migration lock, which is released even if `f` fails
+pub fn with_migration_lock<T>(
+    conn: &mut PgConnection,
+    f: impl FnOnce(&mut PgConnection) -> Result<T>,
+) -> Result<T> {
+    info!("Waiting for the migration lock...");
+    diesel::sql_query("SELECT pg_advisory_lock($1)")
+        .bind::<BigInt, _>(MIGRATION_LOCK_KEY)
+        .execute(conn)
+        .context("Could not take the migration lock")?;
+    let result = f(conn);
+    let unlocked = diesel::sql_query("SELECT pg_advisory_unlock($1)")
+        .bind::<BigInt, _>(MIGRATION_LOCK_KEY)
+        .execute(conn)
+        .context("Could not release the migration lock");
+    let value = result?;
+    unlocked?;
+    Ok(value)
+}
+
+/// Runs the pending migrations while holding the migration lock, and returns the versions it ran
+pub fn run_pending_migrations(conn: &mut PgConnection) -> Result<Vec<String>> {
+    // Each migration is rolled back on its own if it fails
+    with_migration_lock(conn, |conn| {
+        conn.run_pending_migrations(MIGRATIONS)
+            .map(|versions| versions.iter().map(ToString::to_string).collect())
+            .map_err(|err| anyhow!("Migrations failed: {}", err))
+    })
+}
+
+/// Fails if the database is missing any of the binary's migrations
+pub fn ensure_schema_is_current(conn: &mut PgConnection) -> Result<()> {
+    let status = SchemaStatus::get(conn)?;
+    ensure!(
+        status.pending_migrations == 0,
+        "Database schema is at migration {} but the indexer needs {}, {} migrations behind. \
+        Run with --migrate, or without skip_migrations.",
+        status.migration_version.as_deref().unwrap_or("none"),
+        status.latest_migration_version.as_deref().unwrap_or("none"),
+        status.pending_migrations
+    );
+    Ok(())
+}
+
+/// Migrates if asked to, then checks the schema is current, and likewise rehashes if asked to,
+/// then checks the hash scheme is current. Not on a pooled connection, since migrations can take
+/// longer than the pools' timeouts and the lock is held by the session. With a postgres_schema,
+/// the migrations create it and run in it, so each schema is migrated on its own.
+pub fn prepare_schema(
+    postgres_uri: &str,
+    settings: &ConnectionSettings,
+    migrate: bool,
+    rehash: bool,
+) -> Result<()> {
+    let mut conn =
+        PgConnection::establish(postgres_uri).context("Could not connect to postgres")?;
+    if let (Some(schema), true) = (&settings.schema, migrate) {
+        diesel::sql_query(format!("CREATE SCHEMA IF NOT EXISTS {}", schema.name()))
+            .execute(&mut conn)
+            .with_context(|| format!("Could not create schema {}", schema.name()))?;
+    }
+    // Only the search_path, the timeouts are for the processors' statements
+    ConnectionSettings {
+        schema: settings.schema.clone(),
+        ..ConnectionSettings::default()
+    }
+    .apply(&mut conn)
+    .context("Could not set the search_path")?;
+    if migrate {
+        let versions = run_pending_migrations(&mut conn)?;
+        info!(
+            num_migrations = versions.len(),
+            migrations = versions.join(","),
+            "Ran pending migrations"
+        );
+    }
+    ensure_schema_is_current(&mut conn)?;
+    ensure_hash_scheme_is_current(&mut conn, rehash)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{database::new_db_pool, indexer::tailer::test::wipe_database};
+
+    fn database_url() -> String {
+        std::env::var("INDEXER_DATABASE_URL")
+            .expect("must set 'INDEXER_DATABASE_URL' to run tests!")
+    }
+
+    #[test]
+    fn test_fresh_database_comes_up_migrated() {
+        if crate::should_skip_pg_tests() {
+            return;
+        }
+        let database_url = database_url();
+        wipe_database(&mut new_db_pool(&database_url).unwrap().get().unwrap());
+        let mut conn = PgConnection::establish(&database_url).unwrap();
+        let status = SchemaStatus::get(&mut conn).unwrap();
+        assert_eq!(status.migration_version, None);
+        assert!(status.pending_migrations > 0);
+        // Processors refuse to start against it
+        assert!(
+            prepare_schema(&database_url, &ConnectionSettings::default(), false, false).is_err()
+        );
+
+        prepare_schema(&database_url, &ConnectionSettings::default(), true, false).unwrap();
+        let status = SchemaStatus::get(&mut conn).unwrap();
+        assert_eq!(status.pending_migrations, 0);
+        assert_eq!(status.migration_version, status.latest_migration_version);
+        assert!(run_pending_migrations(&mut conn).unwrap().is_empty());
+    }
+
+    #[test]
+    fn test_replicas_migrate_once() {
+        if crate::should_skip_pg_tests() {
+            return;
+        }
+        let database_url = database_url();
+        wipe_database(&mut new_db_pool(&database_url).unwrap().get().unwrap());
+        let replicas = (0..3)
+            .map(|_| {
+                let database_url = database_url.clone();
+                std::thread::spawn(move || {
+                    let mut conn = PgConnection::establish(&database_url).unwrap();
+                    run_pending_migrations(&mut conn).unwrap()
+                })
+            })
+            .collect::<Vec<_>>();
+        let ran = replicas
+            .into_iter()
+            .map(|replica| replica.join().unwrap())
+            .filter(|versions| !versions.is_empty())
+            .count();
+        assert_eq!(ran, 1);
+        let mut conn = PgConnection::establish(&database_url).unwrap();
+        ensure_schema_is_current(&mut conn).unwrap();
+    }
+}
@@ -28,8 +28,10 @@ pub struct CoinBalance {
     pub transaction_timestamp: chrono::NaiveDateTime,
 }
 
-#[derive(Debug, Deserialize, FieldCount, Identifiable, Insertable, Serialize)]
-#[diesel(primary_key(owner_address, coin_type))]
+#[derive(
+    Debug, Deserialize, FieldCount, Identifiable, Insertable, Queryable, Selectable, Serialize,
+)]
+#[diesel(primary_key(owner_address, coin_type_hash))]
 #[diesel(table_name = current_coin_balances)]
 pub struct CurrentCoinBalance {
     pub owner_address: String,
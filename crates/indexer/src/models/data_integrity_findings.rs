@@ -0,0 +1,28 @@
+// Copyright (c) Aptos
+// SPDX-License-Identifier: Apache-2.0
+
+// This is required because a diesel macro makes clippy sad
+#![allow(clippy::extra_unused_lifetimes)]
+#![allow(clippy::unused_unit)]
+
+use crate::schema::data_integrity_findings;
+use bigdecimal::BigDecimal;
+use field_count::FieldCount;
+use serde::{Deserialize, Serialize};
+
+/// A discrepancy found by one of the processors' self checks. `subject` identifies what was
+/// checked, ex: a collection_data_id_hash, and `delta` is `actual - expected`. `transaction_version`
+/// is the last version committed when the check ran, `offending_version` the last version that
+/// touched the subject, if the check knows it.
+#[derive(Debug, Deserialize, FieldCount, Identifiable, Insertable, Serialize)]
+#[diesel(primary_key(check_name, subject, transaction_version))]
+#[diesel(table_name = data_integrity_findings)]
+pub struct DataIntegrityFinding {
+    pub check_name: String,
+    pub subject: String,
+    pub transaction_version: i64,
+    pub expected: BigDecimal,
+    pub actual: BigDecimal,
+    pub delta: BigDecimal,
+    pub offending_version: Option<i64>,
+}
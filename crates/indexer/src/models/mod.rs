@@ -3,11 +3,13 @@
 
 pub mod block_metadata_transactions;
 pub mod coin_models;
+pub mod data_integrity_findings;
 pub mod events;
 pub mod ledger_info;
 pub mod move_modules;
 pub mod move_resources;
 pub mod move_tables;
+pub mod processed_version_ranges;
 pub mod processor_statuses;
 pub mod signatures;
 pub mod token_models;
@@ -0,0 +1,183 @@
+// Copyright (c) Aptos
+// SPDX-License-Identifier: Apache-2.0
+
+// This is required because a diesel macro makes clippy sad
+#![allow(clippy::extra_unused_lifetimes)]
+#![allow(clippy::unused_unit)]
+
+use crate::{database::PgPoolConnection, schema::processed_version_ranges};
+use diesel::{
+    dsl::exists,
+    select, sql_query,
+    sql_types::{BigInt, Text},
+    ExpressionMethods, PgConnection, QueryDsl, QueryResult, RunQueryDsl,
+};
+
+/// Inclusive (start_version, end_version)
+pub type VersionRange = (i64, i64);
+
+/// A run of versions that a processor finished successfully. Ranges that overlap or touch are
+/// merged on write, so a processor without holes has a single row.
+#[derive(Debug, Identifiable, Queryable)]
+#[diesel(primary_key(processor, start_version))]
+#[diesel(table_name = processed_version_ranges)]
+pub struct ProcessedVersionRange {
+    pub processor: String,
+    pub start_version: i64,
+    pub end_version: i64,
+    pub last_updated: chrono::NaiveDateTime,
+}
+
+impl ProcessedVersionRange {
+    /// Records a successful batch. Stored ranges that overlap or touch the batch are deleted and
+    /// folded into the inserted row, all in one statement.
+    pub fn record(
+        conn: &mut PgConnection,
+        processor: &str,
+        start_version: i64,
+        end_version: i64,
+    ) -> QueryResult<usize> {
+        sql_query(
+            "
+            WITH merged AS (
+                DELETE FROM processed_version_ranges
+                WHERE processor = $1
+                    AND start_version <= $3 + 1
+                    AND end_version >= $2 - 1
+                RETURNING start_version, end_version
+            )
+            INSERT INTO processed_version_ranges (processor, start_version, end_version)
+            SELECT $1, LEAST($2, MIN(start_version)), GREATEST($3, MAX(end_version))
+            FROM merged
+            ON CONFLICT (processor, start_version) DO UPDATE SET
+                end_version = GREATEST(
+                    processed_version_ranges.end_version,
+                    excluded.end_version
+                ),
+                last_updated = NOW()
+            ",
+        )
+        .bind::<Text, _>(processor)
+        .bind::<BigInt, _>(start_version)
+        .bind::<BigInt, _>(end_version)
+        .execute(conn)
+    }
+
+    /// Whether a stored range has every version from `start_version` to `end_version`
+    pub fn covers(
+        conn: &mut PgConnection,
+        processor: &str,
+        start_version: i64,
+        end_version: i64,
+    ) -> QueryResult<bool> {
+        select(exists(
+            processed_version_ranges::table
+                .filter(processed_version_ranges::processor.eq(processor))
+                .filter(processed_version_ranges::start_version.le(start_version))
+                .filter(processed_version_ranges::end_version.ge(end_version)),
+        ))
+        .get_result(conn)
+    }
+
+    /// Stored ranges starting at or before `up_to_version`, ordered by start version
+    pub fn get_ranges(
+        conn: &mut PgPoolConnection,
+        processor: &str,
+        up_to_version: i64,
+    ) -> QueryResult<Vec<VersionRange>> {
+        processed_version_ranges::table
+            .filter(processed_version_ranges::processor.eq(processor))
+            .filter(processed_version_ranges::start_version.le(up_to_version))
+            .order(processed_version_ranges::start_version.asc())
+            .select((
+                processed_version_ranges::start_version,
+                processed_version_ranges::end_version,
+            ))
+            .load(conn)
+    }
+
+    /// Versions missing between the first processed version and `up_to_version`. Ranges are merged
+    /// again on read since concurrent batches can each insert a row before seeing the other.
+    pub fn find_gaps(
+        conn: &mut PgPoolConnection,
+        processor: &str,
+        up_to_version: i64,
+    ) -> QueryResult<Vec<VersionRange>> {
+        let ranges = merge_ranges(Self::get_ranges(conn, processor, up_to_version)?);
+        Ok(gaps(&ranges, up_to_version))
+    }
+}
+
+/// Sorts ranges and merges the ones that overlap, touch or repeat
+pub fn merge_ranges(mut ranges: Vec<VersionRange>) -> Vec<VersionRange> {
+    ranges.sort_unstable();
+    let mut merged: Vec<VersionRange> = vec![];
+    for (start_version, end_version) in ranges {
+        match merged.last_mut() {
+            Some((_, last_end_version)) if start_version <= *last_end_version + 1 => {
+                *last_end_version = (*last_end_version).max(end_version);
+            }
+            _ => merged.push((start_version, end_version)),
+        }
+    }
+    merged
+}
+
+/// Holes between merged ranges, plus the tail after the last range up to `up_to_version`.
+/// Versions before the first range aren't a hole since a processor can start at any version.
+pub fn gaps(merged_ranges: &[VersionRange], up_to_version: i64) -> Vec<VersionRange> {
+    let mut gaps = vec![];
+    let mut next_version = match merged_ranges.first() {
+        Some((start_version, _)) => *start_version,
+        None => return gaps,
+    };
+    for (start_version, end_version) in merged_ranges {
+        if *start_version > up_to_version {
+            break;
+        }
+        if *start_version > next_version {
+            gaps.push((next_version, start_version - 1));
+        }
+        next_version = next_version.max(end_version + 1);
+    }
+    if next_version <= up_to_version {
+        gaps.push((next_version, up_to_version));
+    }
+    gaps
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_merge_ranges() {
+        // Overlapping
+        assert_eq!(merge_ranges(vec![(10, 20), (0, 15)]), vec![(0, 20)]);
+        assert_eq!(merge_ranges(vec![(0, 30), (10, 20)]), vec![(0, 30)]);
+        // Adjacent
+        assert_eq!(
+            merge_ranges(vec![(20, 29), (0, 9), (10, 19)]),
+            vec![(0, 29)]
+        );
+        // Duplicate
+        assert_eq!(merge_ranges(vec![(5, 9), (5, 9)]), vec![(5, 9)]);
+        // Neither
+        assert_eq!(
+            merge_ranges(vec![(12, 20), (0, 10)]),
+            vec![(0, 10), (12, 20)]
+        );
+        assert!(merge_ranges(vec![]).is_empty());
+    }
+
+    #[test]
+    fn test_gaps() {
+        let ranges = merge_ranges(vec![(100, 199), (300, 399), (250, 259), (200, 209)]);
+        assert_eq!(gaps(&ranges, 399), vec![(210, 249), (260, 299)]);
+        // Stops at up_to_version and reports the tail
+        assert_eq!(gaps(&ranges, 255), vec![(210, 249)]);
+        assert_eq!(gaps(&ranges, 500), vec![(210, 249), (260, 299), (400, 500)]);
+        assert!(gaps(&ranges, 150).is_empty());
+        assert!(gaps(&[], 500).is_empty());
+    }
+}
@@ -2,7 +2,29 @@
 // SPDX-License-Identifier: Apache-2.0
 #![allow(clippy::extra_unused_lifetimes)]
 use crate::{database::PgPoolConnection, schema::processor_status};
-use diesel::{ExpressionMethods, OptionalExtension, QueryDsl, RunQueryDsl};
+use diesel::{
+    dsl::now,
+    sql_query,
+    sql_types::{Text, Timestamp},
+    ExpressionMethods, OptionalExtension, PgConnection, QueryDsl, RunQueryDsl,
+};
+
+const BACKFILL_CHECKPOINT_INFIX: &str = "_backfill_";
+
+/// Name of the processor_status row tracking a backfill. It's keyed by the start version so that
+/// rerunning a backfill that crashed resumes after the last committed batch.
+pub fn backfill_checkpoint_name(processor_name: &str, start_version: u64) -> String {
+    format!(
+        "{}{}{}",
+        processor_name, BACKFILL_CHECKPOINT_INFIX, start_version
+    )
+}
+
+/// Start version of the backfill tracked by a processor_status row, if it tracks one
+pub fn parse_backfill_checkpoint_name(name: &str) -> Option<u64> {
+    let (_, start_version) = name.rsplit_once(BACKFILL_CHECKPOINT_INFIX)?;
+    start_version.parse().ok()
+}
 
 #[derive(AsChangeset, Debug, Insertable)]
 #[diesel(table_name = processor_status)]
@@ -19,6 +41,54 @@ pub struct ProcessorStatusV2Query {
     pub processor: String,
     pub last_success_version: i64,
     pub last_updated: chrono::NaiveDateTime,
+    /// Time of the latest transaction processed, only tracked by processors that expire offers
+    pub last_transaction_timestamp: Option<chrono::NaiveDateTime>,
+}
+
+impl ProcessorStatusV2 {
+    /// Inserts the status or moves it to `last_success_version`
+    pub fn upsert(&self, conn: &mut PgPoolConnection) -> diesel::QueryResult<usize> {
+        diesel::insert_into(processor_status::table)
+            .values(self)
+            .on_conflict(processor_status::processor)
+            .do_update()
+            .set((
+                processor_status::last_success_version.eq(self.last_success_version),
+                processor_status::last_updated.eq(now),
+            ))
+            .execute(conn)
+    }
+}
+
+#[derive(Debug, QueryableByName)]
+struct ChainTimestamp {
+    #[diesel(sql_type = Timestamp)]
+    last_transaction_timestamp: chrono::NaiveDateTime,
+}
+
+/// Moves the processor's latest processed transaction timestamp forward to `timestamp` and returns
+/// it. It never goes back, so a backfill of older versions judges expiry at the same time as the
+/// processor did.
+pub fn advance_chain_timestamp(
+    conn: &mut PgConnection,
+    processor_name: &str,
+    timestamp: chrono::NaiveDateTime,
+) -> diesel::QueryResult<chrono::NaiveDateTime> {
+    let row = sql_query(
+        "INSERT INTO processor_status (processor, last_success_version, last_transaction_timestamp)
+        VALUES ($1, 0, $2)
+        ON CONFLICT (processor) DO UPDATE SET
+            last_transaction_timestamp = GREATEST(
+                processor_status.last_transaction_timestamp,
+                excluded.last_transaction_timestamp
+            ),
+            last_updated = NOW()
+        RETURNING last_transaction_timestamp",
+    )
+    .bind::<Text, _>(processor_name)
+    .bind::<Timestamp, _>(timestamp)
+    .get_result::<ChainTimestamp>(conn)?;
+    Ok(row.last_transaction_timestamp)
 }
 
 impl ProcessorStatusV2Query {
@@ -31,4 +101,43 @@ impl ProcessorStatusV2Query {
             .first::<Self>(conn)
             .optional()
     }
+
+    /// Start versions of the backfills that haven't finished, i.e. that still have a checkpoint
+    pub fn get_pending_backfill_starts(conn: &mut PgConnection) -> diesel::QueryResult<Vec<u64>> {
+        let names = processor_status::table
+            .filter(processor_status::processor.like(format!("%{}%", BACKFILL_CHECKPOINT_INFIX)))
+            .select(processor_status::processor)
+            .load::<String>(conn)?;
+        Ok(names
+            .iter()
+            .filter_map(|name| parse_backfill_checkpoint_name(name))
+            .collect())
+    }
+
+    pub fn delete_by_processor(
+        processor_name: &String,
+        conn: &mut PgPoolConnection,
+    ) -> diesel::QueryResult<usize> {
+        diesel::delete(
+            processor_status::table.filter(processor_status::processor.eq(processor_name)),
+        )
+        .execute(conn)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_backfill_checkpoint_name() {
+        let name = backfill_checkpoint_name("token_processor", 1200);
+        assert_eq!(name, "token_processor_backfill_1200");
+        assert_eq!(parse_backfill_checkpoint_name(&name), Some(1200));
+        assert_eq!(parse_backfill_checkpoint_name("token_processor"), None);
+        assert_eq!(
+            parse_backfill_checkpoint_name("token_backfill_processor"),
+            None
+        );
+    }
 }
@@ -14,7 +14,9 @@ use std::collections::HashMap;
 type StakingPoolAddress = String;
 pub type StakingPoolVoterMap = HashMap<StakingPoolAddress, CurrentStakingPoolVoter>;
 
-#[derive(Debug, Deserialize, FieldCount, Identifiable, Insertable, Serialize)]
+#[derive(
+    Debug, Deserialize, FieldCount, Identifiable, Insertable, Queryable, Selectable, Serialize,
+)]
 #[diesel(primary_key(staking_pool_address))]
 #[diesel(table_name = current_staking_pool_voter)]
 pub struct CurrentStakingPoolVoter {
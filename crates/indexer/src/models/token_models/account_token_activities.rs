@@ -0,0 +1,220 @@
+// Copyright (c) Aptos
+// SPDX-License-Identifier: Apache-2.0
+
+//! token_activities copied once per account they involve, for reading an account's activities
+//! newest first without an OR over from_address and to_address. Rows are keyed by the account
+//! followed by the activity's key, which is also the feed's order, so a page is a range of the
+//! primary key. Every activity with both a sender and a receiver is stored twice on top of its
+//! token_activities row, which is why the table is only written when enabled.
+
+// This is required because a diesel macro makes clippy sad
+#![allow(clippy::extra_unused_lifetimes)]
+#![allow(clippy::unused_unit)]
+
+use super::token_activities::TokenActivity;
+use crate::schema::account_token_activities;
+use bigdecimal::BigDecimal;
+use field_count::FieldCount;
+use serde::{Deserialize, Serialize};
+
+pub const SIDE_FROM: &str = "from";
+pub const SIDE_TO: &str = "to";
+/// The account sent the token to itself
+pub const SIDE_BOTH: &str = "both";
+
+#[derive(
+    Clone,
+    Debug,
+    Deserialize,
+    FieldCount,
+    Identifiable,
+    Insertable,
+    Queryable,
+    Selectable,
+    Serialize,
+)]
+#[diesel(primary_key(
+    account_address,
+    transaction_version,
+    event_index,
+    event_account_address,
+    event_creation_number,
+    event_sequence_number,
+    token_index
+))]
+#[diesel(table_name = account_token_activities)]
+pub struct AccountTokenActivity {
+    pub account_address: String,
+    pub transaction_version: i64,
+    pub event_index: i64,
+    pub event_account_address: String,
+    pub event_creation_number: i64,
+    pub event_sequence_number: i64,
+    pub token_index: i64,
+    /// Whether the account is the activity's from_address, to_address or both
+    pub side: String,
+    pub collection_data_id_hash: String,
+    pub token_data_id_hash: String,
+    pub property_version: BigDecimal,
+    pub creator_address: String,
+    pub collection_name: String,
+    pub name: String,
+    pub transfer_type: String,
+    pub transfer_kind: Option<String>,
+    pub domain: Option<String>,
+    pub from_address: Option<String>,
+    pub to_address: Option<String>,
+    pub token_amount: BigDecimal,
+    pub coin_type: Option<String>,
+    pub coin_amount: Option<BigDecimal>,
+    pub suspect_value: bool,
+    pub transaction_timestamp: chrono::NaiveDateTime,
+}
+
+impl AccountTokenActivity {
+    /// A row for each of the activity's from_address and to_address, or one if they're the same
+    /// account. Activities without either, ex: mints, aren't in any account's feed.
+    pub fn from_token_activity(activity: &TokenActivity) -> Vec<Self> {
+        match (&activity.from_address, &activity.to_address) {
+            (Some(from_address), Some(to_address)) if from_address == to_address => {
+                vec![Self::new(activity, from_address, SIDE_BOTH)]
+            }
+            (from_address, to_address) => from_address
+                .iter()
+                .map(|from_address| Self::new(activity, from_address, SIDE_FROM))
+                .chain(
+                    to_address
+                        .iter()
+                        .map(|to_address| Self::new(activity, to_address, SIDE_TO)),
+                )
+                .collect(),
+        }
+    }
+
+    pub fn from_token_activities(activities: &[TokenActivity]) -> Vec<Self> {
+        activities
+            .iter()
+            .flat_map(Self::from_token_activity)
+            .collect()
+    }
+
+    fn new(activity: &TokenActivity, account_address: &str, side: &str) -> Self {
+        Self {
+            account_address: account_address.to_string(),
+            transaction_version: activity.transaction_version,
+            event_index: activity.event_index,
+            event_account_address: activity.event_account_address.clone(),
+            event_creation_number: activity.event_creation_number,
+            event_sequence_number: activity.event_sequence_number,
+            token_index: activity.token_index,
+            side: side.to_string(),
+            collection_data_id_hash: activity.collection_data_id_hash.clone(),
+            token_data_id_hash: activity.token_data_id_hash.clone(),
+            property_version: activity.property_version.clone(),
+            creator_address: activity.creator_address.clone(),
+            collection_name: activity.collection_name.clone(),
+            name: activity.name.clone(),
+            transfer_type: activity.transfer_type.clone(),
+            transfer_kind: activity.transfer_kind.clone(),
+            domain: activity.domain.clone(),
+            from_address: activity.from_address.clone(),
+            to_address: activity.to_address.clone(),
+            token_amount: activity.token_amount.clone(),
+            coin_type: activity.coin_type.clone(),
+            coin_amount: activity.coin_amount.clone(),
+            suspect_value: activity.suspect_value,
+            transaction_timestamp: activity.transaction_timestamp,
+        }
+    }
+}
+
+/// The activity the row was copied from, so the account feed reads like token_activities
+impl From<AccountTokenActivity> for TokenActivity {
+    fn from(row: AccountTokenActivity) -> Self {
+        Self {
+            transaction_version: row.transaction_version,
+            event_account_address: row.event_account_address,
+            event_creation_number: row.event_creation_number,
+            event_sequence_number: row.event_sequence_number,
+            event_index: row.event_index,
+            token_index: row.token_index,
+            token_data_id_hash: row.token_data_id_hash,
+            property_version: row.property_version,
+            creator_address: row.creator_address,
+            collection_name: row.collection_name,
+            name: row.name,
+            transfer_type: row.transfer_type,
+            transfer_kind: row.transfer_kind,
+            domain: row.domain,
+            from_address: row.from_address,
+            to_address: row.to_address,
+            token_amount: row.token_amount,
+            coin_type: row.coin_type,
+            coin_amount: row.coin_amount,
+            suspect_value: row.suspect_value,
+            collection_data_id_hash: row.collection_data_id_hash,
+            transaction_timestamp: row.transaction_timestamp,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn activity(from_address: Option<&str>, to_address: Option<&str>) -> TokenActivity {
+        TokenActivity {
+            transaction_version: 10,
+            event_account_address: "0xa11ce".to_string(),
+            event_creation_number: 3,
+            event_sequence_number: 0,
+            event_index: 1,
+            token_index: 0,
+            token_data_id_hash: "potion".to_string(),
+            property_version: BigDecimal::from(0),
+            creator_address: "0xc4e7".to_string(),
+            collection_name: "Potions".to_string(),
+            name: "Potion".to_string(),
+            transfer_type: "0xbb::market::BuyEvent".to_string(),
+            transfer_kind: Some("sale".to_string()),
+            domain: None,
+            from_address: from_address.map(str::to_string),
+            to_address: to_address.map(str::to_string),
+            token_amount: BigDecimal::from(1),
+            coin_type: Some("0x1::aptos_coin::AptosCoin".to_string()),
+            coin_amount: Some(BigDecimal::from(100)),
+            suspect_value: false,
+            collection_data_id_hash: "potions".to_string(),
+            transaction_timestamp: chrono::NaiveDateTime::from_timestamp(1668000000, 0),
+        }
+    }
+
+    fn sides(rows: &[AccountTokenActivity]) -> Vec<(&str, &str)> {
+        rows.iter()
+            .map(|row| (row.account_address.as_str(), row.side.as_str()))
+            .collect()
+    }
+
+    #[test]
+    fn test_a_row_per_account() {
+        // A sale is in both the seller's and the buyer's feed
+        let sale = activity(Some("0xa11ce"), Some("0xb0b"));
+        let rows = AccountTokenActivity::from_token_activity(&sale);
+        assert_eq!(
+            sides(&rows),
+            vec![("0xa11ce", SIDE_FROM), ("0xb0b", SIDE_TO)]
+        );
+        assert_eq!(
+            TokenActivity::from(rows[1].clone()).pk(),
+            sale.pk(),
+            "Copies of the activity convert back to it"
+        );
+
+        let rows =
+            AccountTokenActivity::from_token_activity(&activity(Some("0xa11ce"), Some("0xa11ce")));
+        assert_eq!(sides(&rows), vec![("0xa11ce", SIDE_BOTH)]);
+        let rows = AccountTokenActivity::from_token_activity(&activity(None, Some("0xb0b")));
+        assert_eq!(sides(&rows), vec![("0xb0b", SIDE_TO)]);
+        assert!(AccountTokenActivity::from_token_activity(&activity(None, None)).is_empty());
+    }
+}
@@ -0,0 +1,169 @@
+// Copyright (c) Aptos
+// SPDX-License-Identifier: Apache-2.0
+
+//! Optional range partitioning of token_activities by transaction_version. The table is only
+//! partitioned once `partition_token_activities` (see the token_activities_partitioning
+//! migration) has been called; after that, the processor creates the partitions each batch needs.
+
+use super::token_activities::TokenActivity;
+use anyhow::ensure;
+use diesel::{
+    dsl::sql,
+    select, sql_query,
+    sql_types::{BigInt, Bool, Text},
+    PgConnection, QueryResult, RunQueryDsl,
+};
+use std::{collections::HashSet, sync::Mutex};
+
+/// A partition holding versions `start_version..end_version`
+#[derive(Debug, PartialEq, Eq, QueryableByName)]
+pub struct ActivityPartition {
+    #[diesel(sql_type = Text)]
+    pub name: String,
+    #[diesel(sql_type = BigInt)]
+    pub start_version: i64,
+    #[diesel(sql_type = BigInt)]
+    pub end_version: i64,
+}
+
+#[derive(Debug)]
+pub struct TokenActivityPartitions {
+    partition_size: i64,
+    /// Start versions of the partitions known to exist
+    created: Mutex<HashSet<i64>>,
+}
+
+impl TokenActivityPartitions {
+    pub fn from_config(partition_size: Option<u64>) -> anyhow::Result<Option<Self>> {
+        let partition_size = match partition_size {
+            Some(partition_size) => partition_size,
+            None => return Ok(None),
+        };
+        ensure!(
+            partition_size > 0,
+            "token_activities_partition_size must be greater than 0"
+        );
+        Ok(Some(Self {
+            partition_size: partition_size as i64,
+            created: Mutex::new(HashSet::new()),
+        }))
+    }
+
+    /// Start versions of the partitions the activities fall into, in order
+    pub fn partition_starts(&self, token_activities: &[TokenActivity]) -> Vec<i64> {
+        let mut starts = token_activities
+            .iter()
+            .map(|activity| {
+                activity.transaction_version / self.partition_size * self.partition_size
+            })
+            .collect::<Vec<_>>();
+        starts.sort_unstable();
+        starts.dedup();
+        starts
+    }
+
+    /// Creates the partitions the activities need. Creating a partition locks all of
+    /// token_activities, so this shouldn't run within the batch's transaction.
+    pub fn create_missing(
+        &self,
+        conn: &mut PgConnection,
+        token_activities: &[TokenActivity],
+    ) -> QueryResult<()> {
+        for start_version in self.partition_starts(token_activities) {
+            if self.created.lock().unwrap().contains(&start_version) {
+                continue;
+            }
+            sql_query("SELECT create_token_activities_partition($1, $2)")
+                .bind::<BigInt, _>(start_version)
+                .bind::<BigInt, _>(start_version + self.partition_size)
+                .execute(conn)?;
+            self.created.lock().unwrap().insert(start_version);
+        }
+        Ok(())
+    }
+}
+
+pub fn is_partitioned(conn: &mut PgConnection) -> QueryResult<bool> {
+    select(sql::<Bool>(
+        "EXISTS (
+            SELECT 1 FROM pg_partitioned_table
+            WHERE partrelid = to_regclass('token_activities')
+        )",
+    ))
+    .get_result(conn)
+}
+
+/// Partitions of token_activities by start version, empty if it isn't partitioned
+pub fn list_partitions(conn: &mut PgConnection) -> QueryResult<Vec<ActivityPartition>> {
+    sql_query(
+        r"SELECT name, bounds[1]::BIGINT AS start_version, bounds[2]::BIGINT AS end_version
+        FROM (
+            SELECT
+                c.relname::TEXT AS name,
+                regexp_match(
+                    pg_get_expr(c.relpartbound, c.oid),
+                    'FROM \(''(-?\d+)''\) TO \(''(-?\d+)''\)'
+                ) AS bounds
+            FROM pg_inherits i
+            JOIN pg_class c ON c.oid = i.inhrelid
+            WHERE i.inhparent = to_regclass('token_activities')
+        ) partitions
+        ORDER BY start_version",
+    )
+    .load(conn)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use bigdecimal::BigDecimal;
+
+    fn activity(transaction_version: i64) -> TokenActivity {
+        TokenActivity {
+            transaction_version,
+            event_account_address: "0x1".to_string(),
+            event_creation_number: 0,
+            event_sequence_number: 0,
+            event_index: 0,
+            token_index: 0,
+            token_data_id_hash: "potion".to_string(),
+            property_version: BigDecimal::from(0),
+            creator_address: "0x1".to_string(),
+            collection_name: "Potions".to_string(),
+            name: "Potion".to_string(),
+            transfer_type: "0x3::token::DepositEvent".to_string(),
+            transfer_kind: Some("transfer".to_string()),
+            domain: None,
+            from_address: None,
+            to_address: Some("0x1".to_string()),
+            token_amount: BigDecimal::from(1),
+            coin_type: None,
+            coin_amount: None,
+            suspect_value: false,
+            collection_data_id_hash: "potions".to_string(),
+            transaction_timestamp: chrono::NaiveDateTime::from_timestamp(1668000000, 0),
+        }
+    }
+
+    #[test]
+    fn test_partition_starts() {
+        assert!(TokenActivityPartitions::from_config(None)
+            .unwrap()
+            .is_none());
+        assert!(TokenActivityPartitions::from_config(Some(0)).is_err());
+
+        let partitions = TokenActivityPartitions::from_config(Some(1000))
+            .unwrap()
+            .unwrap();
+        assert_eq!(
+            partitions.partition_starts(&[
+                activity(2500),
+                activity(999),
+                activity(0),
+                activity(2000),
+            ]),
+            vec![0, 2000]
+        );
+        assert!(partitions.partition_starts(&[]).is_empty());
+    }
+}
@@ -0,0 +1,996 @@
+// Copyright (c) Aptos
+// SPDX-License-Identifier: Apache-2.0
+
+//! One-off repair of rows written before addresses were padded to their long form. Token and
+//! collection ids hash the creator address verbatim, so the same token could be stored under two
+//! hashes (ex: one from "0x1::..." and one from "0x0...01::..."). This rewrites the short hashes
+//! and addresses in place and merges the rows that now share a primary key. It's the rehash from
+//! hash scheme 0 to 1, see `hash_scheme`.
+
+use diesel::{result::Error, sql_query, sql_types::Text, PgConnection, QueryResult, RunQueryDsl};
+use Column::{Address as A, CollectionDataIdHash as C, TokenDataIdHash as T};
+
+/// How a column is rewritten
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+enum Column {
+    TokenDataIdHash(&'static str),
+    CollectionDataIdHash(&'static str),
+    Address(&'static str),
+}
+
+impl Column {
+    fn name(&self) -> &'static str {
+        match self {
+            Self::TokenDataIdHash(name)
+            | Self::CollectionDataIdHash(name)
+            | Self::Address(name) => *name,
+        }
+    }
+
+    /// The normalized value of the column of row `t`
+    fn normalized(&self) -> String {
+        let column = format!("t.{}", self.name());
+        match self {
+            Self::TokenDataIdHash(_) => remapped_hash("token_hash_remap", &column),
+            Self::CollectionDataIdHash(_) => remapped_hash("collection_hash_remap", &column),
+            Self::Address(_) => standardized_address(&column),
+        }
+    }
+
+    fn is_changed(&self) -> String {
+        format!("{} IS DISTINCT FROM t.{}", self.normalized(), self.name())
+    }
+}
+
+struct TableSpec {
+    table: &'static str,
+    primary_key: &'static [&'static str],
+    columns: &'static [Column],
+    /// Added up when rows are merged. Every other column is taken from the surviving row.
+    summed: &'static [&'static str],
+    /// Set to an aggregate of the merged rows `x` when rows are merged, as (column, expression)
+    aggregated: &'static [(&'static str, &'static str)],
+}
+
+/// Columns that order a table's rows by when they were last written, latest first
+const RECENCY_COLUMNS: &[&str] = &["last_transaction_version", "last_event_index"];
+
+#[derive(Debug, QueryableByName)]
+struct ColumnName {
+    #[diesel(sql_type = Text)]
+    column_name: String,
+}
+
+const CDH: Column = C("collection_data_id_hash");
+const TDH: Column = T("token_data_id_hash");
+
+const TABLES: &[TableSpec] = &[
+    TableSpec {
+        table: "account_token_activities",
+        primary_key: &[
+            "account_address",
+            "transaction_version",
+            "event_index",
+            "event_account_address",
+            "event_creation_number",
+            "event_sequence_number",
+            "token_index",
+        ],
+        columns: &[
+            TDH,
+            CDH,
+            A("account_address"),
+            A("creator_address"),
+            A("from_address"),
+            A("to_address"),
+        ],
+        summed: &[],
+        aggregated: &[],
+    },
+    TableSpec {
+        table: "collection_curations",
+        primary_key: &["collection_data_id_hash"],
+        columns: &[CDH],
+        summed: &[],
+        aggregated: &[],
+    },
+    TableSpec {
+        table: "collection_daily_reports",
+        primary_key: &[
+            "collection_data_id_hash",
+            "coin_type",
+            "market_address",
+            "report_date",
+        ],
+        columns: &[CDH],
+        summed: &["volume", "sales_count"],
+        aggregated: &[],
+    },
+    TableSpec {
+        table: "collection_datas",
+        primary_key: &["collection_data_id_hash", "transaction_version"],
+        columns: &[CDH, A("creator_address")],
+        summed: &[],
+        aggregated: &[],
+    },
+    TableSpec {
+        table: "collection_hold_durations",
+        primary_key: &["collection_data_id_hash"],
+        columns: &[CDH],
+        summed: &[],
+        aggregated: &[],
+    },
+    TableSpec {
+        table: "collection_leaderboard",
+        primary_key: &["collection_data_id_hash"],
+        columns: &[CDH, A("creator_address")],
+        summed: &[],
+        aggregated: &[],
+    },
+    TableSpec {
+        table: "collection_mints",
+        primary_key: &["transaction_version", "event_index"],
+        columns: &[TDH, CDH, A("creator_address"), A("minter_address")],
+        summed: &[],
+        aggregated: &[],
+    },
+    TableSpec {
+        table: "collection_price_candles",
+        primary_key: &[
+            "collection_data_id_hash",
+            "coin_type",
+            "market_address",
+            "interval_start",
+        ],
+        columns: &[CDH],
+        summed: &["volume", "sales_count"],
+        aggregated: &[],
+    },
+    TableSpec {
+        table: "collection_stats_snapshots",
+        primary_key: &["collection_data_id_hash", "snapshot_date"],
+        columns: &[CDH, A("creator_address")],
+        summed: &[],
+        aggregated: &[],
+    },
+    TableSpec {
+        table: "collection_trait_frequencies",
+        primary_key: &["collection_data_id_hash", "property_key", "property_value"],
+        columns: &[CDH],
+        summed: &[],
+        aggregated: &[],
+    },
+    TableSpec {
+        table: "collection_volumes",
+        primary_key: &["last_transaction_version", "event_index", "token_index"],
+        columns: &[CDH],
+        summed: &[],
+        aggregated: &[],
+    },
+    TableSpec {
+        table: "current_ans_sale_prices",
+        primary_key: &["domain"],
+        columns: &[TDH],
+        summed: &[],
+        aggregated: &[],
+    },
+    TableSpec {
+        table: "current_collection_best_offers",
+        primary_key: &["collection_data_id_hash"],
+        columns: &[CDH, A("buyer")],
+        summed: &[],
+        aggregated: &[],
+    },
+    TableSpec {
+        table: "current_collection_datas",
+        primary_key: &["collection_data_id_hash"],
+        columns: &[CDH, A("creator_address")],
+        summed: &[],
+        aggregated: &[],
+    },
+    TableSpec {
+        table: "current_collection_holder_counts",
+        primary_key: &["collection_data_id_hash"],
+        columns: &[CDH],
+        summed: &[],
+        aggregated: &[],
+    },
+    TableSpec {
+        table: "current_collection_mint_stats",
+        primary_key: &["collection_data_id_hash"],
+        columns: &[CDH],
+        summed: &["total_minted", "mint_volume_apt"],
+        aggregated: &[],
+    },
+    TableSpec {
+        table: "current_collection_offers",
+        primary_key: &["collection_data_id_hash", "buyer", "market_address"],
+        columns: &[CDH, A("buyer"), A("creator_address")],
+        summed: &[],
+        aggregated: &[],
+    },
+    TableSpec {
+        table: "current_collection_price_stats",
+        primary_key: &["collection_data_id_hash"],
+        columns: &[CDH],
+        // The merged median is stale as median_sale_count no longer matches sale_count, so the
+        // periodic pass recomputes it
+        summed: &["sale_count", "sum_price"],
+        aggregated: &[
+            ("all_time_high_price", "MAX(x.all_time_high_price)"),
+            (
+                "all_time_high_version",
+                "(ARRAY_AGG(x.all_time_high_version \
+                 ORDER BY x.all_time_high_price DESC, x.all_time_high_version))[1]",
+            ),
+            ("min_sale_price", "MIN(x.min_sale_price)"),
+        ],
+    },
+    TableSpec {
+        table: "current_collection_volumes",
+        primary_key: &["collection_data_id_hash"],
+        columns: &[CDH],
+        summed: &[
+            "volume",
+            "primary_volume",
+            "secondary_volume",
+            "volume_usd",
+            "volume_decimal",
+        ],
+        aggregated: &[],
+    },
+    TableSpec {
+        table: "current_marketplace_listings",
+        primary_key: &["token_data_id_hash"],
+        columns: &[TDH, CDH, A("creator_address"), A("seller")],
+        summed: &[],
+        aggregated: &[],
+    },
+    TableSpec {
+        table: "current_token_bids",
+        primary_key: &[
+            "token_data_id_hash",
+            "property_version",
+            "buyer",
+            "market_address",
+        ],
+        columns: &[TDH, CDH, A("buyer"), A("creator_address")],
+        summed: &[],
+        aggregated: &[],
+    },
+    TableSpec {
+        table: "current_token_datas",
+        primary_key: &["token_data_id_hash"],
+        columns: &[TDH, CDH, A("creator_address"), A("payee_address")],
+        summed: &[],
+        aggregated: &[],
+    },
+    TableSpec {
+        table: "current_token_ownerships",
+        primary_key: &["token_data_id_hash", "property_version", "owner_address"],
+        columns: &[
+            TDH,
+            CDH,
+            A("owner_address"),
+            A("creator_address"),
+            A("beneficial_owner"),
+        ],
+        summed: &[],
+        aggregated: &[],
+    },
+    TableSpec {
+        table: "current_token_pending_claims",
+        primary_key: &[
+            "token_data_id_hash",
+            "property_version",
+            "from_address",
+            "to_address",
+        ],
+        columns: &[
+            TDH,
+            CDH,
+            A("from_address"),
+            A("to_address"),
+            A("creator_address"),
+        ],
+        summed: &[],
+        aggregated: &[],
+    },
+    TableSpec {
+        table: "current_token_top_bids",
+        primary_key: &["token_data_id_hash", "property_version"],
+        columns: &[TDH, A("buyer")],
+        summed: &[],
+        aggregated: &[],
+    },
+    TableSpec {
+        table: "current_token_transfer_offers",
+        primary_key: &[
+            "token_data_id_hash",
+            "property_version",
+            "from_address",
+            "to_address",
+        ],
+        columns: &[
+            TDH,
+            CDH,
+            A("from_address"),
+            A("to_address"),
+            A("creator_address"),
+        ],
+        summed: &[],
+        aggregated: &[],
+    },
+    TableSpec {
+        table: "current_token_volumes",
+        primary_key: &["token_data_id_hash"],
+        columns: &[TDH],
+        summed: &["volume", "volume_decimal"],
+        aggregated: &[],
+    },
+    TableSpec {
+        table: "marketplace_collection_volumes",
+        primary_key: &["market_address", "collection_data_id_hash", "coin_type"],
+        columns: &[CDH],
+        summed: &["volume", "trade_count", "volume_usd", "volume_decimal"],
+        aggregated: &[],
+    },
+    TableSpec {
+        table: "marketplace_listing_price_changes",
+        primary_key: &["transaction_version", "event_index"],
+        columns: &[TDH, CDH, A("seller")],
+        summed: &[],
+        aggregated: &[],
+    },
+    TableSpec {
+        table: "nft_sales",
+        primary_key: &[
+            "transaction_version",
+            "event_account_address",
+            "event_creation_number",
+            "event_sequence_number",
+            "token_index",
+        ],
+        columns: &[TDH, CDH, A("creator_address"), A("seller"), A("buyer")],
+        summed: &[],
+        aggregated: &[],
+    },
+    TableSpec {
+        table: "nft_transaction_fees",
+        primary_key: &["transaction_version"],
+        columns: &[A("sender"), A("market_address")],
+        summed: &[],
+        aggregated: &[],
+    },
+    TableSpec {
+        table: "spam_collections",
+        primary_key: &["collection_data_id_hash"],
+        columns: &[CDH],
+        summed: &[],
+        aggregated: &[],
+    },
+    TableSpec {
+        table: "token_acquisitions",
+        primary_key: &["token_data_id_hash", "owner_address"],
+        columns: &[TDH, A("owner_address")],
+        summed: &[],
+        aggregated: &[],
+    },
+    TableSpec {
+        table: "token_activities",
+        primary_key: &[
+            "transaction_version",
+            "event_account_address",
+            "event_creation_number",
+            "event_sequence_number",
+            "event_index",
+            "token_index",
+        ],
+        columns: &[
+            TDH,
+            CDH,
+            A("creator_address"),
+            A("from_address"),
+            A("to_address"),
+        ],
+        summed: &[],
+        aggregated: &[],
+    },
+    TableSpec {
+        table: "token_datas",
+        primary_key: &["token_data_id_hash", "transaction_version"],
+        columns: &[TDH, CDH, A("creator_address"), A("payee_address")],
+        summed: &[],
+        aggregated: &[],
+    },
+    TableSpec {
+        table: "token_metadata_cache",
+        primary_key: &["token_data_id_hash"],
+        columns: &[TDH],
+        summed: &[],
+        aggregated: &[],
+    },
+    TableSpec {
+        table: "token_ownerships",
+        primary_key: &[
+            "token_data_id_hash",
+            "property_version",
+            "transaction_version",
+            "table_handle",
+        ],
+        columns: &[TDH, CDH, A("creator_address"), A("owner_address")],
+        summed: &[],
+        aggregated: &[],
+    },
+    TableSpec {
+        table: "token_properties_flat",
+        primary_key: &["token_data_id_hash", "property_key"],
+        columns: &[TDH, CDH],
+        summed: &[],
+        aggregated: &[],
+    },
+    TableSpec {
+        table: "token_property_mutations",
+        primary_key: &["transaction_version", "event_index"],
+        columns: &[
+            T("old_token_data_id_hash"),
+            T("new_token_data_id_hash"),
+            CDH,
+            A("creator_address"),
+        ],
+        summed: &[],
+        aggregated: &[],
+    },
+    TableSpec {
+        table: "token_volumes",
+        primary_key: &["last_transaction_version", "event_index", "token_index"],
+        columns: &[TDH],
+        summed: &[],
+        aggregated: &[],
+    },
+    TableSpec {
+        table: "tokens",
+        primary_key: &[
+            "token_data_id_hash",
+            "property_version",
+            "transaction_version",
+        ],
+        columns: &[TDH, CDH, A("creator_address")],
+        summed: &[],
+        aggregated: &[],
+    },
+    TableSpec {
+        table: "trader_leaderboard",
+        primary_key: &["trader_address"],
+        columns: &[A("trader_address")],
+        summed: &[],
+        aggregated: &[],
+    },
+    TableSpec {
+        table: "wallet_token_cost_basis",
+        primary_key: &["wallet_address", "token_data_id_hash"],
+        columns: &[A("wallet_address"), TDH],
+        summed: &["cost_basis", "amount"],
+        aggregated: &[],
+    },
+];
+
+/// Same as `util::standardize_address`, for a sql expression
+fn standardized_address(expression: &str) -> String {
+    format!(
+        "CASE WHEN {0} ~ '^0x[0-9a-fA-F]{{1,64}}$' \
+         THEN '0x' || lpad(lower(substr({0}, 3)), 64, '0') ELSE {0} END",
+        expression
+    )
+}
+
+/// Same as `util::hash_str`, for a sql expression
+fn hashed(expression: &str) -> String {
+    format!("encode(sha256(convert_to({}, 'UTF8')), 'hex')", expression)
+}
+
+fn remapped_hash(remap_table: &str, column: &str) -> String {
+    format!(
+        "COALESCE((SELECT new_hash FROM {} WHERE old_hash = {}), {})",
+        remap_table, column, column
+    )
+}
+
+/// Maps the hashes of ids with a short creator address to the hash of the padded id. `id_columns`
+/// are concatenated with '::' like the Display of the id type, starting with the creator. A hash
+/// is only remapped if it matches its id, which rules out rows with a truncated name. The remap is
+/// a regular table, kept until every table is rewritten: rewritten rows no longer show their old
+/// hash, so a run picking up after an interrupted one adds to the remap rather than rebuilding it.
+fn create_hash_remap(
+    conn: &mut PgConnection,
+    remap_table: &str,
+    hash_column: &str,
+    sources: &[&str],
+    id_columns: &[&str],
+) -> QueryResult<usize> {
+    let columns = std::iter::once(hash_column)
+        .chain(id_columns.iter().copied())
+        .collect::<Vec<&str>>()
+        .join(", ");
+    let ids = sources
+        .iter()
+        .map(|source| format!("SELECT DISTINCT {} FROM {}", columns, source))
+        .collect::<Vec<String>>()
+        .join(" UNION ");
+    let id_of = |creator: &str| {
+        std::iter::once(creator.to_string())
+            .chain(id_columns[1..].iter().map(|column| column.to_string()))
+            .collect::<Vec<String>>()
+            .join(" || '::' || ")
+    };
+    let creator = id_columns[0];
+    sql_query(format!(
+        "CREATE TABLE IF NOT EXISTS {} (
+            old_hash VARCHAR(64) PRIMARY KEY NOT NULL,
+            new_hash VARCHAR(64) NOT NULL
+        )",
+        remap_table,
+    ))
+    .execute(conn)?;
+    sql_query(format!(
+        "INSERT INTO {}
+        SELECT DISTINCT {} AS old_hash, {} AS new_hash
+        FROM ({}) ids
+        WHERE {} <> {} AND {} = {}
+        ON CONFLICT (old_hash) DO NOTHING",
+        remap_table,
+        hash_column,
+        hashed(&id_of(&standardized_address(creator))),
+        ids,
+        standardized_address(creator),
+        creator,
+        hash_column,
+        hashed(&id_of(creator)),
+    ))
+    .execute(conn)
+}
+
+/// Rewrites a table's columns. If that changes the primary key of some rows, the rows sharing a
+/// new key are merged first: the most recently written row survives (by last_transaction_version,
+/// then last_event_index, for the tables that have them), the summed and aggregated columns of the
+/// group are written to it and the others are deleted. Ties go to the row whose key doesn't change.
+fn normalize_table(conn: &mut PgConnection, spec: &TableSpec) -> QueryResult<usize> {
+    let is_changed = spec
+        .columns
+        .iter()
+        .map(Column::is_changed)
+        .collect::<Vec<String>>()
+        .join(" OR ");
+    let normalized_key = |column: &str| match spec.columns.iter().find(|c| c.name() == column) {
+        Some(c) => c.normalized(),
+        None => format!("t.{}", column),
+    };
+    let key_changes = spec
+        .primary_key
+        .iter()
+        .any(|column| spec.columns.iter().any(|c| c.name() == *column));
+
+    let mut num_rows = 0;
+    if key_changes {
+        let keys = (0..spec.primary_key.len())
+            .map(|i| format!("k{}", i))
+            .collect::<Vec<String>>();
+        let key_list = keys.join(", ");
+        let new_keys = spec
+            .primary_key
+            .iter()
+            .zip(&keys)
+            .map(|(column, key)| format!("{} AS {}", normalized_key(column), key))
+            .collect::<Vec<String>>()
+            .join(", ");
+        let keeps_key = spec
+            .primary_key
+            .iter()
+            .map(|column| format!("{} = t.{}", normalized_key(column), column))
+            .collect::<Vec<String>>()
+            .join(" AND ");
+        let joins_key = |alias: &str| {
+            spec.primary_key
+                .iter()
+                .zip(&keys)
+                .map(|(column, key)| format!("t.{} = {}.{}", column, alias, key))
+                .collect::<Vec<String>>()
+                .join(" AND ")
+        };
+        sql_query(format!(
+            "CREATE TEMP TABLE normalized_rows ON COMMIT DROP AS
+            WITH changed AS (
+                SELECT t.ctid AS row_id, {new_keys}, {keeps_key} AS keeps_key
+                FROM {table} t WHERE {is_changed}
+            )
+            SELECT * FROM changed
+            UNION ALL
+            SELECT t.ctid, {old_keys}, TRUE
+            FROM {table} t JOIN (SELECT DISTINCT {key_list} FROM changed) c ON {joins_key}
+            WHERE NOT ({is_changed})",
+            new_keys = new_keys,
+            keeps_key = keeps_key,
+            table = spec.table,
+            is_changed = is_changed,
+            old_keys = spec
+                .primary_key
+                .iter()
+                .map(|column| format!("t.{}", column))
+                .collect::<Vec<String>>()
+                .join(", "),
+            key_list = key_list,
+            joins_key = joins_key("c"),
+        ))
+        .execute(conn)?;
+        let recency = sql_query(format!(
+            "SELECT attname::TEXT AS column_name FROM pg_attribute
+            WHERE attrelid = '{}'::regclass AND attnum > 0 AND NOT attisdropped",
+            spec.table,
+        ))
+        .load::<ColumnName>(conn)?
+        .into_iter()
+        .map(|column| column.column_name)
+        .collect::<Vec<String>>();
+        let recency = RECENCY_COLUMNS
+            .iter()
+            .filter(|column| recency.iter().any(|name| name == *column))
+            .map(|column| format!("x.{} DESC, ", column))
+            .collect::<String>();
+        sql_query(format!(
+            "CREATE TEMP TABLE surviving_rows ON COMMIT DROP AS
+            SELECT DISTINCT ON ({r_key_list}) r.row_id, {r_key_list}
+            FROM normalized_rows r JOIN {table} x ON x.ctid = r.row_id
+            ORDER BY {r_key_list}, {recency}r.keeps_key DESC, r.row_id DESC",
+            r_key_list = keys
+                .iter()
+                .map(|key| format!("r.{}", key))
+                .collect::<Vec<String>>()
+                .join(", "),
+            table = spec.table,
+            recency = recency,
+        ))
+        .execute(conn)?;
+        let merged = spec
+            .summed
+            .iter()
+            .map(|column| (*column, format!("SUM(x.{})", column)))
+            .chain(
+                spec.aggregated
+                    .iter()
+                    .map(|(column, expression)| (*column, expression.to_string())),
+            )
+            .collect::<Vec<(&str, String)>>();
+        if !merged.is_empty() {
+            num_rows += sql_query(format!(
+                "UPDATE {table} t SET {set_sums}
+                FROM (
+                    SELECT {group_keys}, {sums}
+                    FROM normalized_rows r JOIN {table} x ON x.ctid = r.row_id
+                    GROUP BY {group_keys}
+                ) g, surviving_rows s
+                WHERE t.ctid = s.row_id AND {joins_group}",
+                table = spec.table,
+                set_sums = merged
+                    .iter()
+                    .map(|(column, _)| format!("{0} = g.{0}", column))
+                    .collect::<Vec<String>>()
+                    .join(", "),
+                group_keys = keys
+                    .iter()
+                    .map(|key| format!("r.{}", key))
+                    .collect::<Vec<String>>()
+                    .join(", "),
+                sums = merged
+                    .iter()
+                    .map(|(column, expression)| format!("{} AS {}", expression, column))
+                    .collect::<Vec<String>>()
+                    .join(", "),
+                joins_group = keys
+                    .iter()
+                    .map(|key| format!("s.{0} = g.{0}", key))
+                    .collect::<Vec<String>>()
+                    .join(" AND "),
+            ))
+            .execute(conn)?;
+        }
+        num_rows += sql_query(format!(
+            "DELETE FROM {} t USING normalized_rows r
+            WHERE t.ctid = r.row_id AND r.row_id NOT IN (SELECT row_id FROM surviving_rows)",
+            spec.table,
+        ))
+        .execute(conn)?;
+        sql_query("DROP TABLE normalized_rows, surviving_rows").execute(conn)?;
+    }
+    num_rows += sql_query(format!(
+        "UPDATE {} t SET {} WHERE {}",
+        spec.table,
+        spec.columns
+            .iter()
+            .map(|column| format!("{} = {}", column.name(), column.normalized()))
+            .collect::<Vec<String>>()
+            .join(", "),
+        is_changed,
+    ))
+    .execute(conn)?;
+    Ok(num_rows)
+}
+
+/// Pads every stored creator, owner, buyer and seller address and moves rows stored under the
+/// hash of a short creator address to the hash of the padded one, returning the number of rows
+/// changed. Each table is rewritten in its own transaction, so a large database isn't rewritten in
+/// one, and a run that stops halfway picks up where it left off when run again. Holder counts and
+/// rarity ranks aren't merged, so run `recompute-holder-counts` and `recompute-rarity` afterwards.
+pub fn normalize_addresses(conn: &mut PgConnection) -> QueryResult<usize> {
+    let (num_tokens, num_collections) =
+        conn.build_transaction()
+            .read_write()
+            .run::<_, Error, _>(|pg_conn| {
+                let num_tokens = create_hash_remap(
+                    pg_conn,
+                    "token_hash_remap",
+                    "token_data_id_hash",
+                    &[
+                        "current_token_datas",
+                        "token_activities",
+                        "nft_sales",
+                        "current_token_ownerships",
+                    ],
+                    &["creator_address", "collection_name", "name"],
+                )?;
+                let num_collections = create_hash_remap(
+                    pg_conn,
+                    "collection_hash_remap",
+                    "collection_data_id_hash",
+                    &[
+                        "current_collection_datas",
+                        "token_activities",
+                        "nft_sales",
+                        "collection_mints",
+                    ],
+                    &["creator_address", "collection_name"],
+                )?;
+                Ok((num_tokens, num_collections))
+            })?;
+    aptos_logger::info!(
+        num_tokens = num_tokens,
+        num_collections = num_collections,
+        "Found ids hashed with a short creator address"
+    );
+    let mut num_rows = 0;
+    for spec in TABLES {
+        let num_table_rows = conn
+            .build_transaction()
+            .read_write()
+            .run::<_, Error, _>(|pg_conn| normalize_table(pg_conn, spec))?;
+        aptos_logger::info!(
+            table = spec.table,
+            num_rows = num_table_rows,
+            "Normalized table"
+        );
+        num_rows += num_table_rows;
+    }
+    sql_query("DROP TABLE token_hash_remap, collection_hash_remap").execute(conn)?;
+    Ok(num_rows)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        database::{new_db_pool, PgPoolConnection},
+        indexer::tailer::{test::wipe_database, MIGRATIONS},
+        schema::current_collection_volumes,
+        util::{hash_str, standardize_address},
+    };
+    use bigdecimal::BigDecimal;
+    use diesel::{sql_types::BigInt, QueryDsl};
+    use diesel_migrations::MigrationHarness;
+
+    fn setup() -> PgPoolConnection {
+        let database_url = std::env::var("INDEXER_DATABASE_URL")
+            .expect("must set 'INDEXER_DATABASE_URL' to run tests!");
+        let conn_pool = new_db_pool(database_url.as_str()).unwrap();
+        let mut conn = conn_pool.get().unwrap();
+        wipe_database(&mut conn);
+        conn.run_pending_migrations(MIGRATIONS).unwrap();
+        conn
+    }
+
+    fn insert_collection(conn: &mut PgConnection, creator: &str, volume: i64) -> String {
+        let hash = hash_str(&format!("{}::Potions", creator));
+        sql_query(format!(
+            "INSERT INTO current_collection_datas (
+                collection_data_id_hash, creator_address, collection_name, description,
+                metadata_uri, supply, maximum, maximum_mutable, uri_mutable, description_mutable,
+                last_transaction_version, table_handle, last_transaction_timestamp
+            ) VALUES ('{}', '{}', 'Potions', '', '', 1, 1, false, false, false, {}, '0x7', NOW())",
+            hash, creator, volume,
+        ))
+        .execute(conn)
+        .unwrap();
+        sql_query(format!(
+            "INSERT INTO current_collection_volumes (
+                collection_data_id_hash, volume, last_transaction_version, primary_volume,
+                secondary_volume, last_transaction_timestamp
+            ) VALUES ('{0}', {1}, {1}, 0, {1}, NOW())",
+            hash, volume,
+        ))
+        .execute(conn)
+        .unwrap();
+        hash
+    }
+
+    #[test]
+    fn test_table_specs() {
+        // A table listed twice would be normalized twice, and summed columns can't be rewritten
+        for pair in TABLES.windows(2) {
+            assert!(pair[0].table < pair[1].table, "{}", pair[1].table);
+        }
+        for spec in TABLES {
+            let merged = spec.aggregated.iter().map(|(column, _)| column);
+            for column in spec.summed.iter().chain(merged) {
+                assert!(!spec.primary_key.contains(column), "{}", spec.table);
+                assert!(spec.columns.iter().all(|c| c.name() != *column));
+            }
+        }
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn test_normalize_addresses_merges_split_collections() {
+        if crate::should_skip_pg_tests() {
+            return;
+        }
+        let mut conn = setup();
+        let creator = standardize_address("0xc4e7");
+        let long_hash = insert_collection(&mut conn, &creator, 100);
+        insert_collection(&mut conn, "0xc4e7", 20);
+        insert_collection(&mut conn, "0xC4E7", 3);
+
+        // The 2 short collection datas and volumes are deleted, after adding up the volumes
+        assert_eq!(normalize_addresses(&mut conn).unwrap(), 5);
+        let volumes = current_collection_volumes::table
+            .select((
+                current_collection_volumes::collection_data_id_hash,
+                current_collection_volumes::volume,
+                current_collection_volumes::last_transaction_version,
+            ))
+            .load::<(String, BigDecimal, i64)>(&mut conn)
+            .unwrap();
+        assert_eq!(volumes, vec![(long_hash, BigDecimal::from(123), 100)]);
+
+        // Nothing left to normalize
+        assert_eq!(normalize_addresses(&mut conn).unwrap(), 0);
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn test_normalize_addresses_adds_up_decimal_volumes() {
+        if crate::should_skip_pg_tests() {
+            return;
+        }
+        let mut conn = setup();
+        let long_hash = insert_collection(&mut conn, &standardize_address("0xc4e7"), 100);
+        let short_hash = insert_collection(&mut conn, "0xc4e7", 20);
+        for (hash, volume_usd, volume_decimal) in [
+            (&long_hash, "5.5", "0.000001"),
+            (&short_hash, "1.25", "0.0000002"),
+        ] {
+            sql_query(format!(
+                "UPDATE current_collection_volumes SET volume_usd = {}, volume_decimal = {}
+                WHERE collection_data_id_hash = '{}'",
+                volume_usd, volume_decimal, hash,
+            ))
+            .execute(&mut conn)
+            .unwrap();
+        }
+
+        normalize_addresses(&mut conn).unwrap();
+        let volumes = current_collection_volumes::table
+            .select((
+                current_collection_volumes::collection_data_id_hash,
+                current_collection_volumes::volume_usd,
+                current_collection_volumes::volume_decimal,
+            ))
+            .load::<(String, Option<BigDecimal>, Option<BigDecimal>)>(&mut conn)
+            .unwrap();
+        assert_eq!(
+            volumes,
+            vec![(
+                long_hash,
+                Some("6.75".parse().unwrap()),
+                Some("0.0000012".parse().unwrap()),
+            )]
+        );
+    }
+
+    #[derive(Debug, PartialEq, QueryableByName)]
+    struct PriceStat {
+        #[diesel(sql_type = Text)]
+        collection_data_id_hash: String,
+        #[diesel(sql_type = BigInt)]
+        all_time_high_version: i64,
+        #[diesel(sql_type = BigInt)]
+        sale_count: i64,
+        #[diesel(sql_type = BigInt)]
+        median_sale_count: i64,
+        #[diesel(sql_type = BigInt)]
+        last_transaction_version: i64,
+        #[diesel(sql_type = Text)]
+        high: String,
+        #[diesel(sql_type = Text)]
+        min: String,
+        #[diesel(sql_type = Text)]
+        sum: String,
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn test_normalize_addresses_merges_price_stats() {
+        if crate::should_skip_pg_tests() {
+            return;
+        }
+        let mut conn = setup();
+        let long_hash = insert_collection(&mut conn, &standardize_address("0xc4e7"), 100);
+        let short_hash = insert_collection(&mut conn, "0xc4e7", 20);
+        for (hash, high, high_version, min, count, sum, version) in [
+            (&long_hash, 50, 7, 10, 3, 90, 100),
+            (&short_hash, 80, 12, 4, 2, 84, 20),
+        ] {
+            sql_query(format!(
+                "INSERT INTO current_collection_price_stats (
+                    collection_data_id_hash, all_time_high_price, all_time_high_version,
+                    min_sale_price, sale_count, sum_price, median_price, median_sale_count,
+                    last_transaction_version, last_event_index
+                ) VALUES ('{}', {}, {}, {}, {}, {}, 30, {}, {}, 0)",
+                hash, high, high_version, min, count, sum, count, version,
+            ))
+            .execute(&mut conn)
+            .unwrap();
+        }
+
+        normalize_addresses(&mut conn).unwrap();
+        let stats = sql_query(
+            "SELECT collection_data_id_hash, all_time_high_version, sale_count,
+                median_sale_count, last_transaction_version,
+                all_time_high_price::TEXT AS high, min_sale_price::TEXT AS min,
+                sum_price::TEXT AS sum
+            FROM current_collection_price_stats",
+        )
+        .load::<PriceStat>(&mut conn)
+        .unwrap();
+        assert_eq!(
+            stats,
+            vec![PriceStat {
+                collection_data_id_hash: long_hash,
+                all_time_high_version: 12,
+                sale_count: 5,
+                // Stale, so the median gets recomputed
+                median_sale_count: 3,
+                last_transaction_version: 100,
+                high: "80".to_string(),
+                min: "4".to_string(),
+                sum: "174".to_string(),
+            }]
+        );
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn test_normalize_addresses_keeps_latest_row() {
+        if crate::should_skip_pg_tests() {
+            return;
+        }
+        let mut conn = setup();
+        let long_hash = insert_collection(&mut conn, &standardize_address("0xc4e7"), 20);
+        insert_collection(&mut conn, "0xc4e7", 100);
+
+        // The short row was written last, so it survives with the volumes added up
+        normalize_addresses(&mut conn).unwrap();
+        let volumes = current_collection_volumes::table
+            .select((
+                current_collection_volumes::collection_data_id_hash,
+                current_collection_volumes::volume,
+                current_collection_volumes::last_transaction_version,
+            ))
+            .load::<(String, BigDecimal, i64)>(&mut conn)
+            .unwrap();
+        assert_eq!(volumes, vec![(long_hash, BigDecimal::from(120), 100)]);
+    }
+}
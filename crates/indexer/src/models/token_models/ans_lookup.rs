@@ -8,10 +8,15 @@
 use std::collections::HashMap;
 
 use crate::{
-    schema::current_ans_lookup,
-    util::{bigdecimal_to_u64, parse_timestamp_secs},
+    schema::{current_ans_lookup, current_ans_primary_name},
+    util::{bigdecimal_to_u64, parse_timestamp, parse_timestamp_secs},
 };
-use aptos_api_types::{deserialize_from_string, MoveType, Transaction as APITransaction};
+use aptos_api_types::{
+    deserialize_from_string, DeleteTableItem as APIDeleteTableItem, MoveType,
+    Transaction as APITransaction, WriteSetChange as APIWriteSetChange,
+    WriteTableItem as APIWriteTableItem,
+};
+use aptos_config::config::AnsContractConfig;
 use bigdecimal::BigDecimal;
 use field_count::FieldCount;
 use serde::{Deserialize, Serialize};
@@ -20,8 +25,12 @@ type Domain = String;
 type Subdomain = String;
 // PK of current_ans_lookup, i.e. domain and subdomain name
 pub type CurrentAnsLookupPK = (Domain, Subdomain);
+// PK of current_ans_primary_name, i.e. the address that set its primary name
+pub type CurrentAnsPrimaryNamePK = String;
 
-#[derive(Debug, Deserialize, FieldCount, Identifiable, Insertable, Serialize)]
+#[derive(
+    Debug, Deserialize, FieldCount, Identifiable, Insertable, Queryable, Selectable, Serialize,
+)]
 #[diesel(primary_key(domain, subdomain))]
 #[diesel(table_name = current_ans_lookup)]
 pub struct CurrentAnsLookup {
@@ -30,11 +39,107 @@ pub struct CurrentAnsLookup {
     pub registered_address: Option<String>,
     pub last_transaction_version: i64,
     pub expiration_timestamp: chrono::NaiveDateTime,
+    /// The v2 name record was removed, which leaves the name unregistered. A name that expires
+    /// without being removed keeps its row, expiration_timestamp tells it's expired.
+    pub is_deleted: bool,
+}
+
+/// Reverse lookup, i.e. the primary name an address has chosen. A cleared primary name is kept
+/// as a row with null domain and subdomain so that the previous name doesn't linger.
+#[derive(
+    Debug, Deserialize, FieldCount, Identifiable, Insertable, Queryable, Selectable, Serialize,
+)]
+#[diesel(primary_key(registered_address))]
+#[diesel(table_name = current_ans_primary_name)]
+pub struct CurrentAnsPrimaryName {
+    pub registered_address: String,
+    pub domain: Option<String>,
+    pub subdomain: Option<String>,
+    pub last_transaction_version: i64,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum AnsVersion {
+    V1,
+    V2,
+}
+
+/// An ans contract to index. v2 moved to the `v2_domains` module, changed the name record table
+/// layout, and added renewal events.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct AnsContract {
+    pub address: String,
+    pub version: AnsVersion,
+}
+
+impl AnsContract {
+    /// Combines the legacy `ans_contract_address` (always v1) with `ans_contracts`
+    pub fn from_config(
+        ans_contract_address: Option<&String>,
+        ans_contracts: &[AnsContractConfig],
+    ) -> anyhow::Result<Vec<Self>> {
+        let mut contracts: Vec<Self> = ans_contract_address
+            .map(|address| Self {
+                address: address.clone(),
+                version: AnsVersion::V1,
+            })
+            .into_iter()
+            .collect();
+        for contract in ans_contracts {
+            let version = match contract.version {
+                1 => AnsVersion::V1,
+                2 => AnsVersion::V2,
+                _ => anyhow::bail!(
+                    "unsupported version {} for ans contract {}",
+                    contract.version,
+                    contract.address
+                ),
+            };
+            contracts.push(Self {
+                address: contract.address.clone(),
+                version,
+            });
+        }
+        Ok(contracts)
+    }
 }
 
 pub enum ANSEvent {
     SetNameAddressEventV1(SetNameAddressEventV1),
     RegisterNameEventV1(RegisterNameEventV1),
+    SetReverseLookupEventV1(SetReverseLookupEventV1),
+    RenewNameEventV2(RenewNameEventV2),
+    SetReverseLookupEventV2(SetReverseLookupEventV1),
+}
+
+impl ANSEvent {
+    fn from_event(
+        version: AnsVersion,
+        event_type: &str,
+        data: &serde_json::Value,
+    ) -> serde_json::Result<Option<Self>> {
+        match (version, event_type) {
+            (AnsVersion::V1, "domains::SetNameAddressEventV1") => {
+                serde_json::from_value(data.clone())
+                    .map(|inner| Some(Self::SetNameAddressEventV1(inner)))
+            }
+            (AnsVersion::V1, "domains::RegisterNameEventV1") => {
+                serde_json::from_value(data.clone())
+                    .map(|inner| Some(Self::RegisterNameEventV1(inner)))
+            }
+            (AnsVersion::V1, "domains::SetReverseLookupEventV1") => {
+                serde_json::from_value(data.clone())
+                    .map(|inner| Some(Self::SetReverseLookupEventV1(inner)))
+            }
+            (AnsVersion::V2, "v2_domains::RenewNameEvent") => serde_json::from_value(data.clone())
+                .map(|inner| Some(Self::RenewNameEventV2(inner))),
+            (AnsVersion::V2, "v2_domains::SetReverseLookupEvent") => {
+                serde_json::from_value(data.clone())
+                    .map(|inner| Some(Self::SetReverseLookupEventV2(inner)))
+            }
+            _ => Ok(None),
+        }
+    }
 }
 
 #[derive(Serialize, Deserialize, Debug, Clone)]
@@ -54,6 +159,36 @@ pub struct RegisterNameEventV1 {
     expiration_time_secs: BigDecimal,
 }
 
+/// v2 emits the same fields (plus the previous name, which we don't need)
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct SetReverseLookupEventV1 {
+    account_addr: String,
+    curr_domain_name: OptionalString,
+    curr_subdomain_name: OptionalString,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct RenewNameEventV2 {
+    domain_name: String,
+    subdomain_name: OptionalString,
+    #[serde(deserialize_with = "deserialize_from_string")]
+    expiration_time_secs: BigDecimal,
+    target_address: OptionalString,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct NameRecordKeyV2 {
+    domain_name: String,
+    subdomain_name: OptionalString,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct NameRecordV2 {
+    #[serde(deserialize_with = "deserialize_from_string")]
+    expiration_time_sec: BigDecimal,
+    target_address: OptionalString,
+}
+
 #[derive(Serialize, Deserialize, Debug, Clone)]
 struct OptionalString {
     vec: Vec<String>,
@@ -70,79 +205,137 @@ impl OptionalString {
 }
 
 impl CurrentAnsLookup {
+    /// Returns both forward lookups (name -> address) and primary names (address -> name)
     pub fn from_transaction(
         transaction: &APITransaction,
-        ans_contract_address: Option<String>,
-    ) -> HashMap<CurrentAnsLookupPK, Self> {
+        ans_contracts: &[AnsContract],
+    ) -> (
+        HashMap<CurrentAnsLookupPK, Self>,
+        HashMap<CurrentAnsPrimaryNamePK, CurrentAnsPrimaryName>,
+    ) {
         let mut current_ans_lookups: HashMap<CurrentAnsLookupPK, Self> = HashMap::new();
-        if let Some(addr) = ans_contract_address {
-            if let APITransaction::UserTransaction(user_txn) = transaction {
-                for event in &user_txn.events {
-                    let (event_addr, event_type) = if let MoveType::Struct(inner) = &event.typ {
-                        (
-                            inner.address.to_string(),
-                            format!("{}::{}", inner.module, inner.name),
-                        )
-                    } else {
-                        continue;
-                    };
-                    if event_addr != addr {
-                        continue;
-                    }
-                    let txn_version = user_txn.info.version.0 as i64;
-                    let maybe_ans_event = match event_type.as_str() {
-                        "domains::SetNameAddressEventV1" => {
-                            serde_json::from_value(event.data.clone())
-                                .map(|inner| Some(ANSEvent::SetNameAddressEventV1(inner)))
-                        }
-                        "domains::RegisterNameEventV1" => {
-                            serde_json::from_value(event.data.clone())
-                                .map(|inner| Some(ANSEvent::RegisterNameEventV1(inner)))
+        let mut current_ans_primary_names: HashMap<CurrentAnsPrimaryNamePK, CurrentAnsPrimaryName> =
+            HashMap::new();
+        if ans_contracts.is_empty() {
+            return (current_ans_lookups, current_ans_primary_names);
+        }
+        if let APITransaction::UserTransaction(user_txn) = transaction {
+            let txn_version = user_txn.info.version.0 as i64;
+            for event in &user_txn.events {
+                let (event_addr, event_type) = if let MoveType::Struct(inner) = &event.typ {
+                    (
+                        inner.address.to_string(),
+                        format!("{}::{}", inner.module, inner.name),
+                    )
+                } else {
+                    continue;
+                };
+                let ans_contract = match ans_contracts
+                    .iter()
+                    .find(|contract| contract.address == event_addr)
+                {
+                    Some(ans_contract) => ans_contract,
+                    None => continue,
+                };
+                let maybe_ans_event =
+                    ANSEvent::from_event(ans_contract.version, &event_type, &event.data)
+                        .unwrap_or_else(|e| {
+                            panic!(
+                        "version {} failed! failed to parse type {}, data {:?}. Error: {:?}",
+                        txn_version, event_type, event.data, e
+                    )
+                        });
+                if let Some(ans_event) = maybe_ans_event {
+                    let current_ans_lookup = match ans_event {
+                        ANSEvent::SetNameAddressEventV1(inner) => {
+                            let expiration_timestamp = parse_timestamp_secs(
+                                bigdecimal_to_u64(&inner.expiration_time_secs),
+                                txn_version,
+                            );
+                            Self {
+                                domain: inner.domain_name,
+                                subdomain: inner.subdomain_name.get_string().unwrap_or_default(),
+                                registered_address: inner.new_address.get_string(),
+                                last_transaction_version: txn_version,
+                                expiration_timestamp,
+                                is_deleted: false,
+                            }
                         }
-                        _ => Ok(None),
-                    }
-                    .unwrap_or_else(|e| {
-                        panic!(
-                            "version {} failed! failed to parse type {}, data {:?}. Error: {:?}",
-                            txn_version, event_type, event.data, e
-                        )
-                    });
-                    if let Some(ans_event) = maybe_ans_event {
-                        let current_ans_lookup = match ans_event {
-                            ANSEvent::SetNameAddressEventV1(inner) => {
-                                let expiration_timestamp = parse_timestamp_secs(
-                                    bigdecimal_to_u64(&inner.expiration_time_secs),
-                                    txn_version,
-                                );
-                                Self {
-                                    domain: inner.domain_name,
-                                    subdomain: inner
-                                        .subdomain_name
-                                        .get_string()
-                                        .unwrap_or_default(),
-                                    registered_address: inner.new_address.get_string(),
-                                    last_transaction_version: txn_version,
-                                    expiration_timestamp,
-                                }
+                        ANSEvent::RegisterNameEventV1(inner) => {
+                            let expiration_timestamp = parse_timestamp_secs(
+                                bigdecimal_to_u64(&inner.expiration_time_secs),
+                                txn_version,
+                            );
+                            Self {
+                                domain: inner.domain_name,
+                                subdomain: inner.subdomain_name.get_string().unwrap_or_default(),
+                                registered_address: None,
+                                last_transaction_version: txn_version,
+                                expiration_timestamp,
+                                is_deleted: false,
                             }
-                            ANSEvent::RegisterNameEventV1(inner) => {
-                                let expiration_timestamp = parse_timestamp_secs(
-                                    bigdecimal_to_u64(&inner.expiration_time_secs),
-                                    txn_version,
-                                );
-                                Self {
-                                    domain: inner.domain_name,
-                                    subdomain: inner
-                                        .subdomain_name
-                                        .get_string()
-                                        .unwrap_or_default(),
-                                    registered_address: None,
-                                    last_transaction_version: txn_version,
-                                    expiration_timestamp,
-                                }
+                        }
+                        // Renewals carry the current target, so the whole row can be replaced
+                        ANSEvent::RenewNameEventV2(inner) => {
+                            let expiration_timestamp = parse_timestamp_secs(
+                                bigdecimal_to_u64(&inner.expiration_time_secs),
+                                txn_version,
+                            );
+                            Self {
+                                domain: inner.domain_name,
+                                subdomain: inner.subdomain_name.get_string().unwrap_or_default(),
+                                registered_address: inner.target_address.get_string(),
+                                last_transaction_version: txn_version,
+                                expiration_timestamp,
+                                is_deleted: false,
                             }
-                        };
+                        }
+                        ANSEvent::SetReverseLookupEventV1(inner)
+                        | ANSEvent::SetReverseLookupEventV2(inner) => {
+                            let current_ans_primary_name =
+                                CurrentAnsPrimaryName::from_event(inner, txn_version);
+                            current_ans_primary_names.insert(
+                                current_ans_primary_name.registered_address.clone(),
+                                current_ans_primary_name,
+                            );
+                            continue;
+                        }
+                    };
 
+                    current_ans_lookups.insert(
+                        (
+                            current_ans_lookup.domain.clone(),
+                            current_ans_lookup.subdomain.clone(),
+                        ),
+                        current_ans_lookup,
+                    );
+                }
+            }
+            // v2 registrations, target changes and removals are only visible through the name
+            // record table
+            let txn_timestamp = parse_timestamp(user_txn.timestamp.0, txn_version);
+            for wsc in &user_txn.info.changes {
+                for ans_contract in ans_contracts
+                    .iter()
+                    .filter(|contract| contract.version == AnsVersion::V2)
+                {
+                    let maybe_ans_lookup = match wsc {
+                        APIWriteSetChange::WriteTableItem(table_item) => Self::from_name_record_v2(
+                            table_item,
+                            &ans_contract.address,
+                            txn_version,
+                        ),
+                        APIWriteSetChange::DeleteTableItem(table_item) => {
+                            Self::from_deleted_name_record_v2(
+                                table_item,
+                                &ans_contract.address,
+                                txn_version,
+                                txn_timestamp,
+                            )
+                        }
+                        _ => None,
+                    };
+                    if let Some(current_ans_lookup) = maybe_ans_lookup {
                         current_ans_lookups.insert(
                             (
                                 current_ans_lookup.domain.clone(),
@@ -154,6 +347,289 @@ impl CurrentAnsLookup {
                 }
             }
         }
-        current_ans_lookups
+        (current_ans_lookups, current_ans_primary_names)
+    }
+
+    fn from_name_record_v2(
+        table_item: &APIWriteTableItem,
+        ans_contract_address: &str,
+        txn_version: i64,
+    ) -> Option<Self> {
+        let table_item_data = table_item.data.as_ref()?;
+        if table_item_data.key_type
+            != format!("{}::v2_domains::NameRecordKeyV2", ans_contract_address)
+            || table_item_data.value_type
+                != format!("{}::v2_domains::NameRecordV2", ans_contract_address)
+        {
+            return None;
+        }
+        let (key, value): (NameRecordKeyV2, NameRecordV2) =
+            serde_json::from_value(table_item_data.key.clone())
+                .and_then(|key| {
+                    serde_json::from_value(table_item_data.value.clone()).map(|value| (key, value))
+                })
+                .unwrap_or_else(|e| {
+                    panic!(
+                        "version {} failed! failed to parse name record, data {:?}. Error: {:?}",
+                        txn_version, table_item_data, e
+                    )
+                });
+        Some(Self {
+            domain: key.domain_name,
+            subdomain: key.subdomain_name.get_string().unwrap_or_default(),
+            registered_address: value.target_address.get_string(),
+            last_transaction_version: txn_version,
+            expiration_timestamp: parse_timestamp_secs(
+                bigdecimal_to_u64(&value.expiration_time_sec),
+                txn_version,
+            ),
+            is_deleted: false,
+        })
+    }
+
+    /// The removed record's value isn't in the write set, so the name counts as expired from the
+    /// removal on
+    fn from_deleted_name_record_v2(
+        table_item: &APIDeleteTableItem,
+        ans_contract_address: &str,
+        txn_version: i64,
+        txn_timestamp: chrono::NaiveDateTime,
+    ) -> Option<Self> {
+        let table_item_data = table_item.data.as_ref()?;
+        if table_item_data.key_type
+            != format!("{}::v2_domains::NameRecordKeyV2", ans_contract_address)
+        {
+            return None;
+        }
+        let key: NameRecordKeyV2 = serde_json::from_value(table_item_data.key.clone())
+            .unwrap_or_else(|e| {
+                panic!(
+                    "version {} failed! failed to parse name record key, data {:?}. Error: {:?}",
+                    txn_version, table_item_data, e
+                )
+            });
+        Some(Self {
+            domain: key.domain_name,
+            subdomain: key.subdomain_name.get_string().unwrap_or_default(),
+            registered_address: None,
+            last_transaction_version: txn_version,
+            expiration_timestamp: txn_timestamp,
+            is_deleted: true,
+        })
+    }
+}
+
+impl CurrentAnsPrimaryName {
+    fn from_event(event: SetReverseLookupEventV1, txn_version: i64) -> Self {
+        let domain = event.curr_domain_name.get_string();
+        // Clearing the primary name leaves a tombstone with both domain and subdomain null
+        let subdomain = domain
+            .as_ref()
+            .map(|_| event.curr_subdomain_name.get_string().unwrap_or_default());
+        Self {
+            registered_address: event.account_addr,
+            domain,
+            subdomain,
+            last_transaction_version: txn_version,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    const ANS_V1_ADDRESS: &str = "0x867e";
+    const ANS_V2_ADDRESS: &str = "0x2a5e";
+    const HASH: &str = "0x6a527d06063dfd42c6b3a862574d5f3ec1660afb8058135edda5072712bfdb51";
+
+    fn ans_contracts() -> Vec<AnsContract> {
+        AnsContract::from_config(
+            Some(&ANS_V1_ADDRESS.to_string()),
+            &[AnsContractConfig {
+                address: ANS_V2_ADDRESS.to_string(),
+                version: 2,
+            }],
+        )
+        .unwrap()
+    }
+
+    fn user_txn(
+        version: u64,
+        events: serde_json::Value,
+        changes: serde_json::Value,
+    ) -> APITransaction {
+        serde_json::from_value(json!({
+            "type": "user_transaction",
+            "version": version.to_string(),
+            "hash": HASH,
+            "state_change_hash": HASH,
+            "event_root_hash": HASH,
+            "state_checkpoint_hash": null,
+            "gas_used": "100",
+            "success": true,
+            "vm_status": "Executed successfully",
+            "accumulator_root_hash": HASH,
+            "changes": changes,
+            "sender": "0xa11ce",
+            "sequence_number": "0",
+            "max_gas_amount": "2000",
+            "gas_unit_price": "100",
+            "expiration_timestamp_secs": "1668000600",
+            "payload": {
+                "type": "entry_function_payload",
+                "function": "0x1::aptos_account::transfer",
+                "type_arguments": [],
+                "arguments": []
+            },
+            "events": events,
+            "timestamp": "1668000000000000"
+        }))
+        .unwrap()
+    }
+
+    fn event(address: &str, typ: &str, data: serde_json::Value) -> serde_json::Value {
+        json!({
+            "guid": {"creation_number": "3", "account_address": address},
+            "sequence_number": "0",
+            "type": format!("{}::{}", address, typ),
+            "data": data
+        })
+    }
+
+    fn name_record_v2(domain: &str, target_address: &str, expiration: u64) -> serde_json::Value {
+        json!({
+            "type": "write_table_item",
+            "state_key_hash": HASH,
+            "handle": "0x1234",
+            "key": "0x00",
+            "value": "0x00",
+            "data": {
+                "key": {"domain_name": domain, "subdomain_name": {"vec": []}},
+                "key_type": format!("{}::v2_domains::NameRecordKeyV2", ANS_V2_ADDRESS),
+                "value": {
+                    "expiration_time_sec": expiration.to_string(),
+                    "target_address": {"vec": [target_address]},
+                    "registration_time_sec": "1668000000"
+                },
+                "value_type": format!("{}::v2_domains::NameRecordV2", ANS_V2_ADDRESS)
+            }
+        })
+    }
+
+    #[test]
+    fn test_v1_registration() {
+        let txn = user_txn(
+            1,
+            json!([event(
+                ANS_V1_ADDRESS,
+                "domains::RegisterNameEventV1",
+                json!({
+                    "domain_name": "alice",
+                    "subdomain_name": {"vec": []},
+                    "expiration_time_secs": "1699536000",
+                    "registration_fee_octas": "100000000"
+                })
+            )]),
+            json!([]),
+        );
+        let (lookups, _) = CurrentAnsLookup::from_transaction(&txn, &ans_contracts());
+        let lookup = &lookups[&("alice".to_string(), "".to_string())];
+        assert_eq!(lookup.registered_address, None);
+        assert_eq!(lookup.expiration_timestamp.timestamp(), 1699536000);
+        assert!(!lookup.is_deleted);
+    }
+
+    #[test]
+    fn test_v2_registration_and_renewal() {
+        let txn = user_txn(
+            1,
+            json!([]),
+            json!([name_record_v2("bob", "0xb0b", 1699536000)]),
+        );
+        let (lookups, _) = CurrentAnsLookup::from_transaction(&txn, &ans_contracts());
+        let lookup = &lookups[&("bob".to_string(), "".to_string())];
+        assert_eq!(lookup.registered_address, Some("0xb0b".to_string()));
+        assert_eq!(lookup.expiration_timestamp.timestamp(), 1699536000);
+
+        let txn = user_txn(
+            2,
+            json!([event(
+                ANS_V2_ADDRESS,
+                "v2_domains::RenewNameEvent",
+                json!({
+                    "domain_name": "bob",
+                    "subdomain_name": {"vec": []},
+                    "expiration_time_secs": "1731158400",
+                    "target_address": {"vec": ["0xb0b"]},
+                    "is_primary_name": true
+                })
+            )]),
+            json!([]),
+        );
+        let (lookups, _) = CurrentAnsLookup::from_transaction(&txn, &ans_contracts());
+        let lookup = &lookups[&("bob".to_string(), "".to_string())];
+        assert_eq!(lookup.registered_address, Some("0xb0b".to_string()));
+        assert_eq!(lookup.expiration_timestamp.timestamp(), 1731158400);
+        assert_eq!(lookup.last_transaction_version, 2);
+    }
+
+    #[test]
+    fn test_v2_removal_and_reregistration() {
+        let removal = json!({
+            "type": "delete_table_item",
+            "state_key_hash": HASH,
+            "handle": "0x1234",
+            "key": "0x00",
+            "data": {
+                "key": {"domain_name": "bob", "subdomain_name": {"vec": []}},
+                "key_type": format!("{}::v2_domains::NameRecordKeyV2", ANS_V2_ADDRESS)
+            }
+        });
+        let txn = user_txn(2, json!([]), json!([removal]));
+        let (lookups, _) = CurrentAnsLookup::from_transaction(&txn, &ans_contracts());
+        let lookup = &lookups[&("bob".to_string(), "".to_string())];
+        assert!(lookup.is_deleted);
+        assert_eq!(lookup.registered_address, None);
+        assert_eq!(lookup.expiration_timestamp.timestamp(), 1668000000);
+
+        // Registering the name again brings the row back
+        let txn = user_txn(
+            3,
+            json!([]),
+            json!([name_record_v2("bob", "0xca401", 1731158400)]),
+        );
+        let (lookups, _) = CurrentAnsLookup::from_transaction(&txn, &ans_contracts());
+        let lookup = &lookups[&("bob".to_string(), "".to_string())];
+        assert!(!lookup.is_deleted);
+        assert_eq!(lookup.registered_address, Some("0xca401".to_string()));
+    }
+
+    #[test]
+    fn test_layouts_are_per_version() {
+        // A v2 event emitted by the v1 contract, and a v2 name record without a v2 contract
+        let txn = user_txn(
+            1,
+            json!([event(
+                ANS_V1_ADDRESS,
+                "v2_domains::RenewNameEvent",
+                json!({"unexpected": "layout"})
+            )]),
+            json!([name_record_v2("bob", "0xb0b", 1699536000)]),
+        );
+        let v1_only = AnsContract::from_config(Some(&ANS_V1_ADDRESS.to_string()), &[]).unwrap();
+        let (lookups, primary_names) = CurrentAnsLookup::from_transaction(&txn, &v1_only);
+        assert!(lookups.is_empty());
+        assert!(primary_names.is_empty());
+
+        assert!(AnsContract::from_config(
+            None,
+            &[AnsContractConfig {
+                address: ANS_V2_ADDRESS.to_string(),
+                version: 3,
+            }],
+        )
+        .is_err());
     }
 }
@@ -0,0 +1,209 @@
+// Copyright (c) Aptos
+// SPDX-License-Identifier: Apache-2.0
+
+//! Sales of ANS domain tokens, attributed to their domain. v1 domains are tokens of the "Aptos
+//! Names V1" collection named after the domain, ex: "petra.apt", and are sold on marketplaces
+//! like any other token, so their sales are only told apart by the token's collection.
+
+// This is required because a diesel macro makes clippy sad
+#![allow(clippy::extra_unused_lifetimes)]
+#![allow(clippy::unused_unit)]
+
+use super::{
+    marketplace_event_mappings::is_hex_address, nft_sales::NftSale, token_activities::TokenActivity,
+};
+use crate::{schema::current_ans_sale_prices, util::standardize_address};
+use anyhow::ensure;
+use bigdecimal::BigDecimal;
+use field_count::FieldCount;
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+
+/// Collection the v1 contract mints domain and subdomain tokens in
+pub const DOMAIN_COLLECTION_NAME: &str = "Aptos Names V1";
+const DOMAIN_SUFFIX: &str = ".apt";
+const MAX_DOMAIN_LENGTH: usize = 63;
+
+type Domain = String;
+
+/// The domain a domain token is named after, ex: "petra" for "petra.apt". Subdomain tokens, ex:
+/// "wallet.petra.apt", and names that don't look like a domain are None.
+pub fn domain_from_token_name(name: &str) -> Option<&str> {
+    let domain = name.strip_suffix(DOMAIN_SUFFIX)?;
+    let is_domain = !domain.is_empty()
+        && domain.len() <= MAX_DOMAIN_LENGTH
+        && domain
+            .chars()
+            .all(|c| c.is_ascii_lowercase() || c.is_ascii_digit() || c == '-');
+    is_domain.then_some(domain)
+}
+
+/// Identifies the domain tokens by their creator, since anyone can create a collection named
+/// DOMAIN_COLLECTION_NAME
+#[derive(Clone, Debug, Default)]
+pub struct AnsDomains {
+    /// Standardized, None if domains aren't attributed
+    token_creator: Option<String>,
+}
+
+impl AnsDomains {
+    /// Fails on a malformed `ans_token_creator`
+    pub fn from_config(ans_token_creator: Option<&str>) -> anyhow::Result<Self> {
+        if let Some(creator) = ans_token_creator {
+            ensure!(
+                is_hex_address(creator),
+                "invalid ans_token_creator '{}'",
+                creator
+            );
+        }
+        Ok(Self {
+            token_creator: ans_token_creator.map(standardize_address),
+        })
+    }
+
+    /// The domain of a token, if it's a domain token
+    pub fn domain(
+        &self,
+        creator_address: &str,
+        collection_name: &str,
+        name: &str,
+    ) -> Option<String> {
+        let token_creator = self.token_creator.as_ref()?;
+        if collection_name != DOMAIN_COLLECTION_NAME
+            || standardize_address(creator_address) != *token_creator
+        {
+            return None;
+        }
+        domain_from_token_name(name).map(|domain| domain.to_string())
+    }
+
+    pub fn set_activity_domains(&self, token_activities: &mut [TokenActivity]) {
+        for activity in token_activities.iter_mut() {
+            activity.domain = self.domain(
+                &activity.creator_address,
+                &activity.collection_name,
+                &activity.name,
+            );
+        }
+    }
+
+    pub fn set_sale_domains(&self, nft_sales: &mut [NftSale]) {
+        for sale in nft_sales.iter_mut() {
+            sale.domain = self.domain(&sale.creator_address, &sale.collection_name, &sale.name);
+        }
+    }
+}
+
+/// Last sale of each domain, for what domains currently go for
+#[derive(
+    Clone,
+    Debug,
+    Deserialize,
+    FieldCount,
+    Identifiable,
+    Insertable,
+    Queryable,
+    Selectable,
+    Serialize,
+)]
+#[diesel(primary_key(domain))]
+#[diesel(table_name = current_ans_sale_prices)]
+pub struct CurrentAnsSalePrice {
+    pub domain: String,
+    pub token_data_id_hash: String,
+    pub market_address: String,
+    pub coin_type: Option<String>,
+    pub last_sale_price: Option<BigDecimal>,
+    /// USD price of the sale, see NftSale::price_usd
+    pub last_sale_price_usd: Option<BigDecimal>,
+    pub last_transaction_version: i64,
+    pub last_transaction_timestamp: chrono::NaiveDateTime,
+}
+
+impl CurrentAnsSalePrice {
+    /// The last sale of each domain sold in the batch, sorted by domain. Sales are in version
+    /// order, so later sales of a domain replace earlier ones. Sales with a suspect value are
+    /// skipped.
+    pub fn from_nft_sales(nft_sales: &[NftSale]) -> Vec<Self> {
+        let mut prices: HashMap<Domain, Self> = HashMap::new();
+        for sale in nft_sales.iter().filter(|sale| !sale.suspect_value) {
+            if let Some(domain) = &sale.domain {
+                prices.insert(
+                    domain.clone(),
+                    Self {
+                        domain: domain.clone(),
+                        token_data_id_hash: sale.token_data_id_hash.clone(),
+                        market_address: sale.market_address.clone(),
+                        coin_type: sale.coin_type.clone(),
+                        last_sale_price: sale.price.clone(),
+                        last_sale_price_usd: sale.price_usd.clone(),
+                        last_transaction_version: sale.transaction_version,
+                        last_transaction_timestamp: sale.transaction_timestamp,
+                    },
+                );
+            }
+        }
+        let mut prices: Vec<Self> = prices.into_values().collect();
+        prices.sort_by(|a, b| a.domain.cmp(&b.domain));
+        prices
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const CREATOR: &str = "0x305a";
+
+    #[test]
+    fn test_domain_from_token_name() {
+        assert_eq!(domain_from_token_name("petra.apt"), Some("petra"));
+        assert_eq!(domain_from_token_name("0x-42.apt"), Some("0x-42"));
+        // Subdomains, and names that only look like one
+        for name in [
+            "wallet.petra.apt",
+            ".petra.apt",
+            "petra..apt",
+            "petra.apt.apt",
+            "petra",
+            ".apt",
+            "Petra.apt",
+            "petra.APT",
+            "pe tra.apt",
+            "pétra.apt",
+        ] {
+            assert_eq!(domain_from_token_name(name), None, "{}", name);
+        }
+        let longest = format!("{}.apt", "a".repeat(63));
+        assert!(domain_from_token_name(&longest).is_some());
+        assert_eq!(domain_from_token_name(&format!("a{}", longest)), None);
+    }
+
+    #[test]
+    fn test_domain() {
+        let domains = AnsDomains::from_config(Some(CREATOR)).unwrap();
+        let creator = standardize_address(CREATOR);
+        assert_eq!(
+            domains.domain(&creator, DOMAIN_COLLECTION_NAME, "petra.apt"),
+            Some("petra".to_string())
+        );
+        assert_eq!(
+            domains.domain(&creator, DOMAIN_COLLECTION_NAME, "wallet.petra.apt"),
+            None
+        );
+        // Another creator's collection with the same name
+        assert_eq!(
+            domains.domain("0xbad", DOMAIN_COLLECTION_NAME, "petra.apt"),
+            None
+        );
+        assert_eq!(
+            domains.domain(&creator, "Aptos Names V2", "petra.apt"),
+            None
+        );
+        assert_eq!(
+            AnsDomains::default().domain(&creator, DOMAIN_COLLECTION_NAME, "petra.apt"),
+            None
+        );
+        assert!(AnsDomains::from_config(Some("names")).is_err());
+    }
+}
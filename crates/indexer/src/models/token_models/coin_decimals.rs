@@ -0,0 +1,119 @@
+// Copyright (c) Aptos
+// SPDX-License-Identifier: Apache-2.0
+
+//! Decimals of the coins tokens are traded in, so that prices and volumes can be written in units
+//! of their coin next to the raw amounts. coin_infos has every coin the coin processor indexed,
+//! coin_decimals is seeded with the common ones for databases where it doesn't run.
+
+use super::{
+    collection_reports::{canonicalize_coin_type, DEFAULT_COIN_TYPE},
+    marketplace_listings::CurrentMarketplaceListing,
+    nft_sales::NftSale,
+};
+use crate::{
+    schema::{coin_decimals, coin_infos},
+    util::{hash_str, to_decimal_amount},
+};
+use bigdecimal::BigDecimal;
+use diesel::{ExpressionMethods, PgConnection, QueryDsl, QueryResult, RunQueryDsl};
+use std::collections::HashMap;
+
+/// Decimals by canonical coin type, for the coins of one batch
+#[derive(Debug, Default)]
+pub struct CoinDecimals {
+    decimals: HashMap<String, i32>,
+}
+
+impl CoinDecimals {
+    /// Decimals of the sales' coins, and of APT for listings
+    pub fn load<'a>(
+        conn: &mut PgConnection,
+        nft_sales: impl IntoIterator<Item = &'a NftSale>,
+    ) -> QueryResult<Self> {
+        let mut coin_types: Vec<String> = nft_sales
+            .into_iter()
+            .map(|sale| canonicalize_coin_type(sale.coin_type.as_deref()))
+            .chain([DEFAULT_COIN_TYPE.to_string()])
+            .collect();
+        coin_types.sort();
+        coin_types.dedup();
+        let seeded: Vec<(String, i32)> = coin_decimals::table
+            .filter(coin_decimals::coin_type.eq_any(&coin_types))
+            .select((coin_decimals::coin_type, coin_decimals::decimals))
+            .load(conn)?;
+        // coin_infos is keyed by hash, its coin_type can be truncated
+        let hashes: HashMap<String, &String> = coin_types
+            .iter()
+            .map(|coin_type| (hash_str(coin_type), coin_type))
+            .collect();
+        let indexed: Vec<(String, i32)> = coin_infos::table
+            .filter(
+                coin_infos::coin_type_hash.eq_any(hashes.keys().cloned().collect::<Vec<String>>()),
+            )
+            .select((coin_infos::coin_type_hash, coin_infos::decimals))
+            .load(conn)?;
+        let mut decimals: HashMap<String, i32> = seeded.into_iter().collect();
+        for (coin_type_hash, coin_type_decimals) in indexed {
+            decimals.insert(hashes[&coin_type_hash].clone(), coin_type_decimals);
+        }
+        Ok(Self { decimals })
+    }
+
+    pub fn get(&self, coin_type: Option<&str>) -> Option<i32> {
+        self.decimals
+            .get(&canonicalize_coin_type(coin_type))
+            .copied()
+    }
+
+    /// None when the coin's decimals aren't known
+    pub fn to_decimal(&self, amount: &BigDecimal, coin_type: Option<&str>) -> Option<BigDecimal> {
+        to_decimal_amount(amount, self.get(coin_type))
+    }
+
+    pub fn set_sale_prices(&self, nft_sales: &mut [NftSale]) {
+        for sale in nft_sales.iter_mut() {
+            sale.price_decimal = sale
+                .price
+                .as_ref()
+                .and_then(|price| self.to_decimal(price, sale.coin_type.as_deref()));
+        }
+    }
+
+    /// Listings don't record their coin, they're all priced in APT
+    pub fn set_listing_prices<'a>(
+        &self,
+        listings: impl IntoIterator<Item = &'a mut CurrentMarketplaceListing>,
+    ) {
+        for listing in listings {
+            listing.price_decimal = self.to_decimal(&listing.price, None);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::str::FromStr;
+
+    #[test]
+    fn test_unknown_coin_has_no_decimal_amount() {
+        let coin_decimals = CoinDecimals {
+            decimals: HashMap::from([(DEFAULT_COIN_TYPE.to_string(), 8)]),
+        };
+        assert_eq!(
+            coin_decimals.to_decimal(&BigDecimal::from(250000000), None),
+            Some(BigDecimal::from_str("2.5").unwrap())
+        );
+        assert_eq!(
+            coin_decimals.to_decimal(
+                &BigDecimal::from(250000000),
+                Some("0x0001::aptos_coin::AptosCoin")
+            ),
+            Some(BigDecimal::from_str("2.5").unwrap())
+        );
+        assert_eq!(
+            coin_decimals.to_decimal(&BigDecimal::from(250000000), Some("0xf22b::asset::USDC")),
+            None
+        );
+    }
+}
@@ -0,0 +1,252 @@
+// Copyright (c) Aptos
+// SPDX-License-Identifier: Apache-2.0
+
+//! USD prices of the coins sales are paid in. coin_prices is only appended to, by the
+//! update-coin-prices command from the configured price APIs or by hand, and the processor values
+//! each batch's sales at the latest price of their coin. A coin without a price leaves the USD
+//! columns null rather than counting the sale as worth nothing.
+
+// This is required because a diesel macro makes clippy sad
+#![allow(clippy::extra_unused_lifetimes)]
+
+use super::{
+    collection_reports::canonicalize_coin_type, marketplace_event_mappings::JsonPath,
+    nft_sales::NftSale,
+};
+use crate::{schema::coin_prices, util::to_decimal_amount};
+use anyhow::{ensure, Context, Result};
+use aptos_config::config::CoinPriceSourceConfig;
+use bigdecimal::BigDecimal;
+use diesel::{PgConnection, QueryDsl, QueryResult, RunQueryDsl};
+use field_count::FieldCount;
+use serde::{Deserialize, Serialize};
+use std::{collections::HashMap, time::Duration};
+
+const FETCH_TIMEOUT: Duration = Duration::from_secs(10);
+
+#[derive(Clone, Debug, Deserialize, FieldCount, Insertable, Queryable, Serialize)]
+#[diesel(table_name = coin_prices)]
+pub struct CoinPrice {
+    pub coin_type: String,
+    pub price_usd: BigDecimal,
+    /// Raw amounts are divided by 10^decimals before being priced, ex: 8 for APT
+    pub decimals: i32,
+    pub as_of: chrono::NaiveDateTime,
+}
+
+impl CoinPrice {
+    /// Value in USD of a raw amount of the coin, ex: octas for APT
+    pub fn to_usd(&self, amount: &BigDecimal) -> Option<BigDecimal> {
+        to_decimal_amount(amount, Some(self.decimals)).map(|amount| amount * &self.price_usd)
+    }
+}
+
+/// Latest price of every coin in coin_prices, by canonical coin type
+#[derive(Debug, Default)]
+pub struct CoinPrices {
+    prices: HashMap<String, CoinPrice>,
+}
+
+impl CoinPrices {
+    pub fn load_latest(conn: &mut PgConnection) -> QueryResult<Self> {
+        let rows: Vec<CoinPrice> = coin_prices::table
+            .select((
+                coin_prices::coin_type,
+                coin_prices::price_usd,
+                coin_prices::decimals,
+                coin_prices::as_of,
+            ))
+            .load(conn)?;
+        Ok(Self::from_rows(rows))
+    }
+
+    /// Rows added by hand may not be in canonical form, so the latest row wins across spellings
+    fn from_rows(rows: Vec<CoinPrice>) -> Self {
+        let mut prices: HashMap<String, CoinPrice> = HashMap::new();
+        for row in rows {
+            let key = canonicalize_coin_type(Some(&row.coin_type));
+            match prices.get(&key) {
+                Some(latest) if latest.as_of >= row.as_of => {}
+                _ => {
+                    prices.insert(key, row);
+                }
+            }
+        }
+        Self { prices }
+    }
+
+    pub fn get(&self, coin_type: Option<&str>) -> Option<&CoinPrice> {
+        self.prices.get(&canonicalize_coin_type(coin_type))
+    }
+
+    /// Sets the coin price and the USD price of the sales, left null when the coin has no price
+    pub fn price_sales(&self, nft_sales: &mut [NftSale]) {
+        for sale in nft_sales.iter_mut() {
+            let coin_price = self.get(sale.coin_type.as_deref());
+            sale.coin_price_usd = coin_price.map(|coin_price| coin_price.price_usd.clone());
+            sale.price_usd = coin_price
+                .zip(sale.price.as_ref())
+                .and_then(|(coin_price, price)| coin_price.to_usd(price));
+        }
+    }
+}
+
+#[derive(Debug)]
+struct CoinPriceSource {
+    coin_type: String,
+    decimals: i32,
+    url: reqwest::Url,
+    price_path: JsonPath,
+}
+
+/// Fetches the configured price APIs into coin_prices, see update-coin-prices
+#[derive(Debug)]
+pub struct CoinPriceUpdater {
+    sources: Vec<CoinPriceSource>,
+}
+
+impl CoinPriceUpdater {
+    pub fn from_config(config: Option<&[CoinPriceSourceConfig]>) -> Result<Option<Self>> {
+        let config = match config {
+            Some(config) => config,
+            None => return Ok(None),
+        };
+        ensure!(!config.is_empty(), "at least one source is required");
+        let mut sources: Vec<CoinPriceSource> = vec![];
+        for source in config {
+            ensure!(
+                source.coin_type.split("::").count() == 3,
+                "coin_type '{}' is not in the form address::module::struct",
+                source.coin_type
+            );
+            let coin_type = canonicalize_coin_type(Some(&source.coin_type));
+            ensure!(
+                sources.iter().all(|known| known.coin_type != coin_type),
+                "coin_type {} has more than one source",
+                coin_type
+            );
+            sources.push(CoinPriceSource {
+                coin_type,
+                decimals: source.decimals as i32,
+                url: reqwest::Url::parse(&source.url)
+                    .with_context(|| format!("invalid url for {}", source.coin_type))?,
+                price_path: source
+                    .price_path
+                    .parse()
+                    .with_context(|| format!("invalid price_path for {}", source.coin_type))?,
+            });
+        }
+        Ok(Some(Self { sources }))
+    }
+
+    /// Fetches every source, stamping the prices with now. A source that fails doesn't keep the
+    /// others from being updated, its error is returned with its coin type.
+    pub async fn fetch_prices(&self) -> Result<(Vec<CoinPrice>, Vec<(String, anyhow::Error)>)> {
+        let client = reqwest::Client::builder().timeout(FETCH_TIMEOUT).build()?;
+        let as_of = chrono::Utc::now().naive_utc();
+        let mut prices = vec![];
+        let mut failures = vec![];
+        for source in &self.sources {
+            match Self::fetch(&client, source).await {
+                Ok(price_usd) => prices.push(CoinPrice {
+                    coin_type: source.coin_type.clone(),
+                    price_usd,
+                    decimals: source.decimals,
+                    as_of,
+                }),
+                Err(err) => failures.push((source.coin_type.clone(), err)),
+            }
+        }
+        Ok((prices, failures))
+    }
+
+    async fn fetch(client: &reqwest::Client, source: &CoinPriceSource) -> Result<BigDecimal> {
+        let response: serde_json::Value = client
+            .get(source.url.clone())
+            .send()
+            .await?
+            .error_for_status()?
+            .json()
+            .await?;
+        let price_usd = source.price_path.extract_bigdecimal(&response)?;
+        ensure!(
+            price_usd > BigDecimal::from(0),
+            "price {} is not positive",
+            price_usd
+        );
+        Ok(price_usd)
+    }
+}
+
+pub fn insert_coin_prices(conn: &mut PgConnection, prices: &[CoinPrice]) -> QueryResult<usize> {
+    diesel::insert_into(coin_prices::table)
+        .values(prices)
+        .on_conflict_do_nothing()
+        .execute(conn)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::str::FromStr;
+
+    fn price(coin_type: &str, price_usd: &str, decimals: i32, as_of: i64) -> CoinPrice {
+        CoinPrice {
+            coin_type: coin_type.to_string(),
+            price_usd: BigDecimal::from_str(price_usd).unwrap(),
+            decimals,
+            as_of: chrono::NaiveDateTime::from_timestamp(as_of, 0),
+        }
+    }
+
+    #[test]
+    fn test_to_usd_respects_decimals() {
+        let apt = price("0x1::aptos_coin::AptosCoin", "6.42", 8, 0);
+        assert_eq!(
+            apt.to_usd(&BigDecimal::from(150000000)),
+            Some(BigDecimal::from_str("9.63").unwrap())
+        );
+        let usdc = price("0xf22b::asset::USDC", "1", 6, 0);
+        assert_eq!(
+            usdc.to_usd(&BigDecimal::from(2500000)),
+            Some(BigDecimal::from_str("2.5").unwrap())
+        );
+    }
+
+    #[test]
+    fn test_latest_price_across_spellings() {
+        let prices = CoinPrices::from_rows(vec![
+            price("0x1::aptos_coin::AptosCoin", "6.42", 8, 200),
+            price("0x0001::aptos_coin::AptosCoin", "5", 8, 100),
+            price("0x1::aptos_coin::AptosCoin", "4", 8, 50),
+        ]);
+        // Sales without a coin type are paid in APT
+        assert_eq!(
+            prices.get(None).unwrap().price_usd,
+            BigDecimal::from_str("6.42").unwrap()
+        );
+        assert!(prices.get(Some("0xf22b::asset::USDC")).is_none());
+    }
+
+    #[test]
+    fn test_coin_price_sources() {
+        let source = |coin_type: &str| CoinPriceSourceConfig {
+            coin_type: coin_type.to_string(),
+            decimals: 8,
+            url: "https://prices.example/simple/price?ids=aptos&vs_currencies=usd".to_string(),
+            price_path: "aptos.usd".to_string(),
+        };
+        assert!(CoinPriceUpdater::from_config(None).unwrap().is_none());
+        assert!(
+            CoinPriceUpdater::from_config(Some(&[source("0x1::aptos_coin::AptosCoin")]))
+                .unwrap()
+                .is_some()
+        );
+        assert!(CoinPriceUpdater::from_config(Some(&[source("AptosCoin")])).is_err());
+        assert!(CoinPriceUpdater::from_config(Some(&[
+            source("0x1::aptos_coin::AptosCoin"),
+            source("0x01::aptos_coin::AptosCoin"),
+        ]))
+        .is_err());
+    }
+}
@@ -0,0 +1,436 @@
+// Copyright (c) Aptos
+// SPDX-License-Identifier: Apache-2.0
+
+//! Verified badges, display name overrides and social links per collection, maintained by
+//! operators. collection_curations is only written by the import-collection-curations command,
+//! from a CSV or JSON file. current_collection_datas.is_verified is a copy of the curation's flag:
+//! the import updates the rows that already exist, and the processor sets it on every row it
+//! writes from the curations it reads once per batch.
+
+// This is required because a diesel macro makes clippy sad
+#![allow(clippy::extra_unused_lifetimes)]
+
+use super::{collection_datas::CurrentCollectionData, token_utils::CollectionDataIdType};
+use crate::{database::get_chunks, schema::collection_curations};
+use anyhow::{bail, ensure, Context, Result};
+use diesel::{
+    pg::upsert::excluded,
+    sql_query,
+    sql_types::{Array, Text},
+    Connection, ExpressionMethods, PgConnection, QueryDsl, QueryResult, RunQueryDsl,
+};
+use field_count::FieldCount;
+use serde::{Deserialize, Serialize};
+use std::{
+    collections::{HashMap, HashSet},
+    path::Path,
+};
+
+#[derive(Clone, Debug, Deserialize, FieldCount, Identifiable, Insertable, Queryable, Serialize)]
+#[diesel(primary_key(collection_data_id_hash))]
+#[diesel(table_name = collection_curations)]
+pub struct CollectionCuration {
+    pub collection_data_id_hash: String,
+    pub is_verified: bool,
+    pub display_name_override: Option<String>,
+    pub discord_url: Option<String>,
+    pub twitter_url: Option<String>,
+    pub updated_by: String,
+    pub updated_at: chrono::NaiveDateTime,
+}
+
+/// A row of an import file. The collection is either its hash, or its creator and name.
+#[derive(Debug, Default, Deserialize)]
+pub struct CurationRecord {
+    #[serde(default)]
+    pub collection_data_id_hash: Option<String>,
+    #[serde(default)]
+    pub creator_address: Option<String>,
+    #[serde(default)]
+    pub collection_name: Option<String>,
+    pub is_verified: bool,
+    #[serde(default)]
+    pub display_name_override: Option<String>,
+    #[serde(default)]
+    pub discord_url: Option<String>,
+    #[serde(default)]
+    pub twitter_url: Option<String>,
+}
+
+/// Empty CSV cells and JSON strings are the same as a missing value
+fn non_empty(value: Option<String>) -> Option<String> {
+    value
+        .map(|value| value.trim().to_string())
+        .filter(|value| !value.is_empty())
+}
+
+fn checked_url(field: &str, value: Option<String>) -> Result<Option<String>> {
+    let value = non_empty(value);
+    if let Some(url) = &value {
+        url::Url::parse(url).with_context(|| format!("Invalid {} '{}'", field, url))?;
+    }
+    Ok(value)
+}
+
+impl CurationRecord {
+    fn collection_data_id_hash(&self) -> Result<String> {
+        let hash = non_empty(self.collection_data_id_hash.clone());
+        let creator_address = non_empty(self.creator_address.clone());
+        // Names aren't trimmed, they're hashed as they are on chain
+        let collection_name = self.collection_name.clone().filter(|name| !name.is_empty());
+        match (hash, creator_address, collection_name) {
+            (Some(hash), None, None) => {
+                ensure!(
+                    hash.len() == 64 && hash.chars().all(|c| c.is_ascii_hexdigit()),
+                    "collection_data_id_hash '{}' isn't a 64 character hex hash",
+                    hash
+                );
+                Ok(hash.to_ascii_lowercase())
+            }
+            (None, Some(creator_address), Some(collection_name)) => {
+                Ok(CollectionDataIdType::new(creator_address, collection_name).to_hash())
+            }
+            _ => bail!(
+                "Needs either collection_data_id_hash, or both creator_address and collection_name"
+            ),
+        }
+    }
+
+    fn into_curation(
+        self,
+        updated_by: &str,
+        updated_at: chrono::NaiveDateTime,
+    ) -> Result<CollectionCuration> {
+        Ok(CollectionCuration {
+            collection_data_id_hash: self.collection_data_id_hash()?,
+            is_verified: self.is_verified,
+            display_name_override: non_empty(self.display_name_override),
+            discord_url: checked_url("discord_url", self.discord_url)?,
+            twitter_url: checked_url("twitter_url", self.twitter_url)?,
+            updated_by: updated_by.to_string(),
+            updated_at,
+        })
+    }
+}
+
+/// Reads a .csv file with a header row, or a .json file with an array of objects, with the
+/// fields of CurationRecord
+pub fn read_curation_file(path: &Path) -> Result<Vec<CurationRecord>> {
+    match path.extension().and_then(|extension| extension.to_str()) {
+        Some("csv") => {
+            let mut reader = csv::Reader::from_path(path)
+                .with_context(|| format!("Could not open {}", path.display()))?;
+            reader
+                .deserialize()
+                .enumerate()
+                // The header is line 1
+                .map(|(index, record)| record.with_context(|| format!("Line {}", index + 2)))
+                .collect()
+        }
+        Some("json") => {
+            let file = std::fs::File::open(path)
+                .with_context(|| format!("Could not open {}", path.display()))?;
+            serde_json::from_reader(std::io::BufReader::new(file))
+                .with_context(|| format!("Could not parse {}", path.display()))
+        }
+        _ => bail!("{} should be a .csv or .json file", path.display()),
+    }
+}
+
+/// Checks every record before anything is written, so a bad file changes nothing
+pub fn curations_from_records(
+    records: Vec<CurationRecord>,
+    updated_by: &str,
+    updated_at: chrono::NaiveDateTime,
+) -> Result<Vec<CollectionCuration>> {
+    let mut seen = HashSet::new();
+    let mut curations = records
+        .into_iter()
+        .enumerate()
+        .map(|(index, record)| {
+            let curation = record
+                .into_curation(updated_by, updated_at)
+                .with_context(|| format!("Record {}", index + 1))?;
+            ensure!(
+                seen.insert(curation.collection_data_id_hash.clone()),
+                "Record {}: collection {} is curated twice",
+                index + 1,
+                curation.collection_data_id_hash
+            );
+            Ok(curation)
+        })
+        .collect::<Result<Vec<_>>>()?;
+    // Same order as every other writer of current_collection_datas
+    curations.sort_by(|a, b| a.collection_data_id_hash.cmp(&b.collection_data_id_hash));
+    Ok(curations)
+}
+
+/// Upserts the curations and copies their is_verified to the collections already indexed.
+/// Curations missing from the file are left as they are. Returns the number of
+/// current_collection_datas rows whose flag changed.
+pub fn import_curations(
+    conn: &mut PgConnection,
+    curations: &[CollectionCuration],
+) -> QueryResult<usize> {
+    use collection_curations::dsl::*;
+
+    conn.transaction(|conn| {
+        for (start_ind, end_ind) in get_chunks(curations.len(), CollectionCuration::field_count()) {
+            diesel::insert_into(crate::schema::collection_curations::table)
+                .values(&curations[start_ind..end_ind])
+                .on_conflict(collection_data_id_hash)
+                .do_update()
+                .set((
+                    is_verified.eq(excluded(is_verified)),
+                    display_name_override.eq(excluded(display_name_override)),
+                    discord_url.eq(excluded(discord_url)),
+                    twitter_url.eq(excluded(twitter_url)),
+                    updated_by.eq(excluded(updated_by)),
+                    updated_at.eq(excluded(updated_at)),
+                ))
+                .execute(conn)?;
+        }
+        let hashes = curations
+            .iter()
+            .map(|curation| curation.collection_data_id_hash.clone())
+            .collect::<Vec<String>>();
+        sql_query(
+            "UPDATE current_collection_datas cd SET is_verified = c.is_verified
+            FROM collection_curations c
+            WHERE c.collection_data_id_hash = cd.collection_data_id_hash
+            AND c.collection_data_id_hash = ANY($1)
+            AND cd.is_verified <> c.is_verified",
+        )
+        .bind::<Array<Text>, _>(hashes)
+        .execute(conn)
+    })
+}
+
+/// is_verified of the batch's collections that are curated. The processor reads them once per
+/// batch, so a curation imported while a batch is processed is only copied the next time its
+/// collection is written, or by rerunning the import.
+#[derive(Debug, Default)]
+pub struct CollectionCurations {
+    verified: HashMap<String, bool>,
+}
+
+impl CollectionCurations {
+    pub fn load<'a>(
+        conn: &mut PgConnection,
+        collection_data_id_hashes: impl IntoIterator<Item = &'a String>,
+    ) -> QueryResult<Self> {
+        let hashes = collection_data_id_hashes
+            .into_iter()
+            .collect::<Vec<&String>>();
+        if hashes.is_empty() {
+            return Ok(Self::default());
+        }
+        let verified = collection_curations::table
+            .filter(collection_curations::collection_data_id_hash.eq_any(hashes))
+            .select((
+                collection_curations::collection_data_id_hash,
+                collection_curations::is_verified,
+            ))
+            .load::<(String, bool)>(conn)?;
+        Ok(Self {
+            verified: verified.into_iter().collect(),
+        })
+    }
+
+    /// Collections without a curation aren't verified
+    pub fn is_verified(&self, collection_data_id_hash: &str) -> bool {
+        self.verified
+            .get(collection_data_id_hash)
+            .copied()
+            .unwrap_or_default()
+    }
+
+    pub fn set_is_verified<'a>(
+        &self,
+        collection_datas: impl IntoIterator<Item = &'a mut CurrentCollectionData>,
+    ) {
+        for collection_data in collection_datas {
+            collection_data.is_verified =
+                self.is_verified(&collection_data.collection_data_id_hash);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        database::new_db_pool,
+        indexer::tailer::{test::wipe_database, MIGRATIONS},
+        schema::current_collection_datas,
+    };
+    use diesel_migrations::MigrationHarness;
+
+    fn now() -> chrono::NaiveDateTime {
+        chrono::NaiveDateTime::from_timestamp(1668000000, 0)
+    }
+
+    fn potions_hash() -> String {
+        CollectionDataIdType::new("0xc4e7".to_string(), "Potions".to_string()).to_hash()
+    }
+
+    #[test]
+    fn test_curation_files() {
+        let dir = std::env::temp_dir().join(format!("curations-test-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let csv_path = dir.join("curations.csv");
+        std::fs::write(
+            &csv_path,
+            "collection_data_id_hash,creator_address,collection_name,is_verified,\
+            display_name_override,discord_url,twitter_url\n\
+            ,0xc4e7,Potions,true,\"Potions, Vol. 1\",https://discord.gg/potions,\n\
+            AB5C9C52A5D1B8F1E3B4BEA5FE5F8F1D3D0EBBE25A8A3C73E1C2BAF43D1C4C7E,,,false,,,\n",
+        )
+        .unwrap();
+        let json_path = dir.join("curations.json");
+        std::fs::write(
+            &json_path,
+            r#"[{"creator_address": "0xc4e7", "collection_name": "Potions", "is_verified": true,
+                "display_name_override": "Potions, Vol. 1", "discord_url": "https://discord.gg/potions"},
+               {"collection_data_id_hash": "AB5C9C52A5D1B8F1E3B4BEA5FE5F8F1D3D0EBBE25A8A3C73E1C2BAF43D1C4C7E",
+                "is_verified": false}]"#,
+        )
+        .unwrap();
+
+        for path in [&csv_path, &json_path] {
+            let curations =
+                curations_from_records(read_curation_file(path).unwrap(), "ops", now()).unwrap();
+            let mut expected = vec![
+                (potions_hash(), true, Some("Potions, Vol. 1".to_string())),
+                (
+                    "ab5c9c52a5d1b8f1e3b4bea5fe5f8f1d3d0ebbe25a8a3c73e1c2baf43d1c4c7e".to_string(),
+                    false,
+                    None,
+                ),
+            ];
+            expected.sort();
+            assert_eq!(
+                curations
+                    .iter()
+                    .map(|curation| (
+                        curation.collection_data_id_hash.clone(),
+                        curation.is_verified,
+                        curation.display_name_override.clone()
+                    ))
+                    .collect::<Vec<_>>(),
+                expected
+            );
+            let potions = curations
+                .iter()
+                .find(|curation| curation.collection_data_id_hash == potions_hash())
+                .unwrap();
+            assert_eq!(
+                potions.discord_url.as_deref(),
+                Some("https://discord.gg/potions")
+            );
+            assert_eq!(potions.twitter_url, None);
+            assert_eq!(potions.updated_by, "ops");
+        }
+        assert!(read_curation_file(&dir.join("curations.yaml")).is_err());
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn test_invalid_records() {
+        let record =
+            |collection_data_id_hash: Option<&str>, creator_address: Option<&str>| CurationRecord {
+                collection_data_id_hash: collection_data_id_hash.map(str::to_string),
+                creator_address: creator_address.map(str::to_string),
+                collection_name: creator_address.map(|_| "Potions".to_string()),
+                is_verified: true,
+                ..CurationRecord::default()
+            };
+        // Neither or both ways of naming the collection
+        assert!(curations_from_records(vec![record(None, None)], "ops", now()).is_err());
+        assert!(curations_from_records(
+            vec![record(Some(&potions_hash()), Some("0xc4e7"))],
+            "ops",
+            now()
+        )
+        .is_err());
+        assert!(curations_from_records(vec![record(Some("0xc4e7"), None)], "ops", now()).is_err());
+        // The same collection twice, once by hash
+        assert!(curations_from_records(
+            vec![
+                record(None, Some("0xc4e7")),
+                record(Some(&potions_hash()), None)
+            ],
+            "ops",
+            now()
+        )
+        .is_err());
+        let bad_url = CurationRecord {
+            twitter_url: Some("twitter.com/potions".to_string()),
+            ..record(None, Some("0xc4e7"))
+        };
+        assert!(curations_from_records(vec![bad_url], "ops", now()).is_err());
+    }
+
+    #[test]
+    fn test_import_syncs_indexed_collections() {
+        if crate::should_skip_pg_tests() {
+            return;
+        }
+        let database_url = std::env::var("INDEXER_DATABASE_URL")
+            .expect("must set 'INDEXER_DATABASE_URL' to run tests!");
+        let conn_pool = new_db_pool(database_url.as_str()).unwrap();
+        let mut conn = conn_pool.get().unwrap();
+        wipe_database(&mut conn);
+        conn.run_pending_migrations(MIGRATIONS).unwrap();
+        sql_query(format!(
+            "INSERT INTO current_collection_datas (
+                collection_data_id_hash, creator_address, collection_name, description,
+                metadata_uri, supply, maximum, maximum_mutable, uri_mutable, description_mutable,
+                last_transaction_version, table_handle, last_transaction_timestamp
+            ) VALUES ('{}', '0xc4e7', 'Potions', '', '', 1, 1, false, false, false, 1, '0x7', NOW())",
+            potions_hash(),
+        ))
+        .execute(&mut conn)
+        .unwrap();
+        let load_is_verified = |conn: &mut PgConnection| -> bool {
+            current_collection_datas::table
+                .select(current_collection_datas::is_verified)
+                .first(conn)
+                .unwrap()
+        };
+        assert!(!load_is_verified(&mut conn));
+
+        let curation = |is_verified: bool| CurationRecord {
+            creator_address: Some("0xc4e7".to_string()),
+            collection_name: Some("Potions".to_string()),
+            is_verified,
+            ..CurationRecord::default()
+        };
+        // A collection that isn't indexed yet is curated for when it is
+        let unindexed = CurationRecord {
+            collection_name: Some("Elixirs".to_string()),
+            ..curation(true)
+        };
+        let curations =
+            curations_from_records(vec![curation(true), unindexed], "ops", now()).unwrap();
+        assert_eq!(import_curations(&mut conn, &curations).unwrap(), 1);
+        assert!(load_is_verified(&mut conn));
+        let collection_curations =
+            CollectionCurations::load(&mut conn, &[potions_hash(), "uncurated".to_string()])
+                .unwrap();
+        assert!(collection_curations.is_verified(&potions_hash()));
+        assert!(!collection_curations.is_verified("uncurated"));
+
+        // Importing again overwrites the curation and unverifies the collection
+        let curations = curations_from_records(vec![curation(false)], "ops", now()).unwrap();
+        assert_eq!(import_curations(&mut conn, &curations).unwrap(), 1);
+        assert!(!load_is_verified(&mut conn));
+        assert_eq!(
+            collection_curations::table
+                .count()
+                .get_result::<i64>(&mut conn)
+                .unwrap(),
+            2
+        );
+    }
+}
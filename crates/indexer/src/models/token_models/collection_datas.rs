@@ -6,7 +6,8 @@
 #![allow(clippy::unused_unit)]
 
 use super::{
-    token_utils::{CollectionDataIdType, TokenWriteSet},
+    table_handle_cache::TableHandleCache,
+    token_utils::{CollectionDataIdType, CollectionDataType, TokenWriteSet},
     tokens::{TableHandleToOwner, TableMetadataForToken},
 };
 use crate::{
@@ -19,6 +20,7 @@ use bigdecimal::BigDecimal;
 use diesel::{prelude::*, ExpressionMethods};
 use field_count::FieldCount;
 use serde::{Deserialize, Serialize};
+use std::collections::{HashMap, HashSet};
 
 const QUERY_RETRIES: u32 = 5;
 const QUERY_RETRY_DELAY_MS: u64 = 500;
@@ -41,7 +43,9 @@ pub struct CollectionData {
     pub transaction_timestamp: chrono::NaiveDateTime,
 }
 
-#[derive(Debug, Deserialize, FieldCount, Identifiable, Insertable, Serialize)]
+#[derive(
+    Debug, Deserialize, FieldCount, Identifiable, Insertable, Queryable, Selectable, Serialize,
+)]
 #[diesel(primary_key(collection_data_id_hash))]
 #[diesel(table_name = current_collection_datas)]
 pub struct CurrentCollectionData {
@@ -58,132 +62,311 @@ pub struct CurrentCollectionData {
     pub last_transaction_version: i64,
     pub table_handle: String,
     pub last_transaction_timestamp: chrono::NaiveDateTime,
+    /// Copied from collection_curations, see CollectionCurations
+    pub is_verified: bool,
 }
 
-/// Need a separate struct for queryable because we don't want to define the inserted_at column (letting DB fill)
-#[derive(Debug, Identifiable, Queryable)]
-#[diesel(primary_key(collection_data_id_hash))]
-#[diesel(table_name = current_collection_datas)]
-pub struct CurrentCollectionDataQuery {
-    pub collection_data_id_hash: String,
-    pub creator_address: String,
-    pub collection_name: String,
-    pub description: String,
-    pub metadata_uri: String,
-    pub supply: BigDecimal,
-    pub maximum: BigDecimal,
-    pub maximum_mutable: bool,
-    pub uri_mutable: bool,
-    pub description_mutable: bool,
-    pub last_transaction_version: i64,
-    pub inserted_at: chrono::NaiveDateTime,
-    pub table_handle: String,
-    pub last_transaction_timestamp: chrono::NaiveDateTime,
+/// Creators of the collection tables a batch writes to without writing the Collections resource
+/// that owns them. They're looked up for the whole batch before it's resolved, in a few queries
+/// rather than one per table handle. Since a collection's creator can't change, the
+/// current_collection_datas row of the handle has it.
+#[derive(Debug, Default)]
+pub struct CollectionCreators {
+    /// Table handle to creator, as of before the batch
+    creators: HashMap<String, String>,
+}
+
+impl CollectionCreators {
+    /// Looks up the creators the batch's collection items don't have, `items` in version order.
+    /// Handles written earlier in the batch aren't looked up, the processor adds those to the
+    /// cache as it goes, and neither are those already cached as of the item's version. Handles
+    /// missing from the db are queried again a few times, since their collection could've been
+    /// written by a batch in a separate thread that hasn't committed yet.
+    pub fn prefetch<'a>(
+        conn: &mut PgPoolConnection,
+        table_handle_cache: &TableHandleCache,
+        items: impl IntoIterator<Item = &'a CollectionTableItem>,
+    ) -> anyhow::Result<Self> {
+        let mut lookups = Self::default();
+        // Version each handle is first written at in the batch
+        let mut written_in_batch: HashMap<&str, i64> = HashMap::new();
+        let mut missing = HashSet::new();
+        for item in items {
+            if item.creator_address.is_some() {
+                written_in_batch
+                    .entry(item.table_handle.as_str())
+                    .or_insert(item.txn_version);
+                continue;
+            }
+            let is_written_in_batch = written_in_batch
+                .get(item.table_handle.as_str())
+                .map_or(false, |version| *version <= item.txn_version);
+            if is_written_in_batch || lookups.creators.contains_key(&item.table_handle) {
+                continue;
+            }
+            match table_handle_cache.get(&item.table_handle, item.txn_version) {
+                Some(creator_address) => {
+                    lookups
+                        .creators
+                        .insert(item.table_handle.clone(), creator_address);
+                }
+                None => {
+                    missing.insert(item.table_handle.clone());
+                }
+            }
+        }
+
+        let mut retried = 0;
+        while !missing.is_empty() && retried < QUERY_RETRIES {
+            if retried > 0 {
+                std::thread::sleep(std::time::Duration::from_millis(QUERY_RETRY_DELAY_MS));
+            }
+            retried += 1;
+            let found = match CurrentCollectionData::get_creators_by_table_handles(
+                conn,
+                missing.iter().cloned().collect(),
+            ) {
+                Ok(found) => found,
+                Err(err) if retried == QUERY_RETRIES => {
+                    return Err(err).context("Failed to get collection creators")
+                }
+                Err(_) => continue,
+            };
+            for (table_handle, creator_address, version) in found {
+                table_handle_cache.insert(&table_handle, &creator_address, version);
+                missing.remove(&table_handle);
+                lookups.creators.insert(table_handle, creator_address);
+            }
+        }
+        Ok(lookups)
+    }
+
+    /// The handle's creator as of txn_version: written earlier in the batch, or else as of before
+    /// the batch
+    pub fn get(
+        &self,
+        table_handle: &str,
+        txn_version: i64,
+        table_handle_cache: &TableHandleCache,
+    ) -> Option<String> {
+        table_handle_cache
+            .get(table_handle, txn_version)
+            .or_else(|| self.creators.get(table_handle).cloned())
+    }
 }
 
-impl CollectionData {
+/// A collection table item, parsed without the db. Its creator is only known if the transaction
+/// also wrote the Collections resource that owns the table, otherwise `resolve` looks it up.
+#[derive(Debug)]
+pub struct CollectionTableItem {
+    table_handle: String,
+    creator_address: Option<String>,
+    collection_data: CollectionDataType,
+    txn_version: i64,
+    txn_timestamp: chrono::NaiveDateTime,
+}
+
+impl CollectionTableItem {
     pub fn from_write_table_item(
         table_item: &APIWriteTableItem,
         txn_version: i64,
         txn_timestamp: chrono::NaiveDateTime,
         table_handle_to_owner: &TableHandleToOwner,
-        conn: &mut PgPoolConnection,
-    ) -> anyhow::Result<Option<(Self, CurrentCollectionData)>> {
+    ) -> anyhow::Result<Option<Self>> {
         let table_item_data = table_item.data.as_ref().unwrap();
 
-        let maybe_collection_data = match TokenWriteSet::from_table_item_type(
+        let collection_data = match TokenWriteSet::from_table_item_type(
             table_item_data.value_type.as_str(),
             &table_item_data.value,
             txn_version,
         )? {
-            Some(TokenWriteSet::CollectionData(inner)) => Some(inner),
-            _ => None,
+            Some(TokenWriteSet::CollectionData(inner)) => inner,
+            _ => return Ok(None),
         };
-        if let Some(collection_data) = maybe_collection_data {
-            let table_handle = table_item.handle.to_string();
-            let maybe_creator_address = table_handle_to_owner
-                .get(&TableMetadataForToken::standardize_handle(&table_handle))
-                .map(|table_metadata| table_metadata.owner_address.clone());
-            let creator_address = match maybe_creator_address {
-                Some(ca) => ca,
-                None => Self::get_collection_creator(conn, &table_handle).context(format!(
+        let table_handle = table_item.handle.to_string();
+        let creator_address = table_handle_to_owner
+            .get(&TableMetadataForToken::standardize_handle(&table_handle))
+            .map(|table_metadata| table_metadata.owner_address.clone());
+        Ok(Some(Self {
+            table_handle,
+            creator_address,
+            collection_data,
+            txn_version,
+            txn_timestamp,
+        }))
+    }
+
+    pub fn resolve(
+        self,
+        collection_creators: &CollectionCreators,
+        table_handle_cache: &TableHandleCache,
+    ) -> anyhow::Result<(CollectionData, CurrentCollectionData)> {
+        let Self {
+            table_handle,
+            creator_address,
+            collection_data,
+            txn_version,
+            txn_timestamp,
+        } = self;
+        let creator_address = match creator_address {
+            Some(ca) => ca,
+            None => collection_creators
+                .get(&table_handle, txn_version, table_handle_cache)
+                .context(format!(
                     "Failed to get collection creator for table handle {}, txn version {}",
                     table_handle, txn_version
                 ))?,
-            };
-            let collection_data_id =
-                CollectionDataIdType::new(creator_address, collection_data.get_name().to_string());
-            let collection_data_id_hash = collection_data_id.to_hash();
-            let collection_name = collection_data.get_name_trunc();
-            let metadata_uri = collection_data.get_uri_trunc();
-
-            Ok(Some((
-                Self {
-                    collection_data_id_hash: collection_data_id_hash.clone(),
-                    collection_name: collection_name.clone(),
-                    creator_address: collection_data_id.creator.clone(),
-                    description: collection_data.description.clone(),
-                    transaction_version: txn_version,
-                    metadata_uri: metadata_uri.clone(),
-                    supply: collection_data.supply.clone(),
-                    maximum: collection_data.maximum.clone(),
-                    maximum_mutable: collection_data.mutability_config.maximum,
-                    uri_mutable: collection_data.mutability_config.uri,
-                    description_mutable: collection_data.mutability_config.description,
-                    table_handle: table_handle.clone(),
-                    transaction_timestamp: txn_timestamp,
-                },
-                CurrentCollectionData {
-                    collection_data_id_hash,
-                    collection_name,
-                    creator_address: collection_data_id.creator,
-                    description: collection_data.description,
-                    metadata_uri,
-                    supply: collection_data.supply,
-                    maximum: collection_data.maximum,
-                    maximum_mutable: collection_data.mutability_config.maximum,
-                    uri_mutable: collection_data.mutability_config.uri,
-                    description_mutable: collection_data.mutability_config.description,
-                    last_transaction_version: txn_version,
-                    table_handle,
-                    last_transaction_timestamp: txn_timestamp,
-                },
-            )))
-        } else {
-            Ok(None)
-        }
-    }
+        };
+        let collection_data_id =
+            CollectionDataIdType::new(creator_address, collection_data.get_name().to_string());
+        let collection_data_id_hash = collection_data_id.to_hash();
+        let collection_name = collection_data.get_name_trunc();
+        let metadata_uri = collection_data.get_uri_trunc();
 
-    /// If collection data is not in resources of the same transaction, then try looking for it in the database. Since collection owner
-    /// cannot change, we can just look in the current_collection_datas table.
-    /// Retrying a few times since this collection could've been written in a separate thread.
-    /// Note, there's an edge case where this could still break. If the collection is written in the same thread in an earlier transaction, we won't be able to process.
-    /// TODO: Fast follow with a fix for above problem.
-    pub fn get_collection_creator(
-        conn: &mut PgPoolConnection,
-        table_handle: &str,
-    ) -> anyhow::Result<String> {
-        let mut retried = 0;
-        while retried < QUERY_RETRIES {
-            retried += 1;
-            match CurrentCollectionDataQuery::get_by_table_handle(conn, table_handle) {
-                Ok(current_collection_data) => return Ok(current_collection_data.creator_address),
-                Err(_) => {
-                    std::thread::sleep(std::time::Duration::from_millis(QUERY_RETRY_DELAY_MS));
-                }
-            }
-        }
-        Err(anyhow::anyhow!("Failed to get collection creator"))
+        Ok((
+            CollectionData {
+                collection_data_id_hash: collection_data_id_hash.clone(),
+                collection_name: collection_name.clone(),
+                creator_address: collection_data_id.creator.clone(),
+                description: collection_data.description.clone(),
+                transaction_version: txn_version,
+                metadata_uri: metadata_uri.clone(),
+                supply: collection_data.supply.clone(),
+                maximum: collection_data.maximum.clone(),
+                maximum_mutable: collection_data.mutability_config.maximum,
+                uri_mutable: collection_data.mutability_config.uri,
+                description_mutable: collection_data.mutability_config.description,
+                table_handle: table_handle.clone(),
+                transaction_timestamp: txn_timestamp,
+            },
+            CurrentCollectionData {
+                collection_data_id_hash,
+                collection_name,
+                creator_address: collection_data_id.creator,
+                description: collection_data.description,
+                metadata_uri,
+                supply: collection_data.supply,
+                maximum: collection_data.maximum,
+                maximum_mutable: collection_data.mutability_config.maximum,
+                uri_mutable: collection_data.mutability_config.uri,
+                description_mutable: collection_data.mutability_config.description,
+                last_transaction_version: txn_version,
+                table_handle,
+                last_transaction_timestamp: txn_timestamp,
+                is_verified: false,
+            },
+        ))
     }
 }
 
-impl CurrentCollectionDataQuery {
+impl CurrentCollectionData {
     pub fn get_by_table_handle(
         conn: &mut PgPoolConnection,
         table_handle: &str,
     ) -> diesel::QueryResult<Self> {
         current_collection_datas::table
             .filter(current_collection_datas::table_handle.eq(table_handle))
+            .select(Self::as_select())
             .first::<Self>(conn)
     }
+
+    /// Creator and last version of the collections in each of the table handles, as one
+    /// `table_handle = ANY($1)` query
+    pub fn get_creators_by_table_handles(
+        conn: &mut PgPoolConnection,
+        table_handles: Vec<String>,
+    ) -> diesel::QueryResult<Vec<(String, String, i64)>> {
+        current_collection_datas::table
+            .filter(current_collection_datas::table_handle.eq_any(table_handles))
+            .select((
+                current_collection_datas::table_handle,
+                current_collection_datas::creator_address,
+                current_collection_datas::last_transaction_version,
+            ))
+            .load(conn)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        database::new_db_pool,
+        indexer::tailer::{test::wipe_database, MIGRATIONS},
+    };
+    use diesel_migrations::MigrationHarness;
+    use serde_json::json;
+
+    const CREATOR: &str = "0x000000000000000000000000000000000000000000000000000000000000c4e7";
+
+    fn collection_item(handle: &str, txn_version: i64) -> CollectionTableItem {
+        let table_item: APIWriteTableItem = serde_json::from_value(json!({
+            "state_key_hash": "0x00",
+            "handle": handle,
+            "key": "0x00",
+            "value": "0x00",
+            "data": {
+                "key": "Potions",
+                "key_type": "0x1::string::String",
+                "value": {
+                    "description": "",
+                    "maximum": "0",
+                    "mutability_config": {"description": false, "maximum": false, "uri": false},
+                    "name": "Potions",
+                    "supply": "1",
+                    "uri": ""
+                },
+                "value_type": "0x3::token::CollectionData"
+            }
+        }))
+        .unwrap();
+        CollectionTableItem::from_write_table_item(
+            &table_item,
+            txn_version,
+            chrono::NaiveDateTime::from_timestamp(1668000000, 0),
+            &HashMap::new(),
+        )
+        .unwrap()
+        .unwrap()
+    }
+
+    #[test]
+    fn test_prefetch_creators() {
+        if crate::should_skip_pg_tests() {
+            return;
+        }
+        let database_url = std::env::var("INDEXER_DATABASE_URL")
+            .expect("must set 'INDEXER_DATABASE_URL' to run tests!");
+        let conn_pool = new_db_pool(database_url.as_str()).unwrap();
+        let mut conn = conn_pool.get().unwrap();
+        wipe_database(&mut conn);
+        conn.run_pending_migrations(MIGRATIONS).unwrap();
+        diesel::sql_query(format!(
+            "INSERT INTO current_collection_datas (
+                collection_data_id_hash, creator_address, collection_name, description,
+                metadata_uri, supply, maximum, maximum_mutable, uri_mutable, description_mutable,
+                last_transaction_version, table_handle, last_transaction_timestamp
+            ) VALUES ('hash', '{}', 'Potions', '', '', 1, 0, false, false, false, 5, '0x0a', NOW())",
+            CREATOR,
+        ))
+        .execute(&mut conn)
+        .unwrap();
+
+        let cache = TableHandleCache::new(10);
+        let mut items = vec![collection_item("0x0a", 10), collection_item("0x0a", 11)];
+        let creators = CollectionCreators::prefetch(&mut conn, &cache, &items).unwrap();
+        // Looked up once for both items, and cached for the next batch
+        assert_eq!(creators.creators.len(), 1);
+        assert_eq!(cache.get("0x0a", 10), Some(CREATOR.to_string()));
+        let (collection_data, current_collection_data) =
+            items.remove(0).resolve(&creators, &cache).unwrap();
+        assert_eq!(collection_data.creator_address, CREATOR);
+        assert_eq!(current_collection_data.last_transaction_version, 10);
+
+        // Unknown handles are left to fail when resolved
+        let cache = TableHandleCache::new(10);
+        let item = collection_item("0x0b", 12);
+        let creators = CollectionCreators::prefetch(&mut conn, &cache, [&item]).unwrap();
+        assert!(item.resolve(&creators, &cache).is_err());
+    }
 }
@@ -0,0 +1,434 @@
+// Copyright (c) Aptos
+// SPDX-License-Identifier: Apache-2.0
+
+// This is required because a diesel macro makes clippy sad
+#![allow(clippy::extra_unused_lifetimes)]
+#![allow(clippy::unused_unit)]
+
+use super::{
+    token_ownerships::CurrentTokenOwnership,
+    tokens::{CollectionDataIdHash, CurrentTokenOwnershipPK},
+};
+use crate::schema::{current_collection_holder_counts, current_token_ownerships};
+use bigdecimal::{BigDecimal, Zero};
+use diesel::{
+    sql_query,
+    sql_types::{Array, Nullable, Numeric, Text},
+    ExpressionMethods, PgConnection, QueryDsl, QueryResult, RunQueryDsl,
+};
+use field_count::FieldCount;
+use serde::{Deserialize, Serialize};
+use std::collections::{HashMap, HashSet};
+
+type HolderAddress = String;
+/// Amount and holder of each current_token_ownerships row before the batch
+pub type PreviousOwnershipAmounts = HashMap<CurrentTokenOwnershipPK, (BigDecimal, HolderAddress)>;
+/// Sum of a holder's current_token_ownerships rows in a collection before the batch
+pub type PreviousCollectionBalances = HashMap<(CollectionDataIdHash, HolderAddress), BigDecimal>;
+
+/// Rows built from a batch hold the change in both counts, which the upsert adds to the stored
+/// counts. Parallel or replayed batches can make the stored counts drift, so
+/// `recompute_all` should be run periodically (ex: nightly) as a backstop.
+/// Tokens are counted for their holder, see `CurrentTokenOwnership::holder_address`, so a seller
+/// doesn't stop being a holder when listing on a marketplace that escrows the token.
+#[derive(
+    Debug, Deserialize, FieldCount, Identifiable, Insertable, Queryable, Selectable, Serialize,
+)]
+#[diesel(primary_key(collection_data_id_hash))]
+#[diesel(table_name = current_collection_holder_counts)]
+pub struct CurrentCollectionHolderCount {
+    pub collection_data_id_hash: String,
+    pub distinct_holders: i64,
+    pub total_tokens_held: BigDecimal,
+    pub last_transaction_version: i64,
+}
+
+#[derive(Debug, QueryableByName)]
+struct CollectionBalance {
+    #[diesel(sql_type = Text)]
+    collection_data_id_hash: String,
+    #[diesel(sql_type = Text)]
+    holder_address: String,
+    #[diesel(sql_type = Nullable<Numeric>)]
+    balance: Option<BigDecimal>,
+}
+
+impl CurrentCollectionHolderCount {
+    /// Has to run before the batch's current_token_ownerships are written, since the change in
+    /// holders depends on the balances before the batch
+    pub fn from_current_token_ownerships(
+        conn: &mut PgConnection,
+        current_token_ownerships: &[CurrentTokenOwnership],
+    ) -> QueryResult<Vec<Self>> {
+        if current_token_ownerships.is_empty() {
+            return Ok(vec![]);
+        }
+        let previous_amounts = Self::get_previous_amounts(conn, current_token_ownerships)?;
+        // A row can move from one holder to another, ex: when a listing in escrow sells
+        let holders = current_token_ownerships
+            .iter()
+            .map(|ownership| {
+                (
+                    ownership.collection_data_id_hash.clone(),
+                    ownership.holder_address().to_string(),
+                )
+            })
+            .chain(current_token_ownerships.iter().filter_map(|ownership| {
+                previous_amounts
+                    .get(&(
+                        ownership.token_data_id_hash.clone(),
+                        ownership.property_version.clone(),
+                        ownership.owner_address.clone(),
+                    ))
+                    .map(|(_, holder_address)| {
+                        (
+                            ownership.collection_data_id_hash.clone(),
+                            holder_address.clone(),
+                        )
+                    })
+            }))
+            .collect::<HashSet<_>>();
+        let previous_balances = Self::get_previous_balances(conn, &holders)?;
+        Ok(Self::from_balance_changes(
+            current_token_ownerships,
+            &previous_amounts,
+            &previous_balances,
+        ))
+    }
+
+    /// A holder is added to (or removed from) a collection's holders when their balance in the
+    /// collection goes from 0 to positive (or back to 0)
+    pub fn from_balance_changes(
+        current_token_ownerships: &[CurrentTokenOwnership],
+        previous_amounts: &PreviousOwnershipAmounts,
+        previous_balances: &PreviousCollectionBalances,
+    ) -> Vec<Self> {
+        let mut balance_changes: HashMap<(CollectionDataIdHash, HolderAddress), (BigDecimal, i64)> =
+            HashMap::new();
+        let mut add_change = |collection_data_id_hash: &str,
+                              holder_address: &str,
+                              change: BigDecimal,
+                              version: i64| {
+            let (total_change, last_transaction_version) = balance_changes
+                .entry((
+                    collection_data_id_hash.to_string(),
+                    holder_address.to_string(),
+                ))
+                .or_insert((BigDecimal::zero(), version));
+            *total_change += change;
+            *last_transaction_version = (*last_transaction_version).max(version);
+        };
+        for ownership in current_token_ownerships {
+            // The previous amount comes off whoever held it, which isn't always the new holder
+            if let Some((previous_amount, previous_holder)) = previous_amounts.get(&(
+                ownership.token_data_id_hash.clone(),
+                ownership.property_version.clone(),
+                ownership.owner_address.clone(),
+            )) {
+                add_change(
+                    &ownership.collection_data_id_hash,
+                    previous_holder,
+                    -previous_amount.clone(),
+                    ownership.last_transaction_version,
+                );
+            }
+            add_change(
+                &ownership.collection_data_id_hash,
+                ownership.holder_address(),
+                ownership.amount.clone(),
+                ownership.last_transaction_version,
+            );
+        }
+
+        let mut holder_counts: HashMap<CollectionDataIdHash, Self> = HashMap::new();
+        for ((collection_data_id_hash, holder_address), (change, last_transaction_version)) in
+            balance_changes
+        {
+            let previous_balance = previous_balances
+                .get(&(collection_data_id_hash.clone(), holder_address))
+                .cloned()
+                .unwrap_or_else(BigDecimal::zero);
+            let balance = &previous_balance + &change;
+            let holders_change = match (
+                previous_balance > BigDecimal::zero(),
+                balance > BigDecimal::zero(),
+            ) {
+                (false, true) => 1,
+                (true, false) => -1,
+                _ => 0,
+            };
+            let holder_count = holder_counts
+                .entry(collection_data_id_hash.clone())
+                .or_insert_with(|| Self {
+                    collection_data_id_hash,
+                    distinct_holders: 0,
+                    total_tokens_held: BigDecimal::zero(),
+                    last_transaction_version,
+                });
+            holder_count.distinct_holders += holders_change;
+            holder_count.total_tokens_held += change;
+            holder_count.last_transaction_version = holder_count
+                .last_transaction_version
+                .max(last_transaction_version);
+        }
+        let mut holder_counts = holder_counts.into_values().collect::<Vec<_>>();
+        holder_counts.sort_by(|a, b| a.collection_data_id_hash.cmp(&b.collection_data_id_hash));
+        holder_counts
+    }
+
+    fn get_previous_amounts(
+        conn: &mut PgConnection,
+        current_token_ownerships: &[CurrentTokenOwnership],
+    ) -> QueryResult<PreviousOwnershipAmounts> {
+        let pks = current_token_ownerships
+            .iter()
+            .map(|ownership| {
+                (
+                    ownership.token_data_id_hash.clone(),
+                    ownership.property_version.clone(),
+                    ownership.owner_address.clone(),
+                )
+            })
+            .collect::<HashSet<CurrentTokenOwnershipPK>>();
+        let token_data_id_hashes = pks.iter().map(|pk| pk.0.clone()).collect::<HashSet<_>>();
+        let owner_addresses = pks.iter().map(|pk| pk.2.clone()).collect::<HashSet<_>>();
+        // This can return rows for other owners of the same tokens, which are filtered out below
+        let rows = current_token_ownerships::table
+            .filter(current_token_ownerships::token_data_id_hash.eq_any(token_data_id_hashes))
+            .filter(current_token_ownerships::owner_address.eq_any(owner_addresses))
+            .select((
+                current_token_ownerships::token_data_id_hash,
+                current_token_ownerships::property_version,
+                current_token_ownerships::owner_address,
+                current_token_ownerships::amount,
+                current_token_ownerships::beneficial_owner,
+            ))
+            .load::<(String, BigDecimal, String, BigDecimal, Option<String>)>(conn)?;
+        Ok(rows
+            .into_iter()
+            .map(
+                |(
+                    token_data_id_hash,
+                    property_version,
+                    owner_address,
+                    amount,
+                    beneficial_owner,
+                )| {
+                    let holder_address = beneficial_owner.unwrap_or_else(|| owner_address.clone());
+                    (
+                        (token_data_id_hash, property_version, owner_address),
+                        (amount, holder_address),
+                    )
+                },
+            )
+            .filter(|(pk, _)| pks.contains(pk))
+            .collect())
+    }
+
+    fn get_previous_balances(
+        conn: &mut PgConnection,
+        holders: &HashSet<(CollectionDataIdHash, HolderAddress)>,
+    ) -> QueryResult<PreviousCollectionBalances> {
+        let collection_data_id_hashes = holders
+            .iter()
+            .map(|key| key.0.clone())
+            .collect::<HashSet<_>>()
+            .into_iter()
+            .collect::<Vec<_>>();
+        let holder_addresses = holders
+            .iter()
+            .map(|key| key.1.clone())
+            .collect::<HashSet<_>>()
+            .into_iter()
+            .collect::<Vec<_>>();
+        // Matching either column keeps the owner_address index usable, rows grouped under another
+        // holder are filtered out below
+        let rows = sql_query(
+            "SELECT
+                collection_data_id_hash,
+                COALESCE(beneficial_owner, owner_address) AS holder_address,
+                SUM(amount) AS balance
+            FROM current_token_ownerships
+            WHERE collection_data_id_hash = ANY($1)
+                AND (owner_address = ANY($2) OR beneficial_owner = ANY($2))
+            GROUP BY collection_data_id_hash, COALESCE(beneficial_owner, owner_address)",
+        )
+        .bind::<Array<Text>, _>(collection_data_id_hashes)
+        .bind::<Array<Text>, _>(holder_addresses)
+        .load::<CollectionBalance>(conn)?;
+        Ok(rows
+            .into_iter()
+            .map(|row| {
+                (
+                    (row.collection_data_id_hash, row.holder_address),
+                    row.balance.unwrap_or_else(BigDecimal::zero),
+                )
+            })
+            .filter(|(key, _)| holders.contains(key))
+            .collect())
+    }
+
+    /// Rebuilds every collection's counts from current_token_ownerships, returning the number of
+    /// collections written
+    pub fn recompute_all(conn: &mut PgConnection) -> QueryResult<usize> {
+        sql_query(
+            "INSERT INTO current_collection_holder_counts (
+                collection_data_id_hash,
+                distinct_holders,
+                total_tokens_held,
+                last_transaction_version
+            )
+            SELECT
+                collection_data_id_hash,
+                COUNT(DISTINCT COALESCE(beneficial_owner, owner_address))
+                    FILTER (WHERE amount > 0),
+                SUM(amount),
+                MAX(last_transaction_version)
+            FROM current_token_ownerships
+            GROUP BY collection_data_id_hash
+            ON CONFLICT (collection_data_id_hash) DO UPDATE SET
+                distinct_holders = EXCLUDED.distinct_holders,
+                total_tokens_held = EXCLUDED.total_tokens_held,
+                last_transaction_version = EXCLUDED.last_transaction_version,
+                inserted_at = NOW()",
+        )
+        .execute(conn)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const COLLECTION: &str = "collection";
+
+    fn ownership(token: &str, owner: &str, amount: u64) -> CurrentTokenOwnership {
+        CurrentTokenOwnership {
+            token_data_id_hash: token.to_string(),
+            property_version: BigDecimal::zero(),
+            owner_address: owner.to_string(),
+            creator_address: "0xc4e7".to_string(),
+            collection_name: "Potions".to_string(),
+            name: token.to_string(),
+            amount: BigDecimal::from(amount),
+            token_properties: serde_json::Value::Null,
+            last_transaction_version: 10,
+            collection_data_id_hash: COLLECTION.to_string(),
+            table_type: "0x3::token::TokenStore".to_string(),
+            last_transaction_timestamp: chrono::NaiveDateTime::from_timestamp(1668000000, 0),
+            owner_type: "user".to_string(),
+            beneficial_owner: None,
+            is_deleted: amount == 0,
+        }
+    }
+
+    fn previous_amounts(amounts: &[(&str, &str, u64)]) -> PreviousOwnershipAmounts {
+        amounts
+            .iter()
+            .map(|(token, owner, amount)| {
+                (
+                    (token.to_string(), BigDecimal::zero(), owner.to_string()),
+                    (BigDecimal::from(*amount), owner.to_string()),
+                )
+            })
+            .collect()
+    }
+
+    fn previous_balances(balances: &[(&str, u64)]) -> PreviousCollectionBalances {
+        balances
+            .iter()
+            .map(|(owner, balance)| {
+                (
+                    (COLLECTION.to_string(), owner.to_string()),
+                    BigDecimal::from(*balance),
+                )
+            })
+            .collect()
+    }
+
+    fn changes(holder_counts: &[CurrentCollectionHolderCount]) -> (i64, BigDecimal) {
+        assert_eq!(holder_counts.len(), 1);
+        (
+            holder_counts[0].distinct_holders,
+            holder_counts[0].total_tokens_held.clone(),
+        )
+    }
+
+    #[test]
+    fn test_transfer_to_new_holder() {
+        // alice sends her only token to bob, who didn't hold anything in the collection
+        let holder_counts = CurrentCollectionHolderCount::from_balance_changes(
+            &[ownership("a", "alice", 0), ownership("a", "bob", 1)],
+            &previous_amounts(&[("a", "alice", 1)]),
+            &previous_balances(&[("alice", 1)]),
+        );
+        assert_eq!(changes(&holder_counts), (0, BigDecimal::zero()));
+    }
+
+    #[test]
+    fn test_holders_gained_and_lost() {
+        // alice sells one of her two tokens to bob, carol burns her only token
+        let holder_counts = CurrentCollectionHolderCount::from_balance_changes(
+            &[
+                ownership("a", "alice", 0),
+                ownership("a", "bob", 1),
+                ownership("c", "carol", 0),
+            ],
+            &previous_amounts(&[("a", "alice", 1), ("c", "carol", 1)]),
+            &previous_balances(&[("alice", 2), ("carol", 1)]),
+        );
+        assert_eq!(changes(&holder_counts), (0, BigDecimal::from(-1)));
+    }
+
+    #[test]
+    fn test_mint_to_existing_holder() {
+        let holder_counts = CurrentCollectionHolderCount::from_balance_changes(
+            &[ownership("b", "alice", 5)],
+            &PreviousOwnershipAmounts::new(),
+            &previous_balances(&[("alice", 1)]),
+        );
+        assert_eq!(changes(&holder_counts), (0, BigDecimal::from(5)));
+
+        let holder_counts = CurrentCollectionHolderCount::from_balance_changes(
+            &[ownership("b", "dave", 5)],
+            &PreviousOwnershipAmounts::new(),
+            &PreviousCollectionBalances::new(),
+        );
+        assert_eq!(changes(&holder_counts), (1, BigDecimal::from(5)));
+    }
+
+    #[test]
+    fn test_escrowed_listing_keeps_seller_as_holder() {
+        let escrowed = |amount: u64, beneficial_owner: Option<&str>| CurrentTokenOwnership {
+            owner_type: "marketplace_escrow".to_string(),
+            beneficial_owner: beneficial_owner.map(|seller| seller.to_string()),
+            ..ownership("a", "escrow", amount)
+        };
+        // alice lists her only token, which moves it to the marketplace's escrow
+        let holder_counts = CurrentCollectionHolderCount::from_balance_changes(
+            &[ownership("a", "alice", 0), escrowed(1, Some("alice"))],
+            &previous_amounts(&[("a", "alice", 1)]),
+            &previous_balances(&[("alice", 1)]),
+        );
+        assert_eq!(changes(&holder_counts), (0, BigDecimal::zero()));
+
+        // bob buys it out of escrow, so alice's listing stops counting for her
+        let mut previous = previous_amounts(&[]);
+        previous.insert(
+            ("a".to_string(), BigDecimal::zero(), "escrow".to_string()),
+            (BigDecimal::from(1), "alice".to_string()),
+        );
+        let holder_counts = CurrentCollectionHolderCount::from_balance_changes(
+            &[escrowed(0, None), ownership("a", "bob", 1)],
+            &previous,
+            &previous_balances(&[("alice", 1)]),
+        );
+        assert_eq!(changes(&holder_counts), (0, BigDecimal::zero()));
+        let holder_counts = CurrentCollectionHolderCount::from_balance_changes(
+            &[escrowed(0, None), ownership("a", "bob", 1)],
+            &previous,
+            &previous_balances(&[("alice", 1), ("bob", 3)]),
+        );
+        assert_eq!(changes(&holder_counts), (-1, BigDecimal::zero()));
+    }
+}
@@ -0,0 +1,538 @@
+// Copyright (c) Aptos
+// SPDX-License-Identifier: Apache-2.0
+
+// This is required because a diesel macro makes clippy sad
+#![allow(clippy::extra_unused_lifetimes)]
+#![allow(clippy::unused_unit)]
+
+use super::{
+    collection_reports::{canonicalize_coin_type, DEFAULT_COIN_TYPE},
+    marketplace_event_mappings::{EventKind, MappedMarketplaceEvent, MarketplaceEventMappings},
+    token_utils::{MintTokenEventType, TokenEvent, TokenEvents},
+};
+use crate::{
+    models::coin_models::{
+        coin_activities::EventToCoinType,
+        coin_balances::CoinBalance,
+        coin_utils::{CoinEvent, EventGuidResource},
+    },
+    schema::{collection_mints, current_collection_mint_stats},
+    util::{parse_timestamp, standardize_address},
+};
+use aptos_api_types::{
+    Transaction as APITransaction, UserTransaction as APIUserTransaction,
+    WriteSetChange as APIWriteSetChange,
+};
+use aptos_types::APTOS_COIN_TYPE;
+use bigdecimal::{BigDecimal, Zero};
+use diesel::{ExpressionMethods, PgConnection, QueryDsl, QueryResult, RunQueryDsl};
+use field_count::FieldCount;
+use serde::{Deserialize, Serialize};
+use std::collections::{HashMap, HashSet};
+
+type CollectionDataIdHash = String;
+type MinterAddress = String;
+/// (transaction_version, event_index)
+type CollectionMintPK = (i64, i64);
+
+/// One row per MintTokenEvent. The minter is the transaction sender, and the price is estimated
+/// from the APT the sender paid in the same transaction, split across its mints by amount.
+///
+/// Launchpad mint events (`mint` marketplace event mappings) carry the price and minter, which
+/// replace the estimate and are attributed to the launchpad.
+#[derive(Debug, Deserialize, FieldCount, Identifiable, Insertable, Serialize)]
+#[diesel(primary_key(transaction_version, event_index))]
+#[diesel(table_name = collection_mints)]
+pub struct CollectionMint {
+    pub transaction_version: i64,
+    pub event_index: i64,
+    pub token_data_id_hash: String,
+    pub collection_data_id_hash: String,
+    pub creator_address: String,
+    pub collection_name: String,
+    pub name: String,
+    pub minter_address: String,
+    pub amount: BigDecimal,
+    pub price: BigDecimal,
+    /// The sender didn't pay any APT, ex: free mints or launchpads paying from a resource
+    /// account. The price is 0 in that case.
+    pub is_price_unknown: bool,
+    pub transaction_timestamp: chrono::NaiveDateTime,
+    pub launchpad: Option<String>,
+}
+
+#[derive(
+    Debug, Deserialize, FieldCount, Identifiable, Insertable, Queryable, Selectable, Serialize,
+)]
+#[diesel(primary_key(collection_data_id_hash))]
+#[diesel(table_name = current_collection_mint_stats)]
+pub struct CurrentCollectionMintStat {
+    pub collection_data_id_hash: String,
+    pub total_minted: BigDecimal,
+    pub distinct_minters: i64,
+    pub mint_volume_apt: BigDecimal,
+    pub last_transaction_version: i64,
+    /// Launchpad of the latest launchpad mint, if any
+    pub launchpad: Option<String>,
+}
+
+impl CollectionMint {
+    pub fn from_transaction(
+        transaction: &APITransaction,
+        token_events: &TokenEvents,
+        marketplace_event_mappings: &MarketplaceEventMappings,
+    ) -> Vec<Self> {
+        let user_txn = match transaction {
+            APITransaction::UserTransaction(user_txn) => user_txn,
+            _ => return vec![],
+        };
+        let txn_version = user_txn.info.version.0 as i64;
+        let mint_events = user_txn
+            .events
+            .iter()
+            .enumerate()
+            .filter_map(|(index, _)| match token_events.get(index) {
+                Some(TokenEvent::MintTokenEvent(inner)) => Some((index as i64, inner)),
+                _ => None,
+            })
+            .collect::<Vec<(i64, &MintTokenEventType)>>();
+        let launchpad_mint_events = user_txn
+            .events
+            .iter()
+            .enumerate()
+            .filter_map(|(index, event)| {
+                match marketplace_event_mappings
+                    .from_event(&event.typ.to_string(), &event.data, txn_version)
+                    .unwrap()
+                {
+                    Some(mapped_event) if mapped_event.kind == EventKind::Mint => {
+                        Some((index as i64, mapped_event))
+                    }
+                    _ => None,
+                }
+            })
+            .collect::<Vec<(i64, MappedMarketplaceEvent)>>();
+        if mint_events.is_empty() && launchpad_mint_events.is_empty() {
+            return vec![];
+        }
+
+        let txn_timestamp = parse_timestamp(user_txn.timestamp.0, txn_version);
+        let minter_address = user_txn.request.sender.to_string();
+        let apt_paid = Self::get_apt_paid(user_txn, &minter_address, txn_version, txn_timestamp);
+        // get_apt_paid compares the sender with event guids, which are in the API's short form
+        let minter_address = standardize_address(&minter_address);
+        let total_amount = mint_events
+            .iter()
+            .fold(BigDecimal::zero(), |total, (_, inner)| {
+                total + &inner.amount
+            });
+        let mut mints = mint_events
+            .into_iter()
+            .map(|(event_index, inner)| {
+                let price = match &apt_paid {
+                    Some(apt_paid) if !total_amount.is_zero() => {
+                        (apt_paid * &inner.amount / &total_amount).with_scale(0)
+                    }
+                    _ => BigDecimal::zero(),
+                };
+                Self {
+                    transaction_version: txn_version,
+                    event_index,
+                    token_data_id_hash: inner.id.to_hash(),
+                    collection_data_id_hash: inner.id.get_collection_data_id_hash(),
+                    creator_address: inner.id.get_creator_address(),
+                    collection_name: inner.id.get_collection_trunc(),
+                    name: inner.id.get_name_trunc(),
+                    minter_address: minter_address.clone(),
+                    amount: inner.amount.clone(),
+                    price,
+                    is_price_unknown: apt_paid.is_none(),
+                    transaction_timestamp: txn_timestamp,
+                    launchpad: None,
+                }
+            })
+            .collect::<Vec<Self>>();
+
+        // Launchpad events are matched in order to the MintTokenEvents of the same collection, and
+        // only recorded on their own if the transaction has no such MintTokenEvent left
+        for (event_index, mapped_event) in launchpad_mint_events {
+            let collection_data_id_hash = mapped_event.token_data_id.get_collection_data_id_hash();
+            let is_apt =
+                canonicalize_coin_type(mapped_event.coin_type.as_deref()) == DEFAULT_COIN_TYPE;
+            let price = match (&mapped_event.coin_amount, is_apt) {
+                (Some(price), true) => Some(price.clone()),
+                _ => None,
+            };
+            let launchpad_minter = mapped_event
+                .to_address
+                .clone()
+                .unwrap_or_else(|| minter_address.clone());
+            let mint = match mints.iter_mut().position(|mint| {
+                mint.launchpad.is_none() && mint.collection_data_id_hash == collection_data_id_hash
+            }) {
+                Some(index) => &mut mints[index],
+                None => {
+                    mints.push(Self {
+                        transaction_version: txn_version,
+                        event_index,
+                        token_data_id_hash: mapped_event.token_data_id.to_hash(),
+                        collection_data_id_hash,
+                        creator_address: mapped_event.token_data_id.get_creator_address(),
+                        collection_name: mapped_event.token_data_id.get_collection_trunc(),
+                        name: mapped_event.token_data_id.get_name_trunc(),
+                        minter_address: launchpad_minter.clone(),
+                        amount: mapped_event.token_amount.clone(),
+                        price: BigDecimal::zero(),
+                        is_price_unknown: true,
+                        transaction_timestamp: txn_timestamp,
+                        launchpad: None,
+                    });
+                    mints.last_mut().unwrap()
+                }
+            };
+            mint.minter_address = launchpad_minter;
+            mint.is_price_unknown = price.is_none();
+            mint.price = price.unwrap_or_else(BigDecimal::zero);
+            mint.launchpad = mapped_event.launchpad;
+        }
+        mints
+    }
+
+    /// APT withdrawn from the sender minus APT deposited back to them, ex: refunds. Coin events
+    /// don't carry the coin type, so it comes from the CoinStore resources the transaction wrote.
+    /// Gas isn't a withdrawal, so it's not included.
+    pub fn get_apt_paid(
+        user_txn: &APIUserTransaction,
+        minter_address: &str,
+        txn_version: i64,
+        txn_timestamp: chrono::NaiveDateTime,
+    ) -> Option<BigDecimal> {
+        let mut event_to_coin_type: EventToCoinType = HashMap::new();
+        for wsc in &user_txn.info.changes {
+            if let APIWriteSetChange::WriteResource(write_resource) = wsc {
+                if let Some((_, _, mapping)) =
+                    CoinBalance::from_write_resource(write_resource, txn_version, txn_timestamp)
+                        .unwrap()
+                {
+                    event_to_coin_type.extend(mapping);
+                }
+            }
+        }
+        let aptos_coin_type = APTOS_COIN_TYPE.to_string();
+        let mut apt_paid = BigDecimal::zero();
+        for event in &user_txn.events {
+            let event_guid = EventGuidResource {
+                addr: event.guid.account_address.to_string(),
+                creation_num: event.guid.creation_number.0 as i64,
+            };
+            if event_guid.addr != minter_address
+                || event_to_coin_type.get(&event_guid) != Some(&aptos_coin_type)
+            {
+                continue;
+            }
+            match CoinEvent::from_event(&event.typ.to_string(), &event.data, txn_version).unwrap() {
+                Some(CoinEvent::WithdrawCoinEvent(inner)) => apt_paid += inner.amount,
+                Some(CoinEvent::DepositCoinEvent(inner)) => apt_paid -= inner.amount,
+                None => {}
+            }
+        }
+        (apt_paid > BigDecimal::zero()).then(|| apt_paid)
+    }
+}
+
+impl CurrentCollectionMintStat {
+    /// Mints already in collection_mints, ex: from a batch being reprocessed, aren't counted
+    /// again. Must run before the batch's mints are written.
+    pub fn from_collection_mints(
+        conn: &mut PgConnection,
+        mints: &[CollectionMint],
+    ) -> QueryResult<Vec<Self>> {
+        if mints.is_empty() {
+            return Ok(vec![]);
+        }
+        let collection_data_id_hashes = mints
+            .iter()
+            .map(|mint| mint.collection_data_id_hash.clone())
+            .collect::<HashSet<_>>();
+        let minter_addresses = mints
+            .iter()
+            .map(|mint| mint.minter_address.clone())
+            .collect::<HashSet<_>>();
+        let rows = collection_mints::table
+            .filter(collection_mints::collection_data_id_hash.eq_any(collection_data_id_hashes))
+            .filter(collection_mints::minter_address.eq_any(minter_addresses))
+            .select((
+                collection_mints::collection_data_id_hash,
+                collection_mints::minter_address,
+                collection_mints::transaction_version,
+                collection_mints::event_index,
+            ))
+            .load::<(String, String, i64, i64)>(conn)?;
+        let mut recorded_mints = HashSet::new();
+        let mut previous_minters = HashSet::new();
+        for (collection_data_id_hash, minter_address, transaction_version, event_index) in rows {
+            recorded_mints.insert((transaction_version, event_index));
+            previous_minters.insert((collection_data_id_hash, minter_address));
+        }
+        Ok(Self::aggregate(mints, &recorded_mints, previous_minters))
+    }
+
+    /// Changes to add to each collection's stats, sorted by collection
+    pub fn aggregate(
+        mints: &[CollectionMint],
+        recorded_mints: &HashSet<CollectionMintPK>,
+        mut previous_minters: HashSet<(CollectionDataIdHash, MinterAddress)>,
+    ) -> Vec<Self> {
+        let mut stats: HashMap<CollectionDataIdHash, Self> = HashMap::new();
+        for mint in mints {
+            if recorded_mints.contains(&(mint.transaction_version, mint.event_index)) {
+                continue;
+            }
+            let stat = stats
+                .entry(mint.collection_data_id_hash.clone())
+                .or_insert_with(|| Self {
+                    collection_data_id_hash: mint.collection_data_id_hash.clone(),
+                    total_minted: BigDecimal::zero(),
+                    distinct_minters: 0,
+                    mint_volume_apt: BigDecimal::zero(),
+                    last_transaction_version: mint.transaction_version,
+                    launchpad: None,
+                });
+            stat.total_minted += &mint.amount;
+            stat.mint_volume_apt += &mint.price;
+            stat.last_transaction_version =
+                stat.last_transaction_version.max(mint.transaction_version);
+            if mint.launchpad.is_some() {
+                stat.launchpad = mint.launchpad.clone();
+            }
+            if previous_minters.insert((
+                mint.collection_data_id_hash.clone(),
+                mint.minter_address.clone(),
+            )) {
+                stat.distinct_minters += 1;
+            }
+        }
+        let mut stats = stats.into_values().collect::<Vec<_>>();
+        stats.sort_by(|a, b| a.collection_data_id_hash.cmp(&b.collection_data_id_hash));
+        stats
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    const HASH: &str = "0x6a527d06063dfd42c6b3a862574d5f3ec1660afb8058135edda5072712bfdb51";
+
+    fn coin_store(address: &str, coin_type: &str) -> serde_json::Value {
+        json!({
+            "type": "write_resource",
+            "address": address,
+            "state_key_hash": HASH,
+            "data": {
+                "type": format!("0x1::coin::CoinStore<{}>", coin_type),
+                "data": {
+                    "coin": {"value": "1000000000"},
+                    "deposit_events": {
+                        "counter": "1",
+                        "guid": {"id": {"addr": address, "creation_num": "2"}}
+                    },
+                    "frozen": false,
+                    "withdraw_events": {
+                        "counter": "1",
+                        "guid": {"id": {"addr": address, "creation_num": "3"}}
+                    }
+                }
+            }
+        })
+    }
+
+    fn coin_event(
+        address: &str,
+        creation_number: &str,
+        event_type: &str,
+        amount: u64,
+    ) -> serde_json::Value {
+        json!({
+            "guid": {"creation_number": creation_number, "account_address": address},
+            "sequence_number": "0",
+            "type": event_type,
+            "data": {"amount": amount.to_string()}
+        })
+    }
+
+    fn mint_event(name: &str, amount: u64) -> serde_json::Value {
+        json!({
+            "guid": {"creation_number": "6", "account_address": "0xc4e7"},
+            "sequence_number": "0",
+            "type": "0x3::token::MintTokenEvent",
+            "data": {
+                "amount": amount.to_string(),
+                "id": {"creator": "0xc4e7", "collection": "Potions", "name": name}
+            }
+        })
+    }
+
+    fn mint_txn(changes: Vec<serde_json::Value>, events: Vec<serde_json::Value>) -> APITransaction {
+        serde_json::from_value(json!({
+            "type": "user_transaction",
+            "version": "100",
+            "hash": HASH,
+            "state_change_hash": HASH,
+            "event_root_hash": HASH,
+            "state_checkpoint_hash": null,
+            "gas_used": "100",
+            "success": true,
+            "vm_status": "Executed successfully",
+            "accumulator_root_hash": HASH,
+            "changes": changes,
+            "sender": "0xb0b",
+            "sequence_number": "0",
+            "max_gas_amount": "2000",
+            "gas_unit_price": "100",
+            "expiration_timestamp_secs": "1668000600",
+            "payload": {
+                "type": "entry_function_payload",
+                "function": "0xc4e7::launchpad::mint",
+                "type_arguments": [],
+                "arguments": []
+            },
+            "events": events,
+            "timestamp": "1668000000000000"
+        }))
+        .unwrap()
+    }
+
+    fn mints_from(transaction: APITransaction) -> Vec<CollectionMint> {
+        CollectionMint::from_transaction(
+            &transaction,
+            &TokenEvents::from_transaction(&transaction).unwrap(),
+            &MarketplaceEventMappings::default(),
+        )
+    }
+
+    #[test]
+    fn test_mint_price_from_sender_withdrawals() {
+        let mints = mints_from(mint_txn(
+            vec![
+                coin_store("0xb0b", "0x1::aptos_coin::AptosCoin"),
+                coin_store("0xc4e7", "0x1::aptos_coin::AptosCoin"),
+            ],
+            vec![
+                coin_event("0xb0b", "3", "0x1::coin::WithdrawEvent", 300),
+                coin_event("0xc4e7", "2", "0x1::coin::DepositEvent", 300),
+                mint_event("Potion #1", 1),
+                mint_event("Potion #2", 2),
+                coin_event("0xb0b", "2", "0x1::coin::DepositEvent", 30),
+            ],
+        ));
+        assert_eq!(
+            mints
+                .iter()
+                .map(|mint| (mint.event_index, mint.amount.clone(), mint.price.clone()))
+                .collect::<Vec<_>>(),
+            vec![
+                (2, BigDecimal::from(1), BigDecimal::from(90)),
+                (3, BigDecimal::from(2), BigDecimal::from(180)),
+            ]
+        );
+        assert_eq!(mints[0].minter_address, standardize_address("0xb0b"));
+        assert!(!mints[0].is_price_unknown);
+    }
+
+    #[test]
+    fn test_mint_without_payment_is_flagged() {
+        // Paid in another coin, which isn't counted
+        let mints = mints_from(mint_txn(
+            vec![coin_store("0xb0b", "0xabc::usdc::USDC")],
+            vec![
+                coin_event("0xb0b", "3", "0x1::coin::WithdrawEvent", 300),
+                mint_event("Potion #1", 1),
+            ],
+        ));
+        assert_eq!(mints.len(), 1);
+        assert_eq!(mints[0].price, BigDecimal::zero());
+        assert!(mints[0].is_price_unknown);
+    }
+
+    #[test]
+    fn test_mint_stats() {
+        let mut mints = mints_from(mint_txn(
+            vec![coin_store("0xb0b", "0x1::aptos_coin::AptosCoin")],
+            vec![
+                coin_event("0xb0b", "3", "0x1::coin::WithdrawEvent", 300),
+                mint_event("Potion #1", 1),
+                mint_event("Potion #2", 2),
+            ],
+        ));
+        mints[1].minter_address = "0xa11ce".to_string();
+        let collection_data_id_hash = mints[0].collection_data_id_hash.clone();
+
+        let stats = CurrentCollectionMintStat::aggregate(&mints, &HashSet::new(), HashSet::new());
+        assert_eq!(stats.len(), 1);
+        assert_eq!(stats[0].total_minted, BigDecimal::from(3));
+        assert_eq!(stats[0].distinct_minters, 2);
+        assert_eq!(stats[0].mint_volume_apt, BigDecimal::from(300));
+
+        // bob minted before, and the first mint was already recorded
+        let stats = CurrentCollectionMintStat::aggregate(
+            &mints,
+            &HashSet::from([(100, 1)]),
+            HashSet::from([(collection_data_id_hash, standardize_address("0xb0b"))]),
+        );
+        assert_eq!(stats[0].total_minted, BigDecimal::from(2));
+        assert_eq!(stats[0].distinct_minters, 1);
+        assert_eq!(stats[0].mint_volume_apt, BigDecimal::from(200));
+    }
+
+    #[test]
+    fn test_launchpad_mint_price() {
+        let mappings = MarketplaceEventMappings::from_config(&[serde_json::from_value(json!({
+            "event_type": "0xfa4e::launchpad::MintEvent",
+            "kind": "mint",
+            "creator": "creator",
+            "collection": "collection",
+            "name": "name",
+            "price": "price",
+            "buyer": "minter",
+            "launchpad": "fakepad"
+        }))
+        .unwrap()])
+        .unwrap();
+        // The resource account creator pays the mint fee out of the launchpad's escrow
+        let transaction = mint_txn(
+            vec![],
+            vec![
+                mint_event("Potion #1", 1),
+                json!({
+                    "guid": {"creation_number": "4", "account_address": "0xfa4e"},
+                    "sequence_number": "0",
+                    "type": "0xfa4e::launchpad::MintEvent",
+                    "data": {
+                        "creator": "0xc4e7",
+                        "collection": "Potions",
+                        "name": "Potion #1",
+                        "price": "500",
+                        "minter": "0xa11ce"
+                    }
+                }),
+            ],
+        );
+        let token_events = TokenEvents::from_transaction(&transaction).unwrap();
+        let mints = CollectionMint::from_transaction(&transaction, &token_events, &mappings);
+        assert_eq!(mints.len(), 1);
+        assert_eq!(mints[0].event_index, 0);
+        assert_eq!(mints[0].name, "Potion #1");
+        assert_eq!(mints[0].price, BigDecimal::from(500));
+        assert_eq!(mints[0].minter_address, standardize_address("0xa11ce"));
+        assert_eq!(mints[0].launchpad, Some("fakepad".to_string()));
+        assert!(!mints[0].is_price_unknown);
+
+        let stats = CurrentCollectionMintStat::aggregate(&mints, &HashSet::new(), HashSet::new());
+        assert_eq!(stats[0].launchpad, Some("fakepad".to_string()));
+
+        // Without the mapping the mint has no known price
+        assert!(mints_from(transaction)[0].is_price_unknown);
+    }
+}
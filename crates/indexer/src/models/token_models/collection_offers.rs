@@ -0,0 +1,610 @@
+// Copyright (c) Aptos
+// SPDX-License-Identifier: Apache-2.0
+
+// This is required because a diesel macro makes clippy sad
+#![allow(clippy::extra_unused_lifetimes)]
+#![allow(clippy::unused_unit)]
+
+use super::{
+    collection_reports::{canonicalize_coin_type, DEFAULT_COIN_TYPE},
+    marketplaces::topaz::{
+        TopazCancelCollectionBidEventType, TopazCollectionBidEventType, TopazSellEventType,
+    },
+    token_utils::{CollectionDataIdType, TokenEvent, TokenEvents},
+};
+use crate::{
+    schema::current_collection_offers,
+    util::{parse_timestamp, parse_timestamp_secs, MAX_TIMESTAMP_SECS},
+};
+use aptos_api_types::Transaction as APITransaction;
+use bigdecimal::{BigDecimal, Signed, ToPrimitive, Zero};
+use diesel::{
+    sql_query,
+    sql_types::{Array, BigInt, Numeric, Text, Timestamp},
+    PgConnection, QueryResult, RunQueryDsl,
+};
+use field_count::FieldCount;
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+
+pub const OFFER_STATUS_ACTIVE: &str = "active";
+pub const OFFER_STATUS_CANCELLED: &str = "cancelled";
+pub const OFFER_STATUS_FILLED: &str = "filled";
+/// Past its deadline as of the latest processed transaction, or an outbid auction bid
+pub const OFFER_STATUS_EXPIRED: &str = "expired";
+
+/// (collection_data_id_hash, buyer, market_address)
+pub type CurrentCollectionOfferPK = (String, String, String);
+
+/// Latest collection offer (bid) per buyer and marketplace. Nothing is emitted when an offer
+/// expires, so offers stay active past their deadline until the next expiry pass, see
+/// expire_collection_offers.
+#[derive(
+    Clone,
+    Debug,
+    Deserialize,
+    FieldCount,
+    Identifiable,
+    Insertable,
+    Queryable,
+    Selectable,
+    Serialize,
+)]
+#[diesel(primary_key(collection_data_id_hash, buyer, market_address))]
+#[diesel(table_name = current_collection_offers)]
+pub struct CurrentCollectionOffer {
+    pub collection_data_id_hash: String,
+    pub buyer: String,
+    pub market_address: String,
+    pub bid_id: BigDecimal,
+    pub creator_address: String,
+    pub collection_name: String,
+    /// Price per token
+    pub price: BigDecimal,
+    pub amount_remaining: BigDecimal,
+    pub coin_type: String,
+    /// Unix timestamp in seconds after which the offer can't be filled
+    pub deadline: BigDecimal,
+    pub expires_at: chrono::NaiveDateTime,
+    pub status: String,
+    pub last_transaction_version: i64,
+    pub last_transaction_timestamp: chrono::NaiveDateTime,
+}
+
+/// Fills of offers that weren't placed in the same batch, which are applied to the stored offer
+/// with an update. Fills of the same offer are summed.
+#[derive(Clone, Debug, Deserialize, Serialize)]
+pub struct CollectionOfferFill {
+    pub collection_data_id_hash: String,
+    pub buyer: String,
+    pub market_address: String,
+    pub bid_id: BigDecimal,
+    pub amount: BigDecimal,
+    pub last_transaction_version: i64,
+    pub last_transaction_timestamp: chrono::NaiveDateTime,
+}
+
+impl CollectionOfferFill {
+    fn pk(&self) -> CurrentCollectionOfferPK {
+        (
+            self.collection_data_id_hash.clone(),
+            self.buyer.clone(),
+            self.market_address.clone(),
+        )
+    }
+}
+
+impl CurrentCollectionOffer {
+    pub fn pk(&self) -> CurrentCollectionOfferPK {
+        (
+            self.collection_data_id_hash.clone(),
+            self.buyer.clone(),
+            self.market_address.clone(),
+        )
+    }
+
+    fn from_bid_event(
+        inner: &TopazCollectionBidEventType,
+        market_address: &str,
+        txn_version: i64,
+        txn_timestamp: chrono::NaiveDateTime,
+    ) -> Self {
+        Self {
+            collection_data_id_hash: CollectionDataIdType::new(
+                inner.creator.clone(),
+                inner.collection_name.clone(),
+            )
+            .to_hash(),
+            buyer: inner.buyer.clone(),
+            market_address: market_address.to_string(),
+            bid_id: inner.bid_id.clone(),
+            creator_address: inner.creator.clone(),
+            collection_name: inner.collection_name.clone(),
+            price: inner.price.clone(),
+            amount_remaining: inner.amount.clone(),
+            coin_type: canonicalize_coin_type(Some(&inner.coin_type.to_decoded_string())),
+            deadline: inner.deadline.clone(),
+            expires_at: deadline_to_expires_at(&inner.deadline, txn_version),
+            status: OFFER_STATUS_ACTIVE.to_string(),
+            last_transaction_version: txn_version,
+            last_transaction_timestamp: txn_timestamp,
+        }
+    }
+
+    fn from_cancel_event(
+        inner: &TopazCancelCollectionBidEventType,
+        market_address: &str,
+        txn_version: i64,
+        txn_timestamp: chrono::NaiveDateTime,
+    ) -> Self {
+        Self {
+            collection_data_id_hash: CollectionDataIdType::new(
+                inner.creator.clone(),
+                inner.collection_name.clone(),
+            )
+            .to_hash(),
+            buyer: inner.buyer.clone(),
+            market_address: market_address.to_string(),
+            bid_id: inner.bid_id.clone(),
+            creator_address: inner.creator.clone(),
+            collection_name: inner.collection_name.clone(),
+            price: inner.price.clone(),
+            amount_remaining: inner.amount.clone(),
+            coin_type: canonicalize_coin_type(Some(&inner.coin_type.to_decoded_string())),
+            deadline: inner.deadline.clone(),
+            expires_at: deadline_to_expires_at(&inner.deadline, txn_version),
+            status: OFFER_STATUS_CANCELLED.to_string(),
+            last_transaction_version: txn_version,
+            last_transaction_timestamp: txn_timestamp,
+        }
+    }
+}
+
+/// Deadlines past what a timestamp column holds are capped, like other contract timestamps
+pub fn deadline_to_expires_at(deadline: &BigDecimal, txn_version: i64) -> chrono::NaiveDateTime {
+    let max_secs = MAX_TIMESTAMP_SECS as u64;
+    let secs = deadline
+        .to_u64()
+        .map_or(max_secs, |secs| secs.min(max_secs));
+    parse_timestamp_secs(secs, txn_version)
+}
+
+/// Collection offers placed, cancelled and filled within a batch. Transactions need to be applied
+/// in version order.
+#[derive(Default)]
+pub struct CollectionOfferBook {
+    offers: HashMap<CurrentCollectionOfferPK, CurrentCollectionOffer>,
+    fills: HashMap<(CurrentCollectionOfferPK, BigDecimal), CollectionOfferFill>,
+}
+
+impl CollectionOfferBook {
+    pub fn apply_transaction(&mut self, transaction: &APITransaction, token_events: &TokenEvents) {
+        if let APITransaction::UserTransaction(user_txn) = transaction {
+            let txn_version = user_txn.info.version.0 as i64;
+            let txn_timestamp = parse_timestamp(user_txn.timestamp.0, txn_version);
+            for (event_index, event) in user_txn.events.iter().enumerate() {
+                let event_type = event.typ.to_string();
+                let market_address = event_type.split("::").next().unwrap_or_default();
+                match token_events.get(event_index) {
+                    Some(TokenEvent::TopazCollectionBidEvent(inner)) => {
+                        let offer = CurrentCollectionOffer::from_bid_event(
+                            inner,
+                            market_address,
+                            txn_version,
+                            txn_timestamp,
+                        );
+                        self.offers.insert(offer.pk(), offer);
+                    }
+                    Some(TokenEvent::TopazCancelCollectionBidEvent(inner)) => {
+                        let offer = CurrentCollectionOffer::from_cancel_event(
+                            inner,
+                            market_address,
+                            txn_version,
+                            txn_timestamp,
+                        );
+                        // A cancel of an older offer doesn't touch the buyer's newer one
+                        match self.offers.get(&offer.pk()) {
+                            Some(existing) if existing.bid_id != offer.bid_id => {}
+                            _ => {
+                                self.offers.insert(offer.pk(), offer);
+                            }
+                        }
+                    }
+                    Some(TokenEvent::TopazSellEvent(inner)) => {
+                        self.fill(inner, market_address, txn_version, txn_timestamp)
+                    }
+                    _ => {}
+                }
+            }
+        }
+    }
+
+    /// Sell events are emitted for both token and collection bid fills, so this only applies to
+    /// an offer with the same bid id
+    fn fill(
+        &mut self,
+        inner: &TopazSellEventType,
+        market_address: &str,
+        txn_version: i64,
+        txn_timestamp: chrono::NaiveDateTime,
+    ) {
+        let pk = (
+            inner.token_id.token_data_id.get_collection_data_id_hash(),
+            inner.buyer.clone(),
+            market_address.to_string(),
+        );
+        if let Some(offer) = self.offers.get_mut(&pk) {
+            if offer.bid_id == inner.bid_id {
+                if offer.status == OFFER_STATUS_ACTIVE {
+                    offer.amount_remaining -= &inner.amount;
+                    if !offer.amount_remaining.is_positive() {
+                        offer.amount_remaining = BigDecimal::zero();
+                        offer.status = OFFER_STATUS_FILLED.to_string();
+                    }
+                    offer.last_transaction_version = txn_version;
+                    offer.last_transaction_timestamp = txn_timestamp;
+                }
+                return;
+            }
+        }
+        let fill = self
+            .fills
+            .entry((pk.clone(), inner.bid_id.clone()))
+            .or_insert_with(|| CollectionOfferFill {
+                collection_data_id_hash: pk.0,
+                buyer: pk.1,
+                market_address: pk.2,
+                bid_id: inner.bid_id.clone(),
+                amount: BigDecimal::zero(),
+                last_transaction_version: txn_version,
+                last_transaction_timestamp: txn_timestamp,
+            });
+        fill.amount += &inner.amount;
+        fill.last_transaction_version = txn_version;
+        fill.last_transaction_timestamp = txn_timestamp;
+    }
+
+    /// Offers to upsert and fills to apply to stored offers, both sorted by PK
+    pub fn into_rows(self) -> (Vec<CurrentCollectionOffer>, Vec<CollectionOfferFill>) {
+        let mut offers = self.offers.into_values().collect::<Vec<_>>();
+        offers.sort_by_key(|offer| offer.pk());
+        let mut fills = self.fills.into_values().collect::<Vec<_>>();
+        fills.sort_by(|a, b| (a.pk(), &a.bid_id).cmp(&(b.pk(), &b.bid_id)));
+        (offers, fills)
+    }
+}
+
+/// Decrements the stored offers that were filled in this batch. Runs after the batch's offers are
+/// upserted, so an offer replaced or cancelled later in the batch is left alone.
+pub fn apply_collection_offer_fills(
+    conn: &mut PgConnection,
+    fills: &[CollectionOfferFill],
+) -> QueryResult<()> {
+    if fills.is_empty() {
+        return Ok(());
+    }
+    sql_query(
+        "UPDATE current_collection_offers o SET
+            amount_remaining = GREATEST(o.amount_remaining - f.amount, 0),
+            status = CASE WHEN o.amount_remaining - f.amount <= 0 THEN $8 ELSE o.status END,
+            last_transaction_version = f.last_transaction_version,
+            last_transaction_timestamp = f.last_transaction_timestamp,
+            inserted_at = NOW()
+        FROM (
+            SELECT UNNEST($1::VARCHAR[]) AS collection_data_id_hash,
+                UNNEST($2::VARCHAR[]) AS buyer,
+                UNNEST($3::VARCHAR[]) AS market_address,
+                UNNEST($4::NUMERIC[]) AS bid_id,
+                UNNEST($5::NUMERIC[]) AS amount,
+                UNNEST($6::BIGINT[]) AS last_transaction_version,
+                UNNEST($7::TIMESTAMP[]) AS last_transaction_timestamp
+        ) f
+        WHERE o.collection_data_id_hash = f.collection_data_id_hash
+            AND o.buyer = f.buyer
+            AND o.market_address = f.market_address
+            AND o.bid_id = f.bid_id
+            AND o.status = $9
+            AND o.last_transaction_version < f.last_transaction_version",
+    )
+    .bind::<Array<Text>, _>(
+        fills
+            .iter()
+            .map(|fill| fill.collection_data_id_hash.clone())
+            .collect::<Vec<_>>(),
+    )
+    .bind::<Array<Text>, _>(
+        fills
+            .iter()
+            .map(|fill| fill.buyer.clone())
+            .collect::<Vec<_>>(),
+    )
+    .bind::<Array<Text>, _>(
+        fills
+            .iter()
+            .map(|fill| fill.market_address.clone())
+            .collect::<Vec<_>>(),
+    )
+    .bind::<Array<Numeric>, _>(
+        fills
+            .iter()
+            .map(|fill| fill.bid_id.clone())
+            .collect::<Vec<_>>(),
+    )
+    .bind::<Array<Numeric>, _>(
+        fills
+            .iter()
+            .map(|fill| fill.amount.clone())
+            .collect::<Vec<_>>(),
+    )
+    .bind::<Array<BigInt>, _>(
+        fills
+            .iter()
+            .map(|fill| fill.last_transaction_version)
+            .collect::<Vec<_>>(),
+    )
+    .bind::<Array<Timestamp>, _>(
+        fills
+            .iter()
+            .map(|fill| fill.last_transaction_timestamp)
+            .collect::<Vec<_>>(),
+    )
+    .bind::<Text, _>(OFFER_STATUS_FILLED)
+    .bind::<Text, _>(OFFER_STATUS_ACTIVE)
+    .execute(conn)?;
+    Ok(())
+}
+
+/// Recomputes the highest active APT offer of every collection with offer activity in this batch.
+/// Expiry is judged against the batch's latest transaction timestamp, offers that expire
+/// afterwards are dropped by the next expiry pass.
+pub fn refresh_collection_best_offers(
+    conn: &mut PgConnection,
+    offers: &[CurrentCollectionOffer],
+    fills: &[CollectionOfferFill],
+) -> QueryResult<()> {
+    let collection_data_id_hashes = offers
+        .iter()
+        .map(|offer| offer.collection_data_id_hash.clone())
+        .chain(
+            fills
+                .iter()
+                .map(|fill| fill.collection_data_id_hash.clone()),
+        )
+        .collect::<Vec<_>>();
+    let as_of = offers
+        .iter()
+        .map(|offer| offer.last_transaction_timestamp)
+        .chain(fills.iter().map(|fill| fill.last_transaction_timestamp))
+        .max();
+    let as_of = match as_of {
+        Some(as_of) => as_of,
+        None => return Ok(()),
+    };
+    refresh_collections_best_offers(conn, collection_data_id_hashes, as_of)
+}
+
+/// Recomputes the highest active APT offer of the given collections as of `as_of`
+pub fn refresh_collections_best_offers(
+    conn: &mut PgConnection,
+    mut collection_data_id_hashes: Vec<String>,
+    as_of: chrono::NaiveDateTime,
+) -> QueryResult<()> {
+    // Sorted to avoid deadlocks, same as the other current tables
+    collection_data_id_hashes.sort();
+    collection_data_id_hashes.dedup();
+    sql_query("DELETE FROM current_collection_best_offers WHERE collection_data_id_hash = ANY($1)")
+        .bind::<Array<Text>, _>(collection_data_id_hashes.clone())
+        .execute(conn)?;
+    sql_query(
+        "INSERT INTO current_collection_best_offers (
+            collection_data_id_hash,
+            best_offer,
+            buyer,
+            market_address,
+            bid_id,
+            deadline,
+            last_transaction_version
+        )
+        SELECT DISTINCT ON (collection_data_id_hash) collection_data_id_hash,
+            price,
+            buyer,
+            market_address,
+            bid_id,
+            deadline,
+            last_transaction_version
+        FROM current_collection_offers
+        WHERE collection_data_id_hash = ANY($1)
+            AND status = $2
+            AND amount_remaining > 0
+            AND coin_type = $3
+            AND expires_at > $4
+        ORDER BY collection_data_id_hash, price DESC, last_transaction_version",
+    )
+    .bind::<Array<Text>, _>(collection_data_id_hashes)
+    .bind::<Text, _>(OFFER_STATUS_ACTIVE)
+    .bind::<Text, _>(DEFAULT_COIN_TYPE)
+    .bind::<Timestamp, _>(as_of)
+    .execute(conn)?;
+    Ok(())
+}
+
+#[derive(Debug, QueryableByName)]
+struct ExpiredOffer {
+    #[diesel(sql_type = Text)]
+    collection_data_id_hash: String,
+}
+
+/// Expires every active offer whose deadline is at or before `as_of`, the latest processed
+/// transaction timestamp. Returns the collections of the expired offers, whose best offers need a
+/// refresh.
+pub fn expire_collection_offers(
+    conn: &mut PgConnection,
+    as_of: chrono::NaiveDateTime,
+) -> QueryResult<Vec<String>> {
+    let expired = sql_query(
+        "UPDATE current_collection_offers SET status = $1, inserted_at = NOW()
+        WHERE status = $2
+            AND expires_at <= $3
+        RETURNING collection_data_id_hash",
+    )
+    .bind::<Text, _>(OFFER_STATUS_EXPIRED)
+    .bind::<Text, _>(OFFER_STATUS_ACTIVE)
+    .bind::<Timestamp, _>(as_of)
+    .load::<ExpiredOffer>(conn)?;
+    Ok(expired
+        .into_iter()
+        .map(|offer| offer.collection_data_id_hash)
+        .collect())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    const HASH: &str = "0x6a527d06063dfd42c6b3a862574d5f3ec1660afb8058135edda5072712bfdb51";
+    const TOPAZ: &str = "0x2c7bccf7b31baf770fdbcc768d9e9cb3d87805e255355df5db32ac9a669010a2";
+
+    fn apt() -> serde_json::Value {
+        json!({
+            "account_address": "0x1",
+            "module_name": "0x6170746f735f636f696e",
+            "struct_name": "0x4170746f73436f696e"
+        })
+    }
+
+    fn user_txn(version: u64, event_name: &str, data: serde_json::Value) -> APITransaction {
+        serde_json::from_value(json!({
+            "type": "user_transaction",
+            "version": version.to_string(),
+            "hash": HASH,
+            "state_change_hash": HASH,
+            "event_root_hash": HASH,
+            "state_checkpoint_hash": null,
+            "gas_used": "100",
+            "success": true,
+            "vm_status": "Executed successfully",
+            "accumulator_root_hash": HASH,
+            "changes": [],
+            "sender": "0xb0b",
+            "sequence_number": "0",
+            "max_gas_amount": "2000",
+            "gas_unit_price": "100",
+            "expiration_timestamp_secs": "1668000600",
+            "payload": {
+                "type": "entry_function_payload",
+                "function": format!("{}::bid::collection_bid", TOPAZ),
+                "type_arguments": [],
+                "arguments": []
+            },
+            "events": [{
+                "guid": {"creation_number": "5", "account_address": TOPAZ},
+                "sequence_number": version.to_string(),
+                "type": format!("{}::events::{}", TOPAZ, event_name),
+                "data": data
+            }],
+            "timestamp": "1668000000000000"
+        }))
+        .unwrap()
+    }
+
+    fn bid_txn(version: u64, event_name: &str, bid_id: u64, amount: u64) -> APITransaction {
+        user_txn(
+            version,
+            event_name,
+            json!({
+                "timestamp": "1668000000",
+                "bid_id": bid_id.to_string(),
+                "creator": "0xc4e7",
+                "collection_name": "Potions",
+                "buyer": "0xb0b",
+                "price": "100000000",
+                "coin_type": apt(),
+                "amount": amount.to_string(),
+                "deadline": "1669000000"
+            }),
+        )
+    }
+
+    fn sell_txn(version: u64, bid_id: u64) -> APITransaction {
+        user_txn(
+            version,
+            "SellEvent",
+            json!({
+                "timestamp": "1668000000",
+                "bid_id": bid_id.to_string(),
+                "token_id": {
+                    "token_data_id": {"creator": "0xc4e7", "collection": "Potions", "name": "Potion"},
+                    "property_version": "0"
+                },
+                "deadline": "1669000000",
+                "price": "100000000",
+                "coin_type": apt(),
+                "amount": "1",
+                "buyer": "0xb0b",
+                "seller": "0xa11ce"
+            }),
+        )
+    }
+
+    fn apply(
+        transactions: &[APITransaction],
+    ) -> (Vec<CurrentCollectionOffer>, Vec<CollectionOfferFill>) {
+        let mut book = CollectionOfferBook::default();
+        for txn in transactions {
+            book.apply_transaction(txn, &TokenEvents::from_transaction(txn).unwrap());
+        }
+        book.into_rows()
+    }
+
+    #[test]
+    fn test_offer_filled_within_batch() {
+        let (offers, fills) = apply(&[
+            bid_txn(10, "CollectionBidEvent", 3, 2),
+            sell_txn(11, 3),
+            sell_txn(12, 3),
+        ]);
+        assert!(fills.is_empty());
+        assert_eq!(offers.len(), 1);
+        assert_eq!(offers[0].coin_type, DEFAULT_COIN_TYPE);
+        assert_eq!(offers[0].market_address, TOPAZ);
+        assert_eq!(offers[0].amount_remaining, BigDecimal::zero());
+        assert_eq!(offers[0].status, OFFER_STATUS_FILLED);
+        assert_eq!(offers[0].last_transaction_version, 12);
+    }
+
+    #[test]
+    fn test_fills_of_earlier_offers_are_summed() {
+        let (offers, fills) = apply(&[sell_txn(11, 3), sell_txn(12, 3), sell_txn(13, 4)]);
+        assert!(offers.is_empty());
+        assert_eq!(fills.len(), 2);
+        assert_eq!(fills[0].bid_id, BigDecimal::from(3));
+        assert_eq!(fills[0].amount, BigDecimal::from(2));
+        assert_eq!(fills[0].last_transaction_version, 12);
+    }
+
+    #[test]
+    fn test_cancel_of_older_offer_keeps_newer_one() {
+        let (offers, _) = apply(&[
+            bid_txn(10, "CollectionBidEvent", 4, 2),
+            bid_txn(11, "CancelCollectionBidEvent", 3, 1),
+        ]);
+        assert_eq!(offers[0].bid_id, BigDecimal::from(4));
+        assert_eq!(offers[0].status, OFFER_STATUS_ACTIVE);
+
+        let (offers, _) = apply(&[
+            bid_txn(10, "CollectionBidEvent", 4, 2),
+            bid_txn(11, "CancelCollectionBidEvent", 4, 2),
+        ]);
+        assert_eq!(offers[0].status, OFFER_STATUS_CANCELLED);
+    }
+
+    #[test]
+    fn test_deadline_to_expires_at() {
+        let (offers, _) = apply(&[bid_txn(10, "CollectionBidEvent", 3, 1)]);
+        assert_eq!(offers[0].expires_at.timestamp(), 1669000000);
+
+        let never = deadline_to_expires_at(&BigDecimal::from(u64::MAX), 10);
+        assert_eq!(never.timestamp(), MAX_TIMESTAMP_SECS);
+    }
+}
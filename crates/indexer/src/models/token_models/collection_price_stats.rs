@@ -0,0 +1,341 @@
+// Copyright (c) Aptos
+// SPDX-License-Identifier: Apache-2.0
+
+// This is required because a diesel macro makes clippy sad
+#![allow(clippy::extra_unused_lifetimes)]
+#![allow(clippy::unused_unit)]
+
+use super::{
+    collection_reports::{canonicalize_coin_type, DEFAULT_COIN_TYPE},
+    nft_sales::NftSale,
+};
+use crate::schema::{current_collection_price_stats, nft_sales};
+use anyhow::ensure;
+use bigdecimal::{BigDecimal, Zero};
+use diesel::{
+    result::Error, ExpressionMethods, NullableExpressionMethods, PgConnection, PgExpressionMethods,
+    QueryDsl, QueryResult, RunQueryDsl,
+};
+use field_count::FieldCount;
+use serde::{Deserialize, Serialize};
+use std::{
+    collections::{BTreeMap, HashMap},
+    sync::atomic::{AtomicU64, Ordering},
+};
+
+/// Collections whose median is recomputed per db transaction
+const COLLECTIONS_PER_TRANSACTION: usize = 100;
+
+type CollectionDataIdHash = String;
+
+/// Sale price stats per collection, in octas and from APT priced sales only. The counters are
+/// added to from every batch's sales, while `median_price` is recomputed by
+/// `CollectionPriceMedians` and isn't written here.
+#[derive(Clone, Debug, Deserialize, FieldCount, Identifiable, Insertable, Serialize)]
+#[diesel(primary_key(collection_data_id_hash))]
+#[diesel(table_name = current_collection_price_stats)]
+pub struct CurrentCollectionPriceStat {
+    pub collection_data_id_hash: String,
+    pub all_time_high_price: BigDecimal,
+    /// Version of the first sale at the all time high
+    pub all_time_high_version: i64,
+    pub min_sale_price: BigDecimal,
+    pub sale_count: i64,
+    /// Average price is sum_price / sale_count
+    pub sum_price: BigDecimal,
+    pub last_transaction_version: i64,
+    pub last_event_index: i64,
+}
+
+impl CurrentCollectionPriceStat {
+    fn new(sale: &NftSale, price: &BigDecimal) -> Self {
+        Self {
+            collection_data_id_hash: sale.collection_data_id_hash.clone(),
+            all_time_high_price: price.clone(),
+            all_time_high_version: sale.transaction_version,
+            min_sale_price: price.clone(),
+            sale_count: 0,
+            sum_price: BigDecimal::zero(),
+            last_transaction_version: sale.transaction_version,
+            last_event_index: sale.event_index,
+        }
+    }
+
+    /// The sale's price if it's counted, i.e. priced in APT and without a suspect value
+    pub fn counted_price(sale: &NftSale) -> Option<&BigDecimal> {
+        match &sale.price {
+            Some(price)
+                if !sale.suspect_value
+                    && canonicalize_coin_type(sale.coin_type.as_deref()) == DEFAULT_COIN_TYPE =>
+            {
+                Some(price)
+            }
+            _ => None,
+        }
+    }
+
+    /// Aggregates the sales per collection, sorted by collection. The all time high only moves
+    /// to a strictly higher price, or to an earlier sale at the same price, so it's the same
+    /// whichever order sales are added in.
+    pub fn from_nft_sales<'a>(nft_sales: impl IntoIterator<Item = &'a NftSale>) -> Vec<Self> {
+        let mut stats: BTreeMap<CollectionDataIdHash, Self> = BTreeMap::new();
+        for sale in nft_sales {
+            let price = match Self::counted_price(sale) {
+                Some(price) => price,
+                None => continue,
+            };
+            let stat = stats
+                .entry(sale.collection_data_id_hash.clone())
+                .or_insert_with(|| Self::new(sale, price));
+            if *price > stat.all_time_high_price
+                || (*price == stat.all_time_high_price
+                    && sale.transaction_version < stat.all_time_high_version)
+            {
+                stat.all_time_high_price = price.clone();
+                stat.all_time_high_version = sale.transaction_version;
+            }
+            if *price < stat.min_sale_price {
+                stat.min_sale_price = price.clone();
+            }
+            stat.sale_count += 1;
+            stat.sum_price += price;
+            if (sale.transaction_version, sale.event_index)
+                > (stat.last_transaction_version, stat.last_event_index)
+            {
+                stat.last_transaction_version = sale.transaction_version;
+                stat.last_event_index = sale.event_index;
+            }
+        }
+        stats.into_values().collect()
+    }
+}
+
+/// Periodically recomputes the exact median sale price of collections from nft_sales, so it
+/// needs nft_sales enabled. A median is stale once the collection's sale_count moves past the
+/// median_sale_count it was computed at, and only stale collections are recomputed.
+#[derive(Debug)]
+pub struct CollectionPriceMedians {
+    every_n_versions: u64,
+    next_refresh_version: AtomicU64,
+}
+
+impl CollectionPriceMedians {
+    pub fn from_config(every_n_versions: Option<u64>) -> anyhow::Result<Option<Self>> {
+        let every_n_versions = match every_n_versions {
+            Some(every_n_versions) => every_n_versions,
+            None => return Ok(None),
+        };
+        ensure!(
+            every_n_versions > 0,
+            "price_median_refresh_every_n_versions must be greater than 0"
+        );
+        Ok(Some(Self {
+            every_n_versions,
+            next_refresh_version: AtomicU64::new(0),
+        }))
+    }
+
+    /// Returns true if a refresh should run after the batch ending at `end_version`. The first
+    /// batch after startup always triggers one to catch up.
+    pub fn is_due(&self, end_version: u64) -> bool {
+        self.next_refresh_version
+            .fetch_update(
+                Ordering::Relaxed,
+                Ordering::Relaxed,
+                |next_refresh_version| {
+                    (end_version >= next_refresh_version)
+                        .then(|| end_version.saturating_add(self.every_n_versions))
+                },
+            )
+            .is_ok()
+    }
+
+    /// Recomputes the median of every stale collection, returning the number of collections
+    /// recomputed. A batch committing during the refresh leaves its collections stale for the
+    /// next one, since their sale_count is read before their sales.
+    pub fn refresh(conn: &mut PgConnection) -> QueryResult<usize> {
+        let mut stale = current_collection_price_stats::table
+            .filter(
+                current_collection_price_stats::median_sale_count
+                    .is_distinct_from(current_collection_price_stats::sale_count.nullable()),
+            )
+            .select((
+                current_collection_price_stats::collection_data_id_hash,
+                current_collection_price_stats::sale_count,
+            ))
+            .load::<(String, i64)>(conn)?;
+        stale.sort();
+        for chunk in stale.chunks(COLLECTIONS_PER_TRANSACTION) {
+            conn.build_transaction()
+                .read_write()
+                .run::<_, Error, _>(|pg_conn| Self::refresh_collections(pg_conn, chunk))?;
+        }
+        Ok(stale.len())
+    }
+
+    fn refresh_collections(conn: &mut PgConnection, stale: &[(String, i64)]) -> QueryResult<()> {
+        let collection_data_id_hashes = stale
+            .iter()
+            .map(|(collection_data_id_hash, _)| collection_data_id_hash.as_str())
+            .collect::<Vec<_>>();
+        let rows = nft_sales::table
+            .filter(nft_sales::collection_data_id_hash.eq_any(collection_data_id_hashes))
+            .filter(nft_sales::suspect_value.eq(false))
+            .filter(nft_sales::price.is_not_null())
+            .select((
+                nft_sales::collection_data_id_hash,
+                nft_sales::coin_type,
+                nft_sales::price,
+            ))
+            .load::<(String, Option<String>, Option<BigDecimal>)>(conn)?;
+        let mut prices: HashMap<CollectionDataIdHash, Vec<BigDecimal>> = HashMap::new();
+        for (collection_data_id_hash, coin_type, price) in rows {
+            if let Some(price) = price {
+                if canonicalize_coin_type(coin_type.as_deref()) == DEFAULT_COIN_TYPE {
+                    prices
+                        .entry(collection_data_id_hash)
+                        .or_default()
+                        .push(price);
+                }
+            }
+        }
+
+        for (collection_data_id_hash, sale_count) in stale {
+            let median_price = prices
+                .get_mut(collection_data_id_hash)
+                .and_then(|prices| median(prices));
+            diesel::update(
+                current_collection_price_stats::table.find(collection_data_id_hash.as_str()),
+            )
+            .set((
+                current_collection_price_stats::median_price.eq(median_price),
+                current_collection_price_stats::median_sale_count.eq(*sale_count),
+            ))
+            .execute(conn)?;
+        }
+        Ok(())
+    }
+}
+
+/// Middle price, or the mean of the two middle ones for an even count. None without prices.
+pub fn median(prices: &mut [BigDecimal]) -> Option<BigDecimal> {
+    if prices.is_empty() {
+        return None;
+    }
+    prices.sort();
+    let middle = prices.len() / 2;
+    if prices.len() % 2 == 1 {
+        Some(prices[middle].clone())
+    } else {
+        Some((&prices[middle - 1] + &prices[middle]) / BigDecimal::from(2))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn sale(
+        collection: &str,
+        version: i64,
+        event_index: i64,
+        coin_type: Option<&str>,
+        price: u64,
+    ) -> NftSale {
+        NftSale {
+            transaction_version: version,
+            event_account_address: "0x2c7b".to_string(),
+            event_creation_number: 0,
+            event_sequence_number: version,
+            event_index,
+            token_index: 0,
+            market_address: "0x2c7b".to_string(),
+            event_type: "0x2c7b::events::BuyEvent".to_string(),
+            token_data_id_hash: "token".to_string(),
+            property_version: BigDecimal::zero(),
+            collection_data_id_hash: collection.to_string(),
+            creator_address: "0xc4e7".to_string(),
+            collection_name: "Monkeys".to_string(),
+            name: "Monkey #1".to_string(),
+            seller: Some("0xa11ce".to_string()),
+            buyer: Some("0xb0b".to_string()),
+            token_amount: BigDecimal::from(1),
+            coin_type: coin_type.map(|coin_type| coin_type.to_string()),
+            price: Some(BigDecimal::from(price)),
+            gas_unit_price: BigDecimal::from(100),
+            transaction_rank_in_block: None,
+            transaction_timestamp: chrono::NaiveDateTime::from_timestamp(1668000000, 0),
+            is_primary: false,
+            realized_pnl: None,
+            hold_duration_secs: None,
+            source: "event".to_string(),
+            settlement_amount: None,
+            coin_price_usd: None,
+            price_usd: None,
+            price_decimal: None,
+            sale_group_id: version,
+            group_size: 1,
+            domain: None,
+            suspect_value: false,
+        }
+    }
+
+    #[test]
+    fn test_price_stats() {
+        let mut sales = vec![
+            sale("monkeys", 5, 0, None, 300),
+            sale("monkeys", 6, 1, Some("0x1::aptos_coin::AptosCoin"), 700),
+            sale("monkeys", 6, 0, None, 100),
+            // Ties don't move the all time high to a later sale
+            sale("monkeys", 8, 0, None, 700),
+            // Neither priced in APT nor with a value that can be trusted
+            sale("monkeys", 9, 0, Some("0x5e1f::usdc::USDC"), 5000),
+            sale("monkeys", 9, 1, None, 9000),
+            sale("apes", 7, 0, None, 50),
+        ];
+        sales[5].suspect_value = true;
+        let stats = CurrentCollectionPriceStat::from_nft_sales(&sales);
+        assert_eq!(stats.len(), 2);
+
+        let apes = &stats[0];
+        assert_eq!(apes.collection_data_id_hash, "apes");
+        assert_eq!(apes.all_time_high_price, BigDecimal::from(50));
+        assert_eq!(apes.min_sale_price, BigDecimal::from(50));
+
+        let monkeys = &stats[1];
+        assert_eq!(monkeys.all_time_high_price, BigDecimal::from(700));
+        assert_eq!(monkeys.all_time_high_version, 6);
+        assert_eq!(monkeys.min_sale_price, BigDecimal::from(100));
+        assert_eq!(monkeys.sale_count, 4);
+        assert_eq!(monkeys.sum_price, BigDecimal::from(1800));
+        assert_eq!(
+            (monkeys.last_transaction_version, monkeys.last_event_index),
+            (8, 0)
+        );
+
+        // The all time high set by an earlier sale doesn't depend on the order they're added in
+        sales.reverse();
+        let reversed = CurrentCollectionPriceStat::from_nft_sales(&sales);
+        assert_eq!(reversed[1].all_time_high_version, 6);
+        assert_eq!(
+            (
+                reversed[1].last_transaction_version,
+                reversed[1].last_event_index
+            ),
+            (8, 0)
+        );
+    }
+
+    #[test]
+    fn test_median() {
+        assert_eq!(median(&mut []), None);
+        assert_eq!(
+            median(&mut [3, 1, 2].map(BigDecimal::from)),
+            Some(BigDecimal::from(2))
+        );
+        assert_eq!(
+            median(&mut [4, 1, 3, 2].map(BigDecimal::from)),
+            Some("2.5".parse().unwrap())
+        );
+    }
+}
@@ -0,0 +1,439 @@
+// Copyright (c) Aptos
+// SPDX-License-Identifier: Apache-2.0
+
+// This is required because a diesel macro makes clippy sad
+#![allow(clippy::extra_unused_lifetimes)]
+#![allow(clippy::unused_unit)]
+
+use crate::{
+    database::{execute_with_better_error, get_chunks},
+    schema::{
+        collection_rarity_status, collection_trait_frequencies, current_token_datas,
+        token_properties_flat,
+    },
+};
+use anyhow::ensure;
+use diesel::{
+    dsl::{max, now},
+    pg::upsert::excluded,
+    result::Error,
+    sql_query,
+    sql_types::{Array, BigInt, Double, Text},
+    ExpressionMethods, OptionalExtension, PgConnection, QueryDsl, QueryResult, RunQueryDsl,
+};
+use field_count::FieldCount;
+use serde::{Deserialize, Serialize};
+use std::{
+    collections::{HashMap, HashSet},
+    sync::atomic::{AtomicU64, Ordering},
+};
+
+/// Collections recomputed per db transaction
+const COLLECTIONS_PER_TRANSACTION: usize = 100;
+
+type CollectionDataIdHash = String;
+type TokenDataIdHash = String;
+/// (collection_data_id_hash, token_data_id_hash, property_key, property_value, last_transaction_version)
+pub type PropertyRow = (String, String, String, String, i64);
+
+#[derive(Debug, Deserialize, FieldCount, Identifiable, Insertable, Serialize)]
+#[diesel(primary_key(collection_data_id_hash, property_key, property_value))]
+#[diesel(table_name = collection_trait_frequencies)]
+pub struct CollectionTraitFrequency {
+    pub collection_data_id_hash: String,
+    pub property_key: String,
+    pub property_value: String,
+    pub token_count: i64,
+    pub frequency: f64,
+    pub last_transaction_version: i64,
+}
+
+/// Written to the rarity columns of current_token_datas
+#[derive(Debug, PartialEq)]
+pub struct TokenRarity {
+    pub token_data_id_hash: String,
+    pub rarity_score: f64,
+    pub rarity_rank: i64,
+}
+
+/// Periodically recomputes trait frequencies and rarity scores from token_properties_flat.
+/// A token's score is the sum of 1 / frequency over its properties, and tokens without
+/// properties aren't ranked. Missing traits don't count towards the score.
+///
+/// Only collections with token data changes since the last refresh are recomputed. The version
+/// the rarity tables are up to date with is kept in collection_rarity_status.
+#[derive(Debug)]
+pub struct CollectionRarity {
+    every_n_versions: u64,
+    next_refresh_version: AtomicU64,
+}
+
+impl CollectionRarity {
+    pub fn from_config(every_n_versions: Option<u64>) -> anyhow::Result<Option<Self>> {
+        let every_n_versions = match every_n_versions {
+            Some(every_n_versions) => every_n_versions,
+            None => return Ok(None),
+        };
+        ensure!(
+            every_n_versions > 0,
+            "rarity_refresh_every_n_versions must be greater than 0"
+        );
+        Ok(Some(Self {
+            every_n_versions,
+            next_refresh_version: AtomicU64::new(0),
+        }))
+    }
+
+    /// Returns true if a refresh should run after the batch ending at `end_version`. The first
+    /// batch after startup always triggers one to catch up.
+    pub fn is_due(&self, end_version: u64) -> bool {
+        self.next_refresh_version
+            .fetch_update(
+                Ordering::Relaxed,
+                Ordering::Relaxed,
+                |next_refresh_version| {
+                    (end_version >= next_refresh_version)
+                        .then(|| end_version.saturating_add(self.every_n_versions))
+                },
+            )
+            .is_ok()
+    }
+
+    /// Batches commit out of order, so a refresh triggered by the batch ending at `end_version`
+    /// only picks up changes at least `every_n_versions` older than it. Anything newer is
+    /// picked up by the next refresh.
+    pub fn run(
+        &self,
+        conn: &mut PgConnection,
+        processor_name: &str,
+        end_version: u64,
+    ) -> QueryResult<usize> {
+        let up_to_version = end_version.saturating_sub(self.every_n_versions) as i64;
+        Self::refresh(conn, processor_name, Some(up_to_version))
+    }
+
+    /// Recomputes every collection with token data changes in (last refresh, `up_to_version`],
+    /// returning the number of collections recomputed. Defaults to the latest token data change
+    /// if `up_to_version` isn't set.
+    pub fn refresh(
+        conn: &mut PgConnection,
+        processor_name: &str,
+        up_to_version: Option<i64>,
+    ) -> QueryResult<usize> {
+        let from_version = collection_rarity_status::table
+            .filter(collection_rarity_status::processor.eq(processor_name))
+            .select(collection_rarity_status::last_transaction_version)
+            .first::<i64>(conn)
+            .optional()?
+            .unwrap_or(-1);
+        let up_to_version = match up_to_version {
+            Some(up_to_version) => up_to_version,
+            None => current_token_datas::table
+                .select(max(current_token_datas::last_transaction_version))
+                .first::<Option<i64>>(conn)?
+                .unwrap_or(-1),
+        };
+        if up_to_version <= from_version {
+            return Ok(0);
+        }
+
+        let mut collection_data_id_hashes = current_token_datas::table
+            .filter(current_token_datas::last_transaction_version.gt(from_version))
+            .filter(current_token_datas::last_transaction_version.le(up_to_version))
+            .select(current_token_datas::collection_data_id_hash)
+            .distinct()
+            .load::<String>(conn)?;
+        collection_data_id_hashes.sort();
+        for chunk in collection_data_id_hashes.chunks(COLLECTIONS_PER_TRANSACTION) {
+            conn.build_transaction()
+                .read_write()
+                .run::<_, Error, _>(|pg_conn| Self::refresh_collections(pg_conn, chunk))?;
+        }
+
+        diesel::insert_into(collection_rarity_status::table)
+            .values((
+                collection_rarity_status::processor.eq(processor_name),
+                collection_rarity_status::last_transaction_version.eq(up_to_version),
+            ))
+            .on_conflict(collection_rarity_status::processor)
+            .do_update()
+            .set((
+                collection_rarity_status::last_transaction_version
+                    .eq(excluded(collection_rarity_status::last_transaction_version)),
+                collection_rarity_status::inserted_at.eq(now),
+            ))
+            .execute(conn)?;
+        Ok(collection_data_id_hashes.len())
+    }
+
+    fn refresh_collections(
+        conn: &mut PgConnection,
+        collection_data_id_hashes: &[String],
+    ) -> QueryResult<()> {
+        let rows = token_properties_flat::table
+            .filter(
+                token_properties_flat::collection_data_id_hash.eq_any(collection_data_id_hashes),
+            )
+            .select((
+                token_properties_flat::collection_data_id_hash,
+                token_properties_flat::token_data_id_hash,
+                token_properties_flat::property_key,
+                token_properties_flat::property_value,
+                token_properties_flat::last_transaction_version,
+            ))
+            .load::<PropertyRow>(conn)?;
+        let (frequencies, rarities) = Self::compute(&rows);
+
+        diesel::delete(collection_trait_frequencies::table.filter(
+            collection_trait_frequencies::collection_data_id_hash.eq_any(collection_data_id_hashes),
+        ))
+        .execute(conn)?;
+        for (start_ind, end_ind) in
+            get_chunks(frequencies.len(), CollectionTraitFrequency::field_count())
+        {
+            execute_with_better_error(
+                conn,
+                diesel::insert_into(collection_trait_frequencies::table)
+                    .values(&frequencies[start_ind..end_ind]),
+                None,
+            )?;
+        }
+
+        // Tokens that lost all their properties aren't in `rarities`
+        diesel::update(
+            current_token_datas::table
+                .filter(
+                    current_token_datas::collection_data_id_hash.eq_any(collection_data_id_hashes),
+                )
+                .filter(current_token_datas::rarity_rank.is_not_null()),
+        )
+        .set((
+            current_token_datas::rarity_score.eq(None::<f64>),
+            current_token_datas::rarity_rank.eq(None::<i64>),
+        ))
+        .execute(conn)?;
+        for (start_ind, end_ind) in get_chunks(rarities.len(), 3) {
+            let rarities = &rarities[start_ind..end_ind];
+            sql_query(
+                "UPDATE current_token_datas t
+                SET rarity_score = r.rarity_score, rarity_rank = r.rarity_rank
+                FROM (
+                    SELECT UNNEST($1::text[]) AS token_data_id_hash,
+                        UNNEST($2::double precision[]) AS rarity_score,
+                        UNNEST($3::bigint[]) AS rarity_rank
+                ) r
+                WHERE t.token_data_id_hash = r.token_data_id_hash",
+            )
+            .bind::<Array<Text>, _>(
+                rarities
+                    .iter()
+                    .map(|rarity| rarity.token_data_id_hash.clone())
+                    .collect::<Vec<_>>(),
+            )
+            .bind::<Array<Double>, _>(
+                rarities
+                    .iter()
+                    .map(|rarity| rarity.rarity_score)
+                    .collect::<Vec<_>>(),
+            )
+            .bind::<Array<BigInt>, _>(
+                rarities
+                    .iter()
+                    .map(|rarity| rarity.rarity_rank)
+                    .collect::<Vec<_>>(),
+            )
+            .execute(conn)?;
+        }
+        Ok(())
+    }
+
+    /// Frequencies and rarities for every collection in `rows`, sorted by primary key. Tokens
+    /// with equal scores share a rank, and the next rank skips accordingly.
+    pub fn compute(rows: &[PropertyRow]) -> (Vec<CollectionTraitFrequency>, Vec<TokenRarity>) {
+        let mut collection_tokens: HashMap<&str, HashSet<&str>> = HashMap::new();
+        let mut trait_counts: HashMap<(&str, &str, &str), (i64, i64)> = HashMap::new();
+        for (collection_data_id_hash, token_data_id_hash, key, value, version) in rows {
+            collection_tokens
+                .entry(collection_data_id_hash.as_str())
+                .or_default()
+                .insert(token_data_id_hash.as_str());
+            let (token_count, last_transaction_version) = trait_counts
+                .entry((
+                    collection_data_id_hash.as_str(),
+                    key.as_str(),
+                    value.as_str(),
+                ))
+                .or_insert((0, *version));
+            *token_count += 1;
+            *last_transaction_version = (*last_transaction_version).max(*version);
+        }
+
+        let mut frequencies = trait_counts
+            .into_iter()
+            .map(
+                |(
+                    (collection_data_id_hash, key, value),
+                    (token_count, last_transaction_version),
+                )| {
+                    CollectionTraitFrequency {
+                        collection_data_id_hash: collection_data_id_hash.to_string(),
+                        property_key: key.to_string(),
+                        property_value: value.to_string(),
+                        token_count,
+                        frequency: token_count as f64
+                            / collection_tokens[collection_data_id_hash].len() as f64,
+                        last_transaction_version,
+                    }
+                },
+            )
+            .collect::<Vec<_>>();
+        frequencies.sort_by(|a, b| {
+            (
+                &a.collection_data_id_hash,
+                &a.property_key,
+                &a.property_value,
+            )
+                .cmp(&(
+                    &b.collection_data_id_hash,
+                    &b.property_key,
+                    &b.property_value,
+                ))
+        });
+
+        let frequency_lookup = frequencies
+            .iter()
+            .map(|frequency| {
+                (
+                    (
+                        frequency.collection_data_id_hash.as_str(),
+                        frequency.property_key.as_str(),
+                        frequency.property_value.as_str(),
+                    ),
+                    frequency.frequency,
+                )
+            })
+            .collect::<HashMap<_, _>>();
+        let mut token_terms: HashMap<(&str, &str), Vec<f64>> = HashMap::new();
+        for (collection_data_id_hash, token_data_id_hash, key, value, _) in rows {
+            token_terms
+                .entry((
+                    collection_data_id_hash.as_str(),
+                    token_data_id_hash.as_str(),
+                ))
+                .or_default()
+                .push(
+                    1.0 / frequency_lookup[&(
+                        collection_data_id_hash.as_str(),
+                        key.as_str(),
+                        value.as_str(),
+                    )],
+                );
+        }
+        let mut collection_scores: HashMap<CollectionDataIdHash, Vec<(TokenDataIdHash, f64)>> =
+            HashMap::new();
+        for ((collection_data_id_hash, token_data_id_hash), mut terms) in token_terms {
+            // Summing in a fixed order so tokens with the same traits get exactly the same score
+            terms.sort_by(|a, b| a.total_cmp(b));
+            collection_scores
+                .entry(collection_data_id_hash.to_string())
+                .or_default()
+                .push((token_data_id_hash.to_string(), terms.iter().sum()));
+        }
+
+        let mut rarities = vec![];
+        for (_, mut scores) in collection_scores {
+            scores.sort_by(|a, b| b.1.total_cmp(&a.1).then_with(|| a.0.cmp(&b.0)));
+            let mut rarity_rank = 0;
+            for (index, (token_data_id_hash, rarity_score)) in scores.iter().enumerate() {
+                if index == 0 || *rarity_score != scores[index - 1].1 {
+                    rarity_rank = index as i64 + 1;
+                }
+                rarities.push(TokenRarity {
+                    token_data_id_hash: token_data_id_hash.clone(),
+                    rarity_score: *rarity_score,
+                    rarity_rank,
+                });
+            }
+        }
+        rarities.sort_by(|a, b| a.token_data_id_hash.cmp(&b.token_data_id_hash));
+        (frequencies, rarities)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn rows(properties: &[(&str, &str, &str, &str)]) -> Vec<PropertyRow> {
+        properties
+            .iter()
+            .enumerate()
+            .map(|(index, (collection, token, key, value))| {
+                (
+                    collection.to_string(),
+                    token.to_string(),
+                    key.to_string(),
+                    value.to_string(),
+                    index as i64,
+                )
+            })
+            .collect()
+    }
+
+    #[test]
+    fn test_rarity_scores() {
+        let (frequencies, rarities) = CollectionRarity::compute(&rows(&[
+            ("potions", "a", "Background", "Gold"),
+            ("potions", "a", "Level", "1"),
+            ("potions", "b", "Background", "Blue"),
+            ("potions", "b", "Level", "1"),
+            ("potions", "c", "Level", "2"),
+            ("potions", "c", "Background", "Blue"),
+            ("potions", "d", "Background", "Blue"),
+            ("potions", "d", "Level", "2"),
+            ("swords", "e", "Background", "Gold"),
+        ]));
+        assert_eq!(
+            frequencies
+                .iter()
+                .map(|frequency| (
+                    frequency.collection_data_id_hash.as_str(),
+                    frequency.property_value.as_str(),
+                    frequency.token_count,
+                    frequency.frequency,
+                    frequency.last_transaction_version,
+                ))
+                .collect::<Vec<_>>(),
+            vec![
+                ("potions", "Blue", 3, 0.75, 6),
+                ("potions", "Gold", 1, 0.25, 0),
+                ("potions", "1", 2, 0.5, 3),
+                ("potions", "2", 2, 0.5, 7),
+                ("swords", "Gold", 1, 1.0, 8),
+            ]
+        );
+        assert_eq!(
+            rarities
+                .iter()
+                .map(|rarity| (rarity.token_data_id_hash.as_str(), rarity.rarity_rank))
+                .collect::<Vec<_>>(),
+            vec![("a", 1), ("b", 2), ("c", 2), ("d", 2), ("e", 1)]
+        );
+        assert_eq!(rarities[0].rarity_score, 6.0);
+        assert_eq!(rarities[1].rarity_score, rarities[2].rarity_score);
+        assert_eq!(rarities[4].rarity_score, 1.0);
+    }
+
+    #[test]
+    fn test_refresh_interval() {
+        assert!(CollectionRarity::from_config(None).unwrap().is_none());
+        assert!(CollectionRarity::from_config(Some(0)).is_err());
+
+        let rarity = CollectionRarity::from_config(Some(100)).unwrap().unwrap();
+        let due = [50, 120, 149, 150, 249, 260]
+            .iter()
+            .map(|end_version| rarity.is_due(*end_version))
+            .collect::<Vec<_>>();
+        assert_eq!(due, vec![true, false, false, true, false, true]);
+    }
+}
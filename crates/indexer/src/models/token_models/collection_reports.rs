@@ -0,0 +1,427 @@
+// Copyright (c) Aptos
+// SPDX-License-Identifier: Apache-2.0
+
+// This is required because a diesel macro makes clippy sad
+#![allow(clippy::extra_unused_lifetimes)]
+#![allow(clippy::unused_unit)]
+
+use super::nft_sales::NftSale;
+use crate::schema::{collection_daily_reports, collection_price_candles};
+use bigdecimal::BigDecimal;
+use field_count::FieldCount;
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+
+/// Sales without a coin type in the event are priced in APT
+pub const DEFAULT_COIN_TYPE: &str = "0x1::aptos_coin::AptosCoin";
+/// Value of the coin_type and market_address dimensions on rollup rows
+pub const ALL_DIMENSIONS: &str = "all";
+const CANDLE_INTERVAL_SECS: i64 = 3600;
+
+type CollectionDataIdHash = String;
+type CoinType = String;
+type MarketAddress = String;
+// PK of collection_price_candles
+pub type CollectionPriceCandlePK = (
+    CollectionDataIdHash,
+    CoinType,
+    MarketAddress,
+    chrono::NaiveDateTime,
+);
+// PK of collection_daily_reports
+pub type CollectionDailyReportPK = (
+    CollectionDataIdHash,
+    CoinType,
+    MarketAddress,
+    chrono::NaiveDate,
+);
+
+/// Hourly OHLC of sale prices, per currency and marketplace, plus a rollup row across both
+#[derive(Clone, Debug, Deserialize, FieldCount, Identifiable, Insertable, Serialize)]
+#[diesel(primary_key(collection_data_id_hash, coin_type, market_address, interval_start))]
+#[diesel(table_name = collection_price_candles)]
+pub struct CollectionPriceCandle {
+    pub collection_data_id_hash: String,
+    pub coin_type: String,
+    pub market_address: String,
+    pub interval_start: chrono::NaiveDateTime,
+    pub open_price: BigDecimal,
+    pub high_price: BigDecimal,
+    pub low_price: BigDecimal,
+    pub close_price: BigDecimal,
+    pub volume: BigDecimal,
+    pub sales_count: i64,
+    pub first_transaction_version: i64,
+    pub last_transaction_version: i64,
+}
+
+/// Daily sales summary, per currency and marketplace, plus a rollup row across both
+#[derive(Clone, Debug, Deserialize, FieldCount, Identifiable, Insertable, Serialize)]
+#[diesel(primary_key(collection_data_id_hash, coin_type, market_address, report_date))]
+#[diesel(table_name = collection_daily_reports)]
+pub struct CollectionDailyReport {
+    pub collection_data_id_hash: String,
+    pub coin_type: String,
+    pub market_address: String,
+    pub report_date: chrono::NaiveDate,
+    pub volume: BigDecimal,
+    pub sales_count: i64,
+    pub min_price: BigDecimal,
+    pub max_price: BigDecimal,
+    pub last_transaction_version: i64,
+}
+
+/// Coin types come from move type strings, which may or may not have leading zeros in the address
+pub fn canonicalize_coin_type(coin_type: Option<&str>) -> String {
+    match coin_type {
+        Some(coin_type) => match coin_type.split_once("::") {
+            Some((address, rest)) => {
+                let trimmed = address.trim_start_matches("0x").trim_start_matches('0');
+                let trimmed = if trimmed.is_empty() { "0" } else { trimmed };
+                format!("0x{}::{}", trimmed.to_lowercase(), rest)
+            }
+            None => coin_type.to_string(),
+        },
+        None => DEFAULT_COIN_TYPE.to_string(),
+    }
+}
+
+/// Returns the dimensioned key parts followed by the rollup key parts
+fn dimensions(sale: &NftSale) -> [(String, String); 2] {
+    [
+        (
+            canonicalize_coin_type(sale.coin_type.as_deref()),
+            sale.market_address.clone(),
+        ),
+        (ALL_DIMENSIONS.to_string(), ALL_DIMENSIONS.to_string()),
+    ]
+}
+
+impl CollectionPriceCandle {
+    fn from_sale(
+        sale: &NftSale,
+        price: &BigDecimal,
+        coin_type: String,
+        market_address: String,
+    ) -> Self {
+        let timestamp = sale.transaction_timestamp.timestamp();
+        Self {
+            collection_data_id_hash: sale.collection_data_id_hash.clone(),
+            coin_type,
+            market_address,
+            interval_start: chrono::NaiveDateTime::from_timestamp(
+                timestamp - timestamp.rem_euclid(CANDLE_INTERVAL_SECS),
+                0,
+            ),
+            open_price: price.clone(),
+            high_price: price.clone(),
+            low_price: price.clone(),
+            close_price: price.clone(),
+            volume: price.clone(),
+            sales_count: 1,
+            first_transaction_version: sale.transaction_version,
+            last_transaction_version: sale.transaction_version,
+        }
+    }
+
+    pub fn pk(&self) -> CollectionPriceCandlePK {
+        (
+            self.collection_data_id_hash.clone(),
+            self.coin_type.clone(),
+            self.market_address.clone(),
+            self.interval_start,
+        )
+    }
+
+    /// Combines two candles of the same interval, in any order. This is also what the upsert does.
+    pub fn merge(&mut self, other: &Self) {
+        if other.first_transaction_version < self.first_transaction_version {
+            self.open_price = other.open_price.clone();
+            self.first_transaction_version = other.first_transaction_version;
+        }
+        if other.last_transaction_version > self.last_transaction_version {
+            self.close_price = other.close_price.clone();
+            self.last_transaction_version = other.last_transaction_version;
+        }
+        if other.high_price > self.high_price {
+            self.high_price = other.high_price.clone();
+        }
+        if other.low_price < self.low_price {
+            self.low_price = other.low_price.clone();
+        }
+        self.volume += &other.volume;
+        self.sales_count += other.sales_count;
+    }
+}
+
+impl CollectionDailyReport {
+    fn from_sale(
+        sale: &NftSale,
+        price: &BigDecimal,
+        coin_type: String,
+        market_address: String,
+    ) -> Self {
+        Self {
+            collection_data_id_hash: sale.collection_data_id_hash.clone(),
+            coin_type,
+            market_address,
+            report_date: sale.transaction_timestamp.date(),
+            volume: price.clone(),
+            sales_count: 1,
+            min_price: price.clone(),
+            max_price: price.clone(),
+            last_transaction_version: sale.transaction_version,
+        }
+    }
+
+    pub fn pk(&self) -> CollectionDailyReportPK {
+        (
+            self.collection_data_id_hash.clone(),
+            self.coin_type.clone(),
+            self.market_address.clone(),
+            self.report_date,
+        )
+    }
+
+    /// Combines two reports of the same day, in any order. This is also what the upsert does.
+    pub fn merge(&mut self, other: &Self) {
+        if other.min_price < self.min_price {
+            self.min_price = other.min_price.clone();
+        }
+        if other.max_price > self.max_price {
+            self.max_price = other.max_price.clone();
+        }
+        self.volume += &other.volume;
+        self.sales_count += other.sales_count;
+        self.last_transaction_version = std::cmp::max(
+            self.last_transaction_version,
+            other.last_transaction_version,
+        );
+    }
+
+    /// Aggregates a batch of sales into candles and daily reports. Every sale lands in its
+    /// (coin_type, market_address) row and in the "all" rollup row. Sales without a price, or with
+    /// a suspect one, are skipped. Both are sorted by primary key.
+    pub fn from_nft_sales<'a>(
+        nft_sales: impl IntoIterator<Item = &'a NftSale>,
+    ) -> (
+        BTreeMap<CollectionPriceCandlePK, CollectionPriceCandle>,
+        BTreeMap<CollectionDailyReportPK, Self>,
+    ) {
+        let mut candles: BTreeMap<CollectionPriceCandlePK, CollectionPriceCandle> = BTreeMap::new();
+        let mut reports: BTreeMap<CollectionDailyReportPK, Self> = BTreeMap::new();
+        for sale in nft_sales.into_iter().filter(|sale| !sale.suspect_value) {
+            let price = match &sale.price {
+                Some(price) => price,
+                None => continue,
+            };
+            for (coin_type, market_address) in dimensions(sale) {
+                let candle = CollectionPriceCandle::from_sale(
+                    sale,
+                    price,
+                    coin_type.clone(),
+                    market_address.clone(),
+                );
+                match candles.get_mut(&candle.pk()) {
+                    Some(existing) => existing.merge(&candle),
+                    None => {
+                        candles.insert(candle.pk(), candle);
+                    }
+                }
+                let report = Self::from_sale(sale, price, coin_type, market_address);
+                match reports.get_mut(&report.pk()) {
+                    Some(existing) => existing.merge(&report),
+                    None => {
+                        reports.insert(report.pk(), report);
+                    }
+                }
+            }
+        }
+        (candles, reports)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use bigdecimal::Zero;
+    use std::collections::HashMap;
+
+    const TOPAZ: &str = "0x2c7bccf7b31baf770fdbcc768d9e9cb3d87805e255355df5db32ac9a669010a2";
+    const SOUFFL3: &str = "0xf6994988bd40261af9431cd6dd3fcf765569719e66322c7a05cc78a89cd366d4";
+
+    fn sale(
+        version: i64,
+        timestamp: i64,
+        market_address: &str,
+        coin_type: Option<&str>,
+        price: Option<u64>,
+    ) -> NftSale {
+        NftSale {
+            transaction_version: version,
+            event_account_address: market_address.to_string(),
+            event_creation_number: 0,
+            event_sequence_number: version,
+            event_index: 0,
+            token_index: 0,
+            market_address: market_address.to_string(),
+            event_type: format!("{}::events::BuyEvent", market_address),
+            token_data_id_hash: "token".to_string(),
+            property_version: BigDecimal::zero(),
+            collection_data_id_hash: "collection".to_string(),
+            creator_address: "0xc4e7".to_string(),
+            collection_name: "Monkeys".to_string(),
+            name: "Monkey #1".to_string(),
+            seller: None,
+            buyer: None,
+            token_amount: BigDecimal::from(1),
+            coin_type: coin_type.map(|coin_type| coin_type.to_string()),
+            price: price.map(BigDecimal::from),
+            gas_unit_price: BigDecimal::from(100),
+            transaction_rank_in_block: None,
+            transaction_timestamp: chrono::NaiveDateTime::from_timestamp(timestamp, 0),
+            is_primary: false,
+            realized_pnl: None,
+            hold_duration_secs: None,
+            source: "event".to_string(),
+            settlement_amount: None,
+            coin_price_usd: None,
+            price_usd: None,
+            price_decimal: None,
+            sale_group_id: version,
+            group_size: 1,
+            domain: None,
+            suspect_value: false,
+        }
+    }
+
+    fn sales() -> Vec<NftSale> {
+        vec![
+            sale(1, 1668000000, TOPAZ, None, Some(300)),
+            sale(
+                2,
+                1668000100,
+                SOUFFL3,
+                Some("0x01::aptos_coin::AptosCoin"),
+                Some(500),
+            ),
+            sale(3, 1668000200, TOPAZ, Some("0x5e1f::usdc::USDC"), Some(20)),
+            sale(4, 1668000300, TOPAZ, None, Some(100)),
+            sale(5, 1668000400, TOPAZ, None, None),
+            // Next hour, same day
+            sale(6, 1668004000, TOPAZ, None, Some(200)),
+        ]
+    }
+
+    #[test]
+    fn test_canonicalize_coin_type() {
+        assert_eq!(canonicalize_coin_type(None), DEFAULT_COIN_TYPE);
+        assert_eq!(
+            canonicalize_coin_type(Some("0x0001::aptos_coin::AptosCoin")),
+            DEFAULT_COIN_TYPE
+        );
+        assert_eq!(
+            canonicalize_coin_type(Some("0x5E1F::usdc::USDC")),
+            "0x5e1f::usdc::USDC"
+        );
+    }
+
+    #[test]
+    fn test_dimensioned_rows() {
+        let (candles, reports) = CollectionDailyReport::from_nft_sales(&sales());
+        // 3 (coin, market) pairs + rollup in the first hour, 1 + rollup in the second
+        assert_eq!(candles.len(), 6);
+        assert_eq!(reports.len(), 4);
+
+        let topaz_apt = &candles[&(
+            "collection".to_string(),
+            DEFAULT_COIN_TYPE.to_string(),
+            TOPAZ.to_string(),
+            chrono::NaiveDateTime::from_timestamp(1667998800, 0),
+        )];
+        assert_eq!(topaz_apt.open_price, BigDecimal::from(300));
+        assert_eq!(topaz_apt.close_price, BigDecimal::from(100));
+        assert_eq!(topaz_apt.volume, BigDecimal::from(400));
+        assert_eq!(topaz_apt.sales_count, 2);
+    }
+
+    #[test]
+    fn test_suspect_sales_are_skipped() {
+        let mut suspect = sale(7, 1668000500, TOPAZ, None, Some(u64::MAX));
+        suspect.suspect_value = true;
+        let (candles, reports) =
+            CollectionDailyReport::from_nft_sales(&[sales(), vec![suspect]].concat());
+        assert_eq!((candles.len(), reports.len()), (6, 4));
+
+        let topaz_apt = &candles[&(
+            "collection".to_string(),
+            DEFAULT_COIN_TYPE.to_string(),
+            TOPAZ.to_string(),
+            chrono::NaiveDateTime::from_timestamp(1667998800, 0),
+        )];
+        assert_eq!(topaz_apt.high_price, BigDecimal::from(300));
+        assert_eq!(topaz_apt.volume, BigDecimal::from(400));
+        assert_eq!(topaz_apt.sales_count, 2);
+    }
+
+    #[test]
+    fn test_rollup_matches_dimensions() {
+        let (candles, reports) = CollectionDailyReport::from_nft_sales(&sales());
+
+        let mut merged_candles: HashMap<chrono::NaiveDateTime, CollectionPriceCandle> =
+            HashMap::new();
+        for candle in candles.values().filter(|c| c.coin_type != ALL_DIMENSIONS) {
+            match merged_candles.get_mut(&candle.interval_start) {
+                Some(existing) => existing.merge(candle),
+                None => {
+                    merged_candles.insert(candle.interval_start, candle.clone());
+                }
+            }
+        }
+        for candle in candles.values().filter(|c| c.coin_type == ALL_DIMENSIONS) {
+            assert_eq!(candle.market_address, ALL_DIMENSIONS);
+            let merged = &merged_candles[&candle.interval_start];
+            assert_eq!(
+                (
+                    &candle.open_price,
+                    &candle.high_price,
+                    &candle.low_price,
+                    &candle.close_price,
+                    &candle.volume,
+                    candle.sales_count,
+                ),
+                (
+                    &merged.open_price,
+                    &merged.high_price,
+                    &merged.low_price,
+                    &merged.close_price,
+                    &merged.volume,
+                    merged.sales_count,
+                )
+            );
+        }
+
+        let dimensioned: Vec<&CollectionDailyReport> = reports
+            .values()
+            .filter(|r| r.coin_type != ALL_DIMENSIONS)
+            .collect();
+        let rollup = reports
+            .values()
+            .find(|r| r.coin_type == ALL_DIMENSIONS)
+            .unwrap();
+        assert_eq!(
+            rollup.volume,
+            dimensioned
+                .iter()
+                .map(|r| r.volume.clone())
+                .sum::<BigDecimal>()
+        );
+        assert_eq!(
+            rollup.sales_count,
+            dimensioned.iter().map(|r| r.sales_count).sum::<i64>()
+        );
+        assert_eq!(rollup.sales_count, 5);
+        assert_eq!(rollup.min_price, BigDecimal::from(20));
+        assert_eq!(rollup.max_price, BigDecimal::from(500));
+    }
+}
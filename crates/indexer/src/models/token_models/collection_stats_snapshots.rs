@@ -0,0 +1,363 @@
+// Copyright (c) Aptos
+// SPDX-License-Identifier: Apache-2.0
+
+// This is required because a diesel macro makes clippy sad
+#![allow(clippy::extra_unused_lifetimes)]
+#![allow(clippy::unused_unit)]
+
+use super::token_utils::ValueLimits;
+use crate::schema::collection_stats_snapshots;
+use anyhow::ensure;
+use aptos_config::config::CollectionStatsSnapshotsConfig;
+use bigdecimal::BigDecimal;
+use diesel::{
+    sql_query,
+    sql_types::{Bool, Date, Numeric, Timestamp},
+    PgConnection, QueryResult, RunQueryDsl,
+};
+use serde::{Deserialize, Serialize};
+use std::sync::atomic::{AtomicI64, Ordering};
+
+pub const DEFAULT_INTERVAL_SECS: u64 = 3600;
+
+#[derive(Debug, Deserialize, Identifiable, Queryable, Selectable, Serialize)]
+#[diesel(primary_key(collection_data_id_hash, snapshot_date))]
+#[diesel(table_name = collection_stats_snapshots)]
+pub struct CollectionStatsSnapshot {
+    pub collection_data_id_hash: String,
+    pub snapshot_date: chrono::NaiveDate,
+    pub creator_address: String,
+    pub collection_name: String,
+    pub floor_price: Option<BigDecimal>,
+    pub listed_count: Option<i64>,
+    pub holder_count: Option<i64>,
+    pub supply: Option<BigDecimal>,
+    pub volume_24h: BigDecimal,
+    pub sales_count_24h: i64,
+    pub average_sale_price_24h: Option<BigDecimal>,
+    pub total_volume: Option<BigDecimal>,
+    pub snapshot_at: chrono::NaiveDateTime,
+    pub is_backfilled: bool,
+}
+
+/// Periodically materializes a snapshot of every collection for the day of the batch that
+/// triggered it, from the current tables and the last 24 hours of nft_sales. Later snapshots of
+/// the same day overwrite it, so each day keeps the last one taken.
+///
+/// The interval is in chain time, so catching up on old versions still leaves one snapshot per
+/// day, though only as of the current tables when the batch was committed.
+#[derive(Debug)]
+pub struct CollectionStatsSnapshots {
+    interval_secs: i64,
+    next_snapshot_secs: AtomicI64,
+}
+
+impl CollectionStatsSnapshots {
+    pub fn from_config(
+        config: Option<&CollectionStatsSnapshotsConfig>,
+    ) -> anyhow::Result<Option<Self>> {
+        let config = match config {
+            Some(config) => config,
+            None => return Ok(None),
+        };
+        let interval_secs = config.interval_secs.unwrap_or(DEFAULT_INTERVAL_SECS);
+        ensure!(interval_secs > 0, "interval_secs must be greater than 0");
+        ensure!(
+            interval_secs <= 86400,
+            "interval_secs can't be more than a day, or days would be skipped"
+        );
+        Ok(Some(Self {
+            interval_secs: interval_secs as i64,
+            next_snapshot_secs: AtomicI64::new(i64::MIN),
+        }))
+    }
+
+    /// Returns true if a snapshot should be taken after the batch ending at `as_of`. The first
+    /// batch after startup always takes one.
+    pub fn is_due(&self, as_of: chrono::NaiveDateTime) -> bool {
+        let as_of_secs = as_of.timestamp();
+        self.next_snapshot_secs
+            .fetch_update(Ordering::Relaxed, Ordering::Relaxed, |next_snapshot_secs| {
+                (as_of_secs >= next_snapshot_secs)
+                    .then(|| as_of_secs.saturating_add(self.interval_secs))
+            })
+            .is_ok()
+    }
+
+    /// Writes every collection's snapshot for the day of `as_of`, returning the number of rows
+    /// written. A snapshot older than the stored one for the day is skipped, since batches can
+    /// commit out of order. Listings priced out of the ValueLimits don't count towards the floor.
+    pub fn snapshot(conn: &mut PgConnection, as_of: chrono::NaiveDateTime) -> QueryResult<usize> {
+        sql_query(
+            "INSERT INTO collection_stats_snapshots (
+                collection_data_id_hash, snapshot_date, creator_address, collection_name,
+                floor_price, listed_count, holder_count, supply, volume_24h, sales_count_24h,
+                average_sale_price_24h, total_volume, snapshot_at, is_backfilled
+            )
+            SELECT cd.collection_data_id_hash, $1, cd.creator_address, cd.collection_name,
+                l.floor_price, COALESCE(l.listed_count, 0), COALESCE(h.distinct_holders, 0),
+                cd.supply, COALESCE(s.volume, 0), COALESCE(s.sales_count, 0), s.average_price,
+                COALESCE(v.volume, 0), $2, false
+            FROM current_collection_datas cd
+            LEFT JOIN (
+                SELECT collection_data_id_hash, MIN(price) AS floor_price, COUNT(*) AS listed_count
+                FROM current_marketplace_listings
+                WHERE amount > 0 AND invalidated_reason IS NULL AND price >= 0 AND price <= $3
+                GROUP BY collection_data_id_hash
+            ) l ON l.collection_data_id_hash = cd.collection_data_id_hash
+            LEFT JOIN current_collection_holder_counts h
+                ON h.collection_data_id_hash = cd.collection_data_id_hash
+            LEFT JOIN current_collection_volumes v
+                ON v.collection_data_id_hash = cd.collection_data_id_hash
+            LEFT JOIN (
+                SELECT collection_data_id_hash, SUM(price) AS volume, COUNT(*) AS sales_count,
+                    AVG(price) AS average_price
+                FROM nft_sales
+                WHERE transaction_timestamp > $2 - INTERVAL '1 day'
+                    AND transaction_timestamp <= $2 AND price IS NOT NULL AND NOT suspect_value
+                GROUP BY collection_data_id_hash
+            ) s ON s.collection_data_id_hash = cd.collection_data_id_hash
+            ON CONFLICT (collection_data_id_hash, snapshot_date) DO UPDATE SET
+                creator_address = excluded.creator_address,
+                collection_name = excluded.collection_name,
+                floor_price = excluded.floor_price,
+                listed_count = excluded.listed_count,
+                holder_count = excluded.holder_count,
+                supply = excluded.supply,
+                volume_24h = excluded.volume_24h,
+                sales_count_24h = excluded.sales_count_24h,
+                average_sale_price_24h = excluded.average_sale_price_24h,
+                total_volume = excluded.total_volume,
+                snapshot_at = excluded.snapshot_at,
+                is_backfilled = excluded.is_backfilled
+            WHERE collection_stats_snapshots.snapshot_at <= excluded.snapshot_at",
+        )
+        .bind::<Date, _>(as_of.date())
+        .bind::<Timestamp, _>(as_of)
+        .bind::<Numeric, _>(ValueLimits::current().max_price.clone())
+        .execute(conn)
+    }
+
+    /// Reconstructs the day's snapshots from the history tables, as of the end of the day. The
+    /// 24h columns cover the day's nft_sales, and supply the last collection_datas row before
+    /// the end of the day. Listings and holders have no history, so those columns are kept from
+    /// the day's live snapshot if there is one and left null otherwise. total_volume needs the
+    /// whole collection_volumes history, so it's only written if `volume_history_complete`.
+    pub fn backfill_day(
+        conn: &mut PgConnection,
+        date: chrono::NaiveDate,
+        volume_history_complete: bool,
+    ) -> QueryResult<usize> {
+        let day_start = date.and_hms(0, 0, 0);
+        sql_query(
+            "INSERT INTO collection_stats_snapshots (
+                collection_data_id_hash, snapshot_date, creator_address, collection_name,
+                floor_price, listed_count, holder_count, supply, volume_24h, sales_count_24h,
+                average_sale_price_24h, total_volume, snapshot_at, is_backfilled
+            )
+            SELECT cd.collection_data_id_hash, $1, cd.creator_address, cd.collection_name,
+                NULL, NULL, NULL, cd.supply, COALESCE(s.volume, 0), COALESCE(s.sales_count, 0),
+                s.average_price, CASE WHEN $3 THEN COALESCE(v.volume, 0) END,
+                $2 + INTERVAL '1 day', true
+            FROM (
+                SELECT DISTINCT ON (collection_data_id_hash) collection_data_id_hash,
+                    creator_address, collection_name, supply
+                FROM collection_datas
+                WHERE transaction_timestamp < $2 + INTERVAL '1 day'
+                ORDER BY collection_data_id_hash, transaction_version DESC
+            ) cd
+            LEFT JOIN (
+                SELECT collection_data_id_hash, SUM(volume) AS volume
+                FROM collection_volumes
+                WHERE last_transaction_timestamp < $2 + INTERVAL '1 day'
+                GROUP BY collection_data_id_hash
+            ) v ON v.collection_data_id_hash = cd.collection_data_id_hash
+            LEFT JOIN (
+                SELECT collection_data_id_hash, SUM(price) AS volume, COUNT(*) AS sales_count,
+                    AVG(price) AS average_price
+                FROM nft_sales
+                WHERE transaction_timestamp >= $2
+                    AND transaction_timestamp < $2 + INTERVAL '1 day' AND price IS NOT NULL
+                    AND NOT suspect_value
+                GROUP BY collection_data_id_hash
+            ) s ON s.collection_data_id_hash = cd.collection_data_id_hash
+            ON CONFLICT (collection_data_id_hash, snapshot_date) DO UPDATE SET
+                creator_address = excluded.creator_address,
+                collection_name = excluded.collection_name,
+                supply = excluded.supply,
+                volume_24h = excluded.volume_24h,
+                sales_count_24h = excluded.sales_count_24h,
+                average_sale_price_24h = excluded.average_sale_price_24h,
+                total_volume = COALESCE(excluded.total_volume, collection_stats_snapshots.total_volume),
+                snapshot_at = excluded.snapshot_at,
+                is_backfilled = excluded.is_backfilled",
+        )
+        .bind::<Date, _>(date)
+        .bind::<Timestamp, _>(day_start)
+        .bind::<Bool, _>(volume_history_complete)
+        .execute(conn)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        database::new_db_pool,
+        indexer::tailer::{test::wipe_database, MIGRATIONS},
+    };
+    use diesel::{ExpressionMethods, QueryDsl, SelectableHelper};
+    use diesel_migrations::MigrationHarness;
+
+    fn at(secs: i64) -> chrono::NaiveDateTime {
+        chrono::NaiveDateTime::from_timestamp(1668000000 + secs, 0)
+    }
+
+    #[test]
+    fn test_is_due() {
+        let snapshots =
+            CollectionStatsSnapshots::from_config(Some(&CollectionStatsSnapshotsConfig {
+                interval_secs: Some(60),
+            }))
+            .unwrap()
+            .unwrap();
+        assert!(snapshots.is_due(at(0)));
+        assert!(!snapshots.is_due(at(59)));
+        assert!(snapshots.is_due(at(60)));
+        // Batches catching up on an older day don't trigger one before the interval is up
+        assert!(!snapshots.is_due(at(-3600)));
+        assert!(snapshots.is_due(at(3600)));
+
+        for interval_secs in [0, 86401] {
+            assert!(
+                CollectionStatsSnapshots::from_config(Some(&CollectionStatsSnapshotsConfig {
+                    interval_secs: Some(interval_secs),
+                }))
+                .is_err()
+            );
+        }
+    }
+
+    #[test]
+    fn test_snapshot_and_backfill() {
+        if crate::should_skip_pg_tests() {
+            return;
+        }
+        let database_url = std::env::var("INDEXER_DATABASE_URL").unwrap();
+        let conn_pool = new_db_pool(&database_url).unwrap();
+        let mut conn = conn_pool.get().unwrap();
+        wipe_database(&mut conn);
+        conn.run_pending_migrations(MIGRATIONS).unwrap();
+
+        // Minted the day before, 2 sales on the day of as_of, one listed
+        sql_query(
+            "INSERT INTO collection_datas (
+                collection_data_id_hash, transaction_version, creator_address, collection_name,
+                description, metadata_uri, supply, maximum, maximum_mutable, uri_mutable,
+                description_mutable, table_handle, transaction_timestamp
+            ) VALUES ('potions', 1, '0xc4e7', 'Potions', '', '', 10, 10, false, false, false,
+                '0x7', '2022-11-08 12:00:00')",
+        )
+        .execute(&mut conn)
+        .unwrap();
+        sql_query(
+            "INSERT INTO current_collection_datas (
+                collection_data_id_hash, creator_address, collection_name, description,
+                metadata_uri, supply, maximum, maximum_mutable, uri_mutable, description_mutable,
+                last_transaction_version, table_handle, last_transaction_timestamp
+            ) VALUES ('potions', '0xc4e7', 'Potions', '', '', 10, 10, false, false, false, 1,
+                '0x7', '2022-11-08 12:00:00')",
+        )
+        .execute(&mut conn)
+        .unwrap();
+        for (version, price) in [(2, 100), (3, 300)] {
+            sql_query(format!(
+                "INSERT INTO nft_sales (
+                    transaction_version, event_account_address, event_creation_number,
+                    event_sequence_number, market_address, event_type, token_data_id_hash,
+                    property_version, collection_data_id_hash, creator_address, collection_name,
+                    name, token_amount, price, gas_unit_price, transaction_timestamp, is_primary
+                ) VALUES ({0}, '0xfa4e', 0, {0}, '0xfa4e', 'buy', 'potion', 0, 'potions',
+                    '0xc4e7', 'Potions', 'Potion', 1, {1}, 100, '2022-11-09 12:00:00', false)",
+                version, price,
+            ))
+            .execute(&mut conn)
+            .unwrap();
+            sql_query(format!(
+                "INSERT INTO collection_volumes (
+                    collection_data_id_hash, volume, last_transaction_version,
+                    last_transaction_timestamp, event_index, is_primary
+                ) VALUES ('potions', {1}, {0}, '2022-11-09 12:00:00', 0, false)",
+                version, price,
+            ))
+            .execute(&mut conn)
+            .unwrap();
+        }
+        sql_query(
+            "INSERT INTO current_collection_volumes (
+                collection_data_id_hash, volume, last_transaction_version, primary_volume,
+                secondary_volume, last_transaction_timestamp
+            ) VALUES ('potions', 400, 3, 0, 400, '2022-11-09 12:00:00')",
+        )
+        .execute(&mut conn)
+        .unwrap();
+        sql_query(
+            "INSERT INTO current_marketplace_listings (
+                token_data_id_hash, collection_data_id_hash, market_address, property_version,
+                creator_address, collection_name, name, seller, amount, price, event_type,
+                last_transaction_version, last_transaction_timestamp
+            ) VALUES ('potion', 'potions', '0xfa4e', 0, '0xc4e7', 'Potions', 'Potion', '0xb0b',
+                1, 250, 'list', 4, '2022-11-09 13:00:00')",
+        )
+        .execute(&mut conn)
+        .unwrap();
+
+        let load = |conn: &mut PgConnection| {
+            collection_stats_snapshots::table
+                .order(collection_stats_snapshots::snapshot_date)
+                .select(CollectionStatsSnapshot::as_select())
+                .load::<CollectionStatsSnapshot>(conn)
+                .unwrap()
+        };
+        let as_of = chrono::NaiveDate::from_ymd(2022, 11, 9).and_hms(18, 0, 0);
+        assert_eq!(
+            CollectionStatsSnapshots::snapshot(&mut conn, as_of).unwrap(),
+            1
+        );
+        // Idempotent, and an older snapshot of the day doesn't overwrite it
+        CollectionStatsSnapshots::snapshot(&mut conn, as_of).unwrap();
+        assert_eq!(
+            CollectionStatsSnapshots::snapshot(&mut conn, as_of - chrono::Duration::hours(1))
+                .unwrap(),
+            0
+        );
+        let snapshots = load(&mut conn);
+        assert_eq!(snapshots.len(), 1);
+        let live = &snapshots[0];
+        assert_eq!(live.floor_price, Some(BigDecimal::from(250)));
+        assert_eq!(live.listed_count, Some(1));
+        assert_eq!(live.volume_24h, BigDecimal::from(400));
+        assert_eq!(live.sales_count_24h, 2);
+        assert_eq!(live.average_sale_price_24h, Some(BigDecimal::from(200)));
+        assert_eq!(live.total_volume, Some(BigDecimal::from(400)));
+        assert!(!live.is_backfilled);
+
+        // The day before has no sales, the day of keeps the live floor price
+        for day in 8..=9 {
+            CollectionStatsSnapshots::backfill_day(
+                &mut conn,
+                chrono::NaiveDate::from_ymd(2022, 11, day),
+                true,
+            )
+            .unwrap();
+        }
+        let snapshots = load(&mut conn);
+        assert_eq!(snapshots.len(), 2);
+        assert_eq!(snapshots[0].volume_24h, BigDecimal::from(0));
+        assert_eq!(snapshots[0].total_volume, Some(BigDecimal::from(0)));
+        assert_eq!(snapshots[0].floor_price, None);
+        assert_eq!(snapshots[1].volume_24h, BigDecimal::from(400));
+        assert_eq!(snapshots[1].floor_price, Some(BigDecimal::from(250)));
+        assert!(snapshots[1].is_backfilled);
+    }
+}
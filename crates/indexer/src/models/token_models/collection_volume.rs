@@ -6,19 +6,21 @@
 #![allow(clippy::extra_unused_lifetimes)]
 #![allow(clippy::unused_unit)]
 
-use std::collections::HashMap;
+use std::collections::{BTreeMap, HashMap};
 
-use super::token_utils::{TokenDataIdType, TokenEvent};
-use crate::{
-    schema::{current_collection_volumes, collection_volumes, current_token_volumes, token_volumes},
-    util::{parse_timestamp},
+use super::{
+    collection_reports::canonicalize_coin_type,
+    event_effects::TokenEventEffects,
+    nft_sales::{NftSale, PAYLOAD_INFERRED_SOURCE},
 };
-use aptos_api_types::{Event as APIEvent, Transaction as APITransaction};
+use crate::schema::{current_collection_volumes, collection_volumes, current_token_volumes, token_volumes};
 use bigdecimal::{BigDecimal, Zero};
 use field_count::FieldCount;
 use serde::{Deserialize, Serialize};
 
-#[derive(Debug, Deserialize, FieldCount, Identifiable, Insertable, Serialize)]
+#[derive(
+    Debug, Deserialize, FieldCount, Identifiable, Insertable, Queryable, Selectable, Serialize,
+)]
 #[diesel(primary_key(
     collection_data_id_hash
 ))]
@@ -28,11 +30,25 @@ pub struct CurrentCollectionVolume {
     pub volume: BigDecimal,
     pub inserted_at: chrono::NaiveDateTime,
     pub last_transaction_version: i64,
+    pub last_transaction_timestamp: chrono::NaiveDateTime,
+    // volume split by whether the sale was primary, see NftSale::is_primary
+    pub primary_volume: BigDecimal,
+    pub secondary_volume: BigDecimal,
+    // sum of the sales' NftSale::price_usd, null while none of them had one
+    pub volume_usd: Option<BigDecimal>,
+    // same for NftSale::price_decimal
+    pub volume_decimal: Option<BigDecimal>,
+    // event index of the latest sale counted, the watermark is (last_transaction_version,
+    // last_event_index). Null on rows counted before it was tracked, or recomputed from history,
+    // which cover every event of their version
+    pub last_event_index: Option<i64>,
 }
 
 #[derive(Debug, Deserialize, FieldCount, Identifiable, Insertable, Serialize)]
 #[diesel(primary_key(
-    collection_data_id_hash
+    last_transaction_version,
+    event_index,
+    token_index
 ))]
 #[diesel(table_name = collection_volumes)]
 pub struct CollectionVolume {
@@ -40,9 +56,23 @@ pub struct CollectionVolume {
     pub volume: BigDecimal,
     pub inserted_at: chrono::NaiveDateTime,
     pub last_transaction_version: i64,
+    pub last_transaction_timestamp: chrono::NaiveDateTime,
+    // one row per sale, so current_collection_volumes can be recomputed from these
+    pub event_index: i64,
+    // position of the token within a sweep event, 0 for events that sell a single token
+    pub token_index: i64,
+    pub is_primary: bool,
+    pub volume_usd: Option<BigDecimal>,
+    pub volume_decimal: Option<BigDecimal>,
+    // the sale's marketplace and canonical coin type, for the marketplace volumes. Null for sales
+    // without an NftSale
+    pub market_address: Option<String>,
+    pub coin_type: Option<String>,
 }
 
-#[derive(Debug, Deserialize, FieldCount, Identifiable, Insertable, Serialize)]
+#[derive(
+    Debug, Deserialize, FieldCount, Identifiable, Insertable, Queryable, Selectable, Serialize,
+)]
 #[diesel(primary_key(
     token_data_id_hash
 ))]
@@ -52,11 +82,17 @@ pub struct CurrentTokenVolume {
     pub volume: BigDecimal,
     pub inserted_at: chrono::NaiveDateTime,
     pub last_transaction_version: i64,
+    pub last_transaction_timestamp: chrono::NaiveDateTime,
+    pub volume_decimal: Option<BigDecimal>,
+    // same as CurrentCollectionVolume::last_event_index
+    pub last_event_index: Option<i64>,
 }
 
 #[derive(Debug, Deserialize, FieldCount, Identifiable, Insertable, Serialize)]
 #[diesel(primary_key(
-    token_data_id_hash
+    last_transaction_version,
+    event_index,
+    token_index
 ))]
 #[diesel(table_name = token_volumes)]
 pub struct TokenVolume {
@@ -64,6 +100,10 @@ pub struct TokenVolume {
     pub volume: BigDecimal,
     pub inserted_at: chrono::NaiveDateTime,
     pub last_transaction_version: i64,
+    pub last_transaction_timestamp: chrono::NaiveDateTime,
+    pub event_index: i64,
+    pub token_index: i64,
+    pub volume_decimal: Option<BigDecimal>,
 }
 
 // #[derive(Debug, Deserialize, FieldCount, Identifiable, Insertable, Serialize)]
@@ -102,467 +142,437 @@ pub struct TokenVolume {
 //     pub last_transaction_version: i64,
 // }
 
-struct TokenActivityHelper<'a> {
-    pub token_data_id: &'a TokenDataIdType,
-    pub property_version: BigDecimal,
-    pub from_address: Option<String>,
-    pub to_address: Option<String>,
-    pub token_amount: BigDecimal,
-    pub coin_type: Option<String>,
-    pub coin_amount: Option<BigDecimal>,
-}
-
 impl CurrentCollectionVolume {
-    pub fn from_transaction(transaction: &APITransaction) -> (HashMap<String, Self>, Vec<CollectionVolume>, HashMap<String, CurrentTokenVolume>, Vec<TokenVolume>) {
-        let mut current_collection_volumes: HashMap<String, Self> = HashMap::new();
-        let mut current_token_volumes: HashMap<String, CurrentTokenVolume> = HashMap::new();
+    /// effects are the transaction's TokenEventEffects, nft_sales its sales, already classified
+    /// as primary or secondary
+    pub fn from_effects(effects: &[TokenEventEffects], nft_sales: &[NftSale]) -> (HashMap<String, Self>, Vec<CollectionVolume>, HashMap<String, CurrentTokenVolume>, Vec<TokenVolume>) {
         let mut collection_volumes = vec![];
         let mut token_volumes = vec![];
         // let mut current_daily_collection_volumes: HashMap<String, CurrentDailyCollectionVolume> = HashMap::new();
         // let mut current_weekly_collection_volumes: HashMap<String, CurrentWeeklyCollectionVolume> = HashMap::new();
         // let mut current_monthly_collection_volumes: HashMap<String, CurrentMonthlyCollectionVolume> = HashMap::new();
-        if let APITransaction::UserTransaction(user_txn) = transaction {
-            for event in &user_txn.events {
-                let txn_version = user_txn.info.version.0 as i64;
-                let event_type = event.typ.to_string();
-                match TokenEvent::from_event(event_type.as_str(), &event.data, txn_version).unwrap()
-                {
-                    Some(token_event) => {
-                        let parsed_event = Self::from_parse_event(
-                            &event_type,
-                            event,
-                            &token_event,
-                            txn_version,
-                            parse_timestamp(user_txn.timestamp.0, txn_version),
-                        );
-                        if let Some((current_collection_volume, collection_volume, current_token_volume, token_volume)) = parsed_event {
-                            current_collection_volumes.insert(
-                                current_collection_volume.collection_data_id_hash.clone(),
-                                current_collection_volume,
-                            );
-                            collection_volumes.push(
-                                collection_volume
-                            );
-                            current_token_volumes.insert(
-                                current_token_volume.token_data_id_hash.clone(),
-                                current_token_volume,
-                            );
-                            token_volumes.push(
-                                token_volume
-                            );
-                            // current_daily_collection_volumes.insert(
-                            //     current_daily_collection_volume.collection_data_id_hash.clone(),
-                            //     current_daily_collection_volume,
-                            // );
-                            // current_weekly_collection_volumes.insert(
-                            //     current_weekly_collection_volume.collection_data_id_hash.clone(),
-                            //     current_weekly_collection_volume,
-                            // );
-                            // current_monthly_collection_volumes.insert(
-                            //     current_monthly_collection_volume.collection_data_id_hash.clone(),
-                            //     current_monthly_collection_volume,
-                            // );
-                        }
-                    }
-                    None => {}
-                };
-            }
+        // Sales with a suspect price or amount are left out, see TokenEventEffects::suspect_value
+        for effects in effects.iter().filter(|effects| effects.is_countable_sale()) {
+            // Matched on the index since module events share the same guid
+            let sale = nft_sales
+                .iter()
+                .find(|sale| sale.event_index == effects.event_index && sale.token_index == effects.token_index);
+            let (_, collection_volume, _, token_volume) = Self::from_sale_effects(effects, sale);
+            collection_volumes.push(collection_volume);
+            token_volumes.push(token_volume);
+            // current_daily_collection_volumes.insert(
+            //     current_daily_collection_volume.collection_data_id_hash.clone(),
+            //     current_daily_collection_volume,
+            // );
+            // current_weekly_collection_volumes.insert(
+            //     current_weekly_collection_volume.collection_data_id_hash.clone(),
+            //     current_weekly_collection_volume,
+            // );
+            // current_monthly_collection_volumes.insert(
+            //     current_monthly_collection_volume.collection_data_id_hash.clone(),
+            //     current_monthly_collection_volume,
+            // );
         }
+        // Inferred sales have no sale event to go through from_sale_effects
+        for sale in nft_sales
+            .iter()
+            .filter(|sale| sale.source == PAYLOAD_INFERRED_SOURCE && !sale.suspect_value)
+        {
+            let (_, collection_volume, _, token_volume) = Self::from_inferred_sale(sale);
+            collection_volumes.push(collection_volume);
+            token_volumes.push(token_volume);
+        }
+        // Summed rather than keyed by the last sale, a transaction can sell several tokens of a
+        // collection, or the same token more than once
+        let current_collection_volumes = Self::from_collection_volumes(&collection_volumes)
+            .into_iter()
+            .map(|current| (current.collection_data_id_hash.clone(), current))
+            .collect();
+        let current_token_volumes = CurrentTokenVolume::from_token_volumes(&token_volumes)
+            .into_iter()
+            .map(|current| (current.token_data_id_hash.clone(), current))
+            .collect();
         (current_collection_volumes, collection_volumes, current_token_volumes, token_volumes)
     }
 
-    pub fn from_parse_event(
-        event_type: &str,
-        event: &APIEvent,
-        token_event: &TokenEvent,
-        txn_version: i64,
-        txn_timestamp: chrono::NaiveDateTime,
-    ) -> Option<(Self, CollectionVolume, CurrentTokenVolume, TokenVolume)> {
-        let event_account_address = &event.guid.account_address.to_string();
-        let event_creation_number = event.guid.creation_number.0 as i64;
-        let event_sequence_number = event.sequence_number.0 as i64;
-        let binding = TokenDataIdType {
-            creator: "".to_owned(),
-            collection: "".to_owned(),
-            name: "".to_owned(),
-        }.clone();
-        let token_data_id = match token_event {
-            TokenEvent::BlueMoveAuctionEvent(inner) => &inner.id.token_data_id,
-            TokenEvent::BlueBidEvent(inner) => &inner.id.token_data_id,
-            TokenEvent::BlueBuyEvent(inner) => &inner.id.token_data_id,
-            TokenEvent::BlueChangePriceEvent(inner) => &inner.id.token_data_id,
-            TokenEvent::BlueClaimCoinsEvent(inner) => &inner.id.token_data_id,
-            TokenEvent::BlueClaimTokenEvent(inner) => &inner.id.token_data_id,
-            TokenEvent::BlueDelistEvent(inner) => &inner.id.token_data_id,
-            TokenEvent::BlueListEvent(inner) => &inner.id.token_data_id,
-            TokenEvent::TopazBidEvent(inner) => &inner.token_id.token_data_id,
-            TokenEvent::TopazBuyEvent(inner) => &inner.token_id.token_data_id,
-            TokenEvent::TopazCancelBidEvent(inner) => &inner.token_id.token_data_id,
-            TokenEvent::TopazClaimEvent(inner) => &inner.token_id.token_data_id,
-            TokenEvent::TopazDelistEvent(inner) => &inner.token_id.token_data_id,
-            TokenEvent::TopazListEvent(inner) => &inner.token_id.token_data_id,
-            TokenEvent::TopazSellEvent(inner) => &inner.token_id.token_data_id,
-            TokenEvent::TopazSendEvent(inner) => &inner.token_id.token_data_id,
-            TokenEvent::Souffl3BuyTokenEvent(inner) => &inner.token_id.token_data_id,
-            TokenEvent::Souffl3CancelListTokenEvent(inner) => &inner.token_id.token_data_id,
-            TokenEvent::Souffl3ListTokenEvent(inner) => &inner.token_id.token_data_id,
-            TokenEvent::Souffl3TokenListEvent(inner) => &inner.token_id.token_data_id,
-            TokenEvent::Souffl3TokenSwapEvent(inner) => &inner.token_id.token_data_id,
-            _ => &binding
-        };
-        let binding = match token_event {
-            TokenEvent::TopazCancelCollectionBidEvent(inner) => 
-                TokenDataIdType {
-                    creator: inner.creator.clone(),
-                    collection: inner.collection_name.clone(),
-                    name: "COLLECTION".to_owned(),
-                }.clone(),
-            TokenEvent::TopazCollectionBidEvent(inner) => 
-                TokenDataIdType {
-                    creator: inner.creator.clone(),
-                    collection: inner.collection_name.clone(),
-                    name: "COLLECTION".to_owned(),
-                }.clone(),
-            _ => TokenDataIdType {
-                creator: "".to_owned(),
-                collection: "".to_owned(),
-                name: "COLLECTION".to_owned(),
-            }.clone()
+    fn from_inferred_sale(sale: &NftSale) -> (Self, CollectionVolume, CurrentTokenVolume, TokenVolume) {
+        let volume = sale.price.clone().unwrap_or(BigDecimal::zero());
+        let (primary_volume, secondary_volume) = if sale.is_primary {
+            (volume.clone(), BigDecimal::zero())
+        } else {
+            (BigDecimal::zero(), volume.clone())
         };
-        let token_activity_helper = match token_event {
-            TokenEvent::MintTokenEvent(inner) => TokenActivityHelper {
-                token_data_id: &inner.id,
-                property_version: BigDecimal::zero(),
-                from_address: Some(event_account_address.clone()),
-                to_address: None,
-                token_amount: inner.amount.clone(),
-                coin_type: None,
-                coin_amount: None,
-            },
-            TokenEvent::BurnTokenEvent(inner) => TokenActivityHelper {
-                token_data_id: &inner.id.token_data_id,
-                property_version: inner.id.property_version.clone(),
-                from_address: Some(event_account_address.clone()),
-                to_address: None,
-                token_amount: inner.amount.clone(),
-                coin_type: None,
-                coin_amount: None,
-            },
-            TokenEvent::MutateTokenPropertyMapEvent(inner) => TokenActivityHelper {
-                token_data_id: &inner.new_id.token_data_id,
-                property_version: inner.new_id.property_version.clone(),
-                from_address: Some(event_account_address.clone()),
-                to_address: None,
-                token_amount: BigDecimal::zero(),
-                coin_type: None,
-                coin_amount: None,
-            },
-            TokenEvent::WithdrawTokenEvent(inner) => TokenActivityHelper {
-                token_data_id: &inner.id.token_data_id,
-                property_version: inner.id.property_version.clone(),
-                from_address: Some(event_account_address.clone()),
-                to_address: None,
-                token_amount: inner.amount.clone(),
-                coin_type: None,
-                coin_amount: None,
-            },
-            TokenEvent::DepositTokenEvent(inner) => TokenActivityHelper {
-                token_data_id: &inner.id.token_data_id,
-                property_version: inner.id.property_version.clone(),
-                from_address: None,
-                to_address: Some((&event_account_address).to_string()),
-                token_amount: inner.amount.clone(),
-                coin_type: None,
-                coin_amount: None,
-            },
-            TokenEvent::OfferTokenEvent(inner) => TokenActivityHelper {
-                token_data_id: &inner.token_id.token_data_id,
-                property_version: inner.token_id.property_version.clone(),
-                from_address: Some(event_account_address.clone()),
-                to_address: Some(inner.to_address.clone()),
-                token_amount: inner.amount.clone(),
-                coin_type: None,
-                coin_amount: None,
-            },
-            TokenEvent::CancelTokenOfferEvent(inner) => TokenActivityHelper {
-                token_data_id: &inner.token_id.token_data_id,
-                property_version: inner.token_id.property_version.clone(),
-                from_address: Some(event_account_address.clone()),
-                to_address: Some(inner.to_address.clone()),
-                token_amount: inner.amount.clone(),
-                coin_type: None,
-                coin_amount: None,
+        (Self {
+                collection_data_id_hash: sale.collection_data_id_hash.clone(),
+                volume: volume.clone(),
+                inserted_at: sale.transaction_timestamp,
+                last_transaction_version: sale.transaction_version,
+                last_transaction_timestamp: sale.transaction_timestamp,
+                primary_volume,
+                secondary_volume,
+                volume_usd: sale.price_usd.clone(),
+                volume_decimal: sale.price_decimal.clone(),
+                last_event_index: Some(sale.event_index),
             },
-            TokenEvent::ClaimTokenEvent(inner) => TokenActivityHelper {
-                token_data_id: &inner.token_id.token_data_id,
-                property_version: inner.token_id.property_version.clone(),
-                from_address: Some(event_account_address.clone()),
-                to_address: Some(inner.to_address.clone()),
-                token_amount: inner.amount.clone(),
-                coin_type: None,
-                coin_amount: None,
+            CollectionVolume {
+                collection_data_id_hash: sale.collection_data_id_hash.clone(),
+                volume: volume.clone(),
+                inserted_at: sale.transaction_timestamp,
+                last_transaction_version: sale.transaction_version,
+                last_transaction_timestamp: sale.transaction_timestamp,
+                event_index: sale.event_index,
+                token_index: sale.token_index,
+                is_primary: sale.is_primary,
+                volume_usd: sale.price_usd.clone(),
+                volume_decimal: sale.price_decimal.clone(),
+                market_address: Some(sale.market_address.clone()),
+                coin_type: Some(canonicalize_coin_type(sale.coin_type.as_deref())),
             },
-            TokenEvent::BlueMoveAuctionEvent(inner) => TokenActivityHelper {
-                token_data_id: &inner.id.token_data_id,
-                property_version: inner.id.property_version.clone(),
-                from_address: Some(inner.owner_address.clone()),
-                to_address: None,
-                token_amount: BigDecimal::zero(),
-                coin_type: None,
-                coin_amount: Some(inner.min_selling_price.clone()),
+            CurrentTokenVolume {
+                token_data_id_hash: sale.token_data_id_hash.clone(),
+                volume: volume.clone(),
+                inserted_at: sale.transaction_timestamp,
+                last_transaction_version: sale.transaction_version,
+                last_transaction_timestamp: sale.transaction_timestamp,
+                volume_decimal: sale.price_decimal.clone(),
+                last_event_index: Some(sale.event_index),
             },
-            TokenEvent::BlueBidEvent(inner) => TokenActivityHelper {
-                token_data_id: &inner.id.token_data_id,
-                property_version: inner.id.property_version.clone(),
-                from_address: Some(inner.bider_address.clone()),
-                to_address: None,
-                token_amount: BigDecimal::zero(),
-                coin_type: None,
-                coin_amount: Some(inner.bid.clone()),
+            TokenVolume {
+                token_data_id_hash: sale.token_data_id_hash.clone(),
+                volume,
+                inserted_at: sale.transaction_timestamp,
+                last_transaction_version: sale.transaction_version,
+                last_transaction_timestamp: sale.transaction_timestamp,
+                event_index: sale.event_index,
+                token_index: sale.token_index,
+                volume_decimal: sale.price_decimal.clone(),
             },
-            TokenEvent::BlueBuyEvent(inner) => TokenActivityHelper {
-                token_data_id: &inner.id.token_data_id,
-                property_version: inner.id.property_version.clone(),
-                from_address: None,
-                to_address: Some(inner.buyer_address.clone()),
-                token_amount: BigDecimal::zero(),
-                coin_type: None,
-                coin_amount: None,
-            },
-            TokenEvent::BlueChangePriceEvent(inner) => TokenActivityHelper {
-                token_data_id: &inner.id.token_data_id,
-                property_version: inner.id.property_version.clone(),
-                from_address: Some(inner.seller_address.clone()),
-                to_address: None,
-                token_amount: BigDecimal::zero(),
-                coin_type: None,
-                coin_amount: Some(inner.amount.clone()),
-            },
-            TokenEvent::BlueClaimCoinsEvent(inner) => TokenActivityHelper {
-                token_data_id: &inner.id.token_data_id,
-                property_version: inner.id.property_version.clone(),
-                from_address: Some(inner.owner_token.clone()),
-                to_address: None,
-                token_amount: BigDecimal::zero(),
-                coin_type: None,
-                coin_amount: None,
-            },
-            TokenEvent::BlueClaimTokenEvent(inner) => TokenActivityHelper {
-                token_data_id: &inner.id.token_data_id,
-                property_version: inner.id.property_version.clone(),
-                from_address: None,
-                to_address: Some(inner.bider_address.clone()),
-                token_amount: BigDecimal::zero(),
-                coin_type: None,
-                coin_amount: None,
-            },
-            TokenEvent::BlueDelistEvent(inner) => TokenActivityHelper {
-                token_data_id: &inner.id.token_data_id,
-                property_version: inner.id.property_version.clone(),
-                from_address: Some(inner.seller_address.clone()),
-                to_address: None,
-                token_amount: BigDecimal::zero(),
-                coin_type: None,
-                coin_amount: None,
-            },
-            TokenEvent::BlueListEvent(inner) => TokenActivityHelper {
-                token_data_id: &inner.id.token_data_id,
-                property_version: inner.id.property_version.clone(),
-                from_address: Some(inner.seller_address.clone()),
-                to_address: None,
-                token_amount: inner.amount.clone(),
-                coin_type: None,
-                coin_amount: None,
-            },
-            TokenEvent::TopazBidEvent(inner) => TokenActivityHelper {
-                token_data_id: &inner.token_id.token_data_id,
-                property_version: inner.token_id.property_version.clone(),
-                from_address: Some(inner.buyer.clone()),
-                to_address: None,
-                token_amount: inner.amount.clone(),
-                coin_type: Some(inner.coin_type.to_string()),
-                coin_amount: Some(inner.price.clone()),
-            },
-            TokenEvent::TopazBuyEvent(inner) => TokenActivityHelper {
-                token_data_id: &inner.token_id.token_data_id,
-                property_version: inner.token_id.property_version.clone(),
-                from_address: Some(inner.seller.clone()),
-                to_address: Some(inner.buyer.clone()),
-                token_amount: inner.amount.clone(),
-                coin_type: None,
-                coin_amount: Some(inner.price.clone()),
-            },
-            TokenEvent::TopazCancelBidEvent(inner) => TokenActivityHelper {
-                token_data_id: &inner.token_id.token_data_id,
-                property_version: inner.token_id.property_version.clone(),
-                from_address: Some(inner.buyer.clone()),
-                to_address: None,
-                token_amount: inner.amount.clone(),
-                coin_type: Some(inner.coin_type.to_string()),
-                coin_amount: Some(inner.price.clone()),
-            },
-            TokenEvent::TopazCancelCollectionBidEvent(inner) => TokenActivityHelper {
-                token_data_id: &binding,
-                property_version: BigDecimal::zero(),
-                from_address: Some(inner.buyer.clone()),
-                to_address: None,
-                token_amount: inner.amount.clone(),
-                coin_type: Some(inner.coin_type.to_string()),
-                coin_amount: Some(inner.price.clone()),
-            },
-            TokenEvent::TopazClaimEvent(inner) => TokenActivityHelper {
-                token_data_id: &inner.token_id.token_data_id,
-                property_version: inner.token_id.property_version.clone(),
-                from_address: None,
-                to_address: Some(inner.receiver.clone()),
-                token_amount: BigDecimal::zero(),
-                coin_type: None,
-                coin_amount: None,
-            },
-            TokenEvent::TopazCollectionBidEvent(inner) => TokenActivityHelper {
-                token_data_id: &binding,
-                property_version: BigDecimal::zero(),
-                from_address: Some(inner.buyer.clone()),
-                to_address: None,
-                token_amount: inner.amount.clone(),
-                coin_type: Some(inner.coin_type.to_string()),
-                coin_amount: Some(inner.price.clone()),
-            },
-            TokenEvent::TopazDelistEvent(inner) => TokenActivityHelper {
-                token_data_id: &inner.token_id.token_data_id,
-                property_version: inner.token_id.property_version.clone(),
-                from_address: Some(inner.seller.clone()),
-                to_address: None,
-                token_amount: inner.amount.clone(),
-                coin_type: None,
-                coin_amount: Some(inner.price.clone()),
-            },
-            TokenEvent::TopazListEvent(inner) => TokenActivityHelper {
-                token_data_id: &inner.token_id.token_data_id,
-                property_version: inner.token_id.property_version.clone(),
-                from_address: Some(inner.seller.clone()),
-                to_address: None,
-                token_amount: inner.amount.clone(),
-                coin_type: None,
-                coin_amount: Some(inner.price.clone()),
-            },
-            TokenEvent::TopazSellEvent(inner) => TokenActivityHelper {
-                token_data_id: &inner.token_id.token_data_id,
-                property_version: inner.token_id.property_version.clone(),
-                from_address: Some(inner.seller.clone()),
-                to_address: Some(inner.buyer.clone()),
-                token_amount: inner.amount.clone(),
-                coin_type: Some(inner.coin_type.to_string()),
-                coin_amount: Some(inner.price.clone()),
-            },
-            TokenEvent::TopazSendEvent(inner) => TokenActivityHelper {
-                token_data_id: &inner.token_id.token_data_id,
-                property_version: inner.token_id.property_version.clone(),
-                from_address: Some(inner.sender.clone()),
-                to_address: Some(inner.receiver.clone()),
-                token_amount: inner.amount.clone(),
-                coin_type: None,
-                coin_amount: None,
-            },
-            TokenEvent::Souffl3BuyTokenEvent(inner) => TokenActivityHelper {
-                token_data_id: &inner.token_id.token_data_id,
-                property_version: inner.token_id.property_version.clone(),
-                from_address: Some(inner.token_owner.clone()),
-                to_address: Some(inner.buyer.clone()),
-                token_amount: inner.token_amount.clone(),
-                coin_type: None,
-                coin_amount: Some(inner.coin_per_token.clone()),
+        )
+    }
+
+    /// sale is the NftSale parsed from the event, if any, for its classification and the
+    /// converted prices
+    fn from_sale_effects(
+        effects: &TokenEventEffects,
+        sale: Option<&NftSale>,
+    ) -> (Self, CollectionVolume, CurrentTokenVolume, TokenVolume) {
+        let token_data_id = &effects.token_data_id;
+        let txn_version = effects.transaction_version;
+        let txn_timestamp = effects.transaction_timestamp;
+        let event_index = effects.event_index;
+        let collection_data_id_hash = token_data_id.get_collection_data_id_hash();
+        let volume = effects.coin_amount.clone().unwrap_or(BigDecimal::zero());
+        let is_primary = sale.map_or(false, |sale| sale.is_primary);
+        let volume_usd = sale.and_then(|sale| sale.price_usd.clone());
+        let volume_decimal = sale.and_then(|sale| sale.price_decimal.clone());
+        let market_address = sale.map(|sale| sale.market_address.clone());
+        let coin_type = sale.map(|sale| canonicalize_coin_type(sale.coin_type.as_deref()));
+        let (primary_volume, secondary_volume) = if is_primary {
+            (volume.clone(), BigDecimal::zero())
+        } else {
+            (BigDecimal::zero(), volume.clone())
+        };
+        (Self {
+                collection_data_id_hash: collection_data_id_hash.clone(),
+                volume: volume.clone(),
+                inserted_at: txn_timestamp.clone(),
+                last_transaction_version: txn_version.clone(),
+                last_transaction_timestamp: txn_timestamp,
+                primary_volume,
+                secondary_volume,
+                volume_usd: volume_usd.clone(),
+                volume_decimal: volume_decimal.clone(),
+                last_event_index: Some(event_index),
             },
-            TokenEvent::Souffl3CancelListTokenEvent(inner) => TokenActivityHelper {
-                token_data_id: &inner.token_id.token_data_id,
-                property_version: inner.token_id.property_version.clone(),
-                from_address: None,
-                to_address: None,
-                token_amount: inner.token_amount.clone(),
-                coin_type: None,
-                coin_amount: None,
+            CollectionVolume {
+                collection_data_id_hash: collection_data_id_hash.clone(),
+                volume: volume.clone(),
+                inserted_at: txn_timestamp.clone(),
+                last_transaction_version: txn_version.clone(),
+                last_transaction_timestamp: txn_timestamp,
+                event_index,
+                token_index: effects.token_index,
+                is_primary,
+                volume_usd,
+                volume_decimal: volume_decimal.clone(),
+                market_address,
+                coin_type,
             },
-            TokenEvent::Souffl3ListTokenEvent(inner) => TokenActivityHelper {
-                token_data_id: &inner.token_id.token_data_id,
-                property_version: inner.token_id.property_version.clone(),
-                from_address: Some(inner.token_owner.clone()),
-                to_address: None,
-                token_amount: inner.token_amount.clone(),
-                coin_type: None,
-                coin_amount: Some(inner.coin_per_token.clone()),
+            CurrentTokenVolume {
+                token_data_id_hash: token_data_id.to_hash().clone(),
+                volume: volume.clone(),
+                inserted_at: txn_timestamp.clone(),
+                last_transaction_version: txn_version.clone(),
+                last_transaction_timestamp: txn_timestamp,
+                volume_decimal: volume_decimal.clone(),
+                last_event_index: Some(event_index),
             },
-            TokenEvent::Souffl3TokenListEvent(inner) => TokenActivityHelper {
-                token_data_id: &inner.token_id.token_data_id,
-                property_version: inner.token_id.property_version.clone(),
-                from_address: None,
-                to_address: None,
-                token_amount: inner.amount.clone(),
-                coin_type: Some(inner.coin_type_info.to_string()),
-                coin_amount: Some(inner.min_price.clone()),
+            TokenVolume {
+                token_data_id_hash: token_data_id.to_hash().clone(),
+                volume: volume.clone(),
+                inserted_at: txn_timestamp.clone(),
+                last_transaction_version: txn_version.clone(),
+                last_transaction_timestamp: txn_timestamp,
+                event_index,
+                token_index: effects.token_index,
+                volume_decimal,
             },
-            TokenEvent::Souffl3TokenSwapEvent(inner) => TokenActivityHelper {
-                token_data_id: &inner.token_id.token_data_id,
-                property_version: inner.token_id.property_version.clone(),
-                from_address: None,
-                to_address: Some(inner.token_buyer.clone()),
-                token_amount: inner.token_amount.clone(),
-                coin_type: Some(inner.coin_type_info.to_string()),
-                coin_amount: Some(inner.coin_amount.clone()),
-            }
-        };
-        // onlyadd to volume if event contains "buy" or "sell"
-        if event_type.contains("Buy")
-            || event_type.contains("Sell")
-            || event_type.contains("Swap")
-        {
-            let collection_data_id_hash = token_data_id.get_collection_data_id_hash();
-            let volume = token_activity_helper.coin_amount.clone().unwrap_or(BigDecimal::zero());
-            Some((Self {
-                    collection_data_id_hash: collection_data_id_hash.clone(),
-                    volume: volume.clone(),
-                    inserted_at: txn_timestamp.clone(),
-                    last_transaction_version: txn_version.clone(),
-                },
-                CollectionVolume {
-                    collection_data_id_hash: collection_data_id_hash.clone(),
-                    volume: volume.clone(),
-                    inserted_at: txn_timestamp.clone(),
-                    last_transaction_version: txn_version.clone(),
-                },
-                CurrentTokenVolume {
-                    token_data_id_hash: token_data_id.to_hash().clone(),
-                    volume: volume.clone(),
-                    inserted_at: txn_timestamp.clone(),
-                    last_transaction_version: txn_version.clone(),
-                },
-                TokenVolume {
-                    token_data_id_hash: token_data_id.to_hash().clone(),
-                    volume: volume.clone(),
-                    inserted_at: txn_timestamp.clone(),
-                    last_transaction_version: txn_version.clone(),
-                },
-                // CurrentDailyCollectionVolume {
-                //     collection_data_id_hash: collection_data_id_hash.clone(),
-                //     volume: volume.clone(),
-                //     inserted_at: txn_timestamp.clone(),
-                //     last_transaction_version: txn_version.clone(),
-                // },
-                // CurrentWeeklyCollectionVolume {
-                //     collection_data_id_hash: collection_data_id_hash.clone(),
-                //     volume: volume.clone(),
-                //     inserted_at: txn_timestamp.clone(),
-                //     last_transaction_version: txn_version.clone(),
-                // },
-                // CurrentMonthlyCollectionVolume {
-                //     collection_data_id_hash: collection_data_id_hash.clone(),
-                //     volume: volume.clone(),
-                //     inserted_at: txn_timestamp.clone(),
-                //     last_transaction_version: txn_version.clone(),
-                // }
-            )
+            // CurrentDailyCollectionVolume {
+            //     collection_data_id_hash: collection_data_id_hash.clone(),
+            //     volume: volume.clone(),
+            //     inserted_at: txn_timestamp.clone(),
+            //     last_transaction_version: txn_version.clone(),
+            // },
+            // CurrentWeeklyCollectionVolume {
+            //     collection_data_id_hash: collection_data_id_hash.clone(),
+            //     volume: volume.clone(),
+            //     inserted_at: txn_timestamp.clone(),
+            //     last_transaction_version: txn_version.clone(),
+            // },
+            // CurrentMonthlyCollectionVolume {
+            //     collection_data_id_hash: collection_data_id_hash.clone(),
+            //     volume: volume.clone(),
+            //     inserted_at: txn_timestamp.clone(),
+            //     last_transaction_version: txn_version.clone(),
+            // }
         )
-        } else {
-            None
+    }
+}
+
+impl CurrentCollectionVolume {
+    /// Totals sales per collection into the amounts to add to the current volumes, stamped with
+    /// the latest sale's version and event index. Sorted by collection, like the rest of the
+    /// current rows.
+    pub fn from_collection_volumes<'a>(collection_volumes: impl IntoIterator<Item = &'a CollectionVolume>) -> Vec<Self> {
+        let mut current_collection_volumes: BTreeMap<String, Self> = BTreeMap::new();
+        for collection_volume in collection_volumes {
+            let (primary_volume, secondary_volume) = if collection_volume.is_primary {
+                (collection_volume.volume.clone(), BigDecimal::zero())
+            } else {
+                (BigDecimal::zero(), collection_volume.volume.clone())
+            };
+            match current_collection_volumes.get_mut(&collection_volume.collection_data_id_hash) {
+                Some(current) => {
+                    current.volume += &collection_volume.volume;
+                    current.primary_volume += primary_volume;
+                    current.secondary_volume += secondary_volume;
+                    current.volume_usd = add_known(current.volume_usd.take(), collection_volume.volume_usd.as_ref());
+                    current.volume_decimal = add_known(current.volume_decimal.take(), collection_volume.volume_decimal.as_ref());
+                    if (collection_volume.last_transaction_version, Some(collection_volume.event_index))
+                        > (current.last_transaction_version, current.last_event_index)
+                    {
+                        current.last_event_index = Some(collection_volume.event_index);
+                    }
+                    if collection_volume.last_transaction_version > current.last_transaction_version {
+                        current.inserted_at = collection_volume.inserted_at;
+                        current.last_transaction_version = collection_volume.last_transaction_version;
+                        current.last_transaction_timestamp = collection_volume.last_transaction_timestamp;
+                    }
+                }
+                None => {
+                    current_collection_volumes.insert(
+                        collection_volume.collection_data_id_hash.clone(),
+                        Self {
+                            collection_data_id_hash: collection_volume.collection_data_id_hash.clone(),
+                            volume: collection_volume.volume.clone(),
+                            inserted_at: collection_volume.inserted_at,
+                            last_transaction_version: collection_volume.last_transaction_version,
+                            last_transaction_timestamp: collection_volume.last_transaction_timestamp,
+                            primary_volume,
+                            secondary_volume,
+                            volume_usd: collection_volume.volume_usd.clone(),
+                            volume_decimal: collection_volume.volume_decimal.clone(),
+                            last_event_index: Some(collection_volume.event_index),
+                        },
+                    );
+                }
+            }
+        }
+        current_collection_volumes.into_values().collect()
+    }
+}
+
+/// Converted volumes only add up the sales that could be converted, and stay null if none could
+pub fn add_known(total: Option<BigDecimal>, amount: Option<&BigDecimal>) -> Option<BigDecimal> {
+    match (total, amount) {
+        (Some(total), Some(amount)) => Some(total + amount),
+        (total, amount) => total.or_else(|| amount.cloned()),
+    }
+}
+
+impl CurrentTokenVolume {
+    /// Same as CurrentCollectionVolume::from_collection_volumes, per token
+    pub fn from_token_volumes<'a>(token_volumes: impl IntoIterator<Item = &'a TokenVolume>) -> Vec<Self> {
+        let mut current_token_volumes: BTreeMap<String, Self> = BTreeMap::new();
+        for token_volume in token_volumes {
+            match current_token_volumes.get_mut(&token_volume.token_data_id_hash) {
+                Some(current) => {
+                    current.volume += &token_volume.volume;
+                    current.volume_decimal = add_known(current.volume_decimal.take(), token_volume.volume_decimal.as_ref());
+                    if (token_volume.last_transaction_version, Some(token_volume.event_index))
+                        > (current.last_transaction_version, current.last_event_index)
+                    {
+                        current.last_event_index = Some(token_volume.event_index);
+                    }
+                    if token_volume.last_transaction_version > current.last_transaction_version {
+                        current.inserted_at = token_volume.inserted_at;
+                        current.last_transaction_version = token_volume.last_transaction_version;
+                        current.last_transaction_timestamp = token_volume.last_transaction_timestamp;
+                    }
+                }
+                None => {
+                    current_token_volumes.insert(
+                        token_volume.token_data_id_hash.clone(),
+                        Self {
+                            token_data_id_hash: token_volume.token_data_id_hash.clone(),
+                            volume: token_volume.volume.clone(),
+                            inserted_at: token_volume.inserted_at,
+                            last_transaction_version: token_volume.last_transaction_version,
+                            last_transaction_timestamp: token_volume.last_transaction_timestamp,
+                            volume_decimal: token_volume.volume_decimal.clone(),
+                            last_event_index: Some(token_volume.event_index),
+                        },
+                    );
+                }
+            }
+        }
+        current_token_volumes.into_values().collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::models::token_models::{
+        marketplace_event_mappings::MarketplaceEventMappings, token_activities::TokenActivity,
+        token_utils::TokenEvents,
+    };
+    use aptos_api_types::Transaction as APITransaction;
+    use serde_json::json;
+
+    fn sale(collection_data_id_hash: &str, version: i64, volume: i64, is_primary: bool) -> CollectionVolume {
+        let timestamp = chrono::NaiveDateTime::from_timestamp(version, 0);
+        CollectionVolume {
+            collection_data_id_hash: collection_data_id_hash.to_string(),
+            volume: BigDecimal::from(volume),
+            inserted_at: timestamp,
+            last_transaction_version: version,
+            last_transaction_timestamp: timestamp,
+            event_index: 0,
+            token_index: 0,
+            is_primary,
+            volume_usd: None,
+            volume_decimal: None,
+            market_address: None,
+            coin_type: None,
         }
     }
+
+    #[test]
+    fn test_from_collection_volumes() {
+        let sales = vec![sale("b", 3, 10, false), sale("a", 5, 20, true), sale("a", 4, 30, false)];
+        let current = CurrentCollectionVolume::from_collection_volumes(&sales);
+        let totals = current
+            .iter()
+            .map(|row| {
+                (
+                    row.collection_data_id_hash.as_str(),
+                    row.volume.clone(),
+                    row.primary_volume.clone(),
+                    row.secondary_volume.clone(),
+                    row.last_transaction_version,
+                )
+            })
+            .collect::<Vec<_>>();
+        assert_eq!(totals, vec![
+            ("a", BigDecimal::from(50), BigDecimal::from(20), BigDecimal::from(30), 5),
+            ("b", BigDecimal::from(10), BigDecimal::from(0), BigDecimal::from(10), 3),
+        ]);
+        assert_eq!(current[0].last_transaction_timestamp, sales[1].last_transaction_timestamp);
+    }
+
+    #[test]
+    fn test_usd_volume_skips_unpriced_sales() {
+        let priced = |volume_usd: Option<i64>| CollectionVolume {
+            volume_usd: volume_usd.map(BigDecimal::from),
+            ..sale("a", 1, 10, false)
+        };
+        let current = CurrentCollectionVolume::from_collection_volumes(&[priced(None), priced(Some(4)), priced(Some(6))]);
+        assert_eq!(current[0].volume_usd, Some(BigDecimal::from(10)));
+        // Unpriced sales are unknown rather than worth nothing
+        let current = CurrentCollectionVolume::from_collection_volumes(&[priced(None), priced(None)]);
+        assert_eq!(current[0].volume_usd, None);
+    }
+
+    #[test]
+    fn test_topaz_buy_all_sums_every_token() {
+        let token_id = |name: &str| {
+            json!({
+                "token_data_id": {"creator": "0xc4e7", "collection": "Potions", "name": name},
+                "property_version": "0"
+            })
+        };
+        let hash = "0x6a527d06063dfd42c6b3a862574d5f3ec1660afb8058135edda5072712bfdb51";
+        let transaction: APITransaction = serde_json::from_value(json!({
+            "type": "user_transaction",
+            "version": "10",
+            "hash": hash,
+            "state_change_hash": hash,
+            "event_root_hash": hash,
+            "state_checkpoint_hash": null,
+            "gas_used": "100",
+            "success": true,
+            "vm_status": "Executed successfully",
+            "accumulator_root_hash": hash,
+            "changes": [],
+            "sender": "0xb0b",
+            "sequence_number": "0",
+            "max_gas_amount": "2000",
+            "gas_unit_price": "100",
+            "expiration_timestamp_secs": "1668000600",
+            "payload": {
+                "type": "entry_function_payload",
+                "function": "0x2c7bccf7b31baf770fdbcc768d9e9cb3d87805e255355df5db32ac9a669010a2::marketplace::buy_all",
+                "type_arguments": [],
+                "arguments": []
+            },
+            "events": [{
+                "guid": {"creation_number": "4", "account_address": "0xb0b"},
+                "sequence_number": "0",
+                "type": "0x2c7bccf7b31baf770fdbcc768d9e9cb3d87805e255355df5db32ac9a669010a2::events::BuyAllEvent",
+                "data": {
+                    "timestamp": "1668000000",
+                    "listing_ids": ["1", "2", "3"],
+                    "token_ids": [token_id("Potion #1"), token_id("Potion #2"), token_id("Potion #1")],
+                    "prices": ["100", "250", "40"],
+                    "amounts": ["1", "1", "1"],
+                    "sellers": ["0xa11ce", "0xca201", "0xa11ce"],
+                    "buyer": "0xb0b"
+                }
+            }],
+            "timestamp": "1668000000000000"
+        }))
+        .unwrap();
+
+        let token_events = TokenEvents::from_transaction(&transaction).unwrap();
+        let effects = TokenEventEffects::from_transaction(&transaction, &token_events, &MarketplaceEventMappings::default());
+        let activities = TokenActivity::from_effects(&effects);
+        // Every token of the event gets its own row
+        assert_eq!(
+            activities.iter().map(|activity| (activity.event_index, activity.token_index)).collect::<Vec<_>>(),
+            vec![(0, 0), (0, 1), (0, 2)]
+        );
+        let nft_sales = NftSale::from_token_activities(&transaction, &activities, None);
+        assert_eq!(nft_sales.len(), 3);
+        let (current_collection_volumes, collection_volumes, current_token_volumes, token_volumes) =
+            CurrentCollectionVolume::from_effects(&effects, &nft_sales);
+        assert_eq!(collection_volumes.len(), 3);
+        assert_eq!(token_volumes.len(), 3);
+        let current_collection_volume = current_collection_volumes.values().next().unwrap();
+        assert_eq!(current_collection_volumes.len(), 1);
+        assert_eq!(current_collection_volume.volume, BigDecimal::from(390));
+        let mut token_totals = current_token_volumes.values().map(|current| current.volume.clone()).collect::<Vec<_>>();
+        token_totals.sort();
+        assert_eq!(token_totals, vec![BigDecimal::from(140), BigDecimal::from(250)]);
+    }
 }
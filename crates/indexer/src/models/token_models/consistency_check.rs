@@ -0,0 +1,498 @@
+// Copyright (c) Aptos
+// SPDX-License-Identifier: Apache-2.0
+
+//! Replays the history of a random sample of tokens and compares the result against the current
+//! tables, which are only ever upserted and so silently keep a stale row if a version guard or a
+//! conflict target is wrong:
+//! * current_token_ownerships: token store deposits minus withdrawals in token_activities
+//! * current_token_pending_claims: offers in token_activities since the last claim or cancel
+//! * current_token_datas.supply: mints minus burns in token_activities
+//! * current_collection_volumes and current_token_volumes: collection_volumes and token_volumes
+//!
+//! Only tokens minted after token_activities became complete are sampled. Replay isn't exact
+//! everywhere, so a few rows are skipped rather than reported: ownerships held in other tables than
+//! the token store (ex: escrows), offers with activity from before pending claims were indexed and
+//! collections with volume history from before volumes were kept per sale. Volumes aren't checked
+//! at all once their history has been pruned.
+
+use super::{pruning::is_volume_history_pruned, volume_recompute::find_volume_drift};
+use crate::{models::data_integrity_findings::DataIntegrityFinding, schema::collection_volumes};
+use anyhow::ensure;
+use aptos_config::config::ConsistencyCheckConfig;
+use bigdecimal::BigDecimal;
+use diesel::{
+    sql_query,
+    sql_types::{Array, BigInt, Numeric, Text},
+    ExpressionMethods, PgConnection, QueryDsl, QueryResult, RunQueryDsl,
+};
+use std::sync::atomic::{AtomicU64, Ordering};
+
+pub const OWNERSHIPS_CHECK: &str = "current_token_ownerships_vs_token_activities";
+pub const PENDING_CLAIMS_CHECK: &str = "current_token_pending_claims_vs_token_activities";
+pub const SUPPLY_CHECK: &str = "current_token_datas_supply_vs_token_activities";
+pub const VOLUMES_CHECK: &str = "current_volumes_vs_volume_history";
+pub const DEFAULT_SAMPLE_SIZE: u64 = 20;
+
+const TOKEN_STORE_TYPE: &str = "0x3::token::TokenStore";
+
+#[derive(Debug, QueryableByName)]
+struct SampledToken {
+    #[diesel(sql_type = Text)]
+    token_data_id_hash: String,
+    #[diesel(sql_type = Text)]
+    collection_data_id_hash: String,
+}
+
+/// A current row that differs from its replayed history
+#[derive(Debug, QueryableByName)]
+struct Mismatch {
+    #[diesel(sql_type = Text)]
+    subject: String,
+    #[diesel(sql_type = Numeric)]
+    expected: BigDecimal,
+    #[diesel(sql_type = Numeric)]
+    actual: BigDecimal,
+    #[diesel(sql_type = BigInt)]
+    offending_version: i64,
+}
+
+impl Mismatch {
+    fn into_finding(self, check_name: &str, txn_version: i64) -> DataIntegrityFinding {
+        DataIntegrityFinding {
+            check_name: check_name.to_string(),
+            subject: self.subject,
+            transaction_version: txn_version,
+            delta: &self.actual - &self.expected,
+            expected: self.expected,
+            actual: self.actual,
+            offending_version: Some(self.offending_version),
+        }
+    }
+}
+
+/// Tokens with a mint since `$2` and no activity before it, i.e. with their whole history indexed
+const SAMPLE_TOKENS: &str = "
+    SELECT token_data_id_hash, collection_data_id_hash
+    FROM current_token_datas ctd
+    WHERE EXISTS (
+        SELECT 1 FROM token_activities ta
+        WHERE ta.token_data_id_hash = ctd.token_data_id_hash
+            AND ta.transfer_type = '0x3::token::MintTokenEvent'
+            AND ta.transaction_version >= $2
+    ) AND NOT EXISTS (
+        SELECT 1 FROM token_activities ta
+        WHERE ta.token_data_id_hash = ctd.token_data_id_hash
+            AND ta.transaction_version < $2
+    )
+    ORDER BY RANDOM()
+    LIMIT $1";
+
+/// Every deposit and withdrawal goes through the owner's token store. Rows currently in another
+/// table are skipped, they share the key of the token store row they replaced.
+const REPLAY_OWNERSHIPS: &str = "
+    WITH replayed AS (
+        SELECT
+            token_data_id_hash,
+            property_version,
+            owner_address,
+            SUM(amount) AS amount,
+            MAX(transaction_version) AS last_transaction_version
+        FROM (
+            SELECT
+                token_data_id_hash, property_version, to_address AS owner_address,
+                token_amount AS amount, transaction_version
+            FROM token_activities
+            WHERE token_data_id_hash = ANY($1) AND transfer_type = '0x3::token::DepositEvent'
+            UNION ALL
+            SELECT
+                token_data_id_hash, property_version, from_address AS owner_address,
+                -token_amount AS amount, transaction_version
+            FROM token_activities
+            WHERE token_data_id_hash = ANY($1) AND transfer_type = '0x3::token::WithdrawEvent'
+        ) transfers
+        GROUP BY token_data_id_hash, property_version, owner_address
+    )
+    SELECT
+        CONCAT_WS('::', token_data_id_hash, property_version, owner_address) AS subject,
+        COALESCE(r.amount, 0) AS expected,
+        COALESCE(c.amount, 0) AS actual,
+        GREATEST(c.last_transaction_version, r.last_transaction_version) AS offending_version
+    FROM (
+        SELECT * FROM current_token_ownerships WHERE token_data_id_hash = ANY($1)
+    ) c
+    FULL OUTER JOIN replayed r USING (token_data_id_hash, property_version, owner_address)
+    WHERE COALESCE(c.table_type, $2) = $2 AND COALESCE(c.amount, 0) <> COALESCE(r.amount, 0)
+    ORDER BY subject";
+
+/// Offers to the same receiver add up, and a claim or cancel takes all of them. Offers with
+/// activity before `$2` are skipped.
+const REPLAY_PENDING_CLAIMS: &str = "
+    WITH offer_events AS (
+        SELECT
+            token_data_id_hash, property_version, from_address, to_address, transfer_type,
+            token_amount, transaction_version,
+            COUNT(*) FILTER (
+                WHERE transfer_type <> '0x3::token_transfers::TokenOfferEvent'
+            ) OVER (
+                PARTITION BY token_data_id_hash, property_version, from_address, to_address
+                ORDER BY transaction_version DESC, event_index DESC
+            ) AS num_closed_after
+        FROM token_activities
+        WHERE token_data_id_hash = ANY($1) AND transfer_type IN (
+            '0x3::token_transfers::TokenOfferEvent',
+            '0x3::token_transfers::TokenCancelOfferEvent',
+            '0x3::token_transfers::TokenClaimEvent'
+        )
+    ), replayed AS (
+        SELECT
+            token_data_id_hash,
+            property_version,
+            from_address,
+            to_address,
+            COALESCE(SUM(token_amount) FILTER (
+                WHERE transfer_type = '0x3::token_transfers::TokenOfferEvent'
+                    AND num_closed_after = 0
+            ), 0) AS amount,
+            MIN(transaction_version) AS first_transaction_version,
+            MAX(transaction_version) AS last_transaction_version
+        FROM offer_events
+        GROUP BY token_data_id_hash, property_version, from_address, to_address
+    )
+    SELECT
+        CONCAT_WS('::', token_data_id_hash, property_version, from_address, to_address) AS subject,
+        COALESCE(r.amount, 0) AS expected,
+        COALESCE(c.amount, 0) AS actual,
+        GREATEST(c.last_transaction_version, r.last_transaction_version) AS offending_version
+    FROM (
+        SELECT * FROM current_token_pending_claims WHERE token_data_id_hash = ANY($1)
+    ) c
+    FULL OUTER JOIN replayed r
+        USING (token_data_id_hash, property_version, from_address, to_address)
+    WHERE COALESCE(r.first_transaction_version, $2) >= $2
+        AND COALESCE(c.amount, 0) <> COALESCE(r.amount, 0)
+    ORDER BY subject";
+
+/// Supply is only tracked for tokens with a maximum
+const REPLAY_SUPPLY: &str = "
+    WITH replayed AS (
+        SELECT
+            token_data_id_hash,
+            COALESCE(SUM(token_amount) FILTER (
+                WHERE transfer_type = '0x3::token::MintTokenEvent'
+            ), 0) - COALESCE(SUM(token_amount) FILTER (
+                WHERE transfer_type = '0x3::token::BurnTokenEvent'
+            ), 0) AS supply,
+            MAX(transaction_version) AS last_transaction_version
+        FROM token_activities
+        WHERE token_data_id_hash = ANY($1) AND transfer_type IN (
+            '0x3::token::MintTokenEvent', '0x3::token::BurnTokenEvent'
+        )
+        GROUP BY token_data_id_hash
+    )
+    SELECT
+        token_data_id_hash AS subject,
+        COALESCE(r.supply, 0) AS expected,
+        c.supply AS actual,
+        GREATEST(c.last_transaction_version, r.last_transaction_version) AS offending_version
+    FROM current_token_datas c
+    LEFT JOIN replayed r USING (token_data_id_hash)
+    WHERE c.token_data_id_hash = ANY($1)
+        AND c.maximum > 0
+        AND c.supply <> COALESCE(r.supply, 0)
+    ORDER BY subject";
+
+#[derive(Debug)]
+pub struct ConsistencyCheck {
+    every_n_batches: u64,
+    sample_size: i64,
+    complete_from_version: i64,
+    claims_complete_from_version: i64,
+    batches_seen: AtomicU64,
+}
+
+impl ConsistencyCheck {
+    pub fn from_config(config: Option<&ConsistencyCheckConfig>) -> anyhow::Result<Option<Self>> {
+        let config = match config {
+            Some(config) => config,
+            None => return Ok(None),
+        };
+        ensure!(
+            config.every_n_batches > 0,
+            "every_n_batches must be greater than 0"
+        );
+        let sample_size = config.sample_size.unwrap_or(DEFAULT_SAMPLE_SIZE);
+        ensure!(sample_size > 0, "sample_size must be greater than 0");
+        let complete_from_version = config.complete_from_version.unwrap_or_default();
+        let claims_complete_from_version = config
+            .claims_complete_from_version
+            .unwrap_or(complete_from_version);
+        ensure!(
+            claims_complete_from_version >= complete_from_version,
+            "claims_complete_from_version can't be before complete_from_version"
+        );
+        Ok(Some(Self {
+            every_n_batches: config.every_n_batches,
+            sample_size: sample_size as i64,
+            complete_from_version: complete_from_version as i64,
+            claims_complete_from_version: claims_complete_from_version as i64,
+            batches_seen: AtomicU64::new(0),
+        }))
+    }
+
+    /// Counts a committed batch, returning true if the check should run after it
+    pub fn is_due(&self) -> bool {
+        let batches_seen = self.batches_seen.fetch_add(1, Ordering::Relaxed) + 1;
+        batches_seen % self.every_n_batches == 0
+    }
+
+    /// Samples tokens and returns a finding for every current row of theirs, or of their
+    /// collections' volumes, that doesn't match the replayed history
+    pub fn run(
+        &self,
+        conn: &mut PgConnection,
+        txn_version: i64,
+    ) -> QueryResult<Vec<DataIntegrityFinding>> {
+        let sample = sql_query(SAMPLE_TOKENS)
+            .bind::<BigInt, _>(self.sample_size)
+            .bind::<BigInt, _>(self.complete_from_version)
+            .load::<SampledToken>(conn)?;
+        let token_data_id_hashes = sample
+            .iter()
+            .map(|token| token.token_data_id_hash.clone())
+            .collect::<Vec<_>>();
+        let mut collection_data_id_hashes = sample
+            .into_iter()
+            .map(|token| token.collection_data_id_hash)
+            .collect::<Vec<_>>();
+        collection_data_id_hashes.sort();
+        collection_data_id_hashes.dedup();
+
+        let mut findings = vec![];
+        let ownerships = sql_query(REPLAY_OWNERSHIPS)
+            .bind::<Array<Text>, _>(&token_data_id_hashes)
+            .bind::<Text, _>(TOKEN_STORE_TYPE)
+            .load::<Mismatch>(conn)?;
+        findings.extend(
+            ownerships
+                .into_iter()
+                .map(|row| row.into_finding(OWNERSHIPS_CHECK, txn_version)),
+        );
+        let pending_claims = sql_query(REPLAY_PENDING_CLAIMS)
+            .bind::<Array<Text>, _>(&token_data_id_hashes)
+            .bind::<BigInt, _>(self.claims_complete_from_version)
+            .load::<Mismatch>(conn)?;
+        findings.extend(
+            pending_claims
+                .into_iter()
+                .map(|row| row.into_finding(PENDING_CLAIMS_CHECK, txn_version)),
+        );
+        let supply = sql_query(REPLAY_SUPPLY)
+            .bind::<Array<Text>, _>(&token_data_id_hashes)
+            .load::<Mismatch>(conn)?;
+        findings.extend(
+            supply
+                .into_iter()
+                .map(|row| row.into_finding(SUPPLY_CHECK, txn_version)),
+        );
+
+        let legacy_collections = collection_volumes::table
+            .filter(collection_volumes::collection_data_id_hash.eq_any(&collection_data_id_hashes))
+            .filter(collection_volumes::event_index.eq(-1))
+            .select(collection_volumes::collection_data_id_hash)
+            .distinct()
+            .load::<String>(conn)?;
+        collection_data_id_hashes.retain(|hash| !legacy_collections.contains(hash));
+        if !collection_data_id_hashes.is_empty() && !is_volume_history_pruned(conn)? {
+            let drift = find_volume_drift(conn, Some(collection_data_id_hashes.as_slice()))?;
+            findings.extend(drift.into_iter().map(|drift| DataIntegrityFinding {
+                check_name: VOLUMES_CHECK.to_string(),
+                subject: format!("{}::{}::{}", drift.table, drift.subject, drift.column),
+                transaction_version: txn_version,
+                delta: &drift.recorded - &drift.recomputed,
+                expected: drift.recomputed,
+                actual: drift.recorded,
+                offending_version: Some(drift.last_transaction_version),
+            }));
+        }
+        Ok(findings)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_utils::timestamp;
+    use crate::{
+        database::{new_db_pool, PgPoolConnection},
+        indexer::tailer::{test::wipe_database, MIGRATIONS},
+        models::token_models::{
+            token_activities::TokenActivity, token_datas::CurrentTokenData,
+            token_ownerships::CurrentTokenOwnership,
+        },
+        schema::{current_token_datas, current_token_ownerships, token_activities},
+    };
+    use diesel_migrations::MigrationHarness;
+
+    const OWNER: &str = "0x00000000000000000000000000000000000000000000000000000000000000aa";
+    const BUYER: &str = "0x00000000000000000000000000000000000000000000000000000000000000bb";
+
+    fn check(complete_from_version: u64) -> ConsistencyCheck {
+        ConsistencyCheck::from_config(Some(&ConsistencyCheckConfig {
+            every_n_batches: 2,
+            sample_size: None,
+            complete_from_version: Some(complete_from_version),
+            claims_complete_from_version: None,
+        }))
+        .unwrap()
+        .unwrap()
+    }
+
+    fn setup() -> PgPoolConnection {
+        let database_url = std::env::var("INDEXER_DATABASE_URL")
+            .expect("must set 'INDEXER_DATABASE_URL' to run tests!");
+        let conn_pool = new_db_pool(database_url.as_str()).unwrap();
+        let mut conn = conn_pool.get().unwrap();
+        wipe_database(&mut conn);
+        conn.run_pending_migrations(MIGRATIONS).unwrap();
+        conn
+    }
+
+    fn activity(
+        version: i64,
+        event_index: i64,
+        transfer_type: &str,
+        from_address: Option<&str>,
+        to_address: Option<&str>,
+    ) -> TokenActivity {
+        TokenActivity {
+            transaction_version: version,
+            event_account_address: OWNER.to_string(),
+            event_creation_number: 0,
+            event_sequence_number: version + event_index,
+            collection_data_id_hash: "potions".to_string(),
+            token_data_id_hash: "potion".to_string(),
+            property_version: BigDecimal::from(0),
+            creator_address: OWNER.to_string(),
+            collection_name: "Potions".to_string(),
+            name: "Potion".to_string(),
+            transfer_type: transfer_type.to_string(),
+            transfer_kind: None,
+            domain: None,
+            from_address: from_address.map(str::to_string),
+            to_address: to_address.map(str::to_string),
+            token_amount: BigDecimal::from(1),
+            coin_type: None,
+            coin_amount: None,
+            suspect_value: false,
+            transaction_timestamp: timestamp(),
+            event_index,
+            token_index: 0,
+        }
+    }
+
+    fn ownership(owner_address: &str, amount: i64, version: i64) -> CurrentTokenOwnership {
+        CurrentTokenOwnership {
+            token_data_id_hash: "potion".to_string(),
+            property_version: BigDecimal::from(0),
+            owner_address: owner_address.to_string(),
+            creator_address: OWNER.to_string(),
+            collection_name: "Potions".to_string(),
+            name: "Potion".to_string(),
+            amount: BigDecimal::from(amount),
+            token_properties: serde_json::json!({}),
+            last_transaction_version: version,
+            collection_data_id_hash: "potions".to_string(),
+            table_type: TOKEN_STORE_TYPE.to_string(),
+            last_transaction_timestamp: timestamp(),
+            owner_type: "user".to_string(),
+            beneficial_owner: None,
+            is_deleted: amount == 0,
+        }
+    }
+
+    #[test]
+    fn test_invalid_config() {
+        assert!(ConsistencyCheck::from_config(None).unwrap().is_none());
+        assert!(ConsistencyCheck::from_config(Some(&ConsistencyCheckConfig {
+            every_n_batches: 0,
+            sample_size: None,
+            complete_from_version: None,
+            claims_complete_from_version: None,
+        }))
+        .is_err());
+        assert!(ConsistencyCheck::from_config(Some(&ConsistencyCheckConfig {
+            every_n_batches: 1,
+            sample_size: None,
+            complete_from_version: Some(10),
+            claims_complete_from_version: Some(5),
+        }))
+        .is_err());
+
+        let check = check(0);
+        let due = (0..4).map(|_| check.is_due()).collect::<Vec<_>>();
+        assert_eq!(due, vec![false, true, false, true]);
+    }
+
+    #[test]
+    fn test_stale_ownership_is_reported() {
+        if crate::should_skip_pg_tests() {
+            return;
+        }
+        let mut conn = setup();
+        diesel::insert_into(token_activities::table)
+            .values(&vec![
+                activity(10, 0, "0x3::token::MintTokenEvent", Some(OWNER), None),
+                activity(10, 1, "0x3::token::DepositEvent", None, Some(OWNER)),
+                activity(20, 0, "0x3::token::WithdrawEvent", Some(OWNER), None),
+                activity(20, 1, "0x3::token::DepositEvent", None, Some(BUYER)),
+            ])
+            .execute(&mut conn)
+            .unwrap();
+        let token_data = CurrentTokenData {
+            token_data_id_hash: "potion".to_string(),
+            creator_address: OWNER.to_string(),
+            collection_name: "Potions".to_string(),
+            name: "Potion".to_string(),
+            maximum: BigDecimal::from(1),
+            supply: BigDecimal::from(1),
+            largest_property_version: BigDecimal::from(0),
+            metadata_uri: "".to_string(),
+            payee_address: OWNER.to_string(),
+            royalty_points_numerator: BigDecimal::from(0),
+            royalty_points_denominator: BigDecimal::from(0),
+            maximum_mutable: false,
+            uri_mutable: false,
+            description_mutable: false,
+            properties_mutable: false,
+            royalty_mutable: false,
+            default_properties: serde_json::json!({}),
+            last_transaction_version: 10,
+            collection_data_id_hash: "potions".to_string(),
+            last_transaction_timestamp: timestamp(),
+            description: "".to_string(),
+            metadata_uri_canonical: None,
+            uri_scheme: Some("empty".to_string()),
+        };
+        diesel::insert_into(current_token_datas::table)
+            .values(&token_data)
+            .execute(&mut conn)
+            .unwrap();
+        // The transfer at version 20 only reached the buyer's row
+        diesel::insert_into(current_token_ownerships::table)
+            .values(&vec![ownership(OWNER, 1, 10), ownership(BUYER, 1, 20)])
+            .execute(&mut conn)
+            .unwrap();
+
+        let findings = check(0).run(&mut conn, 30).unwrap();
+        assert_eq!(findings.len(), 1);
+        assert_eq!(findings[0].check_name, OWNERSHIPS_CHECK);
+        assert_eq!(findings[0].subject, format!("potion::0::{}", OWNER));
+        assert_eq!(findings[0].expected, BigDecimal::from(0));
+        assert_eq!(findings[0].delta, BigDecimal::from(1));
+        assert_eq!(findings[0].offending_version, Some(20));
+        assert_eq!(findings[0].transaction_version, 30);
+
+        // Not sampled, the mint is from before token_activities is complete
+        assert!(check(15).run(&mut conn, 30).unwrap().is_empty());
+    }
+}
@@ -0,0 +1,299 @@
+// Copyright (c) Aptos
+// SPDX-License-Identifier: Apache-2.0
+
+//! TokenStore and PendingClaims resources deleted from an account. Their tables go with them
+//! without a DeleteTableItem per token, so all that says the account's tokens and offers are gone
+//! is the DeleteResource. The current rows of the account are zeroed: the ones written earlier in
+//! the batch as the deletion is seen, and the stored ones once the batch is parsed.
+
+use super::{
+    token_claims::{CurrentTokenPendingClaim, PENDING_CLAIMS_TYPE},
+    token_ownerships::{CurrentTokenOwnership, TOKEN_STORE_TYPE},
+    tokens::{CurrentTokenOwnershipPK, CurrentTokenPendingClaimPK},
+};
+use crate::{
+    database::PgPoolConnection,
+    schema::{current_token_ownerships, current_token_pending_claims},
+    util::{parse_timestamp, standardize_address},
+};
+use aptos_api_types::{Transaction as APITransaction, WriteSetChange as APIWriteSetChange};
+use bigdecimal::{BigDecimal, Zero};
+use diesel::{ExpressionMethods, QueryDsl, QueryResult, RunQueryDsl, SelectableHelper};
+use std::collections::{HashMap, HashSet};
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct DeletedTokenResource {
+    pub address: String,
+    /// TOKEN_STORE_TYPE or PENDING_CLAIMS_TYPE
+    pub resource_type: String,
+    pub transaction_version: i64,
+    pub transaction_timestamp: chrono::NaiveDateTime,
+}
+
+impl DeletedTokenResource {
+    pub fn from_transaction(transaction: &APITransaction) -> Vec<Self> {
+        let user_txn = match transaction {
+            APITransaction::UserTransaction(user_txn) => user_txn,
+            _ => return vec![],
+        };
+        let txn_version = user_txn.info.version.0 as i64;
+        let txn_timestamp = parse_timestamp(user_txn.timestamp.0, txn_version);
+        user_txn
+            .info
+            .changes
+            .iter()
+            .filter_map(|wsc| Self::from_write_set_change(wsc, txn_version, txn_timestamp))
+            .collect()
+    }
+
+    pub fn from_write_set_change(
+        wsc: &APIWriteSetChange,
+        txn_version: i64,
+        txn_timestamp: chrono::NaiveDateTime,
+    ) -> Option<Self> {
+        let delete_resource = match wsc {
+            APIWriteSetChange::DeleteResource(delete_resource) => delete_resource,
+            _ => return None,
+        };
+        let typ = &delete_resource.resource;
+        let resource_type = format!("{}::{}::{}", typ.address, typ.module, typ.name);
+        if resource_type != TOKEN_STORE_TYPE && resource_type != PENDING_CLAIMS_TYPE {
+            return None;
+        }
+        Some(Self {
+            address: standardize_address(&delete_resource.address.to_string()),
+            resource_type,
+            transaction_version: txn_version,
+            transaction_timestamp: txn_timestamp,
+        })
+    }
+
+    /// Zeroes the rows of the account written so far in the batch. Called in version order, after
+    /// the deleting transaction's own rows are added.
+    pub fn zero_batch_rows(
+        &self,
+        current_token_ownerships: &mut HashMap<CurrentTokenOwnershipPK, CurrentTokenOwnership>,
+        current_token_claims: &mut HashMap<CurrentTokenPendingClaimPK, CurrentTokenPendingClaim>,
+    ) {
+        if self.resource_type == TOKEN_STORE_TYPE {
+            for ownership in current_token_ownerships.values_mut() {
+                if ownership.owner_address == self.address {
+                    self.zero_ownership(ownership);
+                }
+            }
+        } else {
+            for claim in current_token_claims.values_mut() {
+                if claim.from_address == self.address {
+                    self.zero_claim(claim);
+                }
+            }
+        }
+    }
+
+    /// Zeroes the stored rows of the deleted resources' accounts. Rows the batch has are skipped,
+    /// since zero_batch_rows already zeroed them or they were written after the deletion.
+    pub fn zero_stored_rows(
+        conn: &mut PgPoolConnection,
+        deletions: &[Self],
+        current_token_ownerships: &mut HashMap<CurrentTokenOwnershipPK, CurrentTokenOwnership>,
+        current_token_claims: &mut HashMap<CurrentTokenPendingClaimPK, CurrentTokenPendingClaim>,
+    ) -> QueryResult<()> {
+        let addresses_of = |resource_type: &str| {
+            deletions
+                .iter()
+                .filter(|deletion| deletion.resource_type == resource_type)
+                .map(|deletion| deletion.address.clone())
+                .collect::<HashSet<String>>()
+        };
+        let owners = addresses_of(TOKEN_STORE_TYPE);
+        let offerers = addresses_of(PENDING_CLAIMS_TYPE);
+        if !owners.is_empty() {
+            let stored = current_token_ownerships::table
+                .filter(current_token_ownerships::owner_address.eq_any(owners))
+                .filter(current_token_ownerships::is_deleted.eq(false))
+                .select(CurrentTokenOwnership::as_select())
+                .load::<CurrentTokenOwnership>(conn)?;
+            for mut ownership in stored {
+                let pk = (
+                    ownership.token_data_id_hash.clone(),
+                    ownership.property_version.clone(),
+                    ownership.owner_address.clone(),
+                );
+                if current_token_ownerships.contains_key(&pk) {
+                    continue;
+                }
+                if let Some(deletion) = Self::first_after(
+                    deletions,
+                    TOKEN_STORE_TYPE,
+                    &ownership.owner_address,
+                    ownership.last_transaction_version,
+                ) {
+                    deletion.zero_ownership(&mut ownership);
+                    current_token_ownerships.insert(pk, ownership);
+                }
+            }
+        }
+        if !offerers.is_empty() {
+            let stored = current_token_pending_claims::table
+                .filter(current_token_pending_claims::from_address.eq_any(offerers))
+                .filter(current_token_pending_claims::is_deleted.eq(false))
+                .select(CurrentTokenPendingClaim::as_select())
+                .load::<CurrentTokenPendingClaim>(conn)?;
+            for mut claim in stored {
+                let pk = (
+                    claim.token_data_id_hash.clone(),
+                    claim.property_version.clone(),
+                    claim.from_address.clone(),
+                    claim.to_address.clone(),
+                );
+                if current_token_claims.contains_key(&pk) {
+                    continue;
+                }
+                if let Some(deletion) = Self::first_after(
+                    deletions,
+                    PENDING_CLAIMS_TYPE,
+                    &claim.from_address,
+                    claim.last_transaction_version,
+                ) {
+                    deletion.zero_claim(&mut claim);
+                    current_token_claims.insert(pk, claim);
+                }
+            }
+        }
+        Ok(())
+    }
+
+    /// Deletions are in version order, so this is the one that removed a row stored at version
+    fn first_after<'a>(
+        deletions: &'a [Self],
+        resource_type: &str,
+        address: &str,
+        version: i64,
+    ) -> Option<&'a Self> {
+        deletions.iter().find(|deletion| {
+            deletion.resource_type == resource_type
+                && deletion.address == address
+                && deletion.transaction_version > version
+        })
+    }
+
+    fn zero_ownership(&self, ownership: &mut CurrentTokenOwnership) {
+        ownership.amount = BigDecimal::zero();
+        ownership.is_deleted = true;
+        ownership.last_transaction_version = self.transaction_version;
+        ownership.last_transaction_timestamp = self.transaction_timestamp;
+    }
+
+    fn zero_claim(&self, claim: &mut CurrentTokenPendingClaim) {
+        claim.amount = BigDecimal::zero();
+        claim.is_deleted = true;
+        claim.last_transaction_version = self.transaction_version;
+        claim.last_transaction_timestamp = self.transaction_timestamp;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_utils::timestamp;
+    use serde_json::json;
+
+    const OWNER: &str = "0xa11ce";
+
+    fn delete_resource(resource: &str) -> Option<DeletedTokenResource> {
+        let wsc: APIWriteSetChange = serde_json::from_value(json!({
+            "type": "delete_resource",
+            "address": OWNER,
+            "state_key_hash": "0x00",
+            "resource": resource,
+        }))
+        .unwrap();
+        DeletedTokenResource::from_write_set_change(&wsc, 5, timestamp())
+    }
+
+    fn ownership(owner_address: &str, version: i64) -> CurrentTokenOwnership {
+        CurrentTokenOwnership {
+            token_data_id_hash: "hash".to_string(),
+            property_version: BigDecimal::zero(),
+            owner_address: owner_address.to_string(),
+            creator_address: standardize_address("0xc4e7"),
+            collection_name: "Monkeys".to_string(),
+            name: "Monkey #1".to_string(),
+            amount: BigDecimal::from(1),
+            token_properties: json!({}),
+            last_transaction_version: version,
+            collection_data_id_hash: "collection".to_string(),
+            table_type: TOKEN_STORE_TYPE.to_string(),
+            last_transaction_timestamp: timestamp(),
+            owner_type: "user".to_string(),
+            beneficial_owner: None,
+            is_deleted: false,
+        }
+    }
+
+    #[test]
+    fn test_from_write_set_change() {
+        let deletion = delete_resource("0x3::token::TokenStore").unwrap();
+        assert_eq!(deletion.address, standardize_address(OWNER));
+        assert_eq!(deletion.resource_type, TOKEN_STORE_TYPE);
+        assert_eq!(deletion.transaction_version, 5);
+        assert_eq!(
+            delete_resource("0x3::token_transfers::PendingClaims")
+                .unwrap()
+                .resource_type,
+            PENDING_CLAIMS_TYPE
+        );
+        assert!(delete_resource("0x1::coin::CoinStore<0x1::aptos_coin::AptosCoin>").is_none());
+        assert!(delete_resource("0xbad::token::TokenStore").is_none());
+    }
+
+    #[test]
+    fn test_zero_batch_rows() {
+        let deletion = delete_resource("0x3::token::TokenStore").unwrap();
+        let owner = standardize_address(OWNER);
+        let other = standardize_address("0xb0b");
+        let mut ownerships = HashMap::new();
+        for ownership in [ownership(&owner, 3), ownership(&other, 3)] {
+            ownerships.insert(
+                (
+                    ownership.token_data_id_hash.clone(),
+                    ownership.property_version.clone(),
+                    ownership.owner_address.clone(),
+                ),
+                ownership,
+            );
+        }
+        deletion.zero_batch_rows(&mut ownerships, &mut HashMap::new());
+
+        let zeroed = &ownerships[&("hash".to_string(), BigDecimal::zero(), owner)];
+        assert_eq!(zeroed.amount, BigDecimal::zero());
+        assert!(zeroed.is_deleted);
+        assert_eq!(zeroed.last_transaction_version, 5);
+        let kept = &ownerships[&("hash".to_string(), BigDecimal::zero(), other)];
+        assert_eq!(kept.amount, BigDecimal::from(1));
+        assert!(!kept.is_deleted);
+    }
+
+    #[test]
+    fn test_first_after() {
+        let owner = standardize_address(OWNER);
+        let deletions = [
+            delete_resource("0x3::token::TokenStore").unwrap(),
+            DeletedTokenResource {
+                transaction_version: 9,
+                ..delete_resource("0x3::token::TokenStore").unwrap()
+            },
+        ];
+        let version_of = |version: i64| {
+            DeletedTokenResource::first_after(&deletions, TOKEN_STORE_TYPE, &owner, version)
+                .map(|deletion| deletion.transaction_version)
+        };
+        assert_eq!(version_of(3), Some(5));
+        // Stored after the first deletion, ex: the account got a token again
+        assert_eq!(version_of(7), Some(9));
+        assert_eq!(version_of(9), None);
+        assert!(
+            DeletedTokenResource::first_after(&deletions, PENDING_CLAIMS_TYPE, &owner, 3).is_none()
+        );
+    }
+}
@@ -0,0 +1,669 @@
+// Copyright (c) Aptos
+// SPDX-License-Identifier: Apache-2.0
+
+//! What a token event does, parsed once and shared by every table built from events. Activities,
+//! listings, volumes and listing price changes are all derived from the same TokenEventEffects,
+//! so a sale can't count towards volume without closing the token's listing. Ownership comes
+//! from the write set and the whole batch is written in one db transaction, so readers never see
+//! one without the other.
+
+use super::{
+    marketplace_event_mappings::{EventKind, MappedMarketplaceEvent, MarketplaceEventMappings},
+    marketplaces::ParsedMarketplaceEvent,
+    token_activities::{event_handle_address, event_key, DEPOSIT_EVENT_TYPE, WITHDRAW_EVENT_TYPE},
+    token_utils::{TokenDataIdType, TokenEvent, TokenEvents, ValueLimits},
+};
+use crate::util::parse_timestamp;
+use anyhow::bail;
+use aptos_api_types::{Event as APIEvent, Transaction as APITransaction};
+use bigdecimal::{BigDecimal, Zero};
+use std::str::FromStr;
+
+/// How an event changes the token's marketplace listing. Picked from the event type, first match
+/// wins, so an event is never both a sale and a listing.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum MarketEffect {
+    /// Listed or put up for auction
+    List,
+    /// Price of an active listing changed
+    Reprice,
+    /// Delisted, or taken off the market some other way, ex: sent or filled
+    Unlist,
+    /// Sold, which also counts towards volume and ends the listing
+    Sale,
+}
+
+impl MarketEffect {
+    pub fn from_event_type(event_type: &str) -> Option<Self> {
+        if event_type.contains("Buy") || event_type.contains("Sell") || event_type.contains("Swap")
+        {
+            Some(Self::Sale)
+        } else if event_type.contains("Delist") || event_type.contains("CancelList") {
+            Some(Self::Unlist)
+        } else if event_type.contains("ChangePrice") {
+            Some(Self::Reprice)
+        } else if event_type.contains("List") || event_type.contains("Auction") {
+            Some(Self::List)
+        } else if event_type.contains("Change")
+            || event_type.contains("Fill")
+            || event_type.contains("Send")
+        {
+            Some(Self::Unlist)
+        } else {
+            None
+        }
+    }
+
+    /// Whether the token is still on the market after the event
+    pub fn keeps_listed(self) -> bool {
+        matches!(self, Self::List | Self::Reprice)
+    }
+}
+
+/// What an activity is, stored as token_activities.transfer_kind so consumers can filter a feed to
+/// sales or listings without matching on event types. Token framework events are classified
+/// here, marketplace events by their adapter or the kind of their mapping.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum TransferKind {
+    Sale,
+    /// Listed, put up for auction or repriced
+    Listing,
+    Delisting,
+    /// A bid on a token or a collection
+    Bid,
+    BidCancel,
+    /// Withdrawn, deposited or sent without a sale
+    Transfer,
+    Mint,
+    Burn,
+    /// A direct transfer offer, or its cancellation
+    Offer,
+    /// Claiming an offered token, or what an auction left to claim
+    Claim,
+    Mutation,
+    /// Anything else, so a new kind of event never fails the batch
+    Other,
+}
+
+impl TransferKind {
+    pub fn as_str(self) -> &'static str {
+        match self {
+            Self::Sale => "sale",
+            Self::Listing => "listing",
+            Self::Delisting => "delisting",
+            Self::Bid => "bid",
+            Self::BidCancel => "bid_cancel",
+            Self::Transfer => "transfer",
+            Self::Mint => "mint",
+            Self::Burn => "burn",
+            Self::Offer => "offer",
+            Self::Claim => "claim",
+            Self::Mutation => "mutation",
+            Self::Other => "other",
+        }
+    }
+
+    pub fn from_event_kind(kind: EventKind) -> Self {
+        match kind {
+            EventKind::List => Self::Listing,
+            EventKind::Delist => Self::Delisting,
+            EventKind::Buy => Self::Sale,
+            EventKind::Bid => Self::Bid,
+            EventKind::CancelBid => Self::BidCancel,
+            EventKind::Mint => Self::Mint,
+        }
+    }
+
+    /// The kind of an already indexed activity, from its transfer_type alone. Gives the same kind
+    /// as parsing the event for the types the indexer parses, and guesses from the type's name
+    /// like MarketEffect does for the others.
+    pub fn from_event_type(event_type: &str, mappings: &MarketplaceEventMappings) -> Self {
+        if let Some(kind) = mappings.event_kind(event_type) {
+            return Self::from_event_kind(kind);
+        }
+        match event_type {
+            "0x3::token::MintTokenEvent" => return Self::Mint,
+            "0x3::token::BurnTokenEvent" => return Self::Burn,
+            "0x3::token::MutateTokenPropertyMapEvent" => return Self::Mutation,
+            WITHDRAW_EVENT_TYPE | DEPOSIT_EVENT_TYPE => return Self::Transfer,
+            "0x3::token_transfers::TokenOfferEvent"
+            | "0x3::token_transfers::TokenCancelOfferEvent" => return Self::Offer,
+            "0x3::token_transfers::TokenClaimEvent" => return Self::Claim,
+            _ => {}
+        }
+        let name = event_type.split('<').next().unwrap_or(event_type);
+        let name = name.rsplit("::").next().unwrap_or(name);
+        if name.contains("CancelBid") || name.contains("CancelCollectionBid") {
+            return Self::BidCancel;
+        }
+        if name.contains("Bid") {
+            return Self::Bid;
+        }
+        match MarketEffect::from_event_type(name) {
+            Some(MarketEffect::Sale) => Self::Sale,
+            Some(MarketEffect::List | MarketEffect::Reprice) => Self::Listing,
+            Some(MarketEffect::Unlist) if name.contains("Send") => Self::Transfer,
+            Some(MarketEffect::Unlist) => Self::Delisting,
+            None if name.contains("Claim") => Self::Claim,
+            None => Self::Other,
+        }
+    }
+}
+
+impl FromStr for TransferKind {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> anyhow::Result<Self> {
+        Ok(match s {
+            "sale" => Self::Sale,
+            "listing" => Self::Listing,
+            "delisting" => Self::Delisting,
+            "bid" => Self::Bid,
+            "bid_cancel" => Self::BidCancel,
+            "transfer" => Self::Transfer,
+            "mint" => Self::Mint,
+            "burn" => Self::Burn,
+            "offer" => Self::Offer,
+            "claim" => Self::Claim,
+            "mutation" => Self::Mutation,
+            "other" => Self::Other,
+            _ => bail!(
+                "unknown transfer kind '{}', expected one of sale, listing, delisting, bid, \
+                bid_cancel, transfer, mint, burn, offer, claim, mutation, other",
+                s
+            ),
+        })
+    }
+}
+
+/// A simplified TokenActivity (excluded common fields) to reduce code duplication
+struct TokenActivityHelper<'a> {
+    pub token_data_id: &'a TokenDataIdType,
+    pub property_version: BigDecimal,
+    pub from_address: Option<String>,
+    pub to_address: Option<String>,
+    pub token_amount: BigDecimal,
+    pub coin_type: Option<String>,
+    pub coin_amount: Option<BigDecimal>,
+}
+
+/// One token of an event. Events about several tokens, ex: sweeps, have one per token.
+#[derive(Clone, Debug)]
+pub struct TokenEventEffects {
+    pub transaction_version: i64,
+    pub transaction_timestamp: chrono::NaiveDateTime,
+    pub event_type: String,
+    /// (event_account_address, event_creation_number, event_sequence_number), see event_key
+    pub event_key: (String, i64, i64),
+    pub event_index: i64,
+    pub token_index: i64,
+    pub token_data_id: TokenDataIdType,
+    pub property_version: BigDecimal,
+    pub from_address: Option<String>,
+    pub to_address: Option<String>,
+    pub token_amount: BigDecimal,
+    pub coin_type: Option<String>,
+    pub coin_amount: Option<BigDecimal>,
+    /// Amount and price of the listing the event leaves. Same as token_amount and coin_amount
+    /// except for BlueMove, whose list and change price events carry the price as their amount.
+    pub listing_amount: BigDecimal,
+    pub listing_price: Option<BigDecimal>,
+    pub market_effect: Option<MarketEffect>,
+    pub transfer_kind: TransferKind,
+    /// A price or amount is negative or past the ValueLimits, as a malicious contract's event can
+    /// be. The event is still recorded, but doesn't count towards volumes, floors or leaderboards.
+    pub suspect_value: bool,
+}
+
+impl TokenEventEffects {
+    /// Token framework events are parsed with their typed parser and marketplace events with their
+    /// marketplace's adapter, other events fall back to the configured marketplace event mappings
+    pub fn from_transaction(
+        transaction: &APITransaction,
+        token_events: &TokenEvents,
+        marketplace_event_mappings: &MarketplaceEventMappings,
+    ) -> Vec<Self> {
+        let mut effects = vec![];
+        if let APITransaction::UserTransaction(user_txn) = transaction {
+            let txn_version = user_txn.info.version.0 as i64;
+            let txn_timestamp = parse_timestamp(user_txn.timestamp.0, txn_version);
+            for (event_index, event) in user_txn.events.iter().enumerate() {
+                let event_type = event.typ.to_string();
+                if let Some(tokens) = token_events.marketplace_tokens(event_index) {
+                    for (token_index, parsed) in tokens.iter().enumerate() {
+                        effects.push(Self::from_marketplace_event(
+                            &event_type,
+                            event,
+                            event_index as i64,
+                            token_index,
+                            parsed,
+                            txn_version,
+                            txn_timestamp,
+                        ))
+                    }
+                    continue;
+                }
+                match token_events.get(event_index) {
+                    Some(token_event) => effects.push(Self::from_token_event(
+                        &event_type,
+                        event,
+                        event_index as i64,
+                        token_event,
+                        txn_version,
+                        txn_timestamp,
+                    )),
+                    None => {
+                        if let Some(mapped_event) = marketplace_event_mappings
+                            .from_event(event_type.as_str(), &event.data, txn_version)
+                            .unwrap()
+                        {
+                            effects.push(Self::from_mapped_event(
+                                &event_type,
+                                event,
+                                event_index as i64,
+                                &mapped_event,
+                                txn_version,
+                                txn_timestamp,
+                            ))
+                        }
+                    }
+                };
+            }
+        }
+        effects
+    }
+
+    pub fn from_mapped_event(
+        event_type: &str,
+        event: &APIEvent,
+        event_index: i64,
+        mapped_event: &MappedMarketplaceEvent,
+        txn_version: i64,
+        txn_timestamp: chrono::NaiveDateTime,
+    ) -> Self {
+        Self::from_marketplace_event(
+            event_type,
+            event,
+            event_index,
+            0,
+            &ParsedMarketplaceEvent::from(mapped_event),
+            txn_version,
+            txn_timestamp,
+        )
+    }
+
+    /// A token framework event, which is always about a single token
+    pub fn from_token_event(
+        event_type: &str,
+        event: &APIEvent,
+        event_index: i64,
+        token_event: &TokenEvent,
+        txn_version: i64,
+        txn_timestamp: chrono::NaiveDateTime,
+    ) -> Self {
+        let event_account_address = &event_handle_address(event);
+        let (transfer_kind, token_activity_helper) = match token_event {
+            TokenEvent::MintTokenEvent(inner) => (
+                TransferKind::Mint,
+                TokenActivityHelper {
+                    token_data_id: &inner.id,
+                    property_version: BigDecimal::zero(),
+                    from_address: event_account_address.clone(),
+                    to_address: None,
+                    token_amount: inner.amount.clone(),
+                    coin_type: None,
+                    coin_amount: None,
+                },
+            ),
+            TokenEvent::BurnTokenEvent(inner) => (
+                TransferKind::Burn,
+                TokenActivityHelper {
+                    token_data_id: &inner.id.token_data_id,
+                    property_version: inner.id.property_version.clone(),
+                    from_address: event_account_address.clone(),
+                    to_address: None,
+                    token_amount: inner.amount.clone(),
+                    coin_type: None,
+                    coin_amount: None,
+                },
+            ),
+            TokenEvent::MutateTokenPropertyMapEvent(inner) => (
+                TransferKind::Mutation,
+                TokenActivityHelper {
+                    token_data_id: &inner.new_id.token_data_id,
+                    property_version: inner.new_id.property_version.clone(),
+                    from_address: event_account_address.clone(),
+                    to_address: None,
+                    token_amount: BigDecimal::zero(),
+                    coin_type: None,
+                    coin_amount: None,
+                },
+            ),
+            TokenEvent::WithdrawTokenEvent(inner) => (
+                TransferKind::Transfer,
+                TokenActivityHelper {
+                    token_data_id: &inner.id.token_data_id,
+                    property_version: inner.id.property_version.clone(),
+                    from_address: event_account_address.clone(),
+                    to_address: None,
+                    token_amount: inner.amount.clone(),
+                    coin_type: None,
+                    coin_amount: None,
+                },
+            ),
+            TokenEvent::DepositTokenEvent(inner) => (
+                TransferKind::Transfer,
+                TokenActivityHelper {
+                    token_data_id: &inner.id.token_data_id,
+                    property_version: inner.id.property_version.clone(),
+                    from_address: None,
+                    to_address: event_account_address.clone(),
+                    token_amount: inner.amount.clone(),
+                    coin_type: None,
+                    coin_amount: None,
+                },
+            ),
+            TokenEvent::OfferTokenEvent(inner) => (
+                TransferKind::Offer,
+                TokenActivityHelper {
+                    token_data_id: &inner.token_id.token_data_id,
+                    property_version: inner.token_id.property_version.clone(),
+                    from_address: event_account_address.clone(),
+                    to_address: Some(inner.to_address.clone()),
+                    token_amount: inner.amount.clone(),
+                    coin_type: None,
+                    coin_amount: None,
+                },
+            ),
+            TokenEvent::CancelTokenOfferEvent(inner) => (
+                TransferKind::Offer,
+                TokenActivityHelper {
+                    token_data_id: &inner.token_id.token_data_id,
+                    property_version: inner.token_id.property_version.clone(),
+                    from_address: event_account_address.clone(),
+                    to_address: Some(inner.to_address.clone()),
+                    token_amount: inner.amount.clone(),
+                    coin_type: None,
+                    coin_amount: None,
+                },
+            ),
+            TokenEvent::ClaimTokenEvent(inner) => (
+                TransferKind::Claim,
+                TokenActivityHelper {
+                    token_data_id: &inner.token_id.token_data_id,
+                    property_version: inner.token_id.property_version.clone(),
+                    from_address: event_account_address.clone(),
+                    to_address: Some(inner.to_address.clone()),
+                    token_amount: inner.amount.clone(),
+                    coin_type: None,
+                    coin_amount: None,
+                },
+            ),
+            // Every other event is a marketplace's, whose tokens are normalized by its adapter
+            _ => unreachable!("{:?} isn't a token framework event", token_event),
+        };
+        Self::from_helper(
+            event_type,
+            event,
+            event_index,
+            0,
+            transfer_kind,
+            token_activity_helper,
+            txn_version,
+            txn_timestamp,
+        )
+    }
+
+    /// One token of a marketplace event, normalized by the marketplace's adapter, see marketplaces
+    pub fn from_marketplace_event(
+        event_type: &str,
+        event: &APIEvent,
+        event_index: i64,
+        token_index: usize,
+        parsed: &ParsedMarketplaceEvent,
+        txn_version: i64,
+        txn_timestamp: chrono::NaiveDateTime,
+    ) -> Self {
+        let mut effects = Self::from_helper(
+            event_type,
+            event,
+            event_index,
+            token_index as i64,
+            parsed.kind,
+            TokenActivityHelper {
+                token_data_id: &parsed.token_data_id,
+                property_version: parsed.property_version.clone(),
+                from_address: parsed.from_address.clone(),
+                to_address: parsed.to_address.clone(),
+                token_amount: parsed.token_amount.clone(),
+                coin_type: parsed.coin_type.clone(),
+                coin_amount: parsed.coin_amount.clone(),
+            },
+            txn_version,
+            txn_timestamp,
+        );
+        if let Some((listing_amount, listing_price)) = &parsed.listing {
+            effects.listing_amount = listing_amount.clone();
+            effects.listing_price = Some(listing_price.clone());
+            effects.suspect_value = effects.has_suspect_value(ValueLimits::current());
+        }
+        effects
+    }
+
+    fn from_helper(
+        event_type: &str,
+        event: &APIEvent,
+        event_index: i64,
+        token_index: i64,
+        transfer_kind: TransferKind,
+        token_activity_helper: TokenActivityHelper,
+        txn_version: i64,
+        txn_timestamp: chrono::NaiveDateTime,
+    ) -> Self {
+        let mut effects = Self {
+            transaction_version: txn_version,
+            transaction_timestamp: txn_timestamp,
+            event_type: event_type.to_string(),
+            event_key: event_key(event, event_index),
+            event_index,
+            token_index,
+            token_data_id: token_activity_helper.token_data_id.clone(),
+            property_version: token_activity_helper.property_version,
+            from_address: token_activity_helper.from_address,
+            to_address: token_activity_helper.to_address,
+            listing_amount: token_activity_helper.token_amount.clone(),
+            listing_price: token_activity_helper.coin_amount.clone(),
+            token_amount: token_activity_helper.token_amount,
+            coin_type: token_activity_helper.coin_type,
+            coin_amount: token_activity_helper.coin_amount,
+            market_effect: MarketEffect::from_event_type(event_type),
+            transfer_kind,
+            suspect_value: false,
+        };
+        effects.suspect_value = effects.has_suspect_value(ValueLimits::current());
+        effects
+    }
+
+    fn has_suspect_value(&self, limits: &ValueLimits) -> bool {
+        let is_suspect_price = |price: &Option<BigDecimal>| {
+            price
+                .as_ref()
+                .map_or(false, |price| limits.is_suspect_price(price))
+        };
+        limits.is_suspect_amount(&self.token_amount)
+            || limits.is_suspect_amount(&self.listing_amount)
+            || is_suspect_price(&self.coin_amount)
+            || is_suspect_price(&self.listing_price)
+    }
+
+    pub fn is_sale(&self) -> bool {
+        self.market_effect == Some(MarketEffect::Sale)
+    }
+
+    /// Whether the event counts towards volumes, i.e. it's a sale without a suspect value
+    pub fn is_countable_sale(&self) -> bool {
+        self.is_sale() && !self.suspect_value
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use bigdecimal::One;
+
+    const BLUEMOVE: &str = "0xd1fd99c1944b84d1670a2536417e997864ad12303d19eac725891691b04d614e";
+    const TOPAZ: &str = "0x2c7bccf7b31baf770fdbcc768d9e9cb3d87805e255355df5db32ac9a669010a2";
+    const SOUFFL3: &str = "0xf6994988bd40261af9431cd6dd3fcf765569719e66322c7a05cc78a89cd366d4";
+
+    /// Types of the events TokenEvent::from_event and the built in adapters parse
+    fn event_types() -> Vec<String> {
+        let framework = [
+            "0x3::token::MintTokenEvent",
+            "0x3::token::BurnTokenEvent",
+            "0x3::token::MutateTokenPropertyMapEvent",
+            "0x3::token::WithdrawEvent",
+            "0x3::token::DepositEvent",
+            "0x3::token_transfers::TokenOfferEvent",
+            "0x3::token_transfers::TokenCancelOfferEvent",
+            "0x3::token_transfers::TokenClaimEvent",
+        ];
+        let bluemove = [
+            "AuctionEvent",
+            "BidEvent",
+            "BuyEvent",
+            "ChangePriceEvent",
+            "ClaimCoinsEvent",
+            "ClaimTokenEvent",
+            "DelistEvent",
+            "ListEvent",
+        ];
+        let topaz = [
+            "BidEvent",
+            "BuyEvent",
+            "BuyAllEvent",
+            "CancelBidEvent",
+            "CancelCollectionBidEvent",
+            "ClaimEvent",
+            "CollectionBidEvent",
+            "DelistEvent",
+            "ListEvent",
+            "SellEvent",
+            "SendEvent",
+        ];
+        let souffl3 = [
+            "FixedPriceMarket::BuyTokenEvent",
+            "FixedPriceMarket::CancelListTokenEvent",
+            "FixedPriceMarket::ListTokenEvent",
+            "token_coin_swap::TokenListingEvent",
+            "token_coin_swap::TokenSwapEvent",
+        ];
+        framework
+            .iter()
+            .map(|event_type| event_type.to_string())
+            .chain(
+                bluemove
+                    .iter()
+                    .map(|name| format!("{}::marketplaceV2::{}", BLUEMOVE, name)),
+            )
+            .chain(
+                topaz
+                    .iter()
+                    .map(|name| format!("{}::events::{}", TOPAZ, name)),
+            )
+            .chain(souffl3.iter().map(|name| format!("{}::{}", SOUFFL3, name)))
+            .collect()
+    }
+
+    /// The rules volumes used on their own before TokenEventEffects
+    fn legacy_is_sale(event_type: &str) -> bool {
+        event_type.contains("Buy") || event_type.contains("Sell") || event_type.contains("Swap")
+    }
+
+    /// The rules listings used on their own before TokenEventEffects, whether the event leaves
+    /// the token listed or None when it doesn't touch the listing
+    fn legacy_keeps_listed(event_type: &str) -> Option<bool> {
+        let touches_listing = [
+            "List",
+            "Delist",
+            "Buy",
+            "Sell",
+            "Change",
+            "CancelList",
+            "Fill",
+            "Send",
+            "Auction",
+        ]
+        .iter()
+        .any(|keyword| event_type.contains(keyword));
+        touches_listing.then(|| {
+            (event_type.contains("List")
+                || event_type.contains("Auction")
+                || event_type.contains("ChangePrice"))
+                && !event_type.contains("CancelList")
+                && !event_type.contains("Delist")
+        })
+    }
+
+    #[test]
+    fn test_market_effects_match_legacy_rules() {
+        for event_type in event_types() {
+            let market_effect = MarketEffect::from_event_type(&event_type);
+            assert_eq!(
+                market_effect == Some(MarketEffect::Sale),
+                legacy_is_sale(&event_type),
+                "{}",
+                event_type
+            );
+            if event_type.ends_with("::token_coin_swap::TokenSwapEvent") {
+                // Counted towards volume without ending the listing
+                assert_eq!(legacy_keeps_listed(&event_type), None);
+                assert_eq!(market_effect, Some(MarketEffect::Sale));
+                continue;
+            }
+            assert_eq!(
+                market_effect.map(MarketEffect::keeps_listed),
+                legacy_keeps_listed(&event_type),
+                "{}",
+                event_type
+            );
+        }
+    }
+
+    #[test]
+    fn test_sales_end_listings() {
+        // Counted towards volume while leaving the token listed
+        for event_type in [
+            "0xf00d::market::ListingBuyEvent",
+            "0xf00d::auction::AuctionSellEvent",
+        ] {
+            assert!(legacy_is_sale(event_type));
+            assert_eq!(legacy_keeps_listed(event_type), Some(true));
+            let market_effect = MarketEffect::from_event_type(event_type).unwrap();
+            assert_eq!(market_effect, MarketEffect::Sale);
+            assert!(!market_effect.keeps_listed());
+        }
+    }
+
+    #[test]
+    fn test_bluemove_listing_price() {
+        let path = concat!(
+            env!("CARGO_MANIFEST_DIR"),
+            "/fixtures/golden/transactions/bluemove_list.json"
+        );
+        let transaction: APITransaction =
+            serde_json::from_str(&std::fs::read_to_string(path).unwrap()).unwrap();
+        let effects = TokenEventEffects::from_transaction(
+            &transaction,
+            &TokenEvents::from_transaction(&transaction).unwrap(),
+            &MarketplaceEventMappings::default(),
+        );
+        assert_eq!(effects.len(), 1);
+        let price = BigDecimal::from(130000000);
+        // The activity keeps the event's amount, the listing is one token at that price
+        assert_eq!(effects[0].token_amount, price);
+        assert_eq!(effects[0].coin_amount, None);
+        assert_eq!(effects[0].listing_amount, BigDecimal::one());
+        assert_eq!(effects[0].listing_price, Some(price));
+        assert_eq!(effects[0].market_effect, Some(MarketEffect::List));
+    }
+}
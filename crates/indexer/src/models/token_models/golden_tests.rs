@@ -0,0 +1,185 @@
+// Copyright (c) Aptos
+// SPDX-License-Identifier: Apache-2.0
+
+//! Golden tests for the rows built from a transaction. Every transaction in
+//! `fixtures/golden/transactions` goes through the activity, listing and volume builders, and the
+//! serialized rows are compared with the file of the same name in `fixtures/golden/outputs`.
+//! After an intended change, rerun with `REGENERATE_GOLDEN=1` to rewrite the outputs.
+
+use super::{
+    collection_volume::CurrentCollectionVolume,
+    event_effects::{TokenEventEffects, TransferKind},
+    marketplace_event_mappings::MarketplaceEventMappings,
+    marketplace_listings::CurrentMarketplaceListing,
+    nft_sales::NftSale,
+    token_activities::TokenActivity,
+    token_utils::TokenEvents,
+};
+use aptos_api_types::Transaction as APITransaction;
+use aptos_config::config::MarketplacePayloadMapping;
+use serde_json::{json, Value};
+use std::{
+    collections::HashMap,
+    fs,
+    path::{Path, PathBuf},
+};
+
+const GOLDEN_DIR: &str = concat!(env!("CARGO_MANIFEST_DIR"), "/fixtures/golden");
+/// Market of payload_buy.json, which sells through an entry function without a sale event
+const PAYLOAD_MARKET_ADDRESS: &str =
+    "0x8f6cd2b2a4e0c1a9d5e3f7b1c6a2d4e8f0b3c5a7d9e1f2a4b6c8d0e2f4a6b8c1";
+
+/// Rows built into a HashMap are sorted by key so that the output is stable
+fn sorted_values<T>(rows: HashMap<String, T>) -> Vec<T> {
+    let mut rows: Vec<(String, T)> = rows.into_iter().collect();
+    rows.sort_by(|a, b| a.0.cmp(&b.0));
+    rows.into_iter().map(|(_, row)| row).collect()
+}
+
+fn mappings() -> MarketplaceEventMappings {
+    MarketplaceEventMappings::default()
+        .with_payload_mappings(&[MarketplacePayloadMapping {
+            module_address: PAYLOAD_MARKET_ADDRESS.to_string(),
+            function_name: "market::buy_token".to_string(),
+        }])
+        .unwrap()
+}
+
+/// The transactions in `fixtures/golden/transactions`, sorted by file name
+fn golden_transactions() -> Vec<(PathBuf, APITransaction)> {
+    let mut paths: Vec<PathBuf> = fs::read_dir(Path::new(GOLDEN_DIR).join("transactions"))
+        .unwrap()
+        .map(|entry| entry.unwrap().path())
+        .collect();
+    paths.sort();
+    assert!(!paths.is_empty());
+    paths
+        .into_iter()
+        .map(|path| {
+            let transaction = serde_json::from_str(&fs::read_to_string(&path).unwrap()).unwrap();
+            (path, transaction)
+        })
+        .collect()
+}
+
+/// Same calls as the token processor, without the parts that need the db
+fn build_rows(transaction: &APITransaction) -> Value {
+    let mappings = mappings();
+    let token_events = TokenEvents::from_transaction(transaction).unwrap();
+    let event_effects = TokenEventEffects::from_transaction(transaction, &token_events, &mappings);
+    let token_activities = TokenActivity::from_effects(&event_effects);
+    let mut nft_sales = NftSale::from_token_activities(transaction, &token_activities, None);
+    nft_sales.extend(NftSale::from_payload(
+        transaction,
+        &token_activities,
+        &mappings,
+        None,
+    ));
+    let current_marketplace_listings =
+        CurrentMarketplaceListing::from_transaction(transaction, &event_effects, &mappings);
+    let (current_collection_volumes, collection_volumes, current_token_volumes, token_volumes) =
+        CurrentCollectionVolume::from_effects(&event_effects, &nft_sales);
+    json!({
+        "token_activities": token_activities,
+        "current_marketplace_listings": sorted_values(current_marketplace_listings),
+        "current_collection_volumes": sorted_values(current_collection_volumes),
+        "collection_volumes": collection_volumes,
+        "current_token_volumes": sorted_values(current_token_volumes),
+        "token_volumes": token_volumes,
+    })
+}
+
+#[test]
+fn test_golden_rows() {
+    let regenerate = std::env::var("REGENERATE_GOLDEN").is_ok();
+    for (path, transaction) in golden_transactions() {
+        let rows = build_rows(&transaction);
+        let golden_path = Path::new(GOLDEN_DIR)
+            .join("outputs")
+            .join(path.file_name().unwrap());
+        if regenerate {
+            fs::write(
+                &golden_path,
+                serde_json::to_string_pretty(&rows).unwrap() + "\n",
+            )
+            .unwrap();
+            continue;
+        }
+        let golden: Value = fs::read_to_string(&golden_path)
+            .map(|golden| serde_json::from_str(&golden).unwrap())
+            .unwrap_or_else(|err| {
+                panic!(
+                    "{}: {}, run with REGENERATE_GOLDEN=1 to create it",
+                    golden_path.display(),
+                    err
+                )
+            });
+        assert_eq!(
+            rows,
+            golden,
+            "rows for {} changed, run with REGENERATE_GOLDEN=1 if that's intended",
+            path.display()
+        );
+    }
+}
+
+/// Every sale event ends the token's listing and adds to both volumes exactly once
+#[test]
+fn test_sale_events_close_listings_and_count_towards_volume() {
+    let mut sale_count = 0;
+    for (path, transaction) in golden_transactions() {
+        let mappings = mappings();
+        let token_events = TokenEvents::from_transaction(&transaction).unwrap();
+        let event_effects =
+            TokenEventEffects::from_transaction(&transaction, &token_events, &mappings);
+        let nft_sales = NftSale::from_token_activities(
+            &transaction,
+            &TokenActivity::from_effects(&event_effects),
+            None,
+        );
+        let listings =
+            CurrentMarketplaceListing::from_transaction(&transaction, &event_effects, &mappings);
+        let (_, collection_volumes, _, token_volumes) =
+            CurrentCollectionVolume::from_effects(&event_effects, &nft_sales);
+        for sale in &nft_sales {
+            let listing = &listings[&sale.token_data_id_hash];
+            assert_eq!(listing.market_address, "", "{}", path.display());
+            let key = (sale.event_index, sale.token_index);
+            let volume_count = collection_volumes
+                .iter()
+                .filter(|volume| (volume.event_index, volume.token_index) == key)
+                .count();
+            let token_volume_count = token_volumes
+                .iter()
+                .filter(|volume| (volume.event_index, volume.token_index) == key)
+                .count();
+            assert_eq!(
+                (volume_count, token_volume_count),
+                (1, 1),
+                "{}",
+                path.display()
+            );
+        }
+        sale_count += nft_sales.len();
+    }
+    assert!(sale_count > 0);
+}
+
+/// backfill-transfer-kinds only has the event type to go on, it should land on the kind indexing
+/// the event gives it
+#[test]
+fn test_transfer_kinds_from_event_type_match_indexing() {
+    let mappings = mappings();
+    for (path, transaction) in golden_transactions() {
+        let token_events = TokenEvents::from_transaction(&transaction).unwrap();
+        for effects in TokenEventEffects::from_transaction(&transaction, &token_events, &mappings) {
+            assert_eq!(
+                TransferKind::from_event_type(&effects.event_type, &mappings),
+                effects.transfer_kind,
+                "{}: {}",
+                path.display(),
+                effects.event_type
+            );
+        }
+    }
+}
@@ -0,0 +1,413 @@
+// Copyright (c) Aptos
+// SPDX-License-Identifier: Apache-2.0
+
+// This is required because a diesel macro makes clippy sad
+#![allow(clippy::extra_unused_lifetimes)]
+#![allow(clippy::unused_unit)]
+
+use super::collection_reports::ALL_DIMENSIONS;
+use crate::{
+    database::run_transaction_with_retries,
+    schema::{collection_leaderboard, trader_leaderboard},
+};
+use anyhow::ensure;
+use aptos_config::config::LeaderboardsConfig;
+use bigdecimal::BigDecimal;
+use diesel::{
+    dsl::max,
+    sql_query,
+    sql_types::{BigInt, Text, Timestamp},
+    PgConnection, QueryDsl, QueryResult, RunQueryDsl,
+};
+use serde::{Deserialize, Serialize};
+use std::sync::atomic::{AtomicI64, Ordering};
+
+pub const DEFAULT_INTERVAL_SECS: u64 = 300;
+pub const DEFAULT_TOP_N: u64 = 1000;
+
+#[derive(Debug, Deserialize, Identifiable, Queryable, Selectable, Serialize)]
+#[diesel(primary_key(collection_data_id_hash))]
+#[diesel(table_name = collection_leaderboard)]
+pub struct CollectionLeaderboardEntry {
+    pub collection_data_id_hash: String,
+    pub creator_address: String,
+    pub collection_name: String,
+    pub volume_24h: BigDecimal,
+    pub volume_7d: BigDecimal,
+    pub total_volume: BigDecimal,
+    pub rank_24h: Option<i64>,
+    pub rank_7d: Option<i64>,
+    pub rank_all_time: Option<i64>,
+    pub refreshed_at: chrono::NaiveDateTime,
+}
+
+#[derive(Debug, Deserialize, Identifiable, Queryable, Selectable, Serialize)]
+#[diesel(primary_key(trader_address))]
+#[diesel(table_name = trader_leaderboard)]
+pub struct TraderLeaderboardEntry {
+    pub trader_address: String,
+    pub volume_24h: BigDecimal,
+    pub bought_volume_24h: BigDecimal,
+    pub sold_volume_24h: BigDecimal,
+    pub trades_24h: i64,
+    pub rank: i64,
+    pub refreshed_at: chrono::NaiveDateTime,
+}
+
+/// Periodically rebuilds collection_leaderboard and trader_leaderboard. Collections are ranked
+/// from the hourly rollup rows of collection_price_candles and from current_collection_volumes,
+/// and traders from the last 24 hours of nft_sales, since no rollup is kept per wallet and
+/// window. Equal volumes are ranked by collection hash or trader address, so a rebuild from the
+/// same data gives the same ranks.
+///
+/// Like the stats snapshots, the interval is in chain time.
+#[derive(Debug)]
+pub struct Leaderboards {
+    interval_secs: i64,
+    top_n: i64,
+    next_refresh_secs: AtomicI64,
+}
+
+impl Leaderboards {
+    pub fn from_config(config: Option<&LeaderboardsConfig>) -> anyhow::Result<Option<Self>> {
+        let config = match config {
+            Some(config) => config,
+            None => return Ok(None),
+        };
+        let interval_secs = config.interval_secs.unwrap_or(DEFAULT_INTERVAL_SECS);
+        let top_n = config.top_n.unwrap_or(DEFAULT_TOP_N);
+        ensure!(interval_secs > 0, "interval_secs must be greater than 0");
+        ensure!(
+            interval_secs <= i64::MAX as u64,
+            "interval_secs is too large"
+        );
+        ensure!(top_n > 0, "top_n must be greater than 0");
+        ensure!(top_n <= i64::MAX as u64, "top_n is too large");
+        Ok(Some(Self {
+            interval_secs: interval_secs as i64,
+            top_n: top_n as i64,
+            next_refresh_secs: AtomicI64::new(i64::MIN),
+        }))
+    }
+
+    /// Returns true if the leaderboards should be rebuilt after the batch ending at `as_of`. The
+    /// first batch after startup always rebuilds them.
+    pub fn is_due(&self, as_of: chrono::NaiveDateTime) -> bool {
+        let as_of_secs = as_of.timestamp();
+        self.next_refresh_secs
+            .fetch_update(Ordering::Relaxed, Ordering::Relaxed, |next_refresh_secs| {
+                (as_of_secs >= next_refresh_secs)
+                    .then(|| as_of_secs.saturating_add(self.interval_secs))
+            })
+            .is_ok()
+    }
+
+    /// Rebuilds both leaderboards as of `as_of` and returns the number of collections and
+    /// traders written, or None if they were already built from a later batch, since batches
+    /// can commit out of order.
+    ///
+    /// Each board is computed into a temporary staging table, then swapped in by deleting the
+    /// old rows and copying the staged ones, all in one transaction. Readers keep seeing the
+    /// previous board until it commits. The EXCLUSIVE locks don't block reads, only another
+    /// refresh.
+    pub fn refresh(
+        &self,
+        conn: &mut PgConnection,
+        as_of: chrono::NaiveDateTime,
+    ) -> QueryResult<Option<(usize, usize)>> {
+        run_transaction_with_retries(conn, |conn| {
+            sql_query("LOCK TABLE collection_leaderboard, trader_leaderboard IN EXCLUSIVE MODE")
+                .execute(conn)?;
+            let refreshed_at = collection_leaderboard::table
+                .select(max(collection_leaderboard::refreshed_at))
+                .first::<Option<chrono::NaiveDateTime>>(conn)?;
+            if matches!(refreshed_at, Some(refreshed_at) if refreshed_at > as_of) {
+                return Ok(None);
+            }
+            let num_collections = self.swap_collections(conn, as_of)?;
+            let num_traders = self.swap_traders(conn, as_of)?;
+            Ok(Some((num_collections, num_traders)))
+        })
+    }
+
+    /// Collections without volume in a window have no rank in it, and a collection is kept if
+    /// it's in the top n of any of the rankings
+    fn swap_collections(
+        &self,
+        conn: &mut PgConnection,
+        as_of: chrono::NaiveDateTime,
+    ) -> QueryResult<usize> {
+        sql_query(
+            "CREATE TEMP TABLE collection_leaderboard_staging (LIKE collection_leaderboard)
+            ON COMMIT DROP",
+        )
+        .execute(conn)?;
+        sql_query(
+            "INSERT INTO collection_leaderboard_staging (
+                collection_data_id_hash, creator_address, collection_name, volume_24h,
+                volume_7d, total_volume, rank_24h, rank_7d, rank_all_time, refreshed_at,
+                inserted_at
+            )
+            SELECT *, $1, NOW() FROM (
+                SELECT collection_data_id_hash, creator_address, collection_name, volume_24h,
+                    volume_7d, total_volume,
+                    CASE WHEN volume_24h > 0 THEN ROW_NUMBER() OVER (
+                        ORDER BY volume_24h DESC, collection_data_id_hash
+                    ) END AS rank_24h,
+                    CASE WHEN volume_7d > 0 THEN ROW_NUMBER() OVER (
+                        ORDER BY volume_7d DESC, collection_data_id_hash
+                    ) END AS rank_7d,
+                    CASE WHEN total_volume > 0 THEN ROW_NUMBER() OVER (
+                        ORDER BY total_volume DESC, collection_data_id_hash
+                    ) END AS rank_all_time
+                FROM (
+                    SELECT cd.collection_data_id_hash, cd.creator_address, cd.collection_name,
+                        COALESCE(c.volume_24h, 0) AS volume_24h,
+                        COALESCE(c.volume_7d, 0) AS volume_7d,
+                        COALESCE(v.volume, 0) AS total_volume
+                    FROM current_collection_datas cd
+                    LEFT JOIN (
+                        SELECT collection_data_id_hash,
+                            SUM(volume) FILTER (
+                                WHERE interval_start >= date_trunc('hour', $1) - INTERVAL '23 hours'
+                            ) AS volume_24h,
+                            SUM(volume) AS volume_7d
+                        FROM collection_price_candles
+                        WHERE coin_type = $3 AND market_address = $3
+                            AND interval_start >= date_trunc('hour', $1) - INTERVAL '167 hours'
+                            AND interval_start <= $1
+                        GROUP BY collection_data_id_hash
+                    ) c ON c.collection_data_id_hash = cd.collection_data_id_hash
+                    LEFT JOIN current_collection_volumes v
+                        ON v.collection_data_id_hash = cd.collection_data_id_hash
+                ) volumes
+            ) ranked
+            WHERE rank_24h <= $2 OR rank_7d <= $2 OR rank_all_time <= $2",
+        )
+        .bind::<Timestamp, _>(as_of)
+        .bind::<BigInt, _>(self.top_n)
+        .bind::<Text, _>(ALL_DIMENSIONS)
+        .execute(conn)?;
+        sql_query("DELETE FROM collection_leaderboard").execute(conn)?;
+        sql_query("INSERT INTO collection_leaderboard SELECT * FROM collection_leaderboard_staging")
+            .execute(conn)
+    }
+
+    /// A trader's 24h volume is what they bought plus what they sold
+    fn swap_traders(
+        &self,
+        conn: &mut PgConnection,
+        as_of: chrono::NaiveDateTime,
+    ) -> QueryResult<usize> {
+        sql_query(
+            "CREATE TEMP TABLE trader_leaderboard_staging (LIKE trader_leaderboard)
+            ON COMMIT DROP",
+        )
+        .execute(conn)?;
+        sql_query(
+            "INSERT INTO trader_leaderboard_staging (
+                trader_address, volume_24h, bought_volume_24h, sold_volume_24h, trades_24h,
+                rank, refreshed_at, inserted_at
+            )
+            SELECT trader_address, SUM(bought) + SUM(sold), SUM(bought), SUM(sold), COUNT(*),
+                ROW_NUMBER() OVER (ORDER BY SUM(bought) + SUM(sold) DESC, trader_address)
+                    AS trader_rank,
+                $1, NOW()
+            FROM (
+                SELECT buyer AS trader_address, price AS bought, 0 AS sold
+                FROM nft_sales
+                WHERE buyer IS NOT NULL AND price IS NOT NULL AND NOT suspect_value
+                    AND transaction_timestamp > $1 - INTERVAL '1 day'
+                    AND transaction_timestamp <= $1
+                UNION ALL
+                SELECT seller AS trader_address, 0 AS bought, price AS sold
+                FROM nft_sales
+                WHERE seller IS NOT NULL AND price IS NOT NULL AND NOT suspect_value
+                    AND transaction_timestamp > $1 - INTERVAL '1 day'
+                    AND transaction_timestamp <= $1
+            ) trades
+            GROUP BY trader_address
+            ORDER BY trader_rank
+            LIMIT $2",
+        )
+        .bind::<Timestamp, _>(as_of)
+        .bind::<BigInt, _>(self.top_n)
+        .execute(conn)?;
+        sql_query("DELETE FROM trader_leaderboard").execute(conn)?;
+        sql_query("INSERT INTO trader_leaderboard SELECT * FROM trader_leaderboard_staging")
+            .execute(conn)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        database::new_db_pool,
+        indexer::tailer::{test::wipe_database, MIGRATIONS},
+    };
+    use diesel::{ExpressionMethods, SelectableHelper};
+    use diesel_migrations::MigrationHarness;
+
+    fn at(secs: i64) -> chrono::NaiveDateTime {
+        chrono::NaiveDateTime::from_timestamp(1668000000 + secs, 0)
+    }
+
+    #[test]
+    fn test_is_due() {
+        let leaderboards = Leaderboards::from_config(Some(&LeaderboardsConfig {
+            interval_secs: Some(60),
+            top_n: None,
+        }))
+        .unwrap()
+        .unwrap();
+        assert_eq!(leaderboards.top_n, DEFAULT_TOP_N as i64);
+        assert!(leaderboards.is_due(at(0)));
+        assert!(!leaderboards.is_due(at(59)));
+        assert!(leaderboards.is_due(at(60)));
+
+        assert!(Leaderboards::from_config(None).unwrap().is_none());
+        for (interval_secs, top_n) in [(Some(0), None), (None, Some(0))] {
+            assert!(Leaderboards::from_config(Some(&LeaderboardsConfig {
+                interval_secs,
+                top_n,
+            }))
+            .is_err());
+        }
+    }
+
+    #[test]
+    fn test_refresh() {
+        if crate::should_skip_pg_tests() {
+            return;
+        }
+        let database_url = std::env::var("INDEXER_DATABASE_URL").unwrap();
+        let conn_pool = new_db_pool(&database_url).unwrap();
+        let mut conn = conn_pool.get().unwrap();
+        wipe_database(&mut conn);
+        conn.run_pending_migrations(MIGRATIONS).unwrap();
+
+        // potions and scrolls tie on the last day, elixirs only sold 3 days before
+        for hash in ["elixirs", "potions", "scrolls"] {
+            sql_query(format!(
+                "INSERT INTO current_collection_datas (
+                    collection_data_id_hash, creator_address, collection_name, description,
+                    metadata_uri, supply, maximum, maximum_mutable, uri_mutable,
+                    description_mutable, last_transaction_version, table_handle,
+                    last_transaction_timestamp
+                ) VALUES ('{0}', '0xc4e7', '{0}', '', '', 10, 10, false, false, false, 1,
+                    '0x7', '2022-11-01 12:00:00')",
+                hash,
+            ))
+            .execute(&mut conn)
+            .unwrap();
+        }
+        for (hash, interval_start, volume) in [
+            ("scrolls", "2022-11-09 17:00:00", 300),
+            ("potions", "2022-11-09 10:00:00", 300),
+            ("elixirs", "2022-11-06 12:00:00", 500),
+        ] {
+            sql_query(format!(
+                "INSERT INTO collection_price_candles (
+                    collection_data_id_hash, coin_type, market_address, interval_start,
+                    open_price, high_price, low_price, close_price, volume, sales_count,
+                    first_transaction_version, last_transaction_version
+                ) VALUES ('{0}', 'all', 'all', '{1}', 1, 1, 1, 1, {2}, 1, 2, 2)",
+                hash, interval_start, volume,
+            ))
+            .execute(&mut conn)
+            .unwrap();
+            sql_query(format!(
+                "INSERT INTO current_collection_volumes (
+                    collection_data_id_hash, volume, last_transaction_version, primary_volume,
+                    secondary_volume, last_transaction_timestamp
+                ) VALUES ('{0}', {1}, 2, 0, {1}, '{2}')",
+                hash, volume, interval_start,
+            ))
+            .execute(&mut conn)
+            .unwrap();
+        }
+        for (version, seller, buyer, price, timestamp) in [
+            (2, "0xa11ce", "0xb0b", 300, "2022-11-09 17:00:00"),
+            (3, "0xb0b", "0xa11ce", 300, "2022-11-09 10:00:00"),
+            (4, "0xca41", "0xd0e", 500, "2022-11-06 12:00:00"),
+        ] {
+            sql_query(format!(
+                "INSERT INTO nft_sales (
+                    transaction_version, event_account_address, event_creation_number,
+                    event_sequence_number, market_address, event_type, token_data_id_hash,
+                    property_version, collection_data_id_hash, creator_address, collection_name,
+                    name, seller, buyer, token_amount, price, gas_unit_price,
+                    transaction_timestamp, is_primary
+                ) VALUES ({0}, '0xfa4e', 0, {0}, '0xfa4e', 'buy', 'potion', 0, 'potions',
+                    '0xc4e7', 'Potions', 'Potion', '{1}', '{2}', 1, {3}, 100, '{4}', false)",
+                version, seller, buyer, price, timestamp,
+            ))
+            .execute(&mut conn)
+            .unwrap();
+        }
+
+        let as_of = chrono::NaiveDate::from_ymd(2022, 11, 9).and_hms(18, 0, 0);
+        let leaderboards = Leaderboards::from_config(Some(&LeaderboardsConfig {
+            interval_secs: None,
+            top_n: Some(2),
+        }))
+        .unwrap()
+        .unwrap();
+        assert_eq!(
+            leaderboards.refresh(&mut conn, as_of).unwrap(),
+            Some((3, 2))
+        );
+        // Rebuilding from the same data gives the same board, and an older batch doesn't
+        assert_eq!(
+            leaderboards.refresh(&mut conn, as_of).unwrap(),
+            Some((3, 2))
+        );
+        assert_eq!(
+            leaderboards
+                .refresh(&mut conn, as_of - chrono::Duration::hours(1))
+                .unwrap(),
+            None
+        );
+
+        let collections = collection_leaderboard::table
+            .order(collection_leaderboard::collection_data_id_hash)
+            .select(CollectionLeaderboardEntry::as_select())
+            .load::<CollectionLeaderboardEntry>(&mut conn)
+            .unwrap();
+        let ranks = collections
+            .iter()
+            .map(|entry| {
+                (
+                    entry.collection_data_id_hash.as_str(),
+                    entry.rank_24h,
+                    entry.rank_7d,
+                    entry.rank_all_time,
+                )
+            })
+            .collect::<Vec<_>>();
+        // elixirs is kept for its 7d and all time ranks, with no 24h rank
+        assert_eq!(
+            ranks,
+            vec![
+                ("elixirs", None, Some(1), Some(1)),
+                ("potions", Some(1), Some(2), Some(2)),
+                ("scrolls", Some(2), Some(3), Some(3)),
+            ]
+        );
+
+        let traders = trader_leaderboard::table
+            .order(trader_leaderboard::rank)
+            .select(TraderLeaderboardEntry::as_select())
+            .load::<TraderLeaderboardEntry>(&mut conn)
+            .unwrap();
+        assert_eq!(traders.len(), 2);
+        assert_eq!(traders[0].trader_address, "0xa11ce");
+        assert_eq!(traders[0].volume_24h, BigDecimal::from(600));
+        assert_eq!(traders[0].bought_volume_24h, BigDecimal::from(300));
+        assert_eq!(traders[0].trades_24h, 2);
+        assert_eq!(traders[1].trader_address, "0xb0b");
+        assert_eq!(traders[1].rank, 2);
+        assert_eq!(traders[1].refreshed_at, as_of);
+    }
+}
@@ -0,0 +1,1147 @@
+// Copyright (c) Aptos
+// SPDX-License-Identifier: Apache-2.0
+
+//! Config driven parsing for marketplaces whose events are simple enough that they don't need an
+//! adapter in marketplaces. See `MarketplaceEventMapping` in the indexer config. Listing
+//! structs in the write set are mapped the same way, see `MarketplaceListingMapping`, and
+//! marketplaces without any sale event are matched on their entry function, see
+//! `MarketplacePayloadMapping`. Marketplaces whose events do need a typed parser, but are
+//! deployed at addresses we don't hardcode, are registered with `MarketplaceTypedEventMapping`.
+//! The adapters of the marketplaces we do hardcode are kept here too, along with the
+//! marketplaces defined in `marketplace_definitions_path`, so that one value carries everything
+//! the config says about marketplaces.
+
+use super::{
+    marketplaces::{declarative::read_definitions, souffl3, MarketplaceAdapters, MarketplaceEvent},
+    token_utils::{MarketplaceEventParser, TokenDataIdType, TokenEvent, TokenIdType},
+};
+use crate::util::standardize_address;
+use anyhow::{bail, ensure, Context, Result};
+use aptos_api_types::TransactionPayload;
+use aptos_config::config::{
+    MarketplaceDefinition, MarketplaceEventMapping, MarketplaceListingMapping,
+    MarketplacePayloadMapping, MarketplaceTypedEventMapping,
+};
+use bigdecimal::{BigDecimal, One, Zero};
+use std::{
+    collections::{HashMap, HashSet},
+    fmt,
+    path::Path,
+    str::FromStr,
+};
+
+/// What a configured marketplace event means, which decides how buyer and seller map to the
+/// from/to addresses of the token activity
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum EventKind {
+    List,
+    Delist,
+    Buy,
+    Bid,
+    CancelBid,
+    /// Launchpad mint, which carries the mint price unlike 0x3::token::MintTokenEvent
+    Mint,
+}
+
+impl FromStr for EventKind {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        Ok(match s {
+            "list" => Self::List,
+            "delist" => Self::Delist,
+            "buy" => Self::Buy,
+            "bid" => Self::Bid,
+            "cancel_bid" => Self::CancelBid,
+            "mint" => Self::Mint,
+            _ => bail!(
+                "unknown event kind '{}', expected one of list, delist, buy, bid, cancel_bid, mint",
+                s
+            ),
+        })
+    }
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+enum JsonPathSegment {
+    Key(String),
+    Index(usize),
+}
+
+/// A dot separated path into event data, ex: "token_id.token_data_id.creator" or "tokens.0.price"
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct JsonPath {
+    segments: Vec<JsonPathSegment>,
+}
+
+impl FromStr for JsonPath {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        let segments = s
+            .split('.')
+            .map(|segment| {
+                ensure!(!segment.is_empty(), "empty segment in path '{}'", s);
+                Ok(match segment.parse::<usize>() {
+                    Ok(index) => JsonPathSegment::Index(index),
+                    Err(_) => JsonPathSegment::Key(segment.to_string()),
+                })
+            })
+            .collect::<Result<Vec<_>>>()?;
+        Ok(Self { segments })
+    }
+}
+
+impl fmt::Display for JsonPath {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let segments: Vec<String> = self
+            .segments
+            .iter()
+            .map(|segment| match segment {
+                JsonPathSegment::Key(key) => key.clone(),
+                JsonPathSegment::Index(index) => index.to_string(),
+            })
+            .collect();
+        write!(f, "{}", segments.join("."))
+    }
+}
+
+impl JsonPath {
+    pub fn extract<'a>(&self, data: &'a serde_json::Value) -> Option<&'a serde_json::Value> {
+        self.segments
+            .iter()
+            .try_fold(data, |value, segment| match segment {
+                JsonPathSegment::Key(key) => value.get(key),
+                JsonPathSegment::Index(index) => value.get(index),
+            })
+    }
+
+    /// Move values are serialized as strings (including u64s), but plain json numbers are
+    /// accepted as well
+    pub fn extract_string(&self, data: &serde_json::Value) -> Result<String> {
+        match self.extract(data) {
+            Some(serde_json::Value::String(value)) => Ok(value.clone()),
+            Some(serde_json::Value::Number(value)) => Ok(value.to_string()),
+            Some(value) => bail!("path '{}' is not a string or number: {}", self, value),
+            None => bail!("path '{}' not found", self),
+        }
+    }
+
+    pub fn extract_bigdecimal(&self, data: &serde_json::Value) -> Result<BigDecimal> {
+        let value = self.extract_string(data)?;
+        BigDecimal::from_str(&value)
+            .with_context(|| format!("path '{}' is not a number: {}", self, value))
+    }
+}
+
+/// A configured event resolved into the fields a token activity needs
+#[derive(Debug)]
+pub struct MappedMarketplaceEvent {
+    pub kind: EventKind,
+    pub token_data_id: TokenDataIdType,
+    pub property_version: BigDecimal,
+    pub from_address: Option<String>,
+    pub to_address: Option<String>,
+    pub token_amount: BigDecimal,
+    pub coin_type: Option<String>,
+    pub coin_amount: Option<BigDecimal>,
+    pub launchpad: Option<String>,
+}
+
+#[derive(Clone, Debug)]
+pub(super) struct CompiledMapping {
+    pub(super) kind: EventKind,
+    creator: JsonPath,
+    collection: JsonPath,
+    name: JsonPath,
+    property_version: Option<JsonPath>,
+    amount: Option<JsonPath>,
+    price: Option<JsonPath>,
+    coin_type: Option<JsonPath>,
+    buyer: Option<JsonPath>,
+    seller: Option<JsonPath>,
+    launchpad: Option<String>,
+}
+
+fn parse_optional_path(path: &Option<String>) -> Result<Option<JsonPath>> {
+    path.as_deref().map(JsonPath::from_str).transpose()
+}
+
+impl CompiledMapping {
+    pub(super) fn compile(mapping: &MarketplaceEventMapping) -> Result<Self> {
+        let compiled = Self {
+            kind: mapping.kind.parse()?,
+            creator: mapping.creator.parse()?,
+            collection: mapping.collection.parse()?,
+            name: mapping.name.parse()?,
+            property_version: parse_optional_path(&mapping.property_version)?,
+            amount: parse_optional_path(&mapping.amount)?,
+            price: parse_optional_path(&mapping.price)?,
+            coin_type: parse_optional_path(&mapping.coin_type)?,
+            buyer: parse_optional_path(&mapping.buyer)?,
+            seller: parse_optional_path(&mapping.seller)?,
+            launchpad: mapping.launchpad.clone(),
+        };
+        let (needs_buyer, needs_seller, needs_price) = match compiled.kind {
+            EventKind::List => (false, true, true),
+            EventKind::Delist => (false, true, false),
+            EventKind::Buy => (true, true, true),
+            EventKind::Bid | EventKind::CancelBid | EventKind::Mint => (true, false, true),
+        };
+        ensure!(
+            !needs_buyer || compiled.buyer.is_some(),
+            "{:?} events need a buyer path",
+            compiled.kind
+        );
+        ensure!(
+            !needs_seller || compiled.seller.is_some(),
+            "{:?} events need a seller path",
+            compiled.kind
+        );
+        ensure!(
+            !needs_price || compiled.price.is_some(),
+            "{:?} events need a price path",
+            compiled.kind
+        );
+        ensure!(
+            (compiled.kind == EventKind::Mint) == compiled.launchpad.is_some(),
+            "launchpad must be set for Mint events, and only for them"
+        );
+        Ok(compiled)
+    }
+
+    fn extract_optional_string(
+        path: &Option<JsonPath>,
+        data: &serde_json::Value,
+    ) -> Result<Option<String>> {
+        path.as_ref()
+            .map(|path| path.extract_string(data))
+            .transpose()
+    }
+
+    pub(super) fn apply(&self, data: &serde_json::Value) -> Result<MappedMarketplaceEvent> {
+        let buyer = Self::extract_optional_string(&self.buyer, data)?
+            .map(|buyer| standardize_address(&buyer));
+        let seller = Self::extract_optional_string(&self.seller, data)?
+            .map(|seller| standardize_address(&seller));
+        let (from_address, to_address) = match self.kind {
+            EventKind::List | EventKind::Delist => (seller, None),
+            EventKind::Buy => (seller, buyer),
+            EventKind::Bid | EventKind::CancelBid => (buyer, None),
+            EventKind::Mint => (None, buyer),
+        };
+        Ok(MappedMarketplaceEvent {
+            kind: self.kind,
+            token_data_id: TokenDataIdType {
+                creator: standardize_address(&self.creator.extract_string(data)?),
+                collection: self.collection.extract_string(data)?,
+                name: self.name.extract_string(data)?,
+            },
+            property_version: match &self.property_version {
+                Some(path) => path.extract_bigdecimal(data)?,
+                None => BigDecimal::zero(),
+            },
+            from_address,
+            to_address,
+            token_amount: match &self.amount {
+                Some(path) => path.extract_bigdecimal(data)?,
+                None => BigDecimal::one(),
+            },
+            coin_type: Self::extract_optional_string(&self.coin_type, data)?,
+            coin_amount: self
+                .price
+                .as_ref()
+                .map(|path| path.extract_bigdecimal(data))
+                .transpose()?,
+            launchpad: self.launchpad.clone(),
+        })
+    }
+}
+
+/// A configured listing struct resolved into the fields a listing needs
+#[derive(Debug)]
+pub struct MappedMarketplaceListing {
+    pub market_address: String,
+    pub token_data_id: TokenDataIdType,
+    pub property_version: BigDecimal,
+    pub seller: String,
+    pub amount: BigDecimal,
+    pub price: BigDecimal,
+}
+
+/// A deleted listing table key, resolved into the token that's no longer listed
+#[derive(Debug)]
+pub struct MappedMarketplaceDelisting {
+    pub market_address: String,
+    pub token_id: TokenIdType,
+}
+
+#[derive(Clone, Debug)]
+struct CompiledListingMapping {
+    market_address: String,
+    creator: JsonPath,
+    collection: JsonPath,
+    name: JsonPath,
+    property_version: Option<JsonPath>,
+    amount: Option<JsonPath>,
+    price: JsonPath,
+    seller: JsonPath,
+    key_token_id: Option<JsonPath>,
+}
+
+impl CompiledListingMapping {
+    fn compile(mapping: &MarketplaceListingMapping) -> Result<Self> {
+        ensure!(
+            mapping.key_type.is_some() || mapping.key_token_id.is_none(),
+            "key_token_id is only used with key_type"
+        );
+        Ok(Self {
+            market_address: mapping.market_address.clone(),
+            creator: mapping.creator.parse()?,
+            collection: mapping.collection.parse()?,
+            name: mapping.name.parse()?,
+            property_version: parse_optional_path(&mapping.property_version)?,
+            amount: parse_optional_path(&mapping.amount)?,
+            price: mapping.price.parse()?,
+            seller: mapping.seller.parse()?,
+            key_token_id: parse_optional_path(&mapping.key_token_id)?,
+        })
+    }
+
+    fn apply(&self, data: &serde_json::Value) -> Result<MappedMarketplaceListing> {
+        Ok(MappedMarketplaceListing {
+            market_address: self.market_address.clone(),
+            token_data_id: TokenDataIdType {
+                creator: standardize_address(&self.creator.extract_string(data)?),
+                collection: self.collection.extract_string(data)?,
+                name: self.name.extract_string(data)?,
+            },
+            property_version: match &self.property_version {
+                Some(path) => path.extract_bigdecimal(data)?,
+                None => BigDecimal::zero(),
+            },
+            seller: standardize_address(&self.seller.extract_string(data)?),
+            amount: match &self.amount {
+                Some(path) => path.extract_bigdecimal(data)?,
+                None => BigDecimal::one(),
+            },
+            price: self.price.extract_bigdecimal(data)?,
+        })
+    }
+
+    fn apply_to_key(&self, key: &serde_json::Value) -> Result<MappedMarketplaceDelisting> {
+        let token_id = match &self.key_token_id {
+            Some(path) => path
+                .extract(key)
+                .with_context(|| format!("path '{}' not found", path))?,
+            None => key,
+        };
+        Ok(MappedMarketplaceDelisting {
+            market_address: self.market_address.clone(),
+            token_id: serde_json::from_value(token_id.clone())
+                .context("table key doesn't contain a token id")?,
+        })
+    }
+}
+
+/// All configured marketplace events, keyed by event type, and listing structs, keyed by type
+#[derive(Clone, Debug, Default)]
+pub struct MarketplaceEventMappings {
+    mappings: HashMap<String, CompiledMapping>,
+    /// Event type to the typed parser it's registered with
+    typed_events: HashMap<String, MarketplaceEventParser>,
+    listing_mappings: HashMap<String, CompiledListingMapping>,
+    /// Listing table key type to listing type
+    listing_key_types: HashMap<String, String>,
+    /// (standardized module address, "module::function") of entry functions that buy a token
+    payload_functions: HashSet<(String, String)>,
+    /// Standardized addresses holding listed tokens in escrow
+    escrow_addresses: HashSet<String>,
+    /// Built in marketplaces whose events are parsed, all of them unless the config says otherwise
+    adapters: MarketplaceAdapters,
+}
+
+pub(super) fn is_hex_address(address: &str) -> bool {
+    address.strip_prefix("0x").map_or(false, |hex| {
+        !hex.is_empty() && hex.len() <= 64 && hex.chars().all(|c| c.is_ascii_hexdigit())
+    })
+}
+
+impl MarketplaceEventMappings {
+    /// Validates the config, failing on bad paths, unknown kinds, missing fields required by the
+    /// kind, or duplicate event types
+    pub fn from_config(mappings: &[MarketplaceEventMapping]) -> Result<Self> {
+        let mut compiled_mappings = HashMap::new();
+        for mapping in mappings {
+            let compiled = CompiledMapping::compile(mapping).with_context(|| {
+                format!(
+                    "invalid marketplace event mapping for {}",
+                    mapping.event_type
+                )
+            })?;
+            ensure!(
+                compiled_mappings
+                    .insert(mapping.event_type.clone(), compiled)
+                    .is_none(),
+                "duplicate marketplace event mapping for {}",
+                mapping.event_type
+            );
+        }
+        Ok(Self {
+            mappings: compiled_mappings,
+            ..Self::default()
+        })
+    }
+
+    /// Registers event types with a typed parser, failing on unknown parsers, or event types that
+    /// are registered twice or also mapped
+    pub fn with_typed_event_mappings(
+        mut self,
+        mappings: &[MarketplaceTypedEventMapping],
+    ) -> Result<Self> {
+        for mapping in mappings {
+            let parser = mapping.parser.parse().with_context(|| {
+                format!(
+                    "invalid marketplace typed event mapping for {}",
+                    mapping.event_type
+                )
+            })?;
+            ensure!(
+                !self.mappings.contains_key(&mapping.event_type),
+                "{} has both a marketplace event mapping and a typed event mapping",
+                mapping.event_type
+            );
+            ensure!(
+                self.typed_events
+                    .insert(mapping.event_type.clone(), parser)
+                    .is_none(),
+                "duplicate marketplace typed event mapping for {}",
+                mapping.event_type
+            );
+        }
+        Ok(self)
+    }
+
+    /// Limits the built in marketplaces to the named ones, failing on unknown names
+    pub fn with_adapters(mut self, names: &[String]) -> Result<Self> {
+        self.adapters = self.adapters.with_builtin(names)?;
+        Ok(self)
+    }
+
+    /// Adds marketplaces defined outside the config, failing on invalid definitions, or event
+    /// types a definition shares with the config's mappings
+    pub fn with_definitions(mut self, definitions: &[MarketplaceDefinition]) -> Result<Self> {
+        for definition in definitions {
+            for mapping in &definition.events {
+                ensure!(
+                    !self.mappings.contains_key(&mapping.event_type)
+                        && !self.typed_events.contains_key(&mapping.event_type),
+                    "{} is mapped by both marketplace {} and the config",
+                    mapping.event_type,
+                    definition.name
+                );
+            }
+        }
+        self.adapters = self.adapters.with_definitions(definitions)?;
+        Ok(self)
+    }
+
+    /// with_definitions, with the definitions read from a YAML or JSON file
+    pub fn with_definitions_file(self, path: &Path) -> Result<Self> {
+        let definitions = read_definitions(path)?;
+        self.with_definitions(&definitions)
+            .with_context(|| format!("invalid marketplace definitions in {}", path.display()))
+    }
+
+    /// Parses an event of a built in marketplace, or of a type registered with a typed parser
+    pub fn marketplace_event(
+        &self,
+        data_type: &str,
+        data: &serde_json::Value,
+        txn_version: i64,
+    ) -> Result<Option<MarketplaceEvent>> {
+        match self.adapters.try_parse_event(data_type, data, txn_version) {
+            Ok(None) => self.typed_event(data_type, data, txn_version),
+            parsed => parsed,
+        }
+    }
+
+    /// Parses an event of a type registered with a typed parser
+    pub fn typed_event(
+        &self,
+        data_type: &str,
+        data: &serde_json::Value,
+        txn_version: i64,
+    ) -> Result<Option<MarketplaceEvent>> {
+        match self.typed_events.get(data_type) {
+            Some(parser) => TokenEvent::from_parser(*parser, data_type, data, txn_version)
+                .map(|token_event| Some(souffl3::typed_event(token_event))),
+            None => Ok(None),
+        }
+    }
+
+    /// What a configured event type means, whether it's mapped or registered with a typed parser
+    pub fn event_kind(&self, event_type: &str) -> Option<EventKind> {
+        if let Some(mapping) = self.mappings.get(event_type) {
+            return Some(mapping.kind);
+        }
+        self.typed_events
+            .get(event_type)
+            .map(|parser| match parser {
+                MarketplaceEventParser::Souffl3V2BuyToken
+                | MarketplaceEventParser::Souffl3SweepBuy => EventKind::Buy,
+                MarketplaceEventParser::Souffl3V2CancelListToken => EventKind::Delist,
+                MarketplaceEventParser::Souffl3V2ListToken => EventKind::List,
+            })
+            .or_else(|| self.adapters.declarative_event_kind(event_type))
+    }
+
+    /// Adds the listing structs to detect in the write set, failing on bad paths or duplicate
+    /// listing or key types
+    pub fn with_listing_mappings(mut self, mappings: &[MarketplaceListingMapping]) -> Result<Self> {
+        for mapping in mappings {
+            let compiled = CompiledListingMapping::compile(mapping).with_context(|| {
+                format!(
+                    "invalid marketplace listing mapping for {}",
+                    mapping.listing_type
+                )
+            })?;
+            ensure!(
+                self.listing_mappings
+                    .insert(mapping.listing_type.clone(), compiled)
+                    .is_none(),
+                "duplicate marketplace listing mapping for {}",
+                mapping.listing_type
+            );
+            if let Some(key_type) = &mapping.key_type {
+                ensure!(
+                    self.listing_key_types
+                        .insert(key_type.clone(), mapping.listing_type.clone())
+                        .is_none(),
+                    "duplicate marketplace listing key type {}",
+                    key_type
+                );
+            }
+        }
+        Ok(self)
+    }
+
+    /// Adds the entry functions to infer sales from, failing on malformed addresses or function
+    /// names, or duplicate functions
+    pub fn with_payload_mappings(mut self, mappings: &[MarketplacePayloadMapping]) -> Result<Self> {
+        for mapping in mappings {
+            ensure!(
+                is_hex_address(&mapping.module_address),
+                "invalid module address '{}' in marketplace payload mapping",
+                mapping.module_address
+            );
+            let parts: Vec<&str> = mapping.function_name.split("::").collect();
+            ensure!(
+                parts.len() == 2 && parts.iter().all(|part| !part.is_empty()),
+                "function name '{}' is not of the form module::function",
+                mapping.function_name
+            );
+            ensure!(
+                self.payload_functions.insert((
+                    standardize_address(&mapping.module_address),
+                    mapping.function_name.clone()
+                )),
+                "duplicate marketplace payload mapping for {}::{}",
+                mapping.module_address,
+                mapping.function_name
+            );
+        }
+        Ok(self)
+    }
+
+    /// Adds the addresses marketplaces keep listed tokens in, failing on malformed or duplicate
+    /// addresses
+    pub fn with_escrow_addresses(mut self, addresses: &[String]) -> Result<Self> {
+        for address in addresses {
+            ensure!(
+                is_hex_address(address),
+                "invalid marketplace escrow address '{}'",
+                address
+            );
+            ensure!(
+                self.escrow_addresses.insert(standardize_address(address)),
+                "duplicate marketplace escrow address {}",
+                address
+            );
+        }
+        Ok(self)
+    }
+
+    /// Expects a standardized address
+    pub fn is_escrow_address(&self, address: &str) -> bool {
+        self.escrow_addresses.contains(address)
+    }
+
+    /// Standardized address of the marketplace whose configured entry function the payload
+    /// calls, if any
+    pub fn market_from_payload(&self, payload: &TransactionPayload) -> Option<String> {
+        let function = match payload {
+            TransactionPayload::EntryFunctionPayload(payload) => &payload.function,
+            _ => return None,
+        };
+        let key = (
+            standardize_address(&function.module.address.to_string()),
+            format!("{}::{}", function.module.name, function.name),
+        );
+        self.payload_functions.contains(&key).then(|| key.0)
+    }
+
+    /// Maps a written resource or table item value of a configured listing type, or of a listing
+    /// type a built in marketplace parses
+    pub fn listing_from_data(
+        &self,
+        data_type: &str,
+        data: &serde_json::Value,
+        txn_version: i64,
+    ) -> Result<Option<MappedMarketplaceListing>> {
+        match self.listing_mappings.get(data_type) {
+            Some(mapping) => mapping.apply(data).map(Some).context(format!(
+                "version {} failed! failed to parse listing type {}, data {:?}",
+                txn_version, data_type, data
+            )),
+            None => self
+                .adapters
+                .try_parse_write_change(data_type, data, txn_version),
+        }
+    }
+
+    /// Maps a deleted table key of a configured listing key type
+    pub fn delisting_from_key(
+        &self,
+        key_type: &str,
+        key: &serde_json::Value,
+        txn_version: i64,
+    ) -> Result<Option<MappedMarketplaceDelisting>> {
+        match self
+            .listing_key_types
+            .get(key_type)
+            .and_then(|listing_type| self.listing_mappings.get(listing_type))
+        {
+            Some(mapping) => mapping.apply_to_key(key).map(Some).context(format!(
+                "version {} failed! failed to parse listing key type {}, key {:?}",
+                txn_version, key_type, key
+            )),
+            None => Ok(None),
+        }
+    }
+
+    /// Maps an event of a configured type, or of a type a marketplace definition maps
+    pub fn from_event(
+        &self,
+        data_type: &str,
+        data: &serde_json::Value,
+        txn_version: i64,
+    ) -> Result<Option<MappedMarketplaceEvent>> {
+        match self.mappings.get(data_type) {
+            Some(mapping) => mapping.apply(data).map(Some).context(format!(
+                "version {} failed! failed to parse type {}, data {:?}",
+                txn_version, data_type, data
+            )),
+            None => self
+                .adapters
+                .try_parse_declarative_event(data_type, data, txn_version),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::models::token_models::{
+        collection_volume::CurrentCollectionVolume, event_effects::TokenEventEffects,
+        marketplace_listings::CurrentMarketplaceListing, nft_sales::NftSale,
+        token_activities::TokenActivity, token_utils::TokenEvents,
+    };
+    use serde_json::json;
+
+    const FAKE_BUY_EVENT: &str = "0xfa4e::market::PurchaseEvent";
+
+    fn fake_buy_mapping() -> MarketplaceEventMapping {
+        serde_json::from_value(json!({
+            "event_type": FAKE_BUY_EVENT,
+            "kind": "buy",
+            "creator": "token.creator",
+            "collection": "token.collection",
+            "name": "token.name",
+            "price": "cost",
+            "buyer": "parties.1",
+            "seller": "parties.0"
+        }))
+        .unwrap()
+    }
+
+    #[test]
+    fn test_json_path() {
+        let data = json!({"a": {"b": [{"c": "1"}, {"c": 2}]}});
+        let path: JsonPath = "a.b.1.c".parse().unwrap();
+        assert_eq!(path.extract_string(&data).unwrap(), "2");
+        assert_eq!(path.to_string(), "a.b.1.c");
+        assert!("a..c".parse::<JsonPath>().is_err());
+        let missing: JsonPath = "a.b.5.c".parse().unwrap();
+        assert!(missing.extract_string(&data).is_err());
+        let not_a_number: JsonPath = "a".parse().unwrap();
+        assert!(not_a_number.extract_bigdecimal(&data).is_err());
+    }
+
+    #[test]
+    fn test_map_buy_event() {
+        let mappings = MarketplaceEventMappings::from_config(&[fake_buy_mapping()]).unwrap();
+        let data = json!({
+            "token": {"creator": "0xc4e7", "collection": "Fakes", "name": "Fake #1"},
+            "cost": "1000",
+            "parties": ["0xa11ce", "0xb0b"]
+        });
+        let event = mappings
+            .from_event(FAKE_BUY_EVENT, &data, 1)
+            .unwrap()
+            .unwrap();
+        // Addresses are padded, like the ones deserialized from framework events
+        assert_eq!(
+            event.token_data_id.to_string(),
+            format!("{}::Fakes::Fake #1", standardize_address("0xc4e7"))
+        );
+        assert_eq!(event.from_address, Some(standardize_address("0xa11ce")));
+        assert_eq!(event.to_address, Some(standardize_address("0xb0b")));
+        assert_eq!(event.coin_amount, Some(BigDecimal::from(1000)));
+        assert_eq!(event.token_amount, BigDecimal::one());
+        assert_eq!(event.property_version, BigDecimal::zero());
+
+        assert!(mappings
+            .from_event("0xfa4e::market::ListEvent", &data, 1)
+            .unwrap()
+            .is_none());
+        assert!(mappings
+            .from_event(FAKE_BUY_EVENT, &json!({"cost": "1"}), 1)
+            .is_err());
+    }
+
+    #[test]
+    fn test_invalid_config() {
+        let mut unknown_kind = fake_buy_mapping();
+        unknown_kind.kind = "sweep".to_string();
+        assert!(MarketplaceEventMappings::from_config(&[unknown_kind]).is_err());
+
+        let mut missing_buyer = fake_buy_mapping();
+        missing_buyer.buyer = None;
+        assert!(MarketplaceEventMappings::from_config(&[missing_buyer]).is_err());
+
+        let mut mint_without_launchpad = fake_buy_mapping();
+        mint_without_launchpad.kind = "mint".to_string();
+        assert!(MarketplaceEventMappings::from_config(&[mint_without_launchpad]).is_err());
+        let mut buy_with_launchpad = fake_buy_mapping();
+        buy_with_launchpad.launchpad = Some("fakepad".to_string());
+        assert!(MarketplaceEventMappings::from_config(&[buy_with_launchpad]).is_err());
+
+        assert!(
+            MarketplaceEventMappings::from_config(&[fake_buy_mapping(), fake_buy_mapping()])
+                .is_err()
+        );
+    }
+
+    #[test]
+    fn test_payload_mappings() {
+        let mapping = |module_address: &str, function_name: &str| MarketplacePayloadMapping {
+            module_address: module_address.to_string(),
+            function_name: function_name.to_string(),
+        };
+        let mappings = MarketplaceEventMappings::default()
+            .with_payload_mappings(&[mapping("0xfa4e", "market::buy_token")])
+            .unwrap();
+        let payload = |function: &str| -> TransactionPayload {
+            serde_json::from_value(json!({
+                "type": "entry_function_payload",
+                "function": function,
+                "type_arguments": [],
+                "arguments": []
+            }))
+            .unwrap()
+        };
+        // Matched on the standardized address, so the padded form works as well
+        let padded = format!("{}::market::buy_token", standardize_address("0xfa4e"));
+        assert_eq!(
+            mappings.market_from_payload(&payload(&padded)),
+            Some(standardize_address("0xfa4e"))
+        );
+        assert_eq!(
+            mappings.market_from_payload(&payload("0xfa4e::market::buy_token")),
+            Some(standardize_address("0xfa4e"))
+        );
+        assert_eq!(
+            mappings.market_from_payload(&payload("0xfa4e::market::list_token")),
+            None
+        );
+
+        for (module_address, function_name) in [
+            ("fa4e", "market::buy_token"),
+            ("0xfa4e", "buy_token"),
+            ("0xfa4e", "market::"),
+        ] {
+            assert!(MarketplaceEventMappings::default()
+                .with_payload_mappings(&[mapping(module_address, function_name)])
+                .is_err());
+        }
+        assert!(MarketplaceEventMappings::default()
+            .with_payload_mappings(&[
+                mapping("0xfa4e", "market::buy_token"),
+                mapping(&standardize_address("0xfa4e"), "market::buy_token"),
+            ])
+            .is_err());
+    }
+
+    /// A transaction with one event_type event per cost, each about its own token
+    fn fake_transaction(event_type: &str, costs: &[&str]) -> aptos_api_types::Transaction {
+        let hash = "0x6a527d06063dfd42c6b3a862574d5f3ec1660afb8058135edda5072712bfdb51";
+        let events = costs
+            .iter()
+            .enumerate()
+            .map(|(index, cost)| {
+                json!({
+                    "guid": {"creation_number": "2", "account_address": "0xfa4e"},
+                    "sequence_number": index.to_string(),
+                    "type": event_type,
+                    "data": {
+                        "token": {
+                            "creator": "0xc4e7",
+                            "collection": "Fakes",
+                            "name": format!("Fake #{}", index + 1)
+                        },
+                        "cost": cost,
+                        "parties": ["0xa11ce", "0xb0b"]
+                    }
+                })
+            })
+            .collect::<Vec<_>>();
+        serde_json::from_value(json!({
+            "type": "user_transaction",
+            "version": "10",
+            "hash": hash,
+            "state_change_hash": hash,
+            "event_root_hash": hash,
+            "state_checkpoint_hash": null,
+            "gas_used": "100",
+            "success": true,
+            "vm_status": "Executed successfully",
+            "accumulator_root_hash": hash,
+            "changes": [],
+            "sender": "0xb0b",
+            "sequence_number": "0",
+            "max_gas_amount": "2000",
+            "gas_unit_price": "100",
+            "expiration_timestamp_secs": "1668000600",
+            "payload": {
+                "type": "entry_function_payload",
+                "function": "0xfa4e::market::purchase",
+                "type_arguments": [],
+                "arguments": []
+            },
+            "events": events,
+            "timestamp": "1668000000000000"
+        }))
+        .unwrap()
+    }
+
+    #[test]
+    fn test_onboard_marketplace_via_config() {
+        let mappings = MarketplaceEventMappings::from_config(&[fake_buy_mapping()]).unwrap();
+        let transaction = fake_transaction(FAKE_BUY_EVENT, &["1000"]);
+
+        let token_events = TokenEvents::from_transaction(&transaction).unwrap();
+        let activities = TokenActivity::from_transaction(&transaction, &token_events, &mappings);
+        assert_eq!(activities.len(), 1);
+        let activity = &activities[0];
+        assert_eq!(activity.transfer_type, FAKE_BUY_EVENT);
+        assert_eq!(activity.collection_name, "Fakes");
+        assert_eq!(activity.from_address, Some(standardize_address("0xa11ce")));
+        assert_eq!(activity.to_address, Some(standardize_address("0xb0b")));
+        assert_eq!(activity.coin_amount, Some(BigDecimal::from(1000)));
+
+        // Without the mapping the event is ignored
+        assert!(TokenActivity::from_transaction(
+            &transaction,
+            &token_events,
+            &MarketplaceEventMappings::default()
+        )
+        .is_empty());
+    }
+
+    #[test]
+    fn test_suspect_values_are_recorded_but_not_aggregated() {
+        let event_type = "0xfa4e::market::BuyEvent";
+        let mut mapping = fake_buy_mapping();
+        mapping.event_type = event_type.to_string();
+        let mappings = MarketplaceEventMappings::from_config(&[mapping]).unwrap();
+        let u128_max = u128::MAX.to_string();
+        let transaction = fake_transaction(event_type, &["1000", u128_max.as_str(), "-1"]);
+        let token_events = TokenEvents::from_transaction(&transaction).unwrap();
+        let effects = TokenEventEffects::from_transaction(&transaction, &token_events, &mappings);
+
+        // Every sale is recorded, flagged when its price is out of bounds
+        let activities = TokenActivity::from_effects(&effects);
+        let nft_sales = NftSale::from_token_activities(&transaction, &activities, None);
+        assert_eq!(
+            activities
+                .iter()
+                .map(|activity| activity.suspect_value)
+                .collect::<Vec<_>>(),
+            vec![false, true, true]
+        );
+        assert_eq!(
+            nft_sales
+                .iter()
+                .map(|sale| sale.suspect_value)
+                .collect::<Vec<_>>(),
+            vec![false, true, true]
+        );
+
+        // Only the first counts towards volume
+        let (current_collection_volumes, collection_volumes, current_token_volumes, _) =
+            CurrentCollectionVolume::from_effects(&effects, &nft_sales);
+        assert_eq!(collection_volumes.len(), 1);
+        assert_eq!(current_token_volumes.len(), 1);
+        assert_eq!(
+            current_collection_volumes.values().next().unwrap().volume,
+            BigDecimal::from(1000)
+        );
+
+        // A suspect sale still ends the token's listing
+        let listings =
+            CurrentMarketplaceListing::from_transaction(&transaction, &effects, &mappings);
+        assert_eq!(listings.len(), 3);
+        assert!(listings.values().all(|listing| listing.is_deleted));
+    }
+
+    #[test]
+    fn test_listings_from_write_set() {
+        let listing_mapping: MarketplaceListingMapping = serde_json::from_value(json!({
+            "market_address": "0xfa4e",
+            "listing_type": "0xfa4e::market::Listing",
+            "creator": "token_id.token_data_id.creator",
+            "collection": "token_id.token_data_id.collection",
+            "name": "token_id.token_data_id.name",
+            "property_version": "token_id.property_version",
+            "price": "price",
+            "seller": "seller",
+            "key_type": "0x3::token::TokenId"
+        }))
+        .unwrap();
+        let mappings = MarketplaceEventMappings::default()
+            .with_listing_mappings(&[listing_mapping.clone()])
+            .unwrap();
+        assert!(MarketplaceEventMappings::default()
+            .with_listing_mappings(&[listing_mapping.clone(), listing_mapping])
+            .is_err());
+
+        let token_id = |name: &str| {
+            json!({
+                "token_data_id": {"creator": "0xc4e7", "collection": "Fakes", "name": name},
+                "property_version": "0"
+            })
+        };
+        let hash = "0x6a527d06063dfd42c6b3a862574d5f3ec1660afb8058135edda5072712bfdb51";
+        let transaction: aptos_api_types::Transaction = serde_json::from_value(json!({
+            "type": "user_transaction",
+            "version": "10",
+            "hash": hash,
+            "state_change_hash": hash,
+            "event_root_hash": hash,
+            "state_checkpoint_hash": null,
+            "gas_used": "100",
+            "success": true,
+            "vm_status": "Executed successfully",
+            "accumulator_root_hash": hash,
+            "changes": [
+                {
+                    "type": "write_table_item",
+                    "state_key_hash": hash,
+                    "handle": "0x1a57",
+                    "key": "0x00",
+                    "value": "0x00",
+                    "data": {
+                        "key": token_id("Fake #1"),
+                        "key_type": "0x3::token::TokenId",
+                        "value": {"token_id": token_id("Fake #1"), "price": "700", "seller": "0xa11ce"},
+                        "value_type": "0xfa4e::market::Listing<0x1::aptos_coin::AptosCoin>"
+                    }
+                },
+                {
+                    "type": "delete_table_item",
+                    "state_key_hash": hash,
+                    "handle": "0x1a57",
+                    "key": "0x01",
+                    "data": {"key": token_id("Fake #2"), "key_type": "0x3::token::TokenId"}
+                }
+            ],
+            "sender": "0xa11ce",
+            "sequence_number": "0",
+            "max_gas_amount": "2000",
+            "gas_unit_price": "100",
+            "expiration_timestamp_secs": "1668000600",
+            "payload": {
+                "type": "entry_function_payload",
+                "function": "0xfa4e::market::relist",
+                "type_arguments": [],
+                "arguments": []
+            },
+            "events": [],
+            "timestamp": "1668000000000000"
+        }))
+        .unwrap();
+
+        let listings = CurrentMarketplaceListing::from_transaction(&transaction, &[], &mappings);
+        assert_eq!(listings.len(), 2);
+        let listed = listings
+            .values()
+            .find(|listing| listing.name == "Fake #1")
+            .unwrap();
+        assert_eq!(listed.market_address, "0xfa4e");
+        assert_eq!(listed.seller, standardize_address("0xa11ce"));
+        assert_eq!(listed.price, BigDecimal::from(700));
+        assert_eq!(listed.event_type, "0xfa4e::market::Listing");
+        let delisted = listings
+            .values()
+            .find(|listing| listing.name == "Fake #2")
+            .unwrap();
+        assert_eq!(delisted.market_address, "");
+
+        // Without the mapping the write set is ignored
+        assert!(CurrentMarketplaceListing::from_transaction(
+            &transaction,
+            &[],
+            &MarketplaceEventMappings::default()
+        )
+        .is_empty());
+    }
+
+    #[test]
+    fn test_sweep_via_typed_mapping() {
+        const SWEEP_EVENT: &str = "0xf699::Sweep::SweepBuyEvent";
+        let typed_mapping = |parser: &str| MarketplaceTypedEventMapping {
+            event_type: SWEEP_EVENT.to_string(),
+            parser: parser.to_string(),
+        };
+        let mappings = MarketplaceEventMappings::default()
+            .with_typed_event_mappings(&[typed_mapping("souffl3_sweep_buy")])
+            .unwrap();
+        assert!(MarketplaceEventMappings::default()
+            .with_typed_event_mappings(&[typed_mapping("souffl3_sweep")])
+            .is_err());
+
+        let token_id = |name: &str| {
+            json!({
+                "token_data_id": {"creator": "0xc4e7", "collection": "Fakes", "name": name},
+                "property_version": "0"
+            })
+        };
+        let hash = "0x6a527d06063dfd42c6b3a862574d5f3ec1660afb8058135edda5072712bfdb51";
+        let transaction: aptos_api_types::Transaction = serde_json::from_value(json!({
+            "type": "user_transaction",
+            "version": "10",
+            "hash": hash,
+            "state_change_hash": hash,
+            "event_root_hash": hash,
+            "state_checkpoint_hash": null,
+            "gas_used": "100",
+            "success": true,
+            "vm_status": "Executed successfully",
+            "accumulator_root_hash": hash,
+            "changes": [],
+            "sender": "0xb0b",
+            "sequence_number": "0",
+            "max_gas_amount": "2000",
+            "gas_unit_price": "100",
+            "expiration_timestamp_secs": "1668000600",
+            "payload": {
+                "type": "entry_function_payload",
+                "function": "0xf699::Sweep::batch_buy_script",
+                "type_arguments": [],
+                "arguments": []
+            },
+            "events": [{
+                "guid": {"creation_number": "4", "account_address": "0xf699"},
+                "sequence_number": "0",
+                "type": SWEEP_EVENT,
+                "data": {
+                    "buyer": "0xb0b",
+                    "token_ids": [token_id("Fake #1"), token_id("Fake #2")],
+                    "sellers": ["0xa11ce", "0xca201"],
+                    "coin_per_tokens": ["700", "900"],
+                    "coin_type_info": {
+                        "account_address": "0x1",
+                        "module_name": "0x6170746f735f636f696e",
+                        "struct_name": "0x4170746f73436f696e"
+                    }
+                }
+            }],
+            "timestamp": "1668000000000000"
+        }))
+        .unwrap();
+
+        // One row per swept token, told apart by token_index
+        let token_events =
+            TokenEvents::from_transaction_with_mappings(&transaction, &mappings).unwrap();
+        let activities = TokenActivity::from_transaction(&transaction, &token_events, &mappings);
+        let swept = activities
+            .iter()
+            .map(|activity| {
+                (
+                    activity.token_index,
+                    activity.name.as_str(),
+                    activity.from_address.clone().unwrap(),
+                    activity.coin_amount.clone().unwrap(),
+                )
+            })
+            .collect::<Vec<_>>();
+        assert_eq!(
+            swept,
+            vec![
+                (
+                    0,
+                    "Fake #1",
+                    standardize_address("0xa11ce"),
+                    BigDecimal::from(700)
+                ),
+                (
+                    1,
+                    "Fake #2",
+                    standardize_address("0xca201"),
+                    BigDecimal::from(900)
+                ),
+            ]
+        );
+
+        let sales = NftSale::from_token_activities(&transaction, &activities, None);
+        assert_eq!(
+            sales
+                .iter()
+                .map(|sale| sale.token_index)
+                .collect::<Vec<_>>(),
+            vec![0, 1]
+        );
+        let (current_collection_volumes, collection_volumes, _, token_volumes) =
+            CurrentCollectionVolume::from_effects(
+                &TokenEventEffects::from_transaction(&transaction, &token_events, &mappings),
+                &sales,
+            );
+        assert_eq!(collection_volumes.len(), 2);
+        assert_eq!(token_volumes.len(), 2);
+        assert_eq!(
+            CurrentCollectionVolume::from_collection_volumes(&collection_volumes)[0].volume,
+            BigDecimal::from(1600)
+        );
+        assert_eq!(current_collection_volumes.len(), 1);
+
+        // Without the mapping the sweep is ignored
+        let token_events = TokenEvents::from_transaction(&transaction).unwrap();
+        assert!(TokenActivity::from_transaction(
+            &transaction,
+            &token_events,
+            &MarketplaceEventMappings::default()
+        )
+        .is_empty());
+    }
+}
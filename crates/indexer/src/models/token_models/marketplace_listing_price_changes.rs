@@ -0,0 +1,288 @@
+// Copyright (c) Aptos
+// SPDX-License-Identifier: Apache-2.0
+
+//! Price history of listings. current_marketplace_listings only keeps the latest price, so every
+//! repricing of an active listing is also recorded here: price change events, and list events
+//! that relist a token already listed on the same marketplace at another price.
+
+// This is required because a diesel macro makes clippy sad
+#![allow(clippy::extra_unused_lifetimes)]
+#![allow(clippy::unused_unit)]
+
+use super::{
+    event_effects::{MarketEffect, TokenEventEffects},
+    marketplace_listings::CurrentMarketplaceListing,
+};
+use crate::{
+    database::PgPoolConnection,
+    schema::{current_marketplace_listings, marketplace_listing_price_changes},
+};
+use bigdecimal::{BigDecimal, Zero};
+use diesel::{ExpressionMethods, QueryDsl, QueryResult, RunQueryDsl};
+use field_count::FieldCount;
+use serde::{Deserialize, Serialize};
+use std::collections::{HashMap, HashSet};
+
+#[derive(Clone, Debug, Deserialize, FieldCount, Identifiable, Insertable, Serialize)]
+#[diesel(primary_key(transaction_version, event_index))]
+#[diesel(table_name = marketplace_listing_price_changes)]
+pub struct MarketplaceListingPriceChange {
+    pub transaction_version: i64,
+    pub event_index: i64,
+    pub token_data_id_hash: String,
+    pub property_version: BigDecimal,
+    pub collection_data_id_hash: String,
+    pub market_address: String,
+    pub seller: String,
+    /// None when the listing's earlier price isn't known, ex: listed before the indexer started
+    pub old_price: Option<BigDecimal>,
+    pub new_price: BigDecimal,
+    pub transaction_timestamp: chrono::NaiveDateTime,
+}
+
+/// Whether a change still needs the stored listing once the batch is parsed
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+enum Pending {
+    /// A ChangePriceEvent, recorded even without a stored listing
+    ChangePrice,
+    /// A list event, only a change if the token is already listed at another price
+    Relist,
+}
+
+/// Listing prices as of the transactions applied so far. Transactions need to be applied in
+/// version order.
+#[derive(Default)]
+pub struct ListingPriceChangeBook {
+    /// (market_address, price) of the latest listing of each token seen in the batch, None once
+    /// it's delisted or sold
+    listings: HashMap<String, Option<(String, BigDecimal)>>,
+    changes: Vec<(MarketplaceListingPriceChange, Option<Pending>)>,
+}
+
+impl ListingPriceChangeBook {
+    /// effects are a transaction's TokenEventEffects
+    pub fn apply_effects(&mut self, effects: &[TokenEventEffects]) {
+        for effects in effects {
+            let listing = match CurrentMarketplaceListing::from_effects(effects) {
+                Some(listing) => listing,
+                None => continue,
+            };
+            if listing.market_address.is_empty() || listing.amount.is_zero() {
+                self.listings.insert(listing.token_data_id_hash, None);
+                continue;
+            }
+            let is_change_price = effects.market_effect == Some(MarketEffect::Reprice);
+            let change = match self.listings.get(&listing.token_data_id_hash) {
+                Some(Some((market_address, price)))
+                    if market_address == &listing.market_address
+                        && (is_change_price || price != &listing.price) =>
+                {
+                    Some((Some(price.clone()), None))
+                }
+                Some(_) if is_change_price => Some((None, None)),
+                Some(_) => None,
+                None if is_change_price => Some((None, Some(Pending::ChangePrice))),
+                None => Some((None, Some(Pending::Relist))),
+            };
+            if let Some((old_price, pending)) = change {
+                self.changes.push((
+                    MarketplaceListingPriceChange {
+                        transaction_version: effects.transaction_version,
+                        event_index: effects.event_index,
+                        token_data_id_hash: listing.token_data_id_hash.clone(),
+                        property_version: listing.property_version,
+                        collection_data_id_hash: listing.collection_data_id_hash,
+                        market_address: listing.market_address.clone(),
+                        seller: listing.seller,
+                        old_price,
+                        new_price: listing.price.clone(),
+                        transaction_timestamp: effects.transaction_timestamp,
+                    },
+                    pending,
+                ));
+            }
+            self.listings.insert(
+                listing.token_data_id_hash,
+                Some((listing.market_address, listing.price)),
+            );
+        }
+    }
+
+    /// Resolves the first change of each token in the batch against its stored listing, and
+    /// returns the changes in version order. Has to run before the batch's listings are written.
+    pub fn into_rows(
+        self,
+        conn: &mut PgPoolConnection,
+    ) -> QueryResult<Vec<MarketplaceListingPriceChange>> {
+        let token_data_id_hashes = self
+            .changes
+            .iter()
+            .filter(|(_, pending)| pending.is_some())
+            .map(|(change, _)| change.token_data_id_hash.clone())
+            .collect::<HashSet<String>>();
+        let stored_prices = if token_data_id_hashes.is_empty() {
+            HashMap::new()
+        } else {
+            current_marketplace_listings::table
+                .filter(
+                    current_marketplace_listings::token_data_id_hash.eq_any(token_data_id_hashes),
+                )
+                .filter(current_marketplace_listings::invalidated_reason.is_null())
+                .filter(current_marketplace_listings::amount.gt(BigDecimal::zero()))
+                .select((
+                    current_marketplace_listings::token_data_id_hash,
+                    current_marketplace_listings::market_address,
+                    current_marketplace_listings::price,
+                ))
+                .load::<(String, String, BigDecimal)>(conn)?
+                .into_iter()
+                .map(|(token_data_id_hash, market_address, price)| {
+                    (token_data_id_hash, (market_address, price))
+                })
+                .collect::<HashMap<String, (String, BigDecimal)>>()
+        };
+        Ok(self
+            .changes
+            .into_iter()
+            .filter_map(|(mut change, pending)| {
+                let pending = match pending {
+                    Some(pending) => pending,
+                    None => return Some(change),
+                };
+                match stored_prices.get(&change.token_data_id_hash) {
+                    Some((market_address, price)) if market_address == &change.market_address => {
+                        if pending == Pending::Relist && price == &change.new_price {
+                            return None;
+                        }
+                        change.old_price = Some(price.clone());
+                        Some(change)
+                    }
+                    _ if pending == Pending::ChangePrice => Some(change),
+                    _ => None,
+                }
+            })
+            .collect())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::models::token_models::{
+        marketplace_event_mappings::MarketplaceEventMappings, token_utils::TokenEvents,
+    };
+    use crate::test_utils::token_id;
+    use aptos_api_types::Transaction as APITransaction;
+    use serde_json::json;
+
+    const HASH: &str = "0x6a527d06063dfd42c6b3a862574d5f3ec1660afb8058135edda5072712bfdb51";
+    const BLUEMOVE: &str = "0xd1fd99c1944b84d1670a2536417e997864ad12303d19eac725891691b04d614e";
+
+    fn bluemove_txn(version: u64, event_name: &str, data: serde_json::Value) -> APITransaction {
+        serde_json::from_value(json!({
+            "type": "user_transaction",
+            "version": version.to_string(),
+            "hash": HASH,
+            "state_change_hash": HASH,
+            "event_root_hash": HASH,
+            "state_checkpoint_hash": null,
+            "gas_used": "100",
+            "success": true,
+            "vm_status": "Executed successfully",
+            "accumulator_root_hash": HASH,
+            "changes": [],
+            "sender": "0xa11ce",
+            "sequence_number": "0",
+            "max_gas_amount": "2000",
+            "gas_unit_price": "100",
+            "expiration_timestamp_secs": "1668000600",
+            "payload": {
+                "type": "entry_function_payload",
+                "function": format!("{}::marketplaceV2::list", BLUEMOVE),
+                "type_arguments": [],
+                "arguments": []
+            },
+            "events": [{
+                "guid": {"creation_number": "5", "account_address": BLUEMOVE},
+                "sequence_number": version.to_string(),
+                "type": format!("{}::marketplaceV2::{}", BLUEMOVE, event_name),
+                "data": data
+            }],
+            "timestamp": "1668000000000000"
+        }))
+        .unwrap()
+    }
+
+    fn list_txn(version: u64, price: u64) -> APITransaction {
+        bluemove_txn(
+            version,
+            "ListEvent",
+            json!({
+                "id": token_id(),
+                "amount": price.to_string(),
+                "seller_address": "0xa11ce",
+                "royalty_payee": "0xc4e7",
+                "royalty_numerator": "5",
+                "royalty_denominator": "100"
+            }),
+        )
+    }
+
+    fn change_price_txn(version: u64, price: u64) -> APITransaction {
+        bluemove_txn(
+            version,
+            "ChangePriceEvent",
+            json!({
+                "id": token_id(),
+                "amount": price.to_string(),
+                "seller_address": "0xa11ce"
+            }),
+        )
+    }
+
+    fn apply(
+        transactions: &[APITransaction],
+    ) -> Vec<(MarketplaceListingPriceChange, Option<Pending>)> {
+        let mut book = ListingPriceChangeBook::default();
+        for txn in transactions {
+            book.apply_effects(&TokenEventEffects::from_transaction(
+                txn,
+                &TokenEvents::from_transaction(txn).unwrap(),
+                &MarketplaceEventMappings::default(),
+            ));
+        }
+        book.changes
+    }
+
+    #[test]
+    fn test_change_price_after_listing_in_batch() {
+        let changes = apply(&[list_txn(10, 1000), change_price_txn(11, 600)]);
+        // The listing itself is only pending a check against a stored listing
+        assert_eq!(changes.len(), 2);
+        assert_eq!(changes[0].1, Some(Pending::Relist));
+        assert_eq!(changes[1].1, None);
+        assert_eq!(changes[1].0.old_price, Some(BigDecimal::from(1000)));
+        assert_eq!(changes[1].0.new_price, BigDecimal::from(600));
+        assert_eq!(changes[1].0.market_address, BLUEMOVE);
+        assert_eq!(changes[1].0.transaction_version, 11);
+    }
+
+    #[test]
+    fn test_relist_at_another_price() {
+        let changes = apply(&[
+            list_txn(10, 1000),
+            // Same price, not a change
+            list_txn(11, 1000),
+            list_txn(12, 800),
+        ]);
+        assert_eq!(changes.len(), 2);
+        assert_eq!(changes[1].0.old_price, Some(BigDecimal::from(1000)));
+        assert_eq!(changes[1].0.new_price, BigDecimal::from(800));
+
+        // A first change price of the batch waits for the stored listing
+        let changes = apply(&[change_price_txn(10, 600)]);
+        assert_eq!(changes.len(), 1);
+        assert_eq!(changes[0].0.old_price, None);
+        assert_eq!(changes[0].1, Some(Pending::ChangePrice));
+    }
+}
@@ -5,23 +5,39 @@
 #![allow(clippy::extra_unused_lifetimes)]
 #![allow(clippy::unused_unit)]
 
-use std::collections::HashMap;
+use std::collections::{HashMap, HashSet};
 
-use super::token_utils::{TokenDataIdType, TokenEvent};
+use super::{
+    event_effects::TokenEventEffects,
+    marketplace_event_mappings::{
+        MappedMarketplaceDelisting, MappedMarketplaceListing, MarketplaceEventMappings,
+    },
+    marketplaces::topaz,
+    token_activities::TokenActivity,
+    token_ownerships::{CurrentTokenOwnership, OWNER_TYPE_MARKETPLACE_ESCROW},
+    tokens::CurrentTokenOwnershipPK,
+};
 use crate::{
+    database::PgPoolConnection,
     schema::{current_marketplace_listings},
     util::{parse_timestamp},
 };
-use aptos_api_types::{Event as APIEvent, Transaction as APITransaction};
+use aptos_api_types::{Transaction as APITransaction, WriteSetChange as APIWriteSetChange};
 use bigdecimal::{BigDecimal, Zero};
+use diesel::{ExpressionMethods, QueryDsl, QueryResult, RunQueryDsl, SelectableHelper};
 use field_count::FieldCount;
 use serde::{Deserialize, Serialize};
 
-#[derive(Debug, Deserialize, FieldCount, Identifiable, Insertable, Serialize)]
-#[diesel(primary_key(
-    market_address,
-    token_data_id_hash
-))]
+/// Marketplaces where listed tokens stay in the seller's wallet until the sale, so a listing can't
+/// fill once the seller moves the token. Escrowed listings withdraw the token when listing.
+const ESCROWLESS_MARKET_ADDRESSES: &[&str] = &[topaz::ADDRESS];
+const WITHDRAW_EVENT_TYPE: &str = "0x3::token::WithdrawEvent";
+pub const INVALIDATED_TOKEN_WITHDRAWN: &str = "token_withdrawn";
+
+#[derive(
+    Debug, Deserialize, FieldCount, Identifiable, Insertable, Queryable, Selectable, Serialize,
+)]
+#[diesel(primary_key(token_data_id_hash))]
 #[diesel(table_name = current_marketplace_listings)]
 pub struct CurrentMarketplaceListing {
     pub collection_data_id_hash: String,
@@ -37,432 +53,587 @@ pub struct CurrentMarketplaceListing {
     pub event_type: String,
     pub inserted_at: chrono::NaiveDateTime,
     pub last_transaction_version: i64,
+    /// Set when the listing is still on the market but can never fill, ex: token_withdrawn
+    pub invalidated_reason: Option<String>,
+    pub last_transaction_timestamp: chrono::NaiveDateTime,
+    /// price in APT, set by CoinDecimals::set_listing_prices before the listing is written
+    pub price_decimal: Option<BigDecimal>,
+    /// The listing ended, by a sale, a delisting or a listing written with nothing left. Unlike
+    /// invalidated_reason, the token is off the market. Listing the token again clears it.
+    pub is_deleted: bool,
+    /// Index of the event that last changed the listing, which orders changes within a
+    /// transaction. Listings from the write set come after every event of theirs. Null for rows
+    /// indexed before it was added.
+    pub last_event_index: Option<i64>,
 }
 
-/// A simplified TokenActivity (excluded common fields) to reduce code duplication
-struct TokenActivityHelper<'a> {
-    pub token_data_id: &'a TokenDataIdType,
-    pub property_version: BigDecimal,
-    pub from_address: Option<String>,
-    pub to_address: Option<String>,
-    pub token_amount: BigDecimal,
-    pub coin_type: Option<String>,
-    pub coin_amount: Option<BigDecimal>,
+/// A token withdrawn from a wallet, which invalidates the wallet's escrowless listing of it
+#[derive(Debug)]
+pub struct ListingWithdrawal {
+    pub token_data_id_hash: String,
+    pub from_address: String,
+    pub transaction_version: i64,
+    pub event_index: i64,
+    pub transaction_timestamp: chrono::NaiveDateTime,
 }
 
-
 impl CurrentMarketplaceListing {
-    pub fn from_transaction(transaction: &APITransaction) -> HashMap<String, Self> {
+    /// effects are the transaction's TokenEventEffects
+    pub fn from_transaction(
+        transaction: &APITransaction,
+        effects: &[TokenEventEffects],
+        marketplace_event_mappings: &MarketplaceEventMappings,
+    ) -> HashMap<String, Self> {
         let mut current_marketplace_listings: HashMap<String, Self> = HashMap::new();
+        for current_marketplace_listing in effects.iter().filter_map(Self::from_effects) {
+            Self::insert_latest(&mut current_marketplace_listings, current_marketplace_listing);
+        }
         if let APITransaction::UserTransaction(user_txn) = transaction {
-            for event in &user_txn.events {
-                let txn_version = user_txn.info.version.0 as i64;
-                let event_type = event.typ.to_string();
-                match TokenEvent::from_event(event_type.as_str(), &event.data, txn_version).unwrap()
-                {
-                    Some(token_event) => {
-                        let parsed_event = Self::from_parsed_event(
-                            &event_type,
-                            event,
-                            &token_event,
-                            txn_version,
-                            parse_timestamp(user_txn.timestamp.0, txn_version),
+            // Some marketplace actions change the listing struct without emitting an event we
+            // parse, so the write set is reconciled after the events and wins over them
+            let txn_version = user_txn.info.version.0 as i64;
+            let txn_timestamp = parse_timestamp(user_txn.timestamp.0, txn_version);
+            let event_index = user_txn.events.len() as i64;
+            for wsc in &user_txn.info.changes {
+                let maybe_listing = match wsc {
+                    APIWriteSetChange::WriteResource(write_resource) => {
+                        let listing_type = format!(
+                            "{}::{}::{}",
+                            write_resource.data.typ.address,
+                            write_resource.data.typ.module,
+                            write_resource.data.typ.name
                         );
-                    if let Some(current_marketplace_listing) =  parsed_event {
-                        current_marketplace_listings.insert(
-                            current_marketplace_listing.token_data_id_hash.clone(), 
-                            current_marketplace_listing.into()
-                        )
-                        } else {
-                            None
-                        }
+                        marketplace_event_mappings
+                            .listing_from_data(
+                                &listing_type,
+                                &serde_json::to_value(&write_resource.data.data).unwrap(),
+                                txn_version,
+                            )
+                            .unwrap()
+                            .map(|listing| {
+                                Self::from_mapped_listing(
+                                    listing,
+                                    &listing_type,
+                                    txn_version,
+                                    event_index,
+                                    txn_timestamp,
+                                )
+                            })
+                    }
+                    APIWriteSetChange::WriteTableItem(write_table_item) => {
+                        write_table_item.data.as_ref().and_then(|data| {
+                            // Type arguments, ex: the coin type, aren't part of the configured type
+                            let listing_type =
+                                data.value_type.split('<').next().unwrap_or_default();
+                            marketplace_event_mappings
+                                .listing_from_data(listing_type, &data.value, txn_version)
+                                .unwrap()
+                                .map(|listing| {
+                                    Self::from_mapped_listing(
+                                        listing,
+                                        listing_type,
+                                        txn_version,
+                                        event_index,
+                                        txn_timestamp,
+                                    )
+                                })
+                        })
                     }
-                    None => None
+                    APIWriteSetChange::DeleteTableItem(delete_table_item) => {
+                        delete_table_item.data.as_ref().and_then(|data| {
+                            marketplace_event_mappings
+                                .delisting_from_key(&data.key_type, &data.key, txn_version)
+                                .unwrap()
+                                .map(|delisting| {
+                                    Self::from_mapped_delisting(
+                                        delisting,
+                                        &data.key_type,
+                                        txn_version,
+                                        event_index,
+                                        txn_timestamp,
+                                    )
+                                })
+                        })
+                    }
+                    _ => None,
                 };
+                if let Some(current_marketplace_listing) = maybe_listing {
+                    Self::insert_latest(
+                        &mut current_marketplace_listings,
+                        current_marketplace_listing,
+                    );
+                }
             }
         }
         current_marketplace_listings
     }
 
-    pub fn from_parsed_event(
-        event_type: &str,
-        event: &APIEvent,
-        token_event: &TokenEvent,
+    /// Keeps whichever of the token's listings is later by (version, event index), so the
+    /// listing a transaction leaves doesn't depend on the order its changes are visited in
+    fn insert_latest(current_marketplace_listings: &mut HashMap<String, Self>, listing: Self) {
+        match current_marketplace_listings.get(&listing.token_data_id_hash) {
+            Some(existing) if existing.ordering_key() > listing.ordering_key() => {}
+            _ => {
+                current_marketplace_listings.insert(listing.token_data_id_hash.clone(), listing);
+            }
+        }
+    }
+
+    fn ordering_key(&self) -> (i64, Option<i64>) {
+        (self.last_transaction_version, self.last_event_index)
+    }
+
+    /// Listings written with nothing left are delisted, same as delist events
+    fn from_mapped_listing(
+        listing: MappedMarketplaceListing,
+        listing_type: &str,
         txn_version: i64,
+        event_index: i64,
         txn_timestamp: chrono::NaiveDateTime,
-    ) -> Option<Self> {
-        let event_account_address = &event.guid.account_address.to_string();
-        let event_creation_number = event.guid.creation_number.0 as i64;
-        let event_sequence_number = event.sequence_number.0 as i64;
-        let binding = TokenDataIdType {
-            creator: "".to_owned(),
-            collection: "".to_owned(),
-            name: "".to_owned(),
-        }.clone();
-        let token_data_id = match token_event {
-            TokenEvent::BlueMoveAuctionEvent(inner) => &inner.id.token_data_id,
-            TokenEvent::BlueBidEvent(inner) => &inner.id.token_data_id,
-            TokenEvent::BlueBuyEvent(inner) => &inner.id.token_data_id,
-            TokenEvent::BlueChangePriceEvent(inner) => &inner.id.token_data_id,
-            TokenEvent::BlueClaimCoinsEvent(inner) => &inner.id.token_data_id,
-            TokenEvent::BlueClaimTokenEvent(inner) => &inner.id.token_data_id,
-            TokenEvent::BlueDelistEvent(inner) => &inner.id.token_data_id,
-            TokenEvent::BlueListEvent(inner) => &inner.id.token_data_id,
-            TokenEvent::TopazBidEvent(inner) => &inner.token_id.token_data_id,
-            TokenEvent::TopazBuyEvent(inner) => &inner.token_id.token_data_id,
-            TokenEvent::TopazCancelBidEvent(inner) => &inner.token_id.token_data_id,
-            TokenEvent::TopazClaimEvent(inner) => &inner.token_id.token_data_id,
-            TokenEvent::TopazDelistEvent(inner) => &inner.token_id.token_data_id,
-            TokenEvent::TopazListEvent(inner) => &inner.token_id.token_data_id,
-            TokenEvent::TopazSellEvent(inner) => &inner.token_id.token_data_id,
-            TokenEvent::TopazSendEvent(inner) => &inner.token_id.token_data_id,
-            TokenEvent::Souffl3BuyTokenEvent(inner) => &inner.token_id.token_data_id,
-            TokenEvent::Souffl3CancelListTokenEvent(inner) => &inner.token_id.token_data_id,
-            TokenEvent::Souffl3ListTokenEvent(inner) => &inner.token_id.token_data_id,
-            TokenEvent::Souffl3TokenListEvent(inner) => &inner.token_id.token_data_id,
-            TokenEvent::Souffl3TokenSwapEvent(inner) => &inner.token_id.token_data_id,
-            _ => &binding
-        };
-        let binding = match token_event {
-            TokenEvent::TopazCancelCollectionBidEvent(inner) => 
-                TokenDataIdType {
-                    creator: inner.creator.clone(),
-                    collection: inner.collection_name.clone(),
-                    name: "COLLECTION".to_owned(),
-                }.clone(),
-            TokenEvent::TopazCollectionBidEvent(inner) => 
-                TokenDataIdType {
-                    creator: inner.creator.clone(),
-                    collection: inner.collection_name.clone(),
-                    name: "COLLECTION".to_owned(),
-                }.clone(),
-            _ => TokenDataIdType {
-                creator: "".to_owned(),
-                collection: "".to_owned(),
-                name: "COLLECTION".to_owned(),
-            }.clone()
+    ) -> Self {
+        let token_data_id = &listing.token_data_id;
+        let is_deleted = listing.amount.is_zero();
+        let market_address = if is_deleted {
+            "".to_owned()
+        } else {
+            listing.market_address.clone()
         };
-        let token_activity_helper = match token_event {
-            TokenEvent::MintTokenEvent(inner) => TokenActivityHelper {
-                token_data_id: &inner.id,
-                property_version: BigDecimal::zero(),
-                from_address: Some(event_account_address.clone()),
-                to_address: None,
-                token_amount: inner.amount.clone(),
-                coin_type: None,
-                coin_amount: None,
-            },
-            TokenEvent::BurnTokenEvent(inner) => TokenActivityHelper {
-                token_data_id: &inner.id.token_data_id,
-                property_version: inner.id.property_version.clone(),
-                from_address: Some(event_account_address.clone()),
-                to_address: None,
-                token_amount: inner.amount.clone(),
-                coin_type: None,
-                coin_amount: None,
-            },
-            TokenEvent::MutateTokenPropertyMapEvent(inner) => TokenActivityHelper {
-                token_data_id: &inner.new_id.token_data_id,
-                property_version: inner.new_id.property_version.clone(),
-                from_address: Some(event_account_address.clone()),
-                to_address: None,
-                token_amount: BigDecimal::zero(),
-                coin_type: None,
-                coin_amount: None,
-            },
-            TokenEvent::WithdrawTokenEvent(inner) => TokenActivityHelper {
-                token_data_id: &inner.id.token_data_id,
-                property_version: inner.id.property_version.clone(),
-                from_address: Some(event_account_address.clone()),
-                to_address: None,
-                token_amount: inner.amount.clone(),
-                coin_type: None,
-                coin_amount: None,
-            },
-            TokenEvent::DepositTokenEvent(inner) => TokenActivityHelper {
-                token_data_id: &inner.id.token_data_id,
-                property_version: inner.id.property_version.clone(),
-                from_address: None,
-                to_address: Some((&event_account_address).to_string()),
-                token_amount: inner.amount.clone(),
-                coin_type: None,
-                coin_amount: None,
-            },
-            TokenEvent::OfferTokenEvent(inner) => TokenActivityHelper {
-                token_data_id: &inner.token_id.token_data_id,
-                property_version: inner.token_id.property_version.clone(),
-                from_address: Some(event_account_address.clone()),
-                to_address: Some(inner.to_address.clone()),
-                token_amount: inner.amount.clone(),
-                coin_type: None,
-                coin_amount: None,
-            },
-            TokenEvent::CancelTokenOfferEvent(inner) => TokenActivityHelper {
-                token_data_id: &inner.token_id.token_data_id,
-                property_version: inner.token_id.property_version.clone(),
-                from_address: Some(event_account_address.clone()),
-                to_address: Some(inner.to_address.clone()),
-                token_amount: inner.amount.clone(),
-                coin_type: None,
-                coin_amount: None,
-            },
-            TokenEvent::ClaimTokenEvent(inner) => TokenActivityHelper {
-                token_data_id: &inner.token_id.token_data_id,
-                property_version: inner.token_id.property_version.clone(),
-                from_address: Some(event_account_address.clone()),
-                to_address: Some(inner.to_address.clone()),
-                token_amount: inner.amount.clone(),
-                coin_type: None,
-                coin_amount: None,
-            },
-            TokenEvent::BlueMoveAuctionEvent(inner) => TokenActivityHelper {
-                token_data_id: &inner.id.token_data_id,
-                property_version: inner.id.property_version.clone(),
-                from_address: Some(inner.owner_address.clone()),
-                to_address: None,
-                token_amount: BigDecimal::zero(),
-                coin_type: None,
-                coin_amount: Some(inner.min_selling_price.clone()),
-            },
-            TokenEvent::BlueBidEvent(inner) => TokenActivityHelper {
-                token_data_id: &inner.id.token_data_id,
-                property_version: inner.id.property_version.clone(),
-                from_address: Some(inner.bider_address.clone()),
-                to_address: None,
-                token_amount: BigDecimal::zero(),
-                coin_type: None,
-                coin_amount: Some(inner.bid.clone()),
-            },
-            TokenEvent::BlueBuyEvent(inner) => TokenActivityHelper {
-                token_data_id: &inner.id.token_data_id,
-                property_version: inner.id.property_version.clone(),
-                from_address: None,
-                to_address: Some(inner.buyer_address.clone()),
-                token_amount: BigDecimal::zero(),
-                coin_type: None,
-                coin_amount: None,
-            },
-            TokenEvent::BlueChangePriceEvent(inner) => TokenActivityHelper {
-                token_data_id: &inner.id.token_data_id,
-                property_version: inner.id.property_version.clone(),
-                from_address: Some(inner.seller_address.clone()),
-                to_address: None,
-                token_amount: BigDecimal::zero(),
-                coin_type: None,
-                coin_amount: Some(inner.amount.clone()),
-            },
-            TokenEvent::BlueClaimCoinsEvent(inner) => TokenActivityHelper {
-                token_data_id: &inner.id.token_data_id,
-                property_version: inner.id.property_version.clone(),
-                from_address: Some(inner.owner_token.clone()),
-                to_address: None,
-                token_amount: BigDecimal::zero(),
-                coin_type: None,
-                coin_amount: None,
-            },
-            TokenEvent::BlueClaimTokenEvent(inner) => TokenActivityHelper {
-                token_data_id: &inner.id.token_data_id,
-                property_version: inner.id.property_version.clone(),
-                from_address: None,
-                to_address: Some(inner.bider_address.clone()),
-                token_amount: BigDecimal::zero(),
-                coin_type: None,
-                coin_amount: None,
-            },
-            TokenEvent::BlueDelistEvent(inner) => TokenActivityHelper {
-                token_data_id: &inner.id.token_data_id,
-                property_version: inner.id.property_version.clone(),
-                from_address: Some(inner.seller_address.clone()),
-                to_address: None,
-                token_amount: BigDecimal::zero(),
-                coin_type: None,
-                coin_amount: None,
-            },
-            TokenEvent::BlueListEvent(inner) => TokenActivityHelper {
-                token_data_id: &inner.id.token_data_id,
-                property_version: inner.id.property_version.clone(),
-                from_address: Some(inner.seller_address.clone()),
-                to_address: None,
-                token_amount: inner.amount.clone(),
-                coin_type: None,
-                coin_amount: None,
-            },
-            TokenEvent::TopazBidEvent(inner) => TokenActivityHelper {
-                token_data_id: &inner.token_id.token_data_id,
-                property_version: inner.token_id.property_version.clone(),
-                from_address: Some(inner.buyer.clone()),
-                to_address: None,
-                token_amount: inner.amount.clone(),
-                coin_type: Some(inner.coin_type.to_string()),
-                coin_amount: Some(inner.price.clone()),
-            },
-            TokenEvent::TopazBuyEvent(inner) => TokenActivityHelper {
-                token_data_id: &inner.token_id.token_data_id,
-                property_version: inner.token_id.property_version.clone(),
-                from_address: Some(inner.seller.clone()),
-                to_address: Some(inner.buyer.clone()),
-                token_amount: inner.amount.clone(),
-                coin_type: None,
-                coin_amount: Some(inner.price.clone()),
-            },
-            TokenEvent::TopazCancelBidEvent(inner) => TokenActivityHelper {
-                token_data_id: &inner.token_id.token_data_id,
-                property_version: inner.token_id.property_version.clone(),
-                from_address: Some(inner.buyer.clone()),
-                to_address: None,
-                token_amount: inner.amount.clone(),
-                coin_type: Some(inner.coin_type.to_string()),
-                coin_amount: Some(inner.price.clone()),
-            },
-            TokenEvent::TopazCancelCollectionBidEvent(inner) => TokenActivityHelper {
-                token_data_id: &binding,
-                property_version: BigDecimal::zero(),
-                from_address: Some(inner.buyer.clone()),
-                to_address: None,
-                token_amount: inner.amount.clone(),
-                coin_type: Some(inner.coin_type.to_string()),
-                coin_amount: Some(inner.price.clone()),
-            },
-            TokenEvent::TopazClaimEvent(inner) => TokenActivityHelper {
-                token_data_id: &inner.token_id.token_data_id,
-                property_version: inner.token_id.property_version.clone(),
-                from_address: None,
-                to_address: Some(inner.receiver.clone()),
-                token_amount: BigDecimal::zero(),
-                coin_type: None,
-                coin_amount: None,
-            },
-            TokenEvent::TopazCollectionBidEvent(inner) => TokenActivityHelper {
-                token_data_id: &binding,
-                property_version: BigDecimal::zero(),
-                from_address: Some(inner.buyer.clone()),
-                to_address: None,
-                token_amount: inner.amount.clone(),
-                coin_type: Some(inner.coin_type.to_string()),
-                coin_amount: Some(inner.price.clone()),
-            },
-            TokenEvent::TopazDelistEvent(inner) => TokenActivityHelper {
-                token_data_id: &inner.token_id.token_data_id,
-                property_version: inner.token_id.property_version.clone(),
-                from_address: Some(inner.seller.clone()),
-                to_address: None,
-                token_amount: inner.amount.clone(),
-                coin_type: None,
-                coin_amount: Some(inner.price.clone()),
-            },
-            TokenEvent::TopazListEvent(inner) => TokenActivityHelper {
-                token_data_id: &inner.token_id.token_data_id,
-                property_version: inner.token_id.property_version.clone(),
-                from_address: Some(inner.seller.clone()),
-                to_address: None,
-                token_amount: inner.amount.clone(),
-                coin_type: None,
-                coin_amount: Some(inner.price.clone()),
-            },
-            TokenEvent::TopazSellEvent(inner) => TokenActivityHelper {
-                token_data_id: &inner.token_id.token_data_id,
-                property_version: inner.token_id.property_version.clone(),
-                from_address: Some(inner.seller.clone()),
-                to_address: Some(inner.buyer.clone()),
-                token_amount: inner.amount.clone(),
-                coin_type: Some(inner.coin_type.to_string()),
-                coin_amount: Some(inner.price.clone()),
-            },
-            TokenEvent::TopazSendEvent(inner) => TokenActivityHelper {
-                token_data_id: &inner.token_id.token_data_id,
-                property_version: inner.token_id.property_version.clone(),
-                from_address: Some(inner.sender.clone()),
-                to_address: Some(inner.receiver.clone()),
-                token_amount: inner.amount.clone(),
-                coin_type: None,
-                coin_amount: None,
-            },
-            TokenEvent::Souffl3BuyTokenEvent(inner) => TokenActivityHelper {
-                token_data_id: &inner.token_id.token_data_id,
-                property_version: inner.token_id.property_version.clone(),
-                from_address: Some(inner.token_owner.clone()),
-                to_address: Some(inner.buyer.clone()),
-                token_amount: inner.token_amount.clone(),
-                coin_type: None,
-                coin_amount: Some(inner.coin_per_token.clone()),
-            },
-            TokenEvent::Souffl3CancelListTokenEvent(inner) => TokenActivityHelper {
-                token_data_id: &inner.token_id.token_data_id,
-                property_version: inner.token_id.property_version.clone(),
-                from_address: None,
-                to_address: None,
-                token_amount: inner.token_amount.clone(),
-                coin_type: None,
-                coin_amount: None,
-            },
-            TokenEvent::Souffl3ListTokenEvent(inner) => TokenActivityHelper {
-                token_data_id: &inner.token_id.token_data_id,
-                property_version: inner.token_id.property_version.clone(),
-                from_address: Some(inner.token_owner.clone()),
-                to_address: None,
-                token_amount: inner.token_amount.clone(),
-                coin_type: None,
-                coin_amount: Some(inner.coin_per_token.clone()),
-            },
-            TokenEvent::Souffl3TokenListEvent(inner) => TokenActivityHelper {
-                token_data_id: &inner.token_id.token_data_id,
-                property_version: inner.token_id.property_version.clone(),
-                from_address: None,
-                to_address: None,
-                token_amount: inner.amount.clone(),
-                coin_type: Some(inner.coin_type_info.to_string()),
-                coin_amount: Some(inner.min_price.clone()),
-            },
-            TokenEvent::Souffl3TokenSwapEvent(inner) => TokenActivityHelper {
-                token_data_id: &inner.token_id.token_data_id,
-                property_version: inner.token_id.property_version.clone(),
-                from_address: None,
-                to_address: Some(inner.token_buyer.clone()),
-                token_amount: inner.token_amount.clone(),
-                coin_type: Some(inner.coin_type_info.to_string()),
-                coin_amount: Some(inner.coin_amount.clone()),
+        Self {
+            collection_data_id_hash: token_data_id.get_collection_data_id_hash(),
+            market_address,
+            token_data_id_hash: token_data_id.to_hash(),
+            property_version: listing.property_version.clone(),
+            creator_address: token_data_id.creator.clone(),
+            collection_name: token_data_id.collection.clone(),
+            name: token_data_id.name.clone(),
+            seller: listing.seller.clone(),
+            amount: listing.amount.clone(),
+            price: listing.price.clone(),
+            event_type: listing_type.to_owned(),
+            inserted_at: txn_timestamp,
+            last_transaction_version: txn_version,
+            last_transaction_timestamp: txn_timestamp,
+            invalidated_reason: None,
+            price_decimal: None,
+            is_deleted,
+            last_event_index: Some(event_index),
+        }
+    }
+
+    fn from_mapped_delisting(
+        delisting: MappedMarketplaceDelisting,
+        key_type: &str,
+        txn_version: i64,
+        event_index: i64,
+        txn_timestamp: chrono::NaiveDateTime,
+    ) -> Self {
+        let token_data_id = &delisting.token_id.token_data_id;
+        Self {
+            collection_data_id_hash: token_data_id.get_collection_data_id_hash(),
+            market_address: "".to_owned(),
+            token_data_id_hash: token_data_id.to_hash(),
+            property_version: delisting.token_id.property_version.clone(),
+            creator_address: token_data_id.creator.clone(),
+            collection_name: token_data_id.collection.clone(),
+            name: token_data_id.name.clone(),
+            seller: "".to_owned(),
+            amount: BigDecimal::zero(),
+            price: BigDecimal::zero(),
+            event_type: key_type.to_owned(),
+            inserted_at: txn_timestamp,
+            last_transaction_version: txn_version,
+            last_transaction_timestamp: txn_timestamp,
+            invalidated_reason: None,
+            price_decimal: None,
+            is_deleted: true,
+            last_event_index: Some(event_index),
+        }
+    }
+
+    fn is_active(&self) -> bool {
+        !self.is_deleted && self.invalidated_reason.is_none()
+    }
+
+    fn is_active_escrowless(&self) -> bool {
+        ESCROWLESS_MARKET_ADDRESSES.contains(&self.market_address.as_str()) && self.is_active()
+    }
+
+    /// Invalidates escrowless listings from earlier in the batch whose seller withdrew the token in
+    /// this transaction. Needs to run before the transaction's own listings are added, since a
+    /// sale withdraws the token too. Withdrawals of tokens without a listing in the batch are
+    /// returned to be checked against the db once the batch is parsed.
+    pub fn invalidate_withdrawn(
+        current_marketplace_listings: &mut HashMap<String, Self>,
+        activities: &[TokenActivity],
+    ) -> Vec<ListingWithdrawal> {
+        let mut withdrawals = vec![];
+        for activity in activities {
+            let from_address = match &activity.from_address {
+                Some(from_address) if activity.transfer_type == WITHDRAW_EVENT_TYPE => {
+                    from_address
+                }
+                _ => continue,
+            };
+            match current_marketplace_listings.get_mut(&activity.token_data_id_hash) {
+                Some(listing) => {
+                    if listing.is_active_escrowless()
+                        && &listing.seller == from_address
+                        && listing.last_transaction_version < activity.transaction_version
+                    {
+                        listing.invalidated_reason = Some(INVALIDATED_TOKEN_WITHDRAWN.to_owned());
+                        listing.inserted_at = activity.transaction_timestamp;
+                        listing.last_transaction_version = activity.transaction_version;
+                        listing.last_event_index = Some(activity.event_index);
+                        listing.last_transaction_timestamp = activity.transaction_timestamp;
+                    }
+                }
+                None => withdrawals.push(ListingWithdrawal {
+                    token_data_id_hash: activity.token_data_id_hash.clone(),
+                    from_address: from_address.clone(),
+                    transaction_version: activity.transaction_version,
+                    event_index: activity.event_index,
+                    transaction_timestamp: activity.transaction_timestamp,
+                }),
             }
-        };
-        // only update listing info if event type contains "list", "delist", "buy", "sell", 'change', 'send', or 'claim', else return None
-        if event_type.contains("List")
-            || event_type.contains("Delist")
-            || event_type.contains("Buy")
-            || event_type.contains("Sell")
-            || event_type.contains("Change")
-            || event_type.contains("CancelList")
-            || event_type.contains("Fill")
-            || event_type.contains("Send")
-            || event_type.contains("Auction")
-        {
-            // market address is "0xd1fd99c1944b84d1670a2536417e997864ad12303d19eac725891691b04d614e" for blue/bluemove, "0x2c7bccf7b31baf770fdbcc768d9e9cb3d87805e255355df5db32ac9a669010a2" for topaz, and "0xf6994988bd40261af9431cd6dd3fcf765569719e66322c7a05cc78a89cd366d4" for souffl3
-            let mut market_address = event_type.split("::").next().unwrap(); //
-            if !(event_type.contains("List") || event_type.contains("Auction")) || event_type.contains("CancelList") || event_type.contains("Delist") {
-                market_address = "";
-            } 
-            let token_data_id_hash = token_data_id.to_hash();
-            let creator_address = token_data_id.creator.clone();
-            let collection_name = token_data_id.collection.clone();
-            let name = token_data_id.name.clone();
-            let seller = token_activity_helper.from_address.clone().unwrap_or("".to_owned());
-            let amount = token_activity_helper.token_amount.clone();
-            let price = token_activity_helper.coin_amount.clone().unwrap_or(BigDecimal::zero());
-            Some(Self {
-                collection_data_id_hash: token_data_id.get_collection_data_id_hash(),
-                market_address: market_address.to_owned(),
-                token_data_id_hash,
-                property_version: token_activity_helper.property_version.clone(),
-                creator_address,
-                collection_name,
-                name,
-                seller,
-                amount,
-                price,
-                event_type: event_type.to_owned(),
-                inserted_at: txn_timestamp,
-                last_transaction_version: txn_version
+        }
+        withdrawals
+    }
+
+    /// Same as invalidate_withdrawn for listings from earlier batches. Tokens with a listing in
+    /// this batch are skipped since that listing replaces the stored one.
+    pub fn invalidate_withdrawn_from_db(
+        conn: &mut PgPoolConnection,
+        current_marketplace_listings: &mut HashMap<String, Self>,
+        withdrawals: &[ListingWithdrawal],
+    ) -> QueryResult<()> {
+        let token_data_id_hashes = withdrawals
+            .iter()
+            .filter(|withdrawal| {
+                !current_marketplace_listings.contains_key(&withdrawal.token_data_id_hash)
             })
+            .map(|withdrawal| withdrawal.token_data_id_hash.clone())
+            .collect::<HashSet<String>>();
+        if token_data_id_hashes.is_empty() {
+            return Ok(());
+        }
+        let mut stored_listings = current_marketplace_listings::table
+            .filter(current_marketplace_listings::token_data_id_hash.eq_any(token_data_id_hashes))
+            .filter(
+                current_marketplace_listings::market_address
+                    .eq_any(ESCROWLESS_MARKET_ADDRESSES.to_vec()),
+            )
+            .filter(current_marketplace_listings::invalidated_reason.is_null())
+            .filter(current_marketplace_listings::is_deleted.eq(false))
+            .select(CurrentMarketplaceListing::as_select())
+            .load::<CurrentMarketplaceListing>(conn)?
+            .into_iter()
+            .map(|listing| (listing.token_data_id_hash.clone(), listing))
+            .collect::<HashMap<String, CurrentMarketplaceListing>>();
+        // Withdrawals are in version order, so the first one by the seller invalidates the listing
+        for withdrawal in withdrawals {
+            let matches = match stored_listings.get(&withdrawal.token_data_id_hash) {
+                Some(listing) => {
+                    listing.seller == withdrawal.from_address
+                        && listing.last_transaction_version < withdrawal.transaction_version
+                }
+                None => false,
+            };
+            if matches
+                && !current_marketplace_listings.contains_key(&withdrawal.token_data_id_hash)
+            {
+                let listing = stored_listings.remove(&withdrawal.token_data_id_hash).unwrap();
+                current_marketplace_listings.insert(
+                    listing.token_data_id_hash.clone(),
+                    Self {
+                        collection_data_id_hash: listing.collection_data_id_hash,
+                        market_address: listing.market_address,
+                        token_data_id_hash: listing.token_data_id_hash,
+                        property_version: listing.property_version,
+                        creator_address: listing.creator_address,
+                        collection_name: listing.collection_name,
+                        name: listing.name,
+                        seller: listing.seller,
+                        amount: listing.amount,
+                        price: listing.price,
+                        event_type: listing.event_type,
+                        inserted_at: withdrawal.transaction_timestamp,
+                        last_transaction_version: withdrawal.transaction_version,
+                        last_transaction_timestamp: withdrawal.transaction_timestamp,
+                        invalidated_reason: Some(INVALIDATED_TOKEN_WITHDRAWN.to_owned()),
+                        price_decimal: listing.price_decimal,
+                        is_deleted: listing.is_deleted,
+                        last_event_index: Some(withdrawal.event_index),
+                    },
+                );
+            }
+        }
+        Ok(())
+    }
+
+    /// Credits tokens held in a marketplace's escrow to the seller of their active listing, from
+    /// this batch or else from the db. Has to run once the batch's listings are reconciled, since
+    /// a sale or delisting in the batch ends the listing.
+    pub fn set_escrow_beneficial_owners(
+        conn: &mut PgPoolConnection,
+        current_token_ownerships: &mut HashMap<CurrentTokenOwnershipPK, CurrentTokenOwnership>,
+        current_marketplace_listings: &HashMap<String, Self>,
+    ) -> QueryResult<()> {
+        let escrowed = current_token_ownerships
+            .values_mut()
+            .filter(|ownership| {
+                ownership.owner_type == OWNER_TYPE_MARKETPLACE_ESCROW && !ownership.amount.is_zero()
+            })
+            .collect::<Vec<&mut CurrentTokenOwnership>>();
+        let token_data_id_hashes = escrowed
+            .iter()
+            .filter(|ownership| {
+                !current_marketplace_listings.contains_key(&ownership.token_data_id_hash)
+            })
+            .map(|ownership| ownership.token_data_id_hash.clone())
+            .collect::<HashSet<String>>();
+        let stored_sellers = if token_data_id_hashes.is_empty() {
+            HashMap::new()
         } else {
-            None
+            current_marketplace_listings::table
+                .filter(
+                    current_marketplace_listings::token_data_id_hash.eq_any(token_data_id_hashes),
+                )
+                .filter(current_marketplace_listings::invalidated_reason.is_null())
+                .filter(current_marketplace_listings::is_deleted.eq(false))
+                .filter(current_marketplace_listings::amount.gt(BigDecimal::zero()))
+                .select((
+                    current_marketplace_listings::token_data_id_hash,
+                    current_marketplace_listings::property_version,
+                    current_marketplace_listings::seller,
+                ))
+                .load::<(String, BigDecimal, String)>(conn)?
+                .into_iter()
+                .map(|(token_data_id_hash, property_version, seller)| {
+                    ((token_data_id_hash, property_version), seller)
+                })
+                .collect::<HashMap<(String, BigDecimal), String>>()
+        };
+        for ownership in escrowed {
+            ownership.beneficial_owner =
+                match current_marketplace_listings.get(&ownership.token_data_id_hash) {
+                    Some(listing) => (listing.is_active()
+                        && listing.property_version == ownership.property_version)
+                        .then(|| listing.seller.clone()),
+                    None => stored_sellers
+                        .get(&(
+                            ownership.token_data_id_hash.clone(),
+                            ownership.property_version.clone(),
+                        ))
+                        .cloned(),
+                };
         }
+        Ok(())
     }
-}
\ No newline at end of file
+
+    /// The listing an event leaves the token in, None for events that don't touch listings. A
+    /// sale always ends the listing, on any marketplace.
+    pub fn from_effects(effects: &TokenEventEffects) -> Option<Self> {
+        let market_effect = effects.market_effect?;
+        // market address is "0xd1fd99c1944b84d1670a2536417e997864ad12303d19eac725891691b04d614e" for blue/bluemove, "0x2c7bccf7b31baf770fdbcc768d9e9cb3d87805e255355df5db32ac9a669010a2" for topaz, and "0xf6994988bd40261af9431cd6dd3fcf765569719e66322c7a05cc78a89cd366d4" for souffl3
+        let market_address = if market_effect.keeps_listed() {
+            effects.event_type.split("::").next().unwrap()
+        } else {
+            ""
+        };
+        let token_data_id = &effects.token_data_id;
+        Some(Self {
+            collection_data_id_hash: token_data_id.get_collection_data_id_hash(),
+            market_address: market_address.to_owned(),
+            token_data_id_hash: token_data_id.to_hash(),
+            property_version: effects.property_version.clone(),
+            creator_address: token_data_id.creator.clone(),
+            collection_name: token_data_id.collection.clone(),
+            name: token_data_id.name.clone(),
+            seller: effects.from_address.clone().unwrap_or_default(),
+            amount: effects.listing_amount.clone(),
+            price: effects.listing_price.clone().unwrap_or_else(BigDecimal::zero),
+            event_type: effects.event_type.clone(),
+            inserted_at: effects.transaction_timestamp,
+            last_transaction_version: effects.transaction_version,
+            last_transaction_timestamp: effects.transaction_timestamp,
+            invalidated_reason: None,
+            price_decimal: None,
+            is_deleted: !market_effect.keeps_listed(),
+            last_event_index: Some(effects.event_index),
+        })
+    }
+}
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::models::token_models::{
+        event_effects::{MarketEffect, TransferKind},
+        token_utils::TokenDataIdType,
+    };
+
+    fn listing(market_address: &str, version: i64) -> CurrentMarketplaceListing {
+        CurrentMarketplaceListing {
+            collection_data_id_hash: "collection".to_owned(),
+            market_address: market_address.to_owned(),
+            token_data_id_hash: "token".to_owned(),
+            property_version: BigDecimal::zero(),
+            creator_address: "0xc4e7".to_owned(),
+            collection_name: "Potions".to_owned(),
+            name: "Potion".to_owned(),
+            seller: "0xa11ce".to_owned(),
+            amount: BigDecimal::from(1),
+            price: BigDecimal::from(100),
+            event_type: "ListEvent".to_owned(),
+            inserted_at: chrono::NaiveDateTime::from_timestamp(1668000000, 0),
+            last_transaction_version: version,
+            last_transaction_timestamp: chrono::NaiveDateTime::from_timestamp(1668000000, 0),
+            invalidated_reason: None,
+            price_decimal: None,
+            is_deleted: false,
+            last_event_index: Some(0),
+        }
+    }
+
+    fn withdraw(token_data_id_hash: &str, version: i64) -> TokenActivity {
+        TokenActivity {
+            transaction_version: version,
+            event_account_address: "0xa11ce".to_owned(),
+            event_creation_number: 3,
+            event_sequence_number: 0,
+            event_index: 0,
+            token_index: 0,
+            token_data_id_hash: token_data_id_hash.to_owned(),
+            property_version: BigDecimal::zero(),
+            creator_address: "0xc4e7".to_owned(),
+            collection_name: "Potions".to_owned(),
+            name: "Potion".to_owned(),
+            transfer_type: WITHDRAW_EVENT_TYPE.to_owned(),
+            transfer_kind: Some("transfer".to_owned()),
+            domain: None,
+            from_address: Some("0xa11ce".to_owned()),
+            to_address: None,
+            token_amount: BigDecimal::from(1),
+            coin_type: None,
+            coin_amount: None,
+            suspect_value: false,
+            collection_data_id_hash: "collection".to_owned(),
+            transaction_timestamp: chrono::NaiveDateTime::from_timestamp(1668000100, 0),
+        }
+    }
+
+    #[test]
+    fn test_withdrawal_invalidates_escrowless_listing() {
+        let mut listings = HashMap::new();
+        listings.insert(
+            "token".to_owned(),
+            listing(ESCROWLESS_MARKET_ADDRESSES[0], 10),
+        );
+        let withdrawals = CurrentMarketplaceListing::invalidate_withdrawn(
+            &mut listings,
+            &[withdraw("token", 20), withdraw("other_token", 20)],
+        );
+        assert_eq!(
+            listings["token"].invalidated_reason.as_deref(),
+            Some(INVALIDATED_TOKEN_WITHDRAWN)
+        );
+        assert_eq!(listings["token"].last_transaction_version, 20);
+        // Left for the db check
+        assert_eq!(withdrawals.len(), 1);
+        assert_eq!(withdrawals[0].token_data_id_hash, "other_token");
+    }
+
+    #[test]
+    fn test_escrowed_listing_is_not_invalidated() {
+        let bluemove = "0xd1fd99c1944b84d1670a2536417e997864ad12303d19eac725891691b04d614e";
+        let mut listings = HashMap::new();
+        listings.insert("token".to_owned(), listing(bluemove, 10));
+        CurrentMarketplaceListing::invalidate_withdrawn(&mut listings, &[withdraw("token", 20)]);
+        assert_eq!(listings["token"].invalidated_reason, None);
+
+        // Nor is an escrowless listing withdrawn in the same transaction, ex: its sale
+        let mut listings = HashMap::new();
+        listings.insert(
+            "token".to_owned(),
+            listing(ESCROWLESS_MARKET_ADDRESSES[0], 20),
+        );
+        CurrentMarketplaceListing::invalidate_withdrawn(&mut listings, &[withdraw("token", 20)]);
+        assert_eq!(listings["token"].invalidated_reason, None);
+    }
+    fn effects(market_effect: MarketEffect, version: i64) -> TokenEventEffects {
+        TokenEventEffects {
+            transaction_version: version,
+            transaction_timestamp: chrono::NaiveDateTime::from_timestamp(1668000000, 0),
+            event_type: format!("{}::events::ListTokenEvent", ESCROWLESS_MARKET_ADDRESSES[0]),
+            event_key: ("0xa11ce".to_owned(), 3, version),
+            event_index: 0,
+            token_index: 0,
+            token_data_id: TokenDataIdType {
+                creator: "0xc4e7".to_owned(),
+                collection: "Potions".to_owned(),
+                name: "Potion".to_owned(),
+            },
+            property_version: BigDecimal::zero(),
+            from_address: Some("0xa11ce".to_owned()),
+            to_address: None,
+            token_amount: BigDecimal::from(1),
+            coin_type: None,
+            coin_amount: Some(BigDecimal::from(100)),
+            listing_amount: BigDecimal::from(1),
+            listing_price: Some(BigDecimal::from(100)),
+            market_effect: Some(market_effect),
+            transfer_kind: TransferKind::Listing,
+            suspect_value: false,
+        }
+    }
+
+    #[test]
+    fn test_sale_deletes_listing_until_relisted() {
+        let listed =
+            CurrentMarketplaceListing::from_effects(&effects(MarketEffect::List, 10)).unwrap();
+        assert!(listed.is_active());
+
+        // The sale still carries the sold amount, the flag is what ends the listing
+        let sold =
+            CurrentMarketplaceListing::from_effects(&effects(MarketEffect::Sale, 20)).unwrap();
+        assert_eq!(sold.amount, BigDecimal::from(1));
+        assert_eq!(sold.market_address, "");
+        assert!(sold.is_deleted);
+        assert!(!sold.is_active());
+
+        let relisted =
+            CurrentMarketplaceListing::from_effects(&effects(MarketEffect::List, 30)).unwrap();
+        assert!(!relisted.is_deleted);
+        assert_eq!(relisted.token_data_id_hash, sold.token_data_id_hash);
+    }
+    #[test]
+    fn test_delisting_in_the_listing_transaction_wins() {
+        let listed = effects(MarketEffect::List, 10);
+        let mut delisted = effects(MarketEffect::Unlist, 10);
+        delisted.event_index = 1;
+        // Without its write set, so that only the events change the listing
+        let mut transaction: serde_json::Value = serde_json::from_str(
+            &std::fs::read_to_string(concat!(
+                env!("CARGO_MANIFEST_DIR"),
+                "/fixtures/golden/transactions/bluemove_list.json"
+            ))
+            .unwrap(),
+        )
+        .unwrap();
+        transaction["changes"] = serde_json::json!([]);
+        let transaction: APITransaction = serde_json::from_value(transaction).unwrap();
+
+        // In either order, the later event is the one the transaction leaves
+        for effects in [
+            vec![listed.clone(), delisted.clone()],
+            vec![delisted.clone(), listed.clone()],
+        ] {
+            let listings = CurrentMarketplaceListing::from_transaction(
+                &transaction,
+                &effects,
+                &MarketplaceEventMappings::default(),
+            );
+            assert!(listings["token"].is_deleted);
+            assert_eq!(listings["token"].last_event_index, Some(1));
+        }
+    }
+}
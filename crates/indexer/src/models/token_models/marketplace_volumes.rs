@@ -0,0 +1,323 @@
+// Copyright (c) Aptos
+// SPDX-License-Identifier: Apache-2.0
+
+//! Volume per marketplace and coin, all time and per day, and per marketplace and collection.
+//! They're totalled from the same collection_volumes rows as the current collection volumes, so
+//! they only count each sale once even when a range is processed again. Sales that weren't
+//! attributed to a marketplace, i.e. without an NftSale, aren't counted.
+
+// This is required because a diesel macro makes clippy sad
+#![allow(clippy::extra_unused_lifetimes)]
+#![allow(clippy::unused_unit)]
+
+use super::collection_volume::{add_known, CollectionVolume};
+use crate::schema::{
+    current_marketplace_volumes, marketplace_collection_volumes, marketplace_volumes,
+};
+use bigdecimal::BigDecimal;
+use field_count::FieldCount;
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+
+#[derive(Debug, Deserialize, FieldCount, Identifiable, Insertable, Serialize)]
+#[diesel(primary_key(market_address, coin_type))]
+#[diesel(table_name = current_marketplace_volumes)]
+pub struct CurrentMarketplaceVolume {
+    pub market_address: String,
+    pub coin_type: String,
+    pub volume: BigDecimal,
+    pub trade_count: i64,
+    pub volume_usd: Option<BigDecimal>,
+    pub volume_decimal: Option<BigDecimal>,
+    pub last_transaction_version: i64,
+    pub last_transaction_timestamp: chrono::NaiveDateTime,
+    pub inserted_at: chrono::NaiveDateTime,
+    /// With last_transaction_version, the latest sale counted
+    pub last_event_index: Option<i64>,
+}
+
+/// Same as CurrentMarketplaceVolume, for the sales of one UTC day
+#[derive(Debug, Deserialize, FieldCount, Identifiable, Insertable, Serialize)]
+#[diesel(primary_key(market_address, coin_type, volume_date))]
+#[diesel(table_name = marketplace_volumes)]
+pub struct MarketplaceVolume {
+    pub market_address: String,
+    pub coin_type: String,
+    pub volume_date: chrono::NaiveDate,
+    pub volume: BigDecimal,
+    pub trade_count: i64,
+    pub volume_usd: Option<BigDecimal>,
+    pub volume_decimal: Option<BigDecimal>,
+    pub last_transaction_version: i64,
+    pub last_transaction_timestamp: chrono::NaiveDateTime,
+    pub inserted_at: chrono::NaiveDateTime,
+    /// With last_transaction_version, the latest sale counted
+    pub last_event_index: Option<i64>,
+}
+
+#[derive(Debug, Deserialize, FieldCount, Identifiable, Insertable, Serialize)]
+#[diesel(primary_key(market_address, collection_data_id_hash, coin_type))]
+#[diesel(table_name = marketplace_collection_volumes)]
+pub struct MarketplaceCollectionVolume {
+    pub market_address: String,
+    pub collection_data_id_hash: String,
+    pub coin_type: String,
+    pub volume: BigDecimal,
+    pub trade_count: i64,
+    pub volume_usd: Option<BigDecimal>,
+    pub volume_decimal: Option<BigDecimal>,
+    pub last_transaction_version: i64,
+    pub last_transaction_timestamp: chrono::NaiveDateTime,
+    pub inserted_at: chrono::NaiveDateTime,
+    /// With last_transaction_version, the latest sale counted
+    pub last_event_index: Option<i64>,
+}
+
+/// What a group of sales adds to its row, stamped with the latest sale's version and event index
+struct VolumeTotals {
+    volume: BigDecimal,
+    trade_count: i64,
+    volume_usd: Option<BigDecimal>,
+    volume_decimal: Option<BigDecimal>,
+    last_transaction_version: i64,
+    last_transaction_timestamp: chrono::NaiveDateTime,
+    inserted_at: chrono::NaiveDateTime,
+    last_event_index: i64,
+}
+
+impl VolumeTotals {
+    fn from_sale(sale: &CollectionVolume) -> Self {
+        Self {
+            volume: sale.volume.clone(),
+            trade_count: 1,
+            volume_usd: sale.volume_usd.clone(),
+            volume_decimal: sale.volume_decimal.clone(),
+            last_transaction_version: sale.last_transaction_version,
+            last_transaction_timestamp: sale.last_transaction_timestamp,
+            inserted_at: sale.inserted_at,
+            last_event_index: sale.event_index,
+        }
+    }
+
+    fn add(&mut self, sale: &CollectionVolume) {
+        self.volume += &sale.volume;
+        self.trade_count += 1;
+        self.volume_usd = add_known(self.volume_usd.take(), sale.volume_usd.as_ref());
+        self.volume_decimal = add_known(self.volume_decimal.take(), sale.volume_decimal.as_ref());
+        if (sale.last_transaction_version, sale.event_index)
+            > (self.last_transaction_version, self.last_event_index)
+        {
+            self.last_event_index = sale.event_index;
+        }
+        if sale.last_transaction_version > self.last_transaction_version {
+            self.last_transaction_version = sale.last_transaction_version;
+            self.last_transaction_timestamp = sale.last_transaction_timestamp;
+            self.inserted_at = sale.inserted_at;
+        }
+    }
+}
+
+/// Totals per key, sorted by key so every writer upserts the rows in the same order
+fn totals_by<'a, K: Ord>(
+    sales: &[&'a CollectionVolume],
+    key: impl Fn(&'a CollectionVolume, &'a str, &'a str) -> K,
+) -> BTreeMap<K, VolumeTotals> {
+    let mut totals: BTreeMap<K, VolumeTotals> = BTreeMap::new();
+    for sale in sales.iter().copied() {
+        let (market_address, coin_type) =
+            match (sale.market_address.as_deref(), sale.coin_type.as_deref()) {
+                (Some(market_address), Some(coin_type)) => (market_address, coin_type),
+                _ => continue,
+            };
+        totals
+            .entry(key(sale, market_address, coin_type))
+            .and_modify(|sale_totals| sale_totals.add(sale))
+            .or_insert_with(|| VolumeTotals::from_sale(sale));
+    }
+    totals
+}
+
+/// The rows to add to the three tables for a batch's new sales
+#[derive(Debug, Default)]
+pub struct MarketplaceVolumes {
+    pub current_marketplace_volumes: Vec<CurrentMarketplaceVolume>,
+    pub marketplace_volumes: Vec<MarketplaceVolume>,
+    pub marketplace_collection_volumes: Vec<MarketplaceCollectionVolume>,
+}
+
+impl MarketplaceVolumes {
+    pub fn from_collection_volumes<'a>(
+        collection_volumes: impl IntoIterator<Item = &'a CollectionVolume>,
+    ) -> Self {
+        let sales: Vec<&CollectionVolume> = collection_volumes.into_iter().collect();
+        let current_marketplace_volumes = totals_by(&sales, |_, market_address, coin_type| {
+            (market_address, coin_type)
+        })
+        .into_iter()
+        .map(
+            |((market_address, coin_type), totals)| CurrentMarketplaceVolume {
+                market_address: market_address.to_string(),
+                coin_type: coin_type.to_string(),
+                volume: totals.volume,
+                trade_count: totals.trade_count,
+                volume_usd: totals.volume_usd,
+                volume_decimal: totals.volume_decimal,
+                last_transaction_version: totals.last_transaction_version,
+                last_transaction_timestamp: totals.last_transaction_timestamp,
+                inserted_at: totals.inserted_at,
+                last_event_index: Some(totals.last_event_index),
+            },
+        )
+        .collect();
+        let marketplace_volumes = totals_by(&sales, |sale, market_address, coin_type| {
+            (
+                market_address,
+                coin_type,
+                sale.last_transaction_timestamp.date(),
+            )
+        })
+        .into_iter()
+        .map(
+            |((market_address, coin_type, volume_date), totals)| MarketplaceVolume {
+                market_address: market_address.to_string(),
+                coin_type: coin_type.to_string(),
+                volume_date,
+                volume: totals.volume,
+                trade_count: totals.trade_count,
+                volume_usd: totals.volume_usd,
+                volume_decimal: totals.volume_decimal,
+                last_transaction_version: totals.last_transaction_version,
+                last_transaction_timestamp: totals.last_transaction_timestamp,
+                inserted_at: totals.inserted_at,
+                last_event_index: Some(totals.last_event_index),
+            },
+        )
+        .collect();
+        let marketplace_collection_volumes =
+            totals_by(&sales, |sale, market_address, coin_type| {
+                (
+                    market_address,
+                    sale.collection_data_id_hash.as_str(),
+                    coin_type,
+                )
+            })
+            .into_iter()
+            .map(
+                |((market_address, collection_data_id_hash, coin_type), totals)| {
+                    MarketplaceCollectionVolume {
+                        market_address: market_address.to_string(),
+                        collection_data_id_hash: collection_data_id_hash.to_string(),
+                        coin_type: coin_type.to_string(),
+                        volume: totals.volume,
+                        trade_count: totals.trade_count,
+                        volume_usd: totals.volume_usd,
+                        volume_decimal: totals.volume_decimal,
+                        last_transaction_version: totals.last_transaction_version,
+                        last_transaction_timestamp: totals.last_transaction_timestamp,
+                        inserted_at: totals.inserted_at,
+                        last_event_index: Some(totals.last_event_index),
+                    }
+                },
+            )
+            .collect();
+        Self {
+            current_marketplace_volumes,
+            marketplace_volumes,
+            marketplace_collection_volumes,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn sale(
+        market_address: Option<&str>,
+        collection_data_id_hash: &str,
+        version: i64,
+        hours: i64,
+        volume: i64,
+    ) -> CollectionVolume {
+        let timestamp = chrono::NaiveDateTime::from_timestamp(hours * 3600, 0);
+        CollectionVolume {
+            collection_data_id_hash: collection_data_id_hash.to_string(),
+            volume: BigDecimal::from(volume),
+            inserted_at: timestamp,
+            last_transaction_version: version,
+            last_transaction_timestamp: timestamp,
+            event_index: 0,
+            token_index: 0,
+            is_primary: false,
+            volume_usd: None,
+            volume_decimal: None,
+            market_address: market_address.map(|address| address.to_string()),
+            coin_type: market_address.map(|_| "0x1::aptos_coin::AptosCoin".to_string()),
+        }
+    }
+
+    #[test]
+    fn test_sales_in_one_batch_are_summed() {
+        let sales = vec![
+            sale(Some("0xtopaz"), "a", 3, 1, 10),
+            sale(Some("0xbluemove"), "a", 4, 2, 5),
+            sale(Some("0xtopaz"), "b", 5, 25, 20),
+            sale(None, "a", 6, 26, 100),
+        ];
+        let volumes = MarketplaceVolumes::from_collection_volumes(&sales);
+        let current = volumes
+            .current_marketplace_volumes
+            .iter()
+            .map(|row| {
+                (
+                    row.market_address.as_str(),
+                    row.volume.clone(),
+                    row.trade_count,
+                    row.last_transaction_version,
+                )
+            })
+            .collect::<Vec<_>>();
+        assert_eq!(
+            current,
+            vec![
+                ("0xbluemove", BigDecimal::from(5), 1, 4),
+                ("0xtopaz", BigDecimal::from(30), 2, 5),
+            ]
+        );
+        // Topaz's sales were on two different days
+        let daily = volumes
+            .marketplace_volumes
+            .iter()
+            .map(|row| {
+                (
+                    row.market_address.as_str(),
+                    row.volume_date,
+                    row.trade_count,
+                )
+            })
+            .collect::<Vec<_>>();
+        let day = |days: i64| chrono::NaiveDateTime::from_timestamp(days * 86400, 0).date();
+        assert_eq!(
+            daily,
+            vec![
+                ("0xbluemove", day(0), 1),
+                ("0xtopaz", day(0), 1),
+                ("0xtopaz", day(1), 1),
+            ]
+        );
+        let per_collection = volumes
+            .marketplace_collection_volumes
+            .iter()
+            .map(|row| {
+                (
+                    row.market_address.as_str(),
+                    row.collection_data_id_hash.as_str(),
+                )
+            })
+            .collect::<Vec<_>>();
+        assert_eq!(
+            per_collection,
+            vec![("0xbluemove", "a"), ("0xtopaz", "a"), ("0xtopaz", "b"),]
+        );
+    }
+}
@@ -0,0 +1,214 @@
+// Copyright (c) Aptos
+// SPDX-License-Identifier: Apache-2.0
+
+//! BlueMove's marketplaceV2 module: fixed price listings and auctions. List and change price
+//! events carry the price as their amount, and always list one token.
+
+use super::{MarketplaceAdapter, MarketplaceEvent, ParsedMarketplaceEvent};
+use crate::{
+    models::token_models::{
+        event_effects::TransferKind,
+        token_utils::{TokenEvent, TokenIdType},
+    },
+    util::deserialize_address,
+};
+use anyhow::{Context, Result};
+use aptos_api_types::deserialize_from_string;
+use bigdecimal::{BigDecimal, One, Zero};
+use serde::{Deserialize, Serialize};
+
+pub const ADDRESS: &str = "0xd1fd99c1944b84d1670a2536417e997864ad12303d19eac725891691b04d614e";
+const MODULE: &str = "marketplaceV2";
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct BlueMoveAuctionEventType {
+    pub id: TokenIdType,
+    #[serde(deserialize_with = "deserialize_from_string")]
+    pub min_selling_price: BigDecimal,
+    #[serde(deserialize_with = "deserialize_from_string")]
+    pub duration: BigDecimal,
+    #[serde(deserialize_with = "deserialize_from_string")]
+    pub start_time: BigDecimal,
+    #[serde(deserialize_with = "deserialize_address")]
+    pub owner_address: String,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct BlueBidEventType {
+    pub id: TokenIdType,
+    #[serde(deserialize_with = "deserialize_from_string")]
+    pub bid: BigDecimal,
+    #[serde(deserialize_with = "deserialize_address")]
+    pub bider_address: String,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct BlueBuyEventType {
+    pub id: TokenIdType,
+    #[serde(deserialize_with = "deserialize_address")]
+    pub buyer_address: String,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct BlueChangePriceEventType {
+    pub id: TokenIdType,
+    #[serde(deserialize_with = "deserialize_from_string")]
+    pub amount: BigDecimal,
+    #[serde(deserialize_with = "deserialize_address")]
+    pub seller_address: String,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct BlueClaimCoinsEventType {
+    pub id: TokenIdType,
+    #[serde(deserialize_with = "deserialize_address")]
+    pub owner_token: String,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct BlueClaimTokenEventType {
+    pub id: TokenIdType,
+    #[serde(deserialize_with = "deserialize_address")]
+    pub bider_address: String,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct BlueDelistEventType {
+    pub id: TokenIdType,
+    #[serde(deserialize_with = "deserialize_address")]
+    pub seller_address: String,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct BlueListEventType {
+    pub id: TokenIdType,
+    #[serde(deserialize_with = "deserialize_from_string")]
+    pub amount: BigDecimal,
+    #[serde(deserialize_with = "deserialize_address")]
+    pub seller_address: String,
+    #[serde(deserialize_with = "deserialize_address")]
+    pub royalty_payee: String,
+    #[serde(deserialize_with = "deserialize_from_string")]
+    pub royalty_numerator: BigDecimal,
+    #[serde(deserialize_with = "deserialize_from_string")]
+    pub royalty_denominator: BigDecimal,
+}
+
+pub struct BlueMove;
+
+impl MarketplaceAdapter for BlueMove {
+    fn name(&self) -> &'static str {
+        "bluemove"
+    }
+
+    fn addresses(&self) -> &'static [&'static str] {
+        &[ADDRESS]
+    }
+
+    fn try_parse_event(
+        &self,
+        event_type: &str,
+        data: &serde_json::Value,
+        txn_version: i64,
+    ) -> Result<Option<MarketplaceEvent>> {
+        let name = match event_type
+            .strip_prefix(ADDRESS)
+            .and_then(|rest| rest.strip_prefix("::"))
+            .and_then(|rest| rest.strip_prefix(MODULE))
+            .and_then(|rest| rest.strip_prefix("::"))
+        {
+            Some(name) => name,
+            None => return Ok(None),
+        };
+        match name {
+            "AuctionEvent" => Deserialize::deserialize(data)
+                .map(|inner| Some(TokenEvent::BlueMoveAuctionEvent(inner))),
+            "BidEvent" => {
+                Deserialize::deserialize(data).map(|inner| Some(TokenEvent::BlueBidEvent(inner)))
+            }
+            "BuyEvent" => {
+                Deserialize::deserialize(data).map(|inner| Some(TokenEvent::BlueBuyEvent(inner)))
+            }
+            "ChangePriceEvent" => Deserialize::deserialize(data)
+                .map(|inner| Some(TokenEvent::BlueChangePriceEvent(inner))),
+            "ClaimCoinsEvent" => Deserialize::deserialize(data)
+                .map(|inner| Some(TokenEvent::BlueClaimCoinsEvent(inner))),
+            "ClaimTokenEvent" => Deserialize::deserialize(data)
+                .map(|inner| Some(TokenEvent::BlueClaimTokenEvent(inner))),
+            "DelistEvent" => {
+                Deserialize::deserialize(data).map(|inner| Some(TokenEvent::BlueDelistEvent(inner)))
+            }
+            "ListEvent" => {
+                Deserialize::deserialize(data).map(|inner| Some(TokenEvent::BlueListEvent(inner)))
+            }
+            _ => Ok(None),
+        }
+        .with_context(|| {
+            format!(
+                "version {} failed! failed to parse type {}, data {:?}",
+                txn_version, event_type, data
+            )
+        })
+        .map(|token_event| {
+            token_event
+                .map(|token_event| MarketplaceEvent::from_token_event(token_event, normalize))
+        })
+    }
+}
+
+/// The token of an event parsed by BlueMove, None for other events. BlueMove's events are all
+/// about a single token.
+fn normalize(token_event: &TokenEvent, _token_index: usize) -> Option<ParsedMarketplaceEvent> {
+    let token = |kind: TransferKind, id: &TokenIdType| ParsedMarketplaceEvent {
+        kind,
+        token_data_id: id.token_data_id.clone(),
+        property_version: id.property_version.clone(),
+        from_address: None,
+        to_address: None,
+        token_amount: BigDecimal::zero(),
+        coin_type: None,
+        coin_amount: None,
+        listing: None,
+    };
+    Some(match token_event {
+        TokenEvent::BlueMoveAuctionEvent(inner) => ParsedMarketplaceEvent {
+            from_address: Some(inner.owner_address.clone()),
+            coin_amount: Some(inner.min_selling_price.clone()),
+            ..token(TransferKind::Listing, &inner.id)
+        },
+        TokenEvent::BlueBidEvent(inner) => ParsedMarketplaceEvent {
+            from_address: Some(inner.bider_address.clone()),
+            coin_amount: Some(inner.bid.clone()),
+            ..token(TransferKind::Bid, &inner.id)
+        },
+        TokenEvent::BlueBuyEvent(inner) => ParsedMarketplaceEvent {
+            to_address: Some(inner.buyer_address.clone()),
+            ..token(TransferKind::Sale, &inner.id)
+        },
+        TokenEvent::BlueChangePriceEvent(inner) => ParsedMarketplaceEvent {
+            from_address: Some(inner.seller_address.clone()),
+            coin_amount: Some(inner.amount.clone()),
+            listing: Some((BigDecimal::one(), inner.amount.clone())),
+            ..token(TransferKind::Listing, &inner.id)
+        },
+        TokenEvent::BlueClaimCoinsEvent(inner) => ParsedMarketplaceEvent {
+            from_address: Some(inner.owner_token.clone()),
+            ..token(TransferKind::Claim, &inner.id)
+        },
+        TokenEvent::BlueClaimTokenEvent(inner) => ParsedMarketplaceEvent {
+            to_address: Some(inner.bider_address.clone()),
+            ..token(TransferKind::Claim, &inner.id)
+        },
+        TokenEvent::BlueDelistEvent(inner) => ParsedMarketplaceEvent {
+            from_address: Some(inner.seller_address.clone()),
+            ..token(TransferKind::Delisting, &inner.id)
+        },
+        TokenEvent::BlueListEvent(inner) => ParsedMarketplaceEvent {
+            from_address: Some(inner.seller_address.clone()),
+            token_amount: inner.amount.clone(),
+            listing: Some((BigDecimal::one(), inner.amount.clone())),
+            ..token(TransferKind::Listing, &inner.id)
+        },
+        _ => return None,
+    })
+}
@@ -0,0 +1,327 @@
+// Copyright (c) Aptos
+// SPDX-License-Identifier: Apache-2.0
+
+//! Marketplaces defined in the file at `marketplace_definitions_path` rather than in code. Each
+//! event type of a definition is mapped like the config's `marketplace_event_mappings`, so the
+//! events are parsed without a typed struct, and the definition's sample events are what
+//! `validate-config --validate-marketplace-config` checks the mappings against.
+
+use super::{type_address, MarketplaceAdapters, ParsedMarketplaceEvent};
+use crate::{
+    models::token_models::marketplace_event_mappings::{
+        is_hex_address, CompiledMapping, EventKind, MappedMarketplaceEvent,
+    },
+    util::standardize_address,
+};
+use anyhow::{bail, ensure, Context, Result};
+use aptos_config::config::MarketplaceDefinition;
+use serde::Deserialize;
+use std::{
+    collections::{HashMap, HashSet},
+    path::Path,
+};
+
+/// A marketplace definition, validated and with its event mappings compiled
+#[derive(Clone, Debug)]
+pub struct DeclarativeAdapter {
+    name: String,
+    addresses: Vec<String>,
+    events: HashMap<String, CompiledMapping>,
+}
+
+impl DeclarativeAdapter {
+    /// Fails on malformed addresses, event types declared at other addresses, duplicate event
+    /// types, and the mistakes `MarketplaceEventMappings::from_config` fails on
+    pub fn from_definition(definition: &MarketplaceDefinition) -> Result<Self> {
+        ensure!(!definition.name.is_empty(), "empty marketplace name");
+        ensure!(!definition.addresses.is_empty(), "no addresses");
+        for address in &definition.addresses {
+            ensure!(is_hex_address(address), "invalid address '{}'", address);
+        }
+        let mut events = HashMap::new();
+        for mapping in &definition.events {
+            let address = standardize_address(type_address(&mapping.event_type));
+            ensure!(
+                definition
+                    .addresses
+                    .iter()
+                    .any(|a| standardize_address(a) == address),
+                "{} is not declared at any of the marketplace's addresses",
+                mapping.event_type
+            );
+            let compiled = CompiledMapping::compile(mapping)
+                .with_context(|| format!("invalid event mapping for {}", mapping.event_type))?;
+            ensure!(
+                events
+                    .insert(mapping.event_type.clone(), compiled)
+                    .is_none(),
+                "duplicate event mapping for {}",
+                mapping.event_type
+            );
+        }
+        Ok(Self {
+            name: definition.name.clone(),
+            addresses: definition.addresses.clone(),
+            events,
+        })
+    }
+
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+
+    /// As written in the definition
+    pub fn addresses(&self) -> &[String] {
+        &self.addresses
+    }
+
+    pub fn event_kind(&self, event_type: &str) -> Option<EventKind> {
+        self.events.get(event_type).map(|mapping| mapping.kind)
+    }
+
+    /// Maps an event of one of the marketplace's types, None for other types
+    pub fn try_parse_event(
+        &self,
+        event_type: &str,
+        data: &serde_json::Value,
+        txn_version: i64,
+    ) -> Result<Option<MappedMarketplaceEvent>> {
+        match self.events.get(event_type) {
+            Some(mapping) => mapping.apply(data).map(Some).context(format!(
+                "version {} failed! failed to parse type {}, data {:?}",
+                txn_version, event_type, data
+            )),
+            None => Ok(None),
+        }
+    }
+}
+
+/// Reads a list of marketplace definitions from a .yaml, .yml or .json file
+pub fn read_definitions(path: &Path) -> Result<Vec<MarketplaceDefinition>> {
+    let is_yaml = match path.extension().and_then(|extension| extension.to_str()) {
+        Some("yaml" | "yml") => true,
+        Some("json") => false,
+        _ => bail!("{} should be a .yaml, .yml or .json file", path.display()),
+    };
+    let file =
+        std::fs::File::open(path).with_context(|| format!("Could not open {}", path.display()))?;
+    let reader = std::io::BufReader::new(file);
+    if is_yaml {
+        serde_yaml::from_reader(reader)
+            .with_context(|| format!("Could not parse {}", path.display()))
+    } else {
+        serde_json::from_reader(reader)
+            .with_context(|| format!("Could not parse {}", path.display()))
+    }
+}
+
+/// An event as the API serializes it, which is what sample files hold
+#[derive(Deserialize)]
+struct SampleEvent {
+    #[serde(rename = "type")]
+    event_type: String,
+    data: serde_json::Value,
+}
+
+/// What the sample events of a definitions file parsed into
+#[derive(Debug, Default)]
+pub struct SampleReport {
+    /// (sample path as written in the definition, what it parsed into)
+    pub parsed: Vec<(String, ParsedMarketplaceEvent)>,
+    pub problems: Vec<String>,
+}
+
+/// Parses the samples of every definition in the file, which must be valid as a whole. Samples
+/// that can't be read, aren't of an event type the definition maps or don't parse are problems,
+/// and so are event types without any sample.
+pub fn check_samples(path: &Path) -> Result<SampleReport> {
+    let definitions = read_definitions(path)?;
+    MarketplaceAdapters::default().with_definitions(&definitions)?;
+    // Relative sample paths are relative to the definitions file
+    let dir = path.parent().unwrap_or_else(|| Path::new(""));
+    let mut report = SampleReport::default();
+    for definition in &definitions {
+        let adapter = DeclarativeAdapter::from_definition(definition)
+            .with_context(|| format!("invalid marketplace definition {}", definition.name))?;
+        let mut sampled = HashSet::new();
+        for sample in &definition.samples {
+            let parsed = std::fs::read_to_string(dir.join(sample))
+                .context("Could not read the sample")
+                .and_then(|contents| {
+                    serde_json::from_str::<SampleEvent>(&contents)
+                        .context("Could not parse the sample")
+                })
+                .and_then(|event| {
+                    sampled.insert(event.event_type.clone());
+                    adapter
+                        .try_parse_event(&event.event_type, &event.data, 0)?
+                        .with_context(|| format!("{} is not mapped", event.event_type))
+                });
+            match parsed {
+                Ok(event) => report
+                    .parsed
+                    .push((sample.clone(), ParsedMarketplaceEvent::from(&event))),
+                Err(err) => report.problems.push(format!(
+                    "Sample {} of marketplace {}: {:#}",
+                    sample, definition.name, err
+                )),
+            }
+        }
+        let mut unsampled: Vec<&String> = adapter
+            .events
+            .keys()
+            .filter(|event_type| !sampled.contains(*event_type))
+            .collect();
+        unsampled.sort();
+        for event_type in unsampled {
+            report.problems.push(format!(
+                "Marketplace {} has no sample of {}",
+                definition.name, event_type
+            ));
+        }
+    }
+    Ok(report)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::models::token_models::{
+        event_effects::TransferKind, marketplace_event_mappings::MarketplaceEventMappings,
+        marketplaces::topaz,
+    };
+    use bigdecimal::BigDecimal;
+    use serde_json::json;
+
+    const DEFINITIONS: &str = r#"
+- name: fakemarket
+  addresses: ["0xfa4e"]
+  samples: [purchase.json, listing.json]
+  events:
+    - event_type: "0xfa4e::market::PurchaseEvent"
+      kind: buy
+      creator: token.creator
+      collection: token.collection
+      name: token.name
+      price: cost
+      buyer: buyer
+      seller: seller
+    - event_type: "0xfa4e::market::ListEvent"
+      kind: list
+      creator: token.creator
+      collection: token.collection
+      name: token.name
+      price: cost
+      seller: seller
+    - event_type: "0xfa4e::market::DelistEvent"
+      kind: delist
+      creator: token.creator
+      collection: token.collection
+      name: token.name
+      seller: seller
+"#;
+
+    fn definitions() -> Vec<MarketplaceDefinition> {
+        serde_yaml::from_str(DEFINITIONS).unwrap()
+    }
+
+    #[test]
+    fn test_definitions() {
+        let mappings = MarketplaceEventMappings::default()
+            .with_definitions(&definitions())
+            .unwrap();
+        assert_eq!(
+            mappings.event_kind("0xfa4e::market::PurchaseEvent"),
+            Some(EventKind::Buy)
+        );
+        let data = json!({
+            "token": {"creator": "0xc4e7", "collection": "Fakes", "name": "Fake #1"},
+            "cost": "1000",
+            "buyer": "0xb0b",
+            "seller": "0xa11ce"
+        });
+        let event = mappings
+            .from_event("0xfa4e::market::PurchaseEvent", &data, 1)
+            .unwrap()
+            .unwrap();
+        let parsed = ParsedMarketplaceEvent::from(&event);
+        assert_eq!(parsed.kind, TransferKind::Sale);
+        assert_eq!(parsed.from_address, Some(standardize_address("0xa11ce")));
+        assert_eq!(parsed.coin_amount, Some(BigDecimal::from(1000)));
+
+        // Types at another address, names and addresses that are taken
+        let mut elsewhere = definitions();
+        elsewhere[0].events[0].event_type = "0xbeef::market::PurchaseEvent".to_string();
+        let mut builtin_name = definitions();
+        builtin_name[0].name = "topaz".to_string();
+        let mut builtin_address = definitions();
+        builtin_address[0]
+            .addresses
+            .push(topaz::ADDRESS.to_string());
+        for definitions in [
+            elsewhere,
+            builtin_name,
+            builtin_address,
+            [definitions(), definitions()].concat(),
+        ] {
+            assert!(MarketplaceEventMappings::default()
+                .with_definitions(&definitions)
+                .is_err());
+        }
+        // Nor can the config map a type a definition maps
+        let config_mapping = definitions()[0].events[0].clone();
+        assert!(MarketplaceEventMappings::from_config(&[config_mapping])
+            .unwrap()
+            .with_definitions(&definitions())
+            .is_err());
+    }
+
+    #[test]
+    fn test_check_samples() {
+        let dir = std::env::temp_dir().join(format!("definitions-test-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let path = dir.join("marketplaces.yaml");
+        std::fs::write(&path, DEFINITIONS).unwrap();
+        std::fs::write(
+            dir.join("purchase.json"),
+            json!({
+                "type": "0xfa4e::market::PurchaseEvent",
+                "data": {
+                    "token": {"creator": "0xc4e7", "collection": "Fakes", "name": "Fake #1"},
+                    "cost": "1000",
+                    "buyer": "0xb0b",
+                    "seller": "0xa11ce"
+                }
+            })
+            .to_string(),
+        )
+        .unwrap();
+        // Missing its price
+        std::fs::write(
+            dir.join("listing.json"),
+            json!({
+                "type": "0xfa4e::market::ListEvent",
+                "data": {
+                    "token": {"creator": "0xc4e7", "collection": "Fakes", "name": "Fake #1"},
+                    "seller": "0xa11ce"
+                }
+            })
+            .to_string(),
+        )
+        .unwrap();
+
+        let report = check_samples(&path).unwrap();
+        assert_eq!(report.parsed.len(), 1);
+        assert_eq!(report.parsed[0].0, "purchase.json");
+        assert_eq!(report.problems.len(), 2, "{:?}", report.problems);
+        assert!(report.problems[0].starts_with("Sample listing.json"));
+        assert!(report.problems[1].ends_with("no sample of 0xfa4e::market::DelistEvent"));
+
+        // The same definitions as json
+        let json_path = dir.join("marketplaces.json");
+        std::fs::write(&json_path, serde_json::to_string(&definitions()).unwrap()).unwrap();
+        assert_eq!(read_definitions(&json_path).unwrap(), definitions());
+        assert!(read_definitions(&dir.join("marketplaces.toml")).is_err());
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+}
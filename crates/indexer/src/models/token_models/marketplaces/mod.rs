@@ -0,0 +1,307 @@
+// Copyright (c) Aptos
+// SPDX-License-Identifier: Apache-2.0
+
+//! Marketplaces the indexer parses natively, one MarketplaceAdapter per marketplace. An adapter
+//! owns the marketplace's event types: it parses them into a MarketplaceEvent, one
+//! ParsedMarketplaceEvent per token, which activities, listings and volumes are built from.
+//! Adding a marketplace means adding a module here and an entry in BUILTIN_ADAPTERS. The
+//! marketplaces here today also keep their TokenEvent variants, which bids and offers read as
+//! is, but a new one only needs them if its bids are tracked too. Marketplaces simple enough to
+//! describe in the config don't need an adapter, see marketplace_event_mappings, or can be
+//! defined in a file loaded at startup, see declarative.
+
+pub mod bluemove;
+pub mod declarative;
+pub mod souffl3;
+pub mod topaz;
+
+use super::{
+    event_effects::TransferKind,
+    marketplace_event_mappings::{EventKind, MappedMarketplaceEvent, MappedMarketplaceListing},
+    token_utils::{TokenDataIdType, TokenEvent},
+};
+use crate::util::standardize_address;
+use anyhow::{bail, ensure, Context, Result};
+use aptos_config::config::MarketplaceDefinition;
+use bigdecimal::BigDecimal;
+use declarative::DeclarativeAdapter;
+use std::{collections::HashSet, fmt};
+
+/// Every adapter, in the order they're tried
+pub static BUILTIN_ADAPTERS: &[&dyn MarketplaceAdapter] =
+    &[&bluemove::BlueMove, &topaz::Topaz, &souffl3::Souffl3];
+
+/// One token of a marketplace event, in the terms activities, listings and volumes share
+#[derive(Clone, Debug)]
+pub struct ParsedMarketplaceEvent {
+    pub kind: TransferKind,
+    pub token_data_id: TokenDataIdType,
+    pub property_version: BigDecimal,
+    pub from_address: Option<String>,
+    pub to_address: Option<String>,
+    pub token_amount: BigDecimal,
+    pub coin_type: Option<String>,
+    pub coin_amount: Option<BigDecimal>,
+    /// Amount and price of the listing the event leaves, when they aren't token_amount and
+    /// coin_amount
+    pub listing: Option<(BigDecimal, BigDecimal)>,
+}
+
+/// A marketplace event parsed by its adapter
+#[derive(Clone, Debug)]
+pub struct MarketplaceEvent {
+    /// One per token the event is about, in the event's order
+    pub tokens: Vec<ParsedMarketplaceEvent>,
+    /// The typed event, for marketplaces with TokenEvent variants, None for the others
+    pub token_event: Option<TokenEvent>,
+}
+
+impl MarketplaceEvent {
+    /// A built in marketplace's typed event, with each of its tokens normalized by normalize
+    fn from_token_event(
+        token_event: TokenEvent,
+        normalize: impl Fn(&TokenEvent, usize) -> Option<ParsedMarketplaceEvent>,
+    ) -> Self {
+        Self {
+            tokens: (0..token_event.token_count())
+                .filter_map(|token_index| normalize(&token_event, token_index))
+                .collect(),
+            token_event: Some(token_event),
+        }
+    }
+}
+
+/// Mapped events are about a single token, listed at the event's amount and price
+impl From<&MappedMarketplaceEvent> for ParsedMarketplaceEvent {
+    fn from(event: &MappedMarketplaceEvent) -> Self {
+        Self {
+            kind: TransferKind::from_event_kind(event.kind),
+            token_data_id: event.token_data_id.clone(),
+            property_version: event.property_version.clone(),
+            from_address: event.from_address.clone(),
+            to_address: event.to_address.clone(),
+            token_amount: event.token_amount.clone(),
+            coin_type: event.coin_type.clone(),
+            coin_amount: event.coin_amount.clone(),
+            listing: None,
+        }
+    }
+}
+
+pub trait MarketplaceAdapter: Send + Sync {
+    /// Name of the marketplace in the `marketplace_adapters` config
+    fn name(&self) -> &'static str;
+
+    /// Addresses of the marketplace's modules. Only events and write set changes of types at
+    /// these addresses are passed to the adapter.
+    fn addresses(&self) -> &'static [&'static str];
+
+    /// Parses an event of one of the marketplace's types, None for types the adapter doesn't
+    /// know. A known type whose data doesn't match is an error.
+    fn try_parse_event(
+        &self,
+        event_type: &str,
+        data: &serde_json::Value,
+        txn_version: i64,
+    ) -> Result<Option<MarketplaceEvent>>;
+
+    /// Parses a written listing resource or table item, for marketplaces that change listings
+    /// without an event. None of the built in marketplaces need it, their events are enough.
+    fn try_parse_write_change(
+        &self,
+        _data_type: &str,
+        _data: &serde_json::Value,
+        _txn_version: i64,
+    ) -> Result<Option<MappedMarketplaceListing>> {
+        Ok(None)
+    }
+}
+
+/// The address a type is declared at, ex: 0x3 for 0x3::token::DepositEvent
+fn type_address(data_type: &str) -> &str {
+    data_type.split("::").next().unwrap_or_default()
+}
+
+/// The built in adapters enabled in the config, all of them by default, and the marketplaces
+/// defined in the definitions file
+#[derive(Clone)]
+pub struct MarketplaceAdapters {
+    adapters: Vec<&'static dyn MarketplaceAdapter>,
+    declarative: Vec<DeclarativeAdapter>,
+}
+
+impl Default for MarketplaceAdapters {
+    fn default() -> Self {
+        Self {
+            adapters: BUILTIN_ADAPTERS.to_vec(),
+            declarative: vec![],
+        }
+    }
+}
+
+impl fmt::Debug for MarketplaceAdapters {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_list()
+            .entries(self.adapters.iter().map(|adapter| adapter.name()))
+            .entries(self.declarative.iter().map(|adapter| adapter.name()))
+            .finish()
+    }
+}
+
+impl MarketplaceAdapters {
+    /// Fails on names that aren't a built in adapter
+    pub fn from_config(names: &[String]) -> Result<Self> {
+        Self::default().with_builtin(names)
+    }
+
+    /// Enables only the named built in adapters, keeping the defined marketplaces
+    pub fn with_builtin(mut self, names: &[String]) -> Result<Self> {
+        let mut adapters = vec![];
+        for name in names {
+            match BUILTIN_ADAPTERS
+                .iter()
+                .find(|adapter| adapter.name() == name)
+            {
+                Some(adapter) => adapters.push(*adapter),
+                None => bail!(
+                    "unknown marketplace adapter '{}', expected one of {}",
+                    name,
+                    BUILTIN_ADAPTERS
+                        .iter()
+                        .map(|adapter| adapter.name())
+                        .collect::<Vec<_>>()
+                        .join(", ")
+                ),
+            }
+        }
+        self.adapters = adapters;
+        Ok(self)
+    }
+
+    /// Adds marketplaces defined in a file. Fails on invalid definitions, or names and addresses
+    /// that are already taken, including by built in adapters that aren't enabled, so enabling
+    /// one never changes what a definition parses.
+    pub fn with_definitions(mut self, definitions: &[MarketplaceDefinition]) -> Result<Self> {
+        let mut names: HashSet<String> = BUILTIN_ADAPTERS
+            .iter()
+            .map(|adapter| adapter.name().to_string())
+            .chain(
+                self.declarative
+                    .iter()
+                    .map(|adapter| adapter.name().to_string()),
+            )
+            .collect();
+        let mut addresses: HashSet<String> = BUILTIN_ADAPTERS
+            .iter()
+            .flat_map(|adapter| adapter.addresses().iter().map(|a| standardize_address(a)))
+            .chain(
+                self.declarative
+                    .iter()
+                    .flat_map(|adapter| adapter.addresses().iter().map(|a| standardize_address(a))),
+            )
+            .collect();
+        for definition in definitions {
+            let adapter = DeclarativeAdapter::from_definition(definition)
+                .with_context(|| format!("invalid marketplace definition {}", definition.name))?;
+            ensure!(
+                names.insert(adapter.name().to_string()),
+                "duplicate marketplace name {}",
+                adapter.name()
+            );
+            for address in adapter.addresses() {
+                ensure!(
+                    addresses.insert(standardize_address(address)),
+                    "address {} of marketplace {} belongs to another marketplace",
+                    address,
+                    adapter.name()
+                );
+            }
+            self.declarative.push(adapter);
+        }
+        Ok(self)
+    }
+
+    fn for_type(&self, data_type: &str) -> Option<&'static dyn MarketplaceAdapter> {
+        let address = type_address(data_type);
+        self.adapters
+            .iter()
+            .find(|adapter| adapter.addresses().iter().any(|a| *a == address))
+            .copied()
+    }
+
+    pub fn try_parse_event(
+        &self,
+        event_type: &str,
+        data: &serde_json::Value,
+        txn_version: i64,
+    ) -> Result<Option<MarketplaceEvent>> {
+        match self.for_type(event_type) {
+            Some(adapter) => adapter.try_parse_event(event_type, data, txn_version),
+            None => Ok(None),
+        }
+    }
+
+    /// Maps an event of a type one of the defined marketplaces maps
+    pub fn try_parse_declarative_event(
+        &self,
+        event_type: &str,
+        data: &serde_json::Value,
+        txn_version: i64,
+    ) -> Result<Option<MappedMarketplaceEvent>> {
+        match self
+            .declarative
+            .iter()
+            .find(|adapter| adapter.event_kind(event_type).is_some())
+        {
+            Some(adapter) => adapter.try_parse_event(event_type, data, txn_version),
+            None => Ok(None),
+        }
+    }
+
+    pub fn declarative_event_kind(&self, event_type: &str) -> Option<EventKind> {
+        self.declarative
+            .iter()
+            .find_map(|adapter| adapter.event_kind(event_type))
+    }
+
+    pub fn try_parse_write_change(
+        &self,
+        data_type: &str,
+        data: &serde_json::Value,
+        txn_version: i64,
+    ) -> Result<Option<MappedMarketplaceListing>> {
+        match self.for_type(data_type) {
+            Some(adapter) => adapter.try_parse_write_change(data_type, data, txn_version),
+            None => Ok(None),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_adapters_from_config() {
+        let adapters = MarketplaceAdapters::from_config(&["topaz".to_string()]).unwrap();
+        assert_eq!(format!("{:?}", adapters), r#"["topaz"]"#);
+        let data = serde_json::json!({});
+        // BlueMove's events aren't parsed, not even to fail on their data
+        assert!(adapters
+            .try_parse_event(
+                &format!("{}::marketplaceV2::BuyEvent", bluemove::ADDRESS),
+                &data,
+                1
+            )
+            .unwrap()
+            .is_none());
+        assert!(adapters
+            .try_parse_event(&format!("{}::events::BuyEvent", topaz::ADDRESS), &data, 1)
+            .is_err());
+        assert!(MarketplaceAdapters::from_config(&["opensea".to_string()]).is_err());
+        assert_eq!(
+            format!("{:?}", MarketplaceAdapters::default()),
+            r#"["bluemove", "topaz", "souffl3"]"#
+        );
+    }
+}
@@ -0,0 +1,267 @@
+// Copyright (c) Aptos
+// SPDX-License-Identifier: Apache-2.0
+
+//! Souffl3's FixedPriceMarket and token_coin_swap modules. Its FixedPriceMarketV2 events and
+//! sweeps are deployed at other addresses and registered in the config instead, see
+//! `MarketplaceTypedEventMapping`, but are normalized here all the same.
+
+use super::{MarketplaceAdapter, MarketplaceEvent, ParsedMarketplaceEvent};
+use crate::{
+    models::token_models::{
+        event_effects::TransferKind,
+        token_utils::{TokenEvent, TokenIdType, TypeInfo},
+    },
+    util::{deserialize_address, deserialize_addresses},
+};
+use anyhow::{Context, Result};
+use aptos_api_types::deserialize_from_string;
+use bigdecimal::{BigDecimal, One};
+use serde::{Deserialize, Serialize};
+
+pub const ADDRESS: &str = "0xf6994988bd40261af9431cd6dd3fcf765569719e66322c7a05cc78a89cd366d4";
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct Souffl3BuyTokenEventType {
+    pub id: Souffl3MarketIdType,
+    pub token_id: TokenIdType,
+    #[serde(deserialize_with = "deserialize_from_string")]
+    pub token_amount: BigDecimal,
+    #[serde(deserialize_with = "deserialize_address")]
+    pub buyer: String,
+    #[serde(deserialize_with = "deserialize_address")]
+    pub token_owner: String,
+    #[serde(deserialize_with = "deserialize_from_string")]
+    pub coin_per_token: BigDecimal,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct Souffl3CancelListTokenEventType {
+    pub id: Souffl3MarketIdType,
+    pub token_id: TokenIdType,
+    #[serde(deserialize_with = "deserialize_from_string")]
+    pub token_amount: BigDecimal,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct Souffl3ListTokenEventType {
+    pub id: Souffl3MarketIdType,
+    pub token_id: TokenIdType,
+    #[serde(deserialize_with = "deserialize_address")]
+    pub token_owner: String,
+    #[serde(deserialize_with = "deserialize_from_string")]
+    pub token_amount: BigDecimal,
+    #[serde(deserialize_with = "deserialize_from_string")]
+    pub coin_per_token: BigDecimal,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct Souffl3TokenSwapEventType {
+    pub token_id: TokenIdType,
+    #[serde(deserialize_with = "deserialize_address")]
+    pub token_buyer: String,
+    #[serde(deserialize_with = "deserialize_from_string")]
+    pub token_amount: BigDecimal,
+    #[serde(deserialize_with = "deserialize_from_string")]
+    pub coin_amount: BigDecimal,
+    pub coin_type_info: TypeInfo,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct Souffl3TokenListEventType {
+    pub token_id: TokenIdType,
+    #[serde(deserialize_with = "deserialize_from_string")]
+    pub amount: BigDecimal,
+    #[serde(deserialize_with = "deserialize_from_string")]
+    pub min_price: BigDecimal,
+    #[serde(deserialize_with = "deserialize_from_string")]
+    pub locked_until_secs: BigDecimal,
+    pub coin_type_info: TypeInfo,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct Souffl3MarketIdType {
+    pub market_address: String,
+    pub name: String,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct Souffl3V2BuyTokenEventType {
+    pub id: Souffl3MarketIdType,
+    pub token_id: TokenIdType,
+    #[serde(deserialize_with = "deserialize_from_string")]
+    pub token_amount: BigDecimal,
+    #[serde(deserialize_with = "deserialize_address")]
+    pub buyer: String,
+    #[serde(deserialize_with = "deserialize_address")]
+    pub seller: String,
+    #[serde(deserialize_with = "deserialize_from_string")]
+    pub coin_per_token: BigDecimal,
+    pub coin_type_info: TypeInfo,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct Souffl3V2CancelListTokenEventType {
+    pub id: Souffl3MarketIdType,
+    pub token_id: TokenIdType,
+    #[serde(deserialize_with = "deserialize_address")]
+    pub seller: String,
+    #[serde(deserialize_with = "deserialize_from_string")]
+    pub token_amount: BigDecimal,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct Souffl3V2ListTokenEventType {
+    pub id: Souffl3MarketIdType,
+    pub token_id: TokenIdType,
+    #[serde(deserialize_with = "deserialize_address")]
+    pub seller: String,
+    #[serde(deserialize_with = "deserialize_from_string")]
+    pub token_amount: BigDecimal,
+    #[serde(deserialize_with = "deserialize_from_string")]
+    pub coin_per_token: BigDecimal,
+    pub coin_type_info: TypeInfo,
+}
+
+/// One event for every listing a sweep bought, with the seller and price of token_ids[i] at
+/// sellers[i] and coin_per_tokens[i]. Sweeps buy one of each token.
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct Souffl3SweepBuyEventType {
+    #[serde(deserialize_with = "deserialize_address")]
+    pub buyer: String,
+    pub token_ids: Vec<TokenIdType>,
+    #[serde(deserialize_with = "deserialize_addresses")]
+    pub sellers: Vec<String>,
+    pub coin_per_tokens: Vec<BigDecimal>,
+    pub coin_type_info: TypeInfo,
+}
+
+pub struct Souffl3;
+
+impl MarketplaceAdapter for Souffl3 {
+    fn name(&self) -> &'static str {
+        "souffl3"
+    }
+
+    fn addresses(&self) -> &'static [&'static str] {
+        &[ADDRESS]
+    }
+
+    fn try_parse_event(
+        &self,
+        event_type: &str,
+        data: &serde_json::Value,
+        txn_version: i64,
+    ) -> Result<Option<MarketplaceEvent>> {
+        let name = match event_type.strip_prefix(ADDRESS) {
+            Some(name) => name,
+            None => return Ok(None),
+        };
+        match name {
+            "::FixedPriceMarket::BuyTokenEvent" => Deserialize::deserialize(data)
+                .map(|inner| Some(TokenEvent::Souffl3BuyTokenEvent(inner))),
+            "::FixedPriceMarket::CancelListTokenEvent" => Deserialize::deserialize(data)
+                .map(|inner| Some(TokenEvent::Souffl3CancelListTokenEvent(inner))),
+            "::FixedPriceMarket::ListTokenEvent" => Deserialize::deserialize(data)
+                .map(|inner| Some(TokenEvent::Souffl3ListTokenEvent(inner))),
+            "::token_coin_swap::TokenListingEvent" => Deserialize::deserialize(data)
+                .map(|inner| Some(TokenEvent::Souffl3TokenListEvent(inner))),
+            "::token_coin_swap::TokenSwapEvent" => Deserialize::deserialize(data)
+                .map(|inner| Some(TokenEvent::Souffl3TokenSwapEvent(inner))),
+            _ => Ok(None),
+        }
+        .with_context(|| {
+            format!(
+                "version {} failed! failed to parse type {}, data {:?}",
+                txn_version, event_type, data
+            )
+        })
+        .map(|token_event| {
+            token_event
+                .map(|token_event| MarketplaceEvent::from_token_event(token_event, normalize))
+        })
+    }
+}
+
+/// The token at token_index of an event parsed by Souffl3 or one of its typed parsers, None for
+/// other events
+fn normalize(token_event: &TokenEvent, token_index: usize) -> Option<ParsedMarketplaceEvent> {
+    let token =
+        |kind: TransferKind, id: &TokenIdType, amount: &BigDecimal| ParsedMarketplaceEvent {
+            kind,
+            token_data_id: id.token_data_id.clone(),
+            property_version: id.property_version.clone(),
+            from_address: None,
+            to_address: None,
+            token_amount: amount.clone(),
+            coin_type: None,
+            coin_amount: None,
+            listing: None,
+        };
+    Some(match token_event {
+        TokenEvent::Souffl3BuyTokenEvent(inner) => ParsedMarketplaceEvent {
+            from_address: Some(inner.token_owner.clone()),
+            to_address: Some(inner.buyer.clone()),
+            coin_amount: Some(inner.coin_per_token.clone()),
+            ..token(TransferKind::Sale, &inner.token_id, &inner.token_amount)
+        },
+        TokenEvent::Souffl3CancelListTokenEvent(inner) => token(
+            TransferKind::Delisting,
+            &inner.token_id,
+            &inner.token_amount,
+        ),
+        TokenEvent::Souffl3ListTokenEvent(inner) => ParsedMarketplaceEvent {
+            from_address: Some(inner.token_owner.clone()),
+            coin_amount: Some(inner.coin_per_token.clone()),
+            ..token(TransferKind::Listing, &inner.token_id, &inner.token_amount)
+        },
+        TokenEvent::Souffl3TokenListEvent(inner) => ParsedMarketplaceEvent {
+            coin_type: Some(inner.coin_type_info.to_string()),
+            coin_amount: Some(inner.min_price.clone()),
+            ..token(TransferKind::Listing, &inner.token_id, &inner.amount)
+        },
+        TokenEvent::Souffl3TokenSwapEvent(inner) => ParsedMarketplaceEvent {
+            to_address: Some(inner.token_buyer.clone()),
+            coin_type: Some(inner.coin_type_info.to_string()),
+            coin_amount: Some(inner.coin_amount.clone()),
+            ..token(TransferKind::Sale, &inner.token_id, &inner.token_amount)
+        },
+        TokenEvent::Souffl3V2BuyTokenEvent(inner) => ParsedMarketplaceEvent {
+            from_address: Some(inner.seller.clone()),
+            to_address: Some(inner.buyer.clone()),
+            coin_type: Some(inner.coin_type_info.to_string()),
+            coin_amount: Some(inner.coin_per_token.clone()),
+            ..token(TransferKind::Sale, &inner.token_id, &inner.token_amount)
+        },
+        TokenEvent::Souffl3V2CancelListTokenEvent(inner) => ParsedMarketplaceEvent {
+            from_address: Some(inner.seller.clone()),
+            ..token(
+                TransferKind::Delisting,
+                &inner.token_id,
+                &inner.token_amount,
+            )
+        },
+        TokenEvent::Souffl3V2ListTokenEvent(inner) => ParsedMarketplaceEvent {
+            from_address: Some(inner.seller.clone()),
+            coin_type: Some(inner.coin_type_info.to_string()),
+            coin_amount: Some(inner.coin_per_token.clone()),
+            ..token(TransferKind::Listing, &inner.token_id, &inner.token_amount)
+        },
+        TokenEvent::Souffl3SweepBuyEvent(inner) => ParsedMarketplaceEvent {
+            from_address: Some(inner.sellers[token_index].clone()),
+            to_address: Some(inner.buyer.clone()),
+            coin_type: Some(inner.coin_type_info.to_string()),
+            coin_amount: Some(inner.coin_per_tokens[token_index].clone()),
+            ..token(
+                TransferKind::Sale,
+                &inner.token_ids[token_index],
+                &BigDecimal::one(),
+            )
+        },
+        _ => return None,
+    })
+}
+
+/// An event parsed by one of the typed parsers, see MarketplaceEventParser
+pub fn typed_event(token_event: TokenEvent) -> MarketplaceEvent {
+    MarketplaceEvent::from_token_event(token_event, normalize)
+}
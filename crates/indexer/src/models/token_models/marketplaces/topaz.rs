@@ -0,0 +1,421 @@
+// Copyright (c) Aptos
+// SPDX-License-Identifier: Apache-2.0
+
+//! Topaz: listings that leave the token in the seller's wallet, token bids and collection bids,
+//! and batch buys of several listings in one event.
+
+use super::{MarketplaceAdapter, MarketplaceEvent, ParsedMarketplaceEvent};
+use crate::{
+    models::token_models::{
+        event_effects::TransferKind,
+        token_utils::{TokenDataIdType, TokenEvent, TokenIdType, TypeInfo},
+    },
+    util::{deserialize_address, deserialize_addresses},
+};
+use anyhow::{Context, Result};
+use aptos_api_types::deserialize_from_string;
+use bigdecimal::{BigDecimal, Zero};
+use serde::{Deserialize, Serialize};
+
+pub const ADDRESS: &str = "0x2c7bccf7b31baf770fdbcc768d9e9cb3d87805e255355df5db32ac9a669010a2";
+const MODULE: &str = "events";
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct TopazBidEventType {
+    #[serde(deserialize_with = "deserialize_from_string")]
+    pub timestamp: BigDecimal,
+    #[serde(deserialize_with = "deserialize_from_string")]
+    pub bid_id: BigDecimal,
+    pub token_id: TokenIdType,
+    #[serde(deserialize_with = "deserialize_from_string")]
+    pub deadline: BigDecimal,
+    #[serde(deserialize_with = "deserialize_from_string")]
+    pub price: BigDecimal,
+    pub coin_type: TypeInfo,
+    #[serde(deserialize_with = "deserialize_from_string")]
+    pub amount: BigDecimal,
+    #[serde(deserialize_with = "deserialize_address")]
+    pub buyer: String,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct TopazBuyEventType {
+    #[serde(deserialize_with = "deserialize_from_string")]
+    pub timestamp: BigDecimal,
+    #[serde(deserialize_with = "deserialize_from_string")]
+    pub listing_id: BigDecimal,
+    pub token_id: TokenIdType,
+    #[serde(deserialize_with = "deserialize_from_string")]
+    pub price: BigDecimal,
+    #[serde(deserialize_with = "deserialize_from_string")]
+    pub amount: BigDecimal,
+    #[serde(deserialize_with = "deserialize_address")]
+    pub seller: String,
+    #[serde(deserialize_with = "deserialize_address")]
+    pub buyer: String,
+}
+
+/// Topaz's batch buy, one event for all the listings bought in the transaction. listing_ids[i]
+/// sold amounts[i] of token_ids[i] from sellers[i] for prices[i].
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct TopazBuyAllEventType {
+    #[serde(deserialize_with = "deserialize_from_string")]
+    pub timestamp: BigDecimal,
+    pub listing_ids: Vec<BigDecimal>,
+    pub token_ids: Vec<TokenIdType>,
+    pub prices: Vec<BigDecimal>,
+    pub amounts: Vec<BigDecimal>,
+    #[serde(deserialize_with = "deserialize_addresses")]
+    pub sellers: Vec<String>,
+    #[serde(deserialize_with = "deserialize_address")]
+    pub buyer: String,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct TopazCancelBidEventType {
+    #[serde(deserialize_with = "deserialize_from_string")]
+    pub timestamp: BigDecimal,
+    #[serde(deserialize_with = "deserialize_from_string")]
+    pub bid_id: BigDecimal,
+    pub token_id: TokenIdType,
+    #[serde(deserialize_with = "deserialize_from_string")]
+    pub deadline: BigDecimal,
+    #[serde(deserialize_with = "deserialize_from_string")]
+    pub price: BigDecimal,
+    pub coin_type: TypeInfo,
+    #[serde(deserialize_with = "deserialize_from_string")]
+    pub amount: BigDecimal,
+    #[serde(deserialize_with = "deserialize_address")]
+    pub buyer: String,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct TopazCancelCollectionBidEventType {
+    #[serde(deserialize_with = "deserialize_from_string")]
+    pub timestamp: BigDecimal,
+    #[serde(deserialize_with = "deserialize_from_string")]
+    pub bid_id: BigDecimal,
+    #[serde(deserialize_with = "deserialize_address")]
+    pub creator: String,
+    pub collection_name: String,
+    #[serde(deserialize_with = "deserialize_address")]
+    pub buyer: String,
+    #[serde(deserialize_with = "deserialize_from_string")]
+    pub price: BigDecimal,
+    pub coin_type: TypeInfo,
+    #[serde(deserialize_with = "deserialize_from_string")]
+    pub amount: BigDecimal,
+    #[serde(deserialize_with = "deserialize_from_string")]
+    pub deadline: BigDecimal,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct TopazClaimEventType {
+    #[serde(deserialize_with = "deserialize_from_string")]
+    pub timestamp: BigDecimal,
+    pub token_id: TokenIdType,
+    #[serde(deserialize_with = "deserialize_address")]
+    pub receiver: String,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct TopazCollectionBidEventType {
+    #[serde(deserialize_with = "deserialize_from_string")]
+    pub timestamp: BigDecimal,
+    #[serde(deserialize_with = "deserialize_from_string")]
+    pub bid_id: BigDecimal,
+    #[serde(deserialize_with = "deserialize_address")]
+    pub creator: String,
+    pub collection_name: String,
+    #[serde(deserialize_with = "deserialize_address")]
+    pub buyer: String,
+    #[serde(deserialize_with = "deserialize_from_string")]
+    pub price: BigDecimal,
+    pub coin_type: TypeInfo,
+    #[serde(deserialize_with = "deserialize_from_string")]
+    pub amount: BigDecimal,
+    #[serde(deserialize_with = "deserialize_from_string")]
+    pub deadline: BigDecimal,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct TopazDelistEventType {
+    #[serde(deserialize_with = "deserialize_from_string")]
+    pub timestamp: BigDecimal,
+    #[serde(deserialize_with = "deserialize_from_string")]
+    pub listing_id: BigDecimal,
+    pub token_id: TokenIdType,
+    #[serde(deserialize_with = "deserialize_from_string")]
+    pub price: BigDecimal,
+    #[serde(deserialize_with = "deserialize_from_string")]
+    pub amount: BigDecimal,
+    #[serde(deserialize_with = "deserialize_address")]
+    pub seller: String,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct TopazListEventType {
+    #[serde(deserialize_with = "deserialize_from_string")]
+    pub timestamp: BigDecimal,
+    #[serde(deserialize_with = "deserialize_from_string")]
+    pub listing_id: BigDecimal,
+    pub token_id: TokenIdType,
+    #[serde(deserialize_with = "deserialize_from_string")]
+    pub price: BigDecimal,
+    #[serde(deserialize_with = "deserialize_from_string")]
+    pub amount: BigDecimal,
+    #[serde(deserialize_with = "deserialize_address")]
+    pub seller: String,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct TopazSellEventType {
+    #[serde(deserialize_with = "deserialize_from_string")]
+    pub timestamp: BigDecimal,
+    #[serde(deserialize_with = "deserialize_from_string")]
+    pub bid_id: BigDecimal,
+    pub token_id: TokenIdType,
+    #[serde(deserialize_with = "deserialize_from_string")]
+    pub deadline: BigDecimal,
+    #[serde(deserialize_with = "deserialize_from_string")]
+    pub price: BigDecimal,
+    pub coin_type: TypeInfo,
+    #[serde(deserialize_with = "deserialize_from_string")]
+    pub amount: BigDecimal,
+    #[serde(deserialize_with = "deserialize_address")]
+    pub buyer: String,
+    #[serde(deserialize_with = "deserialize_address")]
+    pub seller: String,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct TopazSendEventType {
+    #[serde(deserialize_with = "deserialize_from_string")]
+    pub timestamp: BigDecimal,
+    pub token_id: TokenIdType,
+    #[serde(deserialize_with = "deserialize_from_string")]
+    pub amount: BigDecimal,
+    #[serde(deserialize_with = "deserialize_address")]
+    pub sender: String,
+    #[serde(deserialize_with = "deserialize_address")]
+    pub receiver: String,
+}
+
+pub struct Topaz;
+
+impl MarketplaceAdapter for Topaz {
+    fn name(&self) -> &'static str {
+        "topaz"
+    }
+
+    fn addresses(&self) -> &'static [&'static str] {
+        &[ADDRESS]
+    }
+
+    fn try_parse_event(
+        &self,
+        event_type: &str,
+        data: &serde_json::Value,
+        txn_version: i64,
+    ) -> Result<Option<MarketplaceEvent>> {
+        let name = match event_type
+            .strip_prefix(ADDRESS)
+            .and_then(|rest| rest.strip_prefix("::"))
+            .and_then(|rest| rest.strip_prefix(MODULE))
+            .and_then(|rest| rest.strip_prefix("::"))
+        {
+            Some(name) => name,
+            None => return Ok(None),
+        };
+        match name {
+            "BidEvent" => {
+                Deserialize::deserialize(data).map(|inner| Some(TokenEvent::TopazBidEvent(inner)))
+            }
+            "BuyEvent" => {
+                Deserialize::deserialize(data).map(|inner| Some(TokenEvent::TopazBuyEvent(inner)))
+            }
+            "BuyAllEvent" => {
+                Deserialize::deserialize(data).and_then(|inner: TopazBuyAllEventType| {
+                    let num_tokens = inner.token_ids.len();
+                    if [
+                        inner.listing_ids.len(),
+                        inner.prices.len(),
+                        inner.amounts.len(),
+                        inner.sellers.len(),
+                    ]
+                    .iter()
+                    .any(|len| *len != num_tokens)
+                    {
+                        return Err(serde::de::Error::custom(
+                            "listing_ids, token_ids, prices, amounts and sellers have different \
+                            lengths",
+                        ));
+                    }
+                    Ok(Some(TokenEvent::TopazBuyAllEvent(inner)))
+                })
+            }
+            "CancelBidEvent" => Deserialize::deserialize(data)
+                .map(|inner| Some(TokenEvent::TopazCancelBidEvent(inner))),
+            "CancelCollectionBidEvent" => Deserialize::deserialize(data)
+                .map(|inner| Some(TokenEvent::TopazCancelCollectionBidEvent(inner))),
+            "ClaimEvent" => {
+                Deserialize::deserialize(data).map(|inner| Some(TokenEvent::TopazClaimEvent(inner)))
+            }
+            "CollectionBidEvent" => Deserialize::deserialize(data)
+                .map(|inner| Some(TokenEvent::TopazCollectionBidEvent(inner))),
+            "DelistEvent" => Deserialize::deserialize(data)
+                .map(|inner| Some(TokenEvent::TopazDelistEvent(inner))),
+            "ListEvent" => {
+                Deserialize::deserialize(data).map(|inner| Some(TokenEvent::TopazListEvent(inner)))
+            }
+            "SellEvent" => {
+                Deserialize::deserialize(data).map(|inner| Some(TokenEvent::TopazSellEvent(inner)))
+            }
+            "SendEvent" => {
+                Deserialize::deserialize(data).map(|inner| Some(TokenEvent::TopazSendEvent(inner)))
+            }
+            _ => Ok(None),
+        }
+        .with_context(|| {
+            format!(
+                "version {} failed! failed to parse type {}, data {:?}",
+                txn_version, event_type, data
+            )
+        })
+        .map(|token_event| {
+            token_event
+                .map(|token_event| MarketplaceEvent::from_token_event(token_event, normalize))
+        })
+    }
+}
+
+/// The token at token_index of an event parsed by Topaz, None for other events
+fn normalize(token_event: &TokenEvent, token_index: usize) -> Option<ParsedMarketplaceEvent> {
+    let token =
+        |kind: TransferKind, id: &TokenIdType, amount: &BigDecimal| ParsedMarketplaceEvent {
+            kind,
+            token_data_id: id.token_data_id.clone(),
+            property_version: id.property_version.clone(),
+            from_address: None,
+            to_address: None,
+            token_amount: amount.clone(),
+            coin_type: None,
+            coin_amount: None,
+            listing: None,
+        };
+    // Collection bids are on any token of the collection
+    let collection =
+        |kind: TransferKind, creator: &str, collection_name: &str| ParsedMarketplaceEvent {
+            kind,
+            token_data_id: TokenDataIdType {
+                creator: creator.to_owned(),
+                collection: collection_name.to_owned(),
+                name: "COLLECTION".to_owned(),
+            },
+            property_version: BigDecimal::zero(),
+            from_address: None,
+            to_address: None,
+            token_amount: BigDecimal::zero(),
+            coin_type: None,
+            coin_amount: None,
+            listing: None,
+        };
+    Some(match token_event {
+        TokenEvent::TopazBidEvent(inner) => ParsedMarketplaceEvent {
+            from_address: Some(inner.buyer.clone()),
+            coin_type: Some(inner.coin_type.to_string()),
+            coin_amount: Some(inner.price.clone()),
+            ..token(TransferKind::Bid, &inner.token_id, &inner.amount)
+        },
+        TokenEvent::TopazBuyEvent(inner) => ParsedMarketplaceEvent {
+            from_address: Some(inner.seller.clone()),
+            to_address: Some(inner.buyer.clone()),
+            coin_amount: Some(inner.price.clone()),
+            ..token(TransferKind::Sale, &inner.token_id, &inner.amount)
+        },
+        TokenEvent::TopazBuyAllEvent(inner) => ParsedMarketplaceEvent {
+            from_address: Some(inner.sellers[token_index].clone()),
+            to_address: Some(inner.buyer.clone()),
+            coin_amount: Some(inner.prices[token_index].clone()),
+            ..token(
+                TransferKind::Sale,
+                &inner.token_ids[token_index],
+                &inner.amounts[token_index],
+            )
+        },
+        TokenEvent::TopazCancelBidEvent(inner) => ParsedMarketplaceEvent {
+            from_address: Some(inner.buyer.clone()),
+            coin_type: Some(inner.coin_type.to_string()),
+            coin_amount: Some(inner.price.clone()),
+            ..token(TransferKind::BidCancel, &inner.token_id, &inner.amount)
+        },
+        TokenEvent::TopazCancelCollectionBidEvent(inner) => ParsedMarketplaceEvent {
+            from_address: Some(inner.buyer.clone()),
+            token_amount: inner.amount.clone(),
+            coin_type: Some(inner.coin_type.to_string()),
+            coin_amount: Some(inner.price.clone()),
+            ..collection(
+                TransferKind::BidCancel,
+                &inner.creator,
+                &inner.collection_name,
+            )
+        },
+        TokenEvent::TopazClaimEvent(inner) => ParsedMarketplaceEvent {
+            to_address: Some(inner.receiver.clone()),
+            ..token(TransferKind::Claim, &inner.token_id, &BigDecimal::zero())
+        },
+        TokenEvent::TopazCollectionBidEvent(inner) => ParsedMarketplaceEvent {
+            from_address: Some(inner.buyer.clone()),
+            token_amount: inner.amount.clone(),
+            coin_type: Some(inner.coin_type.to_string()),
+            coin_amount: Some(inner.price.clone()),
+            ..collection(TransferKind::Bid, &inner.creator, &inner.collection_name)
+        },
+        TokenEvent::TopazDelistEvent(inner) => ParsedMarketplaceEvent {
+            from_address: Some(inner.seller.clone()),
+            coin_amount: Some(inner.price.clone()),
+            ..token(TransferKind::Delisting, &inner.token_id, &inner.amount)
+        },
+        TokenEvent::TopazListEvent(inner) => ParsedMarketplaceEvent {
+            from_address: Some(inner.seller.clone()),
+            coin_amount: Some(inner.price.clone()),
+            ..token(TransferKind::Listing, &inner.token_id, &inner.amount)
+        },
+        TokenEvent::TopazSellEvent(inner) => ParsedMarketplaceEvent {
+            from_address: Some(inner.seller.clone()),
+            to_address: Some(inner.buyer.clone()),
+            coin_type: Some(inner.coin_type.to_string()),
+            coin_amount: Some(inner.price.clone()),
+            ..token(TransferKind::Sale, &inner.token_id, &inner.amount)
+        },
+        TokenEvent::TopazSendEvent(inner) => ParsedMarketplaceEvent {
+            from_address: Some(inner.sender.clone()),
+            to_address: Some(inner.receiver.clone()),
+            ..token(TransferKind::Transfer, &inner.token_id, &inner.amount)
+        },
+        _ => return None,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_buy_all_with_missing_sellers() {
+        let data = serde_json::json!({
+            "timestamp": "1668000000",
+            "listing_ids": ["1", "2"],
+            "token_ids": [
+                {"token_data_id": {"creator": "0xc4e7", "collection": "Potions", "name": "Potion #1"}, "property_version": "0"},
+                {"token_data_id": {"creator": "0xc4e7", "collection": "Potions", "name": "Potion #2"}, "property_version": "0"}
+            ],
+            "prices": ["100", "200"],
+            "amounts": ["1", "1"],
+            "sellers": ["0xa11ce"],
+            "buyer": "0xb0b"
+        });
+        let event = Topaz.try_parse_event(&format!("{}::events::BuyAllEvent", ADDRESS), &data, 1);
+        assert!(event.is_err());
+    }
+}
@@ -0,0 +1,320 @@
+// Copyright (c) Aptos
+// SPDX-License-Identifier: Apache-2.0
+
+//! Normalizes token metadata uris. The same IPFS or Arweave content is referenced as ipfs://CID,
+//! a bare CID, or through any of the public gateways, so the raw uri can't be used to group tokens
+//! by content. The canonical form is ipfs://<cid>[/<path>] or ar://<id>[/<path>] for content
+//! addressed uris, and the parsed url for other http(s) ones.
+
+use super::token_utils::StringLimits;
+use url::Url;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum UriScheme {
+    Ipfs,
+    Arweave,
+    Https,
+    Http,
+    /// data: uris, which have nothing to fetch
+    Data,
+    Empty,
+    /// Anything that couldn't be parsed, including uris longer than the column that were
+    /// truncated
+    Invalid,
+}
+
+impl UriScheme {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            UriScheme::Ipfs => "ipfs",
+            UriScheme::Arweave => "arweave",
+            UriScheme::Https => "https",
+            UriScheme::Http => "http",
+            UriScheme::Data => "data",
+            UriScheme::Empty => "empty",
+            UriScheme::Invalid => "invalid",
+        }
+    }
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct NormalizedUri {
+    pub scheme: UriScheme,
+    /// None unless the uri points at something that can be fetched
+    pub canonical: Option<String>,
+}
+
+impl NormalizedUri {
+    fn new(scheme: UriScheme, canonical: String) -> Self {
+        if StringLimits::current().is_uri_truncated(&canonical) {
+            return Self::without_canonical(UriScheme::Invalid);
+        }
+        Self {
+            scheme,
+            canonical: Some(canonical),
+        }
+    }
+
+    fn without_canonical(scheme: UriScheme) -> Self {
+        Self {
+            scheme,
+            canonical: None,
+        }
+    }
+}
+
+/// Never panics, whatever the input
+pub fn normalize_uri(raw: &str) -> NormalizedUri {
+    let uri = raw.trim();
+    if uri.is_empty() {
+        return NormalizedUri::without_canonical(UriScheme::Empty);
+    }
+    if StringLimits::current().is_uri_truncated(raw) {
+        return NormalizedUri::without_canonical(UriScheme::Invalid);
+    }
+    if strip_prefix_ignore_case(uri, "data:").is_some() {
+        return NormalizedUri::without_canonical(UriScheme::Data);
+    }
+    let normalized = match uri.split_once("://") {
+        Some((scheme, rest)) => match scheme.to_lowercase().as_str() {
+            "ipfs" => {
+                let rest = strip_prefix_ignore_case(rest, "ipfs/").unwrap_or(rest);
+                ipfs_uri(strip_query(rest))
+            }
+            "ar" => arweave_uri(strip_query(rest)),
+            "http" | "https" => http_uri(uri),
+            _ => None,
+        },
+        // A bare CID, or a gateway path without its host
+        None => {
+            let path = uri.trim_start_matches('/');
+            let path = strip_prefix_ignore_case(path, "ipfs/").unwrap_or(path);
+            ipfs_uri(strip_query(path))
+        }
+    };
+    normalized.unwrap_or_else(|| NormalizedUri::without_canonical(UriScheme::Invalid))
+}
+
+fn http_uri(uri: &str) -> Option<NormalizedUri> {
+    let mut url = Url::parse(uri).ok()?;
+    let host = url.host_str()?.to_string();
+    // Path gateways, ex: https://ipfs.io/ipfs/<cid>/1.json
+    if let Some(rest) = url.path().strip_prefix("/ipfs/") {
+        if let Some(normalized) = ipfs_uri(rest) {
+            return Some(normalized);
+        }
+    }
+    // Subdomain gateways, ex: https://<cid>.ipfs.dweb.link/1.json
+    if let Some((cid, _)) = host.split_once(".ipfs.") {
+        if is_cid(cid) {
+            return ipfs_uri(&format!("{}{}", cid, url.path()));
+        }
+    }
+    if host == "arweave.net" || host.ends_with(".arweave.net") {
+        if let Some(normalized) = arweave_uri(url.path().trim_start_matches('/')) {
+            return Some(normalized);
+        }
+    }
+    url.set_fragment(None);
+    let scheme = if url.scheme() == "https" {
+        UriScheme::Https
+    } else {
+        UriScheme::Http
+    };
+    Some(NormalizedUri::new(scheme, url.to_string()))
+}
+
+/// `rest` is <cid>[/<path>]
+fn ipfs_uri(rest: &str) -> Option<NormalizedUri> {
+    let (cid, path) = split_id(rest);
+    if !is_cid(cid) {
+        return None;
+    }
+    // base32 CIDs are case insensitive, base58 ones aren't
+    let cid = if cid.starts_with('Q') {
+        cid.to_string()
+    } else {
+        cid.to_lowercase()
+    };
+    Some(NormalizedUri::new(
+        UriScheme::Ipfs,
+        format!("ipfs://{}{}", cid, path),
+    ))
+}
+
+/// `rest` is <id>[/<path>]
+fn arweave_uri(rest: &str) -> Option<NormalizedUri> {
+    let (id, path) = split_id(rest);
+    if !is_arweave_id(id) {
+        return None;
+    }
+    Some(NormalizedUri::new(
+        UriScheme::Arweave,
+        format!("ar://{}{}", id, path),
+    ))
+}
+
+/// Splits off the id before the first slash. The path keeps its leading slash, and a path that's
+/// only a slash is dropped.
+fn split_id(rest: &str) -> (&str, &str) {
+    match rest.find('/') {
+        Some(index) => {
+            let (id, path) = rest.split_at(index);
+            (id, if path == "/" { "" } else { path })
+        }
+        None => (rest, ""),
+    }
+}
+
+fn strip_query(uri: &str) -> &str {
+    match uri.find(|c| c == '?' || c == '#') {
+        Some(index) => &uri[..index],
+        None => uri,
+    }
+}
+
+fn strip_prefix_ignore_case<'a>(value: &'a str, prefix: &str) -> Option<&'a str> {
+    // get() is None rather than a panic when the prefix length isn't on a char boundary
+    let head = value.get(..prefix.len())?;
+    if head.eq_ignore_ascii_case(prefix) {
+        Some(&value[prefix.len()..])
+    } else {
+        None
+    }
+}
+
+/// CIDv0 (base58 multihash, Qm...) or CIDv1 in base32 (b...)
+fn is_cid(value: &str) -> bool {
+    const BASE58: &str = "123456789ABCDEFGHJKLMNPQRSTUVWXYZabcdefghijkmnopqrstuvwxyz";
+    if value.len() == 46 && value.starts_with("Qm") {
+        return value.chars().all(|c| BASE58.contains(c));
+    }
+    value.len() >= 50
+        && value.starts_with(|c| c == 'b' || c == 'B')
+        && value
+            .chars()
+            .all(|c| matches!(c.to_ascii_lowercase(), 'a'..='z' | '2'..='7'))
+}
+
+/// Arweave transaction ids are 32 bytes in unpadded base64url
+fn is_arweave_id(value: &str) -> bool {
+    value.len() == 43
+        && value
+            .chars()
+            .all(|c| c.is_ascii_alphanumeric() || c == '-' || c == '_')
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use proptest::prelude::*;
+
+    const CID_V0: &str = "QmYwAPJzv5CZsnA625s3Xf2nemtYgPpHdWEz79ojWnPbdG";
+    const CID_V1: &str = "bafybeigdyrzt5sfp7udm7hu76uh7y26nf3efuylqabf3oclgtqy55fbzdi";
+    const AR_ID: &str = "bNbA3TEQVL60xlgCcqdz4ZPHFZ711cZ3hmkpGttDt_U";
+
+    fn canonical(raw: &str) -> (UriScheme, Option<String>) {
+        let normalized = normalize_uri(raw);
+        (normalized.scheme, normalized.canonical)
+    }
+
+    #[test]
+    fn test_ipfs_forms() {
+        let expected = (UriScheme::Ipfs, Some(format!("ipfs://{}/1.json", CID_V0)));
+        for raw in [
+            format!("ipfs://{}/1.json", CID_V0),
+            format!("ipfs://ipfs/{}/1.json", CID_V0),
+            format!("IPFS://{}/1.json", CID_V0),
+            format!("{}/1.json", CID_V0),
+            format!("/ipfs/{}/1.json", CID_V0),
+            format!("https://ipfs.io/ipfs/{}/1.json", CID_V0),
+            format!(
+                "https://gateway.pinata.cloud/ipfs/{}/1.json?filename=1",
+                CID_V0
+            ),
+            format!("  https://cloudflare-ipfs.com/ipfs/{}/1.json  ", CID_V0),
+        ] {
+            assert_eq!(canonical(&raw), expected, "{}", raw);
+        }
+        // Subdomain gateways lowercase the CID
+        assert_eq!(
+            canonical(&format!("https://{}.ipfs.dweb.link/", CID_V1)),
+            (UriScheme::Ipfs, Some(format!("ipfs://{}", CID_V1)))
+        );
+        assert_eq!(
+            canonical(&CID_V1.to_uppercase()),
+            (UriScheme::Ipfs, Some(format!("ipfs://{}", CID_V1)))
+        );
+    }
+
+    #[test]
+    fn test_other_schemes() {
+        let expected = (UriScheme::Arweave, Some(format!("ar://{}", AR_ID)));
+        assert_eq!(canonical(&format!("ar://{}", AR_ID)), expected);
+        assert_eq!(
+            canonical(&format!("https://arweave.net/{}", AR_ID)),
+            expected
+        );
+        assert_eq!(
+            canonical("https://Potions.example:443/meta/1.json#top"),
+            (
+                UriScheme::Https,
+                Some("https://potions.example/meta/1.json".to_string())
+            )
+        );
+        assert_eq!(
+            canonical("http://potions.example/1"),
+            (
+                UriScheme::Http,
+                Some("http://potions.example/1".to_string())
+            )
+        );
+        assert_eq!(
+            canonical("data:application/json;base64,e30="),
+            (UriScheme::Data, None)
+        );
+        assert_eq!(canonical(" "), (UriScheme::Empty, None));
+        for raw in [
+            "potions",
+            "ipfs://not-a-cid/1.json",
+            "ar://short",
+            "ftp://potions.example/1.json",
+            "https://",
+            "ipfs://",
+        ] {
+            assert_eq!(canonical(raw), (UriScheme::Invalid, None), "{}", raw);
+        }
+        // Truncated before it's stored, so what's stored can't be trusted
+        let long_uri = format!(
+            "https://potions.example/{}",
+            "a".repeat(StringLimits::current().uri_length)
+        );
+        assert_eq!(canonical(&long_uri), (UriScheme::Invalid, None));
+    }
+
+    proptest! {
+        #[test]
+        fn test_never_panics(raw in "\\PC*") {
+            let normalized = normalize_uri(&raw);
+            if let Some(canonical) = normalized.canonical {
+                prop_assert!(!StringLimits::current().is_uri_truncated(&canonical));
+            }
+        }
+
+        #[test]
+        fn test_never_panics_near_valid_forms(
+            prefix in prop::sample::select(vec![
+                "", "ipfs://", "ipfs://ipfs/", "ar://", "https://", "http://", "/ipfs/",
+                "https://ipfs.io/ipfs/", "https://arweave.net/", "data:", "IPFS://",
+            ]),
+            rest in "[a-zA-Z0-9_/.:?#%é\\-]{0,80}",
+        ) {
+            let raw = format!("{}{}", prefix, rest);
+            let normalized = normalize_uri(&raw);
+            prop_assert_eq!(normalized.canonical.is_some(), matches!(
+                normalized.scheme,
+                UriScheme::Ipfs | UriScheme::Arweave | UriScheme::Https | UriScheme::Http
+            ));
+        }
+    }
+}
@@ -1,13 +1,57 @@
 // Copyright (c) Aptos
 // SPDX-License-Identifier: Apache-2.0
 
+pub mod account_token_activities;
+pub mod activity_partitions;
+pub mod address_normalization;
 pub mod ans_lookup;
+pub mod ans_sales;
+pub mod coin_decimals;
+pub mod coin_prices;
+pub mod collection_curations;
 pub mod collection_datas;
+pub mod collection_holder_counts;
+pub mod collection_mints;
+pub mod collection_offers;
+pub mod collection_price_stats;
+pub mod collection_rarity;
+pub mod collection_reports;
+pub mod collection_stats_snapshots;
+pub mod consistency_check;
+pub mod deleted_token_resources;
+pub mod event_effects;
+pub mod leaderboards;
+pub mod table_handle_cache;
+pub mod table_item_keys;
+pub mod token_acquisitions;
 pub mod token_activities;
+pub mod token_bids;
 pub mod token_claims;
 pub mod token_datas;
+pub mod token_metadata_cache;
 pub mod token_ownerships;
+pub mod token_properties_flat;
+pub mod token_property_mutations;
+pub mod token_tables;
+pub mod token_transfer_offers;
 pub mod token_utils;
 pub mod tokens;
+pub mod truncated_strings;
+pub mod marketplace_event_mappings;
+pub mod marketplace_listing_price_changes;
 pub mod marketplace_listings;
+pub mod marketplace_volumes;
+pub mod marketplaces;
+pub mod metadata_uri;
+pub mod nft_events;
+pub mod nft_sales;
+pub mod nft_transaction_fees;
+pub mod pruning;
 pub mod collection_volume;
+pub mod volume_reconciliation;
+pub mod volume_recompute;
+pub mod wallet_cost_basis;
+pub mod wallet_nft_stats;
+
+#[cfg(test)]
+mod golden_tests;
@@ -0,0 +1,316 @@
+// Copyright (c) Aptos
+// SPDX-License-Identifier: Apache-2.0
+
+//! Token activities classified into what happened to the token, with the fields that matter for
+//! each kind of event. This is what the token processor builds sales from, and what
+//! `token_stream` hands to services embedding the parser, so both see the same events.
+
+use super::{nft_sales::is_sale_event, token_activities::TokenActivity};
+use bigdecimal::BigDecimal;
+use serde::{Deserialize, Serialize};
+
+/// The token an event is about. Collection bids aren't about a single token, their name is
+/// "COLLECTION".
+#[derive(Clone, Debug, Deserialize, PartialEq, Eq, Serialize)]
+pub struct NftToken {
+    pub token_data_id_hash: String,
+    pub collection_data_id_hash: String,
+    pub creator_address: String,
+    pub collection_name: String,
+    pub name: String,
+    pub property_version: BigDecimal,
+}
+
+/// Where an event comes from, shared by every kind of event
+#[derive(Clone, Debug, Deserialize, PartialEq, Eq, Serialize)]
+pub struct NftEventContext {
+    pub transaction_version: i64,
+    pub transaction_timestamp: chrono::NaiveDateTime,
+    pub event_account_address: String,
+    pub event_creation_number: i64,
+    pub event_sequence_number: i64,
+    /// Position of the event within the transaction
+    pub event_index: i64,
+    /// Position of the token within the event, only sweeps have more than one
+    pub token_index: i64,
+    pub event_type: String,
+    /// Address of the module that emitted the event, i.e. the marketplace for marketplace events
+    pub market_address: String,
+    pub token: NftToken,
+}
+
+/// Prices are in the smallest unit of coin_type, APT when it's None
+#[derive(Clone, Debug, Deserialize, PartialEq, Eq, Serialize)]
+pub enum ParsedNftEvent {
+    Sale {
+        context: NftEventContext,
+        seller: Option<String>,
+        buyer: Option<String>,
+        token_amount: BigDecimal,
+        coin_type: Option<String>,
+        price: Option<BigDecimal>,
+    },
+    /// Includes auctions being opened
+    Listing {
+        context: NftEventContext,
+        seller: Option<String>,
+        token_amount: BigDecimal,
+        coin_type: Option<String>,
+        price: Option<BigDecimal>,
+    },
+    Delisting {
+        context: NftEventContext,
+        seller: Option<String>,
+    },
+    /// Bids on a token and on a whole collection, and auction bids
+    Bid {
+        context: NftEventContext,
+        bidder: Option<String>,
+        token_amount: BigDecimal,
+        coin_type: Option<String>,
+        price: Option<BigDecimal>,
+    },
+    /// Framework deposits and withdrawals only have the side whose event it is, so a transfer
+    /// between wallets is a withdrawal followed by a deposit. A deposit paired with a withdrawal
+    /// in the same transaction also has the withdrawal's address as from_address.
+    Transfer {
+        context: NftEventContext,
+        from_address: Option<String>,
+        to_address: Option<String>,
+        token_amount: BigDecimal,
+    },
+    Mint {
+        context: NftEventContext,
+        minter: Option<String>,
+        token_amount: BigDecimal,
+    },
+    Burn {
+        context: NftEventContext,
+        owner: Option<String>,
+        token_amount: BigDecimal,
+    },
+}
+
+/// The event's struct name, without the module or type arguments
+fn event_name(event_type: &str) -> &str {
+    let event_type = event_type.split('<').next().unwrap_or_default();
+    event_type.rsplit("::").next().unwrap_or_default()
+}
+
+impl ParsedNftEvent {
+    /// Events are classified by their type name, the same way for typed and configured
+    /// marketplace events. Offers, cancelled bids, price changes and property map mutations
+    /// aren't any of the kinds and are left out.
+    pub fn from_token_activity(activity: &TokenActivity) -> Option<Self> {
+        let event_type = activity.transfer_type.as_str();
+        let name = event_name(event_type);
+        let context = NftEventContext {
+            transaction_version: activity.transaction_version,
+            transaction_timestamp: activity.transaction_timestamp,
+            event_account_address: activity.event_account_address.clone(),
+            event_creation_number: activity.event_creation_number,
+            event_sequence_number: activity.event_sequence_number,
+            event_index: activity.event_index,
+            token_index: activity.token_index,
+            event_type: event_type.to_string(),
+            market_address: event_type
+                .split("::")
+                .next()
+                .unwrap_or_default()
+                .to_string(),
+            token: NftToken {
+                token_data_id_hash: activity.token_data_id_hash.clone(),
+                collection_data_id_hash: activity.collection_data_id_hash.clone(),
+                creator_address: activity.creator_address.clone(),
+                collection_name: activity.collection_name.clone(),
+                name: activity.name.clone(),
+                property_version: activity.property_version.clone(),
+            },
+        };
+        let event = if is_sale_event(event_type) {
+            Self::Sale {
+                context,
+                seller: activity.from_address.clone(),
+                buyer: activity.to_address.clone(),
+                token_amount: activity.token_amount.clone(),
+                coin_type: activity.coin_type.clone(),
+                price: activity.coin_amount.clone(),
+            }
+        } else if name.contains("Delist") || name.contains("CancelList") {
+            Self::Delisting {
+                context,
+                seller: activity.from_address.clone(),
+            }
+        } else if name.contains("Cancel") {
+            return None;
+        } else if name.contains("List") || name.contains("Auction") {
+            Self::Listing {
+                context,
+                seller: activity.from_address.clone(),
+                token_amount: activity.token_amount.clone(),
+                coin_type: activity.coin_type.clone(),
+                price: activity.coin_amount.clone(),
+            }
+        } else if name.contains("Bid") {
+            Self::Bid {
+                context,
+                bidder: activity.from_address.clone(),
+                token_amount: activity.token_amount.clone(),
+                coin_type: activity.coin_type.clone(),
+                price: activity.coin_amount.clone(),
+            }
+        } else if name.contains("Mint") {
+            Self::Mint {
+                context,
+                minter: activity.from_address.clone(),
+                token_amount: activity.token_amount.clone(),
+            }
+        } else if name.contains("Burn") {
+            Self::Burn {
+                context,
+                owner: activity.from_address.clone(),
+                token_amount: activity.token_amount.clone(),
+            }
+        } else if matches!(
+            name,
+            "WithdrawEvent"
+                | "DepositEvent"
+                | "TokenClaimEvent"
+                | "ClaimEvent"
+                | "ClaimTokenEvent"
+                | "SendEvent"
+        ) {
+            Self::Transfer {
+                context,
+                from_address: activity.from_address.clone(),
+                to_address: activity.to_address.clone(),
+                token_amount: activity.token_amount.clone(),
+            }
+        } else {
+            return None;
+        };
+        Some(event)
+    }
+
+    /// In the same order as the activities
+    pub fn from_token_activities(activities: &[TokenActivity]) -> Vec<Self> {
+        activities
+            .iter()
+            .filter_map(Self::from_token_activity)
+            .collect()
+    }
+
+    pub fn context(&self) -> &NftEventContext {
+        match self {
+            Self::Sale { context, .. }
+            | Self::Listing { context, .. }
+            | Self::Delisting { context, .. }
+            | Self::Bid { context, .. }
+            | Self::Transfer { context, .. }
+            | Self::Mint { context, .. }
+            | Self::Burn { context, .. } => context,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::models::token_models::{
+        marketplace_event_mappings::MarketplaceEventMappings, token_utils::TokenEvents,
+    };
+    use aptos_api_types::Transaction as APITransaction;
+
+    fn parse(name: &str) -> Vec<ParsedNftEvent> {
+        let path = format!(
+            "{}/fixtures/golden/transactions/{}.json",
+            env!("CARGO_MANIFEST_DIR"),
+            name
+        );
+        let transaction: APITransaction =
+            serde_json::from_str(&std::fs::read_to_string(path).unwrap()).unwrap();
+        let token_events = TokenEvents::from_transaction(&transaction).unwrap();
+        let activities = TokenActivity::from_transaction(
+            &transaction,
+            &token_events,
+            &MarketplaceEventMappings::default(),
+        );
+        ParsedNftEvent::from_token_activities(&activities)
+    }
+
+    #[test]
+    fn test_sale_with_transfer() {
+        let events = parse("topaz_buy");
+        assert_eq!(events.len(), 3);
+        assert!(matches!(
+            &events[0],
+            ParsedNftEvent::Transfer {
+                from_address: Some(_),
+                to_address: None,
+                ..
+            }
+        ));
+        // The deposit is paired with the withdrawal
+        match (&events[0], &events[1]) {
+            (
+                ParsedNftEvent::Transfer {
+                    from_address: withdrawn_from,
+                    ..
+                },
+                ParsedNftEvent::Transfer {
+                    from_address,
+                    to_address: Some(_),
+                    ..
+                },
+            ) => assert_eq!(from_address, withdrawn_from),
+            events => panic!("expected two transfers, got {:?}", events),
+        }
+        match &events[2] {
+            ParsedNftEvent::Sale {
+                context,
+                seller,
+                buyer,
+                price,
+                ..
+            } => {
+                assert_eq!(
+                    context.market_address,
+                    "0x2c7bccf7b31baf770fdbcc768d9e9cb3d87805e255355df5db32ac9a669010a2"
+                );
+                assert_eq!(context.token.name, "AptosMonkeys #1432");
+                assert_eq!(
+                    seller.as_deref(),
+                    Some(events[0].context().event_account_address.as_str())
+                );
+                assert!(buyer.is_some());
+                assert_eq!(price, &Some(BigDecimal::from(100000000)));
+            }
+            event => panic!("expected a sale, got {:?}", event),
+        }
+    }
+
+    #[test]
+    fn test_marketplace_events() {
+        assert!(matches!(
+            parse("bluemove_list").as_slice(),
+            [ParsedNftEvent::Listing { .. }]
+        ));
+        assert!(matches!(
+            parse("bluemove_buy").as_slice(),
+            [ParsedNftEvent::Sale { .. }]
+        ));
+        match parse("topaz_collection_bid").as_slice() {
+            [ParsedNftEvent::Bid { context, .. }] => assert_eq!(context.token.name, "COLLECTION"),
+            events => panic!("expected a bid, got {:?}", events),
+        }
+    }
+
+    #[test]
+    fn test_event_name() {
+        assert_eq!(event_name("0x3::token::DepositEvent"), "DepositEvent");
+        assert_eq!(
+            event_name("0xf6::token_coin_swap::TokenSwapEvent<0x1::aptos_coin::AptosCoin>"),
+            "TokenSwapEvent"
+        );
+    }
+}
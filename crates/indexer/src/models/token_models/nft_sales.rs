@@ -0,0 +1,905 @@
+// Copyright (c) Aptos
+// SPDX-License-Identifier: Apache-2.0
+
+// This is required because a diesel macro makes clippy sad
+#![allow(clippy::extra_unused_lifetimes)]
+#![allow(clippy::unused_unit)]
+
+use super::{
+    collection_mints::CollectionMint,
+    collection_reports::canonicalize_coin_type,
+    event_effects::MarketEffect,
+    marketplace_event_mappings::MarketplaceEventMappings,
+    nft_events::ParsedNftEvent,
+    token_activities::{TokenActivity, DEPOSIT_EVENT_TYPE},
+    token_ownerships::TokenOwnership,
+    token_utils::ValueLimits,
+};
+use crate::{
+    database::PgPoolConnection,
+    models::coin_models::{
+        coin_activities::EventToCoinType,
+        coin_balances::CoinBalance,
+        coin_utils::{CoinEvent, EventGuidResource},
+    },
+    schema::{current_token_ownerships, nft_sales},
+    util::{parse_timestamp, standardize_address, u64_to_bigdecimal},
+};
+use aptos_api_types::{
+    Transaction as APITransaction, TransactionPayload, WriteSetChange as APIWriteSetChange,
+};
+use aptos_types::APTOS_COIN_TYPE;
+use bigdecimal::{BigDecimal, Zero};
+use diesel::{ExpressionMethods, QueryDsl, QueryResult, RunQueryDsl};
+use field_count::FieldCount;
+use serde::{Deserialize, Serialize};
+use std::collections::{HashMap, HashSet};
+
+type TokenDataIdHash = String;
+/// Canonical coin type, None if the transaction didn't write the coin store the event is from
+type CoinType = Option<String>;
+
+/// Sale parsed from a marketplace event
+pub const EVENT_SOURCE: &str = "event";
+/// Sale inferred from the entry function of a marketplace without sale events, see
+/// NftSale::from_payload
+pub const PAYLOAD_INFERRED_SOURCE: &str = "payload_inferred";
+
+/// One row per token sold by a marketplace sale event, along with the gas market context of the
+/// transaction
+#[derive(Clone, Debug, Deserialize, FieldCount, Identifiable, Insertable, Serialize)]
+#[diesel(primary_key(
+    transaction_version,
+    event_account_address,
+    event_creation_number,
+    event_sequence_number,
+    token_index
+))]
+#[diesel(table_name = nft_sales)]
+pub struct NftSale {
+    pub transaction_version: i64,
+    pub event_account_address: String,
+    pub event_creation_number: i64,
+    pub event_sequence_number: i64,
+    /// Position of the event within the transaction
+    pub event_index: i64,
+    /// Position of the token within the event, a sweep has one sale per token
+    pub token_index: i64,
+    pub market_address: String,
+    pub event_type: String,
+    pub token_data_id_hash: String,
+    pub property_version: BigDecimal,
+    pub collection_data_id_hash: String,
+    pub creator_address: String,
+    pub collection_name: String,
+    pub name: String,
+    pub seller: Option<String>,
+    pub buyer: Option<String>,
+    pub token_amount: BigDecimal,
+    pub coin_type: Option<String>,
+    pub price: Option<BigDecimal>,
+    pub gas_unit_price: BigDecimal,
+    pub transaction_rank_in_block: Option<i64>,
+    pub transaction_timestamp: chrono::NaiveDateTime,
+    pub is_primary: bool,
+    /// Set when the seller's cost basis for the token is known, see WalletTokenCostBasis
+    pub realized_pnl: Option<BigDecimal>,
+    /// Seconds since the seller acquired the token, see TokenAcquisition
+    pub hold_duration_secs: Option<i64>,
+    /// EVENT_SOURCE or PAYLOAD_INFERRED_SOURCE
+    pub source: String,
+    /// What the buyer actually paid, which can differ from the declared price (ex: royalties
+    /// paid on top, fee discounts). Only set with record_settlement_amounts, see
+    /// NftSale::set_settlement_amounts
+    pub settlement_amount: Option<BigDecimal>,
+    /// Latest USD price of the coin when the sale was processed, see CoinPrices::price_sales
+    pub coin_price_usd: Option<BigDecimal>,
+    /// price in USD at coin_price_usd, null when either is unknown
+    pub price_usd: Option<BigDecimal>,
+    /// price in units of the coin, null when its decimals aren't known, see CoinDecimals
+    pub price_decimal: Option<BigDecimal>,
+    /// Sales in the same transaction, ex: a sweep, share a group, see NftSale::set_sale_groups
+    pub sale_group_id: i64,
+    pub group_size: i64,
+    /// The ANS domain sold, see AnsDomains::set_sale_domains
+    pub domain: Option<String>,
+    /// Set when the price or amount is out of bounds, see TokenEventEffects::suspect_value. Such
+    /// sales don't count towards any volume, report or leaderboard.
+    pub suspect_value: bool,
+}
+
+/// Same rule that decides whether an event counts towards collection volume and ends its listing
+pub fn is_sale_event(event_type: &str) -> bool {
+    MarketEffect::from_event_type(event_type) == Some(MarketEffect::Sale)
+}
+
+/// Tracks block boundaries while walking a batch in version order. A batch that starts in the
+/// middle of a block has no known boundary until the next BlockMetadata transaction.
+#[derive(Debug, Default)]
+pub struct BlockPosition {
+    block_start_version: Option<i64>,
+}
+
+impl BlockPosition {
+    /// Position of the transaction within its block, with the BlockMetadata transaction at 0
+    pub fn rank(&mut self, transaction: &APITransaction) -> Option<i64> {
+        let version = transaction.version()? as i64;
+        if let APITransaction::BlockMetadataTransaction(_) = transaction {
+            self.block_start_version = Some(version);
+        }
+        self.block_start_version
+            .map(|block_start_version| version - block_start_version)
+    }
+}
+
+impl NftSale {
+    /// Sales are picked out of the activities by ParsedNftEvent, same as for token_stream
+    pub fn from_token_activities(
+        transaction: &APITransaction,
+        token_activities: &[TokenActivity],
+        transaction_rank_in_block: Option<i64>,
+    ) -> Vec<Self> {
+        let gas_unit_price = match transaction {
+            APITransaction::UserTransaction(user_txn) => {
+                u64_to_bigdecimal(user_txn.request.gas_unit_price.0)
+            }
+            _ => return vec![],
+        };
+        let suspect_events = token_activities
+            .iter()
+            .filter(|activity| activity.suspect_value)
+            .map(|activity| (activity.event_index, activity.token_index))
+            .collect::<HashSet<(i64, i64)>>();
+        ParsedNftEvent::from_token_activities(token_activities)
+            .into_iter()
+            .filter_map(|event| match event {
+                ParsedNftEvent::Sale {
+                    context,
+                    seller,
+                    buyer,
+                    token_amount,
+                    coin_type,
+                    price,
+                } => Some(Self {
+                    transaction_version: context.transaction_version,
+                    event_account_address: context.event_account_address,
+                    event_creation_number: context.event_creation_number,
+                    event_sequence_number: context.event_sequence_number,
+                    event_index: context.event_index,
+                    token_index: context.token_index,
+                    market_address: context.market_address,
+                    event_type: context.event_type,
+                    token_data_id_hash: context.token.token_data_id_hash,
+                    property_version: context.token.property_version,
+                    collection_data_id_hash: context.token.collection_data_id_hash,
+                    creator_address: context.token.creator_address,
+                    collection_name: context.token.collection_name,
+                    name: context.token.name,
+                    seller,
+                    buyer,
+                    token_amount,
+                    coin_type,
+                    price,
+                    gas_unit_price: gas_unit_price.clone(),
+                    transaction_rank_in_block,
+                    transaction_timestamp: context.transaction_timestamp,
+                    is_primary: false,
+                    realized_pnl: None,
+                    hold_duration_secs: None,
+                    source: EVENT_SOURCE.to_string(),
+                    settlement_amount: None,
+                    coin_price_usd: None,
+                    price_usd: None,
+                    price_decimal: None,
+                    sale_group_id: context.transaction_version,
+                    group_size: 1,
+                    domain: None,
+                    suspect_value: suspect_events
+                        .contains(&(context.event_index, context.token_index)),
+                }),
+                _ => None,
+            })
+            .collect()
+    }
+
+    /// Infers the sale of a marketplace that only emits the framework's events, when the
+    /// transaction calls one of its configured entry functions. The token is the one deposited,
+    /// the buyer is the account it's deposited to and the price is the APT the buyer paid, so
+    /// transactions with more than one token deposit aren't inferred. Transactions that already
+    /// have a sale event are left to it.
+    pub fn from_payload(
+        transaction: &APITransaction,
+        token_activities: &[TokenActivity],
+        marketplace_event_mappings: &MarketplaceEventMappings,
+        transaction_rank_in_block: Option<i64>,
+    ) -> Option<Self> {
+        let user_txn = match transaction {
+            APITransaction::UserTransaction(user_txn) => user_txn,
+            _ => return None,
+        };
+        let market_address =
+            marketplace_event_mappings.market_from_payload(&user_txn.request.payload)?;
+        if token_activities
+            .iter()
+            .any(|activity| is_sale_event(&activity.transfer_type))
+        {
+            return None;
+        }
+        let mut deposits = token_activities
+            .iter()
+            .filter(|activity| activity.transfer_type == DEPOSIT_EVENT_TYPE);
+        let deposit = match (deposits.next(), deposits.next()) {
+            (Some(deposit), None) => deposit,
+            _ => return None,
+        };
+        let txn_version = user_txn.info.version.0 as i64;
+        // get_apt_paid compares with event guids, so this has to be the API's short form
+        let buyer = user_txn
+            .events
+            .get(deposit.event_index as usize)?
+            .guid
+            .account_address
+            .to_string();
+        let price = CollectionMint::get_apt_paid(
+            user_txn,
+            &buyer,
+            txn_version,
+            parse_timestamp(user_txn.timestamp.0, txn_version),
+        )?;
+        let suspect_value =
+            deposit.suspect_value || ValueLimits::current().is_suspect_price(&price);
+        let event_type = match &user_txn.request.payload {
+            TransactionPayload::EntryFunctionPayload(payload) => format!(
+                "{}::{}::{}",
+                market_address, payload.function.module.name, payload.function.name
+            ),
+            _ => return None,
+        };
+        Some(Self {
+            transaction_version: deposit.transaction_version,
+            event_account_address: deposit.event_account_address.clone(),
+            event_creation_number: deposit.event_creation_number,
+            event_sequence_number: deposit.event_sequence_number,
+            event_index: deposit.event_index,
+            token_index: deposit.token_index,
+            market_address,
+            event_type,
+            token_data_id_hash: deposit.token_data_id_hash.clone(),
+            property_version: deposit.property_version.clone(),
+            collection_data_id_hash: deposit.collection_data_id_hash.clone(),
+            creator_address: deposit.creator_address.clone(),
+            collection_name: deposit.collection_name.clone(),
+            name: deposit.name.clone(),
+            // Set from the withdrawal paired with the deposit, if there's one
+            seller: deposit.from_address.clone(),
+            buyer: deposit.to_address.clone(),
+            token_amount: deposit.token_amount.clone(),
+            coin_type: Some(APTOS_COIN_TYPE.to_string()),
+            price: Some(price),
+            gas_unit_price: u64_to_bigdecimal(user_txn.request.gas_unit_price.0),
+            transaction_rank_in_block,
+            transaction_timestamp: deposit.transaction_timestamp,
+            is_primary: false,
+            realized_pnl: None,
+            hold_duration_secs: None,
+            source: PAYLOAD_INFERRED_SOURCE.to_string(),
+            settlement_amount: None,
+            coin_price_usd: None,
+            price_usd: None,
+            price_decimal: None,
+            sale_group_id: deposit.transaction_version,
+            group_size: 1,
+            domain: None,
+            suspect_value,
+        })
+    }
+
+    /// Groups the sales of one transaction, ex: a sweep buying several tokens at once. The group
+    /// is the transaction's version and group_size its number of sales, so a single sale has a
+    /// group of 1.
+    pub fn set_sale_groups(sales: &mut [NftSale]) {
+        let group_size = sales.len() as i64;
+        for sale in sales.iter_mut() {
+            sale.sale_group_id = sale.transaction_version;
+            sale.group_size = group_size;
+        }
+    }
+
+    /// Sets settlement_amount on the transaction's sales from the coin WithdrawEvents on each
+    /// buyer's account. Coin events don't carry the coin type, so it comes from the CoinStore
+    /// resources the transaction wrote. A buyer with several sales has the total split across
+    /// them by declared price.
+    pub fn set_settlement_amounts(transaction: &APITransaction, sales: &mut [NftSale]) {
+        let user_txn = match transaction {
+            APITransaction::UserTransaction(user_txn) => user_txn,
+            _ => return,
+        };
+        if sales.is_empty() {
+            return;
+        }
+        let txn_version = user_txn.info.version.0 as i64;
+        let txn_timestamp = parse_timestamp(user_txn.timestamp.0, txn_version);
+        let mut event_to_coin_type: EventToCoinType = HashMap::new();
+        for wsc in &user_txn.info.changes {
+            if let APIWriteSetChange::WriteResource(write_resource) = wsc {
+                if let Some((_, _, mapping)) =
+                    CoinBalance::from_write_resource(write_resource, txn_version, txn_timestamp)
+                        .unwrap()
+                {
+                    event_to_coin_type.extend(mapping);
+                }
+            }
+        }
+        let mut withdrawn: HashMap<String, HashMap<CoinType, BigDecimal>> = HashMap::new();
+        let mut received = HashSet::new();
+        for event in &user_txn.events {
+            let coin_event =
+                match CoinEvent::from_event(&event.typ.to_string(), &event.data, txn_version)
+                    .unwrap()
+                {
+                    Some(coin_event) => coin_event,
+                    None => continue,
+                };
+            let event_guid = EventGuidResource {
+                addr: event.guid.account_address.to_string(),
+                creation_num: event.guid.creation_number.0 as i64,
+            };
+            let owner = standardize_address(&event_guid.addr);
+            match coin_event {
+                CoinEvent::WithdrawCoinEvent(inner) => {
+                    let coin_type = event_to_coin_type
+                        .get(&event_guid)
+                        .map(|coin_type| canonicalize_coin_type(Some(coin_type)));
+                    *withdrawn
+                        .entry(owner)
+                        .or_default()
+                        .entry(coin_type)
+                        .or_insert_with(BigDecimal::zero) += inner.amount;
+                }
+                CoinEvent::DepositCoinEvent(_) => {
+                    received.insert(owner);
+                }
+            }
+        }
+
+        let mut sales_by_buyer: HashMap<String, Vec<usize>> = HashMap::new();
+        for (index, sale) in sales.iter().enumerate() {
+            if let Some(buyer) = &sale.buyer {
+                sales_by_buyer.entry(buyer.clone()).or_default().push(index);
+            }
+        }
+        for (buyer, indices) in sales_by_buyer {
+            let declared = indices
+                .iter()
+                .map(|index| {
+                    let sale = &sales[*index];
+                    (
+                        canonicalize_coin_type(sale.coin_type.as_deref()),
+                        sale.price.clone(),
+                    )
+                })
+                .collect::<Vec<_>>();
+            let settlement_amounts =
+                apportion_settlement(&declared, withdrawn.get(&buyer), received.contains(&buyer));
+            for (position, index) in indices.into_iter().enumerate() {
+                sales[index].settlement_amount = settlement_amounts
+                    .as_ref()
+                    .map(|amounts| amounts[position].clone());
+            }
+        }
+    }
+}
+
+/// Splits what a buyer withdrew across their sales, given as (canonical coin type, declared
+/// price), in proportion to the declared prices. The last sale gets the rounding remainder.
+/// None when the withdrawals can't be tied to the sales: nothing or more than one coin type
+/// withdrawn, a coin type other than the sales', coins deposited back to the buyer (ex: a
+/// refund or a swap), or a sale without a declared price.
+fn apportion_settlement(
+    declared: &[(String, Option<BigDecimal>)],
+    withdrawn: Option<&HashMap<CoinType, BigDecimal>>,
+    buyer_received_coins: bool,
+) -> Option<Vec<BigDecimal>> {
+    if buyer_received_coins {
+        return None;
+    }
+    let mut withdrawn = withdrawn?.iter();
+    let (coin_type, total) = match (withdrawn.next(), withdrawn.next()) {
+        (Some((Some(coin_type), total)), None) => (coin_type, total),
+        _ => return None,
+    };
+    let mut total_declared = BigDecimal::zero();
+    for (sale_coin_type, price) in declared {
+        if sale_coin_type != coin_type {
+            return None;
+        }
+        total_declared += price.as_ref()?;
+    }
+    if total_declared <= BigDecimal::zero() {
+        return None;
+    }
+    let mut remaining = total.clone();
+    let mut amounts = Vec::with_capacity(declared.len());
+    for (position, (_, price)) in declared.iter().enumerate() {
+        if position + 1 == declared.len() {
+            amounts.push(remaining.clone());
+        } else {
+            let amount = (total * price.as_ref().unwrap() / &total_declared).with_scale(0);
+            remaining -= &amount;
+            amounts.push(amount);
+        }
+    }
+    Some(amounts)
+}
+
+/// Owners a token had before each sale, from current_token_ownerships plus the ownership changes
+/// seen so far in the batch. The db is read once per batch, for every token the batch sells.
+#[derive(Debug, Default)]
+pub struct PrimarySaleClassifier {
+    /// (owner_address, last_transaction_version) from current_token_ownerships
+    db_owners: HashMap<TokenDataIdHash, Vec<(String, i64)>>,
+    /// (owner_address, transaction_version) from this batch's token ownerships
+    batch_owners: HashMap<TokenDataIdHash, Vec<(String, i64)>>,
+}
+
+impl PrimarySaleClassifier {
+    /// Loads the current owners of the tokens, ordered by version. Batches are processed in
+    /// version order, so these are the owners as of the end of the previous batch.
+    pub fn load_db_owners<'a>(
+        &mut self,
+        conn: &mut PgPoolConnection,
+        token_data_id_hashes: impl IntoIterator<Item = &'a TokenDataIdHash>,
+    ) -> QueryResult<()> {
+        let token_data_id_hashes = token_data_id_hashes
+            .into_iter()
+            .filter(|token_data_id_hash| !self.db_owners.contains_key(*token_data_id_hash))
+            .cloned()
+            .collect::<HashSet<_>>();
+        if token_data_id_hashes.is_empty() {
+            return Ok(());
+        }
+        for token_data_id_hash in &token_data_id_hashes {
+            self.db_owners
+                .insert(token_data_id_hash.clone(), Vec::new());
+        }
+        let owners = current_token_ownerships::table
+            .select((
+                current_token_ownerships::token_data_id_hash,
+                current_token_ownerships::owner_address,
+                current_token_ownerships::last_transaction_version,
+            ))
+            .filter(current_token_ownerships::token_data_id_hash.eq_any(token_data_id_hashes))
+            .order(current_token_ownerships::last_transaction_version)
+            .load::<(String, String, i64)>(conn)?;
+        for (token_data_id_hash, owner_address, txn_version) in owners {
+            self.db_owners
+                .entry(token_data_id_hash)
+                .or_default()
+                .push((owner_address, txn_version));
+        }
+        Ok(())
+    }
+
+    pub fn record_ownerships(&mut self, token_ownerships: &[TokenOwnership]) {
+        for ownership in token_ownerships {
+            if let Some(owner_address) = &ownership.owner_address {
+                self.record_owner(
+                    &ownership.token_data_id_hash,
+                    owner_address,
+                    ownership.transaction_version,
+                );
+            }
+        }
+    }
+
+    fn record_owner(&mut self, token_data_id_hash: &str, owner_address: &str, txn_version: i64) {
+        self.batch_owners
+            .entry(token_data_id_hash.to_string())
+            .or_default()
+            .push((owner_address.to_string(), txn_version));
+    }
+
+    /// Sets is_primary on sales. Only ownerships from before the sale's version count, since the
+    /// sale's own transaction already has the buyer as an owner.
+    pub fn classify(&self, sales: &mut [NftSale]) {
+        for sale in sales {
+            // This also skips db rows written by a later version, ex: when reprocessing
+            let previous_owners = self
+                .db_owners
+                .get(&sale.token_data_id_hash)
+                .into_iter()
+                .chain(self.batch_owners.get(&sale.token_data_id_hash))
+                .flatten()
+                .filter(|(_, txn_version)| *txn_version < sale.transaction_version)
+                .map(|(owner_address, _)| owner_address.as_str());
+            sale.is_primary = is_primary_sale(
+                sale.seller.as_deref(),
+                &sale.creator_address,
+                previous_owners,
+            );
+        }
+    }
+}
+
+/// A sale is primary if the creator is selling, or if the token has never been owned by anyone
+/// other than the seller
+pub fn is_primary_sale<'a>(
+    seller: Option<&str>,
+    creator_address: &str,
+    mut previous_owners: impl Iterator<Item = &'a str>,
+) -> bool {
+    seller == Some(creator_address) || previous_owners.all(|owner| Some(owner) == seller)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::models::token_models::{
+        collection_volume::CurrentCollectionVolume, event_effects::TokenEventEffects,
+        token_utils::TokenEvents,
+    };
+    use crate::util::standardize_address;
+    use aptos_config::config::MarketplacePayloadMapping;
+    use serde_json::json;
+
+    const HASH: &str = "0x6a527d06063dfd42c6b3a862574d5f3ec1660afb8058135edda5072712bfdb51";
+    const TOPAZ_BUY_EVENT: &str =
+        "0x2c7bccf7b31baf770fdbcc768d9e9cb3d87805e255355df5db32ac9a669010a2::events::BuyEvent";
+    const PAYLOAD_MARKET: &str =
+        "0x8f6cd2b2a4e0c1a9d5e3f7b1c6a2d4e8f0b3c5a7d9e1f2a4b6c8d0e2f4a6b8c1";
+
+    fn block_metadata_txn(version: u64) -> APITransaction {
+        serde_json::from_value(json!({
+            "type": "block_metadata_transaction",
+            "version": version.to_string(),
+            "hash": HASH,
+            "state_change_hash": HASH,
+            "event_root_hash": HASH,
+            "state_checkpoint_hash": null,
+            "gas_used": "0",
+            "success": true,
+            "vm_status": "Executed successfully",
+            "accumulator_root_hash": HASH,
+            "changes": [],
+            "id": HASH,
+            "epoch": "1",
+            "round": "1",
+            "events": [],
+            "previous_block_votes_bitvec": [],
+            "proposer": "0x1",
+            "failed_proposer_indices": [],
+            "timestamp": "1668000000000000"
+        }))
+        .unwrap()
+    }
+
+    fn topaz_buy_txn(version: u64, gas_unit_price: u64) -> APITransaction {
+        topaz_sale_txn(version, gas_unit_price, "0xa11ce", "0xb0b")
+    }
+
+    fn topaz_sale_txn(
+        version: u64,
+        gas_unit_price: u64,
+        seller: &str,
+        buyer: &str,
+    ) -> APITransaction {
+        serde_json::from_value(json!({
+            "type": "user_transaction",
+            "version": version.to_string(),
+            "hash": HASH,
+            "state_change_hash": HASH,
+            "event_root_hash": HASH,
+            "state_checkpoint_hash": null,
+            "gas_used": "100",
+            "success": true,
+            "vm_status": "Executed successfully",
+            "accumulator_root_hash": HASH,
+            "changes": [],
+            "sender": buyer,
+            "sequence_number": "0",
+            "max_gas_amount": "2000",
+            "gas_unit_price": gas_unit_price.to_string(),
+            "expiration_timestamp_secs": "1668000600",
+            "payload": {
+                "type": "entry_function_payload",
+                "function": "0x2c7bccf7b31baf770fdbcc768d9e9cb3d87805e255355df5db32ac9a669010a2::marketplace_v2::buy",
+                "type_arguments": [],
+                "arguments": []
+            },
+            "events": [{
+                "guid": {
+                    "creation_number": "4",
+                    "account_address": "0x2c7bccf7b31baf770fdbcc768d9e9cb3d87805e255355df5db32ac9a669010a2"
+                },
+                "sequence_number": "7",
+                "type": TOPAZ_BUY_EVENT,
+                "data": {
+                    "timestamp": "1668000000",
+                    "listing_id": "12",
+                    "token_id": {
+                        "token_data_id": {
+                            "creator": "0xc4e7",
+                            "collection": "Aptos Monkeys",
+                            "name": "Monkey #1"
+                        },
+                        "property_version": "0"
+                    },
+                    "price": "250000000",
+                    "amount": "1",
+                    "seller": seller,
+                    "buyer": buyer
+                }
+            }],
+            "timestamp": "1668000000000000"
+        }))
+        .unwrap()
+    }
+
+    fn sales_from_batch(transactions: &[APITransaction]) -> Vec<NftSale> {
+        let mut block_position = BlockPosition::default();
+        let mut sales = vec![];
+        for txn in transactions {
+            let rank = block_position.rank(txn);
+            let activities = TokenActivity::from_transaction(
+                txn,
+                &TokenEvents::from_transaction(txn).unwrap(),
+                &MarketplaceEventMappings::default(),
+            );
+            sales.append(&mut NftSale::from_token_activities(txn, &activities, rank));
+        }
+        sales
+    }
+
+    #[test]
+    fn test_sales_ranked_within_block() {
+        let sales = sales_from_batch(&[
+            block_metadata_txn(100),
+            topaz_buy_txn(101, 150),
+            topaz_buy_txn(102, 100),
+            block_metadata_txn(103),
+            topaz_buy_txn(104, 100),
+        ]);
+        assert_eq!(sales.len(), 3);
+        assert_eq!(
+            sales
+                .iter()
+                .map(|sale| sale.transaction_rank_in_block)
+                .collect::<Vec<_>>(),
+            vec![Some(1), Some(2), Some(1)]
+        );
+        assert_eq!(sales[0].gas_unit_price, BigDecimal::from(150));
+        assert_eq!(sales[0].price, Some(BigDecimal::from(250000000)));
+        assert_eq!(sales[0].seller, Some(standardize_address("0xa11ce")));
+        assert_eq!(sales[0].buyer, Some(standardize_address("0xb0b")));
+        assert_eq!(
+            sales[0].market_address,
+            "0x2c7bccf7b31baf770fdbcc768d9e9cb3d87805e255355df5db32ac9a669010a2"
+        );
+    }
+
+    #[test]
+    fn test_batch_starting_mid_block_has_no_rank() {
+        let sales = sales_from_batch(&[topaz_buy_txn(101, 150), block_metadata_txn(102)]);
+        assert_eq!(sales.len(), 1);
+        assert_eq!(sales[0].transaction_rank_in_block, None);
+    }
+
+    #[test]
+    fn test_sweep_sales_share_a_group() {
+        // Three Topaz buys in one transaction
+        let mut value = serde_json::to_value(topaz_buy_txn(101, 100)).unwrap();
+        let event = value["events"][0].clone();
+        for (i, name) in ["Monkey #2", "Monkey #3"].into_iter().enumerate() {
+            let mut swept = event.clone();
+            swept["sequence_number"] = json!((8 + i).to_string());
+            swept["data"]["token_id"]["token_data_id"]["name"] = json!(name);
+            value["events"].as_array_mut().unwrap().push(swept);
+        }
+        let sweep: APITransaction = serde_json::from_value(value).unwrap();
+        let mut sales = sales_from_batch(&[sweep, topaz_buy_txn(102, 100)]);
+        let (sweep_sales, single_sales) = sales.split_at_mut(3);
+        NftSale::set_sale_groups(sweep_sales);
+        NftSale::set_sale_groups(single_sales);
+        assert_eq!(
+            sales
+                .iter()
+                .map(|sale| (sale.sale_group_id, sale.group_size))
+                .collect::<Vec<_>>(),
+            vec![(101, 3), (101, 3), (101, 3), (102, 1)]
+        );
+        assert_eq!(sales[2].name, "Monkey #3");
+    }
+
+    #[test]
+    fn test_creator_sells_first_then_buyer_resells() {
+        let mut classifier = PrimarySaleClassifier::default();
+        let first_sale_txn = topaz_sale_txn(101, 100, "0xc4e7", "0xb0b");
+        let mut first_sales = sales_from_batch(&[first_sale_txn.clone()]);
+        let token_data_id_hash = first_sales[0].token_data_id_hash.clone();
+        // Minted to the creator, then the sale moves it to the buyer
+        let creator = standardize_address("0xc4e7");
+        classifier.record_owner(&token_data_id_hash, &creator, 100);
+        classifier.record_owner(&token_data_id_hash, &creator, 101);
+        classifier.record_owner(&token_data_id_hash, &standardize_address("0xb0b"), 101);
+        classifier.classify(&mut first_sales);
+        assert!(first_sales[0].is_primary);
+
+        let resale_txn = topaz_sale_txn(102, 100, "0xb0b", "0xca7");
+        let mut resales = sales_from_batch(&[resale_txn.clone()]);
+        classifier.classify(&mut resales);
+        assert!(!resales[0].is_primary);
+
+        let (first_volumes, ..) = CurrentCollectionVolume::from_effects(
+            &TokenEventEffects::from_transaction(
+                &first_sale_txn,
+                &TokenEvents::from_transaction(&first_sale_txn).unwrap(),
+                &MarketplaceEventMappings::default(),
+            ),
+            &first_sales,
+        );
+        let first_volume = &first_volumes[&first_sales[0].collection_data_id_hash];
+        assert_eq!(first_volume.primary_volume, BigDecimal::from(250000000));
+        assert_eq!(first_volume.secondary_volume, BigDecimal::from(0));
+        let (resale_volumes, ..) = CurrentCollectionVolume::from_effects(
+            &TokenEventEffects::from_transaction(
+                &resale_txn,
+                &TokenEvents::from_transaction(&resale_txn).unwrap(),
+                &MarketplaceEventMappings::default(),
+            ),
+            &resales,
+        );
+        let resale_volume = &resale_volumes[&resales[0].collection_data_id_hash];
+        assert_eq!(resale_volume.primary_volume, BigDecimal::from(0));
+        assert_eq!(resale_volume.secondary_volume, BigDecimal::from(250000000));
+    }
+
+    #[test]
+    fn test_first_sale_by_sole_owner_is_primary() {
+        // Never owned by anyone but the seller, even though the seller isn't the creator
+        assert!(is_primary_sale(
+            Some("0xa11ce"),
+            "0xc4e7",
+            ["0xa11ce", "0xa11ce"].into_iter()
+        ));
+        assert!(!is_primary_sale(
+            Some("0xa11ce"),
+            "0xc4e7",
+            ["0xc4e7", "0xa11ce"].into_iter()
+        ));
+        assert!(is_primary_sale(None, "0xc4e7", std::iter::empty()));
+    }
+
+    fn golden_fixture(name: &str) -> APITransaction {
+        let path = format!(
+            "{}/fixtures/golden/transactions/{}.json",
+            env!("CARGO_MANIFEST_DIR"),
+            name
+        );
+        serde_json::from_str(&std::fs::read_to_string(path).unwrap()).unwrap()
+    }
+
+    fn payload_sale(txn: &APITransaction, mappings: &MarketplaceEventMappings) -> Option<NftSale> {
+        let activities = TokenActivity::from_transaction(
+            txn,
+            &TokenEvents::from_transaction(txn).unwrap(),
+            mappings,
+        );
+        NftSale::from_payload(txn, &activities, mappings, Some(3))
+    }
+
+    /// The market of payload_buy.json, which has no sale event
+    fn payload_market_mappings() -> MarketplaceEventMappings {
+        MarketplaceEventMappings::default()
+            .with_payload_mappings(&[MarketplacePayloadMapping {
+                module_address: PAYLOAD_MARKET.to_string(),
+                function_name: "market::buy_token".to_string(),
+            }])
+            .unwrap()
+    }
+
+    #[test]
+    fn test_sale_inferred_from_payload() {
+        let market = PAYLOAD_MARKET;
+        let mappings = payload_market_mappings();
+        let txn = golden_fixture("payload_buy");
+        let sale = payload_sale(&txn, &mappings).unwrap();
+        assert_eq!(sale.source, PAYLOAD_INFERRED_SOURCE);
+        assert_eq!(sale.market_address, market);
+        assert_eq!(sale.event_type, format!("{}::market::buy_token", market));
+        assert_eq!(sale.name, "Aptos Undead #318");
+        // The buyer's whole payment, including the market's cut
+        assert_eq!(sale.price, Some(BigDecimal::from(150000000)));
+        assert_eq!(sale.coin_type, Some(APTOS_COIN_TYPE.to_string()));
+        assert_eq!(
+            sale.seller.as_deref(),
+            Some("0x4d1b7e3a9c5f2e8d6b0a4c7e1f3d5b9a2c8e6f0d4b7a1c3e5f9d2b6a8c0e4f73")
+        );
+        assert_eq!(
+            sale.buyer.as_deref(),
+            Some("0x6e2a9c4f1b7d3e5a8c0f2d6b4e9a1c7f3d5b8e0a2c4f6d9b1e3a5c7f0d2b4e96")
+        );
+        // Keyed by the token deposit
+        assert_eq!(sale.event_index, 4);
+        assert_eq!(sale.event_creation_number, 5);
+        assert_eq!(sale.transaction_rank_in_block, Some(3));
+
+        // Not a configured entry function
+        assert!(payload_sale(&txn, &MarketplaceEventMappings::default()).is_none());
+
+        // Sales with an event aren't inferred again, even from a configured function
+        let topaz_mappings = MarketplaceEventMappings::default()
+            .with_payload_mappings(&[MarketplacePayloadMapping {
+                module_address: TOPAZ_BUY_EVENT.split("::").next().unwrap().to_string(),
+                function_name: "marketplace::buy".to_string(),
+            }])
+            .unwrap();
+        assert!(payload_sale(&golden_fixture("topaz_buy"), &topaz_mappings).is_none());
+    }
+
+    #[test]
+    fn test_settlement_amount_from_coin_events() {
+        let mappings = payload_market_mappings();
+        let txn = golden_fixture("payload_buy");
+        let mut sales = vec![payload_sale(&txn, &mappings).unwrap()];
+        NftSale::set_settlement_amounts(&txn, &mut sales);
+        assert_eq!(
+            sales[0].settlement_amount,
+            Some(BigDecimal::from(150000000))
+        );
+
+        // No coin events to go by
+        let mut sales = sales_from_batch(&[topaz_buy_txn(101, 100)]);
+        NftSale::set_settlement_amounts(&topaz_buy_txn(101, 100), &mut sales);
+        assert_eq!(sales[0].settlement_amount, None);
+    }
+
+    #[test]
+    fn test_apportion_settlement() {
+        let apt = canonicalize_coin_type(None);
+        let sale = |price: Option<i64>| (apt.clone(), price.map(BigDecimal::from));
+        let withdrawn = |entries: Vec<(Option<&str>, i64)>| {
+            entries
+                .into_iter()
+                .map(|(coin_type, amount)| {
+                    (coin_type.map(str::to_string), BigDecimal::from(amount))
+                })
+                .collect::<HashMap<_, _>>()
+        };
+
+        // Royalties on top of a 100 and a 300 sale, split 1:3 with the remainder on the last
+        let apt_withdrawn = withdrawn(vec![(Some(apt.as_str()), 401)]);
+        assert_eq!(
+            apportion_settlement(
+                &[sale(Some(100)), sale(Some(300))],
+                Some(&apt_withdrawn),
+                false
+            ),
+            Some(vec![BigDecimal::from(100), BigDecimal::from(301)])
+        );
+        // Coins coming back to the buyer, or no withdrawal at all
+        assert_eq!(
+            apportion_settlement(&[sale(Some(100))], Some(&apt_withdrawn), true),
+            None
+        );
+        assert_eq!(apportion_settlement(&[sale(Some(100))], None, false), None);
+        // Another coin moved alongside, or a coin store the transaction didn't write
+        let mixed = withdrawn(vec![
+            (Some(apt.as_str()), 401),
+            (Some("0x1::fake::USDC"), 5),
+        ]);
+        assert_eq!(
+            apportion_settlement(&[sale(Some(100))], Some(&mixed), false),
+            None
+        );
+        let unknown = withdrawn(vec![(None, 401)]);
+        assert_eq!(
+            apportion_settlement(&[sale(Some(100))], Some(&unknown), false),
+            None
+        );
+        // Nothing to apportion by
+        assert_eq!(
+            apportion_settlement(&[sale(Some(100)), sale(None)], Some(&apt_withdrawn), false),
+            None
+        );
+    }
+}
@@ -0,0 +1,163 @@
+// Copyright (c) Aptos
+// SPDX-License-Identifier: Apache-2.0
+
+// This is required because a diesel macro makes clippy sad
+#![allow(clippy::extra_unused_lifetimes)]
+#![allow(clippy::unused_unit)]
+
+use super::token_activities::TokenActivity;
+use crate::{
+    schema::nft_transaction_fees,
+    util::{parse_timestamp, standardize_address, u64_to_bigdecimal},
+};
+use aptos_api_types::Transaction as APITransaction;
+use bigdecimal::BigDecimal;
+use field_count::FieldCount;
+use serde::{Deserialize, Serialize};
+
+/// Module address of the token framework's own events
+const TOKEN_FRAMEWORK_ADDRESS: &str = "0x3";
+
+/// Gas paid by a user transaction that produced at least one token activity
+#[derive(Debug, Deserialize, FieldCount, Identifiable, Insertable, Serialize)]
+#[diesel(primary_key(transaction_version))]
+#[diesel(table_name = nft_transaction_fees)]
+pub struct NftTransactionFee {
+    pub transaction_version: i64,
+    pub sender: String,
+    pub gas_used: BigDecimal,
+    pub gas_unit_price: BigDecimal,
+    /// gas_used * gas_unit_price, in octas
+    pub gas_fee: BigDecimal,
+    pub num_token_events: i64,
+    /// Module address of the transaction's first marketplace event, None if it only has token
+    /// framework events
+    pub market_address: Option<String>,
+    pub transaction_timestamp: chrono::NaiveDateTime,
+}
+
+impl NftTransactionFee {
+    /// `token_activities` are the transaction's activities, in event order
+    pub fn from_token_activities(
+        transaction: &APITransaction,
+        token_activities: &[TokenActivity],
+    ) -> Option<Self> {
+        let user_txn = match transaction {
+            APITransaction::UserTransaction(user_txn) => user_txn,
+            _ => return None,
+        };
+        if token_activities.is_empty() {
+            return None;
+        }
+        let txn_version = user_txn.info.version.0 as i64;
+        let framework_address = standardize_address(TOKEN_FRAMEWORK_ADDRESS);
+        let market_address = token_activities
+            .iter()
+            .filter_map(|activity| activity.transfer_type.split("::").next())
+            .map(standardize_address)
+            .find(|address| *address != framework_address);
+        let gas_used = u64_to_bigdecimal(user_txn.info.gas_used.0);
+        let gas_unit_price = u64_to_bigdecimal(user_txn.request.gas_unit_price.0);
+        Some(Self {
+            transaction_version: txn_version,
+            sender: standardize_address(&user_txn.request.sender.to_string()),
+            gas_fee: &gas_used * &gas_unit_price,
+            gas_used,
+            gas_unit_price,
+            num_token_events: token_activities.len() as i64,
+            market_address,
+            transaction_timestamp: parse_timestamp(user_txn.timestamp.0, txn_version),
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    fn transaction() -> APITransaction {
+        let hash = format!("0x{}", "00".repeat(32));
+        serde_json::from_value(json!({
+            "type": "user_transaction",
+            "version": "10",
+            "hash": hash,
+            "state_change_hash": hash,
+            "event_root_hash": hash,
+            "state_checkpoint_hash": null,
+            "gas_used": "812",
+            "success": true,
+            "vm_status": "Executed successfully",
+            "accumulator_root_hash": hash,
+            "changes": [],
+            "sender": "0xb0b",
+            "sequence_number": "0",
+            "max_gas_amount": "2000",
+            "gas_unit_price": "150",
+            "expiration_timestamp_secs": "0",
+            "payload": {
+                "type": "entry_function_payload",
+                "function": "0xfa4e::market::buy",
+                "type_arguments": [],
+                "arguments": []
+            },
+            "events": [],
+            "timestamp": "1668000000000000"
+        }))
+        .unwrap()
+    }
+
+    fn activity(transfer_type: &str) -> TokenActivity {
+        TokenActivity {
+            transaction_version: 10,
+            event_account_address: "0xfa4e".to_string(),
+            event_creation_number: 0,
+            event_sequence_number: 0,
+            event_index: 0,
+            token_index: 0,
+            token_data_id_hash: "potion".to_string(),
+            property_version: BigDecimal::from(0),
+            creator_address: "0xc4e7".to_string(),
+            collection_name: "Potions".to_string(),
+            name: "Potion".to_string(),
+            transfer_type: transfer_type.to_string(),
+            transfer_kind: None,
+            domain: None,
+            from_address: None,
+            to_address: None,
+            token_amount: BigDecimal::from(1),
+            coin_type: None,
+            coin_amount: None,
+            suspect_value: false,
+            collection_data_id_hash: "potions".to_string(),
+            transaction_timestamp: parse_timestamp(1668000000000000, 10),
+        }
+    }
+
+    #[test]
+    fn test_fee_and_market() {
+        let txn = transaction();
+        let fee = NftTransactionFee::from_token_activities(
+            &txn,
+            &[
+                activity("0x3::token::WithdrawEvent"),
+                activity("0xfa4e::market::BuyEvent"),
+                activity("0x3::token::DepositEvent"),
+            ],
+        )
+        .unwrap();
+        assert_eq!(fee.transaction_version, 10);
+        assert_eq!(fee.sender, standardize_address("0xb0b"));
+        assert_eq!(fee.gas_fee, BigDecimal::from(812 * 150));
+        assert_eq!(fee.num_token_events, 3);
+        assert_eq!(fee.market_address, Some(standardize_address("0xfa4e")));
+
+        let fee = NftTransactionFee::from_token_activities(&txn, &[activity(
+            "0x0000000000000000000000000000000000000000000000000000000000000003::token::DepositEvent",
+        )])
+        .unwrap();
+        assert_eq!(fee.market_address, None);
+
+        assert!(NftTransactionFee::from_token_activities(&txn, &[]).is_none());
+    }
+}
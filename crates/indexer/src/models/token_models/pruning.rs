@@ -0,0 +1,467 @@
+// Copyright (c) Aptos
+// SPDX-License-Identifier: Apache-2.0
+
+//! Deletes history older than a per table retention period. Rows are deleted oldest version first
+//! in bounded batches, each logged to pruning_log in the same statement, so that a prune can be
+//! interrupted at any point and never holds locks for long. Current tables are never pruned.
+//!
+//! Versions from the start of a pending backfill onwards are kept, the backfill is rewriting them.
+//! If token_activities is partitioned, partitions with only prunable versions are dropped instead.
+
+use super::activity_partitions::list_partitions;
+use crate::{models::processor_status::ProcessorStatusV2Query, schema::pruning_log};
+use anyhow::{bail, ensure};
+use aptos_config::config::PruningConfig;
+use diesel::{
+    dsl::exists,
+    result::Error,
+    select, sql_query,
+    sql_types::{BigInt, Text, Timestamp},
+    ExpressionMethods, OptionalExtension, PgConnection, QueryDsl, QueryResult, RunQueryDsl,
+};
+use std::time::Duration;
+
+pub const DEFAULT_BATCH_SIZE: u64 = 50_000;
+pub const DEFAULT_BATCH_PAUSE_MS: u64 = 1000;
+
+/// A history table and the columns its age is read from
+#[derive(Debug)]
+pub struct PrunableTable {
+    pub name: &'static str,
+    version_column: &'static str,
+    timestamp_column: &'static str,
+}
+
+pub const PRUNABLE_TABLES: &[PrunableTable] = &[
+    PrunableTable {
+        name: "token_activities",
+        version_column: "transaction_version",
+        timestamp_column: "transaction_timestamp",
+    },
+    PrunableTable {
+        name: "account_token_activities",
+        version_column: "transaction_version",
+        timestamp_column: "transaction_timestamp",
+    },
+    PrunableTable {
+        name: "collection_volumes",
+        version_column: "last_transaction_version",
+        timestamp_column: "last_transaction_timestamp",
+    },
+    PrunableTable {
+        name: "token_volumes",
+        version_column: "last_transaction_version",
+        timestamp_column: "last_transaction_timestamp",
+    },
+];
+
+#[derive(Debug, QueryableByName)]
+struct Version {
+    #[diesel(sql_type = BigInt)]
+    version: i64,
+}
+
+#[derive(Debug, QueryableByName)]
+struct PrunedBatch {
+    #[diesel(sql_type = BigInt)]
+    num_rows: i64,
+}
+
+#[derive(Debug)]
+pub struct Pruner {
+    retention: Vec<(&'static PrunableTable, chrono::Duration)>,
+    batch_size: i64,
+    batch_pause: Duration,
+}
+
+impl Pruner {
+    pub fn from_config(config: Option<&PruningConfig>) -> anyhow::Result<Option<Self>> {
+        let config = match config {
+            Some(config) => config,
+            None => return Ok(None),
+        };
+        let mut retention = vec![];
+        for (table, days) in &config.retention_days {
+            let prunable = match PRUNABLE_TABLES.iter().find(|known| known.name == table) {
+                Some(prunable) => prunable,
+                None => bail!(
+                    "Table {} can't be pruned, expected one of {}",
+                    table,
+                    PRUNABLE_TABLES
+                        .iter()
+                        .map(|known| known.name)
+                        .collect::<Vec<_>>()
+                        .join(", ")
+                ),
+            };
+            ensure!(
+                *days > 0,
+                "retention_days of {} must be greater than 0",
+                table
+            );
+            retention.push((prunable, chrono::Duration::days(*days as i64)));
+        }
+        let batch_size = config.batch_size.unwrap_or(DEFAULT_BATCH_SIZE);
+        ensure!(batch_size > 0, "batch_size must be greater than 0");
+        Ok(Some(Self {
+            retention,
+            batch_size: batch_size as i64,
+            batch_pause: Duration::from_millis(
+                config.batch_pause_ms.unwrap_or(DEFAULT_BATCH_PAUSE_MS),
+            ),
+        }))
+    }
+
+    /// Prunes every table with a retention period, returning the number of rows deleted per table
+    pub fn run(
+        &self,
+        conn: &mut PgConnection,
+        now: chrono::NaiveDateTime,
+    ) -> QueryResult<Vec<(&'static str, i64)>> {
+        let mut pruned = vec![];
+        for (table, retention) in &self.retention {
+            let cutoff_timestamp = now - *retention;
+            let num_rows = self.prune_table(conn, table, cutoff_timestamp)?;
+            pruned.push((table.name, num_rows));
+        }
+        Ok(pruned)
+    }
+
+    /// Deletes rows older than `cutoff_timestamp` in batches, up to the first version a pending
+    /// backfill still needs
+    fn prune_table(
+        &self,
+        conn: &mut PgConnection,
+        table: &PrunableTable,
+        cutoff_timestamp: chrono::NaiveDateTime,
+    ) -> QueryResult<i64> {
+        let first_kept_version = sql_query(format!(
+            "SELECT {version} AS version FROM {table}
+            WHERE {timestamp} >= $1
+            ORDER BY {version}
+            LIMIT 1",
+            version = table.version_column,
+            table = table.name,
+            timestamp = table.timestamp_column,
+        ))
+        .bind::<Timestamp, _>(cutoff_timestamp)
+        .get_result::<Version>(conn)
+        .optional()?
+        .map(|row| row.version)
+        .unwrap_or(i64::MAX);
+        let mut end_version = first_kept_version;
+        if let Some(backfill_start) = ProcessorStatusV2Query::get_pending_backfill_starts(conn)?
+            .into_iter()
+            .min()
+        {
+            if (backfill_start as i64) < end_version {
+                aptos_logger::warn!(
+                    table = table.name,
+                    backfill_start = backfill_start,
+                    "Not pruning past the start of a pending backfill"
+                );
+                end_version = backfill_start as i64;
+            }
+        }
+
+        // Batches end at a version rather than a row, ctids aren't unique across partitions. A
+        // batch can go over the batch size by the rows sharing its last version.
+        let delete_batch = format!(
+            "WITH batch_end AS (
+                SELECT {version} AS version FROM {table}
+                WHERE {version} < $1 AND {timestamp} < $2
+                ORDER BY {version}
+                OFFSET $3 - 1
+                LIMIT 1
+            ), deleted AS (
+                DELETE FROM {table}
+                WHERE {version} < $1 AND {timestamp} < $2
+                    AND {version} <= COALESCE((SELECT version FROM batch_end), $1)
+                RETURNING {version} AS version
+            ), logged AS (
+                INSERT INTO pruning_log (table_name, cutoff_timestamp, max_version, num_rows)
+                SELECT $4, $2, MAX(version), COUNT(*) FROM deleted HAVING COUNT(*) > 0
+                RETURNING num_rows
+            )
+            SELECT COALESCE((SELECT num_rows FROM logged), 0) AS num_rows",
+            table = table.name,
+            version = table.version_column,
+            timestamp = table.timestamp_column,
+        );
+        // Whole partitions are dropped rather than deleted from
+        let mut total_rows = 0;
+        if table.name == "token_activities" {
+            total_rows += drop_activity_partitions(conn, cutoff_timestamp, end_version)?;
+        }
+        loop {
+            let batch = sql_query(&delete_batch)
+                .bind::<BigInt, _>(end_version)
+                .bind::<Timestamp, _>(cutoff_timestamp)
+                .bind::<BigInt, _>(self.batch_size)
+                .bind::<Text, _>(table.name)
+                .get_result::<PrunedBatch>(conn)?;
+            total_rows += batch.num_rows;
+            aptos_logger::debug!(
+                table = table.name,
+                num_rows = batch.num_rows,
+                "Pruned batch"
+            );
+            if batch.num_rows < self.batch_size {
+                return Ok(total_rows);
+            }
+            std::thread::sleep(self.batch_pause);
+        }
+    }
+}
+
+/// Drops the token_activities partitions with only versions before `end_version`, if it's
+/// partitioned, returning the number of rows dropped
+fn drop_activity_partitions(
+    conn: &mut PgConnection,
+    cutoff_timestamp: chrono::NaiveDateTime,
+    end_version: i64,
+) -> QueryResult<i64> {
+    let mut total_rows = 0;
+    for partition in list_partitions(conn)? {
+        if partition.end_version > end_version {
+            break;
+        }
+        total_rows += conn
+            .build_transaction()
+            .read_write()
+            .run::<_, Error, _>(|conn| {
+                let dropped = sql_query(format!(
+                    "WITH logged AS (
+                        INSERT INTO pruning_log (table_name, cutoff_timestamp, max_version, num_rows)
+                        SELECT $1, $2, MAX(transaction_version), COUNT(*) FROM \"{partition}\"
+                        HAVING COUNT(*) > 0
+                        RETURNING num_rows
+                    )
+                    SELECT COALESCE((SELECT num_rows FROM logged), 0) AS num_rows",
+                    partition = partition.name,
+                ))
+                .bind::<Text, _>("token_activities")
+                .bind::<Timestamp, _>(cutoff_timestamp)
+                .get_result::<PrunedBatch>(conn)?;
+                sql_query(format!("DROP TABLE \"{}\"", partition.name)).execute(conn)?;
+                Ok(dropped.num_rows)
+            })?;
+        aptos_logger::info!(partition = partition.name, "Dropped partition");
+    }
+    Ok(total_rows)
+}
+
+/// Whether any rows of the table have been pruned. Totals recomputed from a pruned history are
+/// missing the pruned rows.
+pub fn is_pruned(conn: &mut PgConnection, table: &str) -> QueryResult<bool> {
+    select(exists(
+        pruning_log::table.filter(pruning_log::table_name.eq(table)),
+    ))
+    .get_result(conn)
+}
+
+/// Volumes recomputed from a pruned collection_volumes or token_volumes are too low
+pub fn is_volume_history_pruned(conn: &mut PgConnection) -> QueryResult<bool> {
+    Ok(is_pruned(conn, "collection_volumes")? || is_pruned(conn, "token_volumes")?)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        database::{new_db_pool, PgPoolConnection},
+        indexer::tailer::{test::wipe_database, MIGRATIONS},
+        models::{
+            processor_status::{backfill_checkpoint_name, ProcessorStatusV2},
+            token_models::{
+                activity_partitions::TokenActivityPartitions, token_activities::TokenActivity,
+            },
+        },
+        schema::token_activities,
+    };
+    use bigdecimal::BigDecimal;
+    use diesel::connection::SimpleConnection;
+    use diesel_migrations::MigrationHarness;
+    use std::collections::BTreeMap;
+
+    fn setup() -> PgPoolConnection {
+        let database_url = std::env::var("INDEXER_DATABASE_URL")
+            .expect("must set 'INDEXER_DATABASE_URL' to run tests!");
+        let conn_pool = new_db_pool(database_url.as_str()).unwrap();
+        let mut conn = conn_pool.get().unwrap();
+        wipe_database(&mut conn);
+        conn.run_pending_migrations(MIGRATIONS).unwrap();
+        conn
+    }
+
+    fn day(day: i64) -> chrono::NaiveDateTime {
+        chrono::NaiveDateTime::from_timestamp(1668000000 + day * 86400, 0)
+    }
+
+    fn config(retention_days: &[(&str, u64)], batch_size: u64) -> PruningConfig {
+        PruningConfig {
+            retention_days: retention_days
+                .iter()
+                .map(|(table, days)| (table.to_string(), *days))
+                .collect::<BTreeMap<_, _>>(),
+            batch_size: Some(batch_size),
+            batch_pause_ms: Some(0),
+        }
+    }
+
+    /// One activity per day, at versions 1 to 5
+    fn activities() -> Vec<TokenActivity> {
+        (1..=5)
+            .map(|version| TokenActivity {
+                transaction_version: version,
+                event_account_address: "0x1".to_string(),
+                event_creation_number: 0,
+                event_sequence_number: version,
+                event_index: 0,
+                token_index: 0,
+                token_data_id_hash: "potion".to_string(),
+                property_version: BigDecimal::from(0),
+                creator_address: "0x1".to_string(),
+                collection_name: "Potions".to_string(),
+                name: "Potion".to_string(),
+                transfer_type: "0x3::token::DepositEvent".to_string(),
+                transfer_kind: Some("transfer".to_string()),
+                domain: None,
+                from_address: None,
+                to_address: Some("0x1".to_string()),
+                token_amount: BigDecimal::from(1),
+                coin_type: None,
+                coin_amount: None,
+                suspect_value: false,
+                collection_data_id_hash: "potions".to_string(),
+                transaction_timestamp: day(version),
+            })
+            .collect()
+    }
+
+    fn insert_activities(conn: &mut PgConnection) {
+        diesel::insert_into(token_activities::table)
+            .values(&activities())
+            .execute(conn)
+            .unwrap();
+    }
+
+    fn remaining_versions(conn: &mut PgConnection) -> Vec<i64> {
+        token_activities::table
+            .select(token_activities::transaction_version)
+            .order(token_activities::transaction_version)
+            .load(conn)
+            .unwrap()
+    }
+
+    #[test]
+    fn test_invalid_config() {
+        assert!(Pruner::from_config(None).unwrap().is_none());
+        assert!(
+            Pruner::from_config(Some(&config(&[("token_activities", 90)], 10)))
+                .unwrap()
+                .is_some()
+        );
+        assert!(
+            Pruner::from_config(Some(&config(&[("current_token_ownerships", 90)], 10))).is_err()
+        );
+        assert!(Pruner::from_config(Some(&config(&[("token_activities", 0)], 10))).is_err());
+        assert!(Pruner::from_config(Some(&config(&[("token_activities", 90)], 0))).is_err());
+    }
+
+    #[test]
+    fn test_prune_in_batches() {
+        if crate::should_skip_pg_tests() {
+            return;
+        }
+        let mut conn = setup();
+        insert_activities(&mut conn);
+        let pruner = Pruner::from_config(Some(&config(&[("token_activities", 2)], 2)))
+            .unwrap()
+            .unwrap();
+
+        // Days 1 to 3 are older than 2 days on day 6
+        assert_eq!(
+            pruner.run(&mut conn, day(6)).unwrap(),
+            vec![("token_activities", 3)]
+        );
+        assert_eq!(remaining_versions(&mut conn), vec![4, 5]);
+        let batches = pruning_log::table
+            .select((pruning_log::max_version, pruning_log::num_rows))
+            .order(pruning_log::max_version)
+            .load::<(i64, i64)>(&mut conn)
+            .unwrap();
+        assert_eq!(batches, vec![(2, 2), (3, 1)]);
+        assert!(is_pruned(&mut conn, "token_activities").unwrap());
+        assert!(!is_pruned(&mut conn, "collection_volumes").unwrap());
+
+        assert_eq!(
+            pruner.run(&mut conn, day(6)).unwrap(),
+            vec![("token_activities", 0)]
+        );
+    }
+
+    #[test]
+    fn test_prune_keeps_pending_backfills() {
+        if crate::should_skip_pg_tests() {
+            return;
+        }
+        let mut conn = setup();
+        insert_activities(&mut conn);
+        ProcessorStatusV2 {
+            processor: backfill_checkpoint_name("token_processor", 2),
+            last_success_version: 4,
+        }
+        .upsert(&mut conn)
+        .unwrap();
+        let pruner = Pruner::from_config(Some(&config(&[("token_activities", 1)], 10)))
+            .unwrap()
+            .unwrap();
+
+        assert_eq!(
+            pruner.run(&mut conn, day(10)).unwrap(),
+            vec![("token_activities", 1)]
+        );
+        assert_eq!(remaining_versions(&mut conn), vec![2, 3, 4, 5]);
+    }
+
+    #[test]
+    fn test_prune_drops_partitions() {
+        if crate::should_skip_pg_tests() {
+            return;
+        }
+        let mut conn = setup();
+        conn.batch_execute("CALL partition_token_activities(2)")
+            .unwrap();
+        TokenActivityPartitions::from_config(Some(2))
+            .unwrap()
+            .unwrap()
+            .create_missing(&mut conn, &activities())
+            .unwrap();
+        insert_activities(&mut conn);
+        let pruner = Pruner::from_config(Some(&config(&[("token_activities", 2)], 10)))
+            .unwrap()
+            .unwrap();
+
+        // Versions 1 to 3 are prunable, which is all of the first two partitions
+        assert_eq!(
+            pruner.run(&mut conn, day(6)).unwrap(),
+            vec![("token_activities", 3)]
+        );
+        assert_eq!(remaining_versions(&mut conn), vec![4, 5]);
+        assert_eq!(
+            list_partitions(&mut conn)
+                .unwrap()
+                .into_iter()
+                .map(|partition| partition.name)
+                .collect::<Vec<_>>(),
+            vec!["token_activities_4"]
+        );
+        let batches = pruning_log::table
+            .select((pruning_log::max_version, pruning_log::num_rows))
+            .order(pruning_log::max_version)
+            .load::<(i64, i64)>(&mut conn)
+            .unwrap();
+        assert_eq!(batches, vec![(1, 1), (3, 2)]);
+    }
+}
@@ -0,0 +1,273 @@
+// Copyright (c) Aptos
+// SPDX-License-Identifier: Apache-2.0
+
+//! Creators of collection table handles, for collection table items written by a transaction that
+//! doesn't also write the Collections resource. Without it, each of those is a query against
+//! current_collection_datas.
+//!
+//! That's the only per-transaction lookup left to cache. Royalties, table types and token data
+//! come from the transaction's own write set without reading the db, and the reads keyed by
+//! token_data_id_hash (ex: owners, listings, cost basis, acquisitions) are made once per batch for
+//! its tokens, against rows that can change in any batch, so there's nothing to reuse from one
+//! batch to the next.
+
+use crate::counters::{
+    TABLE_HANDLE_CACHE_ENTRIES, TABLE_HANDLE_CACHE_EVICTIONS, TABLE_HANDLE_CACHE_LOOKUPS,
+};
+use anyhow::ensure;
+use aptos_config::config::TableHandleCacheConfig;
+use std::{
+    collections::{BTreeMap, HashMap},
+    sync::Mutex,
+    time::{Duration, Instant},
+};
+
+pub const DEFAULT_TABLE_HANDLE_CACHE_SIZE: usize = 100_000;
+
+#[derive(Debug)]
+struct CachedCreator {
+    creator_address: String,
+    /// Version the creator was read or written at
+    version: i64,
+    cached_at: Instant,
+    last_used: u64,
+}
+
+/// Least recently used entries are evicted once the cache is full
+#[derive(Debug)]
+struct Lru {
+    capacity: usize,
+    /// Starts at 1, new entries have last_used 0 until they're touched
+    next_use: u64,
+    entries: HashMap<String, CachedCreator>,
+    by_last_used: BTreeMap<u64, String>,
+}
+
+impl Lru {
+    fn touch(&mut self, table_handle: &str) {
+        let next_use = self.next_use;
+        self.next_use += 1;
+        if let Some(entry) = self.entries.get_mut(table_handle) {
+            self.by_last_used.remove(&entry.last_used);
+            entry.last_used = next_use;
+            self.by_last_used.insert(next_use, table_handle.to_string());
+        }
+    }
+
+    fn evict(&mut self) {
+        while self.entries.len() >= self.capacity {
+            let last_used = match self.by_last_used.keys().next() {
+                Some(last_used) => *last_used,
+                None => return,
+            };
+            if let Some(table_handle) = self.by_last_used.remove(&last_used) {
+                self.entries.remove(&table_handle);
+                TABLE_HANDLE_CACHE_EVICTIONS
+                    .with_label_values(&["capacity"])
+                    .inc();
+            }
+        }
+    }
+
+    /// Drops the handle's entry if it was cached at least ttl ago
+    fn expire(&mut self, table_handle: &str, ttl: Option<Duration>) {
+        let ttl = match ttl {
+            Some(ttl) => ttl,
+            None => return,
+        };
+        let last_used = match self.entries.get(table_handle) {
+            Some(entry) if entry.cached_at.elapsed() >= ttl => entry.last_used,
+            _ => return,
+        };
+        self.entries.remove(table_handle);
+        self.by_last_used.remove(&last_used);
+        TABLE_HANDLE_CACHE_EVICTIONS
+            .with_label_values(&["expired"])
+            .inc();
+        TABLE_HANDLE_CACHE_ENTRIES.set(self.entries.len() as i64);
+    }
+}
+
+#[derive(Debug)]
+pub struct TableHandleCache {
+    lru: Mutex<Lru>,
+    /// Entries older than this are a miss. None if they don't expire
+    ttl: Option<Duration>,
+}
+
+impl TableHandleCache {
+    pub fn from_config(config: Option<&TableHandleCacheConfig>) -> anyhow::Result<Self> {
+        let max_entries = config
+            .and_then(|config| config.max_entries)
+            .unwrap_or(DEFAULT_TABLE_HANDLE_CACHE_SIZE as u64);
+        ensure!(max_entries > 0, "max_entries must be greater than 0");
+        let ttl_secs = config.and_then(|config| config.ttl_secs);
+        ensure!(ttl_secs != Some(0), "ttl_secs must be greater than 0");
+        Ok(Self {
+            ttl: ttl_secs.map(Duration::from_secs),
+            ..Self::new(max_entries as usize)
+        })
+    }
+
+    pub fn new(capacity: usize) -> Self {
+        Self {
+            lru: Mutex::new(Lru {
+                capacity: capacity.max(1),
+                next_use: 1,
+                entries: HashMap::new(),
+                by_last_used: BTreeMap::new(),
+            }),
+            ttl: None,
+        }
+    }
+
+    /// The handle's creator as of txn_version. An entry from a later version is a miss, so a
+    /// backfill never gets a creator it couldn't have seen at the version it's processing, and so
+    /// is an expired one.
+    pub fn get(&self, table_handle: &str, txn_version: i64) -> Option<String> {
+        let mut lru = self.lru.lock().unwrap();
+        lru.expire(table_handle, self.ttl);
+        let creator_address = lru
+            .entries
+            .get(table_handle)
+            .filter(|entry| entry.version <= txn_version)
+            .map(|entry| entry.creator_address.clone());
+        match creator_address {
+            Some(_) => {
+                lru.touch(table_handle);
+                TABLE_HANDLE_CACHE_LOOKUPS.with_label_values(&["hit"]).inc();
+            }
+            None => TABLE_HANDLE_CACHE_LOOKUPS
+                .with_label_values(&["miss"])
+                .inc(),
+        }
+        creator_address
+    }
+
+    /// Records the handle's creator as of version, replacing the cached one unless that is newer
+    /// and hasn't expired
+    pub fn insert(&self, table_handle: &str, creator_address: &str, version: i64) {
+        let mut lru = self.lru.lock().unwrap();
+        lru.expire(table_handle, self.ttl);
+        match lru.entries.get_mut(table_handle) {
+            Some(entry) => {
+                if entry.version > version {
+                    return;
+                }
+                entry.creator_address = creator_address.to_string();
+                entry.version = version;
+                entry.cached_at = Instant::now();
+            }
+            None => {
+                lru.evict();
+                lru.entries.insert(
+                    table_handle.to_string(),
+                    CachedCreator {
+                        creator_address: creator_address.to_string(),
+                        version,
+                        cached_at: Instant::now(),
+                        last_used: 0,
+                    },
+                );
+                TABLE_HANDLE_CACHE_ENTRIES.set(lru.entries.len() as i64);
+            }
+        }
+        lru.touch(table_handle);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_later_versions_are_a_miss() {
+        let cache = TableHandleCache::new(10);
+        cache.insert("0x7ab1e", "0xcafe", 100);
+        assert_eq!(cache.get("0x7ab1e", 99), None);
+        assert_eq!(cache.get("0x7ab1e", 100), Some("0xcafe".to_string()));
+        assert_eq!(cache.get("0x7ab1e", 101), Some("0xcafe".to_string()));
+
+        // An older value doesn't replace a newer one, a newer value does
+        cache.insert("0x7ab1e", "0xbeef", 50);
+        assert_eq!(cache.get("0x7ab1e", 50), None);
+        assert_eq!(cache.get("0x7ab1e", 200), Some("0xcafe".to_string()));
+        cache.insert("0x7ab1e", "0xbeef", 150);
+        assert_eq!(cache.get("0x7ab1e", 120), None);
+        assert_eq!(cache.get("0x7ab1e", 200), Some("0xbeef".to_string()));
+    }
+
+    fn evictions(reason: &str) -> u64 {
+        TABLE_HANDLE_CACHE_EVICTIONS
+            .with_label_values(&[reason])
+            .get()
+    }
+
+    #[test]
+    fn test_least_recently_used_is_evicted() {
+        let cache = TableHandleCache::new(2);
+        cache.insert("0x1", "0xa", 1);
+        cache.insert("0x2", "0xb", 1);
+        assert!(cache.get("0x1", 1).is_some());
+        let evicted = evictions("capacity");
+        cache.insert("0x3", "0xc", 1);
+        // Other tests can evict at the same time
+        assert!(evictions("capacity") > evicted);
+        assert!(cache.get("0x1", 1).is_some());
+        assert!(cache.get("0x2", 1).is_none());
+        assert!(cache.get("0x3", 1).is_some());
+    }
+
+    #[test]
+    fn test_expired_entries_are_a_miss() {
+        let cache = TableHandleCache {
+            ttl: Some(Duration::ZERO),
+            ..TableHandleCache::new(10)
+        };
+        cache.insert("0x7ab1e", "0xcafe", 100);
+        let expired = evictions("expired");
+        assert_eq!(cache.get("0x7ab1e", 100), None);
+        assert!(evictions("expired") > expired);
+        // An older creator can be cached again once the newer one expired
+        cache.insert("0x7ab1e", "0xbeef", 50);
+        assert_eq!(cache.lru.lock().unwrap().entries["0x7ab1e"].version, 50);
+
+        let cache = TableHandleCache {
+            ttl: Some(Duration::from_secs(3600)),
+            ..TableHandleCache::new(10)
+        };
+        cache.insert("0x7ab1e", "0xcafe", 100);
+        assert_eq!(cache.get("0x7ab1e", 100), Some("0xcafe".to_string()));
+    }
+
+    #[test]
+    fn test_from_config() {
+        let cache = TableHandleCache::from_config(None).unwrap();
+        assert_eq!(
+            cache.lru.lock().unwrap().capacity,
+            DEFAULT_TABLE_HANDLE_CACHE_SIZE
+        );
+        assert_eq!(cache.ttl, None);
+
+        let config = TableHandleCacheConfig {
+            max_entries: Some(10),
+            ttl_secs: Some(60),
+        };
+        let cache = TableHandleCache::from_config(Some(&config)).unwrap();
+        assert_eq!(cache.lru.lock().unwrap().capacity, 10);
+        assert_eq!(cache.ttl, Some(Duration::from_secs(60)));
+
+        for config in [
+            TableHandleCacheConfig {
+                max_entries: Some(0),
+                ttl_secs: None,
+            },
+            TableHandleCacheConfig {
+                max_entries: None,
+                ttl_secs: Some(0),
+            },
+        ] {
+            assert!(TableHandleCache::from_config(Some(&config)).is_err());
+        }
+    }
+}
@@ -0,0 +1,173 @@
+// Copyright (c) Aptos
+// SPDX-License-Identifier: Apache-2.0
+
+//! Keys of deleted 0x3 table items. The API only decodes the key of a DeleteTableItem when the
+//! node runs the table info indexer, otherwise all there is are the key's BCS bytes. These are
+//! decoded here into the JSON the API would have given, so deleted items are parsed the same way
+//! either way.
+
+use anyhow::{Context, Result};
+use aptos_api_types::DeleteTableItem as APIDeleteTableItem;
+use aptos_types::account_address::AccountAddress;
+use serde::Deserialize;
+use serde_json::json;
+
+pub const TOKEN_ID_TYPE: &str = "0x3::token::TokenId";
+pub const TOKEN_OFFER_ID_TYPE: &str = "0x3::token_transfers::TokenOfferId";
+
+/// Same layout as 0x3::token::TokenDataId, which is what BCS decodes by
+#[derive(Deserialize)]
+struct TokenDataIdKey {
+    creator: AccountAddress,
+    collection: String,
+    name: String,
+}
+
+#[derive(Deserialize)]
+struct TokenIdKey {
+    token_data_id: TokenDataIdKey,
+    property_version: u64,
+}
+
+#[derive(Deserialize)]
+struct TokenOfferIdKey {
+    to_addr: AccountAddress,
+    token_id: TokenIdKey,
+}
+
+impl TokenIdKey {
+    fn to_json(&self) -> serde_json::Value {
+        json!({
+            "token_data_id": {
+                "creator": self.token_data_id.creator.to_hex_literal(),
+                "collection": self.token_data_id.collection,
+                "name": self.token_data_id.name,
+            },
+            // u64s are strings in the API's JSON
+            "property_version": self.property_version.to_string(),
+        })
+    }
+}
+
+/// Decodes the BCS bytes of a TokenId or TokenOfferId key, None for other key types. Bytes left
+/// over after the key are an error, so a key of another type doesn't decode by accident.
+pub fn decode_table_key(key_type: &str, key: &[u8]) -> Result<Option<serde_json::Value>> {
+    match key_type {
+        TOKEN_ID_TYPE => bcs::from_bytes::<TokenIdKey>(key).map(|key| Some(key.to_json())),
+        TOKEN_OFFER_ID_TYPE => bcs::from_bytes::<TokenOfferIdKey>(key).map(|key| {
+            Some(json!({
+                "to_addr": key.to_addr.to_hex_literal(),
+                "token_id": key.token_id.to_json(),
+            }))
+        }),
+        _ => Ok(None),
+    }
+    .with_context(|| format!("failed to decode {} key {}", key_type, hex::encode(key)))
+}
+
+/// Key type and key of a deleted table item: as the API decoded it, or else decoded from the key
+/// bytes as expected_key_type, which the caller knows from the table the item was in. None if
+/// neither is known.
+pub fn deleted_table_key(
+    table_item: &APIDeleteTableItem,
+    expected_key_type: Option<&str>,
+) -> Result<Option<(String, serde_json::Value)>> {
+    if let Some(data) = &table_item.data {
+        return Ok(Some((data.key_type.clone(), data.key.clone())));
+    }
+    let key_type = match expected_key_type {
+        Some(key_type) => key_type,
+        None => return Ok(None),
+    };
+    Ok(decode_table_key(key_type, &table_item.key.0)?.map(|key| (key_type.to_string(), key)))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::models::token_models::token_utils::TokenWriteSet;
+    use bigdecimal::BigDecimal;
+
+    /// BCS of 0x3::token::TokenId { TokenDataId { 0xc4e7, "Potions", "Potion" }, 1 }
+    fn token_id_bytes() -> Vec<u8> {
+        let mut bytes = AccountAddress::from_hex_literal("0xc4e7").unwrap().to_vec();
+        bytes.push(7);
+        bytes.extend(b"Potions");
+        bytes.push(6);
+        bytes.extend(b"Potion");
+        bytes.extend(1u64.to_le_bytes());
+        bytes
+    }
+
+    fn token_offer_id_bytes() -> Vec<u8> {
+        let mut bytes = AccountAddress::from_hex_literal("0xb0b").unwrap().to_vec();
+        bytes.extend(token_id_bytes());
+        bytes
+    }
+
+    #[test]
+    fn test_decode_table_key() {
+        let token_id = decode_table_key(TOKEN_ID_TYPE, &token_id_bytes())
+            .unwrap()
+            .unwrap();
+        assert_eq!(
+            token_id,
+            json!({
+                "token_data_id": {"creator": "0xc4e7", "collection": "Potions", "name": "Potion"},
+                "property_version": "1"
+            })
+        );
+        // Parses like the key the API decodes
+        match TokenWriteSet::from_table_item_type(TOKEN_ID_TYPE, &token_id, 1).unwrap() {
+            Some(TokenWriteSet::TokenId(inner)) => {
+                assert_eq!(inner.token_data_id.name, "Potion");
+                assert_eq!(inner.property_version, BigDecimal::from(1));
+            }
+            _ => panic!("expected a TokenId"),
+        }
+
+        let offer_id = decode_table_key(TOKEN_OFFER_ID_TYPE, &token_offer_id_bytes())
+            .unwrap()
+            .unwrap();
+        assert_eq!(offer_id["to_addr"], "0xb0b");
+        assert_eq!(offer_id["token_id"], token_id);
+
+        // Keys of the other type, truncated or with bytes left over
+        assert!(decode_table_key(TOKEN_OFFER_ID_TYPE, &token_id_bytes()).is_err());
+        assert!(decode_table_key(TOKEN_ID_TYPE, &token_offer_id_bytes()).is_err());
+        assert!(decode_table_key(TOKEN_ID_TYPE, &token_id_bytes()[..40]).is_err());
+        assert!(decode_table_key("0x1::string::String", &[1, b'a'])
+            .unwrap()
+            .is_none());
+    }
+
+    #[test]
+    fn test_deleted_table_key() {
+        let item = |data: serde_json::Value| -> APIDeleteTableItem {
+            let mut item = json!({
+                "state_key_hash": "0x00",
+                "handle": "0x0a",
+                "key": format!("0x{}", hex::encode(token_id_bytes())),
+            });
+            if !data.is_null() {
+                item["data"] = data;
+            }
+            serde_json::from_value(item).unwrap()
+        };
+        // The API's decoding wins
+        let decoded = json!({"key": {"decoded": true}, "key_type": TOKEN_ID_TYPE});
+        assert_eq!(
+            deleted_table_key(&item(decoded), Some(TOKEN_OFFER_ID_TYPE)).unwrap(),
+            Some((TOKEN_ID_TYPE.to_string(), json!({"decoded": true})))
+        );
+        let (key_type, key) =
+            deleted_table_key(&item(serde_json::Value::Null), Some(TOKEN_ID_TYPE))
+                .unwrap()
+                .unwrap();
+        assert_eq!(key_type, TOKEN_ID_TYPE);
+        assert_eq!(key["token_data_id"]["collection"], "Potions");
+        assert!(deleted_table_key(&item(serde_json::Value::Null), None)
+            .unwrap()
+            .is_none());
+    }
+}
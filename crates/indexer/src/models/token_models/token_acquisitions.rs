@@ -0,0 +1,336 @@
+// Copyright (c) Aptos
+// SPDX-License-Identifier: Apache-2.0
+
+// This is required because a diesel macro makes clippy sad
+#![allow(clippy::extra_unused_lifetimes)]
+#![allow(clippy::unused_unit)]
+
+use super::{nft_sales::NftSale, token_activities::TokenActivity};
+use crate::{database::PgPoolConnection, schema::token_acquisitions};
+use diesel::{
+    sql_query,
+    sql_types::{Array, Text},
+    ExpressionMethods, PgConnection, QueryDsl, QueryResult, RunQueryDsl,
+};
+use field_count::FieldCount;
+use serde::{Deserialize, Serialize};
+use std::collections::{HashMap, HashSet};
+
+const DEPOSIT_EVENT_TYPE: &str = "0x3::token::DepositEvent";
+
+/// (token_data_id_hash, owner_address)
+pub type TokenAcquisitionPK = (String, String);
+
+/// When an owner last received a token, either by a deposit or as the buyer of a sale. This is
+/// what a sale's hold duration is measured from.
+#[derive(Clone, Debug, Deserialize, FieldCount, Identifiable, Insertable, Serialize)]
+#[diesel(primary_key(token_data_id_hash, owner_address))]
+#[diesel(table_name = token_acquisitions)]
+pub struct TokenAcquisition {
+    pub token_data_id_hash: String,
+    pub owner_address: String,
+    pub acquired_at: chrono::NaiveDateTime,
+    pub last_transaction_version: i64,
+}
+
+impl TokenAcquisition {
+    /// Sets hold_duration_secs on the batch's sales and returns the acquisitions that changed.
+    /// Sellers that didn't acquire the token earlier in the batch are looked up in the db, which
+    /// has every earlier acquisition since the token processor commits its batches one at a time
+    /// in version order. Acquisitions from later versions, ex: when reprocessing, are ignored.
+    pub fn from_batch(
+        conn: &mut PgPoolConnection,
+        nft_sales: &mut [NftSale],
+        token_activities: &[TokenActivity],
+    ) -> QueryResult<HashMap<TokenAcquisitionPK, Self>> {
+        let previous = Self::load_seller_acquisitions(conn, nft_sales)?;
+        Ok(Self::apply_to_sales(previous, nft_sales, token_activities))
+    }
+
+    fn load_seller_acquisitions(
+        conn: &mut PgPoolConnection,
+        nft_sales: &[NftSale],
+    ) -> QueryResult<HashMap<TokenAcquisitionPK, Self>> {
+        let (token_data_id_hashes, owner_addresses): (HashSet<String>, HashSet<String>) = nft_sales
+            .iter()
+            .filter_map(|sale| Some((sale.token_data_id_hash.clone(), sale.seller.clone()?)))
+            .unzip();
+        if owner_addresses.is_empty() {
+            return Ok(HashMap::new());
+        }
+        // This can return rows for other owners of the same tokens, which go unused
+        let rows = token_acquisitions::table
+            .select((
+                token_acquisitions::token_data_id_hash,
+                token_acquisitions::owner_address,
+                token_acquisitions::acquired_at,
+                token_acquisitions::last_transaction_version,
+            ))
+            .filter(token_acquisitions::token_data_id_hash.eq_any(token_data_id_hashes))
+            .filter(token_acquisitions::owner_address.eq_any(owner_addresses))
+            .load::<(String, String, chrono::NaiveDateTime, i64)>(conn)?;
+        Ok(rows
+            .into_iter()
+            .map(
+                |(token_data_id_hash, owner_address, acquired_at, last_transaction_version)| {
+                    (
+                        (token_data_id_hash.clone(), owner_address.clone()),
+                        Self {
+                            token_data_id_hash,
+                            owner_address,
+                            acquired_at,
+                            last_transaction_version,
+                        },
+                    )
+                },
+            )
+            .collect())
+    }
+
+    /// Sales and activities are both in version order. Only acquisitions from before a sale's
+    /// version count, so the sale's own deposit to the buyer is never used.
+    fn apply_to_sales(
+        mut acquisitions: HashMap<TokenAcquisitionPK, Self>,
+        nft_sales: &mut [NftSale],
+        token_activities: &[TokenActivity],
+    ) -> HashMap<TokenAcquisitionPK, Self> {
+        let mut changed = HashMap::new();
+        let mut deposits = token_activities
+            .iter()
+            .filter(|activity| activity.transfer_type == DEPOSIT_EVENT_TYPE)
+            .peekable();
+        let mut acquire = |acquisitions: &mut HashMap<TokenAcquisitionPK, Self>,
+                           acquisition: Self| {
+            let pk = (
+                acquisition.token_data_id_hash.clone(),
+                acquisition.owner_address.clone(),
+            );
+            acquisitions.insert(pk.clone(), acquisition.clone());
+            changed.insert(pk, acquisition);
+        };
+        for sale in nft_sales.iter_mut() {
+            while let Some(deposit) =
+                deposits.next_if(|deposit| deposit.transaction_version < sale.transaction_version)
+            {
+                if let Some(acquisition) = Self::from_deposit(deposit) {
+                    acquire(&mut acquisitions, acquisition);
+                }
+            }
+            // NULL rather than 0 when the acquisition is from before indexing
+            sale.hold_duration_secs = sale.seller.as_ref().and_then(|seller| {
+                let acquisition =
+                    acquisitions.get(&(sale.token_data_id_hash.clone(), seller.clone()))?;
+                (acquisition.last_transaction_version < sale.transaction_version)
+                    .then(|| (sale.transaction_timestamp - acquisition.acquired_at).num_seconds())
+            });
+            if let Some(buyer) = &sale.buyer {
+                acquire(
+                    &mut acquisitions,
+                    Self {
+                        token_data_id_hash: sale.token_data_id_hash.clone(),
+                        owner_address: buyer.clone(),
+                        acquired_at: sale.transaction_timestamp,
+                        last_transaction_version: sale.transaction_version,
+                    },
+                );
+            }
+        }
+        for deposit in deposits {
+            if let Some(acquisition) = Self::from_deposit(deposit) {
+                acquire(&mut acquisitions, acquisition);
+            }
+        }
+        changed
+    }
+
+    fn from_deposit(deposit: &TokenActivity) -> Option<Self> {
+        Some(Self {
+            token_data_id_hash: deposit.token_data_id_hash.clone(),
+            owner_address: deposit.to_address.clone()?,
+            acquired_at: deposit.transaction_timestamp,
+            last_transaction_version: deposit.transaction_version,
+        })
+    }
+}
+
+/// Recomputes the average and median hold duration of the collections with sales in the batch.
+/// Medians can't be maintained incrementally, so this aggregates the collections' sales again.
+pub fn refresh_collection_hold_durations(
+    conn: &mut PgConnection,
+    nft_sales: &[NftSale],
+) -> QueryResult<()> {
+    let mut collection_data_id_hashes = nft_sales
+        .iter()
+        .filter(|sale| sale.hold_duration_secs.is_some())
+        .map(|sale| sale.collection_data_id_hash.clone())
+        .collect::<Vec<_>>();
+    if collection_data_id_hashes.is_empty() {
+        return Ok(());
+    }
+    // Sorted to avoid deadlocks, same as the other current tables
+    collection_data_id_hashes.sort();
+    collection_data_id_hashes.dedup();
+    sql_query(
+        "INSERT INTO collection_hold_durations (
+            collection_data_id_hash,
+            avg_hold_duration_secs,
+            median_hold_duration_secs,
+            sales_count,
+            last_transaction_version
+        )
+        SELECT collection_data_id_hash,
+            AVG(hold_duration_secs)::DOUBLE PRECISION,
+            PERCENTILE_CONT(0.5) WITHIN GROUP (ORDER BY hold_duration_secs),
+            COUNT(*),
+            MAX(transaction_version)
+        FROM nft_sales
+        WHERE collection_data_id_hash = ANY($1) AND hold_duration_secs IS NOT NULL
+        GROUP BY collection_data_id_hash
+        ORDER BY collection_data_id_hash
+        ON CONFLICT (collection_data_id_hash) DO UPDATE SET
+            avg_hold_duration_secs = EXCLUDED.avg_hold_duration_secs,
+            median_hold_duration_secs = EXCLUDED.median_hold_duration_secs,
+            sales_count = EXCLUDED.sales_count,
+            last_transaction_version = EXCLUDED.last_transaction_version,
+            inserted_at = NOW()",
+    )
+    .bind::<Array<Text>, _>(collection_data_id_hashes)
+    .execute(conn)?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_utils::token_id;
+    use crate::{
+        models::token_models::{
+            marketplace_event_mappings::MarketplaceEventMappings, token_utils::TokenEvents,
+        },
+        util::standardize_address,
+    };
+    use aptos_api_types::Transaction as APITransaction;
+    use serde_json::json;
+
+    const HASH: &str = "0x6a527d06063dfd42c6b3a862574d5f3ec1660afb8058135edda5072712bfdb51";
+    const TOPAZ: &str = "0x2c7bccf7b31baf770fdbcc768d9e9cb3d87805e255355df5db32ac9a669010a2";
+    const DAY_SECS: i64 = 86400;
+
+    fn user_txn(version: u64, timestamp_secs: i64, event: serde_json::Value) -> APITransaction {
+        serde_json::from_value(json!({
+            "type": "user_transaction",
+            "version": version.to_string(),
+            "hash": HASH,
+            "state_change_hash": HASH,
+            "event_root_hash": HASH,
+            "state_checkpoint_hash": null,
+            "gas_used": "100",
+            "success": true,
+            "vm_status": "Executed successfully",
+            "accumulator_root_hash": HASH,
+            "changes": [],
+            "sender": "0xb0b",
+            "sequence_number": "0",
+            "max_gas_amount": "2000",
+            "gas_unit_price": "100",
+            "expiration_timestamp_secs": "1668000600",
+            "payload": {
+                "type": "entry_function_payload",
+                "function": "0x3::token::direct_transfer_script",
+                "type_arguments": [],
+                "arguments": []
+            },
+            "events": [event],
+            "timestamp": (timestamp_secs * 1_000_000).to_string()
+        }))
+        .unwrap()
+    }
+
+    fn deposit_txn(version: u64, timestamp_secs: i64, owner_address: &str) -> APITransaction {
+        user_txn(
+            version,
+            timestamp_secs,
+            json!({
+                "guid": {"creation_number": "4", "account_address": owner_address},
+                "sequence_number": "0",
+                "type": DEPOSIT_EVENT_TYPE,
+                "data": {"amount": "1", "id": token_id()}
+            }),
+        )
+    }
+
+    fn topaz_sell_txn(version: u64, timestamp_secs: i64, seller: &str) -> APITransaction {
+        user_txn(
+            version,
+            timestamp_secs,
+            json!({
+                "guid": {"creation_number": "5", "account_address": TOPAZ},
+                "sequence_number": version.to_string(),
+                "type": format!("{}::events::SellEvent", TOPAZ),
+                "data": {
+                    "timestamp": timestamp_secs.to_string(),
+                    "bid_id": "3",
+                    "token_id": token_id(),
+                    "deadline": "0",
+                    "price": "100000000",
+                    "coin_type": {
+                        "account_address": "0x1",
+                        "module_name": "0x6170746f735f636f696e",
+                        "struct_name": "0x4170746f73436f696e"
+                    },
+                    "amount": "1",
+                    "buyer": "0xb0b",
+                    "seller": seller
+                }
+            }),
+        )
+    }
+
+    /// Sales with hold durations set, and the acquisitions left after the batch
+    fn apply(
+        transactions: &[APITransaction],
+    ) -> (Vec<NftSale>, HashMap<TokenAcquisitionPK, TokenAcquisition>) {
+        let mut activities = vec![];
+        let mut sales = vec![];
+        for txn in transactions {
+            let mut txn_activities = TokenActivity::from_transaction(
+                txn,
+                &TokenEvents::from_transaction(txn).unwrap(),
+                &MarketplaceEventMappings::default(),
+            );
+            sales.append(&mut NftSale::from_token_activities(
+                txn,
+                &txn_activities,
+                None,
+            ));
+            activities.append(&mut txn_activities);
+        }
+        let changed = TokenAcquisition::apply_to_sales(HashMap::new(), &mut sales, &activities);
+        (sales, changed)
+    }
+
+    #[test]
+    fn test_deposit_then_sell_two_days_later() {
+        let start = 1668000000;
+        let (sales, changed) = apply(&[
+            deposit_txn(10, start, "0xa11ce"),
+            topaz_sell_txn(20, start + 2 * DAY_SECS, "0xa11ce"),
+        ]);
+        assert_eq!(sales.len(), 1);
+        assert_eq!(sales[0].hold_duration_secs, Some(2 * DAY_SECS));
+        let buyer = &changed[&(
+            sales[0].token_data_id_hash.clone(),
+            standardize_address("0xb0b"),
+        )];
+        assert_eq!(buyer.last_transaction_version, 20);
+    }
+
+    #[test]
+    fn test_unknown_acquisition_is_null() {
+        let (sales, _) = apply(&[
+            deposit_txn(10, 1668000000, "0xb0b"),
+            topaz_sell_txn(20, 1668100000, "0xa11ce"),
+        ]);
+        assert_eq!(sales[0].hold_duration_secs, None);
+    }
+}
@@ -5,22 +5,53 @@
 #![allow(clippy::extra_unused_lifetimes)]
 #![allow(clippy::unused_unit)]
 
-use super::token_utils::{TokenDataIdType, TokenEvent};
+use super::{
+    event_effects::{TokenEventEffects, TransferKind},
+    marketplace_event_mappings::MarketplaceEventMappings,
+    nft_sales::is_sale_event,
+    token_utils::{TokenEvent, TokenEvents},
+};
 use crate::{
+    indexer::transaction_trace::TraceSpan,
     schema::token_activities,
-    util::{parse_timestamp},
+    util::standardize_address,
 };
 use aptos_api_types::{Event as APIEvent, Transaction as APITransaction};
-use bigdecimal::{BigDecimal, Zero};
+use bigdecimal::BigDecimal;
+use diesel::{
+    sql_query,
+    sql_types::{Array, BigInt, Nullable, Text},
+    PgConnection, QueryResult, RunQueryDsl,
+};
 use field_count::FieldCount;
 use serde::{Deserialize, Serialize};
 
-#[derive(Debug, Deserialize, FieldCount, Identifiable, Insertable, Serialize)]
+pub const WITHDRAW_EVENT_TYPE: &str = "0x3::token::WithdrawEvent";
+pub const DEPOSIT_EVENT_TYPE: &str = "0x3::token::DepositEvent";
+pub const DEFAULT_TRANSFER_KIND_CHUNK_VERSIONS: i64 = 1_000_000;
+
+/// (transaction_version, event_account_address, event_creation_number, event_sequence_number,
+/// event_index, token_index)
+pub type TokenActivityPK = (i64, String, i64, i64, i64, i64);
+
+#[derive(
+    Clone,
+    Debug,
+    Deserialize,
+    FieldCount,
+    Identifiable,
+    Insertable,
+    Queryable,
+    Selectable,
+    Serialize,
+)]
 #[diesel(primary_key(
     transaction_version,
     event_account_address,
     event_creation_number,
-    event_sequence_number
+    event_sequence_number,
+    event_index,
+    token_index
 ))]
 #[diesel(table_name = token_activities)]
 pub struct TokenActivity {
@@ -28,384 +59,399 @@ pub struct TokenActivity {
     pub event_account_address: String,
     pub event_creation_number: i64,
     pub event_sequence_number: i64,
+    /// Position of the event within the transaction, tells apart events that share a guid
+    pub event_index: i64,
+    /// Position of the token within the event, for events about several tokens like sweeps,
+    /// see TokenEvent::token_count. 0 for everything else.
+    pub token_index: i64,
     pub token_data_id_hash: String,
     pub property_version: BigDecimal,
     pub creator_address: String,
     pub collection_name: String,
     pub name: String,
     pub transfer_type: String,
+    /// What the activity is, see TransferKind. Null for rows indexed before it was added until
+    /// backfill_transfer_kinds fills them.
+    pub transfer_kind: Option<String>,
+    /// The ANS domain the token is named after, set by AnsDomains::set_activity_domains. Null for
+    /// other tokens.
+    pub domain: Option<String>,
     pub from_address: Option<String>,
     pub to_address: Option<String>,
     pub token_amount: BigDecimal,
     pub coin_type: Option<String>,
     pub coin_amount: Option<BigDecimal>,
+    /// Set when the event's price or amount is out of bounds, see TokenEventEffects::suspect_value
+    pub suspect_value: bool,
     pub collection_data_id_hash: String,
     pub transaction_timestamp: chrono::NaiveDateTime,
 }
 
-/// A simplified TokenActivity (excluded common fields) to reduce code duplication
-struct TokenActivityHelper<'a> {
-    pub token_data_id: &'a TokenDataIdType,
-    pub property_version: BigDecimal,
-    pub from_address: Option<String>,
-    pub to_address: Option<String>,
-    pub token_amount: BigDecimal,
-    pub coin_type: Option<String>,
-    pub coin_amount: Option<BigDecimal>,
+/// Module events (event v2) aren't emitted from an event handle, so their guid is the 0x0 sentinel
+/// and their creation and sequence numbers are meaningless
+pub fn is_module_event(event: &APIEvent) -> bool {
+    event.guid.creation_number.0 == 0
+        && event
+            .guid
+            .account_address
+            .to_string()
+            .trim_start_matches("0x")
+            .trim_start_matches('0')
+            .is_empty()
+}
+
+/// (event_account_address, event_creation_number, event_sequence_number) of an event. Module
+/// events all share the sentinel guid, so their index stands in for the sequence number to keep
+/// the primary key unique.
+pub fn event_key(event: &APIEvent, event_index: i64) -> (String, i64, i64) {
+    if is_module_event(event) {
+        return (event.guid.account_address.to_string(), 0, event_index);
+    }
+    (
+        event.guid.account_address.to_string(),
+        event.guid.creation_number.0 as i64,
+        event.sequence_number.0 as i64,
+    )
+}
+
+/// Address of the event handle's owner, which the framework token events use as the account
+/// they're about. Module events don't have one and need to carry the address in their data.
+pub fn event_handle_address(event: &APIEvent) -> Option<String> {
+    if is_module_event(event) {
+        None
+    } else {
+        Some(standardize_address(&event.guid.account_address.to_string()))
+    }
 }
 
 impl TokenActivity {
-    pub fn from_transaction(transaction: &APITransaction) -> Vec<Self> {
-        let mut token_activities = vec![];
+    pub fn pk(&self) -> TokenActivityPK {
+        (
+            self.transaction_version,
+            self.event_account_address.clone(),
+            self.event_creation_number,
+            self.event_sequence_number,
+            self.event_index,
+            self.token_index,
+        )
+    }
+
+    /// Parses the transaction's events with TokenEventEffects::from_transaction
+    pub fn from_transaction(
+        transaction: &APITransaction,
+        token_events: &TokenEvents,
+        marketplace_event_mappings: &MarketplaceEventMappings,
+    ) -> Vec<Self> {
+        Self::from_effects(&TokenEventEffects::from_transaction(
+            transaction,
+            token_events,
+            marketplace_event_mappings,
+        ))
+    }
+
+    /// One activity per token of each event
+    pub fn from_effects(effects: &[TokenEventEffects]) -> Vec<Self> {
+        let mut token_activities = effects.iter().map(Self::from_effect).collect::<Vec<_>>();
+        Self::pair_transfers(&mut token_activities);
+        token_activities
+    }
+
+    /// A framework deposit only has its receiver. When an earlier withdrawal in the same
+    /// transaction moved the same token, property version and amount, the deposit's from_address
+    /// is set to whoever withdrew it, so the deposit reads as a transfer from one to the other.
+    /// Deposits are matched in event order with the earliest withdrawal left unmatched, so
+    /// identical pairs pair up first with first. Unmatched events are left as they are.
+    fn pair_transfers(token_activities: &mut [Self]) {
+        let mut unmatched_withdrawals: Vec<usize> = vec![];
+        for index in 0..token_activities.len() {
+            let activity = &token_activities[index];
+            if activity.transfer_type == WITHDRAW_EVENT_TYPE {
+                unmatched_withdrawals.push(index);
+                continue;
+            }
+            if activity.transfer_type != DEPOSIT_EVENT_TYPE || activity.from_address.is_some() {
+                continue;
+            }
+            let matched = unmatched_withdrawals.iter().position(|withdrawal_index| {
+                let withdrawal = &token_activities[*withdrawal_index];
+                withdrawal.token_data_id_hash == activity.token_data_id_hash
+                    && withdrawal.property_version == activity.property_version
+                    && withdrawal.token_amount == activity.token_amount
+            });
+            if let Some(position) = matched {
+                let withdrawal_index = unmatched_withdrawals.remove(position);
+                token_activities[index].from_address =
+                    token_activities[withdrawal_index].from_address.clone();
+            }
+        }
+    }
+
+    /// Records how each event is parsed, for transactions flagged in `trace_versions`. Parse
+    /// errors are recorded instead of unwrapped.
+    pub fn trace_events(
+        span: &mut TraceSpan,
+        transaction: &APITransaction,
+        marketplace_event_mappings: &MarketplaceEventMappings,
+    ) {
         if let APITransaction::UserTransaction(user_txn) = transaction {
-            for event in &user_txn.events {
-                let txn_version = user_txn.info.version.0 as i64;
+            let txn_version = user_txn.info.version.0 as i64;
+            for (index, event) in user_txn.events.iter().enumerate() {
                 let event_type = event.typ.to_string();
-                match TokenEvent::from_event(event_type.as_str(), &event.data, txn_version).unwrap()
-                {
-                    Some(token_event) => token_activities.push(Self::from_parsed_event(
-                        &event_type,
-                        event,
-                        &token_event,
-                        txn_version,
-                        parse_timestamp(user_txn.timestamp.0, txn_version),
-                    )),
-                    None => {}
+                let event_span = span.child("event");
+                event_span
+                    .attribute("index", index)
+                    .attribute("type", &event_type);
+                let token_event = TokenEvent::from_event(event_type.as_str(), &event.data, txn_version)
+                    .and_then(|token_event| match token_event {
+                        Some(token_event) => Ok(Some(Some(token_event))),
+                        None => marketplace_event_mappings
+                            .marketplace_event(event_type.as_str(), &event.data, txn_version)
+                            .map(|marketplace_event| {
+                                marketplace_event.map(|parsed| parsed.token_event)
+                            }),
+                    });
+                let parser = match token_event {
+                    // A marketplace event without a typed event is only known to its adapter
+                    Ok(Some(None)) => Ok(Some("marketplace_adapter".to_string())),
+                    Ok(Some(Some(token_event))) => {
+                        event_span.attribute("parsed", &token_event);
+                        let debug = format!("{:?}", token_event);
+                        Ok(Some(debug.split('(').next().unwrap_or_default().to_string()))
+                    }
+                    Ok(None) => marketplace_event_mappings
+                        .from_event(event_type.as_str(), &event.data, txn_version)
+                        .map(|mapped_event| {
+                            mapped_event.map(|_| "marketplace_event_mapping".to_string())
+                        }),
+                    Err(err) => Err(err),
                 };
+                match parser {
+                    Ok(parser) => {
+                        event_span
+                            .attribute("is_sale", parser.is_some() && is_sale_event(&event_type))
+                            .attribute("parser", parser);
+                    }
+                    Err(err) => {
+                        event_span.attribute("error", format!("{:#}", err));
+                    }
+                }
             }
         }
-        token_activities
     }
 
-    pub fn from_parsed_event(
-        event_type: &str,
-        event: &APIEvent,
-        token_event: &TokenEvent,
-        txn_version: i64,
-        txn_timestamp: chrono::NaiveDateTime,
-    ) -> Self {
-        let event_account_address = &event.guid.account_address.to_string();
-        let event_creation_number = event.guid.creation_number.0 as i64;
-        let event_sequence_number = event.sequence_number.0 as i64;
-        let binding = match token_event {
-            TokenEvent::TopazCancelCollectionBidEvent(inner) => 
-                TokenDataIdType {
-                    creator: inner.creator.clone(),
-                    collection: inner.collection_name.clone(),
-                    name: "COLLECTION".to_owned(),
-                }.clone(),
-            TokenEvent::TopazCollectionBidEvent(inner) => 
-                TokenDataIdType {
-                    creator: inner.creator.clone(),
-                    collection: inner.collection_name.clone(),
-                    name: "COLLECTION".to_owned(),
-                }.clone(),
-            _ => TokenDataIdType {
-                creator: "".to_owned(),
-                collection: "".to_owned(),
-                name: "COLLECTION".to_owned(),
-            }.clone()
-        };
-        let token_activity_helper = match token_event {
-            TokenEvent::MintTokenEvent(inner) => TokenActivityHelper {
-                token_data_id: &inner.id,
-                property_version: BigDecimal::zero(),
-                from_address: Some(event_account_address.clone()),
-                to_address: None,
-                token_amount: inner.amount.clone(),
-                coin_type: None,
-                coin_amount: None,
-            },
-            TokenEvent::BurnTokenEvent(inner) => TokenActivityHelper {
-                token_data_id: &inner.id.token_data_id,
-                property_version: inner.id.property_version.clone(),
-                from_address: Some(event_account_address.clone()),
-                to_address: None,
-                token_amount: inner.amount.clone(),
-                coin_type: None,
-                coin_amount: None,
-            },
-            TokenEvent::MutateTokenPropertyMapEvent(inner) => TokenActivityHelper {
-                token_data_id: &inner.new_id.token_data_id,
-                property_version: inner.new_id.property_version.clone(),
-                from_address: Some(event_account_address.clone()),
-                to_address: None,
-                token_amount: BigDecimal::zero(),
-                coin_type: None,
-                coin_amount: None,
-            },
-            TokenEvent::WithdrawTokenEvent(inner) => TokenActivityHelper {
-                token_data_id: &inner.id.token_data_id,
-                property_version: inner.id.property_version.clone(),
-                from_address: Some(event_account_address.clone()),
-                to_address: None,
-                token_amount: inner.amount.clone(),
-                coin_type: None,
-                coin_amount: None,
-            },
-            TokenEvent::DepositTokenEvent(inner) => TokenActivityHelper {
-                token_data_id: &inner.id.token_data_id,
-                property_version: inner.id.property_version.clone(),
-                from_address: None,
-                to_address: Some((&event_account_address).to_string()),
-                token_amount: inner.amount.clone(),
-                coin_type: None,
-                coin_amount: None,
-            },
-            TokenEvent::OfferTokenEvent(inner) => TokenActivityHelper {
-                token_data_id: &inner.token_id.token_data_id,
-                property_version: inner.token_id.property_version.clone(),
-                from_address: Some(event_account_address.clone()),
-                to_address: Some(inner.to_address.clone()),
-                token_amount: inner.amount.clone(),
-                coin_type: None,
-                coin_amount: None,
-            },
-            TokenEvent::CancelTokenOfferEvent(inner) => TokenActivityHelper {
-                token_data_id: &inner.token_id.token_data_id,
-                property_version: inner.token_id.property_version.clone(),
-                from_address: Some(event_account_address.clone()),
-                to_address: Some(inner.to_address.clone()),
-                token_amount: inner.amount.clone(),
-                coin_type: None,
-                coin_amount: None,
-            },
-            TokenEvent::ClaimTokenEvent(inner) => TokenActivityHelper {
-                token_data_id: &inner.token_id.token_data_id,
-                property_version: inner.token_id.property_version.clone(),
-                from_address: Some(event_account_address.clone()),
-                to_address: Some(inner.to_address.clone()),
-                token_amount: inner.amount.clone(),
-                coin_type: None,
-                coin_amount: None,
-            },
-            TokenEvent::BlueMoveAuctionEvent(inner) => TokenActivityHelper {
-                token_data_id: &inner.id.token_data_id,
-                property_version: inner.id.property_version.clone(),
-                from_address: Some(inner.owner_address.clone()),
-                to_address: None,
-                token_amount: BigDecimal::zero(),
-                coin_type: None,
-                coin_amount: Some(inner.min_selling_price.clone()),
-            },
-            TokenEvent::BlueBidEvent(inner) => TokenActivityHelper {
-                token_data_id: &inner.id.token_data_id,
-                property_version: inner.id.property_version.clone(),
-                from_address: Some(inner.bider_address.clone()),
-                to_address: None,
-                token_amount: BigDecimal::zero(),
-                coin_type: None,
-                coin_amount: Some(inner.bid.clone()),
-            },
-            TokenEvent::BlueBuyEvent(inner) => TokenActivityHelper {
-                token_data_id: &inner.id.token_data_id,
-                property_version: inner.id.property_version.clone(),
-                from_address: None,
-                to_address: Some(inner.buyer_address.clone()),
-                token_amount: BigDecimal::zero(),
-                coin_type: None,
-                coin_amount: None,
-            },
-            TokenEvent::BlueChangePriceEvent(inner) => TokenActivityHelper {
-                token_data_id: &inner.id.token_data_id,
-                property_version: inner.id.property_version.clone(),
-                from_address: Some(inner.seller_address.clone()),
-                to_address: None,
-                token_amount: BigDecimal::zero(),
-                coin_type: None,
-                coin_amount: Some(inner.amount.clone()),
-            },
-            TokenEvent::BlueClaimCoinsEvent(inner) => TokenActivityHelper {
-                token_data_id: &inner.id.token_data_id,
-                property_version: inner.id.property_version.clone(),
-                from_address: Some(inner.owner_token.clone()),
-                to_address: None,
-                token_amount: BigDecimal::zero(),
-                coin_type: None,
-                coin_amount: None,
-            },
-            TokenEvent::BlueClaimTokenEvent(inner) => TokenActivityHelper {
-                token_data_id: &inner.id.token_data_id,
-                property_version: inner.id.property_version.clone(),
-                from_address: None,
-                to_address: Some(inner.bider_address.clone()),
-                token_amount: BigDecimal::zero(),
-                coin_type: None,
-                coin_amount: None,
-            },
-            TokenEvent::BlueDelistEvent(inner) => TokenActivityHelper {
-                token_data_id: &inner.id.token_data_id,
-                property_version: inner.id.property_version.clone(),
-                from_address: Some(inner.seller_address.clone()),
-                to_address: None,
-                token_amount: BigDecimal::zero(),
-                coin_type: None,
-                coin_amount: None,
-            },
-            TokenEvent::BlueListEvent(inner) => TokenActivityHelper {
-                token_data_id: &inner.id.token_data_id,
-                property_version: inner.id.property_version.clone(),
-                from_address: Some(inner.seller_address.clone()),
-                to_address: None,
-                token_amount: inner.amount.clone(),
-                coin_type: None,
-                coin_amount: None,
-            },
-            TokenEvent::TopazBidEvent(inner) => TokenActivityHelper {
-                token_data_id: &inner.token_id.token_data_id,
-                property_version: inner.token_id.property_version.clone(),
-                from_address: Some(inner.buyer.clone()),
-                to_address: None,
-                token_amount: inner.amount.clone(),
-                coin_type: Some(inner.coin_type.to_string()),
-                coin_amount: Some(inner.price.clone()),
-            },
-            TokenEvent::TopazBuyEvent(inner) => TokenActivityHelper {
-                token_data_id: &inner.token_id.token_data_id,
-                property_version: inner.token_id.property_version.clone(),
-                from_address: Some(inner.seller.clone()),
-                to_address: Some(inner.buyer.clone()),
-                token_amount: inner.amount.clone(),
-                coin_type: None,
-                coin_amount: Some(inner.price.clone()),
-            },
-            TokenEvent::TopazCancelBidEvent(inner) => TokenActivityHelper {
-                token_data_id: &inner.token_id.token_data_id,
-                property_version: inner.token_id.property_version.clone(),
-                from_address: Some(inner.buyer.clone()),
-                to_address: None,
-                token_amount: inner.amount.clone(),
-                coin_type: Some(inner.coin_type.to_string()),
-                coin_amount: Some(inner.price.clone()),
-            },
-            TokenEvent::TopazCancelCollectionBidEvent(inner) => TokenActivityHelper {
-                token_data_id: &binding,
-                property_version: BigDecimal::zero(),
-                from_address: Some(inner.buyer.clone()),
-                to_address: None,
-                token_amount: inner.amount.clone(),
-                coin_type: Some(inner.coin_type.to_string()),
-                coin_amount: Some(inner.price.clone()),
-            },
-            TokenEvent::TopazClaimEvent(inner) => TokenActivityHelper {
-                token_data_id: &inner.token_id.token_data_id,
-                property_version: inner.token_id.property_version.clone(),
-                from_address: None,
-                to_address: Some(inner.receiver.clone()),
-                token_amount: BigDecimal::zero(),
-                coin_type: None,
-                coin_amount: None,
-            },
-            TokenEvent::TopazCollectionBidEvent(inner) => TokenActivityHelper {
-                token_data_id: &binding,
-                property_version: BigDecimal::zero(),
-                from_address: Some(inner.buyer.clone()),
-                to_address: None,
-                token_amount: inner.amount.clone(),
-                coin_type: Some(inner.coin_type.to_string()),
-                coin_amount: Some(inner.price.clone()),
-            },
-            TokenEvent::TopazDelistEvent(inner) => TokenActivityHelper {
-                token_data_id: &inner.token_id.token_data_id,
-                property_version: inner.token_id.property_version.clone(),
-                from_address: Some(inner.seller.clone()),
-                to_address: None,
-                token_amount: inner.amount.clone(),
-                coin_type: None,
-                coin_amount: Some(inner.price.clone()),
-            },
-            TokenEvent::TopazListEvent(inner) => TokenActivityHelper {
-                token_data_id: &inner.token_id.token_data_id,
-                property_version: inner.token_id.property_version.clone(),
-                from_address: Some(inner.seller.clone()),
-                to_address: None,
-                token_amount: inner.amount.clone(),
-                coin_type: None,
-                coin_amount: Some(inner.price.clone()),
-            },
-            TokenEvent::TopazSellEvent(inner) => TokenActivityHelper {
-                token_data_id: &inner.token_id.token_data_id,
-                property_version: inner.token_id.property_version.clone(),
-                from_address: Some(inner.seller.clone()),
-                to_address: Some(inner.buyer.clone()),
-                token_amount: inner.amount.clone(),
-                coin_type: Some(inner.coin_type.to_string()),
-                coin_amount: Some(inner.price.clone()),
-            },
-            TokenEvent::TopazSendEvent(inner) => TokenActivityHelper {
-                token_data_id: &inner.token_id.token_data_id,
-                property_version: inner.token_id.property_version.clone(),
-                from_address: Some(inner.sender.clone()),
-                to_address: Some(inner.receiver.clone()),
-                token_amount: inner.amount.clone(),
-                coin_type: None,
-                coin_amount: None,
-            },
-            TokenEvent::Souffl3BuyTokenEvent(inner) => TokenActivityHelper {
-                token_data_id: &inner.token_id.token_data_id,
-                property_version: inner.token_id.property_version.clone(),
-                from_address: Some(inner.token_owner.clone()),
-                to_address: Some(inner.buyer.clone()),
-                token_amount: inner.token_amount.clone(),
-                coin_type: None,
-                coin_amount: Some(inner.coin_per_token.clone()),
-            },
-            TokenEvent::Souffl3CancelListTokenEvent(inner) => TokenActivityHelper {
-                token_data_id: &inner.token_id.token_data_id,
-                property_version: inner.token_id.property_version.clone(),
-                from_address: None,
-                to_address: None,
-                token_amount: inner.token_amount.clone(),
-                coin_type: None,
-                coin_amount: None,
-            },
-            TokenEvent::Souffl3ListTokenEvent(inner) => TokenActivityHelper {
-                token_data_id: &inner.token_id.token_data_id,
-                property_version: inner.token_id.property_version.clone(),
-                from_address: Some(inner.token_owner.clone()),
-                to_address: None,
-                token_amount: inner.token_amount.clone(),
-                coin_type: None,
-                coin_amount: Some(inner.coin_per_token.clone()),
-            },
-            TokenEvent::Souffl3TokenListEvent(inner) => TokenActivityHelper {
-                token_data_id: &inner.token_id.token_data_id,
-                property_version: inner.token_id.property_version.clone(),
-                from_address: None,
-                to_address: None,
-                token_amount: inner.amount.clone(),
-                coin_type: Some(inner.coin_type_info.to_string()),
-                coin_amount: Some(inner.min_price.clone()),
-            },
-            TokenEvent::Souffl3TokenSwapEvent(inner) => TokenActivityHelper {
-                token_data_id: &inner.token_id.token_data_id,
-                property_version: inner.token_id.property_version.clone(),
-                from_address: None,
-                to_address: Some(inner.token_buyer.clone()),
-                token_amount: inner.token_amount.clone(),
-                coin_type: Some(inner.coin_type_info.to_string()),
-                coin_amount: Some(inner.coin_amount.clone()),
-            }
-        };
-        let token_data_id = token_activity_helper.token_data_id;
+    fn from_effect(effects: &TokenEventEffects) -> Self {
+        let token_data_id = &effects.token_data_id;
+        let (event_account_address, event_creation_number, event_sequence_number) =
+            effects.event_key.clone();
         Self {
-            event_account_address: event_account_address.to_string(),
+            event_account_address,
             event_creation_number,
             event_sequence_number,
+            event_index: effects.event_index,
+            token_index: effects.token_index,
             token_data_id_hash: token_data_id.to_hash(),
-            property_version: token_activity_helper.property_version,
+            property_version: effects.property_version.clone(),
             collection_data_id_hash: token_data_id.get_collection_data_id_hash(),
             creator_address: token_data_id.get_creator_address(),
             collection_name: token_data_id.get_collection_trunc(),
             name: token_data_id.get_name_trunc(),
-            transaction_version: txn_version,
-            transfer_type: event_type.to_string(),
-            from_address: token_activity_helper.from_address,
-            to_address: token_activity_helper.to_address,
-            token_amount: token_activity_helper.token_amount,
-            coin_type: token_activity_helper.coin_type,
-            coin_amount: token_activity_helper.coin_amount,
-            transaction_timestamp: txn_timestamp,
+            transaction_version: effects.transaction_version,
+            transfer_type: effects.event_type.clone(),
+            transfer_kind: Some(effects.transfer_kind.as_str().to_string()),
+            domain: None,
+            from_address: effects.from_address.clone(),
+            to_address: effects.to_address.clone(),
+            token_amount: effects.token_amount.clone(),
+            coin_type: effects.coin_type.clone(),
+            coin_amount: effects.coin_amount.clone(),
+            suspect_value: effects.suspect_value,
+            transaction_timestamp: effects.transaction_timestamp,
+        }
+    }
+}
+
+#[derive(Debug, QueryableByName)]
+struct TransferType {
+    #[diesel(sql_type = Text)]
+    transfer_type: String,
+}
+
+#[derive(Debug, QueryableByName)]
+struct VersionRange {
+    #[diesel(sql_type = Nullable<BigInt>)]
+    min_version: Option<i64>,
+    #[diesel(sql_type = Nullable<BigInt>)]
+    max_version: Option<i64>,
+}
+
+/// Fills the transfer_kind of token_activities and account_token_activities rows indexed before
+/// it was added, from their transfer_type. Each statement updates chunk_versions versions and
+/// commits on its own, so an interrupted backfill picks up where it stopped when run again.
+/// Returns the number of rows filled.
+pub fn backfill_transfer_kinds(
+    conn: &mut PgConnection,
+    mappings: &MarketplaceEventMappings,
+    chunk_versions: i64,
+) -> QueryResult<usize> {
+    let mut num_rows = 0;
+    for table in ["token_activities", "account_token_activities"] {
+        let transfer_types = sql_query(format!(
+            "SELECT DISTINCT transfer_type FROM {} WHERE transfer_kind IS NULL",
+            table
+        ))
+        .load::<TransferType>(conn)?
+        .into_iter()
+        .map(|row| row.transfer_type)
+        .collect::<Vec<_>>();
+        let transfer_kinds = transfer_types
+            .iter()
+            .map(|transfer_type| {
+                TransferKind::from_event_type(transfer_type, mappings)
+                    .as_str()
+                    .to_string()
+            })
+            .collect::<Vec<_>>();
+        let range = sql_query(format!(
+            "SELECT MIN(transaction_version) AS min_version, \
+            MAX(transaction_version) AS max_version FROM {} WHERE transfer_kind IS NULL",
+            table
+        ))
+        .get_result::<VersionRange>(conn)?;
+        let (mut start_version, max_version) = match (range.min_version, range.max_version) {
+            (Some(min_version), Some(max_version)) => (min_version, max_version),
+            _ => continue,
+        };
+        while start_version <= max_version {
+            num_rows += sql_query(format!(
+                "UPDATE {table} SET transfer_kind = kinds.transfer_kind \
+                FROM UNNEST($1, $2) AS kinds (transfer_type, transfer_kind) \
+                WHERE {table}.transfer_type = kinds.transfer_type \
+                AND {table}.transfer_kind IS NULL \
+                AND {table}.transaction_version >= $3 AND {table}.transaction_version < $4",
+                table = table
+            ))
+            .bind::<Array<Text>, _>(transfer_types.clone())
+            .bind::<Array<Text>, _>(transfer_kinds.clone())
+            .bind::<BigInt, _>(start_version)
+            .bind::<BigInt, _>(start_version + chunk_versions)
+            .execute(conn)?;
+            start_version += chunk_versions;
         }
     }
+    Ok(num_rows)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::util::parse_timestamp;
+    use bigdecimal::Zero;
+    use serde_json::json;
+    use std::collections::HashSet;
+
+    fn module_event_transaction() -> APITransaction {
+        let path = concat!(
+            env!("CARGO_MANIFEST_DIR"),
+            "/fixtures/golden/transactions/topaz_buy.json"
+        );
+        let mut transaction: serde_json::Value =
+            serde_json::from_str(&std::fs::read_to_string(path).unwrap()).unwrap();
+        // Same events, emitted as module events
+        for event in transaction["events"].as_array_mut().unwrap() {
+            event["guid"] = json!({"creation_number": "0", "account_address": "0x0"});
+            event["sequence_number"] = json!("0");
+        }
+        serde_json::from_value(transaction).unwrap()
+    }
+
+    #[test]
+    fn test_module_events() {
+        let transaction = module_event_transaction();
+        let activities = TokenActivity::from_transaction(
+            &transaction,
+            &TokenEvents::from_transaction(&transaction).unwrap(),
+            &MarketplaceEventMappings::default(),
+        );
+        assert_eq!(
+            activities
+                .iter()
+                .map(|activity| activity.event_index)
+                .collect::<Vec<i64>>(),
+            vec![0, 1, 2]
+        );
+        let pks = activities
+            .iter()
+            .map(TokenActivity::pk)
+            .collect::<HashSet<TokenActivityPK>>();
+        assert_eq!(pks.len(), 3);
+        // The withdraw and deposit don't say whose token store they're from, so pairing them
+        // doesn't fill in the deposit's sender either
+        assert_eq!(activities[0].from_address, None);
+        assert_eq!(activities[1].from_address, None);
+        assert_eq!(activities[1].to_address, None);
+        // The buy carries its own addresses
+        assert!(activities[2].from_address.is_some());
+        assert!(activities[2].to_address.is_some());
+    }
+
+    fn transfer(transfer_type: &str, name: &str, amount: i64, address: &str) -> TokenActivity {
+        let (from_address, to_address) = if transfer_type == WITHDRAW_EVENT_TYPE {
+            (Some(address.to_string()), None)
+        } else {
+            (None, Some(address.to_string()))
+        };
+        TokenActivity {
+            transaction_version: 10,
+            event_account_address: address.to_string(),
+            event_creation_number: 0,
+            event_sequence_number: 0,
+            event_index: 0,
+            token_index: 0,
+            token_data_id_hash: name.to_string(),
+            property_version: BigDecimal::zero(),
+            creator_address: "0xc4e7".to_string(),
+            collection_name: "Potions".to_string(),
+            name: name.to_string(),
+            transfer_type: transfer_type.to_string(),
+            transfer_kind: None,
+            domain: None,
+            from_address,
+            to_address,
+            token_amount: BigDecimal::from(amount),
+            coin_type: None,
+            coin_amount: None,
+            suspect_value: false,
+            collection_data_id_hash: "potions".to_string(),
+            transaction_timestamp: parse_timestamp(1668000000000000, 10),
+        }
+    }
+
+    #[test]
+    fn test_pair_transfers() {
+        let mut activities = vec![
+            transfer(WITHDRAW_EVENT_TYPE, "potion", 1, "0xa11ce"),
+            transfer(WITHDRAW_EVENT_TYPE, "potion", 1, "0xb0b"),
+            transfer(WITHDRAW_EVENT_TYPE, "elixir", 2, "0xca41"),
+            transfer(DEPOSIT_EVENT_TYPE, "potion", 1, "0xd0e"),
+            transfer(DEPOSIT_EVENT_TYPE, "potion", 1, "0xe11e"),
+            // Different amount, so not the elixir withdrawn above
+            transfer(DEPOSIT_EVENT_TYPE, "elixir", 1, "0xd0e"),
+            // Nothing left to pair with
+            transfer(DEPOSIT_EVENT_TYPE, "potion", 1, "0xf00"),
+        ];
+        TokenActivity::pair_transfers(&mut activities);
+        let addresses = activities
+            .iter()
+            .map(|activity| (activity.from_address.as_deref(), activity.to_address.as_deref()))
+            .collect::<Vec<_>>();
+        assert_eq!(addresses, vec![
+            (Some("0xa11ce"), None),
+            (Some("0xb0b"), None),
+            (Some("0xca41"), None),
+            (Some("0xa11ce"), Some("0xd0e")),
+            (Some("0xb0b"), Some("0xe11e")),
+            (None, Some("0xd0e")),
+            (None, Some("0xf00")),
+        ]);
+    }
 }
@@ -0,0 +1,739 @@
+// Copyright (c) Aptos
+// SPDX-License-Identifier: Apache-2.0
+
+// This is required because a diesel macro makes clippy sad
+#![allow(clippy::extra_unused_lifetimes)]
+#![allow(clippy::unused_unit)]
+
+use super::{
+    collection_offers::{
+        deadline_to_expires_at, OFFER_STATUS_ACTIVE, OFFER_STATUS_CANCELLED, OFFER_STATUS_EXPIRED,
+        OFFER_STATUS_FILLED,
+    },
+    collection_reports::{canonicalize_coin_type, DEFAULT_COIN_TYPE},
+    token_utils::{TokenEvent, TokenEvents, TokenIdType, TypeInfo},
+};
+use crate::{schema::current_token_bids, util::parse_timestamp};
+use aptos_api_types::Transaction as APITransaction;
+use bigdecimal::{BigDecimal, One, Signed, Zero};
+use diesel::{
+    sql_query,
+    sql_types::{Array, BigInt, Nullable, Numeric, Text, Timestamp},
+    PgConnection, QueryResult, RunQueryDsl,
+};
+use field_count::FieldCount;
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+
+/// (token_data_id_hash, property_version, buyer, market_address)
+pub type CurrentTokenBidPK = (String, BigDecimal, String, String);
+/// (token_data_id_hash, property_version, market_address)
+type TokenMarketKey = (String, BigDecimal, String);
+
+/// Latest bid per token, bidder and marketplace. Topaz bids carry a bid id and deadline, while
+/// BlueMove auction bids have neither and are always in APT.
+#[derive(
+    Clone,
+    Debug,
+    Deserialize,
+    FieldCount,
+    Identifiable,
+    Insertable,
+    Queryable,
+    Selectable,
+    Serialize,
+)]
+#[diesel(primary_key(token_data_id_hash, property_version, buyer, market_address))]
+#[diesel(table_name = current_token_bids)]
+pub struct CurrentTokenBid {
+    pub token_data_id_hash: String,
+    pub property_version: BigDecimal,
+    pub buyer: String,
+    pub market_address: String,
+    pub collection_data_id_hash: String,
+    pub creator_address: String,
+    pub collection_name: String,
+    pub name: String,
+    pub bid_id: Option<BigDecimal>,
+    /// Price per token
+    pub price: BigDecimal,
+    pub amount_remaining: BigDecimal,
+    pub coin_type: String,
+    /// Unix timestamp in seconds after which the bid can't be filled
+    pub deadline: Option<BigDecimal>,
+    pub expires_at: Option<chrono::NaiveDateTime>,
+    pub status: String,
+    pub last_transaction_version: i64,
+    pub last_transaction_timestamp: chrono::NaiveDateTime,
+}
+
+/// Fills of bids that weren't placed in the same batch, applied to the stored bid with an update.
+/// Fills of the same bid are summed.
+#[derive(Clone, Debug, Deserialize, Serialize)]
+pub struct TokenBidFill {
+    pub token_data_id_hash: String,
+    pub property_version: BigDecimal,
+    pub buyer: String,
+    pub market_address: String,
+    pub bid_id: Option<BigDecimal>,
+    pub amount: BigDecimal,
+    pub last_transaction_version: i64,
+    pub last_transaction_timestamp: chrono::NaiveDateTime,
+}
+
+/// Latest bid of a BlueMove auction in the batch, which refunds every other bidder on the token
+#[derive(Clone, Debug, Deserialize, Serialize)]
+pub struct TokenAuctionBid {
+    pub token_data_id_hash: String,
+    pub property_version: BigDecimal,
+    pub market_address: String,
+    pub buyer: String,
+    pub last_transaction_version: i64,
+}
+
+impl CurrentTokenBid {
+    pub fn pk(&self) -> CurrentTokenBidPK {
+        (
+            self.token_data_id_hash.clone(),
+            self.property_version.clone(),
+            self.buyer.clone(),
+            self.market_address.clone(),
+        )
+    }
+
+    fn new(
+        token_id: &TokenIdType,
+        buyer: &str,
+        market_address: &str,
+        price: &BigDecimal,
+        coin_type: Option<&TypeInfo>,
+        txn_version: i64,
+        txn_timestamp: chrono::NaiveDateTime,
+    ) -> Self {
+        let token_data_id = &token_id.token_data_id;
+        Self {
+            token_data_id_hash: token_data_id.to_hash(),
+            property_version: token_id.property_version.clone(),
+            buyer: buyer.to_string(),
+            market_address: market_address.to_string(),
+            collection_data_id_hash: token_data_id.get_collection_data_id_hash(),
+            creator_address: token_data_id.get_creator_address(),
+            collection_name: token_data_id.get_collection_trunc(),
+            name: token_data_id.get_name_trunc(),
+            bid_id: None,
+            price: price.clone(),
+            amount_remaining: BigDecimal::one(),
+            coin_type: match coin_type {
+                Some(coin_type) => canonicalize_coin_type(Some(&coin_type.to_decoded_string())),
+                None => DEFAULT_COIN_TYPE.to_string(),
+            },
+            deadline: None,
+            expires_at: None,
+            status: OFFER_STATUS_ACTIVE.to_string(),
+            last_transaction_version: txn_version,
+            last_transaction_timestamp: txn_timestamp,
+        }
+    }
+}
+
+fn bid_pk(token_id: &TokenIdType, buyer: &str, market_address: &str) -> CurrentTokenBidPK {
+    (
+        token_id.token_data_id.to_hash(),
+        token_id.property_version.clone(),
+        buyer.to_string(),
+        market_address.to_string(),
+    )
+}
+
+impl TokenBidFill {
+    fn pk(&self) -> CurrentTokenBidPK {
+        (
+            self.token_data_id_hash.clone(),
+            self.property_version.clone(),
+            self.buyer.clone(),
+            self.market_address.clone(),
+        )
+    }
+}
+
+/// Token bids placed, cancelled and filled within a batch. Transactions need to be applied in
+/// version order.
+#[derive(Default)]
+pub struct TokenBidBook {
+    bids: HashMap<CurrentTokenBidPK, CurrentTokenBid>,
+    fills: HashMap<(CurrentTokenBidPK, Option<BigDecimal>), TokenBidFill>,
+    auction_bids: HashMap<TokenMarketKey, TokenAuctionBid>,
+}
+
+impl TokenBidBook {
+    pub fn apply_transaction(&mut self, transaction: &APITransaction, token_events: &TokenEvents) {
+        if let APITransaction::UserTransaction(user_txn) = transaction {
+            let txn_version = user_txn.info.version.0 as i64;
+            let txn_timestamp = parse_timestamp(user_txn.timestamp.0, txn_version);
+            for (event_index, event) in user_txn.events.iter().enumerate() {
+                let event_type = event.typ.to_string();
+                let market_address = event_type.split("::").next().unwrap_or_default();
+                match token_events.get(event_index) {
+                    Some(TokenEvent::TopazBidEvent(inner)) => {
+                        let mut bid = CurrentTokenBid::new(
+                            &inner.token_id,
+                            &inner.buyer,
+                            market_address,
+                            &inner.price,
+                            Some(&inner.coin_type),
+                            txn_version,
+                            txn_timestamp,
+                        );
+                        bid.bid_id = Some(inner.bid_id.clone());
+                        bid.amount_remaining = inner.amount.clone();
+                        bid.deadline = Some(inner.deadline.clone());
+                        bid.expires_at = Some(deadline_to_expires_at(&inner.deadline, txn_version));
+                        self.bids.insert(bid.pk(), bid);
+                    }
+                    Some(TokenEvent::TopazCancelBidEvent(inner)) => {
+                        let mut bid = CurrentTokenBid::new(
+                            &inner.token_id,
+                            &inner.buyer,
+                            market_address,
+                            &inner.price,
+                            Some(&inner.coin_type),
+                            txn_version,
+                            txn_timestamp,
+                        );
+                        bid.bid_id = Some(inner.bid_id.clone());
+                        bid.amount_remaining = inner.amount.clone();
+                        bid.deadline = Some(inner.deadline.clone());
+                        bid.expires_at = Some(deadline_to_expires_at(&inner.deadline, txn_version));
+                        bid.status = OFFER_STATUS_CANCELLED.to_string();
+                        // A cancel of an older bid doesn't touch the buyer's newer one
+                        match self.bids.get(&bid.pk()) {
+                            Some(existing) if existing.bid_id != bid.bid_id => {}
+                            _ => {
+                                self.bids.insert(bid.pk(), bid);
+                            }
+                        }
+                    }
+                    Some(TokenEvent::TopazSellEvent(inner)) => self.fill(
+                        bid_pk(&inner.token_id, &inner.buyer, market_address),
+                        Some(&inner.bid_id),
+                        &inner.amount,
+                        txn_version,
+                        txn_timestamp,
+                    ),
+                    Some(TokenEvent::BlueBidEvent(inner)) => {
+                        let bid = CurrentTokenBid::new(
+                            &inner.id,
+                            &inner.bider_address,
+                            market_address,
+                            &inner.bid,
+                            None,
+                            txn_version,
+                            txn_timestamp,
+                        );
+                        self.outbid(&bid);
+                        self.bids.insert(bid.pk(), bid);
+                    }
+                    Some(TokenEvent::BlueClaimTokenEvent(inner)) => self.fill(
+                        bid_pk(&inner.id, &inner.bider_address, market_address),
+                        None,
+                        &BigDecimal::one(),
+                        txn_version,
+                        txn_timestamp,
+                    ),
+                    _ => {}
+                }
+            }
+        }
+    }
+
+    /// Sell events are emitted for both token and collection bid fills, so a Topaz fill only
+    /// applies to a bid with the same bid id. BlueMove claims match on the token and bidder.
+    fn fill(
+        &mut self,
+        pk: CurrentTokenBidPK,
+        bid_id: Option<&BigDecimal>,
+        amount: &BigDecimal,
+        txn_version: i64,
+        txn_timestamp: chrono::NaiveDateTime,
+    ) {
+        if let Some(bid) = self.bids.get_mut(&pk) {
+            if bid.bid_id.as_ref() == bid_id {
+                if bid.status == OFFER_STATUS_ACTIVE {
+                    bid.amount_remaining -= amount;
+                    if !bid.amount_remaining.is_positive() {
+                        bid.amount_remaining = BigDecimal::zero();
+                        bid.status = OFFER_STATUS_FILLED.to_string();
+                    }
+                    bid.last_transaction_version = txn_version;
+                    bid.last_transaction_timestamp = txn_timestamp;
+                }
+                return;
+            }
+        }
+        let fill = self
+            .fills
+            .entry((pk.clone(), bid_id.cloned()))
+            .or_insert_with(|| TokenBidFill {
+                token_data_id_hash: pk.0,
+                property_version: pk.1,
+                buyer: pk.2,
+                market_address: pk.3,
+                bid_id: bid_id.cloned(),
+                amount: BigDecimal::zero(),
+                last_transaction_version: txn_version,
+                last_transaction_timestamp: txn_timestamp,
+            });
+        fill.amount += amount;
+        fill.last_transaction_version = txn_version;
+        fill.last_transaction_timestamp = txn_timestamp;
+    }
+
+    /// A new auction bid refunds the previous bidders, so their bids expire
+    fn outbid(&mut self, new_bid: &CurrentTokenBid) {
+        for bid in self.bids.values_mut() {
+            if bid.token_data_id_hash == new_bid.token_data_id_hash
+                && bid.property_version == new_bid.property_version
+                && bid.market_address == new_bid.market_address
+                && bid.buyer != new_bid.buyer
+                && bid.status == OFFER_STATUS_ACTIVE
+            {
+                bid.status = OFFER_STATUS_EXPIRED.to_string();
+                bid.last_transaction_version = new_bid.last_transaction_version;
+                bid.last_transaction_timestamp = new_bid.last_transaction_timestamp;
+            }
+        }
+        self.auction_bids.insert(
+            (
+                new_bid.token_data_id_hash.clone(),
+                new_bid.property_version.clone(),
+                new_bid.market_address.clone(),
+            ),
+            TokenAuctionBid {
+                token_data_id_hash: new_bid.token_data_id_hash.clone(),
+                property_version: new_bid.property_version.clone(),
+                market_address: new_bid.market_address.clone(),
+                buyer: new_bid.buyer.clone(),
+                last_transaction_version: new_bid.last_transaction_version,
+            },
+        );
+    }
+
+    /// Bids to upsert, fills to apply to stored bids and the latest auction bids, all sorted
+    pub fn into_rows(
+        self,
+    ) -> (
+        Vec<CurrentTokenBid>,
+        Vec<TokenBidFill>,
+        Vec<TokenAuctionBid>,
+    ) {
+        let mut bids = self.bids.into_values().collect::<Vec<_>>();
+        bids.sort_by_key(|bid| bid.pk());
+        let mut fills = self.fills.into_values().collect::<Vec<_>>();
+        fills.sort_by(|a, b| (a.pk(), &a.bid_id).cmp(&(b.pk(), &b.bid_id)));
+        let mut auction_bids = self.auction_bids.into_values().collect::<Vec<_>>();
+        auction_bids.sort_by(|a, b| {
+            (
+                &a.token_data_id_hash,
+                &a.property_version,
+                &a.market_address,
+            )
+                .cmp(&(
+                    &b.token_data_id_hash,
+                    &b.property_version,
+                    &b.market_address,
+                ))
+        });
+        (bids, fills, auction_bids)
+    }
+}
+
+/// Decrements the stored bids that were filled in this batch. Runs after the batch's bids are
+/// upserted, so a bid replaced or cancelled later in the batch is left alone.
+pub fn apply_token_bid_fills(conn: &mut PgConnection, fills: &[TokenBidFill]) -> QueryResult<()> {
+    if fills.is_empty() {
+        return Ok(());
+    }
+    sql_query(
+        "UPDATE current_token_bids b SET
+            amount_remaining = GREATEST(b.amount_remaining - f.amount, 0),
+            status = CASE WHEN b.amount_remaining - f.amount <= 0 THEN $9 ELSE b.status END,
+            last_transaction_version = f.last_transaction_version,
+            last_transaction_timestamp = f.last_transaction_timestamp,
+            inserted_at = NOW()
+        FROM (
+            SELECT UNNEST($1::VARCHAR[]) AS token_data_id_hash,
+                UNNEST($2::NUMERIC[]) AS property_version,
+                UNNEST($3::VARCHAR[]) AS buyer,
+                UNNEST($4::VARCHAR[]) AS market_address,
+                UNNEST($5::NUMERIC[]) AS bid_id,
+                UNNEST($6::NUMERIC[]) AS amount,
+                UNNEST($7::BIGINT[]) AS last_transaction_version,
+                UNNEST($8::TIMESTAMP[]) AS last_transaction_timestamp
+        ) f
+        WHERE b.token_data_id_hash = f.token_data_id_hash
+            AND b.property_version = f.property_version
+            AND b.buyer = f.buyer
+            AND b.market_address = f.market_address
+            AND b.bid_id IS NOT DISTINCT FROM f.bid_id
+            AND b.status = $10
+            AND b.last_transaction_version < f.last_transaction_version",
+    )
+    .bind::<Array<Text>, _>(
+        fills
+            .iter()
+            .map(|fill| fill.token_data_id_hash.clone())
+            .collect::<Vec<_>>(),
+    )
+    .bind::<Array<Numeric>, _>(
+        fills
+            .iter()
+            .map(|fill| fill.property_version.clone())
+            .collect::<Vec<_>>(),
+    )
+    .bind::<Array<Text>, _>(
+        fills
+            .iter()
+            .map(|fill| fill.buyer.clone())
+            .collect::<Vec<_>>(),
+    )
+    .bind::<Array<Text>, _>(
+        fills
+            .iter()
+            .map(|fill| fill.market_address.clone())
+            .collect::<Vec<_>>(),
+    )
+    .bind::<Array<Nullable<Numeric>>, _>(
+        fills
+            .iter()
+            .map(|fill| fill.bid_id.clone())
+            .collect::<Vec<_>>(),
+    )
+    .bind::<Array<Numeric>, _>(
+        fills
+            .iter()
+            .map(|fill| fill.amount.clone())
+            .collect::<Vec<_>>(),
+    )
+    .bind::<Array<BigInt>, _>(
+        fills
+            .iter()
+            .map(|fill| fill.last_transaction_version)
+            .collect::<Vec<_>>(),
+    )
+    .bind::<Array<Timestamp>, _>(
+        fills
+            .iter()
+            .map(|fill| fill.last_transaction_timestamp)
+            .collect::<Vec<_>>(),
+    )
+    .bind::<Text, _>(OFFER_STATUS_FILLED)
+    .bind::<Text, _>(OFFER_STATUS_ACTIVE)
+    .execute(conn)?;
+    Ok(())
+}
+
+/// Expires the stored bids of auctions that were outbid in this batch
+pub fn expire_outbid_token_bids(
+    conn: &mut PgConnection,
+    auction_bids: &[TokenAuctionBid],
+) -> QueryResult<()> {
+    if auction_bids.is_empty() {
+        return Ok(());
+    }
+    sql_query(
+        "UPDATE current_token_bids b SET
+            status = $6,
+            last_transaction_version = a.last_transaction_version,
+            inserted_at = NOW()
+        FROM (
+            SELECT UNNEST($1::VARCHAR[]) AS token_data_id_hash,
+                UNNEST($2::NUMERIC[]) AS property_version,
+                UNNEST($3::VARCHAR[]) AS market_address,
+                UNNEST($4::VARCHAR[]) AS buyer,
+                UNNEST($5::BIGINT[]) AS last_transaction_version
+        ) a
+        WHERE b.token_data_id_hash = a.token_data_id_hash
+            AND b.property_version = a.property_version
+            AND b.market_address = a.market_address
+            AND b.buyer <> a.buyer
+            AND b.status = $7
+            AND b.last_transaction_version < a.last_transaction_version",
+    )
+    .bind::<Array<Text>, _>(
+        auction_bids
+            .iter()
+            .map(|bid| bid.token_data_id_hash.clone())
+            .collect::<Vec<_>>(),
+    )
+    .bind::<Array<Numeric>, _>(
+        auction_bids
+            .iter()
+            .map(|bid| bid.property_version.clone())
+            .collect::<Vec<_>>(),
+    )
+    .bind::<Array<Text>, _>(
+        auction_bids
+            .iter()
+            .map(|bid| bid.market_address.clone())
+            .collect::<Vec<_>>(),
+    )
+    .bind::<Array<Text>, _>(
+        auction_bids
+            .iter()
+            .map(|bid| bid.buyer.clone())
+            .collect::<Vec<_>>(),
+    )
+    .bind::<Array<BigInt>, _>(
+        auction_bids
+            .iter()
+            .map(|bid| bid.last_transaction_version)
+            .collect::<Vec<_>>(),
+    )
+    .bind::<Text, _>(OFFER_STATUS_EXPIRED)
+    .bind::<Text, _>(OFFER_STATUS_ACTIVE)
+    .execute(conn)?;
+    Ok(())
+}
+
+/// Expires bids past their deadline and recomputes the highest active APT bid, for every token
+/// with bid activity in this batch. Deadlines are judged against the batch's latest transaction
+/// timestamp, bids that expire afterwards are left to the next expiry pass.
+pub fn refresh_token_top_bids(
+    conn: &mut PgConnection,
+    bids: &[CurrentTokenBid],
+    fills: &[TokenBidFill],
+) -> QueryResult<()> {
+    let mut token_data_id_hashes = bids
+        .iter()
+        .map(|bid| bid.token_data_id_hash.clone())
+        .chain(fills.iter().map(|fill| fill.token_data_id_hash.clone()))
+        .collect::<Vec<_>>();
+    let as_of = bids
+        .iter()
+        .map(|bid| bid.last_transaction_timestamp)
+        .chain(fills.iter().map(|fill| fill.last_transaction_timestamp))
+        .max();
+    let as_of = match as_of {
+        Some(as_of) => as_of,
+        None => return Ok(()),
+    };
+    // Sorted to avoid deadlocks, same as the other current tables
+    token_data_id_hashes.sort();
+    token_data_id_hashes.dedup();
+    sql_query(
+        "UPDATE current_token_bids SET status = $2, inserted_at = NOW()
+        WHERE token_data_id_hash = ANY($1)
+            AND status = $3
+            AND expires_at <= $4",
+    )
+    .bind::<Array<Text>, _>(token_data_id_hashes.clone())
+    .bind::<Text, _>(OFFER_STATUS_EXPIRED)
+    .bind::<Text, _>(OFFER_STATUS_ACTIVE)
+    .bind::<Timestamp, _>(as_of)
+    .execute(conn)?;
+    refresh_tokens_top_bids(conn, token_data_id_hashes)
+}
+
+/// Recomputes the highest active APT bid of the given tokens
+pub fn refresh_tokens_top_bids(
+    conn: &mut PgConnection,
+    mut token_data_id_hashes: Vec<String>,
+) -> QueryResult<()> {
+    token_data_id_hashes.sort();
+    token_data_id_hashes.dedup();
+    sql_query("DELETE FROM current_token_top_bids WHERE token_data_id_hash = ANY($1)")
+        .bind::<Array<Text>, _>(token_data_id_hashes.clone())
+        .execute(conn)?;
+    sql_query(
+        "INSERT INTO current_token_top_bids (
+            token_data_id_hash,
+            property_version,
+            top_bid,
+            buyer,
+            market_address,
+            bid_id,
+            last_transaction_version
+        )
+        SELECT DISTINCT ON (token_data_id_hash, property_version) token_data_id_hash,
+            property_version,
+            price,
+            buyer,
+            market_address,
+            bid_id,
+            last_transaction_version
+        FROM current_token_bids
+        WHERE token_data_id_hash = ANY($1)
+            AND status = $2
+            AND amount_remaining > 0
+            AND coin_type = $3
+        ORDER BY token_data_id_hash, property_version, price DESC, last_transaction_version",
+    )
+    .bind::<Array<Text>, _>(token_data_id_hashes)
+    .bind::<Text, _>(OFFER_STATUS_ACTIVE)
+    .bind::<Text, _>(DEFAULT_COIN_TYPE)
+    .execute(conn)?;
+    Ok(())
+}
+
+#[derive(Debug, QueryableByName)]
+struct ExpiredBid {
+    #[diesel(sql_type = Text)]
+    token_data_id_hash: String,
+}
+
+/// Expires every active bid whose deadline is at or before `as_of`, the latest processed
+/// transaction timestamp. Returns the tokens of the expired bids, whose top bids need a refresh.
+pub fn expire_token_bids(
+    conn: &mut PgConnection,
+    as_of: chrono::NaiveDateTime,
+) -> QueryResult<Vec<String>> {
+    let expired = sql_query(
+        "UPDATE current_token_bids SET status = $1, inserted_at = NOW()
+        WHERE status = $2
+            AND expires_at <= $3
+        RETURNING token_data_id_hash",
+    )
+    .bind::<Text, _>(OFFER_STATUS_EXPIRED)
+    .bind::<Text, _>(OFFER_STATUS_ACTIVE)
+    .bind::<Timestamp, _>(as_of)
+    .load::<ExpiredBid>(conn)?;
+    Ok(expired
+        .into_iter()
+        .map(|bid| bid.token_data_id_hash)
+        .collect())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_utils::token_id;
+    use crate::util::standardize_address;
+    use serde_json::json;
+
+    const HASH: &str = "0x6a527d06063dfd42c6b3a862574d5f3ec1660afb8058135edda5072712bfdb51";
+    const TOPAZ: &str = "0x2c7bccf7b31baf770fdbcc768d9e9cb3d87805e255355df5db32ac9a669010a2";
+    const BLUEMOVE: &str = "0xd1fd99c1944b84d1670a2536417e997864ad12303d19eac725891691b04d614e";
+
+    fn user_txn(version: u64, event_type: String, data: serde_json::Value) -> APITransaction {
+        serde_json::from_value(json!({
+            "type": "user_transaction",
+            "version": version.to_string(),
+            "hash": HASH,
+            "state_change_hash": HASH,
+            "event_root_hash": HASH,
+            "state_checkpoint_hash": null,
+            "gas_used": "100",
+            "success": true,
+            "vm_status": "Executed successfully",
+            "accumulator_root_hash": HASH,
+            "changes": [],
+            "sender": "0xb0b",
+            "sequence_number": "0",
+            "max_gas_amount": "2000",
+            "gas_unit_price": "100",
+            "expiration_timestamp_secs": "1668000600",
+            "payload": {
+                "type": "entry_function_payload",
+                "function": "0x1::aptos_account::transfer",
+                "type_arguments": [],
+                "arguments": []
+            },
+            "events": [{
+                "guid": {"creation_number": "5", "account_address": "0xb0b"},
+                "sequence_number": version.to_string(),
+                "type": event_type,
+                "data": data
+            }],
+            "timestamp": "1668000000000000"
+        }))
+        .unwrap()
+    }
+
+    fn topaz_txn(version: u64, event_name: &str, bid_id: u64) -> APITransaction {
+        user_txn(
+            version,
+            format!("{}::events::{}", TOPAZ, event_name),
+            json!({
+                "timestamp": "1668000000",
+                "bid_id": bid_id.to_string(),
+                "token_id": token_id(),
+                "deadline": "1669000000",
+                "price": "100000000",
+                "coin_type": {
+                    "account_address": "0x1",
+                    "module_name": "0x6170746f735f636f696e",
+                    "struct_name": "0x4170746f73436f696e"
+                },
+                "amount": "1",
+                "buyer": "0xb0b",
+                "seller": "0xa11ce"
+            }),
+        )
+    }
+
+    fn bluemove_bid_txn(version: u64, bidder: &str, bid: u64) -> APITransaction {
+        user_txn(
+            version,
+            format!("{}::marketplaceV2::BidEvent", BLUEMOVE),
+            json!({"id": token_id(), "bid": bid.to_string(), "bider_address": bidder}),
+        )
+    }
+
+    fn apply(
+        transactions: &[APITransaction],
+    ) -> (
+        Vec<CurrentTokenBid>,
+        Vec<TokenBidFill>,
+        Vec<TokenAuctionBid>,
+    ) {
+        let mut book = TokenBidBook::default();
+        for txn in transactions {
+            book.apply_transaction(txn, &TokenEvents::from_transaction(txn).unwrap());
+        }
+        book.into_rows()
+    }
+
+    #[test]
+    fn test_topaz_bid_filled_within_batch() {
+        let (bids, fills, _) = apply(&[
+            topaz_txn(10, "BidEvent", 3),
+            // Fill of a collection bid from the same buyer
+            topaz_txn(11, "SellEvent", 7),
+            topaz_txn(12, "SellEvent", 3),
+        ]);
+        assert_eq!(bids.len(), 1);
+        assert_eq!(bids[0].status, OFFER_STATUS_FILLED);
+        assert_eq!(bids[0].coin_type, DEFAULT_COIN_TYPE);
+        assert_eq!(bids[0].deadline, Some(BigDecimal::from(1669000000)));
+        assert_eq!(
+            bids[0].expires_at.map(|expires_at| expires_at.timestamp()),
+            Some(1669000000)
+        );
+        assert_eq!(fills.len(), 1);
+        assert_eq!(fills[0].bid_id, Some(BigDecimal::from(7)));
+    }
+
+    #[test]
+    fn test_bluemove_auction_outbid_then_claimed() {
+        let (bids, fills, auction_bids) = apply(&[
+            bluemove_bid_txn(10, "0xa11ce", 100),
+            bluemove_bid_txn(11, "0xb0b", 200),
+            user_txn(
+                12,
+                format!("{}::marketplaceV2::ClaimTokenEvent", BLUEMOVE),
+                json!({"id": token_id(), "bider_address": "0xb0b"}),
+            ),
+        ]);
+        assert!(fills.is_empty());
+        let status = |buyer: &str| {
+            bids.iter()
+                .find(|bid| bid.buyer == standardize_address(buyer))
+                .map(|bid| bid.status.as_str())
+        };
+        assert_eq!(status("0xa11ce"), Some(OFFER_STATUS_EXPIRED));
+        assert!(bids.iter().all(|bid| bid.expires_at.is_none()));
+        assert_eq!(status("0xb0b"), Some(OFFER_STATUS_FILLED));
+        assert_eq!(auction_bids.len(), 1);
+        assert_eq!(auction_bids[0].buyer, standardize_address("0xb0b"));
+    }
+}
@@ -6,16 +6,30 @@
 #![allow(clippy::unused_unit)]
 
 use super::{
-    token_utils::TokenWriteSet,
-    tokens::{TableHandleToOwner, TableMetadataForToken},
+    table_item_keys::{deleted_table_key, TOKEN_OFFER_ID_TYPE},
+    token_utils::{TokenEvent, TokenEvents, TokenWriteSet},
+    tokens::{TableHandleToOwner, TableMetadataForToken, TokenDataIdHash},
+};
+use crate::{schema::current_token_pending_claims, util::standardize_address};
+use aptos_api_types::{
+    DeleteTableItem as APIDeleteTableItem, Event as APIEvent, WriteTableItem as APIWriteTableItem,
 };
-use crate::schema::current_token_pending_claims;
-use aptos_api_types::{DeleteTableItem as APIDeleteTableItem, WriteTableItem as APIWriteTableItem};
 use bigdecimal::{BigDecimal, Zero};
 use field_count::FieldCount;
 use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+
+pub const PENDING_CLAIMS_TYPE: &str = "0x3::token_transfers::PendingClaims";
 
-#[derive(Debug, Deserialize, FieldCount, Identifiable, Insertable, Serialize)]
+type ToAddress = String;
+type FromAddress = String;
+/// Offerer of each offer claimed or cancelled in a transaction, keyed by token_data_id_hash +
+/// property_version + to_address
+pub type OfferToOfferer = HashMap<(TokenDataIdHash, BigDecimal, ToAddress), FromAddress>;
+
+#[derive(
+    Debug, Deserialize, FieldCount, Identifiable, Insertable, Queryable, Selectable, Serialize,
+)]
 #[diesel(primary_key(token_data_id_hash, property_version, from_address, to_address))]
 #[diesel(table_name = current_token_pending_claims)]
 pub struct CurrentTokenPendingClaim {
@@ -31,6 +45,9 @@ pub struct CurrentTokenPendingClaim {
     pub table_handle: String,
     pub last_transaction_version: i64,
     pub last_transaction_timestamp: chrono::NaiveDateTime,
+    /// The offer was claimed or cancelled. Offering the token to the same address again brings
+    /// the row back.
+    pub is_deleted: bool,
 }
 
 impl CurrentTokenPendingClaim {
@@ -88,6 +105,7 @@ impl CurrentTokenPendingClaim {
                         table_handle,
                         last_transaction_version: txn_version,
                         last_transaction_timestamp: txn_timestamp,
+                        is_deleted: false,
                     }));
                 } else {
                     aptos_logger::warn!(
@@ -109,34 +127,66 @@ impl CurrentTokenPendingClaim {
         Ok(None)
     }
 
+    /// Claims and cancellations remove the offer from the offerer's PendingClaims table without
+    /// writing the resource, so the table handle owner usually isn't in the write set. The
+    /// TokenClaimEvent/TokenCancelOfferEvent is emitted from the offerer's account though.
+    pub fn get_offerers_from_events(
+        events: &[APIEvent],
+        token_events: &TokenEvents,
+    ) -> OfferToOfferer {
+        let mut offerers = HashMap::new();
+        for (event_index, event) in events.iter().enumerate() {
+            let (to_address, token_id) = match token_events.get(event_index) {
+                Some(TokenEvent::ClaimTokenEvent(inner)) => (&inner.to_address, &inner.token_id),
+                Some(TokenEvent::CancelTokenOfferEvent(inner)) => {
+                    (&inner.to_address, &inner.token_id)
+                }
+                _ => continue,
+            };
+            offerers.insert(
+                (
+                    token_id.token_data_id.to_hash(),
+                    token_id.property_version.clone(),
+                    to_address.clone(),
+                ),
+                standardize_address(&event.guid.account_address.to_string()),
+            );
+        }
+        offerers
+    }
+
+    /// A deleted offer was either claimed or cancelled, either way nothing is pending anymore.
+    /// Keys the API didn't decode are decoded from their bytes, see deleted_table_key.
     pub fn from_delete_table_item(
         table_item: &APIDeleteTableItem,
         txn_version: i64,
         txn_timestamp: chrono::NaiveDateTime,
         table_handle_to_owner: &TableHandleToOwner,
+        offerers: &OfferToOfferer,
     ) -> anyhow::Result<Option<Self>> {
-        let table_item_data = table_item.data.as_ref().unwrap();
-
-        let maybe_offer = match TokenWriteSet::from_table_item_type(
-            table_item_data.key_type.as_str(),
-            &table_item_data.key,
-            txn_version,
-        )? {
-            Some(TokenWriteSet::TokenOfferId(inner)) => Some(inner),
-            _ => None,
+        let table_handle =
+            TableMetadataForToken::standardize_handle(&table_item.handle.to_string());
+        let maybe_key = match table_handle_to_owner.get(&table_handle) {
+            Some(metadata) if metadata.table_type == PENDING_CLAIMS_TYPE => {
+                deleted_table_key(table_item, Some(TOKEN_OFFER_ID_TYPE))?
+            }
+            Some(_) => deleted_table_key(table_item, None)?,
+            // Claiming and cancelling don't write the PendingClaims resource, so the table is
+            // usually unknown. A key that doesn't decode as a TokenOfferId is skipped, and one
+            // that does is only kept if an event names its offerer.
+            None => deleted_table_key(table_item, Some(TOKEN_OFFER_ID_TYPE)).unwrap_or(None),
+        };
+        let (key_type, key) = match maybe_key {
+            Some(key) => key,
+            None => return Ok(None),
         };
-        if let Some(offer) = maybe_offer {
-            let table_handle =
-                TableMetadataForToken::standardize_handle(&table_item.handle.to_string());
-
-            let table_metadata = table_handle_to_owner.get(&table_handle).unwrap_or_else(|| {
-                panic!(
-                    "Missing table handle metadata for claim. \
-                    Version: {}, table handle for PendingClaims: {}, all metadata: {:?}",
-                    txn_version, table_handle, table_handle_to_owner
-                )
-            });
 
+        let maybe_offer =
+            match TokenWriteSet::from_table_item_type(key_type.as_str(), &key, txn_version)? {
+                Some(TokenWriteSet::TokenOfferId(inner)) => Some(inner),
+                _ => None,
+            };
+        if let Some(offer) = maybe_offer {
             let token_id = offer.token_id;
             let token_data_id = token_id.token_data_id;
             let collection_data_id_hash = token_data_id.get_collection_data_id_hash();
@@ -144,10 +194,33 @@ impl CurrentTokenPendingClaim {
             let collection_name = token_data_id.get_collection_trunc();
             let name = token_data_id.get_name_trunc();
 
+            let maybe_from_address = match table_handle_to_owner.get(&table_handle) {
+                Some(table_metadata) => Some(table_metadata.owner_address.clone()),
+                None => offerers
+                    .get(&(
+                        token_data_id_hash.clone(),
+                        token_id.property_version.clone(),
+                        offer.to_addr.clone(),
+                    ))
+                    .cloned(),
+            };
+            let from_address = match maybe_from_address {
+                Some(from_address) => from_address,
+                None => {
+                    aptos_logger::warn!(
+                        transaction_version = txn_version,
+                        table_handle = table_handle,
+                        "Missing table handle metadata and claim event for deleted TokenClaim. {:?}",
+                        table_handle_to_owner
+                    );
+                    return Ok(None);
+                }
+            };
+
             return Ok(Some(Self {
                 token_data_id_hash,
                 property_version: token_id.property_version,
-                from_address: table_metadata.owner_address.clone(),
+                from_address,
                 to_address: offer.to_addr,
                 collection_data_id_hash,
                 creator_address: token_data_id.creator,
@@ -157,8 +230,198 @@ impl CurrentTokenPendingClaim {
                 table_handle,
                 last_transaction_version: txn_version,
                 last_transaction_timestamp: txn_timestamp,
+                is_deleted: true,
             }));
         }
         Ok(None)
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_utils::{timestamp, token_id};
+    use serde_json::json;
+
+    const OFFERER: &str = "0xa11ce";
+    const RECEIVER: &str = "0xb0b";
+    const PENDING_CLAIMS_HANDLE: &str = "0x1234";
+
+    fn offer_key() -> serde_json::Value {
+        json!({"to_addr": RECEIVER, "token_id": token_id()})
+    }
+
+    fn offer(txn_version: i64) -> CurrentTokenPendingClaim {
+        let table_item: APIWriteTableItem = serde_json::from_value(json!({
+            "state_key_hash": "0x00",
+            "handle": PENDING_CLAIMS_HANDLE,
+            "key": "0x00",
+            "value": "0x00",
+            "data": {
+                "key": offer_key(),
+                "key_type": "0x3::token_transfers::TokenOfferId",
+                "value": {"amount": "1", "id": token_id(), "token_properties": {}},
+                "value_type": "0x3::token::Token"
+            }
+        }))
+        .unwrap();
+        // Offering writes the PendingClaims resource, so the owner is known
+        let table_handle_to_owner = HashMap::from([(
+            PENDING_CLAIMS_HANDLE.to_string(),
+            TableMetadataForToken {
+                owner_address: standardize_address(OFFERER),
+                table_type: "0x3::token_transfers::PendingClaims".to_string(),
+            },
+        )]);
+        CurrentTokenPendingClaim::from_write_table_item(
+            &table_item,
+            txn_version,
+            timestamp(),
+            &table_handle_to_owner,
+        )
+        .unwrap()
+        .unwrap()
+    }
+
+    fn remove_offer(
+        txn_version: i64,
+        event_type: Option<&str>,
+    ) -> Option<CurrentTokenPendingClaim> {
+        let events: Vec<APIEvent> = event_type
+            .map(|event_type| {
+                serde_json::from_value(json!([{
+                    "guid": {"creation_number": "5", "account_address": OFFERER},
+                    "sequence_number": "0",
+                    "type": event_type,
+                    "data": {"amount": "1", "to_address": RECEIVER, "token_id": token_id()}
+                }]))
+                .unwrap()
+            })
+            .unwrap_or_default();
+        let table_item: APIDeleteTableItem = serde_json::from_value(json!({
+            "state_key_hash": "0x00",
+            "handle": PENDING_CLAIMS_HANDLE,
+            "key": "0x00",
+            "data": {"key": offer_key(), "key_type": "0x3::token_transfers::TokenOfferId"}
+        }))
+        .unwrap();
+        let token_events = TokenEvents::from_events(&events, txn_version).unwrap();
+        let offerers = CurrentTokenPendingClaim::get_offerers_from_events(&events, &token_events);
+        // Claiming and cancelling don't write the PendingClaims resource
+        CurrentTokenPendingClaim::from_delete_table_item(
+            &table_item,
+            txn_version,
+            timestamp(),
+            &HashMap::new(),
+            &offerers,
+        )
+        .unwrap()
+    }
+
+    fn assert_zeroes_offer(event_type: &str) {
+        let pending = offer(1);
+        assert_eq!(pending.amount, BigDecimal::from(1));
+        assert!(!pending.is_deleted);
+
+        let removed = remove_offer(2, Some(event_type)).unwrap();
+        assert_eq!(
+            (
+                &removed.token_data_id_hash,
+                &removed.property_version,
+                &removed.from_address,
+                &removed.to_address,
+            ),
+            (
+                &pending.token_data_id_hash,
+                &pending.property_version,
+                &pending.from_address,
+                &pending.to_address,
+            )
+        );
+        assert_eq!(removed.amount, BigDecimal::zero());
+        assert!(removed.is_deleted);
+        assert_eq!(removed.table_handle, pending.table_handle);
+        assert_eq!(removed.last_transaction_version, 2);
+    }
+
+    #[test]
+    fn test_offer_then_claim() {
+        assert_zeroes_offer("0x3::token_transfers::TokenClaimEvent");
+    }
+
+    #[test]
+    fn test_offer_then_cancel() {
+        assert_zeroes_offer("0x3::token_transfers::TokenCancelOfferEvent");
+    }
+
+    #[test]
+    fn test_delete_without_offerer_is_skipped() {
+        assert!(remove_offer(2, None).is_none());
+    }
+
+    /// Nodes without the table info indexer leave the key of a deleted item undecoded
+    #[test]
+    fn test_claim_of_undecoded_key() {
+        let pending = offer(1);
+        let address = |address: &str| {
+            aptos_types::account_address::AccountAddress::from_hex_literal(address)
+                .unwrap()
+                .to_vec()
+        };
+        // BCS of the TokenOfferId in offer_key()
+        let mut key = address(RECEIVER);
+        key.extend(address("0xc4e7"));
+        key.push(7);
+        key.extend(b"Potions");
+        key.push(6);
+        key.extend(b"Potion");
+        key.extend(0u64.to_le_bytes());
+
+        let events: Vec<APIEvent> = serde_json::from_value(json!([{
+            "guid": {"creation_number": "5", "account_address": OFFERER},
+            "sequence_number": "0",
+            "type": "0x3::token_transfers::TokenClaimEvent",
+            "data": {"amount": "1", "to_address": RECEIVER, "token_id": token_id()}
+        }]))
+        .unwrap();
+        let table_item: APIDeleteTableItem = serde_json::from_value(json!({
+            "state_key_hash": "0x00",
+            "handle": PENDING_CLAIMS_HANDLE,
+            "key": format!("0x{}", hex::encode(&key)),
+        }))
+        .unwrap();
+        let token_events = TokenEvents::from_events(&events, 2).unwrap();
+        let offerers = CurrentTokenPendingClaim::get_offerers_from_events(&events, &token_events);
+        let removed = CurrentTokenPendingClaim::from_delete_table_item(
+            &table_item,
+            2,
+            timestamp(),
+            &HashMap::new(),
+            &offerers,
+        )
+        .unwrap()
+        .unwrap();
+        assert_eq!(removed.token_data_id_hash, pending.token_data_id_hash);
+        assert_eq!(removed.from_address, pending.from_address);
+        assert_eq!(removed.to_address, pending.to_address);
+        assert_eq!(removed.amount, BigDecimal::zero());
+        assert!(removed.is_deleted);
+
+        // Some other table's key in an unknown table
+        let other: APIDeleteTableItem = serde_json::from_value(json!({
+            "state_key_hash": "0x00",
+            "handle": PENDING_CLAIMS_HANDLE,
+            "key": "0x0161",
+        }))
+        .unwrap();
+        assert!(CurrentTokenPendingClaim::from_delete_table_item(
+            &other,
+            2,
+            timestamp(),
+            &HashMap::new(),
+            &offerers,
+        )
+        .unwrap()
+        .is_none());
+    }
+}
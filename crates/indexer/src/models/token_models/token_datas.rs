@@ -5,7 +5,7 @@
 #![allow(clippy::extra_unused_lifetimes)]
 #![allow(clippy::unused_unit)]
 
-use super::token_utils::TokenWriteSet;
+use super::{metadata_uri::normalize_uri, token_utils::TokenWriteSet};
 use crate::schema::{current_token_datas, token_datas};
 use aptos_api_types::WriteTableItem as APIWriteTableItem;
 use bigdecimal::BigDecimal;
@@ -39,7 +39,9 @@ pub struct TokenData {
     pub description: String,
 }
 
-#[derive(Debug, Deserialize, FieldCount, Identifiable, Insertable, Serialize)]
+#[derive(
+    Debug, Deserialize, FieldCount, Identifiable, Insertable, Queryable, Selectable, Serialize,
+)]
 #[diesel(primary_key(token_data_id_hash))]
 #[diesel(table_name = current_token_datas)]
 pub struct CurrentTokenData {
@@ -64,6 +66,9 @@ pub struct CurrentTokenData {
     pub collection_data_id_hash: String,
     pub last_transaction_timestamp: chrono::NaiveDateTime,
     pub description: String,
+    /// See metadata_uri::normalize_uri, None if the uri has nothing to fetch
+    pub metadata_uri_canonical: Option<String>,
+    pub uri_scheme: Option<String>,
 }
 
 impl TokenData {
@@ -98,6 +103,8 @@ impl TokenData {
                 let collection_name = token_data_id.get_collection_trunc();
                 let name = token_data_id.get_name_trunc();
                 let metadata_uri = token_data.get_uri_trunc();
+                // From the whole uri, a truncated one is flagged as invalid
+                let normalized_uri = normalize_uri(&token_data.uri);
 
                 return Ok(Some((
                     Self {
@@ -151,6 +158,8 @@ impl TokenData {
                         last_transaction_version: txn_version,
                         last_transaction_timestamp: txn_timestamp,
                         description: token_data.description,
+                        metadata_uri_canonical: normalized_uri.canonical,
+                        uri_scheme: Some(normalized_uri.scheme.as_str().to_string()),
                     },
                 )));
             } else {
@@ -0,0 +1,385 @@
+// Copyright (c) Aptos
+// SPDX-License-Identifier: Apache-2.0
+
+//! Rows of token_metadata_cache and the queries the metadata fetcher runs on them. Token datas
+//! whose canonical metadata uri is new or changed are queued as pending, by following
+//! current_token_datas.last_transaction_version from a cursor kept in processor_status.
+
+// This is required because a diesel macro makes clippy sad
+#![allow(clippy::extra_unused_lifetimes)]
+#![allow(clippy::unused_unit)]
+
+use super::metadata_uri::normalize_uri;
+use crate::{
+    database::{execute_chunk_with_context, get_chunks},
+    schema::{current_token_datas, token_metadata_cache},
+    util::hash_str,
+};
+use anyhow::{bail, Context};
+use diesel::{
+    upsert::excluded, ExpressionMethods, OptionalExtension, PgConnection, QueryDsl, QueryResult,
+    RunQueryDsl,
+};
+use field_count::FieldCount;
+use serde::{Deserialize, Serialize};
+
+pub const FETCH_STATUS_PENDING: &str = "pending";
+pub const FETCH_STATUS_FETCHED: &str = "fetched";
+/// Retried at next_fetch_at
+pub const FETCH_STATUS_FAILED: &str = "failed";
+pub const FETCH_STATUS_PERMANENTLY_FAILED: &str = "permanently_failed";
+
+/// processor_status row with the last current_token_datas version queued
+pub const METADATA_FETCHER_STATUS_NAME: &str = "metadata_fetcher";
+
+#[derive(Debug, Deserialize, FieldCount, Identifiable, Insertable, Serialize)]
+#[diesel(primary_key(token_data_id_hash))]
+#[diesel(table_name = token_metadata_cache)]
+pub struct TokenMetadataCacheEntry {
+    pub token_data_id_hash: String,
+    pub metadata_uri: String,
+    pub fetch_status: String,
+    pub name: Option<String>,
+    pub description: Option<String>,
+    pub image: Option<String>,
+    pub attributes: Option<serde_json::Value>,
+    pub content_hash: Option<String>,
+    pub fetch_attempts: i64,
+    pub last_error: Option<String>,
+    pub next_fetch_at: chrono::NaiveDateTime,
+    pub fetched_at: Option<chrono::NaiveDateTime>,
+    pub last_transaction_version: i64,
+}
+
+impl TokenMetadataCacheEntry {
+    pub fn pending(
+        token_data_id_hash: String,
+        metadata_uri: String,
+        last_transaction_version: i64,
+        now: chrono::NaiveDateTime,
+    ) -> Self {
+        Self {
+            token_data_id_hash,
+            metadata_uri,
+            fetch_status: FETCH_STATUS_PENDING.to_string(),
+            name: None,
+            description: None,
+            image: None,
+            attributes: None,
+            content_hash: None,
+            fetch_attempts: 0,
+            last_error: None,
+            next_fetch_at: now,
+            fetched_at: None,
+            last_transaction_version,
+        }
+    }
+}
+
+/// A pending or failed entry whose next fetch is due
+#[derive(Clone, Debug, Queryable)]
+pub struct DueFetch {
+    pub token_data_id_hash: String,
+    pub metadata_uri: String,
+    pub fetch_attempts: i64,
+}
+
+/// The fields frontends read from the usual metadata JSON, ex: OpenSea's
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct TokenMetadata {
+    pub name: Option<String>,
+    pub description: Option<String>,
+    pub image: Option<String>,
+    pub attributes: Option<serde_json::Value>,
+    /// sha256 of the whole body, to tell whether a refetch changed anything
+    pub content_hash: String,
+}
+
+impl TokenMetadata {
+    /// Fails if the body isn't a JSON object. Fields with an unexpected type are left out.
+    pub fn from_json(body: &[u8]) -> anyhow::Result<Self> {
+        let value: serde_json::Value =
+            serde_json::from_slice(body).context("Metadata isn't valid JSON")?;
+        let object = match value.as_object() {
+            Some(object) => object,
+            None => bail!("Metadata isn't a JSON object"),
+        };
+        let string_field = |key: &str| object.get(key).and_then(|v| v.as_str()).map(String::from);
+        Ok(Self {
+            name: string_field("name"),
+            description: string_field("description"),
+            image: string_field("image").or_else(|| string_field("image_url")),
+            attributes: object
+                .get("attributes")
+                .filter(|attributes| !attributes.is_null())
+                .cloned(),
+            content_hash: hash_str(&String::from_utf8_lossy(body)),
+        })
+    }
+}
+
+/// Queues token datas written after `after_version` whose canonical metadata uri isn't cached yet
+/// or changed, about `limit` at a time. Token datas whose uri has nothing to fetch (empty, data:
+/// or invalid) aren't queued. Returns the last version read, None if there was nothing after
+/// `after_version`. Every token data of the last version is read, so the next call can start
+/// after it.
+pub fn enqueue_changed_uris(
+    conn: &mut PgConnection,
+    after_version: i64,
+    limit: i64,
+    now: chrono::NaiveDateTime,
+) -> QueryResult<Option<i64>> {
+    use current_token_datas::dsl as ctd;
+
+    let last_version = ctd::current_token_datas
+        .filter(ctd::last_transaction_version.gt(after_version))
+        .order(ctd::last_transaction_version)
+        .offset(limit - 1)
+        .select(ctd::last_transaction_version)
+        .first::<i64>(conn)
+        .optional()?;
+    let mut query = ctd::current_token_datas
+        .filter(ctd::last_transaction_version.gt(after_version))
+        .select((
+            ctd::token_data_id_hash,
+            ctd::metadata_uri,
+            ctd::metadata_uri_canonical,
+            ctd::uri_scheme,
+            ctd::last_transaction_version,
+        ))
+        .into_boxed();
+    if let Some(last_version) = last_version {
+        query = query.filter(ctd::last_transaction_version.le(last_version));
+    }
+    let rows = query.load::<(String, String, Option<String>, Option<String>, i64)>(conn)?;
+    let queued_version = rows.iter().map(|(.., version)| *version).max();
+    let entries = rows
+        .into_iter()
+        .filter_map(
+            |(token_data_id_hash, raw_uri, canonical_uri, uri_scheme, version)| {
+                // Rows written before uris were normalized only have the raw uri
+                let canonical_uri = match uri_scheme {
+                    Some(_) => canonical_uri,
+                    None => normalize_uri(&raw_uri).canonical,
+                }?;
+                Some(TokenMetadataCacheEntry::pending(
+                    token_data_id_hash,
+                    canonical_uri,
+                    version,
+                    now,
+                ))
+            },
+        )
+        .collect::<Vec<_>>();
+    insert_pending(conn, &entries)?;
+    Ok(queued_version)
+}
+
+/// Entries that are already cached are reset to pending only if their metadata_uri changed. The
+/// previously fetched fields are kept until the new uri is fetched.
+fn insert_pending(
+    conn: &mut PgConnection,
+    items_to_insert: &[TokenMetadataCacheEntry],
+) -> QueryResult<()> {
+    let chunks = get_chunks(
+        items_to_insert.len(),
+        TokenMetadataCacheEntry::field_count(),
+    );
+    for (chunk_index, (start_ind, end_ind)) in chunks.into_iter().enumerate() {
+        execute_chunk_with_context(
+            conn,
+            "token_metadata_cache",
+            chunk_index,
+            &items_to_insert[start_ind..end_ind],
+            |chunk| {
+                diesel::insert_into(token_metadata_cache::table)
+                    .values(chunk)
+                    .on_conflict(token_metadata_cache::token_data_id_hash)
+                    .do_update()
+                    .set((
+                        token_metadata_cache::metadata_uri
+                            .eq(excluded(token_metadata_cache::metadata_uri)),
+                        token_metadata_cache::fetch_status
+                            .eq(excluded(token_metadata_cache::fetch_status)),
+                        token_metadata_cache::fetch_attempts
+                            .eq(excluded(token_metadata_cache::fetch_attempts)),
+                        token_metadata_cache::last_error
+                            .eq(excluded(token_metadata_cache::last_error)),
+                        token_metadata_cache::next_fetch_at
+                            .eq(excluded(token_metadata_cache::next_fetch_at)),
+                        token_metadata_cache::last_transaction_version
+                            .eq(excluded(token_metadata_cache::last_transaction_version)),
+                    ))
+            },
+            Some(" WHERE token_metadata_cache.metadata_uri <> excluded.metadata_uri "),
+        )?;
+    }
+    Ok(())
+}
+
+/// Pending and failed entries due by `now`, the longest waiting first
+pub fn get_due_fetches(
+    conn: &mut PgConnection,
+    now: chrono::NaiveDateTime,
+    limit: i64,
+) -> QueryResult<Vec<DueFetch>> {
+    token_metadata_cache::table
+        .filter(
+            token_metadata_cache::fetch_status.eq_any([FETCH_STATUS_PENDING, FETCH_STATUS_FAILED]),
+        )
+        .filter(token_metadata_cache::next_fetch_at.le(now))
+        .order(token_metadata_cache::next_fetch_at)
+        .limit(limit)
+        .select((
+            token_metadata_cache::token_data_id_hash,
+            token_metadata_cache::metadata_uri,
+            token_metadata_cache::fetch_attempts,
+        ))
+        .load(conn)
+}
+
+/// Results are only recorded if the uri hasn't changed since the fetch was due, otherwise the
+/// entry stays pending for the new uri.
+pub fn record_fetched(
+    conn: &mut PgConnection,
+    fetch: &DueFetch,
+    metadata: &TokenMetadata,
+    now: chrono::NaiveDateTime,
+) -> QueryResult<usize> {
+    diesel::update(
+        token_metadata_cache::table
+            .filter(token_metadata_cache::token_data_id_hash.eq(&fetch.token_data_id_hash))
+            .filter(token_metadata_cache::metadata_uri.eq(&fetch.metadata_uri)),
+    )
+    .set((
+        token_metadata_cache::fetch_status.eq(FETCH_STATUS_FETCHED),
+        token_metadata_cache::name.eq(&metadata.name),
+        token_metadata_cache::description.eq(&metadata.description),
+        token_metadata_cache::image.eq(&metadata.image),
+        token_metadata_cache::attributes.eq(&metadata.attributes),
+        token_metadata_cache::content_hash.eq(&metadata.content_hash),
+        token_metadata_cache::fetch_attempts.eq(fetch.fetch_attempts + 1),
+        token_metadata_cache::last_error.eq(None::<String>),
+        token_metadata_cache::fetched_at.eq(now),
+    ))
+    .execute(conn)
+}
+
+/// Marks the entry as failed, to be retried at `retry_at`, or as permanently failed without it
+pub fn record_failure(
+    conn: &mut PgConnection,
+    fetch: &DueFetch,
+    error: &str,
+    retry_at: Option<chrono::NaiveDateTime>,
+    now: chrono::NaiveDateTime,
+) -> QueryResult<usize> {
+    let status = match retry_at {
+        Some(_) => FETCH_STATUS_FAILED,
+        None => FETCH_STATUS_PERMANENTLY_FAILED,
+    };
+    diesel::update(
+        token_metadata_cache::table
+            .filter(token_metadata_cache::token_data_id_hash.eq(&fetch.token_data_id_hash))
+            .filter(token_metadata_cache::metadata_uri.eq(&fetch.metadata_uri)),
+    )
+    .set((
+        token_metadata_cache::fetch_status.eq(status),
+        token_metadata_cache::fetch_attempts.eq(fetch.fetch_attempts + 1),
+        token_metadata_cache::last_error.eq(error),
+        token_metadata_cache::next_fetch_at.eq(retry_at.unwrap_or(now)),
+    ))
+    .execute(conn)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    #[test]
+    fn test_metadata_from_json() {
+        let body = json!({
+            "name": "Potion #1",
+            "description": "A potion",
+            "image": "ipfs://bafy/1.png",
+            "attributes": [{"trait_type": "Color", "value": "Gold"}],
+            "edition": 1,
+        })
+        .to_string();
+        let metadata = TokenMetadata::from_json(body.as_bytes()).unwrap();
+        assert_eq!(metadata.name.as_deref(), Some("Potion #1"));
+        assert_eq!(metadata.image.as_deref(), Some("ipfs://bafy/1.png"));
+        assert_eq!(
+            metadata.attributes,
+            Some(json!([{"trait_type": "Color", "value": "Gold"}]))
+        );
+        assert_eq!(metadata.content_hash, hash_str(&body));
+
+        // Wrongly typed fields are left out rather than failing the whole fetch
+        let metadata =
+            TokenMetadata::from_json(br#"{"name": 1, "image_url": "https://a/1.png"}"#).unwrap();
+        assert_eq!(metadata.name, None);
+        assert_eq!(metadata.image.as_deref(), Some("https://a/1.png"));
+        assert_eq!(metadata.attributes, None);
+
+        assert!(TokenMetadata::from_json(b"[1, 2]").is_err());
+        assert!(TokenMetadata::from_json(b"<html>").is_err());
+    }
+
+    #[test]
+    fn test_queue_and_record() {
+        if crate::should_skip_pg_tests() {
+            return;
+        }
+        let database_url = std::env::var("INDEXER_DATABASE_URL").unwrap();
+        let conn_pool = crate::database::new_db_pool(&database_url).unwrap();
+        let mut conn = conn_pool.get().unwrap();
+        let now = chrono::Utc::now().naive_utc();
+        diesel::delete(
+            token_metadata_cache::table
+                .filter(token_metadata_cache::token_data_id_hash.eq("metadata_cache_test")),
+        )
+        .execute(&mut conn)
+        .unwrap();
+
+        let entry = |uri: &str| {
+            TokenMetadataCacheEntry::pending(
+                "metadata_cache_test".to_string(),
+                uri.to_string(),
+                1,
+                now,
+            )
+        };
+        insert_pending(&mut conn, &[entry("ipfs://a")]).unwrap();
+        let fetch = DueFetch {
+            token_data_id_hash: "metadata_cache_test".to_string(),
+            metadata_uri: "ipfs://a".to_string(),
+            fetch_attempts: 0,
+        };
+        let metadata = TokenMetadata::from_json(br#"{"name": "A"}"#).unwrap();
+        assert_eq!(
+            record_fetched(&mut conn, &fetch, &metadata, now).unwrap(),
+            1
+        );
+
+        // The same uri isn't fetched again, a changed one is
+        insert_pending(&mut conn, &[entry("ipfs://a")]).unwrap();
+        let status = |conn: &mut PgConnection| {
+            token_metadata_cache::table
+                .filter(token_metadata_cache::token_data_id_hash.eq("metadata_cache_test"))
+                .select(token_metadata_cache::fetch_status)
+                .first::<String>(conn)
+                .unwrap()
+        };
+        assert_eq!(status(&mut conn), FETCH_STATUS_FETCHED);
+        insert_pending(&mut conn, &[entry("ipfs://b")]).unwrap();
+        assert_eq!(status(&mut conn), FETCH_STATUS_PENDING);
+
+        // A result for the old uri isn't recorded over the new one
+        assert_eq!(
+            record_failure(&mut conn, &fetch, "timed out", None, now).unwrap(),
+            0
+        );
+        assert_eq!(status(&mut conn), FETCH_STATUS_PENDING);
+    }
+}
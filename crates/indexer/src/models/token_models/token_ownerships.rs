@@ -5,11 +5,37 @@
 #![allow(clippy::extra_unused_lifetimes)]
 #![allow(clippy::unused_unit)]
 
-use super::tokens::{TableHandleToOwner, TableMetadataForToken, Token};
-use crate::schema::{current_token_ownerships, token_ownerships};
-use bigdecimal::BigDecimal;
+use super::{
+    marketplace_event_mappings::MarketplaceEventMappings,
+    token_activities::event_handle_address,
+    token_utils::{TokenEvent, TokenEvents},
+    tokens::{
+        CurrentTokenOwnershipPK, TableHandleToOwner, TableMetadataForToken, Token, TokenDataIdHash,
+    },
+};
+use crate::{
+    database::PgPoolConnection,
+    schema::{current_token_ownerships, token_ownerships},
+    util::standardize_address,
+};
+use aptos_api_types::{Event as APIEvent, WriteSetChange as APIWriteSetChange};
+use bigdecimal::{BigDecimal, One, Zero};
+use diesel::{ExpressionMethods, QueryDsl, QueryResult, RunQueryDsl};
 use field_count::FieldCount;
 use serde::{Deserialize, Serialize};
+use std::collections::{HashMap, HashSet};
+
+pub const TOKEN_STORE_TYPE: &str = "0x3::token::TokenStore";
+const ACCOUNT_TYPE: &str = "0x1::account::Account";
+/// owner_type of current_token_ownerships
+pub const OWNER_TYPE_USER: &str = "user";
+/// A configured marketplace escrow address, see `marketplace_escrow_addresses`
+pub const OWNER_TYPE_MARKETPLACE_ESCROW: &str = "marketplace_escrow";
+/// A resource account nobody holds the key of, which is how contracts usually hold tokens
+pub const OWNER_TYPE_UNKNOWN_CONTRACT: &str = "unknown_contract";
+
+/// Owner of each token burned in a transaction, keyed by token_data_id_hash + property_version
+pub type BurnedTokenOwners = HashMap<(TokenDataIdHash, BigDecimal), String>;
 
 #[derive(Debug, Deserialize, FieldCount, Identifiable, Insertable, Serialize)]
 #[diesel(primary_key(
@@ -34,7 +60,9 @@ pub struct TokenOwnership {
     pub transaction_timestamp: chrono::NaiveDateTime,
 }
 
-#[derive(Debug, Deserialize, FieldCount, Identifiable, Insertable, Serialize)]
+#[derive(
+    Debug, Deserialize, FieldCount, Identifiable, Insertable, Queryable, Selectable, Serialize,
+)]
 #[diesel(primary_key(token_data_id_hash, property_version, owner_address))]
 #[diesel(table_name = current_token_ownerships)]
 pub struct CurrentTokenOwnership {
@@ -50,39 +78,80 @@ pub struct CurrentTokenOwnership {
     pub collection_data_id_hash: String,
     pub table_type: String,
     pub last_transaction_timestamp: chrono::NaiveDateTime,
+    pub owner_type: String,
+    /// Seller of the active listing for escrowed tokens, i.e. whose token it really is
+    pub beneficial_owner: Option<String>,
+    /// The owner has none of the token left, from a burn, a transfer out or a mutation. Cleared
+    /// again if the owner gets the token back.
+    pub is_deleted: bool,
 }
 
 impl TokenOwnership {
+    /// Burning removes (or for semi-fungible tokens, shrinks) the token in the owner's TokenStore,
+    /// and not every burn flow writes the TokenStore resource. The BurnTokenEvent is emitted from
+    /// the owner's TokenStore though, so it tells us whose ownership to zero out.
+    pub fn get_burned_token_owners_from_events(
+        events: &[APIEvent],
+        token_events: &TokenEvents,
+    ) -> BurnedTokenOwners {
+        let mut burned_token_owners = HashMap::new();
+        for (event_index, event) in events.iter().enumerate() {
+            if let Some(TokenEvent::BurnTokenEvent(inner)) = token_events.get(event_index) {
+                burned_token_owners.insert(
+                    (
+                        inner.id.token_data_id.to_hash(),
+                        inner.id.property_version.clone(),
+                    ),
+                    standardize_address(&event.guid.account_address.to_string()),
+                );
+            }
+        }
+        burned_token_owners
+    }
+
     pub fn from_token(
         token: &Token,
         amount: BigDecimal,
         table_handle: String,
         table_handle_to_owner: &TableHandleToOwner,
+        burned_token_owners: &BurnedTokenOwners,
         // Escrow tables somehow don't appear in resources so this is just a temporary workaround to record that it's an escrow table
         value_type: Option<&str>,
     ) -> (Self, Option<CurrentTokenOwnership>) {
         let table_handle = TableMetadataForToken::standardize_handle(&table_handle);
         let txn_version = token.transaction_version;
-        let maybe_table_metadata = table_handle_to_owner.get(&table_handle);
+        let maybe_owner_and_table_type = match table_handle_to_owner.get(&table_handle) {
+            Some(tm) => Some((tm.owner_address.clone(), tm.table_type.clone())),
+            None => burned_token_owners
+                .get(&(
+                    token.token_data_id_hash.clone(),
+                    token.property_version.clone(),
+                ))
+                .map(|owner_address| (owner_address.clone(), TOKEN_STORE_TYPE.to_string())),
+        };
 
-        let (curr_token_ownership, owner_address, mut table_type) = match maybe_table_metadata {
-            Some(tm) => (
+        let (curr_token_ownership, owner_address, mut table_type) = match maybe_owner_and_table_type
+        {
+            Some((owner_address, table_type)) => (
                 Some(CurrentTokenOwnership {
                     collection_data_id_hash: token.collection_data_id_hash.clone(),
                     token_data_id_hash: token.token_data_id_hash.clone(),
                     property_version: token.property_version.clone(),
-                    owner_address: tm.owner_address.clone(),
+                    owner_address: owner_address.clone(),
                     creator_address: token.creator_address.clone(),
                     collection_name: token.collection_name.clone(),
                     name: token.name.clone(),
                     amount: amount.clone(),
                     token_properties: token.token_properties.clone(),
                     last_transaction_version: txn_version,
-                    table_type: tm.table_type.clone(),
+                    table_type: table_type.clone(),
                     last_transaction_timestamp: token.transaction_timestamp,
+                    owner_type: OWNER_TYPE_USER.to_string(),
+                    beneficial_owner: None,
+                    is_deleted: amount.is_zero(),
                 }),
-                Some(tm.owner_address.clone()),
-                Some(tm.table_type.clone()),
+                Some(owner_address),
+                Some(table_type),
             ),
             None => {
                 aptos_logger::warn!(
@@ -124,3 +193,424 @@ impl TokenOwnership {
         )
     }
 }
+
+impl CurrentTokenOwnership {
+    /// Accounts written in the transaction that nobody can sign for, i.e. whose authentication
+    /// key is all zeros. Resource accounts are created that way, or get their key zeroed once
+    /// their signer capability is retrieved. The Account resource is written when the account
+    /// creates its TokenStore, since that creates event handles, so that's when it's found.
+    pub fn get_contract_addresses(changes: &[APIWriteSetChange]) -> HashSet<String> {
+        let mut contract_addresses = HashSet::new();
+        for wsc in changes {
+            let write_resource = match wsc {
+                APIWriteSetChange::WriteResource(write_resource) => write_resource,
+                _ => continue,
+            };
+            let typ = &write_resource.data.typ;
+            if format!("{}::{}::{}", typ.address, typ.module, typ.name) != ACCOUNT_TYPE {
+                continue;
+            }
+            let data = serde_json::to_value(&write_resource.data.data).unwrap();
+            let is_zero_key = data["authentication_key"]
+                .as_str()
+                .and_then(|key| key.strip_prefix("0x"))
+                .map_or(false, |hex| hex.chars().all(|c| c == '0'));
+            if is_zero_key {
+                contract_addresses.insert(standardize_address(&write_resource.address.to_string()));
+            }
+        }
+        contract_addresses
+    }
+
+    pub fn set_owner_type(
+        &mut self,
+        marketplace_event_mappings: &MarketplaceEventMappings,
+        contract_addresses: &HashSet<String>,
+    ) {
+        let owner_type = if marketplace_event_mappings.is_escrow_address(&self.owner_address) {
+            OWNER_TYPE_MARKETPLACE_ESCROW
+        } else if contract_addresses.contains(&self.owner_address) {
+            OWNER_TYPE_UNKNOWN_CONTRACT
+        } else {
+            OWNER_TYPE_USER
+        };
+        self.owner_type = owner_type.to_string();
+    }
+
+    /// Contracts are only recognized in the transaction their account is written, so owners
+    /// classified earlier in the batch or in the db keep their type in later transactions
+    pub fn set_known_owner_types(
+        conn: &mut PgPoolConnection,
+        current_token_ownerships: &mut HashMap<CurrentTokenOwnershipPK, Self>,
+    ) -> QueryResult<()> {
+        let mut known_owner_types = current_token_ownerships
+            .values()
+            .filter(|ownership| ownership.owner_type != OWNER_TYPE_USER)
+            .map(|ownership| {
+                (
+                    ownership.owner_address.clone(),
+                    ownership.owner_type.clone(),
+                )
+            })
+            .collect::<HashMap<String, String>>();
+        let owner_addresses = current_token_ownerships
+            .values()
+            .filter(|ownership| !known_owner_types.contains_key(&ownership.owner_address))
+            .map(|ownership| ownership.owner_address.clone())
+            .collect::<HashSet<String>>();
+        if !owner_addresses.is_empty() {
+            known_owner_types.extend(
+                current_token_ownerships::table
+                    .filter(current_token_ownerships::owner_address.eq_any(owner_addresses))
+                    .filter(current_token_ownerships::owner_type.ne(OWNER_TYPE_USER))
+                    .select((
+                        current_token_ownerships::owner_address,
+                        current_token_ownerships::owner_type,
+                    ))
+                    .distinct()
+                    .load::<(String, String)>(conn)?,
+            );
+        }
+        for ownership in current_token_ownerships.values_mut() {
+            if let Some(owner_type) = known_owner_types.get(&ownership.owner_address) {
+                ownership.owner_type = owner_type.clone();
+            }
+        }
+        Ok(())
+    }
+
+    /// Who the token counts as held by, the seller for a listed token in escrow
+    pub fn holder_address(&self) -> &str {
+        self.beneficial_owner
+            .as_deref()
+            .unwrap_or(&self.owner_address)
+    }
+
+    /// Mutating the property map of a token at property_version 0 moves one of the owner's tokens
+    /// to a new property_version. The owner's TokenStore changes aren't always in the write set,
+    /// which would leave the old row with the stale amount, so the event fills in the two rows:
+    /// the old property_version zeroed and the new one holding the moved token. Rows the write set
+    /// has are more accurate, ex: for a semi-fungible token with some left, and should win.
+    pub fn from_mutated_token_events(
+        events: &[APIEvent],
+        token_events: &TokenEvents,
+        txn_version: i64,
+        txn_timestamp: chrono::NaiveDateTime,
+    ) -> Vec<Self> {
+        let mut ownerships = vec![];
+        for (event_index, event) in events.iter().enumerate() {
+            let inner = match token_events.get(event_index) {
+                Some(TokenEvent::MutateTokenPropertyMapEvent(inner)) => inner,
+                _ => continue,
+            };
+            // Tokens that already have their own property_version are mutated in place
+            let (old_id, new_id) = (&inner.old_id, &inner.new_id);
+            if old_id.property_version == new_id.property_version {
+                continue;
+            }
+            // Emitted from the owner's TokenStore
+            let owner_address = match event_handle_address(event) {
+                Some(owner_address) => owner_address,
+                None => continue,
+            };
+            for (token_id, amount) in [(old_id, BigDecimal::zero()), (new_id, BigDecimal::one())] {
+                let token_data_id = &token_id.token_data_id;
+                let is_deleted = amount.is_zero();
+                ownerships.push(Self {
+                    token_data_id_hash: token_data_id.to_hash(),
+                    property_version: token_id.property_version.clone(),
+                    owner_address: owner_address.clone(),
+                    creator_address: token_data_id.get_creator_address(),
+                    collection_name: token_data_id.get_collection_trunc(),
+                    name: token_data_id.get_name_trunc(),
+                    amount,
+                    token_properties: serde_json::Value::Null,
+                    last_transaction_version: txn_version,
+                    collection_data_id_hash: token_data_id.get_collection_data_id_hash(),
+                    table_type: TOKEN_STORE_TYPE.to_string(),
+                    last_transaction_timestamp: txn_timestamp,
+                    owner_type: OWNER_TYPE_USER.to_string(),
+                    beneficial_owner: None,
+                    is_deleted,
+                });
+            }
+        }
+        ownerships
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_utils::{timestamp, token_id};
+    use aptos_api_types::{
+        DeleteTableItem as APIDeleteTableItem, WriteTableItem as APIWriteTableItem,
+    };
+    use serde_json::json;
+
+    const OWNER: &str = "0xa11ce";
+    const TOKEN_STORE_HANDLE: &str = "0x1234";
+
+    fn burned_token_owners(burned: bool) -> BurnedTokenOwners {
+        let events: Vec<APIEvent> = if burned {
+            serde_json::from_value(json!([{
+                "guid": {"creation_number": "2", "account_address": OWNER},
+                "sequence_number": "0",
+                "type": "0x3::token::BurnTokenEvent",
+                "data": {"amount": "1", "id": token_id()}
+            }]))
+            .unwrap()
+        } else {
+            vec![]
+        };
+        let token_events = TokenEvents::from_events(&events, 2).unwrap();
+        TokenOwnership::get_burned_token_owners_from_events(&events, &token_events)
+    }
+
+    fn burn_whole(burned: bool) -> Option<CurrentTokenOwnership> {
+        let table_item: APIDeleteTableItem = serde_json::from_value(json!({
+            "state_key_hash": "0x00",
+            "handle": TOKEN_STORE_HANDLE,
+            "key": "0x00",
+            "data": {"key": token_id(), "key_type": "0x3::token::TokenId"}
+        }))
+        .unwrap();
+        // The TokenStore resource isn't in the write set
+        Token::from_delete_table_item(
+            &table_item,
+            2,
+            timestamp(),
+            &HashMap::new(),
+            &burned_token_owners(burned),
+        )
+        .unwrap()
+        .unwrap()
+        .2
+    }
+
+    #[test]
+    fn test_burn_zeroes_ownership() {
+        let ownership = burn_whole(true).unwrap();
+        assert_eq!(ownership.owner_address, standardize_address(OWNER));
+        assert_eq!(ownership.amount, BigDecimal::zero());
+        assert!(ownership.is_deleted);
+        assert_eq!(ownership.table_type, TOKEN_STORE_TYPE);
+        assert_eq!(ownership.last_transaction_version, 2);
+    }
+
+    #[test]
+    fn test_partial_burn_keeps_remaining_amount() {
+        let table_item: APIWriteTableItem = serde_json::from_value(json!({
+            "state_key_hash": "0x00",
+            "handle": TOKEN_STORE_HANDLE,
+            "key": "0x00",
+            "value": "0x00",
+            "data": {
+                "key": token_id(),
+                "key_type": "0x3::token::TokenId",
+                "value": {"amount": "4", "id": token_id(), "token_properties": {}},
+                "value_type": "0x3::token::Token"
+            }
+        }))
+        .unwrap();
+        let (_, _, ownership) = Token::from_write_table_item(
+            &table_item,
+            2,
+            timestamp(),
+            &HashMap::new(),
+            &burned_token_owners(true),
+        )
+        .unwrap()
+        .unwrap();
+        let ownership = ownership.unwrap();
+        assert_eq!(ownership.owner_address, standardize_address(OWNER));
+        assert_eq!(ownership.amount, BigDecimal::from(4));
+        assert!(!ownership.is_deleted);
+    }
+
+    #[test]
+    fn test_delete_without_owner_is_skipped() {
+        assert!(burn_whole(false).is_none());
+    }
+
+    fn token_id_at(property_version: u64) -> serde_json::Value {
+        let mut token_id = token_id();
+        token_id["property_version"] = json!(property_version.to_string());
+        token_id
+    }
+
+    #[test]
+    fn test_mutate_then_transfer() {
+        const RECIPIENT: &str = "0xb0b";
+        const RECIPIENT_HANDLE: &str = "0x5678";
+        let events: Vec<APIEvent> = serde_json::from_value(json!([{
+            "guid": {"creation_number": "4", "account_address": OWNER},
+            "sequence_number": "0",
+            "type": "0x3::token::MutateTokenPropertyMapEvent",
+            "data": {"old_id": token_id_at(0), "new_id": token_id_at(1)}
+        }]))
+        .unwrap();
+        let token_events = TokenEvents::from_events(&events, 2).unwrap();
+        let mut ownerships = HashMap::new();
+        for ownership in
+            CurrentTokenOwnership::from_mutated_token_events(&events, &token_events, 2, timestamp())
+        {
+            ownerships.insert(
+                (
+                    ownership.property_version.clone(),
+                    ownership.owner_address.clone(),
+                ),
+                ownership.amount,
+            );
+        }
+        let owner = standardize_address(OWNER);
+        assert_eq!(
+            ownerships,
+            HashMap::from([
+                ((BigDecimal::zero(), owner.clone()), BigDecimal::zero()),
+                ((BigDecimal::one(), owner.clone()), BigDecimal::one()),
+            ])
+        );
+
+        // The new property_version is then sent to another wallet, both TokenStores are written
+        let table_handle_to_owner = HashMap::from([
+            (
+                TableMetadataForToken::standardize_handle(TOKEN_STORE_HANDLE),
+                TableMetadataForToken {
+                    owner_address: owner.clone(),
+                    table_type: TOKEN_STORE_TYPE.to_string(),
+                },
+            ),
+            (
+                TableMetadataForToken::standardize_handle(RECIPIENT_HANDLE),
+                TableMetadataForToken {
+                    owner_address: standardize_address(RECIPIENT),
+                    table_type: TOKEN_STORE_TYPE.to_string(),
+                },
+            ),
+        ]);
+        let withdrawn: APIDeleteTableItem = serde_json::from_value(json!({
+            "state_key_hash": "0x00",
+            "handle": TOKEN_STORE_HANDLE,
+            "key": "0x00",
+            "data": {"key": token_id_at(1), "key_type": "0x3::token::TokenId"}
+        }))
+        .unwrap();
+        let deposited: APIWriteTableItem = serde_json::from_value(json!({
+            "state_key_hash": "0x00",
+            "handle": RECIPIENT_HANDLE,
+            "key": "0x00",
+            "value": "0x00",
+            "data": {
+                "key": token_id_at(1),
+                "key_type": "0x3::token::TokenId",
+                "value": {"amount": "1", "id": token_id_at(1), "token_properties": {}},
+                "value_type": "0x3::token::Token"
+            }
+        }))
+        .unwrap();
+        let (_, _, withdrawn) = Token::from_delete_table_item(
+            &withdrawn,
+            3,
+            timestamp(),
+            &table_handle_to_owner,
+            &HashMap::new(),
+        )
+        .unwrap()
+        .unwrap();
+        let (_, _, deposited) = Token::from_write_table_item(
+            &deposited,
+            3,
+            timestamp(),
+            &table_handle_to_owner,
+            &HashMap::new(),
+        )
+        .unwrap()
+        .unwrap();
+        for ownership in [withdrawn.unwrap(), deposited.unwrap()] {
+            ownerships.insert(
+                (
+                    ownership.property_version.clone(),
+                    ownership.owner_address.clone(),
+                ),
+                ownership.amount,
+            );
+        }
+        // Nothing is left under the old property_version or with the original owner
+        assert_eq!(
+            ownerships,
+            HashMap::from([
+                ((BigDecimal::zero(), owner.clone()), BigDecimal::zero()),
+                ((BigDecimal::one(), owner), BigDecimal::zero()),
+                (
+                    (BigDecimal::one(), standardize_address(RECIPIENT)),
+                    BigDecimal::one()
+                ),
+            ])
+        );
+    }
+
+    #[test]
+    fn test_mutate_in_place_is_skipped() {
+        let events: Vec<APIEvent> = serde_json::from_value(json!([{
+            "guid": {"creation_number": "4", "account_address": OWNER},
+            "sequence_number": "0",
+            "type": "0x3::token::MutateTokenPropertyMapEvent",
+            "data": {"old_id": token_id_at(1), "new_id": token_id_at(1)}
+        }]))
+        .unwrap();
+        let token_events = TokenEvents::from_events(&events, 2).unwrap();
+        assert!(CurrentTokenOwnership::from_mutated_token_events(
+            &events,
+            &token_events,
+            2,
+            timestamp()
+        )
+        .is_empty());
+    }
+
+    fn account_resource(address: &str, authentication_key: &str) -> APIWriteSetChange {
+        serde_json::from_value(json!({
+            "type": "write_resource",
+            "address": address,
+            "state_key_hash": "0x00",
+            "data": {
+                "type": "0x1::account::Account",
+                "data": {
+                    "authentication_key": authentication_key,
+                    "sequence_number": "0"
+                }
+            }
+        }))
+        .unwrap()
+    }
+
+    #[test]
+    fn test_owner_types() {
+        const ESCROW: &str = "0xe5c0";
+        let contract_addresses = CurrentTokenOwnership::get_contract_addresses(&[
+            account_resource(OWNER, &format!("0x{}", "0".repeat(64))),
+            account_resource(ESCROW, &format!("0x{}", "1e".repeat(32))),
+        ]);
+        assert_eq!(
+            contract_addresses,
+            HashSet::from([standardize_address(OWNER)])
+        );
+
+        let mappings = MarketplaceEventMappings::default()
+            .with_escrow_addresses(&[ESCROW.to_string()])
+            .unwrap();
+        let mut ownership = burn_whole(true).unwrap();
+        ownership.set_owner_type(&mappings, &HashSet::new());
+        assert_eq!(ownership.owner_type, OWNER_TYPE_USER);
+        ownership.set_owner_type(&mappings, &contract_addresses);
+        assert_eq!(ownership.owner_type, OWNER_TYPE_UNKNOWN_CONTRACT);
+        // Escrow addresses are matched standardized, even when they're resource accounts
+        ownership.owner_address = standardize_address(ESCROW);
+        ownership.set_owner_type(&mappings, &contract_addresses);
+        assert_eq!(ownership.owner_type, OWNER_TYPE_MARKETPLACE_ESCROW);
+        assert_eq!(ownership.holder_address(), standardize_address(ESCROW));
+        ownership.beneficial_owner = Some(standardize_address(OWNER));
+        assert_eq!(ownership.holder_address(), standardize_address(OWNER));
+    }
+}
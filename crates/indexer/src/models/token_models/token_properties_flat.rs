@@ -0,0 +1,214 @@
+// Copyright (c) Aptos
+// SPDX-License-Identifier: Apache-2.0
+
+// This is required because a diesel macro makes clippy sad
+#![allow(clippy::extra_unused_lifetimes)]
+#![allow(clippy::unused_unit)]
+
+use super::token_datas::CurrentTokenData;
+use crate::schema::token_properties_flat;
+use field_count::FieldCount;
+use serde::{Deserialize, Serialize};
+use std::collections::HashSet;
+
+/// Property maps are user controlled, so only this many keys are indexed per token
+pub const MAX_PROPERTIES_PER_TOKEN: usize = 64;
+const MAX_PROPERTY_KEY_LENGTH: usize = 128;
+const MAX_PROPERTY_TYPE_LENGTH: usize = 128;
+// Keeps values within the btree index row limit
+const MAX_PROPERTY_VALUE_LENGTH: usize = 500;
+
+/// One row per key of a token's default properties, so tokens can be filtered by trait
+#[derive(Debug, Deserialize, FieldCount, Identifiable, Insertable, Serialize)]
+#[diesel(primary_key(token_data_id_hash, property_key))]
+#[diesel(table_name = token_properties_flat)]
+pub struct TokenPropertyFlat {
+    pub token_data_id_hash: String,
+    pub property_key: String,
+    pub property_value: String,
+    pub property_type: String,
+    pub collection_data_id_hash: String,
+    pub last_transaction_version: i64,
+}
+
+impl TokenPropertyFlat {
+    /// Property maps come in as {"map": {"data": [{"key": .., "value": {"type": .., "value": ..}}]}}
+    /// with bcs encoded values. Common primitive types are decoded, anything else is kept as the
+    /// raw hex string.
+    ///
+    /// Every write of the token data carries the full map, so keys that aren't in it anymore are
+    /// removed by deleting the token's rows with an older last_transaction_version.
+    pub fn from_current_token_data(token_data: &CurrentTokenData) -> Vec<Self> {
+        let entries = match token_data.default_properties["map"]["data"].as_array() {
+            Some(entries) => entries,
+            None => return vec![],
+        };
+        if entries.len() > MAX_PROPERTIES_PER_TOKEN {
+            aptos_logger::warn!(
+                transaction_version = token_data.last_transaction_version,
+                token_data_id_hash = token_data.token_data_id_hash,
+                num_properties = entries.len(),
+                "Too many properties, only indexing the first {}",
+                MAX_PROPERTIES_PER_TOKEN
+            );
+        }
+        // Truncation could make two keys collide, which can't be upserted in the same statement
+        let mut seen_keys = HashSet::new();
+        entries
+            .iter()
+            .take(MAX_PROPERTIES_PER_TOKEN)
+            .filter_map(|entry| {
+                let property_key =
+                    Self::truncate_chars(entry["key"].as_str()?, MAX_PROPERTY_KEY_LENGTH);
+                if !seen_keys.insert(property_key.clone()) {
+                    return None;
+                }
+                let property_type = entry["value"]["type"].as_str().unwrap_or_default();
+                let raw_value = entry["value"]["value"].as_str().unwrap_or_default();
+                Some(Self {
+                    token_data_id_hash: token_data.token_data_id_hash.clone(),
+                    property_key,
+                    property_value: Self::truncate_chars(
+                        &Self::decode_value(property_type, raw_value),
+                        MAX_PROPERTY_VALUE_LENGTH,
+                    ),
+                    property_type: Self::truncate_chars(property_type, MAX_PROPERTY_TYPE_LENGTH),
+                    collection_data_id_hash: token_data.collection_data_id_hash.clone(),
+                    last_transaction_version: token_data.last_transaction_version,
+                })
+            })
+            .collect()
+    }
+
+    /// Keys and values are user controlled, so unlike `util::truncate_str` this can't split a
+    /// multibyte character
+    fn truncate_chars(val: &str, max_chars: usize) -> String {
+        val.chars().take(max_chars).collect()
+    }
+
+    /// Decodes bcs encoded primitives, falling back to the raw value
+    pub fn decode_value(property_type: &str, raw_value: &str) -> String {
+        let bytes = match hex::decode(raw_value.trim_start_matches("0x")) {
+            Ok(bytes) => bytes,
+            Err(_) => return raw_value.to_string(),
+        };
+        let decoded = match property_type {
+            "0x1::string::String" => Self::decode_string(&bytes),
+            "bool" => match bytes.as_slice() {
+                [0] => Some("false".to_string()),
+                [1] => Some("true".to_string()),
+                _ => None,
+            },
+            "u8" | "u64" | "u128" => Self::decode_integer(&bytes),
+            "address" if bytes.len() == 32 => Some(format!("0x{}", hex::encode(&bytes))),
+            _ => None,
+        };
+        decoded.unwrap_or_else(|| raw_value.to_string())
+    }
+
+    /// Strings are the utf8 bytes prefixed with their uleb128 encoded length
+    fn decode_string(bytes: &[u8]) -> Option<String> {
+        let mut length = 0usize;
+        for (index, byte) in bytes.iter().enumerate().take(5) {
+            length |= ((byte & 0x7f) as usize) << (7 * index);
+            if byte & 0x80 == 0 {
+                let content = bytes.get(index + 1..)?;
+                if content.len() != length {
+                    return None;
+                }
+                return String::from_utf8(content.to_vec()).ok();
+            }
+        }
+        None
+    }
+
+    /// Integers are little endian
+    fn decode_integer(bytes: &[u8]) -> Option<String> {
+        if bytes.is_empty() || bytes.len() > 16 {
+            return None;
+        }
+        let mut buf = [0u8; 16];
+        buf[..bytes.len()].copy_from_slice(bytes);
+        Some(u128::from_le_bytes(buf).to_string())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use bigdecimal::BigDecimal;
+    use serde_json::json;
+
+    fn token_data(default_properties: serde_json::Value) -> CurrentTokenData {
+        CurrentTokenData {
+            token_data_id_hash: "token".to_string(),
+            creator_address: "0xc4e7".to_string(),
+            collection_name: "Potions".to_string(),
+            name: "Potion".to_string(),
+            maximum: BigDecimal::from(1),
+            supply: BigDecimal::from(1),
+            largest_property_version: BigDecimal::from(0),
+            metadata_uri: "".to_string(),
+            payee_address: "0xc4e7".to_string(),
+            royalty_points_numerator: BigDecimal::from(0),
+            royalty_points_denominator: BigDecimal::from(1),
+            maximum_mutable: false,
+            uri_mutable: false,
+            description_mutable: false,
+            properties_mutable: true,
+            royalty_mutable: false,
+            default_properties,
+            last_transaction_version: 5,
+            collection_data_id_hash: "collection".to_string(),
+            last_transaction_timestamp: chrono::NaiveDateTime::from_timestamp(1668000000, 0),
+            description: "".to_string(),
+            metadata_uri_canonical: None,
+            uri_scheme: Some("empty".to_string()),
+        }
+    }
+
+    fn property(key: &str, property_type: &str, value: &str) -> serde_json::Value {
+        json!({"key": key, "value": {"type": property_type, "value": value}})
+    }
+
+    #[test]
+    fn test_flatten_properties() {
+        let properties = TokenPropertyFlat::from_current_token_data(&token_data(json!({
+            "map": {"data": [
+                property("Background", "0x1::string::String", "0x04476f6c64"),
+                property("Level", "u64", "0x0700000000000000"),
+                property("Shiny", "bool", "0x01"),
+                property("Blob", "vector<u8>", "0xdead"),
+            ]}
+        })));
+        assert_eq!(
+            properties
+                .iter()
+                .map(|property| (
+                    property.property_key.as_str(),
+                    property.property_value.as_str()
+                ))
+                .collect::<Vec<_>>(),
+            vec![
+                ("Background", "Gold"),
+                ("Level", "7"),
+                ("Shiny", "true"),
+                ("Blob", "0xdead"),
+            ]
+        );
+        assert_eq!(properties[0].property_type, "0x1::string::String");
+        assert_eq!(properties[0].last_transaction_version, 5);
+    }
+
+    #[test]
+    fn test_properties_are_capped() {
+        let entries = (0..100)
+            .map(|i| property(&format!("key_{}", i), "u8", "0x01"))
+            .collect::<Vec<_>>();
+        let properties = TokenPropertyFlat::from_current_token_data(&token_data(
+            json!({"map": {"data": entries}}),
+        ));
+        assert_eq!(properties.len(), MAX_PROPERTIES_PER_TOKEN);
+        assert!(TokenPropertyFlat::from_current_token_data(&token_data(json!({}))).is_empty());
+    }
+}
@@ -0,0 +1,264 @@
+// Copyright (c) Aptos
+// SPDX-License-Identifier: Apache-2.0
+
+// This is required because a diesel macro makes clippy sad
+#![allow(clippy::extra_unused_lifetimes)]
+#![allow(clippy::unused_unit)]
+
+use super::{
+    token_properties_flat::TokenPropertyFlat,
+    token_utils::{MutateTokenPropertyMapEventType, TokenEvent, TokenEvents, TokenWriteSet},
+};
+use crate::{schema::token_property_mutations, util::parse_timestamp};
+use aptos_api_types::{Transaction as APITransaction, WriteSetChange as APIWriteSetChange};
+use bigdecimal::BigDecimal;
+use field_count::FieldCount;
+use serde::{Deserialize, Serialize};
+use serde_json::json;
+
+/// One row per MutateTokenPropertyMapEvent, ex: to find collections revealing their tokens after
+/// the mint.
+///
+/// Only the new side of the property map is in the transaction. The old map is the previous
+/// mutation's new_token_properties, or the token data's default_properties for property_version 0.
+#[derive(Debug, Deserialize, FieldCount, Identifiable, Insertable, Serialize)]
+#[diesel(primary_key(transaction_version, event_index))]
+#[diesel(table_name = token_property_mutations)]
+pub struct TokenPropertyMutation {
+    pub transaction_version: i64,
+    pub event_index: i64,
+    pub old_token_data_id_hash: String,
+    pub new_token_data_id_hash: String,
+    pub old_property_version: BigDecimal,
+    pub new_property_version: BigDecimal,
+    pub collection_data_id_hash: String,
+    pub creator_address: String,
+    pub collection_name: String,
+    pub name: String,
+    /// {key: {"type": .., "value": ..}} of the keys the event set, values decoded like
+    /// token_properties_flat's. Empty for events emitted before the framework added the keys.
+    pub mutated_properties: serde_json::Value,
+    /// The new token's property map as written, null if the token isn't in the write set
+    pub new_token_properties: serde_json::Value,
+    pub transaction_timestamp: chrono::NaiveDateTime,
+}
+
+impl TokenPropertyMutation {
+    pub fn from_transaction(transaction: &APITransaction, token_events: &TokenEvents) -> Vec<Self> {
+        let user_txn = match transaction {
+            APITransaction::UserTransaction(user_txn) => user_txn,
+            _ => return vec![],
+        };
+        let txn_version = user_txn.info.version.0 as i64;
+        let mutations = user_txn
+            .events
+            .iter()
+            .enumerate()
+            .filter_map(|(index, _)| match token_events.get(index) {
+                Some(TokenEvent::MutateTokenPropertyMapEvent(inner)) => Some((index as i64, inner)),
+                _ => None,
+            })
+            .collect::<Vec<(i64, &MutateTokenPropertyMapEventType)>>();
+        if mutations.is_empty() {
+            return vec![];
+        }
+
+        let txn_timestamp = parse_timestamp(user_txn.timestamp.0, txn_version);
+        // Written tokens by their id, to look up the new side of each mutation
+        let written_tokens = user_txn
+            .info
+            .changes
+            .iter()
+            .filter_map(|wsc| match wsc {
+                APIWriteSetChange::WriteTableItem(table_item) => {
+                    let data = table_item.data.as_ref()?;
+                    match TokenWriteSet::from_table_item_type(
+                        data.value_type.as_str(),
+                        &data.value,
+                        txn_version,
+                    )
+                    .unwrap()
+                    {
+                        Some(TokenWriteSet::Token(token)) => {
+                            Some((token.id.to_string(), token.token_properties))
+                        }
+                        _ => None,
+                    }
+                }
+                _ => None,
+            })
+            .collect::<Vec<(String, serde_json::Value)>>();
+
+        mutations
+            .into_iter()
+            .map(|(event_index, inner)| {
+                let new_id = inner.new_id.to_string();
+                let new_token_properties = written_tokens
+                    .iter()
+                    .rev()
+                    .find(|(token_id, _)| *token_id == new_id)
+                    .map(|(_, properties)| properties.clone())
+                    .unwrap_or(serde_json::Value::Null);
+                let new_token_data_id = &inner.new_id.token_data_id;
+                Self {
+                    transaction_version: txn_version,
+                    event_index,
+                    old_token_data_id_hash: inner.old_id.token_data_id.to_hash(),
+                    new_token_data_id_hash: new_token_data_id.to_hash(),
+                    old_property_version: inner.old_id.property_version.clone(),
+                    new_property_version: inner.new_id.property_version.clone(),
+                    collection_data_id_hash: new_token_data_id.get_collection_data_id_hash(),
+                    creator_address: new_token_data_id.get_creator_address(),
+                    collection_name: new_token_data_id.get_collection_trunc(),
+                    name: new_token_data_id.get_name_trunc(),
+                    mutated_properties: Self::get_mutated_properties(inner),
+                    new_token_properties,
+                    transaction_timestamp: txn_timestamp,
+                }
+            })
+            .collect()
+    }
+
+    /// Keys, values and types are parallel vectors in the event
+    fn get_mutated_properties(inner: &MutateTokenPropertyMapEventType) -> serde_json::Value {
+        let properties = inner
+            .keys
+            .iter()
+            .zip(inner.values.iter())
+            .zip(inner.types.iter())
+            .map(|((key, value), property_type)| {
+                (
+                    key.clone(),
+                    json!({
+                        "type": property_type,
+                        "value": TokenPropertyFlat::decode_value(property_type, value),
+                    }),
+                )
+            })
+            .collect::<serde_json::Map<String, serde_json::Value>>();
+        serde_json::Value::Object(properties)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const OWNER: &str = "0x7f2b4a3c";
+
+    fn token_id_at(property_version: u64) -> serde_json::Value {
+        json!({
+            "token_data_id": {
+                "creator": "0xc4e7",
+                "collection": "Potions",
+                "name": "Potion #1"
+            },
+            "property_version": property_version.to_string()
+        })
+    }
+
+    fn transaction(events: serde_json::Value, changes: serde_json::Value) -> APITransaction {
+        let hash = format!("0x{}", "00".repeat(32));
+        serde_json::from_value(json!({
+            "type": "user_transaction",
+            "version": "10",
+            "hash": hash,
+            "state_change_hash": hash,
+            "event_root_hash": hash,
+            "state_checkpoint_hash": null,
+            "gas_used": "0",
+            "success": true,
+            "vm_status": "Executed successfully",
+            "accumulator_root_hash": hash,
+            "changes": changes,
+            "sender": OWNER,
+            "sequence_number": "0",
+            "max_gas_amount": "0",
+            "gas_unit_price": "0",
+            "expiration_timestamp_secs": "0",
+            "payload": {
+                "type": "entry_function_payload",
+                "function": "0xc4e7::potions::reveal",
+                "type_arguments": [],
+                "arguments": []
+            },
+            "events": events,
+            "timestamp": "1668000000000000"
+        }))
+        .unwrap()
+    }
+
+    #[test]
+    fn test_mutation_with_new_token() {
+        let txn = transaction(
+            json!([{
+                "guid": {"creation_number": "4", "account_address": OWNER},
+                "sequence_number": "0",
+                "type": "0x3::token::MutateTokenPropertyMapEvent",
+                "data": {
+                    "old_id": token_id_at(0),
+                    "new_id": token_id_at(1),
+                    "keys": ["Level", "Revealed"],
+                    "values": ["0x0700000000000000", "0x01"],
+                    "types": ["u64", "bool"]
+                }
+            }]),
+            json!([{
+                "type": "write_table_item",
+                "state_key_hash": "0x00",
+                "handle": "0x01",
+                "key": "0x00",
+                "value": "0x00",
+                "data": {
+                    "key": token_id_at(1),
+                    "key_type": "0x3::token::TokenId",
+                    "value": {
+                        "amount": "1",
+                        "id": token_id_at(1),
+                        "token_properties": {"map": {"data": []}}
+                    },
+                    "value_type": "0x3::token::Token"
+                }
+            }]),
+        );
+        let token_events = TokenEvents::from_transaction(&txn).unwrap();
+        let mutations = TokenPropertyMutation::from_transaction(&txn, &token_events);
+        assert_eq!(mutations.len(), 1);
+        let mutation = &mutations[0];
+        assert_eq!(
+            (mutation.transaction_version, mutation.event_index),
+            (10, 0)
+        );
+        assert_eq!(mutation.old_property_version, BigDecimal::from(0));
+        assert_eq!(mutation.new_property_version, BigDecimal::from(1));
+        assert_eq!(
+            mutation.old_token_data_id_hash,
+            mutation.new_token_data_id_hash
+        );
+        assert_eq!(
+            mutation.mutated_properties,
+            json!({
+                "Level": {"type": "u64", "value": "7"},
+                "Revealed": {"type": "bool", "value": "true"},
+            })
+        );
+        assert_eq!(mutation.new_token_properties, json!({"map": {"data": []}}));
+    }
+
+    #[test]
+    fn test_mutation_without_keys() {
+        let txn = transaction(
+            json!([{
+                "guid": {"creation_number": "4", "account_address": OWNER},
+                "sequence_number": "0",
+                "type": "0x3::token::MutateTokenPropertyMapEvent",
+                "data": {"old_id": token_id_at(1), "new_id": token_id_at(1)}
+            }]),
+            json!([]),
+        );
+        let token_events = TokenEvents::from_transaction(&txn).unwrap();
+        let mutations = TokenPropertyMutation::from_transaction(&txn, &token_events);
+        assert_eq!(mutations[0].mutated_properties, json!({}));
+        assert_eq!(mutations[0].new_token_properties, serde_json::Value::Null);
+    }
+}
@@ -0,0 +1,109 @@
+// Copyright (c) Aptos
+// SPDX-License-Identifier: Apache-2.0
+
+use anyhow::bail;
+use std::collections::HashSet;
+
+/// Every table the token processor writes, in the order they're written. Derived tables (ex:
+/// current_collection_holder_counts) are toggled separately from the tables they're computed
+/// from.
+pub const TOKEN_TABLES: &[&str] = &[
+    "current_collection_holder_counts",
+    "current_token_ownerships",
+    "current_collection_mint_stats",
+    "collection_mints",
+    "token_property_mutations",
+    "current_token_datas",
+    "token_properties_flat",
+    "current_collection_datas",
+    "truncated_strings",
+    "token_activities",
+    "account_token_activities",
+    "nft_sales",
+    "nft_transaction_fees",
+    "collection_hold_durations",
+    "current_collection_price_stats",
+    "current_token_pending_claims",
+    "current_token_transfer_offers",
+    "current_ans_lookups",
+    "current_ans_primary_names",
+    "current_ans_sale_prices",
+    "current_marketplace_listings",
+    "marketplace_listing_price_changes",
+    "current_collection_volumes",
+    "collection_volumes",
+    "current_marketplace_volumes",
+    "marketplace_volumes",
+    "marketplace_collection_volumes",
+    "current_token_volumes",
+    "token_volumes",
+    "collection_price_candles",
+    "collection_daily_reports",
+    "current_wallet_nft_stats",
+    "wallet_token_cost_basis",
+    "token_acquisitions",
+    "current_collection_offers",
+    "current_collection_best_offers",
+    "current_token_bids",
+    "current_token_top_bids",
+];
+
+/// Which tables the token processor writes. Every table is parsed regardless, so disabling a
+/// table only skips its writes. Used to backfill a few tables without rewriting the others.
+#[derive(Clone, Debug, Default)]
+pub struct TokenTables {
+    /// None if every table is enabled
+    enabled: Option<HashSet<&'static str>>,
+}
+
+impl TokenTables {
+    pub fn from_config(tables: Option<&[String]>) -> anyhow::Result<Self> {
+        let tables = match tables {
+            Some(tables) => tables,
+            None => return Ok(Self::default()),
+        };
+        let mut enabled = HashSet::new();
+        for table in tables {
+            match TOKEN_TABLES.iter().find(|known| **known == table.as_str()) {
+                Some(known) => enabled.insert(*known),
+                None => bail!(
+                    "Unknown table {} in enabled_tables, expected one of {}",
+                    table,
+                    TOKEN_TABLES.join(", ")
+                ),
+            };
+        }
+        Ok(Self {
+            enabled: Some(enabled),
+        })
+    }
+
+    pub fn is_enabled(&self, table: &str) -> bool {
+        match &self.enabled {
+            Some(enabled) => enabled.contains(table),
+            None => true,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_from_config() {
+        let all = TokenTables::from_config(None).unwrap();
+        assert!(TOKEN_TABLES.iter().all(|table| all.is_enabled(table)));
+
+        let tables = TokenTables::from_config(Some(&[
+            "current_marketplace_listings".to_string(),
+            "collection_volumes".to_string(),
+        ]))
+        .unwrap();
+        assert!(tables.is_enabled("current_marketplace_listings"));
+        assert!(tables.is_enabled("collection_volumes"));
+        assert!(!tables.is_enabled("current_token_ownerships"));
+
+        assert!(TokenTables::from_config(Some(&["ownerships".to_string()])).is_err());
+    }
+}
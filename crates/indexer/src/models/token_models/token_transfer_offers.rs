@@ -0,0 +1,318 @@
+// Copyright (c) Aptos
+// SPDX-License-Identifier: Apache-2.0
+
+//! Tokens offered to a specific address through 0x3::token_transfers, built from the offer, claim
+//! and cancel events alone. current_token_pending_claims follows the offerer's PendingClaims
+//! table instead, so it misses offers whenever the table's owner can't be resolved.
+
+// This is required because a diesel macro makes clippy sad
+#![allow(clippy::extra_unused_lifetimes)]
+#![allow(clippy::unused_unit)]
+
+use super::{
+    token_activities::event_handle_address,
+    token_utils::{TokenEvent, TokenEvents, TokenIdType},
+};
+use crate::{schema::current_token_transfer_offers, util::parse_timestamp};
+use aptos_api_types::Transaction as APITransaction;
+use bigdecimal::BigDecimal;
+use field_count::FieldCount;
+use serde::{Deserialize, Serialize};
+use std::collections::{HashMap, HashSet};
+
+pub const TRANSFER_OFFER_STATUS_PENDING: &str = "pending";
+pub const TRANSFER_OFFER_STATUS_CLAIMED: &str = "claimed";
+pub const TRANSFER_OFFER_STATUS_CANCELLED: &str = "cancelled";
+
+/// (token_data_id_hash, property_version, from_address, to_address)
+pub type CurrentTokenTransferOfferPK = (String, BigDecimal, String, String);
+
+/// Latest state of the offers from one address to another for a token. Offers to the same
+/// receiver add up while pending, and a claim or cancel takes all of them, so amount is what's
+/// pending, claimed or cancelled.
+#[derive(
+    Clone,
+    Debug,
+    Deserialize,
+    FieldCount,
+    Identifiable,
+    Insertable,
+    Queryable,
+    Selectable,
+    Serialize,
+)]
+#[diesel(primary_key(token_data_id_hash, property_version, from_address, to_address))]
+#[diesel(table_name = current_token_transfer_offers)]
+pub struct CurrentTokenTransferOffer {
+    pub token_data_id_hash: String,
+    pub property_version: BigDecimal,
+    pub from_address: String,
+    pub to_address: String,
+    pub collection_data_id_hash: String,
+    pub creator_address: String,
+    pub collection_name: String,
+    pub name: String,
+    pub amount: BigDecimal,
+    pub status: String,
+    pub last_transaction_version: i64,
+    pub last_transaction_timestamp: chrono::NaiveDateTime,
+}
+
+impl CurrentTokenTransferOffer {
+    pub fn pk(&self) -> CurrentTokenTransferOfferPK {
+        (
+            self.token_data_id_hash.clone(),
+            self.property_version.clone(),
+            self.from_address.clone(),
+            self.to_address.clone(),
+        )
+    }
+
+    fn new(
+        token_id: &TokenIdType,
+        from_address: &str,
+        to_address: &str,
+        amount: &BigDecimal,
+        status: &str,
+        txn_version: i64,
+        txn_timestamp: chrono::NaiveDateTime,
+    ) -> Self {
+        let token_data_id = &token_id.token_data_id;
+        Self {
+            token_data_id_hash: token_data_id.to_hash(),
+            property_version: token_id.property_version.clone(),
+            from_address: from_address.to_string(),
+            to_address: to_address.to_string(),
+            collection_data_id_hash: token_data_id.get_collection_data_id_hash(),
+            creator_address: token_data_id.get_creator_address(),
+            collection_name: token_data_id.get_collection_trunc(),
+            name: token_data_id.get_name_trunc(),
+            amount: amount.clone(),
+            status: status.to_string(),
+            last_transaction_version: txn_version,
+            last_transaction_timestamp: txn_timestamp,
+        }
+    }
+}
+
+/// Offers made, claimed and cancelled within a batch. Transactions need to be applied in version
+/// order.
+#[derive(Default)]
+pub struct TokenTransferOfferBook {
+    offers: HashMap<CurrentTokenTransferOfferPK, CurrentTokenTransferOffer>,
+    /// Offers claimed or cancelled in the batch, which replace the stored offer
+    closed: HashSet<CurrentTokenTransferOfferPK>,
+}
+
+impl TokenTransferOfferBook {
+    pub fn apply_transaction(&mut self, transaction: &APITransaction, token_events: &TokenEvents) {
+        if let APITransaction::UserTransaction(user_txn) = transaction {
+            let txn_version = user_txn.info.version.0 as i64;
+            let txn_timestamp = parse_timestamp(user_txn.timestamp.0, txn_version);
+            for (event_index, event) in user_txn.events.iter().enumerate() {
+                let (token_id, to_address, amount, status) = match token_events.get(event_index) {
+                    Some(TokenEvent::OfferTokenEvent(inner)) => (
+                        &inner.token_id,
+                        &inner.to_address,
+                        &inner.amount,
+                        TRANSFER_OFFER_STATUS_PENDING,
+                    ),
+                    Some(TokenEvent::ClaimTokenEvent(inner)) => (
+                        &inner.token_id,
+                        &inner.to_address,
+                        &inner.amount,
+                        TRANSFER_OFFER_STATUS_CLAIMED,
+                    ),
+                    Some(TokenEvent::CancelTokenOfferEvent(inner)) => (
+                        &inner.token_id,
+                        &inner.to_address,
+                        &inner.amount,
+                        TRANSFER_OFFER_STATUS_CANCELLED,
+                    ),
+                    _ => continue,
+                };
+                // All three are emitted from the offerer's PendingClaims
+                let from_address = match event_handle_address(event) {
+                    Some(from_address) => from_address,
+                    None => continue,
+                };
+                let offer = CurrentTokenTransferOffer::new(
+                    token_id,
+                    &from_address,
+                    to_address,
+                    amount,
+                    status,
+                    txn_version,
+                    txn_timestamp,
+                );
+                self.apply(offer);
+            }
+        }
+    }
+
+    fn apply(&mut self, offer: CurrentTokenTransferOffer) {
+        let pk = offer.pk();
+        if offer.status != TRANSFER_OFFER_STATUS_PENDING {
+            self.closed.insert(pk.clone());
+        }
+        match self.offers.get_mut(&pk) {
+            Some(existing)
+                if existing.status == TRANSFER_OFFER_STATUS_PENDING
+                    && offer.status == TRANSFER_OFFER_STATUS_PENDING =>
+            {
+                existing.amount += offer.amount;
+                existing.last_transaction_version = offer.last_transaction_version;
+                existing.last_transaction_timestamp = offer.last_transaction_timestamp;
+            }
+            _ => {
+                self.offers.insert(pk, offer);
+            }
+        }
+    }
+
+    /// Offers that replace the stored ones, and pending offers to add to a stored pending offer
+    /// since nothing closed it in the batch, both sorted by PK
+    pub fn into_rows(
+        self,
+    ) -> (
+        Vec<CurrentTokenTransferOffer>,
+        Vec<CurrentTokenTransferOffer>,
+    ) {
+        let (mut offers, mut additions): (Vec<_>, Vec<_>) = self
+            .offers
+            .into_values()
+            .partition(|offer| self.closed.contains(&offer.pk()));
+        offers.sort_by_key(|offer| offer.pk());
+        additions.sort_by_key(|offer| offer.pk());
+        (offers, additions)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::util::standardize_address;
+    use serde_json::json;
+
+    const HASH: &str = "0x6a527d06063dfd42c6b3a862574d5f3ec1660afb8058135edda5072712bfdb51";
+
+    fn offer_txn(version: u64, event_name: &str, to_address: &str, amount: u64) -> APITransaction {
+        serde_json::from_value(json!({
+            "type": "user_transaction",
+            "version": version.to_string(),
+            "hash": HASH,
+            "state_change_hash": HASH,
+            "event_root_hash": HASH,
+            "state_checkpoint_hash": null,
+            "gas_used": "100",
+            "success": true,
+            "vm_status": "Executed successfully",
+            "accumulator_root_hash": HASH,
+            "changes": [],
+            "sender": "0xa11ce",
+            "sequence_number": "0",
+            "max_gas_amount": "2000",
+            "gas_unit_price": "100",
+            "expiration_timestamp_secs": "1668000600",
+            "payload": {
+                "type": "entry_function_payload",
+                "function": "0x3::token_transfers::offer_script",
+                "type_arguments": [],
+                "arguments": []
+            },
+            "events": [{
+                "guid": {"creation_number": "6", "account_address": "0xa11ce"},
+                "sequence_number": version.to_string(),
+                "type": format!("0x3::token_transfers::{}", event_name),
+                "data": {
+                    "amount": amount.to_string(),
+                    "to_address": to_address,
+                    "token_id": {
+                        "token_data_id": {
+                            "creator": "0xc4e7",
+                            "collection": "Potions",
+                            "name": "Potion"
+                        },
+                        "property_version": "0"
+                    }
+                }
+            }],
+            "timestamp": "1668000000000000"
+        }))
+        .unwrap()
+    }
+
+    fn apply(
+        transactions: &[APITransaction],
+    ) -> (
+        Vec<CurrentTokenTransferOffer>,
+        Vec<CurrentTokenTransferOffer>,
+    ) {
+        let mut book = TokenTransferOfferBook::default();
+        for txn in transactions {
+            book.apply_transaction(txn, &TokenEvents::from_transaction(txn).unwrap());
+        }
+        book.into_rows()
+    }
+
+    #[test]
+    fn test_offers_add_up_until_claimed() {
+        let (offers, additions) = apply(&[
+            offer_txn(10, "TokenOfferEvent", "0xb0b", 1),
+            offer_txn(11, "TokenOfferEvent", "0xb0b", 2),
+            offer_txn(12, "TokenOfferEvent", "0xca201", 1),
+        ]);
+        assert!(offers.is_empty());
+        let pending = additions
+            .iter()
+            .map(|offer| {
+                (
+                    offer.to_address.clone(),
+                    offer.amount.clone(),
+                    offer.status.as_str(),
+                )
+            })
+            .collect::<HashSet<_>>();
+        assert_eq!(
+            pending,
+            HashSet::from([
+                (
+                    standardize_address("0xb0b"),
+                    BigDecimal::from(3),
+                    TRANSFER_OFFER_STATUS_PENDING
+                ),
+                (
+                    standardize_address("0xca201"),
+                    BigDecimal::from(1),
+                    TRANSFER_OFFER_STATUS_PENDING
+                ),
+            ])
+        );
+        assert!(additions
+            .iter()
+            .all(|offer| offer.from_address == standardize_address("0xa11ce")));
+
+        let (offers, additions) = apply(&[
+            offer_txn(10, "TokenOfferEvent", "0xb0b", 3),
+            offer_txn(11, "TokenClaimEvent", "0xb0b", 3),
+        ]);
+        assert!(additions.is_empty());
+        assert_eq!(offers.len(), 1);
+        assert_eq!(offers[0].status, TRANSFER_OFFER_STATUS_CLAIMED);
+        assert_eq!(offers[0].amount, BigDecimal::from(3));
+        assert_eq!(offers[0].last_transaction_version, 11);
+    }
+
+    #[test]
+    fn test_offer_after_cancel_replaces_stored_offer() {
+        let (offers, additions) = apply(&[
+            offer_txn(10, "TokenCancelOfferEvent", "0xb0b", 2),
+            offer_txn(11, "TokenOfferEvent", "0xb0b", 1),
+        ]);
+        // The stored offer was cancelled, so only the new one is pending
+        assert!(additions.is_empty());
+        assert_eq!(offers.len(), 1);
+        assert_eq!(offers[0].status, TRANSFER_OFFER_STATUS_PENDING);
+        assert_eq!(offers[0].amount, BigDecimal::from(1));
+    }
+}
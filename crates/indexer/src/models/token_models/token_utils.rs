@@ -4,15 +4,193 @@
 // This is required because a diesel macro makes clippy sad
 #![allow(clippy::extra_unused_lifetimes)]
 
-use crate::util::{hash_str, truncate_str};
-use anyhow::{Context, Result};
-use aptos_api_types::deserialize_from_string;
-use bigdecimal::BigDecimal;
+use super::{
+    marketplace_event_mappings::MarketplaceEventMappings,
+    marketplaces::{
+        bluemove::{
+            BlueBidEventType, BlueBuyEventType, BlueChangePriceEventType, BlueClaimCoinsEventType,
+            BlueClaimTokenEventType, BlueDelistEventType, BlueListEventType,
+            BlueMoveAuctionEventType,
+        },
+        souffl3::{
+            Souffl3BuyTokenEventType, Souffl3CancelListTokenEventType, Souffl3ListTokenEventType,
+            Souffl3SweepBuyEventType, Souffl3TokenListEventType, Souffl3TokenSwapEventType,
+            Souffl3V2BuyTokenEventType, Souffl3V2CancelListTokenEventType,
+            Souffl3V2ListTokenEventType,
+        },
+        topaz::{
+            TopazBidEventType, TopazBuyAllEventType, TopazBuyEventType, TopazCancelBidEventType,
+            TopazCancelCollectionBidEventType, TopazClaimEventType, TopazCollectionBidEventType,
+            TopazDelistEventType, TopazListEventType, TopazSellEventType, TopazSendEventType,
+        },
+        ParsedMarketplaceEvent,
+    },
+};
+use crate::util::{deserialize_address, hash_str, standardize_address, truncate_str};
+use anyhow::{bail, ensure, Context, Result};
+use aptos_api_types::{deserialize_from_string, Event as APIEvent, Transaction as APITransaction};
+use aptos_config::config::{StringLimitsConfig, ValueLimitsConfig};
+use bigdecimal::{BigDecimal, Zero};
+use once_cell::sync::{Lazy, OnceCell};
 use serde::{Deserialize, Serialize};
-use std::fmt::{self, Formatter};
+use std::{
+    fmt::{self, Formatter},
+    str::FromStr,
+};
+
+pub const DEFAULT_NAME_LENGTH: usize = 128;
+pub const DEFAULT_URI_LENGTH: usize = 512;
+/// Both are indexed, and btree entries are limited to about 2.7kB
+pub const MAX_NAME_LENGTH: usize = 512;
+pub const MAX_URI_LENGTH: usize = 2048;
+
+static STRING_LIMITS: OnceCell<StringLimits> = OnceCell::new();
+/// Way past any real price or supply, u128::MAX is about 3.4 * 10^38
+pub const DEFAULT_MAX_VALUE: &str = "1000000000000000000000000000000";
+
+static VALUE_LIMITS: OnceCell<ValueLimits> = OnceCell::new();
+static DEFAULT_VALUE_LIMITS: Lazy<ValueLimits> = Lazy::new(ValueLimits::default);
+
+/// Characters names and uris are truncated to before they're stored. Hashes are always over the
+/// whole strings, so they still join with ones computed off chain.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct StringLimits {
+    pub name_length: usize,
+    pub uri_length: usize,
+}
+
+impl Default for StringLimits {
+    fn default() -> Self {
+        Self {
+            name_length: DEFAULT_NAME_LENGTH,
+            uri_length: DEFAULT_URI_LENGTH,
+        }
+    }
+}
+
+impl StringLimits {
+    pub fn from_config(config: Option<&StringLimitsConfig>) -> Result<Self> {
+        let config = match config {
+            Some(config) => config,
+            None => return Ok(Self::default()),
+        };
+        let name_length = config.name_length.unwrap_or(DEFAULT_NAME_LENGTH as u64);
+        let uri_length = config.uri_length.unwrap_or(DEFAULT_URI_LENGTH as u64);
+        ensure!(name_length > 0, "name_length must be greater than 0");
+        ensure!(
+            name_length <= MAX_NAME_LENGTH as u64,
+            "name_length can't be more than {}",
+            MAX_NAME_LENGTH
+        );
+        ensure!(uri_length > 0, "uri_length must be greater than 0");
+        ensure!(
+            uri_length <= MAX_URI_LENGTH as u64,
+            "uri_length can't be more than {}",
+            MAX_URI_LENGTH
+        );
+        Ok(Self {
+            name_length: name_length as usize,
+            uri_length: uri_length as usize,
+        })
+    }
+
+    /// Makes these the limits of the process. They can't change once set, as rows written with
+    /// different limits wouldn't match.
+    pub fn install(self) -> Result<()> {
+        let installed = STRING_LIMITS.get_or_init(|| self);
+        ensure!(
+            *installed == self,
+            "string limits are already set to {:?}",
+            installed
+        );
+        Ok(())
+    }
+
+    /// The installed limits, the defaults if none were
+    pub fn current() -> Self {
+        STRING_LIMITS.get().copied().unwrap_or_default()
+    }
+
+    pub fn truncate_name(&self, name: &str) -> String {
+        truncate_str(name, self.name_length)
+    }
+
+    pub fn truncate_uri(&self, uri: &str) -> String {
+        truncate_str(uri, self.uri_length)
+    }
+
+    pub fn is_name_truncated(&self, name: &str) -> bool {
+        name.chars().nth(self.name_length).is_some()
+    }
+
+    pub fn is_uri_truncated(&self, uri: &str) -> bool {
+        uri.chars().nth(self.uri_length).is_some()
+    }
+}
+
+/// Bounds past which a token event's price or amount is taken for a malicious or broken
+/// contract's. Such an event's rows are flagged with suspect_value and left out of aggregates, see
+/// TokenEventEffects::suspect_value.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct ValueLimits {
+    pub max_price: BigDecimal,
+    pub max_amount: BigDecimal,
+}
+
+impl Default for ValueLimits {
+    fn default() -> Self {
+        let max_value = BigDecimal::from_str(DEFAULT_MAX_VALUE).unwrap();
+        Self {
+            max_price: max_value.clone(),
+            max_amount: max_value,
+        }
+    }
+}
+
+impl ValueLimits {
+    pub fn from_config(config: Option<&ValueLimitsConfig>) -> Result<Self> {
+        let config = match config {
+            Some(config) => config,
+            None => return Ok(Self::default()),
+        };
+        let parse = |name: &str, value: &Option<String>| -> Result<BigDecimal> {
+            let value = BigDecimal::from_str(value.as_deref().unwrap_or(DEFAULT_MAX_VALUE))
+                .with_context(|| format!("{} must be a decimal number", name))?;
+            ensure!(value > BigDecimal::zero(), "{} must be greater than 0", name);
+            Ok(value)
+        };
+        Ok(Self {
+            max_price: parse("max_price", &config.max_price)?,
+            max_amount: parse("max_amount", &config.max_amount)?,
+        })
+    }
+
+    /// Makes these the limits of the process. They can't change once set, so a batch doesn't
+    /// count an event another batch left out.
+    pub fn install(self) -> Result<()> {
+        let installed = VALUE_LIMITS.get_or_init(|| self.clone());
+        ensure!(
+            *installed == self,
+            "value limits are already set to {:?}",
+            installed
+        );
+        Ok(())
+    }
+
+    /// The installed limits, the defaults if none were
+    pub fn current() -> &'static Self {
+        VALUE_LIMITS.get().unwrap_or(&DEFAULT_VALUE_LIMITS)
+    }
+
+    pub fn is_suspect_price(&self, price: &BigDecimal) -> bool {
+        price < &BigDecimal::zero() || price > &self.max_price
+    }
+
+    pub fn is_suspect_amount(&self, amount: &BigDecimal) -> bool {
+        amount < &BigDecimal::zero() || amount > &self.max_amount
+    }
+}
 
-const NAME_LENGTH: usize = 128;
-const URI_LENGTH: usize = 512;
 /**
  * This file defines deserialized move types as defined in our 0x3 contracts.
  */
@@ -24,6 +202,7 @@ pub struct Table {
 
 #[derive(Serialize, Deserialize, Debug, Clone)]
 pub struct TokenDataIdType {
+    #[serde(deserialize_with = "deserialize_address")]
     pub creator: String,
     pub collection: String,
     pub name: String,
@@ -35,11 +214,11 @@ impl TokenDataIdType {
     }
 
     pub fn get_collection_trunc(&self) -> String {
-        truncate_str(&self.collection, NAME_LENGTH)
+        StringLimits::current().truncate_name(&self.collection)
     }
 
     pub fn get_name_trunc(&self) -> String {
-        truncate_str(&self.name, NAME_LENGTH)
+        StringLimits::current().truncate_name(&self.name)
     }
 
     pub fn get_collection_data_id_hash(&self) -> String {
@@ -59,20 +238,25 @@ impl fmt::Display for TokenDataIdType {
 
 #[derive(Serialize, Deserialize, Debug, Clone)]
 pub struct CollectionDataIdType {
+    #[serde(deserialize_with = "deserialize_address")]
     pub creator: String,
     pub name: String,
 }
 
 impl CollectionDataIdType {
     pub fn new(creator: String, name: String) -> Self {
-        Self { creator, name }
+        Self {
+            creator: standardize_address(&creator),
+            name,
+        }
     }
+
     pub fn to_hash(&self) -> String {
         hash_str(&self.to_string())
     }
 
     pub fn get_name_trunc(&self) -> String {
-        truncate_str(&self.name, NAME_LENGTH)
+        StringLimits::current().truncate_name(&self.name)
     }
 }
 
@@ -113,12 +297,16 @@ pub struct TokenDataType {
 }
 
 impl TokenDataType {
+    pub fn get_uri(&self) -> &str {
+        &self.uri
+    }
+
     pub fn get_uri_trunc(&self) -> String {
-        truncate_str(&self.uri, URI_LENGTH)
+        StringLimits::current().truncate_uri(&self.uri)
     }
 
     pub fn get_name_trunc(&self) -> String {
-        truncate_str(&self.name, NAME_LENGTH)
+        StringLimits::current().truncate_name(&self.name)
     }
 }
 
@@ -133,6 +321,7 @@ pub struct TokenDataMutabilityConfigType {
 
 #[derive(Serialize, Deserialize, Debug, Clone)]
 pub struct RoyaltyType {
+    #[serde(deserialize_with = "deserialize_address")]
     pub payee_address: String,
     #[serde(deserialize_with = "deserialize_from_string")]
     pub royalty_points_denominator: BigDecimal,
@@ -166,17 +355,22 @@ impl CollectionDataType {
         &self.name
     }
 
+    pub fn get_uri(&self) -> &str {
+        &self.uri
+    }
+
     pub fn get_uri_trunc(&self) -> String {
-        truncate_str(&self.uri, URI_LENGTH)
+        StringLimits::current().truncate_uri(&self.uri)
     }
 
     pub fn get_name_trunc(&self) -> String {
-        truncate_str(&self.name, NAME_LENGTH)
+        StringLimits::current().truncate_name(&self.name)
     }
 }
 
 #[derive(Serialize, Deserialize, Debug, Clone)]
 pub struct TokenOfferIdType {
+    #[serde(deserialize_with = "deserialize_address")]
     pub to_addr: String,
     pub token_id: TokenIdType,
 }
@@ -250,12 +444,21 @@ pub struct BurnTokenEventType {
 pub struct MutateTokenPropertyMapEventType {
     pub old_id: TokenIdType,
     pub new_id: TokenIdType,
+    /// The mutated properties, with bcs encoded values. Missing from events emitted before the
+    /// framework added them.
+    #[serde(default)]
+    pub keys: Vec<String>,
+    #[serde(default)]
+    pub values: Vec<String>,
+    #[serde(default)]
+    pub types: Vec<String>,
 }
 
 #[derive(Serialize, Deserialize, Debug, Clone)]
 pub struct OfferTokenEventType {
     #[serde(deserialize_with = "deserialize_from_string")]
     pub amount: BigDecimal,
+    #[serde(deserialize_with = "deserialize_address")]
     pub to_address: String,
     pub token_id: TokenIdType,
 }
@@ -264,6 +467,7 @@ pub struct OfferTokenEventType {
 pub struct CancelTokenOfferEventType {
     #[serde(deserialize_with = "deserialize_from_string")]
     pub amount: BigDecimal,
+    #[serde(deserialize_with = "deserialize_address")]
     pub to_address: String,
     pub token_id: TokenIdType,
 }
@@ -271,286 +475,11 @@ pub struct CancelTokenOfferEventType {
 pub struct ClaimTokenEventType {
     #[serde(deserialize_with = "deserialize_from_string")]
     pub amount: BigDecimal,
+    #[serde(deserialize_with = "deserialize_address")]
     pub to_address: String,
     pub token_id: TokenIdType,
 }
 
-#[derive(Serialize, Deserialize, Debug, Clone)]
-pub struct BlueMoveAuctionEventType {
-    pub id: TokenIdType,
-    #[serde(deserialize_with = "deserialize_from_string")]
-    pub min_selling_price: BigDecimal,
-    #[serde(deserialize_with = "deserialize_from_string")]
-    pub duration: BigDecimal,
-    #[serde(deserialize_with = "deserialize_from_string")]
-    pub start_time: BigDecimal,
-    pub owner_address: String,
-}
-
-#[derive(Serialize, Deserialize, Debug, Clone)]
-pub struct BlueBidEventType {
-    pub id: TokenIdType,
-    #[serde(deserialize_with = "deserialize_from_string")]
-    pub bid: BigDecimal,
-    pub bider_address: String,
-}
-
-#[derive(Serialize, Deserialize, Debug, Clone)]
-pub struct BlueBuyEventType {
-    pub id: TokenIdType,
-    pub buyer_address: String,
-}
-
-#[derive(Serialize, Deserialize, Debug, Clone)]
-pub struct BlueChangePriceEventType {
-    pub id: TokenIdType,
-    #[serde(deserialize_with = "deserialize_from_string")]
-    pub amount: BigDecimal,
-    pub seller_address: String,
-}
-
-#[derive(Serialize, Deserialize, Debug, Clone)]
-pub struct BlueClaimCoinsEventType {
-    pub id: TokenIdType,
-    pub owner_token: String,
-}
-
-#[derive(Serialize, Deserialize, Debug, Clone)]
-pub struct BlueClaimTokenEventType {
-    pub id: TokenIdType,
-    pub bider_address: String,
-}
-
-#[derive(Serialize, Deserialize, Debug, Clone)]
-pub struct BlueDelistEventType {
-    pub id: TokenIdType,
-    pub seller_address: String,
-}
-
-#[derive(Serialize, Deserialize, Debug, Clone)]
-pub struct BlueListEventType {
-    pub id: TokenIdType,
-    #[serde(deserialize_with = "deserialize_from_string")]
-    pub amount: BigDecimal,
-    pub seller_address: String,
-    pub royalty_payee: String,
-    #[serde(deserialize_with = "deserialize_from_string")]
-    pub royalty_numerator: BigDecimal,
-    #[serde(deserialize_with = "deserialize_from_string")]
-    pub royalty_denominator: BigDecimal,
-}
- 
-#[derive(Serialize, Deserialize, Debug, Clone)]
-pub struct TopazBidEventType {
-    #[serde(deserialize_with = "deserialize_from_string")]
-    pub timestamp: BigDecimal,
-    #[serde(deserialize_with = "deserialize_from_string")]
-    pub bid_id: BigDecimal,
-    pub token_id: TokenIdType,
-    #[serde(deserialize_with = "deserialize_from_string")]
-    pub deadline: BigDecimal,
-    #[serde(deserialize_with = "deserialize_from_string")]
-    pub price: BigDecimal,
-    pub coin_type: TypeInfo,
-    #[serde(deserialize_with = "deserialize_from_string")]
-    pub amount: BigDecimal,
-    pub buyer: String,
-}
-
-#[derive(Serialize, Deserialize, Debug, Clone)]
-pub struct TopazBuyEventType {
-    #[serde(deserialize_with = "deserialize_from_string")]
-    pub timestamp: BigDecimal,
-    #[serde(deserialize_with = "deserialize_from_string")]
-    pub listing_id: BigDecimal,
-    pub token_id: TokenIdType,
-    #[serde(deserialize_with = "deserialize_from_string")]
-    pub price: BigDecimal,
-    #[serde(deserialize_with = "deserialize_from_string")]
-    pub amount: BigDecimal,
-    pub seller: String,
-    pub buyer: String,
-}
-
-#[derive(Serialize, Deserialize, Debug, Clone)]
-pub struct TopazCancelBidEventType {
-    #[serde(deserialize_with = "deserialize_from_string")]
-    pub timestamp: BigDecimal,
-    #[serde(deserialize_with = "deserialize_from_string")]
-    pub bid_id: BigDecimal,
-    pub token_id: TokenIdType,
-    #[serde(deserialize_with = "deserialize_from_string")]
-    pub deadline: BigDecimal,
-    #[serde(deserialize_with = "deserialize_from_string")]
-    pub price: BigDecimal,
-    pub coin_type: TypeInfo,
-    #[serde(deserialize_with = "deserialize_from_string")]
-    pub amount: BigDecimal,
-    pub buyer: String,
-}
-
-#[derive(Serialize, Deserialize, Debug, Clone)]
-pub struct TopazCancelCollectionBidEventType {
-    #[serde(deserialize_with = "deserialize_from_string")]
-    pub timestamp: BigDecimal,
-    #[serde(deserialize_with = "deserialize_from_string")]
-    pub bid_id: BigDecimal,
-    pub creator: String,
-    pub collection_name: String,
-    pub buyer: String,
-    #[serde(deserialize_with = "deserialize_from_string")]
-    pub price: BigDecimal,
-    pub coin_type: TypeInfo,
-    #[serde(deserialize_with = "deserialize_from_string")]
-    pub amount: BigDecimal,
-    #[serde(deserialize_with = "deserialize_from_string")]
-    pub deadline: BigDecimal,
-}
-
-#[derive(Serialize, Deserialize, Debug, Clone)]
-pub struct TopazClaimEventType {
-    #[serde(deserialize_with = "deserialize_from_string")]
-    pub timestamp: BigDecimal,
-    pub token_id: TokenIdType,
-    pub receiver: String,
-}
-
-#[derive(Serialize, Deserialize, Debug, Clone)]
-pub struct TopazCollectionBidEventType {
-    #[serde(deserialize_with = "deserialize_from_string")]
-    pub timestamp: BigDecimal,
-    #[serde(deserialize_with = "deserialize_from_string")]
-    pub bid_id: BigDecimal,
-    pub creator: String,
-    pub collection_name: String,
-    pub buyer: String,
-    #[serde(deserialize_with = "deserialize_from_string")]
-    pub price: BigDecimal,
-    pub coin_type: TypeInfo,
-    #[serde(deserialize_with = "deserialize_from_string")]
-    pub amount: BigDecimal,
-    #[serde(deserialize_with = "deserialize_from_string")]
-    pub deadline: BigDecimal,
-}
-
-#[derive(Serialize, Deserialize, Debug, Clone)]
-pub struct TopazDelistEventType {
-    #[serde(deserialize_with = "deserialize_from_string")]
-    pub timestamp: BigDecimal,
-    #[serde(deserialize_with = "deserialize_from_string")]
-    pub listing_id: BigDecimal,
-    pub token_id: TokenIdType,
-    #[serde(deserialize_with = "deserialize_from_string")]
-    pub price: BigDecimal,
-    #[serde(deserialize_with = "deserialize_from_string")]
-    pub amount: BigDecimal,
-    pub seller: String,
-}
-
-#[derive(Serialize, Deserialize, Debug, Clone)]
-pub struct TopazListEventType {
-    #[serde(deserialize_with = "deserialize_from_string")]
-    pub timestamp: BigDecimal,
-    #[serde(deserialize_with = "deserialize_from_string")]
-    pub listing_id: BigDecimal,
-    pub token_id: TokenIdType,
-    #[serde(deserialize_with = "deserialize_from_string")]
-    pub price: BigDecimal,
-    #[serde(deserialize_with = "deserialize_from_string")]
-    pub amount: BigDecimal,
-    pub seller: String,
-}
-
-#[derive(Serialize, Deserialize, Debug, Clone)]
-pub struct TopazSellEventType {
-    #[serde(deserialize_with = "deserialize_from_string")]
-    pub timestamp: BigDecimal,
-    #[serde(deserialize_with = "deserialize_from_string")]
-    pub bid_id: BigDecimal,
-    pub token_id: TokenIdType,
-    #[serde(deserialize_with = "deserialize_from_string")]
-    pub deadline: BigDecimal,
-    #[serde(deserialize_with = "deserialize_from_string")]
-    pub price: BigDecimal,
-    pub coin_type: TypeInfo,
-    #[serde(deserialize_with = "deserialize_from_string")]
-    pub amount: BigDecimal,
-    pub buyer: String,
-    pub seller: String,
-}
-
-#[derive(Serialize, Deserialize, Debug, Clone)]
-pub struct TopazSendEventType {
-    #[serde(deserialize_with = "deserialize_from_string")]
-    pub timestamp: BigDecimal,
-    pub token_id: TokenIdType,
-    #[serde(deserialize_with = "deserialize_from_string")]
-    pub amount: BigDecimal,
-    pub sender: String,
-    pub receiver: String,
-}
-
-#[derive(Serialize, Deserialize, Debug, Clone)]
-pub struct Souffl3BuyTokenEventType {
-    pub id: Souffl3MarketIdType,
-    pub token_id: TokenIdType,
-    #[serde(deserialize_with = "deserialize_from_string")]
-    pub token_amount: BigDecimal,
-    pub buyer: String,
-    pub token_owner: String,
-    #[serde(deserialize_with = "deserialize_from_string")]
-    pub coin_per_token: BigDecimal,
-}
-
-#[derive(Serialize, Deserialize, Debug, Clone)]
-pub struct Souffl3CancelListTokenEventType {
-    pub id: Souffl3MarketIdType,
-    pub token_id: TokenIdType,
-    #[serde(deserialize_with = "deserialize_from_string")]
-    pub token_amount: BigDecimal,
-}
-
-#[derive(Serialize, Deserialize, Debug, Clone)]
-pub struct Souffl3ListTokenEventType {
-    pub id: Souffl3MarketIdType,
-    pub token_id: TokenIdType,
-    pub token_owner: String,
-    #[serde(deserialize_with = "deserialize_from_string")]
-    pub token_amount: BigDecimal,
-    #[serde(deserialize_with = "deserialize_from_string")]
-    pub coin_per_token: BigDecimal,
-}
-
-#[derive(Serialize, Deserialize, Debug, Clone)]
-pub struct Souffl3TokenSwapEventType {
-    pub token_id: TokenIdType,
-    pub token_buyer: String,
-    #[serde(deserialize_with = "deserialize_from_string")]
-    pub token_amount: BigDecimal,
-    #[serde(deserialize_with = "deserialize_from_string")]
-    pub coin_amount: BigDecimal,
-    pub coin_type_info: TypeInfo,
-}
-
-#[derive(Serialize, Deserialize, Debug, Clone)]
-pub struct Souffl3TokenListEventType {
-    pub token_id: TokenIdType,
-    #[serde(deserialize_with = "deserialize_from_string")]
-    pub amount: BigDecimal,
-    #[serde(deserialize_with = "deserialize_from_string")]
-    pub min_price: BigDecimal,
-    #[serde(deserialize_with = "deserialize_from_string")]
-    pub locked_until_secs: BigDecimal,
-    pub coin_type_info: TypeInfo,
-}
-
-#[derive(Serialize, Deserialize, Debug, Clone)]
-pub struct Souffl3MarketIdType {
-    pub market_address: String,
-    pub name: String,
-}
-
-
 #[derive(Serialize, Deserialize, Debug, Clone)]
 pub struct TypeInfo {
     pub account_address: String,
@@ -570,6 +499,25 @@ impl fmt::Display for TypeInfo {
     }
 }
 
+impl TypeInfo {
+    /// Same as the Display impl, but with the hex encoded module and struct names decoded, ex:
+    /// 0x1::aptos_coin::AptosCoin. Names that aren't hex encoded utf8 are kept as is.
+    pub fn to_decoded_string(&self) -> String {
+        let decode = |name: &str| {
+            name.strip_prefix("0x")
+                .and_then(|hex_name| hex::decode(hex_name).ok())
+                .and_then(|bytes| String::from_utf8(bytes).ok())
+                .unwrap_or_else(|| name.to_string())
+        };
+        format!(
+            "{}::{}::{}",
+            self.account_address,
+            decode(&self.module_name),
+            decode(&self.struct_name)
+        )
+    }
+}
+
 #[derive(Serialize, Deserialize, Debug, Clone)]
 pub enum TokenWriteSet {
     TokenDataId(TokenDataIdType),
@@ -587,25 +535,27 @@ impl TokenWriteSet {
         txn_version: i64,
     ) -> Result<Option<TokenWriteSet>> {
         match data_type {
-            "0x3::token::TokenDataId" => serde_json::from_value(data.clone())
+            "0x3::token::TokenDataId" => Deserialize::deserialize(data)
                 .map(|inner| Some(TokenWriteSet::TokenDataId(inner))),
-            "0x3::token::TokenId" => serde_json::from_value(data.clone())
+            "0x3::token::TokenId" => Deserialize::deserialize(data)
                 .map(|inner| Some(TokenWriteSet::TokenId(inner))),
-            "0x3::token::TokenData" => serde_json::from_value(data.clone())
+            "0x3::token::TokenData" => Deserialize::deserialize(data)
                 .map(|inner| Some(TokenWriteSet::TokenData(inner))),
             "0x3::token::Token" => {
-                serde_json::from_value(data.clone()).map(|inner| Some(TokenWriteSet::Token(inner)))
+                Deserialize::deserialize(data).map(|inner| Some(TokenWriteSet::Token(inner)))
             }
-            "0x3::token::CollectionData" => serde_json::from_value(data.clone())
+            "0x3::token::CollectionData" => Deserialize::deserialize(data)
                 .map(|inner| Some(TokenWriteSet::CollectionData(inner))),
-            "0x3::token_transfers::TokenOfferId" => serde_json::from_value(data.clone())
+            "0x3::token_transfers::TokenOfferId" => Deserialize::deserialize(data)
                 .map(|inner| Some(TokenWriteSet::TokenOfferId(inner))),
             _ => Ok(None),
         }
-        .context(format!(
-            "version {} failed! failed to parse type {}, data {:?}",
-            txn_version, data_type, data
-        ))
+        .with_context(|| {
+            format!(
+                "version {} failed! failed to parse type {}, data {:?}",
+                txn_version, data_type, data
+            )
+        })
     }
 }
 
@@ -629,6 +579,7 @@ pub enum TokenEvent {
     BlueListEvent(BlueListEventType),
     TopazBidEvent(TopazBidEventType),
     TopazBuyEvent(TopazBuyEventType),
+    TopazBuyAllEvent(TopazBuyAllEventType),
     TopazCancelBidEvent(TopazCancelBidEventType),
     TopazCancelCollectionBidEvent(TopazCancelCollectionBidEventType),
     TopazClaimEvent(TopazClaimEventType),
@@ -641,132 +592,199 @@ pub enum TokenEvent {
     Souffl3CancelListTokenEvent(Souffl3CancelListTokenEventType),
     Souffl3ListTokenEvent(Souffl3ListTokenEventType),
     Souffl3TokenListEvent(Souffl3TokenListEventType),
-    Souffl3TokenSwapEvent(Souffl3TokenSwapEventType)
+    Souffl3TokenSwapEvent(Souffl3TokenSwapEventType),
+    Souffl3V2BuyTokenEvent(Souffl3V2BuyTokenEventType),
+    Souffl3V2CancelListTokenEvent(Souffl3V2CancelListTokenEventType),
+    Souffl3V2ListTokenEvent(Souffl3V2ListTokenEventType),
+    Souffl3SweepBuyEvent(Souffl3SweepBuyEventType),
+}
+
+/// Typed parsers for marketplace events that are registered by type in the indexer config, see
+/// `MarketplaceTypedEventMapping`, rather than matched on a fixed type in TokenEvent::from_event
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum MarketplaceEventParser {
+    Souffl3V2BuyToken,
+    Souffl3V2CancelListToken,
+    Souffl3V2ListToken,
+    Souffl3SweepBuy,
+}
+
+impl FromStr for MarketplaceEventParser {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        Ok(match s {
+            "souffl3_v2_buy_token" => Self::Souffl3V2BuyToken,
+            "souffl3_v2_cancel_list_token" => Self::Souffl3V2CancelListToken,
+            "souffl3_v2_list_token" => Self::Souffl3V2ListToken,
+            "souffl3_sweep_buy" => Self::Souffl3SweepBuy,
+            _ => bail!(
+                "unknown parser '{}', expected one of souffl3_v2_buy_token, \
+                souffl3_v2_cancel_list_token, souffl3_v2_list_token, souffl3_sweep_buy",
+                s
+            ),
+        })
+    }
 }
 
 impl TokenEvent {
+    /// Parses an event of a type registered with a typed parser in the config
+    pub fn from_parser(
+        parser: MarketplaceEventParser,
+        data_type: &str,
+        data: &serde_json::Value,
+        txn_version: i64,
+    ) -> Result<TokenEvent> {
+        match parser {
+            MarketplaceEventParser::Souffl3V2BuyToken => {
+                Deserialize::deserialize(data).map(TokenEvent::Souffl3V2BuyTokenEvent)
+            },
+            MarketplaceEventParser::Souffl3V2CancelListToken => {
+                Deserialize::deserialize(data).map(TokenEvent::Souffl3V2CancelListTokenEvent)
+            },
+            MarketplaceEventParser::Souffl3V2ListToken => {
+                Deserialize::deserialize(data).map(TokenEvent::Souffl3V2ListTokenEvent)
+            },
+            MarketplaceEventParser::Souffl3SweepBuy => {
+                Deserialize::deserialize(data).and_then(|inner: Souffl3SweepBuyEventType| {
+                    if inner.sellers.len() != inner.token_ids.len()
+                        || inner.coin_per_tokens.len() != inner.token_ids.len()
+                    {
+                        return Err(serde::de::Error::custom(
+                            "token_ids, sellers and coin_per_tokens have different lengths",
+                        ));
+                    }
+                    Ok(TokenEvent::Souffl3SweepBuyEvent(inner))
+                })
+            },
+        }
+        .with_context(|| {
+            format!(
+                "version {} failed! failed to parse type {} with {:?}, data {:?}",
+                txn_version, data_type, parser, data
+            )
+        })
+    }
+
+    /// Number of tokens the event is about, each of which gets its own activity, sale and
+    /// volume row. Only sweeps and batch buys are about more than one.
+    pub fn token_count(&self) -> usize {
+        match self {
+            TokenEvent::TopazBuyAllEvent(inner) => inner.token_ids.len(),
+            TokenEvent::Souffl3SweepBuyEvent(inner) => inner.token_ids.len(),
+            _ => 1,
+        }
+    }
+
+    /// Parses the token framework's events. Marketplace events are parsed by their marketplace's
+    /// adapter, see MarketplaceEventMappings::marketplace_event.
     pub fn from_event(
         data_type: &str,
         data: &serde_json::Value,
         txn_version: i64,
     ) -> Result<Option<TokenEvent>> {
         match data_type {
-            "0x3::token::MintTokenEvent" => serde_json::from_value(data.clone())
+            "0x3::token::MintTokenEvent" => Deserialize::deserialize(data)
                 .map(|inner| Some(TokenEvent::MintTokenEvent(inner))),
-            "0x3::token::BurnTokenEvent" => serde_json::from_value(data.clone())
+            "0x3::token::BurnTokenEvent" => Deserialize::deserialize(data)
                 .map(|inner| Some(TokenEvent::BurnTokenEvent(inner))),
-            "0x3::token::MutateTokenPropertyMapEvent" => serde_json::from_value(data.clone())
+            "0x3::token::MutateTokenPropertyMapEvent" => Deserialize::deserialize(data)
                 .map(|inner| Some(TokenEvent::MutateTokenPropertyMapEvent(inner))),
-            "0x3::token::WithdrawEvent" => serde_json::from_value(data.clone())
+            "0x3::token::WithdrawEvent" => Deserialize::deserialize(data)
                 .map(|inner| Some(TokenEvent::WithdrawTokenEvent(inner))),
-            "0x3::token::DepositEvent" => serde_json::from_value(data.clone())
+            "0x3::token::DepositEvent" => Deserialize::deserialize(data)
                 .map(|inner| Some(TokenEvent::DepositTokenEvent(inner))),
-            "0x3::token_transfers::TokenOfferEvent" => serde_json::from_value(data.clone())
+            "0x3::token_transfers::TokenOfferEvent" => Deserialize::deserialize(data)
                 .map(|inner| Some(TokenEvent::OfferTokenEvent(inner))),
-            "0x3::token_transfers::TokenCancelOfferEvent" => serde_json::from_value(data.clone())
+            "0x3::token_transfers::TokenCancelOfferEvent" => Deserialize::deserialize(data)
                 .map(|inner| Some(TokenEvent::CancelTokenOfferEvent(inner))),
-            "0x3::token_transfers::TokenClaimEvent" => serde_json::from_value(data.clone())
-                .map(|inner| Some(TokenEvent::ClaimTokenEvent(inner))),
-            "0x3::token_transfers::TokenClaimEvent" => serde_json::from_value(data.clone())
+            "0x3::token_transfers::TokenClaimEvent" => Deserialize::deserialize(data)
                 .map(|inner| Some(TokenEvent::ClaimTokenEvent(inner))),
-            "0xd1fd99c1944b84d1670a2536417e997864ad12303d19eac725891691b04d614e::marketplaceV2::AuctionEvent" => {
-                serde_json::from_value(data.clone())
-                    .map(|inner| Some(TokenEvent::BlueMoveAuctionEvent(inner)))
-            },
-            "0xd1fd99c1944b84d1670a2536417e997864ad12303d19eac725891691b04d614e::marketplaceV2::BidEvent" => {
-                serde_json::from_value(data.clone())
-                    .map(|inner| Some(TokenEvent::BlueBidEvent(inner)))
-            },
-            "0xd1fd99c1944b84d1670a2536417e997864ad12303d19eac725891691b04d614e::marketplaceV2::BuyEvent" => {
-                serde_json::from_value(data.clone())
-                    .map(|inner| Some(TokenEvent::BlueBuyEvent(inner)))
-            },
-            "0xd1fd99c1944b84d1670a2536417e997864ad12303d19eac725891691b04d614e::marketplaceV2::ChangePriceEvent" => {
-                serde_json::from_value(data.clone())
-                    .map(|inner| Some(TokenEvent::BlueChangePriceEvent(inner)))
-            },
-            "0xd1fd99c1944b84d1670a2536417e997864ad12303d19eac725891691b04d614e::marketplaceV2::ClaimCoinsEvent" => {
-                serde_json::from_value(data.clone())
-                    .map(|inner| Some(TokenEvent::BlueClaimCoinsEvent(inner)))
-            },
-            "0xd1fd99c1944b84d1670a2536417e997864ad12303d19eac725891691b04d614e::marketplaceV2::ClaimTokenEvent" => {
-                serde_json::from_value(data.clone())
-                    .map(|inner| Some(TokenEvent::BlueClaimTokenEvent(inner)))
-            },
-            "0xd1fd99c1944b84d1670a2536417e997864ad12303d19eac725891691b04d614e::marketplaceV2::DelistEvent" => {
-                serde_json::from_value(data.clone())
-                    .map(|inner| Some(TokenEvent::BlueDelistEvent(inner)))
-            },
-            "0xd1fd99c1944b84d1670a2536417e997864ad12303d19eac725891691b04d614e::marketplaceV2::ListEvent" => {
-                serde_json::from_value(data.clone())
-                    .map(|inner| Some(TokenEvent::BlueListEvent(inner)))
-            },
-            "0x2c7bccf7b31baf770fdbcc768d9e9cb3d87805e255355df5db32ac9a669010a2::events::BidEvent" => {
-                serde_json::from_value(data.clone())
-                    .map(|inner| Some(TokenEvent::TopazBidEvent(inner)))
-            },
-            "0x2c7bccf7b31baf770fdbcc768d9e9cb3d87805e255355df5db32ac9a669010a2::events::BuyEvent" => {
-                serde_json::from_value(data.clone())
-                    .map(|inner| Some(TokenEvent::TopazBuyEvent(inner)))
-            },
-            "0x2c7bccf7b31baf770fdbcc768d9e9cb3d87805e255355df5db32ac9a669010a2::events::CancelBidEvent" => {
-                serde_json::from_value(data.clone())
-                    .map(|inner| Some(TokenEvent::TopazCancelBidEvent(inner)))
-            },
-            "0x2c7bccf7b31baf770fdbcc768d9e9cb3d87805e255355df5db32ac9a669010a2::events::CancelCollectionBidEvent" => {
-                serde_json::from_value(data.clone())
-                    .map(|inner| Some(TokenEvent::TopazCancelCollectionBidEvent(inner)))
-            },
-            "0x2c7bccf7b31baf770fdbcc768d9e9cb3d87805e255355df5db32ac9a669010a2::events::ClaimEvent" => {
-                serde_json::from_value(data.clone())
-                    .map(|inner| Some(TokenEvent::TopazClaimEvent(inner)))
-            },
-            "0x2c7bccf7b31baf770fdbcc768d9e9cb3d87805e255355df5db32ac9a669010a2::events::CollectionBidEvent" => {
-                serde_json::from_value(data.clone())
-                    .map(|inner| Some(TokenEvent::TopazCollectionBidEvent(inner)))
-            },
-            "0x2c7bccf7b31baf770fdbcc768d9e9cb3d87805e255355df5db32ac9a669010a2::events::DelistEvent" => {
-                serde_json::from_value(data.clone())
-                    .map(|inner| Some(TokenEvent::TopazDelistEvent(inner)))
-            },
-            "0x2c7bccf7b31baf770fdbcc768d9e9cb3d87805e255355df5db32ac9a669010a2::events::ListEvent" => {
-                serde_json::from_value(data.clone())
-                    .map(|inner| Some(TokenEvent::TopazListEvent(inner)))
-            },
-            "0x2c7bccf7b31baf770fdbcc768d9e9cb3d87805e255355df5db32ac9a669010a2::events::SellEvent" => {
-                serde_json::from_value(data.clone())
-                    .map(|inner| Some(TokenEvent::TopazSellEvent(inner)))
-            },
-            "0x2c7bccf7b31baf770fdbcc768d9e9cb3d87805e255355df5db32ac9a669010a2::events::SendEvent" => {
-                serde_json::from_value(data.clone())
-                    .map(|inner| Some(TokenEvent::TopazSendEvent(inner)))
-            },
-            "0xf6994988bd40261af9431cd6dd3fcf765569719e66322c7a05cc78a89cd366d4::FixedPriceMarket::BuyTokenEvent" => {
-                serde_json::from_value(data.clone())
-                    .map(|inner| Some(TokenEvent::Souffl3BuyTokenEvent(inner)))
-            },
-            "0xf6994988bd40261af9431cd6dd3fcf765569719e66322c7a05cc78a89cd366d4::FixedPriceMarket::CancelListTokenEvent" => {
-                serde_json::from_value(data.clone())
-                    .map(|inner| Some(TokenEvent::Souffl3CancelListTokenEvent(inner)))
-            },
-            "0xf6994988bd40261af9431cd6dd3fcf765569719e66322c7a05cc78a89cd366d4::FixedPriceMarket::ListTokenEvent" => {
-                serde_json::from_value(data.clone())
-                    .map(|inner| Some(TokenEvent::Souffl3ListTokenEvent(inner)))
-            },
-            "0xf6994988bd40261af9431cd6dd3fcf765569719e66322c7a05cc78a89cd366d4::token_coin_swap::TokenListingEvent" => {
-                serde_json::from_value(data.clone())
-                    .map(|inner| Some(TokenEvent::Souffl3TokenListEvent(inner)))
-            },
-            "0xf6994988bd40261af9431cd6dd3fcf765569719e66322c7a05cc78a89cd366d4::token_coin_swap::TokenSwapEvent" => {
-                serde_json::from_value(data.clone())
-                    .map(|inner| Some(TokenEvent::Souffl3TokenSwapEvent(inner)))
-            },
             _ => Ok(None),
         }
-        .context(format!(
-            "version {} failed! failed to parse type {}, data {:?}",
-            txn_version, data_type, data
-        ))
+        .with_context(|| {
+            format!(
+                "version {} failed! failed to parse type {}, data {:?}",
+                txn_version, data_type, data
+            )
+        })
+    }
+}
+
+/// A transaction's events parsed with TokenEvent::from_event and the marketplace adapters, once
+/// for every model built from the transaction rather than once per model. Indexed like the transaction's events, with None for
+/// the events that aren't token events.
+#[derive(Debug, Default)]
+pub struct TokenEvents {
+    events: Vec<Option<TokenEvent>>,
+    /// The tokens of each marketplace event, normalized by the adapter that parsed it
+    marketplace_tokens: Vec<Option<Vec<ParsedMarketplaceEvent>>>,
+}
+
+impl TokenEvents {
+    pub fn from_transaction(transaction: &APITransaction) -> Result<Self> {
+        Self::from_transaction_with_mappings(transaction, &MarketplaceEventMappings::default())
+    }
+
+    /// Same as from_transaction, with the event types registered with a typed parser in the
+    /// config too
+    pub fn from_transaction_with_mappings(
+        transaction: &APITransaction,
+        marketplace_event_mappings: &MarketplaceEventMappings,
+    ) -> Result<Self> {
+        match transaction {
+            APITransaction::UserTransaction(user_txn) => Self::from_events_with_mappings(
+                &user_txn.events,
+                user_txn.info.version.0 as i64,
+                marketplace_event_mappings,
+            ),
+            _ => Ok(Self::default()),
+        }
+    }
+
+    pub fn from_events(events: &[APIEvent], txn_version: i64) -> Result<Self> {
+        Self::from_events_with_mappings(events, txn_version, &MarketplaceEventMappings::default())
+    }
+
+    pub fn from_events_with_mappings(
+        events: &[APIEvent],
+        txn_version: i64,
+        marketplace_event_mappings: &MarketplaceEventMappings,
+    ) -> Result<Self> {
+        let (events, marketplace_tokens) = events
+            .iter()
+            .map(|event| {
+                let event_type = event.typ.to_string();
+                match TokenEvent::from_event(&event_type, &event.data, txn_version)? {
+                    Some(token_event) => Ok((Some(token_event), None)),
+                    None => marketplace_event_mappings
+                        .marketplace_event(&event_type, &event.data, txn_version)
+                        .map(|marketplace_event| match marketplace_event {
+                            Some(parsed) => (parsed.token_event, Some(parsed.tokens)),
+                            None => (None, None),
+                        }),
+                }
+            })
+            .collect::<Result<Vec<_>>>()?
+            .into_iter()
+            .unzip();
+        Ok(Self {
+            events,
+            marketplace_tokens,
+        })
+    }
+
+    /// The event at event_index, if it's a token framework event or the typed event of a built
+    /// in marketplace
+    pub fn get(&self, event_index: usize) -> Option<&TokenEvent> {
+        self.events.get(event_index).and_then(Option::as_ref)
+    }
+
+    /// The tokens of the event at event_index, if it's a marketplace event
+    pub fn marketplace_tokens(&self, event_index: usize) -> Option<&[ParsedMarketplaceEvent]> {
+        self.marketplace_tokens
+            .get(event_index)
+            .and_then(|tokens| tokens.as_deref())
     }
 }
 
@@ -793,21 +811,514 @@ impl TokenResource {
         txn_version: i64,
     ) -> Result<TokenResource> {
         match data_type {
-            "0x3::token::Collections" => serde_json::from_value(data.clone())
+            "0x3::token::Collections" => Deserialize::deserialize(data)
                 .map(|inner| Some(TokenResource::CollectionResource(inner))),
-            "0x3::token::TokenStore" => serde_json::from_value(data.clone())
+            "0x3::token::TokenStore" => Deserialize::deserialize(data)
                 .map(|inner| Some(TokenResource::TokenStoreResource(inner))),
-            "0x3::token_transfers::PendingClaims" => serde_json::from_value(data.clone())
+            "0x3::token_transfers::PendingClaims" => Deserialize::deserialize(data)
                 .map(|inner| Some(TokenResource::PendingClaimsResource(inner))),
             _ => Ok(None),
         }
-        .context(format!(
-            "version {} failed! failed to parse type {}, data {:?}",
-            txn_version, data_type, data
-        ))?
-        .context(format!(
-            "Resource unsupported! Call is_resource_supported first. version {} type {}",
-            txn_version, data_type
-        ))
+        .with_context(|| {
+            format!(
+                "version {} failed! failed to parse type {}, data {:?}",
+                txn_version, data_type, data
+            )
+        })?
+        .with_context(|| {
+            format!(
+                "Resource unsupported! Call is_resource_supported first. version {} type {}",
+                txn_version, data_type
+            )
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::models::token_models::marketplaces::souffl3;
+    use std::{collections::HashSet, fs, path::PathBuf};
+
+    /// One `{"type", "data", "expected"}` file per event. Supporting a new event type takes a
+    /// fixture here and an arm in `summarize`. Events parsed with a MarketplaceEventParser name it
+    /// in "parser".
+    const FIXTURE_DIR: &str = concat!(env!("CARGO_MANIFEST_DIR"), "/fixtures/token_events");
+    const NUM_VARIANTS: usize = 36;
+
+    #[derive(Debug, Deserialize)]
+    struct EventFixture {
+        #[serde(rename = "type")]
+        data_type: String,
+        #[serde(default)]
+        parser: Option<String>,
+        data: serde_json::Value,
+        expected: EventSummary,
+    }
+
+    /// Key fields of a parsed event, as strings so that fixtures can spell them out
+    #[derive(Debug, Deserialize, PartialEq)]
+    struct EventSummary {
+        variant: String,
+        token: String,
+        property_version: Option<String>,
+        amount: Option<String>,
+        price: Option<String>,
+        addresses: Vec<String>,
+    }
+
+    fn summary(
+        variant: &str,
+        token: String,
+        property_version: Option<&BigDecimal>,
+        amount: Option<&BigDecimal>,
+        price: Option<&BigDecimal>,
+        addresses: &[&String],
+    ) -> EventSummary {
+        EventSummary {
+            variant: variant.to_string(),
+            token,
+            property_version: property_version.map(|v| v.to_string()),
+            amount: amount.map(|v| v.to_string()),
+            price: price.map(|v| v.to_string()),
+            addresses: addresses.iter().map(|a| a.to_string()).collect(),
+        }
+    }
+
+    fn token_summary(
+        variant: &str,
+        id: &TokenIdType,
+        amount: Option<&BigDecimal>,
+        price: Option<&BigDecimal>,
+        addresses: &[&String],
+    ) -> EventSummary {
+        summary(
+            variant,
+            id.token_data_id.to_string(),
+            Some(&id.property_version),
+            amount,
+            price,
+            addresses,
+        )
+    }
+
+    fn summarize(event: &TokenEvent) -> EventSummary {
+        match event {
+            TokenEvent::MintTokenEvent(e) => summary(
+                "MintTokenEvent",
+                e.id.to_string(),
+                None,
+                Some(&e.amount),
+                None,
+                &[],
+            ),
+            TokenEvent::BurnTokenEvent(e) => {
+                token_summary("BurnTokenEvent", &e.id, Some(&e.amount), None, &[])
+            }
+            TokenEvent::MutateTokenPropertyMapEvent(e) => {
+                token_summary("MutateTokenPropertyMapEvent", &e.new_id, None, None, &[])
+            }
+            TokenEvent::WithdrawTokenEvent(e) => {
+                token_summary("WithdrawTokenEvent", &e.id, Some(&e.amount), None, &[])
+            }
+            TokenEvent::DepositTokenEvent(e) => {
+                token_summary("DepositTokenEvent", &e.id, Some(&e.amount), None, &[])
+            }
+            TokenEvent::OfferTokenEvent(e) => token_summary(
+                "OfferTokenEvent",
+                &e.token_id,
+                Some(&e.amount),
+                None,
+                &[&e.to_address],
+            ),
+            TokenEvent::CancelTokenOfferEvent(e) => token_summary(
+                "CancelTokenOfferEvent",
+                &e.token_id,
+                Some(&e.amount),
+                None,
+                &[&e.to_address],
+            ),
+            TokenEvent::ClaimTokenEvent(e) => token_summary(
+                "ClaimTokenEvent",
+                &e.token_id,
+                Some(&e.amount),
+                None,
+                &[&e.to_address],
+            ),
+            TokenEvent::BlueMoveAuctionEvent(e) => token_summary(
+                "BlueMoveAuctionEvent",
+                &e.id,
+                None,
+                Some(&e.min_selling_price),
+                &[&e.owner_address],
+            ),
+            TokenEvent::BlueBidEvent(e) => token_summary(
+                "BlueBidEvent",
+                &e.id,
+                None,
+                Some(&e.bid),
+                &[&e.bider_address],
+            ),
+            TokenEvent::BlueBuyEvent(e) => {
+                token_summary("BlueBuyEvent", &e.id, None, None, &[&e.buyer_address])
+            }
+            TokenEvent::BlueChangePriceEvent(e) => token_summary(
+                "BlueChangePriceEvent",
+                &e.id,
+                None,
+                Some(&e.amount),
+                &[&e.seller_address],
+            ),
+            TokenEvent::BlueClaimCoinsEvent(e) => {
+                token_summary("BlueClaimCoinsEvent", &e.id, None, None, &[&e.owner_token])
+            }
+            TokenEvent::BlueClaimTokenEvent(e) => token_summary(
+                "BlueClaimTokenEvent",
+                &e.id,
+                None,
+                None,
+                &[&e.bider_address],
+            ),
+            TokenEvent::BlueDelistEvent(e) => {
+                token_summary("BlueDelistEvent", &e.id, None, None, &[&e.seller_address])
+            }
+            TokenEvent::BlueListEvent(e) => token_summary(
+                "BlueListEvent",
+                &e.id,
+                None,
+                Some(&e.amount),
+                &[&e.seller_address, &e.royalty_payee],
+            ),
+            TokenEvent::TopazBidEvent(e) => token_summary(
+                "TopazBidEvent",
+                &e.token_id,
+                Some(&e.amount),
+                Some(&e.price),
+                &[&e.buyer],
+            ),
+            TokenEvent::TopazBuyEvent(e) => token_summary(
+                "TopazBuyEvent",
+                &e.token_id,
+                Some(&e.amount),
+                Some(&e.price),
+                &[&e.seller, &e.buyer],
+            ),
+            TokenEvent::TopazBuyAllEvent(e) => summary(
+                "TopazBuyAllEvent",
+                e.token_ids
+                    .iter()
+                    .map(|token_id| token_id.token_data_id.to_string())
+                    .collect::<Vec<String>>()
+                    .join(", "),
+                None,
+                Some(&e.amounts.iter().sum()),
+                Some(&e.prices.iter().sum()),
+                &std::iter::once(&e.buyer)
+                    .chain(e.sellers.iter())
+                    .collect::<Vec<&String>>(),
+            ),
+            TokenEvent::TopazCancelBidEvent(e) => token_summary(
+                "TopazCancelBidEvent",
+                &e.token_id,
+                Some(&e.amount),
+                Some(&e.price),
+                &[&e.buyer],
+            ),
+            TokenEvent::TopazCancelCollectionBidEvent(e) => summary(
+                "TopazCancelCollectionBidEvent",
+                CollectionDataIdType::new(e.creator.clone(), e.collection_name.clone()).to_string(),
+                None,
+                Some(&e.amount),
+                Some(&e.price),
+                &[&e.buyer],
+            ),
+            TokenEvent::TopazClaimEvent(e) => {
+                token_summary("TopazClaimEvent", &e.token_id, None, None, &[&e.receiver])
+            }
+            TokenEvent::TopazCollectionBidEvent(e) => summary(
+                "TopazCollectionBidEvent",
+                CollectionDataIdType::new(e.creator.clone(), e.collection_name.clone()).to_string(),
+                None,
+                Some(&e.amount),
+                Some(&e.price),
+                &[&e.buyer],
+            ),
+            TokenEvent::TopazDelistEvent(e) => token_summary(
+                "TopazDelistEvent",
+                &e.token_id,
+                Some(&e.amount),
+                Some(&e.price),
+                &[&e.seller],
+            ),
+            TokenEvent::TopazListEvent(e) => token_summary(
+                "TopazListEvent",
+                &e.token_id,
+                Some(&e.amount),
+                Some(&e.price),
+                &[&e.seller],
+            ),
+            TokenEvent::TopazSellEvent(e) => token_summary(
+                "TopazSellEvent",
+                &e.token_id,
+                Some(&e.amount),
+                Some(&e.price),
+                &[&e.buyer, &e.seller],
+            ),
+            TokenEvent::TopazSendEvent(e) => token_summary(
+                "TopazSendEvent",
+                &e.token_id,
+                Some(&e.amount),
+                None,
+                &[&e.sender, &e.receiver],
+            ),
+            TokenEvent::Souffl3BuyTokenEvent(e) => token_summary(
+                "Souffl3BuyTokenEvent",
+                &e.token_id,
+                Some(&e.token_amount),
+                Some(&e.coin_per_token),
+                &[&e.buyer, &e.token_owner],
+            ),
+            TokenEvent::Souffl3CancelListTokenEvent(e) => token_summary(
+                "Souffl3CancelListTokenEvent",
+                &e.token_id,
+                Some(&e.token_amount),
+                None,
+                &[],
+            ),
+            TokenEvent::Souffl3ListTokenEvent(e) => token_summary(
+                "Souffl3ListTokenEvent",
+                &e.token_id,
+                Some(&e.token_amount),
+                Some(&e.coin_per_token),
+                &[&e.token_owner],
+            ),
+            TokenEvent::Souffl3TokenListEvent(e) => token_summary(
+                "Souffl3TokenListEvent",
+                &e.token_id,
+                Some(&e.amount),
+                Some(&e.min_price),
+                &[],
+            ),
+            TokenEvent::Souffl3TokenSwapEvent(e) => token_summary(
+                "Souffl3TokenSwapEvent",
+                &e.token_id,
+                Some(&e.token_amount),
+                Some(&e.coin_amount),
+                &[&e.token_buyer],
+            ),
+            TokenEvent::Souffl3V2BuyTokenEvent(e) => token_summary(
+                "Souffl3V2BuyTokenEvent",
+                &e.token_id,
+                Some(&e.token_amount),
+                Some(&e.coin_per_token),
+                &[&e.buyer, &e.seller],
+            ),
+            TokenEvent::Souffl3V2CancelListTokenEvent(e) => token_summary(
+                "Souffl3V2CancelListTokenEvent",
+                &e.token_id,
+                Some(&e.token_amount),
+                None,
+                &[&e.seller],
+            ),
+            TokenEvent::Souffl3V2ListTokenEvent(e) => token_summary(
+                "Souffl3V2ListTokenEvent",
+                &e.token_id,
+                Some(&e.token_amount),
+                Some(&e.coin_per_token),
+                &[&e.seller],
+            ),
+            // The tokens of a sweep are summarized together, in order
+            TokenEvent::Souffl3SweepBuyEvent(e) => summary(
+                "Souffl3SweepBuyEvent",
+                e.token_ids
+                    .iter()
+                    .map(|token_id| token_id.token_data_id.to_string())
+                    .collect::<Vec<String>>()
+                    .join(", "),
+                None,
+                None,
+                Some(&e.coin_per_tokens.iter().sum()),
+                &std::iter::once(&e.buyer)
+                    .chain(e.sellers.iter())
+                    .collect::<Vec<&String>>(),
+            ),
+        }
+    }
+
+    #[test]
+    fn test_token_event_fixtures() {
+        let mut paths: Vec<PathBuf> = fs::read_dir(FIXTURE_DIR)
+            .unwrap()
+            .map(|entry| entry.unwrap().path())
+            .collect();
+        paths.sort();
+        let mut variants = HashSet::new();
+        for path in paths {
+            let fixture: EventFixture =
+                serde_json::from_str(&fs::read_to_string(&path).unwrap()).unwrap();
+            let marketplace_event = match &fixture.parser {
+                Some(parser) => TokenEvent::from_parser(
+                    parser.parse().unwrap(),
+                    &fixture.data_type,
+                    &fixture.data,
+                    1,
+                )
+                .map(|token_event| Some(souffl3::typed_event(token_event))),
+                None => MarketplaceEventMappings::default().marketplace_event(
+                    &fixture.data_type,
+                    &fixture.data,
+                    1,
+                ),
+            }
+            .unwrap_or_else(|err| panic!("{}: {:?}", path.display(), err));
+            // Only the token framework's events are without a marketplace adapter
+            assert_ne!(
+                fixture.data_type.starts_with("0x3::"),
+                marketplace_event.is_some(),
+                "{}",
+                path.display()
+            );
+            let event = match &marketplace_event {
+                Some(marketplace_event) => Ok(marketplace_event.token_event.clone()),
+                None => TokenEvent::from_event(&fixture.data_type, &fixture.data, 1),
+            }
+            .unwrap_or_else(|err| panic!("{}: {:?}", path.display(), err))
+            .unwrap_or_else(|| panic!("{}: type isn't supported", path.display()));
+            if let Some(marketplace_event) = &marketplace_event {
+                // Every token of the event is normalized
+                assert_eq!(
+                    marketplace_event.tokens.len(),
+                    event.token_count(),
+                    "{}",
+                    path.display()
+                );
+            }
+            let summary = summarize(&event);
+            assert_eq!(summary, fixture.expected, "{}", path.display());
+            variants.insert(summary.variant);
+        }
+        assert_eq!(
+            variants.len(),
+            NUM_VARIANTS,
+            "every variant needs a fixture"
+        );
+    }
+
+    #[test]
+    fn test_unknown_token_event() {
+        let data = serde_json::json!({ "amount": "1" });
+        assert!(TokenEvent::from_event("0x1::coin::DepositEvent", &data, 1)
+            .unwrap()
+            .is_none());
+        // A supported type with a payload that doesn't match is still an error
+        assert!(TokenEvent::from_event("0x3::token::DepositEvent", &data, 1).is_err());
+    }
+
+    #[test]
+    fn test_sweep_with_missing_prices() {
+        let data = serde_json::json!({
+            "buyer": "0xb0b",
+            "token_ids": [
+                {"token_data_id": {"creator": "0xc4e7", "collection": "Potions", "name": "Potion #1"}, "property_version": "0"},
+                {"token_data_id": {"creator": "0xc4e7", "collection": "Potions", "name": "Potion #2"}, "property_version": "0"}
+            ],
+            "sellers": ["0xa11ce", "0xca41"],
+            "coin_per_tokens": ["100"],
+            "coin_type_info": {"account_address": "0x1", "module_name": "aptos_coin", "struct_name": "AptosCoin"}
+        });
+        let event = TokenEvent::from_parser(
+            MarketplaceEventParser::Souffl3SweepBuy,
+            "0xf00d::sweep::SweepBuyEvent",
+            &data,
+            1,
+        );
+        assert!(event.is_err());
+    }
+
+    #[test]
+    fn test_string_limits_from_config() {
+        assert_eq!(StringLimits::from_config(None).unwrap(), StringLimits::default());
+        let limits = StringLimits::from_config(Some(&StringLimitsConfig {
+            name_length: Some(300),
+            uri_length: None,
+        }))
+        .unwrap();
+        assert_eq!(limits.name_length, 300);
+        assert_eq!(limits.uri_length, DEFAULT_URI_LENGTH);
+        for (name_length, uri_length) in [(0, 512), (513, 512), (128, 0), (128, 2049)] {
+            assert!(StringLimits::from_config(Some(&StringLimitsConfig {
+                name_length: Some(name_length),
+                uri_length: Some(uri_length),
+            }))
+            .is_err());
+        }
+
+        // Rows written with other limits wouldn't match
+        StringLimits::default().install().unwrap();
+        StringLimits::default().install().unwrap();
+        assert!(limits.install().is_err());
+        assert_eq!(StringLimits::current(), StringLimits::default());
+    }
+
+    #[test]
+    fn test_value_limits() {
+        assert_eq!(ValueLimits::from_config(None).unwrap(), ValueLimits::default());
+        let limits = ValueLimits::from_config(Some(&ValueLimitsConfig {
+            max_price: Some("1000".to_string()),
+            max_amount: None,
+        }))
+        .unwrap();
+        assert_eq!(limits.max_price, BigDecimal::from(1000));
+        assert_eq!(
+            limits.max_amount,
+            BigDecimal::from_str(DEFAULT_MAX_VALUE).unwrap()
+        );
+        for max_price in ["0", "-1", "lots"] {
+            assert!(ValueLimits::from_config(Some(&ValueLimitsConfig {
+                max_price: Some(max_price.to_string()),
+                max_amount: None,
+            }))
+            .is_err());
+        }
+
+        assert!(!limits.is_suspect_price(&BigDecimal::zero()));
+        assert!(!limits.is_suspect_price(&BigDecimal::from(1000)));
+        assert!(limits.is_suspect_price(&BigDecimal::from(1001)));
+        assert!(limits.is_suspect_price(&BigDecimal::from(-1)));
+        let u128_max = BigDecimal::from_str(&u128::MAX.to_string()).unwrap();
+        assert!(limits.is_suspect_amount(&u128_max));
+        assert!(!limits.is_suspect_amount(&BigDecimal::from(u64::MAX)));
+    }
+
+    #[test]
+    fn test_long_collection_name_is_hashed_whole() {
+        let collection = "P".repeat(300);
+        let token_data_id = TokenDataIdType {
+            creator: standardize_address("0xc4e7"),
+            collection: collection.clone(),
+            name: "Potion".to_string(),
+        };
+        let truncated = token_data_id.get_collection_trunc();
+        assert_eq!(truncated, "P".repeat(DEFAULT_NAME_LENGTH));
+        assert!(StringLimits::current().is_name_truncated(&collection));
+        assert!(!StringLimits::current().is_name_truncated(&truncated));
+
+        // What a consumer computes from the whole name off chain
+        assert_eq!(
+            token_data_id.get_collection_data_id_hash(),
+            hash_str(&format!("{}::{}", standardize_address("0xc4e7"), collection))
+        );
+        assert_ne!(
+            token_data_id.get_collection_data_id_hash(),
+            CollectionDataIdType::new("0xc4e7".to_string(), truncated).to_hash()
+        );
+        assert_eq!(
+            token_data_id.to_hash(),
+            hash_str(&format!(
+                "{}::{}::Potion",
+                standardize_address("0xc4e7"),
+                collection
+            ))
+        );
     }
 }
@@ -6,17 +6,23 @@
 #![allow(clippy::unused_unit)]
 
 use super::{
-    collection_datas::{CollectionData, CurrentCollectionData},
+    collection_datas::{
+        CollectionCreators, CollectionData, CollectionTableItem, CurrentCollectionData,
+    },
+    marketplace_event_mappings::MarketplaceEventMappings,
+    table_handle_cache::TableHandleCache,
+    table_item_keys::{deleted_table_key, TOKEN_ID_TYPE},
     token_claims::CurrentTokenPendingClaim,
     token_datas::{CurrentTokenData, TokenData},
-    token_ownerships::{CurrentTokenOwnership, TokenOwnership},
-    token_utils::{TokenResource, TokenWriteSet},
+    token_ownerships::{
+        BurnedTokenOwners, CurrentTokenOwnership, TokenOwnership, TOKEN_STORE_TYPE,
+    },
+    token_utils::{TokenEvents, TokenResource, TokenWriteSet},
 };
 use crate::{
-    database::PgPoolConnection,
     models::move_resources::MoveResource,
     schema::tokens,
-    util::{ensure_not_negative, parse_timestamp},
+    util::{ensure_not_negative, parse_timestamp, standardize_address},
 };
 use aptos_api_types::{
     DeleteTableItem as APIDeleteTableItem, Transaction as APITransaction,
@@ -62,17 +68,33 @@ pub struct TableMetadataForToken {
     pub table_type: TableType,
 }
 
-impl Token {
-    /// We can find token data from write sets in user transactions. Table items will contain metadata for collections
-    /// and tokens. To find ownership, we have to look in write resource write sets for who owns those table handles
-    ///
-    /// We also will compute current versions of the token tables which are at a higher granularity than the transactional tables (only
-    /// state at the last transaction will be tracked, hence using hashmap to dedupe)
-    pub fn from_transaction(
-        transaction: &APITransaction,
-        conn: &mut PgPoolConnection,
+/// Token rows parsed from a transaction without the db. Collection table items can need the db
+/// to find their creator, so they're only turned into rows by `resolve`.
+#[derive(Debug, Default)]
+pub struct ParsedTokens {
+    tokens: Vec<Token>,
+    token_ownerships: Vec<TokenOwnership>,
+    token_datas: Vec<TokenData>,
+    collection_items: Vec<CollectionTableItem>,
+    current_token_ownerships: HashMap<CurrentTokenOwnershipPK, CurrentTokenOwnership>,
+    current_token_datas: HashMap<TokenDataIdHash, CurrentTokenData>,
+    current_token_claims: HashMap<CurrentTokenPendingClaimPK, CurrentTokenPendingClaim>,
+}
+
+impl ParsedTokens {
+    /// Collection table items whose creator may have to be looked up, see CollectionCreators
+    pub fn collection_items(&self) -> &[CollectionTableItem] {
+        &self.collection_items
+    }
+
+    /// Fills in the creators the transaction didn't have from the batch's prefetched ones, in
+    /// write set order so a later write to the same collection still wins
+    pub fn resolve(
+        self,
+        collection_creators: &CollectionCreators,
+        table_handle_cache: &TableHandleCache,
     ) -> (
-        Vec<Self>,
+        Vec<Token>,
         Vec<TokenOwnership>,
         Vec<TokenData>,
         Vec<CollectionData>,
@@ -81,10 +103,50 @@ impl Token {
         HashMap<TokenDataIdHash, CurrentCollectionData>,
         HashMap<CurrentTokenPendingClaimPK, CurrentTokenPendingClaim>,
     ) {
+        let mut collection_datas = vec![];
+        let mut current_collection_datas: HashMap<TokenDataIdHash, CurrentCollectionData> =
+            HashMap::new();
+        for collection_item in self.collection_items {
+            let (collection_data, current_collection_data) = collection_item
+                .resolve(collection_creators, table_handle_cache)
+                .unwrap();
+            collection_datas.push(collection_data);
+            current_collection_datas.insert(
+                current_collection_data.collection_data_id_hash.clone(),
+                current_collection_data,
+            );
+        }
+        (
+            self.tokens,
+            self.token_ownerships,
+            self.token_datas,
+            collection_datas,
+            self.current_token_ownerships,
+            self.current_token_datas,
+            current_collection_datas,
+            self.current_token_claims,
+        )
+    }
+}
+
+impl Token {
+    /// We can find token data from write sets in user transactions. Table items will contain metadata for collections
+    /// and tokens. To find ownership, we have to look in write resource write sets for who owns those table handles
+    ///
+    /// We also will compute current versions of the token tables which are at a higher granularity than the transactional tables (only
+    /// state at the last transaction will be tracked, hence using hashmap to dedupe)
+    ///
+    /// This doesn't touch the db, so transactions can be parsed in parallel before their
+    /// collection items are resolved in version order.
+    pub fn parse_transaction(
+        transaction: &APITransaction,
+        token_events: &TokenEvents,
+        marketplace_event_mappings: &MarketplaceEventMappings,
+    ) -> ParsedTokens {
         if let APITransaction::UserTransaction(user_txn) = transaction {
             let mut token_ownerships = vec![];
             let mut token_datas = vec![];
-            let mut collection_datas = vec![];
+            let mut collection_items = vec![];
 
             let mut tokens: HashMap<TokenPK, Token> = HashMap::new();
             let mut current_token_ownerships: HashMap<
@@ -93,8 +155,6 @@ impl Token {
             > = HashMap::new();
             let mut current_token_datas: HashMap<TokenDataIdHash, CurrentTokenData> =
                 HashMap::new();
-            let mut current_collection_datas: HashMap<TokenDataIdHash, CurrentCollectionData> =
-                HashMap::new();
             let mut current_token_claims: HashMap<
                 CurrentTokenPendingClaimPK,
                 CurrentTokenPendingClaim,
@@ -116,17 +176,23 @@ impl Token {
                 }
             }
 
+            let offerers =
+                CurrentTokenPendingClaim::get_offerers_from_events(&user_txn.events, token_events);
+            let burned_token_owners =
+                TokenOwnership::get_burned_token_owners_from_events(&user_txn.events, token_events);
+
             // if events contains a listing, we overwrite listed fields, and when delisting, buy, sell, fill, we delete the fields (overwrite w null)
 
             for wsc in &user_txn.info.changes {
                 // Basic token and ownership data
-                let (maybe_token_w_ownership, maybe_token_data, maybe_collection_data) = match wsc {
+                let (maybe_token_w_ownership, maybe_token_data, maybe_collection_item) = match wsc {
                     APIWriteSetChange::WriteTableItem(write_table_item) => (
                         Self::from_write_table_item(
                             write_table_item,
                             txn_version,
                             txn_timestamp,
                             &table_handle_to_owner,
+                            &burned_token_owners,
                         )
                         .unwrap(),
                         TokenData::from_write_table_item(
@@ -135,12 +201,11 @@ impl Token {
                             txn_timestamp,
                         )
                         .unwrap(),
-                        CollectionData::from_write_table_item(
+                        CollectionTableItem::from_write_table_item(
                             write_table_item,
                             txn_version,
                             txn_timestamp,
                             &table_handle_to_owner,
-                            conn,
                         )
                         .unwrap(),
                     ),
@@ -150,6 +215,7 @@ impl Token {
                             txn_version,
                             txn_timestamp,
                             &table_handle_to_owner,
+                            &burned_token_owners,
                         )
                         .unwrap(),
                         None,
@@ -174,6 +240,7 @@ impl Token {
                             txn_version,
                             txn_timestamp,
                             &table_handle_to_owner,
+                            &offerers,
                         )
                         .unwrap()
                     }
@@ -209,12 +276,8 @@ impl Token {
                         current_token_data,
                     );
                 }
-                if let Some((collection_data, current_collection_data)) = maybe_collection_data {
-                    collection_datas.push(collection_data);
-                    current_collection_datas.insert(
-                        current_collection_data.collection_data_id_hash.clone(),
-                        current_collection_data,
-                    );
+                if let Some(collection_item) = maybe_collection_item {
+                    collection_items.push(collection_item);
                 }
                 if let Some(claim) = maybe_current_token_claim {
                     current_token_claims.insert(
@@ -228,16 +291,35 @@ impl Token {
                     );
                 }
             }
-            return (
-                tokens.into_values().collect(),
+            // Only fills in what the write set didn't have
+            for ownership in CurrentTokenOwnership::from_mutated_token_events(
+                &user_txn.events,
+                token_events,
+                txn_version,
+                txn_timestamp,
+            ) {
+                current_token_ownerships
+                    .entry((
+                        ownership.token_data_id_hash.clone(),
+                        ownership.property_version.clone(),
+                        ownership.owner_address.clone(),
+                    ))
+                    .or_insert(ownership);
+            }
+            let contract_addresses =
+                CurrentTokenOwnership::get_contract_addresses(&user_txn.info.changes);
+            for ownership in current_token_ownerships.values_mut() {
+                ownership.set_owner_type(marketplace_event_mappings, &contract_addresses);
+            }
+            return ParsedTokens {
+                tokens: tokens.into_values().collect(),
                 token_ownerships,
                 token_datas,
-                collection_datas,
+                collection_items,
                 current_token_ownerships,
                 current_token_datas,
-                current_collection_datas,
                 current_token_claims,
-            );
+            };
         }
         Default::default()
     }
@@ -250,6 +332,7 @@ impl Token {
         txn_version: i64,
         txn_timestamp: chrono::NaiveDateTime,
         table_handle_to_owner: &TableHandleToOwner,
+        burned_token_owners: &BurnedTokenOwners,
     ) -> anyhow::Result<Option<(Self, TokenOwnership, Option<CurrentTokenOwnership>)>> {
         let table_item_data = table_item.data.as_ref().unwrap();
 
@@ -287,6 +370,7 @@ impl Token {
                 ensure_not_negative(token.amount),
                 table_item.handle.to_string(),
                 table_handle_to_owner,
+                burned_token_owners,
                 Some(table_item_data.value_type.as_str()),
             );
 
@@ -297,24 +381,37 @@ impl Token {
     }
 
     /// Get token from delete table item. The difference from write table item is that value isn't there so
-    /// we'll set amount to 0 and token property to blank.
+    /// we'll set amount to 0 and token property to blank. Keys the API didn't decode are decoded
+    /// from their bytes, see deleted_table_key.
     pub fn from_delete_table_item(
         table_item: &APIDeleteTableItem,
         txn_version: i64,
         txn_timestamp: chrono::NaiveDateTime,
         table_handle_to_owner: &TableHandleToOwner,
+        burned_token_owners: &BurnedTokenOwners,
     ) -> anyhow::Result<Option<(Self, TokenOwnership, Option<CurrentTokenOwnership>)>> {
-        let table_item_data = table_item.data.as_ref().unwrap();
-
-        let maybe_token_id = match TokenWriteSet::from_table_item_type(
-            table_item_data.key_type.as_str(),
-            &table_item_data.key,
-            txn_version,
-        )? {
-            Some(TokenWriteSet::TokenId(inner)) => Some(inner),
-            _ => None,
+        let table_handle =
+            TableMetadataForToken::standardize_handle(&table_item.handle.to_string());
+        let maybe_key = match table_handle_to_owner.get(&table_handle) {
+            Some(metadata) if metadata.table_type == TOKEN_STORE_TYPE => {
+                deleted_table_key(table_item, Some(TOKEN_ID_TYPE))?
+            }
+            Some(_) => deleted_table_key(table_item, None)?,
+            // The table might not be a token store, so a key that doesn't decode as a TokenId
+            // is skipped rather than an error
+            None => deleted_table_key(table_item, Some(TOKEN_ID_TYPE)).unwrap_or(None),
+        };
+        let (key_type, key) = match maybe_key {
+            Some(key) => key,
+            None => return Ok(None),
         };
 
+        let maybe_token_id =
+            match TokenWriteSet::from_table_item_type(key_type.as_str(), &key, txn_version)? {
+                Some(TokenWriteSet::TokenId(inner)) => Some(inner),
+                _ => None,
+            };
+
         if let Some(token_id) = maybe_token_id {
             let token_data_id = token_id.token_data_id;
             let collection_data_id_hash = token_data_id.get_collection_data_id_hash();
@@ -338,6 +435,7 @@ impl Token {
                 BigDecimal::zero(),
                 table_item.handle.to_string(),
                 table_handle_to_owner,
+                burned_token_owners,
                 None,
             );
             Ok(Some((token, token_ownership, current_token_ownership)))
@@ -370,7 +468,7 @@ impl TableMetadataForToken {
         );
 
         let value = TableMetadataForToken {
-            owner_address: resource.address,
+            owner_address: standardize_address(&resource.address),
             table_type: write_resource.data.typ.to_string(),
         };
         let table_handle: TableHandle = match TokenResource::from_resource(
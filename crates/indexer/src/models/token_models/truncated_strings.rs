@@ -0,0 +1,302 @@
+// Copyright (c) Aptos
+// SPDX-License-Identifier: Apache-2.0
+
+//! Whole values of the names and uris longer than the string limits. The token tables store them
+//! truncated, while their hashes are over the whole values, so this is where a consumer joining
+//! on a hash gets the original back.
+
+// This is required because a diesel macro makes clippy sad
+#![allow(clippy::extra_unused_lifetimes)]
+#![allow(clippy::unused_unit)]
+
+use super::{
+    collection_datas::CollectionData,
+    token_utils::{
+        CollectionDataIdType, CollectionDataType, StringLimits, TokenDataIdType, TokenDataType,
+        TokenWriteSet,
+    },
+};
+use crate::schema::truncated_strings;
+use aptos_api_types::{Transaction as APITransaction, WriteSetChange as APIWriteSetChange};
+use field_count::FieldCount;
+use serde::{Deserialize, Serialize};
+
+pub const COLLECTION_NAME_FIELD: &str = "collection_name";
+pub const NAME_FIELD: &str = "name";
+pub const METADATA_URI_FIELD: &str = "metadata_uri";
+
+/// hash, field
+pub type TruncatedStringPK = (String, String);
+
+#[derive(Clone, Debug, Deserialize, FieldCount, Identifiable, Insertable, Serialize)]
+#[diesel(primary_key(hash, field))]
+#[diesel(table_name = truncated_strings)]
+pub struct TruncatedString {
+    /// collection_data_id_hash for collection_name and a collection's metadata_uri,
+    /// token_data_id_hash for name and a token's metadata_uri
+    pub hash: String,
+    pub field: String,
+    pub full_value: String,
+    pub last_transaction_version: i64,
+}
+
+impl TruncatedString {
+    /// From the token and collection datas the transaction wrote. Collection creators come from
+    /// the transaction's resolved collection_datas rows, which share the table handle.
+    pub fn from_transaction(
+        transaction: &APITransaction,
+        collection_datas: &[CollectionData],
+    ) -> Vec<Self> {
+        let user_txn = match transaction {
+            APITransaction::UserTransaction(user_txn) => user_txn,
+            _ => return vec![],
+        };
+        let limits = StringLimits::current();
+        let txn_version = user_txn.info.version.0 as i64;
+        let mut truncated_strings = vec![];
+        for wsc in &user_txn.info.changes {
+            let table_item = match wsc {
+                APIWriteSetChange::WriteTableItem(table_item) => table_item,
+                _ => continue,
+            };
+            let table_item_data = match table_item.data.as_ref() {
+                Some(data) => data,
+                None => continue,
+            };
+            match TokenWriteSet::from_table_item_type(
+                table_item_data.value_type.as_str(),
+                &table_item_data.value,
+                txn_version,
+            )
+            .unwrap()
+            {
+                Some(TokenWriteSet::TokenData(token_data)) => {
+                    if let Some(TokenWriteSet::TokenDataId(token_data_id)) =
+                        TokenWriteSet::from_table_item_type(
+                            table_item_data.key_type.as_str(),
+                            &table_item_data.key,
+                            txn_version,
+                        )
+                        .unwrap()
+                    {
+                        truncated_strings.extend(Self::from_token_data(
+                            &limits,
+                            &token_data_id,
+                            &token_data,
+                            txn_version,
+                        ));
+                    }
+                }
+                Some(TokenWriteSet::CollectionData(collection_data)) => {
+                    let table_handle = table_item.handle.to_string();
+                    if let Some(row) = collection_datas.iter().find(|row| {
+                        row.table_handle == table_handle && row.transaction_version == txn_version
+                    }) {
+                        truncated_strings.extend(Self::from_collection_data(
+                            &limits,
+                            &row.creator_address,
+                            &collection_data,
+                            txn_version,
+                        ));
+                    }
+                }
+                _ => {}
+            }
+        }
+        truncated_strings
+    }
+
+    fn from_token_data(
+        limits: &StringLimits,
+        token_data_id: &TokenDataIdType,
+        token_data: &TokenDataType,
+        txn_version: i64,
+    ) -> Vec<Self> {
+        let token_data_id_hash = token_data_id.to_hash();
+        let mut truncated_strings = vec![];
+        if limits.is_name_truncated(&token_data_id.collection) {
+            truncated_strings.push(Self::new(
+                token_data_id.get_collection_data_id_hash(),
+                COLLECTION_NAME_FIELD,
+                &token_data_id.collection,
+                txn_version,
+            ));
+        }
+        if limits.is_name_truncated(&token_data_id.name) {
+            truncated_strings.push(Self::new(
+                token_data_id_hash.clone(),
+                NAME_FIELD,
+                &token_data_id.name,
+                txn_version,
+            ));
+        }
+        if limits.is_uri_truncated(token_data.get_uri()) {
+            truncated_strings.push(Self::new(
+                token_data_id_hash,
+                METADATA_URI_FIELD,
+                token_data.get_uri(),
+                txn_version,
+            ));
+        }
+        truncated_strings
+    }
+
+    fn from_collection_data(
+        limits: &StringLimits,
+        creator_address: &str,
+        collection_data: &CollectionDataType,
+        txn_version: i64,
+    ) -> Vec<Self> {
+        let collection_data_id_hash = CollectionDataIdType::new(
+            creator_address.to_string(),
+            collection_data.get_name().to_string(),
+        )
+        .to_hash();
+        let mut truncated_strings = vec![];
+        if limits.is_name_truncated(collection_data.get_name()) {
+            truncated_strings.push(Self::new(
+                collection_data_id_hash.clone(),
+                COLLECTION_NAME_FIELD,
+                collection_data.get_name(),
+                txn_version,
+            ));
+        }
+        if limits.is_uri_truncated(collection_data.get_uri()) {
+            truncated_strings.push(Self::new(
+                collection_data_id_hash,
+                METADATA_URI_FIELD,
+                collection_data.get_uri(),
+                txn_version,
+            ));
+        }
+        truncated_strings
+    }
+
+    fn new(hash: String, field: &str, full_value: &str, txn_version: i64) -> Self {
+        Self {
+            hash,
+            field: field.to_string(),
+            full_value: full_value.to_string(),
+            last_transaction_version: txn_version,
+        }
+    }
+
+    pub fn pk(&self) -> TruncatedStringPK {
+        (self.hash.clone(), self.field.clone())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn token_data(uri: &str) -> TokenDataType {
+        serde_json::from_value(serde_json::json!({
+            "default_properties": {"map": {"data": []}},
+            "description": "",
+            "largest_property_version": "0",
+            "maximum": "0",
+            "mutability_config": {
+                "description": false,
+                "maximum": false,
+                "properties": false,
+                "royalty": false,
+                "uri": false
+            },
+            "name": "Potion",
+            "royalty": {
+                "payee_address": "0xc4e7",
+                "royalty_points_denominator": "0",
+                "royalty_points_numerator": "0"
+            },
+            "supply": "1",
+            "uri": uri
+        }))
+        .unwrap()
+    }
+
+    fn collection_data(name: &str) -> CollectionDataType {
+        serde_json::from_value(serde_json::json!({
+            "description": "",
+            "maximum": "0",
+            "mutability_config": {"description": false, "maximum": false, "uri": false},
+            "name": name,
+            "supply": "1",
+            "uri": "https://potions.example"
+        }))
+        .unwrap()
+    }
+
+    #[test]
+    fn test_long_collection_name_is_recoverable() {
+        let limits = StringLimits::default();
+        let collection = "P".repeat(300);
+        let token_data_id = TokenDataIdType {
+            creator: "0xc4e7".to_string(),
+            collection: collection.clone(),
+            name: "Potion".to_string(),
+        };
+        let rows = TruncatedString::from_token_data(
+            &limits,
+            &token_data_id,
+            &token_data("https://potions.example/1"),
+            10,
+        );
+        assert_eq!(rows.len(), 1);
+        assert_eq!(rows[0].field, COLLECTION_NAME_FIELD);
+        assert_eq!(rows[0].full_value, collection);
+        assert_eq!(rows[0].hash, token_data_id.get_collection_data_id_hash());
+
+        // The collection's own write keys the same row
+        let rows = TruncatedString::from_collection_data(
+            &limits,
+            "0xc4e7",
+            &collection_data(&collection),
+            10,
+        );
+        assert_eq!(rows.len(), 1);
+        assert_eq!(rows[0].hash, token_data_id.get_collection_data_id_hash());
+        assert_eq!(rows[0].full_value, collection);
+
+        // Nothing is truncated under a limit it fits in
+        let limits = StringLimits {
+            name_length: 300,
+            ..StringLimits::default()
+        };
+        assert!(TruncatedString::from_token_data(
+            &limits,
+            &token_data_id,
+            &token_data("https://potions.example/1"),
+            10
+        )
+        .is_empty());
+    }
+
+    #[test]
+    fn test_long_name_and_uri() {
+        let token_data_id = TokenDataIdType {
+            creator: "0xc4e7".to_string(),
+            collection: "Potions".to_string(),
+            name: "é".repeat(129),
+        };
+        let uri = format!("https://potions.example/{}", "a".repeat(512));
+        let rows = TruncatedString::from_token_data(
+            &StringLimits::default(),
+            &token_data_id,
+            &token_data(&uri),
+            10,
+        );
+        let token_data_id_hash = token_data_id.to_hash();
+        assert_eq!(rows.len(), 2);
+        assert_eq!(
+            rows[0].pk(),
+            (token_data_id_hash.clone(), NAME_FIELD.to_string())
+        );
+        assert_eq!(rows[0].full_value, token_data_id.name);
+        assert_eq!(
+            rows[1].pk(),
+            (token_data_id_hash, METADATA_URI_FIELD.to_string())
+        );
+        assert_eq!(rows[1].full_value, uri);
+    }
+}
@@ -0,0 +1,364 @@
+// Copyright (c) Aptos
+// SPDX-License-Identifier: Apache-2.0
+
+//! Rebuilds current_collection_volumes and current_token_volumes from the per-sale rows in
+//! collection_volumes and token_volumes. The current tables are maintained by additive upserts,
+//! so a double processed or dropped batch corrupts them for good; this is the way back.
+//!
+//! History written before volumes were keyed by event (event_index -1) only kept one sale per
+//! transaction. Backfill collection_volumes and token_volumes over those versions before relying
+//! on a recompute.
+
+use crate::{counters::VOLUME_DRIFT, schema::collection_volumes};
+use bigdecimal::BigDecimal;
+use diesel::{
+    result::Error,
+    sql_query,
+    sql_types::{Array, BigInt, Nullable, Numeric, Text},
+    ExpressionMethods, PgConnection, QueryDsl, QueryResult, RunQueryDsl,
+};
+
+pub const CURRENT_COLLECTION_VOLUMES: &str = "current_collection_volumes";
+pub const CURRENT_TOKEN_VOLUMES: &str = "current_token_volumes";
+
+/// A current volume that differs from the sum of its history
+#[derive(Debug, PartialEq, Eq)]
+pub struct VolumeDrift {
+    pub table: &'static str,
+    /// collection_data_id_hash or token_data_id_hash
+    pub subject: String,
+    pub column: &'static str,
+    pub recorded: BigDecimal,
+    pub recomputed: BigDecimal,
+    /// Latest version of the current row or its history
+    pub last_transaction_version: i64,
+}
+
+#[derive(Debug, QueryableByName)]
+struct CollectionVolumeComparison {
+    #[diesel(sql_type = Text)]
+    subject: String,
+    #[diesel(sql_type = Numeric)]
+    recorded_volume: BigDecimal,
+    #[diesel(sql_type = Numeric)]
+    recomputed_volume: BigDecimal,
+    #[diesel(sql_type = Numeric)]
+    recorded_primary_volume: BigDecimal,
+    #[diesel(sql_type = Numeric)]
+    recomputed_primary_volume: BigDecimal,
+    #[diesel(sql_type = Numeric)]
+    recorded_secondary_volume: BigDecimal,
+    #[diesel(sql_type = Numeric)]
+    recomputed_secondary_volume: BigDecimal,
+    #[diesel(sql_type = BigInt)]
+    last_transaction_version: i64,
+}
+
+#[derive(Debug, QueryableByName)]
+struct TokenVolumeComparison {
+    #[diesel(sql_type = Text)]
+    subject: String,
+    #[diesel(sql_type = Numeric)]
+    recorded_volume: BigDecimal,
+    #[diesel(sql_type = Numeric)]
+    recomputed_volume: BigDecimal,
+    #[diesel(sql_type = BigInt)]
+    last_transaction_version: i64,
+}
+
+/// Volumes summed from history, same rule as the processor's upserts
+const RECOMPUTED_COLLECTION_VOLUMES: &str = "
+    SELECT
+        collection_data_id_hash,
+        SUM(volume) AS volume,
+        COALESCE(SUM(volume) FILTER (WHERE is_primary), 0) AS primary_volume,
+        COALESCE(SUM(volume) FILTER (WHERE NOT is_primary), 0) AS secondary_volume,
+        SUM(volume_usd) AS volume_usd,
+        SUM(volume_decimal) AS volume_decimal,
+        MAX(last_transaction_version) AS last_transaction_version,
+        MAX(last_transaction_timestamp) AS last_transaction_timestamp
+    FROM collection_volumes
+    WHERE $1::TEXT[] IS NULL OR collection_data_id_hash = ANY($1)
+    GROUP BY collection_data_id_hash";
+
+/// token_volumes doesn't have the collection, so tokens are matched through current_token_datas
+const TOKEN_FILTER: &str = "
+    $1::TEXT[] IS NULL OR token_data_id_hash IN (
+        SELECT token_data_id_hash FROM current_token_datas
+        WHERE collection_data_id_hash = ANY($1)
+    )";
+
+fn recomputed_token_volumes() -> String {
+    format!(
+        "SELECT
+            token_data_id_hash,
+            SUM(volume) AS volume,
+            SUM(volume_decimal) AS volume_decimal,
+            MAX(last_transaction_version) AS last_transaction_version,
+            MAX(last_transaction_timestamp) AS last_transaction_timestamp
+        FROM token_volumes
+        WHERE {}
+        GROUP BY token_data_id_hash",
+        TOKEN_FILTER
+    )
+}
+
+/// Compares current volumes against the sum of their history, for every collection or only the
+/// given ones, and sets the drift gauge of each table to the number of rows that differ. A
+/// missing current row counts as a volume of 0.
+pub fn find_volume_drift(
+    conn: &mut PgConnection,
+    collection_data_id_hashes: Option<&[String]>,
+) -> QueryResult<Vec<VolumeDrift>> {
+    let collections = sql_query(format!(
+        "WITH recomputed AS ({})
+        SELECT
+            collection_data_id_hash AS subject,
+            COALESCE(c.volume, 0) AS recorded_volume,
+            COALESCE(r.volume, 0) AS recomputed_volume,
+            COALESCE(c.primary_volume, 0) AS recorded_primary_volume,
+            COALESCE(r.primary_volume, 0) AS recomputed_primary_volume,
+            COALESCE(c.secondary_volume, 0) AS recorded_secondary_volume,
+            COALESCE(r.secondary_volume, 0) AS recomputed_secondary_volume,
+            GREATEST(c.last_transaction_version, r.last_transaction_version)
+                AS last_transaction_version
+        FROM (
+            SELECT * FROM current_collection_volumes
+            WHERE $1::TEXT[] IS NULL OR collection_data_id_hash = ANY($1)
+        ) c
+        FULL OUTER JOIN recomputed r USING (collection_data_id_hash)
+        WHERE COALESCE(c.volume, 0) <> COALESCE(r.volume, 0)
+            OR COALESCE(c.primary_volume, 0) <> COALESCE(r.primary_volume, 0)
+            OR COALESCE(c.secondary_volume, 0) <> COALESCE(r.secondary_volume, 0)
+        ORDER BY subject",
+        RECOMPUTED_COLLECTION_VOLUMES
+    ))
+    .bind::<Nullable<Array<Text>>, _>(collection_data_id_hashes)
+    .load::<CollectionVolumeComparison>(conn)?;
+    let tokens = sql_query(format!(
+        "WITH recomputed AS ({})
+        SELECT
+            token_data_id_hash AS subject,
+            COALESCE(c.volume, 0) AS recorded_volume,
+            COALESCE(r.volume, 0) AS recomputed_volume,
+            GREATEST(c.last_transaction_version, r.last_transaction_version)
+                AS last_transaction_version
+        FROM (SELECT * FROM current_token_volumes WHERE {}) c
+        FULL OUTER JOIN recomputed r USING (token_data_id_hash)
+        WHERE COALESCE(c.volume, 0) <> COALESCE(r.volume, 0)
+        ORDER BY subject",
+        recomputed_token_volumes(),
+        TOKEN_FILTER
+    ))
+    .bind::<Nullable<Array<Text>>, _>(collection_data_id_hashes)
+    .load::<TokenVolumeComparison>(conn)?;
+
+    VOLUME_DRIFT
+        .with_label_values(&[CURRENT_COLLECTION_VOLUMES])
+        .set(collections.len() as i64);
+    VOLUME_DRIFT
+        .with_label_values(&[CURRENT_TOKEN_VOLUMES])
+        .set(tokens.len() as i64);
+
+    let mut drift = vec![];
+    for row in collections {
+        for (column, recorded, recomputed) in [
+            ("volume", row.recorded_volume, row.recomputed_volume),
+            (
+                "primary_volume",
+                row.recorded_primary_volume,
+                row.recomputed_primary_volume,
+            ),
+            (
+                "secondary_volume",
+                row.recorded_secondary_volume,
+                row.recomputed_secondary_volume,
+            ),
+        ] {
+            if recorded != recomputed {
+                drift.push(VolumeDrift {
+                    table: CURRENT_COLLECTION_VOLUMES,
+                    subject: row.subject.clone(),
+                    column,
+                    recorded,
+                    recomputed,
+                    last_transaction_version: row.last_transaction_version,
+                });
+            }
+        }
+    }
+    drift.extend(tokens.into_iter().map(|row| VolumeDrift {
+        table: CURRENT_TOKEN_VOLUMES,
+        subject: row.subject,
+        column: "volume",
+        recorded: row.recorded_volume,
+        recomputed: row.recomputed_volume,
+        last_transaction_version: row.last_transaction_version,
+    }));
+    Ok(drift)
+}
+
+/// Replaces current volumes with the sum of their history, for every collection or only the
+/// given ones, and returns the number of (collection, token) rows written. Volume writes from a
+/// running processor wait until the rebuild commits.
+pub fn recompute_volumes(
+    conn: &mut PgConnection,
+    collection_data_id_hashes: Option<&[String]>,
+) -> QueryResult<(usize, usize)> {
+    conn.build_transaction()
+        .read_write()
+        .run::<_, Error, _>(|conn| {
+            sql_query(
+                "LOCK TABLE current_collection_volumes, current_token_volumes
+                IN SHARE ROW EXCLUSIVE MODE",
+            )
+            .execute(conn)?;
+            sql_query(
+                "DELETE FROM current_collection_volumes
+                WHERE $1::TEXT[] IS NULL OR collection_data_id_hash = ANY($1)",
+            )
+            .bind::<Nullable<Array<Text>>, _>(collection_data_id_hashes)
+            .execute(conn)?;
+            let num_collections = sql_query(format!(
+                "INSERT INTO current_collection_volumes (
+                    collection_data_id_hash, volume, primary_volume, secondary_volume,
+                    volume_usd, volume_decimal, last_transaction_version, last_transaction_timestamp
+                ) {}",
+                RECOMPUTED_COLLECTION_VOLUMES
+            ))
+            .bind::<Nullable<Array<Text>>, _>(collection_data_id_hashes)
+            .execute(conn)?;
+            sql_query(format!(
+                "DELETE FROM current_token_volumes WHERE {}",
+                TOKEN_FILTER
+            ))
+            .bind::<Nullable<Array<Text>>, _>(collection_data_id_hashes)
+            .execute(conn)?;
+            let num_tokens = sql_query(format!(
+                "INSERT INTO current_token_volumes (
+                    token_data_id_hash, volume, volume_decimal, last_transaction_version,
+                    last_transaction_timestamp
+                ) {}",
+                recomputed_token_volumes()
+            ))
+            .bind::<Nullable<Array<Text>>, _>(collection_data_id_hashes)
+            .execute(conn)?;
+            Ok((num_collections, num_tokens))
+        })
+}
+
+/// Number of collection_volumes rows from before volumes were keyed by event
+pub fn count_legacy_rows(conn: &mut PgConnection) -> QueryResult<i64> {
+    collection_volumes::table
+        .filter(collection_volumes::event_index.eq(-1))
+        .count()
+        .get_result(conn)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_utils::timestamp;
+    use crate::{
+        database::{new_db_pool, PgPoolConnection},
+        indexer::tailer::{test::wipe_database, MIGRATIONS},
+        models::token_models::collection_volume::{
+            CollectionVolume, CurrentCollectionVolume, TokenVolume,
+        },
+        schema::{current_collection_volumes, token_volumes},
+    };
+    use diesel_migrations::MigrationHarness;
+
+    fn setup() -> PgPoolConnection {
+        let database_url = std::env::var("INDEXER_DATABASE_URL")
+            .expect("must set 'INDEXER_DATABASE_URL' to run tests!");
+        let conn_pool = new_db_pool(database_url.as_str()).unwrap();
+        let mut conn = conn_pool.get().unwrap();
+        wipe_database(&mut conn);
+        conn.run_pending_migrations(MIGRATIONS).unwrap();
+        conn
+    }
+
+    fn sale(version: i64, event_index: i64, volume: i64, is_primary: bool) -> CollectionVolume {
+        CollectionVolume {
+            collection_data_id_hash: "potions".to_string(),
+            volume: BigDecimal::from(volume),
+            inserted_at: timestamp(),
+            last_transaction_version: version,
+            last_transaction_timestamp: timestamp(),
+            event_index,
+            token_index: 0,
+            is_primary,
+            volume_usd: None,
+            volume_decimal: None,
+            market_address: None,
+            coin_type: None,
+        }
+    }
+
+    #[test]
+    fn test_recompute_volumes_heals_drift() {
+        if crate::should_skip_pg_tests() {
+            return;
+        }
+        let mut conn = setup();
+        // Two sales in one transaction, which the current row only counted once
+        diesel::insert_into(collection_volumes::table)
+            .values(&vec![
+                sale(1, 0, 100, true),
+                sale(2, 0, 30, false),
+                sale(2, 3, 20, false),
+            ])
+            .execute(&mut conn)
+            .unwrap();
+        diesel::insert_into(token_volumes::table)
+            .values(&TokenVolume {
+                token_data_id_hash: "potion".to_string(),
+                volume: BigDecimal::from(100),
+                inserted_at: timestamp(),
+                last_transaction_version: 1,
+                last_transaction_timestamp: timestamp(),
+                event_index: 0,
+                token_index: 0,
+                volume_decimal: None,
+            })
+            .execute(&mut conn)
+            .unwrap();
+        diesel::insert_into(current_collection_volumes::table)
+            .values(&CurrentCollectionVolume {
+                collection_data_id_hash: "potions".to_string(),
+                volume: BigDecimal::from(120),
+                inserted_at: timestamp(),
+                last_transaction_version: 2,
+                last_transaction_timestamp: timestamp(),
+                primary_volume: BigDecimal::from(100),
+                secondary_volume: BigDecimal::from(20),
+                volume_usd: None,
+                volume_decimal: None,
+                last_event_index: Some(0),
+            })
+            .execute(&mut conn)
+            .unwrap();
+
+        let drift = find_volume_drift(&mut conn, None).unwrap();
+        assert_eq!(
+            drift
+                .iter()
+                .map(|drift| (drift.table, drift.column, drift.recomputed.clone()))
+                .collect::<Vec<_>>(),
+            vec![
+                (CURRENT_COLLECTION_VOLUMES, "volume", BigDecimal::from(150)),
+                (
+                    CURRENT_COLLECTION_VOLUMES,
+                    "secondary_volume",
+                    BigDecimal::from(50)
+                ),
+                (CURRENT_TOKEN_VOLUMES, "volume", BigDecimal::from(100)),
+            ]
+        );
+
+        assert_eq!(recompute_volumes(&mut conn, None).unwrap(), (1, 1));
+        assert!(find_volume_drift(&mut conn, None).unwrap().is_empty());
+        assert_eq!(count_legacy_rows(&mut conn).unwrap(), 0);
+    }
+}
@@ -0,0 +1,234 @@
+// Copyright (c) Aptos
+// SPDX-License-Identifier: Apache-2.0
+
+use super::{pruning::is_volume_history_pruned, volume_recompute::find_volume_drift};
+use crate::{
+    models::data_integrity_findings::DataIntegrityFinding,
+    schema::{current_collection_volumes, nft_sales},
+    util::u64_to_bigdecimal,
+};
+use anyhow::ensure;
+use aptos_config::config::VolumeReconciliationConfig;
+use bigdecimal::{BigDecimal, Zero};
+use diesel::{
+    dsl::{sql, sum},
+    sql_types::Double,
+    ExpressionMethods, PgConnection, QueryDsl, QueryResult, RunQueryDsl,
+};
+use std::{
+    collections::HashMap,
+    sync::atomic::{AtomicU64, Ordering},
+};
+
+pub const VOLUME_RECONCILIATION_CHECK: &str = "current_collection_volumes_vs_nft_sales";
+pub const DEFAULT_SAMPLE_SIZE: u64 = 100;
+
+type CollectionDataIdHash = String;
+
+/// Recomputes the volume of a random sample of collections from nft_sales and compares it
+/// against current_collection_volumes, which is maintained by additive upserts and so drifts
+/// permanently if a batch is ever double counted or dropped.
+///
+/// Collections with sales from before nft_sales was populated will be reported until nft_sales
+/// is backfilled.
+#[derive(Debug)]
+pub struct VolumeReconciliation {
+    every_n_batches: u64,
+    sample_size: i64,
+    tolerance: BigDecimal,
+    batches_seen: AtomicU64,
+}
+
+impl VolumeReconciliation {
+    pub fn from_config(
+        config: Option<&VolumeReconciliationConfig>,
+    ) -> anyhow::Result<Option<Self>> {
+        let config = match config {
+            Some(config) => config,
+            None => return Ok(None),
+        };
+        ensure!(
+            config.every_n_batches > 0,
+            "every_n_batches must be greater than 0"
+        );
+        let sample_size = config.sample_size.unwrap_or(DEFAULT_SAMPLE_SIZE);
+        ensure!(sample_size > 0, "sample_size must be greater than 0");
+        Ok(Some(Self {
+            every_n_batches: config.every_n_batches,
+            sample_size: sample_size as i64,
+            tolerance: u64_to_bigdecimal(config.tolerance.unwrap_or_default()),
+            batches_seen: AtomicU64::new(0),
+        }))
+    }
+
+    /// Counts a committed batch, returning true if the check should run after it
+    pub fn is_due(&self) -> bool {
+        let batches_seen = self.batches_seen.fetch_add(1, Ordering::Relaxed) + 1;
+        batches_seen % self.every_n_batches == 0
+    }
+
+    /// Samples current_collection_volumes, recomputes the sampled volumes from nft_sales and
+    /// returns a finding for every collection that differs by more than the tolerance. The sample
+    /// is also checked against the volume history, see `find_volume_drift`.
+    pub fn run(
+        &self,
+        conn: &mut PgConnection,
+        txn_version: i64,
+    ) -> QueryResult<Vec<DataIntegrityFinding>> {
+        let recorded = Self::sample_recorded_volumes(conn, self.sample_size)?;
+        let collection_data_id_hashes = recorded.keys().cloned().collect::<Vec<_>>();
+        let recomputed = Self::recompute_volumes(conn, &collection_data_id_hashes)?;
+        // Also sets the drift gauges, as a canary for bugs in the additive upserts
+        if !is_volume_history_pruned(conn)? {
+            let history_drift =
+                find_volume_drift(conn, Some(collection_data_id_hashes.as_slice()))?;
+            if !history_drift.is_empty() {
+                aptos_logger::warn!(
+                    txn_version = txn_version,
+                    num_rows = history_drift.len(),
+                    "Current volumes drifted from collection_volumes and token_volumes"
+                );
+            }
+        }
+        Ok(self.find_drift(&recorded, &recomputed, txn_version))
+    }
+
+    fn sample_recorded_volumes(
+        conn: &mut PgConnection,
+        sample_size: i64,
+    ) -> QueryResult<HashMap<CollectionDataIdHash, BigDecimal>> {
+        let rows = current_collection_volumes::table
+            .select((
+                current_collection_volumes::collection_data_id_hash,
+                current_collection_volumes::volume,
+            ))
+            .order(sql::<Double>("RANDOM()"))
+            .limit(sample_size)
+            .load::<(String, BigDecimal)>(conn)?;
+        Ok(rows.into_iter().collect())
+    }
+
+    /// Same rule as current_collection_volumes, i.e. the sum of sale prices without a suspect value
+    fn recompute_volumes(
+        conn: &mut PgConnection,
+        collection_data_id_hashes: &[String],
+    ) -> QueryResult<HashMap<CollectionDataIdHash, BigDecimal>> {
+        let rows = nft_sales::table
+            .filter(nft_sales::collection_data_id_hash.eq_any(collection_data_id_hashes))
+            .filter(nft_sales::suspect_value.eq(false))
+            .group_by(nft_sales::collection_data_id_hash)
+            .select((nft_sales::collection_data_id_hash, sum(nft_sales::price)))
+            .load::<(String, Option<BigDecimal>)>(conn)?;
+        Ok(rows
+            .into_iter()
+            .map(|(collection_data_id_hash, volume)| {
+                (
+                    collection_data_id_hash,
+                    volume.unwrap_or_else(BigDecimal::zero),
+                )
+            })
+            .collect())
+    }
+
+    /// A sampled collection without any sales recomputes to 0
+    pub fn find_drift(
+        &self,
+        recorded: &HashMap<CollectionDataIdHash, BigDecimal>,
+        recomputed: &HashMap<CollectionDataIdHash, BigDecimal>,
+        txn_version: i64,
+    ) -> Vec<DataIntegrityFinding> {
+        let mut findings = recorded
+            .iter()
+            .filter_map(|(collection_data_id_hash, actual)| {
+                let expected = recomputed
+                    .get(collection_data_id_hash)
+                    .cloned()
+                    .unwrap_or_else(BigDecimal::zero);
+                let delta = actual - &expected;
+                if delta.abs() <= self.tolerance {
+                    return None;
+                }
+                Some(DataIntegrityFinding {
+                    check_name: VOLUME_RECONCILIATION_CHECK.to_string(),
+                    subject: collection_data_id_hash.clone(),
+                    transaction_version: txn_version,
+                    expected,
+                    actual: actual.clone(),
+                    delta,
+                    offending_version: None,
+                })
+            })
+            .collect::<Vec<_>>();
+        findings.sort_by(|a, b| a.subject.cmp(&b.subject));
+        findings
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn reconciliation(tolerance: u64) -> VolumeReconciliation {
+        VolumeReconciliation::from_config(Some(&VolumeReconciliationConfig {
+            every_n_batches: 3,
+            sample_size: None,
+            tolerance: Some(tolerance),
+        }))
+        .unwrap()
+        .unwrap()
+    }
+
+    fn volumes(volumes: &[(&str, u64)]) -> HashMap<CollectionDataIdHash, BigDecimal> {
+        volumes
+            .iter()
+            .map(|(hash, volume)| (hash.to_string(), BigDecimal::from(*volume)))
+            .collect()
+    }
+
+    #[test]
+    fn test_every_nth_batch() {
+        let reconciliation = reconciliation(0);
+        let due = (0..6).map(|_| reconciliation.is_due()).collect::<Vec<_>>();
+        assert_eq!(due, vec![false, false, true, false, false, true]);
+    }
+
+    #[test]
+    fn test_invalid_config() {
+        assert!(VolumeReconciliation::from_config(None).unwrap().is_none());
+        assert!(
+            VolumeReconciliation::from_config(Some(&VolumeReconciliationConfig {
+                every_n_batches: 0,
+                sample_size: None,
+                tolerance: None,
+            }))
+            .is_err()
+        );
+    }
+
+    #[test]
+    fn test_drift_is_flagged() {
+        let recomputed = volumes(&[("a", 100), ("b", 200), ("c", 300)]);
+        // "b" was double counted, "c" lost a sale within tolerance, "d" has no sales at all
+        let recorded = volumes(&[("a", 100), ("b", 400), ("c", 295), ("d", 50)]);
+
+        let findings = reconciliation(10).find_drift(&recorded, &recomputed, 42);
+        assert_eq!(
+            findings
+                .iter()
+                .map(|finding| (finding.subject.as_str(), finding.delta.clone()))
+                .collect::<Vec<_>>(),
+            vec![("b", BigDecimal::from(200)), ("d", BigDecimal::from(50))]
+        );
+        assert_eq!(findings[0].expected, BigDecimal::from(200));
+        assert_eq!(findings[0].actual, BigDecimal::from(400));
+        assert_eq!(findings[0].transaction_version, 42);
+        assert_eq!(findings[0].check_name, VOLUME_RECONCILIATION_CHECK);
+
+        assert_eq!(
+            reconciliation(0)
+                .find_drift(&recorded, &recomputed, 42)
+                .len(),
+            3
+        );
+    }
+}
@@ -0,0 +1,386 @@
+// Copyright (c) Aptos
+// SPDX-License-Identifier: Apache-2.0
+
+// This is required because a diesel macro makes clippy sad
+#![allow(clippy::extra_unused_lifetimes)]
+#![allow(clippy::unused_unit)]
+
+use super::{
+    collection_mints::CollectionMint,
+    collection_reports::{canonicalize_coin_type, DEFAULT_COIN_TYPE},
+    nft_sales::NftSale,
+    token_activities::TokenActivity,
+};
+use crate::{database::PgPoolConnection, schema::wallet_token_cost_basis};
+use bigdecimal::{BigDecimal, One, Signed};
+use diesel::{ExpressionMethods, QueryDsl, QueryResult, RunQueryDsl};
+use field_count::FieldCount;
+use serde::{Deserialize, Serialize};
+use std::collections::{BTreeMap, HashMap, HashSet};
+
+const DEPOSIT_EVENT_TYPE: &str = "0x3::token::DepositEvent";
+
+/// (wallet_address, token_data_id_hash)
+pub type WalletTokenPK = (String, String);
+
+/// What a wallet last paid for a token, which is the basis for the realized pnl of its next sale
+/// of that token. For semi-fungible tokens the basis is spread evenly over the amount bought, and
+/// a new purchase replaces the basis of everything the wallet already held.
+#[derive(Clone, Debug, Deserialize, FieldCount, Identifiable, Insertable, Serialize)]
+#[diesel(primary_key(wallet_address, token_data_id_hash))]
+#[diesel(table_name = wallet_token_cost_basis)]
+pub struct WalletTokenCostBasis {
+    pub wallet_address: String,
+    pub token_data_id_hash: String,
+    /// Octas paid for `amount`. None if the token was received via transfer, bought with another
+    /// coin or minted at an unknown price, in which case its sales have no realized pnl.
+    pub cost_basis: Option<BigDecimal>,
+    pub amount: BigDecimal,
+    pub last_transaction_version: i64,
+}
+
+/// Trades of a single transaction that change cost basis
+#[derive(Default)]
+struct TransactionTrades<'a> {
+    sales: Vec<usize>,
+    mints: Vec<&'a CollectionMint>,
+    deposits: Vec<&'a TokenActivity>,
+}
+
+impl WalletTokenCostBasis {
+    /// Sets realized_pnl on the batch's sales and returns the cost basis rows they changed.
+    /// The basis of sellers is looked up in the db if it wasn't set earlier in the batch. This
+    /// relies on the token processor committing its batches one at a time in version order (see
+    /// `run_processor`), so the db has every basis set before the batch. Rows from later versions,
+    /// left by a reprocessed batch, are ignored.
+    pub fn from_batch(
+        conn: &mut PgPoolConnection,
+        nft_sales: &mut [NftSale],
+        collection_mints: &[CollectionMint],
+        token_activities: &[TokenActivity],
+    ) -> QueryResult<HashMap<WalletTokenPK, Self>> {
+        let previous = Self::load_seller_basis(conn, nft_sales)?;
+        Ok(Self::apply_trades(
+            previous,
+            nft_sales,
+            collection_mints,
+            token_activities,
+        ))
+    }
+
+    fn load_seller_basis(
+        conn: &mut PgPoolConnection,
+        nft_sales: &[NftSale],
+    ) -> QueryResult<HashMap<WalletTokenPK, Self>> {
+        let (wallet_addresses, token_data_id_hashes): (HashSet<String>, HashSet<String>) =
+            nft_sales
+                .iter()
+                .filter_map(|sale| Some((sale.seller.clone()?, sale.token_data_id_hash.clone())))
+                .unzip();
+        if wallet_addresses.is_empty() {
+            return Ok(HashMap::new());
+        }
+        // This can return rows for other pairs of the same wallets and tokens, which go unused
+        let rows = wallet_token_cost_basis::table
+            .select((
+                wallet_token_cost_basis::wallet_address,
+                wallet_token_cost_basis::token_data_id_hash,
+                wallet_token_cost_basis::cost_basis,
+                wallet_token_cost_basis::amount,
+                wallet_token_cost_basis::last_transaction_version,
+            ))
+            .filter(wallet_token_cost_basis::wallet_address.eq_any(wallet_addresses))
+            .filter(wallet_token_cost_basis::token_data_id_hash.eq_any(token_data_id_hashes))
+            .load::<(String, String, Option<BigDecimal>, BigDecimal, i64)>(conn)?;
+        Ok(rows
+            .into_iter()
+            .map(
+                |(wallet_address, token_data_id_hash, cost_basis, amount, txn_version)| {
+                    (
+                        (wallet_address.clone(), token_data_id_hash.clone()),
+                        Self {
+                            wallet_address,
+                            token_data_id_hash,
+                            cost_basis,
+                            amount,
+                            last_transaction_version: txn_version,
+                        },
+                    )
+                },
+            )
+            .collect())
+    }
+
+    /// Walks the trades in version order. Within a transaction, sales use the basis from before
+    /// the transaction, then purchases and mints set a new basis, and deposits that aren't
+    /// explained by either are transfers, which clear it.
+    fn apply_trades(
+        mut basis: HashMap<WalletTokenPK, Self>,
+        nft_sales: &mut [NftSale],
+        collection_mints: &[CollectionMint],
+        token_activities: &[TokenActivity],
+    ) -> HashMap<WalletTokenPK, Self> {
+        let mut trades: BTreeMap<i64, TransactionTrades> = BTreeMap::new();
+        for (index, sale) in nft_sales.iter().enumerate() {
+            trades
+                .entry(sale.transaction_version)
+                .or_default()
+                .sales
+                .push(index);
+        }
+        for mint in collection_mints {
+            trades
+                .entry(mint.transaction_version)
+                .or_default()
+                .mints
+                .push(mint);
+        }
+        for activity in token_activities {
+            if activity.transfer_type == DEPOSIT_EVENT_TYPE {
+                trades
+                    .entry(activity.transaction_version)
+                    .or_default()
+                    .deposits
+                    .push(activity);
+            }
+        }
+
+        let mut changed: HashMap<WalletTokenPK, Self> = HashMap::new();
+        for (txn_version, trades) in trades {
+            for index in &trades.sales {
+                let sale = &nft_sales[*index];
+                let realized_pnl = sale.seller.as_ref().and_then(|seller| {
+                    let previous = basis.get(&(seller.clone(), sale.token_data_id_hash.clone()))?;
+                    // Rows written by a later version (ex: when reprocessing) don't apply
+                    if previous.last_transaction_version >= txn_version {
+                        return None;
+                    }
+                    previous.realized_pnl(sale)
+                });
+                nft_sales[*index].realized_pnl = realized_pnl;
+            }
+
+            let mut acquired = vec![];
+            for index in &trades.sales {
+                let sale = &nft_sales[*index];
+                if let Some(buyer) = &sale.buyer {
+                    let cost_basis = match &sale.price {
+                        Some(price) if is_apt(sale) => Some(price.clone()),
+                        _ => None,
+                    };
+                    acquired.push((
+                        (buyer.clone(), sale.token_data_id_hash.clone()),
+                        cost_basis,
+                        units(&sale.token_amount),
+                    ));
+                }
+            }
+            for mint in &trades.mints {
+                acquired.push((
+                    (mint.minter_address.clone(), mint.token_data_id_hash.clone()),
+                    (!mint.is_price_unknown).then(|| mint.price.clone()),
+                    units(&mint.amount),
+                ));
+            }
+            for deposit in &trades.deposits {
+                if let Some(wallet_address) = &deposit.to_address {
+                    let pk = (wallet_address.clone(), deposit.token_data_id_hash.clone());
+                    if !acquired.iter().any(|(acquired_pk, ..)| acquired_pk == &pk) {
+                        acquired.push((pk, None, units(&deposit.token_amount)));
+                    }
+                }
+            }
+
+            for (pk, cost_basis, amount) in acquired {
+                let row = Self {
+                    wallet_address: pk.0.clone(),
+                    token_data_id_hash: pk.1.clone(),
+                    cost_basis,
+                    amount,
+                    last_transaction_version: txn_version,
+                };
+                basis.insert(pk.clone(), row.clone());
+                changed.insert(pk, row);
+            }
+        }
+        changed
+    }
+
+    /// Sale price minus the basis of the amount sold, in whole octas
+    fn realized_pnl(&self, sale: &NftSale) -> Option<BigDecimal> {
+        let cost_basis = self.cost_basis.as_ref()?;
+        let price = sale.price.as_ref().filter(|_| is_apt(sale))?;
+        let sold_basis = cost_basis * units(&sale.token_amount) / &self.amount;
+        Some((price - sold_basis).with_scale(0))
+    }
+}
+
+fn is_apt(sale: &NftSale) -> bool {
+    canonicalize_coin_type(sale.coin_type.as_deref()) == DEFAULT_COIN_TYPE
+}
+
+/// Some marketplace events don't carry the amount, in which case it's a single token
+fn units(amount: &BigDecimal) -> BigDecimal {
+    if amount.is_positive() {
+        amount.clone()
+    } else {
+        BigDecimal::one()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_utils::timestamp;
+    use bigdecimal::Zero;
+
+    const CREATOR: &str = "0xc4e7";
+
+    fn sale(version: i64, seller: &str, buyer: &str, amount: u64, price: u64) -> NftSale {
+        NftSale {
+            transaction_version: version,
+            event_account_address: "0x2c7b".to_string(),
+            event_creation_number: 0,
+            event_sequence_number: version,
+            event_index: 0,
+            token_index: 0,
+            market_address: "0x2c7b".to_string(),
+            event_type: "0x2c7b::events::BuyEvent".to_string(),
+            token_data_id_hash: "token".to_string(),
+            property_version: BigDecimal::zero(),
+            collection_data_id_hash: "collection".to_string(),
+            creator_address: CREATOR.to_string(),
+            collection_name: "Potions".to_string(),
+            name: "Potion".to_string(),
+            seller: Some(seller.to_string()),
+            buyer: Some(buyer.to_string()),
+            token_amount: BigDecimal::from(amount),
+            coin_type: None,
+            price: Some(BigDecimal::from(price)),
+            gas_unit_price: BigDecimal::from(100),
+            transaction_rank_in_block: None,
+            transaction_timestamp: timestamp(),
+            is_primary: false,
+            realized_pnl: None,
+            hold_duration_secs: None,
+            source: "event".to_string(),
+            settlement_amount: None,
+            coin_price_usd: None,
+            price_usd: None,
+            price_decimal: None,
+            sale_group_id: version,
+            group_size: 1,
+            domain: None,
+            suspect_value: false,
+        }
+    }
+
+    fn mint(version: i64, minter: &str, amount: u64, price: Option<u64>) -> CollectionMint {
+        CollectionMint {
+            transaction_version: version,
+            event_index: 0,
+            token_data_id_hash: "token".to_string(),
+            collection_data_id_hash: "collection".to_string(),
+            creator_address: CREATOR.to_string(),
+            collection_name: "Potions".to_string(),
+            name: "Potion".to_string(),
+            minter_address: minter.to_string(),
+            amount: BigDecimal::from(amount),
+            price: BigDecimal::from(price.unwrap_or_default()),
+            is_price_unknown: price.is_none(),
+            transaction_timestamp: timestamp(),
+            launchpad: None,
+        }
+    }
+
+    fn deposit(version: i64, wallet_address: &str, amount: u64) -> TokenActivity {
+        TokenActivity {
+            transaction_version: version,
+            event_account_address: wallet_address.to_string(),
+            event_creation_number: 1,
+            event_sequence_number: version,
+            event_index: 0,
+            token_index: 0,
+            token_data_id_hash: "token".to_string(),
+            property_version: BigDecimal::zero(),
+            creator_address: CREATOR.to_string(),
+            collection_name: "Potions".to_string(),
+            name: "Potion".to_string(),
+            transfer_type: DEPOSIT_EVENT_TYPE.to_string(),
+            transfer_kind: Some("transfer".to_string()),
+            domain: None,
+            from_address: None,
+            to_address: Some(wallet_address.to_string()),
+            token_amount: BigDecimal::from(amount),
+            coin_type: None,
+            coin_amount: None,
+            suspect_value: false,
+            collection_data_id_hash: "collection".to_string(),
+            transaction_timestamp: timestamp(),
+        }
+    }
+
+    fn pnls(sales: &[NftSale]) -> Vec<Option<BigDecimal>> {
+        sales.iter().map(|sale| sale.realized_pnl.clone()).collect()
+    }
+
+    #[test]
+    fn test_realized_pnl() {
+        let mut sales = vec![
+            // Bought for 300, then sold for 500
+            sale(2, CREATOR, "0xb0b", 1, 300),
+            sale(4, "0xb0b", "0xca7", 1, 500),
+            // Minted for 100, then sold at a loss
+            sale(6, "0xa11ce", "0xb0b", 1, 80),
+        ];
+        let changed = WalletTokenCostBasis::apply_trades(
+            HashMap::new(),
+            &mut sales,
+            &[mint(5, "0xa11ce", 1, Some(100))],
+            &[deposit(2, "0xb0b", 1), deposit(5, "0xa11ce", 1)],
+        );
+        assert_eq!(
+            pnls(&sales),
+            vec![
+                None,
+                Some(BigDecimal::from(200)),
+                Some(BigDecimal::from(-20)),
+            ]
+        );
+        let bob = &changed[&("0xb0b".to_string(), "token".to_string())];
+        assert_eq!(bob.cost_basis, Some(BigDecimal::from(80)));
+        assert_eq!(bob.last_transaction_version, 6);
+    }
+
+    #[test]
+    fn test_transfers_and_partial_amounts() {
+        let previous = HashMap::from([(
+            ("0xb0b".to_string(), "token".to_string()),
+            WalletTokenCostBasis {
+                wallet_address: "0xb0b".to_string(),
+                token_data_id_hash: "token".to_string(),
+                cost_basis: Some(BigDecimal::from(400)),
+                amount: BigDecimal::from(4),
+                last_transaction_version: 1,
+            },
+        )]);
+        let mut sales = vec![
+            // A quarter of the basis is realized
+            sale(3, "0xb0b", "0xca7", 1, 150),
+            // 0xca7 received more via transfer, so nothing is realized
+            sale(6, "0xca7", "0xa11ce", 2, 300),
+            // Free mints with an unknown price have no basis either
+            sale(8, "0xd00d", "0xa11ce", 1, 300),
+        ];
+        let changed = WalletTokenCostBasis::apply_trades(
+            previous,
+            &mut sales,
+            &[mint(7, "0xd00d", 1, None)],
+            &[deposit(3, "0xca7", 1), deposit(5, "0xca7", 1)],
+        );
+        assert_eq!(pnls(&sales), vec![Some(BigDecimal::from(50)), None, None]);
+        let cat = &changed[&("0xca7".to_string(), "token".to_string())];
+        assert_eq!(cat.cost_basis, None);
+        assert_eq!(cat.last_transaction_version, 5);
+    }
+}
@@ -0,0 +1,194 @@
+// Copyright (c) Aptos
+// SPDX-License-Identifier: Apache-2.0
+
+// This is required because a diesel macro makes clippy sad
+#![allow(clippy::extra_unused_lifetimes)]
+#![allow(clippy::unused_unit)]
+
+use super::{
+    collection_reports::{canonicalize_coin_type, DEFAULT_COIN_TYPE},
+    nft_sales::NftSale,
+};
+use crate::schema::current_wallet_nft_stats;
+use bigdecimal::{BigDecimal, Zero};
+use field_count::FieldCount;
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+
+type WalletAddress = String;
+
+/// Trading stats per wallet, from the buyer and seller side of every sale. Volumes are in octas
+/// and only include sales priced in APT, while the counts include every sale.
+#[derive(
+    Debug, Deserialize, FieldCount, Identifiable, Insertable, Queryable, Selectable, Serialize,
+)]
+#[diesel(primary_key(wallet_address))]
+#[diesel(table_name = current_wallet_nft_stats)]
+pub struct CurrentWalletNftStat {
+    pub wallet_address: String,
+    pub buy_count: i64,
+    pub sell_count: i64,
+    pub buy_volume: BigDecimal,
+    pub sell_volume: BigDecimal,
+    pub first_trade_version: i64,
+    pub last_trade_version: i64,
+    /// Sum of the realized pnl of the wallet's sales that have a known cost basis
+    pub realized_pnl: BigDecimal,
+}
+
+impl CurrentWalletNftStat {
+    fn new(wallet_address: &str, txn_version: i64) -> Self {
+        Self {
+            wallet_address: wallet_address.to_string(),
+            buy_count: 0,
+            sell_count: 0,
+            buy_volume: BigDecimal::zero(),
+            sell_volume: BigDecimal::zero(),
+            first_trade_version: txn_version,
+            last_trade_version: txn_version,
+            realized_pnl: BigDecimal::zero(),
+        }
+    }
+
+    /// Aggregates a batch of sales per wallet. Buyer and seller are the addresses parsed from the
+    /// sale event rather than the transaction sender, which for aggregators and collection bid
+    /// fills isn't the party that bought or sold. Sales with a suspect value aren't counted.
+    pub fn from_nft_sales<'a>(
+        nft_sales: impl IntoIterator<Item = &'a NftSale>,
+    ) -> BTreeMap<WalletAddress, Self> {
+        let mut stats: BTreeMap<WalletAddress, Self> = BTreeMap::new();
+        for sale in nft_sales.into_iter().filter(|sale| !sale.suspect_value) {
+            let apt_price = match &sale.price {
+                Some(price)
+                    if canonicalize_coin_type(sale.coin_type.as_deref()) == DEFAULT_COIN_TYPE =>
+                {
+                    price.clone()
+                }
+                _ => BigDecimal::zero(),
+            };
+            if let Some(buyer) = &sale.buyer {
+                let stat = Self::get_or_insert(&mut stats, buyer, sale.transaction_version);
+                stat.buy_count += 1;
+                stat.buy_volume += &apt_price;
+            }
+            if let Some(seller) = &sale.seller {
+                let stat = Self::get_or_insert(&mut stats, seller, sale.transaction_version);
+                stat.sell_count += 1;
+                stat.sell_volume += &apt_price;
+                if let Some(realized_pnl) = &sale.realized_pnl {
+                    stat.realized_pnl += realized_pnl;
+                }
+            }
+        }
+        stats
+    }
+
+    fn get_or_insert<'a>(
+        stats: &'a mut BTreeMap<WalletAddress, Self>,
+        wallet_address: &str,
+        txn_version: i64,
+    ) -> &'a mut Self {
+        let stat = stats
+            .entry(wallet_address.to_string())
+            .or_insert_with(|| Self::new(wallet_address, txn_version));
+        stat.first_trade_version = stat.first_trade_version.min(txn_version);
+        stat.last_trade_version = stat.last_trade_version.max(txn_version);
+        stat
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn sale(
+        version: i64,
+        seller: Option<&str>,
+        buyer: Option<&str>,
+        coin_type: Option<&str>,
+        price: u64,
+    ) -> NftSale {
+        NftSale {
+            transaction_version: version,
+            event_account_address: "0x2c7b".to_string(),
+            event_creation_number: 0,
+            event_sequence_number: version,
+            event_index: 0,
+            token_index: 0,
+            market_address: "0x2c7b".to_string(),
+            event_type: "0x2c7b::events::BuyEvent".to_string(),
+            token_data_id_hash: "token".to_string(),
+            property_version: BigDecimal::zero(),
+            collection_data_id_hash: "collection".to_string(),
+            creator_address: "0xc4e7".to_string(),
+            collection_name: "Monkeys".to_string(),
+            name: "Monkey #1".to_string(),
+            seller: seller.map(|seller| seller.to_string()),
+            buyer: buyer.map(|buyer| buyer.to_string()),
+            token_amount: BigDecimal::from(1),
+            coin_type: coin_type.map(|coin_type| coin_type.to_string()),
+            price: Some(BigDecimal::from(price)),
+            gas_unit_price: BigDecimal::from(100),
+            transaction_rank_in_block: None,
+            transaction_timestamp: chrono::NaiveDateTime::from_timestamp(1668000000, 0),
+            is_primary: false,
+            realized_pnl: None,
+            hold_duration_secs: None,
+            source: "event".to_string(),
+            settlement_amount: None,
+            coin_price_usd: None,
+            price_usd: None,
+            price_decimal: None,
+            sale_group_id: version,
+            group_size: 1,
+            domain: None,
+            suspect_value: false,
+        }
+    }
+
+    #[test]
+    fn test_both_sides_of_sales() {
+        let mut sales = vec![
+            sale(5, Some("0xa11ce"), Some("0xb0b"), None, 300),
+            sale(
+                9,
+                Some("0xb0b"),
+                Some("0xca7"),
+                Some("0x1::aptos_coin::AptosCoin"),
+                500,
+            ),
+            // Not priced in APT, so only counted
+            sale(
+                7,
+                Some("0xca7"),
+                Some("0xb0b"),
+                Some("0x5e1f::usdc::USDC"),
+                20,
+            ),
+            // Collection bid fill where the seller isn't known
+            sale(8, None, Some("0xa11ce"), None, 100),
+        ];
+        sales[1].realized_pnl = Some(BigDecimal::from(200));
+        let stats = CurrentWalletNftStat::from_nft_sales(&sales);
+        assert_eq!(stats.len(), 3);
+
+        let bob = &stats["0xb0b"];
+        assert_eq!((bob.buy_count, bob.sell_count), (2, 1));
+        assert_eq!(bob.buy_volume, BigDecimal::from(300));
+        assert_eq!(bob.sell_volume, BigDecimal::from(500));
+        assert_eq!((bob.first_trade_version, bob.last_trade_version), (5, 9));
+        assert_eq!(bob.realized_pnl, BigDecimal::from(200));
+
+        let alice = &stats["0xa11ce"];
+        assert_eq!((alice.buy_count, alice.sell_count), (1, 1));
+        assert_eq!(alice.buy_volume, BigDecimal::from(100));
+        assert_eq!(
+            (alice.first_trade_version, alice.last_trade_version),
+            (5, 8)
+        );
+
+        let cat = &stats["0xca7"];
+        assert_eq!(cat.sell_volume, BigDecimal::zero());
+        assert_eq!((cat.first_trade_version, cat.last_trade_version), (7, 9));
+    }
+}
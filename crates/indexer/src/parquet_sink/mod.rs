@@ -0,0 +1,104 @@
+// Copyright (c) Aptos
+// SPDX-License-Identifier: Apache-2.0
+
+//! Copies the token_activities and nft_sales rows of the batches the token processor commits to
+//! Parquet files, for analytics that would otherwise scan postgres. Files are laid out as
+//! `<table>/date=<transaction date>/part-<first version>-<last version>-<written at>.parquet`,
+//! and every table has a `<table>/_manifest.json` listing its complete files. Readers go through
+//! the manifest: a file is only added to it once it's fully uploaded, so one the indexer was
+//! killed in the middle of is never picked up.
+//!
+//! The export is best effort. Committed rows wait in a bounded queue for the writer, which drops
+//! them when it's behind rather than slowing the processor down, and rows of files that aren't
+//! closed yet are lost when the indexer stops. Batches that are reprocessed are written again,
+//! so readers dedupe on the table's primary key.
+//!
+//! Only the handle the processor sends committed rows through is built without the
+//! `parquet-sink` feature.
+
+#[cfg(feature = "parquet-sink")]
+mod schema;
+#[cfg(feature = "parquet-sink")]
+mod writer;
+
+#[cfg(feature = "parquet-sink")]
+pub use writer::ParquetSinkOptions;
+
+use crate::{
+    counters::PARQUET_SINK_ROWS,
+    models::token_models::{nft_sales::NftSale, token_activities::TokenActivity},
+};
+use aptos_config::config::ParquetSinkConfig;
+use tokio::sync::mpsc::{self, error::TrySendError};
+
+pub const TOKEN_ACTIVITIES_TABLE: &str = "token_activities";
+pub const NFT_SALES_TABLE: &str = "nft_sales";
+
+/// Rows of a committed batch
+#[derive(Debug, Default)]
+pub struct CommittedRows {
+    pub token_activities: Vec<TokenActivity>,
+    pub nft_sales: Vec<NftSale>,
+}
+
+#[derive(Clone, Debug)]
+pub struct ParquetSink {
+    sender: mpsc::Sender<CommittedRows>,
+}
+
+impl ParquetSink {
+    /// Hands the rows of a committed batch to the writer without waiting for it
+    pub fn send(&self, rows: CommittedRows) {
+        if let Err(err) = self.sender.try_send(rows) {
+            let rows = match err {
+                TrySendError::Full(rows) | TrySendError::Closed(rows) => rows,
+            };
+            aptos_logger::warn!(
+                token_activities = rows.token_activities.len(),
+                nft_sales = rows.nft_sales.len(),
+                "Parquet sink is behind, dropped the rows of a committed batch"
+            );
+            PARQUET_SINK_ROWS
+                .with_label_values(&[TOKEN_ACTIVITIES_TABLE, "dropped"])
+                .inc_by(rows.token_activities.len() as u64);
+            PARQUET_SINK_ROWS
+                .with_label_values(&[NFT_SALES_TABLE, "dropped"])
+                .inc_by(rows.nft_sales.len() as u64);
+        }
+    }
+}
+
+/// Checks the parquet_sink config without touching its location
+#[cfg(feature = "parquet-sink")]
+pub fn check_parquet_sink(config: Option<&ParquetSinkConfig>) -> anyhow::Result<()> {
+    ParquetSinkOptions::from_config(config)?;
+    Ok(())
+}
+
+#[cfg(not(feature = "parquet-sink"))]
+pub fn check_parquet_sink(config: Option<&ParquetSinkConfig>) -> anyhow::Result<()> {
+    anyhow::ensure!(
+        config.is_none(),
+        "parquet_sink needs the indexer built with the parquet-sink feature"
+    );
+    Ok(())
+}
+
+/// Starts the writer if the config asks for one. Has to be called within the tokio runtime.
+#[cfg(feature = "parquet-sink")]
+pub fn spawn_parquet_sink(
+    config: Option<&ParquetSinkConfig>,
+) -> anyhow::Result<Option<ParquetSink>> {
+    match ParquetSinkOptions::from_config(config)? {
+        Some(options) => Ok(Some(writer::spawn(options)?)),
+        None => Ok(None),
+    }
+}
+
+#[cfg(not(feature = "parquet-sink"))]
+pub fn spawn_parquet_sink(
+    config: Option<&ParquetSinkConfig>,
+) -> anyhow::Result<Option<ParquetSink>> {
+    check_parquet_sink(config)?;
+    Ok(None)
+}
@@ -0,0 +1,337 @@
+// Copyright (c) Aptos
+// SPDX-License-Identifier: Apache-2.0
+
+//! Arrow schemas of the exported models, derived from the fields of their structs. Numeric
+//! columns are decimal(38, 18), which holds any u64 amount along with 18 decimals of prices in
+//! coins and USD. Finer digits are truncated.
+
+use super::{NFT_SALES_TABLE, TOKEN_ACTIVITIES_TABLE};
+use crate::models::token_models::{nft_sales::NftSale, token_activities::TokenActivity};
+use arrow::{
+    array::{
+        ArrayRef, BooleanArray, Decimal128Array, Int64Array, StringArray, TimestampMicrosecondArray,
+    },
+    datatypes::{DataType, Field, Schema, SchemaRef, TimeUnit},
+    error::ArrowError,
+    record_batch::RecordBatch,
+};
+use bigdecimal::{BigDecimal, ToPrimitive};
+use std::sync::Arc;
+
+pub const DECIMAL_PRECISION: u8 = 38;
+pub const DECIMAL_SCALE: i8 = 18;
+
+/// A Rust field type and the Arrow column it's written as
+pub trait ParquetColumn {
+    fn data_type() -> DataType;
+
+    fn nullable() -> bool {
+        false
+    }
+
+    fn to_array(values: Vec<&Self>) -> Result<ArrayRef, ArrowError>;
+}
+
+impl ParquetColumn for i64 {
+    fn data_type() -> DataType {
+        DataType::Int64
+    }
+
+    fn to_array(values: Vec<&Self>) -> Result<ArrayRef, ArrowError> {
+        Ok(Arc::new(Int64Array::from(
+            values.into_iter().copied().collect::<Vec<i64>>(),
+        )))
+    }
+}
+
+impl ParquetColumn for Option<i64> {
+    fn data_type() -> DataType {
+        DataType::Int64
+    }
+
+    fn nullable() -> bool {
+        true
+    }
+
+    fn to_array(values: Vec<&Self>) -> Result<ArrayRef, ArrowError> {
+        Ok(Arc::new(Int64Array::from(
+            values.into_iter().copied().collect::<Vec<Option<i64>>>(),
+        )))
+    }
+}
+
+impl ParquetColumn for bool {
+    fn data_type() -> DataType {
+        DataType::Boolean
+    }
+
+    fn to_array(values: Vec<&Self>) -> Result<ArrayRef, ArrowError> {
+        Ok(Arc::new(BooleanArray::from(
+            values.into_iter().copied().collect::<Vec<bool>>(),
+        )))
+    }
+}
+
+impl ParquetColumn for String {
+    fn data_type() -> DataType {
+        DataType::Utf8
+    }
+
+    fn to_array(values: Vec<&Self>) -> Result<ArrayRef, ArrowError> {
+        Ok(Arc::new(StringArray::from(
+            values
+                .into_iter()
+                .map(|value| value.as_str())
+                .collect::<Vec<&str>>(),
+        )))
+    }
+}
+
+impl ParquetColumn for Option<String> {
+    fn data_type() -> DataType {
+        DataType::Utf8
+    }
+
+    fn nullable() -> bool {
+        true
+    }
+
+    fn to_array(values: Vec<&Self>) -> Result<ArrayRef, ArrowError> {
+        Ok(Arc::new(StringArray::from(
+            values
+                .into_iter()
+                .map(|value| value.as_deref())
+                .collect::<Vec<Option<&str>>>(),
+        )))
+    }
+}
+
+impl ParquetColumn for chrono::NaiveDateTime {
+    fn data_type() -> DataType {
+        DataType::Timestamp(TimeUnit::Microsecond, None)
+    }
+
+    fn to_array(values: Vec<&Self>) -> Result<ArrayRef, ArrowError> {
+        Ok(Arc::new(TimestampMicrosecondArray::from(
+            values
+                .into_iter()
+                .map(|value| value.timestamp() * 1_000_000 + value.timestamp_subsec_micros() as i64)
+                .collect::<Vec<i64>>(),
+        )))
+    }
+}
+
+impl ParquetColumn for BigDecimal {
+    fn data_type() -> DataType {
+        DataType::Decimal128(DECIMAL_PRECISION, DECIMAL_SCALE)
+    }
+
+    fn to_array(values: Vec<&Self>) -> Result<ArrayRef, ArrowError> {
+        let values = values
+            .into_iter()
+            .map(|value| to_decimal128(value).map(Some))
+            .collect::<Result<Vec<Option<i128>>, ArrowError>>()?;
+        decimal_array(values)
+    }
+}
+
+impl ParquetColumn for Option<BigDecimal> {
+    fn data_type() -> DataType {
+        BigDecimal::data_type()
+    }
+
+    fn nullable() -> bool {
+        true
+    }
+
+    fn to_array(values: Vec<&Self>) -> Result<ArrayRef, ArrowError> {
+        let values = values
+            .into_iter()
+            .map(|value| value.as_ref().map(to_decimal128).transpose())
+            .collect::<Result<Vec<Option<i128>>, ArrowError>>()?;
+        decimal_array(values)
+    }
+}
+
+fn decimal_array(values: Vec<Option<i128>>) -> Result<ArrayRef, ArrowError> {
+    Ok(Arc::new(
+        Decimal128Array::from(values).with_precision_and_scale(DECIMAL_PRECISION, DECIMAL_SCALE)?,
+    ))
+}
+
+/// Unscaled value of the decimal at DECIMAL_SCALE, an error if it doesn't fit DECIMAL_PRECISION
+pub fn to_decimal128(value: &BigDecimal) -> Result<i128, ArrowError> {
+    let (digits, _) = value
+        .with_scale(DECIMAL_SCALE as i64)
+        .as_bigint_and_exponent();
+    digits
+        .to_i128()
+        .filter(|digits| digits.unsigned_abs() < 10u128.pow(DECIMAL_PRECISION as u32))
+        .ok_or_else(|| {
+            ArrowError::InvalidArgumentError(format!(
+                "{} doesn't fit decimal({}, {})",
+                value, DECIMAL_PRECISION, DECIMAL_SCALE
+            ))
+        })
+}
+
+pub fn schema_field<R, T: ParquetColumn>(name: &str, _field: fn(&R) -> &T) -> Field {
+    Field::new(name, T::data_type(), T::nullable())
+}
+
+pub fn column<R, T: ParquetColumn>(
+    rows: &[&R],
+    field: fn(&R) -> &T,
+) -> Result<ArrayRef, ArrowError> {
+    T::to_array(rows.iter().map(|row| field(row)).collect())
+}
+
+/// A model exported to its own table of Parquet files
+pub trait ParquetRows {
+    const TABLE: &'static str;
+
+    fn schema() -> SchemaRef;
+
+    fn to_record_batch(rows: &[&Self]) -> Result<RecordBatch, ArrowError>;
+
+    fn transaction_version(&self) -> i64;
+
+    fn transaction_timestamp(&self) -> chrono::NaiveDateTime;
+}
+
+/// Implements ParquetRows with a column for each of the listed fields, in order. Every field of
+/// the model has to be listed, which the tests check against its FieldCount.
+macro_rules! parquet_rows {
+    ($model:ty, $table:expr, [$($field:ident),* $(,)?]) => {
+        impl ParquetRows for $model {
+            const TABLE: &'static str = $table;
+
+            fn schema() -> SchemaRef {
+                Arc::new(Schema::new(vec![
+                    $(schema_field(stringify!($field), |row: &$model| &row.$field),)*
+                ]))
+            }
+
+            fn to_record_batch(rows: &[&Self]) -> Result<RecordBatch, ArrowError> {
+                RecordBatch::try_new(
+                    Self::schema(),
+                    vec![$(column(rows, |row: &$model| &row.$field)?,)*],
+                )
+            }
+
+            fn transaction_version(&self) -> i64 {
+                self.transaction_version
+            }
+
+            fn transaction_timestamp(&self) -> chrono::NaiveDateTime {
+                self.transaction_timestamp
+            }
+        }
+    };
+}
+
+parquet_rows!(
+    TokenActivity,
+    TOKEN_ACTIVITIES_TABLE,
+    [
+        transaction_version,
+        event_account_address,
+        event_creation_number,
+        event_sequence_number,
+        event_index,
+        token_index,
+        token_data_id_hash,
+        property_version,
+        creator_address,
+        collection_name,
+        name,
+        transfer_type,
+        transfer_kind,
+        domain,
+        from_address,
+        to_address,
+        token_amount,
+        coin_type,
+        coin_amount,
+        suspect_value,
+        collection_data_id_hash,
+        transaction_timestamp,
+    ]
+);
+
+parquet_rows!(
+    NftSale,
+    NFT_SALES_TABLE,
+    [
+        transaction_version,
+        event_account_address,
+        event_creation_number,
+        event_sequence_number,
+        event_index,
+        token_index,
+        market_address,
+        event_type,
+        token_data_id_hash,
+        property_version,
+        collection_data_id_hash,
+        creator_address,
+        collection_name,
+        name,
+        seller,
+        buyer,
+        token_amount,
+        coin_type,
+        price,
+        gas_unit_price,
+        transaction_rank_in_block,
+        transaction_timestamp,
+        is_primary,
+        realized_pnl,
+        hold_duration_secs,
+        source,
+        settlement_amount,
+        coin_price_usd,
+        price_usd,
+        price_decimal,
+        sale_group_id,
+        group_size,
+        domain,
+        suspect_value,
+    ]
+);
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use arrow::array::Array;
+    use field_count::FieldCount;
+    use std::str::FromStr;
+
+    #[test]
+    fn test_every_field_is_exported() {
+        assert_eq!(
+            TokenActivity::schema().fields().len(),
+            TokenActivity::field_count()
+        );
+        assert_eq!(NftSale::schema().fields().len(), NftSale::field_count());
+    }
+
+    #[test]
+    fn test_decimal_columns() {
+        assert_eq!(
+            to_decimal128(&BigDecimal::from(u64::MAX)).unwrap(),
+            u64::MAX as i128 * 10i128.pow(18)
+        );
+        assert_eq!(
+            to_decimal128(&BigDecimal::from_str("1.5").unwrap()).unwrap(),
+            15 * 10i128.pow(17)
+        );
+        assert!(to_decimal128(&BigDecimal::from_str("1e20").unwrap()).is_err());
+
+        let prices = [Some(BigDecimal::from(2)), None];
+        let array = Option::<BigDecimal>::to_array(prices.iter().collect()).unwrap();
+        let array = array.as_any().downcast_ref::<Decimal128Array>().unwrap();
+        assert_eq!(array.value(0), 2 * 10i128.pow(18));
+        assert!(array.is_null(1));
+    }
+}
@@ -0,0 +1,467 @@
+// Copyright (c) Aptos
+// SPDX-License-Identifier: Apache-2.0
+
+use super::{schema::ParquetRows, CommittedRows, ParquetSink};
+use crate::counters::{PARQUET_SINK_ROWS, PARQUET_SINK_WRITE_SECONDS};
+use anyhow::{ensure, Context};
+use aptos_config::config::ParquetSinkConfig;
+use arrow::record_batch::RecordBatch;
+use bytes::Bytes;
+use object_store::{aws::AmazonS3Builder, local::LocalFileSystem, path::Path, ObjectStore};
+use parquet::{arrow::ArrowWriter, basic::Compression, file::properties::WriterProperties};
+use serde::{Deserialize, Serialize};
+use std::{
+    collections::{BTreeMap, HashMap},
+    path::PathBuf,
+    sync::Arc,
+    time::{Duration, Instant},
+};
+use tokio::sync::mpsc;
+
+pub const DEFAULT_MAX_ROWS_PER_FILE: u64 = 1_000_000;
+pub const DEFAULT_MAX_FILE_AGE_SECS: u64 = 600;
+pub const DEFAULT_QUEUE_SIZE: u64 = 100;
+pub const MANIFEST_FILE: &str = "_manifest.json";
+
+/// How often files are checked for max_file_age
+const FILE_AGE_CHECK_INTERVAL: Duration = Duration::from_secs(10);
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum ParquetLocation {
+    Local(PathBuf),
+    S3 { bucket: String, prefix: String },
+}
+
+impl ParquetLocation {
+    pub fn parse(location: &str) -> anyhow::Result<Self> {
+        ensure!(!location.is_empty(), "location can't be empty");
+        match location.strip_prefix("s3://") {
+            Some(bucket_and_prefix) => {
+                let (bucket, prefix) = bucket_and_prefix
+                    .split_once('/')
+                    .unwrap_or((bucket_and_prefix, ""));
+                ensure!(!bucket.is_empty(), "{} has no bucket", location);
+                Ok(Self::S3 {
+                    bucket: bucket.to_string(),
+                    prefix: prefix.trim_matches('/').to_string(),
+                })
+            }
+            None => Ok(Self::Local(PathBuf::from(location))),
+        }
+    }
+
+    /// The store and the path every table is written under
+    fn object_store(&self) -> anyhow::Result<(Arc<dyn ObjectStore>, Path)> {
+        match self {
+            Self::Local(dir) => {
+                std::fs::create_dir_all(dir)
+                    .with_context(|| format!("Failed to create {}", dir.display()))?;
+                Ok((
+                    Arc::new(LocalFileSystem::new_with_prefix(dir)?),
+                    Path::default(),
+                ))
+            }
+            Self::S3 { bucket, prefix } => Ok((
+                Arc::new(
+                    AmazonS3Builder::from_env()
+                        .with_bucket_name(bucket)
+                        .build()?,
+                ),
+                Path::from(prefix.as_str()),
+            )),
+        }
+    }
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct ParquetSinkOptions {
+    pub location: ParquetLocation,
+    pub max_rows_per_file: usize,
+    pub max_file_age: Duration,
+    pub queue_size: usize,
+}
+
+impl ParquetSinkOptions {
+    pub fn from_config(config: Option<&ParquetSinkConfig>) -> anyhow::Result<Option<Self>> {
+        let config = match config {
+            Some(config) => config,
+            None => return Ok(None),
+        };
+        let max_rows_per_file = config
+            .max_rows_per_file
+            .unwrap_or(DEFAULT_MAX_ROWS_PER_FILE);
+        let max_file_age_secs = config
+            .max_file_age_secs
+            .unwrap_or(DEFAULT_MAX_FILE_AGE_SECS);
+        let queue_size = config.queue_size.unwrap_or(DEFAULT_QUEUE_SIZE);
+        ensure!(
+            max_rows_per_file > 0,
+            "max_rows_per_file must be greater than 0"
+        );
+        ensure!(
+            max_file_age_secs > 0,
+            "max_file_age_secs must be greater than 0"
+        );
+        ensure!(queue_size > 0, "queue_size must be greater than 0");
+        Ok(Some(Self {
+            location: ParquetLocation::parse(&config.location)?,
+            max_rows_per_file: max_rows_per_file as usize,
+            max_file_age: Duration::from_secs(max_file_age_secs),
+            queue_size: queue_size as usize,
+        }))
+    }
+}
+
+pub fn spawn(options: ParquetSinkOptions) -> anyhow::Result<ParquetSink> {
+    let (store, prefix) = options.location.object_store()?;
+    let (sender, receiver) = mpsc::channel(options.queue_size);
+    tokio::spawn(ParquetWriter::new(store, prefix, &options).run(receiver));
+    Ok(ParquetSink { sender })
+}
+
+/// A complete file of a table
+#[derive(Clone, Debug, Deserialize, PartialEq, Eq, Serialize)]
+pub struct ManifestFile {
+    /// Relative to the table's directory
+    pub path: String,
+    pub date: chrono::NaiveDate,
+    pub num_rows: usize,
+    pub min_transaction_version: i64,
+    pub max_transaction_version: i64,
+    pub written_at: chrono::NaiveDateTime,
+}
+
+#[derive(Clone, Debug, Default, Deserialize, PartialEq, Eq, Serialize)]
+pub struct Manifest {
+    pub files: Vec<ManifestFile>,
+}
+
+/// Rows of a table and day that aren't in a file yet
+struct OpenFile {
+    batches: Vec<RecordBatch>,
+    num_rows: usize,
+    min_transaction_version: i64,
+    max_transaction_version: i64,
+    opened_at: Instant,
+}
+
+struct ParquetWriter {
+    store: Arc<dyn ObjectStore>,
+    prefix: Path,
+    max_rows_per_file: usize,
+    max_file_age: Duration,
+    open_files: HashMap<(&'static str, chrono::NaiveDate), OpenFile>,
+    /// Loaded on the first file written to the table
+    manifests: HashMap<&'static str, Manifest>,
+}
+
+impl ParquetWriter {
+    fn new(store: Arc<dyn ObjectStore>, prefix: Path, options: &ParquetSinkOptions) -> Self {
+        Self {
+            store,
+            prefix,
+            max_rows_per_file: options.max_rows_per_file,
+            max_file_age: options.max_file_age,
+            open_files: HashMap::new(),
+            manifests: HashMap::new(),
+        }
+    }
+
+    /// Writes until the processor drops its sender, then closes what's left open
+    async fn run(mut self, mut receiver: mpsc::Receiver<CommittedRows>) {
+        let mut file_age_check = tokio::time::interval(FILE_AGE_CHECK_INTERVAL);
+        loop {
+            tokio::select! {
+                rows = receiver.recv() => match rows {
+                    Some(rows) => self.append(rows).await,
+                    None => break,
+                },
+                _ = file_age_check.tick() => {
+                    let max_file_age = self.max_file_age;
+                    self.close_files(|file| file.opened_at.elapsed() >= max_file_age)
+                        .await;
+                }
+            }
+        }
+        self.close_files(|_| true).await;
+    }
+
+    async fn append(&mut self, rows: CommittedRows) {
+        self.stage(&rows.token_activities);
+        self.stage(&rows.nft_sales);
+        let max_rows_per_file = self.max_rows_per_file;
+        self.close_files(|file| file.num_rows >= max_rows_per_file)
+            .await;
+    }
+
+    /// Adds the rows to the open files of their days
+    fn stage<R: ParquetRows>(&mut self, rows: &[R]) {
+        let mut rows_by_date: BTreeMap<chrono::NaiveDate, Vec<&R>> = BTreeMap::new();
+        for row in rows {
+            rows_by_date
+                .entry(row.transaction_timestamp().date())
+                .or_default()
+                .push(row);
+        }
+        for (date, rows) in rows_by_date {
+            let batch = match R::to_record_batch(&rows) {
+                Ok(batch) => batch,
+                Err(err) => {
+                    aptos_logger::error!(
+                        table = R::TABLE,
+                        date = date.to_string(),
+                        error = ?err,
+                        "Failed to convert rows for the parquet sink"
+                    );
+                    PARQUET_SINK_ROWS
+                        .with_label_values(&[R::TABLE, "failed"])
+                        .inc_by(rows.len() as u64);
+                    continue;
+                }
+            };
+            let min_version = rows.iter().map(|row| row.transaction_version()).min();
+            let max_version = rows.iter().map(|row| row.transaction_version()).max();
+            let file = self
+                .open_files
+                .entry((R::TABLE, date))
+                .or_insert_with(|| OpenFile {
+                    batches: vec![],
+                    num_rows: 0,
+                    min_transaction_version: i64::MAX,
+                    max_transaction_version: i64::MIN,
+                    opened_at: Instant::now(),
+                });
+            file.num_rows += batch.num_rows();
+            file.batches.push(batch);
+            file.min_transaction_version = file
+                .min_transaction_version
+                .min(min_version.unwrap_or(i64::MAX));
+            file.max_transaction_version = file
+                .max_transaction_version
+                .max(max_version.unwrap_or(i64::MIN));
+        }
+    }
+
+    async fn close_files(&mut self, should_close: impl Fn(&OpenFile) -> bool) {
+        let keys = self
+            .open_files
+            .iter()
+            .filter(|(_, file)| should_close(file))
+            .map(|(key, _)| *key)
+            .collect::<Vec<_>>();
+        for (table, date) in keys {
+            let file = self
+                .open_files
+                .remove(&(table, date))
+                .expect("Keys were just collected");
+            let timer = PARQUET_SINK_WRITE_SECONDS
+                .with_label_values(&[table])
+                .start_timer();
+            match self.write_file(table, date, &file).await {
+                Ok(path) => {
+                    aptos_logger::info!(
+                        table = table,
+                        path = path,
+                        num_rows = file.num_rows,
+                        "Wrote a parquet file"
+                    );
+                    PARQUET_SINK_ROWS
+                        .with_label_values(&[table, "written"])
+                        .inc_by(file.num_rows as u64);
+                }
+                Err(err) => {
+                    aptos_logger::error!(
+                        table = table,
+                        date = date.to_string(),
+                        num_rows = file.num_rows,
+                        error = ?err,
+                        "Failed to write a parquet file"
+                    );
+                    PARQUET_SINK_ROWS
+                        .with_label_values(&[table, "failed"])
+                        .inc_by(file.num_rows as u64);
+                }
+            }
+            timer.observe_duration();
+        }
+    }
+
+    /// Uploads the file, then lists it in the table's manifest. Returns its path.
+    async fn write_file(
+        &mut self,
+        table: &'static str,
+        date: chrono::NaiveDate,
+        file: &OpenFile,
+    ) -> anyhow::Result<String> {
+        let manifest_path = self.prefix.child(table).child(MANIFEST_FILE);
+        if !self.manifests.contains_key(table) {
+            let manifest = load_manifest(self.store.as_ref(), &manifest_path).await?;
+            self.manifests.insert(table, manifest);
+        }
+
+        let written_at = chrono::Utc::now().naive_utc();
+        let partition = format!("date={}", date);
+        let file_name = format!(
+            "part-{}-{}-{}.parquet",
+            file.min_transaction_version,
+            file.max_transaction_version,
+            written_at.timestamp_millis()
+        );
+        let bytes = encode(&file.batches)?;
+        self.store
+            .put(
+                &self
+                    .prefix
+                    .child(table)
+                    .child(partition.as_str())
+                    .child(file_name.as_str()),
+                Bytes::from(bytes),
+            )
+            .await?;
+
+        let relative_path = format!("{}/{}", partition, file_name);
+        let manifest = self
+            .manifests
+            .get_mut(table)
+            .expect("Manifest was just loaded");
+        manifest.files.push(ManifestFile {
+            path: relative_path.clone(),
+            date,
+            num_rows: file.num_rows,
+            min_transaction_version: file.min_transaction_version,
+            max_transaction_version: file.max_transaction_version,
+            written_at,
+        });
+        // Replacing an object is atomic, readers see either manifest in full. If this fails, the
+        // file is listed with the next one written to the table.
+        self.store
+            .put(&manifest_path, Bytes::from(serde_json::to_vec(manifest)?))
+            .await?;
+        Ok(relative_path)
+    }
+}
+
+async fn load_manifest(store: &dyn ObjectStore, path: &Path) -> anyhow::Result<Manifest> {
+    match store.get(path).await {
+        Ok(result) => Ok(serde_json::from_slice(&result.bytes().await?)
+            .with_context(|| format!("Invalid manifest {}", path))?),
+        Err(object_store::Error::NotFound { .. }) => Ok(Manifest::default()),
+        // Writing a new manifest over one that couldn't be read would lose its files
+        Err(err) => Err(err.into()),
+    }
+}
+
+fn encode(batches: &[RecordBatch]) -> anyhow::Result<Vec<u8>> {
+    let schema = batches.first().context("No rows to write")?.schema();
+    let properties = WriterProperties::builder()
+        .set_compression(Compression::SNAPPY)
+        .build();
+    let mut buffer = vec![];
+    let mut writer = ArrowWriter::try_new(&mut buffer, schema, Some(properties))?;
+    for batch in batches {
+        writer.write(batch)?;
+    }
+    writer.close()?;
+    Ok(buffer)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::models::token_models::token_activities::TokenActivity;
+    use bigdecimal::BigDecimal;
+
+    fn activity(version: i64, timestamp: i64) -> TokenActivity {
+        TokenActivity {
+            transaction_version: version,
+            event_account_address: "0x1".to_string(),
+            event_creation_number: 0,
+            event_sequence_number: version,
+            event_index: 0,
+            token_index: 0,
+            token_data_id_hash: "potion".to_string(),
+            property_version: BigDecimal::from(0),
+            creator_address: "0x1".to_string(),
+            collection_name: "Potions".to_string(),
+            name: "Potion".to_string(),
+            transfer_type: "0x3::token::DepositEvent".to_string(),
+            transfer_kind: Some("transfer".to_string()),
+            domain: None,
+            from_address: None,
+            to_address: Some("0xb0b".to_string()),
+            token_amount: BigDecimal::from(1),
+            coin_type: None,
+            coin_amount: None,
+            suspect_value: false,
+            collection_data_id_hash: "potions".to_string(),
+            transaction_timestamp: chrono::NaiveDateTime::from_timestamp(timestamp, 0),
+        }
+    }
+
+    #[test]
+    fn test_parse_location() {
+        assert_eq!(
+            ParquetLocation::parse("s3://lake/nft/").unwrap(),
+            ParquetLocation::S3 {
+                bucket: "lake".to_string(),
+                prefix: "nft".to_string()
+            }
+        );
+        assert_eq!(
+            ParquetLocation::parse("/data/nft").unwrap(),
+            ParquetLocation::Local(PathBuf::from("/data/nft"))
+        );
+        assert!(ParquetLocation::parse("s3:///nft").is_err());
+        assert!(ParquetLocation::parse("").is_err());
+    }
+
+    #[tokio::test]
+    async fn test_files_are_listed_once_written() {
+        let dir = std::env::temp_dir().join(format!("parquet-sink-test-{}", std::process::id()));
+        let _ = std::fs::remove_dir_all(&dir);
+        let options = ParquetSinkOptions {
+            location: ParquetLocation::Local(dir.clone()),
+            max_rows_per_file: 2,
+            max_file_age: Duration::from_secs(600),
+            queue_size: 1,
+        };
+        let (store, prefix) = options.location.object_store().unwrap();
+        let mut writer = ParquetWriter::new(store.clone(), prefix, &options);
+
+        // 2022-11-09 and 2022-11-10
+        writer
+            .append(CommittedRows {
+                token_activities: vec![
+                    activity(10, 1668000000),
+                    activity(11, 1668000000),
+                    activity(12, 1668100000),
+                ],
+                nft_sales: vec![],
+            })
+            .await;
+        // The first day's file is full, the second one is still open
+        let manifest_path = Path::from("token_activities/_manifest.json");
+        let manifest = load_manifest(store.as_ref(), &manifest_path).await.unwrap();
+        assert_eq!(manifest.files.len(), 1);
+        assert_eq!(manifest.files[0].num_rows, 2);
+        assert_eq!(manifest.files[0].min_transaction_version, 10);
+        assert_eq!(manifest.files[0].max_transaction_version, 11);
+        assert!(manifest.files[0]
+            .path
+            .starts_with("date=2022-11-09/part-10-11-"));
+        assert!(dir
+            .join("token_activities")
+            .join(&manifest.files[0].path)
+            .exists());
+
+        writer.close_files(|_| true).await;
+        let manifest = load_manifest(store.as_ref(), &manifest_path).await.unwrap();
+        assert_eq!(manifest.files.len(), 2);
+        assert_eq!(manifest.files[1].date.to_string(), "2022-11-10");
+        assert_eq!(manifest.files[1].num_rows, 1);
+        // Nothing to write for nft_sales
+        assert!(!dir.join("nft_sales").exists());
+
+        let _ = std::fs::remove_dir_all(&dir);
+    }
+}
@@ -3,11 +3,13 @@
 
 use crate::{
     database::{
-        clean_data_for_db, execute_with_better_error, get_chunks, PgDbPool, PgPoolConnection,
+        clean_data_for_db, execute_chunk_with_context, get_chunks, is_retryable_error,
+        run_transaction_with_retries, PgDbPool, PgPoolConnection,
     },
     indexer::{
-        errors::TransactionProcessingError, processing_result::ProcessingResult,
-        transaction_processor::TransactionProcessor,
+        errors::TransactionProcessingError,
+        processing_result::ProcessingResult,
+        transaction_processor::{run_blocking, TransactionProcessor},
     },
     models::coin_models::{
         coin_activities::{CoinActivity, CurrentCoinBalancePK},
@@ -79,20 +81,19 @@ fn insert_to_db(
         end_version = end_version,
         "Inserting to db",
     );
-    match conn
-        .build_transaction()
-        .read_write()
-        .run::<_, Error, _>(|pg_conn| {
-            insert_to_db_impl(
-                pg_conn,
-                &coin_activities,
-                &coin_infos,
-                &coin_balances,
-                &current_coin_balances,
-                &coin_supply,
-            )
-        }) {
+    match run_transaction_with_retries(conn, |pg_conn| {
+        insert_to_db_impl(
+            pg_conn,
+            &coin_activities,
+            &coin_infos,
+            &coin_balances,
+            &current_coin_balances,
+            &coin_supply,
+        )
+    }) {
         Ok(_) => Ok(()),
+        // Cleaning the data won't help, and it's been retried already
+        Err(err) if is_retryable_error(&err) => Err(err),
         Err(_) => conn
             .build_transaction()
             .read_write()
@@ -121,18 +122,23 @@ fn insert_coin_activities(
     use schema::coin_activities::dsl::*;
 
     let chunks = get_chunks(item_to_insert.len(), CoinActivity::field_count());
-    for (start_ind, end_ind) in chunks {
-        execute_with_better_error(
+    for (chunk_index, (start_ind, end_ind)) in chunks.into_iter().enumerate() {
+        execute_chunk_with_context(
             conn,
-            diesel::insert_into(schema::coin_activities::table)
-                .values(&item_to_insert[start_ind..end_ind])
-                .on_conflict((
-                    transaction_version,
-                    event_account_address,
-                    event_creation_number,
-                    event_sequence_number,
-                ))
-                .do_nothing(),
+            "coin_activities",
+            chunk_index,
+            &item_to_insert[start_ind..end_ind],
+            |chunk| {
+                diesel::insert_into(schema::coin_activities::table)
+                    .values(chunk)
+                    .on_conflict((
+                        transaction_version,
+                        event_account_address,
+                        event_creation_number,
+                        event_sequence_number,
+                    ))
+                    .do_nothing()
+            },
             None,
         )?;
     }
@@ -146,23 +152,28 @@ fn insert_coin_infos(
     use schema::coin_infos::dsl::*;
 
     let chunks = get_chunks(item_to_insert.len(), CoinInfo::field_count());
-    for (start_ind, end_ind) in chunks {
-        execute_with_better_error(
+    for (chunk_index, (start_ind, end_ind)) in chunks.into_iter().enumerate() {
+        execute_chunk_with_context(
             conn,
-            diesel::insert_into(schema::coin_infos::table)
-                .values(&item_to_insert[start_ind..end_ind])
-                .on_conflict(coin_type_hash)
-                .do_update()
-                .set((
-                    transaction_version_created.eq(excluded(transaction_version_created)),
-                    creator_address.eq(excluded(creator_address)),
-                    name.eq(excluded(name)),
-                    symbol.eq(excluded(symbol)),
-                    decimals.eq(excluded(decimals)),
-                    transaction_created_timestamp.eq(excluded(transaction_created_timestamp)),
-                    supply_aggregator_table_handle.eq(excluded(supply_aggregator_table_handle)),
-                    supply_aggregator_table_key.eq(excluded(supply_aggregator_table_key)),
-                )),
+            "coin_infos",
+            chunk_index,
+            &item_to_insert[start_ind..end_ind],
+            |chunk| {
+                diesel::insert_into(schema::coin_infos::table)
+                    .values(chunk)
+                    .on_conflict(coin_type_hash)
+                    .do_update()
+                    .set((
+                        transaction_version_created.eq(excluded(transaction_version_created)),
+                        creator_address.eq(excluded(creator_address)),
+                        name.eq(excluded(name)),
+                        symbol.eq(excluded(symbol)),
+                        decimals.eq(excluded(decimals)),
+                        transaction_created_timestamp.eq(excluded(transaction_created_timestamp)),
+                        supply_aggregator_table_handle.eq(excluded(supply_aggregator_table_handle)),
+                        supply_aggregator_table_key.eq(excluded(supply_aggregator_table_key)),
+                    ))
+            },
             Some(" WHERE coin_infos.transaction_version_created >= EXCLUDED.transaction_version_created "),
         )?;
     }
@@ -176,13 +187,18 @@ fn insert_coin_balances(
     use schema::coin_balances::dsl::*;
 
     let chunks = get_chunks(item_to_insert.len(), CoinBalance::field_count());
-    for (start_ind, end_ind) in chunks {
-        execute_with_better_error(
+    for (chunk_index, (start_ind, end_ind)) in chunks.into_iter().enumerate() {
+        execute_chunk_with_context(
             conn,
-            diesel::insert_into(schema::coin_balances::table)
-                .values(&item_to_insert[start_ind..end_ind])
-                .on_conflict((transaction_version, owner_address, coin_type_hash))
-                .do_nothing(),
+            "coin_balances",
+            chunk_index,
+            &item_to_insert[start_ind..end_ind],
+            |chunk| {
+                diesel::insert_into(schema::coin_balances::table)
+                    .values(chunk)
+                    .on_conflict((transaction_version, owner_address, coin_type_hash))
+                    .do_nothing()
+            },
             None,
         )?;
     }
@@ -196,20 +212,25 @@ fn insert_current_coin_balances(
     use schema::current_coin_balances::dsl::*;
 
     let chunks = get_chunks(item_to_insert.len(), CurrentCoinBalance::field_count());
-    for (start_ind, end_ind) in chunks {
-        execute_with_better_error(
+    for (chunk_index, (start_ind, end_ind)) in chunks.into_iter().enumerate() {
+        execute_chunk_with_context(
             conn,
-            diesel::insert_into(schema::current_coin_balances::table)
-                .values(&item_to_insert[start_ind..end_ind])
-                .on_conflict((owner_address, coin_type_hash))
-                .do_update()
-                .set((
-                    amount.eq(excluded(amount)),
-                    last_transaction_version.eq(excluded(last_transaction_version)),
-                    last_transaction_timestamp.eq(excluded(last_transaction_timestamp)),
-                )),
-                Some(" WHERE current_coin_balances.last_transaction_version <= excluded.last_transaction_version "),
-            )?;
+            "current_coin_balances",
+            chunk_index,
+            &item_to_insert[start_ind..end_ind],
+            |chunk| {
+                diesel::insert_into(schema::current_coin_balances::table)
+                    .values(chunk)
+                    .on_conflict((owner_address, coin_type_hash))
+                    .do_update()
+                    .set((
+                        amount.eq(excluded(amount)),
+                        last_transaction_version.eq(excluded(last_transaction_version)),
+                        last_transaction_timestamp.eq(excluded(last_transaction_timestamp)),
+                    ))
+            },
+            Some(" WHERE current_coin_balances.last_transaction_version <= excluded.last_transaction_version "),
+        )?;
     }
     Ok(())
 }
@@ -221,14 +242,19 @@ fn insert_coin_supply(
     use schema::coin_supply::dsl::*;
 
     let chunks = get_chunks(item_to_insert.len(), CoinSupply::field_count());
-    for (start_ind, end_ind) in chunks {
-        execute_with_better_error(
+    for (chunk_index, (start_ind, end_ind)) in chunks.into_iter().enumerate() {
+        execute_chunk_with_context(
             conn,
-            diesel::insert_into(schema::coin_supply::table)
-                .values(&item_to_insert[start_ind..end_ind])
-                .on_conflict((transaction_version, coin_type_hash))
-                .do_update()
-                .set((transaction_epoch.eq(excluded(transaction_epoch)),)),
+            "coin_supply",
+            chunk_index,
+            &item_to_insert[start_ind..end_ind],
+            |chunk| {
+                diesel::insert_into(schema::coin_supply::table)
+                    .values(chunk)
+                    .on_conflict((transaction_version, coin_type_hash))
+                    .do_update()
+                    .set((transaction_epoch.eq(excluded(transaction_epoch)),))
+            },
             None,
         )?;
     }
@@ -247,7 +273,7 @@ impl TransactionProcessor for CoinTransactionProcessor {
         start_version: u64,
         end_version: u64,
     ) -> Result<ProcessingResult, TransactionProcessingError> {
-        let mut conn = self.get_conn();
+        let mut conn = self.get_conn_async().await;
         // get aptos_coin info for supply tracking
         // TODO: This only needs to be fetched once. Need to persist somehow
         let maybe_aptos_coin_info =
@@ -288,17 +314,21 @@ impl TransactionProcessor for CoinTransactionProcessor {
             (&a.owner_address, &a.coin_type).cmp(&(&b.owner_address, &b.coin_type))
         });
 
-        let tx_result = insert_to_db(
-            &mut conn,
-            self.name(),
-            start_version,
-            end_version,
-            all_coin_activities,
-            all_coin_infos,
-            all_coin_balances,
-            all_current_coin_balances,
-            all_coin_supply,
-        );
+        let name = self.name();
+        let tx_result = run_blocking(move || {
+            insert_to_db(
+                &mut conn,
+                name,
+                start_version,
+                end_version,
+                all_coin_activities,
+                all_coin_infos,
+                all_coin_balances,
+                all_current_coin_balances,
+                all_coin_supply,
+            )
+        })
+        .await;
         match tx_result {
             Ok(_) => Ok(ProcessingResult::new(
                 self.name(),
@@ -318,3 +348,61 @@ impl TransactionProcessor for CoinTransactionProcessor {
         &self.connection_pool
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        database::{
+            new_db_pool, new_db_pool_with_settings, ConnectionSettings, ConnectionTimeouts,
+            DEFAULT_POOL_SIZE,
+        },
+        indexer::tailer::{test::wipe_database, MIGRATIONS},
+    };
+    use diesel::connection::SimpleConnection;
+    use diesel_migrations::MigrationHarness;
+
+    #[test]
+    fn test_statement_timeout_is_retryable() {
+        if crate::should_skip_pg_tests() {
+            return;
+        }
+        let database_url = std::env::var("INDEXER_DATABASE_URL")
+            .expect("must set 'INDEXER_DATABASE_URL' to run tests!");
+        let conn_pool = new_db_pool(database_url.as_str()).unwrap();
+        let mut lock_conn = conn_pool.get().unwrap();
+        wipe_database(&mut lock_conn);
+        lock_conn.run_pending_migrations(MIGRATIONS).unwrap();
+
+        let timeout_pool = new_db_pool_with_settings(
+            database_url.as_str(),
+            ConnectionSettings {
+                timeouts: ConnectionTimeouts {
+                    statement_timeout_ms: Some(50),
+                    ..ConnectionTimeouts::default()
+                },
+                schema: None,
+            },
+            DEFAULT_POOL_SIZE,
+        )
+        .unwrap();
+        // Holds a lock every insert waits for, like a migration would
+        lock_conn
+            .batch_execute("BEGIN; LOCK TABLE coin_activities IN ACCESS EXCLUSIVE MODE;")
+            .unwrap();
+        let err = insert_to_db(
+            &mut timeout_pool.get().unwrap(),
+            NAME,
+            0,
+            0,
+            vec![],
+            vec![],
+            vec![],
+            vec![],
+            vec![],
+        )
+        .unwrap_err();
+        lock_conn.batch_execute("ROLLBACK;").unwrap();
+        assert!(is_retryable_error(&err), "{:?}", err);
+    }
+}
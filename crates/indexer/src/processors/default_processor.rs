@@ -3,11 +3,13 @@
 
 use crate::{
     database::{
-        clean_data_for_db, execute_with_better_error, get_chunks, PgDbPool, PgPoolConnection,
+        clean_data_for_db, execute_chunk_with_context, get_chunks, is_retryable_error,
+        run_transaction_with_retries, PgDbPool, PgPoolConnection,
     },
     indexer::{
-        errors::TransactionProcessingError, processing_result::ProcessingResult,
-        transaction_processor::TransactionProcessor,
+        errors::TransactionProcessingError,
+        processing_result::ProcessingResult,
+        transaction_processor::{run_blocking, TransactionProcessor},
     },
     models::{
         block_metadata_transactions::BlockMetadataTransactionModel,
@@ -67,21 +69,20 @@ fn insert_to_db(
         end_version = end_version,
         "Inserting to db",
     );
-    match conn
-        .build_transaction()
-        .read_write()
-        .run::<_, Error, _>(|pg_conn| {
-            insert_transactions(pg_conn, &txns)?;
-            insert_user_transactions_w_sigs(pg_conn, &txn_details)?;
-            insert_block_metadata_transactions(pg_conn, &txn_details)?;
-            insert_events(pg_conn, &events)?;
-            insert_write_set_changes(pg_conn, &wscs)?;
-            insert_move_modules(pg_conn, &wsc_details)?;
-            insert_move_resources(pg_conn, &wsc_details)?;
-            insert_table_data(pg_conn, &wsc_details)?;
-            Ok(())
-        }) {
+    match run_transaction_with_retries(conn, |pg_conn| {
+        insert_transactions(pg_conn, &txns)?;
+        insert_user_transactions_w_sigs(pg_conn, &txn_details)?;
+        insert_block_metadata_transactions(pg_conn, &txn_details)?;
+        insert_events(pg_conn, &events)?;
+        insert_write_set_changes(pg_conn, &wscs)?;
+        insert_move_modules(pg_conn, &wsc_details)?;
+        insert_move_resources(pg_conn, &wsc_details)?;
+        insert_table_data(pg_conn, &wsc_details)?;
+        Ok(())
+    }) {
         Ok(_) => Ok(()),
+        // Cleaning the data won't help, and it's been retried already
+        Err(err) if is_retryable_error(&err) => Err(err),
         Err(_) => conn
             .build_transaction()
             .read_write()
@@ -111,14 +112,19 @@ fn insert_transactions(
 ) -> Result<(), diesel::result::Error> {
     use schema::transactions::dsl::*;
     let chunks = get_chunks(txns.len(), TransactionModel::field_count());
-    for (start_ind, end_ind) in chunks {
-        execute_with_better_error(
+    for (chunk_index, (start_ind, end_ind)) in chunks.into_iter().enumerate() {
+        execute_chunk_with_context(
             conn,
-            diesel::insert_into(schema::transactions::table)
-                .values(&txns[start_ind..end_ind])
-                .on_conflict(version)
-                .do_update()
-                .set((epoch.eq(excluded(epoch)),)),
+            "transactions",
+            chunk_index,
+            &txns[start_ind..end_ind],
+            |chunk| {
+                diesel::insert_into(schema::transactions::table)
+                    .values(chunk)
+                    .on_conflict(version)
+                    .do_update()
+                    .set((epoch.eq(excluded(epoch)),))
+            },
             None,
         )?;
     }
@@ -142,30 +148,40 @@ fn insert_user_transactions_w_sigs(
         all_user_transactions.len(),
         UserTransactionModel::field_count(),
     );
-    for (start_ind, end_ind) in chunks {
-        execute_with_better_error(
+    for (chunk_index, (start_ind, end_ind)) in chunks.into_iter().enumerate() {
+        execute_chunk_with_context(
             conn,
-            diesel::insert_into(schema::user_transactions::table)
-                .values(&all_user_transactions[start_ind..end_ind])
-                .on_conflict(ut_schema::version)
-                .do_update()
-                .set((ut_schema::epoch.eq(excluded(ut_schema::epoch)),)),
+            "user_transactions",
+            chunk_index,
+            &all_user_transactions[start_ind..end_ind],
+            |chunk| {
+                diesel::insert_into(schema::user_transactions::table)
+                    .values(chunk)
+                    .on_conflict(ut_schema::version)
+                    .do_update()
+                    .set((ut_schema::epoch.eq(excluded(ut_schema::epoch)),))
+            },
             None,
         )?;
     }
     let chunks = get_chunks(all_signatures.len(), Signature::field_count());
-    for (start_ind, end_ind) in chunks {
-        execute_with_better_error(
+    for (chunk_index, (start_ind, end_ind)) in chunks.into_iter().enumerate() {
+        execute_chunk_with_context(
             conn,
-            diesel::insert_into(schema::signatures::table)
-                .values(&all_signatures[start_ind..end_ind])
-                .on_conflict((
-                    sig_schema::transaction_version,
-                    sig_schema::multi_agent_index,
-                    sig_schema::multi_sig_index,
-                    sig_schema::is_sender_primary,
-                ))
-                .do_nothing(),
+            "signatures",
+            chunk_index,
+            &all_signatures[start_ind..end_ind],
+            |chunk| {
+                diesel::insert_into(schema::signatures::table)
+                    .values(chunk)
+                    .on_conflict((
+                        sig_schema::transaction_version,
+                        sig_schema::multi_agent_index,
+                        sig_schema::multi_sig_index,
+                        sig_schema::is_sender_primary,
+                    ))
+                    .do_nothing()
+            },
             None,
         )?;
     }
@@ -187,13 +203,18 @@ fn insert_block_metadata_transactions(
         .collect::<Vec<BlockMetadataTransactionModel>>();
 
     let chunks = get_chunks(bmt.len(), BlockMetadataTransactionModel::field_count());
-    for (start_ind, end_ind) in chunks {
-        execute_with_better_error(
+    for (chunk_index, (start_ind, end_ind)) in chunks.into_iter().enumerate() {
+        execute_chunk_with_context(
             conn,
-            diesel::insert_into(schema::block_metadata_transactions::table)
-                .values(&bmt[start_ind..end_ind])
-                .on_conflict(version)
-                .do_nothing(),
+            "block_metadata_transactions",
+            chunk_index,
+            &bmt[start_ind..end_ind],
+            |chunk| {
+                diesel::insert_into(schema::block_metadata_transactions::table)
+                    .values(chunk)
+                    .on_conflict(version)
+                    .do_nothing()
+            },
             None,
         )?;
     }
@@ -205,13 +226,18 @@ fn insert_events(conn: &mut PgConnection, ev: &[EventModel]) -> Result<(), diese
 
     let chunks = get_chunks(ev.len(), EventModel::field_count());
 
-    for (start_ind, end_ind) in chunks {
-        execute_with_better_error(
+    for (chunk_index, (start_ind, end_ind)) in chunks.into_iter().enumerate() {
+        execute_chunk_with_context(
             conn,
-            diesel::insert_into(schema::events::table)
-                .values(&ev[start_ind..end_ind])
-                .on_conflict((account_address, creation_number, sequence_number))
-                .do_nothing(),
+            "events",
+            chunk_index,
+            &ev[start_ind..end_ind],
+            |chunk| {
+                diesel::insert_into(schema::events::table)
+                    .values(chunk)
+                    .on_conflict((account_address, creation_number, sequence_number))
+                    .do_nothing()
+            },
             None,
         )?;
     }
@@ -226,13 +252,18 @@ fn insert_write_set_changes(
 
     let chunks = get_chunks(wscs.len(), WriteSetChangeModel::field_count());
 
-    for (start_ind, end_ind) in chunks {
-        execute_with_better_error(
+    for (chunk_index, (start_ind, end_ind)) in chunks.into_iter().enumerate() {
+        execute_chunk_with_context(
             conn,
-            diesel::insert_into(schema::write_set_changes::table)
-                .values(&wscs[start_ind..end_ind])
-                .on_conflict((transaction_version, index))
-                .do_nothing(),
+            "write_set_changes",
+            chunk_index,
+            &wscs[start_ind..end_ind],
+            |chunk| {
+                diesel::insert_into(schema::write_set_changes::table)
+                    .values(chunk)
+                    .on_conflict((transaction_version, index))
+                    .do_nothing()
+            },
             None,
         )?;
     }
@@ -254,13 +285,18 @@ fn insert_move_modules(
         .collect::<Vec<MoveModule>>();
 
     let chunks = get_chunks(modules.len(), MoveModule::field_count());
-    for (start_ind, end_ind) in chunks {
-        execute_with_better_error(
+    for (chunk_index, (start_ind, end_ind)) in chunks.into_iter().enumerate() {
+        execute_chunk_with_context(
             conn,
-            diesel::insert_into(schema::move_modules::table)
-                .values(&modules[start_ind..end_ind])
-                .on_conflict((transaction_version, write_set_change_index))
-                .do_nothing(),
+            "move_modules",
+            chunk_index,
+            &modules[start_ind..end_ind],
+            |chunk| {
+                diesel::insert_into(schema::move_modules::table)
+                    .values(chunk)
+                    .on_conflict((transaction_version, write_set_change_index))
+                    .do_nothing()
+            },
             None,
         )?;
     }
@@ -282,13 +318,18 @@ fn insert_move_resources(
         .collect::<Vec<MoveResource>>();
 
     let chunks = get_chunks(resources.len(), MoveResource::field_count());
-    for (start_ind, end_ind) in chunks {
-        execute_with_better_error(
+    for (chunk_index, (start_ind, end_ind)) in chunks.into_iter().enumerate() {
+        execute_chunk_with_context(
             conn,
-            diesel::insert_into(schema::move_resources::table)
-                .values(&resources[start_ind..end_ind])
-                .on_conflict((transaction_version, write_set_change_index))
-                .do_nothing(),
+            "move_resources",
+            chunk_index,
+            &resources[start_ind..end_ind],
+            |chunk| {
+                diesel::insert_into(schema::move_resources::table)
+                    .values(chunk)
+                    .on_conflict((transaction_version, write_set_change_index))
+                    .do_nothing()
+            },
             None,
         )?;
     }
@@ -321,24 +362,34 @@ fn insert_table_data(
     metadata_nonnull.sort_by(|a, b| a.handle.cmp(&b.handle));
 
     let chunks = get_chunks(items.len(), TableItem::field_count());
-    for (start_ind, end_ind) in chunks {
-        execute_with_better_error(
+    for (chunk_index, (start_ind, end_ind)) in chunks.into_iter().enumerate() {
+        execute_chunk_with_context(
             conn,
-            diesel::insert_into(schema::table_items::table)
-                .values(&items[start_ind..end_ind])
-                .on_conflict((ti::transaction_version, ti::write_set_change_index))
-                .do_nothing(),
+            "table_items",
+            chunk_index,
+            &items[start_ind..end_ind],
+            |chunk| {
+                diesel::insert_into(schema::table_items::table)
+                    .values(chunk)
+                    .on_conflict((ti::transaction_version, ti::write_set_change_index))
+                    .do_nothing()
+            },
             None,
         )?;
     }
     let chunks = get_chunks(metadata_nonnull.len(), TableMetadata::field_count());
-    for (start_ind, end_ind) in chunks {
-        execute_with_better_error(
+    for (chunk_index, (start_ind, end_ind)) in chunks.into_iter().enumerate() {
+        execute_chunk_with_context(
             conn,
-            diesel::insert_into(schema::table_metadatas::table)
-                .values(&metadata_nonnull[start_ind..end_ind])
-                .on_conflict(tm::handle)
-                .do_nothing(),
+            "table_metadatas",
+            chunk_index,
+            &metadata_nonnull[start_ind..end_ind],
+            |chunk| {
+                diesel::insert_into(schema::table_metadatas::table)
+                    .values(chunk)
+                    .on_conflict(tm::handle)
+                    .do_nothing()
+            },
             None,
         )?;
     }
@@ -360,18 +411,22 @@ impl TransactionProcessor for DefaultTransactionProcessor {
         let (txns, user_txns, bm_txns, events, write_set_changes) =
             TransactionModel::from_transactions(&transactions);
 
-        let mut conn = self.get_conn();
-        let tx_result = insert_to_db(
-            &mut conn,
-            self.name(),
-            start_version,
-            end_version,
-            txns,
-            user_txns,
-            bm_txns,
-            events,
-            write_set_changes,
-        );
+        let mut conn = self.get_conn_async().await;
+        let name = self.name();
+        let tx_result = run_blocking(move || {
+            insert_to_db(
+                &mut conn,
+                name,
+                start_version,
+                end_version,
+                txns,
+                user_txns,
+                bm_txns,
+                events,
+                write_set_changes,
+            )
+        })
+        .await;
         match tx_result {
             Ok(_) => Ok(ProcessingResult::new(
                 self.name(),
@@ -17,11 +17,17 @@ pub enum Processor {
 
 impl Processor {
     pub fn from_string(input_str: &String) -> Self {
-        match input_str.as_str() {
-            DEFAULT_PROCESSOR_NAME => Self::DefaultProcessor,
-            TOKEN_PROCESSOR_NAME => Self::TokenProcessor,
-            COIN_PROCESSOR_NAME => Self::CoinProcessor,
-            _ => panic!("Processor unsupported {}", input_str),
+        Self::try_from_string(input_str)
+            .unwrap_or_else(|| panic!("Processor unsupported {}", input_str))
+    }
+
+    /// Same as `from_string` but returns `None` for unknown processor names
+    pub fn try_from_string(input_str: &str) -> Option<Self> {
+        match input_str {
+            DEFAULT_PROCESSOR_NAME => Some(Self::DefaultProcessor),
+            TOKEN_PROCESSOR_NAME => Some(Self::TokenProcessor),
+            COIN_PROCESSOR_NAME => Some(Self::CoinProcessor),
+            _ => None,
         }
     }
 }
@@ -3,11 +3,13 @@
 
 use crate::{
     database::{
-        clean_data_for_db, execute_with_better_error, get_chunks, PgDbPool, PgPoolConnection,
+        clean_data_for_db, execute_chunk_with_context, get_chunks, is_retryable_error,
+        run_transaction_with_retries, PgDbPool, PgPoolConnection,
     },
     indexer::{
-        errors::TransactionProcessingError, processing_result::ProcessingResult,
-        transaction_processor::TransactionProcessor,
+        errors::TransactionProcessingError,
+        processing_result::ProcessingResult,
+        transaction_processor::{run_blocking, TransactionProcessor},
     },
     models::stake_models::staking_pool_voter::{CurrentStakingPoolVoter, StakingPoolVoterMap},
     schema,
@@ -61,12 +63,12 @@ fn insert_to_db(
         end_version = end_version,
         "Inserting to db",
     );
-    match conn
-        .build_transaction()
-        .read_write()
-        .run::<_, Error, _>(|pg_conn| insert_to_db_impl(pg_conn, &current_stake_pool_voters))
-    {
+    match run_transaction_with_retries(conn, |pg_conn| {
+        insert_to_db_impl(pg_conn, &current_stake_pool_voters)
+    }) {
         Ok(_) => Ok(()),
+        // Cleaning the data won't help, and it's been retried already
+        Err(err) if is_retryable_error(&err) => Err(err),
         Err(_) => conn
             .build_transaction()
             .read_write()
@@ -85,19 +87,24 @@ fn insert_current_stake_pool_voter(
     use schema::current_staking_pool_voter::dsl::*;
 
     let chunks = get_chunks(item_to_insert.len(), CurrentStakingPoolVoter::field_count());
-    for (start_ind, end_ind) in chunks {
-        execute_with_better_error(
+    for (chunk_index, (start_ind, end_ind)) in chunks.into_iter().enumerate() {
+        execute_chunk_with_context(
             conn,
-            diesel::insert_into(schema::current_staking_pool_voter::table)
-                .values(&item_to_insert[start_ind..end_ind])
-                .on_conflict(staking_pool_address)
-                .do_update()
-                .set((
-                    staking_pool_address.eq(excluded(staking_pool_address)),
-                    voter_address.eq(excluded(voter_address)),
-                    last_transaction_version.eq(excluded(last_transaction_version)),
-                    inserted_at.eq(excluded(inserted_at)),
-                )),
+            "current_staking_pool_voter",
+            chunk_index,
+            &item_to_insert[start_ind..end_ind],
+            |chunk| {
+                diesel::insert_into(schema::current_staking_pool_voter::table)
+                    .values(chunk)
+                    .on_conflict(staking_pool_address)
+                    .do_update()
+                    .set((
+                        staking_pool_address.eq(excluded(staking_pool_address)),
+                        voter_address.eq(excluded(voter_address)),
+                        last_transaction_version.eq(excluded(last_transaction_version)),
+                        inserted_at.eq(excluded(inserted_at)),
+                    ))
+            },
             Some(
                 " WHERE current_staking_pool_voter.last_transaction_version <= EXCLUDED.last_transaction_version ",
             ),
@@ -132,14 +139,18 @@ impl TransactionProcessor for StakeTransactionProcessor {
         all_current_stake_pool_voters
             .sort_by(|a, b| a.staking_pool_address.cmp(&b.staking_pool_address));
 
-        let mut conn = self.get_conn();
-        let tx_result = insert_to_db(
-            &mut conn,
-            self.name(),
-            start_version,
-            end_version,
-            all_current_stake_pool_voters,
-        );
+        let mut conn = self.get_conn_async().await;
+        let name = self.name();
+        let tx_result = run_blocking(move || {
+            insert_to_db(
+                &mut conn,
+                name,
+                start_version,
+                end_version,
+                all_current_stake_pool_voters,
+            )
+        })
+        .await;
         match tx_result {
             Ok(_) => Ok(ProcessingResult::new(
                 self.name(),
@@ -2,47 +2,408 @@
 // SPDX-License-Identifier: Apache-2.0
 
 use crate::{
+    counters::SUSPECT_VALUE_EXCLUSIONS,
     database::{
-        clean_data_for_db, execute_with_better_error, get_chunks, PgDbPool, PgPoolConnection,
+        clean_data_for_db, execute_chunk_with_context, get_chunks, is_retryable_error,
+        run_transaction_with_retries, PgDbPool, PgPoolConnection,
     },
     indexer::{
-        errors::TransactionProcessingError, processing_result::ProcessingResult,
-        transaction_processor::TransactionProcessor,
+        errors::TransactionProcessingError,
+        processing_result::ProcessingResult,
+        transaction_processor::{run_blocking, BatchCheckpoint, TransactionProcessor},
+        transaction_trace::TransactionTracer,
     },
-    models::token_models::{
-        ans_lookup::{CurrentAnsLookup, CurrentAnsLookupPK},
-        collection_datas::{CollectionData, CurrentCollectionData},
-        token_activities::TokenActivity,
-        token_claims::CurrentTokenPendingClaim,
-        token_datas::{CurrentTokenData, TokenData},
-        token_ownerships::{CurrentTokenOwnership, TokenOwnership},
-        tokens::{CurrentTokenOwnershipPK, CurrentTokenPendingClaimPK, Token, TokenDataIdHash, CollectionDataIdHash},
-        marketplace_listings::{CurrentMarketplaceListing},
-        collection_volume::{CurrentCollectionVolume, CollectionVolume, CurrentTokenVolume, TokenVolume}
+    live_feed::LiveFeed,
+    models::{
+        data_integrity_findings::DataIntegrityFinding,
+        processor_status::advance_chain_timestamp,
+        token_models::{
+            account_token_activities::AccountTokenActivity,
+            activity_partitions::TokenActivityPartitions,
+            ans_lookup::{
+                AnsContract, CurrentAnsLookup, CurrentAnsLookupPK, CurrentAnsPrimaryName,
+                CurrentAnsPrimaryNamePK,
+            },
+            ans_sales::{AnsDomains, CurrentAnsSalePrice},
+            coin_decimals::CoinDecimals,
+            coin_prices::CoinPrices,
+            collection_curations::CollectionCurations,
+            collection_datas::{CollectionCreators, CollectionData, CurrentCollectionData},
+            collection_holder_counts::CurrentCollectionHolderCount,
+            collection_mints::{CollectionMint, CurrentCollectionMintStat},
+            collection_offers::{
+                apply_collection_offer_fills, expire_collection_offers,
+                refresh_collection_best_offers, refresh_collections_best_offers,
+                CollectionOfferBook, CollectionOfferFill, CurrentCollectionOffer,
+            },
+            collection_price_stats::{CollectionPriceMedians, CurrentCollectionPriceStat},
+            collection_rarity::CollectionRarity,
+            collection_reports::{CollectionDailyReport, CollectionPriceCandle},
+            collection_stats_snapshots::CollectionStatsSnapshots,
+            collection_volume::{
+                CollectionVolume, CurrentCollectionVolume, CurrentTokenVolume, TokenVolume,
+            },
+            consistency_check::ConsistencyCheck,
+            deleted_token_resources::DeletedTokenResource,
+            event_effects::TokenEventEffects,
+            leaderboards::Leaderboards,
+            marketplace_event_mappings::MarketplaceEventMappings,
+            marketplace_listing_price_changes::{
+                ListingPriceChangeBook, MarketplaceListingPriceChange,
+            },
+            marketplace_listings::CurrentMarketplaceListing,
+            marketplace_volumes::{
+                CurrentMarketplaceVolume, MarketplaceCollectionVolume, MarketplaceVolume,
+                MarketplaceVolumes,
+            },
+            nft_sales::{
+                BlockPosition, NftSale, PrimarySaleClassifier, EVENT_SOURCE,
+                PAYLOAD_INFERRED_SOURCE,
+            },
+            nft_transaction_fees::NftTransactionFee,
+            table_handle_cache::TableHandleCache,
+            token_acquisitions::{refresh_collection_hold_durations, TokenAcquisition},
+            token_activities::{TokenActivity, TokenActivityPK},
+            token_bids::{
+                apply_token_bid_fills, expire_outbid_token_bids, expire_token_bids,
+                refresh_token_top_bids, refresh_tokens_top_bids, CurrentTokenBid, TokenAuctionBid,
+                TokenBidBook, TokenBidFill,
+            },
+            token_claims::CurrentTokenPendingClaim,
+            token_datas::{CurrentTokenData, TokenData},
+            token_ownerships::{CurrentTokenOwnership, TokenOwnership},
+            token_properties_flat::TokenPropertyFlat,
+            token_property_mutations::TokenPropertyMutation,
+            token_tables::TokenTables,
+            token_transfer_offers::{CurrentTokenTransferOffer, TokenTransferOfferBook},
+            token_utils::TokenEvents,
+            tokens::{
+                CurrentTokenOwnershipPK, CurrentTokenPendingClaimPK, ParsedTokens, Token,
+                TokenDataIdHash,
+            },
+            truncated_strings::{TruncatedString, TruncatedStringPK},
+            volume_reconciliation::VolumeReconciliation,
+            wallet_cost_basis::WalletTokenCostBasis,
+            wallet_nft_stats::CurrentWalletNftStat,
+        },
     },
+    parquet_sink::{CommittedRows, ParquetSink},
     schema,
+    util::parse_timestamp,
 };
+use anyhow::{ensure, Context};
 use aptos_api_types::Transaction;
 use async_trait::async_trait;
-use diesel::{pg::upsert::excluded, result::Error, ExpressionMethods, PgConnection};
+use diesel::{
+    pg::upsert::excluded,
+    result::Error,
+    sql_function, sql_query,
+    sql_types::{Array, BigInt, Nullable, Text},
+    BoolExpressionMethods, Connection, ExpressionMethods, PgConnection, QueryDsl, RunQueryDsl,
+};
 use field_count::FieldCount;
-use std::{collections::HashMap, fmt::Debug};
+use futures::future::join_all;
+use rayon::prelude::*;
+use serde::Serialize;
+use std::{
+    collections::{hash_map::DefaultHasher, BTreeMap, HashMap, HashSet},
+    fmt::Debug,
+    hash::{Hash, Hasher},
+    sync::Arc,
+};
 
 pub const NAME: &str = "token_processor";
+
+sql_function!(fn greatest(a: BigInt, b: BigInt) -> BigInt);
+sql_function!(fn least(a: BigInt, b: BigInt) -> BigInt);
+sql_function!(fn coalesce(a: Nullable<Text>, b: Nullable<Text>) -> Nullable<Text>);
+
 pub struct TokenTransactionProcessor {
     connection_pool: PgDbPool,
-    ans_contract_address: Option<String>,
+    row_builder: Arc<TokenRowBuilder>,
+    volume_reconciliation: Option<VolumeReconciliation>,
+    consistency_check: Option<ConsistencyCheck>,
+    collection_rarity: Option<CollectionRarity>,
+    collection_price_medians: Option<CollectionPriceMedians>,
+    collection_stats_snapshots: Option<CollectionStatsSnapshots>,
+    leaderboards: Option<Leaderboards>,
+    tables: TokenTables,
+    activity_partitions: Option<TokenActivityPartitions>,
+    num_shards: usize,
+    live_feed: Option<LiveFeed>,
+    parquet_sink: Option<ParquetSink>,
 }
 
 impl TokenTransactionProcessor {
-    pub fn new(connection_pool: PgDbPool, ans_contract_address: Option<String>) -> Self {
+    pub fn new(
+        connection_pool: PgDbPool,
+        ans_contracts: Vec<AnsContract>,
+        ans_domains: AnsDomains,
+        marketplace_event_mappings: MarketplaceEventMappings,
+        volume_reconciliation: Option<VolumeReconciliation>,
+        consistency_check: Option<ConsistencyCheck>,
+        transaction_tracer: TransactionTracer,
+        collection_rarity: Option<CollectionRarity>,
+        collection_price_medians: Option<CollectionPriceMedians>,
+        collection_stats_snapshots: Option<CollectionStatsSnapshots>,
+        leaderboards: Option<Leaderboards>,
+        record_settlement_amounts: bool,
+        account_token_activities: bool,
+        table_handle_cache: TableHandleCache,
+        tables: TokenTables,
+        activity_partitions: Option<TokenActivityPartitions>,
+        num_shards: usize,
+        live_feed: Option<LiveFeed>,
+        parquet_sink: Option<ParquetSink>,
+    ) -> Self {
         aptos_logger::info!(
-            ans_contract_address = ans_contract_address,
+            ans_contracts = ?ans_contracts,
+            ans_domains = ?ans_domains,
+            volume_reconciliation = ?volume_reconciliation,
+            consistency_check = ?consistency_check,
+            collection_rarity = ?collection_rarity,
+            collection_price_medians = ?collection_price_medians,
+            collection_stats_snapshots = ?collection_stats_snapshots,
+            leaderboards = ?leaderboards,
+            record_settlement_amounts = record_settlement_amounts,
+            account_token_activities = account_token_activities,
+            tables = ?tables,
+            activity_partitions = ?activity_partitions,
+            num_shards = num_shards,
+            live_feed = live_feed.is_some(),
+            parquet_sink = parquet_sink.is_some(),
             "init TokenTransactionProcessor"
         );
         Self {
             connection_pool,
-            ans_contract_address,
+            row_builder: Arc::new(TokenRowBuilder {
+                ans_contracts,
+                ans_domains,
+                marketplace_event_mappings,
+                transaction_tracer,
+                record_settlement_amounts,
+                account_token_activities,
+                table_handle_cache,
+            }),
+            volume_reconciliation,
+            consistency_check,
+            collection_rarity,
+            collection_price_medians,
+            collection_stats_snapshots,
+            leaderboards,
+            tables,
+            activity_partitions,
+            num_shards,
+            live_feed,
+            parquet_sink,
+        }
+    }
+
+    /// Shards to write each batch with, from the token_processor_shards config
+    pub fn num_shards_from_config(num_shards: Option<u64>) -> anyhow::Result<usize> {
+        let num_shards = num_shards.unwrap_or(1);
+        ensure!(
+            num_shards > 0,
+            "token_processor_shards must be greater than 0"
+        );
+        Ok(num_shards as usize)
+    }
+
+    /// Runs the volume self check if it's due. Failing to run it shouldn't fail the batch, which
+    /// has already been committed, so errors are only logged.
+    fn reconcile_collection_volumes(&self, conn: &mut PgPoolConnection, end_version: u64) {
+        let reconciliation = match &self.volume_reconciliation {
+            Some(reconciliation) if reconciliation.is_due() => reconciliation,
+            _ => return,
+        };
+        let result = reconciliation
+            .run(conn, end_version as i64)
+            .and_then(|findings| {
+                insert_data_integrity_findings(conn, &findings)?;
+                Ok(findings.len())
+            });
+        match result {
+            Ok(0) => {}
+            Ok(num_findings) => aptos_logger::warn!(
+                end_version = end_version,
+                num_findings = num_findings,
+                "Collection volumes drifted from nft_sales, see data_integrity_findings"
+            ),
+            Err(err) => aptos_logger::error!(
+                end_version = end_version,
+                error = ?err,
+                "Failed to reconcile collection volumes"
+            ),
+        }
+    }
+
+    /// Runs the consistency check if it's due, with the same error handling as the volume check
+    fn check_consistency(&self, conn: &mut PgPoolConnection, end_version: u64) {
+        let check = match &self.consistency_check {
+            Some(check) if check.is_due() => check,
+            _ => return,
+        };
+        let result = check.run(conn, end_version as i64).and_then(|findings| {
+            insert_data_integrity_findings(conn, &findings)?;
+            Ok(findings.len())
+        });
+        match result {
+            Ok(0) => {}
+            Ok(num_findings) => aptos_logger::warn!(
+                end_version = end_version,
+                num_findings = num_findings,
+                "Current tables differ from their replayed history, see data_integrity_findings"
+            ),
+            Err(err) => aptos_logger::error!(
+                end_version = end_version,
+                error = ?err,
+                "Failed to check consistency"
+            ),
+        }
+    }
+
+    /// Refreshes rarity for collections with token data changes if it's due. Like the volume
+    /// check, errors are only logged and the next refresh retries the same collections.
+    fn refresh_collection_rarity(&self, conn: &mut PgPoolConnection, end_version: u64) {
+        let rarity = match &self.collection_rarity {
+            Some(rarity) if rarity.is_due(end_version) => rarity,
+            _ => return,
+        };
+        match rarity.run(conn, self.name(), end_version) {
+            Ok(num_collections) => aptos_logger::debug!(
+                end_version = end_version,
+                num_collections = num_collections,
+                "Refreshed collection rarity"
+            ),
+            Err(err) => aptos_logger::error!(
+                end_version = end_version,
+                error = ?err,
+                "Failed to refresh collection rarity"
+            ),
+        }
+    }
+
+    /// Recomputes the median sale price of collections with new sales if it's due, with errors
+    /// only logged like the other periodic passes
+    fn refresh_price_medians(&self, conn: &mut PgPoolConnection, end_version: u64) {
+        if !matches!(&self.collection_price_medians, Some(medians) if medians.is_due(end_version)) {
+            return;
+        }
+        match CollectionPriceMedians::refresh(conn) {
+            Ok(num_collections) => aptos_logger::debug!(
+                end_version = end_version,
+                num_collections = num_collections,
+                "Refreshed collection price medians"
+            ),
+            Err(err) => aptos_logger::error!(
+                end_version = end_version,
+                error = ?err,
+                "Failed to refresh collection price medians"
+            ),
+        }
+    }
+
+    /// Takes the day's collection stats snapshots if they're due, with errors only logged like
+    /// the other periodic passes. `as_of` is the time of the batch's last transaction.
+    fn snapshot_collection_stats(
+        &self,
+        conn: &mut PgPoolConnection,
+        as_of: Option<chrono::NaiveDateTime>,
+    ) {
+        let as_of = match (&self.collection_stats_snapshots, as_of) {
+            (Some(snapshots), Some(as_of)) if snapshots.is_due(as_of) => as_of,
+            _ => return,
+        };
+        match CollectionStatsSnapshots::snapshot(conn, as_of) {
+            Ok(num_collections) => aptos_logger::debug!(
+                as_of = as_of.to_string(),
+                num_collections = num_collections,
+                "Took collection stats snapshots"
+            ),
+            Err(err) => aptos_logger::error!(
+                as_of = as_of.to_string(),
+                error = ?err,
+                "Failed to take collection stats snapshots"
+            ),
+        }
+    }
+
+    /// Rebuilds the leaderboards if they're due. `as_of` is the time of the batch's last
+    /// transaction.
+    fn refresh_leaderboards(
+        &self,
+        conn: &mut PgPoolConnection,
+        as_of: Option<chrono::NaiveDateTime>,
+    ) {
+        let (leaderboards, as_of) = match (&self.leaderboards, as_of) {
+            (Some(leaderboards), Some(as_of)) if leaderboards.is_due(as_of) => {
+                (leaderboards, as_of)
+            }
+            _ => return,
+        };
+        match leaderboards.refresh(conn, as_of) {
+            Ok(Some((num_collections, num_traders))) => aptos_logger::debug!(
+                as_of = as_of.to_string(),
+                num_collections = num_collections,
+                num_traders = num_traders,
+                "Refreshed leaderboards"
+            ),
+            Ok(None) => aptos_logger::debug!(
+                as_of = as_of.to_string(),
+                "Leaderboards were already refreshed by a later batch"
+            ),
+            Err(err) => aptos_logger::error!(
+                as_of = as_of.to_string(),
+                error = ?err,
+                "Failed to refresh leaderboards"
+            ),
+        }
+    }
+
+    /// Records `as_of`, the time of the batch's last transaction, as the latest processed chain
+    /// time and expires the bids and collection offers past their deadline at that time. Errors
+    /// are only logged, the next batch expires the same offers.
+    fn expire_offers(&self, conn: &mut PgPoolConnection, as_of: Option<chrono::NaiveDateTime>) {
+        let offers_enabled = self.tables.is_enabled("current_collection_offers");
+        let bids_enabled = self.tables.is_enabled("current_token_bids");
+        let as_of = match as_of {
+            Some(as_of) if offers_enabled || bids_enabled => as_of,
+            _ => return,
+        };
+        let result = conn.transaction::<_, Error, _>(|conn| {
+            let as_of = advance_chain_timestamp(conn, self.name(), as_of)?;
+            let mut num_expired = 0;
+            if offers_enabled {
+                let collection_data_id_hashes = expire_collection_offers(conn, as_of)?;
+                num_expired += collection_data_id_hashes.len();
+                if !collection_data_id_hashes.is_empty()
+                    && self.tables.is_enabled("current_collection_best_offers")
+                {
+                    refresh_collections_best_offers(conn, collection_data_id_hashes, as_of)?;
+                }
+            }
+            if bids_enabled {
+                let token_data_id_hashes = expire_token_bids(conn, as_of)?;
+                num_expired += token_data_id_hashes.len();
+                if !token_data_id_hashes.is_empty()
+                    && self.tables.is_enabled("current_token_top_bids")
+                {
+                    refresh_tokens_top_bids(conn, token_data_id_hashes)?;
+                }
+            }
+            Ok((as_of, num_expired))
+        });
+        match result {
+            Ok((_, 0)) => {}
+            Ok((as_of, num_expired)) => aptos_logger::debug!(
+                as_of = as_of.to_string(),
+                num_expired = num_expired,
+                "Expired offers past their deadline"
+            ),
+            Err(err) => aptos_logger::error!(
+                error = ?err,
+                "Failed to expire offers"
+            ),
         }
     }
 }
@@ -58,8 +419,242 @@ impl Debug for TokenTransactionProcessor {
     }
 }
 
+/// A batch's rows, or a shard's share of them, written in one db transaction
+#[derive(Default, Serialize)]
+pub struct TokenBatchRows {
+    tokens: Vec<Token>,
+    token_ownerships: Vec<TokenOwnership>,
+    token_datas: Vec<TokenData>,
+    collection_datas: Vec<CollectionData>,
+    current_token_ownerships: Vec<CurrentTokenOwnership>,
+    current_token_datas: Vec<CurrentTokenData>,
+    current_collection_datas: Vec<CurrentCollectionData>,
+    token_activities: Vec<TokenActivity>,
+    account_token_activities: Vec<AccountTokenActivity>,
+    nft_sales: Vec<NftSale>,
+    nft_transaction_fees: Vec<NftTransactionFee>,
+    current_token_claims: Vec<CurrentTokenPendingClaim>,
+    current_token_transfer_offers: Vec<CurrentTokenTransferOffer>,
+    token_transfer_offer_additions: Vec<CurrentTokenTransferOffer>,
+    current_ans_lookups: Vec<CurrentAnsLookup>,
+    current_ans_primary_names: Vec<CurrentAnsPrimaryName>,
+    current_marketplace_listings: Vec<CurrentMarketplaceListing>,
+    marketplace_listing_price_changes: Vec<MarketplaceListingPriceChange>,
+    current_collection_volumes: Vec<CurrentCollectionVolume>,
+    collection_volumes: Vec<CollectionVolume>,
+    current_token_volumes: Vec<CurrentTokenVolume>,
+    token_volumes: Vec<TokenVolume>,
+    collection_price_candles: Vec<CollectionPriceCandle>,
+    collection_daily_reports: Vec<CollectionDailyReport>,
+    token_properties_flat: Vec<TokenPropertyFlat>,
+    collection_mints: Vec<CollectionMint>,
+    token_property_mutations: Vec<TokenPropertyMutation>,
+    current_wallet_nft_stats: Vec<CurrentWalletNftStat>,
+    wallet_token_cost_basis: Vec<WalletTokenCostBasis>,
+    token_acquisitions: Vec<TokenAcquisition>,
+    current_collection_offers: Vec<CurrentCollectionOffer>,
+    collection_offer_fills: Vec<CollectionOfferFill>,
+    current_token_bids: Vec<CurrentTokenBid>,
+    token_bid_fills: Vec<TokenBidFill>,
+    token_auction_bids: Vec<TokenAuctionBid>,
+    truncated_strings: Vec<TruncatedString>,
+    // current_daily_collection_volumes: Vec<CurrentDailyCollectionVolume>,
+    // current_weekly_collection_volumes: Vec<CurrentWeeklyCollectionVolume>,
+    // current_monthly_collection_volumes: Vec<CurrentMonthlyCollectionVolume>,
+}
+
+impl TokenBatchRows {
+    /// Splits the rows by collection, so no two shards write the same row. Claims, ANS and the
+    /// rows that aren't keyed by collection stay in the first shard, along with all of the bid
+    /// tables since fills and auction bids update bids from any collection. Rows keep their
+    /// order, so each shard's current rows are still sorted by PK. The marketplace volumes are
+    /// totalled from each shard's collection_volumes, so every shard adds to them.
+    fn split(self, num_shards: usize) -> Vec<Self> {
+        if num_shards <= 1 {
+            return vec![self];
+        }
+        let mut shards = (0..num_shards).map(|_| Self::default()).collect::<Vec<_>>();
+        route_by_collection(
+            &mut shards,
+            self.tokens,
+            |row| row.collection_data_id_hash.as_str(),
+            |shard| &mut shard.tokens,
+        );
+        route_by_collection(
+            &mut shards,
+            self.token_ownerships,
+            |row| row.collection_data_id_hash.as_str(),
+            |shard| &mut shard.token_ownerships,
+        );
+        route_by_collection(
+            &mut shards,
+            self.token_datas,
+            |row| row.collection_data_id_hash.as_str(),
+            |shard| &mut shard.token_datas,
+        );
+        route_by_collection(
+            &mut shards,
+            self.collection_datas,
+            |row| row.collection_data_id_hash.as_str(),
+            |shard| &mut shard.collection_datas,
+        );
+        route_by_collection(
+            &mut shards,
+            self.current_token_ownerships,
+            |row| row.collection_data_id_hash.as_str(),
+            |shard| &mut shard.current_token_ownerships,
+        );
+        route_by_collection(
+            &mut shards,
+            self.current_token_datas,
+            |row| row.collection_data_id_hash.as_str(),
+            |shard| &mut shard.current_token_datas,
+        );
+        route_by_collection(
+            &mut shards,
+            self.current_collection_datas,
+            |row| row.collection_data_id_hash.as_str(),
+            |shard| &mut shard.current_collection_datas,
+        );
+        route_by_collection(
+            &mut shards,
+            self.token_activities,
+            |row| row.collection_data_id_hash.as_str(),
+            |shard| &mut shard.token_activities,
+        );
+        route_by_collection(
+            &mut shards,
+            self.account_token_activities,
+            |row| row.collection_data_id_hash.as_str(),
+            |shard| &mut shard.account_token_activities,
+        );
+        route_by_collection(
+            &mut shards,
+            self.nft_sales,
+            |row| row.collection_data_id_hash.as_str(),
+            |shard| &mut shard.nft_sales,
+        );
+        route_by_collection(
+            &mut shards,
+            self.current_marketplace_listings,
+            |row| row.collection_data_id_hash.as_str(),
+            |shard| &mut shard.current_marketplace_listings,
+        );
+        route_by_collection(
+            &mut shards,
+            self.marketplace_listing_price_changes,
+            |row| row.collection_data_id_hash.as_str(),
+            |shard| &mut shard.marketplace_listing_price_changes,
+        );
+        route_by_collection(
+            &mut shards,
+            self.current_collection_volumes,
+            |row| row.collection_data_id_hash.as_str(),
+            |shard| &mut shard.current_collection_volumes,
+        );
+        route_by_collection(
+            &mut shards,
+            self.collection_volumes,
+            |row| row.collection_data_id_hash.as_str(),
+            |shard| &mut shard.collection_volumes,
+        );
+        route_by_collection(
+            &mut shards,
+            self.collection_price_candles,
+            |row| row.collection_data_id_hash.as_str(),
+            |shard| &mut shard.collection_price_candles,
+        );
+        route_by_collection(
+            &mut shards,
+            self.collection_daily_reports,
+            |row| row.collection_data_id_hash.as_str(),
+            |shard| &mut shard.collection_daily_reports,
+        );
+        route_by_collection(
+            &mut shards,
+            self.token_properties_flat,
+            |row| row.collection_data_id_hash.as_str(),
+            |shard| &mut shard.token_properties_flat,
+        );
+        route_by_collection(
+            &mut shards,
+            self.collection_mints,
+            |row| row.collection_data_id_hash.as_str(),
+            |shard| &mut shard.collection_mints,
+        );
+        route_by_collection(
+            &mut shards,
+            self.token_property_mutations,
+            |row| row.collection_data_id_hash.as_str(),
+            |shard| &mut shard.token_property_mutations,
+        );
+        route_by_collection(
+            &mut shards,
+            self.current_collection_offers,
+            |row| row.collection_data_id_hash.as_str(),
+            |shard| &mut shard.current_collection_offers,
+        );
+        route_by_collection(
+            &mut shards,
+            self.collection_offer_fills,
+            |row| row.collection_data_id_hash.as_str(),
+            |shard| &mut shard.collection_offer_fills,
+        );
+        route_by_collection(
+            &mut shards,
+            self.current_token_transfer_offers,
+            |row| row.collection_data_id_hash.as_str(),
+            |shard| &mut shard.current_token_transfer_offers,
+        );
+        route_by_collection(
+            &mut shards,
+            self.token_transfer_offer_additions,
+            |row| row.collection_data_id_hash.as_str(),
+            |shard| &mut shard.token_transfer_offer_additions,
+        );
+        let first = &mut shards[0];
+        first.nft_transaction_fees = self.nft_transaction_fees;
+        first.current_token_claims = self.current_token_claims;
+        first.current_ans_lookups = self.current_ans_lookups;
+        first.current_ans_primary_names = self.current_ans_primary_names;
+        first.current_token_volumes = self.current_token_volumes;
+        first.token_volumes = self.token_volumes;
+        first.current_wallet_nft_stats = self.current_wallet_nft_stats;
+        first.wallet_token_cost_basis = self.wallet_token_cost_basis;
+        first.token_acquisitions = self.token_acquisitions;
+        first.current_token_bids = self.current_token_bids;
+        first.token_bid_fills = self.token_bid_fills;
+        first.token_auction_bids = self.token_auction_bids;
+        first.truncated_strings = self.truncated_strings;
+        shards
+    }
+}
+
+/// Shard that writes a collection's rows
+fn collection_shard(collection_data_id_hash: &str, num_shards: usize) -> usize {
+    let mut hasher = DefaultHasher::new();
+    collection_data_id_hash.hash(&mut hasher);
+    (hasher.finish() % num_shards as u64) as usize
+}
+
+fn route_by_collection<T>(
+    shards: &mut [TokenBatchRows],
+    rows: Vec<T>,
+    collection_data_id_hash: impl Fn(&T) -> &str,
+    shard_rows: impl Fn(&mut TokenBatchRows) -> &mut Vec<T>,
+) {
+    let num_shards = shards.len();
+    for row in rows {
+        let shard = collection_shard(collection_data_id_hash(&row), num_shards);
+        shard_rows(&mut shards[shard]).push(row);
+    }
+}
+
+/// Returns false without writing anything if the checkpoint shows the batch already committed
 fn insert_to_db_impl(
     conn: &mut PgConnection,
+    tables: &TokenTables,
+    checkpoint: Option<&BatchCheckpoint>,
     basic_token_transaction_lists: (&[Token], &[TokenOwnership], &[TokenData], &[CollectionData]),
     basic_token_current_lists: (
         &[CurrentTokenOwnership],
@@ -67,17 +662,44 @@ fn insert_to_db_impl(
         &[CurrentCollectionData],
     ),
     token_activities: &[TokenActivity],
+    account_token_activities: &[AccountTokenActivity],
+    nft_sales: &[NftSale],
+    nft_transaction_fees: &[NftTransactionFee],
     current_token_claims: &[CurrentTokenPendingClaim],
+    current_token_transfer_offers: &[CurrentTokenTransferOffer],
+    token_transfer_offer_additions: &[CurrentTokenTransferOffer],
     current_ans_lookups: &[CurrentAnsLookup],
+    current_ans_primary_names: &[CurrentAnsPrimaryName],
     all_current_marketplace_listings: &[CurrentMarketplaceListing],
+    marketplace_listing_price_changes: &[MarketplaceListingPriceChange],
     current_collection_volumes: &[CurrentCollectionVolume],
     collection_volumes: &[CollectionVolume],
     current_token_volumes: &[CurrentTokenVolume],
     token_volumes: &[TokenVolume],
+    collection_price_candles: &[CollectionPriceCandle],
+    collection_daily_reports: &[CollectionDailyReport],
+    token_properties_flat: &[TokenPropertyFlat],
+    collection_mints: &[CollectionMint],
+    token_property_mutations: &[TokenPropertyMutation],
+    current_wallet_nft_stats: &[CurrentWalletNftStat],
+    wallet_token_cost_basis: &[WalletTokenCostBasis],
+    token_acquisitions: &[TokenAcquisition],
+    current_collection_offers: &[CurrentCollectionOffer],
+    collection_offer_fills: &[CollectionOfferFill],
+    current_token_bids: &[CurrentTokenBid],
+    token_bid_fills: &[TokenBidFill],
+    token_auction_bids: &[TokenAuctionBid],
+    truncated_strings: &[TruncatedString],
     // current_daily_collection_volumes: &[CurrentDailyCollectionVolume],
     // current_weekly_collection_volumes: &[CurrentWeeklyCollectionVolume],
     // current_monthly_collection_volumes: &[CurrentMonthlyCollectionVolume],
-) -> Result<(), diesel::result::Error> {
+) -> Result<bool, diesel::result::Error> {
+    // Applying a batch twice would double count its volumes, which are added to the stored ones
+    if let Some(checkpoint) = checkpoint {
+        if checkpoint.is_committed(conn)? {
+            return Ok(false);
+        }
+    }
     let (tokens, token_ownerships, token_datas, collection_datas) = basic_token_transaction_lists;
     let (current_token_ownerships, current_token_datas, current_collection_datas) =
         basic_token_current_lists;
@@ -85,83 +707,292 @@ fn insert_to_db_impl(
     // insert_token_datas(conn, token_datas)?;
     // insert_token_ownerships(conn, token_ownerships)?;
     // insert_collection_datas(conn, collection_datas)?;
-    insert_current_token_ownerships(conn, current_token_ownerships)?;
-    insert_current_token_datas(conn, current_token_datas)?;
-    insert_current_collection_datas(conn, current_collection_datas)?;
-    insert_token_activities(conn, token_activities)?;
-    //insert_current_token_claims(conn, current_token_claims)?;
-    insert_current_ans_lookups(conn, current_ans_lookups)?;
-    insert_current_marketplace_listings(conn, all_current_marketplace_listings)?;
-    insert_current_collection_volumes(conn, current_collection_volumes)?;
-    insert_collection_volumes(conn, collection_volumes)?;
-    insert_current_token_volumes(conn, current_token_volumes)?;
-    insert_token_volumes(conn, token_volumes)?;
-    Ok(())
+    // Holder counts are derived from the ownerships before this batch, so they're computed within
+    // the db transaction and before the ownerships are written
+    if tables.is_enabled("current_collection_holder_counts") {
+        let current_collection_holder_counts =
+            CurrentCollectionHolderCount::from_current_token_ownerships(
+                conn,
+                current_token_ownerships,
+            )?;
+        insert_current_collection_holder_counts(conn, &current_collection_holder_counts)?;
+    }
+    if tables.is_enabled("current_token_ownerships") {
+        insert_current_token_ownerships(conn, current_token_ownerships)?;
+    }
+    // Same for mint stats, which skip mints that were already recorded
+    if tables.is_enabled("current_collection_mint_stats") {
+        let current_collection_mint_stats =
+            CurrentCollectionMintStat::from_collection_mints(conn, collection_mints)?;
+        insert_current_collection_mint_stats(conn, &current_collection_mint_stats)?;
+    }
+    if tables.is_enabled("collection_mints") {
+        insert_collection_mints(conn, collection_mints)?;
+    }
+    if tables.is_enabled("token_property_mutations") {
+        insert_token_property_mutations(conn, token_property_mutations)?;
+    }
+    if tables.is_enabled("current_token_datas") {
+        insert_current_token_datas(conn, current_token_datas)?;
+    }
+    if tables.is_enabled("token_properties_flat") {
+        insert_token_properties_flat(conn, token_properties_flat)?;
+        delete_stale_token_properties_flat(conn, current_token_datas)?;
+    }
+    if tables.is_enabled("current_collection_datas") {
+        insert_current_collection_datas(conn, current_collection_datas)?;
+    }
+    if tables.is_enabled("truncated_strings") {
+        insert_truncated_strings(conn, truncated_strings)?;
+    }
+    if tables.is_enabled("token_activities") {
+        insert_token_activities(conn, token_activities)?;
+    }
+    if tables.is_enabled("account_token_activities") {
+        insert_account_token_activities(conn, account_token_activities)?;
+    }
+    // Like the current volumes below, price and wallet stats only add the sales nft_sales didn't
+    // have yet
+    let new_nft_sales = if tables.is_enabled("nft_sales") {
+        Some(insert_nft_sales(conn, nft_sales)?)
+    } else {
+        None
+    };
+    if let Some(new_nft_sales) = &new_nft_sales {
+        if tables.is_enabled("current_collection_price_stats") {
+            let current_collection_price_stats =
+                CurrentCollectionPriceStat::from_nft_sales(new_nft_sales.iter().copied());
+            insert_current_collection_price_stats(conn, &current_collection_price_stats, false)?;
+        }
+    } else if tables.is_enabled("current_collection_price_stats") {
+        let current_collection_price_stats = CurrentCollectionPriceStat::from_nft_sales(nft_sales);
+        insert_current_collection_price_stats(conn, &current_collection_price_stats, true)?;
+    }
+    if tables.is_enabled("nft_transaction_fees") {
+        insert_nft_transaction_fees(conn, nft_transaction_fees)?;
+    }
+    if tables.is_enabled("collection_hold_durations") {
+        refresh_collection_hold_durations(conn, nft_sales)?;
+    }
+    if tables.is_enabled("current_token_pending_claims") {
+        insert_current_token_claims(conn, current_token_claims)?;
+    }
+    if tables.is_enabled("current_token_transfer_offers") {
+        insert_current_token_transfer_offers(conn, current_token_transfer_offers)?;
+        add_pending_token_transfer_offers(conn, token_transfer_offer_additions)?;
+    }
+    if tables.is_enabled("current_ans_lookups") {
+        insert_current_ans_lookups(conn, current_ans_lookups)?;
+    }
+    if tables.is_enabled("current_ans_primary_names") {
+        insert_current_ans_primary_names(conn, current_ans_primary_names)?;
+    }
+    if tables.is_enabled("current_ans_sale_prices") {
+        let current_ans_sale_prices = CurrentAnsSalePrice::from_nft_sales(nft_sales);
+        insert_current_ans_sale_prices(conn, &current_ans_sale_prices)?;
+    }
+    if tables.is_enabled("current_marketplace_listings") {
+        insert_current_marketplace_listings(conn, all_current_marketplace_listings)?;
+    }
+    if tables.is_enabled("marketplace_listing_price_changes") {
+        insert_marketplace_listing_price_changes(conn, marketplace_listing_price_changes)?;
+    }
+    // Current volumes only add the sales the history didn't have yet, so replaying a range that
+    // was already processed, ex: in a backfill, doesn't count its sales twice
+    if tables.is_enabled("collection_volumes") {
+        let new_collection_volumes = insert_collection_volumes(conn, collection_volumes)?;
+        if tables.is_enabled("current_collection_volumes") {
+            let current_collection_volumes = CurrentCollectionVolume::from_collection_volumes(
+                new_collection_volumes.iter().copied(),
+            );
+            insert_current_collection_volumes(conn, &current_collection_volumes, false)?;
+        }
+        let marketplace_volumes =
+            MarketplaceVolumes::from_collection_volumes(new_collection_volumes);
+        insert_marketplace_volumes(conn, tables, &marketplace_volumes, false)?;
+    } else {
+        if tables.is_enabled("current_collection_volumes") {
+            insert_current_collection_volumes(conn, current_collection_volumes, true)?;
+        }
+        let marketplace_volumes = MarketplaceVolumes::from_collection_volumes(collection_volumes);
+        insert_marketplace_volumes(conn, tables, &marketplace_volumes, true)?;
+    }
+    if tables.is_enabled("token_volumes") {
+        let new_token_volumes = insert_token_volumes(conn, token_volumes)?;
+        if tables.is_enabled("current_token_volumes") {
+            let current_token_volumes = CurrentTokenVolume::from_token_volumes(new_token_volumes);
+            insert_current_token_volumes(conn, &current_token_volumes, false)?;
+        }
+    } else if tables.is_enabled("current_token_volumes") {
+        insert_current_token_volumes(conn, current_token_volumes, true)?;
+    }
+    // Same for candles and daily reports
+    if let Some(new_nft_sales) = &new_nft_sales {
+        let (new_collection_price_candles, new_collection_daily_reports) =
+            CollectionDailyReport::from_nft_sales(new_nft_sales.iter().copied());
+        if tables.is_enabled("collection_price_candles") {
+            let collection_price_candles = new_collection_price_candles
+                .into_values()
+                .collect::<Vec<_>>();
+            insert_collection_price_candles(conn, &collection_price_candles, false)?;
+        }
+        if tables.is_enabled("collection_daily_reports") {
+            let collection_daily_reports = new_collection_daily_reports
+                .into_values()
+                .collect::<Vec<_>>();
+            insert_collection_daily_reports(conn, &collection_daily_reports, false)?;
+        }
+    } else {
+        if tables.is_enabled("collection_price_candles") {
+            insert_collection_price_candles(conn, collection_price_candles, true)?;
+        }
+        if tables.is_enabled("collection_daily_reports") {
+            insert_collection_daily_reports(conn, collection_daily_reports, true)?;
+        }
+    }
+    if tables.is_enabled("current_wallet_nft_stats") {
+        if let Some(new_nft_sales) = &new_nft_sales {
+            let current_wallet_nft_stats =
+                CurrentWalletNftStat::from_nft_sales(new_nft_sales.iter().copied())
+                    .into_values()
+                    .collect::<Vec<_>>();
+            insert_current_wallet_nft_stats(conn, &current_wallet_nft_stats, false)?;
+        } else {
+            insert_current_wallet_nft_stats(conn, current_wallet_nft_stats, true)?;
+        }
+    }
+    if tables.is_enabled("wallet_token_cost_basis") {
+        insert_wallet_token_cost_basis(conn, wallet_token_cost_basis)?;
+    }
+    if tables.is_enabled("token_acquisitions") {
+        insert_token_acquisitions(conn, token_acquisitions)?;
+    }
+    if tables.is_enabled("current_collection_offers") {
+        insert_current_collection_offers(conn, current_collection_offers)?;
+        apply_collection_offer_fills(conn, collection_offer_fills)?;
+    }
+    if tables.is_enabled("current_collection_best_offers") {
+        refresh_collection_best_offers(conn, current_collection_offers, collection_offer_fills)?;
+    }
+    if tables.is_enabled("current_token_bids") {
+        insert_current_token_bids(conn, current_token_bids)?;
+        apply_token_bid_fills(conn, token_bid_fills)?;
+        expire_outbid_token_bids(conn, token_auction_bids)?;
+    }
+    if tables.is_enabled("current_token_top_bids") {
+        refresh_token_top_bids(conn, current_token_bids, token_bid_fills)?;
+    }
+    if let Some(checkpoint) = checkpoint {
+        checkpoint.commit(conn)?;
+    }
+    Ok(true)
 }
 
+/// Returns false if the batch was skipped because it already committed
 fn insert_to_db(
     conn: &mut PgPoolConnection,
     name: &'static str,
     start_version: u64,
     end_version: u64,
-    basic_token_transaction_lists: (
-        Vec<Token>,
-        Vec<TokenOwnership>,
-        Vec<TokenData>,
-        Vec<CollectionData>,
-    ),
-    basic_token_current_lists: (
-        Vec<CurrentTokenOwnership>,
-        Vec<CurrentTokenData>,
-        Vec<CurrentCollectionData>,
-    ),
-    token_activities: Vec<TokenActivity>,
-    current_token_claims: Vec<CurrentTokenPendingClaim>,
-    current_ans_lookups: Vec<CurrentAnsLookup>,
-    current_marketplace_listings: Vec<CurrentMarketplaceListing>,
-    current_collection_volumes: Vec<CurrentCollectionVolume>,
-    collection_volumes: Vec<CollectionVolume>,
-    current_token_volumes: Vec<CurrentTokenVolume>,
-    token_volumes: Vec<TokenVolume>,
-    // current_daily_collection_volumes: Vec<CurrentDailyCollectionVolume>,
-    // current_weekly_collection_volumes: Vec<CurrentWeeklyCollectionVolume>,
-    // current_monthly_collection_volumes: Vec<CurrentMonthlyCollectionVolume>,
-) -> Result<(), diesel::result::Error> {
+    tables: &TokenTables,
+    checkpoint: Option<&BatchCheckpoint>,
+    rows: TokenBatchRows,
+) -> Result<bool, diesel::result::Error> {
     aptos_logger::trace!(
         name = name,
         start_version = start_version,
         end_version = end_version,
         "Inserting to db",
     );
-    let (tokens, token_ownerships, token_datas, collection_datas) = basic_token_transaction_lists;
-    let (current_token_ownerships, current_token_datas, current_collection_datas) =
-        basic_token_current_lists;
-    match conn
-        .build_transaction()
-        .read_write()
-        .run::<_, Error, _>(|pg_conn| {
-            insert_to_db_impl(
-                pg_conn,
-                (&tokens, &token_ownerships, &token_datas, &collection_datas),
-                (
-                    &current_token_ownerships,
-                    &current_token_datas,
-                    &current_collection_datas,
-                ),
-                &token_activities,
-                &current_token_claims,
-                &current_ans_lookups,
-                &current_marketplace_listings,
-                &current_collection_volumes,
-                &collection_volumes,
-                &current_token_volumes,
-                &token_volumes,
-                // &current_daily_collection_volumes,
-                // &current_weekly_collection_volumes,
-                // &current_monthly_collection_volumes
-            )
-        }) {
-        Ok(_) => Ok(()),
+    let TokenBatchRows {
+        tokens,
+        token_ownerships,
+        token_datas,
+        collection_datas,
+        current_token_ownerships,
+        current_token_datas,
+        current_collection_datas,
+        token_activities,
+        account_token_activities,
+        nft_sales,
+        nft_transaction_fees,
+        current_token_claims,
+        current_token_transfer_offers,
+        token_transfer_offer_additions,
+        current_ans_lookups,
+        current_ans_primary_names,
+        current_marketplace_listings,
+        marketplace_listing_price_changes,
+        current_collection_volumes,
+        collection_volumes,
+        current_token_volumes,
+        token_volumes,
+        collection_price_candles,
+        collection_daily_reports,
+        token_properties_flat,
+        collection_mints,
+        token_property_mutations,
+        current_wallet_nft_stats,
+        wallet_token_cost_basis,
+        token_acquisitions,
+        current_collection_offers,
+        collection_offer_fills,
+        current_token_bids,
+        token_bid_fills,
+        token_auction_bids,
+        truncated_strings,
+        // current_daily_collection_volumes,
+        // current_weekly_collection_volumes,
+        // current_monthly_collection_volumes,
+    } = rows;
+    match run_transaction_with_retries(conn, |pg_conn| {
+        insert_to_db_impl(
+            pg_conn,
+            tables,
+            checkpoint,
+            (&tokens, &token_ownerships, &token_datas, &collection_datas),
+            (
+                &current_token_ownerships,
+                &current_token_datas,
+                &current_collection_datas,
+            ),
+            &token_activities,
+            &account_token_activities,
+            &nft_sales,
+            &nft_transaction_fees,
+            &current_token_claims,
+            &current_token_transfer_offers,
+            &token_transfer_offer_additions,
+            &current_ans_lookups,
+            &current_ans_primary_names,
+            &current_marketplace_listings,
+            &marketplace_listing_price_changes,
+            &current_collection_volumes,
+            &collection_volumes,
+            &current_token_volumes,
+            &token_volumes,
+            &collection_price_candles,
+            &collection_daily_reports,
+            &token_properties_flat,
+            &collection_mints,
+            &token_property_mutations,
+            &current_wallet_nft_stats,
+            &wallet_token_cost_basis,
+            &token_acquisitions,
+            &current_collection_offers,
+            &collection_offer_fills,
+            &current_token_bids,
+            &token_bid_fills,
+            &token_auction_bids,
+            &truncated_strings,
+            // &current_daily_collection_volumes,
+            // &current_weekly_collection_volumes,
+            // &current_monthly_collection_volumes
+        )
+    }) {
+        Ok(applied) => Ok(applied),
+        // Cleaning the data won't help, and it's been retried already
+        Err(err) if is_retryable_error(&err) => Err(err),
         Err(_) => conn
             .build_transaction()
             .read_write()
@@ -174,19 +1005,47 @@ fn insert_to_db(
                 let current_token_datas = clean_data_for_db(current_token_datas, true);
                 let current_collection_datas = clean_data_for_db(current_collection_datas, true);
                 let token_activities = clean_data_for_db(token_activities, true);
+                let account_token_activities = clean_data_for_db(account_token_activities, true);
+                let nft_sales = clean_data_for_db(nft_sales, true);
+                let nft_transaction_fees = clean_data_for_db(nft_transaction_fees, true);
                 let current_token_claims = clean_data_for_db(current_token_claims, true);
+                let current_token_transfer_offers =
+                    clean_data_for_db(current_token_transfer_offers, true);
+                let token_transfer_offer_additions =
+                    clean_data_for_db(token_transfer_offer_additions, true);
                 let current_ans_lookups = clean_data_for_db(current_ans_lookups, true);
-                let current_marketplace_listings = clean_data_for_db(current_marketplace_listings, true);
-                let current_collection_volumes = clean_data_for_db(current_collection_volumes, true);
+                let current_ans_primary_names = clean_data_for_db(current_ans_primary_names, true);
+                let current_marketplace_listings =
+                    clean_data_for_db(current_marketplace_listings, true);
+                let marketplace_listing_price_changes =
+                    clean_data_for_db(marketplace_listing_price_changes, true);
+                let current_collection_volumes =
+                    clean_data_for_db(current_collection_volumes, true);
                 let collection_volumes = clean_data_for_db(collection_volumes, true);
                 let current_token_volumes = clean_data_for_db(current_token_volumes, true);
                 let token_volumes = clean_data_for_db(token_volumes, true);
+                let collection_price_candles = clean_data_for_db(collection_price_candles, true);
+                let collection_daily_reports = clean_data_for_db(collection_daily_reports, true);
+                let token_properties_flat = clean_data_for_db(token_properties_flat, true);
+                let collection_mints = clean_data_for_db(collection_mints, true);
+                let token_property_mutations = clean_data_for_db(token_property_mutations, true);
+                let current_wallet_nft_stats = clean_data_for_db(current_wallet_nft_stats, true);
+                let wallet_token_cost_basis = clean_data_for_db(wallet_token_cost_basis, true);
+                let token_acquisitions = clean_data_for_db(token_acquisitions, true);
+                let current_collection_offers = clean_data_for_db(current_collection_offers, true);
+                let collection_offer_fills = clean_data_for_db(collection_offer_fills, true);
+                let current_token_bids = clean_data_for_db(current_token_bids, true);
+                let token_bid_fills = clean_data_for_db(token_bid_fills, true);
+                let token_auction_bids = clean_data_for_db(token_auction_bids, true);
+                let truncated_strings = clean_data_for_db(truncated_strings, true);
                 // let current_daily_collection_volumes = clean_data_for_db(current_daily_collection_volumes, true);
                 // let current_weekly_collection_volumes = clean_data_for_db(current_weekly_collection_volumes, true);
                 // let current_monthly_collection_volumes = clean_data_for_db(current_monthly_collection_volumes, true);
 
                 insert_to_db_impl(
                     pg_conn,
+                    tables,
+                    checkpoint,
                     (&tokens, &token_ownerships, &token_datas, &collection_datas),
                     (
                         &current_token_ownerships,
@@ -194,13 +1053,34 @@ fn insert_to_db(
                         &current_collection_datas,
                     ),
                     &token_activities,
+                    &account_token_activities,
+                    &nft_sales,
+                    &nft_transaction_fees,
                     &current_token_claims,
+                    &current_token_transfer_offers,
+                    &token_transfer_offer_additions,
                     &current_ans_lookups,
+                    &current_ans_primary_names,
                     &current_marketplace_listings,
+                    &marketplace_listing_price_changes,
                     &current_collection_volumes,
                     &collection_volumes,
                     &current_token_volumes,
                     &token_volumes,
+                    &collection_price_candles,
+                    &collection_daily_reports,
+                    &token_properties_flat,
+                    &collection_mints,
+                    &token_property_mutations,
+                    &current_wallet_nft_stats,
+                    &wallet_token_cost_basis,
+                    &token_acquisitions,
+                    &current_collection_offers,
+                    &collection_offer_fills,
+                    &current_token_bids,
+                    &token_bid_fills,
+                    &token_auction_bids,
+                    &truncated_strings,
                     // &current_daily_collection_volumes,
                     // &current_weekly_collection_volumes,
                     // &current_monthly_collection_volumes
@@ -216,13 +1096,18 @@ fn insert_tokens(
     use schema::tokens::dsl::*;
 
     let chunks = get_chunks(tokens_to_insert.len(), Token::field_count());
-    for (start_ind, end_ind) in chunks {
-        execute_with_better_error(
+    for (chunk_index, (start_ind, end_ind)) in chunks.into_iter().enumerate() {
+        execute_chunk_with_context(
             conn,
-            diesel::insert_into(schema::tokens::table)
-                .values(&tokens_to_insert[start_ind..end_ind])
-                .on_conflict((token_data_id_hash, property_version, transaction_version))
-                .do_nothing(),
+            "tokens",
+            chunk_index,
+            &tokens_to_insert[start_ind..end_ind],
+            |chunk| {
+                diesel::insert_into(schema::tokens::table)
+                    .values(chunk)
+                    .on_conflict((token_data_id_hash, property_version, transaction_version))
+                    .do_nothing()
+            },
             None,
         )?;
     }
@@ -239,18 +1124,23 @@ fn insert_token_ownerships(
         token_ownerships_to_insert.len(),
         TokenOwnership::field_count(),
     );
-    for (start_ind, end_ind) in chunks {
-        execute_with_better_error(
+    for (chunk_index, (start_ind, end_ind)) in chunks.into_iter().enumerate() {
+        execute_chunk_with_context(
             conn,
-            diesel::insert_into(schema::token_ownerships::table)
-                .values(&token_ownerships_to_insert[start_ind..end_ind])
-                .on_conflict((
-                    token_data_id_hash,
-                    property_version,
-                    transaction_version,
-                    table_handle,
-                ))
-                .do_nothing(),
+            "token_ownerships",
+            chunk_index,
+            &token_ownerships_to_insert[start_ind..end_ind],
+            |chunk| {
+                diesel::insert_into(schema::token_ownerships::table)
+                    .values(chunk)
+                    .on_conflict((
+                        token_data_id_hash,
+                        property_version,
+                        transaction_version,
+                        table_handle,
+                    ))
+                    .do_nothing()
+            },
             None,
         )?;
     }
@@ -264,14 +1154,19 @@ fn insert_token_datas(
     use schema::token_datas::dsl::*;
 
     let chunks = get_chunks(token_datas_to_insert.len(), TokenData::field_count());
-    for (start_ind, end_ind) in chunks {
-        execute_with_better_error(
+    for (chunk_index, (start_ind, end_ind)) in chunks.into_iter().enumerate() {
+        execute_chunk_with_context(
             conn,
-            diesel::insert_into(schema::token_datas::table)
-                .values(&token_datas_to_insert[start_ind..end_ind])
-                .on_conflict((token_data_id_hash, transaction_version))
-                .do_update()
-                .set((description.eq(excluded(description)),)),
+            "token_datas",
+            chunk_index,
+            &token_datas_to_insert[start_ind..end_ind],
+            |chunk| {
+                diesel::insert_into(schema::token_datas::table)
+                    .values(chunk)
+                    .on_conflict((token_data_id_hash, transaction_version))
+                    .do_update()
+                    .set((description.eq(excluded(description)),))
+            },
             None,
         )?;
     }
@@ -288,13 +1183,18 @@ fn insert_collection_datas(
         collection_datas_to_insert.len(),
         CollectionData::field_count(),
     );
-    for (start_ind, end_ind) in chunks {
-        execute_with_better_error(
+    for (chunk_index, (start_ind, end_ind)) in chunks.into_iter().enumerate() {
+        execute_chunk_with_context(
             conn,
-            diesel::insert_into(schema::collection_datas::table)
-                .values(&collection_datas_to_insert[start_ind..end_ind])
-                .on_conflict((collection_data_id_hash, transaction_version))
-                .do_nothing(),
+            "collection_datas",
+            chunk_index,
+            &collection_datas_to_insert[start_ind..end_ind],
+            |chunk| {
+                diesel::insert_into(schema::collection_datas::table)
+                    .values(chunk)
+                    .on_conflict((collection_data_id_hash, transaction_version))
+                    .do_nothing()
+            },
             None,
         )?;
     }
@@ -305,37 +1205,77 @@ fn insert_current_token_ownerships(
     conn: &mut PgConnection,
     items_to_insert: &[CurrentTokenOwnership],
 ) -> Result<(), diesel::result::Error> {
+    use diesel::dsl::sql;
     use schema::current_token_ownerships::dsl::*;
 
     let chunks = get_chunks(items_to_insert.len(), CurrentTokenOwnership::field_count());
 
-    for (start_ind, end_ind) in chunks {
-        execute_with_better_error(
-            conn,
-            diesel::insert_into(schema::current_token_ownerships::table)
-                .values(&items_to_insert[start_ind..end_ind])
-                .on_conflict((token_data_id_hash, property_version, owner_address))
-                .do_update()
-                .set((
-                    creator_address.eq(excluded(creator_address)),
-                    collection_name.eq(excluded(collection_name)),
-                    name.eq(excluded(name)),
-                    amount.eq(excluded(amount)),
-                    token_properties.eq(excluded(token_properties)),
-                    last_transaction_version.eq(excluded(last_transaction_version)),
-                    collection_data_id_hash.eq(excluded(collection_data_id_hash)),
-                    table_type.eq(excluded(table_type)),
-                )),
+    for (chunk_index, (start_ind, end_ind)) in chunks.into_iter().enumerate() {
+        execute_chunk_with_context(
+            conn,
+            "current_token_ownerships",
+            chunk_index,
+            &items_to_insert[start_ind..end_ind],
+            |chunk| {
+                diesel::insert_into(schema::current_token_ownerships::table)
+                    .values(chunk)
+                    .on_conflict((token_data_id_hash, property_version, owner_address))
+                    .do_update()
+                    .set((
+                        creator_address.eq(excluded(creator_address)),
+                        collection_name.eq(excluded(collection_name)),
+                        name.eq(excluded(name)),
+                        amount.eq(excluded(amount)),
+                        token_properties.eq(excluded(token_properties)),
+                        last_transaction_version.eq(excluded(last_transaction_version)),
+                        collection_data_id_hash.eq(excluded(collection_data_id_hash)),
+                        table_type.eq(excluded(table_type)),
+                        // Contracts are only recognized when their account is written, so
+                        // once classified an owner doesn't go back to being a user
+                        owner_type.eq(sql::<Text>(
+                            "CASE WHEN excluded.owner_type = 'user' THEN current_token_ownerships.owner_type ELSE excluded.owner_type END",
+                        )),
+                        beneficial_owner.eq(excluded(beneficial_owner)),
+                        is_deleted.eq(excluded(is_deleted)),
+                    ))
+            },
             Some(" WHERE current_token_ownerships.last_transaction_version <= excluded.last_transaction_version "),
         )?;
     }
     Ok(())
 }
 
+/// The row's last_event_index, from whichever of the stored and the added rows has the later
+/// (version, event index). A null stored index stands for every event of its version.
+fn latest_event_index(table: &str) -> String {
+    format!(
+        "CASE WHEN (excluded.last_transaction_version, excluded.last_event_index) > ({0}.last_transaction_version, {0}.last_event_index) \
+        THEN excluded.last_event_index ELSE {0}.last_event_index END",
+        table
+    )
+}
+
+/// Sales that couldn't be converted don't turn a known converted volume into null
+fn add_converted_volume(table: &str, column: &str) -> String {
+    format!(
+        "CASE WHEN excluded.{1} IS NULL THEN {0}.{1} ELSE COALESCE({0}.{1}, 0) + excluded.{1} END",
+        table, column
+    )
+}
+
+/// Adds the rows to the stored volumes. With only_newer, for when there's no history to tell a
+/// replayed sale from a new one, rows are skipped unless their last sale is past the stored
+/// (last_transaction_version, last_event_index), so replaying the batch that wrote a row doesn't
+/// count its sales twice.
 fn insert_current_collection_volumes(
     conn: &mut PgConnection,
     items_to_insert: &[CurrentCollectionVolume],
+    only_newer: bool,
 ) -> Result<(), diesel::result::Error> {
+    use diesel::{
+        dsl::sql,
+        sql_types::{BigInt, Nullable, Numeric, Timestamp},
+    };
     use schema::current_collection_volumes::dsl::*;
 
     let chunks = get_chunks(
@@ -343,322 +1283,1743 @@ fn insert_current_collection_volumes(
         CurrentCollectionVolume::field_count(),
     );
 
-    for (start_ind, end_ind) in chunks {
-        execute_with_better_error(
+    for (chunk_index, (start_ind, end_ind)) in chunks.into_iter().enumerate() {
+        execute_chunk_with_context(
             conn,
-            diesel::insert_into(schema::current_collection_volumes::table)
-                .values(&items_to_insert[start_ind..end_ind])
-                .on_conflict(collection_data_id_hash)
-                .do_update()
-                .set((
-                    collection_data_id_hash.eq(excluded(collection_data_id_hash)),
-                    volume.eq(volume + excluded(volume)),
-                    inserted_at.eq(excluded(inserted_at)),
-                    last_transaction_version.eq(excluded(last_transaction_version)),
-                )),
-                Some(" WHERE current_collection_volumes.last_transaction_version <= excluded.last_transaction_version "),
+            "current_collection_volumes",
+            chunk_index,
+            &items_to_insert[start_ind..end_ind],
+            |chunk| {
+                diesel::insert_into(schema::current_collection_volumes::table)
+                    .values(chunk)
+                    .on_conflict(collection_data_id_hash)
+                    .do_update()
+                    .set((
+                        collection_data_id_hash.eq(excluded(collection_data_id_hash)),
+                        volume.eq(volume + excluded(volume)),
+                        inserted_at.eq(sql::<Timestamp>(
+                            "CASE WHEN excluded.last_transaction_version > current_collection_volumes.last_transaction_version \
+                            THEN excluded.inserted_at ELSE current_collection_volumes.inserted_at END",
+                        )),
+                        last_transaction_timestamp.eq(sql::<Timestamp>(
+                            "CASE WHEN excluded.last_transaction_version > current_collection_volumes.last_transaction_version \
+                            THEN excluded.last_transaction_timestamp ELSE current_collection_volumes.last_transaction_timestamp END",
+                        )),
+                        last_transaction_version.eq(greatest(last_transaction_version, excluded(last_transaction_version))),
+                        last_event_index.eq(sql::<Nullable<BigInt>>(&latest_event_index("current_collection_volumes"))),
+                        primary_volume.eq(primary_volume + excluded(primary_volume)),
+                        secondary_volume.eq(secondary_volume + excluded(secondary_volume)),
+                        volume_usd.eq(sql::<Nullable<Numeric>>(&add_converted_volume("current_collection_volumes", "volume_usd"))),
+                        volume_decimal.eq(sql::<Nullable<Numeric>>(&add_converted_volume("current_collection_volumes", "volume_decimal"))),
+                    ))
+            },
+            if only_newer {
+                Some(" WHERE (current_collection_volumes.last_transaction_version, current_collection_volumes.last_event_index) < (excluded.last_transaction_version, excluded.last_event_index) ")
+            } else {
+                None
+            },
         )?;
     }
     Ok(())
 }
 
-fn insert_collection_volumes(
+/// Returns the rows that weren't already stored, i.e. the sales the current volumes haven't
+/// counted yet
+fn insert_collection_volumes<'a>(
     conn: &mut PgConnection,
-    items_to_insert: &[CollectionVolume],
-) -> Result<(), diesel::result::Error> {
+    items_to_insert: &'a [CollectionVolume],
+) -> Result<Vec<&'a CollectionVolume>, diesel::result::Error> {
     use schema::collection_volumes::dsl::*;
 
-    let chunks = get_chunks(
-        items_to_insert.len(),
-        CollectionVolume::field_count(),
-    );
+    // Rows from before volumes were keyed by event only kept one sale per transaction, they're
+    // replaced by the reprocessed ones
+    let versions = items_to_insert
+        .iter()
+        .map(|item| item.last_transaction_version)
+        .collect::<Vec<i64>>();
+    diesel::delete(
+        collection_volumes.filter(
+            last_transaction_version
+                .eq_any(versions)
+                .and(event_index.eq(-1)),
+        ),
+    )
+    .execute(conn)?;
 
+    let chunks = get_chunks(items_to_insert.len(), CollectionVolume::field_count());
+
+    // The conflicting rows were inserted by an earlier run over the same versions, only the
+    // returned keys are new
+    let mut inserted = HashSet::new();
     for (start_ind, end_ind) in chunks {
-        execute_with_better_error(
-            conn,
-            diesel::insert_into(schema::collection_volumes::table)
-                .values(&items_to_insert[start_ind..end_ind])
-                .on_conflict(last_transaction_version)
-                .do_nothing(),
-                None,
-        )?;
+        let keys: Vec<(i64, i64, i64)> = diesel::insert_into(schema::collection_volumes::table)
+            .values(&items_to_insert[start_ind..end_ind])
+            .on_conflict((last_transaction_version, event_index, token_index))
+            .do_nothing()
+            .returning((last_transaction_version, event_index, token_index))
+            .get_results(conn)?;
+        inserted.extend(keys);
     }
-    Ok(())
+    Ok(items_to_insert
+        .iter()
+        .filter(|item| {
+            inserted.contains(&(
+                item.last_transaction_version,
+                item.event_index,
+                item.token_index,
+            ))
+        })
+        .collect())
 }
 
+/// Same as insert_current_collection_volumes, per token
 fn insert_current_token_volumes(
     conn: &mut PgConnection,
     items_to_insert: &[CurrentTokenVolume],
+    only_newer: bool,
 ) -> Result<(), diesel::result::Error> {
+    use diesel::{
+        dsl::sql,
+        sql_types::{BigInt, Nullable, Numeric, Timestamp},
+    };
     use schema::current_token_volumes::dsl::*;
 
-    let chunks = get_chunks(
-        items_to_insert.len(),
-        CurrentTokenVolume::field_count(),
-    );
+    let chunks = get_chunks(items_to_insert.len(), CurrentTokenVolume::field_count());
 
-    for (start_ind, end_ind) in chunks {
-        execute_with_better_error(
+    for (chunk_index, (start_ind, end_ind)) in chunks.into_iter().enumerate() {
+        execute_chunk_with_context(
             conn,
-            diesel::insert_into(schema::current_token_volumes::table)
-                .values(&items_to_insert[start_ind..end_ind])
-                .on_conflict(token_data_id_hash)
-                .do_update()
-                .set((
-                    token_data_id_hash.eq(excluded(token_data_id_hash)),
-                    volume.eq(volume + excluded(volume)),
-                    inserted_at.eq(excluded(inserted_at)),
-                    last_transaction_version.eq(excluded(last_transaction_version)),
-                )),
-                Some(" WHERE current_token_volumes.last_transaction_version <= excluded.last_transaction_version "),
+            "current_token_volumes",
+            chunk_index,
+            &items_to_insert[start_ind..end_ind],
+            |chunk| {
+                diesel::insert_into(schema::current_token_volumes::table)
+                    .values(chunk)
+                    .on_conflict(token_data_id_hash)
+                    .do_update()
+                    .set((
+                        token_data_id_hash.eq(excluded(token_data_id_hash)),
+                        volume.eq(volume + excluded(volume)),
+                        volume_decimal.eq(sql::<Nullable<Numeric>>(&add_converted_volume("current_token_volumes", "volume_decimal"))),
+                        inserted_at.eq(sql::<Timestamp>(
+                            "CASE WHEN excluded.last_transaction_version > current_token_volumes.last_transaction_version \
+                            THEN excluded.inserted_at ELSE current_token_volumes.inserted_at END",
+                        )),
+                        last_transaction_timestamp.eq(sql::<Timestamp>(
+                            "CASE WHEN excluded.last_transaction_version > current_token_volumes.last_transaction_version \
+                            THEN excluded.last_transaction_timestamp ELSE current_token_volumes.last_transaction_timestamp END",
+                        )),
+                        last_transaction_version.eq(greatest(last_transaction_version, excluded(last_transaction_version))),
+                        last_event_index.eq(sql::<Nullable<BigInt>>(&latest_event_index("current_token_volumes"))),
+                    ))
+            },
+            if only_newer {
+                Some(" WHERE (current_token_volumes.last_transaction_version, current_token_volumes.last_event_index) < (excluded.last_transaction_version, excluded.last_event_index) ")
+            } else {
+                None
+            },
         )?;
     }
     Ok(())
 }
 
-fn insert_token_volumes(
+/// Returns the rows that weren't already stored, i.e. the sales the current volumes haven't
+/// counted yet
+fn insert_token_volumes<'a>(
     conn: &mut PgConnection,
-    items_to_insert: &[TokenVolume],
-) -> Result<(), diesel::result::Error> {
+    items_to_insert: &'a [TokenVolume],
+) -> Result<Vec<&'a TokenVolume>, diesel::result::Error> {
     use schema::token_volumes::dsl::*;
 
-    let chunks = get_chunks(
-        items_to_insert.len(),
-        TokenVolume::field_count(),
-    );
+    // Rows from before volumes were keyed by event only kept one sale per transaction, they're
+    // replaced by the reprocessed ones
+    let versions = items_to_insert
+        .iter()
+        .map(|item| item.last_transaction_version)
+        .collect::<Vec<i64>>();
+    diesel::delete(
+        token_volumes.filter(
+            last_transaction_version
+                .eq_any(versions)
+                .and(event_index.eq(-1)),
+        ),
+    )
+    .execute(conn)?;
+
+    let chunks = get_chunks(items_to_insert.len(), TokenVolume::field_count());
 
+    // The conflicting rows were inserted by an earlier run over the same versions, only the
+    // returned keys are new
+    let mut inserted = HashSet::new();
     for (start_ind, end_ind) in chunks {
-        execute_with_better_error(
-            conn,
-            diesel::insert_into(schema::token_volumes::table)
-                .values(&items_to_insert[start_ind..end_ind])
-                .on_conflict(last_transaction_version)
-                .do_nothing(),
-                None,
-        )?;
+        let keys: Vec<(i64, i64, i64)> = diesel::insert_into(schema::token_volumes::table)
+            .values(&items_to_insert[start_ind..end_ind])
+            .on_conflict((last_transaction_version, event_index, token_index))
+            .do_nothing()
+            .returning((last_transaction_version, event_index, token_index))
+            .get_results(conn)?;
+        inserted.extend(keys);
     }
-    Ok(())
+    Ok(items_to_insert
+        .iter()
+        .filter(|item| {
+            inserted.contains(&(
+                item.last_transaction_version,
+                item.event_index,
+                item.token_index,
+            ))
+        })
+        .collect())
 }
 
-fn insert_current_token_datas(
+/// Every shard adds its own sales to the same marketplace rows. The rows are upserted in PK order,
+/// so shards wait on each other there rather than deadlocking.
+fn insert_marketplace_volumes(
     conn: &mut PgConnection,
-    items_to_insert: &[CurrentTokenData],
+    tables: &TokenTables,
+    marketplace_volumes: &MarketplaceVolumes,
+    only_newer: bool,
 ) -> Result<(), diesel::result::Error> {
-    use schema::current_token_datas::dsl::*;
-
-    let chunks = get_chunks(items_to_insert.len(), CurrentTokenData::field_count());
-
-    for (start_ind, end_ind) in chunks {
-        execute_with_better_error(
-            conn,
-            diesel::insert_into(schema::current_token_datas::table)
-                .values(&items_to_insert[start_ind..end_ind])
-                .on_conflict(token_data_id_hash)
-                .do_update()
-                .set((
-                    creator_address.eq(excluded(creator_address)),
-                    collection_name.eq(excluded(collection_name)),
-                    name.eq(excluded(name)),
-                    maximum.eq(excluded(maximum)),
-                    supply.eq(excluded(supply)),
-                    largest_property_version.eq(excluded(largest_property_version)),
-                    metadata_uri.eq(excluded(metadata_uri)),
-                    payee_address.eq(excluded(payee_address)),
-                    royalty_points_numerator.eq(excluded(royalty_points_numerator)),
-                    royalty_points_denominator.eq(excluded(royalty_points_denominator)),
-                    maximum_mutable.eq(excluded(maximum_mutable)),
-                    uri_mutable.eq(excluded(uri_mutable)),
-                    description_mutable.eq(excluded(description_mutable)),
-                    properties_mutable.eq(excluded(properties_mutable)),
-                    royalty_mutable.eq(excluded(royalty_mutable)),
-                    default_properties.eq(excluded(default_properties)),
-                    last_transaction_version.eq(excluded(last_transaction_version)),
-                    collection_data_id_hash.eq(excluded(collection_data_id_hash)),
-                    description.eq(excluded(description)),
-                )),
-            Some(" WHERE current_token_datas.last_transaction_version <= excluded.last_transaction_version "),
+    if tables.is_enabled("current_marketplace_volumes") {
+        insert_current_marketplace_volumes(
+            conn,
+            &marketplace_volumes.current_marketplace_volumes,
+            only_newer,
+        )?;
+    }
+    if tables.is_enabled("marketplace_volumes") {
+        insert_marketplace_daily_volumes(
+            conn,
+            &marketplace_volumes.marketplace_volumes,
+            only_newer,
+        )?;
+    }
+    if tables.is_enabled("marketplace_collection_volumes") {
+        insert_marketplace_collection_volumes(
+            conn,
+            &marketplace_volumes.marketplace_collection_volumes,
+            only_newer,
         )?;
     }
     Ok(())
 }
 
-fn insert_current_collection_datas(
+/// Same as insert_current_collection_volumes, per marketplace and coin
+fn insert_current_marketplace_volumes(
     conn: &mut PgConnection,
-    items_to_insert: &[CurrentCollectionData],
+    items_to_insert: &[CurrentMarketplaceVolume],
+    only_newer: bool,
 ) -> Result<(), diesel::result::Error> {
-    use schema::current_collection_datas::dsl::*;
+    use diesel::{
+        dsl::sql,
+        sql_types::{BigInt, Nullable, Numeric, Timestamp},
+    };
+    use schema::current_marketplace_volumes::dsl::*;
 
-    let chunks = get_chunks(items_to_insert.len(), CurrentCollectionData::field_count());
+    let chunks = get_chunks(
+        items_to_insert.len(),
+        CurrentMarketplaceVolume::field_count(),
+    );
 
-    for (start_ind, end_ind) in chunks {
-        execute_with_better_error(
-            conn,
-            diesel::insert_into(schema::current_collection_datas::table)
-                .values(&items_to_insert[start_ind..end_ind])
-                .on_conflict(collection_data_id_hash)
-                .do_update()
-                .set((
-                    creator_address.eq(excluded(creator_address)),
-                    collection_name.eq(excluded(collection_name)),
-                    description.eq(excluded(description)),
-                    metadata_uri.eq(excluded(metadata_uri)),
-                    supply.eq(excluded(supply)),
-                    maximum.eq(excluded(maximum)),
-                    maximum_mutable.eq(excluded(maximum_mutable)),
-                    uri_mutable.eq(excluded(uri_mutable)),
-                    description_mutable.eq(excluded(description_mutable)),
-                    last_transaction_version.eq(excluded(last_transaction_version)),
-                    table_handle.eq(excluded(table_handle)),
-                )),
-            Some(" WHERE current_collection_datas.last_transaction_version <= excluded.last_transaction_version "),
+    for (chunk_index, (start_ind, end_ind)) in chunks.into_iter().enumerate() {
+        execute_chunk_with_context(
+            conn,
+            "current_marketplace_volumes",
+            chunk_index,
+            &items_to_insert[start_ind..end_ind],
+            |chunk| {
+                diesel::insert_into(schema::current_marketplace_volumes::table)
+                    .values(chunk)
+                    .on_conflict((market_address, coin_type))
+                    .do_update()
+                    .set((
+                        volume.eq(volume + excluded(volume)),
+                        trade_count.eq(trade_count + excluded(trade_count)),
+                        volume_usd.eq(sql::<Nullable<Numeric>>(&add_converted_volume("current_marketplace_volumes", "volume_usd"))),
+                        volume_decimal.eq(sql::<Nullable<Numeric>>(&add_converted_volume("current_marketplace_volumes", "volume_decimal"))),
+                        inserted_at.eq(sql::<Timestamp>(
+                            "CASE WHEN excluded.last_transaction_version > current_marketplace_volumes.last_transaction_version \
+                            THEN excluded.inserted_at ELSE current_marketplace_volumes.inserted_at END",
+                        )),
+                        last_transaction_timestamp.eq(sql::<Timestamp>(
+                            "CASE WHEN excluded.last_transaction_version > current_marketplace_volumes.last_transaction_version \
+                            THEN excluded.last_transaction_timestamp ELSE current_marketplace_volumes.last_transaction_timestamp END",
+                        )),
+                        last_transaction_version.eq(greatest(last_transaction_version, excluded(last_transaction_version))),
+                        last_event_index.eq(sql::<Nullable<BigInt>>(&latest_event_index("current_marketplace_volumes"))),
+                    ))
+            },
+            if only_newer {
+                Some(" WHERE (current_marketplace_volumes.last_transaction_version, current_marketplace_volumes.last_event_index) < (excluded.last_transaction_version, excluded.last_event_index) ")
+            } else {
+                None
+            },
         )?;
     }
     Ok(())
 }
 
-fn insert_token_activities(
+fn insert_marketplace_daily_volumes(
     conn: &mut PgConnection,
-    items_to_insert: &[TokenActivity],
+    items_to_insert: &[MarketplaceVolume],
+    only_newer: bool,
 ) -> Result<(), diesel::result::Error> {
-    use schema::token_activities::dsl::*;
+    use diesel::{
+        dsl::sql,
+        sql_types::{BigInt, Nullable, Numeric, Timestamp},
+    };
+    use schema::marketplace_volumes::dsl::*;
 
-    let chunks = get_chunks(items_to_insert.len(), TokenActivity::field_count());
+    let chunks = get_chunks(items_to_insert.len(), MarketplaceVolume::field_count());
 
-    for (start_ind, end_ind) in chunks {
-        execute_with_better_error(
-            conn,
-            diesel::insert_into(schema::token_activities::table)
-                .values(&items_to_insert[start_ind..end_ind])
-                .on_conflict((
-                    transaction_version,
-                    event_account_address,
-                    event_creation_number,
-                    event_sequence_number,
-                ))
-                .do_nothing(),
-            None,
+    for (chunk_index, (start_ind, end_ind)) in chunks.into_iter().enumerate() {
+        execute_chunk_with_context(
+            conn,
+            "marketplace_volumes",
+            chunk_index,
+            &items_to_insert[start_ind..end_ind],
+            |chunk| {
+                diesel::insert_into(schema::marketplace_volumes::table)
+                    .values(chunk)
+                    .on_conflict((market_address, coin_type, volume_date))
+                    .do_update()
+                    .set((
+                        volume.eq(volume + excluded(volume)),
+                        trade_count.eq(trade_count + excluded(trade_count)),
+                        volume_usd.eq(sql::<Nullable<Numeric>>(&add_converted_volume("marketplace_volumes", "volume_usd"))),
+                        volume_decimal.eq(sql::<Nullable<Numeric>>(&add_converted_volume("marketplace_volumes", "volume_decimal"))),
+                        inserted_at.eq(sql::<Timestamp>(
+                            "CASE WHEN excluded.last_transaction_version > marketplace_volumes.last_transaction_version \
+                            THEN excluded.inserted_at ELSE marketplace_volumes.inserted_at END",
+                        )),
+                        last_transaction_timestamp.eq(sql::<Timestamp>(
+                            "CASE WHEN excluded.last_transaction_version > marketplace_volumes.last_transaction_version \
+                            THEN excluded.last_transaction_timestamp ELSE marketplace_volumes.last_transaction_timestamp END",
+                        )),
+                        last_transaction_version.eq(greatest(last_transaction_version, excluded(last_transaction_version))),
+                        last_event_index.eq(sql::<Nullable<BigInt>>(&latest_event_index("marketplace_volumes"))),
+                    ))
+            },
+            if only_newer {
+                Some(" WHERE (marketplace_volumes.last_transaction_version, marketplace_volumes.last_event_index) < (excluded.last_transaction_version, excluded.last_event_index) ")
+            } else {
+                None
+            },
         )?;
     }
     Ok(())
 }
-fn insert_current_token_claims(
+
+fn insert_marketplace_collection_volumes(
     conn: &mut PgConnection,
-    items_to_insert: &[CurrentTokenPendingClaim],
+    items_to_insert: &[MarketplaceCollectionVolume],
+    only_newer: bool,
 ) -> Result<(), diesel::result::Error> {
-    use schema::current_token_pending_claims::dsl::*;
+    use diesel::{
+        dsl::sql,
+        sql_types::{BigInt, Nullable, Numeric, Timestamp},
+    };
+    use schema::marketplace_collection_volumes::dsl::*;
 
     let chunks = get_chunks(
         items_to_insert.len(),
-        CurrentTokenPendingClaim::field_count(),
+        MarketplaceCollectionVolume::field_count(),
     );
 
-    for (start_ind, end_ind) in chunks {
-        execute_with_better_error(
+    for (chunk_index, (start_ind, end_ind)) in chunks.into_iter().enumerate() {
+        execute_chunk_with_context(
             conn,
-            diesel::insert_into(schema::current_token_pending_claims::table)
-                .values(&items_to_insert[start_ind..end_ind])
-                .on_conflict((
-                    token_data_id_hash, property_version, from_address, to_address
-                ))
-                .do_update()
-                .set((
-                    collection_data_id_hash.eq(excluded(collection_data_id_hash)),
-                    creator_address.eq(excluded(creator_address)),
-                    collection_name.eq(excluded(collection_name)),
-                    name.eq(excluded(name)),
-                    amount.eq(excluded(amount)),
-                    table_handle.eq(excluded(table_handle)),
-                    last_transaction_version.eq(excluded(last_transaction_version)),
-                )),
-            Some(" WHERE current_token_pending_claims.last_transaction_version <= excluded.last_transaction_version "),
+            "marketplace_collection_volumes",
+            chunk_index,
+            &items_to_insert[start_ind..end_ind],
+            |chunk| {
+                diesel::insert_into(schema::marketplace_collection_volumes::table)
+                    .values(chunk)
+                    .on_conflict((market_address, collection_data_id_hash, coin_type))
+                    .do_update()
+                    .set((
+                        volume.eq(volume + excluded(volume)),
+                        trade_count.eq(trade_count + excluded(trade_count)),
+                        volume_usd.eq(sql::<Nullable<Numeric>>(&add_converted_volume("marketplace_collection_volumes", "volume_usd"))),
+                        volume_decimal.eq(sql::<Nullable<Numeric>>(&add_converted_volume("marketplace_collection_volumes", "volume_decimal"))),
+                        inserted_at.eq(sql::<Timestamp>(
+                            "CASE WHEN excluded.last_transaction_version > marketplace_collection_volumes.last_transaction_version \
+                            THEN excluded.inserted_at ELSE marketplace_collection_volumes.inserted_at END",
+                        )),
+                        last_transaction_timestamp.eq(sql::<Timestamp>(
+                            "CASE WHEN excluded.last_transaction_version > marketplace_collection_volumes.last_transaction_version \
+                            THEN excluded.last_transaction_timestamp ELSE marketplace_collection_volumes.last_transaction_timestamp END",
+                        )),
+                        last_transaction_version.eq(greatest(last_transaction_version, excluded(last_transaction_version))),
+                        last_event_index.eq(sql::<Nullable<BigInt>>(&latest_event_index("marketplace_collection_volumes"))),
+                    ))
+            },
+            if only_newer {
+                Some(" WHERE (marketplace_collection_volumes.last_transaction_version, marketplace_collection_volumes.last_event_index) < (excluded.last_transaction_version, excluded.last_event_index) ")
+            } else {
+                None
+            },
         )?;
     }
     Ok(())
 }
 
-fn insert_current_ans_lookups(
+/// Candles and reports from different batches of the same interval are merged in the upsert the
+/// same way `merge` combines them in memory, so batches can land in any order
+/// Adds the sales to the stored candles. With only_newer, a candle is skipped unless its last sale
+/// is past the stored one, so a replayed batch isn't counted twice.
+fn insert_collection_price_candles(
     conn: &mut PgConnection,
-    items_to_insert: &[CurrentAnsLookup],
+    items_to_insert: &[CollectionPriceCandle],
+    only_newer: bool,
 ) -> Result<(), diesel::result::Error> {
-    use schema::current_ans_lookup::dsl::*;
+    use diesel::{
+        dsl::sql,
+        sql_types::{BigInt, Numeric},
+    };
+    use schema::collection_price_candles::dsl::*;
 
-    let chunks = get_chunks(items_to_insert.len(), CurrentAnsLookup::field_count());
+    let chunks = get_chunks(items_to_insert.len(), CollectionPriceCandle::field_count());
 
-    for (start_ind, end_ind) in chunks {
-        execute_with_better_error(
-            conn,
-            diesel::insert_into(schema::current_ans_lookup::table)
-                .values(&items_to_insert[start_ind..end_ind])
-                .on_conflict((domain, subdomain))
-                .do_update()
-                .set((
-                    registered_address.eq(excluded(registered_address)),
-                    expiration_timestamp.eq(excluded(expiration_timestamp)),
-                    last_transaction_version.eq(excluded(last_transaction_version)),
-                )),
-                Some(" WHERE current_ans_lookup.last_transaction_version <= excluded.last_transaction_version "),
-            )?;
+    for (chunk_index, (start_ind, end_ind)) in chunks.into_iter().enumerate() {
+        execute_chunk_with_context(
+            conn,
+            "collection_price_candles",
+            chunk_index,
+            &items_to_insert[start_ind..end_ind],
+            |chunk| {
+                diesel::insert_into(schema::collection_price_candles::table)
+                    .values(chunk)
+                    .on_conflict((collection_data_id_hash, coin_type, market_address, interval_start))
+                    .do_update()
+                    .set((
+                        open_price.eq(sql::<Numeric>(
+                            "CASE WHEN excluded.first_transaction_version < collection_price_candles.first_transaction_version \
+                            THEN excluded.open_price ELSE collection_price_candles.open_price END",
+                        )),
+                        close_price.eq(sql::<Numeric>(
+                            "CASE WHEN excluded.last_transaction_version > collection_price_candles.last_transaction_version \
+                            THEN excluded.close_price ELSE collection_price_candles.close_price END",
+                        )),
+                        high_price.eq(sql::<Numeric>(
+                            "GREATEST(collection_price_candles.high_price, excluded.high_price)",
+                        )),
+                        low_price.eq(sql::<Numeric>(
+                            "LEAST(collection_price_candles.low_price, excluded.low_price)",
+                        )),
+                        volume.eq(volume + excluded(volume)),
+                        sales_count.eq(sales_count + excluded(sales_count)),
+                        first_transaction_version.eq(sql::<BigInt>(
+                            "LEAST(collection_price_candles.first_transaction_version, excluded.first_transaction_version)",
+                        )),
+                        last_transaction_version.eq(sql::<BigInt>(
+                            "GREATEST(collection_price_candles.last_transaction_version, excluded.last_transaction_version)",
+                        )),
+                    ))
+            },
+            if only_newer {
+                Some(" WHERE collection_price_candles.last_transaction_version < excluded.last_transaction_version ")
+            } else {
+                None
+            },
+        )?;
     }
     Ok(())
 }
 
-fn insert_current_marketplace_listings(
+/// Same as the candles, for daily reports
+fn insert_collection_daily_reports(
     conn: &mut PgConnection,
-    items_to_insert: &[CurrentMarketplaceListing],
+    items_to_insert: &[CollectionDailyReport],
+    only_newer: bool,
 ) -> Result<(), diesel::result::Error> {
-    use schema::current_marketplace_listings::dsl::*;
+    use diesel::{
+        dsl::sql,
+        sql_types::{BigInt, Numeric},
+    };
+    use schema::collection_daily_reports::dsl::*;
 
-    let chunks = get_chunks(
-        items_to_insert.len(),
-        CurrentMarketplaceListing::field_count(),
-    );
+    let chunks = get_chunks(items_to_insert.len(), CollectionDailyReport::field_count());
+
+    for (chunk_index, (start_ind, end_ind)) in chunks.into_iter().enumerate() {
+        execute_chunk_with_context(
+            conn,
+            "collection_daily_reports",
+            chunk_index,
+            &items_to_insert[start_ind..end_ind],
+            |chunk| {
+                diesel::insert_into(schema::collection_daily_reports::table)
+                    .values(chunk)
+                    .on_conflict((collection_data_id_hash, coin_type, market_address, report_date))
+                    .do_update()
+                    .set((
+                        volume.eq(volume + excluded(volume)),
+                        sales_count.eq(sales_count + excluded(sales_count)),
+                        min_price.eq(sql::<Numeric>(
+                            "LEAST(collection_daily_reports.min_price, excluded.min_price)",
+                        )),
+                        max_price.eq(sql::<Numeric>(
+                            "GREATEST(collection_daily_reports.max_price, excluded.max_price)",
+                        )),
+                        last_transaction_version.eq(sql::<BigInt>(
+                            "GREATEST(collection_daily_reports.last_transaction_version, excluded.last_transaction_version)",
+                        )),
+                    ))
+            },
+            if only_newer {
+                Some(" WHERE collection_daily_reports.last_transaction_version < excluded.last_transaction_version ")
+            } else {
+                None
+            },
+        )?;
+    }
+    Ok(())
+}
+
+fn insert_data_integrity_findings(
+    conn: &mut PgConnection,
+    items_to_insert: &[DataIntegrityFinding],
+) -> Result<(), diesel::result::Error> {
+    use schema::data_integrity_findings::dsl::*;
+
+    let chunks = get_chunks(items_to_insert.len(), DataIntegrityFinding::field_count());
+
+    for (chunk_index, (start_ind, end_ind)) in chunks.into_iter().enumerate() {
+        execute_chunk_with_context(
+            conn,
+            "data_integrity_findings",
+            chunk_index,
+            &items_to_insert[start_ind..end_ind],
+            |chunk| {
+                diesel::insert_into(schema::data_integrity_findings::table)
+                    .values(chunk)
+                    .on_conflict((check_name, subject, transaction_version))
+                    .do_nothing()
+            },
+            None,
+        )?;
+    }
+    Ok(())
+}
+
+fn insert_current_collection_holder_counts(
+    conn: &mut PgConnection,
+    items_to_insert: &[CurrentCollectionHolderCount],
+) -> Result<(), diesel::result::Error> {
+    use schema::current_collection_holder_counts::dsl::*;
+
+    let chunks = get_chunks(
+        items_to_insert.len(),
+        CurrentCollectionHolderCount::field_count(),
+    );
+
+    for (chunk_index, (start_ind, end_ind)) in chunks.into_iter().enumerate() {
+        execute_chunk_with_context(
+            conn,
+            "current_collection_holder_counts",
+            chunk_index,
+            &items_to_insert[start_ind..end_ind],
+            |chunk| {
+                diesel::insert_into(schema::current_collection_holder_counts::table)
+                    .values(chunk)
+                    .on_conflict(collection_data_id_hash)
+                    .do_update()
+                    .set((
+                        distinct_holders.eq(distinct_holders + excluded(distinct_holders)),
+                        total_tokens_held.eq(total_tokens_held + excluded(total_tokens_held)),
+                        last_transaction_version.eq(excluded(last_transaction_version)),
+                        inserted_at.eq(excluded(inserted_at)),
+                    ))
+            },
+            Some(" WHERE current_collection_holder_counts.last_transaction_version <= excluded.last_transaction_version "),
+        )?;
+    }
+    Ok(())
+}
+
+fn insert_collection_mints(
+    conn: &mut PgConnection,
+    items_to_insert: &[CollectionMint],
+) -> Result<(), diesel::result::Error> {
+    use schema::collection_mints::dsl::*;
+
+    let chunks = get_chunks(items_to_insert.len(), CollectionMint::field_count());
+
+    for (chunk_index, (start_ind, end_ind)) in chunks.into_iter().enumerate() {
+        execute_chunk_with_context(
+            conn,
+            "collection_mints",
+            chunk_index,
+            &items_to_insert[start_ind..end_ind],
+            |chunk| {
+                diesel::insert_into(schema::collection_mints::table)
+                    .values(chunk)
+                    .on_conflict((transaction_version, event_index))
+                    .do_nothing()
+            },
+            None,
+        )?;
+    }
+    Ok(())
+}
+
+fn insert_token_property_mutations(
+    conn: &mut PgConnection,
+    items_to_insert: &[TokenPropertyMutation],
+) -> Result<(), diesel::result::Error> {
+    use schema::token_property_mutations::dsl::*;
+
+    let chunks = get_chunks(items_to_insert.len(), TokenPropertyMutation::field_count());
+
+    for (chunk_index, (start_ind, end_ind)) in chunks.into_iter().enumerate() {
+        execute_chunk_with_context(
+            conn,
+            "token_property_mutations",
+            chunk_index,
+            &items_to_insert[start_ind..end_ind],
+            |chunk| {
+                diesel::insert_into(schema::token_property_mutations::table)
+                    .values(chunk)
+                    .on_conflict((transaction_version, event_index))
+                    .do_nothing()
+            },
+            None,
+        )?;
+    }
+    Ok(())
+}
+
+/// Stats are only ever added to, and mints that were already counted are filtered out
+/// beforehand, so batches committing out of order shouldn't drop each other's changes
+fn insert_current_collection_mint_stats(
+    conn: &mut PgConnection,
+    items_to_insert: &[CurrentCollectionMintStat],
+) -> Result<(), diesel::result::Error> {
+    use schema::current_collection_mint_stats::dsl::*;
+
+    let chunks = get_chunks(
+        items_to_insert.len(),
+        CurrentCollectionMintStat::field_count(),
+    );
+
+    for (chunk_index, (start_ind, end_ind)) in chunks.into_iter().enumerate() {
+        execute_chunk_with_context(
+            conn,
+            "current_collection_mint_stats",
+            chunk_index,
+            &items_to_insert[start_ind..end_ind],
+            |chunk| {
+                diesel::insert_into(schema::current_collection_mint_stats::table)
+                    .values(chunk)
+                    .on_conflict(collection_data_id_hash)
+                    .do_update()
+                    .set((
+                        total_minted.eq(total_minted + excluded(total_minted)),
+                        distinct_minters.eq(distinct_minters + excluded(distinct_minters)),
+                        mint_volume_apt.eq(mint_volume_apt + excluded(mint_volume_apt)),
+                        last_transaction_version.eq(greatest(
+                            last_transaction_version,
+                            excluded(last_transaction_version),
+                        )),
+                        inserted_at.eq(excluded(inserted_at)),
+                        launchpad.eq(coalesce(excluded(launchpad), launchpad)),
+                    ))
+            },
+            None,
+        )?;
+    }
+    Ok(())
+}
+
+/// Adds the sales to the stored stats. With only_newer, a wallet's row is skipped unless its last
+/// trade is past the stored one, so a replayed batch isn't counted twice.
+fn insert_current_wallet_nft_stats(
+    conn: &mut PgConnection,
+    items_to_insert: &[CurrentWalletNftStat],
+    only_newer: bool,
+) -> Result<(), diesel::result::Error> {
+    use schema::current_wallet_nft_stats::dsl::*;
+
+    let chunks = get_chunks(items_to_insert.len(), CurrentWalletNftStat::field_count());
+
+    for (chunk_index, (start_ind, end_ind)) in chunks.into_iter().enumerate() {
+        execute_chunk_with_context(
+            conn,
+            "current_wallet_nft_stats",
+            chunk_index,
+            &items_to_insert[start_ind..end_ind],
+            |chunk| {
+                diesel::insert_into(schema::current_wallet_nft_stats::table)
+                    .values(chunk)
+                    .on_conflict(wallet_address)
+                    .do_update()
+                    .set((
+                        buy_count.eq(buy_count + excluded(buy_count)),
+                        sell_count.eq(sell_count + excluded(sell_count)),
+                        buy_volume.eq(buy_volume + excluded(buy_volume)),
+                        sell_volume.eq(sell_volume + excluded(sell_volume)),
+                        realized_pnl.eq(realized_pnl + excluded(realized_pnl)),
+                        first_trade_version
+                            .eq(least(first_trade_version, excluded(first_trade_version))),
+                        last_trade_version
+                            .eq(greatest(last_trade_version, excluded(last_trade_version))),
+                        inserted_at.eq(excluded(inserted_at)),
+                    ))
+            },
+            if only_newer {
+                Some(" WHERE current_wallet_nft_stats.last_trade_version < excluded.last_trade_version ")
+            } else {
+                None
+            },
+        )?;
+    }
+    Ok(())
+}
+
+fn insert_wallet_token_cost_basis(
+    conn: &mut PgConnection,
+    items_to_insert: &[WalletTokenCostBasis],
+) -> Result<(), diesel::result::Error> {
+    use schema::wallet_token_cost_basis::dsl::*;
+
+    let chunks = get_chunks(items_to_insert.len(), WalletTokenCostBasis::field_count());
+
+    for (chunk_index, (start_ind, end_ind)) in chunks.into_iter().enumerate() {
+        execute_chunk_with_context(
+            conn,
+            "wallet_token_cost_basis",
+            chunk_index,
+            &items_to_insert[start_ind..end_ind],
+            |chunk| {
+                diesel::insert_into(schema::wallet_token_cost_basis::table)
+                    .values(chunk)
+                    .on_conflict((wallet_address, token_data_id_hash))
+                    .do_update()
+                    .set((
+                        cost_basis.eq(excluded(cost_basis)),
+                        amount.eq(excluded(amount)),
+                        last_transaction_version.eq(excluded(last_transaction_version)),
+                        inserted_at.eq(excluded(inserted_at)),
+                    ))
+            },
+            Some(" WHERE wallet_token_cost_basis.last_transaction_version <= excluded.last_transaction_version "),
+        )?;
+    }
+    Ok(())
+}
+
+fn insert_token_acquisitions(
+    conn: &mut PgConnection,
+    items_to_insert: &[TokenAcquisition],
+) -> Result<(), diesel::result::Error> {
+    use schema::token_acquisitions::dsl::*;
+
+    let chunks = get_chunks(items_to_insert.len(), TokenAcquisition::field_count());
+
+    for (chunk_index, (start_ind, end_ind)) in chunks.into_iter().enumerate() {
+        execute_chunk_with_context(
+            conn,
+            "token_acquisitions",
+            chunk_index,
+            &items_to_insert[start_ind..end_ind],
+            |chunk| {
+                diesel::insert_into(schema::token_acquisitions::table)
+                    .values(chunk)
+                    .on_conflict((token_data_id_hash, owner_address))
+                    .do_update()
+                    .set((
+                        acquired_at.eq(excluded(acquired_at)),
+                        last_transaction_version.eq(excluded(last_transaction_version)),
+                        inserted_at.eq(excluded(inserted_at)),
+                    ))
+            },
+            Some(" WHERE token_acquisitions.last_transaction_version <= excluded.last_transaction_version "),
+        )?;
+    }
+    Ok(())
+}
+
+fn insert_current_collection_offers(
+    conn: &mut PgConnection,
+    items_to_insert: &[CurrentCollectionOffer],
+) -> Result<(), diesel::result::Error> {
+    use schema::current_collection_offers::dsl::*;
+
+    let chunks = get_chunks(items_to_insert.len(), CurrentCollectionOffer::field_count());
+
+    for (chunk_index, (start_ind, end_ind)) in chunks.into_iter().enumerate() {
+        execute_chunk_with_context(
+            conn,
+            "current_collection_offers",
+            chunk_index,
+            &items_to_insert[start_ind..end_ind],
+            |chunk| {
+                diesel::insert_into(schema::current_collection_offers::table)
+                    .values(chunk)
+                    .on_conflict((collection_data_id_hash, buyer, market_address))
+                    .do_update()
+                    .set((
+                        bid_id.eq(excluded(bid_id)),
+                        creator_address.eq(excluded(creator_address)),
+                        collection_name.eq(excluded(collection_name)),
+                        price.eq(excluded(price)),
+                        amount_remaining.eq(excluded(amount_remaining)),
+                        coin_type.eq(excluded(coin_type)),
+                        deadline.eq(excluded(deadline)),
+                        expires_at.eq(excluded(expires_at)),
+                        status.eq(excluded(status)),
+                        last_transaction_version.eq(excluded(last_transaction_version)),
+                        last_transaction_timestamp.eq(excluded(last_transaction_timestamp)),
+                        inserted_at.eq(excluded(inserted_at)),
+                    ))
+            },
+            // A cancel only applies to the offer with its bid id, not a newer one from the buyer
+            Some(" WHERE current_collection_offers.last_transaction_version <= excluded.last_transaction_version AND (excluded.status <> 'cancelled' OR current_collection_offers.bid_id = excluded.bid_id) "),
+        )?;
+    }
+    Ok(())
+}
+
+fn insert_current_token_bids(
+    conn: &mut PgConnection,
+    items_to_insert: &[CurrentTokenBid],
+) -> Result<(), diesel::result::Error> {
+    use schema::current_token_bids::dsl::*;
+
+    let chunks = get_chunks(items_to_insert.len(), CurrentTokenBid::field_count());
+
+    for (chunk_index, (start_ind, end_ind)) in chunks.into_iter().enumerate() {
+        execute_chunk_with_context(
+            conn,
+            "current_token_bids",
+            chunk_index,
+            &items_to_insert[start_ind..end_ind],
+            |chunk| {
+                diesel::insert_into(schema::current_token_bids::table)
+                    .values(chunk)
+                    .on_conflict((token_data_id_hash, property_version, buyer, market_address))
+                    .do_update()
+                    .set((
+                        collection_data_id_hash.eq(excluded(collection_data_id_hash)),
+                        creator_address.eq(excluded(creator_address)),
+                        collection_name.eq(excluded(collection_name)),
+                        name.eq(excluded(name)),
+                        bid_id.eq(excluded(bid_id)),
+                        price.eq(excluded(price)),
+                        amount_remaining.eq(excluded(amount_remaining)),
+                        coin_type.eq(excluded(coin_type)),
+                        deadline.eq(excluded(deadline)),
+                        expires_at.eq(excluded(expires_at)),
+                        status.eq(excluded(status)),
+                        last_transaction_version.eq(excluded(last_transaction_version)),
+                        last_transaction_timestamp.eq(excluded(last_transaction_timestamp)),
+                        inserted_at.eq(excluded(inserted_at)),
+                    ))
+            },
+            // Same as collection offers, a cancel only applies to the bid with its bid id
+            Some(" WHERE current_token_bids.last_transaction_version <= excluded.last_transaction_version AND (excluded.status <> 'cancelled' OR current_token_bids.bid_id = excluded.bid_id) "),
+        )?;
+    }
+    Ok(())
+}
+
+fn insert_current_token_datas(
+    conn: &mut PgConnection,
+    items_to_insert: &[CurrentTokenData],
+) -> Result<(), diesel::result::Error> {
+    use schema::current_token_datas::dsl::*;
+
+    let chunks = get_chunks(items_to_insert.len(), CurrentTokenData::field_count());
+
+    for (chunk_index, (start_ind, end_ind)) in chunks.into_iter().enumerate() {
+        execute_chunk_with_context(
+            conn,
+            "current_token_datas",
+            chunk_index,
+            &items_to_insert[start_ind..end_ind],
+            |chunk| {
+                diesel::insert_into(schema::current_token_datas::table)
+                    .values(chunk)
+                    .on_conflict(token_data_id_hash)
+                    .do_update()
+                    .set((
+                        creator_address.eq(excluded(creator_address)),
+                        collection_name.eq(excluded(collection_name)),
+                        name.eq(excluded(name)),
+                        maximum.eq(excluded(maximum)),
+                        supply.eq(excluded(supply)),
+                        largest_property_version.eq(excluded(largest_property_version)),
+                        metadata_uri.eq(excluded(metadata_uri)),
+                        payee_address.eq(excluded(payee_address)),
+                        royalty_points_numerator.eq(excluded(royalty_points_numerator)),
+                        royalty_points_denominator.eq(excluded(royalty_points_denominator)),
+                        maximum_mutable.eq(excluded(maximum_mutable)),
+                        uri_mutable.eq(excluded(uri_mutable)),
+                        description_mutable.eq(excluded(description_mutable)),
+                        properties_mutable.eq(excluded(properties_mutable)),
+                        royalty_mutable.eq(excluded(royalty_mutable)),
+                        default_properties.eq(excluded(default_properties)),
+                        last_transaction_version.eq(excluded(last_transaction_version)),
+                        collection_data_id_hash.eq(excluded(collection_data_id_hash)),
+                        description.eq(excluded(description)),
+                        metadata_uri_canonical.eq(excluded(metadata_uri_canonical)),
+                        uri_scheme.eq(excluded(uri_scheme)),
+                    ))
+            },
+            Some(" WHERE current_token_datas.last_transaction_version <= excluded.last_transaction_version "),
+        )?;
+    }
+    Ok(())
+}
+
+fn insert_token_properties_flat(
+    conn: &mut PgConnection,
+    items_to_insert: &[TokenPropertyFlat],
+) -> Result<(), diesel::result::Error> {
+    use schema::token_properties_flat::dsl::*;
+
+    let chunks = get_chunks(items_to_insert.len(), TokenPropertyFlat::field_count());
+
+    for (chunk_index, (start_ind, end_ind)) in chunks.into_iter().enumerate() {
+        execute_chunk_with_context(
+            conn,
+            "token_properties_flat",
+            chunk_index,
+            &items_to_insert[start_ind..end_ind],
+            |chunk| {
+                diesel::insert_into(schema::token_properties_flat::table)
+                    .values(chunk)
+                    .on_conflict((token_data_id_hash, property_key))
+                    .do_update()
+                    .set((
+                        property_value.eq(excluded(property_value)),
+                        property_type.eq(excluded(property_type)),
+                        collection_data_id_hash.eq(excluded(collection_data_id_hash)),
+                        last_transaction_version.eq(excluded(last_transaction_version)),
+                        inserted_at.eq(excluded(inserted_at)),
+                    ))
+            },
+            Some(" WHERE token_properties_flat.last_transaction_version <= excluded.last_transaction_version "),
+        )?;
+    }
+    Ok(())
+}
+
+/// Every write of a token data carries its full property map, so rows older than the token
+/// data's latest write are keys that were removed by a mutation
+fn delete_stale_token_properties_flat(
+    conn: &mut PgConnection,
+    current_token_datas: &[CurrentTokenData],
+) -> Result<(), diesel::result::Error> {
+    let chunks = get_chunks(current_token_datas.len(), 2);
 
     for (start_ind, end_ind) in chunks {
-        execute_with_better_error(
+        let (token_data_id_hashes, last_transaction_versions): (Vec<String>, Vec<i64>) =
+            current_token_datas[start_ind..end_ind]
+                .iter()
+                .map(|token_data| {
+                    (
+                        token_data.token_data_id_hash.clone(),
+                        token_data.last_transaction_version,
+                    )
+                })
+                .unzip();
+        sql_query(
+            "DELETE FROM token_properties_flat t
+            USING (
+                SELECT UNNEST($1::text[]) AS token_data_id_hash,
+                    UNNEST($2::bigint[]) AS last_transaction_version
+            ) d
+            WHERE t.token_data_id_hash = d.token_data_id_hash
+                AND t.last_transaction_version < d.last_transaction_version",
+        )
+        .bind::<Array<Text>, _>(token_data_id_hashes)
+        .bind::<Array<BigInt>, _>(last_transaction_versions)
+        .execute(conn)?;
+    }
+    Ok(())
+}
+
+fn insert_current_collection_datas(
+    conn: &mut PgConnection,
+    items_to_insert: &[CurrentCollectionData],
+) -> Result<(), diesel::result::Error> {
+    use schema::current_collection_datas::dsl::*;
+
+    let chunks = get_chunks(items_to_insert.len(), CurrentCollectionData::field_count());
+
+    for (chunk_index, (start_ind, end_ind)) in chunks.into_iter().enumerate() {
+        execute_chunk_with_context(
             conn,
-            diesel::insert_into(schema::current_marketplace_listings::table)
-                .values(&items_to_insert[start_ind..end_ind])
-                .on_conflict(token_data_id_hash)
-                .do_update()
-                .set((
-                    property_version.eq(excluded(property_version)),
-                    creator_address.eq(excluded(creator_address)),
-                    collection_name.eq(excluded(collection_name)),
-                    name.eq(excluded(name)),
-                    seller.eq(excluded(seller)),
-                    amount.eq(excluded(amount)),
-                    price.eq(excluded(price)),
-                    event_type.eq(excluded(event_type)),
-                    inserted_at.eq(excluded(inserted_at)),
-                    last_transaction_version.eq(excluded(last_transaction_version)),
-                )),
-                Some(" WHERE current_marketplace_listings.last_transaction_version <= excluded.last_transaction_version "),
+            "current_collection_datas",
+            chunk_index,
+            &items_to_insert[start_ind..end_ind],
+            |chunk| {
+                diesel::insert_into(schema::current_collection_datas::table)
+                    .values(chunk)
+                    .on_conflict(collection_data_id_hash)
+                    .do_update()
+                    .set((
+                        creator_address.eq(excluded(creator_address)),
+                        collection_name.eq(excluded(collection_name)),
+                        description.eq(excluded(description)),
+                        metadata_uri.eq(excluded(metadata_uri)),
+                        supply.eq(excluded(supply)),
+                        maximum.eq(excluded(maximum)),
+                        maximum_mutable.eq(excluded(maximum_mutable)),
+                        uri_mutable.eq(excluded(uri_mutable)),
+                        description_mutable.eq(excluded(description_mutable)),
+                        last_transaction_version.eq(excluded(last_transaction_version)),
+                        table_handle.eq(excluded(table_handle)),
+                        is_verified.eq(excluded(is_verified)),
+                    ))
+            },
+            Some(" WHERE current_collection_datas.last_transaction_version <= excluded.last_transaction_version "),
         )?;
     }
     Ok(())
 }
 
-#[async_trait]
-impl TransactionProcessor for TokenTransactionProcessor {
-    fn name(&self) -> &'static str {
-        NAME
+fn insert_truncated_strings(
+    conn: &mut PgConnection,
+    items_to_insert: &[TruncatedString],
+) -> Result<(), diesel::result::Error> {
+    use schema::truncated_strings::dsl::*;
+
+    let chunks = get_chunks(items_to_insert.len(), TruncatedString::field_count());
+
+    for (chunk_index, (start_ind, end_ind)) in chunks.into_iter().enumerate() {
+        execute_chunk_with_context(
+            conn,
+            "truncated_strings",
+            chunk_index,
+            &items_to_insert[start_ind..end_ind],
+            |chunk| {
+                diesel::insert_into(schema::truncated_strings::table)
+                    .values(chunk)
+                    .on_conflict((hash, field))
+                    .do_update()
+                    .set((
+                        full_value.eq(excluded(full_value)),
+                        last_transaction_version.eq(excluded(last_transaction_version)),
+                    ))
+            },
+            Some(" WHERE truncated_strings.last_transaction_version <= excluded.last_transaction_version "),
+        )?;
     }
+    Ok(())
+}
 
-    async fn process_transactions(
-        &self,
-        transactions: Vec<Transaction>,
-        start_version: u64,
-        end_version: u64,
-    ) -> Result<ProcessingResult, TransactionProcessingError> {
-        let mut conn = self.get_conn();
+fn insert_token_activities(
+    conn: &mut PgConnection,
+    items_to_insert: &[TokenActivity],
+) -> Result<(), diesel::result::Error> {
+    let chunks = get_chunks(items_to_insert.len(), TokenActivity::field_count());
+
+    use schema::token_activities::dsl::*;
+
+    for (chunk_index, (start_ind, end_ind)) in chunks.into_iter().enumerate() {
+        execute_chunk_with_context(
+            conn,
+            "token_activities",
+            chunk_index,
+            &items_to_insert[start_ind..end_ind],
+            |chunk| {
+                diesel::insert_into(schema::token_activities::table)
+                    .values(chunk)
+                    .on_conflict((
+                        transaction_version,
+                        event_account_address,
+                        event_creation_number,
+                        event_sequence_number,
+                        event_index,
+                        token_index,
+                    ))
+                    .do_nothing()
+            },
+            None,
+        )?;
+    }
+    Ok(())
+}
+
+fn insert_account_token_activities(
+    conn: &mut PgConnection,
+    items_to_insert: &[AccountTokenActivity],
+) -> Result<(), diesel::result::Error> {
+    let chunks = get_chunks(items_to_insert.len(), AccountTokenActivity::field_count());
+
+    use schema::account_token_activities::dsl::*;
+
+    for (chunk_index, (start_ind, end_ind)) in chunks.into_iter().enumerate() {
+        execute_chunk_with_context(
+            conn,
+            "account_token_activities",
+            chunk_index,
+            &items_to_insert[start_ind..end_ind],
+            |chunk| {
+                diesel::insert_into(schema::account_token_activities::table)
+                    .values(chunk)
+                    .on_conflict((
+                        account_address,
+                        transaction_version,
+                        event_index,
+                        event_account_address,
+                        event_creation_number,
+                        event_sequence_number,
+                        token_index,
+                    ))
+                    .do_nothing()
+            },
+            None,
+        )?;
+    }
+    Ok(())
+}
+
+/// Adds the sales to the stored stats. The all time high only moves to a higher price, or to an
+/// earlier sale at the same price. With only_newer, rows are skipped unless their last sale is
+/// past the stored (last_transaction_version, last_event_index), as for the current volumes.
+fn insert_current_collection_price_stats(
+    conn: &mut PgConnection,
+    items_to_insert: &[CurrentCollectionPriceStat],
+    only_newer: bool,
+) -> Result<(), diesel::result::Error> {
+    use diesel::{
+        dsl::sql,
+        sql_types::{BigInt, Numeric, Timestamp},
+    };
+    use schema::current_collection_price_stats::dsl::*;
+
+    let chunks = get_chunks(
+        items_to_insert.len(),
+        CurrentCollectionPriceStat::field_count(),
+    );
+
+    for (chunk_index, (start_ind, end_ind)) in chunks.into_iter().enumerate() {
+        execute_chunk_with_context(
+            conn,
+            "current_collection_price_stats",
+            chunk_index,
+            &items_to_insert[start_ind..end_ind],
+            |chunk| {
+                diesel::insert_into(schema::current_collection_price_stats::table)
+                    .values(chunk)
+                    .on_conflict(collection_data_id_hash)
+                    .do_update()
+                    .set((
+                        all_time_high_version.eq(sql::<BigInt>(
+                            "CASE WHEN excluded.all_time_high_price > current_collection_price_stats.all_time_high_price \
+                            OR (excluded.all_time_high_price = current_collection_price_stats.all_time_high_price \
+                            AND excluded.all_time_high_version < current_collection_price_stats.all_time_high_version) \
+                            THEN excluded.all_time_high_version ELSE current_collection_price_stats.all_time_high_version END",
+                        )),
+                        all_time_high_price.eq(sql::<Numeric>(
+                            "GREATEST(current_collection_price_stats.all_time_high_price, excluded.all_time_high_price)",
+                        )),
+                        min_sale_price.eq(sql::<Numeric>(
+                            "LEAST(current_collection_price_stats.min_sale_price, excluded.min_sale_price)",
+                        )),
+                        sale_count.eq(sale_count + excluded(sale_count)),
+                        sum_price.eq(sum_price + excluded(sum_price)),
+                        last_transaction_version.eq(greatest(last_transaction_version, excluded(last_transaction_version))),
+                        last_event_index.eq(sql::<BigInt>(&latest_event_index("current_collection_price_stats"))),
+                        inserted_at.eq(sql::<Timestamp>(
+                            "CASE WHEN excluded.last_transaction_version > current_collection_price_stats.last_transaction_version \
+                            THEN excluded.inserted_at ELSE current_collection_price_stats.inserted_at END",
+                        )),
+                    ))
+            },
+            if only_newer {
+                Some(" WHERE (current_collection_price_stats.last_transaction_version, current_collection_price_stats.last_event_index) < (excluded.last_transaction_version, excluded.last_event_index) ")
+            } else {
+                None
+            },
+        )?;
+    }
+    Ok(())
+}
+
+/// Returns the sales that weren't already stored, i.e. the ones the price and wallet stats, candles
+/// and daily reports haven't counted yet
+fn insert_nft_sales<'a>(
+    conn: &mut PgConnection,
+    items_to_insert: &'a [NftSale],
+) -> Result<Vec<&'a NftSale>, diesel::result::Error> {
+    use schema::nft_sales::dsl::*;
+
+    let chunks = get_chunks(items_to_insert.len(), NftSale::field_count());
 
+    let mut inserted = HashSet::new();
+    for (start_ind, end_ind) in chunks {
+        // No conflict target so that either unique key skips the row, the guid for events from a
+        // handle or (transaction_version, event_index) for module events
+        let keys: Vec<(i64, Option<i64>, i64)> = diesel::insert_into(schema::nft_sales::table)
+            .values(&items_to_insert[start_ind..end_ind])
+            .on_conflict_do_nothing()
+            .returning((transaction_version, event_index, token_index))
+            .get_results(conn)?;
+        inserted.extend(keys);
+    }
+    Ok(items_to_insert
+        .iter()
+        .filter(|item| {
+            inserted.contains(&(
+                item.transaction_version,
+                Some(item.event_index),
+                item.token_index,
+            ))
+        })
+        .collect())
+}
+
+fn insert_nft_transaction_fees(
+    conn: &mut PgConnection,
+    items_to_insert: &[NftTransactionFee],
+) -> Result<(), diesel::result::Error> {
+    use schema::nft_transaction_fees::dsl::*;
+
+    let chunks = get_chunks(items_to_insert.len(), NftTransactionFee::field_count());
+
+    for (chunk_index, (start_ind, end_ind)) in chunks.into_iter().enumerate() {
+        execute_chunk_with_context(
+            conn,
+            "nft_transaction_fees",
+            chunk_index,
+            &items_to_insert[start_ind..end_ind],
+            |chunk| {
+                diesel::insert_into(schema::nft_transaction_fees::table)
+                    .values(chunk)
+                    .on_conflict(transaction_version)
+                    .do_nothing()
+            },
+            None,
+        )?;
+    }
+    Ok(())
+}
+
+fn insert_current_token_claims(
+    conn: &mut PgConnection,
+    items_to_insert: &[CurrentTokenPendingClaim],
+) -> Result<(), diesel::result::Error> {
+    use schema::current_token_pending_claims::dsl::*;
+
+    let chunks = get_chunks(
+        items_to_insert.len(),
+        CurrentTokenPendingClaim::field_count(),
+    );
+
+    for (chunk_index, (start_ind, end_ind)) in chunks.into_iter().enumerate() {
+        execute_chunk_with_context(
+            conn,
+            "current_token_pending_claims",
+            chunk_index,
+            &items_to_insert[start_ind..end_ind],
+            |chunk| {
+                diesel::insert_into(schema::current_token_pending_claims::table)
+                    .values(chunk)
+                    .on_conflict((
+                        token_data_id_hash, property_version, from_address, to_address
+                    ))
+                    .do_update()
+                    .set((
+                        collection_data_id_hash.eq(excluded(collection_data_id_hash)),
+                        creator_address.eq(excluded(creator_address)),
+                        collection_name.eq(excluded(collection_name)),
+                        name.eq(excluded(name)),
+                        amount.eq(excluded(amount)),
+                        table_handle.eq(excluded(table_handle)),
+                        last_transaction_version.eq(excluded(last_transaction_version)),
+                        last_transaction_timestamp.eq(excluded(last_transaction_timestamp)),
+                        is_deleted.eq(excluded(is_deleted)),
+                    ))
+            },
+            Some(" WHERE current_token_pending_claims.last_transaction_version <= excluded.last_transaction_version "),
+        )?;
+    }
+    Ok(())
+}
+
+fn insert_current_token_transfer_offers(
+    conn: &mut PgConnection,
+    items_to_insert: &[CurrentTokenTransferOffer],
+) -> Result<(), diesel::result::Error> {
+    use schema::current_token_transfer_offers::dsl::*;
+
+    let chunks = get_chunks(
+        items_to_insert.len(),
+        CurrentTokenTransferOffer::field_count(),
+    );
+
+    for (chunk_index, (start_ind, end_ind)) in chunks.into_iter().enumerate() {
+        execute_chunk_with_context(
+            conn,
+            "current_token_transfer_offers",
+            chunk_index,
+            &items_to_insert[start_ind..end_ind],
+            |chunk| {
+                diesel::insert_into(schema::current_token_transfer_offers::table)
+                    .values(chunk)
+                    .on_conflict((token_data_id_hash, property_version, from_address, to_address))
+                    .do_update()
+                    .set((
+                        collection_data_id_hash.eq(excluded(collection_data_id_hash)),
+                        creator_address.eq(excluded(creator_address)),
+                        collection_name.eq(excluded(collection_name)),
+                        name.eq(excluded(name)),
+                        amount.eq(excluded(amount)),
+                        status.eq(excluded(status)),
+                        last_transaction_version.eq(excluded(last_transaction_version)),
+                        last_transaction_timestamp.eq(excluded(last_transaction_timestamp)),
+                        inserted_at.eq(excluded(inserted_at)),
+                    ))
+            },
+            Some(" WHERE current_token_transfer_offers.last_transaction_version <= excluded.last_transaction_version "),
+        )?;
+    }
+    Ok(())
+}
+
+/// Offers to the same receiver add up, so offers made in this batch are added to a stored offer
+/// that's still pending. Skipped for stored offers at or past the row's version, which would
+/// count the offer twice.
+fn add_pending_token_transfer_offers(
+    conn: &mut PgConnection,
+    items_to_insert: &[CurrentTokenTransferOffer],
+) -> Result<(), diesel::result::Error> {
+    use diesel::{dsl::sql, sql_types::Numeric};
+    use schema::current_token_transfer_offers::dsl::*;
+
+    let chunks = get_chunks(
+        items_to_insert.len(),
+        CurrentTokenTransferOffer::field_count(),
+    );
+
+    for (chunk_index, (start_ind, end_ind)) in chunks.into_iter().enumerate() {
+        execute_chunk_with_context(
+            conn,
+            "current_token_transfer_offers",
+            chunk_index,
+            &items_to_insert[start_ind..end_ind],
+            |chunk| {
+                diesel::insert_into(schema::current_token_transfer_offers::table)
+                    .values(chunk)
+                    .on_conflict((token_data_id_hash, property_version, from_address, to_address))
+                    .do_update()
+                    .set((
+                        amount.eq(sql::<Numeric>(
+                            "CASE WHEN current_token_transfer_offers.status = 'pending' \
+                            THEN current_token_transfer_offers.amount + excluded.amount ELSE excluded.amount END",
+                        )),
+                        status.eq(excluded(status)),
+                        last_transaction_version.eq(excluded(last_transaction_version)),
+                        last_transaction_timestamp.eq(excluded(last_transaction_timestamp)),
+                        inserted_at.eq(excluded(inserted_at)),
+                    ))
+            },
+            Some(" WHERE current_token_transfer_offers.last_transaction_version < excluded.last_transaction_version "),
+        )?;
+    }
+    Ok(())
+}
+
+fn insert_current_ans_lookups(
+    conn: &mut PgConnection,
+    items_to_insert: &[CurrentAnsLookup],
+) -> Result<(), diesel::result::Error> {
+    use schema::current_ans_lookup::dsl::*;
+
+    let chunks = get_chunks(items_to_insert.len(), CurrentAnsLookup::field_count());
+
+    for (chunk_index, (start_ind, end_ind)) in chunks.into_iter().enumerate() {
+        execute_chunk_with_context(
+            conn,
+            "current_ans_lookup",
+            chunk_index,
+            &items_to_insert[start_ind..end_ind],
+            |chunk| {
+                diesel::insert_into(schema::current_ans_lookup::table)
+                    .values(chunk)
+                    .on_conflict((domain, subdomain))
+                    .do_update()
+                    .set((
+                        registered_address.eq(excluded(registered_address)),
+                        expiration_timestamp.eq(excluded(expiration_timestamp)),
+                        last_transaction_version.eq(excluded(last_transaction_version)),
+                        is_deleted.eq(excluded(is_deleted)),
+                    ))
+            },
+            Some(" WHERE current_ans_lookup.last_transaction_version <= excluded.last_transaction_version "),
+            )?;
+    }
+    Ok(())
+}
+
+fn insert_current_ans_primary_names(
+    conn: &mut PgConnection,
+    items_to_insert: &[CurrentAnsPrimaryName],
+) -> Result<(), diesel::result::Error> {
+    use schema::current_ans_primary_name::dsl::*;
+
+    let chunks = get_chunks(items_to_insert.len(), CurrentAnsPrimaryName::field_count());
+
+    for (chunk_index, (start_ind, end_ind)) in chunks.into_iter().enumerate() {
+        execute_chunk_with_context(
+            conn,
+            "current_ans_primary_name",
+            chunk_index,
+            &items_to_insert[start_ind..end_ind],
+            |chunk| {
+                diesel::insert_into(schema::current_ans_primary_name::table)
+                    .values(chunk)
+                    .on_conflict(registered_address)
+                    .do_update()
+                    .set((
+                        domain.eq(excluded(domain)),
+                        subdomain.eq(excluded(subdomain)),
+                        last_transaction_version.eq(excluded(last_transaction_version)),
+                    ))
+            },
+            Some(" WHERE current_ans_primary_name.last_transaction_version <= excluded.last_transaction_version "),
+        )?;
+    }
+    Ok(())
+}
+
+fn insert_current_ans_sale_prices(
+    conn: &mut PgConnection,
+    items_to_insert: &[CurrentAnsSalePrice],
+) -> Result<(), diesel::result::Error> {
+    use schema::current_ans_sale_prices::dsl::*;
+
+    let chunks = get_chunks(items_to_insert.len(), CurrentAnsSalePrice::field_count());
+
+    for (chunk_index, (start_ind, end_ind)) in chunks.into_iter().enumerate() {
+        execute_chunk_with_context(
+            conn,
+            "current_ans_sale_prices",
+            chunk_index,
+            &items_to_insert[start_ind..end_ind],
+            |chunk| {
+                diesel::insert_into(schema::current_ans_sale_prices::table)
+                    .values(chunk)
+                    .on_conflict(domain)
+                    .do_update()
+                    .set((
+                        token_data_id_hash.eq(excluded(token_data_id_hash)),
+                        market_address.eq(excluded(market_address)),
+                        coin_type.eq(excluded(coin_type)),
+                        last_sale_price.eq(excluded(last_sale_price)),
+                        last_sale_price_usd.eq(excluded(last_sale_price_usd)),
+                        last_transaction_version.eq(excluded(last_transaction_version)),
+                        last_transaction_timestamp.eq(excluded(last_transaction_timestamp)),
+                        inserted_at.eq(excluded(inserted_at)),
+                    ))
+            },
+            Some(" WHERE current_ans_sale_prices.last_transaction_version <= excluded.last_transaction_version "),
+        )?;
+    }
+    Ok(())
+}
+
+/// Changes are applied in (last_transaction_version, last_event_index) order, so a replayed
+/// event doesn't undo a later one from the same transaction. A stored row with a null event
+/// index is only replaced from a later version.
+fn insert_current_marketplace_listings(
+    conn: &mut PgConnection,
+    items_to_insert: &[CurrentMarketplaceListing],
+) -> Result<(), diesel::result::Error> {
+    use schema::current_marketplace_listings::dsl::*;
+
+    let chunks = get_chunks(
+        items_to_insert.len(),
+        CurrentMarketplaceListing::field_count(),
+    );
+
+    for (chunk_index, (start_ind, end_ind)) in chunks.into_iter().enumerate() {
+        execute_chunk_with_context(
+            conn,
+            "current_marketplace_listings",
+            chunk_index,
+            &items_to_insert[start_ind..end_ind],
+            |chunk| {
+                diesel::insert_into(schema::current_marketplace_listings::table)
+                    .values(chunk)
+                    .on_conflict(token_data_id_hash)
+                    .do_update()
+                    .set((
+                        property_version.eq(excluded(property_version)),
+                        creator_address.eq(excluded(creator_address)),
+                        collection_name.eq(excluded(collection_name)),
+                        name.eq(excluded(name)),
+                        seller.eq(excluded(seller)),
+                        amount.eq(excluded(amount)),
+                        price.eq(excluded(price)),
+                        event_type.eq(excluded(event_type)),
+                        inserted_at.eq(excluded(inserted_at)),
+                        last_transaction_version.eq(excluded(last_transaction_version)),
+                        invalidated_reason.eq(excluded(invalidated_reason)),
+                        last_transaction_timestamp.eq(excluded(last_transaction_timestamp)),
+                        price_decimal.eq(excluded(price_decimal)),
+                        is_deleted.eq(excluded(is_deleted)),
+                        last_event_index.eq(excluded(last_event_index)),
+                    ))
+            },
+            // Rows written before last_event_index was added have none, and come before every
+            // event of their transaction like they do in `CurrentMarketplaceListing::ordering_key`
+            Some(" WHERE (current_marketplace_listings.last_transaction_version, COALESCE(current_marketplace_listings.last_event_index, -1)) <= (excluded.last_transaction_version, COALESCE(excluded.last_event_index, -1)) "),
+        )?;
+    }
+    Ok(())
+}
+
+// Rows within a batch are sorted by each table's conflict target so that concurrent upserts
+// lock rows in the same order and don't deadlock
+
+/// A fetcher retry can put the same transaction in a batch twice. Collecting activities by PK
+/// drops the duplicates before they're sent to postgres, and leaves them sorted like the others.
+fn dedup_token_activities(items: Vec<TokenActivity>) -> Vec<TokenActivity> {
+    items
+        .into_iter()
+        .map(|activity| (activity.pk(), activity))
+        .collect::<BTreeMap<TokenActivityPK, TokenActivity>>()
+        .into_values()
+        .collect()
+}
+
+fn sort_current_token_ownerships(items: &mut [CurrentTokenOwnership]) {
+    items.sort_by(|a, b| {
+        (&a.token_data_id_hash, &a.property_version, &a.owner_address).cmp(&(
+            &b.token_data_id_hash,
+            &b.property_version,
+            &b.owner_address,
+        ))
+    });
+}
+
+fn sort_current_token_datas(items: &mut [CurrentTokenData]) {
+    items.sort_by(|a, b| a.token_data_id_hash.cmp(&b.token_data_id_hash));
+}
+
+fn sort_current_collection_datas(items: &mut [CurrentCollectionData]) {
+    items.sort_by(|a, b| a.collection_data_id_hash.cmp(&b.collection_data_id_hash));
+}
+
+fn sort_current_token_claims(items: &mut [CurrentTokenPendingClaim]) {
+    items.sort_by(|a, b| {
+        (
+            &a.token_data_id_hash,
+            &a.property_version,
+            &a.from_address,
+            &a.to_address,
+        )
+            .cmp(&(
+                &b.token_data_id_hash,
+                &b.property_version,
+                &b.from_address,
+                &b.to_address,
+            ))
+    });
+}
+
+fn sort_current_ans_lookups(items: &mut [CurrentAnsLookup]) {
+    items.sort_by(|a, b| (&a.domain, &a.subdomain).cmp(&(&b.domain, &b.subdomain)));
+}
+
+fn sort_current_ans_primary_names(items: &mut [CurrentAnsPrimaryName]) {
+    items.sort_by(|a, b| a.registered_address.cmp(&b.registered_address));
+}
+
+fn insert_marketplace_listing_price_changes(
+    conn: &mut PgConnection,
+    items_to_insert: &[MarketplaceListingPriceChange],
+) -> Result<(), diesel::result::Error> {
+    use schema::marketplace_listing_price_changes::dsl::*;
+
+    let chunks = get_chunks(
+        items_to_insert.len(),
+        MarketplaceListingPriceChange::field_count(),
+    );
+
+    for (chunk_index, (start_ind, end_ind)) in chunks.into_iter().enumerate() {
+        execute_chunk_with_context(
+            conn,
+            "marketplace_listing_price_changes",
+            chunk_index,
+            &items_to_insert[start_ind..end_ind],
+            |chunk| {
+                diesel::insert_into(schema::marketplace_listing_price_changes::table)
+                    .values(chunk)
+                    .on_conflict((transaction_version, event_index))
+                    .do_nothing()
+            },
+            None,
+        )?;
+    }
+    Ok(())
+}
+
+fn sort_current_marketplace_listings(items: &mut [CurrentMarketplaceListing]) {
+    items.sort_by(|a, b| a.token_data_id_hash.cmp(&b.token_data_id_hash));
+}
+
+fn sort_truncated_strings(items: &mut [TruncatedString]) {
+    items.sort_by(|a, b| (&a.hash, &a.field).cmp(&(&b.hash, &b.field)));
+}
+
+fn sort_current_collection_volumes(items: &mut [CurrentCollectionVolume]) {
+    items.sort_by(|a, b| a.collection_data_id_hash.cmp(&b.collection_data_id_hash));
+}
+
+fn sort_current_token_volumes(items: &mut [CurrentTokenVolume]) {
+    items.sort_by(|a, b| a.token_data_id_hash.cmp(&b.token_data_id_hash));
+}
+
+/// What a transaction parses to without the db. The rest of its rows need the db or the
+/// transactions before it, so they're built while merging in version order.
+pub struct ParsedTransaction {
+    transaction_rank_in_block: Option<i64>,
+    token_events: TokenEvents,
+    /// Activities, listings, volumes and listing price changes are all derived from these
+    event_effects: Vec<TokenEventEffects>,
+    tokens: ParsedTokens,
+    token_activities: Vec<TokenActivity>,
+    nft_sales: Vec<NftSale>,
+    nft_transaction_fee: Option<NftTransactionFee>,
+    collection_mints: Vec<CollectionMint>,
+    token_property_mutations: Vec<TokenPropertyMutation>,
+    current_ans_lookups: HashMap<CurrentAnsLookupPK, CurrentAnsLookup>,
+    current_ans_primary_names: HashMap<CurrentAnsPrimaryNamePK, CurrentAnsPrimaryName>,
+    current_marketplace_listings: HashMap<TokenDataIdHash, CurrentMarketplaceListing>,
+}
+
+impl ParsedTransaction {
+    pub fn tokens(&self) -> &ParsedTokens {
+        &self.tokens
+    }
+
+    pub fn from_transaction(
+        txn: &Transaction,
+        transaction_rank_in_block: Option<i64>,
+        marketplace_event_mappings: &MarketplaceEventMappings,
+        ans_contracts: &[AnsContract],
+        record_settlement_amounts: bool,
+    ) -> anyhow::Result<Self> {
+        // Shared by every model below and the offer and bid books, so each event is only
+        // deserialized once. An event that doesn't match its mapping fails the batch rather than
+        // the parsing thread.
+        let token_events =
+            TokenEvents::from_transaction_with_mappings(txn, marketplace_event_mappings)
+                .with_context(|| {
+                    format!(
+                        "Failed to parse the token events of transaction {}",
+                        txn.version().unwrap_or_default()
+                    )
+                })?;
+        let event_effects =
+            TokenEventEffects::from_transaction(txn, &token_events, marketplace_event_mappings);
+        let token_activities = TokenActivity::from_effects(&event_effects);
+        let mut nft_sales =
+            NftSale::from_token_activities(txn, &token_activities, transaction_rank_in_block);
+        nft_sales.extend(NftSale::from_payload(
+            txn,
+            &token_activities,
+            marketplace_event_mappings,
+            transaction_rank_in_block,
+        ));
+        NftSale::set_sale_groups(&mut nft_sales);
+        if record_settlement_amounts {
+            NftSale::set_settlement_amounts(txn, &mut nft_sales);
+        }
+        let nft_transaction_fee = NftTransactionFee::from_token_activities(txn, &token_activities);
+        let (current_ans_lookups, current_ans_primary_names) =
+            CurrentAnsLookup::from_transaction(txn, ans_contracts);
+        Ok(Self {
+            transaction_rank_in_block,
+            tokens: Token::parse_transaction(txn, &token_events, marketplace_event_mappings),
+            token_activities,
+            nft_sales,
+            nft_transaction_fee,
+            collection_mints: CollectionMint::from_transaction(
+                txn,
+                &token_events,
+                marketplace_event_mappings,
+            ),
+            token_property_mutations: TokenPropertyMutation::from_transaction(txn, &token_events),
+            current_ans_lookups,
+            current_ans_primary_names,
+            current_marketplace_listings: CurrentMarketplaceListing::from_transaction(
+                txn,
+                &event_effects,
+                marketplace_event_mappings,
+            ),
+            token_events,
+            event_effects,
+        })
+    }
+}
+
+/// Parses a batch's transactions in parallel, returned in the same order. Fails if any of the
+/// transactions doesn't parse.
+pub fn parse_transactions(
+    transactions: &[Transaction],
+    marketplace_event_mappings: &MarketplaceEventMappings,
+    ans_contracts: &[AnsContract],
+    record_settlement_amounts: bool,
+) -> anyhow::Result<Vec<ParsedTransaction>> {
+    // Block boundaries are only known going through the transactions in order
+    let mut block_position = BlockPosition::default();
+    let transaction_ranks_in_block = transactions
+        .iter()
+        .map(|txn| block_position.rank(txn))
+        .collect::<Vec<_>>();
+    transactions
+        .par_iter()
+        .zip(transaction_ranks_in_block)
+        .map(|(txn, transaction_rank_in_block)| {
+            ParsedTransaction::from_transaction(
+                txn,
+                transaction_rank_in_block,
+                marketplace_event_mappings,
+                ans_contracts,
+                record_settlement_amounts,
+            )
+        })
+        .collect()
+}
+
+/// What building a batch's rows needs from the processor. The rows are built on the blocking
+/// thread pool, which can't borrow the processor, so it's shared with it instead.
+struct TokenRowBuilder {
+    ans_contracts: Vec<AnsContract>,
+    ans_domains: AnsDomains,
+    marketplace_event_mappings: MarketplaceEventMappings,
+    transaction_tracer: TransactionTracer,
+    record_settlement_amounts: bool,
+    account_token_activities: bool,
+    table_handle_cache: TableHandleCache,
+}
+
+impl TokenRowBuilder {
+    fn build_rows(
+        &self,
+        conn: &mut PgPoolConnection,
+        transactions: &[Transaction],
+    ) -> anyhow::Result<TokenBatchRows> {
         let mut all_tokens = vec![];
         let mut all_token_ownerships = vec![];
         let mut all_token_datas = vec![];
         let mut all_collection_datas = vec![];
         let mut all_token_activities = vec![];
+        let mut all_nft_sales = vec![];
         let mut all_collection_volumes = vec![];
         let mut all_token_volumes = vec![];
+        let mut all_collection_mints = vec![];
+        let mut all_token_property_mutations = vec![];
+        let mut all_nft_transaction_fees = vec![];
+        let mut all_listing_withdrawals = vec![];
+        let mut all_deleted_token_resources = vec![];
 
         // Hashmap key will be the PK of the table, we do not want to send duplicates writes to the db within a batch
         let mut all_current_token_ownerships: HashMap<
@@ -675,21 +3036,93 @@ impl TransactionProcessor for TokenTransactionProcessor {
         > = HashMap::new();
         let mut all_current_ans_lookups: HashMap<CurrentAnsLookupPK, CurrentAnsLookup> =
             HashMap::new();
-        let mut all_current_marketplace_listings: HashMap<TokenDataIdHash, CurrentMarketplaceListing> =
-            HashMap::new();
-        let mut all_current_collection_volumes: HashMap<CollectionDataIdHash, CurrentCollectionVolume> =
-            HashMap::new();
-        let mut all_current_token_volumes: HashMap<CollectionDataIdHash, CurrentTokenVolume> =
-            HashMap::new();
+        let mut all_current_ans_primary_names: HashMap<
+            CurrentAnsPrimaryNamePK,
+            CurrentAnsPrimaryName,
+        > = HashMap::new();
+        let mut all_current_marketplace_listings: HashMap<
+            TokenDataIdHash,
+            CurrentMarketplaceListing,
+        > = HashMap::new();
+        let mut all_truncated_strings: HashMap<TruncatedStringPK, TruncatedString> = HashMap::new();
         // let mut all_current_daily_collection_volumes: HashMap<CollectionDataIdHash, CurrentDailyCollectionVolume> =
         //     HashMap::new();
         // let mut all_current_weekly_collection_volumes: HashMap<CollectionDataIdHash, CurrentWeeklyCollectionVolume> =
         //     HashMap::new();
         // let mut all_current_monthly_collection_volumes: HashMap<CollectionDataIdHash, CurrentMonthlyCollectionVolume> =
         //     HashMap::new();
-            
 
-        for txn in transactions {
+        // Parsing doesn't need the db, so it's spread over the rayon pool and only the merge below
+        // runs in version order
+        let parsed_transactions = parse_transactions(
+            transactions,
+            &self.marketplace_event_mappings,
+            &self.ans_contracts,
+            self.record_settlement_amounts,
+        )?;
+        // Sales are valued at the coin prices as of processing, not of the sale
+        let coin_prices = CoinPrices::load_latest(conn)?;
+        let coin_decimals = CoinDecimals::load(
+            conn,
+            parsed_transactions
+                .iter()
+                .flat_map(|parsed_transaction| &parsed_transaction.nft_sales),
+        )?;
+
+        // Creators of the collection tables the batch writes to without their Collections
+        // resource, looked up for the whole batch at once rather than per table item
+        let collection_creators = CollectionCreators::prefetch(
+            conn,
+            &self.table_handle_cache,
+            parsed_transactions
+                .iter()
+                .flat_map(|parsed_transaction| parsed_transaction.tokens.collection_items()),
+        )?;
+
+        // Transactions come in version order, so the owners each token had before a sale can be
+        // tracked as we go, on top of the owners in the db of every token the batch sells
+        let mut primary_sale_classifier = PrimarySaleClassifier::default();
+        primary_sale_classifier.load_db_owners(
+            conn,
+            parsed_transactions
+                .iter()
+                .flat_map(|parsed_transaction| &parsed_transaction.nft_sales)
+                .map(|nft_sale| &nft_sale.token_data_id_hash),
+        )?;
+        // Same for collection offers, token bids and transfer offers, which can be placed and
+        // filled within the batch
+        let mut collection_offer_book = CollectionOfferBook::default();
+        let mut token_bid_book = TokenBidBook::default();
+        let mut token_transfer_offer_book = TokenTransferOfferBook::default();
+        // And listing prices, to tell the repricings of a listing
+        let mut listing_price_change_book = ListingPriceChangeBook::default();
+        for (txn, parsed_transaction) in transactions.iter().zip(parsed_transactions) {
+            let ParsedTransaction {
+                transaction_rank_in_block,
+                token_events,
+                event_effects,
+                tokens: parsed_tokens,
+                token_activities: mut activities,
+                mut nft_sales,
+                nft_transaction_fee,
+                mut collection_mints,
+                mut token_property_mutations,
+                current_ans_lookups,
+                current_ans_primary_names,
+                current_marketplace_listings,
+            } = parsed_transaction;
+            // Only set for versions in the trace_versions config
+            let mut trace = self.transaction_tracer.start(NAME, txn);
+            if let Some(trace) = &mut trace {
+                trace.attribute("transaction_rank_in_block", transaction_rank_in_block);
+                TokenActivity::trace_events(
+                    trace.child("events"),
+                    txn,
+                    &self.marketplace_event_mappings,
+                );
+            }
+            // Collection items whose creator isn't in the transaction are resolved in order, so
+            // they see the collections written earlier in the batch
             let (
                 mut tokens,
                 mut token_ownerships,
@@ -699,7 +3132,38 @@ impl TransactionProcessor for TokenTransactionProcessor {
                 current_token_datas,
                 current_collection_datas,
                 current_token_claims,
-            ) = Token::from_transaction(&txn, &mut conn);
+            ) = parsed_tokens.resolve(&collection_creators, &self.table_handle_cache);
+            if let Some(trace) = &mut trace {
+                trace
+                    .child_once("rows")
+                    .attribute("tokens", &tokens)
+                    .attribute("token_ownerships", &token_ownerships)
+                    .attribute("token_datas", &token_datas)
+                    .attribute("collection_datas", &collection_datas)
+                    .attribute(
+                        "current_token_ownerships",
+                        current_token_ownerships.values().collect::<Vec<_>>(),
+                    )
+                    .attribute(
+                        "current_token_datas",
+                        current_token_datas.values().collect::<Vec<_>>(),
+                    )
+                    .attribute(
+                        "current_collection_datas",
+                        current_collection_datas.values().collect::<Vec<_>>(),
+                    )
+                    .attribute(
+                        "current_token_pending_claims",
+                        current_token_claims.values().collect::<Vec<_>>(),
+                    );
+            }
+            // Whole values of the names and uris the rows above store truncated
+            all_truncated_strings.extend(
+                TruncatedString::from_transaction(txn, &collection_datas)
+                    .into_iter()
+                    .map(|row| (row.pk(), row)),
+            );
+            primary_sale_classifier.record_ownerships(&token_ownerships);
             all_tokens.append(&mut tokens);
             all_token_ownerships.append(&mut token_ownerships);
             all_token_datas.append(&mut token_datas);
@@ -707,37 +3171,198 @@ impl TransactionProcessor for TokenTransactionProcessor {
             // Given versions will always be increasing here (within a single batch), we can just override current values
             all_current_token_ownerships.extend(current_token_ownerships);
             all_current_token_datas.extend(current_token_datas);
+            // Later transactions of the batch can't look these up in the db yet
+            for current_collection_data in current_collection_datas.values() {
+                self.table_handle_cache.insert(
+                    &current_collection_data.table_handle,
+                    &current_collection_data.creator_address,
+                    current_collection_data.last_transaction_version,
+                );
+            }
             all_current_collection_datas.extend(current_collection_datas);
 
-            // Track token activities
-            let mut activities = TokenActivity::from_transaction(&txn);
+            // Track token activities, with sales classified against the ownerships seen so far
+            primary_sale_classifier.classify(&mut nft_sales);
+            self.ans_domains.set_activity_domains(&mut activities);
+            self.ans_domains.set_sale_domains(&mut nft_sales);
+            coin_prices.price_sales(&mut nft_sales);
+            coin_decimals.set_sale_prices(&mut nft_sales);
+            if let Some(trace) = &mut trace {
+                trace
+                    .child_once("rows")
+                    .attribute("token_activities", &activities)
+                    .attribute("nft_sales", &nft_sales)
+                    .attribute("nft_transaction_fees", &nft_transaction_fee);
+            }
+            // Escrowless listings from earlier in the batch, before this transaction's listings
+            all_listing_withdrawals.append(&mut CurrentMarketplaceListing::invalidate_withdrawn(
+                &mut all_current_marketplace_listings,
+                &activities,
+            ));
             all_token_activities.append(&mut activities);
+            all_nft_transaction_fees.extend(nft_transaction_fee);
+
+            // Mints
+            if let Some(trace) = &mut trace {
+                trace
+                    .child_once("rows")
+                    .attribute("collection_mints", &collection_mints);
+            }
+            all_collection_mints.append(&mut collection_mints);
+            all_token_property_mutations.append(&mut token_property_mutations);
 
             // claims
             all_current_token_claims.extend(current_token_claims);
 
+            // Token stores and pending claims deleted with their tables
+            for deletion in DeletedTokenResource::from_transaction(txn) {
+                deletion.zero_batch_rows(
+                    &mut all_current_token_ownerships,
+                    &mut all_current_token_claims,
+                );
+                all_deleted_token_resources.push(deletion);
+            }
+
             // ANS lookups
-            let current_ans_lookups =
-                CurrentAnsLookup::from_transaction(&txn, self.ans_contract_address.clone());
+            if let Some(trace) = &mut trace {
+                trace
+                    .child_once("rows")
+                    .attribute(
+                        "current_ans_lookup",
+                        current_ans_lookups.values().collect::<Vec<_>>(),
+                    )
+                    .attribute(
+                        "current_ans_primary_name",
+                        current_ans_primary_names.values().collect::<Vec<_>>(),
+                    );
+            }
             all_current_ans_lookups.extend(current_ans_lookups);
+            all_current_ans_primary_names.extend(current_ans_primary_names);
 
             // Marketplace listings
-            let current_marketplace_listings =
-                CurrentMarketplaceListing::from_transaction(&txn);
+            if let Some(trace) = &mut trace {
+                trace.child_once("rows").attribute(
+                    "current_marketplace_listings",
+                    current_marketplace_listings.values().collect::<Vec<_>>(),
+                );
+            }
             all_current_marketplace_listings.extend(current_marketplace_listings);
+            listing_price_change_book.apply_effects(&event_effects);
+
+            // Collection offers, token bids and transfer offers
+            collection_offer_book.apply_transaction(txn, &token_events);
+            token_bid_book.apply_transaction(txn, &token_events);
+            token_transfer_offer_book.apply_transaction(txn, &token_events);
+
+            // Events and inferred sales with a suspect value are recorded above, but left out of
+            // the volumes and of every aggregate built from the batch's sales
+            SUSPECT_VALUE_EXCLUSIONS
+                .with_label_values(&[EVENT_SOURCE])
+                .inc_by(
+                    event_effects
+                        .iter()
+                        .filter(|effects| effects.suspect_value)
+                        .count() as u64,
+                );
+            SUSPECT_VALUE_EXCLUSIONS
+                .with_label_values(&[PAYLOAD_INFERRED_SOURCE])
+                .inc_by(
+                    nft_sales
+                        .iter()
+                        .filter(|sale| sale.source == PAYLOAD_INFERRED_SOURCE && sale.suspect_value)
+                        .count() as u64,
+                );
 
             // Collection volume
-            let (current_collection_volumes, mut collection_volumes, current_token_volumes, mut token_volumes) =
-                CurrentCollectionVolume::from_transaction(&txn);
-            all_current_collection_volumes.extend(current_collection_volumes);
+            let (
+                current_collection_volumes,
+                mut collection_volumes,
+                current_token_volumes,
+                mut token_volumes,
+            ) = CurrentCollectionVolume::from_effects(&event_effects, &nft_sales);
+            if let Some(mut trace) = trace {
+                trace
+                    .child_once("rows")
+                    .attribute(
+                        "current_collection_volumes",
+                        current_collection_volumes.values().collect::<Vec<_>>(),
+                    )
+                    .attribute("collection_volumes", &collection_volumes)
+                    .attribute(
+                        "current_token_volumes",
+                        current_token_volumes.values().collect::<Vec<_>>(),
+                    )
+                    .attribute("token_volumes", &token_volumes);
+                TransactionTracer::finish(trace);
+            }
             all_collection_volumes.append(&mut collection_volumes);
-            all_current_token_volumes.extend(current_token_volumes);
             all_token_volumes.append(&mut token_volumes);
+            // Kept until here since volumes are split using the sale classification
+            all_nft_sales.append(&mut nft_sales);
             // all_current_daily_collection_volumes.extend(current_daily_collection_volumes);
             // all_current_weekly_collection_volumes.extend(current_weekly_collection_volumes);
             // all_current_monthly_collection_volumes.extend(current_monthly_collection_volumes);
         }
 
+        let all_token_activities = dedup_token_activities(all_token_activities);
+        let all_account_token_activities = if self.account_token_activities {
+            AccountTokenActivity::from_token_activities(&all_token_activities)
+        } else {
+            vec![]
+        };
+
+        // Listings from earlier batches that were invalidated by a withdrawal in this one
+        CurrentMarketplaceListing::invalidate_withdrawn_from_db(
+            conn,
+            &mut all_current_marketplace_listings,
+            &all_listing_withdrawals,
+        )?;
+        coin_decimals.set_listing_prices(all_current_marketplace_listings.values_mut());
+        // Rows from earlier batches of the accounts whose token stores or pending claims were deleted
+        DeletedTokenResource::zero_stored_rows(
+            conn,
+            &all_deleted_token_resources,
+            &mut all_current_token_ownerships,
+            &mut all_current_token_claims,
+        )?;
+        // Prices of the listings repriced in this batch from before it
+        let all_marketplace_listing_price_changes = listing_price_change_book.into_rows(conn)?;
+        CurrentTokenOwnership::set_known_owner_types(conn, &mut all_current_token_ownerships)?;
+        CurrentMarketplaceListing::set_escrow_beneficial_owners(
+            conn,
+            &mut all_current_token_ownerships,
+            &all_current_marketplace_listings,
+        )?;
+        // Curations are read once for all the collections the batch writes
+        CollectionCurations::load(conn, all_current_collection_datas.keys())?
+            .set_is_verified(all_current_collection_datas.values_mut());
+
+        // Realized pnl needs the batch's sales, mints and transfers in version order, so it's set
+        // before anything aggregates the sales
+        let all_wallet_token_cost_basis = WalletTokenCostBasis::from_batch(
+            conn,
+            &mut all_nft_sales,
+            &all_collection_mints,
+            &all_token_activities,
+        )?;
+        let mut all_wallet_token_cost_basis = all_wallet_token_cost_basis
+            .into_values()
+            .collect::<Vec<WalletTokenCostBasis>>();
+        all_wallet_token_cost_basis.sort_by(|a, b| {
+            (&a.wallet_address, &a.token_data_id_hash)
+                .cmp(&(&b.wallet_address, &b.token_data_id_hash))
+        });
+        // Same for hold durations
+        let all_token_acquisitions =
+            TokenAcquisition::from_batch(conn, &mut all_nft_sales, &all_token_activities)?;
+        let mut all_token_acquisitions = all_token_acquisitions
+            .into_values()
+            .collect::<Vec<TokenAcquisition>>();
+        all_token_acquisitions.sort_by(|a, b| {
+            (&a.token_data_id_hash, &a.owner_address)
+                .cmp(&(&b.token_data_id_hash, &b.owner_address))
+        });
+
         // Getting list of values and sorting by pk in order to avoid postgres deadlock since we're doing multi threaded db writes
         let mut all_current_token_ownerships = all_current_token_ownerships
             .into_values()
@@ -753,51 +3378,62 @@ impl TransactionProcessor for TokenTransactionProcessor {
             .collect::<Vec<CurrentTokenPendingClaim>>();
 
         // Sort by PK
-        all_current_token_ownerships.sort_by(|a, b| {
-            (&a.token_data_id_hash, &a.property_version, &a.owner_address).cmp(&(
-                &b.token_data_id_hash,
-                &b.property_version,
-                &b.owner_address,
-            ))
-        });
-        all_current_token_datas.sort_by(|a, b| a.token_data_id_hash.cmp(&b.token_data_id_hash));
-        all_current_collection_datas
-            .sort_by(|a, b| a.collection_data_id_hash.cmp(&b.collection_data_id_hash));
-        all_current_token_claims.sort_by(|a, b| {
-            (
-                &a.token_data_id_hash,
-                &a.property_version,
-                &a.from_address,
-                &a.to_address,
-            )
-                .cmp(&(
-                    &b.token_data_id_hash,
-                    &b.property_version,
-                    &b.from_address,
-                    &a.to_address,
-                ))
-        });
+        sort_current_token_ownerships(&mut all_current_token_ownerships);
+        sort_current_token_datas(&mut all_current_token_datas);
+        // Already sorted by PK since token datas are sorted and keys are unique per token
+        let all_token_properties_flat = all_current_token_datas
+            .iter()
+            .flat_map(TokenPropertyFlat::from_current_token_data)
+            .collect::<Vec<TokenPropertyFlat>>();
+        sort_current_collection_datas(&mut all_current_collection_datas);
+        sort_current_token_claims(&mut all_current_token_claims);
         // Sort ans lookup values for postgres insert
         let mut all_current_ans_lookups = all_current_ans_lookups
             .into_values()
             .collect::<Vec<CurrentAnsLookup>>();
-        all_current_ans_lookups
-            .sort_by(|a, b| a.domain.cmp(&b.domain).then(a.subdomain.cmp(&b.subdomain)));
+        sort_current_ans_lookups(&mut all_current_ans_lookups);
+        let mut all_current_ans_primary_names = all_current_ans_primary_names
+            .into_values()
+            .collect::<Vec<CurrentAnsPrimaryName>>();
+        sort_current_ans_primary_names(&mut all_current_ans_primary_names);
 
         let mut all_current_marketplace_listings = all_current_marketplace_listings
             .into_values()
             .collect::<Vec<CurrentMarketplaceListing>>();
-        all_current_marketplace_listings.sort_by(|a, b| a.token_data_id_hash.cmp(&b.token_data_id_hash));
+        sort_current_marketplace_listings(&mut all_current_marketplace_listings);
+
+        let mut all_truncated_strings = all_truncated_strings
+            .into_values()
+            .collect::<Vec<TruncatedString>>();
+        sort_truncated_strings(&mut all_truncated_strings);
 
-        let mut all_current_collection_volumes = all_current_collection_volumes
+        // Totalled over the whole batch, several of its transactions can sell in a collection
+        let mut all_current_collection_volumes =
+            CurrentCollectionVolume::from_collection_volumes(&all_collection_volumes);
+        sort_current_collection_volumes(&mut all_current_collection_volumes);
+
+        let mut all_current_token_volumes =
+            CurrentTokenVolume::from_token_volumes(&all_token_volumes);
+        sort_current_token_volumes(&mut all_current_token_volumes);
+
+        // Candles and daily reports, with rollup rows, aggregated from this batch's sales
+        let (all_collection_price_candles, all_collection_daily_reports) =
+            CollectionDailyReport::from_nft_sales(&all_nft_sales);
+        let all_collection_price_candles = all_collection_price_candles
+            .into_values()
+            .collect::<Vec<CollectionPriceCandle>>();
+        let all_collection_daily_reports = all_collection_daily_reports
             .into_values()
-            .collect::<Vec<CurrentCollectionVolume>>();
-        all_current_collection_volumes.sort_by(|a, b| a.collection_data_id_hash.cmp(&b.collection_data_id_hash));
-
-        let mut all_current_token_volumes = all_current_token_volumes
+            .collect::<Vec<CollectionDailyReport>>();
+        let all_current_wallet_nft_stats = CurrentWalletNftStat::from_nft_sales(&all_nft_sales)
             .into_values()
-            .collect::<Vec<CurrentTokenVolume>>();
-        all_current_token_volumes.sort_by(|a, b| a.token_data_id_hash.cmp(&b.token_data_id_hash));
+            .collect::<Vec<CurrentWalletNftStat>>();
+        let (all_current_collection_offers, all_collection_offer_fills) =
+            collection_offer_book.into_rows();
+        let (all_current_token_bids, all_token_bid_fills, all_token_auction_bids) =
+            token_bid_book.into_rows();
+        let (all_current_token_transfer_offers, all_token_transfer_offer_additions) =
+            token_transfer_offer_book.into_rows();
         // let mut all_current_daily_collection_volumes = all_current_daily_collection_volumes
         //     .into_values()
         //     .collect::<Vec<CurrentDailyCollectionVolume>>();
@@ -811,40 +3447,244 @@ impl TransactionProcessor for TokenTransactionProcessor {
         //     .collect::<Vec<CurrentMonthlyCollectionVolume>>();
         //     all_current_monthly_collection_volumes.sort_by(|a, b| a.collection_data_id_hash.cmp(&b.collection_data_id_hash));
 
-        let tx_result = insert_to_db(
-            &mut conn,
+        Ok(TokenBatchRows {
+            tokens: all_tokens,
+            token_ownerships: all_token_ownerships,
+            token_datas: all_token_datas,
+            collection_datas: all_collection_datas,
+            current_token_ownerships: all_current_token_ownerships,
+            current_token_datas: all_current_token_datas,
+            current_collection_datas: all_current_collection_datas,
+            token_activities: all_token_activities,
+            account_token_activities: all_account_token_activities,
+            nft_sales: all_nft_sales,
+            nft_transaction_fees: all_nft_transaction_fees,
+            current_token_claims: all_current_token_claims,
+            current_token_transfer_offers: all_current_token_transfer_offers,
+            token_transfer_offer_additions: all_token_transfer_offer_additions,
+            current_ans_lookups: all_current_ans_lookups,
+            current_ans_primary_names: all_current_ans_primary_names,
+            current_marketplace_listings: all_current_marketplace_listings,
+            marketplace_listing_price_changes: all_marketplace_listing_price_changes,
+            current_collection_volumes: all_current_collection_volumes,
+            collection_volumes: all_collection_volumes,
+            current_token_volumes: all_current_token_volumes,
+            token_volumes: all_token_volumes,
+            collection_price_candles: all_collection_price_candles,
+            collection_daily_reports: all_collection_daily_reports,
+            token_properties_flat: all_token_properties_flat,
+            collection_mints: all_collection_mints,
+            token_property_mutations: all_token_property_mutations,
+            current_wallet_nft_stats: all_current_wallet_nft_stats,
+            wallet_token_cost_basis: all_wallet_token_cost_basis,
+            token_acquisitions: all_token_acquisitions,
+            current_collection_offers: all_current_collection_offers,
+            collection_offer_fills: all_collection_offer_fills,
+            current_token_bids: all_current_token_bids,
+            token_bid_fills: all_token_bid_fills,
+            token_auction_bids: all_token_auction_bids,
+            truncated_strings: all_truncated_strings,
+            // current_daily_collection_volumes: all_current_daily_collection_volumes,
+            // current_weekly_collection_volumes: all_current_weekly_collection_volumes,
+            // current_monthly_collection_volumes: all_current_monthly_collection_volumes,
+        })
+    }
+}
+
+impl TokenTransactionProcessor {
+    /// Parses the transactions and builds every row their batch writes, in version order. It
+    /// reads the db, for prices, creators and rows from earlier batches, but doesn't write to it,
+    /// so it also runs on a single transaction outside of a batch, see `debug-transaction`. Both
+    /// the parsing and the db reads run on the blocking thread pool, which hands the connection
+    /// back along with the rows.
+    pub async fn build_rows(
+        &self,
+        mut conn: PgPoolConnection,
+        transactions: Vec<Transaction>,
+    ) -> anyhow::Result<(PgPoolConnection, TokenBatchRows)> {
+        let row_builder = self.row_builder.clone();
+        run_blocking(move || {
+            let rows = row_builder.build_rows(&mut conn, &transactions)?;
+            Ok((conn, rows))
+        })
+        .await
+    }
+
+    /// Writes rows built by build_rows in a single db transaction, without a checkpoint or any
+    /// processor status
+    pub fn write_rows(
+        &self,
+        conn: &mut PgPoolConnection,
+        rows: TokenBatchRows,
+        start_version: u64,
+        end_version: u64,
+    ) -> anyhow::Result<()> {
+        if let Some(activity_partitions) = &self.activity_partitions {
+            if self.tables.is_enabled("token_activities") {
+                activity_partitions.create_missing(conn, &rows.token_activities)?;
+            }
+        }
+        insert_to_db(
+            conn,
             self.name(),
             start_version,
             end_version,
-            (
-                all_tokens,
-                all_token_ownerships,
-                all_token_datas,
-                all_collection_datas,
-            ),
-            (
-                all_current_token_ownerships,
-                all_current_token_datas,
-                all_current_collection_datas,
+            &self.tables,
+            None,
+            rows,
+        )?;
+        Ok(())
+    }
+
+    /// Parses and writes the batch. With a checkpoint, it's committed along with the rows and a
+    /// batch that already committed is skipped, per shard when the batch is written in shards.
+    async fn process_batch(
+        &self,
+        transactions: Vec<Transaction>,
+        start_version: u64,
+        end_version: u64,
+        checkpoint: Option<BatchCheckpoint>,
+    ) -> Result<ProcessingResult, TransactionProcessingError> {
+        // Genesis has no timestamp, so it doesn't date a snapshot
+        let batch_timestamp = transactions
+            .last()
+            .map(|txn| txn.timestamp())
+            .filter(|timestamp| *timestamp > 0)
+            .map(|timestamp| parse_timestamp(timestamp, end_version as i64));
+
+        let conn = self.get_conn_async().await;
+        let (mut conn, rows) = match self.build_rows(conn, transactions).await {
+            Ok(conn_and_rows) => conn_and_rows,
+            Err(err) => {
+                return Err(TransactionProcessingError::TransactionCommitError((
+                    err,
+                    start_version,
+                    end_version,
+                    self.name(),
+                )));
+            }
+        };
+
+        // Creating a partition locks token_activities, so it's done before the batch's transaction
+        if let Some(activity_partitions) = &self.activity_partitions {
+            if self.tables.is_enabled("token_activities") {
+                if let Err(err) =
+                    activity_partitions.create_missing(&mut conn, &rows.token_activities)
+                {
+                    return Err(TransactionProcessingError::TransactionCommitError((
+                        anyhow::Error::from(err),
+                        start_version,
+                        end_version,
+                        self.name(),
+                    )));
+                }
+            }
+        }
+
+        // Built before the rows move to their shards, and published once they're committed
+        let live_messages = match &self.live_feed {
+            Some(live_feed) if live_feed.has_subscribers() => LiveFeed::batch_messages(
+                &rows.nft_sales,
+                &rows.current_marketplace_listings,
+                &rows.token_activities,
             ),
-            all_token_activities,
-            all_current_token_claims,
-            all_current_ans_lookups,
-            all_current_marketplace_listings,
-            all_current_collection_volumes,
-            all_collection_volumes,
-            all_current_token_volumes,
-            all_token_volumes,
-            // all_current_daily_collection_volumes,
-            // all_current_weekly_collection_volumes,
-            // all_current_monthly_collection_volumes,
-        );
+            _ => vec![],
+        };
+        let parquet_rows = self.parquet_sink.as_ref().map(|_| CommittedRows {
+            token_activities: rows.token_activities.clone(),
+            nft_sales: rows.nft_sales.clone(),
+        });
+
+        // Each shard commits on its own connection, the first one reuses the parsing connection
+        let shards = rows.split(self.num_shards);
+        let num_shards = shards.len();
+        let mut shard_conns = vec![conn];
+        for _ in 1..num_shards {
+            shard_conns.push(self.get_conn_async().await);
+        }
+        let name = self.name();
+        let shard_results = join_all(shards.into_iter().zip(shard_conns).enumerate().map(
+            |(shard, (shard_rows, mut shard_conn))| {
+                let tables = self.tables.clone();
+                let shard_checkpoint = checkpoint.as_ref().map(|checkpoint| {
+                    if num_shards > 1 {
+                        checkpoint.for_shard(shard)
+                    } else {
+                        checkpoint.clone()
+                    }
+                });
+                run_blocking(move || {
+                    let tx_result = insert_to_db(
+                        &mut shard_conn,
+                        name,
+                        start_version,
+                        end_version,
+                        &tables,
+                        shard_checkpoint.as_ref(),
+                        shard_rows,
+                    );
+                    (shard_conn, tx_result)
+                })
+            },
+        ))
+        .await;
+
+        // The batch only counts as processed once every shard has committed. With a checkpoint,
+        // shards that did commit are skipped when the batch is retried, otherwise they're
+        // rewritten.
+        let mut first_conn = None;
+        let mut tx_result = Ok(());
+        for (shard, (shard_conn, shard_result)) in shard_results.into_iter().enumerate() {
+            if let Ok(false) = shard_result {
+                aptos_logger::warn!(
+                    start_version = start_version,
+                    end_version = end_version,
+                    shard = shard,
+                    "Skipped a batch that was already committed"
+                );
+            }
+            if let Err(err) = shard_result {
+                if self.num_shards > 1 {
+                    aptos_logger::error!(
+                        start_version = start_version,
+                        end_version = end_version,
+                        shard = shard,
+                        error = ?err,
+                        "Failed to commit a shard of the batch"
+                    );
+                }
+                if tx_result.is_ok() {
+                    tx_result = Err(err);
+                }
+            }
+            // The other shards' connections go back to the pool here
+            if shard == 0 {
+                first_conn = Some(shard_conn);
+            }
+        }
+        let mut conn = first_conn.expect("A batch always has a first shard");
         match tx_result {
-            Ok(_) => Ok(ProcessingResult::new(
-                self.name(),
-                start_version,
-                end_version,
-            )),
+            Ok(_) => {
+                if let Some(live_feed) = &self.live_feed {
+                    live_feed.publish(live_messages);
+                }
+                if let (Some(parquet_sink), Some(parquet_rows)) = (&self.parquet_sink, parquet_rows)
+                {
+                    parquet_sink.send(parquet_rows);
+                }
+                self.reconcile_collection_volumes(&mut conn, end_version);
+                self.check_consistency(&mut conn, end_version);
+                self.refresh_collection_rarity(&mut conn, end_version);
+                self.refresh_price_medians(&mut conn, end_version);
+                self.snapshot_collection_stats(&mut conn, batch_timestamp);
+                self.refresh_leaderboards(&mut conn, batch_timestamp);
+                self.expire_offers(&mut conn, batch_timestamp);
+                let mut processing_result =
+                    ProcessingResult::new(self.name(), start_version, end_version);
+                // Sharded batches only committed the shards' checkpoints
+                processing_result.success_recorded = checkpoint.is_some() && num_shards == 1;
+                Ok(processing_result)
+            }
             Err(err) => Err(TransactionProcessingError::TransactionCommitError((
                 anyhow::Error::from(err),
                 start_version,
@@ -853,8 +3693,1316 @@ impl TransactionProcessor for TokenTransactionProcessor {
             ))),
         }
     }
+}
+
+#[async_trait]
+impl TransactionProcessor for TokenTransactionProcessor {
+    fn name(&self) -> &'static str {
+        NAME
+    }
+
+    async fn process_transactions(
+        &self,
+        transactions: Vec<Transaction>,
+        start_version: u64,
+        end_version: u64,
+    ) -> Result<ProcessingResult, TransactionProcessingError> {
+        self.process_batch(transactions, start_version, end_version, None)
+            .await
+    }
+
+    async fn process_transactions_with_checkpoint(
+        &self,
+        transactions: Vec<Transaction>,
+        checkpoint: BatchCheckpoint,
+    ) -> Result<ProcessingResult, TransactionProcessingError> {
+        let (start_version, end_version) = (checkpoint.start_version, checkpoint.end_version);
+        self.process_batch(transactions, start_version, end_version, Some(checkpoint))
+            .await
+    }
 
     fn connection_pool(&self) -> &PgDbPool {
         &self.connection_pool
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_utils::timestamp;
+    use crate::{
+        database::{
+            new_db_pool_with_settings, ConnectionSettings, ConnectionTimeouts, PgPool,
+            PostgresSchema,
+        },
+        indexer::tailer::{test::wipe_database, MIGRATIONS},
+        migrations::prepare_schema,
+        schema::{
+            collection_daily_reports, collection_price_candles, current_collection_volumes,
+            current_marketplace_listings, current_wallet_nft_stats, nft_sales, token_activities,
+        },
+    };
+    use bigdecimal::BigDecimal;
+    use diesel::{r2d2::ConnectionManager, SelectableHelper};
+    use diesel_migrations::MigrationHarness;
+
+    const FIXTURE_DIR: &str = concat!(env!("CARGO_MANIFEST_DIR"), "/fixtures/golden/transactions");
+
+    /// Token processor on a small pool against a wiped and migrated database. Like the other
+    /// postgres tests, this only runs when INDEXER_DATABASE_URL is set. The pool has room for
+    /// every shard and one connection for the test.
+    fn setup(num_shards: usize) -> (PgDbPool, TokenTransactionProcessor) {
+        let database_url = std::env::var("INDEXER_DATABASE_URL")
+            .expect("must set 'INDEXER_DATABASE_URL' to run tests!");
+        let conn_pool = Arc::new(
+            PgPool::builder()
+                .max_size(num_shards as u32 + 1)
+                .build(ConnectionManager::<PgConnection>::new(database_url))
+                .unwrap(),
+        );
+        wipe_database(&mut conn_pool.get().unwrap());
+        conn_pool
+            .get()
+            .unwrap()
+            .run_pending_migrations(MIGRATIONS)
+            .unwrap();
+        let processor = new_processor(conn_pool.clone(), num_shards);
+        (conn_pool, processor)
+    }
+
+    fn new_processor(conn_pool: PgDbPool, num_shards: usize) -> TokenTransactionProcessor {
+        TokenTransactionProcessor::new(
+            conn_pool,
+            vec![],
+            AnsDomains::default(),
+            MarketplaceEventMappings::default(),
+            None,
+            None,
+            TransactionTracer::new(&[]),
+            None,
+            None,
+            None,
+            None,
+            false,
+            true,
+            TableHandleCache::from_config(None).unwrap(),
+            TokenTables::default(),
+            None,
+            num_shards,
+            None,
+            None,
+        )
+    }
+
+    fn fixture(name: &str) -> Transaction {
+        let path = format!("{}/{}.json", FIXTURE_DIR, name);
+        serde_json::from_str(&std::fs::read_to_string(path).unwrap()).unwrap()
+    }
+
+    async fn process(processor: &TokenTransactionProcessor, transactions: Vec<Transaction>) {
+        let start_version = transactions.first().unwrap().version().unwrap();
+        let end_version = transactions.last().unwrap().version().unwrap();
+        processor
+            .process_transactions(transactions, start_version, end_version)
+            .await
+            .unwrap();
+    }
+
+    fn load_activity_versions(conn: &mut PgPoolConnection) -> Vec<i64> {
+        token_activities::table
+            .select(token_activities::transaction_version)
+            .order(token_activities::transaction_version.asc())
+            .load(conn)
+            .unwrap()
+    }
+
+    /// (market_address, seller, last_transaction_version)
+    fn load_listings(conn: &mut PgPoolConnection) -> Vec<(String, String, i64)> {
+        current_marketplace_listings::table
+            .select((
+                current_marketplace_listings::market_address,
+                current_marketplace_listings::seller,
+                current_marketplace_listings::last_transaction_version,
+            ))
+            .load(conn)
+            .unwrap()
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn test_postgres_schemas_are_isolated() {
+        if crate::should_skip_pg_tests() {
+            return;
+        }
+        let database_url = std::env::var("INDEXER_DATABASE_URL")
+            .expect("must set 'INDEXER_DATABASE_URL' to run tests!");
+        let mut conn_pools = vec![];
+        for schema in ["indexer_test_mainnet", "indexer_test_testnet"] {
+            diesel::sql_query(format!("DROP SCHEMA IF EXISTS {} CASCADE", schema))
+                .execute(&mut PgConnection::establish(&database_url).unwrap())
+                .unwrap();
+            let settings = ConnectionSettings {
+                timeouts: ConnectionTimeouts::default(),
+                schema: PostgresSchema::from_config(Some(schema)).unwrap(),
+            };
+            prepare_schema(&database_url, &settings, true, false).unwrap();
+            conn_pools.push(new_db_pool_with_settings(&database_url, settings, 2).unwrap());
+        }
+        let mainnet = new_processor(conn_pools[0].clone(), 1);
+        let testnet = new_processor(conn_pools[1].clone(), 1);
+
+        process(&mainnet, vec![fixture("bluemove_list")]).await;
+        process(&testnet, vec![fixture("bluemove_buy")]).await;
+        assert_eq!(
+            load_activity_versions(&mut conn_pools[0].get().unwrap()),
+            vec![102]
+        );
+        assert_eq!(
+            load_activity_versions(&mut conn_pools[1].get().unwrap()),
+            vec![103]
+        );
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn test_replaying_batches() {
+        if crate::should_skip_pg_tests() {
+            return;
+        }
+        let (conn_pool, processor) = setup(1);
+        let mut conn = conn_pool.get().unwrap();
+        let batch = vec![fixture("bluemove_list"), fixture("bluemove_buy")];
+
+        process(&processor, batch.clone()).await;
+        assert_eq!(load_activity_versions(&mut conn), vec![102, 103]);
+        let listings = vec![("".to_string(), "".to_string(), 103)];
+        assert_eq!(load_listings(&mut conn), listings);
+        let volumes = load_volumes(&mut conn);
+        let wallet_stats = load_wallet_stats(&mut conn);
+
+        // Replaying the batch leaves the same rows, without adding its sales to the volumes or
+        // wallet stats again
+        process(&processor, batch).await;
+        assert_eq!(load_activity_versions(&mut conn), vec![102, 103]);
+        assert_eq!(load_listings(&mut conn), listings);
+        assert_eq!(load_volumes(&mut conn), volumes);
+        assert_eq!(load_wallet_stats(&mut conn), wallet_stats);
+
+        // An older version doesn't overwrite the listing it was followed by
+        process(&processor, vec![fixture("bluemove_list")]).await;
+        assert_eq!(load_activity_versions(&mut conn), vec![102, 103]);
+        assert_eq!(load_listings(&mut conn), listings);
+
+        // Activities from older versions are still added, without touching the listing, and
+        // their sales still count towards the wallet stats
+        process(&processor, vec![fixture("topaz_buy")]).await;
+        assert_eq!(
+            load_activity_versions(&mut conn),
+            vec![100, 100, 100, 102, 103]
+        );
+        assert_eq!(load_listings(&mut conn), listings);
+        let buy_count = load_wallet_stats(&mut conn)
+            .iter()
+            .map(|(_, buy_count, _)| buy_count)
+            .sum::<i64>();
+        assert_eq!(buy_count, count_sales_with_buyer(&mut conn));
+    }
+
+    /// (wallet_address, buy_count, sell_count)
+    fn load_wallet_stats(conn: &mut PgPoolConnection) -> Vec<(String, i64, i64)> {
+        current_wallet_nft_stats::table
+            .select((
+                current_wallet_nft_stats::wallet_address,
+                current_wallet_nft_stats::buy_count,
+                current_wallet_nft_stats::sell_count,
+            ))
+            .order(current_wallet_nft_stats::wallet_address.asc())
+            .load(conn)
+            .unwrap()
+    }
+
+    fn count_sales_with_buyer(conn: &mut PgPoolConnection) -> i64 {
+        nft_sales::table
+            .filter(nft_sales::buyer.is_not_null())
+            .filter(nft_sales::suspect_value.eq(false))
+            .count()
+            .get_result(conn)
+            .unwrap()
+    }
+
+    /// (coin_type, market_address, volume, sales_count) of every candle
+    fn load_candle_totals(conn: &mut PgPoolConnection) -> Vec<(String, String, BigDecimal, i64)> {
+        collection_price_candles::table
+            .select((
+                collection_price_candles::coin_type,
+                collection_price_candles::market_address,
+                collection_price_candles::volume,
+                collection_price_candles::sales_count,
+            ))
+            .order((
+                collection_price_candles::collection_data_id_hash.asc(),
+                collection_price_candles::coin_type.asc(),
+                collection_price_candles::market_address.asc(),
+                collection_price_candles::interval_start.asc(),
+            ))
+            .load(conn)
+            .unwrap()
+    }
+
+    /// (coin_type, market_address, volume, sales_count) of every daily report
+    fn load_daily_report_totals(
+        conn: &mut PgPoolConnection,
+    ) -> Vec<(String, String, BigDecimal, i64)> {
+        collection_daily_reports::table
+            .select((
+                collection_daily_reports::coin_type,
+                collection_daily_reports::market_address,
+                collection_daily_reports::volume,
+                collection_daily_reports::sales_count,
+            ))
+            .order((
+                collection_daily_reports::collection_data_id_hash.asc(),
+                collection_daily_reports::coin_type.asc(),
+                collection_daily_reports::market_address.asc(),
+                collection_daily_reports::report_date.asc(),
+            ))
+            .load(conn)
+            .unwrap()
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn test_replaying_sales_into_candles_and_reports() {
+        if crate::should_skip_pg_tests() {
+            return;
+        }
+        let (conn_pool, processor) = setup(1);
+        let mut conn = conn_pool.get().unwrap();
+        let batch = vec![fixture("topaz_buy"), fixture("bluemove_buy")];
+
+        process(&processor, batch.clone()).await;
+        let candles = load_candle_totals(&mut conn);
+        let reports = load_daily_report_totals(&mut conn);
+        assert!(!candles.is_empty());
+        assert!(!reports.is_empty());
+
+        // Replaying the batch, or an older transaction of it on its own, adds none of their
+        // sales again
+        process(&processor, batch).await;
+        process(&processor, vec![fixture("topaz_buy")]).await;
+        assert_eq!(load_candle_totals(&mut conn), candles);
+        assert_eq!(load_daily_report_totals(&mut conn), reports);
+    }
+
+    /// bluemove_list with the token delisted again later in the same transaction
+    fn list_and_delist() -> Transaction {
+        let path = format!("{}/bluemove_list.json", FIXTURE_DIR);
+        let mut transaction: serde_json::Value =
+            serde_json::from_str(&std::fs::read_to_string(path).unwrap()).unwrap();
+        let list = transaction["events"][0].clone();
+        let mut delist = list.clone();
+        delist["type"] = serde_json::json!(list["type"]
+            .as_str()
+            .unwrap()
+            .replace("ListEvent", "DelistEvent"));
+        delist["guid"]["creation_number"] = serde_json::json!("4");
+        delist["sequence_number"] = serde_json::json!("0");
+        delist["data"] = serde_json::json!({
+            "id": list["data"]["id"],
+            "seller_address": list["data"]["seller_address"],
+        });
+        transaction["events"] = serde_json::json!([list, delist]);
+        serde_json::from_value(transaction).unwrap()
+    }
+
+    /// (is_deleted, last_event_index)
+    fn load_listing_events(conn: &mut PgPoolConnection) -> Vec<(bool, Option<i64>)> {
+        current_marketplace_listings::table
+            .select((
+                current_marketplace_listings::is_deleted,
+                current_marketplace_listings::last_event_index,
+            ))
+            .load(conn)
+            .unwrap()
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn test_delisting_in_the_listing_transaction() {
+        if crate::should_skip_pg_tests() {
+            return;
+        }
+        let (conn_pool, processor) = setup(1);
+        let mut conn = conn_pool.get().unwrap();
+        let delisted = vec![(true, Some(1))];
+
+        process(&processor, vec![list_and_delist()]).await;
+        assert_eq!(load_listing_events(&mut conn), delisted);
+
+        // Replaying the batch leaves it delisted
+        process(&processor, vec![list_and_delist()]).await;
+        assert_eq!(load_listing_events(&mut conn), delisted);
+
+        // As does replaying only the listing event, which has the same version
+        process(&processor, vec![fixture("bluemove_list")]).await;
+        assert_eq!(load_listing_events(&mut conn), delisted);
+
+        // A listing written before event indexes were stored is still updated by its transaction
+        diesel::update(current_marketplace_listings::table)
+            .set(current_marketplace_listings::last_event_index.eq(None::<i64>))
+            .execute(&mut conn)
+            .unwrap();
+        process(&processor, vec![list_and_delist()]).await;
+        assert_eq!(load_listing_events(&mut conn), delisted);
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn test_sharded_batches() {
+        if crate::should_skip_pg_tests() {
+            return;
+        }
+        let (conn_pool, processor) = setup(4);
+        let mut conn = conn_pool.get().unwrap();
+        process(
+            &processor,
+            vec![
+                fixture("topaz_buy"),
+                fixture("bluemove_list"),
+                fixture("bluemove_buy"),
+            ],
+        )
+        .await;
+        assert_eq!(
+            load_activity_versions(&mut conn),
+            vec![100, 100, 100, 102, 103]
+        );
+        assert_eq!(
+            load_listings(&mut conn),
+            vec![("".to_string(), "".to_string(), 103)]
+        );
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn test_committed_batches_are_published() {
+        if crate::should_skip_pg_tests() {
+            return;
+        }
+        let (_conn_pool, mut processor) = setup(1);
+        let live_feed = LiveFeed::new(64);
+        processor.live_feed = Some(live_feed.clone());
+        let mut receiver = live_feed.subscribe();
+        process(
+            &processor,
+            vec![fixture("bluemove_list"), fixture("bluemove_buy")],
+        )
+        .await;
+
+        let mut messages = vec![];
+        while let Ok(message) = receiver.try_recv() {
+            messages.push((message.message_type, message.transaction_version));
+        }
+        assert!(messages.contains(&("sale", 103)));
+        // The listing as of the end of the batch, after the buy
+        assert!(messages.contains(&("listing", 103)));
+        assert!(!messages.contains(&("listing", 102)));
+    }
+
+    fn load_volumes(conn: &mut PgPoolConnection) -> Vec<BigDecimal> {
+        current_collection_volumes::table
+            .select(current_collection_volumes::volume)
+            .order(current_collection_volumes::collection_data_id_hash.asc())
+            .load(conn)
+            .unwrap()
+    }
+
+    #[test]
+    fn test_volume_watermarks() {
+        if crate::should_skip_pg_tests() {
+            return;
+        }
+        let (conn_pool, _) = setup(1);
+        let mut conn = conn_pool.get().unwrap();
+        let sale = |version: i64, event_index: Option<i64>| CurrentCollectionVolume {
+            collection_data_id_hash: "potions".to_string(),
+            volume: BigDecimal::from(10),
+            inserted_at: timestamp(),
+            last_transaction_version: version,
+            last_transaction_timestamp: timestamp(),
+            primary_volume: BigDecimal::from(0),
+            secondary_volume: BigDecimal::from(10),
+            volume_usd: None,
+            volume_decimal: None,
+            last_event_index: event_index,
+        };
+
+        insert_current_collection_volumes(&mut conn, &[sale(5, Some(1))], true).unwrap();
+        // The same sale again, or an earlier one of its transaction, was already counted
+        insert_current_collection_volumes(&mut conn, &[sale(5, Some(1))], true).unwrap();
+        insert_current_collection_volumes(&mut conn, &[sale(5, Some(0))], true).unwrap();
+        assert_eq!(load_volumes(&mut conn), vec![BigDecimal::from(10)]);
+        // A later event of the same transaction wasn't
+        insert_current_collection_volumes(&mut conn, &[sale(5, Some(2))], true).unwrap();
+        assert_eq!(load_volumes(&mut conn), vec![BigDecimal::from(20)]);
+        insert_current_collection_volumes(&mut conn, &[sale(6, Some(0))], true).unwrap();
+        assert_eq!(load_volumes(&mut conn), vec![BigDecimal::from(30)]);
+        let watermark = current_collection_volumes::table
+            .select((
+                current_collection_volumes::last_transaction_version,
+                current_collection_volumes::last_event_index,
+            ))
+            .first::<(i64, Option<i64>)>(&mut conn)
+            .unwrap();
+        assert_eq!(watermark, (6, Some(0)));
+
+        // Rows counted before the watermark cover every event of their version
+        diesel::update(current_collection_volumes::table)
+            .set(current_collection_volumes::last_event_index.eq(None::<i64>))
+            .execute(&mut conn)
+            .unwrap();
+        insert_current_collection_volumes(&mut conn, &[sale(6, Some(3))], true).unwrap();
+        assert_eq!(load_volumes(&mut conn), vec![BigDecimal::from(30)]);
+        insert_current_collection_volumes(&mut conn, &[sale(7, Some(0))], true).unwrap();
+        assert_eq!(load_volumes(&mut conn), vec![BigDecimal::from(40)]);
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn test_committed_batches_are_skipped() {
+        if crate::should_skip_pg_tests() {
+            return;
+        }
+        for num_shards in [1, 2] {
+            let (conn_pool, processor) = setup(num_shards);
+            let mut conn = conn_pool.get().unwrap();
+            let batch = vec![fixture("bluemove_list"), fixture("bluemove_buy")];
+            let checkpoint = BatchCheckpoint::new(NAME, 102, 103);
+
+            let result = processor
+                .process_transactions_with_checkpoint(batch.clone(), checkpoint.clone())
+                .await
+                .unwrap();
+            assert_eq!(result.success_recorded, num_shards == 1);
+            let volumes = load_volumes(&mut conn);
+            assert!(!volumes.is_empty());
+
+            // Retrying the committed batch doesn't add its volume again
+            processor
+                .process_transactions_with_checkpoint(batch, checkpoint)
+                .await
+                .unwrap();
+            assert_eq!(load_volumes(&mut conn), volumes);
+            assert_eq!(load_activity_versions(&mut conn), vec![102, 103]);
+        }
+    }
+
+    #[test]
+    fn test_parse_transactions_keeps_order() {
+        let transactions = vec![
+            fixture("topaz_buy"),
+            fixture("bluemove_list"),
+            fixture("bluemove_buy"),
+        ];
+        let mappings = MarketplaceEventMappings::default();
+        let activity_keys = |activities: &[TokenActivity]| {
+            activities
+                .iter()
+                .map(|activity| (activity.transaction_version, activity.event_index))
+                .collect::<Vec<_>>()
+        };
+        let serial = transactions
+            .iter()
+            .flat_map(|txn| {
+                let token_events = TokenEvents::from_transaction(txn).unwrap();
+                activity_keys(&TokenActivity::from_transaction(
+                    txn,
+                    &token_events,
+                    &mappings,
+                ))
+            })
+            .collect::<Vec<_>>();
+        let parallel = parse_transactions(&transactions, &mappings, &[], false)
+            .unwrap()
+            .iter()
+            .flat_map(|parsed| activity_keys(&parsed.token_activities))
+            .collect::<Vec<_>>();
+        assert!(!serial.is_empty());
+        assert_eq!(parallel, serial);
+    }
+
+    #[test]
+    fn test_parse_transactions_fails_on_malformed_event() {
+        // bluemove_buy with a BuyEvent that doesn't deserialize
+        let mut malformed = serde_json::to_value(fixture("bluemove_buy")).unwrap();
+        malformed["events"][0]["data"] = serde_json::json!({ "id": "not a token id" });
+        let transactions = vec![
+            fixture("bluemove_list"),
+            serde_json::from_value(malformed).unwrap(),
+        ];
+        let err = parse_transactions(
+            &transactions,
+            &MarketplaceEventMappings::default(),
+            &[],
+            false,
+        )
+        .err()
+        .unwrap();
+        assert!(err.to_string().contains("transaction 103"));
+    }
+
+    fn collection_mint(collection_data_id_hash: &str, transaction_version: i64) -> CollectionMint {
+        CollectionMint {
+            transaction_version,
+            event_index: 0,
+            token_data_id_hash: "token".to_string(),
+            collection_data_id_hash: collection_data_id_hash.to_string(),
+            creator_address: "0xcafe".to_string(),
+            collection_name: "collection".to_string(),
+            name: "token".to_string(),
+            minter_address: "0xbeef".to_string(),
+            amount: BigDecimal::from(1),
+            price: BigDecimal::from(0),
+            is_price_unknown: true,
+            transaction_timestamp: timestamp(),
+            launchpad: None,
+        }
+    }
+
+    #[test]
+    fn test_split_by_collection() {
+        let collections = ["a", "b", "c", "d", "e", "f"];
+        let rows = TokenBatchRows {
+            collection_mints: (0..24)
+                .map(|version| collection_mint(collections[version % 6], version as i64))
+                .collect(),
+            current_wallet_nft_stats: vec![CurrentWalletNftStat {
+                wallet_address: "0xbeef".to_string(),
+                buy_count: 1,
+                sell_count: 0,
+                buy_volume: BigDecimal::from(1),
+                sell_volume: BigDecimal::from(0),
+                first_trade_version: 0,
+                last_trade_version: 0,
+                realized_pnl: BigDecimal::from(0),
+            }],
+            ..TokenBatchRows::default()
+        };
+        let shards = rows.split(3);
+        assert_eq!(shards.len(), 3);
+        assert_eq!(shards[0].current_wallet_nft_stats.len(), 1);
+
+        let mut num_mints = 0;
+        for (shard, shard_rows) in shards.iter().enumerate() {
+            for mint in &shard_rows.collection_mints {
+                assert_eq!(collection_shard(&mint.collection_data_id_hash, 3), shard);
+            }
+            // Versions stay in order within a shard
+            let versions = shard_rows
+                .collection_mints
+                .iter()
+                .map(|mint| mint.transaction_version)
+                .collect::<Vec<_>>();
+            let mut sorted_versions = versions.clone();
+            sorted_versions.sort_unstable();
+            assert_eq!(versions, sorted_versions);
+            num_mints += versions.len();
+        }
+        assert_eq!(num_mints, 24);
+    }
+
+    #[test]
+    fn test_dedup_token_activities() {
+        let mappings = MarketplaceEventMappings::default();
+        let activities = |name: &str| {
+            let txn = fixture(name);
+            let token_events = TokenEvents::from_transaction(&txn).unwrap();
+            TokenActivity::from_transaction(&txn, &token_events, &mappings)
+        };
+        let mut expected = activities("topaz_buy")
+            .iter()
+            .chain(activities("bluemove_list").iter())
+            .map(TokenActivity::pk)
+            .collect::<Vec<TokenActivityPK>>();
+        expected.sort();
+        expected.dedup();
+        assert_eq!(expected.len(), 4);
+
+        // Both transactions fed twice, newest first
+        let mut batch = activities("bluemove_list");
+        batch.extend(activities("topaz_buy"));
+        batch.extend(activities("bluemove_list"));
+        batch.extend(activities("topaz_buy"));
+        let deduped = dedup_token_activities(batch);
+        assert_eq!(
+            deduped.iter().map(TokenActivity::pk).collect::<Vec<_>>(),
+            expected
+        );
+    }
+
+    /// Builds rows from pk tuples in a scrambled order, sorts them, and checks that they come back
+    /// in the order of the tuples. `keys` has to be strictly increasing and is checked as well.
+    fn assert_sorted_by_pk<T, K: Clone + std::fmt::Debug + PartialOrd>(
+        keys: Vec<K>,
+        to_row: impl Fn(K) -> T,
+        sort: fn(&mut [T]),
+        pk: impl Fn(&T) -> K,
+    ) {
+        assert!(keys.windows(2).all(|pair| pair[0] < pair[1]));
+        // Deterministic shuffle, fine as long as the length isn't a multiple of 7
+        assert_ne!(keys.len() % 7, 0);
+        let mut rows: Vec<T> = (0..keys.len())
+            .map(|i| to_row(keys[(i * 7 + 3) % keys.len()].clone()))
+            .collect();
+        sort(&mut rows);
+        assert_eq!(rows.iter().map(pk).collect::<Vec<K>>(), keys);
+    }
+
+    fn hashes() -> Vec<String> {
+        vec!["0x1a".to_string(), "0x1b".to_string(), "0x2a".to_string()]
+    }
+
+    /// Compares numerically, so 10 goes after 2
+    fn property_versions() -> Vec<BigDecimal> {
+        vec![0, 2, 10].into_iter().map(BigDecimal::from).collect()
+    }
+
+    fn addresses() -> Vec<String> {
+        vec!["0x1".to_string(), "0x2".to_string()]
+    }
+
+    #[test]
+    fn test_sort_current_token_ownerships() {
+        let mut keys = vec![];
+        for hash in hashes() {
+            for property_version in property_versions() {
+                for owner_address in addresses() {
+                    keys.push((hash.clone(), property_version.clone(), owner_address));
+                }
+            }
+        }
+        assert_sorted_by_pk(
+            keys,
+            |(token_data_id_hash, property_version, owner_address)| CurrentTokenOwnership {
+                token_data_id_hash,
+                property_version,
+                owner_address,
+                creator_address: "0xcafe".to_string(),
+                collection_name: "collection".to_string(),
+                name: "token".to_string(),
+                amount: BigDecimal::from(1),
+                token_properties: serde_json::Value::Null,
+                last_transaction_version: 1,
+                collection_data_id_hash: "collection".to_string(),
+                table_type: "0x3::token::TokenStore".to_string(),
+                last_transaction_timestamp: timestamp(),
+                owner_type: "user".to_string(),
+                beneficial_owner: None,
+                is_deleted: false,
+            },
+            sort_current_token_ownerships,
+            |row| {
+                (
+                    row.token_data_id_hash.clone(),
+                    row.property_version.clone(),
+                    row.owner_address.clone(),
+                )
+            },
+        );
+    }
+
+    #[test]
+    fn test_sort_current_token_datas() {
+        assert_sorted_by_pk(
+            hashes(),
+            |token_data_id_hash| CurrentTokenData {
+                token_data_id_hash,
+                creator_address: "0xcafe".to_string(),
+                collection_name: "collection".to_string(),
+                name: "token".to_string(),
+                maximum: BigDecimal::from(1),
+                supply: BigDecimal::from(1),
+                largest_property_version: BigDecimal::from(0),
+                metadata_uri: "".to_string(),
+                payee_address: "0xcafe".to_string(),
+                royalty_points_numerator: BigDecimal::from(0),
+                royalty_points_denominator: BigDecimal::from(1),
+                maximum_mutable: false,
+                uri_mutable: false,
+                description_mutable: false,
+                properties_mutable: false,
+                royalty_mutable: false,
+                default_properties: serde_json::Value::Null,
+                last_transaction_version: 1,
+                collection_data_id_hash: "collection".to_string(),
+                last_transaction_timestamp: timestamp(),
+                description: "".to_string(),
+                metadata_uri_canonical: None,
+                uri_scheme: Some("empty".to_string()),
+            },
+            sort_current_token_datas,
+            |row| row.token_data_id_hash.clone(),
+        );
+    }
+
+    #[test]
+    fn test_sort_current_collection_datas() {
+        assert_sorted_by_pk(
+            hashes(),
+            |collection_data_id_hash| CurrentCollectionData {
+                collection_data_id_hash,
+                creator_address: "0xcafe".to_string(),
+                collection_name: "collection".to_string(),
+                description: "".to_string(),
+                metadata_uri: "".to_string(),
+                supply: BigDecimal::from(1),
+                maximum: BigDecimal::from(1),
+                maximum_mutable: false,
+                uri_mutable: false,
+                description_mutable: false,
+                last_transaction_version: 1,
+                table_handle: "0x7ab1e".to_string(),
+                last_transaction_timestamp: timestamp(),
+                is_verified: false,
+            },
+            sort_current_collection_datas,
+            |row| row.collection_data_id_hash.clone(),
+        );
+    }
+
+    #[test]
+    fn test_sort_current_token_claims() {
+        let mut keys = vec![];
+        for hash in hashes() {
+            for property_version in property_versions() {
+                for from_address in addresses() {
+                    // Rows that only differ by to_address still need a total order
+                    for to_address in addresses() {
+                        keys.push((
+                            hash.clone(),
+                            property_version.clone(),
+                            from_address.clone(),
+                            to_address,
+                        ));
+                    }
+                }
+            }
+        }
+        assert_sorted_by_pk(
+            keys,
+            |(token_data_id_hash, property_version, from_address, to_address)| {
+                CurrentTokenPendingClaim {
+                    token_data_id_hash,
+                    property_version,
+                    from_address,
+                    to_address,
+                    collection_data_id_hash: "collection".to_string(),
+                    creator_address: "0xcafe".to_string(),
+                    collection_name: "collection".to_string(),
+                    name: "token".to_string(),
+                    amount: BigDecimal::from(1),
+                    table_handle: "0x7ab1e".to_string(),
+                    last_transaction_version: 1,
+                    last_transaction_timestamp: timestamp(),
+                    is_deleted: false,
+                }
+            },
+            sort_current_token_claims,
+            |row| {
+                (
+                    row.token_data_id_hash.clone(),
+                    row.property_version.clone(),
+                    row.from_address.clone(),
+                    row.to_address.clone(),
+                )
+            },
+        );
+    }
+
+    #[test]
+    fn test_sort_current_ans_lookups() {
+        let mut keys = vec![];
+        for domain in ["aptos", "bob", "bobby"] {
+            for subdomain in ["", "pay", "wallet"] {
+                keys.push((domain.to_string(), subdomain.to_string()));
+            }
+        }
+        assert_sorted_by_pk(
+            keys,
+            |(domain, subdomain)| CurrentAnsLookup {
+                domain,
+                subdomain,
+                registered_address: None,
+                last_transaction_version: 1,
+                expiration_timestamp: timestamp(),
+                is_deleted: false,
+            },
+            sort_current_ans_lookups,
+            |row| (row.domain.clone(), row.subdomain.clone()),
+        );
+    }
+
+    #[test]
+    fn test_sort_current_ans_primary_names() {
+        assert_sorted_by_pk(
+            hashes(),
+            |registered_address| CurrentAnsPrimaryName {
+                registered_address,
+                domain: None,
+                subdomain: None,
+                last_transaction_version: 1,
+            },
+            sort_current_ans_primary_names,
+            |row| row.registered_address.clone(),
+        );
+    }
+
+    #[test]
+    fn test_sort_current_marketplace_listings() {
+        assert_sorted_by_pk(
+            hashes(),
+            |token_data_id_hash| CurrentMarketplaceListing {
+                collection_data_id_hash: "collection".to_string(),
+                market_address: "0xbeef".to_string(),
+                token_data_id_hash,
+                property_version: BigDecimal::from(0),
+                creator_address: "0xcafe".to_string(),
+                collection_name: "collection".to_string(),
+                name: "token".to_string(),
+                seller: "0x1".to_string(),
+                amount: BigDecimal::from(1),
+                price: BigDecimal::from(100),
+                event_type: "list".to_string(),
+                inserted_at: timestamp(),
+                last_transaction_version: 1,
+                last_transaction_timestamp: timestamp(),
+                invalidated_reason: None,
+                price_decimal: None,
+                is_deleted: false,
+                last_event_index: Some(0),
+            },
+            sort_current_marketplace_listings,
+            |row| row.token_data_id_hash.clone(),
+        );
+    }
+
+    #[test]
+    fn test_sort_current_volumes() {
+        assert_sorted_by_pk(
+            hashes(),
+            |collection_data_id_hash| CurrentCollectionVolume {
+                collection_data_id_hash,
+                volume: BigDecimal::from(100),
+                inserted_at: timestamp(),
+                last_transaction_version: 1,
+                last_transaction_timestamp: timestamp(),
+                primary_volume: BigDecimal::from(0),
+                secondary_volume: BigDecimal::from(100),
+                volume_usd: None,
+                volume_decimal: None,
+                last_event_index: Some(0),
+            },
+            sort_current_collection_volumes,
+            |row| row.collection_data_id_hash.clone(),
+        );
+        assert_sorted_by_pk(
+            hashes(),
+            |token_data_id_hash| CurrentTokenVolume {
+                token_data_id_hash,
+                volume: BigDecimal::from(100),
+                inserted_at: timestamp(),
+                last_transaction_version: 1,
+                last_transaction_timestamp: timestamp(),
+                volume_decimal: None,
+                last_event_index: Some(0),
+            },
+            sort_current_token_volumes,
+            |row| row.token_data_id_hash.clone(),
+        );
+    }
+
+    /// Rows are compared serialized, the models don't implement PartialEq
+    fn assert_same_rows<T: serde::Serialize>(inserted: &[T], loaded: Vec<T>) {
+        assert_eq!(
+            serde_json::to_value(&loaded).unwrap(),
+            serde_json::to_value(inserted).unwrap()
+        );
+    }
+
+    #[test]
+    fn test_deleted_rows_come_back() {
+        if crate::should_skip_pg_tests() {
+            return;
+        }
+        let (conn_pool, _) = setup(1);
+        let mut conn = conn_pool.get().unwrap();
+
+        // Burned at version 7, then a later version gives the owner the token again
+        let ownership = |version: i64, amount: i64| CurrentTokenOwnership {
+            token_data_id_hash: "token".to_string(),
+            property_version: BigDecimal::from(0),
+            owner_address: "0x1".to_string(),
+            creator_address: "0xcafe".to_string(),
+            collection_name: "collection".to_string(),
+            name: "name".to_string(),
+            amount: BigDecimal::from(amount),
+            token_properties: serde_json::Value::Null,
+            last_transaction_version: version,
+            collection_data_id_hash: "collection_hash".to_string(),
+            table_type: "0x3::token::TokenStore".to_string(),
+            last_transaction_timestamp: timestamp(),
+            owner_type: "user".to_string(),
+            beneficial_owner: None,
+            is_deleted: amount == 0,
+        };
+        let load_ownership = |conn: &mut PgPoolConnection| {
+            schema::current_token_ownerships::table
+                .select(CurrentTokenOwnership::as_select())
+                .first(conn)
+                .unwrap()
+        };
+        insert_current_token_ownerships(&mut conn, &[ownership(7, 0)]).unwrap();
+        assert!(load_ownership(&mut conn).is_deleted);
+        insert_current_token_ownerships(&mut conn, &[ownership(8, 1)]).unwrap();
+        let stored = load_ownership(&mut conn);
+        assert!(!stored.is_deleted);
+        assert_eq!(stored.amount, BigDecimal::from(1));
+        // Replaying the burn doesn't delete it again
+        insert_current_token_ownerships(&mut conn, &[ownership(7, 0)]).unwrap();
+        assert!(!load_ownership(&mut conn).is_deleted);
+
+        // A removed ans name registered again
+        let ans_lookup = |version: i64, is_deleted: bool| CurrentAnsLookup {
+            domain: "bob".to_string(),
+            subdomain: "".to_string(),
+            registered_address: (!is_deleted).then(|| "0x1".to_string()),
+            last_transaction_version: version,
+            expiration_timestamp: timestamp(),
+            is_deleted,
+        };
+        insert_current_ans_lookups(&mut conn, &[ans_lookup(7, true)]).unwrap();
+        insert_current_ans_lookups(&mut conn, &[ans_lookup(8, false)]).unwrap();
+        let stored = schema::current_ans_lookup::table
+            .select(CurrentAnsLookup::as_select())
+            .first(&mut conn)
+            .unwrap();
+        assert!(!stored.is_deleted);
+        assert_eq!(stored.registered_address, Some("0x1".to_string()));
+    }
+
+    // Fields of the same type get different values, so loading them into the wrong field fails
+    #[test]
+    fn test_current_models_round_trip() {
+        if crate::should_skip_pg_tests() {
+            return;
+        }
+        let (conn_pool, _) = setup(1);
+        let mut conn = conn_pool.get().unwrap();
+
+        let ownerships = vec![CurrentTokenOwnership {
+            token_data_id_hash: "token".to_string(),
+            property_version: BigDecimal::from(3),
+            owner_address: "0x1".to_string(),
+            creator_address: "0xcafe".to_string(),
+            collection_name: "collection".to_string(),
+            name: "name".to_string(),
+            amount: BigDecimal::from(2),
+            token_properties: serde_json::json!({"level": "1"}),
+            last_transaction_version: 7,
+            collection_data_id_hash: "collection_hash".to_string(),
+            table_type: "0x3::token::TokenStore".to_string(),
+            last_transaction_timestamp: timestamp(),
+            owner_type: "user".to_string(),
+            beneficial_owner: None,
+            is_deleted: false,
+        }];
+        insert_current_token_ownerships(&mut conn, &ownerships).unwrap();
+        assert_same_rows(
+            &ownerships,
+            schema::current_token_ownerships::table
+                .select(CurrentTokenOwnership::as_select())
+                .load(&mut conn)
+                .unwrap(),
+        );
+
+        let token_datas = vec![CurrentTokenData {
+            token_data_id_hash: "token".to_string(),
+            creator_address: "0xcafe".to_string(),
+            collection_name: "collection".to_string(),
+            name: "name".to_string(),
+            maximum: BigDecimal::from(100),
+            supply: BigDecimal::from(10),
+            largest_property_version: BigDecimal::from(3),
+            metadata_uri: "https://token".to_string(),
+            payee_address: "0xbeef".to_string(),
+            royalty_points_numerator: BigDecimal::from(5),
+            royalty_points_denominator: BigDecimal::from(1000),
+            maximum_mutable: true,
+            uri_mutable: false,
+            description_mutable: true,
+            properties_mutable: false,
+            royalty_mutable: true,
+            default_properties: serde_json::json!({"level": "0"}),
+            last_transaction_version: 7,
+            collection_data_id_hash: "collection_hash".to_string(),
+            last_transaction_timestamp: timestamp(),
+            description: "description".to_string(),
+            metadata_uri_canonical: Some("https://token/".to_string()),
+            uri_scheme: Some("https".to_string()),
+        }];
+        insert_current_token_datas(&mut conn, &token_datas).unwrap();
+        assert_same_rows(
+            &token_datas,
+            schema::current_token_datas::table
+                .select(CurrentTokenData::as_select())
+                .load(&mut conn)
+                .unwrap(),
+        );
+
+        let collection_datas = vec![CurrentCollectionData {
+            collection_data_id_hash: "collection_hash".to_string(),
+            creator_address: "0xcafe".to_string(),
+            collection_name: "collection".to_string(),
+            description: "description".to_string(),
+            metadata_uri: "https://collection".to_string(),
+            supply: BigDecimal::from(10),
+            maximum: BigDecimal::from(100),
+            maximum_mutable: true,
+            uri_mutable: false,
+            description_mutable: true,
+            last_transaction_version: 7,
+            table_handle: "0x7ab1e".to_string(),
+            last_transaction_timestamp: timestamp(),
+            is_verified: true,
+        }];
+        insert_current_collection_datas(&mut conn, &collection_datas).unwrap();
+        assert_same_rows(
+            &collection_datas,
+            schema::current_collection_datas::table
+                .select(CurrentCollectionData::as_select())
+                .load(&mut conn)
+                .unwrap(),
+        );
+
+        let claims = vec![CurrentTokenPendingClaim {
+            token_data_id_hash: "token".to_string(),
+            property_version: BigDecimal::from(3),
+            from_address: "0x1".to_string(),
+            to_address: "0x2".to_string(),
+            collection_data_id_hash: "collection_hash".to_string(),
+            creator_address: "0xcafe".to_string(),
+            collection_name: "collection".to_string(),
+            name: "name".to_string(),
+            amount: BigDecimal::from(2),
+            table_handle: "0x7ab1e".to_string(),
+            last_transaction_version: 7,
+            last_transaction_timestamp: timestamp(),
+            is_deleted: false,
+        }];
+        insert_current_token_claims(&mut conn, &claims).unwrap();
+        assert_same_rows(
+            &claims,
+            schema::current_token_pending_claims::table
+                .select(CurrentTokenPendingClaim::as_select())
+                .load(&mut conn)
+                .unwrap(),
+        );
+
+        let ans_lookups = vec![CurrentAnsLookup {
+            domain: "bob".to_string(),
+            subdomain: "pay".to_string(),
+            registered_address: Some("0x1".to_string()),
+            last_transaction_version: 7,
+            expiration_timestamp: timestamp(),
+            is_deleted: false,
+        }];
+        insert_current_ans_lookups(&mut conn, &ans_lookups).unwrap();
+        assert_same_rows(
+            &ans_lookups,
+            schema::current_ans_lookup::table
+                .select(CurrentAnsLookup::as_select())
+                .load(&mut conn)
+                .unwrap(),
+        );
+
+        let ans_primary_names = vec![CurrentAnsPrimaryName {
+            registered_address: "0x1".to_string(),
+            domain: Some("bob".to_string()),
+            subdomain: Some("pay".to_string()),
+            last_transaction_version: 7,
+        }];
+        insert_current_ans_primary_names(&mut conn, &ans_primary_names).unwrap();
+        assert_same_rows(
+            &ans_primary_names,
+            schema::current_ans_primary_name::table
+                .select(CurrentAnsPrimaryName::as_select())
+                .load(&mut conn)
+                .unwrap(),
+        );
+
+        let listings = vec![CurrentMarketplaceListing {
+            collection_data_id_hash: "collection_hash".to_string(),
+            market_address: "0xbeef".to_string(),
+            token_data_id_hash: "token".to_string(),
+            property_version: BigDecimal::from(3),
+            creator_address: "0xcafe".to_string(),
+            collection_name: "collection".to_string(),
+            name: "name".to_string(),
+            seller: "0x1".to_string(),
+            amount: BigDecimal::from(2),
+            price: BigDecimal::from(100),
+            event_type: "list".to_string(),
+            inserted_at: timestamp(),
+            last_transaction_version: 7,
+            invalidated_reason: Some("token_withdrawn".to_string()),
+            price_decimal: Some("0.000001".parse().unwrap()),
+            last_transaction_timestamp: timestamp() + chrono::Duration::seconds(1),
+            is_deleted: false,
+            last_event_index: Some(3),
+        }];
+        insert_current_marketplace_listings(&mut conn, &listings).unwrap();
+        assert_same_rows(
+            &listings,
+            schema::current_marketplace_listings::table
+                .select(CurrentMarketplaceListing::as_select())
+                .load(&mut conn)
+                .unwrap(),
+        );
+
+        let collection_volumes = vec![CurrentCollectionVolume {
+            collection_data_id_hash: "collection_hash".to_string(),
+            volume: BigDecimal::from(100),
+            inserted_at: timestamp(),
+            last_transaction_version: 7,
+            last_transaction_timestamp: timestamp() + chrono::Duration::seconds(1),
+            primary_volume: BigDecimal::from(40),
+            secondary_volume: BigDecimal::from(60),
+            volume_usd: Some(BigDecimal::from(640)),
+            volume_decimal: Some("0.000001".parse().unwrap()),
+            last_event_index: Some(3),
+        }];
+        insert_current_collection_volumes(&mut conn, &collection_volumes, false).unwrap();
+        assert_same_rows(
+            &collection_volumes,
+            schema::current_collection_volumes::table
+                .select(CurrentCollectionVolume::as_select())
+                .load(&mut conn)
+                .unwrap(),
+        );
+
+        let token_volumes = vec![CurrentTokenVolume {
+            token_data_id_hash: "token".to_string(),
+            volume: BigDecimal::from(100),
+            inserted_at: timestamp(),
+            last_transaction_version: 7,
+            last_transaction_timestamp: timestamp() + chrono::Duration::seconds(1),
+            volume_decimal: Some("0.000001".parse().unwrap()),
+            last_event_index: Some(3),
+        }];
+        insert_current_token_volumes(&mut conn, &token_volumes, false).unwrap();
+        assert_same_rows(
+            &token_volumes,
+            schema::current_token_volumes::table
+                .select(CurrentTokenVolume::as_select())
+                .load(&mut conn)
+                .unwrap(),
+        );
+
+        let holder_counts = vec![CurrentCollectionHolderCount {
+            collection_data_id_hash: "collection_hash".to_string(),
+            distinct_holders: 3,
+            total_tokens_held: BigDecimal::from(10),
+            last_transaction_version: 7,
+        }];
+        insert_current_collection_holder_counts(&mut conn, &holder_counts).unwrap();
+        assert_same_rows(
+            &holder_counts,
+            schema::current_collection_holder_counts::table
+                .select(CurrentCollectionHolderCount::as_select())
+                .load(&mut conn)
+                .unwrap(),
+        );
+
+        let mint_stats = vec![CurrentCollectionMintStat {
+            collection_data_id_hash: "collection_hash".to_string(),
+            total_minted: BigDecimal::from(10),
+            distinct_minters: 3,
+            mint_volume_apt: BigDecimal::from(50),
+            last_transaction_version: 7,
+            launchpad: Some("0xbeef".to_string()),
+        }];
+        insert_current_collection_mint_stats(&mut conn, &mint_stats).unwrap();
+        assert_same_rows(
+            &mint_stats,
+            schema::current_collection_mint_stats::table
+                .select(CurrentCollectionMintStat::as_select())
+                .load(&mut conn)
+                .unwrap(),
+        );
+
+        let wallet_stats = vec![CurrentWalletNftStat {
+            wallet_address: "0x1".to_string(),
+            buy_count: 2,
+            sell_count: 1,
+            buy_volume: BigDecimal::from(300),
+            sell_volume: BigDecimal::from(200),
+            first_trade_version: 3,
+            last_trade_version: 7,
+            realized_pnl: BigDecimal::from(-50),
+        }];
+        insert_current_wallet_nft_stats(&mut conn, &wallet_stats, false).unwrap();
+        assert_same_rows(
+            &wallet_stats,
+            schema::current_wallet_nft_stats::table
+                .select(CurrentWalletNftStat::as_select())
+                .load(&mut conn)
+                .unwrap(),
+        );
+
+        let offers = vec![CurrentCollectionOffer {
+            collection_data_id_hash: "collection_hash".to_string(),
+            buyer: "0x1".to_string(),
+            market_address: "0xbeef".to_string(),
+            bid_id: BigDecimal::from(9),
+            creator_address: "0xcafe".to_string(),
+            collection_name: "collection".to_string(),
+            price: BigDecimal::from(100),
+            amount_remaining: BigDecimal::from(2),
+            coin_type: "0x1::aptos_coin::AptosCoin".to_string(),
+            deadline: BigDecimal::from(1669000000),
+            expires_at: chrono::NaiveDateTime::from_timestamp(1669000000, 0),
+            status: "active".to_string(),
+            last_transaction_version: 7,
+            last_transaction_timestamp: timestamp(),
+        }];
+        insert_current_collection_offers(&mut conn, &offers).unwrap();
+        assert_same_rows(
+            &offers,
+            schema::current_collection_offers::table
+                .select(CurrentCollectionOffer::as_select())
+                .load(&mut conn)
+                .unwrap(),
+        );
+
+        let bids = vec![CurrentTokenBid {
+            token_data_id_hash: "token".to_string(),
+            property_version: BigDecimal::from(3),
+            buyer: "0x1".to_string(),
+            market_address: "0xbeef".to_string(),
+            collection_data_id_hash: "collection_hash".to_string(),
+            creator_address: "0xcafe".to_string(),
+            collection_name: "collection".to_string(),
+            name: "name".to_string(),
+            bid_id: Some(BigDecimal::from(9)),
+            price: BigDecimal::from(100),
+            amount_remaining: BigDecimal::from(2),
+            coin_type: "0x1::aptos_coin::AptosCoin".to_string(),
+            deadline: Some(BigDecimal::from(1669000000)),
+            expires_at: Some(chrono::NaiveDateTime::from_timestamp(1669000000, 0)),
+            status: "active".to_string(),
+            last_transaction_version: 7,
+            last_transaction_timestamp: timestamp(),
+        }];
+        insert_current_token_bids(&mut conn, &bids).unwrap();
+        assert_same_rows(
+            &bids,
+            schema::current_token_bids::table
+                .select(CurrentTokenBid::as_select())
+                .load(&mut conn)
+                .unwrap(),
+        );
+    }
+}
@@ -0,0 +1,908 @@
+// Copyright (c) Aptos
+// SPDX-License-Identifier: Apache-2.0
+
+//! Reads of the token tables for consumers of the indexer's database. These are the supported
+//! way to look up listings, activities, volumes and ownerships, instead of hand written SQL.
+
+use crate::{
+    models::token_models::{
+        account_token_activities::AccountTokenActivity,
+        collection_stats_snapshots::CollectionStatsSnapshot,
+        collection_volume::CurrentCollectionVolume, event_effects::TransferKind,
+        marketplace_listings::CurrentMarketplaceListing, token_activities::TokenActivity,
+        token_ownerships::CurrentTokenOwnership,
+    },
+    schema::{
+        account_token_activities, collection_stats_snapshots, collection_volumes,
+        current_collection_volumes, current_marketplace_listings, current_token_ownerships,
+        spam_collections, token_activities,
+    },
+    util::standardize_address,
+};
+use bigdecimal::{BigDecimal, Zero};
+use diesel::{
+    dsl::sql,
+    pg::Pg,
+    sql_types::{BigInt, Bool, Nullable, Numeric, Timestamp, VarChar},
+    BoolExpressionMethods, ExpressionMethods, OptionalExtension, PgConnection, QueryDsl,
+    QueryResult, RunQueryDsl, SelectableHelper,
+};
+use serde::{Deserialize, Serialize};
+
+/// Activities returned per call of get_token_activities
+pub const ACTIVITY_PAGE_SIZE: i64 = 100;
+
+/// Where the previous page of activities ended. Rows indexed before event_index was added all
+/// have event_index 0, so the event's guid is part of the cursor too. token_index tells apart the
+/// rows of a sweep event.
+#[derive(Clone, Debug, Deserialize, PartialEq, Eq, Serialize)]
+pub struct ActivityCursor {
+    pub transaction_version: i64,
+    pub event_index: i64,
+    pub event_account_address: String,
+    pub event_creation_number: i64,
+    pub event_sequence_number: i64,
+    pub token_index: i64,
+}
+
+impl From<&TokenActivity> for ActivityCursor {
+    fn from(activity: &TokenActivity) -> Self {
+        Self {
+            transaction_version: activity.transaction_version,
+            event_index: activity.event_index,
+            event_account_address: activity.event_account_address.clone(),
+            event_creation_number: activity.event_creation_number,
+            event_sequence_number: activity.event_sequence_number,
+            token_index: activity.token_index,
+        }
+    }
+}
+
+/// Where the previous page of listings ended
+#[derive(Clone, Debug, Deserialize, PartialEq, Eq, Serialize)]
+pub struct ListingCursor {
+    pub price: BigDecimal,
+    pub token_data_id_hash: String,
+}
+
+impl From<&CurrentMarketplaceListing> for ListingCursor {
+    fn from(listing: &CurrentMarketplaceListing) -> Self {
+        Self {
+            price: listing.price.clone(),
+            token_data_id_hash: listing.token_data_id_hash.clone(),
+        }
+    }
+}
+
+/// Where the previous page of an owner's tokens ended
+#[derive(Clone, Debug, Deserialize, PartialEq, Eq, Serialize)]
+pub struct OwnershipCursor {
+    pub last_transaction_version: i64,
+    pub token_data_id_hash: String,
+    pub property_version: BigDecimal,
+}
+
+impl From<&CurrentTokenOwnership> for OwnershipCursor {
+    fn from(ownership: &CurrentTokenOwnership) -> Self {
+        Self {
+            last_transaction_version: ownership.last_transaction_version,
+            token_data_id_hash: ownership.token_data_id_hash.clone(),
+            property_version: ownership.property_version.clone(),
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum VolumeWindow {
+    AllTime,
+    /// Sales within the duration before now. Only as complete as the pruned collection_volumes.
+    Last(chrono::Duration),
+}
+
+/// Listings that can still fill, cheapest first
+pub fn get_active_listings_by_collection(
+    conn: &mut PgConnection,
+    collection_hash: &str,
+    limit: i64,
+    offset: i64,
+) -> QueryResult<Vec<CurrentMarketplaceListing>> {
+    current_marketplace_listings::table
+        .filter(current_marketplace_listings::collection_data_id_hash.eq(collection_hash))
+        .filter(current_marketplace_listings::is_deleted.eq(false))
+        .filter(current_marketplace_listings::amount.gt(BigDecimal::zero()))
+        .filter(current_marketplace_listings::invalidated_reason.is_null())
+        .select(CurrentMarketplaceListing::as_select())
+        .order((
+            current_marketplace_listings::price.asc(),
+            current_marketplace_listings::token_data_id_hash.asc(),
+        ))
+        .limit(limit)
+        .offset(offset)
+        .load(conn)
+}
+
+/// Like get_active_listings_by_collection, a page at a time. Pass the cursor of the last listing
+/// returned to get the next page, which stays consistent while listings are added or removed.
+pub fn get_active_listings_page(
+    conn: &mut PgConnection,
+    collection_hash: &str,
+    cursor: Option<&ListingCursor>,
+    limit: i64,
+) -> QueryResult<Vec<CurrentMarketplaceListing>> {
+    let mut query = current_marketplace_listings::table
+        .filter(current_marketplace_listings::collection_data_id_hash.eq(collection_hash))
+        .filter(current_marketplace_listings::is_deleted.eq(false))
+        .filter(current_marketplace_listings::amount.gt(BigDecimal::zero()))
+        .filter(current_marketplace_listings::invalidated_reason.is_null())
+        .select(CurrentMarketplaceListing::as_select())
+        .into_boxed();
+    if let Some(cursor) = cursor {
+        query = query.filter(
+            current_marketplace_listings::price
+                .gt(cursor.price.clone())
+                .or(current_marketplace_listings::price
+                    .eq(cursor.price.clone())
+                    .and(
+                        current_marketplace_listings::token_data_id_hash
+                            .gt(cursor.token_data_id_hash.clone()),
+                    )),
+        );
+    }
+    query
+        .order((
+            current_marketplace_listings::price.asc(),
+            current_marketplace_listings::token_data_id_hash.asc(),
+        ))
+        .limit(limit)
+        .load(conn)
+}
+
+/// A page of the token's activities, newest first. Pass the cursor of the last activity returned
+/// to get the next page, a page shorter than ACTIVITY_PAGE_SIZE is the last one.
+pub fn get_token_activities(
+    conn: &mut PgConnection,
+    token_hash: &str,
+    cursor: Option<&ActivityCursor>,
+) -> QueryResult<Vec<TokenActivity>> {
+    let query = token_activities::table
+        .filter(token_activities::token_data_id_hash.eq(token_hash))
+        .select(TokenActivity::as_select())
+        .into_boxed();
+    activity_page(query, cursor).load(conn)
+}
+
+/// A page of the collection's activities of the given kinds, or of every kind if empty, newest
+/// first and paged like get_token_activities. Activities indexed before transfer_kind was added
+/// only match once backfill_transfer_kinds has filled it.
+pub fn get_collection_activities(
+    conn: &mut PgConnection,
+    collection_hash: &str,
+    kinds: &[TransferKind],
+    cursor: Option<&ActivityCursor>,
+) -> QueryResult<Vec<TokenActivity>> {
+    let mut query = token_activities::table
+        .filter(token_activities::collection_data_id_hash.eq(collection_hash))
+        .select(TokenActivity::as_select())
+        .into_boxed();
+    if !kinds.is_empty() {
+        query = query.filter(
+            token_activities::transfer_kind.eq_any(
+                kinds
+                    .iter()
+                    .map(|kind| kind.as_str().to_string())
+                    .collect::<Vec<_>>(),
+            ),
+        );
+    }
+    activity_page(query, cursor).load(conn)
+}
+
+/// A page of the activities the address sent or received tokens in, newest first, paged like
+/// get_token_activities
+pub fn get_account_activities(
+    conn: &mut PgConnection,
+    address: &str,
+    cursor: Option<&ActivityCursor>,
+) -> QueryResult<Vec<TokenActivity>> {
+    let address = standardize_address(address);
+    let query = token_activities::table
+        .filter(
+            token_activities::from_address
+                .eq(address.clone())
+                .or(token_activities::to_address.eq(address)),
+        )
+        .select(TokenActivity::as_select())
+        .into_boxed();
+    activity_page(query, cursor).load(conn)
+}
+
+/// get_account_activities from account_token_activities, which the token processor only
+/// writes with account_token_activities enabled. The account's rows are a range of the primary
+/// key in the feed's order, so a page doesn't have to merge its sent and received activities.
+pub fn get_account_token_activities(
+    conn: &mut PgConnection,
+    address: &str,
+    cursor: Option<&ActivityCursor>,
+) -> QueryResult<Vec<TokenActivity>> {
+    let mut query = account_token_activities::table
+        .filter(account_token_activities::account_address.eq(standardize_address(address)))
+        .select(AccountTokenActivity::as_select())
+        .into_boxed();
+    if let Some(cursor) = cursor {
+        query = query.filter(
+            sql::<Bool>(
+                "(transaction_version, event_index, event_account_address, \
+                event_creation_number, event_sequence_number, token_index) < (",
+            )
+            .bind::<BigInt, _>(cursor.transaction_version)
+            .sql(", ")
+            .bind::<BigInt, _>(cursor.event_index)
+            .sql(", ")
+            .bind::<VarChar, _>(cursor.event_account_address.clone())
+            .sql(", ")
+            .bind::<BigInt, _>(cursor.event_creation_number)
+            .sql(", ")
+            .bind::<BigInt, _>(cursor.event_sequence_number)
+            .sql(", ")
+            .bind::<BigInt, _>(cursor.token_index)
+            .sql(")"),
+        );
+    }
+    let rows = query
+        .order((
+            account_token_activities::transaction_version.desc(),
+            account_token_activities::event_index.desc(),
+            account_token_activities::event_account_address.desc(),
+            account_token_activities::event_creation_number.desc(),
+            account_token_activities::event_sequence_number.desc(),
+            account_token_activities::token_index.desc(),
+        ))
+        .limit(ACTIVITY_PAGE_SIZE)
+        .load::<AccountTokenActivity>(conn)?;
+    Ok(rows.into_iter().map(TokenActivity::from).collect())
+}
+
+/// Orders activities newest first and limits them to the page after the cursor
+fn activity_page<'a, ST>(
+    mut query: token_activities::BoxedQuery<'a, Pg, ST>,
+    cursor: Option<&ActivityCursor>,
+) -> token_activities::BoxedQuery<'a, Pg, ST> {
+    if let Some(cursor) = cursor {
+        query = query.filter(
+            sql::<Bool>(
+                "(transaction_version, event_index, event_account_address, \
+                event_creation_number, event_sequence_number, token_index) < (",
+            )
+            .bind::<BigInt, _>(cursor.transaction_version)
+            .sql(", ")
+            .bind::<BigInt, _>(cursor.event_index)
+            .sql(", ")
+            .bind::<VarChar, _>(cursor.event_account_address.clone())
+            .sql(", ")
+            .bind::<BigInt, _>(cursor.event_creation_number)
+            .sql(", ")
+            .bind::<BigInt, _>(cursor.event_sequence_number)
+            .sql(", ")
+            .bind::<BigInt, _>(cursor.token_index)
+            .sql(")"),
+        );
+    }
+    query
+        .order((
+            token_activities::transaction_version.desc(),
+            token_activities::event_index.desc(),
+            token_activities::event_account_address.desc(),
+            token_activities::event_creation_number.desc(),
+            token_activities::event_sequence_number.desc(),
+            token_activities::token_index.desc(),
+        ))
+        .limit(ACTIVITY_PAGE_SIZE)
+}
+
+/// None if the collection has no sales in the window. For a window, the volume is summed from
+/// collection_volumes and inserted_at is when that was done.
+pub fn get_collection_volume(
+    conn: &mut PgConnection,
+    collection_hash: &str,
+    window: VolumeWindow,
+) -> QueryResult<Option<CurrentCollectionVolume>> {
+    let duration = match window {
+        VolumeWindow::AllTime => {
+            return current_collection_volumes::table
+                .find(collection_hash)
+                .select(CurrentCollectionVolume::as_select())
+                .first(conn)
+                .optional();
+        }
+        VolumeWindow::Last(duration) => duration,
+    };
+    let now = chrono::Utc::now().naive_utc();
+    let (
+        volume,
+        primary_volume,
+        volume_usd,
+        volume_decimal,
+        last_transaction_version,
+        last_transaction_timestamp,
+    ) = collection_volumes::table
+        .filter(collection_volumes::collection_data_id_hash.eq(collection_hash))
+        .filter(collection_volumes::last_transaction_timestamp.ge(now - duration))
+        .select(sql::<(
+            Numeric,
+            Numeric,
+            Nullable<Numeric>,
+            Nullable<Numeric>,
+            Nullable<BigInt>,
+            Nullable<Timestamp>,
+        )>(
+            "COALESCE(SUM(volume), 0), \
+            COALESCE(SUM(volume) FILTER (WHERE is_primary), 0), \
+            SUM(volume_usd), \
+            SUM(volume_decimal), \
+            MAX(last_transaction_version), \
+            MAX(last_transaction_timestamp)",
+        ))
+        .get_result::<(
+            BigDecimal,
+            BigDecimal,
+            Option<BigDecimal>,
+            Option<BigDecimal>,
+            Option<i64>,
+            Option<chrono::NaiveDateTime>,
+        )>(conn)?;
+    Ok(last_transaction_version
+        .zip(last_transaction_timestamp)
+        .map(
+            |(last_transaction_version, last_transaction_timestamp)| CurrentCollectionVolume {
+                collection_data_id_hash: collection_hash.to_string(),
+                secondary_volume: &volume - &primary_volume,
+                volume,
+                primary_volume,
+                volume_usd,
+                volume_decimal,
+                inserted_at: now,
+                last_transaction_version,
+                last_transaction_timestamp,
+                // Summed over every sale, so every event of the last version
+                last_event_index: None,
+            },
+        ))
+}
+
+/// The collection's most recent daily stats, None if it has no snapshot yet
+pub fn get_latest_collection_stats(
+    conn: &mut PgConnection,
+    collection_hash: &str,
+) -> QueryResult<Option<CollectionStatsSnapshot>> {
+    collection_stats_snapshots::table
+        .filter(collection_stats_snapshots::collection_data_id_hash.eq(collection_hash))
+        .select(CollectionStatsSnapshot::as_select())
+        .order(collection_stats_snapshots::snapshot_date.desc())
+        .first(conn)
+        .optional()
+}
+
+/// Tokens the address holds, most recently changed first. Tokens of collections in
+/// spam_collections are left out unless include_spam is set, and tokens the address no longer
+/// holds unless include_deleted is set.
+pub fn get_owner_tokens(
+    conn: &mut PgConnection,
+    address: &str,
+    include_spam: bool,
+    include_deleted: bool,
+) -> QueryResult<Vec<CurrentTokenOwnership>> {
+    let query = current_token_ownerships::table
+        .filter(current_token_ownerships::owner_address.eq(standardize_address(address)))
+        .select(CurrentTokenOwnership::as_select())
+        .into_boxed();
+    owner_tokens(query, include_spam, include_deleted).load(conn)
+}
+
+/// Like get_owner_tokens, a page at a time. Pass the cursor of the last token returned to get
+/// the next page.
+pub fn get_owner_tokens_page(
+    conn: &mut PgConnection,
+    address: &str,
+    include_spam: bool,
+    include_deleted: bool,
+    cursor: Option<&OwnershipCursor>,
+    limit: i64,
+) -> QueryResult<Vec<CurrentTokenOwnership>> {
+    let query = current_token_ownerships::table
+        .filter(current_token_ownerships::owner_address.eq(standardize_address(address)))
+        .select(CurrentTokenOwnership::as_select())
+        .into_boxed();
+    let mut query = owner_tokens(query, include_spam, include_deleted);
+    if let Some(cursor) = cursor {
+        query = query.filter(
+            sql::<Bool>("(last_transaction_version < ")
+                .bind::<BigInt, _>(cursor.last_transaction_version)
+                .sql(" OR (last_transaction_version = ")
+                .bind::<BigInt, _>(cursor.last_transaction_version)
+                .sql(" AND (token_data_id_hash, property_version) > (")
+                .bind::<VarChar, _>(cursor.token_data_id_hash.clone())
+                .sql(", ")
+                .bind::<Numeric, _>(cursor.property_version.clone())
+                .sql(")))"),
+        );
+    }
+    query.limit(limit).load(conn)
+}
+
+/// Leaves out spam unless include_spam is set and deleted ownerships unless include_deleted is,
+/// and orders the owner's tokens most recently changed first. Rows written before is_deleted
+/// existed are only known to be gone by their zero amount.
+fn owner_tokens<'a, ST>(
+    mut query: current_token_ownerships::BoxedQuery<'a, Pg, ST>,
+    include_spam: bool,
+    include_deleted: bool,
+) -> current_token_ownerships::BoxedQuery<'a, Pg, ST> {
+    if !include_deleted {
+        query = query
+            .filter(current_token_ownerships::is_deleted.eq(false))
+            .filter(current_token_ownerships::amount.gt(BigDecimal::zero()));
+    }
+    if !include_spam {
+        query = query.filter(
+            current_token_ownerships::collection_data_id_hash
+                .ne_all(spam_collections::table.select(spam_collections::collection_data_id_hash)),
+        );
+    }
+    query.order((
+        current_token_ownerships::last_transaction_version.desc(),
+        current_token_ownerships::token_data_id_hash.asc(),
+        current_token_ownerships::property_version.asc(),
+    ))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_utils::timestamp;
+    use crate::{
+        database::{new_db_pool, PgPoolConnection},
+        indexer::tailer::{test::wipe_database, MIGRATIONS},
+        models::token_models::collection_volume::CollectionVolume,
+    };
+    use diesel::connection::SimpleConnection;
+    use diesel_migrations::MigrationHarness;
+
+    fn setup() -> PgPoolConnection {
+        let database_url = std::env::var("INDEXER_DATABASE_URL")
+            .expect("must set 'INDEXER_DATABASE_URL' to run tests!");
+        let conn_pool = new_db_pool(database_url.as_str()).unwrap();
+        let mut conn = conn_pool.get().unwrap();
+        wipe_database(&mut conn);
+        conn.run_pending_migrations(MIGRATIONS).unwrap();
+        conn
+    }
+
+    fn listing(
+        token_data_id_hash: &str,
+        price: i64,
+        amount: i64,
+        invalidated_reason: Option<&str>,
+    ) -> CurrentMarketplaceListing {
+        CurrentMarketplaceListing {
+            collection_data_id_hash: "potions".to_string(),
+            market_address: "0xbb".to_string(),
+            token_data_id_hash: token_data_id_hash.to_string(),
+            property_version: BigDecimal::from(0),
+            creator_address: "0x1".to_string(),
+            collection_name: "Potions".to_string(),
+            name: token_data_id_hash.to_string(),
+            seller: "0x2".to_string(),
+            amount: BigDecimal::from(amount),
+            price: BigDecimal::from(price),
+            event_type: "0xbb::market::ListEvent".to_string(),
+            inserted_at: timestamp(),
+            last_transaction_version: 1,
+            invalidated_reason: invalidated_reason.map(|reason| reason.to_string()),
+            last_transaction_timestamp: timestamp(),
+            price_decimal: None,
+            is_deleted: amount == 0,
+            last_event_index: Some(0),
+        }
+    }
+
+    fn activity(
+        transaction_version: i64,
+        event_index: i64,
+        event_sequence_number: i64,
+    ) -> TokenActivity {
+        TokenActivity {
+            transaction_version,
+            event_account_address: "0x1".to_string(),
+            event_creation_number: 0,
+            event_sequence_number,
+            event_index,
+            token_index: 0,
+            token_data_id_hash: "potion".to_string(),
+            property_version: BigDecimal::from(0),
+            creator_address: "0x1".to_string(),
+            collection_name: "Potions".to_string(),
+            name: "Potion".to_string(),
+            transfer_type: "0x3::token::DepositEvent".to_string(),
+            transfer_kind: Some("transfer".to_string()),
+            domain: None,
+            from_address: None,
+            to_address: Some("0x1".to_string()),
+            token_amount: BigDecimal::from(1),
+            coin_type: None,
+            coin_amount: None,
+            suspect_value: false,
+            collection_data_id_hash: "potions".to_string(),
+            transaction_timestamp: timestamp(),
+        }
+    }
+
+    fn ownership(collection_data_id_hash: &str, amount: i64) -> CurrentTokenOwnership {
+        CurrentTokenOwnership {
+            token_data_id_hash: format!("{}_token", collection_data_id_hash),
+            property_version: BigDecimal::from(0),
+            owner_address: standardize_address("0x2"),
+            creator_address: "0x1".to_string(),
+            collection_name: collection_data_id_hash.to_string(),
+            name: "Token".to_string(),
+            amount: BigDecimal::from(amount),
+            token_properties: serde_json::json!({}),
+            last_transaction_version: 1,
+            collection_data_id_hash: collection_data_id_hash.to_string(),
+            table_type: "0x3::token::TokenStore".to_string(),
+            last_transaction_timestamp: timestamp(),
+            owner_type: "user".to_string(),
+            beneficial_owner: None,
+            is_deleted: amount == 0,
+        }
+    }
+
+    #[test]
+    fn test_get_active_listings_by_collection() {
+        if crate::should_skip_pg_tests() {
+            return;
+        }
+        let mut conn = setup();
+        diesel::insert_into(current_marketplace_listings::table)
+            .values(&vec![
+                listing("sword", 30, 1, None),
+                listing("shield", 10, 1, None),
+                listing("delisted", 5, 0, None),
+                // Sales keep the sold amount
+                CurrentMarketplaceListing {
+                    is_deleted: true,
+                    ..listing("sold", 5, 1, None)
+                },
+                listing("withdrawn", 5, 1, Some("token_withdrawn")),
+                listing("potion", 20, 1, None),
+            ])
+            .execute(&mut conn)
+            .unwrap();
+
+        let names = |listings: Vec<CurrentMarketplaceListing>| {
+            listings
+                .into_iter()
+                .map(|listing| listing.token_data_id_hash)
+                .collect::<Vec<_>>()
+        };
+        assert_eq!(
+            names(get_active_listings_by_collection(&mut conn, "potions", 2, 0).unwrap()),
+            vec!["shield", "potion"]
+        );
+        assert_eq!(
+            names(get_active_listings_by_collection(&mut conn, "potions", 2, 2).unwrap()),
+            vec!["sword"]
+        );
+        assert!(get_active_listings_by_collection(&mut conn, "swords", 2, 0)
+            .unwrap()
+            .is_empty());
+    }
+
+    #[test]
+    fn test_get_token_activities_pages() {
+        if crate::should_skip_pg_tests() {
+            return;
+        }
+        let mut conn = setup();
+        // The rows at version 1 have event_index 0 from before it was indexed
+        let mut activities = vec![activity(1, 0, 1), activity(1, 0, 2), activity(2, 1, 3)];
+        activities
+            .extend((3..=ACTIVITY_PAGE_SIZE).map(|version| activity(version, 0, version + 1)));
+        diesel::insert_into(token_activities::table)
+            .values(&activities)
+            .execute(&mut conn)
+            .unwrap();
+
+        let first_page = get_token_activities(&mut conn, "potion", None).unwrap();
+        assert_eq!(first_page.len(), ACTIVITY_PAGE_SIZE as usize);
+        assert_eq!(first_page[0].transaction_version, ACTIVITY_PAGE_SIZE);
+        let last = first_page.last().unwrap();
+        assert_eq!(
+            (last.transaction_version, last.event_sequence_number),
+            (1, 2)
+        );
+
+        let second_page =
+            get_token_activities(&mut conn, "potion", Some(&ActivityCursor::from(last))).unwrap();
+        assert_eq!(
+            second_page
+                .iter()
+                .map(|activity| (activity.transaction_version, activity.event_sequence_number))
+                .collect::<Vec<_>>(),
+            vec![(1, 1)]
+        );
+    }
+
+    #[test]
+    fn test_get_collection_activities_by_kind() {
+        if crate::should_skip_pg_tests() {
+            return;
+        }
+        let mut conn = setup();
+        let with_kind = |activity: TokenActivity, kind: Option<TransferKind>| TokenActivity {
+            transfer_kind: kind.map(|kind| kind.as_str().to_string()),
+            ..activity
+        };
+        diesel::insert_into(token_activities::table)
+            .values(&vec![
+                with_kind(activity(1, 0, 1), Some(TransferKind::Listing)),
+                with_kind(activity(2, 0, 2), Some(TransferKind::Sale)),
+                with_kind(activity(3, 0, 3), Some(TransferKind::Transfer)),
+                // Indexed before transfer_kind, until it's backfilled
+                with_kind(activity(4, 0, 4), None),
+                TokenActivity {
+                    collection_data_id_hash: "swords".to_string(),
+                    ..with_kind(activity(5, 0, 5), Some(TransferKind::Sale))
+                },
+            ])
+            .execute(&mut conn)
+            .unwrap();
+
+        let mut versions = |kinds: &[TransferKind]| {
+            get_collection_activities(&mut conn, "potions", kinds, None)
+                .unwrap()
+                .iter()
+                .map(|activity| activity.transaction_version)
+                .collect::<Vec<_>>()
+        };
+        assert_eq!(
+            versions(&[TransferKind::Sale, TransferKind::Listing]),
+            vec![2, 1]
+        );
+        assert_eq!(versions(&[TransferKind::Transfer]), vec![3]);
+        assert_eq!(versions(&[]), vec![4, 3, 2, 1]);
+    }
+
+    #[test]
+    fn test_get_account_token_activities_pages() {
+        if crate::should_skip_pg_tests() {
+            return;
+        }
+        let mut conn = setup();
+        let (seller, buyer) = (standardize_address("0x2"), standardize_address("0x3"));
+        let sale = TokenActivity {
+            from_address: Some(seller.clone()),
+            to_address: Some(buyer.clone()),
+            ..activity(1, 0, 1)
+        };
+        let deposit = TokenActivity {
+            to_address: Some(seller.clone()),
+            ..activity(2, 0, 2)
+        };
+        let withdrawal = TokenActivity {
+            from_address: Some(seller.clone()),
+            to_address: None,
+            ..activity(3, 0, 3)
+        };
+        diesel::insert_into(account_token_activities::table)
+            .values(&AccountTokenActivity::from_token_activities(&[
+                sale, deposit, withdrawal,
+            ]))
+            .execute(&mut conn)
+            .unwrap();
+
+        let versions = |activities: Vec<TokenActivity>| {
+            activities
+                .iter()
+                .map(|activity| activity.transaction_version)
+                .collect::<Vec<_>>()
+        };
+        let seller_feed = get_account_token_activities(&mut conn, "0x2", None).unwrap();
+        assert_eq!(versions(seller_feed.clone()), vec![3, 2, 1]);
+        // The sale reads the same from both sides
+        assert_eq!(seller_feed[2].to_address, Some(buyer));
+        assert_eq!(
+            versions(get_account_token_activities(&mut conn, "0x3", None).unwrap()),
+            vec![1]
+        );
+        assert_eq!(
+            versions(
+                get_account_token_activities(
+                    &mut conn,
+                    "0x2",
+                    Some(&ActivityCursor::from(&seller_feed[0]))
+                )
+                .unwrap()
+            ),
+            vec![2, 1]
+        );
+    }
+
+    #[test]
+    fn test_get_collection_volume() {
+        if crate::should_skip_pg_tests() {
+            return;
+        }
+        let mut conn = setup();
+        let now = chrono::Utc::now().naive_utc();
+        let sale = |version: i64, days_ago: i64, volume: i64, is_primary: bool| CollectionVolume {
+            collection_data_id_hash: "potions".to_string(),
+            volume: BigDecimal::from(volume),
+            inserted_at: now,
+            last_transaction_version: version,
+            last_transaction_timestamp: now - chrono::Duration::days(days_ago),
+            event_index: 0,
+            token_index: 0,
+            is_primary,
+            volume_usd: None,
+            volume_decimal: None,
+            market_address: None,
+            coin_type: None,
+        };
+        diesel::insert_into(collection_volumes::table)
+            .values(&vec![
+                sale(1, 40, 100, true),
+                sale(2, 3, 20, true),
+                sale(3, 1, 5, false),
+            ])
+            .execute(&mut conn)
+            .unwrap();
+        diesel::insert_into(current_collection_volumes::table)
+            .values(&CurrentCollectionVolume {
+                collection_data_id_hash: "potions".to_string(),
+                volume: BigDecimal::from(125),
+                inserted_at: now,
+                last_transaction_version: 3,
+                last_transaction_timestamp: now - chrono::Duration::days(1),
+                primary_volume: BigDecimal::from(120),
+                secondary_volume: BigDecimal::from(5),
+                volume_usd: None,
+                volume_decimal: None,
+                last_event_index: Some(0),
+            })
+            .execute(&mut conn)
+            .unwrap();
+
+        let all_time = get_collection_volume(&mut conn, "potions", VolumeWindow::AllTime)
+            .unwrap()
+            .unwrap();
+        assert_eq!(all_time.volume, BigDecimal::from(125));
+
+        let week = get_collection_volume(
+            &mut conn,
+            "potions",
+            VolumeWindow::Last(chrono::Duration::days(7)),
+        )
+        .unwrap()
+        .unwrap();
+        assert_eq!(week.volume, BigDecimal::from(25));
+        assert_eq!(week.primary_volume, BigDecimal::from(20));
+        assert_eq!(week.secondary_volume, BigDecimal::from(5));
+        assert_eq!(week.last_transaction_version, 3);
+
+        assert!(get_collection_volume(
+            &mut conn,
+            "potions",
+            VolumeWindow::Last(chrono::Duration::hours(1)),
+        )
+        .unwrap()
+        .is_none());
+        assert!(
+            get_collection_volume(&mut conn, "swords", VolumeWindow::AllTime)
+                .unwrap()
+                .is_none()
+        );
+    }
+
+    #[test]
+    fn test_get_owner_tokens_page() {
+        if crate::should_skip_pg_tests() {
+            return;
+        }
+        let mut conn = setup();
+        diesel::insert_into(current_token_ownerships::table)
+            .values(&vec![
+                ownership("swords", 1),
+                ownership("potions", 1),
+                ownership("shields", 1),
+            ])
+            .execute(&mut conn)
+            .unwrap();
+
+        let hashes = |ownerships: &[CurrentTokenOwnership]| {
+            ownerships
+                .iter()
+                .map(|ownership| ownership.token_data_id_hash.clone())
+                .collect::<Vec<_>>()
+        };
+        let first_page = get_owner_tokens_page(&mut conn, "0x2", false, false, None, 2).unwrap();
+        assert_eq!(hashes(&first_page), vec!["potions_token", "shields_token"]);
+        let cursor = OwnershipCursor::from(first_page.last().unwrap());
+        let second_page =
+            get_owner_tokens_page(&mut conn, "0x2", false, false, Some(&cursor), 2).unwrap();
+        assert_eq!(hashes(&second_page), vec!["swords_token"]);
+    }
+
+    #[test]
+    fn test_get_owner_tokens_skips_spam() {
+        if crate::should_skip_pg_tests() {
+            return;
+        }
+        let mut conn = setup();
+        diesel::insert_into(current_token_ownerships::table)
+            .values(&vec![
+                ownership("potions", 1),
+                ownership("airdrop", 1),
+                ownership("sold", 0),
+            ])
+            .execute(&mut conn)
+            .unwrap();
+        conn.batch_execute(
+            "INSERT INTO spam_collections (collection_data_id_hash) VALUES ('airdrop')",
+        )
+        .unwrap();
+
+        let collections = |ownerships: Vec<CurrentTokenOwnership>| {
+            let mut collections = ownerships
+                .into_iter()
+                .map(|ownership| ownership.collection_data_id_hash)
+                .collect::<Vec<_>>();
+            collections.sort();
+            collections
+        };
+        assert_eq!(
+            collections(get_owner_tokens(&mut conn, "0x2", false, false).unwrap()),
+            vec!["potions"]
+        );
+        assert_eq!(
+            collections(get_owner_tokens(&mut conn, "0x02", true, false).unwrap()),
+            vec!["airdrop", "potions"]
+        );
+    }
+
+    #[test]
+    fn test_get_owner_tokens_include_deleted() {
+        if crate::should_skip_pg_tests() {
+            return;
+        }
+        let mut conn = setup();
+        diesel::insert_into(current_token_ownerships::table)
+            .values(&vec![
+                ownership("potions", 1),
+                ownership("burned", 0),
+                // Zeroed before is_deleted was added
+                CurrentTokenOwnership {
+                    is_deleted: false,
+                    ..ownership("legacy", 0)
+                },
+            ])
+            .execute(&mut conn)
+            .unwrap();
+
+        let collections = |ownerships: Vec<CurrentTokenOwnership>| {
+            let mut collections = ownerships
+                .into_iter()
+                .map(|ownership| ownership.collection_data_id_hash)
+                .collect::<Vec<_>>();
+            collections.sort();
+            collections
+        };
+        assert_eq!(
+            collections(get_owner_tokens(&mut conn, "0x2", false, false).unwrap()),
+            vec!["potions"]
+        );
+        assert_eq!(
+            collections(get_owner_tokens(&mut conn, "0x2", false, true).unwrap()),
+            vec!["burned", "legacy", "potions"]
+        );
+    }
+}
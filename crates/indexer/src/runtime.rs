@@ -2,17 +2,46 @@
 // SPDX-License-Identifier: Apache-2.0
 
 use crate::{
-    database::new_db_pool,
+    database::{new_db_pool_with_settings, ConnectionSettings, PgDbPool, DEFAULT_POOL_SIZE},
     indexer::{
-        fetcher::TransactionFetcherOptions, tailer::Tailer,
+        fetch_cache::FetchCache,
+        fetch_retry::FetchRetry,
+        fetcher::{AdaptiveFetch, TransactionFetcherOptions},
+        tailer::Tailer,
         transaction_processor::TransactionProcessor,
+        transaction_stream::TransactionStream,
+        transaction_trace::TransactionTracer,
+        upstream_nodes::UpstreamNodes,
     },
+    live_feed::LiveFeed,
+    migrations::prepare_schema,
+    models::token_models::{
+        activity_partitions::TokenActivityPartitions,
+        ans_lookup::AnsContract,
+        ans_sales::AnsDomains,
+        collection_price_stats::CollectionPriceMedians,
+        collection_rarity::CollectionRarity,
+        collection_stats_snapshots::CollectionStatsSnapshots,
+        consistency_check::ConsistencyCheck,
+        leaderboards::Leaderboards,
+        marketplace_event_mappings::MarketplaceEventMappings,
+        table_handle_cache::TableHandleCache,
+        token_tables::TokenTables,
+        token_utils::{StringLimits, ValueLimits},
+        volume_reconciliation::VolumeReconciliation,
+    },
+    parquet_sink::{spawn_parquet_sink, ParquetSink},
     processors::{
         coin_processor::CoinTransactionProcessor, default_processor::DefaultTransactionProcessor,
         token_processor::TokenTransactionProcessor, Processor,
     },
 };
 
+#[cfg(feature = "http-api")]
+use crate::http_api::HttpApi;
+#[cfg(feature = "metadata-fetcher")]
+use crate::metadata_fetcher::MetadataFetcher;
+use anyhow::{bail, ensure};
 use aptos_api::context::Context;
 use aptos_config::config::{IndexerConfig, NodeConfig};
 use aptos_logger::{error, info};
@@ -103,25 +132,350 @@ pub fn bootstrap(
     Some(Ok(runtime))
 }
 
+/// Instantiates the processor named in the config. Shared by the node runtime and the standalone
+/// `aptos-token-indexer` binary so both run exactly the same pipeline.
+pub fn build_processor(
+    config: &IndexerConfig,
+    conn_pool: PgDbPool,
+) -> Arc<dyn TransactionProcessor> {
+    build_processor_with_outputs(config, conn_pool, None, None)
+}
+
+/// Like build_processor, with the token processor publishing its committed batches to live_feed
+/// and copying their rows to parquet_sink
+pub fn build_processor_with_outputs(
+    config: &IndexerConfig,
+    conn_pool: PgDbPool,
+    live_feed: Option<LiveFeed>,
+    parquet_sink: Option<ParquetSink>,
+) -> Arc<dyn TransactionProcessor> {
+    let processor_name = config.processor.clone().unwrap();
+    install_limits(config);
+    match Processor::from_string(&processor_name) {
+        Processor::DefaultProcessor => Arc::new(DefaultTransactionProcessor::new(conn_pool)),
+        Processor::TokenProcessor => Arc::new(build_token_processor(
+            config,
+            conn_pool,
+            live_feed,
+            parquet_sink,
+        )),
+        Processor::CoinProcessor => Arc::new(CoinTransactionProcessor::new(conn_pool)),
+    }
+}
+
+/// Process wide, names and uris are truncated and event values bounded wherever the token models
+/// are built
+fn install_limits(config: &IndexerConfig) {
+    StringLimits::from_config(config.string_limits.as_ref())
+        .and_then(StringLimits::install)
+        .expect("Invalid string_limits");
+    ValueLimits::from_config(config.value_limits.as_ref())
+        .and_then(ValueLimits::install)
+        .expect("Invalid value_limits");
+}
+
+/// The token processor build_processor runs, for callers that need more than the
+/// TransactionProcessor interface, e.g. to parse a transaction without writing it
+pub fn build_token_processor(
+    config: &IndexerConfig,
+    conn_pool: PgDbPool,
+    live_feed: Option<LiveFeed>,
+    parquet_sink: Option<ParquetSink>,
+) -> TokenTransactionProcessor {
+    install_limits(config);
+    TokenTransactionProcessor::new(
+        conn_pool,
+        AnsContract::from_config(
+            config.ans_contract_address.as_ref(),
+            config.ans_contracts.as_deref().unwrap_or_default(),
+        )
+        .expect("Invalid ans_contracts"),
+        AnsDomains::from_config(config.ans_token_creator.as_deref())
+            .expect("Invalid ans_token_creator"),
+        MarketplaceEventMappings::from_config(
+            config
+                .marketplace_event_mappings
+                .as_deref()
+                .unwrap_or_default(),
+        )
+        .and_then(|mappings| {
+            mappings.with_typed_event_mappings(
+                config
+                    .marketplace_typed_event_mappings
+                    .as_deref()
+                    .unwrap_or_default(),
+            )
+        })
+        .and_then(|mappings| {
+            mappings.with_listing_mappings(
+                config
+                    .marketplace_listing_mappings
+                    .as_deref()
+                    .unwrap_or_default(),
+            )
+        })
+        .and_then(|mappings| {
+            mappings.with_payload_mappings(
+                config
+                    .marketplace_payload_mappings
+                    .as_deref()
+                    .unwrap_or_default(),
+            )
+        })
+        .and_then(|mappings| {
+            mappings.with_escrow_addresses(
+                config
+                    .marketplace_escrow_addresses
+                    .as_deref()
+                    .unwrap_or_default(),
+            )
+        })
+        .and_then(|mappings| match &config.marketplace_adapters {
+            Some(names) => mappings.with_adapters(names),
+            None => Ok(mappings),
+        })
+        .and_then(|mappings| match &config.marketplace_definitions_path {
+            Some(path) => mappings.with_definitions_file(path),
+            None => Ok(mappings),
+        })
+        .expect("Invalid marketplace_event_mappings"),
+        VolumeReconciliation::from_config(config.volume_reconciliation.as_ref())
+            .expect("Invalid volume_reconciliation"),
+        ConsistencyCheck::from_config(config.consistency_check.as_ref())
+            .expect("Invalid consistency_check"),
+        TransactionTracer::new(config.trace_versions.as_deref().unwrap_or_default()),
+        CollectionRarity::from_config(config.rarity_refresh_every_n_versions)
+            .expect("Invalid rarity_refresh_every_n_versions"),
+        CollectionPriceMedians::from_config(config.price_median_refresh_every_n_versions)
+            .expect("Invalid price_median_refresh_every_n_versions"),
+        CollectionStatsSnapshots::from_config(config.collection_stats_snapshots.as_ref())
+            .expect("Invalid collection_stats_snapshots"),
+        Leaderboards::from_config(config.leaderboards.as_ref()).expect("Invalid leaderboards"),
+        config.record_settlement_amounts.unwrap_or(false),
+        config.account_token_activities.unwrap_or(false),
+        TableHandleCache::from_config(config.table_handle_cache.as_ref())
+            .expect("Invalid table_handle_cache"),
+        TokenTables::from_config(config.enabled_tables.as_deref()).expect("Invalid enabled_tables"),
+        TokenActivityPartitions::from_config(config.token_activities_partition_size)
+            .expect("Invalid token_activities_partition_size"),
+        TokenTransactionProcessor::num_shards_from_config(config.token_processor_shards)
+            .expect("Invalid token_processor_shards"),
+        live_feed,
+        parquet_sink,
+    )
+}
+
+/// `processor` followed by the `additional_processors`, each of which has to be supported and
+/// named only once
+pub fn processor_names(config: &IndexerConfig) -> anyhow::Result<Vec<String>> {
+    let mut processor_names: Vec<String> = config.processor.iter().cloned().collect();
+    for processor_name in config.additional_processors.iter().flatten() {
+        if Processor::try_from_string(processor_name).is_none() {
+            bail!("Unsupported processor '{}'", processor_name);
+        }
+        ensure!(
+            !processor_names.contains(processor_name),
+            "'{}' is run more than once",
+            processor_name
+        );
+        processor_names.push(processor_name.clone());
+    }
+    Ok(processor_names)
+}
+
+/// Checks the metadata_fetcher config, which is only usable when built with the
+/// `metadata-fetcher` feature
+#[cfg(feature = "metadata-fetcher")]
+pub fn check_metadata_fetcher(config: &IndexerConfig) -> anyhow::Result<()> {
+    MetadataFetcher::from_config(config.metadata_fetcher.as_ref())?;
+    Ok(())
+}
+
+#[cfg(not(feature = "metadata-fetcher"))]
+pub fn check_metadata_fetcher(config: &IndexerConfig) -> anyhow::Result<()> {
+    ensure!(
+        config.metadata_fetcher.is_none(),
+        "metadata_fetcher needs the indexer built with the metadata-fetcher feature"
+    );
+    Ok(())
+}
+
+/// Starts the metadata fetcher on its own small pool, if it's configured
+#[cfg(feature = "metadata-fetcher")]
+fn spawn_metadata_fetcher(config: &IndexerConfig) {
+    let fetcher = match MetadataFetcher::from_config(config.metadata_fetcher.as_ref())
+        .expect("Invalid metadata_fetcher")
+    {
+        Some(fetcher) => fetcher,
+        None => return,
+    };
+    let conn_pool = new_db_pool_with_settings(
+        config.postgres_uri.as_ref().unwrap(),
+        ConnectionSettings::from_config(config).expect("Invalid postgres_schema"),
+        2,
+    )
+    .expect("Failed to create the metadata fetcher's connection pool");
+    tokio::spawn(fetcher.run(conn_pool));
+}
+
+#[cfg(not(feature = "metadata-fetcher"))]
+fn spawn_metadata_fetcher(config: &IndexerConfig) {
+    check_metadata_fetcher(config).expect("Invalid metadata_fetcher");
+}
+
+/// Serves the HTTP API alongside the processors if the config asks for it, and returns the live
+/// feed of its websocket endpoint
+#[cfg(feature = "http-api")]
+fn spawn_http_api(config: &IndexerConfig) -> Option<LiveFeed> {
+    let http_api = HttpApi::from_config(
+        config.http_api.as_ref(),
+        config.account_token_activities.unwrap_or(false),
+    )
+    .expect("Invalid http_api");
+    if !http_api.serve_with_indexer() {
+        return None;
+    }
+    let live_feed = http_api.live_feed();
+    let conn_pool = new_db_pool_with_settings(
+        config.postgres_uri.as_ref().unwrap(),
+        ConnectionSettings::from_config(config).expect("Invalid postgres_schema"),
+        http_api.pool_size(),
+    )
+    .expect("Failed to create the HTTP API's connection pool");
+    tokio::spawn(http_api.serve(conn_pool, Some(live_feed.clone())));
+    Some(live_feed)
+}
+
+#[cfg(not(feature = "http-api"))]
+fn spawn_http_api(config: &IndexerConfig) -> Option<LiveFeed> {
+    assert!(
+        !serves_http_api_with_indexer(config),
+        "http_api.serve_with_indexer needs the indexer built with the http-api feature"
+    );
+    None
+}
+
+pub fn serves_http_api_with_indexer(config: &IndexerConfig) -> bool {
+    config
+        .http_api
+        .as_ref()
+        .and_then(|http_api| http_api.serve_with_indexer)
+        .unwrap_or(false)
+}
+
+/// Runs every processor in the config, each with its own tailer continuing from its own version.
+/// Their fetchers share a fetch cache, so versions one of them fetched aren't fetched again by the
+/// others while they're cached.
 pub async fn run_forever(config: IndexerConfig, context: Arc<Context>) {
+    let processor_names = processor_names(&config).expect("Invalid additional_processors");
+    let batch_size = config.batch_size.unwrap();
+
+    prepare_schema(
+        config.postgres_uri.as_ref().unwrap(),
+        &ConnectionSettings::from_config(&config).expect("Invalid postgres_schema"),
+        !config.skip_migrations.unwrap(),
+        config.rehash.unwrap_or(false),
+    )
+    .expect("Database schema isn't ready");
+
+    // Shared by every processor's fetcher
+    let options = TransactionFetcherOptions::new(
+        None,
+        None,
+        Some(batch_size),
+        None,
+        config.fetch_tasks.unwrap() as usize,
+    )
+    .with_adaptive_fetch(
+        AdaptiveFetch::from_config(config.adaptive_fetch.as_ref(), batch_size)
+            .expect("Invalid adaptive_fetch"),
+    )
+    .with_fetch_retry(
+        FetchRetry::from_config(config.fetch_retry.as_ref()).expect("Invalid fetch_retry"),
+    )
+    .with_upstream_nodes(
+        UpstreamNodes::from_config(config.upstream_nodes.as_ref()).expect("Invalid upstream_nodes"),
+    )
+    .with_transaction_stream(
+        TransactionStream::from_config(
+            config.transaction_stream.as_ref(),
+            config.upstream_nodes.as_ref(),
+        )
+        .expect("Invalid transaction_stream"),
+    )
+    .with_fetch_cache(
+        FetchCache::from_config(config.fetch_cache.as_ref(), processor_names.len())
+            .expect("Invalid fetch_cache"),
+    );
+
+    // Runs alongside the processors, reading the token datas they write
+    spawn_metadata_fetcher(&config);
+    let live_feed = spawn_http_api(&config);
+    let parquet_sink =
+        spawn_parquet_sink(config.parquet_sink.as_ref()).expect("Invalid parquet_sink");
+
+    let tasks: Vec<_> = processor_names
+        .into_iter()
+        .map(|processor_name| {
+            let config = IndexerConfig {
+                processor: Some(processor_name),
+                ..config.clone()
+            };
+            tokio::spawn(run_processor(
+                config,
+                context.clone(),
+                options.clone(),
+                live_feed.clone(),
+                parquet_sink.clone(),
+            ))
+        })
+        .collect();
+    if let Err(err) = futures::future::try_join_all(tasks).await {
+        panic!("Indexer processor failed: {:?}", err);
+    }
+}
+
+async fn run_processor(
+    config: IndexerConfig,
+    context: Arc<Context>,
+    options: TransactionFetcherOptions,
+    live_feed: Option<LiveFeed>,
+    parquet_sink: Option<ParquetSink>,
+) {
     // All of these options should be filled already with defaults
     let processor_name = config.processor.clone().unwrap();
     let check_chain_id = config.check_chain_id.unwrap();
-    let skip_migrations = config.skip_migrations.unwrap();
-    let fetch_tasks = config.fetch_tasks.unwrap();
-    let processor_tasks = config.processor_tasks.unwrap();
+    // Token processor batches read rows the batches before them wrote (e.g. a token's owners
+    // before its sale, or the seller's cost basis), so they're processed one at a time, in
+    // version order, each committed before the next one is built
+    let processor_tasks = match Processor::from_string(&processor_name) {
+        Processor::TokenProcessor => 1,
+        _ => config.processor_tasks.unwrap(),
+    };
     let emit_every = config.emit_every.unwrap();
-    let batch_size = config.batch_size.unwrap();
     let lookback_versions = config.gap_lookback_versions.unwrap() as i64;
 
     info!(processor_name = processor_name, "Starting indexer...");
 
-    let db_uri = &config.postgres_uri.unwrap();
+    let db_uri = config.postgres_uri.as_ref().unwrap();
     info!(
         processor_name = processor_name,
         "Creating connection pool..."
     );
-    let conn_pool = new_db_pool(db_uri).expect("Failed to create connection pool");
+    // Every shard of a token processor batch holds its own connection while it commits
+    let num_shards = match Processor::from_string(&processor_name) {
+        Processor::TokenProcessor => {
+            TokenTransactionProcessor::num_shards_from_config(config.token_processor_shards)
+                .expect("Invalid token_processor_shards")
+        }
+        _ => 1,
+    };
+    let conn_pool = new_db_pool_with_settings(
+        db_uri,
+        ConnectionSettings::from_config(&config).expect("Invalid postgres_schema"),
+        DEFAULT_POOL_SIZE + processor_tasks as u32 * (num_shards as u32 - 1),
+    )
+    .expect("Failed to create connection pool");
     info!(
         processor_name = processor_name,
         "Created the connection pool... "
@@ -129,28 +483,12 @@ pub async fn run_forever(config: IndexerConfig, context: Arc<Context>) {
 
     info!(processor_name = processor_name, "Instantiating tailer... ");
 
-    let processor_enum = Processor::from_string(&processor_name);
-    let processor: Arc<dyn TransactionProcessor> = match processor_enum {
-        Processor::DefaultProcessor => {
-            Arc::new(DefaultTransactionProcessor::new(conn_pool.clone()))
-        }
-        Processor::TokenProcessor => Arc::new(TokenTransactionProcessor::new(
-            conn_pool.clone(),
-            config.ans_contract_address,
-        )),
-        Processor::CoinProcessor => Arc::new(CoinTransactionProcessor::new(conn_pool.clone())),
-    };
+    let processor =
+        build_processor_with_outputs(&config, conn_pool.clone(), live_feed, parquet_sink);
 
-    let options =
-        TransactionFetcherOptions::new(None, None, Some(batch_size), None, fetch_tasks as usize);
-
-    let tailer = Tailer::new(context, conn_pool.clone(), processor, options)
+    let mut tailer = Tailer::new(context, conn_pool.clone(), processor, options)
         .expect("Failed to instantiate tailer");
-
-    if !skip_migrations {
-        info!(processor_name = processor_name, "Running migrations...");
-        tailer.run_migrations();
-    }
+    tailer.limit_in_flight_batches(processor_tasks as usize);
 
     info!(
         processor_name = processor_name,
@@ -1,5 +1,35 @@
 // @generated automatically by Diesel CLI.
 
+diesel::table! {
+    account_token_activities (account_address, transaction_version, event_index, event_account_address, event_creation_number, event_sequence_number, token_index) {
+        account_address -> Varchar,
+        transaction_version -> Int8,
+        event_index -> Int8,
+        event_account_address -> Varchar,
+        event_creation_number -> Int8,
+        event_sequence_number -> Int8,
+        token_index -> Int8,
+        side -> Varchar,
+        collection_data_id_hash -> Varchar,
+        token_data_id_hash -> Varchar,
+        property_version -> Numeric,
+        creator_address -> Varchar,
+        collection_name -> Varchar,
+        name -> Varchar,
+        transfer_type -> Varchar,
+        from_address -> Nullable<Varchar>,
+        to_address -> Nullable<Varchar>,
+        token_amount -> Numeric,
+        coin_type -> Nullable<Text>,
+        coin_amount -> Nullable<Numeric>,
+        transaction_timestamp -> Timestamp,
+        inserted_at -> Timestamp,
+        transfer_kind -> Nullable<Varchar>,
+        domain -> Nullable<Varchar>,
+        suspect_value -> Bool,
+    }
+}
+
 diesel::table! {
     block_metadata_transactions (version) {
         version -> Int8,
@@ -46,6 +76,14 @@ diesel::table! {
     }
 }
 
+diesel::table! {
+    coin_decimals (coin_type) {
+        coin_type -> Varchar,
+        decimals -> Int4,
+        inserted_at -> Timestamp,
+    }
+}
+
 diesel::table! {
     coin_infos (coin_type_hash) {
         coin_type_hash -> Varchar,
@@ -62,6 +100,16 @@ diesel::table! {
     }
 }
 
+diesel::table! {
+    coin_prices (coin_type, as_of) {
+        coin_type -> Varchar,
+        price_usd -> Numeric,
+        decimals -> Int4,
+        as_of -> Timestamp,
+        inserted_at -> Timestamp,
+    }
+}
+
 diesel::table! {
     coin_supply (transaction_version, coin_type_hash) {
         transaction_version -> Int8,
@@ -74,6 +122,33 @@ diesel::table! {
     }
 }
 
+diesel::table! {
+    collection_curations (collection_data_id_hash) {
+        collection_data_id_hash -> Varchar,
+        is_verified -> Bool,
+        display_name_override -> Nullable<Varchar>,
+        discord_url -> Nullable<Varchar>,
+        twitter_url -> Nullable<Varchar>,
+        updated_by -> Varchar,
+        updated_at -> Timestamp,
+    }
+}
+
+diesel::table! {
+    collection_daily_reports (collection_data_id_hash, coin_type, market_address, report_date) {
+        collection_data_id_hash -> Varchar,
+        coin_type -> Varchar,
+        market_address -> Varchar,
+        report_date -> Date,
+        volume -> Numeric,
+        sales_count -> Int8,
+        min_price -> Numeric,
+        max_price -> Numeric,
+        last_transaction_version -> Int8,
+        inserted_at -> Timestamp,
+    }
+}
+
 diesel::table! {
     collection_datas (collection_data_id_hash, transaction_version) {
         collection_data_id_hash -> Varchar,
@@ -94,11 +169,123 @@ diesel::table! {
 }
 
 diesel::table! {
-    collection_volumes (last_transaction_version) {
+    collection_hold_durations (collection_data_id_hash) {
+        collection_data_id_hash -> Varchar,
+        avg_hold_duration_secs -> Float8,
+        median_hold_duration_secs -> Float8,
+        sales_count -> Int8,
+        last_transaction_version -> Int8,
+        inserted_at -> Timestamp,
+    }
+}
+
+diesel::table! {
+    collection_leaderboard (collection_data_id_hash) {
+        collection_data_id_hash -> Varchar,
+        creator_address -> Varchar,
+        collection_name -> Varchar,
+        volume_24h -> Numeric,
+        volume_7d -> Numeric,
+        total_volume -> Numeric,
+        rank_24h -> Nullable<Int8>,
+        rank_7d -> Nullable<Int8>,
+        rank_all_time -> Nullable<Int8>,
+        refreshed_at -> Timestamp,
+        inserted_at -> Timestamp,
+    }
+}
+
+diesel::table! {
+    collection_mints (transaction_version, event_index) {
+        transaction_version -> Int8,
+        event_index -> Int8,
+        token_data_id_hash -> Varchar,
+        collection_data_id_hash -> Varchar,
+        creator_address -> Varchar,
+        collection_name -> Varchar,
+        name -> Varchar,
+        minter_address -> Varchar,
+        amount -> Numeric,
+        price -> Numeric,
+        is_price_unknown -> Bool,
+        transaction_timestamp -> Timestamp,
+        inserted_at -> Timestamp,
+        launchpad -> Nullable<Varchar>,
+    }
+}
+
+diesel::table! {
+    collection_price_candles (collection_data_id_hash, coin_type, market_address, interval_start) {
+        collection_data_id_hash -> Varchar,
+        coin_type -> Varchar,
+        market_address -> Varchar,
+        interval_start -> Timestamp,
+        open_price -> Numeric,
+        high_price -> Numeric,
+        low_price -> Numeric,
+        close_price -> Numeric,
+        volume -> Numeric,
+        sales_count -> Int8,
+        first_transaction_version -> Int8,
+        last_transaction_version -> Int8,
+        inserted_at -> Timestamp,
+    }
+}
+
+diesel::table! {
+    collection_rarity_status (processor) {
+        processor -> Varchar,
+        last_transaction_version -> Int8,
+        inserted_at -> Timestamp,
+    }
+}
+
+diesel::table! {
+    collection_stats_snapshots (collection_data_id_hash, snapshot_date) {
+        collection_data_id_hash -> Varchar,
+        snapshot_date -> Date,
+        creator_address -> Varchar,
+        collection_name -> Varchar,
+        floor_price -> Nullable<Numeric>,
+        listed_count -> Nullable<Int8>,
+        holder_count -> Nullable<Int8>,
+        supply -> Nullable<Numeric>,
+        volume_24h -> Numeric,
+        sales_count_24h -> Int8,
+        average_sale_price_24h -> Nullable<Numeric>,
+        total_volume -> Nullable<Numeric>,
+        snapshot_at -> Timestamp,
+        is_backfilled -> Bool,
+        inserted_at -> Timestamp,
+    }
+}
+
+diesel::table! {
+    collection_trait_frequencies (collection_data_id_hash, property_key, property_value) {
+        collection_data_id_hash -> Varchar,
+        property_key -> Varchar,
+        property_value -> Text,
+        token_count -> Int8,
+        frequency -> Float8,
+        last_transaction_version -> Int8,
+        inserted_at -> Timestamp,
+    }
+}
+
+diesel::table! {
+    collection_volumes (last_transaction_version, event_index, token_index) {
         collection_data_id_hash -> Varchar,
         volume -> Numeric,
         inserted_at -> Timestamp,
         last_transaction_version -> Int8,
+        last_transaction_timestamp -> Timestamp,
+        event_index -> Int8,
+        is_primary -> Bool,
+        volume_usd -> Nullable<Numeric>,
+        volume_decimal -> Nullable<Numeric>,
+        market_address -> Nullable<Varchar>,
+        coin_type -> Nullable<Varchar>,
+        token_index -> Int8,
     }
 }
 
@@ -110,6 +297,31 @@ diesel::table! {
         expiration_timestamp -> Timestamp,
         last_transaction_version -> Int8,
         inserted_at -> Timestamp,
+        is_deleted -> Bool,
+    }
+}
+
+diesel::table! {
+    current_ans_primary_name (registered_address) {
+        registered_address -> Varchar,
+        domain -> Nullable<Varchar>,
+        subdomain -> Nullable<Varchar>,
+        last_transaction_version -> Int8,
+        inserted_at -> Timestamp,
+    }
+}
+
+diesel::table! {
+    current_ans_sale_prices (domain) {
+        domain -> Varchar,
+        token_data_id_hash -> Varchar,
+        market_address -> Varchar,
+        coin_type -> Nullable<Text>,
+        last_sale_price -> Nullable<Numeric>,
+        last_sale_price_usd -> Nullable<Numeric>,
+        last_transaction_version -> Int8,
+        last_transaction_timestamp -> Timestamp,
+        inserted_at -> Timestamp,
     }
 }
 
@@ -125,6 +337,19 @@ diesel::table! {
     }
 }
 
+diesel::table! {
+    current_collection_best_offers (collection_data_id_hash) {
+        collection_data_id_hash -> Varchar,
+        best_offer -> Numeric,
+        buyer -> Varchar,
+        market_address -> Varchar,
+        bid_id -> Numeric,
+        deadline -> Numeric,
+        last_transaction_version -> Int8,
+        inserted_at -> Timestamp,
+    }
+}
+
 diesel::table! {
     current_collection_datas (collection_data_id_hash) {
         collection_data_id_hash -> Varchar,
@@ -141,6 +366,65 @@ diesel::table! {
         inserted_at -> Timestamp,
         table_handle -> Varchar,
         last_transaction_timestamp -> Timestamp,
+        is_verified -> Bool,
+    }
+}
+
+diesel::table! {
+    current_collection_holder_counts (collection_data_id_hash) {
+        collection_data_id_hash -> Varchar,
+        distinct_holders -> Int8,
+        total_tokens_held -> Numeric,
+        last_transaction_version -> Int8,
+        inserted_at -> Timestamp,
+    }
+}
+
+diesel::table! {
+    current_collection_mint_stats (collection_data_id_hash) {
+        collection_data_id_hash -> Varchar,
+        total_minted -> Numeric,
+        distinct_minters -> Int8,
+        mint_volume_apt -> Numeric,
+        last_transaction_version -> Int8,
+        inserted_at -> Timestamp,
+        launchpad -> Nullable<Varchar>,
+    }
+}
+
+diesel::table! {
+    current_collection_offers (collection_data_id_hash, buyer, market_address) {
+        collection_data_id_hash -> Varchar,
+        buyer -> Varchar,
+        market_address -> Varchar,
+        bid_id -> Numeric,
+        creator_address -> Varchar,
+        collection_name -> Varchar,
+        price -> Numeric,
+        amount_remaining -> Numeric,
+        coin_type -> Varchar,
+        deadline -> Numeric,
+        status -> Varchar,
+        last_transaction_version -> Int8,
+        last_transaction_timestamp -> Timestamp,
+        inserted_at -> Timestamp,
+        expires_at -> Timestamp,
+    }
+}
+
+diesel::table! {
+    current_collection_price_stats (collection_data_id_hash) {
+        collection_data_id_hash -> Varchar,
+        all_time_high_price -> Numeric,
+        all_time_high_version -> Int8,
+        min_sale_price -> Numeric,
+        sale_count -> Int8,
+        sum_price -> Numeric,
+        median_price -> Nullable<Numeric>,
+        median_sale_count -> Nullable<Int8>,
+        last_transaction_version -> Int8,
+        last_event_index -> Int8,
+        inserted_at -> Timestamp,
     }
 }
 
@@ -150,6 +434,12 @@ diesel::table! {
         volume -> Numeric,
         inserted_at -> Timestamp,
         last_transaction_version -> Int8,
+        primary_volume -> Numeric,
+        secondary_volume -> Numeric,
+        last_transaction_timestamp -> Timestamp,
+        volume_usd -> Nullable<Numeric>,
+        volume_decimal -> Nullable<Numeric>,
+        last_event_index -> Nullable<Int8>,
     }
 }
 
@@ -168,6 +458,26 @@ diesel::table! {
         event_type -> Varchar,
         inserted_at -> Timestamp,
         last_transaction_version -> Int8,
+        invalidated_reason -> Nullable<Varchar>,
+        last_transaction_timestamp -> Timestamp,
+        price_decimal -> Nullable<Numeric>,
+        is_deleted -> Bool,
+        last_event_index -> Nullable<Int8>,
+    }
+}
+
+diesel::table! {
+    current_marketplace_volumes (market_address, coin_type) {
+        market_address -> Varchar,
+        coin_type -> Varchar,
+        volume -> Numeric,
+        trade_count -> Int8,
+        volume_usd -> Nullable<Numeric>,
+        volume_decimal -> Nullable<Numeric>,
+        last_transaction_version -> Int8,
+        last_transaction_timestamp -> Timestamp,
+        inserted_at -> Timestamp,
+        last_event_index -> Nullable<Int8>,
     }
 }
 
@@ -180,6 +490,29 @@ diesel::table! {
     }
 }
 
+diesel::table! {
+    current_token_bids (token_data_id_hash, property_version, buyer, market_address) {
+        token_data_id_hash -> Varchar,
+        property_version -> Numeric,
+        buyer -> Varchar,
+        market_address -> Varchar,
+        collection_data_id_hash -> Varchar,
+        creator_address -> Varchar,
+        collection_name -> Varchar,
+        name -> Varchar,
+        bid_id -> Nullable<Numeric>,
+        price -> Numeric,
+        amount_remaining -> Numeric,
+        coin_type -> Varchar,
+        deadline -> Nullable<Numeric>,
+        status -> Varchar,
+        last_transaction_version -> Int8,
+        last_transaction_timestamp -> Timestamp,
+        inserted_at -> Timestamp,
+        expires_at -> Nullable<Timestamp>,
+    }
+}
+
 diesel::table! {
     current_token_datas (token_data_id_hash) {
         token_data_id_hash -> Varchar,
@@ -204,6 +537,10 @@ diesel::table! {
         collection_data_id_hash -> Varchar,
         last_transaction_timestamp -> Timestamp,
         description -> Text,
+        rarity_score -> Nullable<Float8>,
+        rarity_rank -> Nullable<Int8>,
+        metadata_uri_canonical -> Nullable<Varchar>,
+        uri_scheme -> Nullable<Varchar>,
     }
 }
 
@@ -222,6 +559,9 @@ diesel::table! {
         collection_data_id_hash -> Varchar,
         table_type -> Text,
         last_transaction_timestamp -> Timestamp,
+        owner_type -> Varchar,
+        beneficial_owner -> Nullable<Varchar>,
+        is_deleted -> Bool,
     }
 }
 
@@ -240,6 +580,38 @@ diesel::table! {
         last_transaction_version -> Int8,
         inserted_at -> Timestamp,
         last_transaction_timestamp -> Timestamp,
+        is_deleted -> Bool,
+    }
+}
+
+diesel::table! {
+    current_token_transfer_offers (token_data_id_hash, property_version, from_address, to_address) {
+        token_data_id_hash -> Varchar,
+        property_version -> Numeric,
+        from_address -> Varchar,
+        to_address -> Varchar,
+        collection_data_id_hash -> Varchar,
+        creator_address -> Varchar,
+        collection_name -> Varchar,
+        name -> Varchar,
+        amount -> Numeric,
+        status -> Varchar,
+        last_transaction_version -> Int8,
+        last_transaction_timestamp -> Timestamp,
+        inserted_at -> Timestamp,
+    }
+}
+
+diesel::table! {
+    current_token_top_bids (token_data_id_hash, property_version) {
+        token_data_id_hash -> Varchar,
+        property_version -> Numeric,
+        top_bid -> Numeric,
+        buyer -> Varchar,
+        market_address -> Varchar,
+        bid_id -> Nullable<Numeric>,
+        last_transaction_version -> Int8,
+        inserted_at -> Timestamp,
     }
 }
 
@@ -249,6 +621,36 @@ diesel::table! {
         volume -> Numeric,
         inserted_at -> Timestamp,
         last_transaction_version -> Int8,
+        last_transaction_timestamp -> Timestamp,
+        volume_decimal -> Nullable<Numeric>,
+        last_event_index -> Nullable<Int8>,
+    }
+}
+
+diesel::table! {
+    current_wallet_nft_stats (wallet_address) {
+        wallet_address -> Varchar,
+        buy_count -> Int8,
+        sell_count -> Int8,
+        buy_volume -> Numeric,
+        sell_volume -> Numeric,
+        first_trade_version -> Int8,
+        last_trade_version -> Int8,
+        inserted_at -> Timestamp,
+        realized_pnl -> Numeric,
+    }
+}
+
+diesel::table! {
+    data_integrity_findings (check_name, subject, transaction_version) {
+        check_name -> Varchar,
+        subject -> Varchar,
+        transaction_version -> Int8,
+        expected -> Numeric,
+        actual -> Numeric,
+        delta -> Numeric,
+        inserted_at -> Timestamp,
+        offending_version -> Nullable<Int8>,
     }
 }
 
@@ -266,6 +668,14 @@ diesel::table! {
     }
 }
 
+diesel::table! {
+    indexer_metadata (name) {
+        name -> Varchar,
+        value -> Varchar,
+        updated_at -> Timestamp,
+    }
+}
+
 diesel::table! {
     indexer_status (db) {
         db -> Varchar,
@@ -280,6 +690,54 @@ diesel::table! {
     }
 }
 
+diesel::table! {
+    marketplace_collection_volumes (market_address, collection_data_id_hash, coin_type) {
+        market_address -> Varchar,
+        collection_data_id_hash -> Varchar,
+        coin_type -> Varchar,
+        volume -> Numeric,
+        trade_count -> Int8,
+        volume_usd -> Nullable<Numeric>,
+        volume_decimal -> Nullable<Numeric>,
+        last_transaction_version -> Int8,
+        last_transaction_timestamp -> Timestamp,
+        inserted_at -> Timestamp,
+        last_event_index -> Nullable<Int8>,
+    }
+}
+
+diesel::table! {
+    marketplace_listing_price_changes (transaction_version, event_index) {
+        transaction_version -> Int8,
+        event_index -> Int8,
+        token_data_id_hash -> Varchar,
+        property_version -> Numeric,
+        collection_data_id_hash -> Varchar,
+        market_address -> Varchar,
+        seller -> Varchar,
+        old_price -> Nullable<Numeric>,
+        new_price -> Numeric,
+        transaction_timestamp -> Timestamp,
+        inserted_at -> Timestamp,
+    }
+}
+
+diesel::table! {
+    marketplace_volumes (market_address, coin_type, volume_date) {
+        market_address -> Varchar,
+        coin_type -> Varchar,
+        volume_date -> Date,
+        volume -> Numeric,
+        trade_count -> Int8,
+        volume_usd -> Nullable<Numeric>,
+        volume_decimal -> Nullable<Numeric>,
+        last_transaction_version -> Int8,
+        last_transaction_timestamp -> Timestamp,
+        inserted_at -> Timestamp,
+        last_event_index -> Nullable<Int8>,
+    }
+}
+
 diesel::table! {
     move_modules (transaction_version, write_set_change_index) {
         transaction_version -> Int8,
@@ -313,11 +771,75 @@ diesel::table! {
     }
 }
 
+diesel::table! {
+    nft_sales (transaction_version, event_account_address, event_creation_number, event_sequence_number, token_index) {
+        transaction_version -> Int8,
+        event_account_address -> Varchar,
+        event_creation_number -> Int8,
+        event_sequence_number -> Int8,
+        market_address -> Varchar,
+        event_type -> Varchar,
+        token_data_id_hash -> Varchar,
+        property_version -> Numeric,
+        collection_data_id_hash -> Varchar,
+        creator_address -> Varchar,
+        collection_name -> Varchar,
+        name -> Varchar,
+        seller -> Nullable<Varchar>,
+        buyer -> Nullable<Varchar>,
+        token_amount -> Numeric,
+        coin_type -> Nullable<Text>,
+        price -> Nullable<Numeric>,
+        gas_unit_price -> Numeric,
+        transaction_rank_in_block -> Nullable<Int8>,
+        transaction_timestamp -> Timestamp,
+        inserted_at -> Timestamp,
+        is_primary -> Bool,
+        realized_pnl -> Nullable<Numeric>,
+        hold_duration_secs -> Nullable<Int8>,
+        event_index -> Nullable<Int8>,
+        source -> Varchar,
+        settlement_amount -> Nullable<Numeric>,
+        coin_price_usd -> Nullable<Numeric>,
+        price_usd -> Nullable<Numeric>,
+        price_decimal -> Nullable<Numeric>,
+        sale_group_id -> Int8,
+        group_size -> Int8,
+        token_index -> Int8,
+        domain -> Nullable<Varchar>,
+        suspect_value -> Bool,
+    }
+}
+
+diesel::table! {
+    nft_transaction_fees (transaction_version) {
+        transaction_version -> Int8,
+        sender -> Varchar,
+        gas_used -> Numeric,
+        gas_unit_price -> Numeric,
+        gas_fee -> Numeric,
+        num_token_events -> Int8,
+        market_address -> Nullable<Varchar>,
+        transaction_timestamp -> Timestamp,
+        inserted_at -> Timestamp,
+    }
+}
+
+diesel::table! {
+    processed_version_ranges (processor, start_version) {
+        processor -> Varchar,
+        start_version -> Int8,
+        end_version -> Int8,
+        last_updated -> Timestamp,
+    }
+}
+
 diesel::table! {
     processor_status (processor) {
         processor -> Varchar,
         last_success_version -> Int8,
         last_updated -> Timestamp,
+        last_transaction_timestamp -> Nullable<Timestamp>,
     }
 }
 
@@ -331,6 +853,16 @@ diesel::table! {
     }
 }
 
+diesel::table! {
+    pruning_log (table_name, pruned_at) {
+        table_name -> Varchar,
+        pruned_at -> Timestamp,
+        cutoff_timestamp -> Timestamp,
+        max_version -> Int8,
+        num_rows -> Int8,
+    }
+}
+
 diesel::table! {
     signatures (transaction_version, multi_agent_index, multi_sig_index, is_sender_primary) {
         transaction_version -> Int8,
@@ -349,6 +881,14 @@ diesel::table! {
     }
 }
 
+diesel::table! {
+    spam_collections (collection_data_id_hash) {
+        collection_data_id_hash -> Varchar,
+        reason -> Nullable<Text>,
+        inserted_at -> Timestamp,
+    }
+}
+
 diesel::table! {
     table_items (transaction_version, write_set_change_index) {
         key -> Text,
@@ -373,7 +913,17 @@ diesel::table! {
 }
 
 diesel::table! {
-    token_activities (transaction_version, event_account_address, event_creation_number, event_sequence_number) {
+    token_acquisitions (token_data_id_hash, owner_address) {
+        token_data_id_hash -> Varchar,
+        owner_address -> Varchar,
+        acquired_at -> Timestamp,
+        last_transaction_version -> Int8,
+        inserted_at -> Timestamp,
+    }
+}
+
+diesel::table! {
+    token_activities (transaction_version, event_account_address, event_creation_number, event_sequence_number, event_index, token_index) {
         transaction_version -> Int8,
         event_account_address -> Varchar,
         event_creation_number -> Int8,
@@ -392,6 +942,11 @@ diesel::table! {
         coin_amount -> Nullable<Numeric>,
         inserted_at -> Timestamp,
         transaction_timestamp -> Timestamp,
+        event_index -> Int8,
+        token_index -> Int8,
+        transfer_kind -> Nullable<Varchar>,
+        domain -> Nullable<Varchar>,
+        suspect_value -> Bool,
     }
 }
 
@@ -422,6 +977,25 @@ diesel::table! {
     }
 }
 
+diesel::table! {
+    token_metadata_cache (token_data_id_hash) {
+        token_data_id_hash -> Varchar,
+        metadata_uri -> Varchar,
+        fetch_status -> Varchar,
+        name -> Nullable<Text>,
+        description -> Nullable<Text>,
+        image -> Nullable<Text>,
+        attributes -> Nullable<Jsonb>,
+        content_hash -> Nullable<Varchar>,
+        fetch_attempts -> Int8,
+        last_error -> Nullable<Text>,
+        next_fetch_at -> Timestamp,
+        fetched_at -> Nullable<Timestamp>,
+        last_transaction_version -> Int8,
+        inserted_at -> Timestamp,
+    }
+}
+
 diesel::table! {
     token_ownerships (token_data_id_hash, property_version, transaction_version, table_handle) {
         token_data_id_hash -> Varchar,
@@ -441,11 +1015,46 @@ diesel::table! {
 }
 
 diesel::table! {
-    token_volumes (last_transaction_version) {
+    token_properties_flat (token_data_id_hash, property_key) {
+        token_data_id_hash -> Varchar,
+        property_key -> Varchar,
+        property_value -> Text,
+        property_type -> Varchar,
+        collection_data_id_hash -> Varchar,
+        last_transaction_version -> Int8,
+        inserted_at -> Timestamp,
+    }
+}
+
+diesel::table! {
+    token_property_mutations (transaction_version, event_index) {
+        transaction_version -> Int8,
+        event_index -> Int8,
+        old_token_data_id_hash -> Varchar,
+        new_token_data_id_hash -> Varchar,
+        old_property_version -> Numeric,
+        new_property_version -> Numeric,
+        collection_data_id_hash -> Varchar,
+        creator_address -> Varchar,
+        collection_name -> Varchar,
+        name -> Varchar,
+        mutated_properties -> Jsonb,
+        new_token_properties -> Jsonb,
+        transaction_timestamp -> Timestamp,
+        inserted_at -> Timestamp,
+    }
+}
+
+diesel::table! {
+    token_volumes (last_transaction_version, event_index, token_index) {
         token_data_id_hash -> Varchar,
         volume -> Numeric,
         inserted_at -> Timestamp,
         last_transaction_version -> Int8,
+        last_transaction_timestamp -> Timestamp,
+        event_index -> Int8,
+        volume_decimal -> Nullable<Numeric>,
+        token_index -> Int8,
     }
 }
 
@@ -464,6 +1073,19 @@ diesel::table! {
     }
 }
 
+diesel::table! {
+    trader_leaderboard (trader_address) {
+        trader_address -> Varchar,
+        volume_24h -> Numeric,
+        bought_volume_24h -> Numeric,
+        sold_volume_24h -> Numeric,
+        trades_24h -> Int8,
+        rank -> Int8,
+        refreshed_at -> Timestamp,
+        inserted_at -> Timestamp,
+    }
+}
+
 diesel::table! {
     transactions (version) {
         version -> Int8,
@@ -486,6 +1108,16 @@ diesel::table! {
     }
 }
 
+diesel::table! {
+    truncated_strings (hash, field) {
+        hash -> Varchar,
+        field -> Varchar,
+        full_value -> Text,
+        last_transaction_version -> Int8,
+        inserted_at -> Timestamp,
+    }
+}
+
 diesel::table! {
     user_transactions (version) {
         version -> Int8,
@@ -503,6 +1135,17 @@ diesel::table! {
     }
 }
 
+diesel::table! {
+    wallet_token_cost_basis (wallet_address, token_data_id_hash) {
+        wallet_address -> Varchar,
+        token_data_id_hash -> Varchar,
+        cost_basis -> Nullable<Numeric>,
+        amount -> Numeric,
+        last_transaction_version -> Int8,
+        inserted_at -> Timestamp,
+    }
+}
+
 diesel::table! {
     write_set_changes (transaction_version, index) {
         transaction_version -> Int8,
@@ -517,39 +1160,80 @@ diesel::table! {
 }
 
 diesel::allow_tables_to_appear_in_same_query!(
+    account_token_activities,
     block_metadata_transactions,
     coin_activities,
     coin_balances,
+    coin_decimals,
     coin_infos,
+    coin_prices,
     coin_supply,
+    collection_curations,
+    collection_daily_reports,
     collection_datas,
+    collection_hold_durations,
+    collection_leaderboard,
+    collection_mints,
+    collection_price_candles,
+    collection_rarity_status,
+    collection_stats_snapshots,
+    collection_trait_frequencies,
     collection_volumes,
     current_ans_lookup,
+    current_ans_primary_name,
+    current_ans_sale_prices,
     current_coin_balances,
+    current_collection_best_offers,
     current_collection_datas,
+    current_collection_holder_counts,
+    current_collection_mint_stats,
+    current_collection_offers,
+    current_collection_price_stats,
     current_collection_volumes,
     current_marketplace_listings,
+    current_marketplace_volumes,
     current_staking_pool_voter,
+    current_token_bids,
     current_token_datas,
     current_token_ownerships,
     current_token_pending_claims,
+    current_token_top_bids,
+    current_token_transfer_offers,
     current_token_volumes,
+    current_wallet_nft_stats,
+    data_integrity_findings,
     events,
+    indexer_metadata,
     indexer_status,
     ledger_infos,
+    marketplace_collection_volumes,
+    marketplace_listing_price_changes,
+    marketplace_volumes,
     move_modules,
     move_resources,
+    nft_sales,
+    nft_transaction_fees,
+    processed_version_ranges,
     processor_status,
     processor_statuses,
+    pruning_log,
     signatures,
+    spam_collections,
     table_items,
     table_metadatas,
+    token_acquisitions,
     token_activities,
     token_datas,
+    token_metadata_cache,
     token_ownerships,
+    token_properties_flat,
+    token_property_mutations,
     token_volumes,
     tokens,
+    trader_leaderboard,
     transactions,
+    truncated_strings,
     user_transactions,
+    wallet_token_cost_basis,
     write_set_changes,
 );
@@ -0,0 +1,45 @@
+// Copyright (c) Aptos
+// SPDX-License-Identifier: Apache-2.0
+
+//! Fixtures shared by the unit tests across the crate.
+
+use serde_json::json;
+use std::{
+    cell::RefCell,
+    sync::{Mutex, MutexGuard},
+};
+
+/// A `0x3::token::TokenId` as it appears in event and table item data.
+pub fn token_id() -> serde_json::Value {
+    json!({
+        "token_data_id": {"creator": "0xc4e7", "collection": "Potions", "name": "Potion"},
+        "property_version": "0"
+    })
+}
+
+pub fn timestamp() -> chrono::NaiveDateTime {
+    chrono::NaiveDateTime::from_timestamp(1668000000, 0)
+}
+
+/// Every postgres test shares the database behind `INDEXER_DATABASE_URL`, and most of them start
+/// by dropping its public schema, so they can't run concurrently. The lock is held by the test's
+/// thread until it exits; taking it again from the same thread is a no-op.
+static DATABASE_LOCK: Mutex<()> = Mutex::new(());
+
+thread_local! {
+    static DATABASE_GUARD: RefCell<Option<MutexGuard<'static, ()>>> = RefCell::new(None);
+}
+
+pub fn lock_database() {
+    DATABASE_GUARD.with(|guard| {
+        let mut guard = guard.borrow_mut();
+        if guard.is_none() {
+            // A failed test poisons the lock, which shouldn't fail the ones after it
+            *guard = Some(
+                DATABASE_LOCK
+                    .lock()
+                    .unwrap_or_else(|poisoned| poisoned.into_inner()),
+            );
+        }
+    });
+}
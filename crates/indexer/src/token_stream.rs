@@ -0,0 +1,169 @@
+// Copyright (c) Aptos
+// SPDX-License-Identifier: Apache-2.0
+
+//! NFT events parsed from transactions, for services that want them without the indexer's
+//! database. Events are parsed the same way as for the token processor, by the typed parsers in
+//! token_utils and the configured marketplace event mappings, then classified by
+//! `ParsedNftEvent`. See the README for an example.
+
+pub use crate::models::token_models::{
+    marketplace_event_mappings::MarketplaceEventMappings,
+    nft_events::{NftEventContext, NftToken, ParsedNftEvent},
+};
+use crate::{
+    indexer::fetcher::TransactionFetcherTrait,
+    models::token_models::{token_activities::TokenActivity, token_utils::TokenEvents},
+};
+use aptos_api_types::Transaction;
+
+/// The transaction's NFT events in event order, with only the marketplaces that have a typed
+/// parser
+pub fn parse_transaction(transaction: &Transaction) -> Vec<ParsedNftEvent> {
+    parse_transaction_with_mappings(transaction, &MarketplaceEventMappings::default())
+}
+
+/// Same as parse_transaction, with the configured marketplaces too. Like the token processor,
+/// panics on an event of a known type that doesn't deserialize.
+pub fn parse_transaction_with_mappings(
+    transaction: &Transaction,
+    marketplace_event_mappings: &MarketplaceEventMappings,
+) -> Vec<ParsedNftEvent> {
+    let token_events =
+        TokenEvents::from_transaction_with_mappings(transaction, marketplace_event_mappings)
+            .unwrap();
+    let token_activities =
+        TokenActivity::from_transaction(transaction, &token_events, marketplace_event_mappings);
+    ParsedNftEvent::from_token_activities(&token_activities)
+}
+
+/// The NFT events of a batch of consecutive versions. Batches without any are still returned, so
+/// that the caller knows how far the stream got.
+#[derive(Debug)]
+pub struct NftEventBatch {
+    pub start_version: u64,
+    pub end_version: u64,
+    pub events: Vec<ParsedNftEvent>,
+}
+
+/// Parses the batches of a transaction fetcher, ex: a `TransactionFetcher` on the node's db
+pub struct TokenEventStream {
+    fetcher: Box<dyn TransactionFetcherTrait>,
+    marketplace_event_mappings: MarketplaceEventMappings,
+}
+
+impl TokenEventStream {
+    pub fn new(
+        fetcher: Box<dyn TransactionFetcherTrait>,
+        marketplace_event_mappings: MarketplaceEventMappings,
+    ) -> Self {
+        Self {
+            fetcher,
+            marketplace_event_mappings,
+        }
+    }
+
+    /// Starts fetching from the version. Like the fetcher, it can only be started once.
+    pub async fn start(&mut self, version: u64) {
+        self.fetcher.set_version(version).await;
+        self.fetcher.start().await;
+    }
+
+    /// Waits for the next batch to be fetched
+    pub async fn next_batch(&mut self) -> NftEventBatch {
+        let transactions = self.fetcher.fetch_next_batch().await;
+        let start_version = transactions
+            .first()
+            .and_then(|txn| txn.version())
+            .unwrap_or_default();
+        let end_version = transactions
+            .last()
+            .and_then(|txn| txn.version())
+            .unwrap_or_default();
+        let events = transactions
+            .iter()
+            .flat_map(|txn| parse_transaction_with_mappings(txn, &self.marketplace_event_mappings))
+            .collect();
+        NftEventBatch {
+            start_version,
+            end_version,
+            events,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use aptos_api_types::LedgerInfo;
+
+    fn fixture(name: &str) -> Transaction {
+        let path = format!(
+            "{}/fixtures/golden/transactions/{}.json",
+            env!("CARGO_MANIFEST_DIR"),
+            name
+        );
+        serde_json::from_str(&std::fs::read_to_string(path).unwrap()).unwrap()
+    }
+
+    /// Hands out the given batches in order
+    struct FakeFetcher {
+        batches: Vec<Vec<Transaction>>,
+        version: Option<u64>,
+    }
+
+    #[async_trait::async_trait]
+    impl TransactionFetcherTrait for FakeFetcher {
+        async fn fetch_next_batch(&mut self) -> Vec<Transaction> {
+            self.batches.remove(0)
+        }
+
+        fn fetch_ledger_info(&mut self) -> LedgerInfo {
+            unimplemented!();
+        }
+
+        async fn set_version(&mut self, version: u64) {
+            self.version = Some(version);
+        }
+
+        async fn start(&mut self) {
+            assert!(self.version.is_some());
+        }
+    }
+
+    #[test]
+    fn test_parse_transaction() {
+        let events = parse_transaction(&fixture("topaz_buy"));
+        let event_indices: Vec<i64> = events
+            .iter()
+            .map(|event| event.context().event_index)
+            .collect();
+        assert_eq!(event_indices, vec![0, 1, 2]);
+        assert!(matches!(events[2], ParsedNftEvent::Sale { .. }));
+    }
+
+    #[tokio::test]
+    async fn test_stream_batches() {
+        let fetcher = FakeFetcher {
+            batches: vec![
+                vec![fixture("topaz_buy"), fixture("topaz_collection_bid")],
+                vec![fixture("bluemove_list"), fixture("bluemove_buy")],
+            ],
+            version: None,
+        };
+        let mut stream =
+            TokenEventStream::new(Box::new(fetcher), MarketplaceEventMappings::default());
+        stream.start(100).await;
+
+        let batch = stream.next_batch().await;
+        assert_eq!((batch.start_version, batch.end_version), (100, 101));
+        assert_eq!(batch.events.len(), 4);
+        assert!(matches!(batch.events[3], ParsedNftEvent::Bid { .. }));
+
+        let batch = stream.next_batch().await;
+        assert_eq!((batch.start_version, batch.end_version), (102, 103));
+        assert!(matches!(
+            batch.events.as_slice(),
+            [ParsedNftEvent::Listing { .. }, ParsedNftEvent::Sale { .. }]
+        ));
+    }
+}
@@ -12,10 +12,49 @@ pub fn hash_str(val: &str) -> String {
     hex::encode(sha2::Sha256::digest(val.as_bytes()))
 }
 
+/// Counts characters rather than bytes, like postgres' VARCHAR(n), so a multibyte character is
+/// never split
 pub fn truncate_str(val: &str, max_chars: usize) -> String {
-    let mut trunc = val.to_string();
-    trunc.truncate(max_chars);
-    trunc
+    match val.char_indices().nth(max_chars) {
+        Some((end, _)) => val[..end].to_string(),
+        None => val.to_string(),
+    }
+}
+
+/// Pads an address to 0x followed by 64 lowercase hex chars. The API strips leading zeros from
+/// addresses while some contracts store them padded, and hashes and keys built from both forms
+/// would otherwise differ for the same account. Anything that isn't a hex address is returned as
+/// is, e.g. the empty seller of a delisting.
+pub fn standardize_address(address: &str) -> String {
+    match address.strip_prefix("0x") {
+        Some(hex)
+            if !hex.is_empty() && hex.len() <= 64 && hex.chars().all(|c| c.is_ascii_hexdigit()) =>
+        {
+            format!("0x{:0>64}", hex.to_ascii_lowercase())
+        }
+        _ => address.to_string(),
+    }
+}
+
+/// For address fields of move values
+pub fn deserialize_address<'de, D>(deserializer: D) -> Result<String, D::Error>
+where
+    D: serde::Deserializer<'de>,
+{
+    let address = <String as serde::Deserialize>::deserialize(deserializer)?;
+    Ok(standardize_address(&address))
+}
+
+/// For vectors of addresses in move values
+pub fn deserialize_addresses<'de, D>(deserializer: D) -> Result<Vec<String>, D::Error>
+where
+    D: serde::Deserializer<'de>,
+{
+    let addresses = <Vec<String> as serde::Deserialize>::deserialize(deserializer)?;
+    Ok(addresses
+        .iter()
+        .map(|address| standardize_address(address))
+        .collect())
 }
 
 pub fn u64_to_bigdecimal(val: u64) -> BigDecimal {
@@ -27,6 +66,13 @@ pub fn bigdecimal_to_u64(val: &BigDecimal) -> u64 {
     val.to_u64().expect("Unable to convert big decimal to u64")
 }
 
+/// Converts a raw on-chain amount into units of its coin, ex: octas into APT with 8 decimals.
+/// None when the coin's decimals aren't known, which is different from an amount of 0.
+pub fn to_decimal_amount(amount: &BigDecimal, decimals: Option<i32>) -> Option<BigDecimal> {
+    let (digits, scale) = amount.as_bigint_and_exponent();
+    decimals.map(|decimals| BigDecimal::new(digits, scale + decimals as i64))
+}
+
 pub fn ensure_not_negative(val: BigDecimal) -> BigDecimal {
     if val.is_negative() {
         return BigDecimal::zero();
@@ -80,6 +126,7 @@ fn string_null_byte_replacement(value: &mut str) -> String {
 mod tests {
     use super::*;
     use chrono::Datelike;
+    use std::str::FromStr;
 
     #[test]
     fn test_parse_timestamp() {
@@ -95,4 +142,49 @@ mod tests {
         let ts3 = parse_timestamp_secs(1659386386, 2);
         assert_eq!(ts3.timestamp(), 1659386386);
     }
+
+    #[test]
+    fn test_to_decimal_amount() {
+        assert_eq!(
+            to_decimal_amount(&BigDecimal::from(150000000), Some(8)),
+            Some(BigDecimal::from_str("1.5").unwrap())
+        );
+        assert_eq!(
+            to_decimal_amount(&BigDecimal::from(2500000), Some(6)),
+            Some(BigDecimal::from_str("2.5").unwrap())
+        );
+        assert_eq!(
+            to_decimal_amount(&BigDecimal::from_str("12.5").unwrap(), Some(0)),
+            Some(BigDecimal::from_str("12.5").unwrap())
+        );
+        assert_eq!(to_decimal_amount(&BigDecimal::from(100), None), None);
+    }
+
+    #[test]
+    fn test_truncate_str() {
+        assert_eq!(truncate_str("potion", 3), "pot");
+        assert_eq!(truncate_str("potion", 6), "potion");
+        assert_eq!(truncate_str("potion", 128), "potion");
+        // 2 bytes each, a byte count would cut the second one in half
+        assert_eq!(truncate_str("ééé", 2), "éé");
+    }
+
+    #[test]
+    fn test_standardize_address() {
+        let one = format!("0x{}1", "0".repeat(63));
+        assert_eq!(standardize_address("0x1"), one);
+        assert_eq!(standardize_address(&one), one);
+        assert_eq!(
+            standardize_address("0xC4E7"),
+            format!("0x{}c4e7", "0".repeat(60))
+        );
+        // Not addresses
+        assert_eq!(standardize_address(""), "");
+        assert_eq!(standardize_address("0x"), "0x");
+        assert_eq!(standardize_address("0xhello"), "0xhello");
+        assert_eq!(
+            standardize_address(&format!("0x{}", "1".repeat(65))).len(),
+            67
+        );
+    }
 }